@@ -30,7 +30,7 @@ use serde_json::Value;
 use thiserror::Error;
 use tower::{Service, ServiceExt};
 
-use crate::http_client_factory::HttpClientFactory;
+use crate::{client_certificate::ClientCertificate, http_client_factory::HttpClientFactory};
 
 static JWT_BEARER_CLIENT_ASSERTION: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
 
@@ -62,6 +62,10 @@ pub enum Credentials {
         client_id: String,
         jwt: Box<Jwt<'static, HashMap<String, serde_json::Value>>>,
     },
+    ClientCertificate {
+        client_id: String,
+        certificate: ClientCertificate,
+    },
 }
 
 impl Credentials {
@@ -72,7 +76,8 @@ impl Credentials {
             Credentials::None { client_id }
             | Credentials::ClientSecretBasic { client_id, .. }
             | Credentials::ClientSecretPost { client_id, .. }
-            | Credentials::ClientAssertionJwtBearer { client_id, .. } => client_id,
+            | Credentials::ClientAssertionJwtBearer { client_id, .. }
+            | Credentials::ClientCertificate { client_id, .. } => client_id,
         }
     }
 
@@ -90,7 +95,8 @@ impl Credentials {
             Credentials::None { client_id }
             | Credentials::ClientSecretBasic { client_id, .. }
             | Credentials::ClientSecretPost { client_id, .. }
-            | Credentials::ClientAssertionJwtBearer { client_id, .. } => client_id,
+            | Credentials::ClientAssertionJwtBearer { client_id, .. }
+            | Credentials::ClientCertificate { client_id, .. } => client_id,
         };
 
         repo.oauth2_client().find_by_client_id(client_id).await
@@ -110,7 +116,51 @@ impl Credentials {
         client: &Client,
     ) -> Result<(), CredentialsVerificationError> {
         match (self, method) {
-            (Credentials::None { .. }, OAuthClientAuthenticationMethod::None) => {}
+            (
+                Credentials::None { .. } | Credentials::ClientCertificate { .. },
+                OAuthClientAuthenticationMethod::None,
+            ) => {}
+
+            (
+                Credentials::ClientCertificate { certificate, .. },
+                OAuthClientAuthenticationMethod::TlsClientAuth,
+            ) => {
+                // The certificate chain was verified by the reverse proxy, we only have to
+                // check that it was issued to this client
+                let subject_dn = client
+                    .tls_client_auth_subject_dn
+                    .as_deref()
+                    .ok_or(CredentialsVerificationError::InvalidClientConfig)?;
+
+                if !certificate.subject_dn_matches(subject_dn) {
+                    return Err(CredentialsVerificationError::CertificateMismatch);
+                }
+            }
+
+            (
+                Credentials::ClientCertificate { certificate, .. },
+                OAuthClientAuthenticationMethod::SelfSignedTlsClientAuth,
+            ) => {
+                // Get the client JWKS
+                let jwks = client
+                    .jwks
+                    .as_ref()
+                    .ok_or(CredentialsVerificationError::InvalidClientConfig)?;
+
+                let jwks = fetch_jwks(http_client_factory, jwks)
+                    .await
+                    .map_err(|_| CredentialsVerificationError::JwksFetchFailed)?;
+
+                // The certificate must be the first one of the chain of one of the keys
+                let registered = jwks
+                    .iter()
+                    .filter_map(|key| key.x5c()?.first())
+                    .any(|registered| registered.as_bytes() == certificate.as_der());
+
+                if !registered {
+                    return Err(CredentialsVerificationError::CertificateMismatch);
+                }
+            }
 
             (
                 Credentials::ClientSecretPost { client_secret, .. },
@@ -180,7 +230,12 @@ impl Credentials {
     }
 }
 
-async fn fetch_jwks(
+/// Get the keys from a [`JwksOrJwksUri`], fetching them if necessary
+///
+/// # Errors
+///
+/// Returns an error if the keys could not be fetched
+pub async fn fetch_jwks(
     http_client_factory: &HttpClientFactory,
     jwks: &JwksOrJwksUri,
 ) -> Result<PublicJsonWebKeySet, BoxError> {
@@ -195,7 +250,7 @@ async fn fetch_jwks(
         .unwrap();
 
     let mut client = http_client_factory
-        .client("client.fetch_jwks")
+        .restricted_client("client.fetch_jwks")
         .response_body_to_bytes()
         .json_response::<PublicJsonWebKeySet>();
 
@@ -223,6 +278,9 @@ pub enum CredentialsVerificationError {
 
     #[error("failed to fetch jwks")]
     JwksFetchFailed,
+
+    #[error("client certificate did not match")]
+    CertificateMismatch,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -344,6 +402,9 @@ where
         // Split the request into parts so we can extract some headers
         let (mut parts, body) = req.into_parts();
 
+        // The client certificate, if the reverse proxy forwarded one
+        let certificate = parts.extensions.get::<ClientCertificate>().cloned();
+
         let header =
             TypedHeader::<Authorization<Basic>>::from_request_parts(&mut parts, state).await;
 
@@ -420,8 +481,16 @@ where
             }
 
             (None, Some(client_id), None, None, None) => {
-                // Only got a client_id in the form
-                Credentials::None { client_id }
+                // Only got a client_id in the form, the client may have authenticated with
+                // its certificate
+                if let Some(certificate) = certificate {
+                    Credentials::ClientCertificate {
+                        client_id,
+                        certificate,
+                    }
+                } else {
+                    Credentials::None { client_id }
+                }
             }
 
             (
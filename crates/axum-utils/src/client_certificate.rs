@@ -0,0 +1,420 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Client certificates forwarded by the reverse proxy terminating TLS, used
+//! for mutual-TLS client authentication and certificate-bound access tokens,
+//! as defined in [RFC 8705](https://www.rfc-editor.org/rfc/rfc8705.html)
+
+use data_encoding::{BASE64, BASE64URL_NOPAD, HEXLOWER};
+use der::{
+    asn1::{AnyRef, ObjectIdentifier},
+    Decode, Encode, Header, Reader, SliceReader, Tag, TagNumber, Tagged,
+};
+use http::HeaderValue;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const PEM_HEADER: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_FOOTER: &str = "-----END CERTIFICATE-----";
+
+/// Short names of the usual attribute types in distinguished names, as
+/// defined in RFC 4514 section 3
+const ATTRIBUTE_TYPES: &[(ObjectIdentifier, &str)] = &[
+    (ObjectIdentifier::new_unwrap("2.5.4.3"), "CN"),
+    (ObjectIdentifier::new_unwrap("2.5.4.6"), "C"),
+    (ObjectIdentifier::new_unwrap("2.5.4.7"), "L"),
+    (ObjectIdentifier::new_unwrap("2.5.4.8"), "ST"),
+    (ObjectIdentifier::new_unwrap("2.5.4.9"), "STREET"),
+    (ObjectIdentifier::new_unwrap("2.5.4.10"), "O"),
+    (ObjectIdentifier::new_unwrap("2.5.4.11"), "OU"),
+    (
+        ObjectIdentifier::new_unwrap("0.9.2342.19200300.100.1.1"),
+        "UID",
+    ),
+    (
+        ObjectIdentifier::new_unwrap("0.9.2342.19200300.100.1.25"),
+        "DC",
+    ),
+];
+
+#[derive(Debug, Error)]
+pub enum ClientCertificateError {
+    #[error("the client certificate is not correctly encoded")]
+    InvalidEncoding,
+
+    #[error("the client certificate could not be parsed")]
+    InvalidCertificate(#[from] der::Error),
+}
+
+/// A client certificate, as verified by the reverse proxy terminating TLS
+#[derive(Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    der: Vec<u8>,
+    subject_dn: String,
+}
+
+impl std::fmt::Debug for ClientCertificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientCertificate")
+            .field("subject_dn", &self.subject_dn)
+            .field("thumbprint", &self.thumbprint())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientCertificate {
+    /// Parse a DER-encoded X.509 certificate
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate could not be parsed
+    pub fn from_der(der: Vec<u8>) -> Result<Self, ClientCertificateError> {
+        let subject_dn = parse_subject_dn(&der)?;
+        Ok(Self { der, subject_dn })
+    }
+
+    /// Parse a certificate from the value of the header set by the reverse
+    /// proxy
+    ///
+    /// The certificate can either be PEM-encoded, optionally URL-encoded like
+    /// the `$ssl_client_escaped_cert` variable of nginx, or base64-encoded
+    /// DER.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header value is not a valid certificate
+    pub fn from_header_value(value: &HeaderValue) -> Result<Self, ClientCertificateError> {
+        let value = value
+            .to_str()
+            .map_err(|_| ClientCertificateError::InvalidEncoding)?;
+        let value =
+            urlencoding::decode(value).map_err(|_| ClientCertificateError::InvalidEncoding)?;
+        let value = value.trim();
+
+        // Strip the PEM armor if there is one. Some proxies replace the newlines with
+        // spaces, so all whitespaces are ignored
+        let body = if let Some(rest) = value.strip_prefix(PEM_HEADER) {
+            rest.split_once(PEM_FOOTER)
+                .ok_or(ClientCertificateError::InvalidEncoding)?
+                .0
+        } else {
+            value
+        };
+
+        let body: String = body.split_ascii_whitespace().collect();
+        let der = BASE64
+            .decode(body.as_bytes())
+            .map_err(|_| ClientCertificateError::InvalidEncoding)?;
+
+        Self::from_der(der)
+    }
+
+    /// The DER encoding of the certificate
+    #[must_use]
+    pub fn as_der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// The subject of the certificate, formatted as described in RFC 4514
+    #[must_use]
+    pub fn subject_dn(&self) -> &str {
+        &self.subject_dn
+    }
+
+    /// The base64url-encoded SHA-256 thumbprint of the certificate, used as
+    /// the `x5t#S256` confirmation method of certificate-bound tokens
+    #[must_use]
+    pub fn thumbprint(&self) -> String {
+        BASE64URL_NOPAD.encode(&Sha256::digest(&self.der))
+    }
+
+    /// Check if the subject of the certificate matches the given
+    /// distinguished name
+    ///
+    /// Both are normalised before being compared, so that the comparison
+    /// ignores the case of the values and the whitespaces around the
+    /// separators.
+    #[must_use]
+    pub fn subject_dn_matches(&self, expected: &str) -> bool {
+        match (normalize_dn(&self.subject_dn), normalize_dn(expected)) {
+            (Some(actual), Some(expected)) => actual == expected,
+            _ => false,
+        }
+    }
+}
+
+/// Extract the subject of a DER-encoded X.509 certificate, and format it as
+/// described in RFC 4514
+fn parse_subject_dn(der: &[u8]) -> Result<String, der::Error> {
+    let mut reader = SliceReader::new(der)?;
+    let subject = reader.sequence(|certificate| {
+        let subject = certificate.sequence(|tbs_certificate| {
+            // The version is optional, and explicitly tagged with [0]
+            let version_tag = Tag::ContextSpecific {
+                constructed: true,
+                number: TagNumber::N0,
+            };
+            if tbs_certificate.peek_tag()? == version_tag {
+                AnyRef::decode(tbs_certificate)?;
+            }
+
+            // Skip the serial number, the signature algorithm, the issuer and the
+            // validity
+            for _ in 0..4 {
+                AnyRef::decode(tbs_certificate)?;
+            }
+
+            let subject = AnyRef::decode(tbs_certificate)?;
+            subject.tag().assert_eq(Tag::Sequence)?;
+
+            // Skip the rest of the certificate content
+            while !tbs_certificate.is_finished() {
+                tbs_certificate.tlv_bytes()?;
+            }
+
+            Ok(subject)
+        })?;
+
+        // Skip the signature
+        while !certificate.is_finished() {
+            certificate.tlv_bytes()?;
+        }
+
+        Ok(subject)
+    })?;
+    let subject = reader.finish(subject)?;
+
+    format_name(subject.value())
+}
+
+/// Format the content of an X.509 `Name` as described in RFC 4514
+fn format_name(name: &[u8]) -> Result<String, der::Error> {
+    let mut reader = SliceReader::new(name)?;
+    let mut rdns = Vec::new();
+
+    while !reader.is_finished() {
+        let header = Header::decode(&mut reader)?;
+        header.tag.assert_eq(Tag::Set)?;
+
+        let rdn = reader.read_nested(header.length, |set| {
+            let mut attributes = Vec::new();
+            while !set.is_finished() {
+                let attribute = set.sequence(|attribute| {
+                    let attribute_type = ObjectIdentifier::decode(attribute)?;
+                    let value = AnyRef::decode(attribute)?;
+                    Ok(format!(
+                        "{}={}",
+                        format_attribute_type(&attribute_type),
+                        format_attribute_value(&value)?
+                    ))
+                })?;
+                attributes.push(attribute);
+            }
+
+            Ok(attributes.join("+"))
+        })?;
+
+        rdns.push(rdn);
+    }
+
+    // The RDNs are in the reverse order in the string representation
+    rdns.reverse();
+    Ok(rdns.join(","))
+}
+
+fn format_attribute_type(attribute_type: &ObjectIdentifier) -> String {
+    ATTRIBUTE_TYPES
+        .iter()
+        .find(|(oid, _)| oid == attribute_type)
+        .map_or_else(
+            || attribute_type.to_string(),
+            |(_, name)| (*name).to_owned(),
+        )
+}
+
+fn format_attribute_value(value: &AnyRef<'_>) -> Result<String, der::Error> {
+    let string = match value.tag() {
+        Tag::Utf8String
+        | Tag::PrintableString
+        | Tag::Ia5String
+        | Tag::TeletexString
+        | Tag::VisibleString => std::str::from_utf8(value.value()).ok(),
+        _ => None,
+    };
+
+    let Some(string) = string else {
+        // Values which aren't strings are represented by their hex-encoded DER
+        return Ok(format!("#{}", HEXLOWER.encode(&value.to_der()?)));
+    };
+
+    let mut escaped = String::with_capacity(string.len());
+    for (index, c) in string.char_indices() {
+        let leading = index == 0;
+        let trailing = index + c.len_utf8() == string.len();
+        match c {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' if leading => escaped.push_str("\\#"),
+            ' ' if leading || trailing => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            c => escaped.push(c),
+        }
+    }
+
+    Ok(escaped)
+}
+
+/// Parse the string representation of a distinguished name into a list of
+/// RDNs, each being a sorted list of attribute types and values, so that
+/// different representations of the same name can be compared
+fn normalize_dn(dn: &str) -> Option<Vec<Vec<(String, String)>>> {
+    let mut rdns = Vec::new();
+    let mut rdn = Vec::new();
+    let mut attribute_type = None;
+    let mut current = Vec::new();
+
+    let mut bytes = dn.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' => {
+                let next = bytes.next()?;
+                if next.is_ascii_hexdigit() {
+                    let low = bytes.next()?;
+                    let pair = [next, low];
+                    let pair = std::str::from_utf8(&pair).ok()?;
+                    current.push(u8::from_str_radix(pair, 16).ok()?);
+                } else {
+                    current.push(next);
+                }
+            }
+
+            b'=' if attribute_type.is_none() => {
+                attribute_type = Some(normalize_attribute_type(&current)?);
+                current.clear();
+            }
+
+            b',' | b'+' => {
+                rdn.push((attribute_type.take()?, normalize_attribute_value(&current)?));
+                current.clear();
+
+                if byte == b',' {
+                    rdn.sort();
+                    rdns.push(std::mem::take(&mut rdn));
+                }
+            }
+
+            byte => current.push(byte),
+        }
+    }
+
+    rdn.push((attribute_type.take()?, normalize_attribute_value(&current)?));
+    rdn.sort();
+    rdns.push(rdn);
+
+    Some(rdns)
+}
+
+fn normalize_attribute_type(attribute_type: &[u8]) -> Option<String> {
+    let attribute_type = std::str::from_utf8(attribute_type).ok()?.trim();
+    if attribute_type.is_empty() {
+        return None;
+    }
+
+    // Attribute types can also be given by their OID
+    let attribute_type = attribute_type.parse::<ObjectIdentifier>().map_or_else(
+        |_| attribute_type.to_owned(),
+        |oid| format_attribute_type(&oid),
+    );
+
+    Some(attribute_type.to_ascii_uppercase())
+}
+
+fn normalize_attribute_value(value: &[u8]) -> Option<String> {
+    let value = std::str::from_utf8(value).ok()?;
+    Some(value.trim().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIB/jCCAaWgAwIBAgIUChiSy/CKd40GMrMOK/g+VQJb8jowCgYIKoZIzj0EAwIw
+VDELMAkGA1UEBhMCR0IxFjAUBgNVBAoMDUV4YW1wbGUsIEluYy4xEDAOBgNVBAsM
+B0JhY2tlbmQxGzAZBgNVBAMMEmNsaWVudC5leGFtcGxlLmNvbTAgFw0yNjEwMTYx
+MDM3MjBaGA8yMTI2MDkyMjEwMzcyMFowVDELMAkGA1UEBhMCR0IxFjAUBgNVBAoM
+DUV4YW1wbGUsIEluYy4xEDAOBgNVBAsMB0JhY2tlbmQxGzAZBgNVBAMMEmNsaWVu
+dC5leGFtcGxlLmNvbTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABFcprwfWm869
+2DX2lot1/6J3rZTyfXKz+Nus8P14B/nrqULnjVfgmPa0Juf+2SmJuYBguBkSPHwR
+UWyAqwVoOjSjUzBRMB0GA1UdDgQWBBTBv4R81VHAPt3N5Mlbn2A3r+s2+DAfBgNV
+HSMEGDAWgBTBv4R81VHAPt3N5Mlbn2A3r+s2+DAPBgNVHRMBAf8EBTADAQH/MAoG
+CCqGSM49BAMCA0cAMEQCIDCcK1foQ1WWwCYnx5pIG4NFCvlEJLWM8+25MaOD9O5d
+AiBHDVdVBmEMshpHuP/MHxbcmOOPvf3wk8Mb8OStmciAlQ==
+-----END CERTIFICATE-----
+";
+
+    const SUBJECT_DN: &str = "CN=client.example.com,OU=Backend,O=Example\\, Inc.,C=GB";
+    const THUMBPRINT: &str = "MYrq53l-kG_lI8063ZrSr0K3VgFyGrjxV3CiNXkjgyA";
+
+    #[test]
+    fn parse_pem() {
+        let value = HeaderValue::from_str(&CERTIFICATE.replace('\n', " ")).unwrap();
+        let certificate = ClientCertificate::from_header_value(&value).unwrap();
+        assert_eq!(certificate.subject_dn(), SUBJECT_DN);
+        assert_eq!(certificate.thumbprint(), THUMBPRINT);
+    }
+
+    #[test]
+    fn parse_url_encoded_pem() {
+        let value = urlencoding::encode(CERTIFICATE);
+        let value = HeaderValue::from_str(&value).unwrap();
+        let certificate = ClientCertificate::from_header_value(&value).unwrap();
+        assert_eq!(certificate.subject_dn(), SUBJECT_DN);
+        assert_eq!(certificate.thumbprint(), THUMBPRINT);
+    }
+
+    #[test]
+    fn parse_base64_der() {
+        let body: String = CERTIFICATE
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let value = HeaderValue::from_str(&body).unwrap();
+        let certificate = ClientCertificate::from_header_value(&value).unwrap();
+        assert_eq!(certificate.subject_dn(), SUBJECT_DN);
+        assert_eq!(certificate.thumbprint(), THUMBPRINT);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let value = HeaderValue::from_static("not a certificate");
+        assert!(ClientCertificate::from_header_value(&value).is_err());
+
+        let value = HeaderValue::from_static("aGVsbG8=");
+        assert!(ClientCertificate::from_header_value(&value).is_err());
+    }
+
+    #[test]
+    fn subject_dn_matching() {
+        let value = HeaderValue::from_str(&CERTIFICATE.replace('\n', " ")).unwrap();
+        let certificate = ClientCertificate::from_header_value(&value).unwrap();
+
+        assert!(certificate.subject_dn_matches(SUBJECT_DN));
+        assert!(certificate
+            .subject_dn_matches("cn=Client.Example.com, ou=Backend, o=Example\\2C Inc., c=GB"));
+        assert!(certificate.subject_dn_matches(
+            "2.5.4.3=client.example.com,2.5.4.11=Backend,2.5.4.10=Example\\, Inc.,2.5.4.6=GB"
+        ));
+
+        assert!(!certificate.subject_dn_matches("CN=client.example.com"));
+        assert!(!certificate
+            .subject_dn_matches("CN=other.example.com,OU=Backend,O=Example\\, Inc.,C=GB"));
+        assert!(!certificate
+            .subject_dn_matches("C=GB,O=Example\\, Inc.,OU=Backend,CN=client.example.com"));
+        assert!(!certificate.subject_dn_matches("not a DN"));
+    }
+}
@@ -138,6 +138,14 @@ impl CookieJar {
         self
     }
 
+    /// Remove a cookie from the jar
+    #[must_use]
+    pub fn remove(mut self, key: &str) -> Self {
+        let cookie = self.options.apply(Cookie::from(key.to_owned()));
+        self.inner = self.inner.remove(cookie);
+        self
+    }
+
     /// Load and deserialize a cookie from the jar
     ///
     /// Returns `None` if the cookie is not present
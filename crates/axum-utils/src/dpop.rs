@@ -0,0 +1,168 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Validation of the [DPoP] proofs sent by clients to bind tokens to a key
+//! they hold
+//!
+//! [DPoP]: https://www.rfc-editor.org/rfc/rfc9449
+
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use http::{HeaderMap, HeaderName, Method};
+use mas_jose::{
+    jwa::{AsymmetricVerifyingKey, SUPPORTED_ASYMMETRIC_SIGNING_ALGORITHMS},
+    jwt::Jwt,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+/// The name of the header carrying the DPoP proof
+pub static DPOP: HeaderName = HeaderName::from_static("dpop");
+
+/// The `typ` header value of DPoP proofs
+const DPOP_JWT_TYPE: &str = "dpop+jwt";
+
+/// How long after being issued a proof is accepted
+const MAX_AGE: Duration = Duration::minutes(5);
+
+/// How much in the future a proof can be issued, to account for clock skew
+const LEEWAY: Duration = Duration::seconds(30);
+
+#[derive(Debug, Error)]
+pub enum DPoPProofError {
+    #[error("multiple DPoP proofs were provided")]
+    MultipleProofs,
+
+    #[error("the DPoP proof is not a valid JWT")]
+    InvalidJwt,
+
+    #[error("the DPoP proof has the wrong type")]
+    InvalidType,
+
+    #[error("the DPoP proof is signed with an unsupported algorithm")]
+    UnsupportedAlgorithm,
+
+    #[error("the DPoP proof does not include a suitable public key")]
+    InvalidKey,
+
+    #[error("the DPoP proof has an invalid signature")]
+    InvalidSignature,
+
+    #[error("the DPoP proof was made for another request")]
+    RequestMismatch,
+
+    #[error("the DPoP proof was not issued recently")]
+    Expired,
+
+    #[error("the DPoP proof was made for another access token")]
+    AccessTokenMismatch,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DPoPProofClaims {
+    #[allow(dead_code)]
+    jti: String,
+    htm: String,
+    htu: Url,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    iat: DateTime<Utc>,
+    #[serde(default)]
+    ath: Option<String>,
+}
+
+/// A DPoP proof, as sent by a client in the `DPoP` header
+#[derive(Debug, Clone)]
+pub struct DPoPProof {
+    jwt: Jwt<'static, DPoPProofClaims>,
+}
+
+impl DPoPProof {
+    /// Parse the DPoP proof out of the request headers, if there is one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are multiple proofs, or if the proof is not a
+    /// valid JWT
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, DPoPProofError> {
+        let mut values = headers.get_all(&DPOP).iter();
+        let Some(value) = values.next() else {
+            return Ok(None);
+        };
+
+        if values.next().is_some() {
+            return Err(DPoPProofError::MultipleProofs);
+        }
+
+        let value = value.to_str().map_err(|_| DPoPProofError::InvalidJwt)?;
+        let jwt = Jwt::try_from(value.to_owned()).map_err(|_| DPoPProofError::InvalidJwt)?;
+
+        Ok(Some(Self { jwt }))
+    }
+
+    /// Verify the proof for the given request, and return the thumbprint of
+    /// the key it was signed with
+    ///
+    /// If the proof is presented along with an access token, it must include
+    /// the hash of that token.
+    ///
+    /// Replays are not detected, but are limited by only accepting recently
+    /// issued proofs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the proof is not valid for this request
+    pub fn verify(
+        &self,
+        now: DateTime<Utc>,
+        method: &Method,
+        url: &Url,
+        access_token: Option<&str>,
+    ) -> Result<String, DPoPProofError> {
+        let header = self.jwt.header();
+        if header.typ() != Some(DPOP_JWT_TYPE) {
+            return Err(DPoPProofError::InvalidType);
+        }
+
+        // Only asymmetric algorithms make sense here, as the key is public
+        if !SUPPORTED_ASYMMETRIC_SIGNING_ALGORITHMS.contains(header.alg()) {
+            return Err(DPoPProofError::UnsupportedAlgorithm);
+        }
+
+        let jwk = header.jwk().ok_or(DPoPProofError::InvalidKey)?;
+        let key = AsymmetricVerifyingKey::from_jwk_and_alg(jwk.params(), header.alg())
+            .map_err(|_| DPoPProofError::InvalidKey)?;
+        self.jwt
+            .verify(&key)
+            .map_err(|_| DPoPProofError::InvalidSignature)?;
+
+        let claims = self.jwt.payload();
+
+        // The proof is bound to the URL without its query and fragment
+        let mut htu = claims.htu.clone();
+        htu.set_query(None);
+        htu.set_fragment(None);
+        let mut expected_htu = url.clone();
+        expected_htu.set_query(None);
+        expected_htu.set_fragment(None);
+        if claims.htm != method.as_str() || htu != expected_htu {
+            return Err(DPoPProofError::RequestMismatch);
+        }
+
+        if claims.iat > now + LEEWAY || claims.iat < now - MAX_AGE {
+            return Err(DPoPProofError::Expired);
+        }
+
+        if let Some(access_token) = access_token {
+            let hash = BASE64URL_NOPAD.encode(&Sha256::digest(access_token.as_bytes()));
+            if claims.ath.as_deref() != Some(hash.as_str()) {
+                return Err(DPoPProofError::AccessTokenMismatch);
+            }
+        }
+
+        Ok(jwk.params().thumbprint())
+    }
+}
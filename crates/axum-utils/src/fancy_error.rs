@@ -12,6 +12,7 @@ use axum::{
 use axum_extra::typed_header::TypedHeader;
 use headers::ContentType;
 use mas_templates::ErrorContext;
+use mas_tower::RequestId;
 
 use crate::sentry::SentryEventID;
 
@@ -54,13 +55,20 @@ impl<E: std::fmt::Debug + std::fmt::Display> From<E> for FancyError {
 
 impl IntoResponse for FancyError {
     fn into_response(self) -> Response {
-        let error = format!("{}", self.context);
+        let context = match RequestId::current() {
+            Some(request_id) if self.context.request_id().is_none() => {
+                self.context.with_request_id(request_id.to_string())
+            }
+            _ => self.context,
+        };
+
+        let error = format!("{context}");
         let event_id = sentry::capture_message(&error, sentry::Level::Error);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             TypedHeader(ContentType::text()),
             SentryEventID::from(event_id),
-            Extension(self.context),
+            Extension(context),
             error,
         )
             .into_response()
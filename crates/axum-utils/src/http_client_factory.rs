@@ -4,11 +4,13 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::HashMap;
+
 use http_body_util::Full;
 use hyper_util::rt::TokioExecutor;
 use mas_http::{
-    make_traced_connector, BodyToBytesResponseLayer, Client, ClientLayer, ClientService,
-    HttpService, TracedClient, TracedConnector,
+    make_restricted_traced_connector, make_traced_connector, BodyToBytesResponseLayer, Client,
+    ClientLayer, ClientService, HttpService, OutboundPolicy, TracedClient, TracedConnector,
 };
 use tower::{
     util::{MapErrLayer, MapRequestLayer},
@@ -18,6 +20,8 @@ use tower::{
 #[derive(Debug, Clone)]
 pub struct HttpClientFactory {
     traced_connector: TracedConnector,
+    restricted_connector: TracedConnector,
+    category_connectors: HashMap<&'static str, TracedConnector>,
     client_layer: ClientLayer,
 }
 
@@ -33,10 +37,32 @@ impl HttpClientFactory {
     pub fn new() -> Self {
         Self {
             traced_connector: make_traced_connector(),
+            restricted_connector: make_restricted_traced_connector(OutboundPolicy::new()),
+            category_connectors: HashMap::new(),
             client_layer: ClientLayer::new(),
         }
     }
 
+    /// Set the [`OutboundPolicy`] applied to restricted clients
+    #[must_use]
+    pub fn with_outbound_policy(mut self, policy: OutboundPolicy) -> Self {
+        self.restricted_connector = make_restricted_traced_connector(policy);
+        self
+    }
+
+    /// Set the [`OutboundPolicy`] applied to restricted clients of a specific
+    /// category, instead of the default one
+    #[must_use]
+    pub fn with_category_outbound_policy(
+        mut self,
+        category: &'static str,
+        policy: OutboundPolicy,
+    ) -> Self {
+        self.category_connectors
+            .insert(category, make_restricted_traced_connector(policy));
+        self
+    }
+
     /// Constructs a new HTTP client
     pub fn client<B>(&self, category: &'static str) -> ClientService<TracedClient<B>>
     where
@@ -50,6 +76,27 @@ impl HttpClientFactory {
             .layer(client)
     }
 
+    /// Constructs a new HTTP client, restricted by the [`OutboundPolicy`] of
+    /// its category.
+    ///
+    /// This must be used for requests to URLs which are set by users or
+    /// operators, like webhook endpoints or remote avatars.
+    pub fn restricted_client<B>(&self, category: &'static str) -> ClientService<TracedClient<B>>
+    where
+        B: axum::body::HttpBody + Send,
+        B::Data: Send,
+    {
+        let connector = self
+            .category_connectors
+            .get(category)
+            .unwrap_or(&self.restricted_connector);
+        let client = Client::builder(TokioExecutor::new()).build(connector.clone());
+        self.client_layer
+            .clone()
+            .with_category(category)
+            .layer(client)
+    }
+
     /// Constructs a new [`HttpService`], suitable for `mas-oidc-client`
     pub fn http_service(&self, category: &'static str) -> HttpService {
         let client = self.client(category);
@@ -8,8 +8,10 @@
 #![allow(clippy::module_name_repetitions)]
 
 pub mod client_authorization;
+pub mod client_certificate;
 pub mod cookies;
 pub mod csrf;
+pub mod dpop;
 pub mod error_wrapper;
 pub mod fancy_error;
 pub mod http_client_factory;
@@ -4,8 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use mas_data_model::BrowserSession;
-use mas_storage::{user::BrowserSessionRepository, RepositoryAccess};
+use mas_data_model::{BrowserSession, SiteConfig};
+use mas_storage::{user::BrowserSessionRepository, Clock, RepositoryAccess};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
@@ -15,6 +15,11 @@ use crate::cookies::CookieJar;
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct SessionInfo {
     current: Option<Ulid>,
+
+    /// The token proving the browser holds the credential the session is
+    /// bound to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    binding: Option<String>,
 }
 
 impl SessionInfo {
@@ -23,6 +28,10 @@ impl SessionInfo {
     pub fn from_session(session: &BrowserSession) -> Self {
         Self {
             current: Some(session.id),
+            binding: session
+                .binding
+                .as_ref()
+                .map(|binding| binding.token.clone()),
         }
     }
 
@@ -30,11 +39,16 @@ impl SessionInfo {
     #[must_use]
     pub fn mark_session_ended(mut self) -> Self {
         self.current = None;
+        self.binding = None;
         self
     }
 
     /// Load the [`BrowserSession`] from database
     ///
+    /// Sessions bound to a credential are only loaded if the cookie holds the
+    /// current binding token. Sessions which went past their maximum lifetime,
+    /// or which stayed inactive for too long, are not loaded either.
+    ///
     /// # Errors
     ///
     /// Returns an error if the session is not found or if the session is not
@@ -42,6 +56,8 @@ impl SessionInfo {
     pub async fn load_session<E>(
         &self,
         repo: &mut impl RepositoryAccess<Error = E>,
+        clock: &impl Clock,
+        site_config: &SiteConfig,
     ) -> Result<Option<BrowserSession>, E> {
         let Some(session_id) = self.current else {
             return Ok(None);
@@ -52,7 +68,17 @@ impl SessionInfo {
             .lookup(session_id)
             .await?
             // Ensure that the session is still active
-            .filter(BrowserSession::active);
+            .filter(BrowserSession::active)
+            // Ensure that the session didn't expire, even if it wasn't ended yet
+            .filter(|session| !site_config.is_browser_session_expired(session, clock.now()))
+            // Ensure that the cookie wasn't copied out of the browser holding
+            // the credential the session is bound to
+            .filter(|session| {
+                session
+                    .binding
+                    .as_ref()
+                    .map_or(true, |binding| binding.accepts(self.binding.as_deref()))
+            });
 
         Ok(maybe_session)
     }
@@ -10,13 +10,15 @@ use async_trait::async_trait;
 use axum::{
     extract::{
         rejection::{FailedToDeserializeForm, FormRejection},
-        Form, FromRequest, FromRequestParts,
+        Form, FromRequest,
     },
     response::{IntoResponse, Response},
 };
-use axum_extra::typed_header::{TypedHeader, TypedHeaderRejectionReason};
-use headers::{authorization::Bearer, Authorization, Header, HeaderMapExt, HeaderName};
-use http::{header::WWW_AUTHENTICATE, HeaderMap, HeaderValue, Request, StatusCode};
+use headers::{Header, HeaderMapExt, HeaderName};
+use http::{
+    header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    HeaderMap, HeaderValue, Method, Request, StatusCode,
+};
 use mas_data_model::Session;
 use mas_storage::{
     oauth2::{OAuth2AccessTokenRepository, OAuth2SessionRepository},
@@ -24,6 +26,9 @@ use mas_storage::{
 };
 use serde::{de::DeserializeOwned, Deserialize};
 use thiserror::Error;
+use url::Url;
+
+use crate::{client_certificate::ClientCertificate, dpop::DPoPProof};
 
 #[derive(Debug, Deserialize)]
 struct AuthorizedForm<F> {
@@ -38,6 +43,8 @@ struct AuthorizedForm<F> {
 enum AccessToken {
     Form(String),
     Header(String),
+    /// A token sent with the `DPoP` authorization scheme, along with its proof
+    DPoP(String),
     None,
 }
 
@@ -47,7 +54,7 @@ impl AccessToken {
         repo: &mut impl RepositoryAccess<Error = E>,
     ) -> Result<(mas_data_model::AccessToken, Session), AuthorizationVerificationError<E>> {
         let token = match self {
-            AccessToken::Form(t) | AccessToken::Header(t) => t,
+            AccessToken::Form(t) | AccessToken::Header(t) | AccessToken::DPoP(t) => t,
             AccessToken::None => return Err(AuthorizationVerificationError::MissingToken),
         };
 
@@ -70,6 +77,9 @@ impl AccessToken {
 #[derive(Debug)]
 pub struct UserAuthorization<F = ()> {
     access_token: AccessToken,
+    dpop: Option<DPoPProof>,
+    certificate: Option<ClientCertificate>,
+    method: Method,
     form: Option<F>,
 }
 
@@ -78,6 +88,11 @@ impl<F: Send> UserAuthorization<F> {
     /// Verify a user authorization and return the session and the protected
     /// form value
     ///
+    /// The `url` is the public URL of the protected resource, against which
+    /// DPoP proofs are checked. If the access token is restricted to a
+    /// narrower scope than its session, the scope of the returned session is
+    /// the one of the token.
+    ///
     /// # Errors
     ///
     /// Returns an error if the token is invalid, if the user session ended or
@@ -86,7 +101,29 @@ impl<F: Send> UserAuthorization<F> {
         self,
         repo: &mut impl RepositoryAccess<Error = E>,
         clock: &impl Clock,
+        url: &Url,
     ) -> Result<(Session, F), AuthorizationVerificationError<E>> {
+        let (token, session, form) = self.protected_form_token(repo, clock, url).await?;
+        Ok((token.restrict_session(session), form))
+    }
+
+    /// Verify a user authorization and return the access token, its session
+    /// and the protected form value
+    ///
+    /// Unlike [`Self::protected_form`], the scope of the session is left
+    /// untouched, so callers must check whether the token is restricted
+    /// themselves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token is invalid, if the user session ended or
+    /// if the form is missing
+    pub async fn protected_form_token<E>(
+        self,
+        repo: &mut impl RepositoryAccess<Error = E>,
+        clock: &impl Clock,
+        url: &Url,
+    ) -> Result<(mas_data_model::AccessToken, Session, F), AuthorizationVerificationError<E>> {
         let Some(form) = self.form else {
             return Err(AuthorizationVerificationError::MissingForm);
         };
@@ -97,12 +134,28 @@ impl<F: Send> UserAuthorization<F> {
             return Err(AuthorizationVerificationError::InvalidToken);
         }
 
-        Ok((session, form))
+        verify_dpop(
+            &self.access_token,
+            &token,
+            self.dpop.as_ref(),
+            &self.method,
+            url,
+            clock,
+        )?;
+
+        verify_certificate(&token, self.certificate.as_ref())?;
+
+        Ok((token, session, form))
     }
 
     // TODO: take scopes to validate as parameter
     /// Verify a user authorization and return the session
     ///
+    /// The `url` is the public URL of the protected resource, against which
+    /// DPoP proofs are checked. If the access token is restricted to a
+    /// narrower scope than its session, the scope of the returned session is
+    /// the one of the token.
+    ///
     /// # Errors
     ///
     /// Returns an error if the token is invalid or if the user session ended
@@ -110,6 +163,7 @@ impl<F: Send> UserAuthorization<F> {
         self,
         repo: &mut impl RepositoryAccess<Error = E>,
         clock: &impl Clock,
+        url: &Url,
     ) -> Result<Session, AuthorizationVerificationError<E>> {
         let (token, session) = self.access_token.fetch(repo).await?;
 
@@ -117,8 +171,68 @@ impl<F: Send> UserAuthorization<F> {
             return Err(AuthorizationVerificationError::InvalidToken);
         }
 
-        Ok(session)
+        verify_dpop(
+            &self.access_token,
+            &token,
+            self.dpop.as_ref(),
+            &self.method,
+            url,
+            clock,
+        )?;
+
+        verify_certificate(&token, self.certificate.as_ref())?;
+
+        Ok(token.restrict_session(session))
+    }
+}
+
+/// Check that DPoP-bound tokens are sent with the `DPoP` scheme and a proof
+/// made with the key they are bound to, and that only those are sent with the
+/// `DPoP` scheme
+fn verify_dpop<E>(
+    access_token: &AccessToken,
+    token: &mas_data_model::AccessToken,
+    proof: Option<&DPoPProof>,
+    method: &Method,
+    url: &Url,
+    clock: &impl Clock,
+) -> Result<(), AuthorizationVerificationError<E>> {
+    match (access_token, token.dpop_jkt.as_deref()) {
+        (AccessToken::DPoP(t), Some(expected_jkt)) => {
+            let proof = proof.ok_or(AuthorizationVerificationError::InvalidDPoPProof)?;
+            let jkt = proof
+                .verify(clock.now(), method, url, Some(t))
+                .map_err(|_| AuthorizationVerificationError::InvalidDPoPProof)?;
+
+            if jkt != expected_jkt {
+                return Err(AuthorizationVerificationError::InvalidDPoPProof);
+            }
+
+            Ok(())
+        }
+        (AccessToken::DPoP(_), None) | (_, Some(_)) => {
+            Err(AuthorizationVerificationError::InvalidToken)
+        }
+        (_, None) => Ok(()),
+    }
+}
+
+/// Check that certificate-bound tokens are presented along with the client
+/// certificate they are bound to
+fn verify_certificate<E>(
+    token: &mas_data_model::AccessToken,
+    certificate: Option<&ClientCertificate>,
+) -> Result<(), AuthorizationVerificationError<E>> {
+    let Some(expected_thumbprint) = token.x5t_s256.as_deref() else {
+        return Ok(());
+    };
+
+    let certificate = certificate.ok_or(AuthorizationVerificationError::InvalidToken)?;
+    if certificate.thumbprint() != expected_thumbprint {
+        return Err(AuthorizationVerificationError::InvalidToken);
     }
+
+    Ok(())
 }
 
 pub enum UserAuthorizationError {
@@ -139,6 +253,9 @@ pub enum AuthorizationVerificationError<E> {
     #[error("missing form")]
     MissingForm,
 
+    #[error("invalid DPoP proof")]
+    InvalidDPoPProof,
+
     #[error(transparent)]
     Internal(#[from] E),
 }
@@ -175,12 +292,17 @@ impl BearerError {
 
 enum WwwAuthenticate {
     #[allow(dead_code)]
-    Basic { realm: HeaderValue },
+    Basic {
+        realm: HeaderValue,
+    },
     Bearer {
         realm: Option<HeaderValue>,
         error: BearerError,
         error_description: Option<HeaderValue>,
     },
+    DPoP {
+        error: HeaderValue,
+    },
 }
 
 impl Header for WwwAuthenticate {
@@ -221,6 +343,11 @@ impl Header for WwwAuthenticate {
 
                 ("Bearer", params)
             }
+            WwwAuthenticate::DPoP { error } => {
+                let mut params = HashMap::new();
+                params.insert("error", error.clone());
+                ("DPoP", params)
+            }
         };
 
         let params = params.into_iter().map(|(k, v)| format!(" {k}={v:?}"));
@@ -274,6 +401,14 @@ where
                 });
                 (StatusCode::BAD_REQUEST, headers).into_response()
             }
+            Self::InvalidDPoPProof => {
+                let mut headers = HeaderMap::new();
+
+                headers.typed_insert(WwwAuthenticate::DPoP {
+                    error: HeaderValue::from_static("invalid_dpop_proof"),
+                });
+                (StatusCode::UNAUTHORIZED, headers).into_response()
+            }
             Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
         }
     }
@@ -291,21 +426,39 @@ where
         req: Request<axum::body::Body>,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let (mut parts, body) = req.into_parts();
-        let header =
-            TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, state).await;
-
-        // Take the Authorization header
-        let token_from_header = match header {
-            Ok(header) => Some(header.token().to_owned()),
-            Err(err) => match err.reason() {
-                // If it's missing it is fine
-                TypedHeaderRejectionReason::Missing => None,
-                // If the header could not be parsed, return the error
-                _ => return Err(UserAuthorizationError::InvalidHeader),
-            },
+        let (parts, body) = req.into_parts();
+
+        // Take the Authorization header, which can either use the `Bearer` or the
+        // `DPoP` scheme
+        let token_from_header = match parts.headers.get(AUTHORIZATION) {
+            Some(value) => {
+                let (scheme, token) = value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.split_once(' '))
+                    .ok_or(UserAuthorizationError::InvalidHeader)?;
+                let token = token.trim();
+                if token.is_empty() {
+                    return Err(UserAuthorizationError::InvalidHeader);
+                }
+
+                if scheme.eq_ignore_ascii_case("bearer") {
+                    Some(AccessToken::Header(token.to_owned()))
+                } else if scheme.eq_ignore_ascii_case("dpop") {
+                    Some(AccessToken::DPoP(token.to_owned()))
+                } else {
+                    return Err(UserAuthorizationError::InvalidHeader);
+                }
+            }
+            // If it's missing it is fine
+            None => None,
         };
 
+        let dpop = DPoPProof::from_headers(&parts.headers)
+            .map_err(|_| UserAuthorizationError::InvalidHeader)?;
+        let certificate = parts.extensions.get::<ClientCertificate>().cloned();
+        let method = parts.method.clone();
+
         let req = Request::from_parts(parts, body);
 
         // Take the form value
@@ -325,11 +478,17 @@ where
         let access_token = match (token_from_header, token_from_form) {
             // Ensure the token should not be in both the form and the access token
             (Some(_), Some(_)) => return Err(UserAuthorizationError::TokenInFormAndHeader),
-            (Some(t), None) => AccessToken::Header(t),
+            (Some(t), None) => t,
             (None, Some(t)) => AccessToken::Form(t),
             (None, None) => AccessToken::None,
         };
 
-        Ok(UserAuthorization { access_token, form })
+        Ok(UserAuthorization {
+            access_token,
+            dpop,
+            certificate,
+            method,
+            form,
+        })
     }
 }
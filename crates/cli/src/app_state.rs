@@ -9,22 +9,29 @@ use std::{convert::Infallible, net::IpAddr, sync::Arc, time::Instant};
 use axum::{
     async_trait,
     extract::{FromRef, FromRequestParts},
+    http::HeaderName,
 };
 use ipnetwork::IpNetwork;
+use mas_config::DatabaseQueryBudgetConfig;
 use mas_data_model::SiteConfig;
+use mas_email::Mailer;
 use mas_handlers::{
-    passwords::PasswordManager, ActivityTracker, BoundActivityTracker, CookieManager, ErrorWrapper,
-    GraphQLSchema, HttpClientFactory, Limiter, MetadataCache, RequesterFingerprint,
+    passwords::PasswordManager, ActivityTracker, BoundActivityTracker, ClientLogoCache,
+    CookieManager, ErrorWrapper, GraphQLSchema, HttpClientFactory, Limiter, MetadataCache,
+    RequesterFingerprint,
 };
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, Keystore};
-use mas_matrix::BoxHomeserverConnection;
+use mas_matrix::{BoxHomeserverConnection, MultiHomeserverConnection};
 use mas_matrix_synapse::SynapseConnection;
 use mas_policy::{Policy, PolicyFactory};
 use mas_router::UrlBuilder;
-use mas_storage::{BoxClock, BoxRepository, BoxRng, SystemClock};
+use mas_storage::{
+    cache::RepositoryCache, BoxClock, BoxRepository, BoxRng, ReadOnlyRepository, SystemClock,
+};
 use mas_storage_pg::PgRepository;
 use mas_templates::Templates;
+use mas_tower::FaultInjector;
 use opentelemetry::{
     metrics::{Histogram, MetricsError},
     KeyValue,
@@ -32,25 +39,35 @@ use opentelemetry::{
 use rand::SeedableRng;
 use sqlx::PgPool;
 
+use crate::keys::RotatingKeystore;
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
+    pub replica_pool: Option<PgPool>,
     pub templates: Templates,
-    pub key_store: Keystore,
+    pub key_store: RotatingKeystore,
     pub cookie_manager: CookieManager,
     pub encrypter: Encrypter,
     pub url_builder: UrlBuilder,
-    pub homeserver_connection: SynapseConnection,
+    pub homeserver_connection: MultiHomeserverConnection<SynapseConnection>,
     pub policy_factory: Arc<PolicyFactory>,
     pub graphql_schema: GraphQLSchema,
     pub http_client_factory: HttpClientFactory,
     pub password_manager: PasswordManager,
     pub metadata_cache: MetadataCache,
+    pub client_logo_cache: ClientLogoCache,
+    pub mailer: Mailer,
     pub site_config: SiteConfig,
     pub activity_tracker: ActivityTracker,
     pub trusted_proxies: Vec<IpNetwork>,
+    pub client_certificate_header: Option<HeaderName>,
     pub limiter: Limiter,
+    pub database_fault_injector: FaultInjector,
+    pub repository_cache: Option<RepositoryCache>,
     pub conn_acquisition_histogram: Option<Histogram<u64>>,
+    pub queries_per_request_histogram: Option<Histogram<u64>>,
+    pub query_budget: Option<DatabaseQueryBudgetConfig>,
 }
 
 impl AppState {
@@ -98,6 +115,14 @@ impl AppState {
             .init();
         self.conn_acquisition_histogram = Some(histogram);
 
+        // Track the number of queries done by each request
+        let histogram = meter
+            .u64_histogram("db.client.queries_per_request")
+            .with_description("The number of database queries done by a single HTTP request.")
+            .with_unit("{query}")
+            .init();
+        self.queries_per_request_histogram = Some(histogram);
+
         Ok(())
     }
 
@@ -122,6 +147,7 @@ impl AppState {
 
         self.metadata_cache
             .warm_up_and_run(
+                Box::new(SystemClock::default()),
                 http_service,
                 std::time::Duration::from_secs(60 * 15),
                 &mut repo,
@@ -157,7 +183,7 @@ impl FromRef<AppState> for Arc<Translator> {
 
 impl FromRef<AppState> for Keystore {
     fn from_ref(input: &AppState) -> Self {
-        input.key_store.clone()
+        input.key_store.keystore()
     }
 }
 
@@ -197,6 +223,18 @@ impl FromRef<AppState> for MetadataCache {
     }
 }
 
+impl FromRef<AppState> for ClientLogoCache {
+    fn from_ref(input: &AppState) -> Self {
+        input.client_logo_cache.clone()
+    }
+}
+
+impl FromRef<AppState> for Mailer {
+    fn from_ref(input: &AppState) -> Self {
+        input.mailer.clone()
+    }
+}
+
 impl FromRef<AppState> for SiteConfig {
     fn from_ref(input: &AppState) -> Self {
         input.site_config.clone()
@@ -250,11 +288,19 @@ impl FromRequestParts<AppState> for Policy {
     type Rejection = ErrorWrapper<mas_policy::InstantiateError>;
 
     async fn from_request_parts(
-        _parts: &mut axum::http::request::Parts,
+        parts: &mut axum::http::request::Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
         let policy = state.policy_factory.instantiate().await?;
-        Ok(policy)
+
+        // Pass the configured request headers to the policies
+        let request_headers = state.policy_factory.request_headers();
+        let headers = request_headers.iter().filter_map(|name| {
+            let value = parts.headers.get(name.as_str())?.to_str().ok()?;
+            Some((name.clone(), value.to_owned()))
+        });
+
+        Ok(policy.with_request_headers(headers))
     }
 }
 
@@ -362,8 +408,17 @@ impl FromRequestParts<AppState> for BoxRepository {
         _parts: &mut axum::http::request::Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
+        state
+            .database_fault_injector
+            .inject()
+            .await
+            .map_err(|_| mas_storage_pg::DatabaseError::from(sqlx::Error::PoolTimedOut))?;
+
         let start = Instant::now();
-        let repo = PgRepository::from_pool(&state.pool).await?;
+        let mut repo = PgRepository::from_pool(&state.pool).await?;
+        if let Some(cache) = &state.repository_cache {
+            repo = repo.with_cache(cache.clone());
+        }
 
         // Measure the time it took to create the connection
         let duration = start.elapsed();
@@ -376,3 +431,40 @@ impl FromRequestParts<AppState> for BoxRepository {
         Ok(repo.boxed())
     }
 }
+
+#[async_trait]
+impl FromRequestParts<AppState> for ReadOnlyRepository {
+    type Rejection = ErrorWrapper<mas_storage_pg::DatabaseError>;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let pool = state.replica_pool.as_ref().unwrap_or(&state.pool);
+
+        state
+            .database_fault_injector
+            .inject()
+            .await
+            .map_err(|_| mas_storage_pg::DatabaseError::from(sqlx::Error::PoolTimedOut))?;
+
+        let start = Instant::now();
+        let mut repo = PgRepository::from_pool_read_only(pool).await?;
+        if let Some(cache) = &state.repository_cache {
+            repo = repo.with_cache(cache.clone());
+        }
+
+        // Measure the time it took to create the connection
+        let duration = start.elapsed();
+        let duration_ms = duration.as_millis().try_into().unwrap_or(u64::MAX);
+
+        if let Some(histogram) = &state.conn_acquisition_histogram {
+            histogram.record(
+                duration_ms,
+                &[KeyValue::new("replica", state.replica_pool.is_some())],
+            );
+        }
+
+        Ok(ReadOnlyRepository(repo.boxed()))
+    }
+}
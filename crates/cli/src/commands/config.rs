@@ -10,10 +10,11 @@ use anyhow::Context;
 use camino::Utf8PathBuf;
 use clap::Parser;
 use figment::Figment;
-use mas_config::{ConfigurationSection, RootConfig, SyncConfig};
-use mas_storage::SystemClock;
-use mas_storage_pg::MIGRATOR;
+use mas_config::{ConfigurationSection, EffectiveConfig, RootConfig, SyncConfig};
+use mas_storage::{RepositoryAccess, RepositoryTransaction, SystemClock};
+use mas_storage_pg::{PgRepository, MIGRATOR};
 use rand::SeedableRng;
+use sqlx::Acquire;
 use tokio::io::AsyncWriteExt;
 use tracing::{info, info_span, Instrument};
 
@@ -34,6 +35,12 @@ enum Subcommand {
         /// If not specified, the config will be written to stdout
         #[clap(short, long)]
         output: Option<Utf8PathBuf>,
+
+        /// Dump the effective configuration as JSON instead, with the secrets
+        /// redacted, where each value comes from, and the clients and
+        /// upstream providers synced in the database
+        #[clap(long)]
+        effective: bool,
     },
 
     /// Check a config file
@@ -48,6 +55,16 @@ enum Subcommand {
         output: Option<Utf8PathBuf>,
     },
 
+    /// Rewrite the config files to use the current name of the options which
+    /// were renamed
+    ///
+    /// Comments and formatting are lost in the files which are rewritten.
+    Upgrade {
+        /// Print the upgraded config files instead of writing them back
+        #[clap(long)]
+        dry_run: bool,
+    },
+
     /// Sync the clients and providers from the config file to the database
     Sync {
         /// Prune elements that are in the database but not in the config file
@@ -62,14 +79,22 @@ enum Subcommand {
 }
 
 impl Options {
-    pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+    pub async fn run(
+        self,
+        figment: &Figment,
+        config_paths: &[Utf8PathBuf],
+    ) -> anyhow::Result<ExitCode> {
         use Subcommand as SC;
         match self.subcommand {
-            SC::Dump { output } => {
+            SC::Dump { output, effective } => {
                 let _span = info_span!("cli.config.dump").entered();
 
                 let config = RootConfig::extract(figment)?;
-                let config = serde_yaml::to_string(&config)?;
+                let config = if effective {
+                    effective_config(figment, &config).await?
+                } else {
+                    serde_yaml::to_string(&config)?
+                };
 
                 if let Some(output) = output {
                     info!("Writing configuration to {output:?}");
@@ -106,6 +131,43 @@ impl Options {
                 }
             }
 
+            SC::Upgrade { dry_run } => {
+                let _span = info_span!("cli.config.upgrade").entered();
+
+                for path in config_paths {
+                    let source = tokio::fs::read_to_string(path)
+                        .await
+                        .with_context(|| format!("could not read {path}"))?;
+                    let upgraded = mas_config::upgrade_yaml(&source)
+                        .with_context(|| format!("could not parse {path}"))?;
+
+                    if upgraded.renamed.is_empty() {
+                        info!(%path, "Configuration file is up to date");
+                        continue;
+                    }
+
+                    for option in &upgraded.renamed {
+                        info!(
+                            %path,
+                            option.old = option.old,
+                            option.new = option.new,
+                            "Renaming configuration option"
+                        );
+                    }
+
+                    if dry_run {
+                        tokio::io::stdout()
+                            .write_all(upgraded.yaml.as_bytes())
+                            .await?;
+                    } else {
+                        tokio::fs::write(path, upgraded.yaml)
+                            .await
+                            .with_context(|| format!("could not write {path}"))?;
+                        info!(%path, "Configuration file upgraded");
+                    }
+                }
+            }
+
             SC::Sync { prune, dry_run } => {
                 let config = SyncConfig::extract(figment)?;
                 let clock = SystemClock::default();
@@ -136,3 +198,56 @@ impl Options {
         Ok(ExitCode::SUCCESS)
     }
 }
+
+/// Export the effective configuration, along with the clients and upstream
+/// providers synced in the database
+async fn effective_config(figment: &Figment, config: &RootConfig) -> anyhow::Result<String> {
+    let effective = EffectiveConfig::new(figment, config)?;
+
+    let mut conn = database_connection_from_config(&config.database).await?;
+    let txn = conn.begin().await?;
+    let mut repo = PgRepository::from_conn(txn);
+
+    let clients: Vec<_> = repo
+        .oauth2_client()
+        .all_static()
+        .await?
+        .into_iter()
+        .map(|client| {
+            serde_json::json!({
+                "client_id": client.client_id,
+                "client_name": client.client_name,
+                "redirect_uris": client.redirect_uris,
+            })
+        })
+        .collect();
+
+    let providers: Vec<_> = repo
+        .upstream_oauth_provider()
+        .all_enabled()
+        .await?
+        .into_iter()
+        .map(|provider| {
+            serde_json::json!({
+                "human_name": provider.human_name,
+                "id": provider.id,
+                "issuer": provider.issuer,
+            })
+        })
+        .collect();
+
+    repo.cancel().await?;
+
+    let export = serde_json::json!({
+        "config": effective.config,
+        "database": {
+            "clients": clients,
+            "upstream_oauth2_providers": providers,
+        },
+        "provenance": effective.provenance,
+    });
+
+    let mut export = serde_json::to_string_pretty(&export)?;
+    export.push('\n');
+    Ok(export)
+}
@@ -0,0 +1,494 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Load testing of the token and introspection endpoints
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::Parser;
+use figment::Figment;
+use hyper::{header::CONTENT_TYPE, Request, Response};
+use mas_config::{ConfigurationSectionExt, DatabaseConfig, HttpConfig};
+use mas_data_model::{Device, TokenType};
+use mas_handlers::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    oauth2::{
+        OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2RefreshTokenRepository,
+        OAuth2SessionRepository,
+    },
+    user::{UserHomeserver, UserRepository},
+    RepositoryAccess, SystemClock,
+};
+use mas_storage_pg::PgRepository;
+use oauth2_types::scope::{Scope, ScopeToken, OPENID};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    SeedableRng,
+};
+use serde_json::json;
+use sqlx::Acquire;
+use tower::{Service, ServiceExt};
+use tracing::{info, warn};
+use url::{form_urlencoded, Url};
+
+use crate::util::database_connection_from_config;
+
+const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
+
+#[derive(Parser, Debug)]
+pub(super) struct BenchOptions {
+    /// Base URL of the instance to test. Defaults to the `http.public_base`
+    /// of the configuration
+    #[arg(long)]
+    base_url: Option<Url>,
+
+    /// ID of the client used to issue and introspect tokens.
+    ///
+    /// It must be allowed to use the client credentials grant and
+    /// authenticate with the `client_secret_post` method.
+    #[arg(long)]
+    client_id: String,
+
+    /// Secret of the client
+    #[arg(long)]
+    client_secret: String,
+
+    /// Username of the user for whom sessions are started to test refreshes.
+    ///
+    /// Sessions are added directly to the database, one per worker. Required
+    /// if refreshes are part of the mix.
+    #[arg(long)]
+    username: Option<String>,
+
+    /// Number of concurrent workers
+    #[arg(long, default_value_t = 10)]
+    concurrency: usize,
+
+    /// Duration of the run, in seconds
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+
+    /// Relative weight of token issuances, through the client credentials
+    /// grant
+    #[arg(long, default_value_t = 1)]
+    token_weight: u32,
+
+    /// Relative weight of refresh token grants
+    #[arg(long, default_value_t = 0)]
+    refresh_weight: u32,
+
+    /// Relative weight of token introspections
+    #[arg(long, default_value_t = 4)]
+    introspection_weight: u32,
+
+    /// Seed of the random number generator picking the operations, to run the
+    /// same sequence of requests across runs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Output the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Token,
+    Refresh,
+    Introspection,
+}
+
+impl Operation {
+    const ALL: [Self; 3] = [Self::Token, Self::Refresh, Self::Introspection];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Token => "token",
+            Self::Refresh => "refresh",
+            Self::Introspection => "introspection",
+        }
+    }
+
+    const fn index(self) -> usize {
+        match self {
+            Self::Token => 0,
+            Self::Refresh => 1,
+            Self::Introspection => 2,
+        }
+    }
+}
+
+/// Latencies of the requests made for one kind of operation
+#[derive(Debug, Default)]
+struct OperationStats {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+impl OperationStats {
+    fn merge(&mut self, other: Self) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+
+    /// Get the latency under which the given fraction of the requests
+    /// completed
+    fn percentile(&self, fraction: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let rank = (fraction * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+struct Endpoints {
+    token: Url,
+    introspection: Url,
+}
+
+struct Credentials {
+    client_id: String,
+    client_secret: String,
+}
+
+impl Credentials {
+    fn form(&self, params: &[(&str, &str)]) -> String {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .append_pair("client_id", &self.client_id)
+            .append_pair("client_secret", &self.client_secret)
+            .finish()
+    }
+}
+
+/// State of a single worker
+struct Worker {
+    factory: HttpClientFactory,
+    endpoints: Arc<Endpoints>,
+    credentials: Arc<Credentials>,
+    rng: rand_chacha::ChaChaRng,
+    distribution: WeightedIndex<u32>,
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    stats: [OperationStats; 3],
+}
+
+/// Send a form to the given endpoint, returning the JSON response if the
+/// request succeeded
+async fn send<S>(client: &mut S, url: &Url, form: String) -> Option<serde_json::Value>
+where
+    S: Service<Request<axum::body::Body>, Response = Response<serde_json::Value>>,
+{
+    let request = Request::post(url.as_str())
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(axum::body::Body::from(form))
+        .ok()?;
+
+    let response = client.ready().await.ok()?.call(request).await.ok()?;
+
+    if response.status().is_success() {
+        Some(response.into_body())
+    } else {
+        None
+    }
+}
+
+impl Worker {
+    async fn run(mut self, deadline: Instant) -> [OperationStats; 3] {
+        let mut client = self
+            .factory
+            .client("debug.bench")
+            .response_body_to_bytes()
+            .json_response::<serde_json::Value>();
+
+        while Instant::now() < deadline {
+            let mut operation = Operation::ALL[self.distribution.sample(&mut self.rng)];
+
+            // Introspections need a token first, and refreshes can't continue once the
+            // refresh token was lost after a failure
+            if (operation == Operation::Introspection && self.access_token.is_none())
+                || (operation == Operation::Refresh && self.refresh_token.is_none())
+            {
+                operation = Operation::Token;
+            }
+
+            let form = match operation {
+                Operation::Token => self
+                    .credentials
+                    .form(&[("grant_type", "client_credentials")]),
+                Operation::Refresh => {
+                    let refresh_token = self.refresh_token.as_deref().unwrap_or_default();
+                    self.credentials.form(&[
+                        ("grant_type", "refresh_token"),
+                        ("refresh_token", refresh_token),
+                    ])
+                }
+                Operation::Introspection => {
+                    let access_token = self.access_token.as_deref().unwrap_or_default();
+                    self.credentials.form(&[("token", access_token)])
+                }
+            };
+
+            let url = match operation {
+                Operation::Token | Operation::Refresh => &self.endpoints.token,
+                Operation::Introspection => &self.endpoints.introspection,
+            };
+
+            let start = Instant::now();
+            let body = send(&mut client, url, form).await;
+            let elapsed = start.elapsed();
+
+            let stats = &mut self.stats[operation.index()];
+            let Some(body) = body else {
+                stats.errors += 1;
+                if operation == Operation::Refresh {
+                    self.refresh_token = None;
+                }
+                continue;
+            };
+            stats.latencies.push(elapsed);
+
+            if let Some(access_token) = body.get("access_token").and_then(|v| v.as_str()) {
+                self.access_token = Some(access_token.to_owned());
+            }
+
+            if operation == Operation::Refresh {
+                self.refresh_token = body
+                    .get("refresh_token")
+                    .and_then(|v| v.as_str())
+                    .map(ToOwned::to_owned);
+            }
+        }
+
+        self.stats
+    }
+}
+
+impl BenchOptions {
+    #[allow(clippy::too_many_lines)]
+    pub(super) async fn run(self, figment: &Figment) -> anyhow::Result<()> {
+        let http_config = HttpConfig::extract_or_default(figment)?;
+        let base_url = self
+            .base_url
+            .clone()
+            .unwrap_or_else(|| http_config.public_base.clone());
+        let url_builder = UrlBuilder::new(base_url, None, None);
+        let endpoints = Arc::new(Endpoints {
+            token: url_builder.oauth_token_endpoint(),
+            introspection: url_builder.oauth_introspection_endpoint(),
+        });
+
+        let credentials = Arc::new(Credentials {
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+        });
+
+        let weights = [
+            self.token_weight,
+            self.refresh_weight,
+            self.introspection_weight,
+        ];
+        let distribution =
+            WeightedIndex::new(weights).context("At least one operation must have a weight")?;
+
+        let concurrency = self.concurrency.max(1);
+
+        // XXX: we should disallow SeedableRng::from_entropy
+        let mut rng = if let Some(seed) = self.seed {
+            rand_chacha::ChaChaRng::seed_from_u64(seed)
+        } else {
+            rand_chacha::ChaChaRng::from_entropy()
+        };
+
+        // Start one session per worker to refresh, directly in the database
+        let refresh_tokens = if self.refresh_weight > 0 {
+            let username = self
+                .username
+                .as_deref()
+                .context("A username is required to test refreshes")?;
+            let client_id = self.client_id.parse().context("Invalid client ID")?;
+
+            let database_config = DatabaseConfig::extract_or_default(figment)?;
+            let mut conn = database_connection_from_config(&database_config).await?;
+            let txn = conn.begin().await?;
+            let mut repo = PgRepository::from_conn(txn);
+            let clock = SystemClock::default();
+
+            let user = repo
+                .user()
+                .find_by_username(UserHomeserver::Main, username)
+                .await?
+                .context("User not found")?;
+            let client = repo
+                .oauth2_client()
+                .lookup(client_id)
+                .await?
+                .context("Client not found")?;
+
+            let mut refresh_tokens = Vec::with_capacity(concurrency);
+            for _ in 0..concurrency {
+                let device = Device::generate(&mut rng);
+                let scope: Scope = [OPENID, API_SCOPE, device.to_scope_token()]
+                    .into_iter()
+                    .collect();
+                let session = repo
+                    .oauth2_session()
+                    .add(&mut rng, &clock, &client, Some(&user), None, scope)
+                    .await?;
+                let access_token = repo
+                    .oauth2_access_token()
+                    .add(
+                        &mut rng,
+                        &clock,
+                        &session,
+                        TokenType::AccessToken.generate(&mut rng),
+                        Some(chrono::Duration::minutes(5)),
+                        None,
+                        None,
+                    )
+                    .await?;
+                let refresh_token = repo
+                    .oauth2_refresh_token()
+                    .add(
+                        &mut rng,
+                        &clock,
+                        &session,
+                        &access_token,
+                        TokenType::RefreshToken.generate(&mut rng),
+                        None,
+                    )
+                    .await?;
+                refresh_tokens.push(Some(refresh_token.refresh_token));
+            }
+
+            repo.into_inner().commit().await?;
+            refresh_tokens
+        } else {
+            vec![None; concurrency]
+        };
+
+        let factory = HttpClientFactory::new();
+        let duration = Duration::from_secs(self.duration);
+        info!(
+            concurrency,
+            duration = self.duration,
+            token_endpoint = %endpoints.token,
+            introspection_endpoint = %endpoints.introspection,
+            "Starting load test"
+        );
+
+        let start = Instant::now();
+        let deadline = start + duration;
+        let mut handles = Vec::with_capacity(concurrency);
+        for refresh_token in refresh_tokens {
+            let worker = Worker {
+                factory: factory.clone(),
+                endpoints: endpoints.clone(),
+                credentials: credentials.clone(),
+                rng: rand_chacha::ChaChaRng::from_rng(&mut rng)?,
+                distribution: distribution.clone(),
+                access_token: None,
+                refresh_token,
+                stats: Default::default(),
+            };
+            handles.push(tokio::spawn(worker.run(deadline)));
+        }
+
+        let mut stats: [OperationStats; 3] = Default::default();
+        for handle in handles {
+            for (total, worker_stats) in stats.iter_mut().zip(handle.await?) {
+                total.merge(worker_stats);
+            }
+        }
+        let elapsed = start.elapsed();
+
+        for operation_stats in &mut stats {
+            operation_stats.latencies.sort_unstable();
+        }
+
+        let report: Vec<_> = Operation::ALL
+            .into_iter()
+            .zip(&stats)
+            .filter(|(_, stats)| !stats.latencies.is_empty() || stats.errors > 0)
+            .map(|(operation, stats)| {
+                let count = stats.latencies.len();
+                #[allow(clippy::cast_precision_loss)]
+                let throughput = count as f64 / elapsed.as_secs_f64();
+                let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+                json!({
+                    "operation": operation.name(),
+                    "count": count,
+                    "errors": stats.errors,
+                    "throughput": throughput,
+                    "p50_ms": millis(stats.percentile(0.5)),
+                    "p90_ms": millis(stats.percentile(0.9)),
+                    "p99_ms": millis(stats.percentile(0.99)),
+                    "max_ms": millis(stats.percentile(1.0)),
+                })
+            })
+            .collect();
+
+        if stats.iter().any(|stats| stats.errors > 0) {
+            warn!("Some requests failed, check the client configuration and the server logs");
+        }
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!(
+                "{:<14} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                "operation", "count", "errors", "req/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+            );
+            for row in &report {
+                println!(
+                    "{:<14} {:>8} {:>8} {:>10.1} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+                    row["operation"].as_str().unwrap_or_default(),
+                    row["count"],
+                    row["errors"],
+                    row["throughput"].as_f64().unwrap_or_default(),
+                    row["p50_ms"].as_f64().unwrap_or_default(),
+                    row["p90_ms"].as_f64().unwrap_or_default(),
+                    row["p99_ms"].as_f64().unwrap_or_default(),
+                    row["max_ms"].as_f64().unwrap_or_default(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let stats = OperationStats {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            errors: 0,
+        };
+
+        assert_eq!(stats.percentile(0.5), Duration::from_millis(50));
+        assert_eq!(stats.percentile(0.99), Duration::from_millis(99));
+        assert_eq!(stats.percentile(1.0), Duration::from_millis(100));
+        assert_eq!(stats.percentile(0.0), Duration::from_millis(1));
+
+        assert_eq!(OperationStats::default().percentile(0.5), Duration::ZERO);
+    }
+}
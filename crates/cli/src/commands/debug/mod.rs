@@ -0,0 +1,477 @@
+// Copyright 2024 New Vector Ltd.
+// Copyright 2022-2024 The Matrix.org Foundation C.I.C.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::process::ExitCode;
+
+use chrono::Duration;
+use clap::Parser;
+use figment::Figment;
+use http_body_util::BodyExt;
+use hyper::{Response, Uri};
+use mas_config::{ConfigurationSectionExt, DatabaseConfig, PolicyConfig, ScopesConfig};
+use mas_data_model::{Client, Device, TokenType, UserAgent};
+use mas_handlers::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_storage::{
+    clock::MockClock,
+    compat::{CompatAccessTokenRepository, CompatSessionRepository},
+    oauth2::{
+        OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2RefreshTokenRepository,
+        OAuth2SessionRepository,
+    },
+    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
+    user::{BrowserSessionRepository, UserEmailRepository, UserRepository},
+    Clock, RepositoryAccess, SystemClock,
+};
+use mas_storage_pg::PgRepository;
+use oauth2_types::{
+    oidc::ApplicationType,
+    requests::GrantType,
+    scope::{Scope, ScopeToken, OPENID},
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
+use sqlx::Acquire;
+use tokio::io::AsyncWriteExt;
+use tower::{Service, ServiceExt};
+use tracing::{info, info_span};
+
+use self::bench::BenchOptions;
+use crate::util::{database_connection_from_config, policy_factory_from_config};
+
+mod bench;
+
+/// Share of users with a verified email address
+const EMAIL_RATIO: f64 = 0.9;
+
+/// Share of users linked to an upstream provider, if any is configured
+const UPSTREAM_LINK_RATIO: f64 = 0.5;
+
+/// Share of users with a session from a legacy Matrix client
+const COMPAT_SESSION_RATIO: f64 = 0.3;
+
+/// Share of browser sessions from which an OAuth 2.0 session was started
+const OAUTH2_SESSION_RATIO: f64 = 0.7;
+
+/// Share of sessions which are finished
+const FINISHED_SESSION_RATIO: f64 = 0.6;
+
+/// Share of users whose account is locked
+const LOCKED_USER_RATIO: f64 = 0.01;
+
+/// Upper bound of the number of browser sessions of a single user
+const MAX_SESSIONS_PER_USER: usize = 100;
+
+const USER_AGENTS: [&str; 4] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/129.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 Safari/605.1.15",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 18_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 Mobile/15E148 Safari/604.1",
+];
+
+const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
+
+#[derive(Parser, Debug)]
+pub(super) struct Options {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Parser, Debug)]
+enum Subcommand {
+    /// Perform an HTTP request with the default HTTP client
+    Http {
+        /// Show response headers
+        #[arg(long, short = 'I')]
+        show_headers: bool,
+
+        /// Parse the response as JSON
+        #[arg(long, short = 'j')]
+        json: bool,
+
+        /// URI where to perform a GET request
+        url: Uri,
+    },
+
+    /// Check that the policies compile
+    Policy,
+
+    /// Fill the database with synthetic users, sessions, clients and upstream
+    /// links, for load testing
+    ///
+    /// The generated data doesn't contain any real user data, but follows
+    /// distributions similar to the ones of real deployments.
+    Seed(SeedOptions),
+
+    /// Drive a mix of token issuances, refreshes and introspections against
+    /// an instance, and report the latency percentiles of each operation
+    Bench(BenchOptions),
+}
+
+#[derive(Parser, Debug)]
+struct SeedOptions {
+    /// Number of users to create
+    #[arg(long, default_value_t = 1000)]
+    users: usize,
+
+    /// Number of OAuth 2.0 clients to register
+    #[arg(long, default_value_t = 10)]
+    clients: usize,
+
+    /// Average number of browser sessions per user
+    #[arg(long, default_value_t = 3.0)]
+    sessions_per_user: f64,
+
+    /// Spread the creation of the users over this many days in the past
+    #[arg(long, default_value_t = 365)]
+    days: u32,
+
+    /// Prefix of the generated usernames and client names
+    #[arg(long, default_value = "seed")]
+    prefix: String,
+
+    /// Seed of the random number generator, to generate the same dataset
+    /// across runs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Number of users created in each database transaction
+    #[arg(long, default_value_t = 100)]
+    batch_size: usize,
+}
+
+fn print_headers(parts: &hyper::http::response::Parts) {
+    println!(
+        "{:?} {} {}",
+        parts.version,
+        parts.status.as_str(),
+        parts.status.canonical_reason().unwrap_or_default()
+    );
+
+    for (header, value) in &parts.headers {
+        println!("{header}: {value:?}");
+    }
+    println!();
+}
+
+impl Options {
+    #[tracing::instrument(skip_all)]
+    pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+        use Subcommand as SC;
+        let http_client_factory = HttpClientFactory::new();
+        match self.subcommand {
+            SC::Http {
+                show_headers,
+                json: false,
+                url,
+            } => {
+                let _span = info_span!("cli.debug.http").entered();
+                let mut client = http_client_factory.client("debug");
+                let request = hyper::Request::builder()
+                    .uri(url)
+                    .body(axum::body::Body::empty())?;
+
+                let response = client.ready().await?.call(request).await?;
+                let (parts, body) = response.into_parts();
+
+                if show_headers {
+                    print_headers(&parts);
+                }
+
+                let mut body = body.collect().await?.to_bytes();
+                let mut stdout = tokio::io::stdout();
+                stdout.write_all_buf(&mut body).await?;
+            }
+
+            SC::Http {
+                show_headers,
+                json: true,
+                url,
+            } => {
+                let _span = info_span!("cli.debug.http").entered();
+                let mut client = http_client_factory
+                    .client("debug")
+                    .response_body_to_bytes()
+                    .json_response();
+                let request = hyper::Request::builder()
+                    .uri(url)
+                    .body(axum::body::Body::empty())?;
+
+                let response: Response<serde_json::Value> =
+                    client.ready().await?.call(request).await?;
+                let (parts, body) = response.into_parts();
+
+                if show_headers {
+                    print_headers(&parts);
+                }
+
+                let body = serde_json::to_string_pretty(&body)?;
+                println!("{body}");
+            }
+
+            SC::Policy => {
+                let _span = info_span!("cli.debug.policy").entered();
+                let config = PolicyConfig::extract_or_default(figment)?;
+                let scopes_config = ScopesConfig::extract_or_default(figment)?;
+                info!("Loading and compiling the policy module");
+                let policy_factory = policy_factory_from_config(&config, &scopes_config).await?;
+
+                let _instance = policy_factory.instantiate().await?;
+            }
+
+            SC::Seed(options) => {
+                let _span = info_span!("cli.debug.seed").entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                options.run(&config).await?;
+            }
+
+            SC::Bench(options) => {
+                let _span = info_span!("cli.debug.bench").entered();
+                options.run(figment).await?;
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Move the clock forward by a random amount of time, staying before `until`
+fn advance_randomly(clock: &MockClock, rng: &mut impl Rng, until: chrono::DateTime<chrono::Utc>) {
+    let remaining = (until - clock.now()).num_seconds();
+    if remaining > 0 {
+        clock.advance(Duration::seconds(rng.gen_range(0..=remaining / 4)));
+    }
+}
+
+/// Sample a number of items following a geometric distribution with the given
+/// mean
+fn sample_count(rng: &mut impl Rng, mean: f64) -> usize {
+    let mean = mean.max(0.0);
+    let continue_probability = mean / (mean + 1.0);
+    let mut count = 0;
+    while count < MAX_SESSIONS_PER_USER && rng.gen_bool(continue_probability) {
+        count += 1;
+    }
+    count
+}
+
+impl SeedOptions {
+    #[allow(clippy::too_many_lines)]
+    async fn run(self, config: &DatabaseConfig) -> anyhow::Result<()> {
+        let mut rng = if let Some(seed) = self.seed {
+            rand_chacha::ChaChaRng::seed_from_u64(seed)
+        } else {
+            // XXX: we should disallow SeedableRng::from_entropy
+            rand_chacha::ChaChaRng::from_entropy()
+        };
+
+        let now = SystemClock::default().now();
+        let start = now - Duration::days(self.days.into());
+        let batch_size = self.batch_size.max(1);
+
+        let mut conn = database_connection_from_config(config).await?;
+
+        // Register the clients first, so that sessions can be started from them
+        let txn = conn.begin().await?;
+        let mut repo = PgRepository::from_conn(txn);
+        let clock = MockClock::new(start);
+        let mut clients: Vec<Client> = Vec::with_capacity(self.clients);
+        for index in 0..self.clients {
+            let client = repo
+                .oauth2_client()
+                .add(
+                    &mut rng,
+                    &clock,
+                    vec![format!("https://{}-{index}.example.com/callback", self.prefix).parse()?],
+                    None,
+                    Some(ApplicationType::Web),
+                    vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
+                    Some(format!("{} client {index}", self.prefix)),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some(OAuthClientAuthenticationMethod::None),
+                    None,
+                    None,
+                    false,
+                    None,
+                    false,
+                )
+                .await?;
+            clients.push(client);
+        }
+
+        let providers = repo.upstream_oauth_provider().all_enabled().await?;
+        repo.into_inner().commit().await?;
+        info!(count = clients.len(), "Registered OAuth 2.0 clients");
+
+        let mut users = 0;
+        let mut browser_sessions = 0;
+        let mut oauth2_sessions = 0;
+        let mut compat_sessions = 0;
+        let mut upstream_links = 0;
+
+        let user_interval = (now - start) / i32::try_from(self.users.max(1))?;
+        for batch_start in (0..self.users).step_by(batch_size) {
+            let txn = conn.begin().await?;
+            let mut repo = PgRepository::from_conn(txn);
+
+            for index in batch_start..(batch_start + batch_size).min(self.users) {
+                // Users are registered at a regular pace over the period, and their
+                // sessions are started at random times after that
+                let registered_at = start + user_interval * i32::try_from(index)?;
+                let clock = MockClock::new(registered_at);
+
+                let username = format!("{}-{index}", self.prefix);
+                let user = repo.user().add(&mut rng, &clock, username).await?;
+                users += 1;
+
+                if rng.gen_bool(EMAIL_RATIO) {
+                    let email = format!("{}@example.com", user.username);
+                    let user_email = repo
+                        .user_email()
+                        .add(&mut rng, &clock, &user, email)
+                        .await?;
+                    let user_email = repo
+                        .user_email()
+                        .mark_as_verified(&clock, user_email)
+                        .await?;
+                    repo.user_email().set_as_primary(&user_email).await?;
+                }
+
+                if !providers.is_empty() && rng.gen_bool(UPSTREAM_LINK_RATIO) {
+                    let provider = providers.choose(&mut rng).unwrap();
+                    let subject = Alphanumeric.sample_string(&mut rng, 16);
+                    let link = repo
+                        .upstream_oauth_link()
+                        .add(&mut rng, &clock, provider, subject)
+                        .await?;
+                    repo.upstream_oauth_link()
+                        .associate_to_user(&link, &user)
+                        .await?;
+                    upstream_links += 1;
+                }
+
+                for _ in 0..sample_count(&mut rng, self.sessions_per_user) {
+                    advance_randomly(&clock, &mut rng, now);
+                    let user_agent = USER_AGENTS.choose(&mut rng).unwrap();
+                    let browser_session = repo
+                        .browser_session()
+                        .add(
+                            &mut rng,
+                            &clock,
+                            &user,
+                            Some(UserAgent::parse((*user_agent).to_owned())),
+                        )
+                        .await?;
+                    browser_sessions += 1;
+
+                    if let Some(client) = clients.choose(&mut rng) {
+                        if rng.gen_bool(OAUTH2_SESSION_RATIO) {
+                            let device = Device::generate(&mut rng);
+                            let scope: Scope = [OPENID, API_SCOPE, device.to_scope_token()]
+                                .into_iter()
+                                .collect();
+                            let session = repo
+                                .oauth2_session()
+                                .add_from_browser_session(
+                                    &mut rng,
+                                    &clock,
+                                    client,
+                                    &browser_session,
+                                    scope,
+                                )
+                                .await?;
+                            let access_token = repo
+                                .oauth2_access_token()
+                                .add(
+                                    &mut rng,
+                                    &clock,
+                                    &session,
+                                    TokenType::AccessToken.generate(&mut rng),
+                                    Some(Duration::minutes(5)),
+                                    None,
+                                    None,
+                                )
+                                .await?;
+                            repo.oauth2_refresh_token()
+                                .add(
+                                    &mut rng,
+                                    &clock,
+                                    &session,
+                                    &access_token,
+                                    TokenType::RefreshToken.generate(&mut rng),
+                                    None,
+                                )
+                                .await?;
+                            oauth2_sessions += 1;
+
+                            if rng.gen_bool(FINISHED_SESSION_RATIO) {
+                                advance_randomly(&clock, &mut rng, now);
+                                repo.oauth2_session().finish(&clock, session).await?;
+                            }
+                        }
+                    }
+
+                    if rng.gen_bool(FINISHED_SESSION_RATIO) {
+                        advance_randomly(&clock, &mut rng, now);
+                        repo.browser_session()
+                            .finish(&clock, browser_session)
+                            .await?;
+                    }
+                }
+
+                if rng.gen_bool(COMPAT_SESSION_RATIO) {
+                    advance_randomly(&clock, &mut rng, now);
+                    let device = Device::generate(&mut rng);
+                    let compat_session = repo
+                        .compat_session()
+                        .add(&mut rng, &clock, &user, device, None, false)
+                        .await?;
+                    repo.compat_access_token()
+                        .add(
+                            &mut rng,
+                            &clock,
+                            &compat_session,
+                            TokenType::CompatAccessToken.generate(&mut rng),
+                            None,
+                        )
+                        .await?;
+                    compat_sessions += 1;
+
+                    if rng.gen_bool(FINISHED_SESSION_RATIO) {
+                        advance_randomly(&clock, &mut rng, now);
+                        repo.compat_session().finish(&clock, compat_session).await?;
+                    }
+                }
+
+                if rng.gen_bool(LOCKED_USER_RATIO) {
+                    advance_randomly(&clock, &mut rng, now);
+                    repo.user().lock(&clock, user).await?;
+                }
+            }
+
+            repo.into_inner().commit().await?;
+            info!(users, total = self.users, "Created users");
+        }
+
+        info!(
+            users,
+            browser_sessions, oauth2_sessions, compat_sessions, upstream_links, "Database seeded"
+        );
+
+        Ok(())
+    }
+}
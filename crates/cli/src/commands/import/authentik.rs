@@ -0,0 +1,437 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Parse Authentik blueprint exports
+//!
+//! Blueprints are YAML files made of a list of entries, each one describing an
+//! instance of a model. References between entries either use the primary key
+//! of the target, or custom YAML tags like `!Find`.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use mas_config::PasswordAlgorithm;
+use serde_yaml::Value;
+use url::Url;
+
+use super::{decode_base64, Export, ImportedClient, ImportedPassword, ImportedUser, Report};
+
+const USER_MODEL: &str = "authentik_core.user";
+const OAUTH2_PROVIDER_MODEL: &str = "authentik_providers_oauth2.oauth2provider";
+const OAUTH_SOURCE_MODEL: &str = "authentik_sources_oauth.oauthsource";
+const OAUTH_SOURCE_CONNECTION_MODEL: &str = "authentik_sources_oauth.useroauthsourceconnection";
+
+/// Models which are expected in an export, but have no equivalent
+const IGNORED_MODELS: &[&str] = &[
+    "authentik_core.application",
+    "authentik_blueprints.metaapplyblueprint",
+];
+
+/// An entry of a blueprint, with its identifiers merged in its attributes
+struct Entry<'a> {
+    model: &'a str,
+    fields: BTreeMap<&'a str, &'a Value>,
+}
+
+impl<'a> Entry<'a> {
+    fn from_value(value: &'a Value) -> Option<Self> {
+        let model = value.get("model")?.as_str()?;
+        let mut fields = BTreeMap::new();
+        for key in ["identifiers", "attrs"] {
+            if let Some(Value::Mapping(mapping)) = value.get(key) {
+                for (name, value) in mapping {
+                    if let Some(name) = name.as_str() {
+                        fields.insert(name, value);
+                    }
+                }
+            }
+        }
+
+        Some(Self { model, fields })
+    }
+
+    fn str(&self, name: &str) -> Option<&'a str> {
+        self.fields.get(name)?.as_str()
+    }
+
+    fn bool(&self, name: &str) -> Option<bool> {
+        self.fields.get(name)?.as_bool()
+    }
+
+    fn value(&self, name: &str) -> Option<&'a Value> {
+        self.fields.get(name).copied()
+    }
+}
+
+/// A reference to another entry, either by primary key or by a `!Find` tag
+enum Reference {
+    Pk(String),
+    Field(String, String),
+}
+
+impl Reference {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(pk) => Some(Self::Pk(pk.to_string())),
+            Value::String(pk) => Some(Self::Pk(pk.clone())),
+
+            // `!Find [model, [field, value]]`
+            Value::Tagged(tagged) if tagged.tag == "Find" => {
+                let [_model, Value::Sequence(query)] = tagged.value.as_sequence()?.as_slice()
+                else {
+                    return None;
+                };
+                let [field, value] = query.as_slice() else {
+                    return None;
+                };
+                Some(Self::Field(
+                    field.as_str()?.to_owned(),
+                    value.as_str()?.to_owned(),
+                ))
+            }
+
+            _ => None,
+        }
+    }
+
+    fn matches(&self, entry: &Entry<'_>) -> bool {
+        match self {
+            Self::Pk(pk) => entry.value("pk").is_some_and(|value| match value {
+                Value::Number(n) => n.to_string() == *pk,
+                Value::String(s) => s == pk,
+                _ => false,
+            }),
+            Self::Field(field, value) => entry.str(field) == Some(value),
+        }
+    }
+}
+
+/// Parse an Authentik blueprint, as produced by `ak export_blueprint`
+pub(super) fn parse(content: &str) -> anyhow::Result<Export> {
+    let blueprint: Value =
+        serde_yaml::from_str(content).context("Could not parse the Authentik blueprint")?;
+
+    let entries: Vec<_> = blueprint
+        .get("entries")
+        .and_then(Value::as_sequence)
+        .context("The blueprint has no entries")?
+        .iter()
+        .filter_map(Entry::from_value)
+        .collect();
+
+    let mut report = Report::default();
+    let mut users = Vec::new();
+    let mut clients = Vec::new();
+
+    for entry in &entries {
+        match entry.model {
+            USER_MODEL => {
+                if let Some(user) = convert_user(entry, &entries, &mut report) {
+                    users.push(user);
+                }
+            }
+
+            OAUTH2_PROVIDER_MODEL => {
+                if let Some(client) = convert_client(entry, &mut report) {
+                    clients.push(client);
+                }
+            }
+
+            // Those are handled along with the users
+            OAUTH_SOURCE_CONNECTION_MODEL => {
+                if entry
+                    .value("user")
+                    .and_then(Reference::from_value)
+                    .is_none()
+                {
+                    report.unsupported("OAuth source connections with an unresolvable user");
+                }
+            }
+            OAUTH_SOURCE_MODEL => {}
+
+            model if IGNORED_MODELS.contains(&model) => {}
+
+            model => report.unsupported(format!("Entries of model `{model}`")),
+        }
+    }
+
+    Ok(Export {
+        users,
+        clients,
+        report,
+    })
+}
+
+fn convert_user(
+    entry: &Entry<'_>,
+    entries: &[Entry<'_>],
+    report: &mut Report,
+) -> Option<ImportedUser> {
+    let Some(username) = entry.str("username") else {
+        report.unsupported("Users without a username");
+        return None;
+    };
+
+    if entry
+        .str("type")
+        .is_some_and(|kind| kind.ends_with("service_account"))
+    {
+        report.skip_user(username, "service account");
+        return None;
+    }
+
+    let password = entry
+        .str("password")
+        .and_then(|password| convert_password(password, report));
+
+    if entry
+        .value("groups")
+        .and_then(Value::as_sequence)
+        .is_some_and(|groups| !groups.is_empty())
+    {
+        report.unsupported("Group memberships");
+    }
+
+    if entry
+        .value("attributes")
+        .and_then(Value::as_mapping)
+        .is_some_and(|attributes| !attributes.is_empty())
+    {
+        report.unsupported("Custom user attributes");
+    }
+
+    // Find the links to OAuth sources of this user, and resolve the slug of the
+    // source they point to
+    let mut upstream_links = Vec::new();
+    for connection in entries
+        .iter()
+        .filter(|e| e.model == OAUTH_SOURCE_CONNECTION_MODEL)
+    {
+        let Some(user) = connection.value("user").and_then(Reference::from_value) else {
+            continue;
+        };
+
+        if !user.matches(entry) {
+            continue;
+        }
+
+        let slug = connection
+            .value("source")
+            .and_then(Reference::from_value)
+            .and_then(|source| {
+                entries
+                    .iter()
+                    .filter(|e| e.model == OAUTH_SOURCE_MODEL)
+                    .find(|e| source.matches(e))
+            })
+            .and_then(|source| source.str("slug"));
+
+        match (slug, connection.str("identifier")) {
+            (Some(slug), Some(identifier)) => {
+                upstream_links.push((slug.to_owned(), identifier.to_owned()));
+            }
+            _ => report.unsupported("OAuth source connections with an unresolvable source"),
+        }
+    }
+
+    Some(ImportedUser {
+        username: username.to_owned(),
+        display_name: entry
+            .str("name")
+            .filter(|name| !name.is_empty())
+            .map(ToOwned::to_owned),
+        email: entry
+            .str("email")
+            .filter(|email| !email.is_empty())
+            .map(ToOwned::to_owned),
+        // Authentik doesn't track whether email addresses were verified, so they
+        // are trusted like Authentik itself does
+        email_verified: true,
+        locked: !entry.bool("is_active").unwrap_or(true),
+        password,
+        upstream_links,
+    })
+}
+
+/// Convert a password hash in the Django format
+fn convert_password(password: &str, report: &mut Report) -> Option<ImportedPassword> {
+    // Unusable passwords start with a `!`
+    if password.is_empty() || password.starts_with('!') {
+        return None;
+    }
+
+    let parts: Vec<_> = password.split('$').collect();
+    let imported = match parts.as_slice() {
+        ["pbkdf2_sha256", iterations, salt, hash] => iterations
+            .parse()
+            .ok()
+            .zip(decode_base64(hash))
+            .and_then(|(iterations, hash)| {
+                // Django uses the salt as-is, not base64-decoded
+                ImportedPassword::pbkdf2("sha256", iterations, salt.as_bytes(), &hash)
+            }),
+
+        // Django prefixes the PHC string of argon2 hashes with `argon2`
+        ["argon2", "argon2id", ..] => Some(ImportedPassword {
+            algorithm: PasswordAlgorithm::Argon2id,
+            hash: password.trim_start_matches("argon2").to_owned(),
+        }),
+
+        _ => None,
+    };
+
+    if imported.is_none() {
+        let algorithm = parts.first().copied().unwrap_or_default();
+        report.unsupported(format!("Passwords hashed with `{algorithm}`"));
+    }
+
+    imported
+}
+
+fn convert_client(entry: &Entry<'_>, report: &mut Report) -> Option<ImportedClient> {
+    let Some(client_id) = entry.str("client_id") else {
+        report.unsupported("OAuth 2.0 providers without a client ID");
+        return None;
+    };
+
+    let mut redirect_uris = Vec::new();
+    match entry.value("redirect_uris") {
+        // Before 2024.10, redirect URIs were a newline-separated string, where
+        // each line could be a regular expression
+        Some(Value::String(uris)) => {
+            for uri in uris.lines().map(str::trim).filter(|uri| !uri.is_empty()) {
+                match Url::parse(uri) {
+                    Ok(url) if !uri.contains('*') => redirect_uris.push(url),
+                    _ => report.unsupported("Redirect URIs matched with regular expressions"),
+                }
+            }
+        }
+
+        Some(Value::Sequence(uris)) => {
+            for uri in uris {
+                let mode = uri.get("matching_mode").and_then(Value::as_str);
+                let url = uri.get("url").and_then(Value::as_str);
+                match (mode, url.map(Url::parse)) {
+                    (Some("strict") | None, Some(Ok(url))) => redirect_uris.push(url),
+                    (Some("regex"), _) => {
+                        report.unsupported("Redirect URIs matched with regular expressions");
+                    }
+                    _ => report.unsupported("Invalid redirect URIs"),
+                }
+            }
+        }
+
+        _ => {}
+    }
+
+    let secret = if entry.str("client_type") == Some("public") {
+        None
+    } else {
+        let Some(secret) = entry.str("client_secret") else {
+            report.skip_client(client_id, "confidential client without an exported secret");
+            return None;
+        };
+        Some(secret.to_owned())
+    };
+
+    let client_credentials = secret.is_some();
+
+    Some(ImportedClient {
+        client_id: client_id.to_owned(),
+        name: entry.str("name").map(ToOwned::to_owned),
+        redirect_uris,
+        secret,
+        client_credentials,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_blueprint() {
+        let export = parse(
+            r"
+version: 1
+entries:
+- model: authentik_core.user
+  identifiers:
+    pk: 4
+  attrs:
+    username: alice
+    name: Alice Liddell
+    email: alice@example.com
+    is_active: true
+    type: internal
+    password: pbkdf2_sha256$1000$0123456789abcdef$pj4T35D2v4tYmC1sTJ1y5tcMADOdtnQGvuHmyYDQh2g=
+- model: authentik_core.user
+  identifiers:
+    pk: 5
+  attrs:
+    username: backend
+    type: service_account
+- model: authentik_sources_oauth.oauthsource
+  identifiers:
+    pk: 2a0d8f48-0b3c-4d6b-9f8a-6b5f8c2d7e21
+  attrs:
+    slug: github
+    name: GitHub
+- model: authentik_sources_oauth.useroauthsourceconnection
+  identifiers:
+    user: !Find [authentik_core.user, [username, alice]]
+    source: 2a0d8f48-0b3c-4d6b-9f8a-6b5f8c2d7e21
+  attrs:
+    identifier: '1234'
+- model: authentik_providers_oauth2.oauth2provider
+  identifiers:
+    pk: 1
+  attrs:
+    name: Web app
+    client_type: confidential
+    client_id: web
+    client_secret: s3cr3t
+    redirect_uris:
+    - matching_mode: strict
+      url: https://app.example.com/callback
+    - matching_mode: regex
+      url: https://.*\.example\.com/callback
+- model: authentik_flows.flow
+  identifiers:
+    slug: default-authentication-flow
+",
+        )
+        .unwrap();
+
+        assert_eq!(export.users.len(), 1);
+        let alice = &export.users[0];
+        assert_eq!(alice.username, "alice");
+        assert_eq!(alice.display_name.as_deref(), Some("Alice Liddell"));
+        assert_eq!(
+            alice.upstream_links,
+            vec![("github".to_owned(), "1234".to_owned())]
+        );
+        assert!(alice
+            .password
+            .as_ref()
+            .unwrap()
+            .hash
+            .starts_with("$pbkdf2-sha256$i=1000,l=32$MDEyMzQ1Njc4OWFiY2RlZg$"));
+
+        assert_eq!(export.clients.len(), 1);
+        assert_eq!(export.clients[0].redirect_uris.len(), 1);
+        assert_eq!(export.clients[0].secret.as_deref(), Some("s3cr3t"));
+
+        assert_eq!(export.report.skipped_users.len(), 1);
+        assert!(export
+            .report
+            .unsupported
+            .contains_key("Redirect URIs matched with regular expressions"));
+        assert!(export
+            .report
+            .unsupported
+            .contains_key("Entries of model `authentik_flows.flow`"));
+    }
+}
@@ -0,0 +1,459 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Parse Keycloak realm exports
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::Deserialize;
+use url::Url;
+
+use super::{decode_base64, Export, ImportedClient, ImportedPassword, ImportedUser, Report};
+
+/// Clients which are created by Keycloak in every realm
+const BUILTIN_CLIENTS: &[&str] = &[
+    "account",
+    "account-console",
+    "admin-cli",
+    "broker",
+    "realm-management",
+    "security-admin-console",
+];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Realm {
+    #[serde(default)]
+    users: Vec<User>,
+
+    #[serde(default)]
+    clients: Vec<Client>,
+
+    #[serde(default)]
+    identity_providers: Vec<serde_json::Value>,
+
+    #[serde(default)]
+    groups: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct User {
+    username: String,
+
+    #[serde(default = "default_true")]
+    enabled: bool,
+
+    email: Option<String>,
+
+    #[serde(default)]
+    email_verified: bool,
+
+    first_name: Option<String>,
+    last_name: Option<String>,
+
+    service_account_client_id: Option<String>,
+
+    #[serde(default)]
+    credentials: Vec<Credential>,
+
+    #[serde(default)]
+    federated_identities: Vec<FederatedIdentity>,
+
+    #[serde(default)]
+    required_actions: Vec<String>,
+
+    #[serde(default)]
+    groups: Vec<String>,
+
+    #[serde(default)]
+    realm_roles: Vec<String>,
+
+    #[serde(default)]
+    attributes: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Credential {
+    #[serde(rename = "type")]
+    kind: String,
+
+    /// A JSON-encoded [`SecretData`]
+    #[serde(default)]
+    secret_data: String,
+
+    /// A JSON-encoded [`CredentialData`]
+    #[serde(default)]
+    credential_data: String,
+}
+
+#[derive(Deserialize)]
+struct SecretData {
+    value: String,
+    salt: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CredentialData {
+    hash_iterations: u32,
+    algorithm: String,
+
+    #[serde(default)]
+    additional_parameters: BTreeMap<String, Vec<String>>,
+}
+
+impl CredentialData {
+    fn parameter<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.additional_parameters.get(name)?.first()?.parse().ok()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FederatedIdentity {
+    identity_provider: String,
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Client {
+    client_id: String,
+    name: Option<String>,
+
+    #[serde(default = "default_true")]
+    enabled: bool,
+
+    #[serde(default)]
+    public_client: bool,
+
+    secret: Option<String>,
+
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+
+    protocol: Option<String>,
+
+    #[serde(default)]
+    bearer_only: bool,
+
+    #[serde(default = "default_true")]
+    standard_flow_enabled: bool,
+
+    #[serde(default)]
+    implicit_flow_enabled: bool,
+
+    #[serde(default)]
+    direct_access_grants_enabled: bool,
+
+    #[serde(default)]
+    service_accounts_enabled: bool,
+
+    #[serde(default)]
+    attributes: BTreeMap<String, serde_json::Value>,
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+/// Parse a Keycloak realm export, as produced by `kc.sh export`
+pub(super) fn parse(content: &str) -> anyhow::Result<Export> {
+    let realm: Realm =
+        serde_json::from_str(content).context("Could not parse the Keycloak realm export")?;
+
+    let mut report = Report::default();
+
+    if !realm.identity_providers.is_empty() {
+        report.unsupported(
+            "Identity provider definitions, which must be configured as upstream providers",
+        );
+    }
+
+    if !realm.groups.is_empty() {
+        report.unsupported("Groups");
+    }
+
+    let users = realm
+        .users
+        .into_iter()
+        .filter_map(|user| convert_user(user, &mut report))
+        .collect();
+
+    let clients = realm
+        .clients
+        .into_iter()
+        .filter_map(|client| convert_client(client, &mut report))
+        .collect();
+
+    Ok(Export {
+        users,
+        clients,
+        report,
+    })
+}
+
+fn convert_user(user: User, report: &mut Report) -> Option<ImportedUser> {
+    if user.service_account_client_id.is_some() {
+        report.skip_user(&user.username, "service account of a client");
+        return None;
+    }
+
+    let mut password = None;
+    for credential in &user.credentials {
+        if credential.kind == "password" {
+            password = convert_password(credential, report);
+        } else {
+            report.unsupported(format!("Credentials of type `{}`", credential.kind));
+        }
+    }
+
+    if !user.required_actions.is_empty() {
+        report.unsupported("Required actions on users");
+    }
+
+    if !user.groups.is_empty() || !user.realm_roles.is_empty() {
+        report.unsupported("Group and role memberships");
+    }
+
+    if !user.attributes.is_empty() {
+        report.unsupported("Custom user attributes");
+    }
+
+    let display_name = match (user.first_name, user.last_name) {
+        (Some(first), Some(last)) => Some(format!("{first} {last}")),
+        (Some(name), None) | (None, Some(name)) => Some(name),
+        (None, None) => None,
+    };
+
+    let upstream_links = user
+        .federated_identities
+        .into_iter()
+        .map(|identity| (identity.identity_provider, identity.user_id))
+        .collect();
+
+    Some(ImportedUser {
+        username: user.username,
+        display_name,
+        email: user.email,
+        email_verified: user.email_verified,
+        locked: !user.enabled,
+        password,
+        upstream_links,
+    })
+}
+
+fn convert_password(credential: &Credential, report: &mut Report) -> Option<ImportedPassword> {
+    let (Ok(secret), Ok(data)) = (
+        serde_json::from_str::<SecretData>(&credential.secret_data),
+        serde_json::from_str::<CredentialData>(&credential.credential_data),
+    ) else {
+        report.unsupported("Password credentials without a readable hash");
+        return None;
+    };
+
+    let (Some(salt), Some(key)) = (decode_base64(&secret.salt), decode_base64(&secret.value))
+    else {
+        report.unsupported("Password credentials without a readable hash");
+        return None;
+    };
+
+    let password = match data.algorithm.as_str() {
+        "pbkdf2-sha256" => ImportedPassword::pbkdf2("sha256", data.hash_iterations, &salt, &key),
+        "pbkdf2-sha512" => ImportedPassword::pbkdf2("sha512", data.hash_iterations, &salt, &key),
+        "argon2" => data
+            .parameter::<String>("type")
+            .zip(data.parameter("memory"))
+            .zip(data.parameter("parallelism"))
+            .and_then(|((variant, memory), parallelism)| {
+                ImportedPassword::argon2(
+                    &variant,
+                    memory,
+                    data.hash_iterations,
+                    parallelism,
+                    &salt,
+                    &key,
+                )
+            }),
+        _ => None,
+    };
+
+    if password.is_none() {
+        report.unsupported(format!("Passwords hashed with `{}`", data.algorithm));
+    }
+
+    password
+}
+
+fn convert_client(client: Client, report: &mut Report) -> Option<ImportedClient> {
+    if BUILTIN_CLIENTS.contains(&client.client_id.as_str()) {
+        return None;
+    }
+
+    if client
+        .protocol
+        .as_deref()
+        .is_some_and(|p| p != "openid-connect")
+    {
+        report.skip_client(&client.client_id, "not an OpenID Connect client");
+        return None;
+    }
+
+    if client.bearer_only {
+        report.skip_client(&client.client_id, "bearer-only client");
+        return None;
+    }
+
+    if !client.enabled {
+        report.skip_client(&client.client_id, "disabled client");
+        return None;
+    }
+
+    if client.implicit_flow_enabled {
+        report.unsupported("Implicit flow on clients");
+    }
+
+    if client.direct_access_grants_enabled {
+        report.unsupported("Direct access grants (resource owner password flow) on clients");
+    }
+
+    if client.attributes.contains_key("post.logout.redirect.uris") {
+        report.unsupported("Post-logout redirect URIs on clients");
+    }
+
+    let mut redirect_uris = Vec::new();
+    if client.standard_flow_enabled {
+        for redirect_uri in &client.redirect_uris {
+            if redirect_uri.contains('*') {
+                report.unsupported("Wildcard redirect URIs");
+                continue;
+            }
+
+            match Url::parse(redirect_uri) {
+                Ok(url) => redirect_uris.push(url),
+                Err(_) => report.unsupported("Relative redirect URIs"),
+            }
+        }
+    }
+
+    let confidential = !client.public_client;
+    let secret = if confidential { client.secret } else { None };
+
+    if confidential && secret.is_none() {
+        report.skip_client(
+            &client.client_id,
+            "confidential client without an exported secret",
+        );
+        return None;
+    }
+
+    Some(ImportedClient {
+        client_id: client.client_id,
+        name: client.name,
+        redirect_uris,
+        secret,
+        client_credentials: confidential && client.service_accounts_enabled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_realm() {
+        let export = parse(
+            r#"{
+                "realm": "example",
+                "users": [
+                    {
+                        "username": "alice",
+                        "enabled": true,
+                        "email": "alice@example.com",
+                        "emailVerified": true,
+                        "firstName": "Alice",
+                        "lastName": "Liddell",
+                        "credentials": [
+                            {
+                                "type": "password",
+                                "secretData": "{\"value\":\"pj4T35D2v4tYmC1sTJ1y5tcMADOdtnQGvuHmyYDQh2g4XkpZpcdyAmZD1VE7UxlrMNwDtaqSURWbbWSpagqFRg==\",\"salt\":\"MDEyMzQ1Njc4OWFiY2RlZg==\"}",
+                                "credentialData": "{\"hashIterations\":1000,\"algorithm\":\"pbkdf2-sha256\"}"
+                            },
+                            { "type": "otp" }
+                        ],
+                        "federatedIdentities": [
+                            { "identityProvider": "github", "userId": "1234", "userName": "alice" }
+                        ]
+                    },
+                    {
+                        "username": "bob",
+                        "enabled": false
+                    },
+                    {
+                        "username": "service-account-backend",
+                        "serviceAccountClientId": "backend"
+                    }
+                ],
+                "clients": [
+                    { "clientId": "account", "redirectUris": ["/realms/example/account/*"] },
+                    {
+                        "clientId": "web",
+                        "name": "Web app",
+                        "publicClient": true,
+                        "redirectUris": ["https://app.example.com/callback", "https://app.example.com/*"]
+                    },
+                    {
+                        "clientId": "backend",
+                        "secret": "s3cr3t",
+                        "standardFlowEnabled": false,
+                        "serviceAccountsEnabled": true
+                    },
+                    { "clientId": "saml-app", "protocol": "saml" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(export.users.len(), 2);
+        let alice = &export.users[0];
+        assert_eq!(alice.username, "alice");
+        assert_eq!(alice.display_name.as_deref(), Some("Alice Liddell"));
+        assert!(alice.email_verified);
+        assert!(!alice.locked);
+        assert_eq!(
+            alice.upstream_links,
+            vec![("github".to_owned(), "1234".to_owned())]
+        );
+        assert!(alice
+            .password
+            .as_ref()
+            .unwrap()
+            .hash
+            .starts_with("$pbkdf2-sha256$i=1000,l=64$MDEyMzQ1Njc4OWFiY2RlZg$"));
+        assert!(export.users[1].locked);
+
+        assert_eq!(export.clients.len(), 2);
+        assert_eq!(export.clients[0].client_id, "web");
+        assert_eq!(export.clients[0].redirect_uris.len(), 1);
+        assert!(export.clients[0].secret.is_none());
+        assert_eq!(export.clients[1].client_id, "backend");
+        assert!(export.clients[1].redirect_uris.is_empty());
+        assert!(export.clients[1].client_credentials);
+
+        assert_eq!(export.report.skipped_users.len(), 1);
+        assert_eq!(export.report.skipped_clients.len(), 1);
+        assert!(export
+            .report
+            .unsupported
+            .contains_key("Credentials of type `otp`"));
+        assert!(export
+            .report
+            .unsupported
+            .contains_key("Wildcard redirect URIs"));
+    }
+}
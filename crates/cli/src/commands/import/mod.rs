@@ -0,0 +1,607 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Import users and clients from the exports of other identity providers
+
+use std::{collections::BTreeMap, process::ExitCode};
+
+use anyhow::Context;
+use base64ct::{Base64, Base64Unpadded, Encoding};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use console::{style, Term};
+use figment::Figment;
+use mas_config::{
+    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, PasswordAlgorithm,
+    PasswordsConfig, SecretsConfig,
+};
+use mas_data_model::{Ulid, UpstreamOAuthProvider};
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_keystore::Encrypter;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob},
+    user::UserHomeserver,
+    Clock, RepositoryAccess, SystemClock,
+};
+use mas_storage_pg::PgRepository;
+use oauth2_types::requests::GrantType;
+use rand::{RngCore, SeedableRng};
+use sqlx::Acquire;
+use tracing::{info, info_span, warn};
+use url::Url;
+
+use crate::util::database_connection_from_config;
+
+mod authentik;
+mod keycloak;
+
+#[derive(Parser, Debug)]
+pub(super) struct Options {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Parser, Debug)]
+enum Subcommand {
+    /// Import the users and clients of a Keycloak realm export
+    ///
+    /// The export is the JSON file produced by `kc.sh export --realm <realm>`
+    /// or by the "Partial export" of the admin console.
+    Keycloak(ImportOptions),
+
+    /// Import the users and clients of an Authentik blueprint export
+    ///
+    /// The export is the YAML file produced by `ak export_blueprint` or by the
+    /// "Export" action on a blueprint in the admin interface.
+    Authentik(ImportOptions),
+}
+
+#[derive(Parser, Debug)]
+struct ImportOptions {
+    /// Path to the export file
+    file: Utf8PathBuf,
+
+    /// Map an identity provider of the export to an upstream OAuth 2.0
+    /// provider, to import the links of the users to it. The format is
+    /// `<alias>=<upstream provider ID>`, where the alias is the Keycloak
+    /// identity provider alias, or the Authentik source slug.
+    #[arg(long = "provider", value_name = "ALIAS=ID", value_parser = parse_provider_mapping)]
+    providers: Vec<(String, Ulid)>,
+
+    /// Do not actually write to the database
+    #[arg(long)]
+    dry_run: bool,
+}
+
+fn parse_provider_mapping(s: &str) -> Result<(String, Ulid), anyhow::Error> {
+    let (alias, id) = s.split_once('=').context("Invalid format")?;
+    let id = id.parse().context("Invalid upstream provider ID")?;
+    Ok((alias.to_owned(), id))
+}
+
+/// The content of an export, in a provider-independent format
+#[derive(Debug, Default)]
+struct Export {
+    users: Vec<ImportedUser>,
+    clients: Vec<ImportedClient>,
+    report: Report,
+}
+
+/// A user to import
+#[derive(Debug)]
+struct ImportedUser {
+    username: String,
+    display_name: Option<String>,
+    email: Option<String>,
+    email_verified: bool,
+    locked: bool,
+    password: Option<ImportedPassword>,
+
+    /// Links to identity providers, as pairs of alias and subject
+    upstream_links: Vec<(String, String)>,
+}
+
+/// A password hash, converted to the PHC string format used by the service
+#[derive(Debug)]
+struct ImportedPassword {
+    algorithm: PasswordAlgorithm,
+    hash: String,
+}
+
+impl ImportedPassword {
+    /// Convert a PBKDF2 hash, given its raw salt and derived key
+    fn pbkdf2(digest: &str, iterations: u32, salt: &[u8], key: &[u8]) -> Option<Self> {
+        let id = match digest {
+            "sha256" => "pbkdf2-sha256",
+            "sha512" => "pbkdf2-sha512",
+            _ => return None,
+        };
+
+        let hash = format!(
+            "${id}$i={iterations},l={length}${salt}${key}",
+            length = key.len(),
+            salt = Base64Unpadded::encode_string(salt),
+            key = Base64Unpadded::encode_string(key),
+        );
+
+        Some(Self {
+            algorithm: PasswordAlgorithm::Pbkdf2,
+            hash,
+        })
+    }
+
+    /// Convert an Argon2 hash, given its parameters, raw salt and hash
+    fn argon2(
+        variant: &str,
+        memory: u32,
+        iterations: u32,
+        parallelism: u32,
+        salt: &[u8],
+        key: &[u8],
+    ) -> Option<Self> {
+        if !matches!(variant, "id" | "i" | "d") {
+            return None;
+        }
+
+        let hash = format!(
+            "$argon2{variant}$v=19$m={memory},t={iterations},p={parallelism}${salt}${key}",
+            salt = Base64Unpadded::encode_string(salt),
+            key = Base64Unpadded::encode_string(key),
+        );
+
+        Some(Self {
+            algorithm: PasswordAlgorithm::Argon2id,
+            hash,
+        })
+    }
+}
+
+/// Decode a base64 string, with or without padding
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    Base64::decode_vec(value)
+        .or_else(|_| Base64Unpadded::decode_vec(value))
+        .ok()
+}
+
+/// An OAuth 2.0 client to import
+#[derive(Debug)]
+struct ImportedClient {
+    client_id: String,
+    name: Option<String>,
+    redirect_uris: Vec<Url>,
+
+    /// The client secret, for confidential clients
+    secret: Option<String>,
+
+    /// Whether the client can use the client credentials grant
+    client_credentials: bool,
+}
+
+/// What happened during the import
+#[derive(Debug, Default)]
+struct Report {
+    users_imported: usize,
+    skipped_users: Vec<(String, String)>,
+    clients_imported: Vec<(String, Ulid)>,
+    skipped_clients: Vec<(String, String)>,
+
+    /// Features of the export which were not imported, with the number of
+    /// times they were found
+    unsupported: BTreeMap<String, usize>,
+}
+
+impl Report {
+    fn unsupported(&mut self, feature: impl Into<String>) {
+        *self.unsupported.entry(feature.into()).or_default() += 1;
+    }
+
+    fn skip_user(&mut self, username: &str, reason: impl Into<String>) {
+        self.skipped_users
+            .push((username.to_owned(), reason.into()));
+    }
+
+    fn skip_client(&mut self, client_id: &str, reason: impl Into<String>) {
+        self.skipped_clients
+            .push((client_id.to_owned(), reason.into()));
+    }
+
+    fn show(&self, term: &Term, dry_run: bool) -> std::io::Result<()> {
+        let title = if dry_run {
+            "Import report (dry run, nothing was written)"
+        } else {
+            "Import report"
+        };
+        term.write_line(&format!("{}\n", style(title).bold()))?;
+
+        term.write_line(&format!("Users imported: {}", self.users_imported))?;
+        for (username, reason) in &self.skipped_users {
+            term.write_line(&format!(
+                "  {} {username}: {reason}",
+                style("skipped").yellow()
+            ))?;
+        }
+
+        term.write_line(&format!(
+            "\nClients imported: {}",
+            self.clients_imported.len()
+        ))?;
+        for (client_id, id) in &self.clients_imported {
+            term.write_line(&format!("  {client_id} → {id}"))?;
+        }
+        for (client_id, reason) in &self.skipped_clients {
+            term.write_line(&format!(
+                "  {} {client_id}: {reason}",
+                style("skipped").yellow()
+            ))?;
+        }
+
+        if !self.unsupported.is_empty() {
+            term.write_line(&format!(
+                "\n{}",
+                style("Unsupported features, which were not imported:").bold()
+            ))?;
+            for (feature, count) in &self.unsupported {
+                term.write_line(&format!("  - {feature} (×{count})"))?;
+            }
+        }
+
+        term.flush()
+    }
+}
+
+/// Whether the username is a valid Matrix ID localpart
+fn is_valid_localpart(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 255
+        && username.chars().all(|c| {
+            c.is_ascii_lowercase()
+                || c.is_ascii_digit()
+                || matches!(c, '.' | '_' | '=' | '-' | '/' | '+')
+        })
+}
+
+impl Options {
+    pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+        let (kind, options) = match self.subcommand {
+            Subcommand::Keycloak(options) => ("keycloak", options),
+            Subcommand::Authentik(options) => ("authentik", options),
+        };
+
+        let _span =
+            info_span!("cli.import", import.kind = kind, import.file = %options.file).entered();
+
+        let content = tokio::fs::read_to_string(&options.file)
+            .await
+            .with_context(|| format!("Could not read the export file {}", options.file))?;
+
+        let export = match kind {
+            "keycloak" => keycloak::parse(&content)?,
+            _ => authentik::parse(&content)?,
+        };
+
+        info!(
+            users = export.users.len(),
+            clients = export.clients.len(),
+            "Parsed the export"
+        );
+
+        let database_config = DatabaseConfig::extract_or_default(figment)?;
+        let passwords_config = PasswordsConfig::extract_or_default(figment)?;
+        let secrets_config = SecretsConfig::extract(figment)?;
+        let encrypter = secrets_config.encrypter();
+
+        // Imported hashes can only be verified by schemes without a secret, and the
+        // passwords get upgraded to the current scheme on the next login
+        let password_schemes: Vec<_> = if passwords_config.enabled() {
+            passwords_config
+                .load()
+                .await?
+                .into_iter()
+                .filter(|(_, _, _, secret)| secret.is_none())
+                .map(|(version, algorithm, _, _)| (version, algorithm))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut conn = database_connection_from_config(&database_config).await?;
+        let txn = conn.begin().await?;
+        let mut repo = PgRepository::from_conn(txn);
+
+        let mut providers = BTreeMap::new();
+        for (alias, id) in options.providers {
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(id)
+                .await?
+                .with_context(|| format!("Upstream provider {id} not found"))?;
+            providers.insert(alias, provider);
+        }
+
+        let clock = SystemClock::default();
+        // XXX: we should disallow SeedableRng::from_entropy
+        let mut rng = rand_chacha::ChaChaRng::from_entropy();
+
+        let importer = Importer {
+            providers: &providers,
+            password_schemes: &password_schemes,
+            encrypter: &encrypter,
+        };
+        let report = importer.import(&mut repo, &mut rng, &clock, export).await?;
+
+        if options.dry_run {
+            info!("Dry run, not saving");
+            repo.into_inner().rollback().await?;
+        } else {
+            repo.into_inner().commit().await?;
+        }
+
+        report.show(&Term::buffered_stdout(), options.dry_run)?;
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Writes the content of an export to the database
+struct Importer<'a> {
+    /// Upstream providers, by alias of the identity provider in the export
+    providers: &'a BTreeMap<String, UpstreamOAuthProvider>,
+
+    /// Password schemes without a secret, from the newest to the oldest
+    password_schemes: &'a [(u16, PasswordAlgorithm)],
+
+    encrypter: &'a Encrypter,
+}
+
+impl Importer<'_> {
+    /// Get the version of the newest password scheme which can verify hashes
+    /// of the given algorithm
+    fn password_scheme_for(&self, algorithm: PasswordAlgorithm) -> Option<u16> {
+        self.password_schemes
+            .iter()
+            .find(|(_, scheme)| {
+                matches!(
+                    (scheme, algorithm),
+                    (PasswordAlgorithm::Pbkdf2, PasswordAlgorithm::Pbkdf2)
+                        | (PasswordAlgorithm::Argon2id, PasswordAlgorithm::Argon2id)
+                )
+            })
+            .map(|(version, _)| *version)
+    }
+
+    async fn import<E: std::error::Error + Send + Sync + 'static>(
+        &self,
+        repo: &mut dyn RepositoryAccess<Error = E>,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        export: Export,
+    ) -> anyhow::Result<Report> {
+        let Export {
+            users,
+            clients,
+            mut report,
+        } = export;
+
+        for user in users {
+            let _span = info_span!("user", user.username = user.username).entered();
+            self.import_user(repo, rng, clock, user, &mut report)
+                .await?;
+        }
+
+        for client in clients {
+            let _span = info_span!("client", client.client_id = client.client_id).entered();
+            self.import_client(repo, rng, clock, client, &mut report)
+                .await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn import_user<E: std::error::Error + Send + Sync + 'static>(
+        &self,
+        repo: &mut dyn RepositoryAccess<Error = E>,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        imported: ImportedUser,
+        report: &mut Report,
+    ) -> anyhow::Result<()> {
+        let username = imported.username.to_lowercase();
+        if !is_valid_localpart(&username) {
+            report.skip_user(&imported.username, "not a valid Matrix ID localpart");
+            return Ok(());
+        }
+
+        if repo.user().exists(UserHomeserver::Main, &username).await? {
+            report.skip_user(
+                &imported.username,
+                "a user with this username already exists",
+            );
+            return Ok(());
+        }
+
+        let mut user = repo.user().add(rng, clock, username).await?;
+
+        if let Some(password) = imported.password {
+            if let Some(version) = self.password_scheme_for(password.algorithm) {
+                repo.user_password()
+                    .add(rng, clock, &user, version, password.hash, None)
+                    .await?;
+            } else {
+                report.unsupported(format!(
+                    "Passwords hashed with {:?}, as no password scheme without a secret is configured for it",
+                    password.algorithm
+                ));
+            }
+        }
+
+        if let Some(email) = imported.email {
+            let user_email = repo.user_email().add(rng, clock, &user, email).await?;
+            if imported.email_verified {
+                let user_email = repo
+                    .user_email()
+                    .mark_as_verified(clock, user_email)
+                    .await?;
+                repo.user_email().set_as_primary(&user_email).await?;
+                user.primary_user_email_id = Some(user_email.id);
+            }
+        }
+
+        for (alias, subject) in imported.upstream_links {
+            let Some(provider) = self.providers.get(&alias) else {
+                report.unsupported(format!(
+                    "Links to the identity provider `{alias}`, which is not mapped to an upstream provider"
+                ));
+                continue;
+            };
+
+            if repo
+                .upstream_oauth_link()
+                .find_by_subject(provider, &subject)
+                .await?
+                .is_some()
+            {
+                warn!(%provider.id, subject, "Upstream link already exists, skipping");
+                continue;
+            }
+
+            let link = repo
+                .upstream_oauth_link()
+                .add(rng, clock, provider, subject)
+                .await?;
+            repo.upstream_oauth_link()
+                .associate_to_user(&link, &user)
+                .await?;
+        }
+
+        if imported.locked {
+            user = repo.user().lock(clock, user).await?;
+        }
+
+        let mut provision_job = ProvisionUserJob::new(&user);
+        if let Some(display_name) = imported.display_name {
+            provision_job = provision_job.set_display_name(display_name);
+        }
+        repo.job().schedule_job(provision_job).await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_registered(rng, clock, &user))
+            .await?;
+
+        info!(%user.id, "User imported");
+        report.users_imported += 1;
+        Ok(())
+    }
+
+    async fn import_client<E: std::error::Error + Send + Sync + 'static>(
+        &self,
+        repo: &mut dyn RepositoryAccess<Error = E>,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        imported: ImportedClient,
+        report: &mut Report,
+    ) -> anyhow::Result<()> {
+        if imported.redirect_uris.is_empty() && !imported.client_credentials {
+            report.skip_client(&imported.client_id, "no supported redirect URI");
+            return Ok(());
+        }
+
+        let mut grant_types = Vec::new();
+        if !imported.redirect_uris.is_empty() {
+            grant_types.push(GrantType::AuthorizationCode);
+            grant_types.push(GrantType::RefreshToken);
+        }
+        if imported.client_credentials {
+            grant_types.push(GrantType::ClientCredentials);
+        }
+
+        let (auth_method, encrypted_client_secret) = if let Some(secret) = &imported.secret {
+            (
+                OAuthClientAuthenticationMethod::ClientSecretBasic,
+                Some(self.encrypter.encrypt_to_string(secret.as_bytes())?),
+            )
+        } else {
+            (OAuthClientAuthenticationMethod::None, None)
+        };
+
+        let client = repo
+            .oauth2_client()
+            .add(
+                rng,
+                clock,
+                imported.redirect_uris,
+                encrypted_client_secret,
+                None,
+                grant_types,
+                imported.name,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(auth_method),
+                None,
+                None,
+                false,
+                None,
+                false,
+            )
+            .await?;
+
+        info!(%client.id, "Client imported");
+        report
+            .clients_imported
+            .push((imported.client_id, client.id));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_handlers::passwords::{Hasher, PasswordManager};
+    use zeroize::Zeroizing;
+
+    use super::*;
+
+    #[test]
+    fn test_valid_localpart() {
+        assert!(is_valid_localpart("alice"));
+        assert!(is_valid_localpart("alice.bob-42"));
+        assert!(!is_valid_localpart(""));
+        assert!(!is_valid_localpart("Alice"));
+        assert!(!is_valid_localpart("alice@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_imported_pbkdf2_hash() {
+        // PBKDF2-HMAC-SHA256 of "hunter2", with 1000 iterations, a 16-byte salt and a
+        // 64-byte derived key, like Keycloak does
+        let salt = b"0123456789abcdef";
+        let key = decode_base64(
+            "pj4T35D2v4tYmC1sTJ1y5tcMADOdtnQGvuHmyYDQh2g4XkpZpcdyAmZD1VE7UxlrMNwDtaqSURWbbWSpagqFRg==",
+        )
+        .unwrap();
+        let password = ImportedPassword::pbkdf2("sha256", 1000, salt, &key).unwrap();
+
+        let manager = PasswordManager::new(0, [(1, Hasher::pbkdf2(None))]).unwrap();
+        manager
+            .verify(
+                1,
+                Zeroizing::new(b"hunter2".to_vec()),
+                password.hash.clone(),
+            )
+            .await
+            .unwrap();
+        manager
+            .verify(1, Zeroizing::new(b"hunter3".to_vec()), password.hash)
+            .await
+            .unwrap_err();
+
+        // Unknown digests are not supported
+        assert!(ImportedPassword::pbkdf2("md5", 1000, salt, &key).is_none());
+    }
+}
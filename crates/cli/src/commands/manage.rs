@@ -7,33 +7,52 @@
 use std::{collections::BTreeMap, process::ExitCode};
 
 use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
 use clap::{ArgAction, CommandFactory, Parser};
 use console::{pad_str, style, Alignment, Style, Term};
 use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, Password};
 use figment::Figment;
+use ipnetwork::IpNetwork;
 use mas_config::{
-    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, MatrixConfig, PasswordsConfig,
+    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, HttpConfig,
+    InactiveAccountsConfig, KeyRotationConfig, MatrixConfig, PasswordsConfig, SecretsConfig,
+};
+use mas_data_model::{
+    AuditEventKind, Device, SigningKey, SigningKeyState, TokenType, Ulid, UpstreamOAuthProvider,
+    User,
 };
-use mas_data_model::{Device, TokenType, Ulid, UpstreamOAuthProvider, User};
 use mas_email::Address;
 use mas_handlers::HttpClientFactory;
-use mas_matrix::HomeserverConnection;
+use mas_matrix::{HomeserverConnection, MultiHomeserverConnection};
 use mas_matrix_synapse::SynapseConnection;
+use mas_router::{EmergencyAccess, UrlBuilder};
 use mas_storage::{
+    audit::AuditEventRepository,
     compat::{CompatAccessTokenRepository, CompatSessionFilter, CompatSessionRepository},
     job::{
-        DeactivateUserJob, JobRepositoryExt, ProvisionUserJob, ReactivateUserJob, SyncDevicesJob,
+        DeactivateUserJob, DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob,
+        ReactivateUserJob, SyncDevicesJob,
     },
     oauth2::OAuth2SessionFilter,
-    user::{BrowserSessionFilter, UserEmailRepository, UserPasswordRepository, UserRepository},
+    user::{
+        BrowserSessionFilter, UserEmailRepository, UserEmergencyAccessRepository, UserHomeserver,
+        UserPasswordRepository, UserRepository,
+    },
     Clock, RepositoryAccess, SystemClock,
 };
 use mas_storage_pg::{DatabaseError, PgRepository};
-use rand::{RngCore, SeedableRng};
+use mas_tower::FaultInjector;
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    RngCore, SeedableRng,
+};
 use sqlx::{types::Uuid, Acquire};
 use tracing::{error, info, info_span, warn};
 
-use crate::util::{database_connection_from_config, password_manager_from_config};
+use crate::util::{
+    database_connection_from_config, homeserver_connection_from_config,
+    inactive_accounts_from_config, password_manager_from_config,
+};
 
 const USER_ATTRIBUTES_HEADING: &str = "User attributes";
 
@@ -66,6 +85,8 @@ enum Subcommand {
     VerifyEmail { username: String, email: String },
 
     /// Set a user password
+    ///
+    /// This also ends all the browser sessions of the user.
     SetPassword {
         username: String,
         password: String,
@@ -73,6 +94,10 @@ enum Subcommand {
         /// configured complexity.
         #[clap(long)]
         ignore_complexity: bool,
+
+        /// Require the user to change the password on their next login.
+        #[clap(long)]
+        must_change: bool,
     },
 
     /// Issue a compatibility token
@@ -89,6 +114,25 @@ enum Subcommand {
         admin: bool,
     },
 
+    /// Issue a one-time link to log in as an administrator, for emergencies
+    ///
+    /// The link starts a browser session for the user, even if the upstream
+    /// identity providers or the email server are unavailable. It can only be
+    /// used once, from the given network, and expires after a few minutes.
+    IssueEmergencyAccess {
+        /// User for which to issue the link. It must be allowed to request
+        /// admin privileges.
+        username: String,
+
+        /// Network from which the link can be used, e.g. `192.0.2.10/32`
+        #[arg(long, value_name = "CIDR")]
+        allowed_network: IpNetwork,
+
+        /// How many minutes the link is valid for
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=60))]
+        ttl_minutes: u16,
+    },
+
     /// Trigger a provisioning job for all users
     ProvisionAllUsers,
 
@@ -118,6 +162,24 @@ enum Subcommand {
         username: String,
     },
 
+    /// Warn the users who didn't use their account for a long time, and
+    /// deactivate the account of those who were warned long enough ago
+    ///
+    /// This uses the `inactive_accounts` section of the configuration, even if
+    /// the periodic processing is not enabled.
+    ProcessInactiveUsers {
+        /// Only report what would be done
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Verify that the audit log was not tampered with
+    VerifyAuditLog,
+
+    /// List the signing keys generated by the automated key rotation, and
+    /// when they activate or get retired
+    ListSigningKeys,
+
     /// Register a user
     ///
     /// This will interactively prompt for the user's attributes unless the
@@ -182,6 +244,7 @@ impl Options {
                 username,
                 password,
                 ignore_complexity,
+                must_change,
             } => {
                 let _span =
                     info_span!("cli.manage.set_password", user.username = %username).entered();
@@ -196,7 +259,7 @@ impl Options {
                 let mut repo = PgRepository::from_conn(txn);
                 let user = repo
                     .user()
-                    .find_by_username(&username)
+                    .find_by_username(UserHomeserver::Main, &username)
                     .await?
                     .context("User not found")?;
 
@@ -209,11 +272,41 @@ impl Options {
 
                 let (version, hashed_password) = password_manager.hash(&mut rng, password).await?;
 
-                repo.user_password()
+                let password = repo
+                    .user_password()
                     .add(&mut rng, &clock, &user, version, hashed_password, None)
                     .await?;
 
-                info!(%user.id, %user.username, "Password changed");
+                if must_change {
+                    repo.user_password()
+                        .require_change(&clock, password)
+                        .await?;
+                }
+
+                let filter = BrowserSessionFilter::new().for_user(&user).active_only();
+                let ended_browser_sessions =
+                    repo.browser_session().finish_bulk(&clock, filter).await?;
+
+                repo.audit_event()
+                    .add(
+                        &mut rng,
+                        &clock,
+                        AuditEventKind::PasswordChanged,
+                        None,
+                        Some(user.id),
+                        None,
+                        None,
+                        serde_json::json!({ "method": "cli" }),
+                    )
+                    .await?;
+
+                info!(
+                    %user.id,
+                    %user.username,
+                    must_change,
+                    ended_browser_sessions,
+                    "Password changed"
+                );
                 repo.into_inner().commit().await?;
 
                 Ok(ExitCode::SUCCESS)
@@ -234,7 +327,7 @@ impl Options {
 
                 let user = repo
                     .user()
-                    .find_by_username(&username)
+                    .find_by_username(UserHomeserver::Main, &username)
                     .await?
                     .context("User not found")?;
 
@@ -268,7 +361,7 @@ impl Options {
 
                 let user = repo
                     .user()
-                    .find_by_username(&username)
+                    .find_by_username(UserHomeserver::Main, &username)
                     .await?
                     .context("User not found")?;
 
@@ -304,6 +397,87 @@ impl Options {
                 Ok(ExitCode::SUCCESS)
             }
 
+            SC::IssueEmergencyAccess {
+                username,
+                allowed_network,
+                ttl_minutes,
+            } => {
+                let _span = info_span!(
+                    "cli.manage.issue_emergency_access",
+                    user.username = username
+                )
+                .entered();
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let http_config = HttpConfig::extract_or_default(figment)?;
+                let url_builder =
+                    UrlBuilder::new(http_config.public_base, http_config.issuer, None);
+
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(UserHomeserver::Main, &username)
+                    .await?
+                    .context("User not found")?;
+
+                if !user.is_valid() {
+                    error!(%user.id, "User is locked");
+                    return Ok(ExitCode::FAILURE);
+                }
+
+                if !user.can_request_admin {
+                    error!(%user.id, "User is not allowed to request admin privileges");
+                    return Ok(ExitCode::FAILURE);
+                }
+
+                let token = Alphanumeric.sample_string(&mut rng, 32);
+                let access_token = repo
+                    .user_emergency_access()
+                    .add(
+                        &mut rng,
+                        &clock,
+                        &user,
+                        token,
+                        allowed_network,
+                        Duration::minutes(ttl_minutes.into()),
+                    )
+                    .await?;
+
+                repo.audit_event()
+                    .add(
+                        &mut rng,
+                        &clock,
+                        AuditEventKind::EmergencyAccessIssued,
+                        None,
+                        Some(user.id),
+                        None,
+                        None,
+                        serde_json::json!({
+                            "user_emergency_access_token_id": access_token.id.to_string(),
+                            "allowed_network": allowed_network.to_string(),
+                            "expires_at": access_token.expires_at,
+                        }),
+                    )
+                    .await?;
+
+                repo.into_inner().commit().await?;
+
+                let url =
+                    url_builder.absolute_url_for(&EmergencyAccess::new(access_token.token.clone()));
+                warn!(
+                    user_emergency_access_token.id = %access_token.id,
+                    %user.id,
+                    %user.username,
+                    %allowed_network,
+                    %access_token.expires_at,
+                    "Emergency access link issued: {url}"
+                );
+
+                Ok(ExitCode::SUCCESS)
+            }
+
             SC::ProvisionAllUsers => {
                 let _span = info_span!("cli.manage.provision_all_users").entered();
                 let database_config = DatabaseConfig::extract_or_default(figment)?;
@@ -339,7 +513,7 @@ impl Options {
 
                 let user = repo
                     .user()
-                    .find_by_username(&username)
+                    .find_by_username(UserHomeserver::Main, &username)
                     .await?
                     .context("User not found")?;
 
@@ -409,7 +583,7 @@ impl Options {
 
                 let user = repo
                     .user()
-                    .find_by_username(&username)
+                    .find_by_username(UserHomeserver::Main, &username)
                     .await?
                     .context("User not found")?;
 
@@ -441,7 +615,7 @@ impl Options {
 
                 let user = repo
                     .user()
-                    .find_by_username(&username)
+                    .find_by_username(UserHomeserver::Main, &username)
                     .await?
                     .context("User not found")?;
 
@@ -455,6 +629,95 @@ impl Options {
                 Ok(ExitCode::SUCCESS)
             }
 
+            SC::ProcessInactiveUsers { dry_run } => {
+                let _span = info_span!("cli.manage.process_inactive_users").entered();
+                let inactive_accounts_config = InactiveAccountsConfig::extract_or_default(figment)?;
+                let settings = inactive_accounts_from_config(&inactive_accounts_config)?;
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let report =
+                    mas_tasks::process_inactive_accounts(&mut repo, &clock, &settings).await?;
+
+                for user in &report.cleared {
+                    info!(%user.id, %user.username, "User came back, forgetting the warning");
+                }
+
+                for user in &report.deactivated {
+                    warn!(%user.id, %user.username, "Deactivating the account of the user");
+                }
+
+                for user in &report.warned {
+                    info!(%user.id, %user.username, "Warning the user about their inactivity");
+                }
+
+                info!(
+                    cleared = report.cleared.len(),
+                    warned = report.warned.len(),
+                    deactivated = report.deactivated.len(),
+                    "Processed inactive accounts"
+                );
+
+                let txn = repo.into_inner();
+                if dry_run {
+                    info!("Dry run, not saving");
+                    txn.rollback().await?;
+                } else {
+                    txn.commit().await?;
+                }
+
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::VerifyAuditLog => {
+                let _span = info_span!("cli.manage.verify_audit_log").entered();
+                let config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let broken = repo.audit_event().verify_chain().await?;
+                repo.into_inner().rollback().await?;
+
+                if let Some(id) = broken {
+                    error!(audit_event.id = %id, "The audit log was tampered with");
+                    return Ok(ExitCode::FAILURE);
+                }
+
+                info!("The audit log is intact");
+                Ok(ExitCode::SUCCESS)
+            }
+
+            SC::ListSigningKeys => {
+                let _span = info_span!("cli.manage.list_signing_keys").entered();
+                let secrets_config = SecretsConfig::extract(figment)?;
+                let database_config = DatabaseConfig::extract_or_default(figment)?;
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let keys = repo.signing_key().list_unretired().await?;
+                repo.into_inner().rollback().await?;
+
+                if let Some(key_rotation) = &secrets_config.key_rotation {
+                    info!(
+                        algorithm = %key_rotation.algorithm,
+                        interval = ?key_rotation.interval,
+                        grace_period = ?key_rotation.grace_period,
+                        retention = ?key_rotation.retention,
+                        "Automated rotation of signing keys is enabled"
+                    );
+                } else if !keys.is_empty() {
+                    warn!("Automated rotation of signing keys is disabled, the keys below are not used");
+                }
+
+                list_signing_keys(&keys, secrets_config.key_rotation.as_ref(), clock.now())?;
+
+                Ok(ExitCode::SUCCESS)
+            }
+
             SC::RegisterUser {
                 username,
                 password,
@@ -472,11 +735,10 @@ impl Options {
                 let matrix_config = MatrixConfig::extract(figment)?;
 
                 let password_manager = password_manager_from_config(&password_config).await?;
-                let homeserver = SynapseConnection::new(
-                    matrix_config.homeserver,
-                    matrix_config.endpoint,
-                    matrix_config.secret,
-                    http_client_factory,
+                let homeserver = homeserver_connection_from_config(
+                    &matrix_config,
+                    &http_client_factory,
+                    &FaultInjector::disabled(),
                 );
                 let mut conn = database_connection_from_config(&database_config).await?;
                 let txn = conn.begin().await?;
@@ -722,10 +984,72 @@ impl std::fmt::Display for HumanReadable<&UpstreamOAuthProvider> {
     }
 }
 
+/// Log the state of each signing key, and when it is expected to change
+fn list_signing_keys(
+    keys: &[SigningKey],
+    key_rotation: Option<&KeyRotationConfig>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    if keys.is_empty() {
+        info!("No signing key was generated");
+        return Ok(());
+    }
+
+    let interval = key_rotation
+        .map(|key_rotation| Duration::from_std(key_rotation.interval))
+        .transpose()?;
+    let retention = key_rotation
+        .map(|key_rotation| Duration::from_std(key_rotation.retention))
+        .transpose()?;
+
+    for key in keys {
+        let kid = &key.kid;
+        let algorithm = &key.algorithm;
+        match key.state(keys, now) {
+            SigningKeyState::Pending => {
+                info!(
+                    kid,
+                    %algorithm,
+                    activates_at = %key.activates_at,
+                    "Pending: published in the JWKS, not used to sign yet"
+                );
+            }
+            SigningKeyState::Active => {
+                // The key is superseded by the next pending key, or at the end of its interval
+                let superseded_at = keys
+                    .iter()
+                    .map(|other| other.activates_at)
+                    .find(|activates_at| *activates_at > key.activates_at)
+                    .or_else(|| interval.map(|interval| key.activates_at + interval));
+                info!(
+                    kid,
+                    %algorithm,
+                    activates_at = %key.activates_at,
+                    superseded_at = superseded_at.map(tracing::field::display),
+                    "Active: used to sign"
+                );
+            }
+            SigningKeyState::Superseded { superseded_at } => {
+                let retires_at = retention.map(|retention| superseded_at + retention);
+                info!(
+                    kid,
+                    %algorithm,
+                    %superseded_at,
+                    retires_at = retires_at.map(tracing::field::display),
+                    "Superseded: published in the JWKS until the tokens it signed expire"
+                );
+            }
+            SigningKeyState::Retired { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
 async fn check_and_normalize_username<'a>(
     localpart_or_mxid: &'a str,
     repo: &mut dyn RepositoryAccess<Error = DatabaseError>,
-    homeserver: &SynapseConnection,
+    homeserver: &MultiHomeserverConnection<SynapseConnection>,
 ) -> anyhow::Result<&'a str> {
     // XXX: this is a very basic MXID to localpart conversion
     // Strip any leading '@'
@@ -740,7 +1064,7 @@ async fn check_and_normalize_username<'a>(
         return Err(anyhow::anyhow!("Username cannot be empty"));
     }
 
-    if repo.user().exists(localpart).await? {
+    if repo.user().exists(UserHomeserver::Main, localpart).await? {
         return Err(anyhow::anyhow!("User already exists"));
     }
 
@@ -807,7 +1131,11 @@ impl UserCreationRequest<'_> {
     }
 
     /// Show the user creation request in a human-readable format
-    fn show(&self, term: &Term, homeserver: &SynapseConnection) -> std::io::Result<()> {
+    fn show(
+        &self,
+        term: &Term,
+        homeserver: &MultiHomeserverConnection<SynapseConnection>,
+    ) -> std::io::Result<()> {
         let value_style = Style::new().green();
         let key_style = Style::new().bold();
         let warning_style = Style::new().italic().red().bright();
@@ -931,6 +1259,10 @@ impl UserCreationRequest<'_> {
 
         repo.job().schedule_job(provision_job).await?;
 
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_registered(rng, clock, &user))
+            .await?;
+
         Ok(user)
     }
 }
@@ -12,11 +12,13 @@ use figment::{
     providers::{Env, Format, Yaml},
     Figment,
 };
+use mas_config::{EnvOverrides, LegacyOptions, ENV_OVERRIDE_PREFIX, ENV_OVERRIDE_SEPARATOR};
 
 mod config;
 mod database;
 mod debug;
 mod doctor;
+mod import;
 mod manage;
 mod server;
 mod templates;
@@ -48,6 +50,9 @@ enum Subcommand {
 
     /// Run diagnostics on the deployment
     Doctor(self::doctor::Options),
+
+    /// Import users and clients from other identity providers
+    Import(self::import::Options),
 }
 
 #[derive(Parser, Debug)]
@@ -63,24 +68,34 @@ pub struct Options {
 impl Options {
     pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
         use Subcommand as S;
+        let config_loader = ConfigLoader {
+            paths: self.config_paths(),
+        };
+
         // We Box the futures for each subcommand so that we avoid this function being
         // big on the stack all the time
         match self.subcommand {
-            Some(S::Config(c)) => Box::pin(c.run(figment)).await,
+            Some(S::Config(c)) => Box::pin(c.run(figment, &config_loader.paths)).await,
             Some(S::Database(c)) => Box::pin(c.run(figment)).await,
-            Some(S::Server(c)) => Box::pin(c.run(figment)).await,
+            Some(S::Server(c)) => Box::pin(c.run(figment, config_loader)).await,
             Some(S::Worker(c)) => Box::pin(c.run(figment)).await,
             Some(S::Manage(c)) => Box::pin(c.run(figment)).await,
             Some(S::Templates(c)) => Box::pin(c.run(figment)).await,
             Some(S::Debug(c)) => Box::pin(c.run(figment)).await,
             Some(S::Doctor(c)) => Box::pin(c.run(figment)).await,
-            None => Box::pin(self::server::Options::default().run(figment)).await,
+            Some(S::Import(c)) => Box::pin(c.run(figment)).await,
+            None => Box::pin(self::server::Options::default().run(figment, config_loader)).await,
         }
     }
 
     /// Get a [`Figment`] instance with the configuration loaded
     pub fn figment(&self) -> Figment {
-        let configs = if self.config.is_empty() {
+        figment_from_paths(self.config_paths())
+    }
+
+    /// Get the paths of the configuration files to load
+    fn config_paths(&self) -> Vec<Utf8PathBuf> {
+        if self.config.is_empty() {
             // Read the MAS_CONFIG environment variable
             std::env::var("MAS_CONFIG")
                 // Default to "config.yaml"
@@ -91,11 +106,42 @@ impl Options {
                 .collect()
         } else {
             self.config.clone()
-        };
-        let base = Figment::new().merge(Env::prefixed("MAS_").split("_"));
+        }
+    }
+}
+
+fn figment_from_paths(paths: Vec<Utf8PathBuf>) -> Figment {
+    // Variables with nested keys are handled by the overrides below
+    let base = Figment::new().merge(
+        Env::prefixed(ENV_OVERRIDE_PREFIX)
+            .filter(|key| !key.as_str().contains(ENV_OVERRIDE_SEPARATOR))
+            .split("_"),
+    );
+
+    let figment = paths
+        .into_iter()
+        .fold(base, |f, path| f.merge(Yaml::file(path)));
+
+    // Environment variables take precedence over the configuration files
+    let overrides = EnvOverrides::from_env(&figment);
+    let figment = figment.merge(overrides);
+
+    // Options set with their current name take precedence over the ones set with
+    // their previous name
+    let legacy = LegacyOptions::from_figment(&figment);
+    figment.join(legacy)
+}
+
+/// Loads the configuration again from the same sources as on startup
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    paths: Vec<Utf8PathBuf>,
+}
 
-        configs
-            .into_iter()
-            .fold(base, |f, path| f.merge(Yaml::file(path)))
+impl ConfigLoader {
+    /// Load the configuration, replacing the references to secrets in Vault
+    pub async fn load(&self) -> anyhow::Result<Figment> {
+        let figment = figment_from_paths(self.paths.clone());
+        crate::vault::resolve_references(figment).await
     }
 }
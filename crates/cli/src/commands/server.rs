@@ -13,27 +13,37 @@ use itertools::Itertools;
 use mas_config::{
     AppConfig, ClientsConfig, ConfigurationSection, ConfigurationSectionExt, UpstreamOAuth2Config,
 };
-use mas_handlers::{ActivityTracker, CookieManager, HttpClientFactory, Limiter, MetadataCache};
+use mas_handlers::{ActivityTracker, ClientLogoCache, CookieManager, Limiter, MetadataCache};
 use mas_listener::server::Server;
-use mas_matrix_synapse::SynapseConnection;
 use mas_router::UrlBuilder;
 use mas_storage::SystemClock;
 use mas_storage_pg::MIGRATOR;
 use rand::{
     distributions::{Alphanumeric, DistString},
-    thread_rng,
+    thread_rng, SeedableRng,
 };
+use rand_chacha::ChaChaRng;
 use sqlx::migrate::Migrate;
 use tracing::{info, info_span, warn, Instrument};
 
 use crate::{
     app_state::AppState,
+    commands::ConfigLoader,
+    keys::RotatingKeystore,
+    policy_bundle::PolicyBundleFetcher,
+    reload::{watch_templates_changes, ConfigReloader},
     shutdown::ShutdownManager,
     util::{
-        database_pool_from_config, mailer_from_config, password_manager_from_config,
-        policy_factory_from_config, register_sighup, site_config_from_config,
-        templates_from_config,
+        database_pool_from_config, database_replica_pool_from_config, event_stream_from_config,
+        expiry_warnings_from_config, fault_injector_from_config, geoip_from_config,
+        homeserver_connection_from_config, honeytoken_alert_emails_from_config,
+        http_client_factory_from_config, inactive_accounts_from_config, key_rotation_from_config,
+        mailer_from_config, password_manager_from_config, policy_factory_from_config,
+        register_sighup, repository_cache_from_config, site_config_from_config,
+        suspicious_login_detection_from_config, synapse_password_fallback_from_config,
+        templates_from_config, webhook_endpoints_from_config,
     },
+    vault::register_database_credentials_refresh,
 };
 
 #[allow(clippy::struct_excessive_bools)]
@@ -59,7 +69,11 @@ pub(super) struct Options {
 
 impl Options {
     #[allow(clippy::too_many_lines)]
-    pub async fn run(self, figment: &Figment) -> anyhow::Result<ExitCode> {
+    pub async fn run(
+        self,
+        figment: &Figment,
+        config_loader: ConfigLoader,
+    ) -> anyhow::Result<ExitCode> {
         let span = info_span!("cli.run.init").entered();
         let shutdown = ShutdownManager::new()?;
         let config = AppConfig::extract(figment)?;
@@ -71,6 +85,11 @@ impl Options {
         // Connect to the database
         info!("Connecting to the database");
         let pool = database_pool_from_config(&config.database).await?;
+        register_database_credentials_refresh(figment, &config.database, &pool)?;
+        let replica_pool = database_replica_pool_from_config(&config.database).await?;
+        if replica_pool.is_some() {
+            info!("Connected to the database read replica");
+        }
 
         if self.no_migrate {
             // Check that we applied all the migrations
@@ -120,12 +139,22 @@ impl Options {
             .await
             .context("could not import keys from config")?;
 
+        // Add the keys generated by the automated key rotation, if enabled
+        let key_rotation = key_rotation_from_config(&config.secrets)?;
+        let key_store = RotatingKeystore::new(key_store, encrypter.clone());
+        if key_rotation.is_some() {
+            key_store
+                .reload(&pool)
+                .await
+                .context("could not load the rotated signing keys")?;
+        }
+
         let cookie_manager =
             CookieManager::derive_from(config.http.public_base.clone(), &config.secrets.encryption);
 
         // Load and compile the WASM policies (and fallback to the default embedded one)
         info!("Loading and compiling the policy module");
-        let policy_factory = policy_factory_from_config(&config.policy).await?;
+        let policy_factory = policy_factory_from_config(&config.policy, &config.scopes).await?;
         let policy_factory = Arc::new(policy_factory);
 
         let url_builder = UrlBuilder::new(
@@ -142,36 +171,90 @@ impl Options {
             &config.passwords,
             &config.account,
             &config.captcha,
+            &config.jwt_bearer,
+            &config.compat,
+            &config.introspection,
+            &config.scopes,
+            &config.policy,
         )?;
 
         // Load and compile the templates
         let templates =
             templates_from_config(&config.templates, &site_config, &url_builder).await?;
 
-        let http_client_factory = HttpClientFactory::new();
+        let http_client_factory = http_client_factory_from_config(&config.http);
 
-        let homeserver_connection = SynapseConnection::new(
-            config.matrix.homeserver.clone(),
-            config.matrix.endpoint.clone(),
-            config.matrix.secret.clone(),
-            http_client_factory.clone(),
+        let homeserver_connection = homeserver_connection_from_config(
+            &config.matrix,
+            &http_client_factory,
+            &fault_injector_from_config(&config.chaos, "homeserver", &config.chaos.homeserver),
+        );
+
+        let database_fault_injector =
+            fault_injector_from_config(&config.chaos, "database", &config.chaos.database);
+
+        let repository_cache = repository_cache_from_config(&config.cache);
+        if repository_cache.is_some() {
+            info!("In-process cache of database lookups enabled");
+        }
+
+        // The mailer is also used by the status endpoint of the admin API, so it is
+        // built even if the worker is disabled
+        let mailer = mailer_from_config(&config.email, &templates)?.with_fault_injector(
+            fault_injector_from_config(&config.chaos, "email", &config.chaos.email),
         );
 
         if !self.no_worker {
-            let mailer = mailer_from_config(&config.email, &templates)?;
             mailer.test_connection().await?;
 
             #[allow(clippy::disallowed_methods)]
             let mut rng = thread_rng();
             let worker_name = Alphanumeric.sample_string(&mut rng, 10);
+            let worker_rng = ChaChaRng::from_rng(&mut rng)?;
 
             info!(worker_name, "Starting task worker");
             let monitor = mas_tasks::init(
                 &worker_name,
                 &pool,
+                Box::new(SystemClock::default()),
+                Box::new(worker_rng),
                 &mailer,
                 homeserver_connection.clone(),
                 url_builder.clone(),
+                database_fault_injector.clone(),
+                config.audit.retention,
+                honeytoken_alert_emails_from_config(&config.audit)?,
+                site_config.session_ttl,
+                site_config.session_inactivity_ttl,
+                site_config.compat_session_ttl,
+                site_config.compat_session_inactivity_ttl,
+                site_config.browser_session_ttl,
+                site_config.browser_session_inactivity_ttl,
+                site_config.kiosk_session_ttl,
+                site_config.kiosk_session_inactivity_ttl,
+                site_config.activity_digest_interval,
+                http_client_factory.clone(),
+                webhook_endpoints_from_config(&config.webhooks),
+                event_stream_from_config(&config.event_stream).await?,
+                key_rotation.clone(),
+                config
+                    .webhooks
+                    .changes_feed
+                    .enabled
+                    .then_some(config.webhooks.changes_feed.retention),
+                expiry_warnings_from_config(
+                    &config.expiry_warnings,
+                    &config.http,
+                    &UpstreamOAuth2Config::extract_or_default(figment)?,
+                )?,
+                suspicious_login_detection_from_config(&config.suspicious_logins),
+                geoip_from_config(&config.suspicious_logins)?,
+                config
+                    .inactive_accounts
+                    .enabled
+                    .then(|| inactive_accounts_from_config(&config.inactive_accounts))
+                    .transpose()?,
+                site_config.guest_inactivity_ttl,
             )
             .await?;
 
@@ -192,12 +275,26 @@ impl Options {
         }
 
         let listeners_config = config.http.listeners.clone();
-
-        let password_manager = password_manager_from_config(&config.passwords).await?;
+        let theme_assets = config
+            .templates
+            .theme_path
+            .as_ref()
+            .map(|theme_path| theme_path.join("assets"));
+        let watch_templates = config.templates.watch;
+
+        let mut password_manager = password_manager_from_config(&config.passwords).await?;
+        if let Some(fallback) =
+            synapse_password_fallback_from_config(&config.passwords, &config.matrix).await?
+        {
+            password_manager = password_manager.with_synapse_fallback(fallback);
+        }
 
         // The upstream OIDC metadata cache
         let metadata_cache = MetadataCache::new();
 
+        // The cache of the client logos shown on the consent screens
+        let client_logo_cache = ClientLogoCache::new();
+
         // Initialize the activity tracker
         // Activity is flushed every minute
         let activity_tracker = ActivityTracker::new(
@@ -207,6 +304,14 @@ impl Options {
             shutdown.soft_shutdown_token(),
         );
         let trusted_proxies = config.http.trusted_proxies.clone();
+        let client_certificate_header = config
+            .http
+            .client_certificate_header
+            .as_deref()
+            .map(axum::http::HeaderName::try_from)
+            .transpose()
+            .context("invalid client certificate header name")?;
+        let query_budget = config.database.query_budget.clone();
 
         // Build a rate limiter.
         // This should not raise an error here as the config should already have been
@@ -214,16 +319,59 @@ impl Options {
         let limiter = Limiter::new(&config.rate_limiting)
             .context("rate-limiting configuration is not valid")?;
 
+        let policy_bundle_fetcher = PolicyBundleFetcher::from_config(
+            &config.policy,
+            &config.scopes,
+            policy_factory.clone(),
+            http_client_factory.clone(),
+        );
+
         // Explicitly the config to properly zeroize secret keys
         drop(config);
 
-        // Listen for SIGHUP
-        register_sighup(&templates, &activity_tracker)?;
+        // Listen for SIGHUP, to reload the parts of the configuration which can be
+        // changed at runtime
+        let reloader = ConfigReloader::new(
+            config_loader,
+            pool.clone(),
+            templates.clone(),
+            policy_factory.clone(),
+            limiter.clone(),
+            (!self.no_worker).then(|| mailer.clone()),
+            repository_cache.clone(),
+            !self.no_sync,
+        );
+        register_sighup(reloader, &activity_tracker)?;
+
+        // Reload the templates as soon as they change on disk, if asked to
+        if watch_templates {
+            watch_templates_changes(
+                templates.clone(),
+                shutdown.task_tracker(),
+                shutdown.soft_shutdown_token(),
+            );
+        }
 
         limiter.start();
 
+        // Keep the policy up to date with the bundle server
+        if let Some(policy_bundle_fetcher) = policy_bundle_fetcher {
+            policy_bundle_fetcher.start(shutdown.task_tracker(), shutdown.soft_shutdown_token());
+        }
+
+        // Pick up the rotated signing keys
+        if key_rotation.is_some() {
+            key_store.start(
+                pool.clone(),
+                Duration::from_secs(60),
+                shutdown.task_tracker(),
+                shutdown.soft_shutdown_token(),
+            );
+        }
+
         let graphql_schema = mas_handlers::graphql_schema(
             &pool,
+            replica_pool.as_ref(),
             &policy_factory,
             homeserver_connection.clone(),
             site_config.clone(),
@@ -233,6 +381,7 @@ impl Options {
         let state = {
             let mut s = AppState {
                 pool,
+                replica_pool,
                 templates,
                 key_store,
                 cookie_manager,
@@ -244,11 +393,18 @@ impl Options {
                 http_client_factory,
                 password_manager,
                 metadata_cache,
+                client_logo_cache,
+                mailer,
                 site_config,
                 activity_tracker,
                 trusted_proxies,
+                client_certificate_header,
                 limiter,
+                database_fault_injector,
+                repository_cache,
                 conn_acquisition_histogram: None,
+                queries_per_request_histogram: None,
+                query_budget,
             };
             s.init_metrics()?;
             // XXX: this might panic
@@ -278,6 +434,7 @@ impl Options {
                     &config.resources,
                     config.prefix.as_deref(),
                     config.name.as_deref(),
+                    theme_assets.as_deref(),
                 );
 
 
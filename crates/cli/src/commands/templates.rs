@@ -4,17 +4,29 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::process::ExitCode;
+use std::{fmt::Write, net::SocketAddr, process::ExitCode, sync::Arc, time::SystemTime};
 
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use camino::{Utf8Path, Utf8PathBuf};
 use clap::Parser;
 use figment::Figment;
 use mas_config::{
-    AccountConfig, BrandingConfig, CaptchaConfig, ConfigurationSection, ConfigurationSectionExt,
-    ExperimentalConfig, MatrixConfig, PasswordsConfig, TemplatesConfig,
+    AccountConfig, BrandingConfig, CaptchaConfig, CompatConfig, ConfigurationSection,
+    ConfigurationSectionExt, ExperimentalConfig, IntrospectionConfig, JwtBearerConfig,
+    MatrixConfig, PasswordsConfig, PolicyConfig, ScopesConfig, TemplatesConfig,
 };
 use mas_storage::{Clock, SystemClock};
+use mas_templates::{RenderedSamples, Templates};
 use rand::SeedableRng;
-use tracing::info_span;
+use serde::Deserialize;
+use tracing::{info, info_span};
 
 use crate::util::{site_config_from_config, templates_from_config};
 
@@ -28,6 +40,17 @@ pub(super) struct Options {
 enum Subcommand {
     /// Check that the templates specified in the config are valid
     Check,
+
+    /// Serve a preview of every template rendered with sample data
+    ///
+    /// Templates are reloaded from disk on every request, and the previews
+    /// refresh themselves when a template file changes. This is meant for
+    /// customizing the templates, and should not be exposed publicly.
+    Serve {
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8081")]
+        address: SocketAddr,
+    },
 }
 
 impl Options {
@@ -37,33 +60,228 @@ impl Options {
             SC::Check => {
                 let _span = info_span!("cli.templates.check").entered();
 
-                let template_config = TemplatesConfig::extract_or_default(figment)?;
-                let branding_config = BrandingConfig::extract_or_default(figment)?;
-                let matrix_config = MatrixConfig::extract(figment)?;
-                let experimental_config = ExperimentalConfig::extract_or_default(figment)?;
-                let password_config = PasswordsConfig::extract_or_default(figment)?;
-                let account_config = AccountConfig::extract_or_default(figment)?;
-                let captcha_config = CaptchaConfig::extract_or_default(figment)?;
+                let (templates, _path) = load_templates(figment).await?;
 
                 let clock = SystemClock::default();
                 // XXX: we should disallow SeedableRng::from_entropy
                 let mut rng = rand_chacha::ChaChaRng::from_entropy();
-                let url_builder =
-                    mas_router::UrlBuilder::new("https://example.com/".parse()?, None, None);
-                let site_config = site_config_from_config(
-                    &branding_config,
-                    &matrix_config,
-                    &experimental_config,
-                    &password_config,
-                    &account_config,
-                    &captcha_config,
-                )?;
-                let templates =
-                    templates_from_config(&template_config, &site_config, &url_builder).await?;
                 templates.check_render(clock.now(), &mut rng)?;
 
                 Ok(ExitCode::SUCCESS)
             }
+
+            SC::Serve { address } => {
+                let _span = info_span!("cli.templates.serve").entered();
+
+                let (templates, path) = load_templates(figment).await?;
+                let state = Arc::new(PreviewState { templates, path });
+
+                let router = Router::new()
+                    .route("/", get(preview_index))
+                    .route("/_version", get(preview_version))
+                    .route("/*template", get(preview_template))
+                    .with_state(state);
+
+                let listener = tokio::net::TcpListener::bind(address)
+                    .await
+                    .context("could not bind address")?;
+                info!("Serving template previews on http://{address}/");
+                axum::serve(listener, router).await?;
+
+                Ok(ExitCode::SUCCESS)
+            }
         }
     }
 }
+
+/// Load the templates from the configuration, along with the path to the
+/// templates directory
+async fn load_templates(figment: &Figment) -> anyhow::Result<(Templates, Utf8PathBuf)> {
+    let template_config = TemplatesConfig::extract_or_default(figment)?;
+    let branding_config = BrandingConfig::extract_or_default(figment)?;
+    let matrix_config = MatrixConfig::extract(figment)?;
+    let experimental_config = ExperimentalConfig::extract_or_default(figment)?;
+    let password_config = PasswordsConfig::extract_or_default(figment)?;
+    let account_config = AccountConfig::extract_or_default(figment)?;
+    let captcha_config = CaptchaConfig::extract_or_default(figment)?;
+    let jwt_bearer_config = JwtBearerConfig::extract_or_default(figment)?;
+    let compat_config = CompatConfig::extract_or_default(figment)?;
+    let introspection_config = IntrospectionConfig::extract_or_default(figment)?;
+    let scopes_config = ScopesConfig::extract_or_default(figment)?;
+    let policy_config = PolicyConfig::extract_or_default(figment)?;
+
+    let url_builder = mas_router::UrlBuilder::new("https://example.com/".parse()?, None, None);
+    let site_config = site_config_from_config(
+        &branding_config,
+        &matrix_config,
+        &experimental_config,
+        &password_config,
+        &account_config,
+        &captcha_config,
+        &jwt_bearer_config,
+        &compat_config,
+        &introspection_config,
+        &scopes_config,
+        &policy_config,
+    )?;
+    let templates = templates_from_config(&template_config, &site_config, &url_builder).await?;
+
+    Ok((templates, template_config.path))
+}
+
+struct PreviewState {
+    templates: Templates,
+    path: Utf8PathBuf,
+}
+
+impl PreviewState {
+    /// Reload the templates from disk and render all of them with the sample
+    /// data.
+    ///
+    /// The samples are generated with a fixed seed, so that they stay the same
+    /// across reloads.
+    async fn render(&self) -> Result<Vec<RenderedSamples>, Response> {
+        self.templates
+            .reload()
+            .await
+            .map_err(|e| preview_error(&anyhow::Error::from(e)))?;
+
+        let clock = SystemClock::default();
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        self.templates
+            .render_samples(clock.now(), &mut rng)
+            .map_err(|e| preview_error(&e))
+    }
+
+    /// Get a value which changes whenever a file in the templates directory
+    /// changes
+    async fn version(&self) -> String {
+        let path = self.path.clone();
+        let latest = tokio::task::spawn_blocking(move || latest_modification(&path))
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        latest
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .to_string()
+    }
+
+    /// The script injected in the previews, which reloads the page when the
+    /// templates change
+    async fn live_reload_script(&self) -> String {
+        let version = self.version().await;
+        format!(
+            r#"<script>
+(() => {{
+  let version = "{version}";
+  setInterval(async () => {{
+    try {{
+      const response = await fetch("/_version");
+      const next = await response.text();
+      if (next !== version) {{
+        window.location.reload();
+      }}
+    }} catch (e) {{}}
+  }}, 1000);
+}})();
+</script>"#
+        )
+    }
+}
+
+/// Find the latest modification time of the files in the given directory
+fn latest_modification(path: &Utf8Path) -> std::io::Result<SystemTime> {
+    let mut latest = path.metadata()?.modified()?;
+    for entry in path.read_dir_utf8()? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let modified = if metadata.is_dir() {
+            latest_modification(entry.path())?
+        } else {
+            metadata.modified()?
+        };
+        latest = latest.max(modified);
+    }
+
+    Ok(latest)
+}
+
+fn preview_error(error: &anyhow::Error) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+        format!("{error:?}"),
+    )
+        .into_response()
+}
+
+async fn preview_version(State(state): State<Arc<PreviewState>>) -> String {
+    state.version().await
+}
+
+async fn preview_index(State(state): State<Arc<PreviewState>>) -> Response {
+    let rendered = match state.render().await {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+
+    let mut body = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Templates</title></head><body>\n<h1>Templates</h1>\n<ul>\n",
+    );
+    for RenderedSamples { template, samples } in &rendered {
+        let _ = write!(body, "<li><code>{template}</code>:");
+        for index in 0..samples.len() {
+            let _ = write!(
+                body,
+                r#" <a href="/{template}?sample={index}">#{index}</a>"#
+            );
+        }
+        body.push_str("</li>\n");
+    }
+    body.push_str("</ul>\n");
+    body.push_str(&state.live_reload_script().await);
+    body.push_str("\n</body></html>\n");
+
+    Html(body).into_response()
+}
+
+#[derive(Deserialize)]
+struct PreviewParams {
+    #[serde(default)]
+    sample: usize,
+}
+
+async fn preview_template(
+    State(state): State<Arc<PreviewState>>,
+    Path(template): Path<String>,
+    Query(params): Query<PreviewParams>,
+) -> Response {
+    let rendered = match state.render().await {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+
+    let Some(output) = rendered
+        .into_iter()
+        .find(|rendered| rendered.template == template)
+        .and_then(|rendered| rendered.samples.into_iter().nth(params.sample))
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if Utf8Path::new(&template).extension() == Some("html") {
+        // Inject the live reload script at the end of the page
+        let script = state.live_reload_script().await;
+        let output = match output.rfind("</body>") {
+            Some(index) => format!("{}{script}{}", &output[..index], &output[index..]),
+            None => format!("{output}{script}"),
+        };
+        Html(output).into_response()
+    } else {
+        ([(CONTENT_TYPE, "text/plain; charset=utf-8")], output).into_response()
+    }
+}
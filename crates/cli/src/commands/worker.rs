@@ -8,18 +8,26 @@ use std::process::ExitCode;
 
 use clap::Parser;
 use figment::Figment;
-use mas_config::{AppConfig, ConfigurationSection};
-use mas_handlers::HttpClientFactory;
-use mas_matrix_synapse::SynapseConnection;
+use mas_config::{AppConfig, ConfigurationSection, ConfigurationSectionExt, UpstreamOAuth2Config};
 use mas_router::UrlBuilder;
+use mas_storage::SystemClock;
 use rand::{
     distributions::{Alphanumeric, DistString},
-    thread_rng,
+    thread_rng, SeedableRng,
 };
+use rand_chacha::ChaChaRng;
 use tracing::{info, info_span};
 
-use crate::util::{
-    database_pool_from_config, mailer_from_config, site_config_from_config, templates_from_config,
+use crate::{
+    util::{
+        database_pool_from_config, event_stream_from_config, expiry_warnings_from_config,
+        fault_injector_from_config, geoip_from_config, homeserver_connection_from_config,
+        honeytoken_alert_emails_from_config, http_client_factory_from_config,
+        inactive_accounts_from_config, key_rotation_from_config, mailer_from_config,
+        site_config_from_config, suspicious_login_detection_from_config, templates_from_config,
+        webhook_endpoints_from_config,
+    },
+    vault::register_database_credentials_refresh,
 };
 
 #[derive(Parser, Debug, Default)]
@@ -33,6 +41,7 @@ impl Options {
         // Connect to the database
         info!("Connecting to the database");
         let pool = database_pool_from_config(&config.database).await?;
+        register_database_credentials_refresh(figment, &config.database, &pool)?;
 
         let url_builder = UrlBuilder::new(
             config.http.public_base.clone(),
@@ -48,31 +57,106 @@ impl Options {
             &config.passwords,
             &config.account,
             &config.captcha,
+            &config.jwt_bearer,
+            &config.compat,
+            &config.introspection,
+            &config.scopes,
+            &config.policy,
         )?;
 
         // Load and compile the templates
         let templates =
             templates_from_config(&config.templates, &site_config, &url_builder).await?;
 
-        let mailer = mailer_from_config(&config.email, &templates)?;
+        let mailer = mailer_from_config(&config.email, &templates)?.with_fault_injector(
+            fault_injector_from_config(&config.chaos, "email", &config.chaos.email),
+        );
         mailer.test_connection().await?;
 
-        let http_client_factory = HttpClientFactory::new();
-        let conn = SynapseConnection::new(
-            config.matrix.homeserver.clone(),
-            config.matrix.endpoint.clone(),
-            config.matrix.secret.clone(),
-            http_client_factory,
+        let http_client_factory = http_client_factory_from_config(&config.http);
+        let conn = homeserver_connection_from_config(
+            &config.matrix,
+            &http_client_factory,
+            &fault_injector_from_config(&config.chaos, "homeserver", &config.chaos.homeserver),
         );
 
+        let database_fault_injector =
+            fault_injector_from_config(&config.chaos, "database", &config.chaos.database);
+
+        let audit_retention = config.audit.retention;
+        let honeytoken_alert_emails = honeytoken_alert_emails_from_config(&config.audit)?;
+        let session_ttl = config.experimental.session_ttl;
+        let session_inactivity_ttl = config.experimental.session_inactivity_ttl;
+        let compat_session_ttl = config.compat.session_ttl;
+        let compat_session_inactivity_ttl = config.compat.session_inactivity_ttl;
+        let browser_session_ttl = config.experimental.browser_session_ttl;
+        let browser_session_inactivity_ttl = config.experimental.browser_session_inactivity_ttl;
+        let kiosk_session_ttl = config.experimental.kiosk_session_ttl;
+        let kiosk_session_inactivity_ttl = config.experimental.kiosk_session_inactivity_ttl;
+        let activity_digest_interval = config.account.activity_digest_interval;
+        let webhook_endpoints = webhook_endpoints_from_config(&config.webhooks);
+        let event_stream = event_stream_from_config(&config.event_stream).await?;
+        let key_rotation = key_rotation_from_config(&config.secrets)?;
+        let changes_feed_retention = config
+            .webhooks
+            .changes_feed
+            .enabled
+            .then_some(config.webhooks.changes_feed.retention);
+        let expiry_warnings = expiry_warnings_from_config(
+            &config.expiry_warnings,
+            &config.http,
+            &UpstreamOAuth2Config::extract_or_default(figment)?,
+        )?;
+        let suspicious_login_detection =
+            suspicious_login_detection_from_config(&config.suspicious_logins);
+        let geoip = geoip_from_config(&config.suspicious_logins)?;
+        let inactive_accounts = config
+            .inactive_accounts
+            .enabled
+            .then(|| inactive_accounts_from_config(&config.inactive_accounts))
+            .transpose()?;
+        let guest_inactivity_ttl = config.account.guest_inactivity_ttl;
+
         drop(config);
 
         #[allow(clippy::disallowed_methods)]
         let mut rng = thread_rng();
         let worker_name = Alphanumeric.sample_string(&mut rng, 10);
+        let worker_rng = ChaChaRng::from_rng(&mut rng)?;
 
         info!(worker_name, "Starting task scheduler");
-        let monitor = mas_tasks::init(&worker_name, &pool, &mailer, conn, url_builder).await?;
+        let monitor = mas_tasks::init(
+            &worker_name,
+            &pool,
+            Box::new(SystemClock::default()),
+            Box::new(worker_rng),
+            &mailer,
+            conn,
+            url_builder,
+            database_fault_injector,
+            audit_retention,
+            honeytoken_alert_emails,
+            session_ttl,
+            session_inactivity_ttl,
+            compat_session_ttl,
+            compat_session_inactivity_ttl,
+            browser_session_ttl,
+            browser_session_inactivity_ttl,
+            kiosk_session_ttl,
+            kiosk_session_inactivity_ttl,
+            activity_digest_interval,
+            http_client_factory,
+            webhook_endpoints,
+            event_stream,
+            key_rotation,
+            changes_feed_retention,
+            expiry_warnings,
+            suspicious_login_detection,
+            geoip,
+            inactive_accounts,
+            guest_inactivity_ttl,
+        )
+        .await?;
 
         span.exit();
 
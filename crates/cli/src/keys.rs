@@ -0,0 +1,145 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Loading of the signing keys generated by the automated key rotation
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use mas_data_model::SigningKey;
+use mas_iana::jose::JsonWebKeyUse;
+use mas_keystore::{Encrypter, JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
+use mas_storage::{Clock, RepositoryAccess, SystemClock};
+use mas_storage_pg::PgRepository;
+use sqlx::PgPool;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{error, info};
+use zeroize::Zeroizing;
+
+/// Decrypt and load a key generated by the automated key rotation
+///
+/// # Errors
+///
+/// Returns an error if the key could not be decrypted or parsed
+pub fn load_signing_key(
+    encrypter: &Encrypter,
+    key: &SigningKey,
+) -> anyhow::Result<JsonWebKey<PrivateKey>> {
+    let pem = Zeroizing::new(
+        encrypter
+            .decrypt_string(&key.encrypted_key)
+            .context("could not decrypt the key")?,
+    );
+    let pem = std::str::from_utf8(&pem).context("key is not valid UTF-8")?;
+    let private_key = PrivateKey::load_pem(pem).context("could not parse the key")?;
+
+    Ok(JsonWebKey::new(private_key)
+        .with_kid(key.kid.clone())
+        .with_use(JsonWebKeyUse::Sig)
+        .with_alg(key.algorithm.clone()))
+}
+
+/// The [`Keystore`] used by the server, which combines the keys from the
+/// configuration with the keys generated by the automated key rotation
+///
+/// The rotated keys are regularly reloaded from the database.
+#[derive(Clone)]
+pub struct RotatingKeystore {
+    base: Keystore,
+    encrypter: Encrypter,
+    current: Arc<ArcSwap<Keystore>>,
+}
+
+impl RotatingKeystore {
+    /// Create a new [`RotatingKeystore`], with only the keys from the
+    /// configuration
+    #[must_use]
+    pub fn new(base: Keystore, encrypter: Encrypter) -> Self {
+        let current = Arc::new(ArcSwap::from_pointee(base.clone()));
+        Self {
+            base,
+            encrypter,
+            current,
+        }
+    }
+
+    /// Get the current [`Keystore`]
+    #[must_use]
+    pub fn keystore(&self) -> Keystore {
+        Keystore::clone(&self.current.load())
+    }
+
+    /// Reload the rotated keys from the database
+    ///
+    /// The active key is added to the keys used to sign, after the keys from
+    /// the configuration so that it is preferred for its algorithm. The pending
+    /// and superseded keys are only published in the JWKS.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys could not be fetched from the database, or
+    /// if one of them could not be loaded
+    pub async fn reload(&self, pool: &PgPool) -> anyhow::Result<()> {
+        let mut repo = PgRepository::from_pool(pool).await?;
+        let keys = repo.signing_key().list_unretired().await?;
+        repo.cancel().await?;
+
+        let now = SystemClock::default().now();
+        let mut signing_keys: Vec<_> = self.base.iter().cloned().collect();
+        let mut published_keys = Vec::new();
+        for key in &keys {
+            let state = key.state(&keys, now);
+            if !state.is_published() {
+                continue;
+            }
+
+            let jwk = load_signing_key(&self.encrypter, key)
+                .with_context(|| format!("could not load signing key {}", key.kid))?;
+
+            if state.is_active() {
+                signing_keys.push(jwk);
+            } else {
+                published_keys.push(jwk);
+            }
+        }
+
+        let keystore = Keystore::new(JsonWebKeySet::new(signing_keys))
+            .with_published_keys(JsonWebKeySet::new(published_keys));
+        self.current.store(Arc::new(keystore));
+
+        Ok(())
+    }
+
+    /// Start reloading the rotated keys in the background, at the given
+    /// interval
+    pub fn start(
+        &self,
+        pool: PgPool,
+        interval: Duration,
+        task_tracker: &TaskTracker,
+        cancellation_token: CancellationToken,
+    ) {
+        info!("Reloading rotated signing keys every {interval:?}");
+        let this = self.clone();
+        task_tracker.spawn(async move {
+            loop {
+                tokio::select! {
+                    biased;
+
+                    () = cancellation_token.cancelled() => {
+                        return;
+                    }
+
+                    () = tokio::time::sleep(interval) => {
+                        if let Err(err) = this.reload(&pool).await {
+                            error!(?err, "Error while reloading the signing keys");
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
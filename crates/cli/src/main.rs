@@ -10,7 +10,7 @@ use std::{io::IsTerminal, process::ExitCode, sync::Arc};
 
 use anyhow::Context;
 use clap::Parser;
-use mas_config::{ConfigurationSection, TelemetryConfig};
+use mas_config::{ConfigurationSection, RenamedOption, TelemetryConfig};
 use sentry_tracing::EventFilter;
 use tracing_subscriber::{
     filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry,
@@ -20,12 +20,16 @@ use crate::sentry_transport::HyperTransportFactory;
 
 mod app_state;
 mod commands;
+mod keys;
+mod policy_bundle;
+mod reload;
 mod sentry_transport;
 mod server;
 mod shutdown;
 mod sync;
 mod telemetry;
 mod util;
+mod vault;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<ExitCode> {
@@ -127,6 +131,24 @@ async fn try_main() -> anyhow::Result<ExitCode> {
         Err(e) => tracing::warn!(?e, "Failed to load .env file"),
     }
 
+    // Warn about the options which are still set with their previous name, which
+    // will stop working in the next major version
+    for option in RenamedOption::used_in(&figment) {
+        tracing::warn!(
+            option.old = option.old,
+            option.new = option.new,
+            option.since = option.since,
+            "Configuration option {} is deprecated, use {} instead. Run `mas-cli config upgrade` to update the configuration files",
+            option.old,
+            option.new,
+        );
+    }
+
+    // Replace the references to secrets in Vault in the configuration
+    let figment = self::vault::resolve_references(figment)
+        .await
+        .context("could not fetch the secrets from Vault")?;
+
     // And run the command
     tracing::trace!(?opts, "Running command");
     opts.run(&figment).await
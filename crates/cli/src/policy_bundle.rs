@@ -0,0 +1,192 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Keep the policy up to date with a signed OPA bundle
+
+use std::{
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use http::{
+    header::{AUTHORIZATION, ETAG, IF_NONE_MATCH},
+    HeaderValue, Request, StatusCode,
+};
+use mas_config::{PolicyConfig, ScopesConfig};
+use mas_handlers::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use mas_policy::{
+    bundle::{Bundle, BundleVerification, BundleVerificationKey},
+    Entrypoints, PolicyFactory,
+};
+use opentelemetry::{metrics::Counter, KeyValue};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::{Service, ServiceExt};
+use tracing::{error, info};
+use url::Url;
+
+use crate::util::{policy_data_from_config, policy_entrypoints_from_config};
+
+static BUNDLE_FETCHES: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("mas.policy.bundle.fetches")
+    .with_description("The number of times the policy bundle was fetched")
+    .with_unit("{fetches}")
+    .init()
+});
+
+/// Periodically fetches the policy bundle, and replaces the policy with it
+/// when it changes
+pub struct PolicyBundleFetcher {
+    url: Url,
+    token: Option<String>,
+    polling_interval: Duration,
+    verification: BundleVerification,
+    data: serde_json::Value,
+    entrypoints: Entrypoints,
+    policy_factory: Arc<PolicyFactory>,
+    http_client_factory: HttpClientFactory,
+
+    /// The `ETag` of the current bundle, to only download it again when it
+    /// changes
+    etag: Option<HeaderValue>,
+}
+
+impl PolicyBundleFetcher {
+    /// Create a fetcher from the policy configuration, if it has a bundle
+    pub fn from_config(
+        config: &PolicyConfig,
+        scopes_config: &ScopesConfig,
+        policy_factory: Arc<PolicyFactory>,
+        http_client_factory: HttpClientFactory,
+    ) -> Option<Self> {
+        let bundle = config.bundle.as_ref()?;
+
+        // The configuration is validated to have exactly one of them
+        let key = match (&bundle.jwks, &bundle.secret) {
+            (Some(jwks), _) => BundleVerificationKey::Jwks(jwks.clone()),
+            (None, Some(secret)) => {
+                BundleVerificationKey::SharedSecret(secret.clone().into_bytes())
+            }
+            (None, None) => return None,
+        };
+        let verification = BundleVerification::new(key);
+        let verification = match &bundle.scope {
+            Some(scope) => verification.with_scope(scope.clone()),
+            None => verification,
+        };
+
+        Some(Self {
+            url: bundle.url.clone(),
+            token: bundle.token.clone(),
+            polling_interval: bundle.polling_interval,
+            verification,
+            data: policy_data_from_config(config, scopes_config),
+            entrypoints: policy_entrypoints_from_config(config),
+            policy_factory,
+            http_client_factory,
+            etag: None,
+        })
+    }
+
+    /// Fetch the bundle, and replace the policy with it if it changed
+    ///
+    /// Returns whether the policy was replaced
+    async fn fetch(&mut self) -> Result<bool, anyhow::Error> {
+        let mut builder = Request::get(self.url.as_str());
+        if let Some(token) = &self.token {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        if let Some(etag) = &self.etag {
+            builder = builder.header(IF_NONE_MATCH, etag.clone());
+        }
+        let request = builder.body(Bytes::new())?;
+
+        let mut client = self
+            .http_client_factory
+            .client("policy_bundle")
+            .request_bytes_to_body()
+            .response_body_to_bytes();
+
+        let response = client
+            .ready()
+            .await?
+            .call(request)
+            .await
+            .context("failed to fetch the policy bundle")?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(false);
+        }
+
+        if !status.is_success() {
+            anyhow::bail!("the bundle server responded with status {status}");
+        }
+
+        let etag = response.headers().get(ETAG).cloned();
+
+        let bundle = Bundle::from_tar_gz(response.body(), &self.verification)
+            .context("invalid policy bundle")?;
+
+        // The data of the bundle replaces the top-level keys of the configured data
+        let mut data = self.data.clone();
+        if let (Some(data), Some(bundle_data)) = (data.as_object_mut(), bundle.data().as_object()) {
+            data.extend(bundle_data.clone());
+        }
+
+        self.policy_factory
+            .reload(bundle.wasm(), data, self.entrypoints.clone())
+            .await
+            .context("could not load the policy from the bundle")?;
+
+        self.etag = etag;
+        info!(
+            bundle.revision = bundle.revision(),
+            "Policy updated from the bundle"
+        );
+
+        Ok(true)
+    }
+
+    /// Fetch the bundle right away, and then at the configured interval
+    pub fn start(mut self, task_tracker: &TaskTracker, cancellation_token: CancellationToken) {
+        info!(
+            "Fetching the policy bundle from {} every {:?}",
+            self.url, self.polling_interval
+        );
+
+        task_tracker.spawn(async move {
+            loop {
+                let result = match self.fetch().await {
+                    Ok(true) => "success",
+                    Ok(false) => "not_modified",
+                    Err(err) => {
+                        error!(?err, "Error while fetching the policy bundle");
+                        "failure"
+                    }
+                };
+                BUNDLE_FETCHES.add(1, &[KeyValue::new("result", result)]);
+
+                tokio::select! {
+                    biased;
+
+                    () = cancellation_token.cancelled() => {
+                        return;
+                    }
+
+                    () = tokio::time::sleep(self.polling_interval) => {}
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Reload the parts of the configuration which can be changed without
+//! restarting the server
+
+use std::{
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use mas_config::{
+    AppConfig, ClientsConfig, ConfigurationSection, ConfigurationSectionExt, UpstreamOAuth2Config,
+};
+use mas_email::Mailer;
+use mas_handlers::Limiter;
+use mas_policy::PolicyFactory;
+use mas_storage::{cache::RepositoryCache, SystemClock};
+use mas_templates::{SiteConfigExt, Templates};
+use opentelemetry::{metrics::Counter, KeyValue};
+use sqlx::PgPool;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{error, info, warn};
+
+use crate::{
+    commands::ConfigLoader,
+    util::{mailer_from_config, prepare_policy_reload_from_config, site_config_from_config},
+};
+
+/// How often the templates are checked for changes when watching them
+const TEMPLATES_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+static POLICY_RELOADS: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("mas.policy.reloads")
+    .with_description("The number of times the policy was reloaded")
+    .with_unit("{reloads}")
+    .init()
+});
+
+/// Applies a new configuration to the running server
+///
+/// The following settings are reloaded:
+///  - the templates, translations and branding
+///  - the policy and its data
+///  - the upstream OAuth 2.0 providers and the clients, if the configuration is
+///    synced with the database
+///  - the rate limits
+///  - the email settings
+///
+/// Other changes to the configuration need a restart to take effect.
+#[derive(Clone)]
+pub struct ConfigReloader {
+    loader: ConfigLoader,
+    pool: PgPool,
+    templates: Templates,
+    policy_factory: Arc<PolicyFactory>,
+    limiter: Limiter,
+    mailer: Option<Mailer>,
+    repository_cache: Option<RepositoryCache>,
+    sync: bool,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        loader: ConfigLoader,
+        pool: PgPool,
+        templates: Templates,
+        policy_factory: Arc<PolicyFactory>,
+        limiter: Limiter,
+        mailer: Option<Mailer>,
+        repository_cache: Option<RepositoryCache>,
+        sync: bool,
+    ) -> Self {
+        Self {
+            loader,
+            pool,
+            templates,
+            policy_factory,
+            limiter,
+            mailer,
+            repository_cache,
+            sync,
+        }
+    }
+
+    /// Load the configuration again and apply it
+    ///
+    /// The new configuration is fully loaded and validated, and the clients
+    /// and upstream providers synced with the database, before the new
+    /// policy, templates, rate limits and email settings are swapped in
+    /// together. An invalid configuration leaves the server untouched.
+    #[tracing::instrument(name = "cli.reload", skip_all, err(Debug))]
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let figment = self.loader.load().await?;
+        let config = AppConfig::extract(&figment).context("invalid configuration")?;
+
+        let site_config = site_config_from_config(
+            &config.branding,
+            &config.matrix,
+            &config.experimental,
+            &config.passwords,
+            &config.account,
+            &config.captcha,
+            &config.jwt_bearer,
+            &config.compat,
+            &config.introspection,
+            &config.scopes,
+            &config.policy,
+        )?;
+
+        // Build the new mailer ahead of time, to make sure it works before swapping it
+        let mailer = if self.mailer.is_some() {
+            let mailer = mailer_from_config(&config.email, &self.templates)?;
+            mailer
+                .test_connection()
+                .await
+                .context("could not connect to the mail server")?;
+            Some(mailer)
+        } else {
+            None
+        };
+
+        // The new policy is compiled and checked before replacing the current one
+        let policy =
+            prepare_policy_reload_from_config(&self.policy_factory, &config.policy, &config.scopes)
+                .await;
+        if policy.is_err() {
+            POLICY_RELOADS.add(1, &[KeyValue::new("result", "failure")]);
+        }
+        let policy = policy.context("could not reload the policy")?;
+
+        let templates = self
+            .templates
+            .prepare_reload(
+                site_config.templates_branding(),
+                site_config.templates_features(),
+            )
+            .await
+            .context("could not reload the templates")?;
+
+        // The sync happens in a transaction, so nothing is changed in the
+        // database if it fails
+        if self.sync {
+            let clients_config = ClientsConfig::extract_or_default(&figment)?;
+            let upstream_oauth2_config = UpstreamOAuth2Config::extract_or_default(&figment)?;
+            let mut conn = self.pool.acquire().await?;
+
+            crate::sync::config_sync(
+                upstream_oauth2_config,
+                clients_config,
+                &mut conn,
+                &config.secrets.encrypter(),
+                &SystemClock::default(),
+                false,
+                false,
+            )
+            .await
+            .context("could not sync the configuration with the database")?;
+
+            // Make sure the clients are looked up again with their new settings
+            if let Some(repository_cache) = &self.repository_cache {
+                repository_cache.clear();
+            }
+
+            info!("Clients and upstream providers reloaded");
+        }
+
+        // Everything was loaded successfully, swap the new settings in
+        if let Some(policy) = policy {
+            self.policy_factory.apply_reload(policy);
+        }
+        self.policy_factory
+            .set_request_headers(config.policy.request_headers.clone());
+        POLICY_RELOADS.add(1, &[KeyValue::new("result", "success")]);
+        info!("Policy reloaded");
+
+        self.templates.apply_reload(templates);
+        info!("Templates reloaded");
+
+        if self.limiter.reload(&config.rate_limiting) {
+            info!("Rate limits reloaded");
+        } else {
+            warn!("Rate-limiting configuration is not valid, keeping the current rate limits");
+        }
+
+        if let (Some(current), Some(mailer)) = (&self.mailer, mailer) {
+            current.reconfigure(&mailer);
+            info!("Email settings reloaded");
+        }
+
+        Ok(())
+    }
+}
+
+/// Reload the templates whenever the files they are loaded from change on
+/// disk, until the cancellation token is cancelled
+pub fn watch_templates_changes(
+    templates: Templates,
+    task_tracker: &TaskTracker,
+    cancellation_token: CancellationToken,
+) {
+    info!("Watching the templates for changes");
+
+    task_tracker.spawn(async move {
+        let mut fingerprint = templates.fingerprint().await.ok();
+
+        loop {
+            tokio::select! {
+                biased;
+
+                () = cancellation_token.cancelled() => {
+                    return;
+                }
+
+                () = tokio::time::sleep(TEMPLATES_WATCH_INTERVAL) => {}
+            }
+
+            let current = match templates.fingerprint().await {
+                Ok(current) => current,
+                Err(e) => {
+                    warn!(
+                        error = &e as &dyn std::error::Error,
+                        "Could not check the templates for changes"
+                    );
+                    continue;
+                }
+            };
+
+            if fingerprint == Some(current) {
+                continue;
+            }
+            fingerprint = Some(current);
+
+            match templates.reload().await {
+                Ok(()) => info!("Templates changed on disk, reloaded them"),
+                Err(e) => error!(
+                    error = &e as &dyn std::error::Error,
+                    "Could not reload the templates, keeping the current ones"
+                ),
+            }
+        }
+    });
+}
@@ -13,21 +13,26 @@ use std::{
 use anyhow::Context;
 use axum::{
     error_handling::HandleErrorLayer,
-    extract::{FromRef, MatchedPath},
+    extract::{FromRef, MatchedPath, State},
+    middleware::{from_fn_with_state, Next},
+    response::IntoResponse,
     Extension, Router,
 };
+use camino::Utf8Path;
 use hyper::{
     header::{HeaderValue, CACHE_CONTROL, USER_AGENT},
     Method, Request, Response, StatusCode, Version,
 };
 use listenfd::ListenFd;
 use mas_config::{HttpBindConfig, HttpResource, HttpTlsConfig, UnixOrTcp};
+use mas_data_model::SiteConfig;
+use mas_handlers::ClientCertificate;
 use mas_listener::{unix_or_tcp::UnixOrTcpListener, ConnectionInfo};
 use mas_router::Route;
 use mas_templates::Templates;
 use mas_tower::{
-    make_span_fn, metrics_attributes_fn, DurationRecorderLayer, InFlightCounterLayer, TraceLayer,
-    KV,
+    make_span_fn, metrics_attributes_fn, DurationRecorderLayer, InFlightCounterLayer, RequestId,
+    RequestIdLayer, TraceLayer, KV,
 };
 use opentelemetry::{Key, KeyValue};
 use opentelemetry_http::HeaderExtractor;
@@ -120,6 +125,7 @@ fn make_http_span<B>(req: &Request<B>) -> Span {
         { URL_QUERY } = tracing::field::Empty,
         { URL_SCHEME } = otel_url_scheme(req),
         { USER_AGENT_ORIGINAL } = tracing::field::Empty,
+        "mas.request.id" = tracing::field::Empty,
     );
 
     if let Some(route) = route.as_ref() {
@@ -130,6 +136,10 @@ fn make_http_span<B>(req: &Request<B>) -> Span {
         span.record(URL_QUERY, query);
     }
 
+    if let Some(request_id) = req.extensions().get::<RequestId>() {
+        span.record("mas.request.id", request_id.as_str());
+    }
+
     if let Some(user_agent) = req
         .headers()
         .get(USER_AGENT)
@@ -170,13 +180,102 @@ fn on_http_response_labels<B>(res: &Response<B>) -> Vec<KeyValue> {
     )]
 }
 
+/// Count the database queries done by each request, record them in a
+/// histogram, and check them against the configured query budget
+async fn query_budget_middleware(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let route = otel_http_route(&request).unwrap_or("FALLBACK").to_owned();
+
+    let (response, count) = mas_storage_pg::count_queries(next.run(request)).await;
+
+    if let Some(histogram) = &state.queries_per_request_histogram {
+        histogram.record(count, &[KeyValue::new(HTTP_ROUTE, route.clone())]);
+    }
+
+    if let Some(budget) = &state.query_budget {
+        if count > u64::from(budget.max_queries.get()) {
+            tracing::warn!(
+                http.route = %route,
+                count,
+                max_queries = budget.max_queries.get(),
+                "Request went over the database query budget"
+            );
+
+            if budget.enforce {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    response
+}
+
+/// Take the client certificate forwarded by the reverse proxy terminating TLS
+/// in the configured header, and make it available to the handlers
+///
+/// The header is always removed from the request, and only trusted if the
+/// request comes directly from a trusted proxy.
+async fn client_certificate_middleware(
+    State(state): State<AppState>,
+    mut request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let Some(header) = &state.client_certificate_header else {
+        return next.run(request).await;
+    };
+
+    let Some(value) = request.headers_mut().remove(header) else {
+        return next.run(request).await;
+    };
+
+    let peer = request
+        .extensions()
+        .get::<ConnectionInfo>()
+        .and_then(ConnectionInfo::get_peer_addr)
+        .map(|addr| addr.ip());
+
+    let trusted = peer.is_some_and(|ip| {
+        state
+            .trusted_proxies
+            .iter()
+            .any(|network| network.contains(ip))
+    });
+
+    if !trusted {
+        tracing::warn!(
+            peer = ?peer,
+            "Ignoring the client certificate header sent by an untrusted peer"
+        );
+        return next.run(request).await;
+    }
+
+    match ClientCertificate::from_header_value(&value) {
+        Ok(certificate) => {
+            request.extensions_mut().insert(certificate);
+        }
+        Err(e) => {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                "Invalid client certificate forwarded by the reverse proxy"
+            );
+        }
+    }
+
+    next.run(request).await
+}
+
 pub fn build_router(
     state: AppState,
     resources: &[HttpResource],
     prefix: Option<&str>,
     name: Option<&str>,
+    theme_assets: Option<&Utf8Path>,
 ) -> Router<()> {
     let templates = Templates::from_ref(&state);
+    let site_config = SiteConfig::from_ref(&state);
     let mut router = Router::new();
 
     for resource in resources {
@@ -215,19 +314,40 @@ pub fn build_router(
                     HeaderValue::from_static("public, max-age=31536000, immutable"),
                 );
 
-                router.nest_service(
+                let router = router.nest_service(
                     mas_router::StaticAsset::route(),
-                    (error_layer, cache_layer).layer(static_service),
-                )
+                    (error_layer.clone(), cache_layer).layer(static_service),
+                );
+
+                if let Some(theme_assets) = theme_assets {
+                    // The theme assets are not versioned, so browsers have to check whether
+                    // they changed every time
+                    let theme_service =
+                        ServeDir::new(theme_assets).append_index_html_on_directories(false);
+                    let theme_cache_layer = SetResponseHeaderLayer::overriding(
+                        CACHE_CONTROL,
+                        HeaderValue::from_static("no-cache"),
+                    );
+
+                    router.nest_service(
+                        mas_router::ThemeAsset::route(),
+                        (error_layer, theme_cache_layer).layer(theme_service),
+                    )
+                } else {
+                    router
+                }
             }
             mas_config::HttpResource::OAuth => router.merge(mas_handlers::api_router::<AppState>()),
             mas_config::HttpResource::Compat => {
-                router.merge(mas_handlers::compat_router::<AppState>())
+                router.merge(mas_handlers::compat_router::<AppState>(&site_config))
             }
             mas_config::HttpResource::AdminApi => {
                 let (_, api_router) = mas_handlers::admin_api_router::<AppState>();
                 router.merge(api_router)
             }
+            mas_config::HttpResource::PasswordProvider => {
+                router.merge(mas_handlers::password_provider_router::<AppState>())
+            }
             // TODO: do a better handler here
             mas_config::HttpResource::ConnectionInfo => router.route(
                 "/connection-info",
@@ -246,6 +366,11 @@ pub fn build_router(
     router = router.fallback(mas_handlers::fallback);
 
     router
+        .layer(from_fn_with_state(state.clone(), query_budget_middleware))
+        .layer(from_fn_with_state(
+            state.clone(),
+            client_certificate_middleware,
+        ))
         .layer(
             InFlightCounterLayer::new("http.server.active_requests").on_request((
                 name.map(|name| MAS_LISTENER_NAME.string(name.to_owned())),
@@ -273,6 +398,7 @@ pub fn build_router(
         )
         .layer(SentryHttpLayer::new())
         .layer(NewSentryLayer::new_from_top())
+        .layer(RequestIdLayer::new())
         .with_state(state)
 }
 
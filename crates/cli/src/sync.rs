@@ -52,6 +52,10 @@ fn map_claims_imports(
             action: map_import_action(config.displayname.action),
             template: config.displayname.template.clone(),
         },
+        avatar: mas_data_model::UpstreamOAuthProviderImportPreference {
+            action: map_import_action(config.avatar.action),
+            template: config.avatar.template.clone(),
+        },
         email: mas_data_model::UpstreamOAuthProviderImportPreference {
             action: map_import_action(config.email.action),
             template: config.email.template.clone(),
@@ -67,6 +71,21 @@ fn map_claims_imports(
                 mas_data_model::UpsreamOAuthProviderSetEmailVerification::Import
             }
         },
+        on_email_change: match config.email.on_change {
+            mas_config::UpstreamOAuth2OnEmailChange::Ignore => {
+                mas_data_model::UpstreamOAuthProviderOnEmailChange::Ignore
+            }
+            mas_config::UpstreamOAuth2OnEmailChange::Review => {
+                mas_data_model::UpstreamOAuthProviderOnEmailChange::Review
+            }
+            mas_config::UpstreamOAuth2OnEmailChange::Update => {
+                mas_data_model::UpstreamOAuthProviderOnEmailChange::Update
+            }
+        },
+        admin: mas_data_model::UpstreamOAuthProviderImportPreference {
+            action: map_import_action(config.admin.action),
+            template: config.admin.template.clone(),
+        },
     }
 }
 
@@ -187,11 +206,65 @@ pub async fn config_sync(
                 continue;
             }
 
-            let encrypted_client_secret = provider
-                .client_secret
-                .as_deref()
-                .map(|client_secret| encrypter.encrypt_to_string(client_secret.as_bytes()))
-                .transpose()?;
+            let encrypted_client_secret = if let Some(client_secret) = &provider.client_secret {
+                Some(encrypter.encrypt_to_string(client_secret.as_bytes())?)
+            } else if let Some(sign_in_with_apple) = &provider.sign_in_with_apple {
+                // For Sign in with Apple, we store the key and its metadata as
+                // the client secret, so that the client secret JWT can be
+                // generated when talking to the provider
+                let private_key = match (
+                    &sign_in_with_apple.private_key,
+                    &sign_in_with_apple.private_key_file,
+                ) {
+                    (Some(private_key), None) => private_key.clone(),
+                    (None, Some(path)) => tokio::fs::read_to_string(path).await?,
+                    _ => anyhow::bail!(
+                        "Exactly one of `private_key` or `private_key_file` must be set"
+                    ),
+                };
+
+                let params = serde_json::json!({
+                    "private_key": private_key,
+                    "team_id": sign_in_with_apple.team_id,
+                    "key_id": sign_in_with_apple.key_id,
+                });
+
+                Some(encrypter.encrypt_to_string(&serde_json::to_vec(&params)?)?)
+            } else {
+                None
+            };
+
+            let token_endpoint_auth_method = match provider.token_endpoint_auth_method {
+                mas_config::UpstreamOAuth2TokenAuthMethod::None => {
+                    mas_data_model::UpstreamOAuthProviderTokenAuthMethod::None
+                }
+                mas_config::UpstreamOAuth2TokenAuthMethod::ClientSecretBasic => {
+                    mas_data_model::UpstreamOAuthProviderTokenAuthMethod::ClientSecretBasic
+                }
+                mas_config::UpstreamOAuth2TokenAuthMethod::ClientSecretPost => {
+                    mas_data_model::UpstreamOAuthProviderTokenAuthMethod::ClientSecretPost
+                }
+                mas_config::UpstreamOAuth2TokenAuthMethod::ClientSecretJwt => {
+                    mas_data_model::UpstreamOAuthProviderTokenAuthMethod::ClientSecretJwt
+                }
+                mas_config::UpstreamOAuth2TokenAuthMethod::PrivateKeyJwt => {
+                    mas_data_model::UpstreamOAuthProviderTokenAuthMethod::PrivateKeyJwt
+                }
+                mas_config::UpstreamOAuth2TokenAuthMethod::SignInWithApple => {
+                    mas_data_model::UpstreamOAuthProviderTokenAuthMethod::SignInWithApple
+                }
+            };
+
+            let response_mode = provider
+                .response_mode
+                .map(|response_mode| match response_mode {
+                    mas_config::UpstreamOAuth2ResponseMode::Query => {
+                        mas_data_model::UpstreamOAuthProviderResponseMode::Query
+                    }
+                    mas_config::UpstreamOAuth2ResponseMode::FormPost => {
+                        mas_data_model::UpstreamOAuthProviderResponseMode::FormPost
+                    }
+                });
 
             let discovery_mode = match provider.discovery_mode {
                 mas_config::UpstreamOAuth2DiscoveryMode::Oidc => {
@@ -231,6 +304,18 @@ pub async fn config_sync(
                 }
             };
 
+            let par_mode = match provider.pushed_authorization_requests {
+                mas_config::UpstreamOAuth2PushedAuthorizationRequestsMode::Auto => {
+                    mas_data_model::UpstreamOAuthProviderParMode::Auto
+                }
+                mas_config::UpstreamOAuth2PushedAuthorizationRequestsMode::Always => {
+                    mas_data_model::UpstreamOAuthProviderParMode::Always
+                }
+                mas_config::UpstreamOAuth2PushedAuthorizationRequestsMode::Never => {
+                    mas_data_model::UpstreamOAuthProviderParMode::Disabled
+                }
+            };
+
             repo.upstream_oauth_provider()
                 .upsert(
                     clock,
@@ -240,7 +325,7 @@ pub async fn config_sync(
                         human_name: provider.human_name,
                         brand_name: provider.brand_name,
                         scope: provider.scope.parse()?,
-                        token_endpoint_auth_method: provider.token_endpoint_auth_method.into(),
+                        token_endpoint_auth_method,
                         token_endpoint_signing_alg: provider
                             .token_endpoint_auth_signing_alg
                             .clone(),
@@ -252,10 +337,23 @@ pub async fn config_sync(
                         jwks_uri_override: provider.jwks_uri,
                         discovery_mode,
                         pkce_mode,
+                        par_mode,
+                        pushed_authorization_request_endpoint_override: provider
+                            .pushed_authorization_request_endpoint,
+                        request_object_signing_alg: provider.request_object_signing_alg,
+                        response_mode,
                         additional_authorization_parameters: provider
                             .additional_authorization_parameters
                             .into_iter()
                             .collect(),
+                        allow_idp_initiated_login: provider.idp_initiated_login.is_some(),
+                        idp_initiated_login_client_id: provider
+                            .idp_initiated_login
+                            .and_then(|idp_initiated_login| idp_initiated_login.client_id),
+                        propagate_logout: provider.propagate_logout,
+                        end_session_endpoint_override: provider.end_session_endpoint,
+                        fetch_userinfo: provider.fetch_userinfo,
+                        userinfo_endpoint_override: provider.userinfo_endpoint,
                     },
                 )
                 .await?;
@@ -321,6 +419,14 @@ pub async fn config_sync(
                     jwks.cloned(),
                     jwks_uri.cloned(),
                     client.redirect_uris,
+                    client.require_pushed_authorization_requests,
+                    client.tls_client_auth_subject_dn,
+                    client.tls_client_certificate_bound_access_tokens,
+                    client.jwt_access_tokens,
+                    client.access_token_ttl,
+                    client.refresh_token_ttl,
+                    client.session_ttl,
+                    client.session_inactivity_ttl,
                 )
                 .await?;
         }
@@ -8,21 +8,45 @@ use std::time::Duration;
 
 use anyhow::Context;
 use mas_config::{
-    AccountConfig, BrandingConfig, CaptchaConfig, DatabaseConfig, EmailConfig, EmailSmtpMode,
-    EmailTransportKind, ExperimentalConfig, MatrixConfig, PasswordsConfig, PolicyConfig,
-    TemplatesConfig,
+    AccountConfig, AuditConfig, BrandingConfig, CacheConfig, CaptchaConfig, ChaosConfig,
+    CompatConfig, CompatEndpointConfig, CompatLoginTypeConfig, DatabaseConfig, EmailConfig,
+    EmailSmtpMode, EmailTransportKind, EventStreamConfig, ExperimentalConfig, ExpiryWarningsConfig,
+    FaultInjectionConfig, HttpConfig, InactiveAccountsConfig, IntrospectionConfig,
+    IntrospectionTokenTypeConfig, JwtBearerConfig, MatrixConfig, OutboundAllowListConfig,
+    PasswordsConfig, PolicyConfig, ScopesConfig, SecretsConfig, SessionLimitActionConfig,
+    SuspiciousLoginsConfig, TemplatesConfig, UpstreamOAuth2Config, WebhookEvent, WebhooksConfig,
 };
-use mas_data_model::SiteConfig;
-use mas_email::{MailTransport, Mailer};
-use mas_handlers::{passwords::PasswordManager, ActivityTracker};
-use mas_policy::PolicyFactory;
+use mas_data_model::{
+    AdditionalHomeserver, Appservice, CompatDeprecation, CompatEndpoint, CompatLoginType,
+    CustomScope, ExpiringCredential, ExpiringCredentialKind, IntrospectionRestriction,
+    JwksOrJwksUri, JwtBearerIssuer, RedirectUriRules, SessionLimit, SessionLimitAction, SiteConfig,
+    TokenType, WebhookEventKind,
+};
+use mas_email::{Address, MailTransport, Mailer};
+use mas_handlers::{
+    passwords::{PasswordManager, SynapsePasswordFallback},
+    ActivityTracker, HttpClientFactory,
+};
+use mas_http::OutboundPolicy;
+use mas_matrix::MultiHomeserverConnection;
+use mas_matrix_synapse::SynapseConnection;
+use mas_policy::{PolicyFactory, PreparedPolicy};
 use mas_router::UrlBuilder;
+use mas_storage::{cache::RepositoryCache, SystemClock};
+use mas_tasks::{
+    EventStream, ExpiryWarnings, GeoIp, InactiveAccounts, KeyRotation, SuspiciousLoginDetection,
+    WebhookEndpoint,
+};
 use mas_templates::{SiteConfigExt, TemplateLoadingError, Templates};
+use mas_tower::FaultInjector;
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     ConnectOptions, PgConnection, PgPool,
 };
-use tracing::{error, info, log::LevelFilter};
+use tracing::{error, info, log::LevelFilter, warn};
+use url::Url;
+
+use crate::reload::ConfigReloader;
 
 pub async fn password_manager_from_config(
     config: &PasswordsConfig,
@@ -49,6 +73,37 @@ pub async fn password_manager_from_config(
     PasswordManager::new(config.minimum_complexity(), schemes)
 }
 
+/// Connect to the Synapse database used to verify the passwords of users
+/// migrated from Synapse, if configured
+pub async fn synapse_password_fallback_from_config(
+    config: &PasswordsConfig,
+    matrix_config: &MatrixConfig,
+) -> Result<Option<SynapsePasswordFallback>, anyhow::Error> {
+    let Some(fallback) = config.synapse_fallback() else {
+        return Ok(None);
+    };
+
+    let options: PgConnectOptions = fallback
+        .uri
+        .parse()
+        .context("could not parse Synapse database connection string")?;
+
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .min_connections(0)
+        .connect_with(options.application_name("matrix-authentication-service"))
+        .await
+        .context("could not connect to the Synapse database")?;
+
+    warn!("Passwords of users without one will be verified against the Synapse database");
+
+    Ok(Some(SynapsePasswordFallback::new(
+        pool,
+        matrix_config.homeserver.clone(),
+        fallback.version,
+    )))
+}
+
 pub fn mailer_from_config(
     config: &EmailConfig,
     templates: &Templates,
@@ -93,23 +148,224 @@ pub fn mailer_from_config(
     Ok(Mailer::new(templates.clone(), transport, from, reply_to))
 }
 
+/// Parse the addresses which should be alerted when a honeytoken is used
+///
+/// # Errors
+///
+/// Returns an error if one of the addresses is invalid
+pub fn honeytoken_alert_emails_from_config(
+    config: &AuditConfig,
+) -> Result<Vec<Address>, anyhow::Error> {
+    config
+        .honeytoken_alert_emails
+        .iter()
+        .map(|email| {
+            email
+                .parse()
+                .with_context(|| format!("invalid honeytoken alert email address {email:?}"))
+        })
+        .collect()
+}
+
+/// Collect the credentials with a known expiry date from the configuration:
+/// the TLS certificates of the listeners and the client secrets of the
+/// upstream OAuth 2.0 providers
+pub fn expiry_warnings_from_config(
+    config: &ExpiryWarningsConfig,
+    http_config: &HttpConfig,
+    upstream_oauth2_config: &UpstreamOAuth2Config,
+) -> Result<ExpiryWarnings, anyhow::Error> {
+    let threshold = chrono::Duration::from_std(config.threshold)
+        .context("expiry warnings threshold is out of range")?;
+
+    let emails = config
+        .emails
+        .iter()
+        .map(|email| {
+            email
+                .parse()
+                .with_context(|| format!("invalid expiry warning email address {email:?}"))
+        })
+        .collect::<Result<Vec<Address>, _>>()?;
+
+    let mut credentials = Vec::new();
+
+    for (index, listener) in http_config.listeners.iter().enumerate() {
+        let Some(tls) = &listener.tls else { continue };
+        let name = listener.name.clone().unwrap_or_else(|| index.to_string());
+
+        // Failing to load the certificate here is not fatal, the listener
+        // itself will fail to start if it is invalid
+        match tls.certificate_expires_at() {
+            Ok(expires_at) => credentials.push(ExpiringCredential::new(
+                ExpiringCredentialKind::TlsCertificate,
+                name,
+                expires_at,
+            )),
+            Err(e) => warn!(
+                error = &*e as &dyn std::error::Error,
+                listener.name = name,
+                "Could not read the expiry date of the TLS certificate"
+            ),
+        }
+    }
+
+    for provider in &upstream_oauth2_config.providers {
+        if !provider.enabled {
+            continue;
+        }
+
+        if let Some(expires_at) = provider.client_secret_expiry() {
+            credentials.push(ExpiringCredential::new(
+                ExpiringCredentialKind::UpstreamClientSecret,
+                provider.id.to_string(),
+                expires_at,
+            ));
+        }
+    }
+
+    Ok(ExpiryWarnings::new(threshold, emails, credentials))
+}
+
+/// Load the settings for the detection of suspicious logins, if enabled
+pub fn suspicious_login_detection_from_config(
+    config: &SuspiciousLoginsConfig,
+) -> Option<SuspiciousLoginDetection> {
+    if !config.enabled {
+        return None;
+    }
+
+    Some(SuspiciousLoginDetection::new(
+        config.impossible_travel_speed,
+        config.failed_attempts,
+        config.new_device,
+    ))
+}
+
+pub fn inactive_accounts_from_config(
+    config: &InactiveAccountsConfig,
+) -> Result<InactiveAccounts, anyhow::Error> {
+    let inactivity_period = chrono::Duration::from_std(config.inactivity_period)
+        .context("inactive accounts inactivity period is out of range")?;
+    let grace_period = chrono::Duration::from_std(config.grace_period)
+        .context("inactive accounts grace period is out of range")?;
+
+    Ok(InactiveAccounts::new(
+        inactivity_period,
+        grace_period,
+        config.exclude_usernames.clone(),
+        config.exclude_admins,
+    ))
+}
+
+/// Load the `GeoIP` database, if one is configured
+///
+/// It is loaded even if suspicious login detection is disabled, as it is also
+/// used to locate new sign-ins.
+pub fn geoip_from_config(config: &SuspiciousLoginsConfig) -> Result<Option<GeoIp>, anyhow::Error> {
+    config
+        .geoip_database
+        .as_deref()
+        .map(|path| {
+            GeoIp::open(path).with_context(|| format!("failed to load the GeoIP database {path}"))
+        })
+        .transpose()
+}
+
+pub fn policy_entrypoints_from_config(config: &PolicyConfig) -> mas_policy::Entrypoints {
+    mas_policy::Entrypoints {
+        register: config.register_entrypoint.clone(),
+        client_registration: config.client_registration_entrypoint.clone(),
+        authorization_grant: config.authorization_grant_entrypoint.clone(),
+        email: config.email_entrypoint.clone(),
+        impersonation: config.impersonation_entrypoint.clone(),
+        kiosk: config.kiosk_entrypoint.clone(),
+    }
+}
+
+/// Build the data passed to the policy, which is the configured data along
+/// with the custom scopes and which clients can request them, and the rules
+/// for the redirect URIs of clients
+pub fn policy_data_from_config(
+    config: &PolicyConfig,
+    scopes_config: &ScopesConfig,
+) -> serde_json::Value {
+    let mut data = config.data.clone();
+
+    if let Some(data) = data.as_object_mut() {
+        let custom_scopes = scopes_config
+            .custom
+            .iter()
+            .map(|custom| {
+                serde_json::json!({
+                    "scope": custom.scope,
+                    "clients": custom.clients,
+                })
+            })
+            .collect();
+        data.insert(
+            "custom_scopes".to_owned(),
+            serde_json::Value::Array(custom_scopes),
+        );
+
+        data.insert(
+            "redirect_uris".to_owned(),
+            serde_json::json!({
+                "native_custom_schemes": config.redirect_uris.native_custom_schemes,
+                "web_require_https": config.redirect_uris.web_require_https,
+            }),
+        );
+    }
+
+    data
+}
+
 pub async fn policy_factory_from_config(
     config: &PolicyConfig,
+    scopes_config: &ScopesConfig,
 ) -> Result<PolicyFactory, anyhow::Error> {
     let policy_file = tokio::fs::File::open(&config.wasm_module)
         .await
         .context("failed to open OPA WASM policy file")?;
 
-    let entrypoints = mas_policy::Entrypoints {
-        register: config.register_entrypoint.clone(),
-        client_registration: config.client_registration_entrypoint.clone(),
-        authorization_grant: config.authorization_grant_entrypoint.clone(),
-        email: config.email_entrypoint.clone(),
-    };
+    let entrypoints = policy_entrypoints_from_config(config);
+    let data = policy_data_from_config(config, scopes_config);
 
-    PolicyFactory::load(policy_file, config.data.clone(), entrypoints)
+    let policy_factory = PolicyFactory::load(policy_file, data, entrypoints)
         .await
-        .context("failed to load the policy")
+        .context("failed to load the policy")?;
+    policy_factory.set_request_headers(config.request_headers.clone());
+
+    Ok(policy_factory)
+}
+
+/// Load the policy again from the configuration, without replacing the
+/// current one yet
+///
+/// If the policy is fetched from a bundle, this returns `None`, so that the
+/// policy from the bundle is kept.
+pub async fn prepare_policy_reload_from_config(
+    policy_factory: &PolicyFactory,
+    config: &PolicyConfig,
+    scopes_config: &ScopesConfig,
+) -> Result<Option<PreparedPolicy>, anyhow::Error> {
+    if config.bundle.is_some() {
+        return Ok(None);
+    }
+
+    let policy_file = tokio::fs::File::open(&config.wasm_module)
+        .await
+        .context("failed to open OPA WASM policy file")?;
+
+    let entrypoints = policy_entrypoints_from_config(config);
+    let data = policy_data_from_config(config, scopes_config);
+
+    let policy = policy_factory
+        .prepare_reload(policy_file, data, entrypoints)
+        .await
+        .context("failed to load the policy")?;
+
+    Ok(Some(policy))
 }
 
 pub fn captcha_config_from_config(
@@ -147,26 +403,203 @@ pub fn site_config_from_config(
     password_config: &PasswordsConfig,
     account_config: &AccountConfig,
     captcha_config: &CaptchaConfig,
+    jwt_bearer_config: &JwtBearerConfig,
+    compat_config: &CompatConfig,
+    introspection_config: &IntrospectionConfig,
+    scopes_config: &ScopesConfig,
+    policy_config: &PolicyConfig,
 ) -> Result<SiteConfig, anyhow::Error> {
     let captcha = captcha_config_from_config(captcha_config)?;
+    let jwt_bearer_issuers = jwt_bearer_config
+        .issuers
+        .iter()
+        .map(|issuer| {
+            let jwks = match (&issuer.jwks, &issuer.jwks_uri) {
+                (Some(jwks), None) => JwksOrJwksUri::Jwks(jwks.clone()),
+                (None, Some(jwks_uri)) => JwksOrJwksUri::JwksUri(jwks_uri.clone()),
+                _ => anyhow::bail!("invalid keys for JWT bearer issuer {:?}", issuer.issuer),
+            };
+
+            Ok(JwtBearerIssuer {
+                issuer: issuer.issuer.clone(),
+                jwks,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    let compat_deprecation =
+        compat_config
+            .deprecation
+            .as_ref()
+            .map(|deprecation| CompatDeprecation {
+                endpoints: deprecation
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| match endpoint {
+                        CompatEndpointConfig::Login => CompatEndpoint::Login,
+                        CompatEndpointConfig::Logout => CompatEndpoint::Logout,
+                        CompatEndpointConfig::Refresh => CompatEndpoint::Refresh,
+                        CompatEndpointConfig::Register => CompatEndpoint::Register,
+                        CompatEndpointConfig::LoginSsoRedirect => CompatEndpoint::LoginSsoRedirect,
+                    })
+                    .collect(),
+                deprecated_at: deprecation.deprecated_at,
+                sunset_at: deprecation.sunset_at,
+                link: deprecation.link.clone(),
+            });
+
+    let compat_disabled_login_types = compat_config
+        .disabled_login_types
+        .iter()
+        .map(|disabled| {
+            let login_type = match disabled.login_type {
+                CompatLoginTypeConfig::Password => CompatLoginType::Password,
+                CompatLoginTypeConfig::Token => CompatLoginType::Token,
+            };
+            (login_type, disabled.after)
+        })
+        .collect();
+
+    let introspection_restrictions = introspection_config
+        .clients
+        .iter()
+        .map(|client| IntrospectionRestriction {
+            client_id: client.client_id,
+            token_types: client
+                .token_types
+                .iter()
+                .map(|token_type| match token_type {
+                    IntrospectionTokenTypeConfig::AccessToken => TokenType::AccessToken,
+                    IntrospectionTokenTypeConfig::RefreshToken => TokenType::RefreshToken,
+                    IntrospectionTokenTypeConfig::CompatAccessToken => TokenType::CompatAccessToken,
+                    IntrospectionTokenTypeConfig::CompatRefreshToken => {
+                        TokenType::CompatRefreshToken
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    let session_limit = account_config
+        .max_sessions_per_user
+        .map(|max_sessions| SessionLimit {
+            max_sessions: max_sessions.try_into().unwrap_or(usize::MAX),
+            action: match account_config.session_limit_action {
+                SessionLimitActionConfig::Refuse => SessionLimitAction::Refuse,
+                SessionLimitActionConfig::Evict => SessionLimitAction::Evict,
+            },
+        });
+
+    let additional_homeservers = matrix_config
+        .additional_homeservers
+        .iter()
+        .map(|additional| AdditionalHomeserver {
+            server_name: additional.homeserver.clone(),
+            hosts: additional.hosts.clone(),
+            clients: additional.clients.clone(),
+        })
+        .collect();
+
+    // The default policy accepts any URI when this is set, so the redirect URIs
+    // shouldn't be rejected later on either
+    let insecure_uris_allowed = policy_config
+        .data
+        .pointer("/client_registration/allow_insecure_uris")
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false);
+    let redirect_uri_rules = RedirectUriRules {
+        native_custom_schemes: policy_config.redirect_uris.native_custom_schemes.clone(),
+        loopback_any_port: policy_config.redirect_uris.loopback_any_port,
+        web_require_https: policy_config.redirect_uris.web_require_https,
+        insecure_uris_allowed,
+    };
+
+    let appservices = compat_config
+        .load_appservices()
+        .context("failed to load the application services")?
+        .into_iter()
+        .map(|appservice| {
+            let id = appservice.id.clone();
+            Appservice::new(
+                appservice.id,
+                appservice.as_token,
+                appservice.sender_localpart,
+                appservice
+                    .namespaces
+                    .users
+                    .iter()
+                    .map(|namespace| namespace.regex.as_str()),
+            )
+            .with_context(|| format!("invalid user namespace for application service {id:?}"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let custom_scopes = scopes_config
+        .custom
+        .iter()
+        .map(|custom| CustomScope {
+            scope: custom.scope.clone(),
+            requires_consent: custom.requires_consent,
+            description: custom.description.clone(),
+        })
+        .collect();
+
     Ok(SiteConfig {
         access_token_ttl: experimental_config.access_token_ttl,
-        compat_token_ttl: experimental_config.compat_token_ttl,
+        refresh_token_ttl: experimental_config.refresh_token_ttl,
+        session_ttl: experimental_config.session_ttl,
+        session_inactivity_ttl: experimental_config.session_inactivity_ttl,
+        compat_session_ttl: compat_config.session_ttl,
+        compat_session_inactivity_ttl: compat_config.session_inactivity_ttl,
+        browser_session_ttl: experimental_config.browser_session_ttl,
+        browser_session_inactivity_ttl: experimental_config.browser_session_inactivity_ttl,
+        compat_token_ttl: compat_config.access_token_ttl,
         server_name: matrix_config.homeserver.clone(),
         policy_uri: branding_config.policy_uri.clone(),
         tos_uri: branding_config.tos_uri.clone(),
+        terms_acceptance_required: account_config.terms_acceptance_required,
         imprint: branding_config.imprint.clone(),
-        password_login_enabled: password_config.enabled(),
-        password_registration_enabled: password_config.enabled()
+        service_name: branding_config.service_name.clone(),
+        logo_uri: branding_config.logo_uri.clone(),
+        theme_colors: branding_config.colors.clone(),
+        password_login_enabled: password_config.login_enabled(),
+        password_login_exceptions: password_config.login_exceptions().to_vec(),
+        password_registration_enabled: password_config.login_enabled()
             && account_config.password_registration_enabled,
+        guest_registration_enabled: account_config.guest_registration_enabled,
+        guest_inactivity_ttl: account_config.guest_inactivity_ttl,
         email_change_allowed: account_config.email_change_allowed,
         displayname_change_allowed: account_config.displayname_change_allowed,
         password_change_allowed: password_config.enabled()
             && account_config.password_change_allowed,
-        account_recovery_allowed: password_config.enabled()
+        account_recovery_allowed: password_config.login_enabled()
             && account_config.password_recovery_enabled,
+        admin_elevation_ttl: account_config.admin_elevation_ttl,
+        consent_ttl: account_config.consent_ttl,
+        activity_digest_interval: account_config.activity_digest_interval,
+        account_deletion_grace_period: account_config.deletion_grace_period,
+        new_device_emails: account_config.new_device_emails,
+        session_limit,
         captcha,
         minimum_password_complexity: password_config.minimum_complexity(),
+        jwt_bearer_issuers,
+        compat_deprecation,
+        compat_disabled_login_types,
+        require_pushed_authorization_requests: experimental_config
+            .require_pushed_authorization_requests,
+        notify_refresh_token_reuse: experimental_config.notify_refresh_token_reuse,
+        browser_session_binding_ttl: experimental_config.browser_session_binding_ttl,
+        impersonation_ttl: experimental_config.impersonation_ttl,
+        trusted_browser_ttl: experimental_config.trusted_browser_ttl,
+        kiosk_session_ttl: experimental_config.kiosk_session_ttl,
+        kiosk_session_inactivity_ttl: experimental_config.kiosk_session_inactivity_ttl,
+        rendezvous_ttl: experimental_config.rendezvous_ttl,
+        introspection_grace_period: introspection_config.grace_period,
+        introspection_restrictions,
+        additional_homeservers,
+        custom_scopes,
+        redirect_uri_rules,
+        appservices,
     })
 }
 
@@ -177,16 +610,19 @@ pub async fn templates_from_config(
 ) -> Result<Templates, TemplateLoadingError> {
     Templates::load(
         config.path.clone(),
+        config.theme_path.clone(),
         url_builder.clone(),
         config.assets_manifest.clone(),
         config.translations_path.clone(),
+        config.translations_overrides_path.clone(),
+        config.translations_fallbacks.clone(),
         site_config.templates_branding(),
         site_config.templates_features(),
     )
     .await
 }
 
-fn database_connect_options_from_config(
+pub fn database_connect_options_from_config(
     config: &DatabaseConfig,
 ) -> Result<PgConnectOptions, anyhow::Error> {
     let options = if let Some(uri) = config.uri.as_deref() {
@@ -222,6 +658,17 @@ fn database_connect_options_from_config(
         opts
     };
 
+    database_connect_options_with_ssl(options, config)
+}
+
+/// Apply the SSL and logging settings from the configuration to the connection
+/// options.
+///
+/// Those are shared between the primary database and the read replica.
+fn database_connect_options_with_ssl(
+    options: PgConnectOptions,
+    config: &DatabaseConfig,
+) -> Result<PgConnectOptions, anyhow::Error> {
     let options = match (config.ssl_ca.as_deref(), config.ssl_ca_file.as_deref()) {
         (None, None) => options,
         (Some(pem), None) => options.ssl_root_cert_from_pem(pem.as_bytes().to_owned()),
@@ -292,6 +739,230 @@ pub async fn database_pool_from_config(config: &DatabaseConfig) -> Result<PgPool
         .context("could not connect to the database")
 }
 
+/// Create a connection pool to the read-only replica of the database, if one
+/// is configured
+#[tracing::instrument(name = "db.connect_replica", skip_all, err(Debug))]
+pub async fn database_replica_pool_from_config(
+    config: &DatabaseConfig,
+) -> Result<Option<PgPool>, anyhow::Error> {
+    let Some(replica) = &config.read_replica else {
+        return Ok(None);
+    };
+
+    let options = replica
+        .uri
+        .parse()
+        .context("could not parse database replica connection string")?;
+    let options = database_connect_options_with_ssl(options, config)?;
+
+    let pool = PgPoolOptions::new()
+        .max_connections(replica.max_connections.into())
+        .min_connections(replica.min_connections)
+        .acquire_timeout(config.connect_timeout)
+        .idle_timeout(config.idle_timeout)
+        .max_lifetime(config.max_lifetime)
+        .connect_with(options)
+        .await
+        .context("could not connect to the database replica")?;
+
+    Ok(Some(pool))
+}
+
+/// Create the in-process [`RepositoryCache`] from the configuration, if it is
+/// enabled
+pub fn repository_cache_from_config(config: &CacheConfig) -> Option<RepositoryCache> {
+    if !config.enabled {
+        return None;
+    }
+
+    Some(RepositoryCache::new(
+        Box::new(SystemClock::default()),
+        config.max_entries,
+        config.client_ttl,
+        config.access_token_ttl,
+    ))
+}
+
+/// Create the [`HttpClientFactory`], with the outbound policies from the
+/// configuration
+pub fn http_client_factory_from_config(config: &HttpConfig) -> HttpClientFactory {
+    let outbound = &config.outbound;
+    let policy = OutboundPolicy::new()
+        .with_private_networks_allowed(outbound.allow_private_networks)
+        .with_allowed_networks(outbound.allowed_networks.iter().copied())
+        .with_allowed_hosts(outbound.allowed_hosts.iter().cloned());
+
+    let category_policy = |allow_list: &OutboundAllowListConfig| {
+        policy
+            .clone()
+            .with_allowed_networks(allow_list.allowed_networks.iter().copied())
+            .with_allowed_hosts(allow_list.allowed_hosts.iter().cloned())
+    };
+
+    HttpClientFactory::new()
+        .with_category_outbound_policy("webhook", category_policy(&outbound.webhooks))
+        .with_category_outbound_policy("avatar", category_policy(&outbound.avatars))
+        .with_category_outbound_policy("client.fetch_jwks", category_policy(&outbound.jwks))
+        .with_category_outbound_policy("client.fetch_logo", category_policy(&outbound.logos))
+        .with_outbound_policy(policy)
+}
+
+/// Build the list of [`WebhookEndpoint`] from the webhooks configuration
+pub fn webhook_endpoints_from_config(config: &WebhooksConfig) -> Vec<WebhookEndpoint> {
+    config
+        .endpoints
+        .iter()
+        .map(|endpoint| {
+            let webhook_endpoint =
+                WebhookEndpoint::new(endpoint.url.clone(), endpoint.secret.clone());
+
+            match &endpoint.events {
+                Some(events) => {
+                    webhook_endpoint.with_events(events.iter().map(|event| match event {
+                        WebhookEvent::UserRegistered => WebhookEventKind::UserRegistered,
+                        WebhookEvent::UserDeactivated => WebhookEventKind::UserDeactivated,
+                        WebhookEvent::UserReactivated => WebhookEventKind::UserReactivated,
+                        WebhookEvent::UserUpdated => WebhookEventKind::UserUpdated,
+                        WebhookEvent::SessionCreated => WebhookEventKind::SessionCreated,
+                        WebhookEvent::SessionRevoked => WebhookEventKind::SessionRevoked,
+                        WebhookEvent::HoneytokenUsed => WebhookEventKind::HoneytokenUsed,
+                    }))
+                }
+                None => webhook_endpoint,
+            }
+        })
+        .collect()
+}
+
+/// Connect to the event streaming platform configured, if any
+///
+/// # Errors
+///
+/// Returns an error if the connection to the event streaming platform failed
+#[cfg_attr(not(feature = "nats"), allow(clippy::unused_async))]
+pub async fn event_stream_from_config(
+    config: &EventStreamConfig,
+) -> Result<Option<EventStream>, anyhow::Error> {
+    if let Some(kafka) = &config.kafka {
+        #[cfg(feature = "kafka")]
+        {
+            info!(topic = %kafka.topic, "Publishing events to Kafka");
+            let stream = EventStream::kafka(&kafka.brokers, kafka.topic.clone())?;
+            return Ok(Some(stream));
+        }
+
+        #[cfg(not(feature = "kafka"))]
+        anyhow::bail!(
+            "This build does not support publishing events to Kafka (topic {:?})",
+            kafka.topic
+        );
+    }
+
+    if let Some(nats) = &config.nats {
+        #[cfg(feature = "nats")]
+        {
+            info!(subject = %nats.subject, "Publishing events to NATS");
+            let stream = EventStream::nats(&nats.url, nats.subject.clone()).await?;
+            return Ok(Some(stream));
+        }
+
+        #[cfg(not(feature = "nats"))]
+        anyhow::bail!(
+            "This build does not support publishing events to NATS (subject {:?})",
+            nats.subject
+        );
+    }
+
+    Ok(None)
+}
+
+/// Build the [`KeyRotation`] settings from the secrets configuration, if the
+/// automated rotation of signing keys is enabled
+///
+/// # Errors
+///
+/// Returns an error if one of the configured durations is out of range
+pub fn key_rotation_from_config(config: &SecretsConfig) -> anyhow::Result<Option<KeyRotation>> {
+    let Some(key_rotation) = &config.key_rotation else {
+        return Ok(None);
+    };
+
+    let interval = chrono::Duration::from_std(key_rotation.interval)
+        .context("key rotation interval is out of range")?;
+    let grace_period = chrono::Duration::from_std(key_rotation.grace_period)
+        .context("key rotation grace period is out of range")?;
+    let retention = chrono::Duration::from_std(key_rotation.retention)
+        .context("key rotation retention is out of range")?;
+
+    Ok(Some(KeyRotation::new(
+        key_rotation.algorithm.clone(),
+        config.encrypter(),
+        interval,
+        grace_period,
+        retention,
+    )))
+}
+
+/// Create the connection to the homeservers, with the additional homeservers
+/// from the configuration
+pub fn homeserver_connection_from_config(
+    config: &MatrixConfig,
+    http_client_factory: &HttpClientFactory,
+    fault_injector: &FaultInjector,
+) -> MultiHomeserverConnection<SynapseConnection> {
+    let connect = |homeserver: &str, endpoint: &Url, secret: &str| {
+        SynapseConnection::new(
+            homeserver.to_owned(),
+            endpoint.clone(),
+            secret.to_owned(),
+            http_client_factory.clone(),
+        )
+        .with_fault_injector(fault_injector.clone())
+    };
+
+    MultiHomeserverConnection::with_additional(
+        connect(&config.homeserver, &config.endpoint, &config.secret),
+        config.additional_homeservers.iter().map(|additional| {
+            connect(
+                &additional.homeserver,
+                &additional.endpoint,
+                &additional.secret,
+            )
+        }),
+    )
+}
+
+/// Create a [`FaultInjector`] for the given kind of calls from the chaos
+/// configuration
+pub fn fault_injector_from_config(
+    config: &ChaosConfig,
+    target: &'static str,
+    fault_config: &FaultInjectionConfig,
+) -> FaultInjector {
+    if !config.enabled {
+        return FaultInjector::disabled();
+    }
+
+    let fault_injector = FaultInjector::new(
+        target,
+        fault_config.latency_rate,
+        fault_config.max_latency,
+        fault_config.error_rate,
+    );
+
+    if fault_injector.is_enabled() {
+        warn!(
+            target,
+            latency_rate = fault_config.latency_rate,
+            max_latency = ?fault_config.max_latency,
+            error_rate = fault_config.error_rate,
+            "Fault injection is enabled. This must never be used in production!"
+        );
+    }
+
+    fault_injector
+}
+
 /// Create a single database connection from the configuration
 #[tracing::instrument(name = "db.connect", skip_all, err(Debug))]
 pub async fn database_connection_from_config(
@@ -305,13 +976,12 @@ pub async fn database_connection_from_config(
 
 /// Reload templates on SIGHUP
 pub fn register_sighup(
-    templates: &Templates,
+    reloader: ConfigReloader,
     activity_tracker: &ActivityTracker,
 ) -> anyhow::Result<()> {
     #[cfg(unix)]
     {
         let mut signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
-        let templates = templates.clone();
         let activity_tracker = activity_tracker.clone();
 
         tokio::spawn(async move {
@@ -321,11 +991,11 @@ pub fn register_sighup(
                     break;
                 };
 
-                info!("SIGHUP received, reloading templates & flushing activity tracker");
+                info!("SIGHUP received, reloading configuration & flushing activity tracker");
 
                 activity_tracker.flush().await;
-                templates.clone().reload().await.unwrap_or_else(|err| {
-                    error!(?err, "Error while reloading templates");
+                reloader.reload().await.unwrap_or_else(|err| {
+                    error!(?err, "Error while reloading configuration");
                 });
             }
         });
@@ -0,0 +1,586 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Fetch secrets referenced in the configuration from `HashiCorp` Vault
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use anyhow::Context;
+use bytes::Bytes;
+use figment::{
+    providers::Serialized,
+    value::{Dict, Value},
+    Figment,
+};
+use http::{header::HeaderName, Method, Request};
+use mas_config::{
+    ConfigurationSection, ConfigurationSectionExt, DatabaseConfig, VaultAuthConfig, VaultConfig,
+    VaultReference,
+};
+use mas_handlers::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use sqlx::PgPool;
+use tower::{Service, ServiceExt};
+use tracing::{error, info};
+use url::Url;
+
+use crate::util::database_connect_options_from_config;
+
+const VAULT_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-vault-token");
+const VAULT_NAMESPACE_HEADER: HeaderName = HeaderName::from_static("x-vault-namespace");
+
+/// Dynamic credentials are fetched again when this fraction of their lease has
+/// elapsed
+const LEASE_REFRESH_RATIO: f64 = 2. / 3.;
+
+/// Don't fetch the dynamic credentials more often than this, in case Vault
+/// gives very short leases
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The lease of the database credentials fetched on startup, so that the
+/// background refresh knows when to fetch new ones
+static DATABASE_CREDENTIALS_LEASE: OnceLock<Duration> = OnceLock::new();
+
+/// A secret read from Vault
+struct VaultSecret {
+    data: serde_json::Map<String, serde_json::Value>,
+    lease_duration: Duration,
+}
+
+impl VaultSecret {
+    /// Get a field of the secret as a string
+    fn field(&self, field: &str) -> Option<String> {
+        match self.data.get(field)? {
+            serde_json::Value::String(value) => Some(value.clone()),
+            serde_json::Value::Null => None,
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+/// A client to the Vault HTTP API
+#[derive(Clone)]
+struct VaultClient {
+    address: Url,
+    namespace: Option<String>,
+    auth: VaultAuthConfig,
+    http_client_factory: HttpClientFactory,
+}
+
+impl VaultClient {
+    fn from_config(config: &VaultConfig) -> Option<Self> {
+        Some(Self {
+            address: config.address.clone()?,
+            namespace: config.namespace.clone(),
+            auth: config.auth.clone()?,
+            http_client_factory: HttpClientFactory::new(),
+        })
+    }
+
+    async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        let url = self
+            .address
+            .join(&format!("v1/{path}"))
+            .context("invalid Vault path")?;
+
+        let mut builder = Request::builder().method(method).uri(url.as_str());
+        if let Some(token) = token {
+            builder = builder.header(VAULT_TOKEN_HEADER, token);
+        }
+        if let Some(namespace) = &self.namespace {
+            builder = builder.header(VAULT_NAMESPACE_HEADER, namespace);
+        }
+
+        let body = match body {
+            Some(body) => Bytes::from(serde_json::to_vec(&body)?),
+            None => Bytes::new(),
+        };
+        let request = builder.body(body)?;
+
+        let mut client = self
+            .http_client_factory
+            .client("vault")
+            .request_bytes_to_body()
+            .response_body_to_bytes();
+
+        let response = client
+            .ready()
+            .await?
+            .call(request)
+            .await
+            .context("failed to call Vault")?;
+
+        let status = response.status();
+        let body: serde_json::Value =
+            serde_json::from_slice(response.body()).unwrap_or(serde_json::Value::Null);
+
+        if !status.is_success() {
+            let errors = body
+                .get("errors")
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            anyhow::bail!("Vault returned an error ({status}) for {path:?}: {errors}");
+        }
+
+        Ok(body)
+    }
+
+    /// Get a token to authenticate the next requests, logging in if needed
+    async fn token(&self) -> Result<String, anyhow::Error> {
+        let (mount, body) = match &self.auth {
+            VaultAuthConfig::Token { token, token_file } => {
+                return match (token, token_file) {
+                    (Some(token), None) => Ok(token.clone()),
+                    (None, Some(path)) => {
+                        let token = tokio::fs::read_to_string(path)
+                            .await
+                            .with_context(|| format!("could not read the Vault token {path}"))?;
+                        Ok(token.trim().to_owned())
+                    }
+                    _ => anyhow::bail!("Exactly one of `token` and `token_file` must be set"),
+                };
+            }
+
+            VaultAuthConfig::AppRole {
+                mount,
+                role_id,
+                secret_id,
+                secret_id_file,
+            } => {
+                let secret_id = match (secret_id, secret_id_file) {
+                    (Some(secret_id), None) => secret_id.clone(),
+                    (None, Some(path)) => tokio::fs::read_to_string(path)
+                        .await
+                        .with_context(|| format!("could not read the Vault secret ID {path}"))?
+                        .trim()
+                        .to_owned(),
+                    _ => {
+                        anyhow::bail!("Exactly one of `secret_id` and `secret_id_file` must be set")
+                    }
+                };
+
+                (
+                    mount,
+                    serde_json::json!({ "role_id": role_id, "secret_id": secret_id }),
+                )
+            }
+
+            VaultAuthConfig::Kubernetes {
+                mount,
+                role,
+                jwt_file,
+            } => {
+                let jwt = tokio::fs::read_to_string(jwt_file).await.with_context(|| {
+                    format!("could not read the Kubernetes service account token {jwt_file}")
+                })?;
+
+                (
+                    mount,
+                    serde_json::json!({ "role": role, "jwt": jwt.trim() }),
+                )
+            }
+        };
+
+        let mount = mount.trim_matches('/');
+        let response = self
+            .call(
+                Method::POST,
+                &format!("auth/{mount}/login"),
+                None,
+                Some(body),
+            )
+            .await
+            .context("could not log in to Vault")?;
+
+        response
+            .pointer("/auth/client_token")
+            .and_then(serde_json::Value::as_str)
+            .map(ToOwned::to_owned)
+            .context("Vault did not return a token")
+    }
+
+    /// Read the secret at the given path
+    async fn read(&self, token: &str, path: &str) -> Result<VaultSecret, anyhow::Error> {
+        let response = self.call(Method::GET, path, Some(token), None).await?;
+
+        let lease_duration = response
+            .get("lease_duration")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or_default();
+
+        let mut data = match response.get("data") {
+            Some(serde_json::Value::Object(data)) => data.clone(),
+            _ => anyhow::bail!("Vault returned no data for {path:?}"),
+        };
+
+        // Secrets from the version 2 of the key/value secrets engine are nested
+        // in a `data` field, next to their metadata
+        if data.contains_key("metadata") {
+            if let Some(serde_json::Value::Object(inner)) = data.remove("data") {
+                data = inner;
+            }
+        }
+
+        Ok(VaultSecret {
+            data,
+            lease_duration: Duration::from_secs(lease_duration),
+        })
+    }
+
+    /// Fetch a new set of dynamic database credentials
+    async fn database_credentials(
+        &self,
+        path: &str,
+    ) -> Result<(String, String, Duration), anyhow::Error> {
+        let token = self.token().await?;
+        let secret = self
+            .read(&token, path)
+            .await
+            .context("could not fetch the database credentials from Vault")?;
+
+        let username = secret
+            .field("username")
+            .context("the database credentials have no username")?;
+        let password = secret
+            .field("password")
+            .context("the database credentials have no password")?;
+
+        Ok((username, password, secret.lease_duration))
+    }
+}
+
+/// Collect the Vault references in the configuration
+fn collect_references(value: &Value, references: &mut BTreeSet<String>) {
+    match value {
+        Value::String(_, s) if VaultReference::is_reference(s) => {
+            references.insert(s.clone());
+        }
+        Value::Dict(_, dict) => {
+            for value in dict.values() {
+                collect_references(value, references);
+            }
+        }
+        Value::Array(_, array) => {
+            for value in array {
+                collect_references(value, references);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace the Vault references in the configuration by their values
+///
+/// Returns `None` if there was nothing to replace. Dictionaries only keep the
+/// keys which were replaced, so that the result can be merged on top of the
+/// original configuration, but arrays are returned entirely, as they replace
+/// the original ones when merged.
+fn replace_references(value: &Value, resolved: &HashMap<String, String>) -> Option<Value> {
+    match value {
+        Value::String(_, s) => resolved.get(s).map(|v| Value::from(v.clone())),
+        Value::Dict(_, dict) => {
+            let patch: Dict = dict
+                .iter()
+                .filter_map(|(key, value)| {
+                    replace_references(value, resolved).map(|value| (key.clone(), value))
+                })
+                .collect();
+
+            (!patch.is_empty()).then(|| Value::from(patch))
+        }
+        Value::Array(_, array) => {
+            let patched: Vec<_> = array
+                .iter()
+                .map(|value| replace_references(value, resolved))
+                .collect();
+
+            if patched.iter().all(Option::is_none) {
+                return None;
+            }
+
+            let array: Vec<Value> = array
+                .iter()
+                .zip(patched)
+                .map(|(original, patched)| {
+                    // Nested dictionaries in arrays are patched partially, so
+                    // they need to be merged with the original
+                    match (original, patched) {
+                        (_, None) => original.clone(),
+                        (Value::Dict(_, original), Some(Value::Dict(_, patch))) => {
+                            let mut merged = original.clone();
+                            merge_dict(&mut merged, patch);
+                            Value::from(merged)
+                        }
+                        (_, Some(patched)) => patched,
+                    }
+                })
+                .collect();
+
+            Some(Value::from(array))
+        }
+        _ => None,
+    }
+}
+
+/// Recursively merge a partial dictionary into another
+fn merge_dict(target: &mut Dict, patch: Dict) {
+    for (key, value) in patch {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Dict(_, target)), Value::Dict(_, patch)) => merge_dict(target, patch),
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replace the values of the form `vault:<path>#<field>` in the configuration
+/// by the secrets they reference, and fetch the dynamic database credentials
+/// if configured
+///
+/// # Errors
+///
+/// Returns an error if Vault is not configured but secrets reference it, or if
+/// a secret could not be fetched
+pub async fn resolve_references(figment: Figment) -> Result<Figment, anyhow::Error> {
+    let config = VaultConfig::extract_or_default(&figment)?;
+
+    let mut root: Value = figment.extract()?;
+    // The Vault configuration itself can't reference secrets in Vault
+    if let Value::Dict(_, dict) = &mut root {
+        dict.remove(VaultConfig::PATH.unwrap());
+    }
+
+    let mut references = BTreeSet::new();
+    collect_references(&root, &mut references);
+
+    if references.is_empty() && config.database_credentials.is_none() {
+        return Ok(figment);
+    }
+
+    let Some(client) = VaultClient::from_config(&config) else {
+        anyhow::bail!(
+            "The configuration references secrets in Vault, but the `vault` section is not configured"
+        );
+    };
+
+    let token = client.token().await?;
+
+    // Read each secret only once, even if multiple fields are referenced
+    let mut secrets = HashMap::new();
+    let mut resolved = HashMap::new();
+    for raw in references {
+        let reference: VaultReference = raw.parse()?;
+        if !secrets.contains_key(&reference.path) {
+            let secret = client
+                .read(&token, &reference.path)
+                .await
+                .with_context(|| format!("could not read the secret {:?}", reference.path))?;
+            secrets.insert(reference.path.clone(), secret);
+        }
+
+        let value = secrets[&reference.path]
+            .field(&reference.field)
+            .with_context(|| {
+                format!(
+                    "the secret {:?} has no field {:?}",
+                    reference.path, reference.field
+                )
+            })?;
+        resolved.insert(raw, value);
+    }
+
+    info!(
+        secrets = secrets.len(),
+        values = resolved.len(),
+        "Fetched secrets from Vault"
+    );
+
+    let mut patch = match replace_references(&root, &resolved) {
+        Some(Value::Dict(_, patch)) => patch,
+        _ => Dict::new(),
+    };
+
+    if let Some(database_credentials) = &config.database_credentials {
+        let database = DatabaseConfig::extract_or_default(&figment)?;
+        if database.uri.is_some() {
+            anyhow::bail!("Dynamic database credentials from Vault can't be used with `database.uri`, use the split `host`, `port`, `database`, etc. options instead");
+        }
+
+        let (username, password, lease_duration) = client
+            .database_credentials(&database_credentials.path)
+            .await?;
+        info!(
+            %username,
+            lease_duration = lease_duration.as_secs(),
+            "Fetched the database credentials from Vault"
+        );
+
+        let mut credentials = Dict::new();
+        credentials.insert("username".to_owned(), Value::from(username));
+        credentials.insert("password".to_owned(), Value::from(password));
+        merge_dict(
+            &mut patch,
+            Dict::from([("database".to_owned(), Value::from(credentials))]),
+        );
+
+        let _ = DATABASE_CREDENTIALS_LEASE.set(lease_duration);
+    }
+
+    Ok(figment.merge(Serialized::defaults(patch)))
+}
+
+/// When the database credentials are fetched dynamically from Vault, fetch new
+/// ones before their lease expires and use them for the new connections of the
+/// pool
+///
+/// # Errors
+///
+/// Returns an error if the Vault configuration is invalid
+pub fn register_database_credentials_refresh(
+    figment: &Figment,
+    database_config: &DatabaseConfig,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let config = VaultConfig::extract_or_default(figment)?;
+    let Some(database_credentials) = config.database_credentials.clone() else {
+        return Ok(());
+    };
+    let Some(client) = VaultClient::from_config(&config) else {
+        return Ok(());
+    };
+
+    // The lease of the credentials fetched on startup
+    let Some(lease_duration) = DATABASE_CREDENTIALS_LEASE.get().copied() else {
+        return Ok(());
+    };
+
+    // A lease of zero means the credentials never expire
+    if lease_duration.is_zero() {
+        return Ok(());
+    }
+
+    let base_options = database_connect_options_from_config(database_config)?;
+    let pool = pool.clone();
+
+    tokio::spawn(async move {
+        let mut lease_duration = lease_duration;
+        loop {
+            let wait = lease_duration
+                .mul_f64(LEASE_REFRESH_RATIO)
+                .max(MIN_REFRESH_INTERVAL);
+            tokio::time::sleep(wait).await;
+
+            match client
+                .database_credentials(&database_credentials.path)
+                .await
+            {
+                Ok((username, password, new_lease_duration)) => {
+                    info!(
+                        %username,
+                        lease_duration = new_lease_duration.as_secs(),
+                        "Fetched new database credentials from Vault"
+                    );
+
+                    pool.set_connect_options(
+                        base_options.clone().username(&username).password(&password),
+                    );
+                    lease_duration = new_lease_duration;
+                }
+
+                Err(err) => {
+                    // Try again soon, the current credentials might still be valid
+                    error!(?err, "Could not fetch new database credentials from Vault");
+                    lease_duration = MIN_REFRESH_INTERVAL;
+                }
+            }
+
+            if lease_duration.is_zero() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::providers::{Format, Yaml};
+
+    use super::*;
+
+    #[test]
+    fn test_replace_references() {
+        let figment = Figment::new().merge(Yaml::string(
+            r#"
+                email:
+                  hostname: smtp.example.com
+                  password: "vault:secret/data/mas#smtp_password"
+                secrets:
+                  encryption: "vault:secret/data/mas#encryption"
+                  keys:
+                    - kid: plain
+                      key: plain-key
+                    - kid: from-vault
+                      key: "vault:secret/data/keys#rsa"
+            "#,
+        ));
+
+        let root: Value = figment.extract().unwrap();
+        let mut references = BTreeSet::new();
+        collect_references(&root, &mut references);
+        assert_eq!(
+            references.into_iter().collect::<Vec<_>>(),
+            vec![
+                "vault:secret/data/keys#rsa".to_owned(),
+                "vault:secret/data/mas#encryption".to_owned(),
+                "vault:secret/data/mas#smtp_password".to_owned(),
+            ]
+        );
+
+        let resolved = HashMap::from([
+            (
+                "vault:secret/data/mas#smtp_password".to_owned(),
+                "hunter2".to_owned(),
+            ),
+            (
+                "vault:secret/data/mas#encryption".to_owned(),
+                "0000".to_owned(),
+            ),
+            (
+                "vault:secret/data/keys#rsa".to_owned(),
+                "rsa-key".to_owned(),
+            ),
+        ]);
+
+        let Some(Value::Dict(_, patch)) = replace_references(&root, &resolved) else {
+            panic!("expected a patch");
+        };
+        let figment = figment.merge(Serialized::defaults(patch));
+
+        let hostname: String = figment.extract_inner("email.hostname").unwrap();
+        assert_eq!(hostname, "smtp.example.com");
+        let password: String = figment.extract_inner("email.password").unwrap();
+        assert_eq!(password, "hunter2");
+        let encryption: String = figment.extract_inner("secrets.encryption").unwrap();
+        assert_eq!(encryption, "0000");
+        let keys: Vec<HashMap<String, String>> = figment.extract_inner("secrets.keys").unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0]["key"], "plain-key");
+        assert_eq!(keys[1]["kid"], "from-vault");
+        assert_eq!(keys[1]["key"], "rsa-key");
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Override configuration values with environment variables
+
+use figment::{
+    value::{Dict, Map, Value},
+    Error, Figment, Metadata, Profile, Provider,
+};
+
+/// The prefix of the environment variables which override configuration
+/// values
+pub const ENV_OVERRIDE_PREFIX: &str = "MAS_";
+
+/// The separator between the keys of nested configuration values in the
+/// environment variables which override them
+pub const ENV_OVERRIDE_SEPARATOR: &str = "__";
+
+/// A part of the path of a configuration value
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Segment {
+    Index(usize),
+    Key(String),
+}
+
+/// A [`Provider`] which overrides configuration values with environment
+/// variables
+///
+/// The name of the variable is the path of the value, prefixed by `MAS_`, with
+/// `__` between the keys: for example, `MAS_HTTP__PUBLIC_BASE` overrides
+/// `http.public_base`. Items of lists are addressed by their index, starting
+/// from zero: `MAS_HTTP__LISTENERS__0__NAME` overrides the name of the first
+/// HTTP listener. Using the index right after the last item of a list adds a
+/// new item to it.
+///
+/// Values are parsed like with [`figment::providers::Env`], so they can also
+/// be whole lists or maps, like `[1, 2, 3]` or `{key = "value"}`.
+#[derive(Debug, Clone)]
+pub struct EnvOverrides {
+    patch: Result<Dict, Error>,
+}
+
+impl EnvOverrides {
+    /// Compute the overrides from the environment variables of the process,
+    /// applied on top of the given configuration
+    #[must_use]
+    pub fn from_env(base: &Figment) -> Self {
+        Self::from_vars(base, std::env::vars())
+    }
+
+    /// Compute the overrides from a list of environment variables, applied on
+    /// top of the given configuration
+    #[must_use]
+    pub fn from_vars(base: &Figment, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut overrides: Vec<_> = vars
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let path = parse_name(&name)?;
+                let value: Value = value.parse().unwrap_or_else(|e| match e {});
+                Some((name, path, value))
+            })
+            .collect();
+
+        // Apply the overrides in order, so that items are added to lists in the
+        // order of their index
+        overrides.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+
+        // Lists are overridden as a whole, so start from the current configuration
+        let mut root = match base.extract() {
+            Ok(Value::Dict(_, dict)) => dict,
+            _ => Dict::new(),
+        };
+        let mut patch = Dict::new();
+
+        for (name, path, value) in overrides {
+            if let Err(message) = set(&mut root, &path, value) {
+                return Self {
+                    patch: Err(Error::from(format!(
+                        "Invalid environment variable {name}: {message}"
+                    ))),
+                };
+            }
+
+            // Add to the patch the overridden value, or the whole list it is in
+            let keys: Vec<&str> = path
+                .iter()
+                .map_while(|segment| match segment {
+                    Segment::Key(key) => Some(key.as_str()),
+                    Segment::Index(_) => None,
+                })
+                .collect();
+            if let Some(value) = lookup(&root, &keys) {
+                insert(&mut patch, &keys, value.clone());
+            }
+        }
+
+        Self { patch: Ok(patch) }
+    }
+}
+
+impl Provider for EnvOverrides {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("environment variable(s)").interpolater(|_: &Profile, keys: &[&str]| {
+            let keys: Vec<_> = keys.iter().map(|k| k.to_ascii_uppercase()).collect();
+            format!("{ENV_OVERRIDE_PREFIX}{}", keys.join(ENV_OVERRIDE_SEPARATOR))
+        })
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, Error> {
+        Ok(Profile::Default.collect(self.patch.clone()?))
+    }
+}
+
+/// Get the path of the configuration value overridden by an environment
+/// variable, if it is one
+fn parse_name(name: &str) -> Option<Vec<Segment>> {
+    let name = name.strip_prefix(ENV_OVERRIDE_PREFIX)?;
+
+    // Variables without a separator, like `MAS_CONFIG`, are not overrides
+    if !name.contains(ENV_OVERRIDE_SEPARATOR) {
+        return None;
+    }
+
+    let path: Vec<_> = name
+        .split(ENV_OVERRIDE_SEPARATOR)
+        .map(|segment| match segment.parse() {
+            Ok(index) => Segment::Index(index),
+            Err(_) => Segment::Key(segment.to_ascii_lowercase()),
+        })
+        .collect();
+
+    let valid = path.iter().all(|segment| match segment {
+        Segment::Key(key) => !key.is_empty(),
+        Segment::Index(_) => true,
+    });
+
+    (valid && matches!(path[0], Segment::Key(_))).then_some(path)
+}
+
+/// Set the value at the given path in a map, creating the maps on the way if
+/// needed
+fn set(dict: &mut Dict, path: &[Segment], value: Value) -> Result<(), String> {
+    let Some((Segment::Key(key), rest)) = path.split_first() else {
+        return Err("expected a key".to_owned());
+    };
+
+    if rest.is_empty() {
+        dict.insert(key.clone(), value);
+        return Ok(());
+    }
+
+    let node = dict
+        .entry(key.clone())
+        .or_insert_with(|| empty_node(&rest[0]));
+    set_value(node, rest, value)
+}
+
+fn set_value(node: &mut Value, path: &[Segment], value: Value) -> Result<(), String> {
+    match path.first() {
+        None => {
+            *node = value;
+            Ok(())
+        }
+
+        Some(Segment::Key(_)) => {
+            if !matches!(node, Value::Dict(..)) {
+                *node = empty_node(&path[0]);
+            }
+            let Value::Dict(_, dict) = node else {
+                unreachable!()
+            };
+            set(dict, path, value)
+        }
+
+        Some(Segment::Index(index)) => {
+            if !matches!(node, Value::Array(..)) {
+                *node = empty_node(&path[0]);
+            }
+            let Value::Array(_, items) = node else {
+                unreachable!()
+            };
+
+            let index = *index;
+            if index > items.len() {
+                return Err(format!(
+                    "index {index} is out of bounds, the list has {len} item(s)",
+                    len = items.len()
+                ));
+            }
+
+            if index == items.len() {
+                let item = path
+                    .get(1)
+                    .map_or_else(|| Value::from(Dict::new()), empty_node);
+                items.push(item);
+            }
+
+            set_value(&mut items[index], &path[1..], value)
+        }
+    }
+}
+
+/// An empty value to be indexed by the given segment
+fn empty_node(segment: &Segment) -> Value {
+    match segment {
+        Segment::Key(_) => Value::from(Dict::new()),
+        Segment::Index(_) => Value::from(Vec::<Value>::new()),
+    }
+}
+
+/// Get the value at the given path of keys in a map
+fn lookup<'a>(dict: &'a Dict, keys: &[&str]) -> Option<&'a Value> {
+    let (key, rest) = keys.split_first()?;
+    let value = dict.get(*key)?;
+    match value {
+        _ if rest.is_empty() => Some(value),
+        Value::Dict(_, dict) => lookup(dict, rest),
+        _ => None,
+    }
+}
+
+/// Insert a value at the given path of keys in a map, replacing the values
+/// which are not maps on the way
+fn insert(dict: &mut Dict, keys: &[&str], value: Value) {
+    let Some((key, rest)) = keys.split_first() else {
+        return;
+    };
+
+    if rest.is_empty() {
+        dict.insert((*key).to_owned(), value);
+        return;
+    }
+
+    let node = dict
+        .entry((*key).to_owned())
+        .or_insert_with(|| Value::from(Dict::new()));
+    if let Value::Dict(_, dict) = node {
+        insert(dict, rest, value);
+    } else {
+        let mut dict = Dict::new();
+        insert(&mut dict, rest, value);
+        *node = Value::from(dict);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Jail,
+    };
+
+    use super::*;
+    use crate::{ConfigurationSection, HttpConfig, MatrixConfig};
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(parse_name("MAS_CONFIG"), None);
+        assert_eq!(parse_name("HOME"), None);
+        assert_eq!(parse_name("MAS___SECRET"), None);
+        assert_eq!(parse_name("MAS_0__NAME"), None);
+        assert_eq!(
+            parse_name("MAS_HTTP__LISTENERS__1__NAME"),
+            Some(vec![
+                Segment::Key("http".to_owned()),
+                Segment::Key("listeners".to_owned()),
+                Segment::Index(1),
+                Segment::Key("name".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn override_values() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    http:
+                      public_base: https://auth.example.com/
+                      listeners:
+                        - name: web
+                          resources:
+                            - name: discovery
+                          binds:
+                            - address: '[::]:8080'
+                    matrix:
+                      homeserver: example.com
+                      secret: hunter2
+                      endpoint: http://localhost:8008/
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let overrides = EnvOverrides::from_vars(
+                &figment,
+                vars(&[
+                    ("MAS_MATRIX__SECRET", "correct horse battery staple"),
+                    ("MAS_HTTP__PUBLIC_BASE", "https://sso.example.com/"),
+                    ("MAS_HTTP__LISTENERS__0__BINDS__0__ADDRESS", "[::]:9090"),
+                    ("MAS_HTTP__LISTENERS__1__NAME", "internal"),
+                    ("MAS_HTTP__LISTENERS__1__RESOURCES__0__NAME", "health"),
+                    ("MAS_HTTP__LISTENERS__1__BINDS__0__PORT", "8081"),
+                    ("MAS_CONFIG", "config.yaml"),
+                ]),
+            );
+            let figment = figment.merge(overrides);
+
+            let matrix = MatrixConfig::extract(&figment)?;
+            assert_eq!(matrix.homeserver, "example.com");
+            assert_eq!(matrix.secret, "correct horse battery staple");
+
+            let http = HttpConfig::extract(&figment)?;
+            assert_eq!(http.public_base.as_str(), "https://sso.example.com/");
+            assert_eq!(http.listeners.len(), 2);
+            assert_eq!(http.listeners[0].name.as_deref(), Some("web"));
+            assert_eq!(http.listeners[0].resources.len(), 1);
+            assert_eq!(http.listeners[1].name.as_deref(), Some("internal"));
+            assert_eq!(http.listeners[1].resources.len(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn out_of_bounds_index() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    upstream_oauth2:
+                      providers: []
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let overrides = EnvOverrides::from_vars(
+                &figment,
+                vars(&[("MAS_UPSTREAM_OAUTH2__PROVIDERS__1__ISSUER", "https://x/")]),
+            );
+            let error = figment.merge(overrides).extract::<Value>().unwrap_err();
+            assert!(error.to_string().contains("out of bounds"));
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Accept the previous names of the configuration options which were renamed
+//! or moved
+
+use figment::{
+    value::{Dict, Map, Value},
+    Error, Figment, Metadata, Profile, Provider,
+};
+use serde_yaml::Mapping;
+
+/// A configuration option which was renamed or moved to another section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenamedOption {
+    /// The previous path of the option, like `experimental.compat_token_ttl`
+    pub old: &'static str,
+
+    /// The current path of the option
+    pub new: &'static str,
+
+    /// The version in which the option was renamed. The previous path is
+    /// accepted until the next major version after this one.
+    pub since: &'static str,
+}
+
+/// All the configuration options which were renamed, and whose previous path
+/// is still accepted
+pub const RENAMED_OPTIONS: &[RenamedOption] = &[
+    RenamedOption {
+        old: "experimental.compat_token_ttl",
+        new: "compat.access_token_ttl",
+        since: "0.14.0",
+    },
+    RenamedOption {
+        old: "experimental.compat_session_ttl",
+        new: "compat.session_ttl",
+        since: "0.14.0",
+    },
+    RenamedOption {
+        old: "experimental.compat_session_inactivity_ttl",
+        new: "compat.session_inactivity_ttl",
+        since: "0.14.0",
+    },
+];
+
+impl RenamedOption {
+    /// The renamed options which are still set with their previous path in
+    /// the given configuration
+    #[must_use]
+    pub fn used_in(figment: &Figment) -> Vec<&'static Self> {
+        RENAMED_OPTIONS
+            .iter()
+            .filter(|option| figment.contains(option.old))
+            .collect()
+    }
+}
+
+/// A [`Provider`] which sets the renamed options from their previous path
+///
+/// It is meant to be joined to the configuration with [`Figment::join`], so
+/// that the options set with their current path take precedence.
+#[derive(Debug, Clone)]
+pub struct LegacyOptions {
+    patch: Dict,
+}
+
+impl LegacyOptions {
+    /// Find the renamed options set with their previous path in the given
+    /// configuration
+    #[must_use]
+    pub fn from_figment(base: &Figment) -> Self {
+        let mut patch = Dict::new();
+        for option in RENAMED_OPTIONS {
+            if let Ok(value) = base.find_value(option.old) {
+                insert(&mut patch, option.new, value);
+            }
+        }
+
+        Self { patch }
+    }
+}
+
+impl Provider for LegacyOptions {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("renamed configuration option(s)")
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, Error> {
+        Ok(Profile::Default.collect(self.patch.clone()))
+    }
+}
+
+/// Set the value at the given dotted path in a map, creating the maps on the
+/// way if needed
+fn insert(dict: &mut Dict, path: &str, value: Value) {
+    match path.split_once('.') {
+        None => {
+            dict.insert(path.to_owned(), value);
+        }
+        Some((key, rest)) => {
+            let node = dict
+                .entry(key.to_owned())
+                .or_insert_with(|| Value::from(Dict::new()));
+            if let Value::Dict(_, dict) = node {
+                insert(dict, rest, value);
+            }
+        }
+    }
+}
+
+/// A configuration file rewritten by [`upgrade_yaml`]
+#[derive(Debug)]
+pub struct UpgradedYaml {
+    /// The rewritten configuration file
+    pub yaml: String,
+
+    /// The options which were renamed in the file
+    pub renamed: Vec<&'static RenamedOption>,
+}
+
+/// Rewrite a YAML configuration file to use the current path of the renamed
+/// options
+///
+/// If an option is set with both its previous and its current path, the
+/// current one is kept, as it is the one which takes effect. Sections left
+/// empty are removed. The file is only reformatted if something was renamed,
+/// in which case comments are lost.
+///
+/// # Errors
+///
+/// Returns an error if the file is not valid YAML
+pub fn upgrade_yaml(source: &str) -> Result<UpgradedYaml, serde_yaml::Error> {
+    let mut root: serde_yaml::Value = serde_yaml::from_str(source)?;
+    let mut renamed = Vec::new();
+
+    if let Some(root) = root.as_mapping_mut() {
+        for option in RENAMED_OPTIONS {
+            let Some(value) = take(root, option.old) else {
+                continue;
+            };
+
+            if !contains(root, option.new) {
+                put(root, option.new, value);
+            }
+
+            renamed.push(option);
+        }
+    }
+
+    let yaml = if renamed.is_empty() {
+        source.to_owned()
+    } else {
+        serde_yaml::to_string(&root)?
+    };
+
+    Ok(UpgradedYaml { yaml, renamed })
+}
+
+/// Remove the value at the given dotted path, and the maps left empty
+fn take(node: &mut Mapping, path: &str) -> Option<serde_yaml::Value> {
+    match path.split_once('.') {
+        None => node.shift_remove(path),
+        Some((key, rest)) => {
+            let child = node.get_mut(key)?.as_mapping_mut()?;
+            let value = take(child, rest)?;
+            if child.is_empty() {
+                node.shift_remove(key);
+            }
+            Some(value)
+        }
+    }
+}
+
+fn contains(node: &Mapping, path: &str) -> bool {
+    match path.split_once('.') {
+        None => node.contains_key(path),
+        Some((key, rest)) => node
+            .get(key)
+            .and_then(serde_yaml::Value::as_mapping)
+            .is_some_and(|child| contains(child, rest)),
+    }
+}
+
+/// Set the value at the given dotted path, creating the maps on the way if
+/// needed
+fn put(node: &mut Mapping, path: &str, value: serde_yaml::Value) {
+    match path.split_once('.') {
+        None => {
+            node.insert(path.into(), value);
+        }
+        Some((key, rest)) => {
+            let child = node
+                .entry(key.into())
+                .or_insert_with(|| Mapping::new().into());
+            if !child.is_mapping() {
+                *child = Mapping::new().into();
+            }
+            if let Some(child) = child.as_mapping_mut() {
+                put(child, rest, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use figment::{
+        providers::{Format, Yaml},
+        Jail,
+    };
+
+    use super::*;
+    use crate::{CompatConfig, ConfigurationSection};
+
+    #[test]
+    fn accept_previous_names() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      compat_token_ttl: 600
+                      compat_session_ttl: 86400
+                    compat:
+                      session_ttl: 3600
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let used = RenamedOption::used_in(&figment);
+            assert_eq!(used, [&RENAMED_OPTIONS[0], &RENAMED_OPTIONS[1]]);
+
+            let legacy = LegacyOptions::from_figment(&figment);
+            let figment = figment.join(legacy);
+            let config = CompatConfig::extract(&figment)?;
+
+            assert_eq!(config.access_token_ttl, Duration::try_minutes(10).unwrap());
+            // The current name takes precedence
+            assert_eq!(config.session_ttl, Some(Duration::try_hours(1).unwrap()));
+            assert_eq!(config.session_inactivity_ttl, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn upgrade_file() {
+        let source = r"
+experimental:
+  compat_token_ttl: 600
+  compat_session_ttl: 86400
+compat:
+  session_ttl: 3600
+http:
+  public_base: https://auth.example.com/
+";
+
+        let upgraded = upgrade_yaml(source).unwrap();
+        assert_eq!(upgraded.renamed, [&RENAMED_OPTIONS[0], &RENAMED_OPTIONS[1]]);
+        assert_eq!(
+            upgraded.yaml,
+            "compat:
+  session_ttl: 3600
+  access_token_ttl: 600
+http:
+  public_base: https://auth.example.com/
+"
+        );
+
+        // Upgrading again changes nothing
+        let again = upgrade_yaml(&upgraded.yaml).unwrap();
+        assert!(again.renamed.is_empty());
+        assert_eq!(again.yaml, upgraded.yaml);
+    }
+}
@@ -14,11 +14,17 @@
 #[cfg(all(feature = "docker", feature = "dist"))]
 compile_error!("Only one of the `docker` and `dist` features can be enabled at once");
 
+mod effective;
+mod env;
+mod legacy;
 pub(crate) mod schema;
 mod sections;
 pub(crate) mod util;
 
 pub use self::{
+    effective::{EffectiveConfig, REDACTED},
+    env::{EnvOverrides, ENV_OVERRIDE_PREFIX, ENV_OVERRIDE_SEPARATOR},
+    legacy::{upgrade_yaml, LegacyOptions, RenamedOption, UpgradedYaml, RENAMED_OPTIONS},
     sections::*,
     util::{ConfigurationSection, ConfigurationSectionExt},
 };
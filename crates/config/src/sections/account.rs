@@ -4,8 +4,10 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use chrono::Duration;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 
 use crate::ConfigurationSection;
 
@@ -18,6 +20,14 @@ const fn is_default_true(value: &bool) -> bool {
     *value == default_true()
 }
 
+fn default_guest_inactivity_ttl() -> Duration {
+    Duration::days(7)
+}
+
+fn is_default_guest_inactivity_ttl(value: &Duration) -> bool {
+    *value == default_guest_inactivity_ttl()
+}
+
 const fn default_false() -> bool {
     false
 }
@@ -27,7 +37,29 @@ const fn is_default_false(value: &bool) -> bool {
     *value == default_false()
 }
 
+/// What happens when a user who reached the maximum number of sessions logs
+/// in again
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimitActionConfig {
+    /// Refuse the new login
+    #[default]
+    Refuse,
+
+    /// End the least recently active session of the user, and delete its
+    /// device, to make room for the new one
+    Evict,
+}
+
+impl SessionLimitActionConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// Configuration section to configure features related to account management
+#[serde_as]
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
 pub struct AccountConfig {
@@ -61,6 +93,92 @@ pub struct AccountConfig {
     /// This has no effect if password login is disabled.
     #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
     pub password_recovery_enabled: bool,
+
+    /// How long, in seconds, users keep their admin privileges after they
+    /// authenticated. Once elapsed, they have to authenticate again to use
+    /// the GraphQL and admin APIs as an admin. Defaults to no limit.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub admin_elevation_ttl: Option<Duration>,
+
+    /// How long, in seconds, the consent users give to clients is remembered.
+    /// Once elapsed, users have to consent again the next time the client asks
+    /// for authorization. Defaults to no limit.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub consent_ttl: Option<Duration>,
+
+    /// How often, in seconds, users who opted in receive an email digest of
+    /// their account activity: new sessions, new client authorizations and
+    /// security-relevant changes. Defaults to no digest, in which case users
+    /// can't opt in.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub activity_digest_interval: Option<Duration>,
+
+    /// Whether users are notified by email when they sign in from a browser or
+    /// device they never used before. Defaults to `false`.
+    ///
+    /// Users can still opt out of those emails from their account settings.
+    #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
+    pub new_device_emails: bool,
+
+    /// Maximum number of OAuth 2.0 and compatibility sessions each user can
+    /// have at the same time. Defaults to no limit.
+    #[schemars(range(min = 1))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sessions_per_user: Option<u32>,
+
+    /// What happens when a user who reached `max_sessions_per_user` logs in
+    /// again. Defaults to `refuse`.
+    #[serde(default, skip_serializing_if = "SessionLimitActionConfig::is_default")]
+    pub session_limit_action: SessionLimitActionConfig,
+
+    /// Whether users have to accept the current terms of service before
+    /// continuing after they log in, or before authorizing a client. Defaults
+    /// to `false`.
+    ///
+    /// The URL of the terms, set in `branding.tos_uri`, identifies their
+    /// version: to ask everyone to accept new terms, publish them under a new
+    /// URL, for example by adding a version query parameter. This has no
+    /// effect if `branding.tos_uri` is not set.
+    #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
+    pub terms_acceptance_required: bool,
+
+    /// How long, in seconds, accounts are kept after users asked for them to
+    /// be deleted. Defaults to none, in which case users can't delete their
+    /// account themselves.
+    ///
+    /// The account is locked as soon as the user asks for its deletion, and is
+    /// erased, both locally and on the homeserver, once this grace period
+    /// elapsed. Logging back in before then cancels the deletion.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub deletion_grace_period: Option<Duration>,
+
+    /// Whether clients can register guest accounts through the compatibility
+    /// layer, with `/_matrix/client/v3/register?kind=guest`. Defaults to
+    /// `false`.
+    ///
+    /// Guests get a generated username and no credentials, and their sessions
+    /// only have access to the endpoints the homeserver opens to guests.
+    #[serde(default = "default_false", skip_serializing_if = "is_default_false")]
+    pub guest_registration_enabled: bool,
+
+    /// How long, in seconds, guest accounts are kept once none of their
+    /// sessions were used. Stale guests are deactivated, both locally and on
+    /// the homeserver. Defaults to 7 days.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_guest_inactivity_ttl",
+        skip_serializing_if = "is_default_guest_inactivity_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub guest_inactivity_ttl: Duration,
 }
 
 impl Default for AccountConfig {
@@ -71,6 +189,16 @@ impl Default for AccountConfig {
             password_registration_enabled: default_false(),
             password_change_allowed: default_true(),
             password_recovery_enabled: default_false(),
+            admin_elevation_ttl: None,
+            consent_ttl: None,
+            activity_digest_interval: None,
+            new_device_emails: default_false(),
+            max_sessions_per_user: None,
+            session_limit_action: SessionLimitActionConfig::default(),
+            terms_acceptance_required: default_false(),
+            deletion_grace_period: None,
+            guest_registration_enabled: default_false(),
+            guest_inactivity_ttl: default_guest_inactivity_ttl(),
         }
     }
 }
@@ -83,9 +211,81 @@ impl AccountConfig {
             && is_default_true(&self.displayname_change_allowed)
             && is_default_true(&self.password_change_allowed)
             && is_default_false(&self.password_recovery_enabled)
+            && self.admin_elevation_ttl.is_none()
+            && self.consent_ttl.is_none()
+            && self.activity_digest_interval.is_none()
+            && is_default_false(&self.new_device_emails)
+            && self.max_sessions_per_user.is_none()
+            && self.session_limit_action.is_default()
+            && is_default_false(&self.terms_acceptance_required)
+            && self.deletion_grace_period.is_none()
+            && is_default_false(&self.guest_registration_enabled)
+            && is_default_guest_inactivity_ttl(&self.guest_inactivity_ttl)
     }
 }
 
 impl ConfigurationSection for AccountConfig {
     const PATH: Option<&'static str> = Some("account");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let annotate = |field: &str, message: &str| {
+            let mut error = figment::Error::from(message.to_owned());
+            error.metadata = figment
+                .find_metadata(&format!("{root}.{field}", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            Err(error)
+        };
+
+        if self
+            .admin_elevation_ttl
+            .is_some_and(|ttl| ttl < Duration::minutes(1))
+        {
+            return annotate(
+                "admin_elevation_ttl",
+                "The admin elevation TTL must be at least one minute",
+            );
+        }
+
+        if self.consent_ttl.is_some_and(|ttl| ttl < Duration::days(1)) {
+            return annotate("consent_ttl", "The consent TTL must be at least one day");
+        }
+
+        if self
+            .activity_digest_interval
+            .is_some_and(|interval| interval < Duration::hours(1))
+        {
+            return annotate(
+                "activity_digest_interval",
+                "The activity digest interval must be at least one hour",
+            );
+        }
+
+        if self
+            .deletion_grace_period
+            .is_some_and(|period| period < Duration::hours(1))
+        {
+            return annotate(
+                "deletion_grace_period",
+                "The account deletion grace period must be at least one hour",
+            );
+        }
+
+        if self.guest_inactivity_ttl < Duration::hours(1) {
+            return annotate(
+                "guest_inactivity_ttl",
+                "The guest inactivity TTL must be at least one hour",
+            );
+        }
+
+        if self.max_sessions_per_user == Some(0) {
+            return annotate(
+                "max_sessions_per_user",
+                "The maximum number of sessions per user must be at least one",
+            );
+        }
+
+        Ok(())
+    }
 }
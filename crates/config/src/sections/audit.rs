@@ -0,0 +1,45 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ConfigurationSection;
+
+/// Configuration section for the audit log
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct AuditConfig {
+    /// How long audit events are kept, in seconds. Events older than this are
+    /// periodically deleted.
+    ///
+    /// Defaults to keeping events forever.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub retention: Option<Duration>,
+
+    /// Email addresses which are alerted when a honeytoken is used
+    ///
+    /// Honeytokens are decoy tokens and accounts planted through the admin
+    /// API. Their use is always recorded in the audit log and sent to the
+    /// webhook endpoints.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub honeytoken_alert_emails: Vec<String>,
+}
+
+impl AuditConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.retention.is_none() && self.honeytoken_alert_emails.is_empty()
+    }
+}
+
+impl ConfigurationSection for AuditConfig {
+    const PATH: Option<&'static str> = Some("audit");
+}
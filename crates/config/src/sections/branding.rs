@@ -4,12 +4,33 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeMap;
+
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Serialize};
 use url::Url;
 
 use crate::ConfigurationSection;
 
+/// Whether the name is a valid Compound color token, like
+/// `bg-action-primary-rest`
+fn is_valid_color_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('-').all(|part| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        })
+}
+
+/// Whether the value is a hexadecimal CSS color, like `#0dbd8b`
+fn is_valid_color(value: &str) -> bool {
+    value.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 4 | 6 | 8) && hex.bytes().all(|b| b.is_ascii_hexdigit())
+    })
+}
+
 /// Configuration section for tweaking the branding of the service
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Default)]
 pub struct BrandingConfig {
@@ -37,6 +58,14 @@ pub struct BrandingConfig {
     /// Logo displayed in some web pages.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logo_uri: Option<Url>,
+
+    /// Colors of the web pages, replacing the ones of the default theme.
+    ///
+    /// Keys are the names of the color tokens of the Compound design system,
+    /// without the `--cpd-color-` prefix, like `bg-action-primary-rest`, and
+    /// values are hexadecimal colors, like `#0dbd8b`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub colors: BTreeMap<String, String>,
 }
 
 impl BrandingConfig {
@@ -47,9 +76,99 @@ impl BrandingConfig {
             && self.tos_uri.is_none()
             && self.imprint.is_none()
             && self.logo_uri.is_none()
+            && self.colors.is_empty()
     }
 }
 
 impl ConfigurationSection for BrandingConfig {
     const PATH: Option<&'static str> = Some("branding");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        for (name, value) in &self.colors {
+            let message = if !is_valid_color_name(name) {
+                format!("Invalid color name {name:?}")
+            } else if !is_valid_color(value) {
+                format!("Invalid color {value:?}, expected a hexadecimal color like \"#0dbd8b\"")
+            } else {
+                continue;
+            };
+
+            let mut error = figment::Error::custom(message);
+            error.metadata = figment
+                .find_metadata(&format!("{root}.colors", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "colors".to_owned(),
+                name.clone(),
+            ];
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r##"
+                    branding:
+                      service_name: Example
+                      logo_uri: https://example.com/logo.png
+                      colors:
+                        bg-action-primary-rest: "#0dbd8b"
+                        text-primary: "#FFF"
+                "##,
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<BrandingConfig>("branding")?;
+
+            assert_eq!(config.service_name.as_deref(), Some("Example"));
+            assert_eq!(config.colors.len(), 2);
+            assert_eq!(config.colors["text-primary"], "#FFF");
+            assert!(config.validate(&Figment::new()).is_ok());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_colors() {
+        for (name, value) in [
+            ("", "#fff"),
+            ("--cpd-color-text-primary", "#fff"),
+            ("text_primary", "#fff"),
+            ("Text-Primary", "#fff"),
+            ("text-primary", "fff"),
+            ("text-primary", "#ff"),
+            ("text-primary", "#ggg"),
+            ("text-primary", "red"),
+            ("text-primary", "#fff; background: url(x)"),
+        ] {
+            let config = BrandingConfig {
+                colors: BTreeMap::from([(name.to_owned(), value.to_owned())]),
+                ..BrandingConfig::default()
+            };
+
+            assert!(
+                config.validate(&Figment::new()).is_err(),
+                "{name:?}: {value:?}"
+            );
+        }
+    }
 }
@@ -0,0 +1,107 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ConfigurationSection;
+
+const fn default_max_entries() -> usize {
+    10_000
+}
+
+fn is_default_max_entries(value: &usize) -> bool {
+    *value == default_max_entries()
+}
+
+const fn default_client_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn is_default_client_ttl(value: &Duration) -> bool {
+    *value == default_client_ttl()
+}
+
+const fn default_access_token_ttl() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn is_default_access_token_ttl(value: &Duration) -> bool {
+    *value == default_access_token_ttl()
+}
+
+/// Configuration section for the in-process cache of hot database lookups
+///
+/// When enabled, OAuth 2.0 clients and access tokens are kept in memory for a
+/// short time, to avoid querying the database on every token introspection.
+/// Changes made through this instance are invalidated as soon as they are
+/// committed, but changes made by other instances are only picked up once the
+/// TTL expires.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CacheConfig {
+    /// Whether the cache is enabled. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Maximum number of entries to keep for each kind of record. Defaults to
+    /// 10000.
+    #[serde(
+        default = "default_max_entries",
+        skip_serializing_if = "is_default_max_entries"
+    )]
+    pub max_entries: usize,
+
+    /// How long OAuth 2.0 clients are kept in the cache, in seconds. Defaults
+    /// to 60 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_client_ttl",
+        skip_serializing_if = "is_default_client_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub client_ttl: Duration,
+
+    /// How long access tokens are kept in the cache, in seconds. Defaults to 10
+    /// seconds.
+    ///
+    /// A token revoked by another instance may still be accepted for that
+    /// long.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_access_token_ttl",
+        skip_serializing_if = "is_default_access_token_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub access_token_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: default_max_entries(),
+            client_ttl: default_client_ttl(),
+            access_token_ttl: default_access_token_ttl(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled
+            && is_default_max_entries(&self.max_entries)
+            && is_default_client_ttl(&self.client_ttl)
+            && is_default_access_token_ttl(&self.access_token_ttl)
+    }
+}
+
+impl ConfigurationSection for CacheConfig {
+    const PATH: Option<&'static str> = Some("cache");
+}
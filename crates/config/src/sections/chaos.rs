@@ -0,0 +1,152 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ConfigurationSection;
+
+/// Fault injection settings for a single kind of outbound call
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq)]
+pub struct FaultInjectionConfig {
+    /// Probability, between 0 and 1, of adding latency to a call
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[schemars(range(min = 0.0, max = 1.0))]
+    pub latency_rate: f64,
+
+    /// Maximum latency to add to a call, in milliseconds. The actual latency
+    /// is picked uniformly between 0 and this value.
+    #[schemars(with = "u64")]
+    #[serde(default, skip_serializing_if = "Duration::is_zero")]
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub max_latency: Duration,
+
+    /// Probability, between 0 and 1, of making a call fail
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[schemars(range(min = 0.0, max = 1.0))]
+    pub error_rate: f64,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+impl FaultInjectionConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Configuration section to inject faults in outbound calls
+///
+/// This is meant to validate monitoring and alerting in staging deployments,
+/// and must never be enabled in production.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct ChaosConfig {
+    /// Whether fault injection is enabled. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Faults to inject in calls to the homeserver
+    #[serde(default, skip_serializing_if = "FaultInjectionConfig::is_default")]
+    pub homeserver: FaultInjectionConfig,
+
+    /// Faults to inject when acquiring a database connection
+    #[serde(default, skip_serializing_if = "FaultInjectionConfig::is_default")]
+    pub database: FaultInjectionConfig,
+
+    /// Faults to inject when sending emails
+    #[serde(default, skip_serializing_if = "FaultInjectionConfig::is_default")]
+    pub email: FaultInjectionConfig,
+}
+
+impl ChaosConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled
+            && self.homeserver.is_default()
+            && self.database.is_default()
+            && self.email.is_default()
+    }
+}
+
+impl ConfigurationSection for ChaosConfig {
+    const PATH: Option<&'static str> = Some("chaos");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let metadata = figment.find_metadata(Self::PATH.unwrap());
+
+        let error_on_field = |mut error: figment::error::Error, field: &'static str| {
+            error.metadata = metadata.cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            error
+        };
+
+        for (field, config) in [
+            ("homeserver", &self.homeserver),
+            ("database", &self.database),
+            ("email", &self.email),
+        ] {
+            for rate in [config.latency_rate, config.error_rate] {
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err(error_on_field(
+                        figment::error::Error::from(format!(
+                            "fault injection rates must be between 0 and 1, got {rate}"
+                        )),
+                        field,
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    chaos:
+                      enabled: true
+                      homeserver:
+                        latency_rate: 0.5
+                        max_latency: 2000
+                      email:
+                        error_rate: 0.1
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<ChaosConfig>("chaos")?;
+
+            assert!(config.enabled);
+            assert!((config.homeserver.latency_rate - 0.5).abs() < f64::EPSILON);
+            assert_eq!(config.homeserver.max_latency, Duration::from_secs(2));
+            assert!((config.email.error_rate - 0.1).abs() < f64::EPSILON);
+            assert!(config.database.is_default());
+
+            Ok(())
+        });
+    }
+}
@@ -6,11 +6,13 @@
 
 use std::ops::Deref;
 
+use chrono::Duration;
 use figment::Figment;
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
 use mas_jose::jwk::PublicJsonWebKeySet;
 use schemars::JsonSchema;
 use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
 use ulid::Ulid;
 use url::Url;
 
@@ -51,6 +53,14 @@ pub enum ClientAuthMethodConfig {
     /// `client_secret_basic`: a `client_assertion` sent in the request body and
     /// signed by an asymmetric key
     PrivateKeyJwt,
+
+    /// `tls_client_auth`: a client certificate issued by a trusted certificate
+    /// authority, with the expected subject
+    TlsClientAuth,
+
+    /// `self_signed_tls_client_auth`: a self-signed client certificate,
+    /// registered in the client JWKS
+    SelfSignedTlsClientAuth,
 }
 
 impl std::fmt::Display for ClientAuthMethodConfig {
@@ -61,11 +71,16 @@ impl std::fmt::Display for ClientAuthMethodConfig {
             ClientAuthMethodConfig::ClientSecretPost => write!(f, "client_secret_post"),
             ClientAuthMethodConfig::ClientSecretJwt => write!(f, "client_secret_jwt"),
             ClientAuthMethodConfig::PrivateKeyJwt => write!(f, "private_key_jwt"),
+            ClientAuthMethodConfig::TlsClientAuth => write!(f, "tls_client_auth"),
+            ClientAuthMethodConfig::SelfSignedTlsClientAuth => {
+                write!(f, "self_signed_tls_client_auth")
+            }
         }
     }
 }
 
 /// An OAuth 2.0 client configuration
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ClientConfig {
     /// The client ID
@@ -84,30 +99,94 @@ pub struct ClientConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_secret: Option<String>,
 
-    /// The JSON Web Key Set (JWKS) used by the `private_key_jwt` authentication
-    /// method. Mutually exclusive with `jwks_uri`
+    /// The JSON Web Key Set (JWKS) used by the `private_key_jwt` and
+    /// `self_signed_tls_client_auth` authentication methods. Mutually
+    /// exclusive with `jwks_uri`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwks: Option<PublicJsonWebKeySet>,
 
-    /// The URL of the JSON Web Key Set (JWKS) used by the `private_key_jwt`
-    /// authentication method. Mutually exclusive with `jwks`
+    /// The URL of the JSON Web Key Set (JWKS) used by the `private_key_jwt` and
+    /// `self_signed_tls_client_auth` authentication methods. Mutually
+    /// exclusive with `jwks`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwks_uri: Option<Url>,
 
     /// List of allowed redirect URIs
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub redirect_uris: Vec<Url>,
+
+    /// Whether the client must use pushed authorization requests to start an
+    /// authorization grant. Plain requests to the authorization endpoint are
+    /// then rejected for this client
+    #[serde(default)]
+    pub require_pushed_authorization_requests: bool,
+
+    /// The expected subject distinguished name of the client certificate, as
+    /// described in RFC 4514, used by the `tls_client_auth` authentication
+    /// method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_client_auth_subject_dn: Option<String>,
+
+    /// Whether the access tokens issued to the client are bound to the client
+    /// certificate it presented on the token endpoint
+    #[serde(default)]
+    pub tls_client_certificate_bound_access_tokens: bool,
+
+    /// Whether the access tokens issued to the client are signed JWTs, as
+    /// described in RFC 9068, instead of opaque tokens. This lets resource
+    /// servers validate them without calling the introspection endpoint
+    #[serde(default)]
+    pub jwt_access_tokens: bool,
+
+    /// Time-to-live of the access tokens issued to the client, in seconds.
+    /// Defaults to `experimental.access_token_ttl`
+    #[schemars(with = "Option<u64>", range(min = 60, max = 86400))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub access_token_ttl: Option<Duration>,
+
+    /// How long the refresh tokens issued to the client can be used, in
+    /// seconds. Defaults to `experimental.refresh_token_ttl`
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// Maximum lifetime of the sessions of the client, in seconds. Defaults to
+    /// `experimental.session_ttl`
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub session_ttl: Option<Duration>,
+
+    /// How long the sessions of the client can stay inactive before they are
+    /// ended, in seconds. Defaults to `experimental.session_inactivity_ttl`
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub session_inactivity_ttl: Option<Duration>,
 }
 
 impl ClientConfig {
     fn validate(&self) -> Result<(), figment::error::Error> {
         let auth_method = self.client_auth_method;
+
+        if self.tls_client_auth_subject_dn.is_some()
+            && !matches!(auth_method, ClientAuthMethodConfig::TlsClientAuth)
+        {
+            let error = figment::error::Error::custom(format!(
+                "tls_client_auth_subject_dn is not allowed with {auth_method}"
+            ));
+            return Err(error.with_path("tls_client_auth_subject_dn"));
+        }
+
         match self.client_auth_method {
-            ClientAuthMethodConfig::PrivateKeyJwt => {
+            ClientAuthMethodConfig::PrivateKeyJwt
+            | ClientAuthMethodConfig::SelfSignedTlsClientAuth => {
                 if self.jwks.is_none() && self.jwks_uri.is_none() {
-                    let error = figment::error::Error::custom(
-                        "jwks or jwks_uri is required for private_key_jwt",
-                    );
+                    let error = figment::error::Error::custom(format!(
+                        "jwks or jwks_uri is required for {auth_method}"
+                    ));
                     return Err(error.with_path("client_auth_method"));
                 }
 
@@ -117,12 +196,41 @@ impl ClientConfig {
                     return Err(error.with_path("jwks"));
                 }
 
+                if self.client_secret.is_some() {
+                    let error = figment::error::Error::custom(format!(
+                        "client_secret is not allowed with {auth_method}"
+                    ));
+                    return Err(error.with_path("client_secret"));
+                }
+            }
+
+            ClientAuthMethodConfig::TlsClientAuth => {
+                if self.tls_client_auth_subject_dn.is_none() {
+                    let error = figment::error::Error::custom(
+                        "tls_client_auth_subject_dn is required for tls_client_auth",
+                    );
+                    return Err(error.with_path("client_auth_method"));
+                }
+
                 if self.client_secret.is_some() {
                     let error = figment::error::Error::custom(
-                        "client_secret is not allowed with private_key_jwt",
+                        "client_secret is not allowed with tls_client_auth",
                     );
                     return Err(error.with_path("client_secret"));
                 }
+
+                if self.jwks.is_some() {
+                    let error =
+                        figment::error::Error::custom("jwks is not allowed with tls_client_auth");
+                    return Err(error.with_path("jwks"));
+                }
+
+                if self.jwks_uri.is_some() {
+                    let error = figment::error::Error::custom(
+                        "jwks_uri is not allowed with tls_client_auth",
+                    );
+                    return Err(error.with_path("jwks_uri"));
+                }
             }
 
             ClientAuthMethodConfig::ClientSecretPost
@@ -192,6 +300,10 @@ impl ClientConfig {
                 OAuthClientAuthenticationMethod::ClientSecretJwt
             }
             ClientAuthMethodConfig::PrivateKeyJwt => OAuthClientAuthenticationMethod::PrivateKeyJwt,
+            ClientAuthMethodConfig::TlsClientAuth => OAuthClientAuthenticationMethod::TlsClientAuth,
+            ClientAuthMethodConfig::SelfSignedTlsClientAuth => {
+                OAuthClientAuthenticationMethod::SelfSignedTlsClientAuth
+            }
         }
     }
 }
@@ -296,6 +408,14 @@ mod tests {
                           use: "sig"
                           e: "AQAB"
                           n: "0hukqytPwrj1RbMYhYoepCi3CN5k7DwYkTe_Cmb7cP9_qv4ok78KdvFXt5AnQxCRwBD7-qTNkkfMWO2RxUMBdQD0ED6tsSb1n5dp0XY8dSWiBDCX8f6Hr-KolOpvMLZKRy01HdAWcM6RoL9ikbjYHUEW1C8IJnw3MzVHkpKFDL354aptdNLaAdTCBvKzU9WpXo10g-5ctzSlWWjQuecLMQ4G1mNdsR1LHhUENEnOvgT8cDkX0fJzLbEbyBYkdMgKggyVPEB1bg6evG4fTKawgnf0IDSPxIU-wdS9wdSP9ZCJJPLi5CEp-6t6rE_sb2dGcnzjCGlembC57VwpkUvyMw"
+
+                    - client_id: 01JAMX5QY1DJ4V6W3HKS7YF3XZ
+                      client_auth_method: tls_client_auth
+                      tls_client_auth_subject_dn: "CN=backend.example.com,O=Example"
+                      tls_client_certificate_bound_access_tokens: true
+                      jwt_access_tokens: true
+                      access_token_ttl: 120
+                      session_inactivity_ttl: 604800
                 "#,
             )?;
 
@@ -303,7 +423,7 @@ mod tests {
                 .merge(Yaml::file("config.yaml"))
                 .extract_inner::<ClientsConfig>("clients")?;
 
-            assert_eq!(config.0.len(), 5);
+            assert_eq!(config.0.len(), 6);
 
             assert_eq!(
                 config.0[0].client_id,
@@ -320,6 +440,41 @@ mod tests {
             );
             assert_eq!(config.0[1].redirect_uris, Vec::new());
 
+            assert_eq!(
+                config.0[5].client_auth_method(),
+                OAuthClientAuthenticationMethod::TlsClientAuth
+            );
+            assert_eq!(
+                config.0[5].tls_client_auth_subject_dn.as_deref(),
+                Some("CN=backend.example.com,O=Example")
+            );
+            assert!(config.0[5].tls_client_certificate_bound_access_tokens);
+            assert!(config.0[5].jwt_access_tokens);
+            assert!(!config.0[0].jwt_access_tokens);
+            assert_eq!(config.0[5].access_token_ttl, Some(Duration::minutes(2)));
+            assert_eq!(config.0[5].refresh_token_ttl, None);
+            assert_eq!(config.0[5].session_ttl, None);
+            assert_eq!(config.0[5].session_inactivity_ttl, Some(Duration::days(7)));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_tls_client_auth_without_subject_dn() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                  clients:
+                    - client_id: 01JAMX5QY1DJ4V6W3HKS7YF3XZ
+                      client_auth_method: tls_client_auth
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(ClientsConfig::extract(&figment).is_err());
+
             Ok(())
         });
     }
@@ -0,0 +1,407 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
+
+use crate::ConfigurationSection;
+
+fn default_access_token_ttl() -> Duration {
+    Duration::microseconds(5 * 60 * 1000 * 1000)
+}
+
+fn is_default_access_token_ttl(value: &Duration) -> bool {
+    *value == default_access_token_ttl()
+}
+
+/// An endpoint of the Matrix Client-Server API compatibility layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompatEndpointConfig {
+    /// `/_matrix/client/v3/login`
+    Login,
+
+    /// `/_matrix/client/v3/logout`
+    Logout,
+
+    /// `/_matrix/client/v3/refresh`
+    Refresh,
+
+    /// `/_matrix/client/v3/register`
+    Register,
+
+    /// `/_matrix/client/v3/login/sso/redirect`
+    LoginSsoRedirect,
+}
+
+/// A login type of the Matrix Client-Server API compatibility layer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum CompatLoginTypeConfig {
+    /// `m.login.password`
+    #[serde(rename = "m.login.password")]
+    Password,
+
+    /// `m.login.token`, used to complete SSO logins
+    #[serde(rename = "m.login.token")]
+    Token,
+}
+
+/// Advertise the deprecation of compatibility endpoints to clients
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct CompatDeprecationConfig {
+    /// The endpoints which are deprecated. Defaults to all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<CompatEndpointConfig>,
+
+    /// When the endpoints were or will be deprecated, advertised in the
+    /// `Deprecation` header
+    pub deprecated_at: DateTime<Utc>,
+
+    /// When the endpoints will stop working, advertised in the `Sunset` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sunset_at: Option<DateTime<Utc>>,
+
+    /// A link to a page documenting the deprecation, advertised in the `Link`
+    /// header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<Url>,
+}
+
+/// Disable a login type from a given date
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct CompatDisabledLoginTypeConfig {
+    /// The login type to disable
+    #[serde(rename = "type")]
+    pub login_type: CompatLoginTypeConfig,
+
+    /// From when the login type is disabled
+    pub after: DateTime<Utc>,
+}
+
+/// A namespace of users owned by an application service
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AppserviceNamespaceConfig {
+    /// Regular expression matching the full Matrix IDs of the users in this
+    /// namespace
+    pub regex: String,
+
+    /// Whether the application service is the only one allowed to manage
+    /// those users
+    #[serde(default)]
+    pub exclusive: bool,
+}
+
+/// The namespaces of an application service
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct AppserviceNamespacesConfig {
+    /// The users the application service can log in as
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<AppserviceNamespaceConfig>,
+}
+
+/// The registration of an application service, in the same format as the
+/// registration file given to the homeserver. Fields which are not used by
+/// the service are ignored.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AppserviceRegistrationConfig {
+    /// A unique identifier for the application service
+    pub id: String,
+
+    /// The token the application service uses to authenticate its requests
+    pub as_token: String,
+
+    /// The localpart of the user the application service acts as by default
+    pub sender_localpart: String,
+
+    /// The namespaces the application service is interested in
+    #[serde(default)]
+    pub namespaces: AppserviceNamespacesConfig,
+}
+
+/// Configuration section for the Matrix Client-Server API compatibility layer
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CompatConfig {
+    /// Time-to-live of compatibility access tokens in seconds, when refresh
+    /// tokens are supported. Defaults to 5 minutes.
+    #[schemars(with = "u64", range(min = 60, max = 86400))]
+    #[serde(
+        default = "default_access_token_ttl",
+        skip_serializing_if = "is_default_access_token_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub access_token_ttl: Duration,
+
+    /// Maximum lifetime, in seconds, of compatibility sessions. Once elapsed,
+    /// the session is ended and its device removed from the homeserver.
+    /// Defaults to no limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub session_ttl: Option<Duration>,
+
+    /// How long, in seconds, compatibility sessions can stay inactive before
+    /// they are ended. Defaults to no limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub session_inactivity_ttl: Option<Duration>,
+
+    /// Advertise the deprecation of some endpoints with the `Deprecation`,
+    /// `Sunset` and `Link` headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecation: Option<CompatDeprecationConfig>,
+
+    /// Login types which are disabled from a given date
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_login_types: Vec<CompatDisabledLoginTypeConfig>,
+
+    /// Application services which can log in as the users in their namespace
+    /// with the `m.login.application_service` login type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub appservices: Vec<AppserviceRegistrationConfig>,
+
+    /// Paths to the registration files of other application services, as
+    /// given to the homeserver
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub appservice_registration_files: Vec<Utf8PathBuf>,
+}
+
+impl Default for CompatConfig {
+    fn default() -> Self {
+        Self {
+            access_token_ttl: default_access_token_ttl(),
+            session_ttl: None,
+            session_inactivity_ttl: None,
+            deprecation: None,
+            disabled_login_types: Vec::new(),
+            appservices: Vec::new(),
+            appservice_registration_files: Vec::new(),
+        }
+    }
+}
+
+impl CompatConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        is_default_access_token_ttl(&self.access_token_ttl)
+            && self.session_ttl.is_none()
+            && self.session_inactivity_ttl.is_none()
+            && self.deprecation.is_none()
+            && self.disabled_login_types.is_empty()
+            && self.appservices.is_empty()
+            && self.appservice_registration_files.is_empty()
+    }
+
+    /// Load the registrations of the application services, both the inline
+    /// ones and the ones from the registration files
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a registration file could not be read or parsed, or
+    /// if multiple application services have the same ID or token
+    pub fn load_appservices(&self) -> Result<Vec<AppserviceRegistrationConfig>, anyhow::Error> {
+        let mut appservices = self.appservices.clone();
+
+        for path in &self.appservice_registration_files {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read the registration file {path}"))?;
+            let registration: AppserviceRegistrationConfig = serde_yaml::from_str(&contents)
+                .with_context(|| format!("invalid registration file {path}"))?;
+            appservices.push(registration);
+        }
+
+        for (index, appservice) in appservices.iter().enumerate() {
+            if appservices[..index]
+                .iter()
+                .any(|other| other.id == appservice.id)
+            {
+                anyhow::bail!(
+                    "Multiple application services have the ID {:?}",
+                    appservice.id
+                );
+            }
+
+            if appservices[..index]
+                .iter()
+                .any(|other| other.as_token == appservice.as_token)
+            {
+                anyhow::bail!(
+                    "Application service {:?} has the same token as another one",
+                    appservice.id
+                );
+            }
+        }
+
+        Ok(appservices)
+    }
+}
+
+impl ConfigurationSection for CompatConfig {
+    const PATH: Option<&'static str> = Some("compat");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let annotate = |mut error: figment::Error, field: &str| {
+            error.metadata = figment
+                .find_metadata(&format!("{root}.{field}", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            Err(error)
+        };
+
+        if let Some(deprecation) = &self.deprecation {
+            if deprecation
+                .sunset_at
+                .is_some_and(|sunset_at| sunset_at < deprecation.deprecated_at)
+            {
+                return annotate(
+                    figment::Error::custom("The sunset date must be after the deprecation date"),
+                    "deprecation",
+                );
+            }
+        }
+
+        for (index, disabled) in self.disabled_login_types.iter().enumerate() {
+            if self.disabled_login_types[..index]
+                .iter()
+                .any(|other| other.login_type == disabled.login_type)
+            {
+                return annotate(
+                    figment::Error::custom("Login types can only be disabled once"),
+                    "disabled_login_types",
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    compat:
+                      deprecation:
+                        endpoints: [login, refresh]
+                        deprecated_at: 2024-11-01T00:00:00Z
+                        sunset_at: 2025-06-01T00:00:00Z
+                        link: https://example.com/migration
+                      disabled_login_types:
+                        - type: m.login.password
+                          after: 2025-06-01T00:00:00Z
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = CompatConfig::extract(&figment)?;
+
+            let deprecation = config.deprecation.unwrap();
+            assert_eq!(
+                deprecation.endpoints,
+                [CompatEndpointConfig::Login, CompatEndpointConfig::Refresh]
+            );
+            assert!(deprecation.sunset_at.is_some());
+            assert_eq!(config.disabled_login_types.len(), 1);
+            assert_eq!(
+                config.disabled_login_types[0].login_type,
+                CompatLoginTypeConfig::Password
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_appservices() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    compat:
+                      appservices:
+                        - id: irc
+                          as_token: irc-token
+                          sender_localpart: _irc_bot
+                          namespaces:
+                            users:
+                              - regex: '@_irc_.*:example\.com'
+                                exclusive: true
+                      appservice_registration_files:
+                        - whatsapp.yaml
+                ",
+            )?;
+
+            jail.create_file(
+                "whatsapp.yaml",
+                r"
+                    id: whatsapp
+                    url: http://localhost:29318
+                    as_token: whatsapp-token
+                    hs_token: whatsapp-hs-token
+                    sender_localpart: whatsappbot
+                    rate_limited: false
+                    namespaces:
+                      users:
+                        - regex: '@whatsapp_.*:example\.com'
+                          exclusive: true
+                      aliases: []
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = CompatConfig::extract(&figment)?;
+
+            let appservices = config.load_appservices().unwrap();
+            assert_eq!(appservices.len(), 2);
+            assert_eq!(appservices[0].id, "irc");
+            assert_eq!(appservices[1].id, "whatsapp");
+            assert_eq!(appservices[1].as_token, "whatsapp-token");
+            assert_eq!(appservices[1].sender_localpart, "whatsappbot");
+            assert_eq!(appservices[1].namespaces.users.len(), 1);
+            assert!(appservices[1].namespaces.users[0].exclusive);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_sunset_before_deprecation() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    compat:
+                      deprecation:
+                        deprecated_at: 2025-06-01T00:00:00Z
+                        sunset_at: 2024-11-01T00:00:00Z
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(CompatConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+}
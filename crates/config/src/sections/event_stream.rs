@@ -0,0 +1,166 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use url::Url;
+
+use crate::ConfigurationSection;
+
+/// Publish events to a Kafka topic
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct KafkaEventStreamConfig {
+    /// List of `host:port` pairs of the Kafka brokers to bootstrap from
+    pub brokers: Vec<String>,
+
+    /// The topic to which events are published
+    ///
+    /// Messages are keyed by the ID of the user they relate to, so that events
+    /// for the same user end up in the same partition.
+    pub topic: String,
+}
+
+/// Publish events to a NATS JetStream subject
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct NatsEventStreamConfig {
+    /// URL of the NATS server, e.g. `nats://localhost:4222`
+    pub url: Url,
+
+    /// The subject prefix to which events are published
+    ///
+    /// The kind of event is appended to it, so that events are published to
+    /// subjects like `mas.events.user.registered`. A JetStream stream must be
+    /// configured to capture those subjects.
+    pub subject: String,
+}
+
+/// Configuration section for publishing authentication events to an event
+/// streaming platform
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct EventStreamConfig {
+    /// Publish events to a Kafka topic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kafka: Option<KafkaEventStreamConfig>,
+
+    /// Publish events to a NATS JetStream subject
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nats: Option<NatsEventStreamConfig>,
+}
+
+impl EventStreamConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.kafka.is_none() && self.nats.is_none()
+    }
+}
+
+impl ConfigurationSection for EventStreamConfig {
+    const PATH: Option<&'static str> = Some("event_stream");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let annotate = |mut error: figment::Error, field: &str| {
+            error.metadata = figment
+                .find_metadata(&format!("{root}.{field}", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            Err(error)
+        };
+
+        if self.kafka.is_some() && self.nats.is_some() {
+            return annotate(
+                figment::Error::custom("Only one of `kafka` and `nats` can be configured"),
+                "nats",
+            );
+        }
+
+        if let Some(kafka) = &self.kafka {
+            if kafka.brokers.is_empty() {
+                return annotate(
+                    figment::Error::custom("At least one Kafka broker must be configured"),
+                    "kafka",
+                );
+            }
+
+            if kafka.topic.is_empty() {
+                return annotate(
+                    figment::Error::custom("The Kafka topic must not be empty"),
+                    "kafka",
+                );
+            }
+        }
+
+        if let Some(nats) = &self.nats {
+            if nats.subject.is_empty() {
+                return annotate(
+                    figment::Error::custom("The NATS subject must not be empty"),
+                    "nats",
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    event_stream:
+                      kafka:
+                        brokers:
+                          - kafka-1:9092
+                          - kafka-2:9092
+                        topic: mas-events
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = EventStreamConfig::extract(&figment)?;
+
+            let kafka = config.kafka.unwrap();
+            assert_eq!(kafka.brokers, ["kafka-1:9092", "kafka-2:9092"]);
+            assert_eq!(kafka.topic, "mas-events");
+            assert!(config.nats.is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_both_sinks() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    event_stream:
+                      kafka:
+                        brokers: [kafka:9092]
+                        topic: mas-events
+                      nats:
+                        url: nats://localhost:4222
+                        subject: mas.events
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(EventStreamConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -19,6 +19,22 @@ fn is_default_token_ttl(value: &Duration) -> bool {
     *value == default_token_ttl()
 }
 
+fn default_kiosk_session_ttl() -> Duration {
+    Duration::microseconds(60 * 60 * 1000 * 1000)
+}
+
+fn is_default_kiosk_session_ttl(value: &Duration) -> bool {
+    *value == default_kiosk_session_ttl()
+}
+
+fn default_kiosk_session_inactivity_ttl() -> Duration {
+    Duration::microseconds(5 * 60 * 1000 * 1000)
+}
+
+fn is_default_kiosk_session_inactivity_ttl(value: &Duration) -> bool {
+    *value == default_kiosk_session_inactivity_ttl()
+}
+
 /// Configuration sections for experimental options
 ///
 /// Do not change these options unless you know what you are doing.
@@ -35,29 +51,148 @@ pub struct ExperimentalConfig {
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
     pub access_token_ttl: Duration,
 
-    /// Time-to-live of compatibility access tokens in seconds. Defaults to 5
-    /// minutes.
+    /// How long, in seconds, OAuth 2.0 refresh tokens can be used after they
+    /// are issued. As each refresh issues a new refresh token, this is how
+    /// long a client can stay without refreshing its tokens. Defaults to no
+    /// limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// Maximum lifetime, in seconds, of OAuth 2.0 sessions. Once elapsed, the
+    /// session can't be refreshed anymore and is ended. Defaults to no limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub session_ttl: Option<Duration>,
+
+    /// How long, in seconds, OAuth 2.0 sessions can stay inactive before they
+    /// are ended. Defaults to no limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub session_inactivity_ttl: Option<Duration>,
+
+    /// Maximum lifetime, in seconds, of browser sessions. Once elapsed, the
+    /// user has to log in again. Defaults to no limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub browser_session_ttl: Option<Duration>,
+
+    /// How long, in seconds, browser sessions can stay inactive before the
+    /// user has to log in again. Defaults to no limit.
+    #[schemars(with = "Option<u64>", range(min = 60))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub browser_session_inactivity_ttl: Option<Duration>,
+
+    /// Whether all clients must use pushed authorization requests to start an
+    /// authorization grant, regardless of their own settings. Defaults to
+    /// `false`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_pushed_authorization_requests: bool,
+
+    /// Whether to notify users by email when a refresh token which was already
+    /// used is presented again, which ends the session it belongs to. Defaults
+    /// to `false`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub notify_refresh_token_reuse: bool,
+
+    /// How long, in seconds, a browser session bound to a WebAuthn credential
+    /// stays valid without the browser proving again that it holds the
+    /// credential. When set, browsers which support it bind a platform
+    /// credential to their session, and silently renew that proof while the
+    /// session is in use. Defaults to disabled.
+    #[schemars(with = "Option<u64>", range(min = 300))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub browser_session_binding_ttl: Option<Duration>,
+
+    /// How long, in seconds, the browser sessions administrators start to
+    /// impersonate users last. When set, administrators can impersonate the
+    /// users the impersonation policy allows them to. Defaults to disabled.
+    #[schemars(with = "Option<u64>", range(min = 60, max = 86400))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub impersonation_ttl: Option<Duration>,
+
+    /// How long, in seconds, users can choose to trust the browser they log in
+    /// from. Trusted browsers are not challenged again for additional
+    /// authentication factors until this period elapses. When set, the login
+    /// form lets users remember the browser. Defaults to disabled.
+    #[schemars(with = "Option<u64>", range(min = 3600))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub trusted_browser_ttl: Option<Duration>,
+
+    /// Maximum lifetime, in seconds, of the sessions of users logged in on a
+    /// kiosk device. Defaults to 1 hour.
     #[schemars(with = "u64", range(min = 60, max = 86400))]
     #[serde(
-        default = "default_token_ttl",
-        skip_serializing_if = "is_default_token_ttl"
+        default = "default_kiosk_session_ttl",
+        skip_serializing_if = "is_default_kiosk_session_ttl"
     )]
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
-    pub compat_token_ttl: Duration,
+    pub kiosk_session_ttl: Duration,
+
+    /// How long, in seconds, the sessions of users logged in on a kiosk device
+    /// can stay inactive before they are ended. Defaults to 5 minutes.
+    #[schemars(with = "u64", range(min = 60, max = 86400))]
+    #[serde(
+        default = "default_kiosk_session_inactivity_ttl",
+        skip_serializing_if = "is_default_kiosk_session_inactivity_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub kiosk_session_inactivity_ttl: Duration,
+
+    /// How long, in seconds, the rendezvous sessions used to sign in a new
+    /// device by scanning a QR code (MSC4108) last. When set, the rendezvous
+    /// endpoints are served by the compatibility layer. Defaults to disabled.
+    #[schemars(with = "Option<u64>", range(min = 10, max = 3600))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub rendezvous_ttl: Option<Duration>,
 }
 
 impl Default for ExperimentalConfig {
     fn default() -> Self {
         Self {
             access_token_ttl: default_token_ttl(),
-            compat_token_ttl: default_token_ttl(),
+            refresh_token_ttl: None,
+            session_ttl: None,
+            session_inactivity_ttl: None,
+            browser_session_ttl: None,
+            browser_session_inactivity_ttl: None,
+            require_pushed_authorization_requests: false,
+            notify_refresh_token_reuse: false,
+            browser_session_binding_ttl: None,
+            impersonation_ttl: None,
+            trusted_browser_ttl: None,
+            kiosk_session_ttl: default_kiosk_session_ttl(),
+            kiosk_session_inactivity_ttl: default_kiosk_session_inactivity_ttl(),
+            rendezvous_ttl: None,
         }
     }
 }
 
 impl ExperimentalConfig {
     pub(crate) fn is_default(&self) -> bool {
-        is_default_token_ttl(&self.access_token_ttl) && is_default_token_ttl(&self.compat_token_ttl)
+        is_default_token_ttl(&self.access_token_ttl)
+            && self.refresh_token_ttl.is_none()
+            && self.session_ttl.is_none()
+            && self.session_inactivity_ttl.is_none()
+            && self.browser_session_ttl.is_none()
+            && self.browser_session_inactivity_ttl.is_none()
+            && !self.require_pushed_authorization_requests
+            && !self.notify_refresh_token_reuse
+            && self.browser_session_binding_ttl.is_none()
+            && self.impersonation_ttl.is_none()
+            && self.trusted_browser_ttl.is_none()
+            && is_default_kiosk_session_ttl(&self.kiosk_session_ttl)
+            && is_default_kiosk_session_inactivity_ttl(&self.kiosk_session_inactivity_ttl)
+            && self.rendezvous_ttl.is_none()
     }
 }
 
@@ -0,0 +1,61 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ConfigurationSection;
+
+const fn default_threshold() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+/// Configuration section for the warnings about expiring credentials
+///
+/// The TLS certificates of the HTTP listeners, the client secrets of the
+/// upstream OAuth 2.0 providers which have a known expiry date, and the
+/// signing keys planned to be retired by the key rotation are regularly
+/// checked. Warnings are logged and exposed as metrics when they are about to
+/// expire.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ExpiryWarningsConfig {
+    /// How long before a credential expires to start warning about it, in
+    /// seconds. Defaults to 30 days.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_threshold")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub threshold: Duration,
+
+    /// Email addresses which are warned about expiring credentials
+    ///
+    /// Each credential is reported once, and then every week until it is
+    /// renewed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<String>,
+}
+
+impl Default for ExpiryWarningsConfig {
+    fn default() -> Self {
+        Self {
+            threshold: default_threshold(),
+            emails: Vec::new(),
+        }
+    }
+}
+
+impl ExpiryWarningsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.threshold == default_threshold() && self.emails.is_empty()
+    }
+}
+
+impl ConfigurationSection for ExpiryWarningsConfig {
+    const PATH: Option<&'static str> = Some("expiry_warnings");
+}
@@ -8,8 +8,13 @@
 
 use std::{borrow::Cow, io::Cursor};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use camino::Utf8PathBuf;
+use chrono::{DateTime, Utc};
+use der::{
+    asn1::{AnyRef, GeneralizedTime, UtcTime},
+    Decode, Reader, SliceReader, Tag, TagNumber,
+};
 use ipnetwork::IpNetwork;
 use mas_keystore::PrivateKey;
 use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
@@ -242,6 +247,28 @@ impl TlsConfig {
         let key = key.to_pkcs8_der()?;
         let key = PrivatePkcs8KeyDer::from(key.to_vec()).into();
 
+        let certificate_chain = self.load_certificate_chain()?;
+
+        Ok((key, certificate_chain))
+    }
+
+    /// Load the TLS certificate chain, and return when the leaf certificate
+    /// expires
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate chain could not be loaded, or if
+    /// the leaf certificate could not be parsed
+    pub fn certificate_expires_at(&self) -> Result<DateTime<Utc>, anyhow::Error> {
+        let certificate_chain = self.load_certificate_chain()?;
+        let leaf = certificate_chain
+            .first()
+            .context("TLS certificate chain is empty")?;
+        let expires_at = parse_not_after(leaf).context("Could not parse the TLS certificate")?;
+        Ok(expires_at)
+    }
+
+    fn load_certificate_chain(&self) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
         let certificate_chain_pem = match (&self.certificate, &self.certificate_file) {
             (None, None) => bail!("Either `certificate` or `certificate_file` must be set"),
             (Some(_), Some(_)) => {
@@ -260,10 +287,61 @@ impl TlsConfig {
             bail!("TLS certificate chain is empty (or invalid)")
         }
 
-        Ok((key, certificate_chain))
+        Ok(certificate_chain)
     }
 }
 
+/// Extract the end of the validity period of a DER-encoded X.509 certificate
+fn parse_not_after(der: &[u8]) -> Result<DateTime<Utc>, der::Error> {
+    let mut reader = SliceReader::new(der)?;
+    let not_after = reader.sequence(|certificate| {
+        let not_after = certificate.sequence(|tbs_certificate| {
+            // The version is optional, and explicitly tagged with [0]
+            let version_tag = Tag::ContextSpecific {
+                constructed: true,
+                number: TagNumber::N0,
+            };
+            if tbs_certificate.peek_tag()? == version_tag {
+                AnyRef::decode(tbs_certificate)?;
+            }
+
+            // Skip the serial number, the signature algorithm and the issuer
+            for _ in 0..3 {
+                AnyRef::decode(tbs_certificate)?;
+            }
+
+            // The validity is a sequence of two times, each being either an
+            // UTCTime or a GeneralizedTime
+            let not_after = tbs_certificate.sequence(|validity| {
+                AnyRef::decode(validity)?;
+                if validity.peek_tag()? == Tag::UtcTime {
+                    Ok(UtcTime::decode(validity)?.to_unix_duration())
+                } else {
+                    Ok(GeneralizedTime::decode(validity)?.to_unix_duration())
+                }
+            })?;
+
+            // Skip the rest of the certificate content
+            while !tbs_certificate.is_finished() {
+                tbs_certificate.tlv_bytes()?;
+            }
+
+            Ok(not_after)
+        })?;
+
+        // Skip the signature
+        while !certificate.is_finished() {
+            certificate.tlv_bytes()?;
+        }
+
+        Ok(not_after)
+    })?;
+    let not_after = reader.finish(not_after)?;
+
+    let timestamp = i64::try_from(not_after.as_secs()).map_err(|_| der::ErrorKind::DateTime)?;
+    DateTime::from_timestamp(timestamp, 0).ok_or_else(|| der::ErrorKind::DateTime.into())
+}
+
 /// HTTP resources to mount
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 #[serde(tag = "name", rename_all = "lowercase")]
@@ -311,6 +389,11 @@ pub enum Resource {
     /// Admin API, served at `/api/admin/v1`
     AdminApi,
 
+    /// Password provider API for Synapse, served at
+    /// `/_matrix-internal/identity/v1/check_credentials`. It should not be
+    /// exposed to the public internet.
+    PasswordProvider,
+
     /// Mount a "/connection-info" handler which helps debugging informations on
     /// the upstream connection
     #[serde(rename = "connection-info")]
@@ -344,6 +427,83 @@ pub struct ListenerConfig {
     pub tls: Option<TlsConfig>,
 }
 
+/// Destinations which can be reached by outbound requests, even if they are
+/// not public
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct OutboundAllowListConfig {
+    /// Networks which can be reached
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_networks: Vec<IpNetwork>,
+
+    /// Host names which can be reached, whatever address they resolve to.
+    ///
+    /// Host names starting with `*.` match all their subdomains.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+}
+
+impl OutboundAllowListConfig {
+    fn is_default(&self) -> bool {
+        self.allowed_networks.is_empty() && self.allowed_hosts.is_empty()
+    }
+}
+
+/// Restrictions on the outbound requests made to URLs set by users or
+/// operators, like webhook endpoints, remote avatars, client JWKS or client
+/// logos.
+///
+/// By default, those requests can only reach publicly routable addresses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct OutboundConfig {
+    /// Allow requests to private, loopback and link-local addresses, for
+    /// example in air-gapped networks.
+    ///
+    /// Cloud metadata endpoints stay blocked unless explicitly allowed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_private_networks: bool,
+
+    /// Networks which can be reached by all outbound requests
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_networks: Vec<IpNetwork>,
+
+    /// Host names which can be reached by all outbound requests, whatever
+    /// address they resolve to.
+    ///
+    /// Host names starting with `*.` match all their subdomains.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+
+    /// Destinations which can be reached when sending webhooks
+    #[serde(default, skip_serializing_if = "OutboundAllowListConfig::is_default")]
+    pub webhooks: OutboundAllowListConfig,
+
+    /// Destinations which can be reached when fetching avatars from upstream
+    /// providers
+    #[serde(default, skip_serializing_if = "OutboundAllowListConfig::is_default")]
+    pub avatars: OutboundAllowListConfig,
+
+    /// Destinations which can be reached when fetching the JWKS of clients and
+    /// JWT bearer issuers
+    #[serde(default, skip_serializing_if = "OutboundAllowListConfig::is_default")]
+    pub jwks: OutboundAllowListConfig,
+
+    /// Destinations which can be reached when fetching the logos of clients
+    #[serde(default, skip_serializing_if = "OutboundAllowListConfig::is_default")]
+    pub logos: OutboundAllowListConfig,
+}
+
+impl OutboundConfig {
+    fn is_default(&self) -> bool {
+        !self.allow_private_networks
+            && self.allowed_networks.is_empty()
+            && self.allowed_hosts.is_empty()
+            && self.webhooks.is_default()
+            && self.avatars.is_default()
+            && self.jwks.is_default()
+            && self.logos.is_default()
+    }
+}
+
 /// Configuration related to the web server
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct HttpConfig {
@@ -362,6 +522,21 @@ pub struct HttpConfig {
     /// OIDC issuer URL. Defaults to `public_base` if not set.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub issuer: Option<Url>,
+
+    /// Name of the header in which the reverse proxy terminating TLS forwards
+    /// the client certificate it verified, used for mutual-TLS client
+    /// authentication.
+    ///
+    /// The certificate can be PEM-encoded, optionally URL-encoded, or
+    /// base64-encoded DER. The header is only trusted on requests coming
+    /// directly from one of the `trusted_proxies`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_certificate_header: Option<String>,
+
+    /// Restrictions on the outbound requests made to URLs set by users or
+    /// operators
+    #[serde(default, skip_serializing_if = "OutboundConfig::is_default")]
+    pub outbound: OutboundConfig,
 }
 
 impl Default for HttpConfig {
@@ -405,6 +580,8 @@ impl Default for HttpConfig {
             trusted_proxies: default_trusted_proxies(),
             issuer: Some(default_public_base()),
             public_base: default_public_base(),
+            client_certificate_header: None,
+            outbound: OutboundConfig::default(),
         }
     }
 }
@@ -474,3 +651,41 @@ impl ConfigurationSection for HttpConfig {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn certificate_expiry() {
+        let config = TlsConfig {
+            certificate: Some(
+                "-----BEGIN CERTIFICATE-----
+MIIBizCCATGgAwIBAgIUCynweB7iGsv1TZCfIAMRPgUy0eQwCgYIKoZIzj0EAwIw
+GzEZMBcGA1UEAwwQYXV0aC5leGFtcGxlLmNvbTAeFw0yNjEwMTYxNDI1MzlaFw0y
+NzAxMTQxNDI1MzlaMBsxGTAXBgNVBAMMEGF1dGguZXhhbXBsZS5jb20wWTATBgcq
+hkjOPQIBBggqhkjOPQMBBwNCAARDqlHBwEzqs3POvdA/mge4XIMUF9v90JShWM/d
+xnnU/vPMR2oTqVVryJegN2bHHN+nttfKh68qJAeXw+ry03/7o1MwUTAdBgNVHQ4E
+FgQU9lbwgvSTyuGfVPG5FnSv2FZjwsYwHwYDVR0jBBgwFoAU9lbwgvSTyuGfVPG5
+FnSv2FZjwsYwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA2z86
+jBeALbZdMJIVqWc4fzn0PIrXtViiUv8T+jB5uK4CIGO1W639UjvTJJATuflQgcva
+N2aWQPdFVj7j2YAQH6EC
+-----END CERTIFICATE-----
+"
+                .to_owned(),
+            ),
+            certificate_file: None,
+            key: None,
+            key_file: None,
+            password: None,
+            password_file: None,
+        };
+
+        assert_eq!(
+            config.certificate_expires_at().unwrap(),
+            Utc.with_ymd_and_hms(2027, 1, 14, 14, 25, 39).unwrap()
+        );
+    }
+}
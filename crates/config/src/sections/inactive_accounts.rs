@@ -0,0 +1,125 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ConfigurationSection;
+
+const ONE_DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value
+}
+
+const fn default_inactivity_period() -> Duration {
+    Duration::from_secs(365 * ONE_DAY.as_secs())
+}
+
+const fn default_grace_period() -> Duration {
+    Duration::from_secs(30 * ONE_DAY.as_secs())
+}
+
+/// Configuration section for the deactivation of inactive accounts
+///
+/// Once a day, the users who have no active session and didn't log in for
+/// the inactivity period are warned by email. If they still didn't log in at
+/// the end of the grace period, their account is deactivated, both locally
+/// and on the homeserver, without erasing their data.
+///
+/// Bots, guests, users under legal hold and users with a pending account
+/// deletion are never deactivated. Guests are cleaned up separately, after
+/// `account.guest_inactivity_ttl`.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct InactiveAccountsConfig {
+    /// Whether inactive accounts are deactivated. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How long, in seconds, users must not have used their account before
+    /// they are warned. Defaults to a year.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_inactivity_period")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub inactivity_period: Duration,
+
+    /// How long, in seconds, to wait after the warning before deactivating the
+    /// account. Defaults to 30 days.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_grace_period")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub grace_period: Duration,
+
+    /// Usernames of the users whose account is never deactivated, for example
+    /// service accounts which only log in once in a while
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_usernames: Vec<String>,
+
+    /// Whether users who can request admin privileges are never deactivated.
+    /// Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub exclude_admins: bool,
+}
+
+impl Default for InactiveAccountsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            inactivity_period: default_inactivity_period(),
+            grace_period: default_grace_period(),
+            exclude_usernames: Vec::new(),
+            exclude_admins: default_true(),
+        }
+    }
+}
+
+impl InactiveAccountsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled
+            && self.inactivity_period == default_inactivity_period()
+            && self.grace_period == default_grace_period()
+            && self.exclude_usernames.is_empty()
+            && self.exclude_admins
+    }
+}
+
+impl ConfigurationSection for InactiveAccountsConfig {
+    const PATH: Option<&'static str> = Some("inactive_accounts");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let annotate = |field: &str, message: &str| {
+            let mut error = figment::Error::from(message.to_owned());
+            error.metadata = figment
+                .find_metadata(&format!("{root}.{field}", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            Err(error)
+        };
+
+        if self.inactivity_period < ONE_DAY {
+            return annotate(
+                "inactivity_period",
+                "The inactivity period must be at least one day",
+            );
+        }
+
+        if self.grace_period < ONE_DAY {
+            return annotate("grace_period", "The grace period must be at least one day");
+        }
+
+        Ok(())
+    }
+}
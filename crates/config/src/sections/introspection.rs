@@ -0,0 +1,178 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeSet;
+
+use chrono::Duration;
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
+use ulid::Ulid;
+
+use crate::ConfigurationSection;
+
+/// The maximum grace period which can be configured
+const MAX_GRACE_PERIOD_SECONDS: i64 = 5 * 60;
+
+/// A type of token which can be introspected
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IntrospectionTokenTypeConfig {
+    /// OAuth 2.0 access tokens
+    AccessToken,
+
+    /// OAuth 2.0 refresh tokens
+    RefreshToken,
+
+    /// Access tokens issued through the Matrix Client-Server API
+    /// compatibility layer
+    CompatAccessToken,
+
+    /// Refresh tokens issued through the Matrix Client-Server API
+    /// compatibility layer
+    CompatRefreshToken,
+}
+
+/// Restrict which types of tokens a client can introspect
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct IntrospectionClientConfig {
+    /// The ID of the client
+    #[schemars(
+        with = "String",
+        regex(pattern = r"^[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}$"),
+        description = "A ULID as per https://github.com/ulid/spec"
+    )]
+    pub client_id: Ulid,
+
+    /// The types of tokens this client is allowed to introspect. Other tokens
+    /// are reported as inactive to this client.
+    pub token_types: Vec<IntrospectionTokenTypeConfig>,
+}
+
+/// Configuration section for the token introspection endpoint
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct IntrospectionConfig {
+    /// How long access tokens are still reported as active after they
+    /// expired, in seconds. This absorbs clock skew between the service and
+    /// the resource servers. Defaults to 0, and can't be more than 5 minutes.
+    #[serde(default, skip_serializing_if = "Duration::is_zero")]
+    #[schemars(with = "u64", range(max = 300))]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub grace_period: Duration,
+
+    /// Restrict the types of tokens some clients can introspect. Clients not
+    /// listed here can introspect all types of tokens.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clients: Vec<IntrospectionClientConfig>,
+}
+
+impl IntrospectionConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.grace_period.is_zero() && self.clients.is_empty()
+    }
+}
+
+impl ConfigurationSection for IntrospectionConfig {
+    const PATH: Option<&'static str> = Some("introspection");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.grace_period < Duration::zero()
+            || self.grace_period > Duration::seconds(MAX_GRACE_PERIOD_SECONDS)
+        {
+            let mut error = figment::Error::custom(format!(
+                "grace_period must be between 0 and {MAX_GRACE_PERIOD_SECONDS} seconds"
+            ));
+            error.metadata = figment
+                .find_metadata(&format!("{root}.grace_period", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "grace_period".to_owned()];
+            return Err(error);
+        }
+
+        let mut seen = BTreeSet::new();
+        for (index, client) in self.clients.iter().enumerate() {
+            if !seen.insert(client.client_id) {
+                let mut error =
+                    figment::Error::custom(format!("Duplicate client {}", client.client_id));
+                error.metadata = figment
+                    .find_metadata(&format!("{root}.clients", root = Self::PATH.unwrap()))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "clients".to_owned(),
+                    index.to_string(),
+                ];
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    introspection:
+                      grace_period: 30
+                      clients:
+                        - client_id: 01H3FDH2HGDV6WZ0VN6EEX8VYD
+                          token_types:
+                            - access_token
+                            - compat_access_token
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = IntrospectionConfig::extract(&figment)?;
+
+            assert_eq!(config.grace_period, Duration::seconds(30));
+            assert_eq!(config.clients.len(), 1);
+            assert_eq!(
+                config.clients[0].token_types,
+                vec![
+                    IntrospectionTokenTypeConfig::AccessToken,
+                    IntrospectionTokenTypeConfig::CompatAccessToken,
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_long_grace_period() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    introspection:
+                      grace_period: 3600
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(IntrospectionConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeSet;
+
+use mas_jose::jwk::PublicJsonWebKeySet;
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use url::Url;
+
+use crate::ConfigurationSection;
+
+/// An issuer trusted to sign assertions for the JWT bearer grant
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct JwtBearerIssuerConfig {
+    /// The expected `iss` claim of the assertions
+    pub issuer: String,
+
+    /// The keys used to verify the assertions. Mutually exclusive with
+    /// `jwks_uri`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<PublicJsonWebKeySet>,
+
+    /// The URL of the keys used to verify the assertions. Mutually exclusive
+    /// with `jwks`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<Url>,
+}
+
+/// Configuration section for the JWT bearer assertion grant (RFC 7523)
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct JwtBearerConfig {
+    /// List of issuers trusted to sign assertions
+    ///
+    /// The issuers must also be allowed by the policy, through the
+    /// `jwt_bearer_issuers` policy data.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub issuers: Vec<JwtBearerIssuerConfig>,
+}
+
+impl JwtBearerConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.issuers.is_empty()
+    }
+}
+
+impl ConfigurationSection for JwtBearerConfig {
+    const PATH: Option<&'static str> = Some("jwt_bearer");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let mut seen = BTreeSet::new();
+
+        for (index, issuer) in self.issuers.iter().enumerate() {
+            let annotate = |mut error: figment::Error| {
+                error.metadata = figment
+                    .find_metadata(&format!("{root}.issuers", root = Self::PATH.unwrap()))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "issuers".to_owned(),
+                    index.to_string(),
+                ];
+                Err(error)
+            };
+
+            if !seen.insert(&issuer.issuer) {
+                return annotate(figment::Error::custom(format!(
+                    "Duplicate issuer {:?}",
+                    issuer.issuer
+                )));
+            }
+
+            match (&issuer.jwks, &issuer.jwks_uri) {
+                (None, None) => {
+                    return annotate(figment::Error::custom("jwks or jwks_uri is required"));
+                }
+                (Some(_), Some(_)) => {
+                    return annotate(figment::Error::custom(
+                        "jwks and jwks_uri are mutually exclusive",
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    jwt_bearer:
+                      issuers:
+                        - issuer: https://hr.example.com
+                          jwks_uri: https://hr.example.com/jwks.json
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = JwtBearerConfig::extract(&figment)?;
+
+            assert_eq!(config.issuers.len(), 1);
+            assert_eq!(config.issuers[0].issuer, "https://hr.example.com");
+            assert_eq!(
+                config.issuers[0].jwks_uri.as_ref().map(Url::as_str),
+                Some("https://hr.example.com/jwks.json")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_missing_keys() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    jwt_bearer:
+                      issuers:
+                        - issuer: https://hr.example.com
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(JwtBearerConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+}
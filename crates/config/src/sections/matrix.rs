@@ -4,13 +4,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeSet;
+
 use rand::{
     distributions::{Alphanumeric, DistString},
     Rng,
 };
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Serialize};
 use serde_with::serde_as;
+use ulid::Ulid;
 use url::Url;
 
 use super::ConfigurationSection;
@@ -37,10 +40,88 @@ pub struct MatrixConfig {
     /// The base URL of the homeserver's client API
     #[serde(default = "default_endpoint")]
     pub endpoint: Url,
+
+    /// Other homeservers served by this service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_homeservers: Vec<AdditionalHomeserverConfig>,
+}
+
+/// An additional homeserver served by this service
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdditionalHomeserverConfig {
+    /// The server name of the homeserver.
+    pub homeserver: String,
+
+    /// Shared secret to use for calls to the admin API
+    pub secret: String,
+
+    /// The base URL of the homeserver's client API
+    pub endpoint: Url,
+
+    /// Hostnames on which this homeserver is selected. Users registering on
+    /// those hosts belong to this homeserver.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+
+    /// Clients belonging to this homeserver, like the client it uses to
+    /// introspect tokens. Tokens of users of other homeservers are reported
+    /// as inactive to those clients.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub clients: Vec<Ulid>,
 }
 
 impl ConfigurationSection for MatrixConfig {
     const PATH: Option<&'static str> = Some("matrix");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let mut server_names = BTreeSet::from([self.homeserver.as_str()]);
+        let mut hosts = BTreeSet::new();
+        let mut clients = BTreeSet::new();
+
+        for (index, additional) in self.additional_homeservers.iter().enumerate() {
+            let annotate = |mut error: figment::Error| {
+                error.metadata = figment
+                    .find_metadata(&format!(
+                        "{root}.additional_homeservers",
+                        root = Self::PATH.unwrap()
+                    ))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "additional_homeservers".to_owned(),
+                    index.to_string(),
+                ];
+                Err(error)
+            };
+
+            if !server_names.insert(&additional.homeserver) {
+                return annotate(figment::Error::custom(format!(
+                    "Duplicate homeserver {:?}",
+                    additional.homeserver
+                )));
+            }
+
+            for host in &additional.hosts {
+                if !hosts.insert(host.to_lowercase()) {
+                    return annotate(figment::Error::custom(format!(
+                        "Host {host:?} is used by multiple homeservers"
+                    )));
+                }
+            }
+
+            for client in &additional.clients {
+                if !clients.insert(*client) {
+                    return annotate(figment::Error::custom(format!(
+                        "Client {client} belongs to multiple homeservers"
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl MatrixConfig {
@@ -52,6 +133,7 @@ impl MatrixConfig {
             homeserver: default_homeserver(),
             secret: Alphanumeric.sample_string(&mut rng, 32),
             endpoint: default_endpoint(),
+            additional_homeservers: Vec::new(),
         }
     }
 
@@ -60,6 +142,7 @@ impl MatrixConfig {
             homeserver: default_homeserver(),
             secret: "test".to_owned(),
             endpoint: default_endpoint(),
+            additional_homeservers: Vec::new(),
         }
     }
 }
@@ -95,4 +178,59 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn load_additional_homeservers() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    matrix:
+                      homeserver: example.com
+                      secret: test
+                      additional_homeservers:
+                        - homeserver: example.org
+                          secret: other
+                          endpoint: http://synapse-org:8008/
+                          hosts:
+                            - auth.example.org
+                          clients:
+                            - 01H3FDH2HGDV6WZ0VN6EEX8VYD
+                ",
+            )?;
+
+            let config = MatrixConfig::extract(&Figment::new().merge(Yaml::file("config.yaml")))?;
+
+            assert_eq!(config.additional_homeservers.len(), 1);
+            let additional = &config.additional_homeservers[0];
+            assert_eq!(&additional.homeserver, "example.org");
+            assert_eq!(additional.hosts, vec!["auth.example.org".to_owned()]);
+            assert_eq!(additional.clients.len(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_duplicate_homeserver() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    matrix:
+                      homeserver: example.com
+                      secret: test
+                      additional_homeservers:
+                        - homeserver: example.com
+                          secret: other
+                          endpoint: http://synapse-org:8008/
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(MatrixConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
 }
@@ -9,39 +9,68 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 mod account;
+mod audit;
 mod branding;
+mod cache;
 mod captcha;
+mod chaos;
 mod clients;
+mod compat;
 mod database;
 mod email;
+mod event_stream;
 mod experimental;
+mod expiry_warnings;
 mod http;
+mod inactive_accounts;
+mod introspection;
+mod jwt_bearer;
 mod matrix;
 mod passwords;
 mod policy;
 mod rate_limiting;
+mod scopes;
 mod secrets;
+mod suspicious_logins;
 mod telemetry;
 mod templates;
 mod upstream_oauth2;
+mod vault;
+mod webhooks;
 
 pub use self::{
-    account::AccountConfig,
+    account::{AccountConfig, SessionLimitActionConfig},
+    audit::AuditConfig,
     branding::BrandingConfig,
+    cache::CacheConfig,
     captcha::{CaptchaConfig, CaptchaServiceKind},
+    chaos::{ChaosConfig, FaultInjectionConfig},
     clients::{ClientAuthMethodConfig, ClientConfig, ClientsConfig},
-    database::{DatabaseConfig, PgSslMode},
+    compat::{
+        AppserviceNamespaceConfig, AppserviceNamespacesConfig, AppserviceRegistrationConfig,
+        CompatConfig, CompatDeprecationConfig, CompatDisabledLoginTypeConfig, CompatEndpointConfig,
+        CompatLoginTypeConfig,
+    },
+    database::{DatabaseConfig, DatabaseQueryBudgetConfig, DatabaseReplicaConfig, PgSslMode},
     email::{EmailConfig, EmailSmtpMode, EmailTransportKind},
+    event_stream::{EventStreamConfig, KafkaEventStreamConfig, NatsEventStreamConfig},
     experimental::ExperimentalConfig,
+    expiry_warnings::ExpiryWarningsConfig,
     http::{
         BindConfig as HttpBindConfig, HttpConfig, ListenerConfig as HttpListenerConfig,
-        Resource as HttpResource, TlsConfig as HttpTlsConfig, UnixOrTcp,
+        OutboundAllowListConfig, OutboundConfig, Resource as HttpResource,
+        TlsConfig as HttpTlsConfig, UnixOrTcp,
     },
-    matrix::MatrixConfig,
-    passwords::{Algorithm as PasswordAlgorithm, PasswordsConfig},
-    policy::PolicyConfig,
+    inactive_accounts::InactiveAccountsConfig,
+    introspection::{IntrospectionClientConfig, IntrospectionConfig, IntrospectionTokenTypeConfig},
+    jwt_bearer::{JwtBearerConfig, JwtBearerIssuerConfig},
+    matrix::{AdditionalHomeserverConfig, MatrixConfig},
+    passwords::{Algorithm as PasswordAlgorithm, PasswordsConfig, SynapsePasswordFallbackConfig},
+    policy::{PolicyBundleConfig, PolicyConfig, RedirectUrisConfig},
     rate_limiting::RateLimitingConfig,
-    secrets::SecretsConfig,
+    scopes::{CustomScopeConfig, ScopesConfig},
+    secrets::{KeyRotationConfig, SecretsConfig},
+    suspicious_logins::SuspiciousLoginsConfig,
     telemetry::{
         MetricsConfig, MetricsExporterKind, Propagator, TelemetryConfig, TracingConfig,
         TracingExporterKind,
@@ -50,9 +79,20 @@ pub use self::{
     upstream_oauth2::{
         ClaimsImports as UpstreamOAuth2ClaimsImports, DiscoveryMode as UpstreamOAuth2DiscoveryMode,
         EmailImportPreference as UpstreamOAuth2EmailImportPreference,
-        ImportAction as UpstreamOAuth2ImportAction, PkceMethod as UpstreamOAuth2PkceMethod,
-        SetEmailVerification as UpstreamOAuth2SetEmailVerification, UpstreamOAuth2Config,
+        IdpInitiatedLogin as UpstreamOAuth2IdpInitiatedLogin,
+        ImportAction as UpstreamOAuth2ImportAction, OnEmailChange as UpstreamOAuth2OnEmailChange,
+        PkceMethod as UpstreamOAuth2PkceMethod,
+        PushedAuthorizationRequestsMode as UpstreamOAuth2PushedAuthorizationRequestsMode,
+        ResponseMode as UpstreamOAuth2ResponseMode,
+        SetEmailVerification as UpstreamOAuth2SetEmailVerification,
+        SignInWithApple as UpstreamOAuth2SignInWithApple,
+        TokenAuthMethod as UpstreamOAuth2TokenAuthMethod, UpstreamOAuth2Config,
+    },
+    vault::{
+        InvalidVaultReferenceError, VaultAuthConfig, VaultConfig, VaultDatabaseCredentialsConfig,
+        VaultReference, VAULT_REFERENCE_PREFIX,
     },
+    webhooks::{ChangesFeedConfig, WebhookEndpointConfig, WebhookEvent, WebhooksConfig},
 };
 use crate::util::ConfigurationSection;
 
@@ -119,6 +159,60 @@ pub struct RootConfig {
     #[serde(default, skip_serializing_if = "AccountConfig::is_default")]
     pub account: AccountConfig,
 
+    /// Configuration section to inject faults in outbound calls, for staging
+    /// deployments
+    #[serde(default, skip_serializing_if = "ChaosConfig::is_default")]
+    pub chaos: ChaosConfig,
+
+    /// Configuration section for the in-process cache of hot database lookups
+    #[serde(default, skip_serializing_if = "CacheConfig::is_default")]
+    pub cache: CacheConfig,
+
+    /// Configuration section for the audit log
+    #[serde(default, skip_serializing_if = "AuditConfig::is_default")]
+    pub audit: AuditConfig,
+
+    /// Configuration section for the warnings about expiring credentials
+    #[serde(default, skip_serializing_if = "ExpiryWarningsConfig::is_default")]
+    pub expiry_warnings: ExpiryWarningsConfig,
+
+    /// Configuration section for the detection of suspicious logins
+    #[serde(default, skip_serializing_if = "SuspiciousLoginsConfig::is_default")]
+    pub suspicious_logins: SuspiciousLoginsConfig,
+
+    /// Configuration section for the deactivation of inactive accounts
+    #[serde(default, skip_serializing_if = "InactiveAccountsConfig::is_default")]
+    pub inactive_accounts: InactiveAccountsConfig,
+
+    /// Configuration section for outbound webhooks
+    #[serde(default, skip_serializing_if = "WebhooksConfig::is_default")]
+    pub webhooks: WebhooksConfig,
+
+    /// Configuration section for publishing events to Kafka or NATS
+    #[serde(default, skip_serializing_if = "EventStreamConfig::is_default")]
+    pub event_stream: EventStreamConfig,
+
+    /// Configuration section for the JWT bearer assertion grant
+    #[serde(default, skip_serializing_if = "JwtBearerConfig::is_default")]
+    pub jwt_bearer: JwtBearerConfig,
+
+    /// Configuration section for the token introspection endpoint
+    #[serde(default, skip_serializing_if = "IntrospectionConfig::is_default")]
+    pub introspection: IntrospectionConfig,
+
+    /// Configuration section for deployment-specific scopes
+    #[serde(default, skip_serializing_if = "ScopesConfig::is_default")]
+    pub scopes: ScopesConfig,
+
+    /// Configuration related to the Matrix Client-Server API compatibility
+    /// layer
+    #[serde(default, skip_serializing_if = "CompatConfig::is_default")]
+    pub compat: CompatConfig,
+
+    /// Configuration section to fetch secrets from `HashiCorp` Vault
+    #[serde(default, skip_serializing_if = "VaultConfig::is_default")]
+    pub vault: VaultConfig,
+
     /// Experimental configuration options
     #[serde(default, skip_serializing_if = "ExperimentalConfig::is_default")]
     pub experimental: ExperimentalConfig,
@@ -141,6 +235,19 @@ impl ConfigurationSection for RootConfig {
         self.branding.validate(figment)?;
         self.captcha.validate(figment)?;
         self.account.validate(figment)?;
+        self.chaos.validate(figment)?;
+        self.cache.validate(figment)?;
+        self.audit.validate(figment)?;
+        self.expiry_warnings.validate(figment)?;
+        self.suspicious_logins.validate(figment)?;
+        self.inactive_accounts.validate(figment)?;
+        self.webhooks.validate(figment)?;
+        self.event_stream.validate(figment)?;
+        self.jwt_bearer.validate(figment)?;
+        self.introspection.validate(figment)?;
+        self.scopes.validate(figment)?;
+        self.compat.validate(figment)?;
+        self.vault.validate(figment)?;
         self.experimental.validate(figment)?;
 
         Ok(())
@@ -173,6 +280,19 @@ impl RootConfig {
             branding: BrandingConfig::default(),
             captcha: CaptchaConfig::default(),
             account: AccountConfig::default(),
+            chaos: ChaosConfig::default(),
+            cache: CacheConfig::default(),
+            audit: AuditConfig::default(),
+            expiry_warnings: ExpiryWarningsConfig::default(),
+            suspicious_logins: SuspiciousLoginsConfig::default(),
+            inactive_accounts: InactiveAccountsConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            event_stream: EventStreamConfig::default(),
+            jwt_bearer: JwtBearerConfig::default(),
+            introspection: IntrospectionConfig::default(),
+            scopes: ScopesConfig::default(),
+            compat: CompatConfig::default(),
+            vault: VaultConfig::default(),
             experimental: ExperimentalConfig::default(),
         })
     }
@@ -196,6 +316,19 @@ impl RootConfig {
             branding: BrandingConfig::default(),
             captcha: CaptchaConfig::default(),
             account: AccountConfig::default(),
+            chaos: ChaosConfig::default(),
+            cache: CacheConfig::default(),
+            audit: AuditConfig::default(),
+            expiry_warnings: ExpiryWarningsConfig::default(),
+            suspicious_logins: SuspiciousLoginsConfig::default(),
+            inactive_accounts: InactiveAccountsConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            event_stream: EventStreamConfig::default(),
+            jwt_bearer: JwtBearerConfig::default(),
+            introspection: IntrospectionConfig::default(),
+            scopes: ScopesConfig::default(),
+            compat: CompatConfig::default(),
+            vault: VaultConfig::default(),
             experimental: ExperimentalConfig::default(),
         }
     }
@@ -239,6 +372,42 @@ pub struct AppConfig {
     #[serde(default)]
     pub account: AccountConfig,
 
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+
+    #[serde(default)]
+    pub cache: CacheConfig,
+
+    #[serde(default)]
+    pub audit: AuditConfig,
+
+    #[serde(default)]
+    pub expiry_warnings: ExpiryWarningsConfig,
+
+    #[serde(default)]
+    pub suspicious_logins: SuspiciousLoginsConfig,
+
+    #[serde(default)]
+    pub inactive_accounts: InactiveAccountsConfig,
+
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+
+    #[serde(default)]
+    pub event_stream: EventStreamConfig,
+
+    #[serde(default)]
+    pub jwt_bearer: JwtBearerConfig,
+
+    #[serde(default)]
+    pub introspection: IntrospectionConfig,
+
+    #[serde(default)]
+    pub scopes: ScopesConfig,
+
+    #[serde(default)]
+    pub compat: CompatConfig,
+
     #[serde(default)]
     pub experimental: ExperimentalConfig,
 }
@@ -257,6 +426,18 @@ impl ConfigurationSection for AppConfig {
         self.branding.validate(figment)?;
         self.captcha.validate(figment)?;
         self.account.validate(figment)?;
+        self.chaos.validate(figment)?;
+        self.cache.validate(figment)?;
+        self.audit.validate(figment)?;
+        self.expiry_warnings.validate(figment)?;
+        self.suspicious_logins.validate(figment)?;
+        self.inactive_accounts.validate(figment)?;
+        self.webhooks.validate(figment)?;
+        self.event_stream.validate(figment)?;
+        self.jwt_bearer.validate(figment)?;
+        self.introspection.validate(figment)?;
+        self.scopes.validate(figment)?;
+        self.compat.validate(figment)?;
         self.experimental.validate(figment)?;
 
         Ok(())
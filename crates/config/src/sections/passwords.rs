@@ -52,6 +52,24 @@ pub struct PasswordsConfig {
     /// - 4: any more than that
     #[serde(default = "default_minimum_complexity")]
     minimum_complexity: u8,
+
+    /// Whether users can log in with their password. Defaults to `true`.
+    ///
+    /// Disabling this forces users to log in through an upstream provider,
+    /// while still allowing the users listed in `login_exceptions` to log in
+    /// with their password.
+    #[serde(default = "default_enabled")]
+    login_enabled: bool,
+
+    /// Usernames of the users who can still log in with their password when
+    /// `login_enabled` is `false`, e.g. break-glass admin accounts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    login_exceptions: Vec<String>,
+
+    /// Verify the passwords of users who don't have one yet against the
+    /// Synapse database, to import them on the fly during a migration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    synapse_fallback: Option<SynapsePasswordFallbackConfig>,
 }
 
 impl Default for PasswordsConfig {
@@ -60,6 +78,9 @@ impl Default for PasswordsConfig {
             enabled: default_enabled(),
             schemes: default_schemes(),
             minimum_complexity: default_minimum_complexity(),
+            login_enabled: default_enabled(),
+            login_exceptions: Vec::new(),
+            synapse_fallback: None,
         }
     }
 }
@@ -94,6 +115,19 @@ impl ConfigurationSection for PasswordsConfig {
             }
         }
 
+        if let Some(fallback) = &self.synapse_fallback {
+            let scheme = self
+                .schemes
+                .iter()
+                .find(|scheme| scheme.version == fallback.version);
+
+            if !matches!(scheme, Some(scheme) if matches!(scheme.algorithm, Algorithm::Bcrypt)) {
+                return annotate(figment::Error::from(
+                    "`synapse_fallback.version` must refer to a bcrypt password scheme".to_owned(),
+                ));
+            }
+        }
+
         Ok(())
     }
 }
@@ -105,6 +139,35 @@ impl PasswordsConfig {
         self.enabled
     }
 
+    /// Whether users can log in with their password, apart from the users
+    /// listed in [`Self::login_exceptions`]
+    #[must_use]
+    pub fn login_enabled(&self) -> bool {
+        self.enabled && self.login_enabled
+    }
+
+    /// Usernames of the users who can log in with their password even if
+    /// password login is disabled
+    #[must_use]
+    pub fn login_exceptions(&self) -> &[String] {
+        if self.enabled {
+            &self.login_exceptions
+        } else {
+            &[]
+        }
+    }
+
+    /// Where to verify the passwords of users who don't have one yet, if
+    /// password-based authentication is enabled
+    #[must_use]
+    pub fn synapse_fallback(&self) -> Option<&SynapsePasswordFallbackConfig> {
+        if self.enabled {
+            self.synapse_fallback.as_ref()
+        } else {
+            None
+        }
+    }
+
     /// Minimum complexity of passwords, from 0 to 4, according to the zxcvbn
     /// scorer.
     #[must_use]
@@ -154,6 +217,22 @@ impl PasswordsConfig {
     }
 }
 
+/// Configuration of the Synapse database used to verify the passwords of users
+/// migrated from Synapse
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SynapsePasswordFallbackConfig {
+    /// Connection URI of the Synapse database, which should use a read-only
+    /// role
+    #[schemars(url)]
+    pub uri: String,
+
+    /// Version of the password scheme to import the Synapse hashes with.
+    ///
+    /// It must be a `bcrypt` scheme with Synapse's `password_config.pepper`
+    /// as secret.
+    pub version: u16,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HashingScheme {
     version: u16,
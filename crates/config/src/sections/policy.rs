@@ -4,10 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::time::Duration;
+
 use camino::Utf8PathBuf;
+use mas_jose::jwk::PublicJsonWebKeySet;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Serialize};
 use serde_with::serde_as;
+use url::Url;
 
 use super::ConfigurationSection;
 
@@ -70,6 +74,22 @@ fn is_default_email_entrypoint(value: &String) -> bool {
     *value == default_email_entrypoint()
 }
 
+fn default_impersonation_entrypoint() -> String {
+    "impersonation/violation".to_owned()
+}
+
+fn is_default_impersonation_entrypoint(value: &String) -> bool {
+    *value == default_impersonation_entrypoint()
+}
+
+fn default_kiosk_entrypoint() -> String {
+    "kiosk/violation".to_owned()
+}
+
+fn is_default_kiosk_entrypoint(value: &String) -> bool {
+    *value == default_kiosk_entrypoint()
+}
+
 fn default_data() -> serde_json::Value {
     serde_json::json!({})
 }
@@ -78,6 +98,112 @@ fn is_default_data(value: &serde_json::Value) -> bool {
     *value == default_data()
 }
 
+const fn default_bundle_polling_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn is_default_bundle_polling_interval(value: &Duration) -> bool {
+    *value == default_bundle_polling_interval()
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value
+}
+
+/// Checks that the given string is a valid URI scheme, as per RFC3986 section
+/// 3.1
+fn is_valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| matches!(c, 'a'..='z' | '0'..='9' | '+' | '-' | '.'))
+}
+
+/// Rules for the redirect URIs clients can use, depending on their
+/// application type
+///
+/// Those are checked when a client registers, and again when it starts an
+/// authorization, so that tightening them also applies to the clients which
+/// registered before.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RedirectUrisConfig {
+    /// Custom URI schemes which native clients can use in their redirect
+    /// URIs, like `com.example.app`.
+    ///
+    /// Native clients can always use the reverse-DNS notation of their
+    /// `client_uri` host as a scheme.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub native_custom_schemes: Vec<String>,
+
+    /// Whether redirect URIs on the loopback interface (`localhost`,
+    /// `127.0.0.1` or `[::1]`) match regardless of their port, as native
+    /// clients usually listen on an ephemeral port. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub loopback_any_port: bool,
+
+    /// Whether web clients have to use HTTPS redirect URIs. Defaults to
+    /// `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub web_require_https: bool,
+}
+
+impl Default for RedirectUrisConfig {
+    fn default() -> Self {
+        Self {
+            native_custom_schemes: Vec::new(),
+            loopback_any_port: true,
+            web_require_https: true,
+        }
+    }
+}
+
+impl RedirectUrisConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.native_custom_schemes.is_empty() && self.loopback_any_port && self.web_require_https
+    }
+}
+
+/// A signed OPA bundle to fetch the policy from
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PolicyBundleConfig {
+    /// URL of the bundle, a gzipped tarball built with `opa build -t wasm`
+    pub url: Url,
+
+    /// How often, in seconds, to check for a new version of the bundle.
+    /// Defaults to 60 seconds.
+    #[schemars(with = "u64")]
+    #[serde(
+        default = "default_bundle_polling_interval",
+        skip_serializing_if = "is_default_bundle_polling_interval"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub polling_interval: Duration,
+
+    /// Bearer token to authenticate to the bundle server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Public keys used to verify the signature of the bundle. Mutually
+    /// exclusive with `secret`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<PublicJsonWebKeySet>,
+
+    /// Shared secret used to verify the signature of the bundle, for bundles
+    /// signed with an HMAC algorithm. Mutually exclusive with `jwks`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+
+    /// Only accept bundles signed for this scope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
 /// Application secrets
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -125,9 +251,43 @@ pub struct PolicyConfig {
     )]
     pub email_entrypoint: String,
 
+    /// Entrypoint to use when an administrator starts impersonating a user
+    #[serde(
+        default = "default_impersonation_entrypoint",
+        skip_serializing_if = "is_default_impersonation_entrypoint"
+    )]
+    pub impersonation_entrypoint: String,
+
+    /// Entrypoint to use when a client registers a kiosk device, or logs a
+    /// user in on one
+    #[serde(
+        default = "default_kiosk_entrypoint",
+        skip_serializing_if = "is_default_kiosk_entrypoint"
+    )]
+    pub kiosk_entrypoint: String,
+
     /// Arbitrary data to pass to the policy
     #[serde(default = "default_data", skip_serializing_if = "is_default_data")]
     pub data: serde_json::Value,
+
+    /// Names of the request headers to pass to the policies, in the
+    /// `requester.headers` input field. This can be used to pass information
+    /// added by a reverse proxy, like the country or the autonomous system the
+    /// request comes from.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub request_headers: Vec<String>,
+
+    /// Rules for the redirect URIs clients can use
+    #[serde(default, skip_serializing_if = "RedirectUrisConfig::is_default")]
+    pub redirect_uris: RedirectUrisConfig,
+
+    /// Fetch the policy from a signed OPA bundle, instead of the `wasm_module`
+    /// file
+    ///
+    /// The `wasm_module` file is used until the bundle is first fetched, and
+    /// the data from the bundle is merged on top of the `data` setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<PolicyBundleConfig>,
 }
 
 impl Default for PolicyConfig {
@@ -139,7 +299,12 @@ impl Default for PolicyConfig {
             authorization_grant_entrypoint: default_authorization_grant_entrypoint(),
             password_entrypoint: default_password_entrypoint(),
             email_entrypoint: default_email_entrypoint(),
+            impersonation_entrypoint: default_impersonation_entrypoint(),
+            kiosk_entrypoint: default_kiosk_entrypoint(),
             data: default_data(),
+            request_headers: Vec::new(),
+            redirect_uris: RedirectUrisConfig::default(),
+            bundle: None,
         }
     }
 }
@@ -153,10 +318,171 @@ impl PolicyConfig {
             && is_default_authorization_grant_entrypoint(&self.authorization_grant_entrypoint)
             && is_default_password_entrypoint(&self.password_entrypoint)
             && is_default_email_entrypoint(&self.email_entrypoint)
+            && is_default_impersonation_entrypoint(&self.impersonation_entrypoint)
+            && is_default_kiosk_entrypoint(&self.kiosk_entrypoint)
             && is_default_data(&self.data)
+            && self.request_headers.is_empty()
+            && self.redirect_uris.is_default()
+            && self.bundle.is_none()
     }
 }
 
 impl ConfigurationSection for PolicyConfig {
     const PATH: Option<&'static str> = Some("policy");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        for (index, scheme) in self.redirect_uris.native_custom_schemes.iter().enumerate() {
+            if !is_valid_scheme(scheme) || scheme == "http" || scheme == "https" {
+                let mut error =
+                    figment::Error::custom(format!("Invalid custom URI scheme {scheme:?}"));
+                error.metadata = figment
+                    .find_metadata(&format!(
+                        "{root}.redirect_uris.native_custom_schemes",
+                        root = Self::PATH.unwrap()
+                    ))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "redirect_uris".to_owned(),
+                    "native_custom_schemes".to_owned(),
+                    index.to_string(),
+                ];
+                return Err(error);
+            }
+        }
+
+        let Some(bundle) = &self.bundle else {
+            return Ok(());
+        };
+
+        let annotate = |mut error: figment::Error| {
+            error.metadata = figment
+                .find_metadata(&format!("{root}.bundle", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "bundle".to_owned()];
+            Err(error)
+        };
+
+        if bundle.jwks.is_some() == bundle.secret.is_some() {
+            return annotate(figment::Error::custom(
+                "Exactly one of `jwks` and `secret` is required to verify the bundle",
+            ));
+        }
+
+        if bundle.polling_interval.is_zero() {
+            return annotate(figment::Error::custom(
+                "The polling interval must be at least one second",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_bundle_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    policy:
+                      bundle:
+                        url: https://policies.example.com/bundles/mas.tar.gz
+                        secret: hunter2
+                        scope: production
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<PolicyConfig>("policy")?;
+            config.validate(&Figment::new()).unwrap();
+
+            let bundle = config.bundle.unwrap();
+            assert_eq!(
+                bundle.url.as_str(),
+                "https://policies.example.com/bundles/mas.tar.gz"
+            );
+            assert_eq!(bundle.polling_interval, Duration::from_secs(60));
+            assert_eq!(bundle.secret.as_deref(), Some("hunter2"));
+            assert_eq!(bundle.scope.as_deref(), Some("production"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn bundle_requires_a_key() {
+        let config = PolicyConfig {
+            bundle: Some(PolicyBundleConfig {
+                url: "https://policies.example.com/bundles/mas.tar.gz"
+                    .parse()
+                    .unwrap(),
+                polling_interval: default_bundle_polling_interval(),
+                token: None,
+                jwks: None,
+                secret: None,
+                scope: None,
+            }),
+            ..PolicyConfig::default()
+        };
+
+        assert!(config.validate(&Figment::new()).is_err());
+    }
+
+    #[test]
+    fn load_redirect_uris_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    policy:
+                      redirect_uris:
+                        native_custom_schemes:
+                          - com.example.app
+                        web_require_https: false
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<PolicyConfig>("policy")?;
+            config.validate(&Figment::new()).unwrap();
+
+            assert_eq!(
+                config.redirect_uris.native_custom_schemes,
+                vec!["com.example.app".to_owned()]
+            );
+            assert!(config.redirect_uris.loopback_any_port);
+            assert!(!config.redirect_uris.web_require_https);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn invalid_custom_schemes() {
+        for scheme in ["", "https", "Com.Example", "com_example", "1app"] {
+            let config = PolicyConfig {
+                redirect_uris: RedirectUrisConfig {
+                    native_custom_schemes: vec![scheme.to_owned()],
+                    ..RedirectUrisConfig::default()
+                },
+                ..PolicyConfig::default()
+            };
+
+            assert!(config.validate(&Figment::new()).is_err(), "{scheme:?}");
+        }
+    }
 }
@@ -0,0 +1,219 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeSet;
+
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::ConfigurationSection;
+
+/// Scopes and scope prefixes which are defined by the service itself, and
+/// can't be redefined as custom scopes
+const RESERVED_SCOPES: &[&str] = &["openid", "email"];
+const RESERVED_SCOPE_PREFIXES: &[&str] = &["urn:matrix:", "urn:synapse:", "urn:mas:"];
+
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value
+}
+
+/// Checks that the scope is a valid scope token, as per RFC6749 appendix A.4
+fn is_valid_scope_token(scope: &str) -> bool {
+    !scope.is_empty()
+        && scope
+            .chars()
+            .all(|c| matches!(c, '\x21' | '\x23'..='\x5B' | '\x5D'..='\x7E'))
+}
+
+/// A deployment-specific scope
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct CustomScopeConfig {
+    /// The scope, for example `urn:ourcorp:bridge:*`
+    pub scope: String,
+
+    /// The clients which are allowed to request this scope. If empty, any
+    /// client can request it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(with = "Vec<String>")]
+    pub clients: Vec<Ulid>,
+
+    /// Whether users have to explicitly consent to this scope before it is
+    /// granted to a client. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub requires_consent: bool,
+
+    /// What granting this scope allows the client to do, as shown to users on
+    /// the consent screen. If not set, the scope itself is shown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Configuration section for deployment-specific scopes
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct ScopesConfig {
+    /// Custom scopes which can be requested by clients, in addition to the
+    /// ones supported by the service.
+    ///
+    /// The default policy only allows the clients listed for each scope to
+    /// request it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom: Vec<CustomScopeConfig>,
+}
+
+impl ScopesConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.custom.is_empty()
+    }
+}
+
+impl ConfigurationSection for ScopesConfig {
+    const PATH: Option<&'static str> = Some("scopes");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let annotate = |mut error: figment::Error, index: usize| {
+            error.metadata = figment
+                .find_metadata(&format!("{root}.custom", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "custom".to_owned(),
+                index.to_string(),
+                "scope".to_owned(),
+            ];
+            error
+        };
+
+        let mut seen = BTreeSet::new();
+        for (index, custom) in self.custom.iter().enumerate() {
+            let scope = custom.scope.as_str();
+
+            if !is_valid_scope_token(scope) {
+                return Err(annotate(
+                    figment::Error::custom(format!("Invalid scope {scope:?}")),
+                    index,
+                ));
+            }
+
+            if RESERVED_SCOPES.contains(&scope)
+                || RESERVED_SCOPE_PREFIXES
+                    .iter()
+                    .any(|prefix| scope.starts_with(prefix))
+            {
+                return Err(annotate(
+                    figment::Error::custom(format!(
+                        "Scope {scope:?} is reserved and can't be used as a custom scope"
+                    )),
+                    index,
+                ));
+            }
+
+            if !seen.insert(scope) {
+                return Err(annotate(
+                    figment::Error::custom(format!("Duplicate custom scope {scope:?}")),
+                    index,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    scopes:
+                      custom:
+                        - scope: 'urn:ourcorp:bridge:*'
+                          clients:
+                            - 01H3FDH2HGDV6WZ0VN6EEX8VYD
+                          requires_consent: false
+                        - scope: 'urn:ourcorp:calendar'
+                          description: Read and edit your calendar
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = ScopesConfig::extract(&figment)?;
+
+            assert_eq!(config.custom.len(), 2);
+            assert_eq!(config.custom[0].scope, "urn:ourcorp:bridge:*");
+            assert_eq!(config.custom[0].clients.len(), 1);
+            assert!(!config.custom[0].requires_consent);
+            assert!(config.custom[1].clients.is_empty());
+            assert!(config.custom[1].requires_consent);
+            assert_eq!(
+                config.custom[1].description.as_deref(),
+                Some("Read and edit your calendar")
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_reserved_scopes() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    scopes:
+                      custom:
+                        - scope: 'urn:mas:bridge'
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(ScopesConfig::extract(&figment).is_err());
+
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    scopes:
+                      custom:
+                        - scope: 'urn:ourcorp:"bridge"'
+                "#,
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(ScopesConfig::extract(&figment).is_err());
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    scopes:
+                      custom:
+                        - scope: 'urn:ourcorp:bridge'
+                        - scope: 'urn:ourcorp:bridge'
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(ScopesConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -4,10 +4,11 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
 
 use anyhow::{bail, Context};
 use camino::Utf8PathBuf;
+use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::jwk::{JsonWebKey, JsonWebKeySet};
 use mas_keystore::{Encrypter, Keystore, PrivateKey};
 use rand::{
@@ -26,6 +27,74 @@ fn example_secret() -> &'static str {
     "0000111122223333444455556666777788889999aaaabbbbccccddddeeeeffff"
 }
 
+const fn default_rotation_algorithm() -> JsonWebSignatureAlg {
+    JsonWebSignatureAlg::Rs256
+}
+
+const fn default_rotation_interval() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+const fn default_rotation_grace_period() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+const fn default_rotation_retention() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+/// Settings for the automated rotation of signing keys
+///
+/// When enabled, a new signing key is regularly generated and stored in the
+/// database, encrypted with the encryption secret. The new key is published
+/// in the JWKS for a grace period before it is used to sign, so that relying
+/// parties can pick it up, and older keys stay published until the tokens they
+/// signed have expired.
+///
+/// Keys from the `keys` list are still used, but a rotated key is preferred
+/// when signing with its algorithm.
+#[serde_as]
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRotationConfig {
+    /// The algorithm the generated keys are used for. Defaults to `RS256`.
+    #[serde(default = "default_rotation_algorithm")]
+    pub algorithm: JsonWebSignatureAlg,
+
+    /// How often a new key is used to sign, in seconds. Defaults to 30 days.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_rotation_interval")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub interval: Duration,
+
+    /// How long a new key is published in the JWKS before it is used to sign,
+    /// in seconds. Defaults to 1 day.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_rotation_grace_period")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub grace_period: Duration,
+
+    /// How long a key stays published in the JWKS after a newer key replaced
+    /// it, in seconds. Defaults to 1 day.
+    ///
+    /// This should be longer than the lifetime of the tokens signed by the
+    /// keys, like ID tokens.
+    #[schemars(with = "u64")]
+    #[serde(default = "default_rotation_retention")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub retention: Duration,
+}
+
+impl Default for KeyRotationConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: default_rotation_algorithm(),
+            interval: default_rotation_interval(),
+            grace_period: default_rotation_grace_period(),
+            retention: default_rotation_retention(),
+        }
+    }
+}
+
 #[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
 pub struct KeyConfig {
     kid: String,
@@ -45,6 +114,79 @@ pub struct KeyConfig {
     key_file: Option<Utf8PathBuf>,
 }
 
+/// A key pair held in a PKCS#11 token
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
+pub struct Pkcs11KeyConfig {
+    /// The key ID to publish in the JWKS
+    pub kid: String,
+
+    /// The label of the private and public key objects in the token
+    pub label: String,
+}
+
+/// Signing keys held in a PKCS#11 token, like a hardware security module
+///
+/// The private keys never leave the token: signing operations are done by the
+/// PKCS#11 module. This requires a build of MAS including the
+/// `mas-keystore-pkcs11` crate.
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
+pub struct Pkcs11Config {
+    /// Path to the PKCS#11 module shared library
+    #[schemars(with = "String")]
+    pub module: Utf8PathBuf,
+
+    /// The ID of the slot holding the token
+    pub slot: u64,
+
+    /// The user PIN of the token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+
+    /// Path to a file containing the user PIN of the token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub pin_file: Option<Utf8PathBuf>,
+
+    /// List of key pairs in the token to use for signing
+    #[serde(default)]
+    pub keys: Vec<Pkcs11KeyConfig>,
+}
+
+/// Where a key held in a cloud key management service is
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum KmsKeyReference {
+    /// A key held in AWS KMS. This requires a build of MAS including the
+    /// `mas-keystore-kms` crate.
+    Aws {
+        /// The ID or the ARN of the key, or an alias prefixed with `alias/`
+        key_id: String,
+
+        /// The region of the key. Defaults to the region configured in the
+        /// environment.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<String>,
+    },
+
+    /// A key version held in GCP Cloud KMS. This requires a build of MAS
+    /// including the `mas-keystore-kms` crate.
+    Gcp {
+        /// The full resource name of the key version, like
+        /// `projects/*/locations/*/keyRings/*/cryptoKeys/*/cryptoKeyVersions/*`
+        name: String,
+    },
+}
+
+/// A signing key held in a cloud key management service
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
+pub struct KmsKeyConfig {
+    /// The key ID to publish in the JWKS
+    pub kid: String,
+
+    #[serde(flatten)]
+    pub key: KmsKeyReference,
+}
+
 /// Application secrets
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -61,6 +203,19 @@ pub struct SecretsConfig {
     /// List of private keys to use for signing and encrypting payloads
     #[serde(default)]
     keys: Vec<KeyConfig>,
+
+    /// Automated rotation of signing keys. Disabled if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_rotation: Option<KeyRotationConfig>,
+
+    /// Signing keys held in a PKCS#11 token. Disabled if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkcs11: Option<Pkcs11Config>,
+
+    /// Signing keys held in a cloud key management service, like AWS KMS or
+    /// GCP Cloud KMS
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kms_keys: Vec<KmsKeyConfig>,
 }
 
 impl SecretsConfig {
@@ -112,6 +267,16 @@ impl SecretsConfig {
             keys.push(key);
         }
 
+        // The external key backends are not part of the workspace, see the
+        // `mas-keystore-pkcs11` and `mas-keystore-kms` crates
+        if self.pkcs11.is_some() {
+            bail!("This build does not support keys held in a PKCS#11 token");
+        }
+
+        if !self.kms_keys.is_empty() {
+            bail!("This build does not support keys held in a cloud key management service");
+        }
+
         let keys = JsonWebKeySet::new(keys);
         Ok(Keystore::new(keys))
     }
@@ -160,6 +325,53 @@ impl ConfigurationSection for SecretsConfig {
             }
         }
 
+        if let Some(pkcs11) = &self.pkcs11 {
+            let annotate = |mut error: figment::Error| {
+                error.metadata = figment
+                    .find_metadata(&format!("{root}.pkcs11", root = Self::PATH.unwrap()))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![Self::PATH.unwrap().to_owned(), "pkcs11".to_owned()];
+                Err(error)
+            };
+
+            if pkcs11.pin.is_none() && pkcs11.pin_file.is_none() {
+                return annotate(figment::Error::from(
+                    "Missing `pin` or `pin_file`".to_owned(),
+                ));
+            }
+
+            if pkcs11.pin.is_some() && pkcs11.pin_file.is_some() {
+                return annotate(figment::Error::from(
+                    "Cannot specify both `pin` and `pin_file`".to_owned(),
+                ));
+            }
+        }
+
+        if let Some(key_rotation) = &self.key_rotation {
+            let annotate = |mut error: figment::Error| {
+                error.metadata = figment
+                    .find_metadata(&format!("{root}.key_rotation", root = Self::PATH.unwrap()))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![Self::PATH.unwrap().to_owned(), "key_rotation".to_owned()];
+                Err(error)
+            };
+
+            if !PrivateKey::can_generate_for_alg(&key_rotation.algorithm) {
+                return annotate(figment::Error::from(format!(
+                    "Keys can't be generated for the {} algorithm",
+                    key_rotation.algorithm
+                )));
+            }
+
+            if key_rotation.interval <= key_rotation.grace_period {
+                return annotate(figment::Error::from(
+                    "`interval` must be longer than `grace_period`".to_owned(),
+                ));
+            }
+        }
+
         Ok(())
     }
 }
@@ -247,6 +459,9 @@ impl SecretsConfig {
         Ok(Self {
             encryption: rng.gen(),
             keys: vec![rsa_key, ec_p256_key, ec_p384_key, ec_k256_key],
+            key_rotation: None,
+            pkcs11: None,
+            kms_keys: Vec::new(),
         })
     }
 
@@ -292,6 +507,9 @@ impl SecretsConfig {
         Self {
             encryption: [0xEA; 32],
             keys: vec![rsa_key, ecdsa_key],
+            key_rotation: None,
+            pkcs11: None,
+            kms_keys: Vec::new(),
         }
     }
 }
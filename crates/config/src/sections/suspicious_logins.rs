@@ -0,0 +1,97 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use camino::Utf8PathBuf;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::ConfigurationSection;
+
+const fn default_true() -> bool {
+    true
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_true(value: &bool) -> bool {
+    *value
+}
+
+const fn default_impossible_travel_speed() -> u32 {
+    1000
+}
+
+const fn default_failed_attempts() -> u32 {
+    5
+}
+
+/// Configuration section for the detection of suspicious logins
+///
+/// Every successful login is compared with the previous logins of the same
+/// user. When it looks suspicious, an audit event is recorded and the user is
+/// notified by email, with a link to review their sessions.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SuspiciousLoginsConfig {
+    /// Whether logins should be checked. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Path to a `MaxMind` `GeoIP2` or `GeoLite2` City or Country database,
+    /// used to locate the IP address of logins
+    ///
+    /// Logins from a country the user never logged in from, and impossible
+    /// travels, are only detected if this is set. It is also used to show the
+    /// approximate location of new sign-ins, see `account.new_device_emails`,
+    /// even if suspicious login detection is disabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub geoip_database: Option<Utf8PathBuf>,
+
+    /// The speed above which travelling between two logins is considered
+    /// impossible, in kilometres per hour. Defaults to 1000 km/h.
+    ///
+    /// This needs a City database, as Country databases don't have
+    /// coordinates. Set to 0 to disable this check.
+    #[serde(default = "default_impossible_travel_speed")]
+    pub impossible_travel_speed: u32,
+
+    /// How many failed login attempts since the previous successful login make
+    /// a login suspicious. Defaults to 5.
+    ///
+    /// Set to 0 to disable this check.
+    #[serde(default = "default_failed_attempts")]
+    pub failed_attempts: u32,
+
+    /// Whether logins from a browser or device the user never logged in with
+    /// are considered suspicious. Defaults to `true`.
+    #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
+    pub new_device: bool,
+}
+
+impl Default for SuspiciousLoginsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            geoip_database: None,
+            impossible_travel_speed: default_impossible_travel_speed(),
+            failed_attempts: default_failed_attempts(),
+            new_device: default_true(),
+        }
+    }
+}
+
+impl SuspiciousLoginsConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled
+            && self.geoip_database.is_none()
+            && self.impossible_travel_speed == default_impossible_travel_speed()
+            && self.failed_attempts == default_failed_attempts()
+            && self.new_device
+    }
+}
+
+impl ConfigurationSection for SuspiciousLoginsConfig {
+    const PATH: Option<&'static str> = Some("suspicious_logins");
+}
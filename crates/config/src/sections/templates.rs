@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeMap;
+
 use camino::Utf8PathBuf;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -90,6 +92,36 @@ pub struct TemplatesConfig {
     )]
     #[schemars(with = "Option<String>")]
     pub translations_path: Utf8PathBuf,
+
+    /// Path to a folder holding additional translations.
+    ///
+    /// It has the same layout as the translations folder, with one JSON file
+    /// per locale. Messages found there replace the built-in ones, and locales
+    /// which aren't shipped are added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub translations_overrides_path: Option<Utf8PathBuf>,
+
+    /// Locales to fall back to when a message is missing, by locale.
+    ///
+    /// Messages are first looked up in the parent locales, e.g. `fr-CA` then
+    /// `fr`, then in the locales set here, then in English.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub translations_fallbacks: BTreeMap<String, Vec<String>>,
+
+    /// Path to a theme directory.
+    ///
+    /// Templates in its `templates` subdirectory replace the built-in ones
+    /// with the same name, and files in its `assets` subdirectory are served
+    /// under `/theme/`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub theme_path: Option<Utf8PathBuf>,
+
+    /// Reload the templates, translations and theme when they change on disk,
+    /// instead of only on `SIGHUP`. Useful while working on a theme.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub watch: bool,
 }
 
 impl Default for TemplatesConfig {
@@ -98,6 +130,10 @@ impl Default for TemplatesConfig {
             path: default_path(),
             assets_manifest: default_assets_path(),
             translations_path: default_translations_path(),
+            translations_overrides_path: None,
+            translations_fallbacks: BTreeMap::new(),
+            theme_path: None,
+            watch: false,
         }
     }
 }
@@ -108,6 +144,10 @@ impl TemplatesConfig {
         is_default_path(&self.path)
             && is_default_assets_path(&self.assets_manifest)
             && is_default_translations_path(&self.translations_path)
+            && self.translations_overrides_path.is_none()
+            && self.translations_fallbacks.is_empty()
+            && self.theme_path.is_none()
+            && !self.watch
     }
 }
 
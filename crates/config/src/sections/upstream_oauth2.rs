@@ -6,7 +6,10 @@
 
 use std::collections::BTreeMap;
 
-use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use camino::Utf8PathBuf;
+use chrono::{DateTime, Utc};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_jose::jwt::Jwt;
 use schemars::JsonSchema;
 use serde::{de::Error, Deserialize, Serialize};
 use serde_with::skip_serializing_none;
@@ -32,6 +35,7 @@ impl UpstreamOAuth2Config {
 impl ConfigurationSection for UpstreamOAuth2Config {
     const PATH: Option<&'static str> = Some("upstream_oauth2");
 
+    #[allow(clippy::too_many_lines)]
     fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
         for (index, provider) in self.providers.iter().enumerate() {
             let annotate = |mut error: figment::Error| {
@@ -48,7 +52,9 @@ impl ConfigurationSection for UpstreamOAuth2Config {
             };
 
             match provider.token_endpoint_auth_method {
-                TokenAuthMethod::None | TokenAuthMethod::PrivateKeyJwt => {
+                TokenAuthMethod::None
+                | TokenAuthMethod::PrivateKeyJwt
+                | TokenAuthMethod::SignInWithApple => {
                     if provider.client_secret.is_some() {
                         return annotate(figment::Error::custom("Unexpected field `client_secret` for the selected authentication method"));
                     }
@@ -65,7 +71,8 @@ impl ConfigurationSection for UpstreamOAuth2Config {
             match provider.token_endpoint_auth_method {
                 TokenAuthMethod::None
                 | TokenAuthMethod::ClientSecretBasic
-                | TokenAuthMethod::ClientSecretPost => {
+                | TokenAuthMethod::ClientSecretPost
+                | TokenAuthMethod::SignInWithApple => {
                     if provider.token_endpoint_auth_signing_alg.is_some() {
                         return annotate(figment::Error::custom(
                             "Unexpected field `token_endpoint_auth_signing_alg` for the selected authentication method",
@@ -80,6 +87,68 @@ impl ConfigurationSection for UpstreamOAuth2Config {
                     }
                 }
             }
+
+            match provider.token_endpoint_auth_method {
+                TokenAuthMethod::SignInWithApple => {
+                    let Some(sign_in_with_apple) = &provider.sign_in_with_apple else {
+                        return annotate(figment::Error::missing_field("sign_in_with_apple"));
+                    };
+
+                    match (
+                        &sign_in_with_apple.private_key,
+                        &sign_in_with_apple.private_key_file,
+                    ) {
+                        (None, None) => {
+                            return annotate(figment::Error::custom(
+                                "Missing `private_key` or `private_key_file` in `sign_in_with_apple`",
+                            ));
+                        }
+                        (Some(_), Some(_)) => {
+                            return annotate(figment::Error::custom(
+                                "Cannot specify both `private_key` and `private_key_file` in `sign_in_with_apple`",
+                            ));
+                        }
+                        _ => {}
+                    }
+                }
+                TokenAuthMethod::None
+                | TokenAuthMethod::ClientSecretBasic
+                | TokenAuthMethod::ClientSecretPost
+                | TokenAuthMethod::ClientSecretJwt
+                | TokenAuthMethod::PrivateKeyJwt => {
+                    if provider.sign_in_with_apple.is_some() {
+                        return annotate(figment::Error::custom(
+                            "Unexpected field `sign_in_with_apple` for the selected authentication method",
+                        ));
+                    }
+                }
+            }
+
+            if matches!(
+                provider.pushed_authorization_requests,
+                PushedAuthorizationRequestsMode::Always
+            ) && matches!(provider.discovery_mode, DiscoveryMode::Disabled)
+                && provider.pushed_authorization_request_endpoint.is_none()
+            {
+                return annotate(figment::Error::missing_field(
+                    "pushed_authorization_request_endpoint",
+                ));
+            }
+
+            if provider.fetch_userinfo
+                && matches!(provider.discovery_mode, DiscoveryMode::Disabled)
+                && provider.userinfo_endpoint.is_none()
+            {
+                return annotate(figment::Error::missing_field("userinfo_endpoint"));
+            }
+
+            if !provider.claims_imports.admin.action.is_default()
+                && provider.claims_imports.admin.template.is_none()
+            {
+                return annotate(figment::Error::custom(
+                    "Missing `template` in `claims_imports.admin`",
+                ));
+            }
         }
 
         Ok(())
@@ -108,20 +177,63 @@ pub enum TokenAuthMethod {
     /// `private_key_jwt`: a `client_assertion` sent in the request body and
     /// signed by an asymmetric key
     PrivateKeyJwt,
+
+    /// `sign_in_with_apple`: a special method for Sign in with Apple, where
+    /// the `client_secret` is a JWT signed with the key issued by Apple
+    SignInWithApple,
 }
 
-impl From<TokenAuthMethod> for OAuthClientAuthenticationMethod {
-    fn from(method: TokenAuthMethod) -> Self {
-        match method {
-            TokenAuthMethod::None => OAuthClientAuthenticationMethod::None,
-            TokenAuthMethod::ClientSecretBasic => {
-                OAuthClientAuthenticationMethod::ClientSecretBasic
-            }
-            TokenAuthMethod::ClientSecretPost => OAuthClientAuthenticationMethod::ClientSecretPost,
-            TokenAuthMethod::ClientSecretJwt => OAuthClientAuthenticationMethod::ClientSecretJwt,
-            TokenAuthMethod::PrivateKeyJwt => OAuthClientAuthenticationMethod::PrivateKeyJwt,
-        }
-    }
+/// How the provider should send the authorization response back
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// `query`: the response is sent in the query string of a redirect to the
+    /// callback
+    Query,
+
+    /// `form_post`: the response is sent in the body of a form submitted to
+    /// the callback with a `POST` request
+    ///
+    /// This is required by Sign in with Apple when requesting the `name` or
+    /// `email` scopes
+    FormPost,
+}
+
+/// Settings used to generate the client secret for Sign in with Apple
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SignInWithApple {
+    /// The private key issued by Apple to sign the client secret, in PEM
+    /// format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+
+    /// Path to the `.p8` file containing the private key issued by Apple
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub private_key_file: Option<Utf8PathBuf>,
+
+    /// The ID of the team which owns the Services ID
+    pub team_id: String,
+
+    /// The ID of the private key
+    pub key_id: String,
+}
+
+/// Settings for the logins initiated by the provider
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct IdpInitiatedLogin {
+    /// The ID of the client on which the user lands after logging in, through
+    /// the client `initiate_login_uri`
+    ///
+    /// If not set, the user lands on their account page.
+    #[schemars(
+        with = "Option<String>",
+        regex(pattern = r"^[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}$"),
+        description = "A ULID as per https://github.com/ulid/spec"
+    )]
+    pub client_id: Option<Ulid>,
 }
 
 /// How to handle a claim
@@ -172,6 +284,31 @@ impl SetEmailVerification {
     }
 }
 
+/// What should be done when the email address asserted by the provider for a
+/// linked account changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEmailChange {
+    /// Only keep track of the new email address
+    #[default]
+    Ignore,
+
+    /// Ask an administrator to review the change through the admin API
+    Review,
+
+    /// Add the new email address to the account, and make it the primary one
+    /// if it can be marked as verified. If it already belongs to another
+    /// account, ask an administrator to review the change instead.
+    Update,
+}
+
+impl OnEmailChange {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    const fn is_default(&self) -> bool {
+        matches!(self, OnEmailChange::Ignore)
+    }
+}
+
 /// What should be done for the subject attribute
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
 pub struct SubjectImportPreference {
@@ -228,6 +365,33 @@ impl DisplaynameImportPreference {
     }
 }
 
+/// What should be done with the avatar attribute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+pub struct AvatarImportPreference {
+    /// How to handle the attribute
+    ///
+    /// There is no way for users to opt out of the import on the registration
+    /// form, so the `suggest` action behaves like `force`.
+    #[serde(default, skip_serializing_if = "ImportAction::is_default")]
+    pub action: ImportAction,
+
+    /// The Jinja2 template to use for the avatar attribute
+    ///
+    /// It should render to either an `mxc://` URI or an `https://` URL. In the
+    /// latter case, the image is downloaded and uploaded to the homeserver
+    /// media repository.
+    ///
+    /// If not provided, the default template is `{{ user.picture }}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+impl AvatarImportPreference {
+    const fn is_default(&self) -> bool {
+        self.action.is_default() && self.template.is_none()
+    }
+}
+
 /// What should be done with the email attribute
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
 pub struct EmailImportPreference {
@@ -244,6 +408,14 @@ pub struct EmailImportPreference {
     /// Should the email address be marked as verified
     #[serde(default, skip_serializing_if = "SetEmailVerification::is_default")]
     pub set_email_verification: SetEmailVerification,
+
+    /// What to do when the email address asserted by the provider changes for
+    /// an account which is already linked
+    ///
+    /// The change is detected on login, and the claim is not imported if the
+    /// action is `ignore`.
+    #[serde(default, skip_serializing_if = "OnEmailChange::is_default")]
+    pub on_change: OnEmailChange,
 }
 
 impl EmailImportPreference {
@@ -251,6 +423,32 @@ impl EmailImportPreference {
         self.action.is_default()
             && self.template.is_none()
             && self.set_email_verification.is_default()
+            && self.on_change.is_default()
+    }
+}
+
+/// What should be done with the admin attribute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+pub struct AdminImportPreference {
+    /// How to handle the attribute
+    ///
+    /// If not ignored, the `can_request_admin` flag of the user is set
+    /// according to the template on each login. There is no way for users to
+    /// opt out of it, so the `suggest` action behaves like `force`.
+    #[serde(default, skip_serializing_if = "ImportAction::is_default")]
+    pub action: ImportAction,
+
+    /// The Jinja2 template to use for the admin attribute
+    ///
+    /// It must render to either `true` or `false`, for example `{{ "admins"
+    /// in user.groups }}`. It is required if the attribute is not ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+impl AdminImportPreference {
+    const fn is_default(&self) -> bool {
+        self.action.is_default() && self.template.is_none()
     }
 }
 
@@ -272,10 +470,19 @@ pub struct ClaimsImports {
     )]
     pub displayname: DisplaynameImportPreference,
 
+    /// Import the avatar of the user
+    #[serde(default, skip_serializing_if = "AvatarImportPreference::is_default")]
+    pub avatar: AvatarImportPreference,
+
     /// Import the email address of the user based on the `email` and
     /// `email_verified` claims
     #[serde(default, skip_serializing_if = "EmailImportPreference::is_default")]
     pub email: EmailImportPreference,
+
+    /// Set whether the user can request admin privileges, for example based
+    /// on the groups they belong to
+    #[serde(default, skip_serializing_if = "AdminImportPreference::is_default")]
+    pub admin: AdminImportPreference,
 }
 
 impl ClaimsImports {
@@ -283,7 +490,9 @@ impl ClaimsImports {
         self.subject.is_default()
             && self.localpart.is_default()
             && self.displayname.is_default()
+            && self.avatar.is_default()
             && self.email.is_default()
+            && self.admin.is_default()
     }
 }
 
@@ -334,6 +543,30 @@ impl PkceMethod {
     }
 }
 
+/// Whether to use pushed authorization requests (PAR) with the provider
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PushedAuthorizationRequestsMode {
+    /// Use PAR if the provider requires it
+    ///
+    /// Defaults to not using PAR if provider discovery is disabled
+    #[default]
+    Auto,
+
+    /// Always use PAR
+    Always,
+
+    /// Never use PAR
+    Never,
+}
+
+impl PushedAuthorizationRequestsMode {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    const fn is_default(&self) -> bool {
+        matches!(self, PushedAuthorizationRequestsMode::Auto)
+    }
+}
+
 fn default_true() -> bool {
     true
 }
@@ -391,6 +624,15 @@ pub struct Provider {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_secret: Option<String>,
 
+    /// When the client secret expires, for providers which issue secrets
+    /// with a limited lifetime, like Microsoft Entra ID
+    ///
+    /// Used to warn the administrators before logins through this provider
+    /// start failing. If not set, the expiry is read from the `exp` claim of
+    /// the client secret if it is a JWT.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret_expires_at: Option<DateTime<Utc>>,
+
     /// The method to authenticate the client with the provider
     pub token_endpoint_auth_method: TokenAuthMethod,
 
@@ -401,6 +643,12 @@ pub struct Provider {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
 
+    /// Settings used to generate the client secret
+    ///
+    /// Used by the `sign_in_with_apple` method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sign_in_with_apple: Option<SignInWithApple>,
+
     /// The scopes to request from the provider
     pub scope: String,
 
@@ -436,8 +684,58 @@ pub struct Provider {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwks_uri: Option<Url>,
 
-    /// How claims should be imported from the `id_token` provided by the
-    /// provider
+    /// Whether to push the authorization request to the provider before
+    /// redirecting the user, as per RFC 9126.
+    ///
+    /// Defaults to `auto`, which uses PAR if the provider requires it.
+    #[serde(
+        default,
+        skip_serializing_if = "PushedAuthorizationRequestsMode::is_default"
+    )]
+    pub pushed_authorization_requests: PushedAuthorizationRequestsMode,
+
+    /// The URL to use for the provider's pushed authorization request endpoint
+    ///
+    /// Defaults to the `pushed_authorization_request_endpoint` provided through
+    /// discovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pushed_authorization_request_endpoint: Option<Url>,
+
+    /// The JWS algorithm to use to sign the authorization request as a request
+    /// object, as per RFC 9101.
+    ///
+    /// The request object is signed with one of the keys from the `secrets`
+    /// section. If not set, the request is only signed if the provider
+    /// requires it, using `RS256`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_object_signing_alg: Option<JsonWebSignatureAlg>,
+
+    /// How the provider should send the authorization response back
+    ///
+    /// Defaults to letting the provider decide, which usually means the
+    /// response is sent in the query string. Sign in with Apple needs this to
+    /// be set to `form_post`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mode: Option<ResponseMode>,
+
+    /// Whether to fetch the user profile from the provider's userinfo
+    /// endpoint after the authorization code exchange.
+    ///
+    /// The response is merged with the claims of the `id_token`, and made
+    /// available to the `claims_imports` templates. When enabled, providers
+    /// which don't issue ID tokens, like plain OAuth 2.0 providers, can be
+    /// used. Defaults to `false`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fetch_userinfo: bool,
+
+    /// The URL to use for the provider's userinfo endpoint
+    ///
+    /// Defaults to the `userinfo_endpoint` provided through discovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userinfo_endpoint: Option<Url>,
+
+    /// How claims should be imported from the `id_token` and userinfo
+    /// response provided by the provider
     #[serde(default, skip_serializing_if = "ClaimsImports::is_default")]
     pub claims_imports: ClaimsImports,
 
@@ -446,4 +744,48 @@ pub struct Provider {
     /// Orders of the keys are not preserved.
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub additional_authorization_parameters: BTreeMap<String, String>,
+
+    /// Allow the provider to initiate logins, by sending users to the
+    /// `/upstream/initiate/{id}` endpoint with its issuer in the `iss`
+    /// parameter
+    ///
+    /// Disabled if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idp_initiated_login: Option<IdpInitiatedLogin>,
+
+    /// Whether to log users out of the provider when they end their session,
+    /// by redirecting them through the provider's end session endpoint
+    ///
+    /// Only applies to sessions which were last authenticated through this
+    /// provider. Defaults to `false`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub propagate_logout: bool,
+
+    /// The URL to use for the provider's end session endpoint
+    ///
+    /// Defaults to the `end_session_endpoint` provided through discovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_session_endpoint: Option<Url>,
+}
+
+impl Provider {
+    /// When the client secret expires, if known
+    ///
+    /// This is either the configured `client_secret_expires_at`, or the `exp`
+    /// claim of the client secret if it is a JWT
+    #[must_use]
+    pub fn client_secret_expiry(&self) -> Option<DateTime<Utc>> {
+        #[derive(Deserialize)]
+        struct Claims {
+            exp: Option<i64>,
+        }
+
+        if let Some(expires_at) = self.client_secret_expires_at {
+            return Some(expires_at);
+        }
+
+        let client_secret = self.client_secret.as_deref()?;
+        let jwt = Jwt::<Claims>::try_from(client_secret).ok()?;
+        DateTime::from_timestamp(jwt.payload().exp?, 0)
+    }
 }
@@ -0,0 +1,316 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::str::FromStr;
+
+use camino::Utf8PathBuf;
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::ConfigurationSection;
+
+/// The prefix of configuration values which should be fetched from Vault
+pub const VAULT_REFERENCE_PREFIX: &str = "vault:";
+
+fn default_approle_mount() -> String {
+    "approle".to_owned()
+}
+
+fn default_kubernetes_mount() -> String {
+    "kubernetes".to_owned()
+}
+
+fn default_kubernetes_jwt_file() -> Utf8PathBuf {
+    "/var/run/secrets/kubernetes.io/serviceaccount/token".into()
+}
+
+/// How to authenticate against Vault
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum VaultAuthConfig {
+    /// Use a static token
+    Token {
+        /// The token to use
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+
+        /// Path to a file containing the token to use. The file is read again
+        /// each time secrets are fetched, so that it can be renewed by an
+        /// external agent.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(with = "Option<String>")]
+        token_file: Option<Utf8PathBuf>,
+    },
+
+    /// Log in with the `AppRole` auth method
+    #[serde(rename = "approle")]
+    AppRole {
+        /// The path where the auth method is mounted
+        #[serde(default = "default_approle_mount")]
+        mount: String,
+
+        /// The role ID to log in with
+        role_id: String,
+
+        /// The secret ID to log in with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret_id: Option<String>,
+
+        /// Path to a file containing the secret ID to log in with
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[schemars(with = "Option<String>")]
+        secret_id_file: Option<Utf8PathBuf>,
+    },
+
+    /// Log in with the Kubernetes auth method, using the token of the service
+    /// account MAS runs as
+    Kubernetes {
+        /// The path where the auth method is mounted
+        #[serde(default = "default_kubernetes_mount")]
+        mount: String,
+
+        /// The role to log in as
+        role: String,
+
+        /// Path to the service account token
+        #[serde(default = "default_kubernetes_jwt_file")]
+        #[schemars(with = "String")]
+        jwt_file: Utf8PathBuf,
+    },
+}
+
+/// Fetch the database credentials from a Vault secrets engine which generates
+/// them dynamically, like the database secrets engine
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct VaultDatabaseCredentialsConfig {
+    /// The path of the credentials, like `database/creds/mas`
+    ///
+    /// The secret must have a `username` and a `password` field. The
+    /// credentials are fetched again before their lease expires, and used for
+    /// the new connections to the database.
+    pub path: String,
+}
+
+/// Configuration section to fetch secrets from `HashiCorp` Vault
+///
+/// When configured, any string in the configuration of the form
+/// `vault:<path>#<field>` is replaced on startup by the field of the secret
+/// read at that path, for example `vault:secret/data/mas#smtp_password`.
+/// Secrets from both versions of the key/value secrets engine are supported.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct VaultConfig {
+    /// The address of the Vault server, like `https://vault.example.com:8200/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<Url>,
+
+    /// The Vault Enterprise namespace to use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+
+    /// How to authenticate against Vault
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<VaultAuthConfig>,
+
+    /// Fetch the database credentials dynamically from Vault. This cannot be
+    /// used with the `database.uri` option.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database_credentials: Option<VaultDatabaseCredentialsConfig>,
+}
+
+impl VaultConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.address.is_none()
+            && self.namespace.is_none()
+            && self.auth.is_none()
+            && self.database_credentials.is_none()
+    }
+
+    /// Returns true if Vault is configured
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        self.address.is_some()
+    }
+}
+
+impl ConfigurationSection for VaultConfig {
+    const PATH: Option<&'static str> = Some("vault");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        let annotate = |mut error: figment::Error, field: &str| {
+            error.metadata = figment
+                .find_metadata(&format!("{root}.{field}", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), field.to_owned()];
+            Err(error)
+        };
+
+        if self.address.is_none() {
+            if !self.is_default() {
+                return annotate(
+                    figment::Error::custom("The address of the Vault server must be set"),
+                    "address",
+                );
+            }
+
+            return Ok(());
+        }
+
+        match &self.auth {
+            None => {
+                return annotate(
+                    figment::Error::custom("The Vault authentication method must be set"),
+                    "auth",
+                );
+            }
+
+            Some(VaultAuthConfig::Token { token, token_file }) => {
+                if token.is_some() == token_file.is_some() {
+                    return annotate(
+                        figment::Error::custom(
+                            "Exactly one of `token` and `token_file` must be set",
+                        ),
+                        "auth",
+                    );
+                }
+            }
+
+            Some(VaultAuthConfig::AppRole {
+                secret_id,
+                secret_id_file,
+                ..
+            }) => {
+                if secret_id.is_some() == secret_id_file.is_some() {
+                    return annotate(
+                        figment::Error::custom(
+                            "Exactly one of `secret_id` and `secret_id_file` must be set",
+                        ),
+                        "auth",
+                    );
+                }
+            }
+
+            Some(VaultAuthConfig::Kubernetes { .. }) => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// A reference to a field of a secret stored in Vault, in the
+/// `vault:<path>#<field>` form
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VaultReference {
+    /// The path of the secret, like `secret/data/mas`
+    pub path: String,
+
+    /// The field of the secret to use
+    pub field: String,
+}
+
+/// The error returned when a `vault:` reference is invalid
+#[derive(Debug, Error)]
+#[error("Invalid Vault reference {0:?}, expected `vault:<path>#<field>`")]
+pub struct InvalidVaultReferenceError(String);
+
+impl VaultReference {
+    /// Returns `true` if the given configuration value references a Vault
+    /// secret
+    #[must_use]
+    pub fn is_reference(value: &str) -> bool {
+        value.starts_with(VAULT_REFERENCE_PREFIX)
+    }
+}
+
+impl FromStr for VaultReference {
+    type Err = InvalidVaultReferenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidVaultReferenceError(s.to_owned());
+        let reference = s.strip_prefix(VAULT_REFERENCE_PREFIX).ok_or_else(invalid)?;
+        let (path, field) = reference.rsplit_once('#').ok_or_else(invalid)?;
+        let path = path.trim_matches('/');
+
+        if path.is_empty() || field.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            field: field.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn parse_references() {
+        let reference: VaultReference = "vault:secret/data/mas#password".parse().unwrap();
+        assert_eq!(reference.path, "secret/data/mas");
+        assert_eq!(reference.field, "password");
+
+        let reference: VaultReference = "vault:/kv/mas/#key".parse().unwrap();
+        assert_eq!(reference.path, "kv/mas");
+        assert_eq!(reference.field, "key");
+
+        assert!("vault:secret/data/mas".parse::<VaultReference>().is_err());
+        assert!("vault:#password".parse::<VaultReference>().is_err());
+        assert!("vault:secret/data/mas#".parse::<VaultReference>().is_err());
+        assert!("secret/data/mas#password"
+            .parse::<VaultReference>()
+            .is_err());
+
+        assert!(VaultReference::is_reference(
+            "vault:secret/data/mas#password"
+        ));
+        assert!(!VaultReference::is_reference("hunter2"));
+    }
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    vault:
+                      address: https://vault.example.com:8200/
+                      auth:
+                        method: approle
+                        role_id: mas
+                        secret_id_file: /run/secrets/vault-secret-id
+                      database_credentials:
+                        path: database/creds/mas
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<VaultConfig>("vault")?;
+
+            assert!(config.enabled());
+            assert!(matches!(
+                config.auth,
+                Some(VaultAuthConfig::AppRole { ref mount, .. }) if mount == "approle"
+            ));
+            assert_eq!(
+                config.database_credentials.unwrap().path,
+                "database/creds/mas"
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{collections::BTreeSet, time::Duration};
+
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
+
+use crate::ConfigurationSection;
+
+/// The kind of events which can be sent to webhook endpoints
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema,
+)]
+pub enum WebhookEvent {
+    /// A new user was registered
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+
+    /// A user was deactivated
+    #[serde(rename = "user.deactivated")]
+    UserDeactivated,
+
+    /// A user was reactivated
+    #[serde(rename = "user.reactivated")]
+    UserReactivated,
+
+    /// A user was updated, for example locked or granted admin rights
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+
+    /// A user started a new session
+    #[serde(rename = "session.created")]
+    SessionCreated,
+
+    /// A session was ended
+    #[serde(rename = "session.revoked")]
+    SessionRevoked,
+
+    /// A honeytoken was used
+    #[serde(rename = "honeytoken.used")]
+    HoneytokenUsed,
+}
+
+/// An endpoint to which webhook events are sent
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct WebhookEndpointConfig {
+    /// The URL to which events are POSTed
+    pub url: Url,
+
+    /// The secret used to sign the requests with HMAC-SHA256
+    pub secret: String,
+
+    /// Which events to send to this endpoint.
+    ///
+    /// Defaults to sending all events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<BTreeSet<WebhookEvent>>,
+}
+
+fn default_changes_feed_retention() -> Duration {
+    Duration::from_secs(7 * 24 * 60 * 60)
+}
+
+/// Configuration of the changes feed, which records the webhook events so that
+/// they can be polled through the admin API
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ChangesFeedConfig {
+    /// Whether the events are recorded in the changes feed.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How long entries are kept in the changes feed, in seconds. Consumers
+    /// which poll less often than this may miss entries.
+    ///
+    /// Defaults to 7 days.
+    #[schemars(with = "u64", range(min = 60))]
+    #[serde(default = "default_changes_feed_retention")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub retention: Duration,
+}
+
+impl Default for ChangesFeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention: default_changes_feed_retention(),
+        }
+    }
+}
+
+impl ChangesFeedConfig {
+    fn is_default(&self) -> bool {
+        !self.enabled && self.retention == default_changes_feed_retention()
+    }
+}
+
+/// Configuration section for outbound webhooks
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct WebhooksConfig {
+    /// List of endpoints to which events are sent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<WebhookEndpointConfig>,
+
+    /// Configuration of the changes feed
+    #[serde(default, skip_serializing_if = "ChangesFeedConfig::is_default")]
+    pub changes_feed: ChangesFeedConfig,
+}
+
+impl WebhooksConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        self.endpoints.is_empty() && self.changes_feed.is_default()
+    }
+}
+
+impl ConfigurationSection for WebhooksConfig {
+    const PATH: Option<&'static str> = Some("webhooks");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.changes_feed.retention < Duration::from_secs(60) {
+            let mut error =
+                figment::Error::custom("The changes feed retention must be at least one minute");
+            error.metadata = figment
+                .find_metadata(&format!("{root}.changes_feed", root = Self::PATH.unwrap()))
+                .cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "changes_feed".to_owned(),
+                "retention".to_owned(),
+            ];
+            return Err(error);
+        }
+
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            let annotate = |mut error: figment::Error| {
+                error.metadata = figment
+                    .find_metadata(&format!("{root}.endpoints", root = Self::PATH.unwrap()))
+                    .cloned();
+                error.profile = Some(figment::Profile::Default);
+                error.path = vec![
+                    Self::PATH.unwrap().to_owned(),
+                    "endpoints".to_owned(),
+                    index.to_string(),
+                ];
+                Err(error)
+            };
+
+            if endpoint.secret.is_empty() {
+                return annotate(figment::Error::custom(
+                    "The webhook signing secret must not be empty",
+                ));
+            }
+
+            if endpoint.events.as_ref().is_some_and(BTreeSet::is_empty) {
+                return annotate(figment::Error::custom(
+                    "The list of webhook events must not be empty; remove it to send all events",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    webhooks:
+                      endpoints:
+                        - url: https://example.com/all
+                          secret: hunter2
+                        - url: https://example.com/users
+                          secret: hunter3
+                          events:
+                            - user.registered
+                            - user.deactivated
+                      changes_feed:
+                        enabled: true
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<WebhooksConfig>("webhooks")?;
+
+            assert_eq!(config.endpoints.len(), 2);
+            assert_eq!(config.endpoints[0].url.as_str(), "https://example.com/all");
+            assert!(config.endpoints[0].events.is_none());
+            assert_eq!(
+                config.endpoints[1].events,
+                Some(BTreeSet::from([
+                    WebhookEvent::UserRegistered,
+                    WebhookEvent::UserDeactivated
+                ]))
+            );
+            assert!(config.changes_feed.enabled);
+            assert_eq!(
+                config.changes_feed.retention,
+                Duration::from_secs(7 * 24 * 60 * 60)
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use ulid::Ulid;
+
+/// The kind of action recorded in an [`AuditEvent`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    /// A user successfully logged in
+    Login,
+
+    /// A user failed to log in
+    LoginFailed,
+
+    /// The password of a user was changed
+    PasswordChanged,
+
+    /// A session was ended, either by the user or by an administrator
+    SessionEnded,
+
+    /// An administrator performed an action on a user
+    AdminAction,
+
+    /// An emergency access link was issued for an administrator
+    EmergencyAccessIssued,
+
+    /// A refresh token which was already used was presented again, and the
+    /// session it belongs to was ended
+    RefreshTokenReused,
+
+    /// A user authorized a client to access their account
+    ClientAuthorized,
+
+    /// A honeytoken was used, which means the credentials it impersonates
+    /// leaked
+    HoneytokenUsed,
+
+    /// A login looked suspicious, e.g. because it came from an unusual
+    /// location or device
+    SuspiciousLogin,
+}
+
+impl AuditEventKind {
+    /// Get the string representation of this kind, as stored in the database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::LoginFailed => "login_failed",
+            Self::PasswordChanged => "password_changed",
+            Self::SessionEnded => "session_ended",
+            Self::AdminAction => "admin_action",
+            Self::EmergencyAccessIssued => "emergency_access_issued",
+            Self::RefreshTokenReused => "refresh_token_reused",
+            Self::ClientAuthorized => "client_authorized",
+            Self::HoneytokenUsed => "honeytoken_used",
+            Self::SuspiciousLogin => "suspicious_login",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid [`AuditEventKind`]
+#[derive(Debug, Error)]
+#[error("invalid audit event kind {0:?}")]
+pub struct InvalidAuditEventKindError(String);
+
+impl std::str::FromStr for AuditEventKind {
+    type Err = InvalidAuditEventKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "login" => Ok(Self::Login),
+            "login_failed" => Ok(Self::LoginFailed),
+            "password_changed" => Ok(Self::PasswordChanged),
+            "session_ended" => Ok(Self::SessionEnded),
+            "admin_action" => Ok(Self::AdminAction),
+            "emergency_access_issued" => Ok(Self::EmergencyAccessIssued),
+            "refresh_token_reused" => Ok(Self::RefreshTokenReused),
+            "client_authorized" => Ok(Self::ClientAuthorized),
+            "honeytoken_used" => Ok(Self::HoneytokenUsed),
+            "suspicious_login" => Ok(Self::SuspiciousLogin),
+            _ => Err(InvalidAuditEventKindError(s.to_owned())),
+        }
+    }
+}
+
+/// A security-relevant event, recorded in the audit log
+///
+/// Events are chained together: each event includes the hash of the previous
+/// one in its own hash, so that modifying or removing an event can be
+/// detected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEvent {
+    pub id: Ulid,
+    pub created_at: DateTime<Utc>,
+    pub kind: AuditEventKind,
+
+    /// The user who performed the action, if any
+    pub actor_user_id: Option<Ulid>,
+
+    /// The user the action was performed on, if any
+    pub user_id: Option<Ulid>,
+
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+
+    /// Additional details about the event
+    pub details: serde_json::Value,
+
+    /// The hash of the previous event in the chain, if any
+    pub previous_hash: Option<String>,
+
+    /// The hash of this event, including the previous hash
+    pub hash: String,
+}
+
+impl AuditEvent {
+    /// Compute the hash of this event, based on its content and the hash of
+    /// the previous event
+    #[must_use]
+    pub fn compute_hash(&self) -> String {
+        // The timestamp is hashed with a microsecond precision, as this is the
+        // precision it is stored with in the database
+        let content = serde_json::json!([
+            self.previous_hash,
+            self.id.to_string(),
+            self.created_at.timestamp_micros(),
+            self.kind.as_str(),
+            self.actor_user_id.map(|id| id.to_string()),
+            self.user_id.map(|id| id.to_string()),
+            self.ip_address.map(|ip| ip.to_string()),
+            self.user_agent,
+            self.details,
+        ]);
+
+        let digest = Sha256::digest(content.to_string().as_bytes());
+        digest.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Returns `true` if the hash of this event matches its content
+    #[must_use]
+    pub fn is_hash_valid(&self) -> bool {
+        self.hash == self.compute_hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_kind_round_trip() {
+        for kind in [
+            AuditEventKind::Login,
+            AuditEventKind::LoginFailed,
+            AuditEventKind::PasswordChanged,
+            AuditEventKind::SessionEnded,
+            AuditEventKind::AdminAction,
+            AuditEventKind::EmergencyAccessIssued,
+            AuditEventKind::RefreshTokenReused,
+            AuditEventKind::ClientAuthorized,
+            AuditEventKind::HoneytokenUsed,
+            AuditEventKind::SuspiciousLogin,
+        ] {
+            assert_eq!(AuditEventKind::from_str(kind.as_str()).unwrap(), kind);
+        }
+
+        assert!(AuditEventKind::from_str("unknown").is_err());
+    }
+
+    #[test]
+    fn test_hash_chain() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut event = AuditEvent {
+            id: Ulid::nil(),
+            created_at: now,
+            kind: AuditEventKind::Login,
+            actor_user_id: None,
+            user_id: Some(Ulid::nil()),
+            ip_address: None,
+            user_agent: None,
+            details: serde_json::json!({ "method": "password" }),
+            previous_hash: None,
+            hash: String::new(),
+        };
+        event.hash = event.compute_hash();
+        assert!(event.is_hash_valid());
+
+        let mut next = event.clone();
+        next.previous_hash = Some(event.hash.clone());
+        next.hash = next.compute_hash();
+        assert!(next.is_hash_valid());
+        assert_ne!(next.hash, event.hash);
+
+        // Tampering with the content invalidates the hash
+        next.kind = AuditEventKind::LoginFailed;
+        assert!(!next.is_hash_valid());
+    }
+}
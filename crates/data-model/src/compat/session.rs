@@ -78,6 +78,7 @@ pub struct CompatSession {
     pub user_agent: Option<UserAgent>,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
+    pub tags: Vec<String>,
 }
 
 impl std::ops::Deref for CompatSession {
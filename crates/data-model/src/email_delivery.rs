@@ -0,0 +1,28 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+
+/// An email which could not be delivered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmailDeliveryFailure {
+    pub id: Ulid,
+    pub created_at: DateTime<Utc>,
+
+    /// The address the email was sent to
+    pub email: String,
+
+    /// The domain of the address, lowercased
+    pub domain: String,
+
+    /// Whether the mail server rejected the email permanently. No more emails
+    /// are sent to addresses which hard-bounced.
+    pub permanent: bool,
+
+    /// The error returned by the mail server
+    pub reason: String,
+}
@@ -0,0 +1,82 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+/// What kind of credential an [`ExpiringCredential`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiringCredentialKind {
+    /// The TLS certificate served by an HTTP listener
+    TlsCertificate,
+
+    /// The client secret used to authenticate with an upstream OAuth 2.0
+    /// provider
+    UpstreamClientSecret,
+
+    /// A signing key which is planned to be retired by the key rotation
+    SigningKey,
+}
+
+impl ExpiringCredentialKind {
+    /// Get the string representation of this kind, as stored in the database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::TlsCertificate => "tls_certificate",
+            Self::UpstreamClientSecret => "upstream_client_secret",
+            Self::SigningKey => "signing_key",
+        }
+    }
+}
+
+impl std::fmt::Display for ExpiringCredentialKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A credential which stops working at a known point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExpiringCredential {
+    /// What kind of credential this is
+    pub kind: ExpiringCredentialKind,
+
+    /// A name identifying the credential among the ones of the same kind, like
+    /// the listener name, the provider ID or the key ID
+    pub name: String,
+
+    /// When the credential stops working
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ExpiringCredential {
+    /// Create a new [`ExpiringCredential`]
+    #[must_use]
+    pub fn new(
+        kind: ExpiringCredentialKind,
+        name: impl Into<String>,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+            expires_at,
+        }
+    }
+
+    /// Whether the credential expires within the given threshold
+    #[must_use]
+    pub fn expires_within(&self, threshold: Duration, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now + threshold
+    }
+
+    /// Whether the credential already expired
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+use ulid::Ulid;
+
+use crate::TokenType;
+
+/// What a [`Honeytoken`] impersonates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoneytokenKind {
+    /// An OAuth 2.0 access token
+    AccessToken,
+
+    /// An OAuth 2.0 refresh token
+    RefreshToken,
+
+    /// A compatibility access token
+    CompatAccessToken,
+
+    /// A compatibility refresh token
+    CompatRefreshToken,
+
+    /// A user account, which can't actually be logged into
+    Account,
+}
+
+impl HoneytokenKind {
+    /// Get the string representation of this kind, as stored in the database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::AccessToken => "access_token",
+            Self::RefreshToken => "refresh_token",
+            Self::CompatAccessToken => "compat_access_token",
+            Self::CompatRefreshToken => "compat_refresh_token",
+            Self::Account => "account",
+        }
+    }
+
+    /// The type of token to generate for this kind of honeytoken, or `None`
+    /// for decoy accounts
+    #[must_use]
+    pub const fn token_type(self) -> Option<TokenType> {
+        match self {
+            Self::AccessToken => Some(TokenType::AccessToken),
+            Self::RefreshToken => Some(TokenType::RefreshToken),
+            Self::CompatAccessToken => Some(TokenType::CompatAccessToken),
+            Self::CompatRefreshToken => Some(TokenType::CompatRefreshToken),
+            Self::Account => None,
+        }
+    }
+}
+
+impl From<TokenType> for HoneytokenKind {
+    fn from(token_type: TokenType) -> Self {
+        match token_type {
+            TokenType::AccessToken => Self::AccessToken,
+            TokenType::RefreshToken => Self::RefreshToken,
+            TokenType::CompatAccessToken => Self::CompatAccessToken,
+            TokenType::CompatRefreshToken => Self::CompatRefreshToken,
+        }
+    }
+}
+
+impl std::fmt::Display for HoneytokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid [`HoneytokenKind`]
+#[derive(Debug, Error)]
+#[error("invalid honeytoken kind {0:?}")]
+pub struct InvalidHoneytokenKindError(String);
+
+impl std::str::FromStr for HoneytokenKind {
+    type Err = InvalidHoneytokenKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "access_token" => Ok(Self::AccessToken),
+            "refresh_token" => Ok(Self::RefreshToken),
+            "compat_access_token" => Ok(Self::CompatAccessToken),
+            "compat_refresh_token" => Ok(Self::CompatRefreshToken),
+            "account" => Ok(Self::Account),
+            _ => Err(InvalidHoneytokenKindError(s.to_owned())),
+        }
+    }
+}
+
+/// A decoy token or account, planted to detect credential leaks
+///
+/// It never grants access to anything: any attempt to use it is reported to
+/// the administrators.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Honeytoken {
+    pub id: Ulid,
+    pub created_at: DateTime<Utc>,
+    pub kind: HoneytokenKind,
+
+    /// The decoy token, or the username of the decoy account
+    pub value: String,
+
+    /// A free-form label, to remember where the honeytoken was planted
+    pub label: String,
+
+    /// When the honeytoken was last used, if ever
+    pub last_used_at: Option<DateTime<Utc>>,
+}
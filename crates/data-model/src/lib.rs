@@ -8,13 +8,23 @@
 
 use thiserror::Error;
 
+pub(crate) mod audit;
 pub(crate) mod compat;
+pub(crate) mod email_delivery;
+pub(crate) mod expiring_credential;
+pub(crate) mod honeytoken;
+pub(crate) mod notifications;
 pub(crate) mod oauth2;
+pub(crate) mod rendezvous;
+pub(crate) mod runtime_resource;
+pub(crate) mod signing_key;
 mod site_config;
+pub(crate) mod suspicious_login;
 pub(crate) mod tokens;
 pub(crate) mod upstream_oauth2;
 pub(crate) mod user_agent;
 pub(crate) mod users;
+pub(crate) mod webhook;
 
 /// Error when an invalid state transition is attempted.
 #[derive(Debug, Error)]
@@ -24,28 +34,53 @@ pub struct InvalidTransitionError;
 pub use ulid::Ulid;
 
 pub use self::{
+    audit::{AuditEvent, AuditEventKind, InvalidAuditEventKindError},
     compat::{
         CompatAccessToken, CompatRefreshToken, CompatRefreshTokenState, CompatSession,
         CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device,
     },
+    email_delivery::EmailDeliveryFailure,
+    expiring_credential::{ExpiringCredential, ExpiringCredentialKind},
+    honeytoken::{Honeytoken, HoneytokenKind, InvalidHoneytokenKindError},
+    notifications::{
+        DigestFrequency, InvalidDigestFrequencyError, NotificationKind, UserNotificationPreferences,
+    },
     oauth2::{
-        AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, DeviceCodeGrant,
-        DeviceCodeGrantState, InvalidRedirectUriError, JwksOrJwksUri, Pkce, Session, SessionState,
+        AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, CibaGrant, CibaGrantState,
+        Client, ClientConsent, DeviceCodeGrant, DeviceCodeGrantState, InvalidRedirectUriError,
+        JwksOrJwksUri, KioskDevice, Pkce, PushedAuthorizationRequest, Session, SessionState,
+    },
+    rendezvous::RendezvousSession,
+    runtime_resource::{DisabledResource, InvalidRuntimeResourceError, RuntimeResource},
+    signing_key::{SigningKey, SigningKeyState},
+    site_config::{
+        AdditionalHomeserver, Appservice, CaptchaConfig, CaptchaService, CompatDeprecation,
+        CompatEndpoint, CompatLoginType, CustomScope, IntrospectionRestriction, JwtBearerIssuer,
+        RedirectUriRules, SessionLimit, SessionLimitAction, SiteConfig,
     },
-    site_config::{CaptchaConfig, CaptchaService, SiteConfig},
+    suspicious_login::SuspiciousLoginReason,
     tokens::{
         AccessToken, AccessTokenState, RefreshToken, RefreshTokenState, TokenFormatError, TokenType,
     },
     upstream_oauth2::{
+        InvalidUpstreamOAuthEmailChangeResolutionError, InvalidUpstreamOAuthFunnelStageError,
         UpsreamOAuthProviderSetEmailVerification, UpstreamOAuthAuthorizationSession,
-        UpstreamOAuthAuthorizationSessionState, UpstreamOAuthLink, UpstreamOAuthProvider,
+        UpstreamOAuthAuthorizationSessionState, UpstreamOAuthEmailChange,
+        UpstreamOAuthEmailChangeResolution, UpstreamOAuthFunnelStage,
+        UpstreamOAuthFunnelStageReport, UpstreamOAuthLink, UpstreamOAuthProvider,
         UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderDiscoveryMode,
         UpstreamOAuthProviderImportAction, UpstreamOAuthProviderImportPreference,
-        UpstreamOAuthProviderPkceMode, UpstreamOAuthProviderSubjectPreference,
+        UpstreamOAuthProviderOnEmailChange, UpstreamOAuthProviderParMode,
+        UpstreamOAuthProviderPkceMode, UpstreamOAuthProviderResponseMode,
+        UpstreamOAuthProviderSubjectPreference, UpstreamOAuthProviderTokenAuthMethod,
     },
     user_agent::{DeviceType, UserAgent},
     users::{
-        Authentication, AuthenticationMethod, BrowserSession, Password, User, UserEmail,
-        UserEmailVerification, UserEmailVerificationState, UserRecoverySession, UserRecoveryTicket,
+        Authentication, AuthenticationMethod, BrowserSession, BrowserSessionBinding,
+        BrowserSessionImpersonation, Password, User, UserActivityDigest, UserEmail,
+        UserEmailVerification, UserEmailVerificationState, UserEmergencyAccessToken,
+        UserRecoverySession, UserRecoveryTicket, UserTermsAcceptance, UserTrustedBrowser,
+        ACR_PASSWORD,
     },
+    webhook::{ChangesFeedEntry, InvalidWebhookEventKindError, WebhookEvent, WebhookEventKind},
 };
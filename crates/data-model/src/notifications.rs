@@ -0,0 +1,211 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::Duration;
+use serde::Serialize;
+use thiserror::Error;
+
+/// How often a user receives the digest of their account activity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestFrequency {
+    /// At the interval configured on the server
+    #[default]
+    Default,
+
+    /// At most once a day
+    Daily,
+
+    /// At most once a week
+    Weekly,
+
+    /// At most once a month
+    Monthly,
+}
+
+impl DigestFrequency {
+    /// Get the string representation of this frequency, as stored in the
+    /// database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+        }
+    }
+
+    /// The time between two digests with this frequency, given the interval
+    /// configured on the server
+    ///
+    /// Digests are never sent more often than the configured interval.
+    #[must_use]
+    pub fn interval(self, configured: Duration) -> Duration {
+        let interval = match self {
+            Self::Default => return configured,
+            Self::Daily => Duration::days(1),
+            Self::Weekly => Duration::weeks(1),
+            Self::Monthly => Duration::days(30),
+        };
+
+        interval.max(configured)
+    }
+}
+
+impl std::fmt::Display for DigestFrequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid [`DigestFrequency`]
+#[derive(Debug, Error)]
+#[error("invalid digest frequency {0:?}")]
+pub struct InvalidDigestFrequencyError(String);
+
+impl std::str::FromStr for DigestFrequency {
+    type Err = InvalidDigestFrequencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            "monthly" => Ok(Self::Monthly),
+            _ => Err(InvalidDigestFrequencyError(s.to_owned())),
+        }
+    }
+}
+
+/// The kinds of emails sent to users on the initiative of the server, which
+/// their notification preferences apply to
+///
+/// Emails sent as part of an action of the user, like email verification or
+/// account recovery, are always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationKind {
+    /// An alert about a new login on the account
+    NewLogin,
+
+    /// The periodic digest of the account activity
+    ActivityDigest,
+
+    /// An alert about a security issue on the account, like a refresh token
+    /// being reused
+    SecurityAlert,
+}
+
+impl NotificationKind {
+    /// Whether this kind of notification is about the security of the account
+    #[must_use]
+    pub const fn is_security(self) -> bool {
+        match self {
+            Self::NewLogin | Self::SecurityAlert => true,
+            Self::ActivityDigest => false,
+        }
+    }
+}
+
+/// The notification preferences of a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct UserNotificationPreferences {
+    /// Whether the user receives an alert when someone logs in to their
+    /// account
+    pub new_login_alerts: bool,
+
+    /// How often the user receives the digest of their account activity, if
+    /// they subscribed to it
+    pub digest_frequency: DigestFrequency,
+
+    /// Whether the user only receives notifications about the security of
+    /// their account
+    pub security_only: bool,
+}
+
+impl Default for UserNotificationPreferences {
+    fn default() -> Self {
+        Self {
+            new_login_alerts: true,
+            digest_frequency: DigestFrequency::Default,
+            security_only: false,
+        }
+    }
+}
+
+impl UserNotificationPreferences {
+    /// Whether a notification of the given kind should be sent to the user
+    #[must_use]
+    pub const fn allows(&self, kind: NotificationKind) -> bool {
+        if self.security_only && !kind.is_security() {
+            return false;
+        }
+
+        match kind {
+            NotificationKind::NewLogin => self.new_login_alerts,
+            NotificationKind::ActivityDigest | NotificationKind::SecurityAlert => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn digest_frequency_roundtrip() {
+        for frequency in [
+            DigestFrequency::Default,
+            DigestFrequency::Daily,
+            DigestFrequency::Weekly,
+            DigestFrequency::Monthly,
+        ] {
+            assert_eq!(
+                DigestFrequency::from_str(frequency.as_str()).unwrap(),
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn digest_frequency_interval() {
+        let configured = Duration::hours(6);
+        assert_eq!(DigestFrequency::Default.interval(configured), configured);
+        assert_eq!(
+            DigestFrequency::Weekly.interval(configured),
+            Duration::weeks(1)
+        );
+
+        // The configured interval is the minimum
+        let configured = Duration::weeks(2);
+        assert_eq!(DigestFrequency::Daily.interval(configured), configured);
+    }
+
+    #[test]
+    fn allows() {
+        let preferences = UserNotificationPreferences::default();
+        assert!(preferences.allows(NotificationKind::NewLogin));
+        assert!(preferences.allows(NotificationKind::ActivityDigest));
+        assert!(preferences.allows(NotificationKind::SecurityAlert));
+
+        let preferences = UserNotificationPreferences {
+            new_login_alerts: false,
+            ..preferences
+        };
+        assert!(!preferences.allows(NotificationKind::NewLogin));
+        assert!(preferences.allows(NotificationKind::SecurityAlert));
+
+        let preferences = UserNotificationPreferences {
+            new_login_alerts: true,
+            security_only: true,
+            ..preferences
+        };
+        assert!(preferences.allows(NotificationKind::NewLogin));
+        assert!(!preferences.allows(NotificationKind::ActivityDigest));
+        assert!(preferences.allows(NotificationKind::SecurityAlert));
+    }
+}
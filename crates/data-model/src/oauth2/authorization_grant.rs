@@ -22,7 +22,7 @@ use ulid::Ulid;
 use url::Url;
 
 use super::session::Session;
-use crate::InvalidTransitionError;
+use crate::{Authentication, InvalidTransitionError};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Pkce {
@@ -157,6 +157,14 @@ pub struct AuthorizationGrant {
     pub response_type_id_token: bool,
     pub created_at: DateTime<Utc>,
     pub requires_consent: bool,
+
+    /// Whether the client asked for the user to log in again, with
+    /// `prompt=login`
+    pub requires_login: bool,
+
+    /// The Authentication Context Class References the client asked for, out
+    /// of the ones we support. Empty if the client didn't ask for any.
+    pub acr_values: Vec<String>,
 }
 
 impl std::ops::Deref for AuthorizationGrant {
@@ -170,8 +178,16 @@ impl std::ops::Deref for AuthorizationGrant {
 const DEFAULT_MAX_AGE: Duration = Duration::microseconds(3600 * 24 * 365 * 1000 * 1000);
 
 impl AuthorizationGrant {
+    /// The time after which the user must have authenticated to complete
+    /// this grant
     #[must_use]
     pub fn max_auth_time(&self) -> DateTime<Utc> {
+        // With prompt=login, the user has to authenticate after the grant was
+        // created, whatever the max_age
+        if self.requires_login {
+            return self.created_at;
+        }
+
         let max_age = self
             .max_age
             .and_then(|x| Duration::try_seconds(x.get().into()))
@@ -179,6 +195,17 @@ impl AuthorizationGrant {
         self.created_at - max_age
     }
 
+    /// Whether the given authentication is both recent enough and of the
+    /// class requested by the client to complete this grant
+    #[must_use]
+    pub fn is_satisfied_by(&self, authentication: &Authentication) -> bool {
+        authentication.created_at > self.max_auth_time()
+            && (self.acr_values.is_empty()
+                || authentication
+                    .acr()
+                    .is_some_and(|acr| self.acr_values.iter().any(|value| value == acr)))
+    }
+
     /// Mark the authorization grant as exchanged.
     ///
     /// # Errors
@@ -242,6 +269,70 @@ impl AuthorizationGrant {
             response_type_id_token: false,
             created_at: now,
             requires_consent: false,
+            requires_login: false,
+            acr_values: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    use super::*;
+    use crate::{AuthenticationMethod, ACR_PASSWORD};
+
+    #[test]
+    fn test_is_satisfied_by() {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let now = Utc.with_ymd_and_hms(2024, 11, 16, 9, 0, 0).unwrap();
+        let grant = AuthorizationGrant::sample(now, &mut rng);
+
+        let password = Authentication {
+            id: Ulid::from_datetime_with_source(now.into(), &mut rng),
+            created_at: now - Duration::try_minutes(5).unwrap(),
+            authentication_method: AuthenticationMethod::Password {
+                user_password_id: Ulid::from_datetime_with_source(now.into(), &mut rng),
+            },
+        };
+        let upstream = Authentication {
+            authentication_method: AuthenticationMethod::UpstreamOAuth2 {
+                upstream_oauth2_session_id: Ulid::from_datetime_with_source(now.into(), &mut rng),
+            },
+            ..password.clone()
+        };
+
+        // Without any requirement, any authentication works
+        assert!(grant.is_satisfied_by(&password));
+        assert!(grant.is_satisfied_by(&upstream));
+
+        // With a max_age, the authentication must be recent enough
+        let with_max_age = AuthorizationGrant {
+            max_age: NonZeroU32::new(60),
+            ..grant.clone()
+        };
+        assert!(!with_max_age.is_satisfied_by(&password));
+
+        // With prompt=login, the authentication must happen after the grant
+        let with_login = AuthorizationGrant {
+            requires_login: true,
+            ..grant.clone()
+        };
+        assert!(!with_login.is_satisfied_by(&password));
+        let fresh = Authentication {
+            created_at: now + Duration::try_seconds(10).unwrap(),
+            ..password.clone()
+        };
+        assert!(with_login.is_satisfied_by(&fresh));
+
+        // With acr_values, the authentication must be of the right class
+        let with_acr = AuthorizationGrant {
+            acr_values: vec![ACR_PASSWORD.to_owned()],
+            ..grant
+        };
+        assert!(with_acr.is_satisfied_by(&password));
+        assert!(!with_acr.is_satisfied_by(&upstream));
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use oauth2_types::scope::{Scope, OPENID};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    RngCore,
+};
+use serde::Serialize;
+use ulid::Ulid;
+
+use crate::{BrowserSession, Client, InvalidTransitionError, Session, User, UserAgent};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum CibaGrantState {
+    /// The CIBA grant is pending.
+    Pending,
+
+    /// The CIBA grant has been approved by the user.
+    Fulfilled {
+        /// The browser session which was used to complete this CIBA grant.
+        browser_session_id: Ulid,
+
+        /// The time at which this CIBA grant was fulfilled.
+        fulfilled_at: DateTime<Utc>,
+    },
+
+    /// The CIBA grant has been rejected by the user.
+    Rejected {
+        /// The browser session which was used to reject this CIBA grant.
+        browser_session_id: Ulid,
+
+        /// The time at which this CIBA grant was rejected.
+        rejected_at: DateTime<Utc>,
+    },
+
+    /// The CIBA grant was exchanged for an access token.
+    Exchanged {
+        /// The browser session which was used to exchange this CIBA grant.
+        browser_session_id: Ulid,
+
+        /// The time at which the CIBA grant was fulfilled.
+        fulfilled_at: DateTime<Utc>,
+
+        /// The time at which this CIBA grant was exchanged.
+        exchanged_at: DateTime<Utc>,
+
+        /// The OAuth 2.0 session ID which was created by this CIBA grant.
+        session_id: Ulid,
+    },
+}
+
+impl CibaGrantState {
+    /// Mark this CIBA grant as fulfilled, returning a new state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is not in the [`Pending`]
+    /// state.
+    ///
+    /// [`Pending`]: CibaGrantState::Pending
+    pub fn fulfill(
+        self,
+        browser_session: &BrowserSession,
+        fulfilled_at: DateTime<Utc>,
+    ) -> Result<Self, InvalidTransitionError> {
+        match self {
+            CibaGrantState::Pending => Ok(CibaGrantState::Fulfilled {
+                browser_session_id: browser_session.id,
+                fulfilled_at,
+            }),
+            _ => Err(InvalidTransitionError),
+        }
+    }
+
+    /// Mark this CIBA grant as rejected, returning a new state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is not in the [`Pending`]
+    /// state.
+    ///
+    /// [`Pending`]: CibaGrantState::Pending
+    pub fn reject(
+        self,
+        browser_session: &BrowserSession,
+        rejected_at: DateTime<Utc>,
+    ) -> Result<Self, InvalidTransitionError> {
+        match self {
+            CibaGrantState::Pending => Ok(CibaGrantState::Rejected {
+                browser_session_id: browser_session.id,
+                rejected_at,
+            }),
+            _ => Err(InvalidTransitionError),
+        }
+    }
+
+    /// Mark this CIBA grant as exchanged, returning a new state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is not in the [`Fulfilled`]
+    /// state.
+    ///
+    /// [`Fulfilled`]: CibaGrantState::Fulfilled
+    pub fn exchange(
+        self,
+        session: &Session,
+        exchanged_at: DateTime<Utc>,
+    ) -> Result<Self, InvalidTransitionError> {
+        match self {
+            CibaGrantState::Fulfilled {
+                fulfilled_at,
+                browser_session_id,
+                ..
+            } => Ok(CibaGrantState::Exchanged {
+                browser_session_id,
+                fulfilled_at,
+                exchanged_at,
+                session_id: session.id,
+            }),
+            _ => Err(InvalidTransitionError),
+        }
+    }
+
+    /// Returns `true` if the CIBA grant state is [`Pending`].
+    ///
+    /// [`Pending`]: CibaGrantState::Pending
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Pending)
+    }
+
+    /// Returns `true` if the CIBA grant state is [`Fulfilled`].
+    ///
+    /// [`Fulfilled`]: CibaGrantState::Fulfilled
+    #[must_use]
+    pub fn is_fulfilled(&self) -> bool {
+        matches!(self, Self::Fulfilled { .. })
+    }
+
+    /// Returns `true` if the CIBA grant state is [`Rejected`].
+    ///
+    /// [`Rejected`]: CibaGrantState::Rejected
+    #[must_use]
+    pub fn is_rejected(&self) -> bool {
+        matches!(self, Self::Rejected { .. })
+    }
+
+    /// Returns `true` if the CIBA grant state is [`Exchanged`].
+    ///
+    /// [`Exchanged`]: CibaGrantState::Exchanged
+    #[must_use]
+    pub fn is_exchanged(&self) -> bool {
+        matches!(self, Self::Exchanged { .. })
+    }
+}
+
+/// A [Client-Initiated Backchannel Authentication] request, in which a client
+/// asks for a specific user to approve a login on another device.
+///
+/// [Client-Initiated Backchannel Authentication]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CibaGrant {
+    pub id: Ulid,
+    #[serde(flatten)]
+    pub state: CibaGrantState,
+
+    /// The client ID which requested this CIBA grant.
+    pub client_id: Ulid,
+
+    /// The user whose approval was requested.
+    pub user_id: Ulid,
+
+    /// The scope which was requested by this CIBA grant.
+    pub scope: Scope,
+
+    /// The identifier of the request, which the client uses to poll for an
+    /// access token.
+    pub auth_req_id: String,
+
+    /// The message shown both to the user and by the client, so that the user
+    /// can make sure they are approving the right request.
+    pub binding_message: Option<String>,
+
+    /// The time at which this CIBA grant was created.
+    pub created_at: DateTime<Utc>,
+
+    /// The time at which this CIBA grant will expire.
+    pub expires_at: DateTime<Utc>,
+
+    /// The IP address of the client which requested this CIBA grant.
+    pub ip_address: Option<IpAddr>,
+
+    /// The user agent used to request this CIBA grant.
+    pub user_agent: Option<UserAgent>,
+}
+
+impl std::ops::Deref for CibaGrant {
+    type Target = CibaGrantState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+impl CibaGrant {
+    /// Mark this CIBA grant as fulfilled, returning the updated grant.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is not in the [`Pending`]
+    /// state.
+    ///
+    /// [`Pending`]: CibaGrantState::Pending
+    pub fn fulfill(
+        self,
+        browser_session: &BrowserSession,
+        fulfilled_at: DateTime<Utc>,
+    ) -> Result<Self, InvalidTransitionError> {
+        Ok(Self {
+            state: self.state.fulfill(browser_session, fulfilled_at)?,
+            ..self
+        })
+    }
+
+    /// Mark this CIBA grant as rejected, returning the updated grant.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is not in the [`Pending`]
+    /// state.
+    ///
+    /// [`Pending`]: CibaGrantState::Pending
+    pub fn reject(
+        self,
+        browser_session: &BrowserSession,
+        rejected_at: DateTime<Utc>,
+    ) -> Result<Self, InvalidTransitionError> {
+        Ok(Self {
+            state: self.state.reject(browser_session, rejected_at)?,
+            ..self
+        })
+    }
+
+    /// Mark this CIBA grant as exchanged, returning the updated grant.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is not in the [`Fulfilled`]
+    /// state.
+    ///
+    /// [`Fulfilled`]: CibaGrantState::Fulfilled
+    pub fn exchange(
+        self,
+        session: &Session,
+        exchanged_at: DateTime<Utc>,
+    ) -> Result<Self, InvalidTransitionError> {
+        Ok(Self {
+            state: self.state.exchange(session, exchanged_at)?,
+            ..self
+        })
+    }
+
+    #[doc(hidden)]
+    pub fn sample(
+        now: DateTime<Utc>,
+        rng: &mut impl RngCore,
+        client: &Client,
+        user: &User,
+    ) -> Self {
+        Self {
+            id: Ulid::from_datetime_with_source(now.into(), rng),
+            state: CibaGrantState::Pending,
+            client_id: client.id,
+            user_id: user.id,
+            scope: [OPENID].into_iter().collect(),
+            auth_req_id: Alphanumeric.sample_string(rng, 32),
+            binding_message: Some("W4SCT".to_owned()),
+            created_at: now - Duration::try_minutes(1).unwrap(),
+            expires_at: now + Duration::try_minutes(9).unwrap(),
+            ip_address: Some(IpAddr::from([192, 0, 2, 1])),
+            user_agent: None,
+        }
+    }
+}
@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
 use oauth2_types::{
@@ -18,6 +18,8 @@ use thiserror::Error;
 use ulid::Ulid;
 use url::Url;
 
+use crate::RedirectUriRules;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum JwksOrJwksUri {
@@ -37,6 +39,11 @@ pub struct Client {
 
     pub encrypted_client_secret: Option<String>,
 
+    /// The token with which the client can manage its own registration, if it
+    /// registered dynamically
+    #[serde(skip)]
+    pub encrypted_registration_access_token: Option<String>,
+
     pub application_type: Option<ApplicationType>,
 
     /// Array of Redirection URI values used by the Client
@@ -83,6 +90,42 @@ pub struct Client {
     /// URI using the https scheme that a third party can use to initiate a
     /// login by the RP
     pub initiate_login_uri: Option<Url>,
+
+    /// Whether the client must use pushed authorization requests to start an
+    /// authorization grant
+    pub require_pushed_authorization_requests: bool,
+
+    /// The expected subject DN of the certificate the client presents when
+    /// using the `tls_client_auth` authentication method
+    pub tls_client_auth_subject_dn: Option<String>,
+
+    /// Whether the access tokens issued to the client are bound to the
+    /// certificate it used on the token endpoint
+    pub tls_client_certificate_bound_access_tokens: bool,
+
+    /// Whether the access tokens issued to the client are signed JWTs
+    /// following RFC 9068, instead of opaque tokens
+    pub jwt_access_tokens: bool,
+
+    /// The lifetime of the access tokens issued to the client, overriding the
+    /// server default
+    #[serde(skip)]
+    pub access_token_ttl: Option<Duration>,
+
+    /// How long the refresh tokens issued to the client can be used, overriding
+    /// the server default
+    #[serde(skip)]
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// The maximum lifetime of the sessions of the client, overriding the
+    /// server default
+    #[serde(skip)]
+    pub session_ttl: Option<Duration>,
+
+    /// How long the sessions of the client can stay inactive before they are
+    /// ended, overriding the server default
+    #[serde(skip)]
+    pub session_inactivity_ttl: Option<Duration>,
 }
 
 #[derive(Debug, Error)]
@@ -95,6 +138,9 @@ pub enum InvalidRedirectUriError {
 
     #[error("client has no redirect_uri registered")]
     NoneRegistered,
+
+    #[error("redirect_uri is not allowed by the redirect URI rules")]
+    Forbidden,
 }
 
 impl Client {
@@ -105,18 +151,55 @@ impl Client {
     /// Returns an error if:
     ///
     ///  - no URL was given but multiple redirect URIs are registered,
-    ///  - no URL was registered, or
-    ///  - the given URL is not registered
+    ///  - no URL was registered,
+    ///  - the given URL is not registered, or
+    ///  - the URL doesn't follow the redirect URI rules for the application
+    ///    type of the client
     pub fn resolve_redirect_uri<'a>(
         &'a self,
         redirect_uri: &'a Option<Url>,
+        rules: &RedirectUriRules,
     ) -> Result<&'a Url, InvalidRedirectUriError> {
-        match (&self.redirect_uris[..], redirect_uri) {
-            ([], _) => Err(InvalidRedirectUriError::NoneRegistered),
-            ([one], None) => Ok(one),
-            (_, None) => Err(InvalidRedirectUriError::MultipleRegistered),
-            (uris, Some(uri)) if uri_matches_one_of(uri, uris) => Ok(uri),
-            _ => Err(InvalidRedirectUriError::NotAllowed),
+        let uri = match (&self.redirect_uris[..], redirect_uri) {
+            ([], _) => return Err(InvalidRedirectUriError::NoneRegistered),
+            ([one], None) => one,
+            (_, None) => return Err(InvalidRedirectUriError::MultipleRegistered),
+            (uris, Some(uri)) if uri_matches_one_of(uri, uris, rules.loopback_any_port) => uri,
+            _ => return Err(InvalidRedirectUriError::NotAllowed),
+        };
+
+        if !self.follows_redirect_uri_rules(uri, rules) {
+            return Err(InvalidRedirectUriError::Forbidden);
+        }
+
+        Ok(uri)
+    }
+
+    /// Whether the given redirect URI is allowed for the application type of
+    /// this client.
+    ///
+    /// The rules are checked again here, as they might have changed since the
+    /// client registered.
+    fn follows_redirect_uri_rules(&self, uri: &Url, rules: &RedirectUriRules) -> bool {
+        match &self.application_type {
+            Some(ApplicationType::Native) => match uri.scheme() {
+                "https" => true,
+                "http" => rules.insecure_uris_allowed || is_loopback(uri),
+                scheme => {
+                    rules.native_custom_schemes.iter().any(|s| s == scheme)
+                        || self
+                            .client_uri
+                            .as_ref()
+                            .and_then(Url::host_str)
+                            .is_some_and(|host| reverse_dns_matches(host, scheme))
+                }
+            },
+            Some(ApplicationType::Web) => {
+                !rules.web_require_https || rules.insecure_uris_allowed || uri.scheme() == "https"
+            }
+            // Clients from the configuration don't have an application type, and
+            // aren't subject to those rules
+            _ => true,
         }
     }
 
@@ -161,7 +244,11 @@ impl Client {
             default_acr_values: None,
             request_uris: None,
             require_signed_request_object: None,
-            require_pushed_authorization_requests: None,
+            require_pushed_authorization_requests: Some(self.require_pushed_authorization_requests),
+            tls_client_auth_subject_dn: self.tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens: Some(
+                self.tls_client_certificate_bound_access_tokens,
+            ),
             introspection_signed_response_alg: None,
             introspection_encrypted_response_alg: None,
             introspection_encrypted_response_enc: None,
@@ -177,6 +264,7 @@ impl Client {
                 id: Ulid::from_datetime_with_source(now.into(), rng),
                 client_id: "client1".to_owned(),
                 encrypted_client_secret: None,
+                encrypted_registration_access_token: None,
                 application_type: Some(ApplicationType::Web),
                 redirect_uris: vec![
                     Url::parse("https://client1.example.com/redirect").unwrap(),
@@ -196,12 +284,21 @@ impl Client {
                 id_token_signed_response_alg: None,
                 userinfo_signed_response_alg: None,
                 jwks: None,
+                require_pushed_authorization_requests: false,
+                tls_client_auth_subject_dn: None,
+                tls_client_certificate_bound_access_tokens: false,
+                jwt_access_tokens: false,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                session_ttl: None,
+                session_inactivity_ttl: None,
             },
             // Another client without any URIs set
             Self {
                 id: Ulid::from_datetime_with_source(now.into(), rng),
                 client_id: "client2".to_owned(),
                 encrypted_client_secret: None,
+                encrypted_registration_access_token: None,
                 application_type: Some(ApplicationType::Native),
                 redirect_uris: vec![Url::parse("https://client2.example.com/redirect").unwrap()],
                 grant_types: vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
@@ -216,6 +313,14 @@ impl Client {
                 id_token_signed_response_alg: None,
                 userinfo_signed_response_alg: None,
                 jwks: None,
+                require_pushed_authorization_requests: false,
+                tls_client_auth_subject_dn: None,
+                tls_client_certificate_bound_access_tokens: false,
+                jwt_access_tokens: false,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                session_ttl: None,
+                session_inactivity_ttl: None,
             },
         ]
     }
@@ -224,12 +329,25 @@ impl Client {
 /// The hosts that match the loopback interface.
 const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
 
+/// Whether the given URI is on the loopback interface.
+fn is_loopback(uri: &Url) -> bool {
+    LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default())
+}
+
+/// Whether the given URI scheme is the reverse-DNS notation of the host, or of
+/// one of its subdomains, e.g. `io.element.app` for `element.io`.
+fn reverse_dns_matches(host: &str, scheme: &str) -> bool {
+    let mut scheme_parts = scheme.split('.');
+    host.rsplit('.')
+        .all(|part| scheme_parts.next() == Some(part))
+}
+
 /// Whether the given URI matches one of the registered URIs.
 ///
-/// If the URI host is one if `localhost`, `127.0.0.1` or `[::1]`, any port is
-/// accepted.
-fn uri_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
-    if LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default()) {
+/// If `loopback_any_port` is set and the URI host is one if `localhost`,
+/// `127.0.0.1` or `[::1]`, any port is accepted.
+fn uri_matches_one_of(uri: &Url, registered_uris: &[Url], loopback_any_port: bool) -> bool {
+    if loopback_any_port && is_loopback(uri) {
         let mut uri = uri.clone();
         // Try matching without the port first
         if uri.set_port(None).is_ok() && registered_uris.contains(&uri) {
@@ -256,25 +374,119 @@ mod tests {
         // Non-loopback interface URIs.
         assert!(uri_matches_one_of(
             &Url::parse("https://example.org").unwrap(),
-            registered_uris
+            registered_uris,
+            true
         ));
         assert!(!uri_matches_one_of(
             &Url::parse("https://example.org:8080").unwrap(),
-            registered_uris
+            registered_uris,
+            true
         ));
 
         // Loopback interface URIS.
         assert!(uri_matches_one_of(
             &Url::parse("http://127.0.0.1").unwrap(),
-            registered_uris
+            registered_uris,
+            true
         ));
         assert!(uri_matches_one_of(
             &Url::parse("http://127.0.0.1:8080").unwrap(),
-            registered_uris
+            registered_uris,
+            true
         ));
         assert!(!uri_matches_one_of(
             &Url::parse("http://localhost").unwrap(),
-            registered_uris
+            registered_uris,
+            true
         ));
     }
+    #[test]
+    fn test_uri_matches_one_of_exact_port() {
+        let registered_uris = &[Url::parse("http://127.0.0.1").unwrap()];
+
+        assert!(uri_matches_one_of(
+            &Url::parse("http://127.0.0.1").unwrap(),
+            registered_uris,
+            false
+        ));
+        assert!(!uri_matches_one_of(
+            &Url::parse("http://127.0.0.1:8080").unwrap(),
+            registered_uris,
+            false
+        ));
+    }
+
+    #[test]
+    fn test_resolve_redirect_uri_rules() {
+        let now = chrono::Utc::now();
+        let mut rng = rand::thread_rng();
+        let [mut web, mut native] = Client::samples(now, &mut rng).try_into().unwrap();
+        let rules = RedirectUriRules::default();
+
+        // Web clients have to use HTTPS
+        let http = Url::parse("http://client1.example.com/redirect").unwrap();
+        web.redirect_uris.push(http.clone());
+        assert!(matches!(
+            web.resolve_redirect_uri(&Some(http.clone()), &rules),
+            Err(InvalidRedirectUriError::Forbidden)
+        ));
+        let relaxed = RedirectUriRules {
+            web_require_https: false,
+            ..RedirectUriRules::default()
+        };
+        assert!(web
+            .resolve_redirect_uri(&Some(http.clone()), &relaxed)
+            .is_ok());
+
+        // Clients without an application type aren't subject to the rules
+        web.application_type = None;
+        assert!(web.resolve_redirect_uri(&Some(http), &rules).is_ok());
+
+        // Native clients can use the loopback interface, the reverse-DNS notation of
+        // their client URI and the allowed custom schemes
+        let loopback = Url::parse("http://127.0.0.1/callback").unwrap();
+        let reverse_dns = Url::parse("com.example.client2.app:/callback").unwrap();
+        let custom = Url::parse("myapp:/callback").unwrap();
+        native.client_uri = Some(Url::parse("https://client2.example.com/").unwrap());
+        native.redirect_uris = vec![loopback.clone(), reverse_dns.clone(), custom.clone()];
+
+        assert!(native
+            .resolve_redirect_uri(
+                &Some(Url::parse("http://127.0.0.1:1234/callback").unwrap()),
+                &rules
+            )
+            .is_ok());
+        assert!(native
+            .resolve_redirect_uri(&Some(reverse_dns), &rules)
+            .is_ok());
+        assert!(matches!(
+            native.resolve_redirect_uri(&Some(custom.clone()), &rules),
+            Err(InvalidRedirectUriError::Forbidden)
+        ));
+
+        let rules = RedirectUriRules {
+            native_custom_schemes: vec!["myapp".to_owned()],
+            loopback_any_port: false,
+            ..RedirectUriRules::default()
+        };
+        assert!(native.resolve_redirect_uri(&Some(custom), &rules).is_ok());
+        assert!(native.resolve_redirect_uri(&Some(loopback), &rules).is_ok());
+        assert!(matches!(
+            native.resolve_redirect_uri(
+                &Some(Url::parse("http://127.0.0.1:1234/callback").unwrap()),
+                &rules
+            ),
+            Err(InvalidRedirectUriError::NotAllowed)
+        ));
+    }
+
+    #[test]
+    fn test_reverse_dns_matches() {
+        assert!(reverse_dns_matches("element.io", "io.element"));
+        assert!(reverse_dns_matches("element.io", "io.element.app"));
+        assert!(reverse_dns_matches("app.element.io", "io.element.app"));
+        assert!(!reverse_dns_matches("app.element.io", "io.element"));
+        assert!(!reverse_dns_matches("element.io", "io.elementx"));
+        assert!(!reverse_dns_matches("element.io", "com.element"));
+    }
 }
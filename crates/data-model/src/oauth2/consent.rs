@@ -0,0 +1,26 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use oauth2_types::scope::Scope;
+use serde::Serialize;
+use ulid::Ulid;
+
+/// The consent a user gave to a client, which makes it one of their
+/// authorized clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientConsent {
+    /// The ID of the client the user consented to
+    pub client_id: Ulid,
+
+    /// The scope the user consented to
+    pub scope: Scope,
+
+    /// When the user last gave or refreshed their consent
+    pub consented_at: DateTime<Utc>,
+
+    /// When one of the sessions of the user with this client was last active
+    pub last_active_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,30 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+
+use crate::Device;
+
+/// A device shared between users, registered once by a kiosk client, on which
+/// users log in one at a time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KioskDevice {
+    pub id: Ulid,
+
+    /// The kiosk client which registered the device
+    pub client_id: Ulid,
+
+    /// The Matrix device given to the sessions of the users logged in on this
+    /// device
+    pub device: Device,
+
+    pub created_at: DateTime<Utc>,
+
+    /// The OAuth 2.0 session of the user currently logged in on the device, if
+    /// any
+    pub current_session_id: Option<Ulid>,
+}
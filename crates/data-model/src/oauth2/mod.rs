@@ -5,13 +5,21 @@
 // Please see LICENSE in the repository root for full details.
 
 mod authorization_grant;
+mod ciba_grant;
 mod client;
+mod consent;
 mod device_code_grant;
+mod kiosk_device;
+mod pushed_authorization_request;
 mod session;
 
 pub use self::{
     authorization_grant::{AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Pkce},
+    ciba_grant::{CibaGrant, CibaGrantState},
     client::{Client, InvalidRedirectUriError, JwksOrJwksUri},
+    consent::ClientConsent,
     device_code_grant::{DeviceCodeGrant, DeviceCodeGrantState},
+    kiosk_device::KioskDevice,
+    pushed_authorization_request::PushedAuthorizationRequest,
     session::{Session, SessionState},
 };
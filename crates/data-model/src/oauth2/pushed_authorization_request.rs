@@ -0,0 +1,61 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+
+use crate::InvalidTransitionError;
+
+/// An authorization request pushed by a client to the pushed authorization
+/// request endpoint, as defined by [RFC 9126]
+///
+/// [RFC 9126]: https://www.rfc-editor.org/rfc/rfc9126.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PushedAuthorizationRequest {
+    pub id: Ulid,
+
+    /// The client which pushed the request
+    pub client_id: Ulid,
+
+    /// The `request_uri` given back to the client, used to reference this
+    /// request in the authorization endpoint
+    pub request_uri: String,
+
+    /// The parameters of the authorization request, as they were pushed
+    pub parameters: BTreeMap<String, String>,
+
+    pub created_at: DateTime<Utc>,
+
+    pub expires_at: DateTime<Utc>,
+
+    /// When the request was used in the authorization endpoint. A request can
+    /// only be used once
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+impl PushedAuthorizationRequest {
+    /// Whether this request can still be used in the authorization endpoint
+    #[must_use]
+    pub fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        self.consumed_at.is_none() && now < self.expires_at
+    }
+
+    /// Mark this request as used
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request was already used
+    pub fn consume(mut self, consumed_at: DateTime<Utc>) -> Result<Self, InvalidTransitionError> {
+        if self.consumed_at.is_some() {
+            return Err(InvalidTransitionError);
+        }
+
+        self.consumed_at = Some(consumed_at);
+        Ok(self)
+    }
+}
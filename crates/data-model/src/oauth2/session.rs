@@ -83,6 +83,7 @@ pub struct Session {
     pub user_agent: Option<UserAgent>,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
+    pub tags: Vec<String>,
 }
 
 impl std::ops::Deref for Session {
@@ -0,0 +1,38 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+
+/// A rendezvous session, through which two devices exchange messages to sign
+/// in one of them by scanning a QR code, as per MSC4108
+///
+/// The devices establish a secure channel over the session, so its content is
+/// opaque to the service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RendezvousSession {
+    pub id: Ulid,
+    pub created_at: DateTime<Utc>,
+
+    /// When the content was last replaced
+    pub updated_at: DateTime<Utc>,
+
+    pub expires_at: DateTime<Utc>,
+
+    /// Changes every time the content is replaced
+    pub etag: String,
+
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+impl RendezvousSession {
+    /// Whether the session expired at the given time
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
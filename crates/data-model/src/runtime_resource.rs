@@ -0,0 +1,77 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+
+/// A resource which administrators can disable at runtime, for example during
+/// an incident, without restarting the service or editing its configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuntimeResource {
+    /// The OAuth 2.0 dynamic client registration endpoint
+    ClientRegistration,
+
+    /// The Matrix compatibility login endpoint
+    CompatLogin,
+
+    /// The OAuth 2.0 device authorization endpoint
+    DeviceAuthorization,
+}
+
+impl RuntimeResource {
+    /// All the resources which can be disabled at runtime
+    pub const ALL: [Self; 3] = [
+        Self::ClientRegistration,
+        Self::CompatLogin,
+        Self::DeviceAuthorization,
+    ];
+
+    /// Get the string representation of this resource, as stored in the
+    /// database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::ClientRegistration => "client_registration",
+            Self::CompatLogin => "compat_login",
+            Self::DeviceAuthorization => "device_authorization",
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeResource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid [`RuntimeResource`]
+#[derive(Debug, Error)]
+#[error("invalid runtime resource {0:?}")]
+pub struct InvalidRuntimeResourceError(String);
+
+impl std::str::FromStr for RuntimeResource {
+    type Err = InvalidRuntimeResourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client_registration" => Ok(Self::ClientRegistration),
+            "compat_login" => Ok(Self::CompatLogin),
+            "device_authorization" => Ok(Self::DeviceAuthorization),
+            _ => Err(InvalidRuntimeResourceError(s.to_owned())),
+        }
+    }
+}
+
+/// A [`RuntimeResource`] which was disabled by an administrator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DisabledResource {
+    pub resource: RuntimeResource,
+    pub disabled_at: DateTime<Utc>,
+
+    /// Why the resource was disabled, if the administrator said so
+    pub reason: Option<String>,
+}
@@ -0,0 +1,160 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use mas_iana::jose::JsonWebSignatureAlg;
+use ulid::Ulid;
+
+/// Where a [`SigningKey`] is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningKeyState {
+    /// The key is published in the JWKS, but not used to sign yet
+    Pending,
+
+    /// The key is the one currently used to sign
+    Active,
+
+    /// A newer key replaced this one. It stays published in the JWKS until the
+    /// tokens it signed expire.
+    Superseded {
+        /// When the newer key became active
+        superseded_at: DateTime<Utc>,
+    },
+
+    /// The key was removed from the JWKS
+    Retired {
+        /// When the key was retired
+        retired_at: DateTime<Utc>,
+    },
+}
+
+impl SigningKeyState {
+    /// Whether the key should be published in the JWKS
+    #[must_use]
+    pub fn is_published(&self) -> bool {
+        !matches!(self, Self::Retired { .. })
+    }
+
+    /// Whether the key should be used to sign
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        matches!(self, Self::Active)
+    }
+}
+
+/// A signing key generated by the automated key rotation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningKey {
+    pub id: Ulid,
+
+    /// The key ID, as published in the JWKS
+    pub kid: String,
+
+    /// The signing algorithm this key is used for
+    pub algorithm: JsonWebSignatureAlg,
+
+    /// The private key, PEM-encoded and encrypted with the encryption secret
+    pub encrypted_key: String,
+
+    pub created_at: DateTime<Utc>,
+
+    /// When the key starts being used to sign
+    pub activates_at: DateTime<Utc>,
+
+    /// When the key was removed from the JWKS, if it was
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    /// Get the state of this key at the given time
+    ///
+    /// A key is superseded as soon as a newer key becomes active.
+    ///
+    /// # Parameters
+    ///
+    /// * `keys`: All the rotated keys, including this one
+    /// * `now`: The current time
+    #[must_use]
+    pub fn state(&self, keys: &[SigningKey], now: DateTime<Utc>) -> SigningKeyState {
+        if let Some(retired_at) = self.retired_at {
+            return SigningKeyState::Retired { retired_at };
+        }
+
+        if self.activates_at > now {
+            return SigningKeyState::Pending;
+        }
+
+        let superseded_at = keys
+            .iter()
+            .map(|key| key.activates_at)
+            .filter(|activates_at| *activates_at > self.activates_at && *activates_at <= now)
+            .min();
+
+        match superseded_at {
+            Some(superseded_at) => SigningKeyState::Superseded { superseded_at },
+            None => SigningKeyState::Active,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn key(id: u128, activates_at: DateTime<Utc>) -> SigningKey {
+        SigningKey {
+            id: Ulid::from(id),
+            kid: format!("key-{id}"),
+            algorithm: JsonWebSignatureAlg::Rs256,
+            encrypted_key: String::new(),
+            created_at: activates_at - Duration::days(1),
+            activates_at,
+            retired_at: None,
+        }
+    }
+
+    #[test]
+    fn test_signing_key_state() {
+        let now = DateTime::UNIX_EPOCH + Duration::days(100);
+
+        let old = key(1, now - Duration::days(60));
+        let current = key(2, now - Duration::days(30));
+        let next = key(3, now + Duration::days(1));
+        let mut retired = key(4, now - Duration::days(90));
+        retired.retired_at = Some(now - Duration::days(20));
+
+        let keys = [old.clone(), current.clone(), next.clone(), retired.clone()];
+
+        assert_eq!(
+            old.state(&keys, now),
+            SigningKeyState::Superseded {
+                superseded_at: current.activates_at
+            }
+        );
+        assert_eq!(current.state(&keys, now), SigningKeyState::Active);
+        assert_eq!(next.state(&keys, now), SigningKeyState::Pending);
+        assert_eq!(
+            retired.state(&keys, now),
+            SigningKeyState::Retired {
+                retired_at: now - Duration::days(20)
+            }
+        );
+
+        // Once the next key activates, it supersedes the current one
+        let later = now + Duration::days(2);
+        assert_eq!(
+            current.state(&keys, later),
+            SigningKeyState::Superseded {
+                superseded_at: next.activates_at
+            }
+        );
+        assert_eq!(next.state(&keys, later), SigningKeyState::Active);
+        assert!(!next.state(&keys, now).is_active());
+        assert!(next.state(&keys, now).is_published());
+        assert!(!retired.state(&keys, now).is_published());
+    }
+}
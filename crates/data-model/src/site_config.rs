@@ -4,9 +4,15 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use chrono::Duration;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use ulid::Ulid;
 use url::Url;
 
+use crate::{BrowserSession, CompatSession, JwksOrJwksUri, TokenType, User};
+
 /// Which Captcha service is being used
 #[derive(Debug, Clone, Copy)]
 pub enum CaptchaService {
@@ -28,6 +34,219 @@ pub struct CaptchaConfig {
     pub secret_key: String,
 }
 
+/// An issuer trusted to sign assertions for the JWT bearer grant
+#[derive(Debug, Clone)]
+pub struct JwtBearerIssuer {
+    /// The expected `iss` claim of the assertions
+    pub issuer: String,
+
+    /// The keys used to verify the signature of the assertions
+    pub jwks: JwksOrJwksUri,
+}
+
+/// An endpoint of the Matrix Client-Server API compatibility layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatEndpoint {
+    Login,
+    Logout,
+    Refresh,
+    Register,
+    LoginSsoRedirect,
+}
+
+impl CompatEndpoint {
+    /// Get the endpoint as a string, suitable for metrics labels
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Logout => "logout",
+            Self::Refresh => "refresh",
+            Self::Register => "register",
+            Self::LoginSsoRedirect => "login_sso_redirect",
+        }
+    }
+}
+
+/// A login type of the Matrix Client-Server API compatibility layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatLoginType {
+    /// `m.login.password`
+    Password,
+
+    /// `m.login.token`
+    Token,
+}
+
+/// The deprecation of some compatibility endpoints
+#[derive(Debug, Clone)]
+pub struct CompatDeprecation {
+    /// The endpoints which are deprecated. Empty means all of them.
+    pub endpoints: Vec<CompatEndpoint>,
+
+    /// When the endpoints were or will be deprecated
+    pub deprecated_at: DateTime<Utc>,
+
+    /// When the endpoints will stop working
+    pub sunset_at: Option<DateTime<Utc>>,
+
+    /// A link to a page documenting the deprecation
+    pub link: Option<Url>,
+}
+
+impl CompatDeprecation {
+    /// Whether the given endpoint is deprecated
+    #[must_use]
+    pub fn applies_to(&self, endpoint: CompatEndpoint) -> bool {
+        self.endpoints.is_empty() || self.endpoints.contains(&endpoint)
+    }
+}
+
+/// Restrict which types of tokens a client can introspect
+#[derive(Debug, Clone)]
+pub struct IntrospectionRestriction {
+    /// The ID of the client this restriction applies to
+    pub client_id: Ulid,
+
+    /// The types of tokens the client is allowed to introspect
+    pub token_types: Vec<TokenType>,
+}
+
+/// A deployment-specific scope which clients can request
+#[derive(Debug, Clone)]
+pub struct CustomScope {
+    /// The scope itself
+    pub scope: String,
+
+    /// Whether users have to explicitly consent to this scope
+    pub requires_consent: bool,
+
+    /// What the scope allows, as shown to users on the consent screen
+    pub description: Option<String>,
+}
+
+/// Rules for the redirect URIs clients can use, depending on their
+/// application type
+#[derive(Debug, Clone)]
+pub struct RedirectUriRules {
+    /// Custom URI schemes native clients can use, on top of the reverse-DNS
+    /// notation of their `client_uri` host
+    pub native_custom_schemes: Vec<String>,
+
+    /// Whether redirect URIs on the loopback interface match regardless of
+    /// their port
+    pub loopback_any_port: bool,
+
+    /// Whether web clients have to use HTTPS redirect URIs
+    pub web_require_https: bool,
+
+    /// Whether plain HTTP redirect URIs are accepted anywhere, because the
+    /// policy is configured to accept insecure URIs
+    pub insecure_uris_allowed: bool,
+}
+
+impl Default for RedirectUriRules {
+    fn default() -> Self {
+        Self {
+            native_custom_schemes: Vec::new(),
+            loopback_any_port: true,
+            web_require_https: true,
+            insecure_uris_allowed: false,
+        }
+    }
+}
+
+/// An application service which can log in as the users in its namespace
+/// through the compatibility layer
+#[derive(Debug, Clone)]
+pub struct Appservice {
+    /// The ID of the application service
+    pub id: String,
+
+    /// The token the application service authenticates with
+    pub as_token: String,
+
+    /// The localpart of the user the application service acts as by default
+    pub sender_localpart: String,
+
+    /// Regular expressions matching the Matrix IDs of the users in its
+    /// namespace, anchored to match the whole Matrix ID
+    pub user_namespaces: Vec<Regex>,
+}
+
+impl Appservice {
+    /// Create an application service, anchoring the regular expressions of its
+    /// user namespaces to match the whole Matrix ID
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the regular expressions is invalid
+    pub fn new<'a>(
+        id: String,
+        as_token: String,
+        sender_localpart: String,
+        user_namespaces: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, regex::Error> {
+        let user_namespaces = user_namespaces
+            .into_iter()
+            .map(|regex| Regex::new(&format!("^(?:{regex})$")))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            id,
+            as_token,
+            sender_localpart,
+            user_namespaces,
+        })
+    }
+
+    /// Whether the application service can log in as the user with the given
+    /// localpart and Matrix ID
+    #[must_use]
+    pub fn can_log_in_as(&self, localpart: &str, mxid: &str) -> bool {
+        localpart == self.sender_localpart
+            || self
+                .user_namespaces
+                .iter()
+                .any(|namespace| namespace.is_match(mxid))
+    }
+}
+
+/// An additional homeserver served by this service
+#[derive(Debug, Clone)]
+pub struct AdditionalHomeserver {
+    /// The server name of the homeserver
+    pub server_name: String,
+
+    /// Hostnames on which this homeserver is selected
+    pub hosts: Vec<String>,
+
+    /// Clients belonging to this homeserver
+    pub clients: Vec<Ulid>,
+}
+
+/// What happens when a user who reached the maximum number of sessions starts
+/// a new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionLimitAction {
+    /// Refuse the new session
+    Refuse,
+
+    /// End the least recently active session of the user to make room for the
+    /// new one
+    Evict,
+}
+
+/// A limit on the number of sessions each user can have at the same time
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimit {
+    /// Maximum number of active OAuth 2.0 and compatibility sessions per user
+    pub max_sessions: usize,
+
+    /// What happens when a user reaches the limit
+    pub action: SessionLimitAction,
+}
+
 /// Random site configuration we want accessible in various places.
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone)]
@@ -35,6 +254,31 @@ pub struct SiteConfig {
     /// Time-to-live of access tokens.
     pub access_token_ttl: Duration,
 
+    /// How long OAuth 2.0 refresh tokens can be used after they are issued, if
+    /// limited.
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// Maximum lifetime of OAuth 2.0 sessions, if limited.
+    pub session_ttl: Option<Duration>,
+
+    /// How long OAuth 2.0 sessions can stay inactive before they are ended, if
+    /// limited.
+    pub session_inactivity_ttl: Option<Duration>,
+
+    /// Maximum lifetime of compatibility sessions, if limited.
+    pub compat_session_ttl: Option<Duration>,
+
+    /// How long compatibility sessions can stay inactive before they are
+    /// ended, if limited.
+    pub compat_session_inactivity_ttl: Option<Duration>,
+
+    /// Maximum lifetime of browser sessions, if limited.
+    pub browser_session_ttl: Option<Duration>,
+
+    /// How long browser sessions can stay inactive before they are ended, if
+    /// limited.
+    pub browser_session_inactivity_ttl: Option<Duration>,
+
     /// Time-to-live of compatibility access tokens.
     pub compat_token_ttl: Duration,
 
@@ -47,15 +291,40 @@ pub struct SiteConfig {
     /// The URL to the terms of service.
     pub tos_uri: Option<Url>,
 
+    /// Whether users have to accept the current terms of service before
+    /// continuing after they log in
+    pub terms_acceptance_required: bool,
+
     /// Imprint to show in the footer.
     pub imprint: Option<String>,
 
+    /// The human-readable name of the service, shown in the web pages.
+    pub service_name: Option<String>,
+
+    /// The URL to the logo shown in the web pages.
+    pub logo_uri: Option<Url>,
+
+    /// Colors replacing the ones of the default theme, keyed by the name of
+    /// their Compound color token.
+    pub theme_colors: BTreeMap<String, String>,
+
     /// Whether password login is enabled.
     pub password_login_enabled: bool,
 
+    /// Usernames of the users who can log in with their password even if
+    /// password login is disabled.
+    pub password_login_exceptions: Vec<String>,
+
     /// Whether password registration is enabled.
     pub password_registration_enabled: bool,
 
+    /// Whether guest accounts can be registered through the compatibility
+    /// layer.
+    pub guest_registration_enabled: bool,
+
+    /// How long guest accounts are kept once none of their sessions were used
+    pub guest_inactivity_ttl: Duration,
+
     /// Whether users can change their email.
     pub email_change_allowed: bool,
 
@@ -68,10 +337,243 @@ pub struct SiteConfig {
     /// Whether users can recover their account via email.
     pub account_recovery_allowed: bool,
 
+    /// How long users keep their admin privileges after they authenticated, if
+    /// limited
+    pub admin_elevation_ttl: Option<Duration>,
+
+    /// How long the consent users give to clients is remembered, if limited
+    pub consent_ttl: Option<Duration>,
+
+    /// How often users who opted in receive a digest of their account
+    /// activity by email. Users can't opt in if this is not set.
+    pub activity_digest_interval: Option<Duration>,
+
+    /// How long accounts are kept after users asked for them to be deleted.
+    /// Users can't delete their account themselves if this is not set.
+    pub account_deletion_grace_period: Option<Duration>,
+
+    /// Whether users are notified by email when they sign in from a new device
+    pub new_device_emails: bool,
+
+    /// Limit on the number of sessions each user can have at the same time, if
+    /// any
+    pub session_limit: Option<SessionLimit>,
+
     /// Captcha configuration
     pub captcha: Option<CaptchaConfig>,
 
     /// Minimum password complexity, between 0 and 4.
     /// This is a score from zxcvbn.
     pub minimum_password_complexity: u8,
+
+    /// Issuers trusted to sign assertions for the JWT bearer grant
+    pub jwt_bearer_issuers: Vec<JwtBearerIssuer>,
+
+    /// Deprecation of compatibility endpoints, advertised to clients
+    pub compat_deprecation: Option<CompatDeprecation>,
+
+    /// Compatibility login types which are disabled, and from when
+    pub compat_disabled_login_types: Vec<(CompatLoginType, DateTime<Utc>)>,
+
+    /// Whether all clients must use pushed authorization requests to start an
+    /// authorization grant
+    pub require_pushed_authorization_requests: bool,
+
+    /// Whether users are notified by email when a refresh token of one of
+    /// their sessions is reused
+    pub notify_refresh_token_reuse: bool,
+
+    /// How long browser sessions bound to a WebAuthn credential stay valid
+    /// without a new proof of possession, if session binding is enabled
+    pub browser_session_binding_ttl: Option<Duration>,
+
+    /// How long the browser sessions administrators start to impersonate
+    /// users last, if impersonation is enabled
+    pub impersonation_ttl: Option<Duration>,
+
+    /// How long browsers users chose to trust stay trusted, if users can
+    /// trust browsers
+    pub trusted_browser_ttl: Option<Duration>,
+
+    /// Maximum lifetime of the sessions of users logged in on kiosk devices
+    pub kiosk_session_ttl: Duration,
+
+    /// How long the sessions of users logged in on kiosk devices can stay
+    /// inactive before they are ended
+    pub kiosk_session_inactivity_ttl: Duration,
+
+    /// How long the rendezvous sessions used to sign in with a QR code last,
+    /// if the rendezvous endpoints are enabled
+    pub rendezvous_ttl: Option<Duration>,
+
+    /// How long access tokens are still reported as active by the
+    /// introspection endpoint after they expired
+    pub introspection_grace_period: Duration,
+
+    /// Restrictions on the types of tokens some clients can introspect
+    pub introspection_restrictions: Vec<IntrospectionRestriction>,
+
+    /// Other homeservers served by this service, in addition to the main one
+    pub additional_homeservers: Vec<AdditionalHomeserver>,
+
+    /// Deployment-specific scopes which clients can request
+    pub custom_scopes: Vec<CustomScope>,
+
+    /// Rules for the redirect URIs clients can use
+    pub redirect_uri_rules: RedirectUriRules,
+
+    /// Application services which can log in as the users in their namespace
+    pub appservices: Vec<Appservice>,
+}
+
+impl SiteConfig {
+    /// The terms of service users have to accept before continuing, if any
+    #[must_use]
+    pub fn required_terms(&self) -> Option<&Url> {
+        self.tos_uri
+            .as_ref()
+            .filter(|_| self.terms_acceptance_required)
+    }
+
+    /// Whether the given user can log in with their password
+    #[must_use]
+    pub fn is_password_login_allowed(&self, username: &str) -> bool {
+        self.password_login_enabled
+            || self
+                .password_login_exceptions
+                .iter()
+                .any(|exception| exception == username)
+    }
+
+    /// Whether the given compatibility login type is still enabled at the
+    /// given time
+    #[must_use]
+    pub fn is_compat_login_type_enabled(
+        &self,
+        login_type: CompatLoginType,
+        now: DateTime<Utc>,
+    ) -> bool {
+        !self
+            .compat_disabled_login_types
+            .iter()
+            .any(|(disabled, after)| *disabled == login_type && *after <= now)
+    }
+
+    /// Get the additional homeserver selected on the given hostname, if any
+    #[must_use]
+    pub fn homeserver_for_host(&self, host: &str) -> Option<&str> {
+        self.additional_homeservers
+            .iter()
+            .find(|homeserver| {
+                homeserver
+                    .hosts
+                    .iter()
+                    .any(|candidate| candidate.eq_ignore_ascii_case(host))
+            })
+            .map(|homeserver| homeserver.server_name.as_str())
+    }
+
+    /// Whether the given client may see the users of the given homeserver,
+    /// `None` being the main one.
+    ///
+    /// Clients belonging to an additional homeserver only see its users, and
+    /// other clients only see the users of the main homeserver.
+    #[must_use]
+    pub fn client_sees_homeserver(&self, client_id: Ulid, homeserver: Option<&str>) -> bool {
+        self.homeserver_for_client(client_id) == homeserver
+    }
+
+    /// Get the additional homeserver the given client belongs to, if any
+    #[must_use]
+    pub fn homeserver_for_client(&self, client_id: Ulid) -> Option<&str> {
+        self.additional_homeservers
+            .iter()
+            .find(|candidate| candidate.clients.contains(&client_id))
+            .map(|candidate| candidate.server_name.as_str())
+    }
+
+    /// Whether the given server name is the one of an additional homeserver
+    #[must_use]
+    pub fn is_additional_homeserver(&self, server_name: &str) -> bool {
+        self.additional_homeservers
+            .iter()
+            .any(|homeserver| homeserver.server_name == server_name)
+    }
+
+    /// Whether the given browser session went past its maximum lifetime, or
+    /// stayed inactive for too long, at the given time
+    #[must_use]
+    pub fn is_browser_session_expired(&self, session: &BrowserSession, now: DateTime<Utc>) -> bool {
+        is_expired(
+            session.created_at,
+            session.last_active_at,
+            self.browser_session_ttl,
+            self.browser_session_inactivity_ttl,
+            now,
+        )
+    }
+
+    /// Whether the given compatibility session went past its maximum lifetime,
+    /// or stayed inactive for too long, at the given time
+    ///
+    /// Sessions of bot accounts don't expire for inactivity.
+    #[must_use]
+    pub fn is_compat_session_expired(
+        &self,
+        session: &CompatSession,
+        user: &User,
+        now: DateTime<Utc>,
+    ) -> bool {
+        is_expired(
+            session.created_at,
+            session.last_active_at,
+            self.compat_session_ttl,
+            self.compat_session_inactivity_ttl.filter(|_| !user.is_bot),
+            now,
+        )
+    }
+
+    /// Whether the given client is allowed to introspect the given type of
+    /// token
+    #[must_use]
+    pub fn can_introspect(&self, client_id: Ulid, token_type: TokenType) -> bool {
+        self.introspection_restrictions
+            .iter()
+            .find(|restriction| restriction.client_id == client_id)
+            .map_or(true, |restriction| {
+                restriction.token_types.contains(&token_type)
+            })
+    }
+
+    /// Whether users have to explicitly consent to the given scope before it
+    /// is granted to a client
+    #[must_use]
+    pub fn scope_requires_consent(&self, scope: &str) -> bool {
+        self.custom_scopes
+            .iter()
+            .find(|custom| custom.scope == scope)
+            .map_or(true, |custom| custom.requires_consent)
+    }
+
+    /// Find the application service authenticating with the given token
+    #[must_use]
+    pub fn appservice_by_token(&self, token: &str) -> Option<&Appservice> {
+        self.appservices
+            .iter()
+            .find(|appservice| appservice.as_token == token)
+    }
+}
+
+/// Whether a session created and last active at the given times expired at the
+/// given time
+fn is_expired(
+    created_at: DateTime<Utc>,
+    last_active_at: Option<DateTime<Utc>>,
+    ttl: Option<Duration>,
+    inactivity_ttl: Option<Duration>,
+    now: DateTime<Utc>,
+) -> bool {
+    let last_active_at = last_active_at.unwrap_or(created_at);
+    ttl.is_some_and(|ttl| created_at + ttl < now)
+        || inactivity_ttl.is_some_and(|ttl| last_active_at + ttl < now)
 }
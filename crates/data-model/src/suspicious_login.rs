@@ -0,0 +1,36 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use serde::Serialize;
+
+/// Why a login was considered suspicious
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SuspiciousLoginReason {
+    /// The login came from a country the user never logged in from before
+    NewCountry {
+        /// The ISO 3166-1 code of the country
+        country: String,
+    },
+
+    /// The login came from too far away from the previous one for the user to
+    /// have travelled in between
+    ImpossibleTravel {
+        /// The distance between the two logins, in kilometres
+        distance_km: u64,
+
+        /// The speed the user would have travelled at, in kilometres per hour
+        speed_kmh: u64,
+    },
+
+    /// The login was made with a browser or device the user never used before
+    NewDevice,
+
+    /// The login was preceded by many failed attempts
+    FailedAttempts {
+        /// How many failed attempts there were since the previous login
+        count: usize,
+    },
+}
@@ -0,0 +1,111 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+use ulid::Ulid;
+
+/// How a [`UpstreamOAuthEmailChange`] was resolved by an administrator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamOAuthEmailChangeResolution {
+    /// The new email address was added to the account
+    Applied,
+
+    /// The change was dismissed, and the account was left as is
+    Dismissed,
+}
+
+impl UpstreamOAuthEmailChangeResolution {
+    /// Get the string representation of this resolution, as stored in the
+    /// database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Applied => "applied",
+            Self::Dismissed => "dismissed",
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamOAuthEmailChangeResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid
+/// [`UpstreamOAuthEmailChangeResolution`]
+#[derive(Debug, Error)]
+#[error("invalid upstream OAuth 2.0 email change resolution {0:?}")]
+pub struct InvalidUpstreamOAuthEmailChangeResolutionError(String);
+
+impl std::str::FromStr for UpstreamOAuthEmailChangeResolution {
+    type Err = InvalidUpstreamOAuthEmailChangeResolutionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "applied" => Ok(Self::Applied),
+            "dismissed" => Ok(Self::Dismissed),
+            _ => Err(InvalidUpstreamOAuthEmailChangeResolutionError(s.to_owned())),
+        }
+    }
+}
+
+/// A change of the email address asserted by an upstream provider for a linked
+/// account, which needs to be reviewed by an administrator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpstreamOAuthEmailChange {
+    pub id: Ulid,
+    pub created_at: DateTime<Utc>,
+    pub link_id: Ulid,
+    pub user_id: Ulid,
+
+    /// The other account which already has the new email address, if any
+    pub conflicting_user_id: Option<Ulid>,
+
+    /// The email address the provider asserted before
+    pub previous_email: Option<String>,
+
+    /// The email address the provider asserts now
+    pub new_email: String,
+
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolution: Option<UpstreamOAuthEmailChangeResolution>,
+}
+
+impl UpstreamOAuthEmailChange {
+    /// Returns `true` if the change still needs to be reviewed
+    #[must_use]
+    pub const fn is_pending(&self) -> bool {
+        self.resolved_at.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_round_trip() {
+        for resolution in [
+            UpstreamOAuthEmailChangeResolution::Applied,
+            UpstreamOAuthEmailChangeResolution::Dismissed,
+        ] {
+            assert_eq!(
+                resolution
+                    .as_str()
+                    .parse::<UpstreamOAuthEmailChangeResolution>()
+                    .unwrap(),
+                resolution
+            );
+        }
+
+        assert!("unknown"
+            .parse::<UpstreamOAuthEmailChangeResolution>()
+            .is_err());
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use thiserror::Error;
+
+/// A stage of the funnel users go through when they sign up with an upstream
+/// OAuth 2.0 provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamOAuthFunnelStage {
+    /// The user was redirected to the provider
+    Redirected,
+
+    /// The provider redirected the user back with an authorization code
+    CallbackReceived,
+
+    /// The claims of the provider were mapped to the attributes of a new
+    /// account, and the user was shown the registration form
+    ClaimsMapped,
+
+    /// The user account was created
+    UserCreated,
+
+    /// The user was provisioned on the homeserver
+    Provisioned,
+
+    /// A client got its first token on behalf of the user
+    FirstTokenIssued,
+}
+
+impl UpstreamOAuthFunnelStage {
+    /// All the stages, in the order users go through them
+    pub const ALL: [Self; 6] = [
+        Self::Redirected,
+        Self::CallbackReceived,
+        Self::ClaimsMapped,
+        Self::UserCreated,
+        Self::Provisioned,
+        Self::FirstTokenIssued,
+    ];
+
+    /// Get the string representation of this stage, as stored in the database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Redirected => "redirected",
+            Self::CallbackReceived => "callback_received",
+            Self::ClaimsMapped => "claims_mapped",
+            Self::UserCreated => "user_created",
+            Self::Provisioned => "provisioned",
+            Self::FirstTokenIssued => "first_token_issued",
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamOAuthFunnelStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid [`UpstreamOAuthFunnelStage`]
+#[derive(Debug, Error)]
+#[error("invalid upstream OAuth 2.0 funnel stage {0:?}")]
+pub struct InvalidUpstreamOAuthFunnelStageError(String);
+
+impl std::str::FromStr for UpstreamOAuthFunnelStage {
+    type Err = InvalidUpstreamOAuthFunnelStageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redirected" => Ok(Self::Redirected),
+            "callback_received" => Ok(Self::CallbackReceived),
+            "claims_mapped" => Ok(Self::ClaimsMapped),
+            "user_created" => Ok(Self::UserCreated),
+            "provisioned" => Ok(Self::Provisioned),
+            "first_token_issued" => Ok(Self::FirstTokenIssued),
+            _ => Err(InvalidUpstreamOAuthFunnelStageError(s.to_owned())),
+        }
+    }
+}
+
+/// How many users reached a stage of the upstream OAuth 2.0 funnel, and how
+/// many dropped off at that stage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpstreamOAuthFunnelStageReport {
+    /// The stage of the funnel
+    pub stage: UpstreamOAuthFunnelStage,
+
+    /// How many times the stage was reached
+    pub reached: u64,
+
+    /// How many times users dropped off at that stage, by reason
+    pub dropoffs: BTreeMap<String, u64>,
+}
+
+impl UpstreamOAuthFunnelStageReport {
+    /// Create an empty report for the given stage
+    #[must_use]
+    pub fn new(stage: UpstreamOAuthFunnelStage) -> Self {
+        Self {
+            stage,
+            reached: 0,
+            dropoffs: BTreeMap::new(),
+        }
+    }
+
+    /// How many times users dropped off at that stage, for any reason
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.dropoffs.values().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_round_trip() {
+        for stage in UpstreamOAuthFunnelStage::ALL {
+            assert_eq!(
+                stage.as_str().parse::<UpstreamOAuthFunnelStage>().unwrap(),
+                stage
+            );
+        }
+
+        assert!("unknown".parse::<UpstreamOAuthFunnelStage>().is_err());
+    }
+}
@@ -14,5 +14,9 @@ pub struct UpstreamOAuthLink {
     pub provider_id: Ulid,
     pub user_id: Option<Ulid>,
     pub subject: String,
+
+    /// The email address the provider last asserted for this subject
+    pub asserted_email: Option<String>,
+
     pub created_at: DateTime<Utc>,
 }
@@ -4,20 +4,33 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+mod email_change;
+mod funnel;
 mod link;
 mod provider;
 mod session;
 
 pub use self::{
+    email_change::{
+        InvalidUpstreamOAuthEmailChangeResolutionError, UpstreamOAuthEmailChange,
+        UpstreamOAuthEmailChangeResolution,
+    },
+    funnel::{
+        InvalidUpstreamOAuthFunnelStageError, UpstreamOAuthFunnelStage,
+        UpstreamOAuthFunnelStageReport,
+    },
     link::UpstreamOAuthLink,
     provider::{
         ClaimsImports as UpstreamOAuthProviderClaimsImports,
         DiscoveryMode as UpstreamOAuthProviderDiscoveryMode,
         ImportAction as UpstreamOAuthProviderImportAction,
         ImportPreference as UpstreamOAuthProviderImportPreference,
-        PkceMode as UpstreamOAuthProviderPkceMode,
+        OnEmailChange as UpstreamOAuthProviderOnEmailChange,
+        ParMode as UpstreamOAuthProviderParMode, PkceMode as UpstreamOAuthProviderPkceMode,
+        ResponseMode as UpstreamOAuthProviderResponseMode,
         SetEmailVerification as UpsreamOAuthProviderSetEmailVerification,
-        SubjectPreference as UpstreamOAuthProviderSubjectPreference, UpstreamOAuthProvider,
+        SubjectPreference as UpstreamOAuthProviderSubjectPreference,
+        TokenAuthMethod as UpstreamOAuthProviderTokenAuthMethod, UpstreamOAuthProvider,
     },
     session::{UpstreamOAuthAuthorizationSession, UpstreamOAuthAuthorizationSessionState},
 };
@@ -5,7 +5,7 @@
 // Please see LICENSE in the repository root for full details.
 
 use chrono::{DateTime, Utc};
-use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use mas_iana::jose::JsonWebSignatureAlg;
 use oauth2_types::scope::Scope;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -116,6 +116,170 @@ impl std::fmt::Display for PkceMode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParMode {
+    /// Use pushed authorization requests if the provider requires it
+    #[default]
+    Auto,
+
+    /// Always use pushed authorization requests
+    Always,
+
+    /// Don't use pushed authorization requests
+    Disabled,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid PAR mode {0:?}")]
+pub struct InvalidParModeError(String);
+
+impl std::str::FromStr for ParMode {
+    type Err = InvalidParModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "disabled" => Ok(Self::Disabled),
+            s => Err(InvalidParModeError(s.to_owned())),
+        }
+    }
+}
+
+impl ParMode {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Always => "always",
+            Self::Disabled => "disabled",
+        }
+    }
+}
+
+impl std::fmt::Display for ParMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How MAS authenticates against the provider token endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAuthMethod {
+    /// No client authentication
+    None,
+
+    /// Client ID and secret sent in the `Authorization` header
+    ClientSecretBasic,
+
+    /// Client ID and secret sent in the request body
+    ClientSecretPost,
+
+    /// A client assertion signed with the client secret
+    ClientSecretJwt,
+
+    /// A client assertion signed with one of the MAS keys
+    PrivateKeyJwt,
+
+    /// A client secret generated as a JWT signed with the Apple-issued key, as
+    /// required by Sign in with Apple
+    SignInWithApple,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid token auth method {0:?}")]
+pub struct InvalidTokenAuthMethodError(String);
+
+impl std::str::FromStr for TokenAuthMethod {
+    type Err = InvalidTokenAuthMethodError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "client_secret_basic" => Ok(Self::ClientSecretBasic),
+            "client_secret_post" => Ok(Self::ClientSecretPost),
+            "client_secret_jwt" => Ok(Self::ClientSecretJwt),
+            "private_key_jwt" => Ok(Self::PrivateKeyJwt),
+            "sign_in_with_apple" => Ok(Self::SignInWithApple),
+            s => Err(InvalidTokenAuthMethodError(s.to_owned())),
+        }
+    }
+}
+
+impl TokenAuthMethod {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::ClientSecretBasic => "client_secret_basic",
+            Self::ClientSecretPost => "client_secret_post",
+            Self::ClientSecretJwt => "client_secret_jwt",
+            Self::PrivateKeyJwt => "private_key_jwt",
+            Self::SignInWithApple => "sign_in_with_apple",
+        }
+    }
+}
+
+impl std::fmt::Display for TokenAuthMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the provider should send the authorization response back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// Parameters are sent in the query string of a redirect
+    Query,
+
+    /// Parameters are sent in the body of a form submitted with `POST`
+    FormPost,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid response mode {0:?}")]
+pub struct InvalidResponseModeError(String);
+
+impl std::str::FromStr for ResponseMode {
+    type Err = InvalidResponseModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "query" => Ok(Self::Query),
+            "form_post" => Ok(Self::FormPost),
+            s => Err(InvalidResponseModeError(s.to_owned())),
+        }
+    }
+}
+
+impl ResponseMode {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Query => "query",
+            Self::FormPost => "form_post",
+        }
+    }
+}
+
+impl std::fmt::Display for ResponseMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<ResponseMode> for oauth2_types::requests::ResponseMode {
+    fn from(value: ResponseMode) -> Self {
+        match value {
+            ResponseMode::Query => Self::Query,
+            ResponseMode::FormPost => Self::FormPost,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct UpstreamOAuthProvider {
     pub id: Ulid,
@@ -127,15 +291,41 @@ pub struct UpstreamOAuthProvider {
     pub jwks_uri_override: Option<Url>,
     pub authorization_endpoint_override: Option<Url>,
     pub token_endpoint_override: Option<Url>,
+    pub par_mode: ParMode,
+    pub pushed_authorization_request_endpoint_override: Option<Url>,
+    pub request_object_signing_alg: Option<JsonWebSignatureAlg>,
     pub scope: Scope,
     pub client_id: String,
     pub encrypted_client_secret: Option<String>,
     pub token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
-    pub token_endpoint_auth_method: OAuthClientAuthenticationMethod,
+    pub token_endpoint_auth_method: TokenAuthMethod,
+    pub response_mode: Option<ResponseMode>,
     pub created_at: DateTime<Utc>,
     pub disabled_at: Option<DateTime<Utc>>,
     pub claims_imports: ClaimsImports,
     pub additional_authorization_parameters: Vec<(String, String)>,
+
+    /// Whether the provider can initiate a login, by redirecting the user to
+    /// the login initiation endpoint
+    pub allow_idp_initiated_login: bool,
+
+    /// The ID of the client on which the user lands after a login initiated by
+    /// the provider. If `None`, the user lands on the account page.
+    pub idp_initiated_login_client_id: Option<Ulid>,
+
+    /// Whether to log the user out of the provider, through its end session
+    /// endpoint, when their session ends
+    pub propagate_logout: bool,
+
+    /// The end session endpoint to use instead of the discovered one
+    pub end_session_endpoint_override: Option<Url>,
+
+    /// Whether to fetch the user profile from the userinfo endpoint after the
+    /// authorization code exchange
+    pub fetch_userinfo: bool,
+
+    /// The userinfo endpoint to use instead of the discovered one
+    pub userinfo_endpoint_override: Option<Url>,
 }
 
 impl PartialOrd for UpstreamOAuthProvider {
@@ -184,6 +374,24 @@ impl SetEmailVerification {
     }
 }
 
+/// What to do when the email address asserted by the upstream provider for a
+/// linked account changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEmailChange {
+    /// Only keep track of the new email address
+    #[default]
+    Ignore,
+
+    /// Ask an administrator to review the change
+    Review,
+
+    /// Add the new email address to the account, unless it already belongs to
+    /// another account, in which case an administrator is asked to review the
+    /// change
+    Update,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct ClaimsImports {
     #[serde(default)]
@@ -195,11 +403,20 @@ pub struct ClaimsImports {
     #[serde(default)]
     pub displayname: ImportPreference,
 
+    #[serde(default)]
+    pub avatar: ImportPreference,
+
     #[serde(default)]
     pub email: ImportPreference,
 
     #[serde(default)]
     pub verify_email: SetEmailVerification,
+
+    #[serde(default)]
+    pub on_email_change: OnEmailChange,
+
+    #[serde(default)]
+    pub admin: ImportPreference,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
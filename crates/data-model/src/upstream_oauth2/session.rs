@@ -19,12 +19,16 @@ pub enum UpstreamOAuthAuthorizationSessionState {
         completed_at: DateTime<Utc>,
         link_id: Ulid,
         id_token: Option<String>,
+        extra_callback_parameters: Option<serde_json::Value>,
+        userinfo: Option<serde_json::Value>,
     },
     Consumed {
         completed_at: DateTime<Utc>,
         consumed_at: DateTime<Utc>,
         link_id: Ulid,
         id_token: Option<String>,
+        extra_callback_parameters: Option<serde_json::Value>,
+        userinfo: Option<serde_json::Value>,
     },
 }
 
@@ -42,12 +46,16 @@ impl UpstreamOAuthAuthorizationSessionState {
         completed_at: DateTime<Utc>,
         link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        extra_callback_parameters: Option<serde_json::Value>,
+        userinfo: Option<serde_json::Value>,
     ) -> Result<Self, InvalidTransitionError> {
         match self {
             Self::Pending => Ok(Self::Completed {
                 completed_at,
                 link_id: link.id,
                 id_token,
+                extra_callback_parameters,
+                userinfo,
             }),
             Self::Completed { .. } | Self::Consumed { .. } => Err(InvalidTransitionError),
         }
@@ -67,11 +75,15 @@ impl UpstreamOAuthAuthorizationSessionState {
                 completed_at,
                 link_id,
                 id_token,
+                extra_callback_parameters,
+                userinfo,
             } => Ok(Self::Consumed {
                 completed_at,
                 link_id,
                 consumed_at,
                 id_token,
+                extra_callback_parameters,
+                userinfo,
             }),
             Self::Pending | Self::Consumed { .. } => Err(InvalidTransitionError),
         }
@@ -124,6 +136,42 @@ impl UpstreamOAuthAuthorizationSessionState {
         }
     }
 
+    /// Get the extra parameters the upstream provider sent back to the
+    /// callback, besides the authorization code and state.
+    ///
+    /// Returns `None` if the upstream OAuth 2.0 authorization session state is
+    /// [`Pending`].
+    ///
+    /// [`Pending`]: UpstreamOAuthAuthorizationSessionState::Pending
+    #[must_use]
+    pub fn extra_callback_parameters(&self) -> Option<&serde_json::Value> {
+        match self {
+            Self::Pending => None,
+            Self::Completed {
+                extra_callback_parameters,
+                ..
+            }
+            | Self::Consumed {
+                extra_callback_parameters,
+                ..
+            } => extra_callback_parameters.as_ref(),
+        }
+    }
+
+    /// Get the claims returned by the upstream provider's userinfo endpoint.
+    ///
+    /// Returns `None` if the upstream OAuth 2.0 authorization session state is
+    /// [`Pending`], or if the userinfo endpoint was not queried.
+    ///
+    /// [`Pending`]: UpstreamOAuthAuthorizationSessionState::Pending
+    #[must_use]
+    pub fn userinfo(&self) -> Option<&serde_json::Value> {
+        match self {
+            Self::Pending => None,
+            Self::Completed { userinfo, .. } | Self::Consumed { userinfo, .. } => userinfo.as_ref(),
+        }
+    }
+
     /// Get the time at which the upstream OAuth 2.0 authorization session was
     /// consumed.
     ///
@@ -201,8 +249,16 @@ impl UpstreamOAuthAuthorizationSession {
         completed_at: DateTime<Utc>,
         link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        extra_callback_parameters: Option<serde_json::Value>,
+        userinfo: Option<serde_json::Value>,
     ) -> Result<Self, InvalidTransitionError> {
-        self.state = self.state.complete(completed_at, link, id_token)?;
+        self.state = self.state.complete(
+            completed_at,
+            link,
+            id_token,
+            extra_callback_parameters,
+            userinfo,
+        )?;
         Ok(self)
     }
 
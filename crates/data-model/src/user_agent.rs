@@ -218,4 +218,56 @@ impl UserAgent {
             raw: user_agent,
         }
     }
+
+    /// Identifies the kind of device this user agent runs on, from the name of
+    /// the browser or client, the operating system and the type of device
+    ///
+    /// Versions are left out, so that updating the browser doesn't make it
+    /// look like a new device. Returns `None` if the user agent could not be
+    /// parsed.
+    #[must_use]
+    pub fn device_fingerprint(&self) -> Option<String> {
+        let name = self.name.as_deref()?;
+        let os = self.os.as_deref().unwrap_or("unknown");
+        let device_type = match self.device_type {
+            DeviceType::Pc => "pc",
+            DeviceType::Mobile => "mobile",
+            DeviceType::Tablet => "tablet",
+            DeviceType::Unknown => "unknown",
+        };
+
+        Some(format!("{name}/{os}/{device_type}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_fingerprint() {
+        let firefox = UserAgent::parse(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0".to_owned(),
+        );
+        assert_eq!(
+            firefox.device_fingerprint().as_deref(),
+            Some("Firefox/Linux/pc")
+        );
+
+        // Updating the browser doesn't change the fingerprint
+        let updated = UserAgent::parse(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:132.0) Gecko/20100101 Firefox/132.0".to_owned(),
+        );
+        assert_eq!(updated.device_fingerprint(), firefox.device_fingerprint());
+
+        let element =
+            UserAgent::parse("Element X/1.2.3 (iPhone14,5; iOS 17.1; Scale/3.00)".to_owned());
+        assert_eq!(
+            element.device_fingerprint().as_deref(),
+            Some("Element X/iOS/mobile")
+        );
+
+        let unknown = UserAgent::parse("definitely not a user agent".to_owned());
+        assert_eq!(unknown.device_fingerprint(), None);
+    }
 }
@@ -7,9 +7,11 @@
 use std::{net::IpAddr, ops::Deref};
 
 use chrono::{DateTime, Duration, Utc};
+use ipnetwork::IpNetwork;
 use rand::{Rng, SeedableRng};
 use serde::Serialize;
 use ulid::Ulid;
+use url::Url;
 
 use crate::UserAgent;
 
@@ -22,6 +24,31 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub locked_at: Option<DateTime<Utc>>,
     pub can_request_admin: bool,
+
+    /// When the user was placed under legal hold, if they are. Their data
+    /// can't be pruned or erased until the hold is released.
+    pub legal_hold_at: Option<DateTime<Utc>>,
+
+    /// The server name of the homeserver the user belongs to, if it is not
+    /// the main one
+    pub homeserver: Option<String>,
+
+    /// Whether this is a bot account, used for automation rather than by a
+    /// human
+    pub is_bot: bool,
+
+    /// The human account which owns this bot, if any. The owner can manage
+    /// the sessions of the bot.
+    pub bot_owner_id: Option<Ulid>,
+
+    /// Whether this is a guest account, registered without any credentials.
+    /// Guests get a restricted set of scopes, and are cleaned up once stale.
+    pub is_guest: bool,
+
+    /// When the account is due to be erased, if the user asked for it to be
+    /// deleted. The account is locked until then, and logging back in cancels
+    /// the deletion.
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
 }
 
 impl User {
@@ -30,6 +57,25 @@ impl User {
     pub fn is_valid(&self) -> bool {
         self.locked_at.is_none()
     }
+
+    /// Returns `true` if the user is under legal hold
+    #[must_use]
+    pub fn is_under_legal_hold(&self) -> bool {
+        self.legal_hold_at.is_some()
+    }
+
+    /// Returns `true` if the user asked for their account to be deleted, and
+    /// can still cancel it by logging back in
+    #[must_use]
+    pub fn is_pending_deletion(&self, now: DateTime<Utc>) -> bool {
+        self.deletion_scheduled_at.is_some_and(|at| at > now)
+    }
+
+    /// Returns `true` if the given user owns this bot account
+    #[must_use]
+    pub fn is_bot_owned_by(&self, owner: &User) -> bool {
+        self.is_bot && self.bot_owner_id == Some(owner.id)
+    }
 }
 
 impl User {
@@ -44,6 +90,12 @@ impl User {
             created_at: now,
             locked_at: None,
             can_request_admin: false,
+            legal_hold_at: None,
+            homeserver: None,
+            is_bot: false,
+            bot_owner_id: None,
+            is_guest: false,
+            deletion_scheduled_at: None,
         }]
     }
 }
@@ -55,8 +107,25 @@ pub struct Password {
     pub version: u16,
     pub upgraded_from_id: Option<Ulid>,
     pub created_at: DateTime<Utc>,
+
+    /// When the password was flagged as having to be changed on the next
+    /// login, if it was
+    pub must_change_at: Option<DateTime<Utc>>,
+}
+
+impl Password {
+    /// Returns `true` if the user has to change this password on their next
+    /// login
+    #[must_use]
+    pub fn must_change(&self) -> bool {
+        self.must_change_at.is_some()
+    }
 }
 
+/// The Authentication Context Class Reference of authentications where the
+/// user entered their password
+pub const ACR_PASSWORD: &str = "urn:mas:acr:password";
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Authentication {
     pub id: Ulid,
@@ -64,6 +133,18 @@ pub struct Authentication {
     pub authentication_method: AuthenticationMethod,
 }
 
+impl Authentication {
+    /// The Authentication Context Class Reference achieved by this
+    /// authentication, if it has one
+    #[must_use]
+    pub fn acr(&self) -> Option<&'static str> {
+        match self.authentication_method {
+            AuthenticationMethod::Password { .. } => Some(ACR_PASSWORD),
+            AuthenticationMethod::UpstreamOAuth2 { .. } | AuthenticationMethod::Unknown => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum AuthenticationMethod {
     Password { user_password_id: Ulid },
@@ -109,6 +190,109 @@ impl UserRecoveryTicket {
     }
 }
 
+/// The subscription of a [`User`] to the periodic email digest of their
+/// account activity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserActivityDigest {
+    pub id: Ulid,
+    pub user_id: Ulid,
+
+    /// The locale in which the digest is sent
+    pub locale: String,
+
+    pub created_at: DateTime<Utc>,
+    pub last_sent_at: Option<DateTime<Utc>>,
+}
+
+impl UserActivityDigest {
+    /// The start of the period covered by the next digest, which is when the
+    /// last digest was sent, or when the user subscribed
+    #[must_use]
+    pub fn period_start(&self) -> DateTime<Utc> {
+        self.last_sent_at.unwrap_or(self.created_at)
+    }
+}
+
+/// A one-time token, issued from the CLI, which lets an administrator start a
+/// browser session when the usual login methods are unavailable
+///
+/// The token can only be used once, before it expires, and from the network
+/// it was issued for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserEmergencyAccessToken {
+    pub id: Ulid,
+    pub user_id: Ulid,
+    pub token: String,
+    pub allowed_network: IpNetwork,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    pub user_session_id: Option<Ulid>,
+}
+
+impl UserEmergencyAccessToken {
+    /// Returns `true` if the token was not used yet and has not expired
+    #[must_use]
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.consumed_at.is_none() && now < self.expires_at
+    }
+
+    /// Returns `true` if the token can be used from the given IP address
+    #[must_use]
+    pub fn is_allowed_from(&self, ip: IpAddr) -> bool {
+        self.allowed_network.contains(ip)
+    }
+}
+
+/// A browser a [`User`] chose to trust when logging in, which isn't challenged
+/// again for additional authentication factors until the trust expires
+///
+/// The browser holds the ID in an encrypted cookie, and the trust only applies
+/// as long as the browser still looks like the one it was given to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserTrustedBrowser {
+    pub id: Ulid,
+    pub user_id: Ulid,
+    pub user_agent: Option<UserAgent>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl UserTrustedBrowser {
+    /// Returns `true` if the trust was not revoked and has not expired
+    #[must_use]
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && now < self.expires_at
+    }
+
+    /// Returns `true` if the given user agent looks like the one of the
+    /// browser which was trusted
+    ///
+    /// Only the kind of device is compared, so that updating the browser
+    /// doesn't revoke the trust.
+    #[must_use]
+    pub fn matches(&self, user_agent: Option<&UserAgent>) -> bool {
+        self.user_agent
+            .as_ref()
+            .and_then(UserAgent::device_fingerprint)
+            == user_agent.and_then(UserAgent::device_fingerprint)
+    }
+}
+
+/// The record of a [`User`] accepting a version of the terms of service
+///
+/// The URL of the terms of service identifies their version, so publishing
+/// new terms under a new URL asks users to accept them again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserTermsAcceptance {
+    pub id: Ulid,
+    pub user_id: Ulid,
+    pub terms_url: Url,
+    pub accepted_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct BrowserSession {
     pub id: Ulid,
@@ -118,6 +302,9 @@ pub struct BrowserSession {
     pub user_agent: Option<UserAgent>,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
+    pub tags: Vec<String>,
+    pub binding: Option<BrowserSessionBinding>,
+    pub impersonation: Option<BrowserSessionImpersonation>,
 }
 
 impl BrowserSession {
@@ -127,6 +314,70 @@ impl BrowserSession {
     }
 }
 
+/// Details about a [`BrowserSession`] that an administrator started to
+/// impersonate the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrowserSessionImpersonation {
+    /// The ID of the administrator impersonating the user, if they still exist
+    pub impersonator_user_id: Option<Ulid>,
+
+    /// The ID of the browser session of the administrator, which is restored
+    /// when the impersonation ends, if it still exists
+    pub impersonator_session_id: Option<Ulid>,
+
+    /// When the session ends, regardless of the activity on it
+    pub expires_at: DateTime<Utc>,
+}
+
+impl BrowserSessionImpersonation {
+    /// Returns `true` if the impersonation has not expired yet
+    #[must_use]
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at
+    }
+}
+
+/// A WebAuthn credential bound to a [`BrowserSession`], which the browser has
+/// to regularly prove it still holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrowserSessionBinding {
+    /// The ID of the credential, base64url-encoded
+    pub credential_id: String,
+
+    /// The public key of the credential, as a DER-encoded
+    /// `SubjectPublicKeyInfo`
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+
+    /// The signature counter reported by the authenticator on the last proof
+    pub sign_count: u32,
+
+    /// The token the session cookie has to hold
+    #[serde(skip)]
+    pub token: String,
+
+    /// The token issued before the last proof, which is still accepted so that
+    /// requests made concurrently to the renewal don't fail
+    #[serde(skip)]
+    pub previous_token: Option<String>,
+
+    /// When the session ends if the browser doesn't prove again that it holds
+    /// the credential
+    pub expires_at: DateTime<Utc>,
+}
+
+impl BrowserSessionBinding {
+    /// Whether the given token from the session cookie matches this binding
+    #[must_use]
+    pub fn accepts(&self, token: Option<&str>) -> bool {
+        let Some(token) = token else {
+            return false;
+        };
+
+        token == self.token || self.previous_token.as_deref() == Some(token)
+    }
+}
+
 impl BrowserSession {
     #[must_use]
     pub fn samples(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self> {
@@ -142,6 +393,9 @@ impl BrowserSession {
                 )),
                 last_active_at: Some(now),
                 last_active_ip: None,
+                tags: Vec::new(),
+                binding: None,
+                impersonation: None,
             })
             .collect()
     }
@@ -0,0 +1,117 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ulid::Ulid;
+
+/// The kind of event sent to webhook endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WebhookEventKind {
+    /// A new user was registered
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+
+    /// A user was deactivated
+    #[serde(rename = "user.deactivated")]
+    UserDeactivated,
+
+    /// A user was reactivated
+    #[serde(rename = "user.reactivated")]
+    UserReactivated,
+
+    /// A user was updated, for example locked or granted admin rights
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+
+    /// A user started a new session
+    #[serde(rename = "session.created")]
+    SessionCreated,
+
+    /// A session was ended, either by the user or by an administrator
+    #[serde(rename = "session.revoked")]
+    SessionRevoked,
+
+    /// A honeytoken was used
+    #[serde(rename = "honeytoken.used")]
+    HoneytokenUsed,
+}
+
+impl WebhookEventKind {
+    /// Get the string representation of this kind, as sent to the endpoints
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::UserRegistered => "user.registered",
+            Self::UserDeactivated => "user.deactivated",
+            Self::UserReactivated => "user.reactivated",
+            Self::UserUpdated => "user.updated",
+            Self::SessionCreated => "session.created",
+            Self::SessionRevoked => "session.revoked",
+            Self::HoneytokenUsed => "honeytoken.used",
+        }
+    }
+}
+
+impl std::fmt::Display for WebhookEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing an invalid [`WebhookEventKind`]
+#[derive(Debug, Error)]
+#[error("invalid webhook event kind {0:?}")]
+pub struct InvalidWebhookEventKindError(String);
+
+impl std::str::FromStr for WebhookEventKind {
+    type Err = InvalidWebhookEventKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user.registered" => Ok(Self::UserRegistered),
+            "user.deactivated" => Ok(Self::UserDeactivated),
+            "user.reactivated" => Ok(Self::UserReactivated),
+            "user.updated" => Ok(Self::UserUpdated),
+            "session.created" => Ok(Self::SessionCreated),
+            "session.revoked" => Ok(Self::SessionRevoked),
+            "honeytoken.used" => Ok(Self::HoneytokenUsed),
+            _ => Err(InvalidWebhookEventKindError(s.to_owned())),
+        }
+    }
+}
+
+/// An event sent to webhook endpoints and to the event stream
+///
+/// This is serialized as-is as the body of the webhook requests and of the
+/// messages published to the event stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// Unique ID of the event, which stays the same across delivery attempts
+    pub id: Ulid,
+
+    /// The kind of event
+    #[serde(rename = "type")]
+    pub kind: WebhookEventKind,
+
+    /// When the event happened
+    pub created_at: DateTime<Utc>,
+
+    /// Event-specific data
+    pub data: serde_json::Value,
+}
+
+/// An entry of the changes feed, which records the webhook events so that they
+/// can be polled incrementally
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangesFeedEntry {
+    /// ID of the entry, used as a cursor. Entries IDs are strictly increasing
+    /// in the order in which the entries were recorded.
+    pub id: Ulid,
+
+    /// The event recorded in this entry
+    pub event: WebhookEvent,
+}
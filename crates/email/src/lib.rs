@@ -17,6 +17,6 @@ pub use lettre::{
 pub use mas_templates::EmailVerificationContext;
 
 pub use self::{
-    mailer::Mailer,
+    mailer::{Email, Error, Mailer},
     transport::{SmtpMode, Transport as MailTransport},
 };
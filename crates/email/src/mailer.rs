@@ -6,30 +6,162 @@
 
 //! Send emails to users
 
+use std::sync::{Arc, LazyLock};
+
+use arc_swap::ArcSwap;
 use lettre::{
-    message::{Mailbox, MessageBuilder, MultiPart},
-    AsyncTransport, Message,
+    address::Envelope,
+    message::{
+        header::{Header, HeaderName, HeaderValue},
+        Mailbox, MessageBuilder, MultiPart,
+    },
+    Address, AsyncTransport, Message,
+};
+use mas_templates::{
+    EmailActivityDigestContext, EmailCibaContext, EmailCredentialsExpiringContext,
+    EmailHoneytokenUsedContext, EmailInactiveAccountContext, EmailNewDeviceContext,
+    EmailRecoveryContext, EmailRefreshTokenReuseContext, EmailSuspiciousLoginContext,
+    EmailVerificationContext, Templates, WithLanguage,
 };
-use mas_templates::{EmailRecoveryContext, EmailVerificationContext, Templates, WithLanguage};
+use mas_tower::{FaultInjector, RequestId};
+use opentelemetry::{metrics::Counter, Key, KeyValue};
 use thiserror::Error;
 
 use crate::MailTransport;
 
+const DOMAIN: Key = Key::from_static_str("domain");
+const MAILER: Key = Key::from_static_str("mailer");
+const OUTCOME: Key = Key::from_static_str("outcome");
+
+static DELIVERIES: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("mas.email.deliveries")
+    .with_description(
+        "The number of emails handed to the mail server, by destination domain, mailer and outcome",
+    )
+    .with_unit("{email}")
+    .init()
+});
+
+/// The `X-Request-ID` email header
+#[derive(Debug, Clone)]
+struct XRequestId(String);
+
+impl Header for XRequestId {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("X-Request-ID")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_owned()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+/// An email rendered by the [`Mailer`], ready to be handed to the mail server
+///
+/// It can be stored, for example in a job, and sent later with
+/// [`Mailer::send`].
+#[derive(Debug, Clone)]
+pub struct Email {
+    envelope: Envelope,
+    content: Vec<u8>,
+}
+
+impl Email {
+    fn from_message(message: &Message) -> Self {
+        Self {
+            envelope: message.envelope().clone(),
+            content: message.formatted(),
+        }
+    }
+
+    /// Rebuild an email from its sender, recipients and formatted content
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no recipients
+    pub fn new(
+        sender: Option<Address>,
+        recipients: Vec<Address>,
+        content: Vec<u8>,
+    ) -> Result<Self, Error> {
+        let envelope = Envelope::new(sender, recipients)?;
+        Ok(Self { envelope, content })
+    }
+
+    /// The address the email is sent from
+    #[must_use]
+    pub fn sender(&self) -> Option<&Address> {
+        self.envelope.from()
+    }
+
+    /// The addresses the email is sent to
+    #[must_use]
+    pub fn recipients(&self) -> &[Address] {
+        self.envelope.to()
+    }
+
+    /// The formatted email, with its headers
+    #[must_use]
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+}
+
 /// Helps sending mails to users
 #[derive(Clone)]
 pub struct Mailer {
     templates: Templates,
+    settings: Arc<ArcSwap<MailerSettings>>,
+    fault_injector: FaultInjector,
+}
+
+/// The parts of the [`Mailer`] which can be changed at runtime
+struct MailerSettings {
     transport: MailTransport,
     from: Mailbox,
     reply_to: Mailbox,
 }
 
+/// An error which happened while sending an email
 #[derive(Debug, Error)]
 #[error(transparent)]
 pub enum Error {
+    /// The mail server didn't accept the email
     Transport(#[from] crate::transport::Error),
+
+    /// The email could not be rendered
     Templates(#[from] mas_templates::TemplateError),
+
+    /// The email could not be built
     Content(#[from] lettre::error::Error),
+
+    /// A fault was injected instead of sending the email
+    FaultInjected(#[from] mas_tower::InjectedFault),
+}
+
+impl Error {
+    /// Whether the email was rendered, but could not be delivered
+    #[must_use]
+    pub fn is_delivery_failure(&self) -> bool {
+        matches!(self, Self::Transport(_) | Self::FaultInjected(_))
+    }
+
+    /// Whether the mail server rejected the email permanently, meaning that
+    /// sending it again to the same address is pointless
+    #[must_use]
+    pub fn is_bounce(&self) -> bool {
+        matches!(self, Self::Transport(crate::transport::Error::Smtp(e)) if e.is_permanent())
+    }
 }
 
 impl Mailer {
@@ -43,16 +175,86 @@ impl Mailer {
     ) -> Self {
         Self {
             templates,
-            transport,
-            from,
-            reply_to,
+            settings: Arc::new(ArcSwap::from_pointee(MailerSettings {
+                transport,
+                from,
+                reply_to,
+            })),
+            fault_injector: FaultInjector::disabled(),
         }
     }
 
+    /// Use the transport and addresses of another [`Mailer`] for the emails
+    /// sent from now on, by this [`Mailer`] and all its clones
+    pub fn reconfigure(&self, other: &Self) {
+        self.settings.store(other.settings.load_full());
+    }
+
+    fn transport(&self) -> MailTransport {
+        self.settings.load().transport.clone()
+    }
+
+    /// Set the [`FaultInjector`] used to inject faults when sending emails
+    #[must_use]
+    pub fn with_fault_injector(mut self, fault_injector: FaultInjector) -> Self {
+        self.fault_injector = fault_injector;
+        self
+    }
+
     fn base_message(&self) -> MessageBuilder {
-        Message::builder()
-            .from(self.from.clone())
-            .reply_to(self.reply_to.clone())
+        let settings = self.settings.load();
+        let builder = Message::builder()
+            .from(settings.from.clone())
+            .reply_to(settings.reply_to.clone());
+
+        // Tag the email with the request which triggered it, to help with
+        // debugging deliverability issues
+        if let Some(request_id) = RequestId::current() {
+            builder.header(XRequestId(request_id.to_string()))
+        } else {
+            builder
+        }
+    }
+
+    /// Hand a rendered email to the mail server, recording the outcome per
+    /// destination domain and mailer
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the mail server did not accept the email
+    #[tracing::instrument(name = "email.send", skip_all, err)]
+    pub async fn send(&self, email: &Email) -> Result<(), Error> {
+        let domain = email
+            .recipients()
+            .first()
+            .map(|address| address.domain().to_lowercase())
+            .unwrap_or_default();
+
+        let transport = self.transport();
+        let mailer = transport.kind();
+
+        let result = async {
+            self.fault_injector.inject().await?;
+            transport.send_raw(&email.envelope, &email.content).await?;
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        let outcome = match &result {
+            Ok(()) => "sent",
+            Err(e) if e.is_bounce() => "bounced",
+            Err(_) => "deferred",
+        };
+        DELIVERIES.add(
+            1,
+            &[
+                KeyValue::new(DOMAIN, domain),
+                KeyValue::new(MAILER, mailer),
+                KeyValue::new(OUTCOME, outcome),
+            ],
+        );
+
+        result
     }
 
     fn prepare_verification_email(
@@ -99,13 +301,209 @@ impl Mailer {
         Ok(message)
     }
 
-    /// Send the verification email to a user
+    fn prepare_ciba_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailCibaContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_ciba_txt(context)?;
+
+        let html = self.templates.render_email_ciba_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self.templates.render_email_ciba_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_refresh_token_reuse_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailRefreshTokenReuseContext>,
+    ) -> Result<Message, Error> {
+        let plain = self
+            .templates
+            .render_email_refresh_token_reused_txt(context)?;
+
+        let html = self
+            .templates
+            .render_email_refresh_token_reused_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_refresh_token_reused_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_suspicious_login_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailSuspiciousLoginContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_suspicious_login_txt(context)?;
+
+        let html = self.templates.render_email_suspicious_login_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_suspicious_login_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_new_device_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailNewDeviceContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_new_device_txt(context)?;
+
+        let html = self.templates.render_email_new_device_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self.templates.render_email_new_device_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_inactive_account_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailInactiveAccountContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_inactive_account_txt(context)?;
+
+        let html = self.templates.render_email_inactive_account_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_inactive_account_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_activity_digest_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailActivityDigestContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_activity_digest_txt(context)?;
+
+        let html = self.templates.render_email_activity_digest_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_activity_digest_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_honeytoken_used_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailHoneytokenUsedContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_honeytoken_used_txt(context)?;
+
+        let html = self.templates.render_email_honeytoken_used_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_honeytoken_used_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    fn prepare_credentials_expiring_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailCredentialsExpiringContext>,
+    ) -> Result<Message, Error> {
+        let plain = self
+            .templates
+            .render_email_credentials_expiring_txt(context)?;
+
+        let html = self
+            .templates
+            .render_email_credentials_expiring_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_credentials_expiring_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    /// Render the verification email for a user
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the email failed rendering or failed sending
+    /// Will return `Err` if the email failed rendering
     #[tracing::instrument(
-        name = "email.verification.send",
+        name = "email.verification.render",
         skip_all,
         fields(
             email.to = %to,
@@ -116,23 +514,22 @@ impl Mailer {
         ),
         err,
     )]
-    pub async fn send_verification_email(
+    pub fn render_verification_email(
         &self,
         to: Mailbox,
         context: &WithLanguage<EmailVerificationContext>,
-    ) -> Result<(), Error> {
+    ) -> Result<Email, Error> {
         let message = self.prepare_verification_email(to, context)?;
-        self.transport.send(message).await?;
-        Ok(())
+        Ok(Email::from_message(&message))
     }
 
-    /// Send the recovery email to a user
+    /// Render the recovery email for a user
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the email failed rendering or failed sending
+    /// Will return `Err` if the email failed rendering
     #[tracing::instrument(
-        name = "email.recovery.send",
+        name = "email.recovery.render",
         skip_all,
         fields(
             email.to = %to,
@@ -142,14 +539,210 @@ impl Mailer {
         ),
         err,
     )]
-    pub async fn send_recovery_email(
+    pub fn render_recovery_email(
         &self,
         to: Mailbox,
         context: &WithLanguage<EmailRecoveryContext>,
-    ) -> Result<(), Error> {
+    ) -> Result<Email, Error> {
         let message = self.prepare_recovery_email(to, context)?;
-        self.transport.send(message).await?;
-        Ok(())
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the email asking a user to approve a backchannel authentication
+    /// request
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.ciba.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+            oauth2_ciba_grant.id = %context.grant().id,
+        ),
+        err,
+    )]
+    pub fn render_ciba_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailCibaContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_ciba_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the email notifying a user that one of their sessions was ended
+    /// because one of its refresh tokens was reused
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.refresh_token_reused.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+            oauth2_session.id = %context.session().id,
+        ),
+        err,
+    )]
+    pub fn render_refresh_token_reuse_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailRefreshTokenReuseContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_refresh_token_reuse_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the email notifying a user of a suspicious login on their account
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.suspicious_login.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+        ),
+        err,
+    )]
+    pub fn render_suspicious_login_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailSuspiciousLoginContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_suspicious_login_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the email notifying a user of a sign-in from a new device
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.new_device.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+        ),
+        err,
+    )]
+    pub fn render_new_device_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailNewDeviceContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_new_device_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the periodic digest of the activity on a user's account
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.activity_digest.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+        ),
+        err,
+    )]
+    pub fn render_activity_digest_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailActivityDigestContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_activity_digest_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the email warning a user that their account will be deactivated
+    /// because they didn't use it for a long time
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.inactive_account.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+        ),
+        err,
+    )]
+    pub fn render_inactive_account_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailInactiveAccountContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_inactive_account_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the email alerting an administrator that a honeytoken was used
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.honeytoken_used.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            honeytoken.id = %context.honeytoken().id,
+        ),
+        err,
+    )]
+    pub fn render_honeytoken_used_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailHoneytokenUsedContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_honeytoken_used_email(to, context)?;
+        Ok(Email::from_message(&message))
+    }
+
+    /// Render the warning about expiring credentials to an administrator
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering
+    #[tracing::instrument(
+        name = "email.credentials_expiring.render",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            credentials.count = context.credentials().len(),
+        ),
+        err,
+    )]
+    pub fn render_credentials_expiring_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailCredentialsExpiringContext>,
+    ) -> Result<Email, Error> {
+        let message = self.prepare_credentials_expiring_email(to, context)?;
+        Ok(Email::from_message(&message))
     }
 
     /// Test the connetion to the mail server
@@ -159,6 +752,6 @@ impl Mailer {
     /// Returns an error if the connection failed
     #[tracing::instrument(name = "email.test_connection", skip_all, err)]
     pub async fn test_connection(&self) -> Result<(), crate::transport::Error> {
-        self.transport.test_connection().await
+        self.transport().test_connection().await
     }
 }
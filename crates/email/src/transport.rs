@@ -95,6 +95,16 @@ impl Transport {
 }
 
 impl Transport {
+    /// The kind of backend behind this transport, used to label metrics
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self.inner.as_ref() {
+            TransportInner::Blackhole => "blackhole",
+            TransportInner::Smtp(_) => "smtp",
+            TransportInner::Sendmail(_) => "sendmail",
+        }
+    }
+
     /// Test the connection to the underlying transport. Only works with the
     /// SMTP backend for now
     ///
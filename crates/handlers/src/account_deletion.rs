@@ -0,0 +1,51 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Account deletions requested by the users themselves
+//!
+//! When users ask for the deletion of their account, it is locked and only
+//! deleted at the end of a grace period. Logging back in before then cancels
+//! the deletion, which is what this module takes care of.
+
+use mas_data_model::User;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    user::UserRepository,
+    Clock, RepositoryAccess,
+};
+use rand::RngCore;
+
+/// Cancel the deletion of the account of a user who just authenticated, if
+/// one is pending, and unlock the account
+///
+/// Returns the user unchanged if no deletion is pending.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn cancel_pending_deletion<R: RepositoryAccess>(
+    rng: &mut (impl RngCore + Send),
+    clock: &dyn Clock,
+    repo: &mut R,
+    user: User,
+) -> Result<User, R::Error> {
+    if !user.is_pending_deletion(clock.now()) {
+        return Ok(user);
+    }
+
+    let user = repo.user().clear_scheduled_deletion(user).await?;
+    let user = repo.user().unlock(user).await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_updated(rng, clock, &user))
+        .await?;
+
+    tracing::info!(
+        user.id = %user.id,
+        "User logged back in, cancelling the deletion of their account"
+    );
+
+    Ok(user)
+}
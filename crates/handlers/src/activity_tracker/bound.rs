@@ -6,7 +6,7 @@
 
 use std::net::IpAddr;
 
-use mas_data_model::{BrowserSession, CompatSession, Session};
+use mas_data_model::{BrowserSession, CompatSession, Honeytoken, Session};
 use mas_storage::Clock;
 
 use crate::activity_tracker::ActivityTracker;
@@ -51,4 +51,17 @@ impl Bound {
             .record_browser_session(clock, session, self.ip)
             .await;
     }
+
+    /// Report that a honeytoken was used.
+    pub async fn report_honeytoken(
+        &self,
+        clock: &dyn Clock,
+        honeytoken: &Honeytoken,
+        endpoint: &str,
+        user_agent: Option<String>,
+    ) {
+        self.tracker
+            .report_honeytoken(clock, honeytoken, endpoint, self.ip, user_agent)
+            .await;
+    }
 }
@@ -10,8 +10,8 @@ mod worker;
 use std::net::IpAddr;
 
 use chrono::{DateTime, Utc};
-use mas_data_model::{BrowserSession, CompatSession, Session};
-use mas_storage::Clock;
+use mas_data_model::{BrowserSession, CompatSession, Honeytoken, Session};
+use mas_storage::{job::ReportHoneytokenUseJob, Clock};
 use sqlx::PgPool;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use ulid::Ulid;
@@ -45,6 +45,7 @@ enum Message {
         date_time: DateTime<Utc>,
         ip: Option<IpAddr>,
     },
+    ReportHoneytoken(ReportHoneytokenUseJob),
     Flush(tokio::sync::oneshot::Sender<()>),
 }
 
@@ -153,6 +154,35 @@ impl ActivityTracker {
         }
     }
 
+    /// Report that a honeytoken was used.
+    ///
+    /// Unlike activity records, the report is written right away, so that the
+    /// administrators are alerted as soon as possible.
+    pub async fn report_honeytoken(
+        &self,
+        clock: &dyn Clock,
+        honeytoken: &Honeytoken,
+        endpoint: &str,
+        ip: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) {
+        tracing::warn!(
+            honeytoken.id = %honeytoken.id,
+            honeytoken.kind = %honeytoken.kind,
+            endpoint,
+            "A honeytoken was used"
+        );
+
+        let job = ReportHoneytokenUseJob::new(honeytoken, clock.now(), endpoint)
+            .with_ip(ip)
+            .with_user_agent(user_agent);
+        let res = self.channel.send(Message::ReportHoneytoken(job)).await;
+
+        if let Err(e) = res {
+            tracing::error!("Failed to report honeytoken use: {}", e);
+        }
+    }
+
     /// Manually flush the activity tracker.
     pub async fn flush(&self) {
         let (tx, rx) = tokio::sync::oneshot::channel();
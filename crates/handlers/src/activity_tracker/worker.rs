@@ -7,7 +7,11 @@
 use std::{collections::HashMap, net::IpAddr};
 
 use chrono::{DateTime, Utc};
-use mas_storage::{user::BrowserSessionRepository, RepositoryAccess};
+use mas_storage::{
+    job::{JobRepositoryExt, ReportHoneytokenUseJob},
+    user::BrowserSessionRepository,
+    RepositoryAccess,
+};
 use opentelemetry::{
     metrics::{Counter, Histogram},
     Key,
@@ -72,6 +76,7 @@ impl Worker {
                 &[TYPE.string("record"), SESSION_KIND.string(kind.as_str())],
             );
         }
+        message_counter.add(0, &[TYPE.string("honeytoken")]);
         message_counter.add(0, &[TYPE.string("flush")]);
         message_counter.add(0, &[TYPE.string("shutdown")]);
 
@@ -152,6 +157,14 @@ impl Worker {
                     record.end_time = date_time.max(record.end_time);
                 }
 
+                Message::ReportHoneytoken(job) => {
+                    self.message_counter.add(1, &[TYPE.string("honeytoken")]);
+
+                    if let Err(e) = self.report_honeytoken(job).await {
+                        tracing::error!("Failed to report honeytoken use: {}", e);
+                    }
+                }
+
                 Message::Flush(tx) => {
                     self.message_counter.add(1, &[TYPE.string("flush")]);
 
@@ -165,6 +178,20 @@ impl Worker {
         self.flush().await;
     }
 
+    /// Schedule the job which records the use of a honeytoken and alerts the
+    /// administrators.
+    #[tracing::instrument(name = "activity_tracker.report_honeytoken", skip_all)]
+    async fn report_honeytoken(&self, job: ReportHoneytokenUseJob) -> Result<(), anyhow::Error> {
+        let mut repo = mas_storage_pg::PgRepository::from_pool(&self.pool)
+            .await?
+            .boxed();
+
+        repo.job().schedule_job(job).await?;
+        repo.save().await?;
+
+        Ok(())
+    }
+
     /// Flush the activity tracker.
     async fn flush(&mut self) {
         // Short path: if there are no pending records, we don't need to flush
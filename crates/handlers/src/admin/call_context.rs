@@ -4,23 +4,25 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::convert::Infallible;
+use std::{convert::Infallible, net::IpAddr};
 
 use aide::OperationIo;
 use axum::{
-    extract::FromRequestParts,
+    extract::{FromRef, FromRequestParts},
     response::{IntoResponse, Response},
     Json,
 };
 use axum_extra::TypedHeader;
-use headers::{authorization::Bearer, Authorization};
+use headers::{authorization::Bearer, Authorization, UserAgent};
 use hyper::StatusCode;
-use mas_data_model::{Session, User};
-use mas_storage::{BoxClock, BoxRepository, RepositoryError};
+use mas_axum_utils::client_certificate::ClientCertificate;
+use mas_data_model::{AuditEventKind, Session, SiteConfig, User};
+use mas_storage::{audit::AuditEventRepository, BoxClock, BoxRepository, Clock, RepositoryError};
+use rand::RngCore;
 use ulid::Ulid;
 
 use super::response::ErrorResponse;
-use crate::BoundActivityTracker;
+use crate::{admin_elevation::has_admin_privileges, BoundActivityTracker};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Rejection {
@@ -48,6 +50,14 @@ pub enum Rejection {
     #[error("Access token expired")]
     TokenExpired,
 
+    #[error("Access token is bound to a DPoP key")]
+    DPoPBoundToken,
+
+    /// The access token is bound to a client certificate which wasn't
+    /// presented
+    #[error("Access token is bound to another client certificate")]
+    CertificateMismatch,
+
     /// The session associated with the access token was revoked
     #[error("Access token revoked")]
     SessionRevoked,
@@ -67,6 +77,10 @@ pub enum Rejection {
     /// The session does not have the `urn:mas:admin` scope
     #[error("Missing urn:mas:admin scope")]
     MissingScope,
+
+    /// The user authenticated too long ago to keep their admin privileges
+    #[error("Admin privileges expired, the user must authenticate again")]
+    ElevationExpired,
 }
 
 impl Rejection {
@@ -77,9 +91,12 @@ impl Rejection {
             }
             Self::UnknownAccessToken
             | Self::TokenExpired
+            | Self::DPoPBoundToken
+            | Self::CertificateMismatch
             | Self::SessionRevoked
             | Self::UserLocked
-            | Self::MissingScope => StatusCode::UNAUTHORIZED,
+            | Self::MissingScope
+            | Self::ElevationExpired => StatusCode::UNAUTHORIZED,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -105,12 +122,66 @@ pub struct CallContext {
     pub clock: BoxClock,
     pub user: Option<User>,
     pub session: Session,
+    pub audit: AuditContext,
+}
+
+/// Information about the caller, used to record admin actions in the audit
+/// log
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    actor_user_id: Option<Ulid>,
+    session_id: Ulid,
+    ip_address: Option<IpAddr>,
+    user_agent: Option<String>,
+}
+
+impl AuditContext {
+    /// Record an event about the given user in the audit log
+    ///
+    /// The OAuth 2.0 session used by the caller is added to the details of the
+    /// event.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository fails
+    pub async fn record(
+        &self,
+        repo: &mut BoxRepository,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: AuditEventKind,
+        user_id: Option<Ulid>,
+        mut details: serde_json::Value,
+    ) -> Result<(), RepositoryError> {
+        if let Some(details) = details.as_object_mut() {
+            details.insert(
+                "oauth2_session_id".to_owned(),
+                self.session_id.to_string().into(),
+            );
+        }
+
+        repo.audit_event()
+            .add(
+                rng,
+                clock,
+                kind,
+                self.actor_user_id,
+                user_id,
+                self.ip_address,
+                self.user_agent.clone(),
+                details,
+            )
+            .await?;
+
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
 impl<S> FromRequestParts<S> for CallContext
 where
     S: Send + Sync,
+    SiteConfig: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S, Rejection = Infallible>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S, Rejection = Infallible>,
@@ -141,6 +212,11 @@ where
             .map_err(Into::into)
             .map_err(Rejection::RepositorySetup)?;
 
+        let user_agent = TypedHeader::<UserAgent>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|TypedHeader(ua)| ua.as_str().to_owned());
+
         // Extract the access token from the authorization header
         let token = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
             .await
@@ -170,6 +246,9 @@ where
             .await?
             .ok_or_else(|| Rejection::LoadSession(token.session_id))?;
 
+        // Tokens restricted to a narrower scope only grant that scope
+        let session = token.restrict_session(session);
+
         // Record the activity on the session
         activity_tracker
             .record_oauth2_session(&clock, &session)
@@ -202,17 +281,48 @@ where
             return Err(Rejection::TokenExpired);
         }
 
+        // This API only accepts bearer tokens, so DPoP-bound tokens can't be used
+        if token.dpop_jkt.is_some() {
+            return Err(Rejection::DPoPBoundToken);
+        }
+
+        // Certificate-bound tokens must be presented with the same certificate
+        if let Some(x5t_s256) = &token.x5t_s256 {
+            let thumbprint = parts
+                .extensions
+                .get::<ClientCertificate>()
+                .map(ClientCertificate::thumbprint);
+            if thumbprint.as_ref() != Some(x5t_s256) {
+                return Err(Rejection::CertificateMismatch);
+            }
+        }
+
         // For now, we only check that the session has the admin scope
         // Later we might want to check other route-specific scopes
         if !session.scope.contains("urn:mas:admin") {
             return Err(Rejection::MissingScope);
         }
 
+        // Users only keep their admin privileges for a while after they
+        // authenticated, if configured so
+        let site_config = SiteConfig::from_ref(state);
+        if !has_admin_privileges(&mut repo, &clock, &site_config, &session).await? {
+            return Err(Rejection::ElevationExpired);
+        }
+
+        let audit = AuditContext {
+            actor_user_id: user.as_ref().map(|user| user.id),
+            session_id: session.id,
+            ip_address: activity_tracker.ip(),
+            user_agent,
+        };
+
         Ok(Self {
             repo,
             clock,
             user,
             session,
+            audit,
         })
     }
 }
@@ -16,8 +16,11 @@ use axum::{
 };
 use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
 use indexmap::IndexMap;
-use mas_axum_utils::FancyError;
+use mas_axum_utils::{http_client_factory::HttpClientFactory, FancyError};
+use mas_data_model::SiteConfig;
+use mas_email::Mailer;
 use mas_http::CorsLayerExt;
+use mas_keystore::Keystore;
 use mas_matrix::BoxHomeserverConnection;
 use mas_router::{
     ApiDoc, ApiDocCallback, OAuth2AuthorizationEndpoint, OAuth2TokenEndpoint, Route, SimpleRoute,
@@ -25,6 +28,7 @@ use mas_router::{
 };
 use mas_storage::BoxRng;
 use mas_templates::{ApiDocContext, Templates};
+use sqlx::PgPool;
 use tower_http::cors::{Any, CorsLayer};
 
 mod call_context;
@@ -41,7 +45,12 @@ pub fn router<S>() -> (OpenApi, Router<S>)
 where
     S: Clone + Send + Sync + 'static,
     BoxHomeserverConnection: FromRef<S>,
+    HttpClientFactory: FromRef<S>,
+    Keystore: FromRef<S>,
+    Mailer: FromRef<S>,
     PasswordManager: FromRef<S>,
+    PgPool: FromRef<S>,
+    SiteConfig: FromRef<S>,
     BoxRng: FromRequestParts<S>,
     CallContext: FromRequestParts<S>,
     Templates: FromRef<S>,
@@ -56,11 +65,50 @@ where
         .nest("/api/admin/v1", self::v1::router())
         .finish_api_with(&mut api, |t| {
             t.title("Matrix Authentication Service admin API")
+                .tag(Tag {
+                    name: "audit-event".to_owned(),
+                    description: Some("Query the audit log".to_owned()),
+                    ..Tag::default()
+                })
+                .tag(Tag {
+                    name: "changes-feed".to_owned(),
+                    description: Some("Poll the events which affected users".to_owned()),
+                    ..Tag::default()
+                })
+                .tag(Tag {
+                    name: "email-delivery-failure".to_owned(),
+                    description: Some("Investigate emails which could not be delivered".to_owned()),
+                    ..Tag::default()
+                })
+                .tag(Tag {
+                    name: "honeytoken".to_owned(),
+                    description: Some("Plant decoy tokens and accounts".to_owned()),
+                    ..Tag::default()
+                })
                 .tag(Tag {
                     name: "oauth2-session".to_owned(),
                     description: Some("Manage OAuth2 sessions".to_owned()),
                     ..Tag::default()
                 })
+                .tag(Tag {
+                    name: "runtime-resource".to_owned(),
+                    description: Some(
+                        "Disable resources without restarting the service".to_owned(),
+                    ),
+                    ..Tag::default()
+                })
+                .tag(Tag {
+                    name: "status".to_owned(),
+                    description: Some("Monitor the health of the service".to_owned()),
+                    ..Tag::default()
+                })
+                .tag(Tag {
+                    name: "upstream-oauth2".to_owned(),
+                    description: Some(
+                        "Follow the signups through upstream OAuth 2.0 providers".to_owned(),
+                    ),
+                    ..Tag::default()
+                })
                 .tag(Tag {
                     name: "user".to_owned(),
                     description: Some("Manage users".to_owned()),
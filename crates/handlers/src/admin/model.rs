@@ -47,6 +47,24 @@ pub struct User {
 
     /// Whether the user can request admin privileges.
     admin: bool,
+
+    /// When the user was placed under legal hold. If null, the user is not
+    /// under legal hold.
+    legal_hold_at: Option<DateTime<Utc>>,
+
+    /// The server name of the homeserver the user belongs to. If null, the
+    /// user belongs to the main homeserver.
+    homeserver: Option<String>,
+
+    /// Whether the user is a bot account.
+    bot: bool,
+
+    /// The ID of the user who owns this bot, if any.
+    #[schemars(with = "Option<super::schema::Ulid>")]
+    bot_owner_id: Option<Ulid>,
+
+    /// Whether the user is a guest account.
+    guest: bool,
 }
 
 impl User {
@@ -59,6 +77,11 @@ impl User {
                 created_at: DateTime::default(),
                 locked_at: None,
                 admin: false,
+                legal_hold_at: None,
+                homeserver: None,
+                bot: false,
+                bot_owner_id: None,
+                guest: false,
             },
             Self {
                 id: Ulid::from_bytes([0x02; 16]),
@@ -66,6 +89,11 @@ impl User {
                 created_at: DateTime::default(),
                 locked_at: None,
                 admin: true,
+                legal_hold_at: None,
+                homeserver: None,
+                bot: false,
+                bot_owner_id: None,
+                guest: false,
             },
             Self {
                 id: Ulid::from_bytes([0x03; 16]),
@@ -73,6 +101,11 @@ impl User {
                 created_at: DateTime::default(),
                 locked_at: Some(DateTime::default()),
                 admin: false,
+                legal_hold_at: Some(DateTime::default()),
+                homeserver: Some("example.org".to_owned()),
+                bot: true,
+                bot_owner_id: Some(Ulid::from_bytes([0x01; 16])),
+                guest: false,
             },
         ]
     }
@@ -86,6 +119,11 @@ impl From<mas_data_model::User> for User {
             created_at: user.created_at,
             locked_at: user.locked_at,
             admin: user.can_request_admin,
+            legal_hold_at: user.legal_hold_at,
+            homeserver: user.homeserver,
+            bot: user.is_bot,
+            bot_owner_id: user.bot_owner_id,
+            guest: user.is_guest,
         }
     }
 }
@@ -205,3 +243,409 @@ impl Resource for OAuth2Session {
         self.id
     }
 }
+
+/// An event recorded in the audit log
+#[derive(Serialize, JsonSchema)]
+pub struct AuditEvent {
+    #[serde(skip)]
+    id: Ulid,
+
+    /// When the event was recorded
+    created_at: DateTime<Utc>,
+
+    /// The kind of event, one of `login`, `login_failed`, `password_changed`,
+    /// `session_ended`, `admin_action`, `emergency_access_issued`,
+    /// `refresh_token_reused`, `client_authorized`, `honeytoken_used` or
+    /// `suspicious_login`
+    kind: String,
+
+    /// The ID of the user who performed the action, if any
+    #[schemars(with = "Option<super::schema::Ulid>")]
+    actor_user_id: Option<Ulid>,
+
+    /// The ID of the user the action was performed on, if any
+    #[schemars(with = "Option<super::schema::Ulid>")]
+    user_id: Option<Ulid>,
+
+    /// The IP address of the requester, if known
+    ip_address: Option<IpAddr>,
+
+    /// The user agent of the requester, if known
+    user_agent: Option<String>,
+
+    /// Additional details about the event
+    details: serde_json::Value,
+
+    /// The hash of the previous event in the audit log
+    previous_hash: Option<String>,
+
+    /// The hash of this event, including the hash of the previous event
+    hash: String,
+}
+
+impl From<mas_data_model::AuditEvent> for AuditEvent {
+    fn from(event: mas_data_model::AuditEvent) -> Self {
+        Self {
+            id: event.id,
+            created_at: event.created_at,
+            kind: event.kind.to_string(),
+            actor_user_id: event.actor_user_id,
+            user_id: event.user_id,
+            ip_address: event.ip_address,
+            user_agent: event.user_agent,
+            details: event.details,
+            previous_hash: event.previous_hash,
+            hash: event.hash,
+        }
+    }
+}
+
+impl AuditEvent {
+    /// Samples of audit events
+    pub fn samples() -> [Self; 2] {
+        [
+            Self {
+                id: Ulid::from_bytes([0x01; 16]),
+                created_at: DateTime::default(),
+                kind: "login".to_owned(),
+                actor_user_id: Some(Ulid::from_bytes([0x02; 16])),
+                user_id: Some(Ulid::from_bytes([0x02; 16])),
+                ip_address: Some("127.0.0.1".parse().unwrap()),
+                user_agent: Some("Mozilla/5.0".to_owned()),
+                details: serde_json::json!({ "method": "password" }),
+                previous_hash: None,
+                hash: "0e9a4a1e5c0f1b7b5d2a7f5b1e0b9e4f8c3a2d1e0f9b8a7c6d5e4f3a2b1c0d9e".to_owned(),
+            },
+            Self {
+                id: Ulid::from_bytes([0x02; 16]),
+                created_at: DateTime::default(),
+                kind: "admin_action".to_owned(),
+                actor_user_id: None,
+                user_id: Some(Ulid::from_bytes([0x03; 16])),
+                ip_address: None,
+                user_agent: None,
+                details: serde_json::json!({
+                    "action": "lock",
+                    "oauth2_session_id": Ulid::from_bytes([0x04; 16]).to_string(),
+                }),
+                previous_hash: Some(
+                    "0e9a4a1e5c0f1b7b5d2a7f5b1e0b9e4f8c3a2d1e0f9b8a7c6d5e4f3a2b1c0d9e".to_owned(),
+                ),
+                hash: "5b2c9e8d7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a1b0c".to_owned(),
+            },
+        ]
+    }
+}
+
+impl Resource for AuditEvent {
+    const KIND: &'static str = "audit-event";
+    const PATH: &'static str = "/api/admin/v1/audit-events";
+
+    fn id(&self) -> Ulid {
+        self.id
+    }
+}
+
+/// An email which could not be delivered
+#[derive(Serialize, JsonSchema)]
+pub struct EmailDeliveryFailure {
+    #[serde(skip)]
+    id: Ulid,
+
+    /// When the delivery failed
+    created_at: DateTime<Utc>,
+
+    /// The address the email was sent to
+    email: String,
+
+    /// The domain of the address, lowercased
+    domain: String,
+
+    /// Whether the mail server rejected the email permanently. No more emails
+    /// are sent to addresses which hard-bounced.
+    permanent: bool,
+
+    /// The error returned by the mail server
+    reason: String,
+}
+
+impl From<mas_data_model::EmailDeliveryFailure> for EmailDeliveryFailure {
+    fn from(failure: mas_data_model::EmailDeliveryFailure) -> Self {
+        Self {
+            id: failure.id,
+            created_at: failure.created_at,
+            email: failure.email,
+            domain: failure.domain,
+            permanent: failure.permanent,
+            reason: failure.reason,
+        }
+    }
+}
+
+impl EmailDeliveryFailure {
+    /// Samples of email delivery failures
+    pub fn samples() -> [Self; 2] {
+        [
+            Self {
+                id: Ulid::from_bytes([0x01; 16]),
+                created_at: DateTime::default(),
+                email: "alice@example.com".to_owned(),
+                domain: "example.com".to_owned(),
+                permanent: false,
+                reason: "transient error (421): Try again later".to_owned(),
+            },
+            Self {
+                id: Ulid::from_bytes([0x02; 16]),
+                created_at: DateTime::default(),
+                email: "bob@example.com".to_owned(),
+                domain: "example.com".to_owned(),
+                permanent: true,
+                reason: "permanent error (550): No such user".to_owned(),
+            },
+        ]
+    }
+}
+
+impl Resource for EmailDeliveryFailure {
+    const KIND: &'static str = "email-delivery-failure";
+    const PATH: &'static str = "/api/admin/v1/email-delivery-failures";
+
+    fn id(&self) -> Ulid {
+        self.id
+    }
+}
+
+/// An entry of the changes feed, recording an event which affected a user
+#[derive(Serialize, JsonSchema)]
+pub struct ChangesFeedEntry {
+    #[serde(skip)]
+    id: Ulid,
+
+    /// The type of event, one of `user.registered`, `user.deactivated`,
+    /// `user.reactivated`, `user.updated`, `session.created`,
+    /// `session.revoked` or `honeytoken.used`
+    #[serde(rename = "type")]
+    kind: String,
+
+    /// The ID of the event, which is the same as the one sent to webhook
+    /// endpoints
+    #[schemars(with = "super::schema::Ulid")]
+    event_id: Ulid,
+
+    /// When the event happened
+    created_at: DateTime<Utc>,
+
+    /// Event-specific data
+    data: serde_json::Value,
+}
+
+impl From<mas_data_model::ChangesFeedEntry> for ChangesFeedEntry {
+    fn from(entry: mas_data_model::ChangesFeedEntry) -> Self {
+        Self {
+            id: entry.id,
+            kind: entry.event.kind.to_string(),
+            event_id: entry.event.id,
+            created_at: entry.event.created_at,
+            data: entry.event.data,
+        }
+    }
+}
+
+impl ChangesFeedEntry {
+    /// Samples of changes feed entries
+    pub fn samples() -> [Self; 2] {
+        [
+            Self {
+                id: Ulid::from_bytes([0x01; 16]),
+                kind: "user.registered".to_owned(),
+                event_id: Ulid::from_bytes([0x02; 16]),
+                created_at: DateTime::default(),
+                data: serde_json::json!({
+                    "user_id": Ulid::from_bytes([0x03; 16]).to_string(),
+                    "username": "alice",
+                }),
+            },
+            Self {
+                id: Ulid::from_bytes([0x04; 16]),
+                kind: "user.updated".to_owned(),
+                event_id: Ulid::from_bytes([0x05; 16]),
+                created_at: DateTime::default(),
+                data: serde_json::json!({
+                    "user_id": Ulid::from_bytes([0x03; 16]).to_string(),
+                    "username": "alice",
+                    "locked": true,
+                    "can_request_admin": false,
+                }),
+            },
+        ]
+    }
+}
+
+impl Resource for ChangesFeedEntry {
+    const KIND: &'static str = "changes-feed-entry";
+    const PATH: &'static str = "/api/admin/v1/changes-feed";
+
+    fn id(&self) -> Ulid {
+        self.id
+    }
+}
+
+/// A decoy token or account, planted to detect credential leaks
+#[derive(Serialize, JsonSchema)]
+pub struct Honeytoken {
+    #[serde(skip)]
+    id: Ulid,
+
+    /// When the honeytoken was planted
+    created_at: DateTime<Utc>,
+
+    /// What the honeytoken impersonates, one of `access_token`,
+    /// `refresh_token`, `compat_access_token`, `compat_refresh_token` or
+    /// `account`
+    kind: String,
+
+    /// The decoy token, or the username of the decoy account
+    value: String,
+
+    /// A free-form label, to remember where the honeytoken was planted
+    label: String,
+
+    /// When the honeytoken was last used, if ever
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<mas_data_model::Honeytoken> for Honeytoken {
+    fn from(honeytoken: mas_data_model::Honeytoken) -> Self {
+        Self {
+            id: honeytoken.id,
+            created_at: honeytoken.created_at,
+            kind: honeytoken.kind.to_string(),
+            value: honeytoken.value,
+            label: honeytoken.label,
+            last_used_at: honeytoken.last_used_at,
+        }
+    }
+}
+
+impl Honeytoken {
+    /// Samples of honeytokens
+    pub fn samples() -> [Self; 2] {
+        [
+            Self {
+                id: Ulid::from_bytes([0x01; 16]),
+                created_at: DateTime::default(),
+                kind: "access_token".to_owned(),
+                value: "mat_aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa_000000".to_owned(),
+                label: "CI secrets".to_owned(),
+                last_used_at: None,
+            },
+            Self {
+                id: Ulid::from_bytes([0x02; 16]),
+                created_at: DateTime::default(),
+                kind: "account".to_owned(),
+                value: "backup-admin".to_owned(),
+                label: "Wiki page about backups".to_owned(),
+                last_used_at: Some(DateTime::default()),
+            },
+        ]
+    }
+}
+
+impl Resource for Honeytoken {
+    const KIND: &'static str = "honeytoken";
+    const PATH: &'static str = "/api/admin/v1/honeytokens";
+
+    fn id(&self) -> Ulid {
+        self.id
+    }
+}
+
+/// A change of the email address asserted by an upstream provider for a
+/// linked account, waiting for an administrator to review it
+#[derive(Serialize, JsonSchema)]
+pub struct UpstreamOAuthEmailChange {
+    #[serde(skip)]
+    id: Ulid,
+
+    /// When the change was detected
+    created_at: DateTime<Utc>,
+
+    /// The ID of the upstream OAuth 2.0 link on which the change was detected
+    #[schemars(with = "super::schema::Ulid")]
+    upstream_oauth_link_id: Ulid,
+
+    /// The ID of the user the link belongs to
+    #[schemars(with = "super::schema::Ulid")]
+    user_id: Ulid,
+
+    /// The ID of another user who already has the new email address, if any
+    #[schemars(with = "Option<super::schema::Ulid>")]
+    conflicting_user_id: Option<Ulid>,
+
+    /// The email address previously asserted by the provider
+    previous_email: Option<String>,
+
+    /// The email address now asserted by the provider
+    new_email: String,
+
+    /// When the change was applied or dismissed
+    resolved_at: Option<DateTime<Utc>>,
+
+    /// How the change was resolved, either `applied` or `dismissed`
+    resolution: Option<String>,
+}
+
+impl From<mas_data_model::UpstreamOAuthEmailChange> for UpstreamOAuthEmailChange {
+    fn from(change: mas_data_model::UpstreamOAuthEmailChange) -> Self {
+        Self {
+            id: change.id,
+            created_at: change.created_at,
+            upstream_oauth_link_id: change.link_id,
+            user_id: change.user_id,
+            conflicting_user_id: change.conflicting_user_id,
+            previous_email: change.previous_email,
+            new_email: change.new_email,
+            resolved_at: change.resolved_at,
+            resolution: change.resolution.map(|r| r.as_str().to_owned()),
+        }
+    }
+}
+
+impl UpstreamOAuthEmailChange {
+    /// Samples of upstream email changes
+    pub fn samples() -> [Self; 2] {
+        [
+            Self {
+                id: Ulid::from_bytes([0x01; 16]),
+                created_at: DateTime::default(),
+                upstream_oauth_link_id: Ulid::from_bytes([0x02; 16]),
+                user_id: Ulid::from_bytes([0x03; 16]),
+                conflicting_user_id: Some(Ulid::from_bytes([0x04; 16])),
+                previous_email: Some("alice@example.com".to_owned()),
+                new_email: "alice@example.org".to_owned(),
+                resolved_at: None,
+                resolution: None,
+            },
+            Self {
+                id: Ulid::from_bytes([0x05; 16]),
+                created_at: DateTime::default(),
+                upstream_oauth_link_id: Ulid::from_bytes([0x06; 16]),
+                user_id: Ulid::from_bytes([0x07; 16]),
+                conflicting_user_id: None,
+                previous_email: Some("bob@example.com".to_owned()),
+                new_email: "bob@example.org".to_owned(),
+                resolved_at: Some(DateTime::default()),
+                resolution: Some("applied".to_owned()),
+            },
+        ]
+    }
+}
+
+impl Resource for UpstreamOAuthEmailChange {
+    const KIND: &'static str = "upstream-oauth2-email-change";
+    const PATH: &'static str = "/api/admin/v1/upstream-oauth2-email-changes";
+
+    fn id(&self) -> Ulid {
+        self.id
+    }
+}
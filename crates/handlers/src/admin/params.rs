@@ -65,6 +65,28 @@ impl std::ops::Deref for UlidPathParam {
 /// The default page size if not specified
 const DEFAULT_PAGE_SIZE: usize = 10;
 
+/// How to count the total number of items in a paginated response
+#[derive(Deserialize, JsonSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CountMode {
+    /// Count the items exactly
+    #[default]
+    Exact,
+
+    /// Only count the items exactly if there are few of them, else estimate
+    /// their number
+    Estimated,
+}
+
+impl std::fmt::Display for CountMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact => write!(f, "exact"),
+            Self::Estimated => write!(f, "estimated"),
+        }
+    }
+}
+
 #[derive(Deserialize, JsonSchema, Clone, Copy)]
 struct PaginationParams {
     /// Retrieve the items before the given ID
@@ -84,6 +106,14 @@ struct PaginationParams {
     /// Retrieve the last N items
     #[serde(rename = "page[last]")]
     last: Option<NonZeroUsize>,
+
+    /// How to count the total number of items
+    ///
+    /// Counting the items exactly can be slow on large datasets, so this can
+    /// be set to `estimated` to only get an estimation in this case.
+    ///
+    /// Defaults to `exact`.
+    count: Option<CountMode>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -108,7 +138,7 @@ impl IntoResponse for PaginationRejection {
 /// An extractor for pagination parameters in the query string
 #[derive(OperationIo, Debug, Clone, Copy)]
 #[aide(input_with = "Query<PaginationParams>")]
-pub struct Pagination(pub mas_storage::Pagination);
+pub struct Pagination(pub mas_storage::Pagination, pub CountMode);
 
 #[async_trait]
 impl<S: Send + Sync> FromRequestParts<S> for Pagination {
@@ -132,11 +162,14 @@ impl<S: Send + Sync> FromRequestParts<S> for Pagination {
             (None, Some(last)) => (PaginationDirection::Backward, last.into()),
         };
 
-        Ok(Self(mas_storage::Pagination {
-            before: params.before,
-            after: params.after,
-            direction,
-            count,
-        }))
+        Ok(Self(
+            mas_storage::Pagination {
+                before: params.before,
+                after: params.after,
+                direction,
+                count,
+            },
+            params.count.unwrap_or_default(),
+        ))
     }
 }
@@ -6,12 +6,12 @@
 
 #![allow(clippy::module_name_repetitions)]
 
-use mas_storage::Pagination;
+use mas_storage::{pagination::Count, Pagination};
 use schemars::JsonSchema;
 use serde::Serialize;
 use ulid::Ulid;
 
-use super::model::Resource;
+use super::{model::Resource, params::CountMode};
 
 /// Related links
 #[derive(Serialize, JsonSchema)]
@@ -43,6 +43,12 @@ struct PaginationLinks {
 struct PaginationMeta {
     /// The total number of results
     count: usize,
+
+    /// Whether the total number of results is an estimation
+    ///
+    /// Only present if the count was estimated
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    estimated: bool,
 }
 
 /// A top-level response with a page of resources
@@ -58,7 +64,7 @@ pub struct PaginatedResponse<T> {
     links: PaginationLinks,
 }
 
-fn url_with_pagination(base: &str, pagination: Pagination) -> String {
+fn url_with_pagination(base: &str, pagination: Pagination, count_mode: CountMode) -> String {
     let (path, query) = base.split_once('?').unwrap_or((base, ""));
     let mut query = query.to_owned();
 
@@ -80,6 +86,10 @@ fn url_with_pagination(base: &str, pagination: Pagination) -> String {
         }
     }
 
+    if count_mode != CountMode::default() {
+        query += &format!("&count={count_mode}");
+    }
+
     // Remove the first '&'
     let query = query.trim_start_matches('&');
 
@@ -90,19 +100,25 @@ impl<T: Resource> PaginatedResponse<T> {
     pub fn new(
         page: mas_storage::Page<T>,
         current_pagination: Pagination,
-        count: usize,
+        count_mode: CountMode,
+        count: Count,
         base: &str,
     ) -> Self {
         let links = PaginationLinks {
-            self_: url_with_pagination(base, current_pagination),
-            first: url_with_pagination(base, Pagination::first(current_pagination.count)),
-            last: url_with_pagination(base, Pagination::last(current_pagination.count)),
+            self_: url_with_pagination(base, current_pagination, count_mode),
+            first: url_with_pagination(
+                base,
+                Pagination::first(current_pagination.count),
+                count_mode,
+            ),
+            last: url_with_pagination(base, Pagination::last(current_pagination.count), count_mode),
             next: page.has_next_page.then(|| {
                 url_with_pagination(
                     base,
                     current_pagination
                         .clear_before()
                         .after(page.edges.last().unwrap().id()),
+                    count_mode,
                 )
             }),
             prev: if page.has_previous_page {
@@ -111,6 +127,7 @@ impl<T: Resource> PaginatedResponse<T> {
                     current_pagination
                         .clear_after()
                         .before(page.edges.first().unwrap().id()),
+                    count_mode,
                 ))
             } else {
                 None
@@ -120,7 +137,10 @@ impl<T: Resource> PaginatedResponse<T> {
         let data = page.edges.into_iter().map(SingleResource::new).collect();
 
         Self {
-            meta: PaginationMeta { count },
+            meta: PaginationMeta {
+                count: count.value(),
+                estimated: count.is_estimated(),
+            },
             data,
             links,
         }
@@ -0,0 +1,366 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::StatusCode;
+use mas_storage::{audit::AuditEventFilter, pagination::Count, Page};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{AuditEvent, Resource},
+        params::{CountMode, Pagination},
+        response::{ErrorResponse, PaginatedResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum AuditEventKind {
+    Login,
+    LoginFailed,
+    PasswordChanged,
+    SessionEnded,
+    AdminAction,
+    EmergencyAccessIssued,
+    RefreshTokenReused,
+    ClientAuthorized,
+    HoneytokenUsed,
+    SuspiciousLogin,
+}
+
+impl From<AuditEventKind> for mas_data_model::AuditEventKind {
+    fn from(kind: AuditEventKind) -> Self {
+        match kind {
+            AuditEventKind::Login => Self::Login,
+            AuditEventKind::LoginFailed => Self::LoginFailed,
+            AuditEventKind::PasswordChanged => Self::PasswordChanged,
+            AuditEventKind::SessionEnded => Self::SessionEnded,
+            AuditEventKind::AdminAction => Self::AdminAction,
+            AuditEventKind::EmergencyAccessIssued => Self::EmergencyAccessIssued,
+            AuditEventKind::RefreshTokenReused => Self::RefreshTokenReused,
+            AuditEventKind::ClientAuthorized => Self::ClientAuthorized,
+            AuditEventKind::HoneytokenUsed => Self::HoneytokenUsed,
+            AuditEventKind::SuspiciousLogin => Self::SuspiciousLogin,
+        }
+    }
+}
+
+impl std::fmt::Display for AuditEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&mas_data_model::AuditEventKind::from(*self), f)
+    }
+}
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "AuditEventFilter")]
+#[aide(input_with = "Query<FilterParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct FilterParams {
+    /// Retrieve the events about the given user
+    #[serde(rename = "filter[user]")]
+    #[schemars(with = "Option<crate::admin::schema::Ulid>")]
+    user: Option<Ulid>,
+
+    /// Retrieve the events performed by the given user
+    #[serde(rename = "filter[actor]")]
+    #[schemars(with = "Option<crate::admin::schema::Ulid>")]
+    actor: Option<Ulid>,
+
+    /// Retrieve the events of the given kind
+    #[serde(rename = "filter[kind]")]
+    kind: Option<AuditEventKind>,
+
+    /// Retrieve the events recorded at or after the given time
+    #[serde(rename = "filter[since]")]
+    since: Option<DateTime<Utc>>,
+
+    /// Retrieve the events recorded before the given time
+    #[serde(rename = "filter[until]")]
+    until: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Display for FilterParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = '?';
+
+        if let Some(user) = self.user {
+            write!(f, "{sep}filter[user]={user}")?;
+            sep = '&';
+        }
+
+        if let Some(actor) = self.actor {
+            write!(f, "{sep}filter[actor]={actor}")?;
+            sep = '&';
+        }
+
+        if let Some(kind) = self.kind {
+            write!(f, "{sep}filter[kind]={kind}")?;
+            sep = '&';
+        }
+
+        if let Some(since) = self.since {
+            let since = since.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            write!(f, "{sep}filter[since]={since}")?;
+            sep = '&';
+        }
+
+        if let Some(until) = self.until {
+            let until = until.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            write!(f, "{sep}filter[until]={until}")?;
+            sep = '&';
+        }
+
+        let _ = sep;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("User ID {0} not found")]
+    UserNotFound(Ulid),
+
+    #[error("Invalid filter parameters")]
+    InvalidFilter(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::UserNotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("listAuditEvents")
+        .summary("List audit events")
+        .description(
+            "Retrieve a list of events recorded in the audit log, with the oldest first.
+Use the `page[last]` parameter to retrieve the last N events.",
+        )
+        .tag("audit-event")
+        .response_with::<200, Json<PaginatedResponse<AuditEvent>>, _>(|t| {
+            let events = AuditEvent::samples();
+            let pagination = mas_storage::Pagination::first(events.len());
+            let page = Page {
+                edges: events.into(),
+                has_next_page: true,
+                has_previous_page: false,
+            };
+
+            t.description("Paginated response of audit events")
+                .example(PaginatedResponse::new(
+                    page,
+                    pagination,
+                    CountMode::Exact,
+                    Count::Exact(42),
+                    AuditEvent::PATH,
+                ))
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::UserNotFound(Ulid::nil()));
+            t.description("User was not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.audit_events.list", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    Pagination(pagination, count_mode): Pagination,
+    params: FilterParams,
+) -> Result<Json<PaginatedResponse<AuditEvent>>, RouteError> {
+    let base = format!("{path}{params}", path = AuditEvent::PATH);
+    let filter = AuditEventFilter::new();
+
+    let user = if let Some(user_id) = params.user {
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .ok_or(RouteError::UserNotFound(user_id))?;
+
+        Some(user)
+    } else {
+        None
+    };
+
+    let filter = match &user {
+        Some(user) => filter.for_user(user),
+        None => filter,
+    };
+
+    let actor = if let Some(actor_id) = params.actor {
+        let actor = repo
+            .user()
+            .lookup(actor_id)
+            .await?
+            .ok_or(RouteError::UserNotFound(actor_id))?;
+
+        Some(actor)
+    } else {
+        None
+    };
+
+    let filter = match &actor {
+        Some(actor) => filter.for_actor(actor),
+        None => filter,
+    };
+
+    let filter = match params.kind {
+        Some(kind) => filter.with_kind(kind.into()),
+        None => filter,
+    };
+
+    let filter = match params.since {
+        Some(since) => filter.since(since),
+        None => filter,
+    };
+
+    let filter = match params.until {
+        Some(until) => filter.until(until),
+        None => filter,
+    };
+
+    let page = repo.audit_event().list(filter, pagination).await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.audit_event().count(filter).await?),
+        CountMode::Estimated => repo.audit_event().estimate_count(filter).await?,
+    };
+
+    Ok(Json(PaginatedResponse::new(
+        page.map(AuditEvent::from),
+        pagination,
+        count_mode,
+        count,
+        &base,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::AuditEventKind;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_list(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        let alice = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.audit_event()
+            .add(
+                &mut rng,
+                &state.clock,
+                AuditEventKind::Login,
+                Some(alice.id),
+                Some(alice.id),
+                None,
+                None,
+                serde_json::json!({ "method": "password" }),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Locking the user through the admin API records an event
+        let request = Request::post(format!("/api/admin/v1/users/{}/lock", alice.id))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let request = Request::get("/api/admin/v1/audit-events")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 2);
+        assert_eq!(body["data"][0]["attributes"]["kind"], "login");
+        assert_eq!(body["data"][1]["attributes"]["kind"], "admin_action");
+        assert_eq!(body["data"][1]["attributes"]["details"]["action"], "lock");
+        assert_eq!(
+            body["data"][1]["attributes"]["previous_hash"],
+            body["data"][0]["attributes"]["hash"],
+        );
+
+        // Filter by kind
+        let request = Request::get("/api/admin/v1/audit-events?filter[kind]=admin_action")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+
+        // Filter by user
+        let request = Request::get(format!(
+            "/api/admin/v1/audit-events?filter[user]={}",
+            alice.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 2);
+
+        // Few events are counted exactly even if an estimation is requested,
+        // and the links keep the count mode
+        let request = Request::get("/api/admin/v1/audit-events?count=estimated")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 2);
+        assert!(body["meta"].get("estimated").is_none());
+        assert_eq!(
+            body["links"]["self"],
+            "/api/admin/v1/audit-events?page[first]=10&count=estimated"
+        );
+
+        // Invalid kind
+        let request = Request::get("/api/admin/v1/audit-events?filter[kind]=unknown")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
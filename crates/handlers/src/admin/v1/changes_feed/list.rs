@@ -0,0 +1,254 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use hyper::StatusCode;
+use mas_data_model::WebhookEventKind;
+use mas_storage::{changes_feed::ChangesFeedFilter, pagination::Count, Page};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{ChangesFeedEntry, Resource},
+        params::{CountMode, Pagination},
+        response::{ErrorResponse, PaginatedResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Deserialize, JsonSchema, Clone, Copy)]
+enum ChangesFeedEventType {
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+
+    #[serde(rename = "user.deactivated")]
+    UserDeactivated,
+
+    #[serde(rename = "user.reactivated")]
+    UserReactivated,
+
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+
+    #[serde(rename = "session.created")]
+    SessionCreated,
+
+    #[serde(rename = "session.revoked")]
+    SessionRevoked,
+
+    #[serde(rename = "honeytoken.used")]
+    HoneytokenUsed,
+}
+
+impl From<ChangesFeedEventType> for WebhookEventKind {
+    fn from(kind: ChangesFeedEventType) -> Self {
+        match kind {
+            ChangesFeedEventType::UserRegistered => Self::UserRegistered,
+            ChangesFeedEventType::UserDeactivated => Self::UserDeactivated,
+            ChangesFeedEventType::UserReactivated => Self::UserReactivated,
+            ChangesFeedEventType::UserUpdated => Self::UserUpdated,
+            ChangesFeedEventType::SessionCreated => Self::SessionCreated,
+            ChangesFeedEventType::SessionRevoked => Self::SessionRevoked,
+            ChangesFeedEventType::HoneytokenUsed => Self::HoneytokenUsed,
+        }
+    }
+}
+
+impl std::fmt::Display for ChangesFeedEventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&WebhookEventKind::from(*self), f)
+    }
+}
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "ChangesFeedFilter")]
+#[aide(input_with = "Query<FilterParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct FilterParams {
+    /// Retrieve the entries of the given type of event
+    #[serde(rename = "filter[type]")]
+    kind: Option<ChangesFeedEventType>,
+}
+
+impl std::fmt::Display for FilterParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(kind) = self.kind {
+            write!(f, "?filter[type]={kind}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Invalid filter parameters")]
+    InvalidFilter(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("listChangesFeed")
+        .summary("List changes feed entries")
+        .description(
+            "Retrieve the events which affected users, in the order in which they were recorded.
+The feed is only recorded if `webhooks.changes_feed.enabled` is set in the configuration.
+
+Consumers should remember the ID of the last entry they processed, and pass it as the `page[after]` parameter on the next poll to only retrieve the entries recorded since.
+Entries are deleted after the retention period configured in `webhooks.changes_feed.retention`.",
+        )
+        .tag("changes-feed")
+        .response_with::<200, Json<PaginatedResponse<ChangesFeedEntry>>, _>(|t| {
+            let entries = ChangesFeedEntry::samples();
+            let pagination = mas_storage::Pagination::first(entries.len());
+            let page = Page {
+                edges: entries.into(),
+                has_next_page: true,
+                has_previous_page: false,
+            };
+
+            t.description("Paginated response of changes feed entries")
+                .example(PaginatedResponse::new(
+                    page,
+                    pagination,
+                    CountMode::Exact,
+                    Count::Exact(42),
+                    ChangesFeedEntry::PATH,
+                ))
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.changes_feed.list", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    Pagination(pagination, count_mode): Pagination,
+    params: FilterParams,
+) -> Result<Json<PaginatedResponse<ChangesFeedEntry>>, RouteError> {
+    let base = format!("{path}{params}", path = ChangesFeedEntry::PATH);
+    let filter = ChangesFeedFilter::new();
+
+    let filter = match params.kind {
+        Some(kind) => filter.with_kind(kind.into()),
+        None => filter,
+    };
+
+    let page = repo.changes_feed().list(filter, pagination).await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.changes_feed().count(filter).await?),
+        CountMode::Estimated => repo.changes_feed().estimate_count(filter).await?,
+    };
+
+    Ok(Json(PaginatedResponse::new(
+        page.map(ChangesFeedEntry::from),
+        pagination,
+        count_mode,
+        count,
+        &base,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_storage::job::DispatchWebhookJob;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_list(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        let alice = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let registered = DispatchWebhookJob::user_registered(&mut rng, &state.clock, &alice);
+        repo.changes_feed()
+            .add(&mut rng, &state.clock, registered.event())
+            .await
+            .unwrap();
+        let alice = repo.user().lock(&state.clock, alice).await.unwrap();
+        let updated = DispatchWebhookJob::user_updated(&mut rng, &state.clock, &alice);
+        repo.changes_feed()
+            .add(&mut rng, &state.clock, updated.event())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/changes-feed")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 2);
+        assert_eq!(body["data"][0]["type"], "changes-feed-entry");
+        assert_eq!(body["data"][0]["attributes"]["type"], "user.registered");
+        assert_eq!(body["data"][1]["attributes"]["type"], "user.updated");
+        assert_eq!(body["data"][1]["attributes"]["data"]["locked"], true);
+
+        // Poll the entries recorded after the first one
+        let cursor = body["data"][0]["id"].as_str().unwrap();
+        let request = Request::get(format!("/api/admin/v1/changes-feed?page[after]={cursor}"))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"][0]["attributes"]["type"], "user.updated");
+
+        // Filter by type
+        let request = Request::get("/api/admin/v1/changes-feed?filter[type]=user.registered")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+        assert_eq!(
+            body["links"]["self"],
+            "/api/admin/v1/changes-feed?filter[type]=user.registered&page[first]=10"
+        );
+
+        // Invalid type
+        let request = Request::get("/api/admin/v1/changes-feed?filter[type]=unknown")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
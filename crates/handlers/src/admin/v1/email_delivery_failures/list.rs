@@ -0,0 +1,233 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use hyper::StatusCode;
+use mas_storage::{email_delivery::EmailDeliveryFailureFilter, pagination::Count, Page};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{EmailDeliveryFailure, Resource},
+        params::{CountMode, Pagination},
+        response::{ErrorResponse, PaginatedResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "EmailDeliveryFailureFilter")]
+#[aide(input_with = "Query<FilterParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct FilterParams {
+    /// Retrieve the failures for addresses of the given domain
+    #[serde(rename = "filter[domain]")]
+    domain: Option<String>,
+
+    /// Retrieve only the hard bounces if `true`, or only the deferred emails
+    /// if `false`
+    #[serde(rename = "filter[permanent]")]
+    permanent: Option<bool>,
+}
+
+impl std::fmt::Display for FilterParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = '?';
+
+        if let Some(domain) = &self.domain {
+            write!(f, "{sep}filter[domain]={domain}")?;
+            sep = '&';
+        }
+
+        if let Some(permanent) = self.permanent {
+            write!(f, "{sep}filter[permanent]={permanent}")?;
+            sep = '&';
+        }
+
+        let _ = sep;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Invalid filter parameters")]
+    InvalidFilter(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("listEmailDeliveryFailures")
+        .summary("List email delivery failures")
+        .description(
+            "Retrieve a list of emails which the mail server deferred or rejected, with the oldest first.
+Use the `page[last]` parameter to retrieve the last N failures.
+No more emails are sent to the addresses which hard-bounced.",
+        )
+        .tag("email-delivery-failure")
+        .response_with::<200, Json<PaginatedResponse<EmailDeliveryFailure>>, _>(|t| {
+            let failures = EmailDeliveryFailure::samples();
+            let pagination = mas_storage::Pagination::first(failures.len());
+            let page = Page {
+                edges: failures.into(),
+                has_next_page: true,
+                has_previous_page: false,
+            };
+
+            t.description("Paginated response of email delivery failures")
+                .example(PaginatedResponse::new(
+                    page,
+                    pagination,
+                    CountMode::Exact,
+                    Count::Exact(42),
+                    EmailDeliveryFailure::PATH,
+                ))
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.email_delivery_failures.list", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    Pagination(pagination, count_mode): Pagination,
+    params: FilterParams,
+) -> Result<Json<PaginatedResponse<EmailDeliveryFailure>>, RouteError> {
+    let base = format!("{path}{params}", path = EmailDeliveryFailure::PATH);
+    let filter = EmailDeliveryFailureFilter::new();
+
+    let filter = match &params.domain {
+        Some(domain) => filter.for_domain(domain),
+        None => filter,
+    };
+
+    let filter = match params.permanent {
+        Some(true) => filter.permanent_only(),
+        Some(false) => filter.deferred_only(),
+        None => filter,
+    };
+
+    let page = repo
+        .email_delivery_failure()
+        .list(filter, pagination)
+        .await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.email_delivery_failure().count(filter).await?),
+        CountMode::Estimated => repo.email_delivery_failure().estimate_count(filter).await?,
+    };
+
+    Ok(Json(PaginatedResponse::new(
+        page.map(EmailDeliveryFailure::from),
+        pagination,
+        count_mode,
+        count,
+        &base,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_list(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        repo.email_delivery_failure()
+            .add(
+                &mut rng,
+                &state.clock,
+                "alice@example.com",
+                false,
+                "transient error (421): Try again later".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.email_delivery_failure()
+            .add(
+                &mut rng,
+                &state.clock,
+                "bob@example.com",
+                true,
+                "permanent error (550): No such user".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.email_delivery_failure()
+            .add(
+                &mut rng,
+                &state.clock,
+                "charlie@example.org",
+                true,
+                "permanent error (550): No such user".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/email-delivery-failures")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 3);
+        assert_eq!(body["data"][0]["type"], "email-delivery-failure");
+
+        // Filter by domain and outcome
+        let request = Request::get(
+            "/api/admin/v1/email-delivery-failures?filter[domain]=example.com&filter[permanent]=true",
+        )
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+        assert_eq!(body["data"][0]["attributes"]["email"], "bob@example.com");
+        assert_eq!(body["data"][0]["attributes"]["permanent"], true);
+        assert_eq!(
+            body["links"]["self"],
+            "/api/admin/v1/email-delivery-failures?filter[domain]=example.com&filter[permanent]=true&page[first]=10"
+        );
+
+        // Invalid filter
+        let request = Request::get("/api/admin/v1/email-delivery-failures?filter[permanent]=maybe")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
+use mas_storage::{user::UserHomeserver, BoxRng};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::HoneytokenKind;
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::Honeytoken,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("A username is required for decoy accounts")]
+    MissingUsername,
+
+    #[error("Only decoy accounts have a username")]
+    UnexpectedUsername,
+
+    #[error("Username {0:?} is already taken")]
+    UsernameTaken(String),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::MissingUsername | Self::UnexpectedUsername => StatusCode::BAD_REQUEST,
+            Self::UsernameTaken(_) => StatusCode::CONFLICT,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// # JSON payload for the `POST /api/admin/v1/honeytokens` endpoint
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename = "AddHoneytokenRequest")]
+pub struct Request {
+    /// What the honeytoken impersonates
+    kind: HoneytokenKind,
+
+    /// A free-form label, to remember where the honeytoken was planted
+    #[serde(default)]
+    label: String,
+
+    /// The username of the decoy account. Required for decoy accounts, and
+    /// forbidden for decoy tokens, which are generated by the server.
+    #[serde(default)]
+    username: Option<String>,
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("addHoneytoken")
+        .summary("Plant a new honeytoken")
+        .description(
+            "Mint a decoy token, or reserve the username of a decoy account.
+Honeytokens never grant access to anything: any attempt to use one is recorded in the audit log, sent to the webhooks and reported to the configured addresses.",
+        )
+        .tag("honeytoken")
+        .response_with::<200, Json<SingleResponse<Honeytoken>>, _>(|t| {
+            let [sample, ..] = Honeytoken::samples();
+            let response = SingleResponse::new_canonical(sample);
+            t.description("Honeytoken was planted").example(response)
+        })
+        .response_with::<400, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::MissingUsername);
+            t.description("A username is required for decoy accounts")
+                .example(response)
+        })
+        .response_with::<409, RouteError, _>(|t| {
+            let response =
+                ErrorResponse::from_error(&RouteError::UsernameTaken("alice".to_owned()));
+            t.description("Username is already taken").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.honeytokens.add", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    Json(params): Json<Request>,
+) -> Result<Json<SingleResponse<Honeytoken>>, RouteError> {
+    let kind = mas_data_model::HoneytokenKind::from(params.kind);
+
+    let value = match (kind.token_type(), params.username) {
+        (Some(token_type), None) => token_type.generate(&mut rng),
+        (Some(_), Some(_)) => return Err(RouteError::UnexpectedUsername),
+        (None, None) => return Err(RouteError::MissingUsername),
+        (None, Some(username)) => {
+            // Decoy accounts must not shadow a real user, or another decoy
+            if repo.user().exists(UserHomeserver::Main, &username).await?
+                || repo.honeytoken().find_account(&username).await?.is_some()
+            {
+                return Err(RouteError::UsernameTaken(username));
+            }
+
+            username
+        }
+    };
+
+    let honeytoken = repo
+        .honeytoken()
+        .add(&mut rng, &clock, kind, value, params.label)
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            None,
+            serde_json::json!({
+                "action": "add_honeytoken",
+                "honeytoken_id": honeytoken.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new_canonical(Honeytoken::from(
+        honeytoken,
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::TokenType;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_add_token(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let request = Request::post("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "kind": "compat_access_token",
+                "label": "CI secrets",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["data"]["type"], "honeytoken");
+        assert_eq!(body["data"]["attributes"]["label"], "CI secrets");
+
+        // The server minted a token which looks like a real one
+        let value = body["data"]["attributes"]["value"].as_str().unwrap();
+        assert_eq!(
+            TokenType::check(value).unwrap(),
+            TokenType::CompatAccessToken
+        );
+
+        // Tokens can't have a username
+        let request = Request::post("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "kind": "access_token",
+                "username": "alice",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_add_account(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        repo.user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "kind": "account",
+                "label": "Wiki page",
+                "username": "backup-admin",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["data"]["attributes"]["value"], "backup-admin");
+
+        // The username is now taken, both for decoys and for real users
+        let request = Request::post("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "kind": "account",
+                "username": "backup-admin",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CONFLICT);
+
+        let request = Request::post("/api/admin/v1/users")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "username": "backup-admin",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CONFLICT);
+
+        // Decoys can't shadow real users
+        let request = Request::post("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "kind": "account",
+                "username": "alice",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CONFLICT);
+
+        // Decoy accounts need a username
+        let request = Request::post("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "kind": "account",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
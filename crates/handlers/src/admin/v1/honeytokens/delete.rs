@@ -0,0 +1,152 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
+use mas_storage::BoxRng;
+use ulid::Ulid;
+
+use crate::{
+    admin::{call_context::CallContext, params::UlidPathParam, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Honeytoken ID {0} not found")]
+    NotFound(Ulid),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("deleteHoneytoken")
+        .summary("Remove a honeytoken")
+        .description(
+            "Stop watching for a honeytoken. The uses which were already recorded are kept in the audit log.",
+        )
+        .tag("honeytoken")
+        .response_with::<204, (), _>(|t| t.description("Honeytoken was removed"))
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("Honeytoken was not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.honeytokens.delete", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    id: UlidPathParam,
+) -> Result<StatusCode, RouteError> {
+    let honeytoken = repo
+        .honeytoken()
+        .lookup(*id)
+        .await?
+        .ok_or(RouteError::NotFound(*id))?;
+
+    repo.honeytoken().remove(honeytoken).await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            None,
+            serde_json::json!({
+                "action": "remove_honeytoken",
+                "honeytoken_id": id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::HoneytokenKind;
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_delete(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let honeytoken = repo
+            .honeytoken()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                HoneytokenKind::Account,
+                "backup-admin".to_owned(),
+                "Wiki page".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::delete(format!("/api/admin/v1/honeytokens/{}", honeytoken.id))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+
+        // The username is free again
+        let mut repo = state.repository().await.unwrap();
+        assert!(repo
+            .honeytoken()
+            .find_account("backup-admin")
+            .await
+            .unwrap()
+            .is_none());
+        repo.save().await.unwrap();
+
+        // Removing it again fails
+        let request = Request::delete(format!("/api/admin/v1/honeytokens/{}", honeytoken.id))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        let request = Request::delete(format!("/api/admin/v1/honeytokens/{}", Ulid::nil()))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
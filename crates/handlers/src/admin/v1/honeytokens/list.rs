@@ -0,0 +1,233 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use hyper::StatusCode;
+use mas_storage::{honeytoken::HoneytokenFilter, pagination::Count, Page};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::HoneytokenKind;
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Honeytoken, Resource},
+        params::{CountMode, Pagination},
+        response::{ErrorResponse, PaginatedResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "HoneytokenFilter")]
+#[aide(input_with = "Query<FilterParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct FilterParams {
+    /// Retrieve the honeytokens of the given kind
+    #[serde(rename = "filter[kind]")]
+    kind: Option<HoneytokenKind>,
+
+    /// Retrieve only the honeytokens which were used if `true`, or only the
+    /// ones which were never used if `false`
+    #[serde(rename = "filter[used]")]
+    used: Option<bool>,
+}
+
+impl std::fmt::Display for FilterParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = '?';
+
+        if let Some(kind) = self.kind {
+            write!(f, "{sep}filter[kind]={kind}")?;
+            sep = '&';
+        }
+
+        if let Some(used) = self.used {
+            write!(f, "{sep}filter[used]={used}")?;
+            sep = '&';
+        }
+
+        let _ = sep;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Invalid filter parameters")]
+    InvalidFilter(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("listHoneytokens")
+        .summary("List honeytokens")
+        .description(
+            "Retrieve a list of the decoy tokens and accounts planted to detect credential leaks.",
+        )
+        .tag("honeytoken")
+        .response_with::<200, Json<PaginatedResponse<Honeytoken>>, _>(|t| {
+            let honeytokens = Honeytoken::samples();
+            let pagination = mas_storage::Pagination::first(honeytokens.len());
+            let page = Page {
+                edges: honeytokens.into(),
+                has_next_page: true,
+                has_previous_page: false,
+            };
+
+            t.description("Paginated response of honeytokens")
+                .example(PaginatedResponse::new(
+                    page,
+                    pagination,
+                    CountMode::Exact,
+                    Count::Exact(42),
+                    Honeytoken::PATH,
+                ))
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.honeytokens.list", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    Pagination(pagination, count_mode): Pagination,
+    params: FilterParams,
+) -> Result<Json<PaginatedResponse<Honeytoken>>, RouteError> {
+    let base = format!("{path}{params}", path = Honeytoken::PATH);
+    let filter = HoneytokenFilter::new();
+
+    let filter = match params.kind {
+        Some(kind) => filter.with_kind(kind.into()),
+        None => filter,
+    };
+
+    let filter = match params.used {
+        Some(true) => filter.used_only(),
+        Some(false) => filter.unused_only(),
+        None => filter,
+    };
+
+    let page = repo.honeytoken().list(filter, pagination).await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.honeytoken().count(filter).await?),
+        CountMode::Estimated => repo.honeytoken().estimate_count(filter).await?,
+    };
+
+    Ok(Json(PaginatedResponse::new(
+        page.map(Honeytoken::from),
+        pagination,
+        count_mode,
+        count,
+        &base,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::HoneytokenKind;
+    use mas_storage::Clock;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_list(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .honeytoken()
+            .add(
+                &mut rng,
+                &state.clock,
+                HoneytokenKind::AccessToken,
+                "mat_decoy".to_owned(),
+                "CI secrets".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.honeytoken()
+            .add(
+                &mut rng,
+                &state.clock,
+                HoneytokenKind::Account,
+                "backup-admin".to_owned(),
+                "Wiki page".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.honeytoken()
+            .record_use(access_token, state.clock.now())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/honeytokens")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 2);
+        assert_eq!(body["data"][0]["type"], "honeytoken");
+
+        // Filter by kind
+        let request = Request::get("/api/admin/v1/honeytokens?filter[kind]=account")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+        assert_eq!(body["data"][0]["attributes"]["value"], "backup-admin");
+
+        // Filter by use
+        let request = Request::get("/api/admin/v1/honeytokens?filter[used]=true")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+        assert_eq!(body["data"][0]["attributes"]["kind"], "access_token");
+        assert_eq!(
+            body["links"]["self"],
+            "/api/admin/v1/honeytokens?filter[used]=true&page[first]=10"
+        );
+
+        // Invalid filter
+        let request = Request::get("/api/admin/v1/honeytokens?filter[kind]=password")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
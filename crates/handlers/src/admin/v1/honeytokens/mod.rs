@@ -0,0 +1,46 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+mod add;
+mod delete;
+mod list;
+
+pub use self::{
+    add::{doc as add_doc, handler as add},
+    delete::{doc as delete_doc, handler as delete},
+    list::{doc as list_doc, handler as list},
+};
+
+/// What a honeytoken impersonates
+#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum HoneytokenKind {
+    AccessToken,
+    RefreshToken,
+    CompatAccessToken,
+    CompatRefreshToken,
+    Account,
+}
+
+impl From<HoneytokenKind> for mas_data_model::HoneytokenKind {
+    fn from(kind: HoneytokenKind) -> Self {
+        match kind {
+            HoneytokenKind::AccessToken => Self::AccessToken,
+            HoneytokenKind::RefreshToken => Self::RefreshToken,
+            HoneytokenKind::CompatAccessToken => Self::CompatAccessToken,
+            HoneytokenKind::CompatRefreshToken => Self::CompatRefreshToken,
+            HoneytokenKind::Account => Self::Account,
+        }
+    }
+}
+
+impl std::fmt::Display for HoneytokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&mas_data_model::HoneytokenKind::from(*self), f)
+    }
+}
@@ -5,28 +5,74 @@
 // Please see LICENSE in the repository root for full details.
 
 use aide::axum::{
-    routing::{get_with, post_with},
+    routing::{delete_with, get_with, post_with},
     ApiRouter,
 };
 use axum::extract::{FromRef, FromRequestParts};
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_data_model::SiteConfig;
+use mas_email::Mailer;
+use mas_keystore::Keystore;
 use mas_matrix::BoxHomeserverConnection;
 use mas_storage::BoxRng;
+use sqlx::PgPool;
 
 use super::call_context::CallContext;
 use crate::passwords::PasswordManager;
 
+mod audit_events;
+mod changes_feed;
+mod email_delivery_failures;
+mod honeytokens;
 mod oauth2_sessions;
+mod runtime_resources;
+mod status;
+mod upstream_oauth2_email_changes;
+mod upstream_oauth2_funnel;
 mod users;
 
 pub fn router<S>() -> ApiRouter<S>
 where
     S: Clone + Send + Sync + 'static,
     BoxHomeserverConnection: FromRef<S>,
+    HttpClientFactory: FromRef<S>,
+    Keystore: FromRef<S>,
+    Mailer: FromRef<S>,
     PasswordManager: FromRef<S>,
+    PgPool: FromRef<S>,
+    SiteConfig: FromRef<S>,
     BoxRng: FromRequestParts<S>,
     CallContext: FromRequestParts<S>,
 {
     ApiRouter::<S>::new()
+        .api_route(
+            "/audit-events",
+            get_with(self::audit_events::list, self::audit_events::list_doc),
+        )
+        .api_route(
+            "/audit-events/:id",
+            get_with(self::audit_events::get, self::audit_events::get_doc),
+        )
+        .api_route(
+            "/changes-feed",
+            get_with(self::changes_feed::list, self::changes_feed::list_doc),
+        )
+        .api_route(
+            "/email-delivery-failures",
+            get_with(
+                self::email_delivery_failures::list,
+                self::email_delivery_failures::list_doc,
+            ),
+        )
+        .api_route(
+            "/honeytokens",
+            get_with(self::honeytokens::list, self::honeytokens::list_doc)
+                .post_with(self::honeytokens::add, self::honeytokens::add_doc),
+        )
+        .api_route(
+            "/honeytokens/:id",
+            delete_with(self::honeytokens::delete, self::honeytokens::delete_doc),
+        )
         .api_route(
             "/oauth2-sessions",
             get_with(self::oauth2_sessions::list, self::oauth2_sessions::list_doc),
@@ -35,6 +81,59 @@ where
             "/oauth2-sessions/:id",
             get_with(self::oauth2_sessions::get, self::oauth2_sessions::get_doc),
         )
+        .api_route(
+            "/runtime-resources",
+            get_with(
+                self::runtime_resources::list,
+                self::runtime_resources::list_doc,
+            ),
+        )
+        .api_route(
+            "/runtime-resources/:name/disable",
+            post_with(
+                self::runtime_resources::disable,
+                self::runtime_resources::disable_doc,
+            ),
+        )
+        .api_route(
+            "/runtime-resources/:name/enable",
+            post_with(
+                self::runtime_resources::enable,
+                self::runtime_resources::enable_doc,
+            ),
+        )
+        .api_route(
+            "/status",
+            get_with(self::status::get, self::status::get_doc),
+        )
+        .api_route(
+            "/upstream-oauth2-email-changes",
+            get_with(
+                self::upstream_oauth2_email_changes::list,
+                self::upstream_oauth2_email_changes::list_doc,
+            ),
+        )
+        .api_route(
+            "/upstream-oauth2-email-changes/:id/apply",
+            post_with(
+                self::upstream_oauth2_email_changes::apply,
+                self::upstream_oauth2_email_changes::apply_doc,
+            ),
+        )
+        .api_route(
+            "/upstream-oauth2-email-changes/:id/dismiss",
+            post_with(
+                self::upstream_oauth2_email_changes::dismiss,
+                self::upstream_oauth2_email_changes::dismiss_doc,
+            ),
+        )
+        .api_route(
+            "/upstream-oauth2-funnel",
+            get_with(
+                self::upstream_oauth2_funnel::get,
+                self::upstream_oauth2_funnel::get_doc,
+            ),
+        )
         .api_route(
             "/users",
             get_with(self::users::list, self::users::list_doc)
@@ -56,6 +155,10 @@ where
             "/users/:id/set-admin",
             post_with(self::users::set_admin, self::users::set_admin_doc),
         )
+        .api_route(
+            "/users/:id/set-bot",
+            post_with(self::users::set_bot, self::users::set_bot_doc),
+        )
         .api_route(
             "/users/:id/deactivate",
             post_with(self::users::deactivate, self::users::deactivate_doc),
@@ -68,4 +171,22 @@ where
             "/users/:id/unlock",
             post_with(self::users::unlock, self::users::unlock_doc),
         )
+        .api_route(
+            "/users/:id/place-legal-hold",
+            post_with(
+                self::users::place_legal_hold,
+                self::users::place_legal_hold_doc,
+            ),
+        )
+        .api_route(
+            "/users/:id/release-legal-hold",
+            post_with(
+                self::users::release_legal_hold,
+                self::users::release_legal_hold_doc,
+            ),
+        )
+        .api_route(
+            "/users/:id/terms",
+            get_with(self::users::terms, self::users::terms_doc),
+        )
 }
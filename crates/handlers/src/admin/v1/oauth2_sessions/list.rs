@@ -14,7 +14,7 @@ use axum::{
 };
 use axum_macros::FromRequestParts;
 use hyper::StatusCode;
-use mas_storage::{oauth2::OAuth2SessionFilter, Page};
+use mas_storage::{oauth2::OAuth2SessionFilter, pagination::Count, Page};
 use oauth2_types::scope::{Scope, ScopeToken};
 use schemars::JsonSchema;
 use serde::Deserialize;
@@ -24,7 +24,7 @@ use crate::{
     admin::{
         call_context::CallContext,
         model::{OAuth2Session, Resource},
-        params::Pagination,
+        params::{CountMode, Pagination},
         response::{ErrorResponse, PaginatedResponse},
     },
     impl_from_error_for_route,
@@ -174,7 +174,8 @@ Use the `filter[status]` parameter to filter the sessions by their status and `p
                 .example(PaginatedResponse::new(
                     page,
                     pagination,
-                    42,
+                    CountMode::Exact,
+                    Count::Exact(42),
                     OAuth2Session::PATH,
                 ))
         })
@@ -191,7 +192,7 @@ Use the `filter[status]` parameter to filter the sessions by their status and `p
 #[tracing::instrument(name = "handler.admin.v1.oauth2_sessions.list", skip_all, err)]
 pub async fn handler(
     CallContext { mut repo, .. }: CallContext,
-    Pagination(pagination): Pagination,
+    Pagination(pagination, count_mode): Pagination,
     params: FilterParams,
 ) -> Result<Json<PaginatedResponse<OAuth2Session>>, RouteError> {
     let base = format!("{path}{params}", path = OAuth2Session::PATH);
@@ -268,11 +269,15 @@ pub async fn handler(
     };
 
     let page = repo.oauth2_session().list(filter, pagination).await?;
-    let count = repo.oauth2_session().count(filter).await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.oauth2_session().count(filter).await?),
+        CountMode::Estimated => repo.oauth2_session().estimate_count(filter).await?,
+    };
 
     Ok(Json(PaginatedResponse::new(
         page.map(OAuth2Session::from),
         pagination,
+        count_mode,
         count,
         &base,
     )))
@@ -0,0 +1,176 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{extract::Path, response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::{AuditEventKind, RuntimeResource};
+use mas_storage::BoxRng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::{ResourcePathParam, RuntimeResourceStatus};
+use crate::{
+    admin::{call_context::CallContext, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Resource {0:?} not found")]
+    NotFound(String),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// # JSON payload for the `POST /api/admin/v1/runtime-resources/:name/disable` endpoint
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename = "DisableRuntimeResourceRequest")]
+pub struct Request {
+    /// Why the resource is disabled, for other administrators to see
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("disableRuntimeResource")
+        .summary("Disable a resource")
+        .description(
+            "Turn off a resource on all the instances of the service, without restarting them.
+Requests to a disabled resource fail with a 503 error, and it is omitted from the discovery document.
+Disabling a resource which is already disabled only updates the reason.",
+        )
+        .tag("runtime-resource")
+        .response_with::<200, Json<RuntimeResourceStatus>, _>(|t| {
+            let [_, sample, _] = RuntimeResourceStatus::samples();
+            t.description("Resource was disabled").example(sample)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound("login".to_owned()));
+            t.description("Resource was not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.runtime_resources.disable", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    Path(ResourcePathParam { name }): Path<ResourcePathParam>,
+    Json(params): Json<Request>,
+) -> Result<Json<RuntimeResourceStatus>, RouteError> {
+    let resource: RuntimeResource = name.parse().map_err(|_| RouteError::NotFound(name))?;
+
+    let disabled = repo
+        .runtime_resource()
+        .disable(&clock, resource, params.reason)
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            None,
+            serde_json::json!({
+                "action": "disable_runtime_resource",
+                "resource": resource.as_str(),
+                "reason": disabled.reason,
+            }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(RuntimeResourceStatus::disabled(disabled)))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::RuntimeResource;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_disable(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let request = Request::post("/api/admin/v1/runtime-resources/client_registration/disable")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "reason": "Spam registrations",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["name"], "client_registration");
+        assert_eq!(body["enabled"], false);
+        assert_eq!(body["reason"], "Spam registrations");
+
+        let mut repo = state.repository().await.unwrap();
+        assert!(repo
+            .runtime_resource()
+            .is_disabled(RuntimeResource::ClientRegistration)
+            .await
+            .unwrap());
+        repo.save().await.unwrap();
+
+        // Clients can't register anymore
+        let request = Request::post("/oauth2/registration").json(serde_json::json!({
+            "client_uri": "https://example.com/",
+            "redirect_uris": ["https://example.com/"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code"],
+            "token_endpoint_auth_method": "none",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+        // And the endpoint is not advertised anymore
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert!(body.get("registration_endpoint").is_none());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_disable_unknown(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let request = Request::post("/api/admin/v1/runtime-resources/login/disable")
+            .bearer(&token)
+            .json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{extract::Path, response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::{AuditEventKind, RuntimeResource};
+use mas_storage::BoxRng;
+
+use super::{ResourcePathParam, RuntimeResourceStatus};
+use crate::{
+    admin::{call_context::CallContext, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Resource {0:?} not found")]
+    NotFound(String),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("enableRuntimeResource")
+        .summary("Enable a resource again")
+        .description(
+            "Turn a resource which was disabled back on, on all the instances of the service.
+Enabling a resource which is not disabled does nothing.",
+        )
+        .tag("runtime-resource")
+        .response_with::<200, Json<RuntimeResourceStatus>, _>(|t| {
+            let [sample, ..] = RuntimeResourceStatus::samples();
+            t.description("Resource was enabled").example(sample)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound("login".to_owned()));
+            t.description("Resource was not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.runtime_resources.enable", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    Path(ResourcePathParam { name }): Path<ResourcePathParam>,
+) -> Result<Json<RuntimeResourceStatus>, RouteError> {
+    let resource: RuntimeResource = name.parse().map_err(|_| RouteError::NotFound(name))?;
+
+    if repo.runtime_resource().enable(resource).await? {
+        audit
+            .record(
+                &mut repo,
+                &mut rng,
+                &clock,
+                AuditEventKind::AdminAction,
+                None,
+                serde_json::json!({
+                    "action": "enable_runtime_resource",
+                    "resource": resource.as_str(),
+                }),
+            )
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok(Json(RuntimeResourceStatus::enabled(resource)))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::RuntimeResource;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_enable(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        repo.runtime_resource()
+            .disable(&state.clock, RuntimeResource::DeviceAuthorization, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert!(body.get("device_authorization_endpoint").is_none());
+
+        let request = Request::post("/api/admin/v1/runtime-resources/device_authorization/enable")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["name"], "device_authorization");
+        assert_eq!(body["enabled"], true);
+
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert!(body.get("device_authorization_endpoint").is_some());
+    }
+}
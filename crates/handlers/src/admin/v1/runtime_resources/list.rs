@@ -0,0 +1,119 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::RuntimeResource;
+
+use super::RuntimeResourceStatus;
+use crate::{
+    admin::{call_context::CallContext, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("listRuntimeResources")
+        .summary("List the resources which can be disabled at runtime")
+        .description(
+            "Get the status of every resource administrators can turn off without restarting the service, for example during an incident.",
+        )
+        .tag("runtime-resource")
+        .response_with::<200, Json<Vec<RuntimeResourceStatus>>, _>(|t| {
+            t.description("The status of each resource")
+                .example(RuntimeResourceStatus::samples())
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.runtime_resources.list", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+) -> Result<Json<Vec<RuntimeResourceStatus>>, RouteError> {
+    let mut disabled = repo.runtime_resource().list_disabled().await?;
+
+    let resources = RuntimeResource::ALL
+        .into_iter()
+        .map(
+            |resource| match disabled.iter().position(|d| d.resource == resource) {
+                Some(index) => RuntimeResourceStatus::disabled(disabled.swap_remove(index)),
+                None => RuntimeResourceStatus::enabled(resource),
+            },
+        )
+        .collect();
+
+    Ok(Json(resources))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::RuntimeResource;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_list(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        repo.runtime_resource()
+            .disable(
+                &state.clock,
+                RuntimeResource::CompatLogin,
+                Some("Incident".to_owned()),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/runtime-resources")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        insta::assert_json_snapshot!(body, @r###"
+        [
+          {
+            "name": "client_registration",
+            "enabled": true
+          },
+          {
+            "name": "compat_login",
+            "enabled": false,
+            "disabled_at": "2022-01-16T14:40:00Z",
+            "reason": "Incident"
+          },
+          {
+            "name": "device_authorization",
+            "enabled": true
+          }
+        ]
+        "###);
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use chrono::{DateTime, Utc};
+use mas_data_model::{DisabledResource, RuntimeResource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+mod disable;
+mod enable;
+mod list;
+
+pub use self::{
+    disable::{doc as disable_doc, handler as disable},
+    enable::{doc as enable_doc, handler as enable},
+    list::{doc as list_doc, handler as list},
+};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ResourcePathParam {
+    /// The name of the resource, for example `client_registration`
+    name: String,
+}
+
+/// Whether a resource is available, or was disabled by an administrator
+#[derive(Serialize, JsonSchema)]
+pub struct RuntimeResourceStatus {
+    /// The name of the resource
+    name: String,
+
+    /// Whether the resource is available
+    enabled: bool,
+
+    /// When the resource was disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disabled_at: Option<DateTime<Utc>>,
+
+    /// Why the resource was disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl RuntimeResourceStatus {
+    fn enabled(resource: RuntimeResource) -> Self {
+        Self {
+            name: resource.to_string(),
+            enabled: true,
+            disabled_at: None,
+            reason: None,
+        }
+    }
+
+    fn disabled(resource: DisabledResource) -> Self {
+        Self {
+            name: resource.resource.to_string(),
+            enabled: false,
+            disabled_at: Some(resource.disabled_at),
+            reason: resource.reason,
+        }
+    }
+
+    fn samples() -> [Self; 3] {
+        [
+            Self::enabled(RuntimeResource::ClientRegistration),
+            Self::disabled(DisabledResource {
+                resource: RuntimeResource::CompatLogin,
+                disabled_at: DateTime::default(),
+                reason: Some("Credential stuffing attack in progress".to_owned()),
+            }),
+            Self::enabled(RuntimeResource::DeviceAuthorization),
+        ]
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{future::Future, time::Instant};
+
+use aide::{transform::TransformOperation, OperationIo};
+use anyhow::bail;
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::{future::BoxFuture, FutureExt};
+use hyper::StatusCode;
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderDiscoveryMode};
+use mas_email::Mailer;
+use mas_http::HttpService;
+use mas_keystore::Keystore;
+use mas_matrix::BoxHomeserverConnection;
+use mas_storage::Clock;
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{
+    admin::{call_context::CallContext, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+/// How long a single check can take before the component is considered
+/// unhealthy
+const CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long workers can go without a heartbeat, and jobs can wait past their
+/// schedule, before the job queue is reported as not healthy
+const JOB_QUEUE_LAG_MINUTES: i64 = 5;
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// The health of a component
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    /// The component works as expected
+    Healthy,
+
+    /// The component works, but something needs attention
+    Degraded,
+
+    /// The component doesn't work
+    Unhealthy,
+}
+
+/// The result of the check of a single component
+#[derive(Serialize, JsonSchema)]
+pub struct ComponentStatus {
+    /// The name of the component
+    name: String,
+
+    /// The health of the component
+    status: Health,
+
+    /// When the component was last checked
+    checked_at: DateTime<Utc>,
+
+    /// How long the check took, in milliseconds
+    duration_ms: u64,
+
+    /// What is wrong with the component, if it is not healthy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// The health of all the subsystems the service relies on
+#[derive(Serialize, JsonSchema)]
+pub struct ServiceStatus {
+    /// The health of the least healthy component
+    status: Health,
+
+    /// When the checks were started
+    checked_at: DateTime<Utc>,
+
+    /// The status of each component
+    components: Vec<ComponentStatus>,
+}
+
+impl ServiceStatus {
+    fn sample() -> Self {
+        let component = |name: &str, status, message: Option<&str>| ComponentStatus {
+            name: name.to_owned(),
+            status,
+            checked_at: DateTime::default(),
+            duration_ms: 3,
+            message: message.map(ToOwned::to_owned),
+        };
+
+        Self {
+            status: Health::Degraded,
+            checked_at: DateTime::default(),
+            components: vec![
+                component("database", Health::Healthy, None),
+                component("homeserver", Health::Healthy, None),
+                component("email", Health::Healthy, None),
+                component(
+                    "job-queue",
+                    Health::Degraded,
+                    Some("Pending jobs more than 5 minutes late: 12"),
+                ),
+                component("signing-keys", Health::Healthy, None),
+                component(
+                    "upstream-oauth2-provider/01040G2081040G2081040G2081",
+                    Health::Healthy,
+                    None,
+                ),
+            ],
+        }
+    }
+}
+
+/// A problem found by a check, which doesn't prevent the component from
+/// working
+struct Degraded(String);
+
+type CheckResult = Result<Option<Degraded>, anyhow::Error>;
+
+/// Run a check with a timeout, and record its outcome
+async fn run_check(
+    name: String,
+    clock: &dyn Clock,
+    check: impl Future<Output = CheckResult>,
+) -> ComponentStatus {
+    let start = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check).await;
+    let duration_ms = start.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+
+    let (status, message) = match result {
+        Ok(Ok(None)) => (Health::Healthy, None),
+        Ok(Ok(Some(Degraded(message)))) => (Health::Degraded, Some(message)),
+        Ok(Err(e)) => (Health::Unhealthy, Some(format!("{e:#}"))),
+        Err(_) => (Health::Unhealthy, Some("The check timed out".to_owned())),
+    };
+
+    ComponentStatus {
+        name,
+        status,
+        checked_at: clock.now(),
+        duration_ms,
+        message,
+    }
+}
+
+async fn check_database(pool: &PgPool) -> CheckResult {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(None)
+}
+
+async fn check_homeserver(homeserver: &BoxHomeserverConnection) -> CheckResult {
+    homeserver.health_check().await?;
+    Ok(None)
+}
+
+async fn check_email(mailer: &Mailer) -> CheckResult {
+    mailer.test_connection().await?;
+    Ok(None)
+}
+
+async fn check_job_queue(pool: &PgPool, now: DateTime<Utc>) -> CheckResult {
+    let threshold = now - Duration::minutes(JOB_QUEUE_LAG_MINUTES);
+
+    let workers: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM apalis.workers WHERE last_seen >= $1")
+            .bind(threshold)
+            .fetch_one(pool)
+            .await?;
+
+    if workers == 0 {
+        bail!("No worker was seen in the last {JOB_QUEUE_LAG_MINUTES} minutes");
+    }
+
+    let late_jobs: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM apalis.jobs WHERE status = 'Pending' AND run_at < $1",
+    )
+    .bind(threshold)
+    .fetch_one(pool)
+    .await?;
+
+    if late_jobs > 0 {
+        return Ok(Some(Degraded(format!(
+            "Pending jobs more than {JOB_QUEUE_LAG_MINUTES} minutes late: {late_jobs}"
+        ))));
+    }
+
+    Ok(None)
+}
+
+async fn check_signing_keys(keystore: &Keystore) -> CheckResult {
+    if keystore.is_empty() {
+        bail!("No key is available to sign tokens");
+    }
+
+    Ok(None)
+}
+
+/// Fetch the metadata and the JWKS of the provider, bypassing the metadata
+/// cache
+async fn check_upstream_provider(
+    http_service: &HttpService,
+    provider: &UpstreamOAuthProvider,
+) -> CheckResult {
+    let metadata = match provider.discovery_mode {
+        UpstreamOAuthProviderDiscoveryMode::Oidc => Some(
+            mas_oidc_client::requests::discovery::discover(http_service, &provider.issuer).await?,
+        ),
+        UpstreamOAuthProviderDiscoveryMode::Insecure => Some(
+            mas_oidc_client::requests::discovery::insecure_discover(http_service, &provider.issuer)
+                .await?,
+        ),
+        UpstreamOAuthProviderDiscoveryMode::Disabled => None,
+    };
+
+    let jwks_uri = provider
+        .jwks_uri_override
+        .as_ref()
+        .or_else(|| metadata.as_ref().map(|metadata| metadata.jwks_uri()));
+
+    if let Some(jwks_uri) = jwks_uri {
+        mas_oidc_client::requests::jose::fetch_jwks(http_service, jwks_uri).await?;
+    }
+
+    Ok(None)
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("getStatus")
+        .summary("Get the health of the service")
+        .description(
+            "Check every subsystem the service relies on, and report their health in a single document, for use in monitoring dashboards.
+The checks are run when the request is made, and each of them times out after 10 seconds.
+
+The upstream OAuth 2.0 providers are checked by fetching their metadata and JWKS, bypassing the cache.
+The email transport connection is only tested with SMTP.",
+        )
+        .tag("status")
+        .response_with::<200, Json<ServiceStatus>, _>(|t| {
+            t.description("The health of each component")
+                .example(ServiceStatus::sample())
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.status.get", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo, clock, ..
+    }: CallContext,
+    State(pool): State<PgPool>,
+    State(homeserver): State<BoxHomeserverConnection>,
+    State(mailer): State<Mailer>,
+    State(keystore): State<Keystore>,
+    State(http_client_factory): State<HttpClientFactory>,
+) -> Result<Json<ServiceStatus>, RouteError> {
+    let checked_at = clock.now();
+
+    let providers = repo.upstream_oauth_provider().all_enabled().await?;
+    // Don't hold a connection while the checks run
+    repo.cancel().await?;
+
+    let http_service = http_client_factory.http_service("upstream_oauth2.status");
+    let clock = &*clock;
+
+    let mut checks: Vec<BoxFuture<'_, ComponentStatus>> = vec![
+        run_check("database".to_owned(), clock, check_database(&pool)).boxed(),
+        run_check(
+            "homeserver".to_owned(),
+            clock,
+            check_homeserver(&homeserver),
+        )
+        .boxed(),
+        run_check("email".to_owned(), clock, check_email(&mailer)).boxed(),
+        run_check(
+            "job-queue".to_owned(),
+            clock,
+            check_job_queue(&pool, checked_at),
+        )
+        .boxed(),
+        run_check(
+            "signing-keys".to_owned(),
+            clock,
+            check_signing_keys(&keystore),
+        )
+        .boxed(),
+    ];
+
+    for provider in &providers {
+        checks.push(
+            run_check(
+                format!("upstream-oauth2-provider/{}", provider.id),
+                clock,
+                check_upstream_provider(&http_service, provider),
+            )
+            .boxed(),
+        );
+    }
+
+    let components = futures_util::future::join_all(checks).await;
+    let status = components
+        .iter()
+        .map(|component| component.status)
+        .max()
+        .unwrap_or(Health::Healthy);
+
+    Ok(Json(ServiceStatus {
+        status,
+        checked_at,
+        components,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_storage::Clock;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_status(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let request = Request::get("/api/admin/v1/status").bearer(&token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+
+        // There is no worker running in the tests
+        assert_eq!(body["status"], "unhealthy");
+        let components = body["components"].as_array().unwrap();
+        let names: Vec<&str> = components
+            .iter()
+            .map(|component| component["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "database",
+                "homeserver",
+                "email",
+                "job-queue",
+                "signing-keys"
+            ]
+        );
+        assert_eq!(components[0]["status"], "healthy");
+        assert_eq!(components[1]["status"], "healthy");
+        assert_eq!(components[2]["status"], "healthy");
+        assert_eq!(components[3]["status"], "unhealthy");
+        assert_eq!(components[4]["status"], "healthy");
+
+        // Pretend a worker is alive, but lagging behind
+        let now = state.clock.now();
+        sqlx::query(
+            "INSERT INTO apalis.workers (id, worker_type, storage_name, last_seen)
+                VALUES ('worker', 'test', 'test', $1)",
+        )
+        .bind(now)
+        .execute(&state.pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO apalis.jobs (job, id, job_type, status, run_at)
+                VALUES ('{}', 'job', 'test', 'Pending', $1)",
+        )
+        .bind(now - chrono::Duration::hours(1))
+        .execute(&state.pool)
+        .await
+        .unwrap();
+
+        let request = Request::get("/api/admin/v1/status").bearer(&token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["components"][3]["status"], "degraded");
+        assert_eq!(
+            body["components"][3]["message"],
+            "Pending jobs more than 5 minutes late: 1"
+        );
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::{AuditEventKind, UpstreamOAuthEmailChangeResolution};
+use mas_storage::BoxRng;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, UpstreamOAuthEmailChange},
+        params::UlidPathParam,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+    upstream_oauth2::email_change,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Upstream email change ID {0} not found")]
+    NotFound(Ulid),
+
+    #[error("Upstream email change ID {0} was already resolved")]
+    AlreadyResolved(Ulid),
+
+    #[error("The email address {0:?} already belongs to another user")]
+    EmailTaken(String),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::AlreadyResolved(_) | Self::EmailTaken(_) => StatusCode::CONFLICT,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("applyUpstreamOAuthEmailChange")
+        .summary("Apply an upstream email change")
+        .description(
+            "Add the new email address to the user, mark it as verified and make it their primary email address.
+The address previously asserted by the provider is removed from the user.
+This fails if the new address still belongs to another user, which has to be resolved first.",
+        )
+        .tag("upstream-oauth2")
+        .response_with::<200, Json<SingleResponse<UpstreamOAuthEmailChange>>, _>(|t| {
+            let [_, change] = UpstreamOAuthEmailChange::samples();
+            let id = change.id();
+            let response = SingleResponse::new(
+                change,
+                format!("/api/admin/v1/upstream-oauth2-email-changes/{id}/apply"),
+            );
+            t.description("The change was applied").example(response)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("Upstream email change was not found")
+                .example(response)
+        })
+        .response_with::<409, RouteError, _>(|t| {
+            let response =
+                ErrorResponse::from_error(&RouteError::EmailTaken("alice@example.org".to_owned()));
+            t.description("The change was already resolved, or the email address belongs to another user")
+                .example(response)
+        })
+}
+
+#[tracing::instrument(
+    name = "handler.admin.v1.upstream_oauth2_email_changes.apply",
+    skip_all,
+    err
+)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    id: UlidPathParam,
+) -> Result<Json<SingleResponse<UpstreamOAuthEmailChange>>, RouteError> {
+    let id = *id;
+    let change = repo
+        .upstream_oauth_email_change()
+        .lookup(id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    if !change.is_pending() {
+        return Err(RouteError::AlreadyResolved(id));
+    }
+
+    // The change is deleted along with the user, so they must exist
+    let user = repo
+        .user()
+        .lookup(change.user_id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    // The conflict may have been resolved since the change was recorded, or a
+    // new one may have appeared
+    if email_change::find_conflicting_user(&mut repo, &user, &change.new_email)
+        .await?
+        .is_some()
+    {
+        return Err(RouteError::EmailTaken(change.new_email));
+    }
+
+    // The administrator reviewed the change, so the address is trusted
+    email_change::apply(
+        &mut repo,
+        &mut rng,
+        &clock,
+        &user,
+        change.previous_email.as_deref(),
+        &change.new_email,
+        true,
+    )
+    .await?;
+
+    let change = repo
+        .upstream_oauth_email_change()
+        .resolve(&clock, change, UpstreamOAuthEmailChangeResolution::Applied)
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({
+                "action": "apply_upstream_oauth_email_change",
+                "upstream_oauth_email_change_id": id.to_string(),
+                "email": change.new_email,
+            }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new(
+        UpstreamOAuthEmailChange::from(change),
+        format!("/api/admin/v1/upstream-oauth2-email-changes/{id}/apply"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::{
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderTokenAuthMethod,
+    };
+    use mas_storage::{
+        upstream_oauth2::UpstreamOAuthProviderParams, user::UserEmailRepository, RepositoryAccess,
+    };
+    use oauth2_types::scope::{Scope, OPENID};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_apply(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: None,
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    authorization_endpoint_override: None,
+                    token_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let alice = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let previous = repo
+            .user_email()
+            .add(
+                &mut rng,
+                &state.clock,
+                &alice,
+                "alice@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        let previous = repo
+            .user_email()
+            .mark_as_verified(&state.clock, previous)
+            .await
+            .unwrap();
+        repo.user_email().set_as_primary(&previous).await.unwrap();
+
+        // Bob already has the new address of alice
+        let bob = repo
+            .user()
+            .add(&mut rng, &state.clock, "bob".to_owned())
+            .await
+            .unwrap();
+        let bob_email = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &bob, "alice@example.org".to_owned())
+            .await
+            .unwrap();
+        let bob_email = repo
+            .user_email()
+            .mark_as_verified(&state.clock, bob_email)
+            .await
+            .unwrap();
+
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &alice)
+            .await
+            .unwrap();
+        let change = repo
+            .upstream_oauth_email_change()
+            .add(
+                &mut rng,
+                &state.clock,
+                &link,
+                &alice,
+                Some(&bob),
+                Some("alice@example.com".to_owned()),
+                "alice@example.org".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The address still belongs to bob
+        let request = Request::post(format!(
+            "/api/admin/v1/upstream-oauth2-email-changes/{}/apply",
+            change.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CONFLICT);
+
+        // Once bob doesn't have it anymore, the change can be applied
+        let mut repo = state.repository().await.unwrap();
+        repo.user_email().remove(bob_email).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(format!(
+            "/api/admin/v1/upstream-oauth2-email-changes/{}/apply",
+            change.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["data"]["attributes"]["resolution"], "applied");
+
+        // The new address is the primary one, and the previous one is gone
+        let mut repo = state.repository().await.unwrap();
+        let primary = repo
+            .user_email()
+            .get_primary(&alice)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(primary.email, "alice@example.org");
+        assert!(primary.confirmed_at.is_some());
+        assert!(repo
+            .user_email()
+            .find(&alice, "alice@example.com")
+            .await
+            .unwrap()
+            .is_none());
+        repo.save().await.unwrap();
+
+        // It can't be applied twice
+        let request = Request::post(format!(
+            "/api/admin/v1/upstream-oauth2-email-changes/{}/apply",
+            change.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_apply_unknown(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let request = Request::post(
+            "/api/admin/v1/upstream-oauth2-email-changes/01040G2081040G2081040G2081/apply",
+        )
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "Upstream email change ID 01040G2081040G2081040G2081 not found"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::{AuditEventKind, UpstreamOAuthEmailChangeResolution};
+use mas_storage::BoxRng;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, UpstreamOAuthEmailChange},
+        params::UlidPathParam,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Upstream email change ID {0} not found")]
+    NotFound(Ulid),
+
+    #[error("Upstream email change ID {0} was already resolved")]
+    AlreadyResolved(Ulid),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::AlreadyResolved(_) => StatusCode::CONFLICT,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("dismissUpstreamOAuthEmailChange")
+        .summary("Dismiss an upstream email change")
+        .description(
+            "Keep the email addresses of the user as they are.
+The change won't be recorded again until the provider asserts yet another email address.",
+        )
+        .tag("upstream-oauth2")
+        .response_with::<200, Json<SingleResponse<UpstreamOAuthEmailChange>>, _>(|t| {
+            let [_, change] = UpstreamOAuthEmailChange::samples();
+            let id = change.id();
+            let response = SingleResponse::new(
+                change,
+                format!("/api/admin/v1/upstream-oauth2-email-changes/{id}/dismiss"),
+            );
+            t.description("The change was dismissed").example(response)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("Upstream email change was not found")
+                .example(response)
+        })
+        .response_with::<409, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::AlreadyResolved(Ulid::nil()));
+            t.description("The change was already resolved")
+                .example(response)
+        })
+}
+
+#[tracing::instrument(
+    name = "handler.admin.v1.upstream_oauth2_email_changes.dismiss",
+    skip_all,
+    err
+)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    id: UlidPathParam,
+) -> Result<Json<SingleResponse<UpstreamOAuthEmailChange>>, RouteError> {
+    let id = *id;
+    let change = repo
+        .upstream_oauth_email_change()
+        .lookup(id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    if !change.is_pending() {
+        return Err(RouteError::AlreadyResolved(id));
+    }
+
+    let change = repo
+        .upstream_oauth_email_change()
+        .resolve(
+            &clock,
+            change,
+            UpstreamOAuthEmailChangeResolution::Dismissed,
+        )
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(change.user_id),
+            serde_json::json!({
+                "action": "dismiss_upstream_oauth_email_change",
+                "upstream_oauth_email_change_id": id.to_string(),
+                "email": change.new_email,
+            }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new(
+        UpstreamOAuthEmailChange::from(change),
+        format!("/api/admin/v1/upstream-oauth2-email-changes/{id}/dismiss"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::{
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderTokenAuthMethod,
+    };
+    use mas_storage::{
+        upstream_oauth2::UpstreamOAuthProviderParams, user::UserEmailRepository, RepositoryAccess,
+    };
+    use oauth2_types::scope::{Scope, OPENID};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_dismiss(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: None,
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    authorization_endpoint_override: None,
+                    token_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let alice = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &alice)
+            .await
+            .unwrap();
+        let change = repo
+            .upstream_oauth_email_change()
+            .add(
+                &mut rng,
+                &state.clock,
+                &link,
+                &alice,
+                None,
+                Some("alice@example.com".to_owned()),
+                "alice@example.org".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(format!(
+            "/api/admin/v1/upstream-oauth2-email-changes/{}/dismiss",
+            change.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["data"]["attributes"]["resolution"], "dismissed");
+
+        // The email addresses of the user didn't change
+        let mut repo = state.repository().await.unwrap();
+        assert!(repo.user_email().all(&alice).await.unwrap().is_empty());
+        repo.save().await.unwrap();
+
+        // It can't be dismissed twice
+        let request = Request::post(format!(
+            "/api/admin/v1/upstream-oauth2-email-changes/{}/dismiss",
+            change.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use hyper::StatusCode;
+use mas_storage::{pagination::Count, upstream_oauth2::UpstreamOAuthEmailChangeFilter, Page};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, UpstreamOAuthEmailChange},
+        params::{CountMode, Pagination},
+        response::{ErrorResponse, PaginatedResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum UpstreamOAuthEmailChangeStatus {
+    Pending,
+    Resolved,
+}
+
+impl std::fmt::Display for UpstreamOAuthEmailChangeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Resolved => write!(f, "resolved"),
+        }
+    }
+}
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "UpstreamOAuthEmailChangeFilter")]
+#[aide(input_with = "Query<FilterParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct FilterParams {
+    /// Retrieve the items for the given user
+    #[serde(rename = "filter[user]")]
+    #[schemars(with = "Option<crate::admin::schema::Ulid>")]
+    user: Option<Ulid>,
+
+    /// Retrieve the items with the given status
+    ///
+    /// Defaults to retrieve all changes, including resolved ones.
+    ///
+    /// * `pending`: Only retrieve the changes waiting for a review
+    ///
+    /// * `resolved`: Only retrieve the changes which were applied or dismissed
+    #[serde(rename = "filter[status]")]
+    status: Option<UpstreamOAuthEmailChangeStatus>,
+}
+
+impl std::fmt::Display for FilterParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = '?';
+
+        if let Some(user) = self.user {
+            write!(f, "{sep}filter[user]={user}")?;
+            sep = '&';
+        }
+        if let Some(status) = self.status {
+            write!(f, "{sep}filter[status]={status}")?;
+            sep = '&';
+        }
+
+        let _ = sep;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("User ID {0} not found")]
+    UserNotFound(Ulid),
+
+    #[error("Invalid filter parameters")]
+    InvalidFilter(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::UserNotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("listUpstreamOAuthEmailChanges")
+        .summary("List upstream email changes")
+        .description(
+            "Retrieve a list of changes of the email address asserted by upstream providers for linked accounts, with the oldest first.
+Those are recorded when a provider is configured to review the changes, or when the new address already belongs to another account.
+Use the `filter[status]` parameter to only retrieve the changes waiting for a review.",
+        )
+        .tag("upstream-oauth2")
+        .response_with::<200, Json<PaginatedResponse<UpstreamOAuthEmailChange>>, _>(|t| {
+            let changes = UpstreamOAuthEmailChange::samples();
+            let pagination = mas_storage::Pagination::first(changes.len());
+            let page = Page {
+                edges: changes.into(),
+                has_next_page: true,
+                has_previous_page: false,
+            };
+
+            t.description("Paginated response of upstream email changes")
+                .example(PaginatedResponse::new(
+                    page,
+                    pagination,
+                    CountMode::Exact,
+                    Count::Exact(42),
+                    UpstreamOAuthEmailChange::PATH,
+                ))
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::UserNotFound(Ulid::nil()));
+            t.description("User was not found").example(response)
+        })
+}
+
+#[tracing::instrument(
+    name = "handler.admin.v1.upstream_oauth2_email_changes.list",
+    skip_all,
+    err
+)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    Pagination(pagination, count_mode): Pagination,
+    params: FilterParams,
+) -> Result<Json<PaginatedResponse<UpstreamOAuthEmailChange>>, RouteError> {
+    let base = format!("{path}{params}", path = UpstreamOAuthEmailChange::PATH);
+    let filter = UpstreamOAuthEmailChangeFilter::new();
+
+    // Load the user from the filter
+    let user = if let Some(user_id) = params.user {
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .ok_or(RouteError::UserNotFound(user_id))?;
+
+        Some(user)
+    } else {
+        None
+    };
+
+    let filter = match &user {
+        Some(user) => filter.for_user(user),
+        None => filter,
+    };
+
+    let filter = match params.status {
+        Some(UpstreamOAuthEmailChangeStatus::Pending) => filter.pending_only(),
+        Some(UpstreamOAuthEmailChangeStatus::Resolved) => filter.resolved_only(),
+        None => filter,
+    };
+
+    let page = repo
+        .upstream_oauth_email_change()
+        .list(filter, pagination)
+        .await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.upstream_oauth_email_change().count(filter).await?),
+        CountMode::Estimated => {
+            repo.upstream_oauth_email_change()
+                .estimate_count(filter)
+                .await?
+        }
+    };
+
+    Ok(Json(PaginatedResponse::new(
+        page.map(UpstreamOAuthEmailChange::from),
+        pagination,
+        count_mode,
+        count,
+        &base,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::{
+        UpstreamOAuthEmailChangeResolution, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderTokenAuthMethod,
+    };
+    use mas_storage::{upstream_oauth2::UpstreamOAuthProviderParams, RepositoryAccess};
+    use oauth2_types::scope::{Scope, OPENID};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_list(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: None,
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    authorization_endpoint_override: None,
+                    token_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let alice = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let bob = repo
+            .user()
+            .add(&mut rng, &state.clock, "bob".to_owned())
+            .await
+            .unwrap();
+
+        for (user, subject) in [(&alice, "alice"), (&bob, "bob")] {
+            let link = repo
+                .upstream_oauth_link()
+                .add(&mut rng, &state.clock, &provider, subject.to_owned())
+                .await
+                .unwrap();
+            repo.upstream_oauth_link()
+                .associate_to_user(&link, user)
+                .await
+                .unwrap();
+
+            let change = repo
+                .upstream_oauth_email_change()
+                .add(
+                    &mut rng,
+                    &state.clock,
+                    &link,
+                    user,
+                    None,
+                    Some(format!("{subject}@example.com")),
+                    format!("{subject}@example.org"),
+                )
+                .await
+                .unwrap();
+
+            if subject == "bob" {
+                repo.upstream_oauth_email_change()
+                    .resolve(
+                        &state.clock,
+                        change,
+                        UpstreamOAuthEmailChangeResolution::Dismissed,
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/upstream-oauth2-email-changes")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 2);
+        assert_eq!(body["data"][0]["type"], "upstream-oauth2-email-change");
+
+        // Only the pending changes
+        let request =
+            Request::get("/api/admin/v1/upstream-oauth2-email-changes?filter[status]=pending")
+                .bearer(&token)
+                .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+        assert_eq!(
+            body["data"][0]["attributes"]["new_email"],
+            "alice@example.org"
+        );
+        assert_eq!(
+            body["data"][0]["attributes"]["user_id"],
+            alice.id.to_string()
+        );
+
+        // Only the changes of bob
+        let request = Request::get(format!(
+            "/api/admin/v1/upstream-oauth2-email-changes?filter[user]={}",
+            bob.id
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["meta"]["count"], 1);
+        assert_eq!(body["data"][0]["attributes"]["resolution"], "dismissed");
+
+        // Unknown user
+        let request = Request::get(
+            "/api/admin/v1/upstream-oauth2-email-changes?filter[user]=01040G2081040G2081040G2081",
+        )
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
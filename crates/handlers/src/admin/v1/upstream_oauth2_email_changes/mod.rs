@@ -0,0 +1,14 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+mod apply;
+mod dismiss;
+mod list;
+
+pub use self::{
+    apply::{doc as apply_doc, handler as apply},
+    dismiss::{doc as dismiss_doc, handler as dismiss},
+    list::{doc as list_doc, handler as list},
+};
@@ -0,0 +1,389 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::BTreeMap;
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{
+    extract::{rejection::QueryRejection, Query},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use mas_data_model::UpstreamOAuthFunnelStageReport;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    admin::{call_context::CallContext, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "UpstreamOAuthFunnelFilter")]
+#[aide(input_with = "Query<FilterParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct FilterParams {
+    /// Only count the stages reached at or after the given time
+    #[serde(rename = "filter[since]")]
+    since: Option<DateTime<Utc>>,
+
+    /// Only count the stages reached before the given time
+    #[serde(rename = "filter[until]")]
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Invalid filter parameters")]
+    InvalidFilter(#[from] QueryRejection),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidFilter(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// A stage of the signup funnel
+#[derive(Serialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FunnelStage {
+    /// The user was redirected to the provider
+    Redirected,
+
+    /// The provider redirected the user back with an authorization code
+    CallbackReceived,
+
+    /// The user was shown the registration form, with the attributes mapped
+    /// from the claims of the provider
+    ClaimsMapped,
+
+    /// The user account was created
+    UserCreated,
+
+    /// The user was provisioned on the homeserver
+    Provisioned,
+
+    /// A client got its first token on behalf of the user
+    FirstTokenIssued,
+}
+
+impl From<mas_data_model::UpstreamOAuthFunnelStage> for FunnelStage {
+    fn from(stage: mas_data_model::UpstreamOAuthFunnelStage) -> Self {
+        use mas_data_model::UpstreamOAuthFunnelStage as Stage;
+        match stage {
+            Stage::Redirected => Self::Redirected,
+            Stage::CallbackReceived => Self::CallbackReceived,
+            Stage::ClaimsMapped => Self::ClaimsMapped,
+            Stage::UserCreated => Self::UserCreated,
+            Stage::Provisioned => Self::Provisioned,
+            Stage::FirstTokenIssued => Self::FirstTokenIssued,
+        }
+    }
+}
+
+/// How many times a stage of the funnel was reached, and how many times users
+/// dropped off at that stage
+#[derive(Serialize, JsonSchema)]
+pub struct FunnelStageReport {
+    /// The stage of the funnel
+    stage: FunnelStage,
+
+    /// How many times the stage was reached
+    reached: u64,
+
+    /// How many times users dropped off at that stage, for any reason
+    dropped: u64,
+
+    /// How many times users dropped off at that stage, by reason
+    dropoffs: BTreeMap<String, u64>,
+}
+
+impl From<UpstreamOAuthFunnelStageReport> for FunnelStageReport {
+    fn from(report: UpstreamOAuthFunnelStageReport) -> Self {
+        Self {
+            stage: report.stage.into(),
+            reached: report.reached,
+            dropped: report.dropped(),
+            dropoffs: report.dropoffs,
+        }
+    }
+}
+
+/// The signup funnel of an upstream OAuth 2.0 provider
+#[derive(Serialize, JsonSchema)]
+pub struct ProviderFunnel {
+    /// The ID of the provider
+    #[schemars(with = "crate::admin::schema::Ulid")]
+    id: Ulid,
+
+    /// The issuer of the provider
+    issuer: String,
+
+    /// The name of the provider, as shown to users
+    #[serde(skip_serializing_if = "Option::is_none")]
+    human_name: Option<String>,
+
+    /// The stages of the funnel, in the order users go through them
+    stages: Vec<FunnelStageReport>,
+}
+
+/// The signup funnels of the upstream OAuth 2.0 providers
+#[derive(Serialize, JsonSchema)]
+pub struct UpstreamOAuthFunnel {
+    /// The funnel of each enabled provider
+    providers: Vec<ProviderFunnel>,
+}
+
+impl UpstreamOAuthFunnel {
+    fn sample() -> Self {
+        let stage = |stage, reached, dropoffs: &[(&str, u64)]| FunnelStageReport {
+            stage,
+            reached,
+            dropped: dropoffs.iter().map(|(_, count)| count).sum(),
+            dropoffs: dropoffs
+                .iter()
+                .map(|(reason, count)| ((*reason).to_owned(), *count))
+                .collect(),
+        };
+
+        Self {
+            providers: vec![ProviderFunnel {
+                id: Ulid::from_bytes([0x01; 16]),
+                issuer: "https://accounts.google.com".to_owned(),
+                human_name: Some("Google".to_owned()),
+                stages: vec![
+                    stage(FunnelStage::Redirected, 120, &[]),
+                    stage(FunnelStage::CallbackReceived, 104, &[("access_denied", 9)]),
+                    stage(
+                        FunnelStage::ClaimsMapped,
+                        61,
+                        &[("required_claim_missing", 2), ("username_unavailable", 3)],
+                    ),
+                    stage(FunnelStage::UserCreated, 52, &[]),
+                    stage(FunnelStage::Provisioned, 52, &[]),
+                    stage(FunnelStage::FirstTokenIssued, 49, &[]),
+                ],
+            }],
+        }
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("getUpstreamOAuthFunnel")
+        .summary("Get the signup funnel of the upstream OAuth 2.0 providers")
+        .description(
+            "Count, for each enabled upstream OAuth 2.0 provider, how many times users reached each stage of the signup funnel, and how many dropped off at each stage and why.
+Users who log in with an existing account only go through the first two stages.
+The events are kept for 90 days.",
+        )
+        .tag("upstream-oauth2")
+        .response_with::<200, Json<UpstreamOAuthFunnel>, _>(|t| {
+            t.description("The signup funnel of each provider")
+                .example(UpstreamOAuthFunnel::sample())
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.upstream_oauth2_funnel.get", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    params: FilterParams,
+) -> Result<Json<UpstreamOAuthFunnel>, RouteError> {
+    let providers = repo.upstream_oauth_provider().all_enabled().await?;
+
+    let mut funnels = Vec::with_capacity(providers.len());
+    for provider in providers {
+        let stages = repo
+            .upstream_oauth_funnel()
+            .report(&provider, params.since, params.until)
+            .await?;
+
+        funnels.push(ProviderFunnel {
+            id: provider.id,
+            issuer: provider.issuer,
+            human_name: provider.human_name,
+            stages: stages.into_iter().map(FunnelStageReport::from).collect(),
+        });
+    }
+
+    Ok(Json(UpstreamOAuthFunnel { providers: funnels }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::{
+        UpstreamOAuthFunnelStage, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderTokenAuthMethod,
+    };
+    use mas_storage::{upstream_oauth2::UpstreamOAuthProviderParams, Clock, RepositoryAccess};
+    use oauth2_types::scope::{Scope, OPENID};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_funnel(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: Some("Example Ltd.".to_owned()),
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    authorization_endpoint_override: None,
+                    token_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // Two users are redirected, one of them declines at the provider
+        for _ in 0..2 {
+            repo.upstream_oauth_funnel()
+                .record(
+                    &mut rng,
+                    &state.clock,
+                    provider.id,
+                    None,
+                    UpstreamOAuthFunnelStage::Redirected,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &state.clock,
+                provider.id,
+                None,
+                UpstreamOAuthFunnelStage::CallbackReceived,
+                Some("access_denied"),
+            )
+            .await
+            .unwrap();
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &state.clock,
+                provider.id,
+                None,
+                UpstreamOAuthFunnelStage::CallbackReceived,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get("/api/admin/v1/upstream-oauth2-funnel")
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["providers"][0]["id"], provider.id.to_string());
+        insta::assert_json_snapshot!(body["providers"][0]["stages"], @r###"
+        [
+          {
+            "stage": "redirected",
+            "reached": 2,
+            "dropped": 0,
+            "dropoffs": {}
+          },
+          {
+            "stage": "callback_received",
+            "reached": 1,
+            "dropped": 1,
+            "dropoffs": {
+              "access_denied": 1
+            }
+          },
+          {
+            "stage": "claims_mapped",
+            "reached": 0,
+            "dropped": 0,
+            "dropoffs": {}
+          },
+          {
+            "stage": "user_created",
+            "reached": 0,
+            "dropped": 0,
+            "dropoffs": {}
+          },
+          {
+            "stage": "provisioned",
+            "reached": 0,
+            "dropped": 0,
+            "dropoffs": {}
+          },
+          {
+            "stage": "first_token_issued",
+            "reached": 0,
+            "dropped": 0,
+            "dropoffs": {}
+          }
+        ]
+        "###);
+
+        // Events recorded before the start of the window are not counted
+        let since = (state.clock.now() + chrono::Duration::minutes(1))
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let request = Request::get(format!(
+            "/api/admin/v1/upstream-oauth2-funnel?filter[since]={since}"
+        ))
+        .bearer(&token)
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["providers"][0]["stages"][0]["reached"], 0);
+        assert_eq!(body["providers"][0]["stages"][1]["dropped"], 0);
+    }
+}
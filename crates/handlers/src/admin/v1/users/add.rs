@@ -7,9 +7,11 @@
 use aide::{transform::TransformOperation, NoApi, OperationIo};
 use axum::{extract::State, response::IntoResponse, Json};
 use hyper::StatusCode;
+use mas_data_model::{AuditEventKind, SiteConfig};
 use mas_matrix::BoxHomeserverConnection;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob},
+    honeytoken::HoneytokenRepository,
+    job::{DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob},
     BoxRng,
 };
 use schemars::JsonSchema;
@@ -72,6 +74,9 @@ pub enum RouteError {
 
     #[error("Username is reserved by the homeserver")]
     UsernameReserved,
+
+    #[error("Unknown homeserver {0:?}")]
+    UnknownHomeserver(String),
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -81,7 +86,7 @@ impl IntoResponse for RouteError {
         let error = ErrorResponse::from_error(&self);
         let status = match self {
             Self::Internal(_) | Self::Homeserver(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::UsernameNotValid => StatusCode::BAD_REQUEST,
+            Self::UsernameNotValid | Self::UnknownHomeserver(_) => StatusCode::BAD_REQUEST,
             Self::UserAlreadyExists | Self::UsernameReserved => StatusCode::CONFLICT,
         };
         (status, Json(error)).into_response()
@@ -102,6 +107,11 @@ pub struct Request {
     /// tokens (like with admin access) for them
     #[serde(default)]
     skip_homeserver_check: bool,
+
+    /// The server name of the homeserver the user belongs to. Defaults to the
+    /// main homeserver.
+    #[serde(default)]
+    homeserver: Option<String>,
 }
 
 pub fn doc(operation: TransformOperation) -> TransformOperation {
@@ -132,13 +142,37 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 #[tracing::instrument(name = "handler.admin.v1.users.add", skip_all, err)]
 pub async fn handler(
     CallContext {
-        mut repo, clock, ..
+        mut repo,
+        clock,
+        audit,
+        ..
     }: CallContext,
     NoApi(mut rng): NoApi<BoxRng>,
     State(homeserver): State<BoxHomeserverConnection>,
+    State(site_config): State<SiteConfig>,
     Json(params): Json<Request>,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
-    if repo.user().exists(&params.username).await? {
+    let user_homeserver = match params.homeserver {
+        Some(server_name) if site_config.is_additional_homeserver(&server_name) => {
+            Some(server_name)
+        }
+        Some(server_name) if server_name != site_config.server_name => {
+            return Err(RouteError::UnknownHomeserver(server_name));
+        }
+        _ => None,
+    };
+
+    // Decoy accounts must not be taken over by a real user
+    if repo
+        .user()
+        .exists(user_homeserver.as_deref().into(), &params.username)
+        .await?
+        || repo
+            .honeytoken()
+            .find_account(&params.username)
+            .await?
+            .is_some()
+    {
         return Err(RouteError::UserAlreadyExists);
     }
 
@@ -149,7 +183,7 @@ pub async fn handler(
 
     // Ask the homeserver if the username is available
     let homeserver_available = homeserver
-        .is_localpart_available(&params.username)
+        .is_localpart_available_on(user_homeserver.as_deref(), &params.username)
         .await
         .map_err(RouteError::Homeserver)?;
 
@@ -162,12 +196,35 @@ pub async fn handler(
         warn!("Skipped homeserver check for username {}", params.username);
     }
 
-    let user = repo.user().add(&mut rng, &clock, params.username).await?;
+    let user = repo
+        .user()
+        .add_for_homeserver(
+            &mut rng,
+            &clock,
+            user_homeserver.as_deref().into(),
+            params.username,
+        )
+        .await?;
 
     repo.job()
         .schedule_job(ProvisionUserJob::new(&user))
         .await?;
 
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_registered(&mut rng, &clock, &user))
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "add_user" }),
+        )
+        .await?;
+
     repo.save().await?;
 
     Ok(Json(SingleResponse::new_canonical(User::from(user))))
@@ -176,6 +233,7 @@ pub async fn handler(
 #[cfg(test)]
 mod tests {
     use hyper::{Request, StatusCode};
+    use mas_data_model::AdditionalHomeserver;
     use mas_storage::{user::UserRepository, RepositoryAccess};
     use sqlx::PgPool;
 
@@ -213,6 +271,84 @@ mod tests {
         assert_eq!(user.username, "alice");
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_add_user_homeserver(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state
+            .site_config
+            .additional_homeservers
+            .push(AdditionalHomeserver {
+                server_name: "example.org".to_owned(),
+                hosts: Vec::new(),
+                clients: Vec::new(),
+            });
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let request = Request::post("/api/admin/v1/users")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "username": "alice",
+                "homeserver": "example.org",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["data"]["attributes"]["homeserver"], "example.org");
+
+        // Users of the main homeserver don't have one set
+        let request = Request::post("/api/admin/v1/users")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "username": "bob",
+                "homeserver": state.site_config.server_name,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["data"]["attributes"]["homeserver"],
+            serde_json::Value::Null
+        );
+
+        // Usernames are only unique per homeserver
+        let request = Request::post("/api/admin/v1/users")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "username": "alice",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["data"]["attributes"]["homeserver"],
+            serde_json::Value::Null
+        );
+
+        // Unknown homeservers are rejected
+        let request = Request::post("/api/admin/v1/users")
+            .bearer(&token)
+            .json(serde_json::json!({
+                "username": "charlie",
+                "homeserver": "example.net",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "Unknown homeserver \"example.net\""
+        );
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_add_user_invalid_username(pool: PgPool) {
         setup();
@@ -5,8 +5,15 @@
 // Please see LICENSE in the repository root for full details.
 
 use aide::{transform::TransformOperation, OperationIo};
-use axum::{extract::Path, response::IntoResponse, Json};
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_macros::FromRequestParts;
 use hyper::StatusCode;
+use mas_data_model::SiteConfig;
+use mas_storage::user::UserHomeserver;
 use schemars::JsonSchema;
 use serde::Deserialize;
 
@@ -16,6 +23,7 @@ use crate::{
         model::User,
         response::{ErrorResponse, SingleResponse},
     },
+    homeserver::user_homeserver,
     impl_from_error_for_route,
 };
 
@@ -27,6 +35,9 @@ pub enum RouteError {
 
     #[error("User with username {0:?} not found")]
     NotFound(String),
+
+    #[error("Invalid query parameters")]
+    InvalidQuery(#[from] QueryRejection),
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -37,6 +48,7 @@ impl IntoResponse for RouteError {
         let status = match self {
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidQuery(_) => StatusCode::BAD_REQUEST,
         };
         (status, Json(error)).into_response()
     }
@@ -48,6 +60,16 @@ pub struct UsernamePathParam {
     username: String,
 }
 
+#[derive(FromRequestParts, Deserialize, JsonSchema, OperationIo)]
+#[serde(rename = "UserByUsernameQuery")]
+#[aide(input_with = "Query<QueryParams>")]
+#[from_request(via(Query), rejection(RouteError))]
+pub struct QueryParams {
+    /// The server name of the homeserver the user belongs to. Defaults to the
+    /// main homeserver.
+    homeserver: Option<String>,
+}
+
 pub fn doc(operation: TransformOperation) -> TransformOperation {
     operation
         .id("getUserByUsername")
@@ -68,12 +90,20 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 #[tracing::instrument(name = "handler.admin.v1.users.by_username", skip_all, err)]
 pub async fn handler(
     CallContext { mut repo, .. }: CallContext,
+    State(site_config): State<SiteConfig>,
     Path(UsernamePathParam { username }): Path<UsernamePathParam>,
+    params: QueryParams,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
     let self_path = format!("/api/admin/v1/users/by-username/{username}");
+    let homeserver = params
+        .homeserver
+        .as_deref()
+        .map_or(UserHomeserver::Main, |server_name| {
+            user_homeserver(&site_config, server_name)
+        });
     let user = repo
         .user()
-        .find_by_username(&username)
+        .find_by_username(homeserver, &username)
         .await?
         .ok_or(RouteError::NotFound(username))?;
 
@@ -4,10 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use aide::{transform::TransformOperation, OperationIo};
+use aide::{transform::TransformOperation, NoApi, OperationIo};
 use axum::{response::IntoResponse, Json};
 use hyper::StatusCode;
-use mas_storage::job::{DeactivateUserJob, JobRepositoryExt};
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    job::{DeactivateUserJob, JobRepositoryExt},
+    BoxRng,
+};
 use tracing::info;
 use ulid::Ulid;
 
@@ -49,7 +53,8 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
         .id("deactivateUser")
         .summary("Deactivate a user")
         .description("Calling this endpoint will lock and deactivate the user, preventing them from doing any action.
-This invalidates any existing session, and will ask the homeserver to make them leave all rooms.")
+This invalidates any existing session, and will ask the homeserver to make them leave all rooms.
+The data of the user is also erased from the homeserver, unless they are under legal hold.")
         .tag("user")
         .response_with::<200, Json<SingleResponse<User>>, _>(|t| {
             // In the samples, the third user is the one locked
@@ -67,8 +72,12 @@ This invalidates any existing session, and will ask the homeserver to make them
 #[tracing::instrument(name = "handler.admin.v1.users.deactivate", skip_all, err)]
 pub async fn handler(
     CallContext {
-        mut repo, clock, ..
+        mut repo,
+        clock,
+        audit,
+        ..
     }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
     id: UlidPathParam,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
     let id = *id;
@@ -87,6 +96,17 @@ pub async fn handler(
         .schedule_job(DeactivateUserJob::new(&user, true))
         .await?;
 
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "deactivate" }),
+        )
+        .await?;
+
     repo.save().await?;
 
     Ok(Json(SingleResponse::new(
@@ -6,13 +6,18 @@
 
 use aide::{transform::TransformOperation, OperationIo};
 use axum::{
-    extract::{rejection::QueryRejection, Query},
+    extract::{rejection::QueryRejection, Query, State},
     response::IntoResponse,
     Json,
 };
 use axum_macros::FromRequestParts;
 use hyper::StatusCode;
-use mas_storage::{user::UserFilter, Page};
+use mas_data_model::SiteConfig;
+use mas_storage::{
+    pagination::Count,
+    user::{UserFilter, UserHomeserver},
+    Page,
+};
 use schemars::JsonSchema;
 use serde::Deserialize;
 
@@ -20,7 +25,7 @@ use crate::{
     admin::{
         call_context::CallContext,
         model::{Resource, User},
-        params::Pagination,
+        params::{CountMode, Pagination},
         response::{ErrorResponse, PaginatedResponse},
     },
     impl_from_error_for_route,
@@ -60,6 +65,22 @@ pub struct FilterParams {
     /// * `locked`: Only retrieve locked users
     #[serde(rename = "filter[status]")]
     status: Option<UserStatus>,
+
+    /// Retrieve users under (or not under) legal hold
+    #[serde(rename = "filter[legal_hold]")]
+    legal_hold: Option<bool>,
+
+    /// Retrieve users of the homeserver with the given server name
+    #[serde(rename = "filter[homeserver]")]
+    homeserver: Option<String>,
+
+    /// Retrieve bot accounts (or human accounts)
+    #[serde(rename = "filter[bot]")]
+    bot: Option<bool>,
+
+    /// Retrieve guest accounts (or non-guest accounts)
+    #[serde(rename = "filter[guest]")]
+    guest: Option<bool>,
 }
 
 impl std::fmt::Display for FilterParams {
@@ -74,6 +95,22 @@ impl std::fmt::Display for FilterParams {
             write!(f, "{sep}filter[status]={status}")?;
             sep = '&';
         }
+        if let Some(legal_hold) = self.legal_hold {
+            write!(f, "{sep}filter[legal_hold]={legal_hold}")?;
+            sep = '&';
+        }
+        if let Some(homeserver) = &self.homeserver {
+            write!(f, "{sep}filter[homeserver]={homeserver}")?;
+            sep = '&';
+        }
+        if let Some(bot) = self.bot {
+            write!(f, "{sep}filter[bot]={bot}")?;
+            sep = '&';
+        }
+        if let Some(guest) = self.guest {
+            write!(f, "{sep}filter[guest]={guest}")?;
+            sep = '&';
+        }
 
         let _ = sep;
         Ok(())
@@ -118,14 +155,21 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
             };
 
             t.description("Paginated response of users")
-                .example(PaginatedResponse::new(page, pagination, 42, User::PATH))
+                .example(PaginatedResponse::new(
+                    page,
+                    pagination,
+                    CountMode::Exact,
+                    Count::Exact(42),
+                    User::PATH,
+                ))
         })
 }
 
 #[tracing::instrument(name = "handler.admin.v1.users.list", skip_all, err)]
 pub async fn handler(
     CallContext { mut repo, .. }: CallContext,
-    Pagination(pagination): Pagination,
+    Pagination(pagination, count_mode): Pagination,
+    State(site_config): State<SiteConfig>,
     params: FilterParams,
 ) -> Result<Json<PaginatedResponse<User>>, RouteError> {
     let base = format!("{path}{params}", path = User::PATH);
@@ -143,12 +187,42 @@ pub async fn handler(
         None => filter,
     };
 
+    let filter = match params.legal_hold {
+        Some(true) => filter.legal_hold_only(),
+        Some(false) => filter.no_legal_hold_only(),
+        None => filter,
+    };
+
+    let filter = match params.homeserver.as_deref() {
+        Some(server_name) if server_name == site_config.server_name => {
+            filter.for_homeserver(UserHomeserver::Main)
+        }
+        Some(server_name) => filter.for_homeserver(UserHomeserver::Additional(server_name)),
+        None => filter,
+    };
+
+    let filter = match params.bot {
+        Some(true) => filter.bots_only(),
+        Some(false) => filter.humans_only(),
+        None => filter,
+    };
+
+    let filter = match params.guest {
+        Some(true) => filter.guests_only(),
+        Some(false) => filter.non_guests_only(),
+        None => filter,
+    };
+
     let page = repo.user().list(filter, pagination).await?;
-    let count = repo.user().count(filter).await?;
+    let count = match count_mode {
+        CountMode::Exact => Count::Exact(repo.user().count(filter).await?),
+        CountMode::Estimated => repo.user().estimate_count(filter).await?,
+    };
 
     Ok(Json(PaginatedResponse::new(
         page.map(User::from),
         pagination,
+        count_mode,
         count,
         &base,
     )))
@@ -4,9 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use aide::{transform::TransformOperation, OperationIo};
+use aide::{transform::TransformOperation, NoApi, OperationIo};
 use axum::{response::IntoResponse, Json};
 use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    BoxRng,
+};
 use ulid::Ulid;
 
 use crate::{
@@ -65,8 +70,12 @@ This DOES NOT invalidate any existing session, meaning that all their existing s
 #[tracing::instrument(name = "handler.admin.v1.users.lock", skip_all, err)]
 pub async fn handler(
     CallContext {
-        mut repo, clock, ..
+        mut repo,
+        clock,
+        audit,
+        ..
     }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
     id: UlidPathParam,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
     let id = *id;
@@ -78,8 +87,23 @@ pub async fn handler(
 
     if user.locked_at.is_none() {
         user = repo.user().lock(&clock, user).await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_updated(&mut rng, &clock, &user))
+            .await?;
     }
 
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "lock" }),
+        )
+        .await?;
+
     repo.save().await?;
 
     Ok(Json(SingleResponse::new(
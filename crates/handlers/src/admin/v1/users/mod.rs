@@ -10,8 +10,12 @@ mod deactivate;
 mod get;
 mod list;
 mod lock;
+mod place_legal_hold;
+mod release_legal_hold;
 mod set_admin;
+mod set_bot;
 mod set_password;
+mod terms;
 mod unlock;
 
 pub use self::{
@@ -21,7 +25,15 @@ pub use self::{
     get::{doc as get_doc, handler as get},
     list::{doc as list_doc, handler as list},
     lock::{doc as lock_doc, handler as lock},
+    place_legal_hold::{doc as place_legal_hold_doc, handler as place_legal_hold},
+    release_legal_hold::{doc as release_legal_hold_doc, handler as release_legal_hold},
     set_admin::{doc as set_admin_doc, handler as set_admin},
+    set_bot::{doc as set_bot_doc, handler as set_bot},
     set_password::{doc as set_password_doc, handler as set_password},
+    terms::{doc as terms_doc, handler as terms},
     unlock::{doc as unlock_doc, handler as unlock},
 };
+
+/// The scope needed, in addition to `urn:mas:admin`, to place users under legal
+/// hold and to release them
+const LEGAL_HOLD_SCOPE: &str = "urn:mas:admin:legal-hold";
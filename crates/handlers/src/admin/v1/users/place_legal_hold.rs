@@ -0,0 +1,236 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
+use mas_storage::BoxRng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use ulid::Ulid;
+
+use super::LEGAL_HOLD_SCOPE;
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, User},
+        params::UlidPathParam,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Missing {LEGAL_HOLD_SCOPE} scope")]
+    MissingScope,
+
+    #[error("User ID {0} not found")]
+    NotFound(Ulid),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::MissingScope => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// # JSON payload for the `POST /api/admin/v1/users/:id/place-legal-hold` endpoint
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename = "UserPlaceLegalHoldRequest")]
+pub struct Request {
+    /// Why the user is placed under legal hold, for example a case reference.
+    /// This is recorded in the audit log.
+    reason: String,
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("placeLegalHold")
+        .summary("Place a user under legal hold")
+        .description("While under legal hold, the data of the user is preserved: it is not pruned from the audit log, and deactivating the user doesn't erase it from the homeserver.
+This requires the `urn:mas:admin:legal-hold` scope, in addition to the `urn:mas:admin` scope.")
+        .tag("user")
+        .response_with::<200, Json<SingleResponse<User>>, _>(|t| {
+            // In the samples, the third user is the one under legal hold
+            let [_alice, _bob, charlie, ..] = User::samples();
+            let id = charlie.id();
+            let response =
+                SingleResponse::new(charlie, format!("/api/admin/v1/users/{id}/place-legal-hold"));
+            t.description("User was placed under legal hold").example(response)
+        })
+        .response_with::<403, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::MissingScope);
+            t.description("The session is not allowed to manage legal holds")
+                .example(response)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("User ID not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.users.place_legal_hold", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        session,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    id: UlidPathParam,
+    Json(params): Json<Request>,
+) -> Result<Json<SingleResponse<User>>, RouteError> {
+    if !session.scope.contains(LEGAL_HOLD_SCOPE) {
+        return Err(RouteError::MissingScope);
+    }
+
+    let id = *id;
+    let user = repo
+        .user()
+        .lookup(id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    let user = repo.user().place_legal_hold(&clock, user).await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "place_legal_hold", "reason": params.reason }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new(
+        User::from(user),
+        format!("/api/admin/v1/users/{id}/place-legal-hold"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::AuditEventKind;
+    use mas_storage::{
+        audit::{AuditEventFilter, AuditEventRepository},
+        user::UserRepository,
+        Clock, Pagination, RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_place_legal_hold(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state
+            .token_with_scope("urn:mas:admin urn:mas:admin:legal-hold")
+            .await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(format!("/api/admin/v1/users/{}/place-legal-hold", user.id))
+            .bearer(&token)
+            .json(serde_json::json!({ "reason": "Case 1234" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["data"]["attributes"]["legal_hold_at"],
+            serde_json::json!(state.clock.now())
+        );
+
+        // The action should be in the audit log, with the reason
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        assert!(user.is_under_legal_hold());
+
+        let filter = AuditEventFilter::new()
+            .for_user(&user)
+            .with_kind(AuditEventKind::AdminAction);
+        let page = repo
+            .audit_event()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        assert_eq!(page.edges[0].details["action"], "place_legal_hold");
+        assert_eq!(page.edges[0].details["reason"], "Case 1234");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_place_legal_hold_missing_scope(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(format!("/api/admin/v1/users/{}/place-legal-hold", user.id))
+            .bearer(&token)
+            .json(serde_json::json!({ "reason": "Case 1234" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        assert!(!user.is_under_legal_hold());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_place_legal_hold_unknown_user(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state
+            .token_with_scope("urn:mas:admin urn:mas:admin:legal-hold")
+            .await;
+
+        let request =
+            Request::post("/api/admin/v1/users/01040G2081040G2081040G2081/place-legal-hold")
+                .bearer(&token)
+                .json(serde_json::json!({ "reason": "Case 1234" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["errors"][0]["title"],
+            "User ID 01040G2081040G2081040G2081 not found"
+        );
+    }
+}
@@ -4,9 +4,14 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use aide::{transform::TransformOperation, OperationIo};
+use aide::{transform::TransformOperation, NoApi, OperationIo};
 use axum::{response::IntoResponse, Json};
 use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    BoxRng,
+};
 use schemars::JsonSchema;
 use serde::Deserialize;
 use ulid::Ulid;
@@ -73,7 +78,13 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.set_admin", skip_all, err)]
 pub async fn handler(
-    CallContext { mut repo, .. }: CallContext,
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
     id: UlidPathParam,
     Json(params): Json<Request>,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
@@ -84,11 +95,29 @@ pub async fn handler(
         .await?
         .ok_or(RouteError::NotFound(id))?;
 
+    let changed = user.can_request_admin != params.admin;
     let user = repo
         .user()
         .set_can_request_admin(user, params.admin)
         .await?;
 
+    if changed {
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_updated(&mut rng, &clock, &user))
+            .await?;
+    }
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "set_admin" }),
+        )
+        .await?;
+
     repo.save().await?;
 
     Ok(Json(SingleResponse::new(
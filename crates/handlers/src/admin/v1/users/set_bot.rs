@@ -0,0 +1,230 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, NoApi, OperationIo};
+use axum::{response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    BoxRng,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use ulid::Ulid;
+
+use crate::{
+    admin::{
+        call_context::CallContext,
+        model::{Resource, User},
+        params::UlidPathParam,
+        response::{ErrorResponse, SingleResponse},
+    },
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("User ID {0} not found")]
+    NotFound(Ulid),
+
+    #[error("Owner user ID {0} not found")]
+    OwnerNotFound(Ulid),
+
+    #[error("User ID {0} is a bot, it can't own other bots")]
+    OwnerIsBot(Ulid),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::OwnerNotFound(_) | Self::OwnerIsBot(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// # JSON payload for the `POST /api/admin/v1/users/:id/set-bot` endpoint
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename = "UserSetBotRequest")]
+pub struct Request {
+    /// The ID of the human user who owns the bot, and can manage its sessions.
+    /// If null, only admins can manage the sessions of the bot.
+    #[schemars(with = "Option<crate::admin::schema::Ulid>")]
+    owner_id: Option<Ulid>,
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("userSetBot")
+        .summary("Turn a user into a bot account")
+        .description("Bot accounts can't log in with a password, their sessions don't expire for inactivity, and their tokens are flagged as such when introspected.
+Their sessions can be managed by admins, and by their owner if they have one.
+Calling this endpoint again on a bot changes its owner.")
+        .tag("user")
+        .response_with::<200, Json<SingleResponse<User>>, _>(|t| {
+            // In the samples, the third user is a bot
+            let [_alice, _bob, charlie, ..] = User::samples();
+            let id = charlie.id();
+            let response = SingleResponse::new(charlie, format!("/api/admin/v1/users/{id}/set-bot"));
+            t.description("User was turned into a bot").example(response)
+        })
+        .response_with::<400, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::OwnerNotFound(Ulid::nil()));
+            t.description("The owner was not found, or is a bot").example(response)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("User ID not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.users.set_bot", skip_all, err)]
+pub async fn handler(
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
+    id: UlidPathParam,
+    Json(params): Json<Request>,
+) -> Result<Json<SingleResponse<User>>, RouteError> {
+    let id = *id;
+    let user = repo
+        .user()
+        .lookup(id)
+        .await?
+        .ok_or(RouteError::NotFound(id))?;
+
+    let owner = if let Some(owner_id) = params.owner_id {
+        let owner = repo
+            .user()
+            .lookup(owner_id)
+            .await?
+            .ok_or(RouteError::OwnerNotFound(owner_id))?;
+
+        // This also prevents a bot from owning itself
+        if owner.is_bot || owner.id == user.id {
+            return Err(RouteError::OwnerIsBot(owner_id));
+        }
+
+        Some(owner)
+    } else {
+        None
+    };
+
+    let user = repo.user().set_bot(user, owner.as_ref()).await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_updated(&mut rng, &clock, &user))
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({
+                "action": "set_bot",
+                "bot_owner_id": user.bot_owner_id.map(|id| id.to_string()),
+            }),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    Ok(Json(SingleResponse::new(
+        User::from(user),
+        format!("/api/admin/v1/users/{id}/set-bot"),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_storage::{user::UserRepository, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_set_bot(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let alice = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let robot = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "robot".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-bot", robot.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "owner_id": alice.id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+
+        assert_eq!(body["data"]["attributes"]["bot"], true);
+        assert_eq!(
+            body["data"]["attributes"]["bot_owner_id"],
+            alice.id.to_string()
+        );
+
+        // Look at the state from the repository
+        let mut repo = state.repository().await.unwrap();
+        let robot = repo.user().lookup(robot.id).await.unwrap().unwrap();
+        assert!(robot.is_bot_owned_by(&alice));
+        repo.save().await.unwrap();
+
+        // A bot can't own another bot
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-bot", alice.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "owner_id": robot.id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // Remove the owner
+        let request = Request::post(format!("/api/admin/v1/users/{}/set-bot", robot.id))
+            .bearer(&token)
+            .json(serde_json::json!({
+                "owner_id": null,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+
+        assert_eq!(body["data"]["attributes"]["bot"], true);
+        assert_eq!(body["data"]["attributes"]["bot_owner_id"], None::<String>);
+    }
+}
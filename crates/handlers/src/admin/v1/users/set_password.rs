@@ -7,6 +7,7 @@
 use aide::{transform::TransformOperation, NoApi, OperationIo};
 use axum::{extract::State, response::IntoResponse, Json};
 use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
 use mas_storage::BoxRng;
 use schemars::JsonSchema;
 use serde::Deserialize;
@@ -93,7 +94,10 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 #[tracing::instrument(name = "handler.admin.v1.users.set_password", skip_all, err)]
 pub async fn handler(
     CallContext {
-        mut repo, clock, ..
+        mut repo,
+        clock,
+        audit,
+        ..
     }: CallContext,
     NoApi(mut rng): NoApi<BoxRng>,
     State(password_manager): State<PasswordManager>,
@@ -130,6 +134,28 @@ pub async fn handler(
         .add(&mut rng, &clock, &user, version, hashed_password, None)
         .await?;
 
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::PasswordChanged,
+            Some(user.id),
+            serde_json::json!({ "method": "admin_api" }),
+        )
+        .await?;
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "set_password" }),
+        )
+        .await?;
+
     repo.save().await?;
 
     Ok(StatusCode::NO_CONTENT)
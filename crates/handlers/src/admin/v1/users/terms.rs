@@ -0,0 +1,221 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use aide::{transform::TransformOperation, OperationIo};
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use mas_data_model::{SiteConfig, UserTermsAcceptance};
+use schemars::JsonSchema;
+use serde::Serialize;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{
+    admin::{call_context::CallContext, params::UlidPathParam, response::ErrorResponse},
+    impl_from_error_for_route,
+};
+
+#[derive(Debug, thiserror::Error, OperationIo)]
+#[aide(output_with = "Json<ErrorResponse>")]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("User ID {0} not found")]
+    NotFound(Ulid),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let error = ErrorResponse::from_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+/// A version of the terms of service accepted by a user
+#[derive(Serialize, JsonSchema)]
+pub struct TermsAcceptance {
+    /// The URL of the terms of service, which identifies their version
+    terms_url: Url,
+
+    /// When the user accepted them
+    accepted_at: DateTime<Utc>,
+}
+
+impl From<UserTermsAcceptance> for TermsAcceptance {
+    fn from(acceptance: UserTermsAcceptance) -> Self {
+        Self {
+            terms_url: acceptance.terms_url,
+            accepted_at: acceptance.accepted_at,
+        }
+    }
+}
+
+/// Whether a user accepted the current terms of service, and which versions
+/// of the terms they accepted
+#[derive(Serialize, JsonSchema)]
+pub struct UserTermsStatus {
+    /// The terms of service users currently have to accept, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    required_terms_url: Option<Url>,
+
+    /// Whether the user still has to accept the current terms of service
+    pending: bool,
+
+    /// The versions of the terms of service the user accepted, most recent
+    /// first
+    acceptances: Vec<TermsAcceptance>,
+}
+
+impl UserTermsStatus {
+    fn samples() -> [Self; 2] {
+        let accepted_at = DateTime::default();
+        [
+            Self {
+                required_terms_url: Some("https://example.com/tos?v=2".parse().unwrap()),
+                pending: false,
+                acceptances: vec![
+                    TermsAcceptance {
+                        terms_url: "https://example.com/tos?v=2".parse().unwrap(),
+                        accepted_at,
+                    },
+                    TermsAcceptance {
+                        terms_url: "https://example.com/tos?v=1".parse().unwrap(),
+                        accepted_at,
+                    },
+                ],
+            },
+            Self {
+                required_terms_url: Some("https://example.com/tos?v=2".parse().unwrap()),
+                pending: true,
+                acceptances: vec![TermsAcceptance {
+                    terms_url: "https://example.com/tos?v=1".parse().unwrap(),
+                    accepted_at,
+                }],
+            },
+        ]
+    }
+}
+
+pub fn doc(operation: TransformOperation) -> TransformOperation {
+    operation
+        .id("getUserTerms")
+        .summary("Get the terms of service a user accepted")
+        .description(
+            "Tells whether the user still has to accept the current terms of service, and lists the versions of the terms they accepted.",
+        )
+        .tag("user")
+        .response_with::<200, Json<UserTermsStatus>, _>(|t| {
+            let [sample, ..] = UserTermsStatus::samples();
+            t.description("The terms of service status of the user")
+                .example(sample)
+        })
+        .response_with::<404, RouteError, _>(|t| {
+            let response = ErrorResponse::from_error(&RouteError::NotFound(Ulid::nil()));
+            t.description("User was not found").example(response)
+        })
+}
+
+#[tracing::instrument(name = "handler.admin.v1.users.terms", skip_all, err)]
+pub async fn handler(
+    CallContext { mut repo, .. }: CallContext,
+    State(site_config): State<SiteConfig>,
+    id: UlidPathParam,
+) -> Result<Json<UserTermsStatus>, RouteError> {
+    let user = repo
+        .user()
+        .lookup(*id)
+        .await?
+        .ok_or(RouteError::NotFound(*id))?;
+
+    let acceptances = repo.user_terms().all(&user).await?;
+
+    let required_terms_url = site_config.required_terms().cloned();
+    let pending = required_terms_url
+        .as_ref()
+        .is_some_and(|url| !acceptances.iter().any(|a| &a.terms_url == url));
+
+    Ok(Json(UserTermsStatus {
+        required_terms_url,
+        pending,
+        acceptances: acceptances.into_iter().map(TermsAcceptance::from).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::SiteConfig;
+    use mas_storage::RepositoryAccess;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, test_site_config, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_terms(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                tos_uri: Some("https://example.com/tos?v=2".parse().unwrap()),
+                terms_acceptance_required: true,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let alice = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.user_terms()
+            .accept_terms(
+                &mut state.rng(),
+                &state.clock,
+                &alice,
+                "https://example.com/tos?v=1".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get(format!("/api/admin/v1/users/{}/terms", alice.id))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        insta::assert_json_snapshot!(body, @r###"
+        {
+          "required_terms_url": "https://example.com/tos?v=2",
+          "pending": true,
+          "acceptances": [
+            {
+              "terms_url": "https://example.com/tos?v=1",
+              "accepted_at": "2022-01-16T14:40:00Z"
+            }
+          ]
+        }
+        "###);
+
+        // Unknown users are not found
+        let request = Request::get(format!("/api/admin/v1/users/{}/terms", ulid::Ulid::nil()))
+            .bearer(&token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -4,10 +4,15 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use aide::{transform::TransformOperation, OperationIo};
+use aide::{transform::TransformOperation, NoApi, OperationIo};
 use axum::{extract::State, response::IntoResponse, Json};
 use hyper::StatusCode;
+use mas_data_model::AuditEventKind;
 use mas_matrix::BoxHomeserverConnection;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    BoxRng,
+};
 use ulid::Ulid;
 
 use crate::{
@@ -66,7 +71,13 @@ pub fn doc(operation: TransformOperation) -> TransformOperation {
 
 #[tracing::instrument(name = "handler.admin.v1.users.unlock", skip_all, err)]
 pub async fn handler(
-    CallContext { mut repo, .. }: CallContext,
+    CallContext {
+        mut repo,
+        clock,
+        audit,
+        ..
+    }: CallContext,
+    NoApi(mut rng): NoApi<BoxRng>,
     State(homeserver): State<BoxHomeserverConnection>,
     id: UlidPathParam,
 ) -> Result<Json<SingleResponse<User>>, RouteError> {
@@ -78,15 +89,33 @@ pub async fn handler(
         .ok_or(RouteError::NotFound(id))?;
 
     // Call the homeserver synchronously to unlock the user
-    let mxid = homeserver.mxid(&user.username);
+    let mxid = homeserver.mxid_on(user.homeserver.as_deref(), &user.username);
     homeserver
         .reactivate_user(&mxid)
         .await
         .map_err(RouteError::Homeserver)?;
 
     // Now unlock the user in our database
+    let was_locked = user.locked_at.is_some();
     let user = repo.user().unlock(user).await?;
 
+    if was_locked {
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_updated(&mut rng, &clock, &user))
+            .await?;
+    }
+
+    audit
+        .record(
+            &mut repo,
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "unlock" }),
+        )
+        .await?;
+
     repo.save().await?;
 
     Ok(Json(SingleResponse::new(
@@ -0,0 +1,61 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Time-boxed admin privileges
+//!
+//! OAuth 2.0 sessions with the `urn:mas:admin` scope give admin privileges on
+//! the GraphQL and admin APIs. If an admin elevation TTL is configured,
+//! sessions started by a user only keep those privileges for a while after the
+//! user last authenticated. Once elapsed, the user has to authenticate again,
+//! for example through the re-authentication page.
+
+use mas_data_model::{Session, SiteConfig};
+use mas_storage::{user::BrowserSessionRepository, Clock, RepositoryAccess};
+
+/// Check whether an OAuth 2.0 session currently has admin privileges
+///
+/// Sessions without a user, like the ones obtained with the client credentials
+/// grant, are not time-boxed. Sessions which were not started from a browser
+/// session are elevated from when they were created.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn has_admin_privileges<R: RepositoryAccess>(
+    repo: &mut R,
+    clock: &dyn Clock,
+    site_config: &SiteConfig,
+    session: &Session,
+) -> Result<bool, R::Error> {
+    // This has to be in sync with the policy
+    if !session.scope.contains("urn:mas:admin") {
+        return Ok(false);
+    }
+
+    let Some(ttl) = site_config.admin_elevation_ttl else {
+        return Ok(true);
+    };
+
+    if session.user_id.is_none() {
+        return Ok(true);
+    }
+
+    let authenticated_at = if let Some(user_session_id) = session.user_session_id {
+        let browser_session = repo.browser_session().lookup(user_session_id).await?;
+        let authentication = if let Some(browser_session) = browser_session {
+            repo.browser_session()
+                .get_last_authentication(&browser_session)
+                .await?
+        } else {
+            None
+        };
+
+        authentication.map(|authentication| authentication.created_at)
+    } else {
+        Some(session.created_at)
+    };
+
+    Ok(authenticated_at.is_some_and(|authenticated_at| clock.now() < authenticated_at + ttl))
+}
@@ -55,10 +55,14 @@ impl_from_request_parts!(mas_storage::BoxClock);
 impl_from_request_parts!(mas_storage::BoxRng);
 impl_from_request_parts!(mas_handlers::BoundActivityTracker);
 impl_from_ref!(mas_router::UrlBuilder);
+impl_from_ref!(mas_data_model::SiteConfig);
 impl_from_ref!(mas_templates::Templates);
 impl_from_ref!(mas_matrix::BoxHomeserverConnection);
 impl_from_ref!(mas_keystore::Keystore);
 impl_from_ref!(mas_handlers::passwords::PasswordManager);
+impl_from_ref!(mas_handlers::HttpClientFactory);
+impl_from_ref!(mas_email::Mailer);
+impl_from_ref!(sqlx::PgPool);
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (mut api, _) = mas_handlers::admin_api_router::<DummyState>();
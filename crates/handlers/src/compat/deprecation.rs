@@ -0,0 +1,113 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Advertise the deprecation of compatibility endpoints to clients, through
+//! the `Deprecation` (RFC 9745), `Sunset` (RFC 8594) and `Link` headers
+
+use std::sync::{Arc, LazyLock};
+
+use axum::{
+    extract::{Request, State},
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    routing::MethodRouter,
+};
+use axum_extra::typed_header::TypedHeader;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, LINK};
+use mas_data_model::{CompatDeprecation, CompatEndpoint, UserAgent};
+use opentelemetry::{metrics::Counter, Key};
+
+const ENDPOINT: Key = Key::from_static_str("endpoint");
+const CLIENT: Key = Key::from_static_str("client");
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+static DEPRECATED_REQUESTS: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("mas.compat.deprecated_requests")
+    .with_description("The number of requests made to deprecated compatibility endpoints")
+    .with_unit("{requests}")
+    .init()
+});
+
+struct Deprecation {
+    endpoint: CompatEndpoint,
+    headers: HeaderMap,
+}
+
+impl Deprecation {
+    fn new(deprecation: &CompatDeprecation, endpoint: CompatEndpoint) -> Self {
+        let mut headers = HeaderMap::new();
+
+        // The Deprecation header is a structured field date, in seconds since the epoch
+        let value = format!("@{}", deprecation.deprecated_at.timestamp());
+        headers.insert(DEPRECATION, HeaderValue::from_str(&value).unwrap());
+
+        if let Some(sunset_at) = deprecation.sunset_at {
+            let value = sunset_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            headers.insert(SUNSET, HeaderValue::from_str(&value).unwrap());
+        }
+
+        if let Some(link) = &deprecation.link {
+            let value = format!(r#"<{link}>; rel="deprecation"; type="text/html""#);
+            // This can only fail if the URL has invalid characters, which
+            // shouldn't happen for a parsed URL
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(LINK, value);
+            }
+        }
+
+        Self { endpoint, headers }
+    }
+}
+
+async fn middleware(
+    State(deprecation): State<Arc<Deprecation>>,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Label the requests by the name of the client, as guessed from the user
+    // agent, so that operators know which clients still need to migrate
+    let client = user_agent
+        .and_then(|ua| UserAgent::parse(ua.as_str().to_owned()).name)
+        .unwrap_or_else(|| "unknown".to_owned());
+    DEPRECATED_REQUESTS.add(
+        1,
+        &[
+            ENDPOINT.string(deprecation.endpoint.as_str()),
+            CLIENT.string(client),
+        ],
+    );
+
+    let mut response = next.run(request).await;
+    response.headers_mut().extend(deprecation.headers.clone());
+    response
+}
+
+/// Add the deprecation headers to the responses of the given endpoint, if it
+/// is deprecated
+pub(crate) fn deprecated<S>(
+    router: MethodRouter<S>,
+    deprecation: Option<&CompatDeprecation>,
+    endpoint: CompatEndpoint,
+) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    match deprecation {
+        Some(deprecation) if deprecation.applies_to(endpoint) => router.layer(from_fn_with_state(
+            Arc::new(Deprecation::new(deprecation, endpoint)),
+            middleware,
+        )),
+        _ => router,
+    }
+}
@@ -7,19 +7,30 @@
 use axum::{extract::State, response::IntoResponse, Json};
 use axum_extra::typed_header::TypedHeader;
 use chrono::Duration;
+use headers::{authorization::Bearer, Authorization};
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
 use mas_data_model::{
-    CompatSession, CompatSsoLoginState, Device, SiteConfig, TokenType, User, UserAgent,
+    Appservice, AuditEventKind, CompatLoginType, CompatSession, CompatSsoLoginState, Device,
+    RuntimeResource, SiteConfig, TokenType, User, UserAgent,
 };
 use mas_matrix::BoxHomeserverConnection;
 use mas_storage::{
+    audit::AuditEventRepository,
     compat::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
     },
-    user::{UserPasswordRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+    honeytoken::HoneytokenRepository,
+    job::{
+        CheckSuspiciousLoginJob, DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob,
+        SendNewDeviceNotificationJob,
+    },
+    user::{
+        UserEmailFilter, UserEmailRepository, UserHomeserver, UserPasswordRepository,
+        UserRepository,
+    },
+    BoxClock, BoxRepository, BoxRng, Clock, Pagination, RepositoryAccess,
 };
 use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
@@ -29,7 +40,8 @@ use zeroize::Zeroizing;
 
 use super::MatrixError;
 use crate::{
-    impl_from_error_for_route, passwords::PasswordManager, rate_limit::PasswordCheckLimitedError,
+    homeserver::RequestHomeserver, impl_from_error_for_route, passwords::PasswordManager,
+    rate_limit::PasswordCheckLimitedError, session_limit::make_room_for_session,
     BoundActivityTracker, Limiter, RequesterFingerprint,
 };
 
@@ -51,6 +63,9 @@ enum LoginType {
         #[serde(rename = "org.matrix.msc3824.delegated_oidc_compatibility")]
         delegated_oidc_compatibility: bool,
     },
+
+    #[serde(rename = "m.login.application_service")]
+    ApplicationService,
 }
 
 #[derive(Debug, Serialize)]
@@ -64,30 +79,51 @@ struct LoginTypes {
     flows: Vec<LoginType>,
 }
 
-#[tracing::instrument(name = "handlers.compat.login.get", skip_all)]
-pub(crate) async fn get(State(password_manager): State<PasswordManager>) -> impl IntoResponse {
-    let flows = if password_manager.is_enabled() {
-        vec![
-            LoginType::Password,
-            LoginType::Sso {
-                identity_providers: vec![],
-                delegated_oidc_compatibility: true,
-            },
-            LoginType::Token,
-        ]
-    } else {
-        vec![
-            LoginType::Sso {
-                identity_providers: vec![],
-                delegated_oidc_compatibility: true,
-            },
-            LoginType::Token,
-        ]
-    };
+#[tracing::instrument(name = "handlers.compat.login.get", skip_all, err)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(password_manager): State<PasswordManager>,
+    State(site_config): State<SiteConfig>,
+) -> Result<impl IntoResponse, RouteError> {
+    let now = clock.now();
+    let mut flows = Vec::with_capacity(4);
+
+    // Don't advertise any flow while the endpoint is disabled
+    let disabled = repo
+        .runtime_resource()
+        .is_disabled(RuntimeResource::CompatLogin)
+        .await?;
+    repo.cancel().await?;
+    if disabled {
+        return Ok(Json(LoginTypes { flows }));
+    }
+
+    // Break-glass accounts can still log in with their password, but the flow is
+    // not advertised to clients, to force them through SSO
+    if password_manager.is_enabled()
+        && site_config.password_login_enabled
+        && site_config.is_compat_login_type_enabled(CompatLoginType::Password, now)
+    {
+        flows.push(LoginType::Password);
+    }
+
+    // SSO logins are completed with a login token, so they can't work without it
+    if site_config.is_compat_login_type_enabled(CompatLoginType::Token, now) {
+        flows.push(LoginType::Sso {
+            identity_providers: vec![],
+            delegated_oidc_compatibility: true,
+        });
+        flows.push(LoginType::Token);
+    }
+
+    if !site_config.appservices.is_empty() {
+        flows.push(LoginType::ApplicationService);
+    }
 
     let res = LoginTypes { flows };
 
-    Json(res)
+    Ok(Json(res))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -111,6 +147,9 @@ pub enum Credentials {
     #[serde(rename = "m.login.token")]
     Token { token: String },
 
+    #[serde(rename = "m.login.application_service")]
+    ApplicationService { identifier: Identifier },
+
     #[serde(other)]
     Unsupported,
 }
@@ -121,6 +160,9 @@ pub enum Identifier {
     #[serde(rename = "m.id.user")]
     User { user: String },
 
+    #[serde(rename = "m.id.thirdparty")]
+    ThirdParty { medium: String, address: String },
+
     #[serde(other)]
     Unsupported,
 }
@@ -145,6 +187,9 @@ pub enum RouteError {
     #[error("unsupported login method")]
     Unsupported,
 
+    #[error("login method disabled")]
+    LoginTypeDisabled,
+
     #[error("user not found")]
     UserNotFound,
 
@@ -168,6 +213,18 @@ pub enum RouteError {
 
     #[error("failed to provision device")]
     ProvisionDeviceFailed(#[source] anyhow::Error),
+
+    #[error("user reached the maximum number of sessions")]
+    TooManySessions,
+
+    #[error("login is disabled")]
+    Disabled,
+
+    #[error("missing or invalid application service token")]
+    InvalidAppserviceToken,
+
+    #[error("user is not in the namespace of the application service")]
+    NotInAppserviceNamespace,
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -193,6 +250,11 @@ impl IntoResponse for RouteError {
                 error: "Invalid login type",
                 status: StatusCode::BAD_REQUEST,
             },
+            Self::LoginTypeDisabled => MatrixError {
+                errcode: "M_UNRECOGNIZED",
+                error: "This login type is no longer supported",
+                status: StatusCode::BAD_REQUEST,
+            },
             Self::UserNotFound | Self::NoPassword | Self::PasswordVerificationFailed(_) => {
                 MatrixError {
                     errcode: "M_FORBIDDEN",
@@ -210,6 +272,26 @@ impl IntoResponse for RouteError {
                 error: "Invalid login token",
                 status: StatusCode::FORBIDDEN,
             },
+            Self::TooManySessions => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Too many sessions, log out from another device first",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::Disabled => MatrixError {
+                errcode: "M_UNKNOWN",
+                error: "Login is temporarily disabled",
+                status: StatusCode::SERVICE_UNAVAILABLE,
+            },
+            Self::InvalidAppserviceToken => MatrixError {
+                errcode: "M_UNKNOWN_TOKEN",
+                error: "Invalid application service token",
+                status: StatusCode::UNAUTHORIZED,
+            },
+            Self::NotInAppserviceNamespace => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Application service cannot log in as this user",
+                status: StatusCode::FORBIDDEN,
+            },
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -227,38 +309,172 @@ pub(crate) async fn post(
     State(site_config): State<SiteConfig>,
     State(limiter): State<Limiter>,
     requester: RequesterFingerprint,
+    request_homeserver: RequestHomeserver,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
     Json(input): Json<RequestBody>,
 ) -> Result<impl IntoResponse, RouteError> {
+    if repo
+        .runtime_resource()
+        .is_disabled(RuntimeResource::CompatLogin)
+        .await?
+    {
+        return Err(RouteError::Disabled);
+    }
+
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
-    let (mut session, user) = match (password_manager.is_enabled(), input.credentials) {
-        (
-            true,
-            Credentials::Password {
-                identifier: Identifier::User { user },
-                password,
-            },
-        ) => {
-            user_password_login(
-                &mut rng,
-                &clock,
-                &password_manager,
-                &limiter,
-                requester,
-                &mut repo,
-                &homeserver,
-                user,
-                password,
-            )
-            .await?
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
+
+    let login_type = match &input.credentials {
+        Credentials::Password { .. } => Some(CompatLoginType::Password),
+        Credentials::Token { .. } => Some(CompatLoginType::Token),
+        Credentials::ApplicationService { .. } | Credentials::Unsupported => None,
+    };
+    if let Some(login_type) = login_type {
+        if !site_config.is_compat_login_type_enabled(login_type, clock.now()) {
+            return Err(RouteError::LoginTypeDisabled);
         }
+    }
 
-        (_, Credentials::Token { token }) => token_login(&mut repo, &clock, &token).await?,
+    let (login_method, (mut session, user)) =
+        match (password_manager.is_enabled(), input.credentials) {
+            (
+                true,
+                Credentials::Password {
+                    identifier,
+                    password,
+                },
+            ) => {
+                // Legacy clients may identify the user with one of their email
+                // addresses, which we resolve to a username
+                let (user, email) = match identifier {
+                    Identifier::User { user } => (Some(user), None),
+                    Identifier::ThirdParty { medium, address } if medium == "email" => {
+                        let user = find_username_by_email(&mut repo, &address).await?;
+                        (user, Some(address))
+                    }
+                    _ => return Err(RouteError::Unsupported),
+                };
 
-        _ => {
-            return Err(RouteError::Unsupported);
-        }
-    };
+                if !site_config.is_password_login_allowed(user.as_deref().unwrap_or_default()) {
+                    return Err(RouteError::LoginTypeDisabled);
+                }
+
+                let res = match user.clone() {
+                    Some(user) => {
+                        user_password_login(
+                            &mut rng,
+                            &clock,
+                            &password_manager,
+                            &limiter,
+                            requester,
+                            &mut repo,
+                            &homeserver,
+                            &site_config,
+                            request_homeserver.user_homeserver(),
+                            user,
+                            password,
+                        )
+                        .await
+                    }
+                    None => Err(RouteError::UserNotFound),
+                };
+
+                match res {
+                    Ok(res) => ("password", res),
+                    Err(
+                        e @ (RouteError::UserNotFound
+                        | RouteError::NoPassword
+                        | RouteError::PasswordVerificationFailed(_)
+                        | RouteError::RateLimited(_)),
+                    ) => {
+                        // Record the failed attempt before bailing out
+                        let target = match &user {
+                            Some(user) => {
+                                repo.user()
+                                    .find_by_username(request_homeserver.user_homeserver(), user)
+                                    .await?
+                            }
+                            None => None,
+                        };
+
+                        // Nobody should ever try to log in with a decoy account
+                        if let (None, Some(user)) = (&target, &user) {
+                            if let Some(honeytoken) = repo.honeytoken().find_account(user).await? {
+                                activity_tracker
+                                    .report_honeytoken(
+                                        &clock,
+                                        &honeytoken,
+                                        "compat_login",
+                                        raw_user_agent.clone(),
+                                    )
+                                    .await;
+                            }
+                        }
+
+                        let mut data = serde_json::json!({
+                            "method": "compat_password",
+                            "username": user,
+                            "reason": e.to_string(),
+                        });
+                        if let Some(email) = email {
+                            data["email"] = email.into();
+                        }
+
+                        repo.audit_event()
+                            .add(
+                                &mut rng,
+                                &clock,
+                                AuditEventKind::LoginFailed,
+                                None,
+                                target.map(|user| user.id),
+                                activity_tracker.ip(),
+                                raw_user_agent,
+                                data,
+                            )
+                            .await?;
+                        repo.save().await?;
+
+                        return Err(e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            (_, Credentials::Token { token }) => {
+                ("token", token_login(&mut repo, &clock, &token).await?)
+            }
+
+            (
+                _,
+                Credentials::ApplicationService {
+                    identifier: Identifier::User { user },
+                },
+            ) => {
+                let appservice = authorization
+                    .and_then(|TypedHeader(Authorization(bearer))| {
+                        site_config.appservice_by_token(bearer.token())
+                    })
+                    .ok_or(RouteError::InvalidAppserviceToken)?;
+
+                let res = appservice_login(
+                    &mut rng,
+                    &clock,
+                    &mut repo,
+                    &homeserver,
+                    &site_config,
+                    appservice,
+                    &user,
+                )
+                .await?;
+
+                ("appservice", res)
+            }
+
+            _ => {
+                return Err(RouteError::Unsupported);
+            }
+        };
 
     if let Some(user_agent) = user_agent {
         session = repo
@@ -267,7 +483,7 @@ pub(crate) async fn post(
             .await?;
     }
 
-    let user_id = homeserver.mxid(&user.username);
+    let user_id = homeserver.mxid_on(user.homeserver.as_deref(), &user.username);
 
     // If the client asked for a refreshable token, make it expire
     let expires_in = if input.refresh_token {
@@ -293,6 +509,43 @@ pub(crate) async fn post(
         None
     };
 
+    let event = repo
+        .audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::Login,
+            Some(user.id),
+            Some(user.id),
+            activity_tracker.ip(),
+            raw_user_agent,
+            serde_json::json!({
+                "method": format!("compat_{login_method}"),
+                "compat_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(CheckSuspiciousLoginJob::new(&event))
+        .await?;
+
+    if site_config.new_device_emails {
+        repo.job()
+            .schedule_job(
+                SendNewDeviceNotificationJob::compat(&session).with_ip(activity_tracker.ip()),
+            )
+            .await?;
+    }
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_created(
+            &mut rng, &clock, &user, "compat", session.id,
+        ))
+        .await?;
+
+    crate::upstream_oauth2::funnel::record_first_token(&mut repo, &mut rng, &clock, &user).await?;
+
     repo.save().await?;
 
     activity_tracker
@@ -375,6 +628,109 @@ async fn token_login(
     Ok((session, user))
 }
 
+async fn appservice_login(
+    mut rng: &mut (impl RngCore + CryptoRng + Send),
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    homeserver: &BoxHomeserverConnection,
+    site_config: &SiteConfig,
+    appservice: &Appservice,
+    user: &str,
+) -> Result<(CompatSession, User), RouteError> {
+    // The user can be given either as a full Matrix ID or as a localpart
+    let localpart = match user.strip_prefix('@') {
+        Some(mxid) => match mxid.split_once(':') {
+            Some((localpart, server_name)) if server_name == homeserver.homeserver() => localpart,
+            _ => return Err(RouteError::NotInAppserviceNamespace),
+        },
+        None => user,
+    };
+
+    let mxid = homeserver.mxid(localpart);
+    if !appservice.can_log_in_as(localpart, &mxid) {
+        return Err(RouteError::NotInAppserviceNamespace);
+    }
+
+    let user = match repo
+        .user()
+        .find_by_username(UserHomeserver::Main, localpart)
+        .await?
+    {
+        Some(user) => user,
+        None => {
+            // Application services register their users on the homeserver, so
+            // import them on their first login
+            if let Err(err) = homeserver.query_user(&mxid).await {
+                tracing::warn!(
+                    error = &*err as &dyn std::error::Error,
+                    appservice.id = %appservice.id,
+                    "Application service tried to log in as a user unknown to the homeserver"
+                );
+                return Err(RouteError::UserNotFound);
+            }
+
+            let user = repo
+                .user()
+                .add(&mut rng, clock, localpart.to_owned())
+                .await?;
+            let user = repo.user().set_bot(user, None).await?;
+
+            repo.job()
+                .schedule_job(ProvisionUserJob::new(&user))
+                .await?;
+            repo.job()
+                .schedule_job(DispatchWebhookJob::user_registered(&mut rng, clock, &user))
+                .await?;
+
+            user
+        }
+    };
+
+    // Users of other homeservers are not in the namespace of the application
+    // service
+    if !user.is_valid() || user.homeserver.is_some() {
+        return Err(RouteError::UserNotFound);
+    }
+
+    repo.user().acquire_lock_for_sync(&user).await?;
+
+    if !make_room_for_session(&mut rng, clock, repo, site_config, &user).await? {
+        return Err(RouteError::TooManySessions);
+    }
+
+    let device = Device::generate(&mut rng);
+    homeserver
+        .create_device(&mxid, device.as_str())
+        .await
+        .map_err(RouteError::ProvisionDeviceFailed)?;
+
+    let session = repo
+        .compat_session()
+        .add(&mut rng, clock, &user, device, None, false)
+        .await?;
+
+    Ok((session, user))
+}
+
+/// Find the user who verified the given email address, and return their
+/// username
+///
+/// Returns `None` if no user, or more than one user, verified this address
+async fn find_username_by_email(
+    repo: &mut BoxRepository,
+    email: &str,
+) -> Result<Option<String>, RouteError> {
+    let filter = UserEmailFilter::new().for_email(email).verified_only();
+    let page = repo.user_email().list(filter, Pagination::first(2)).await?;
+
+    let [user_email] = page.edges.as_slice() else {
+        return Ok(None);
+    };
+
+    let user = repo.user().lookup(user_email.user_id).await?;
+    Ok(user.map(|user| user.username))
+}
+
 async fn user_password_login(
     mut rng: &mut (impl RngCore + CryptoRng + Send),
     clock: &impl Clock,
@@ -383,29 +739,36 @@ async fn user_password_login(
     requester: RequesterFingerprint,
     repo: &mut BoxRepository,
     homeserver: &BoxHomeserverConnection,
+    site_config: &SiteConfig,
+    user_homeserver: UserHomeserver<'_>,
     username: String,
     password: String,
 ) -> Result<(CompatSession, User), RouteError> {
-    // Find the user
+    // Find the user. Bots can't log in with a password
     let user = repo
         .user()
-        .find_by_username(&username)
+        .find_by_username(user_homeserver, &username)
         .await?
-        .filter(mas_data_model::User::is_valid)
+        .filter(|user| user.is_valid() && !user.is_bot)
         .ok_or(RouteError::UserNotFound)?;
 
     // Check the rate limit
     limiter.check_password(requester, &user)?;
 
-    // Lookup its password
-    let user_password = repo
-        .user_password()
-        .active(&user)
-        .await?
-        .ok_or(RouteError::NoPassword)?;
+    let password = Zeroizing::new(password.into_bytes());
+
+    // Lookup its password. Users who don't have one yet may still have one in
+    // Synapse, if we're migrating from it
+    let user_password = match repo.user_password().active(&user).await? {
+        Some(user_password) => user_password,
+        None => password_manager
+            .import_from_synapse(&mut rng, clock, repo, &user, password.clone())
+            .await
+            .map_err(|e| RouteError::Internal(e.into()))?
+            .ok_or(RouteError::NoPassword)?,
+    };
 
     // Verify the password
-    let password = Zeroizing::new(password.into_bytes());
 
     let new_password_hash = password_manager
         .verify_and_upgrade(
@@ -434,9 +797,14 @@ async fn user_password_login(
     // Lock the user sync to make sure we don't get into a race condition
     repo.user().acquire_lock_for_sync(&user).await?;
 
+    // Make sure the user didn't reach the maximum number of sessions
+    if !make_room_for_session(&mut rng, clock, repo, site_config, &user).await? {
+        return Err(RouteError::TooManySessions);
+    }
+
     // Now that the user credentials have been verified, start a new compat session
     let device = Device::generate(&mut rng);
-    let mxid = homeserver.mxid(&user.username);
+    let mxid = homeserver.mxid_on(user.homeserver.as_deref(), &user.username);
     homeserver
         .create_device(&mxid, device.as_str())
         .await
@@ -453,7 +821,9 @@ async fn user_password_login(
 #[cfg(test)]
 mod tests {
     use hyper::Request;
+    use mas_data_model::{CompatDeprecation, CompatEndpoint, SessionLimit, SessionLimitAction};
     use mas_matrix::{HomeserverConnection, ProvisionRequest};
+    use mas_storage::app_session::{AppSession, AppSessionFilter};
     use rand::distributions::{Alphanumeric, DistString};
     use sqlx::PgPool;
 
@@ -539,6 +909,81 @@ mod tests {
         assert_eq!(body["errcode"], "M_UNRECOGNIZED");
     }
 
+    /// Test that deprecated endpoints advertise it, and that disabled login
+    /// types are rejected
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_deprecated_password_login(pool: PgPool) {
+        setup();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            let now = state.clock.now();
+            state.site_config.compat_deprecation = Some(CompatDeprecation {
+                endpoints: vec![CompatEndpoint::Login],
+                deprecated_at: now - Duration::days(30),
+                sunset_at: Some(now + Duration::days(30)),
+                link: Some("https://example.com/migration".parse().unwrap()),
+            });
+            state.site_config.compat_disabled_login_types =
+                vec![(CompatLoginType::Password, now - Duration::hours(1))];
+            state
+        };
+
+        let request = Request::get("/_matrix/client/v3/login").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let deprecation = (state.clock.now() - Duration::days(30)).timestamp();
+        assert_eq!(
+            response.headers().get("deprecation").unwrap(),
+            format!("@{deprecation}").as_str()
+        );
+        assert!(response.headers().contains_key("sunset"));
+        assert_eq!(
+            response.headers().get("link").unwrap(),
+            r#"<https://example.com/migration>; rel="deprecation"; type="text/html""#
+        );
+        let body: serde_json::Value = response.json();
+
+        // The password flow is not advertised anymore
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "flows": [
+                    {
+                        "type": "m.login.sso",
+                        "org.matrix.msc3824.delegated_oidc_compatibility": true,
+                    },
+                    {
+                        "type": "m.login.token",
+                    }
+                ],
+            })
+        );
+
+        // Try to login with a password, it should be rejected
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.headers().contains_key("deprecation"));
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_UNRECOGNIZED");
+        assert_eq!(body["error"], "This login type is no longer supported");
+
+        // Other endpoints are not deprecated
+        let request = Request::post("/_matrix/client/v3/refresh").json(serde_json::json!({
+            "refresh_token": "invalid",
+        }));
+        let response = state.request(request).await;
+        assert!(!response.headers().contains_key("deprecation"));
+    }
+
     /// Test that a user can login with a password using the Matrix
     /// compatibility API.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -713,6 +1158,242 @@ mod tests {
         assert_eq!(body["error"], "Too many login attempts");
     }
 
+    /// Test that users can log in with a password, using one of their verified
+    /// email addresses as identifier.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_user_password_login_with_email(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let user = provision_user_with_password(&state, "alice").await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo
+            .user_email()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                "alice@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.user_email()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                "unverified@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.user_email()
+            .mark_as_verified(&state.clock, user_email)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let login = |address: &str, password: &str| {
+            Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.thirdparty",
+                    "medium": "email",
+                    "address": address,
+                },
+                "password": password,
+            }))
+        };
+
+        let response = state.request(login("alice@example.com", "password")).await;
+        response.assert_status(StatusCode::OK);
+        let body: ResponseBody = response.json();
+        assert_eq!(body.user_id, "@alice:example.com");
+
+        // The password is still checked
+        let response = state.request(login("alice@example.com", "wrong")).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        // Unverified and unknown addresses don't resolve to a user
+        let response = state
+            .request(login("unverified@example.com", "password"))
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let response = state.request(login("bob@example.com", "password")).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // Other mediums are not supported
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.thirdparty",
+                "medium": "msisdn",
+                "address": "447700900000",
+            },
+            "password": "password",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_UNRECOGNIZED");
+    }
+
+    /// Provision a user with the `password` password
+    async fn provision_user_with_password(state: &TestState, username: &str) -> User {
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, username.to_owned())
+            .await
+            .unwrap();
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(mxid, &user.sub))
+            .await
+            .unwrap();
+
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(
+                &mut state.rng(),
+                Zeroizing::new("password".to_owned().into_bytes()),
+            )
+            .await
+            .unwrap();
+
+        repo.user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        user
+    }
+
+    /// Test that logins are refused once the user reached the maximum number
+    /// of sessions, if configured so.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_session_limit_refuse(pool: PgPool) {
+        setup();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            state.site_config.session_limit = Some(SessionLimit {
+                max_sessions: 1,
+                action: SessionLimitAction::Refuse,
+            });
+            state
+        };
+        let user = provision_user_with_password(&state, "alice").await;
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        // The first login works
+        let response = state.request(request.clone()).await;
+        response.assert_status(StatusCode::OK);
+        let first: ResponseBody = response.json();
+
+        // The second one is refused
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // The first session is still there
+        let mut repo = state.repository().await.unwrap();
+        let filter = AppSessionFilter::new().for_user(&user).active_only();
+        let page = repo
+            .app_session()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        let AppSession::Compat(session) = &page.edges[0] else {
+            panic!("expected a compat session");
+        };
+        assert_eq!(session.device, first.device_id);
+    }
+
+    /// Test that the least recently active session of the user is ended when
+    /// they reached the maximum number of sessions, if configured so.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_session_limit_evict(pool: PgPool) {
+        setup();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            state.site_config.session_limit = Some(SessionLimit {
+                max_sessions: 1,
+                action: SessionLimitAction::Evict,
+            });
+            state
+        };
+        let user = provision_user_with_password(&state, "alice").await;
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request.clone()).await;
+        response.assert_status(StatusCode::OK);
+        let first: ResponseBody = response.json();
+
+        // The second login ends the first session
+        state.clock.advance(Duration::try_minutes(1).unwrap());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let second: ResponseBody = response.json();
+        assert_ne!(first.device_id, second.device_id);
+
+        let mut repo = state.repository().await.unwrap();
+        let filter = AppSessionFilter::new().for_user(&user).active_only();
+        let page = repo
+            .app_session()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        let AppSession::Compat(session) = &page.edges[0] else {
+            panic!("expected a compat session");
+        };
+        assert_eq!(session.device, second.device_id);
+
+        // The first session was ended
+        let filter = AppSessionFilter::new().for_user(&user).finished_only();
+        let page = repo
+            .app_session()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        let AppSession::Compat(session) = &page.edges[0] else {
+            panic!("expected a compat session");
+        };
+        assert_eq!(session.device, first.device_id);
+    }
+
     /// Test the response of an unsupported login flow.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unsupported_login(pool: PgPool) {
@@ -859,4 +1540,96 @@ mod tests {
 
         (device, token)
     }
+
+    /// Test the `m.login.application_service` login flow.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_appservice_login(pool: PgPool) {
+        setup();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            state.site_config.appservices = vec![Appservice::new(
+                "irc".to_owned(),
+                "irc-token".to_owned(),
+                "_irc_bot".to_owned(),
+                [r"@_irc_.*:example\.com"],
+            )
+            .unwrap()];
+            state
+        };
+
+        // The bridge registered the user on the homeserver
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(
+                "@_irc_alice:example.com",
+                "_irc_alice",
+            ))
+            .await
+            .unwrap();
+
+        let body = serde_json::json!({
+            "type": "m.login.application_service",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "@_irc_alice:example.com",
+            },
+        });
+
+        // The token is required
+        let request = Request::post("/_matrix/client/v3/login").json(&body);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        let error: serde_json::Value = response.json();
+        assert_eq!(error["errcode"], "M_UNKNOWN_TOKEN");
+
+        let request = Request::post("/_matrix/client/v3/login")
+            .bearer("wrong-token")
+            .json(&body);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // The user is imported on their first login
+        let request = Request::post("/_matrix/client/v3/login")
+            .bearer("irc-token")
+            .json(&body);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: ResponseBody = response.json();
+        assert_eq!(body.user_id, "@_irc_alice:example.com");
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .find_by_username(UserHomeserver::Main, "_irc_alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.is_bot);
+        repo.cancel().await.unwrap();
+
+        // Users outside of the namespace are refused
+        let request = Request::post("/_matrix/client/v3/login")
+            .bearer("irc-token")
+            .json(serde_json::json!({
+                "type": "m.login.application_service",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": "alice",
+                },
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // The flow is advertised
+        let request = Request::get("/_matrix/client/v3/login").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body["flows"].as_array().unwrap().last().unwrap()["type"],
+            "m.login.application_service"
+        );
+    }
 }
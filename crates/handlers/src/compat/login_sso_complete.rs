@@ -17,7 +17,7 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::Device;
+use mas_data_model::{Device, SiteConfig};
 use mas_matrix::BoxHomeserverConnection;
 use mas_router::{CompatLoginSsoAction, PostAuthAction, UrlBuilder};
 use mas_storage::{
@@ -28,7 +28,7 @@ use mas_templates::{CompatSsoContext, ErrorContext, TemplateContext, Templates};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
-use crate::PreferredLanguage;
+use crate::{session_limit::make_room_for_session, PreferredLanguage};
 
 #[derive(Serialize)]
 struct AllParams<'s> {
@@ -54,6 +54,7 @@ pub async fn get(
     PreferredLanguage(locale): PreferredLanguage,
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     mut repo: BoxRepository,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
@@ -64,7 +65,9 @@ pub async fn get(
     let (session_info, cookie_jar) = cookie_jar.session_info();
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login or register screen
@@ -128,6 +131,7 @@ pub async fn post(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(homeserver): State<BoxHomeserverConnection>,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
     Path(id): Path<Ulid>,
     Query(params): Query<Params>,
@@ -136,7 +140,9 @@ pub async fn post(
     let (session_info, cookie_jar) = cookie_jar.session_info();
     cookie_jar.verify_form(&clock, form)?;
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login or register screen
@@ -196,8 +202,22 @@ pub async fn post(
     // Lock the user sync to make sure we don't get into a race condition
     repo.user().acquire_lock_for_sync(&session.user).await?;
 
+    // Make sure the user didn't reach the maximum number of sessions
+    if !make_room_for_session(&mut rng, &clock, &mut repo, &site_config, &session.user).await? {
+        let ctx = ErrorContext::new()
+            .with_code("session_limit_reached")
+            .with_description(
+                "You reached the maximum number of sessions. Log out from another device and try again."
+                    .to_owned(),
+            )
+            .with_language(&locale);
+
+        let content = templates.render_error(&ctx)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
     let device = Device::generate(&mut rng);
-    let mxid = homeserver.mxid(&session.user.username);
+    let mxid = homeserver.mxid_on(session.user.homeserver.as_deref(), &session.user.username);
     homeserver
         .create_device(&mxid, device.as_str())
         .await
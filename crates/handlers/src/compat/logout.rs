@@ -9,11 +9,12 @@ use axum_extra::typed_header::TypedHeader;
 use headers::{authorization::Bearer, Authorization};
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
-use mas_data_model::TokenType;
+use mas_data_model::{AuditEventKind, TokenType};
 use mas_storage::{
+    audit::AuditEventRepository,
     compat::{CompatAccessTokenRepository, CompatSessionRepository},
-    job::{JobRepositoryExt, SyncDevicesJob},
-    BoxClock, BoxRepository, Clock, RepositoryAccess,
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use thiserror::Error;
 
@@ -64,10 +65,12 @@ impl IntoResponse for RouteError {
 
 #[tracing::instrument(name = "handlers.compat.logout.post", skip_all, err)]
 pub(crate) async fn post(
+    mut rng: BoxRng,
     clock: BoxClock,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     maybe_authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
 ) -> Result<impl IntoResponse, RouteError> {
     let TypedHeader(authorization) = maybe_authorization.ok_or(RouteError::MissingAuthorization)?;
 
@@ -106,7 +109,26 @@ pub(crate) async fn post(
     // Schedule a job to sync the devices of the user with the homeserver
     repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
 
-    repo.compat_session().finish(&clock, session).await?;
+    let session = repo.compat_session().finish(&clock, session).await?;
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::SessionEnded,
+            Some(user.id),
+            Some(user.id),
+            activity_tracker.ip(),
+            user_agent.map(|TypedHeader(ua)| ua.as_str().to_owned()),
+            serde_json::json!({ "compat_session_id": session.id.to_string() }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_revoked(
+            &mut rng, &clock, &user, "compat", session.id,
+        ))
+        .await?;
 
     repo.save().await?;
 
@@ -8,11 +8,16 @@ use axum::{response::IntoResponse, Json};
 use hyper::StatusCode;
 use serde::Serialize;
 
+pub(crate) use self::deprecation::deprecated;
+
+mod deprecation;
 pub(crate) mod login;
 pub(crate) mod login_sso_complete;
 pub(crate) mod login_sso_redirect;
 pub(crate) mod logout;
 pub(crate) mod refresh;
+pub(crate) mod register;
+pub(crate) mod rendezvous;
 
 #[derive(Debug, Serialize)]
 struct MatrixError {
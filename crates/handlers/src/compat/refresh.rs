@@ -5,12 +5,15 @@
 // Please see LICENSE in the repository root for full details.
 
 use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::typed_header::TypedHeader;
 use chrono::Duration;
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
-use mas_data_model::{SiteConfig, TokenFormatError, TokenType};
+use mas_data_model::{SiteConfig, TokenFormatError, TokenType, UserAgent};
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
+    honeytoken::HoneytokenRepository,
+    user::UserRepository,
     BoxClock, BoxRepository, BoxRng, Clock,
 };
 use serde::{Deserialize, Serialize};
@@ -41,6 +44,9 @@ pub enum RouteError {
 
     #[error("unknown session")]
     UnknownSession,
+
+    #[error("user is locked")]
+    UserLocked,
 }
 
 impl IntoResponse for RouteError {
@@ -57,6 +63,11 @@ impl IntoResponse for RouteError {
                 error: "Invalid refresh token",
                 status: StatusCode::UNAUTHORIZED,
             },
+            Self::UserLocked => MatrixError {
+                errcode: "M_USER_LOCKED",
+                error: "This account has been locked",
+                status: StatusCode::UNAUTHORIZED,
+            },
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -87,25 +98,44 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(site_config): State<SiteConfig>,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     Json(input): Json<RequestBody>,
 ) -> Result<impl IntoResponse, RouteError> {
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
     let token_type = TokenType::check(&input.refresh_token)?;
 
     if token_type != TokenType::CompatRefreshToken {
         return Err(RouteError::InvalidToken);
     }
 
-    let refresh_token = repo
+    let Some(refresh_token) = repo
         .compat_refresh_token()
         .find_by_token(&input.refresh_token)
         .await?
-        .ok_or(RouteError::InvalidToken)?;
+    else {
+        if let Some(honeytoken) = repo
+            .honeytoken()
+            .find_by_token(&input.refresh_token)
+            .await?
+        {
+            activity_tracker
+                .report_honeytoken(
+                    &clock,
+                    &honeytoken,
+                    "compat_refresh",
+                    user_agent.map(|ua| ua.raw),
+                )
+                .await;
+        }
+
+        return Err(RouteError::InvalidToken);
+    };
 
     if !refresh_token.is_valid() {
         return Err(RouteError::RefreshTokenConsumed);
     }
 
-    let session = repo
+    let mut session = repo
         .compat_session()
         .lookup(refresh_token.session_id)
         .await?
@@ -115,6 +145,30 @@ pub(crate) async fn post(
         return Err(RouteError::InvalidSession);
     }
 
+    let user = repo
+        .user()
+        .lookup(session.user_id)
+        .await?
+        .ok_or(RouteError::InvalidSession)?;
+
+    // Locked users can't refresh their tokens anymore
+    if !user.is_valid() {
+        return Err(RouteError::UserLocked);
+    }
+
+    if site_config.is_compat_session_expired(&session, &user, clock.now()) {
+        return Err(RouteError::InvalidSession);
+    }
+
+    // Record the user agent on each refresh, like for OAuth 2.0 sessions, so
+    // that it follows the client being updated
+    if let Some(user_agent) = user_agent {
+        session = repo
+            .compat_session()
+            .record_user_agent(session, user_agent)
+            .await?;
+    }
+
     activity_tracker
         .record_compat_session(&clock, &session)
         .await;
@@ -168,3 +222,105 @@ pub(crate) async fn post(
         expires_in_ms: expires_in,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::USER_AGENT, Request, StatusCode};
+    use mas_data_model::DeviceType;
+    use mas_storage::{
+        compat::{CompatAccessTokenRepository, CompatSessionRepository},
+        user::{UserPasswordRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use sqlx::PgPool;
+    use zeroize::Zeroizing;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Test that refreshing a session records the user agent of the client
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_records_user_agent(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(
+                &mut state.rng(),
+                Zeroizing::new("password".to_owned().into_bytes()),
+            )
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post("/_matrix/client/v3/login")
+            .header(USER_AGENT, "curl/8.0.0")
+            .json(serde_json::json!({
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": "alice",
+                },
+                "password": "password",
+                "refresh_token": true,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        let refresh_token = body["refresh_token"].as_str().unwrap();
+
+        // The client got updated, and now runs on a phone
+        let request = Request::post("/_matrix/client/v3/refresh")
+            .header(
+                USER_AGENT,
+                "Element X/1.2.3 (iPhone14,5; iOS 17.0; Scale/3.00)",
+            )
+            .json(serde_json::json!({
+                "refresh_token": refresh_token,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        let access_token = body["access_token"].as_str().unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .compat_access_token()
+            .find_by_token(access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .compat_session()
+            .lookup(access_token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let user_agent = session.user_agent.unwrap();
+        assert_eq!(
+            user_agent.raw,
+            "Element X/1.2.3 (iPhone14,5; iOS 17.0; Scale/3.00)"
+        );
+        assert_eq!(user_agent.name.as_deref(), Some("Element X"));
+        assert_eq!(user_agent.os.as_deref(), Some("iOS"));
+        assert_eq!(user_agent.device_type, DeviceType::Mobile);
+        repo.save().await.unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::typed_header::TypedHeader;
+use chrono::Duration;
+use hyper::StatusCode;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{AuditEventKind, Device, SiteConfig, TokenType, UserAgent};
+use mas_matrix::{BoxHomeserverConnection, ProvisionRequest};
+use mas_policy::{Policy, Requester};
+use mas_storage::{
+    audit::AuditEventRepository,
+    compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    user::{UserHomeserver, UserRepository},
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, skip_serializing_none, DurationMilliSeconds};
+use thiserror::Error;
+
+use super::MatrixError;
+use crate::{
+    impl_from_error_for_route, rate_limit::RegistrationLimitedError, BoundActivityTracker, Limiter,
+    RequesterFingerprint,
+};
+
+/// The characters used in the generated usernames of guests
+const GUEST_USERNAME_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+/// How many times to try to generate a username which isn't taken yet
+const GUEST_USERNAME_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RegistrationKind {
+    #[default]
+    User,
+    Guest,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Params {
+    #[serde(default)]
+    kind: RegistrationKind,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RequestBody {
+    #[serde(default)]
+    refresh_token: bool,
+}
+
+#[skip_serializing_none]
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseBody {
+    access_token: String,
+    device_id: Device,
+    user_id: String,
+    refresh_token: Option<String>,
+    #[serde_as(as = "Option<DurationMilliSeconds<i64>>")]
+    expires_in_ms: Option<Duration>,
+}
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("only guest registration is supported")]
+    UserRegistrationNotSupported,
+
+    #[error("guest registration is disabled")]
+    GuestRegistrationDisabled,
+
+    #[error("guest registration denied by the policy")]
+    PolicyDenied,
+
+    #[error("request rate limited")]
+    RateLimited(#[from] RegistrationLimitedError),
+
+    #[error("could not generate an available username")]
+    NoUsernameAvailable,
+
+    #[error("failed to provision the guest")]
+    ProvisionFailed(#[source] anyhow::Error),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_policy::EvaluationError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(_) | Self::NoUsernameAvailable | Self::ProvisionFailed(_) => {
+                MatrixError {
+                    errcode: "M_UNKNOWN",
+                    error: "Internal server error",
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                }
+            }
+            Self::UserRegistrationNotSupported => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Only guest registration is supported",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::GuestRegistrationDisabled | Self::PolicyDenied => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Guest access is disabled",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::RateLimited(_) => MatrixError {
+                errcode: "M_LIMIT_EXCEEDED",
+                error: "Too many registration attempts",
+                status: StatusCode::TOO_MANY_REQUESTS,
+            },
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+/// Generate a random username for a guest, like `guest-0a1b2c3d4e`
+fn generate_guest_username(rng: &mut impl Rng) -> String {
+    let suffix: String = (0..10)
+        .map(|_| char::from(*GUEST_USERNAME_CHARSET.choose(rng).unwrap()))
+        .collect();
+    format!("guest-{suffix}")
+}
+
+#[tracing::instrument(name = "handlers.compat.register.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    mut policy: Policy,
+    activity_tracker: BoundActivityTracker,
+    State(homeserver): State<BoxHomeserverConnection>,
+    State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
+    requester: RequesterFingerprint,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    Query(params): Query<Params>,
+    input: Option<Json<RequestBody>>,
+) -> Result<impl IntoResponse, RouteError> {
+    // Users register through the authorization server, this endpoint is only
+    // there for guests
+    if params.kind != RegistrationKind::Guest {
+        return Err(RouteError::UserRegistrationNotSupported);
+    }
+
+    if !site_config.guest_registration_enabled {
+        return Err(RouteError::GuestRegistrationDisabled);
+    }
+
+    let input = input.map(|Json(input)| input).unwrap_or_default();
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
+
+    limiter.check_registration(requester)?;
+
+    // Pick a username which is neither taken locally nor on the homeserver
+    let mut username = None;
+    for _ in 0..GUEST_USERNAME_ATTEMPTS {
+        let candidate = generate_guest_username(&mut rng);
+        if !repo.user().exists(UserHomeserver::Main, &candidate).await?
+            && homeserver
+                .is_localpart_available(&candidate)
+                .await
+                .map_err(RouteError::ProvisionFailed)?
+        {
+            username = Some(candidate);
+            break;
+        }
+    }
+    let username = username.ok_or(RouteError::NoUsernameAvailable)?;
+
+    let res = policy
+        .evaluate_guest_register(
+            &username,
+            &Requester::new(activity_tracker.ip(), raw_user_agent.clone()),
+        )
+        .await?;
+    if !res.valid() {
+        tracing::info!(violations = ?res.violations, "Guest registration denied by the policy");
+        return Err(RouteError::PolicyDenied);
+    }
+
+    let user = repo.user().add(&mut rng, &clock, username).await?;
+    let user = repo.user().set_guest(user).await?;
+
+    // The client uses the token straight away, so the guest has to exist on the
+    // homeserver before we respond
+    let mxid = homeserver.mxid(&user.username);
+    homeserver
+        .provision_user(&ProvisionRequest::new(&mxid, &user.sub))
+        .await
+        .map_err(RouteError::ProvisionFailed)?;
+
+    let device = Device::generate(&mut rng);
+    homeserver
+        .create_device(&mxid, device.as_str())
+        .await
+        .map_err(RouteError::ProvisionFailed)?;
+
+    let mut session = repo
+        .compat_session()
+        .add(&mut rng, &clock, &user, device, None, false)
+        .await?;
+
+    if let Some(user_agent) = user_agent {
+        session = repo
+            .compat_session()
+            .record_user_agent(session, user_agent)
+            .await?;
+    }
+
+    // If the client asked for a refreshable token, make it expire
+    let expires_in = if input.refresh_token {
+        Some(site_config.compat_token_ttl)
+    } else {
+        None
+    };
+
+    let access_token = TokenType::CompatAccessToken.generate(&mut rng);
+    let access_token = repo
+        .compat_access_token()
+        .add(&mut rng, &clock, &session, access_token, expires_in)
+        .await?;
+
+    let refresh_token = if input.refresh_token {
+        let refresh_token = TokenType::CompatRefreshToken.generate(&mut rng);
+        let refresh_token = repo
+            .compat_refresh_token()
+            .add(&mut rng, &clock, &session, &access_token, refresh_token)
+            .await?;
+        Some(refresh_token.token)
+    } else {
+        None
+    };
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::Login,
+            Some(user.id),
+            Some(user.id),
+            activity_tracker.ip(),
+            raw_user_agent,
+            serde_json::json!({
+                "method": "compat_guest",
+                "compat_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_registered(&mut rng, &clock, &user))
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_created(
+            &mut rng, &clock, &user, "compat", session.id,
+        ))
+        .await?;
+
+    repo.save().await?;
+
+    activity_tracker
+        .record_compat_session(&clock, &session)
+        .await;
+
+    Ok(Json(ResponseBody {
+        access_token: access_token.token,
+        device_id: session.device,
+        user_id: mxid,
+        refresh_token,
+        expires_in_ms: expires_in,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use mas_matrix::HomeserverConnection;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Test registering guests through the compatibility layer
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_guest_registration(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+
+        // Guest registration is disabled by default
+        let request =
+            Request::post("/_matrix/client/v3/register?kind=guest").json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let error: serde_json::Value = response.json();
+        assert_eq!(error["errcode"], "M_FORBIDDEN");
+
+        state.site_config.guest_registration_enabled = true;
+
+        // Regular users can't register through this endpoint
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "username": "alice",
+            "password": "hunter2",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let request =
+            Request::post("/_matrix/client/v3/register?kind=guest").json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: ResponseBody = response.json();
+        assert!(body.user_id.starts_with("@guest-"));
+        assert!(body.refresh_token.is_none());
+
+        // The guest exists on the homeserver straight away
+        state
+            .homeserver_connection
+            .query_user(&body.user_id)
+            .await
+            .unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let (localpart, _) = body.user_id[1..].split_once(':').unwrap();
+        let user = repo
+            .user()
+            .find_by_username(UserHomeserver::Main, localpart)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.is_guest);
+        assert!(!user.is_bot);
+    }
+}
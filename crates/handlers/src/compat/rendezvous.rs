@@ -0,0 +1,424 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Rendezvous sessions, as per MSC4108
+//!
+//! A device which wants to sign in shows a QR code pointing to a rendezvous
+//! session, and a device which is already signed in scans it. Both devices
+//! then establish a secure channel by exchanging messages through the
+//! session, which they use to hand off a device authorization grant to the
+//! new device. The messages are end-to-end encrypted, so the service only
+//! stores and relays them.
+
+use std::time::SystemTime;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::typed_header::TypedHeader;
+use headers::{
+    CacheControl, ContentType, ETag, Expires, HeaderMap, HeaderMapExt, HeaderValue, IfMatch,
+    IfNoneMatch, LastModified, Pragma,
+};
+use hyper::{header::CONTENT_TYPE, StatusCode};
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{RendezvousSession, SiteConfig};
+use mas_router::UrlBuilder;
+use mas_storage::{
+    rendezvous::RendezvousSessionRepository, BoxClock, BoxRepository, BoxRng, Clock,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ulid::Ulid;
+use url::Url;
+
+use super::MatrixError;
+use crate::impl_from_error_for_route;
+
+/// The maximum size of a message exchanged through a rendezvous session
+const MAX_CONTENT_LENGTH: usize = 4096;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateResponse {
+    url: Url,
+}
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("rendezvous sessions are disabled")]
+    Disabled,
+
+    #[error("rendezvous session not found")]
+    NotFound,
+
+    #[error("the content type must be text/plain")]
+    InvalidContentType,
+
+    #[error("the message is too large")]
+    TooLarge,
+
+    #[error("missing If-Match header")]
+    MissingIfMatch,
+
+    #[error("the session was updated concurrently")]
+    ConcurrentWrite,
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(_) => MatrixError {
+                errcode: "M_UNKNOWN",
+                error: "Internal server error",
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::Disabled => MatrixError {
+                errcode: "M_UNRECOGNIZED",
+                error: "Unrecognized request",
+                status: StatusCode::NOT_FOUND,
+            },
+            Self::NotFound => MatrixError {
+                errcode: "M_NOT_FOUND",
+                error: "Rendezvous session not found",
+                status: StatusCode::NOT_FOUND,
+            },
+            Self::InvalidContentType => MatrixError {
+                errcode: "M_INVALID_PARAM",
+                error: "Content-Type must be text/plain",
+                status: StatusCode::BAD_REQUEST,
+            },
+            Self::TooLarge => MatrixError {
+                errcode: "M_TOO_LARGE",
+                error: "Message too large",
+                status: StatusCode::PAYLOAD_TOO_LARGE,
+            },
+            Self::MissingIfMatch => MatrixError {
+                errcode: "M_MISSING_PARAM",
+                error: "Missing If-Match header",
+                status: StatusCode::BAD_REQUEST,
+            },
+            Self::ConcurrentWrite => MatrixError {
+                errcode: "M_CONCURRENT_WRITE",
+                error: "The session was updated concurrently",
+                status: StatusCode::PRECONDITION_FAILED,
+            },
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+/// Check the content type and size of a message, returning its content type
+fn check_message(
+    content_type: Option<TypedHeader<ContentType>>,
+    body: &Bytes,
+) -> Result<String, RouteError> {
+    let TypedHeader(content_type) = content_type.ok_or(RouteError::InvalidContentType)?;
+    let mime = mime::Mime::from(content_type);
+    if mime.type_() != mime::TEXT || mime.subtype() != mime::PLAIN {
+        return Err(RouteError::InvalidContentType);
+    }
+
+    if body.len() > MAX_CONTENT_LENGTH {
+        return Err(RouteError::TooLarge);
+    }
+
+    Ok(mime.to_string())
+}
+
+/// Find a session which didn't expire yet
+async fn find_session(
+    repo: &mut BoxRepository,
+    clock: &BoxClock,
+    id: Ulid,
+) -> Result<RendezvousSession, RouteError> {
+    repo.rendezvous_session()
+        .lookup(id)
+        .await?
+        .filter(|session| !session.is_expired(clock.now()))
+        .ok_or(RouteError::NotFound)
+}
+
+fn session_etag(session: &RendezvousSession) -> Result<ETag, RouteError> {
+    format!("\"{}\"", session.etag)
+        .parse()
+        .map_err(|e| RouteError::Internal(Box::new(e)))
+}
+
+/// The headers describing the current state of a session
+fn session_headers(session: &RendezvousSession) -> Result<HeaderMap, RouteError> {
+    let mut headers = HeaderMap::new();
+    headers.typed_insert(session_etag(session)?);
+    headers.typed_insert(Expires::from(SystemTime::from(session.expires_at)));
+    headers.typed_insert(LastModified::from(SystemTime::from(session.updated_at)));
+    headers.typed_insert(CacheControl::new().with_no_store());
+    headers.typed_insert(Pragma::no_cache());
+    Ok(headers)
+}
+
+#[tracing::instrument(name = "handlers.compat.rendezvous.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(site_config): State<SiteConfig>,
+    State(url_builder): State<UrlBuilder>,
+    content_type: Option<TypedHeader<ContentType>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, RouteError> {
+    let ttl = site_config.rendezvous_ttl.ok_or(RouteError::Disabled)?;
+    let content_type = check_message(content_type, &body)?;
+
+    let session = repo
+        .rendezvous_session()
+        .add(&mut rng, &clock, ttl, content_type, body.to_vec())
+        .await?;
+
+    repo.save().await?;
+
+    let url = url_builder.absolute_url_for(&mas_router::RendezvousSession::new(session.id));
+
+    Ok((
+        StatusCode::CREATED,
+        session_headers(&session)?,
+        Json(CreateResponse { url }),
+    ))
+}
+
+#[tracing::instrument(
+    name = "handlers.compat.rendezvous.get",
+    fields(rendezvous_session.id = %id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(site_config): State<SiteConfig>,
+    Path(id): Path<Ulid>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+) -> Result<impl IntoResponse, RouteError> {
+    if site_config.rendezvous_ttl.is_none() {
+        return Err(RouteError::Disabled);
+    }
+
+    let session = find_session(&mut repo, &clock, id).await?;
+    let headers = session_headers(&session)?;
+
+    // Let the device poll cheaply until the other one sends a new message
+    if let Some(TypedHeader(if_none_match)) = if_none_match {
+        if !if_none_match.precondition_passes(&session_etag(&session)?) {
+            return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+        }
+    }
+
+    let content_type = HeaderValue::try_from(session.content_type)
+        .map_err(|e| RouteError::Internal(Box::new(e)))?;
+
+    Ok((headers, [(CONTENT_TYPE, content_type)], session.content).into_response())
+}
+
+#[tracing::instrument(
+    name = "handlers.compat.rendezvous.put",
+    fields(rendezvous_session.id = %id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn put(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(site_config): State<SiteConfig>,
+    Path(id): Path<Ulid>,
+    if_match: Option<TypedHeader<IfMatch>>,
+    content_type: Option<TypedHeader<ContentType>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, RouteError> {
+    if site_config.rendezvous_ttl.is_none() {
+        return Err(RouteError::Disabled);
+    }
+
+    let TypedHeader(if_match) = if_match.ok_or(RouteError::MissingIfMatch)?;
+    let content_type = check_message(content_type, &body)?;
+
+    let session = find_session(&mut repo, &clock, id).await?;
+
+    // Make sure the device saw the last message before replacing it
+    if !if_match.precondition_passes(&session_etag(&session)?) {
+        return Err(RouteError::ConcurrentWrite);
+    }
+
+    let session = repo
+        .rendezvous_session()
+        .update(&mut rng, &clock, session, content_type, body.to_vec())
+        .await?
+        .ok_or(RouteError::ConcurrentWrite)?;
+
+    repo.save().await?;
+
+    Ok((StatusCode::ACCEPTED, session_headers(&session)?))
+}
+
+#[tracing::instrument(
+    name = "handlers.compat.rendezvous.delete",
+    fields(rendezvous_session.id = %id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn delete(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(site_config): State<SiteConfig>,
+    Path(id): Path<Ulid>,
+) -> Result<impl IntoResponse, RouteError> {
+    if site_config.rendezvous_ttl.is_none() {
+        return Err(RouteError::Disabled);
+    }
+
+    let session = find_session(&mut repo, &clock, id).await?;
+    repo.rendezvous_session().remove(session).await?;
+
+    repo.save().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{
+        header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+        Request,
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Builds the request with the given plain text message as body
+    fn text(builder: hyper::http::request::Builder, body: &str) -> Request<String> {
+        builder
+            .header(CONTENT_TYPE, "text/plain")
+            .body(body.to_owned())
+            .unwrap()
+    }
+
+    /// Test a full exchange between two devices through a rendezvous session
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_rendezvous(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let create_uri = "/_matrix/client/unstable/org.matrix.msc4108/rendezvous";
+
+        // The endpoints are disabled by default
+        let request = text(Request::post(create_uri), "hello");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        state.site_config.rendezvous_ttl = Some(Duration::try_minutes(1).unwrap());
+
+        // Only plain text messages are accepted
+        let request = Request::post(create_uri).json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_INVALID_PARAM");
+
+        let request = text(
+            Request::post(create_uri),
+            &"a".repeat(MAX_CONTENT_LENGTH + 1),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+        // The new device starts a session
+        let request = text(Request::post(create_uri), "hello");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let first_etag = response.headers()[ETAG].clone();
+        let body: CreateResponse = response.json();
+        let session_uri = body.url.path().to_owned();
+        assert!(session_uri.starts_with(create_uri));
+
+        // The other device reads the first message
+        let request = Request::get(&session_uri).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], first_etag);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/plain");
+        assert_eq!(response.body(), "hello");
+
+        // Polling without a new message doesn't return the content again
+        let request = Request::get(&session_uri)
+            .header(IF_NONE_MATCH, first_etag.clone())
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+
+        // Replacing the message requires the current ETag
+        let request = text(Request::put(&session_uri), "world");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_MISSING_PARAM");
+
+        let request = text(
+            Request::put(&session_uri).header(IF_MATCH, first_etag.clone()),
+            "world",
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::ACCEPTED);
+        let second_etag = response.headers()[ETAG].clone();
+        assert_ne!(first_etag, second_etag);
+
+        // A device which missed the last message can't overwrite it
+        let request = text(
+            Request::put(&session_uri).header(IF_MATCH, first_etag.clone()),
+            "stale",
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::PRECONDITION_FAILED);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_CONCURRENT_WRITE");
+
+        let request = Request::get(&session_uri)
+            .header(IF_NONE_MATCH, first_etag)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.body(), "world");
+
+        // Once deleted, the session is gone
+        let request = Request::delete(&session_uri).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+
+        let request = Request::get(&session_uri).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_NOT_FOUND");
+
+        // Sessions can't be used after they expired
+        let request = text(Request::post(create_uri), "hello");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let body: CreateResponse = response.json();
+
+        state.clock.advance(Duration::try_minutes(2).unwrap());
+        let request = Request::get(body.url.path()).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
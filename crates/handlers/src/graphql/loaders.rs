@@ -0,0 +1,126 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Batch loaders used by the resolvers, so that resolving a field on a list
+//! of objects doesn't do one database query per object.
+//!
+//! Lookups made while resolving the same level of a query are grouped
+//! together and done in a single query. The loaders don't cache anything, so
+//! they can be shared by all requests.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use mas_data_model::{Authentication, BrowserSession, Client, User};
+use mas_storage::{
+    oauth2::OAuth2ClientRepository,
+    user::{BrowserSessionRepository, UserRepository},
+    RepositoryError,
+};
+use ulid::Ulid;
+
+use super::state::SharedState;
+
+/// The batch loaders available to the resolvers
+pub struct Loaders {
+    /// Load OAuth 2.0 clients by their ID
+    pub oauth2_client: DataLoader<OAuth2ClientLoader>,
+
+    /// Load users by their ID
+    pub user: DataLoader<UserLoader>,
+
+    /// Load browser sessions by their ID
+    pub browser_session: DataLoader<BrowserSessionLoader>,
+
+    /// Load the last authentication of browser sessions by the session ID
+    pub last_authentication: DataLoader<LastAuthenticationLoader>,
+}
+
+impl Loaders {
+    #[must_use]
+    pub fn new(state: &SharedState) -> Self {
+        Self {
+            oauth2_client: DataLoader::new(OAuth2ClientLoader(state.clone()), tokio::spawn),
+            user: DataLoader::new(UserLoader(state.clone()), tokio::spawn),
+            browser_session: DataLoader::new(BrowserSessionLoader(state.clone()), tokio::spawn),
+            last_authentication: DataLoader::new(
+                LastAuthenticationLoader(state.clone()),
+                tokio::spawn,
+            ),
+        }
+    }
+}
+
+pub struct OAuth2ClientLoader(SharedState);
+
+impl Loader<Ulid> for OAuth2ClientLoader {
+    type Value = Client;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = self.0.repository().await?;
+        let clients = repo
+            .oauth2_client()
+            .load_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(clients.into_iter().collect())
+    }
+}
+
+pub struct UserLoader(SharedState);
+
+impl Loader<Ulid> for UserLoader {
+    type Value = User;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = self.0.repository().await?;
+        let users = repo
+            .user()
+            .load_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(users.into_iter().collect())
+    }
+}
+
+pub struct BrowserSessionLoader(SharedState);
+
+impl Loader<Ulid> for BrowserSessionLoader {
+    type Value = BrowserSession;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = self.0.repository().await?;
+        let browser_sessions = repo
+            .browser_session()
+            .load_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(browser_sessions.into_iter().collect())
+    }
+}
+
+pub struct LastAuthenticationLoader(SharedState);
+
+impl Loader<Ulid> for LastAuthenticationLoader {
+    type Value = Authentication;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = self.0.repository().await?;
+        let authentications = repo
+            .browser_session()
+            .get_last_authentication_batch(keys.iter().copied().collect())
+            .await?;
+        repo.cancel().await?;
+
+        Ok(authentications.into_iter().collect())
+    }
+}
@@ -6,7 +6,7 @@
 
 #![allow(clippy::module_name_repetitions)]
 
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 use async_graphql::{
     extensions::Tracing,
@@ -24,10 +24,11 @@ use axum::{
 use axum_extra::typed_header::TypedHeader;
 use chrono::{DateTime, Utc};
 use futures_util::TryStreamExt;
-use headers::{authorization::Bearer, Authorization, ContentType, HeaderValue};
+use headers::{authorization::Bearer, Authorization, ContentType, HeaderValue, UserAgent};
 use hyper::header::CACHE_CONTROL;
 use mas_axum_utils::{
-    cookies::CookieJar, sentry::SentryEventID, FancyError, SessionInfo, SessionInfoExt,
+    client_certificate::ClientCertificate, cookies::CookieJar, sentry::SentryEventID, FancyError,
+    SessionInfo, SessionInfoExt,
 };
 use mas_data_model::{BrowserSession, Session, SiteConfig, User};
 use mas_matrix::HomeserverConnection;
@@ -41,18 +42,25 @@ use sqlx::PgPool;
 use tracing::{info_span, Instrument};
 use ulid::Ulid;
 
+mod loaders;
 mod model;
 mod mutations;
 mod query;
 mod state;
 
-pub use self::state::{BoxState, State};
+pub use self::{
+    loaders::Loaders,
+    state::{SharedState, State},
+};
 use self::{
     model::{CreationEvent, Node},
     mutations::Mutation,
     query::Query,
 };
-use crate::{impl_from_error_for_route, passwords::PasswordManager, BoundActivityTracker};
+use crate::{
+    admin_elevation::has_admin_privileges, impl_from_error_for_route, passwords::PasswordManager,
+    BoundActivityTracker,
+};
 
 #[cfg(test)]
 mod tests;
@@ -66,6 +74,7 @@ pub struct ExtraRouterParameters {
 
 struct GraphQLState {
     pool: PgPool,
+    replica_pool: Option<PgPool>,
     homeserver_connection: Arc<dyn HomeserverConnection<Error = anyhow::Error>>,
     policy_factory: Arc<PolicyFactory>,
     site_config: SiteConfig,
@@ -82,6 +91,15 @@ impl state::State for GraphQLState {
         Ok(repo.boxed())
     }
 
+    async fn read_only_repository(&self) -> Result<BoxRepository, RepositoryError> {
+        let pool = self.replica_pool.as_ref().unwrap_or(&self.pool);
+        let repo = PgRepository::from_pool_read_only(pool)
+            .await
+            .map_err(RepositoryError::from_error)?;
+
+        Ok(repo.boxed())
+    }
+
     async fn policy(&self) -> Result<Policy, InstantiateError> {
         self.policy_factory.instantiate().await
     }
@@ -115,6 +133,7 @@ impl state::State for GraphQLState {
 #[must_use]
 pub fn schema(
     pool: &PgPool,
+    replica_pool: Option<&PgPool>,
     policy_factory: &Arc<PolicyFactory>,
     homeserver_connection: impl HomeserverConnection<Error = anyhow::Error> + 'static,
     site_config: SiteConfig,
@@ -122,14 +141,20 @@ pub fn schema(
 ) -> Schema {
     let state = GraphQLState {
         pool: pool.clone(),
+        replica_pool: replica_pool.cloned(),
         policy_factory: Arc::clone(policy_factory),
         homeserver_connection: Arc::new(homeserver_connection),
         site_config,
         password_manager,
     };
-    let state: BoxState = Box::new(state);
-
-    schema_builder().extension(Tracing).data(state).finish()
+    let state: SharedState = Arc::new(state);
+    let loaders = Loaders::new(&state);
+
+    schema_builder()
+        .extension(Tracing)
+        .data(state)
+        .data(loaders)
+        .finish()
 }
 
 fn span_for_graphql_request(request: &async_graphql::Request) -> tracing::Span {
@@ -218,10 +243,12 @@ impl IntoResponse for RouteError {
 async fn get_requester(
     undocumented_oauth2_access: bool,
     clock: &impl Clock,
+    site_config: &SiteConfig,
     activity_tracker: &BoundActivityTracker,
     mut repo: BoxRepository,
     session_info: SessionInfo,
     token: Option<&str>,
+    client_certificate: Option<&ClientCertificate>,
 ) -> Result<Requester, RouteError> {
     let requester = if let Some(token) = token {
         // If we haven't enabled undocumented_oauth2_access on the listener, we bail out
@@ -241,6 +268,9 @@ async fn get_requester(
             .await?
             .ok_or(RouteError::LoadFailed)?;
 
+        // Tokens restricted to a narrower scope only grant that scope
+        let session = token.restrict_session(session);
+
         activity_tracker
             .record_oauth2_session(clock, &session)
             .await;
@@ -264,13 +294,32 @@ async fn get_requester(
             return Err(RouteError::InvalidToken);
         }
 
+        // This API only accepts bearer tokens, so DPoP-bound tokens can't be used
+        if token.dpop_jkt.is_some() {
+            return Err(RouteError::InvalidToken);
+        }
+
+        // Certificate-bound tokens must be presented with the same certificate
+        if let Some(x5t_s256) = &token.x5t_s256 {
+            let thumbprint = client_certificate.map(ClientCertificate::thumbprint);
+            if thumbprint.as_ref() != Some(x5t_s256) {
+                return Err(RouteError::InvalidToken);
+            }
+        }
+
         if !session.scope.contains("urn:mas:graphql:*") {
             return Err(RouteError::MissingScope);
         }
 
-        Requester::OAuth2Session(Box::new((session, user)))
+        // Users only keep their admin privileges for a while after they
+        // authenticated, if configured so
+        let is_admin = has_admin_privileges(&mut repo, clock, site_config, &session).await?;
+
+        Requester::OAuth2Session(Box::new((session, user, is_admin)))
     } else {
-        let maybe_session = session_info.load_session(&mut repo).await?;
+        let maybe_session = session_info
+            .load_session(&mut repo, clock, site_config)
+            .await?;
 
         if let Some(session) = maybe_session.as_ref() {
             activity_tracker
@@ -289,12 +338,15 @@ pub async fn post(
     Extension(ExtraRouterParameters {
         undocumented_oauth2_access,
     }): Extension<ExtraRouterParameters>,
+    AxumState(site_config): AxumState<SiteConfig>,
     clock: BoxClock,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     content_type: Option<TypedHeader<ContentType>>,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    client_certificate: Option<Extension<ClientCertificate>>,
     body: Body,
 ) -> Result<impl IntoResponse, RouteError> {
     let body = body.into_data_stream();
@@ -305,10 +357,12 @@ pub async fn post(
     let requester = get_requester(
         undocumented_oauth2_access,
         &clock,
+        &site_config,
         &activity_tracker,
         repo,
         session_info,
         token,
+        client_certificate.as_ref().map(|Extension(c)| c),
     )
     .await?;
 
@@ -321,7 +375,11 @@ pub async fn post(
         MultipartOptions::default(),
     )
     .await?
-    .data(requester); // XXX: this should probably return another error response?
+    .data(requester) // XXX: this should probably return another error response?
+    .data(RequestMetadata {
+        ip_address: activity_tracker.ip(),
+        user_agent: user_agent.map(|TypedHeader(ua)| ua.as_str().to_owned()),
+    });
 
     let span = span_for_graphql_request(&request);
     let response = schema.execute(request).instrument(span).await;
@@ -342,11 +400,14 @@ pub async fn get(
     Extension(ExtraRouterParameters {
         undocumented_oauth2_access,
     }): Extension<ExtraRouterParameters>,
+    AxumState(site_config): AxumState<SiteConfig>,
     clock: BoxClock,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    client_certificate: Option<Extension<ClientCertificate>>,
     RawQuery(query): RawQuery,
 ) -> Result<impl IntoResponse, FancyError> {
     let token = authorization
@@ -356,15 +417,21 @@ pub async fn get(
     let requester = get_requester(
         undocumented_oauth2_access,
         &clock,
+        &site_config,
         &activity_tracker,
         repo,
         session_info,
         token,
+        client_certificate.as_ref().map(|Extension(c)| c),
     )
     .await?;
 
-    let request =
-        async_graphql::http::parse_query_string(&query.unwrap_or_default())?.data(requester);
+    let request = async_graphql::http::parse_query_string(&query.unwrap_or_default())?
+        .data(requester)
+        .data(RequestMetadata {
+            ip_address: activity_tracker.ip(),
+            user_agent: user_agent.map(|TypedHeader(ua)| ua.as_str().to_owned()),
+        });
 
     let span = span_for_graphql_request(&request);
     let response = schema.execute(request).instrument(span).await;
@@ -396,6 +463,16 @@ pub fn schema_builder() -> SchemaBuilder {
         .register_output_type::<CreationEvent>()
 }
 
+/// Information about the HTTP request, recorded in the audit log
+#[derive(Debug, Clone, Default)]
+pub struct RequestMetadata {
+    /// The IP address of the requester, if known
+    pub ip_address: Option<IpAddr>,
+
+    /// The raw user agent of the requester, if known
+    pub user_agent: Option<String>,
+}
+
 /// The identity of the requester.
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum Requester {
@@ -406,8 +483,9 @@ pub enum Requester {
     /// The requester is a browser session, stored in a cookie.
     BrowserSession(Box<BrowserSession>),
 
-    /// The requester is a `OAuth2` session, with an access token.
-    OAuth2Session(Box<(Session, Option<User>)>),
+    /// The requester is a `OAuth2` session, with an access token, and whether
+    /// it currently has admin privileges.
+    OAuth2Session(Box<(Session, Option<User>, bool)>),
 }
 
 trait OwnerId {
@@ -444,6 +522,12 @@ impl OwnerId for mas_data_model::CompatSession {
     }
 }
 
+impl OwnerId for mas_data_model::UserTrustedBrowser {
+    fn owner_id(&self) -> Option<Ulid> {
+        Some(self.user_id)
+    }
+}
+
 impl OwnerId for mas_data_model::UpstreamOAuthLink {
     fn owner_id(&self) -> Option<Ulid> {
         self.user_id
@@ -501,13 +585,28 @@ impl Requester {
         user.id == owner_id
     }
 
+    /// Returns true if the requester can manage the sessions of the given
+    /// user.
+    ///
+    /// The sessions of a bot can only be managed by its owner and by admins,
+    /// not by the bot itself.
+    fn can_manage_sessions_of(&self, user: &User) -> bool {
+        if !user.is_bot {
+            return self.is_owner_or_admin(user);
+        }
+
+        if self.is_admin() {
+            return true;
+        }
+
+        self.user().is_some_and(|owner| user.is_bot_owned_by(owner))
+    }
+
     fn is_admin(&self) -> bool {
         match self {
-            Self::OAuth2Session(tuple) => {
-                // TODO: is this the right scope?
-                // This has to be in sync with the policy
-                tuple.0.scope.contains("urn:mas:admin")
-            }
+            // This was checked against the `urn:mas:admin` scope and the
+            // admin elevation TTL when loading the session
+            Self::OAuth2Session(tuple) => tuple.2,
             Self::BrowserSession(_) | Self::Anonymous => false,
         }
     }
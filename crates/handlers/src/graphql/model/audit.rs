@@ -0,0 +1,159 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use anyhow::Context as _;
+use async_graphql::{Context, Description, Enum, Object, ID};
+use chrono::{DateTime, Utc};
+use ulid::Ulid;
+
+use super::{NodeType, User};
+use crate::graphql::state::ContextExt;
+
+/// The kind of an audit event.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum AuditEventKind {
+    /// A user successfully logged in.
+    Login,
+
+    /// A user failed to log in.
+    LoginFailed,
+
+    /// The password of a user was changed.
+    PasswordChanged,
+
+    /// A session was ended.
+    SessionEnded,
+
+    /// An administrator performed an action on a user.
+    AdminAction,
+
+    /// An emergency access link was issued for an administrator.
+    EmergencyAccessIssued,
+
+    /// A refresh token was used more than once, and its session was ended.
+    RefreshTokenReused,
+
+    /// A user authorized a client to access their account.
+    ClientAuthorized,
+
+    /// A honeytoken was used.
+    HoneytokenUsed,
+
+    /// A login looked suspicious.
+    SuspiciousLogin,
+}
+
+impl From<mas_data_model::AuditEventKind> for AuditEventKind {
+    fn from(kind: mas_data_model::AuditEventKind) -> Self {
+        match kind {
+            mas_data_model::AuditEventKind::Login => Self::Login,
+            mas_data_model::AuditEventKind::LoginFailed => Self::LoginFailed,
+            mas_data_model::AuditEventKind::PasswordChanged => Self::PasswordChanged,
+            mas_data_model::AuditEventKind::SessionEnded => Self::SessionEnded,
+            mas_data_model::AuditEventKind::AdminAction => Self::AdminAction,
+            mas_data_model::AuditEventKind::EmergencyAccessIssued => Self::EmergencyAccessIssued,
+            mas_data_model::AuditEventKind::RefreshTokenReused => Self::RefreshTokenReused,
+            mas_data_model::AuditEventKind::ClientAuthorized => Self::ClientAuthorized,
+            mas_data_model::AuditEventKind::HoneytokenUsed => Self::HoneytokenUsed,
+            mas_data_model::AuditEventKind::SuspiciousLogin => Self::SuspiciousLogin,
+        }
+    }
+}
+
+impl From<AuditEventKind> for mas_data_model::AuditEventKind {
+    fn from(kind: AuditEventKind) -> Self {
+        match kind {
+            AuditEventKind::Login => Self::Login,
+            AuditEventKind::LoginFailed => Self::LoginFailed,
+            AuditEventKind::PasswordChanged => Self::PasswordChanged,
+            AuditEventKind::SessionEnded => Self::SessionEnded,
+            AuditEventKind::AdminAction => Self::AdminAction,
+            AuditEventKind::EmergencyAccessIssued => Self::EmergencyAccessIssued,
+            AuditEventKind::RefreshTokenReused => Self::RefreshTokenReused,
+            AuditEventKind::ClientAuthorized => Self::ClientAuthorized,
+            AuditEventKind::HoneytokenUsed => Self::HoneytokenUsed,
+            AuditEventKind::SuspiciousLogin => Self::SuspiciousLogin,
+        }
+    }
+}
+
+/// A security-relevant event recorded in the audit log.
+#[derive(Description)]
+pub struct AuditEvent(pub mas_data_model::AuditEvent);
+
+impl AuditEvent {
+    async fn load_user(
+        ctx: &Context<'_>,
+        user_id: Option<Ulid>,
+    ) -> Result<Option<User>, async_graphql::Error> {
+        let Some(user_id) = user_id else {
+            return Ok(None);
+        };
+
+        let state = ctx.state();
+        let mut repo = state.read_only_repository().await?;
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .context("Could not load user")?;
+        repo.cancel().await?;
+
+        Ok(Some(User(user)))
+    }
+}
+
+#[Object(use_type_description)]
+impl AuditEvent {
+    /// ID of the object.
+    pub async fn id(&self) -> ID {
+        NodeType::AuditEvent.id(self.0.id)
+    }
+
+    /// When the event was recorded.
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// The kind of event.
+    pub async fn kind(&self) -> AuditEventKind {
+        self.0.kind.into()
+    }
+
+    /// The user who performed the action, if any.
+    pub async fn actor(&self, ctx: &Context<'_>) -> Result<Option<User>, async_graphql::Error> {
+        Self::load_user(ctx, self.0.actor_user_id).await
+    }
+
+    /// The user the action was performed on, if any.
+    pub async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>, async_graphql::Error> {
+        Self::load_user(ctx, self.0.user_id).await
+    }
+
+    /// The IP address of the requester, if known.
+    pub async fn ip_address(&self) -> Option<String> {
+        self.0.ip_address.map(|ip| ip.to_string())
+    }
+
+    /// The user agent of the requester, if known.
+    pub async fn user_agent(&self) -> Option<&str> {
+        self.0.user_agent.as_deref()
+    }
+
+    /// Additional details about the event, as a JSON-encoded object.
+    pub async fn details(&self) -> String {
+        self.0.details.to_string()
+    }
+
+    /// The hash of the previous event in the chain, if any.
+    pub async fn previous_hash(&self) -> Option<&str> {
+        self.0.previous_hash.as_deref()
+    }
+
+    /// The hash of this event, which includes the previous hash.
+    pub async fn hash(&self) -> &str {
+        &self.0.hash
+    }
+}
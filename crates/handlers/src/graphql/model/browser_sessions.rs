@@ -10,9 +10,7 @@ use async_graphql::{
 };
 use chrono::{DateTime, Utc};
 use mas_data_model::Device;
-use mas_storage::{
-    app_session::AppSessionFilter, user::BrowserSessionRepository, Pagination, RepositoryAccess,
-};
+use mas_storage::{app_session::AppSessionFilter, pagination::Count, Pagination, RepositoryAccess};
 
 use super::{
     AppSession, CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session, PreloadedTotalCount,
@@ -47,16 +45,12 @@ impl BrowserSession {
         &self,
         ctx: &Context<'_>,
     ) -> Result<Option<Authentication>, async_graphql::Error> {
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-
-        let last_authentication = repo
-            .browser_session()
-            .get_last_authentication(&self.0)
+        let last_authentication = ctx
+            .loaders()
+            .last_authentication
+            .load_one(self.0.id)
             .await?;
 
-        repo.cancel().await?;
-
         Ok(last_authentication.map(Authentication))
     }
 
@@ -94,6 +88,11 @@ impl BrowserSession {
         self.0.last_active_at
     }
 
+    /// The tags the user gave to the session.
+    pub async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+
     /// Get the list of both compat and OAuth 2.0 sessions started by this
     /// browser session, chronologically sorted
     #[allow(clippy::too_many_arguments)]
@@ -153,7 +152,7 @@ impl BrowserSession {
                 let page = repo.app_session().list(filter, pagination).await?;
 
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.app_session().count(filter).await?)
+                    Some(Count::Exact(repo.app_session().count(filter).await?))
                 } else {
                     None
                 };
@@ -7,7 +7,7 @@
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, Object, ID};
 use chrono::{DateTime, Utc};
-use mas_storage::{compat::CompatSessionRepository, user::UserRepository};
+use mas_storage::compat::CompatSessionRepository;
 use url::Url;
 
 use super::{BrowserSession, NodeType, SessionState, User, UserAgent};
@@ -68,14 +68,12 @@ impl CompatSession {
 
     /// The user authorized for this session.
     async fn user(&self, ctx: &Context<'_>) -> Result<User, async_graphql::Error> {
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let user = repo
-            .user()
-            .lookup(self.session.user_id)
+        let user = ctx
+            .loaders()
+            .user
+            .load_one(self.session.user_id)
             .await?
             .context("Could not load user")?;
-        repo.cancel().await?;
 
         Ok(User(user))
     }
@@ -131,14 +129,12 @@ impl CompatSession {
             return Ok(None);
         };
 
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let browser_session = repo
-            .browser_session()
-            .lookup(user_session_id)
+        let browser_session = ctx
+            .loaders()
+            .browser_session
+            .load_one(user_session_id)
             .await?
             .context("Could not load browser session")?;
-        repo.cancel().await?;
 
         Ok(Some(BrowserSession(browser_session)))
     }
@@ -160,6 +156,11 @@ impl CompatSession {
     pub async fn last_active_at(&self) -> Option<DateTime<Utc>> {
         self.session.last_active_at
     }
+
+    /// The tags the user gave to the session.
+    pub async fn tags(&self) -> &[String] {
+        &self.session.tags
+    }
 }
 
 /// A compat SSO login represents a login done through the legacy Matrix login
@@ -5,6 +5,7 @@
 // Please see LICENSE in the repository root for full details.
 
 use async_graphql::SimpleObject;
+use mas_data_model::User;
 use mas_matrix::HomeserverConnection;
 
 #[derive(SimpleObject)]
@@ -25,9 +26,9 @@ pub struct MatrixUser {
 impl MatrixUser {
     pub(crate) async fn load<C: HomeserverConnection + ?Sized>(
         conn: &C,
-        user: &str,
+        user: &User,
     ) -> Result<MatrixUser, C::Error> {
-        let mxid = conn.mxid(user);
+        let mxid = conn.mxid_on(user.homeserver.as_deref(), &user.username);
 
         let info = conn.query_user(&mxid).await?;
 
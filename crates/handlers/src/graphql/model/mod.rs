@@ -6,7 +6,9 @@
 
 use async_graphql::{Enum, Interface, Object, SimpleObject};
 use chrono::{DateTime, Utc};
+use mas_storage::pagination::Count;
 
+mod audit;
 mod browser_sessions;
 mod compat_sessions;
 mod cursor;
@@ -19,14 +21,18 @@ mod users;
 mod viewer;
 
 pub use self::{
+    audit::{AuditEvent, AuditEventKind},
     browser_sessions::{Authentication, BrowserSession},
     compat_sessions::{CompatSession, CompatSsoLogin},
     cursor::{Cursor, NodeCursor},
     node::{Node, NodeType},
-    oauth::{OAuth2Client, OAuth2Session},
+    oauth::{KioskDevice, OAuth2Client, OAuth2Consent, OAuth2Session},
     site_config::{SiteConfig, SITE_CONFIG_ID},
     upstream_oauth::{UpstreamOAuth2Link, UpstreamOAuth2Provider},
-    users::{AppSession, User, UserEmail},
+    users::{
+        AppSession, DigestFrequency, NotificationPreferences, TermsAcceptance, TrustedBrowser,
+        User, UserEmail,
+    },
     viewer::{Anonymous, Viewer, ViewerSession},
 };
 
@@ -47,15 +53,34 @@ pub enum CreationEvent {
     OAuth2Session(Box<OAuth2Session>),
 }
 
-pub struct PreloadedTotalCount(pub Option<usize>);
+pub struct PreloadedTotalCount(pub Option<Count>);
 
 #[Object]
 impl PreloadedTotalCount {
     /// Identifies the total count of items in the connection.
     async fn total_count(&self) -> Result<usize, async_graphql::Error> {
         self.0
+            .map(Count::value)
             .ok_or_else(|| async_graphql::Error::new("total count not preloaded"))
     }
+
+    /// Whether the total count is an estimation. This only happens when the
+    /// connection was requested with the `ESTIMATED` count mode.
+    async fn total_count_estimated(&self) -> bool {
+        self.0.is_some_and(Count::is_estimated)
+    }
+}
+
+/// How to count the total number of items in a connection
+#[derive(Enum, Copy, Clone, Default, Eq, PartialEq)]
+pub enum CountMode {
+    /// Count the items exactly.
+    #[default]
+    Exact,
+
+    /// Only count the items exactly if there are few of them, else estimate
+    /// their number.
+    Estimated,
 }
 
 /// The state of a session
@@ -16,12 +16,15 @@ use super::{
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum NodeType {
+    AuditEvent,
     Authentication,
     BrowserSession,
     CompatSession,
     CompatSsoLogin,
+    KioskDevice,
     OAuth2Client,
     OAuth2Session,
+    TrustedBrowser,
     UpstreamOAuth2Provider,
     UpstreamOAuth2Link,
     User,
@@ -40,12 +43,15 @@ pub enum InvalidID {
 impl NodeType {
     fn to_prefix(self) -> &'static str {
         match self {
+            NodeType::AuditEvent => "audit_event",
             NodeType::Authentication => "authentication",
             NodeType::BrowserSession => "browser_session",
             NodeType::CompatSession => "compat_session",
             NodeType::CompatSsoLogin => "compat_sso_login",
+            NodeType::KioskDevice => "kiosk_device",
             NodeType::OAuth2Client => "oauth2_client",
             NodeType::OAuth2Session => "oauth2_session",
+            NodeType::TrustedBrowser => "trusted_browser",
             NodeType::UpstreamOAuth2Provider => "upstream_oauth2_provider",
             NodeType::UpstreamOAuth2Link => "upstream_oauth2_link",
             NodeType::User => "user",
@@ -55,12 +61,15 @@ impl NodeType {
 
     fn from_prefix(prefix: &str) -> Option<Self> {
         match prefix {
+            "audit_event" => Some(NodeType::AuditEvent),
             "authentication" => Some(NodeType::Authentication),
             "browser_session" => Some(NodeType::BrowserSession),
             "compat_session" => Some(NodeType::CompatSession),
             "compat_sso_login" => Some(NodeType::CompatSsoLogin),
+            "kiosk_device" => Some(NodeType::KioskDevice),
             "oauth2_client" => Some(NodeType::OAuth2Client),
             "oauth2_session" => Some(NodeType::OAuth2Session),
+            "trusted_browser" => Some(NodeType::TrustedBrowser),
             "upstream_oauth2_provider" => Some(NodeType::UpstreamOAuth2Provider),
             "upstream_oauth2_link" => Some(NodeType::UpstreamOAuth2Link),
             "user" => Some(NodeType::User),
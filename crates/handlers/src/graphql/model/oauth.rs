@@ -7,9 +7,8 @@
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, Object, ID};
 use chrono::{DateTime, Utc};
-use mas_storage::{oauth2::OAuth2ClientRepository, user::BrowserSessionRepository};
-use oauth2_types::{oidc::ApplicationType, scope::Scope};
-use ulid::Ulid;
+use mas_storage::oauth2::{OAuth2ClientRepository, OAuth2SessionRepository};
+use oauth2_types::oidc::ApplicationType;
 use url::Url;
 
 use super::{BrowserSession, NodeType, SessionState, User, UserAgent};
@@ -29,14 +28,12 @@ impl OAuth2Session {
 
     /// OAuth 2.0 client used by this session.
     pub async fn client(&self, ctx: &Context<'_>) -> Result<OAuth2Client, async_graphql::Error> {
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let client = repo
-            .oauth2_client()
-            .lookup(self.0.client_id)
+        let client = ctx
+            .loaders()
+            .oauth2_client
+            .load_one(self.0.client_id)
             .await?
             .context("Could not load client")?;
-        repo.cancel().await?;
 
         Ok(OAuth2Client(client))
     }
@@ -81,21 +78,18 @@ impl OAuth2Session {
             return Ok(None);
         };
 
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let browser_session = repo
-            .browser_session()
-            .lookup(user_session_id)
+        let browser_session = ctx
+            .loaders()
+            .browser_session
+            .load_one(user_session_id)
             .await?
             .context("Could not load browser session")?;
-        repo.cancel().await?;
 
         Ok(Some(BrowserSession(browser_session)))
     }
 
     /// User authorized for this session.
     pub async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>, async_graphql::Error> {
-        let state = ctx.state();
         let Some(user_id) = self.0.user_id else {
             return Ok(None);
         };
@@ -104,13 +98,12 @@ impl OAuth2Session {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
-        let mut repo = state.repository().await?;
-        let user = repo
-            .user()
-            .lookup(user_id)
+        let user = ctx
+            .loaders()
+            .user
+            .load_one(user_id)
             .await?
             .context("Could not load user")?;
-        repo.cancel().await?;
 
         Ok(Some(User(user)))
     }
@@ -124,6 +117,11 @@ impl OAuth2Session {
     pub async fn last_active_at(&self) -> Option<DateTime<Utc>> {
         self.0.last_active_at
     }
+
+    /// The tags the user gave to the session.
+    pub async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
 }
 
 /// The application type advertised by the client.
@@ -190,34 +188,118 @@ impl OAuth2Client {
             ApplicationType::Unknown(_) => None,
         }
     }
+
+    /// The consent the current user gave to this client, if any.
+    pub async fn viewer_consent(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<OAuth2Consent>, async_graphql::Error> {
+        let Some(user) = ctx.requester().user() else {
+            return Ok(None);
+        };
+
+        let state = ctx.state();
+        let mut repo = state.read_only_repository().await?;
+        let consents = repo.oauth2_client().list_consents_for_user(user).await?;
+        repo.cancel().await?;
+
+        let consent = consents
+            .into_iter()
+            .find(|consent| consent.client_id == self.0.id)
+            .map(OAuth2Consent);
+
+        Ok(consent)
+    }
 }
 
 /// An OAuth 2.0 consent represents the scope a user consented to grant to a
 /// client.
 #[derive(Description)]
-pub struct OAuth2Consent {
-    scope: Scope,
-    client_id: Ulid,
-}
+pub struct OAuth2Consent(pub mas_data_model::ClientConsent);
 
 #[Object(use_type_description)]
 impl OAuth2Consent {
     /// Scope consented by the user for this client.
     pub async fn scope(&self) -> String {
-        self.scope.to_string()
+        self.0.scope.to_string()
+    }
+
+    /// When the user last gave or refreshed their consent.
+    pub async fn consented_at(&self) -> DateTime<Utc> {
+        self.0.consented_at
+    }
+
+    /// The last time one of the sessions of the user with this client was
+    /// active.
+    pub async fn last_active_at(&self) -> Option<DateTime<Utc>> {
+        self.0.last_active_at
     }
 
     /// OAuth 2.0 client for which the user granted access.
     pub async fn client(&self, ctx: &Context<'_>) -> Result<OAuth2Client, async_graphql::Error> {
-        let state = ctx.state();
-        let mut repo = state.repository().await?;
-        let client = repo
-            .oauth2_client()
-            .lookup(self.client_id)
+        let client = ctx
+            .loaders()
+            .oauth2_client
+            .load_one(self.0.client_id)
+            .await?
+            .context("Could not load client")?;
+
+        Ok(OAuth2Client(client))
+    }
+}
+
+/// A device shared between users, registered once by a kiosk client, on which
+/// users log in one at a time.
+#[derive(Description)]
+pub struct KioskDevice(pub mas_data_model::KioskDevice);
+
+#[Object(use_type_description)]
+impl KioskDevice {
+    /// ID of the object.
+    pub async fn id(&self) -> ID {
+        NodeType::KioskDevice.id(self.0.id)
+    }
+
+    /// The Matrix device ID given to the sessions of the users logged in on
+    /// this device.
+    pub async fn device_id(&self) -> &str {
+        self.0.device.as_str()
+    }
+
+    /// When the device was registered.
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// The kiosk client which registered the device.
+    pub async fn client(&self, ctx: &Context<'_>) -> Result<OAuth2Client, async_graphql::Error> {
+        let client = ctx
+            .loaders()
+            .oauth2_client
+            .load_one(self.0.client_id)
             .await?
             .context("Could not load client")?;
-        repo.cancel().await?;
 
         Ok(OAuth2Client(client))
     }
+
+    /// The session of the user currently logged in on the device, if any.
+    pub async fn current_session(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<OAuth2Session>, async_graphql::Error> {
+        let Some(session_id) = self.0.current_session_id else {
+            return Ok(None);
+        };
+
+        let state = ctx.state();
+        let mut repo = state.read_only_repository().await?;
+        let session = repo.oauth2_session().lookup(session_id).await?;
+        repo.cancel().await?;
+
+        // The session may have expired since the user logged in
+        Ok(session
+            .filter(|session| session.is_valid())
+            .map(OAuth2Session))
+    }
 }
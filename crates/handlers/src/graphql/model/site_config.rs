@@ -28,6 +28,10 @@ pub struct SiteConfig {
     /// The URL to the terms of service.
     tos_uri: Option<Url>,
 
+    /// Whether users have to accept the current terms of service, and accept
+    /// them again when they change.
+    terms_acceptance_required: bool,
+
     /// Imprint to show in the footer.
     imprint: Option<String>,
 
@@ -50,6 +54,13 @@ pub struct SiteConfig {
     /// The exact scorer (including dictionaries and other data tables)
     /// in use is <https://crates.io/crates/zxcvbn>.
     minimum_password_complexity: u8,
+
+    /// Whether users can subscribe to a periodic email digest of the activity
+    /// on their account.
+    activity_digest_enabled: bool,
+
+    /// Whether users can delete their own account.
+    account_deletion_allowed: bool,
 }
 
 #[derive(SimpleObject)]
@@ -87,13 +98,18 @@ impl SiteConfig {
             server_name: data_model.server_name.clone(),
             policy_uri: data_model.policy_uri.clone(),
             tos_uri: data_model.tos_uri.clone(),
+            terms_acceptance_required: data_model.required_terms().is_some(),
             imprint: data_model.imprint.clone(),
             email_change_allowed: data_model.email_change_allowed,
             display_name_change_allowed: data_model.displayname_change_allowed,
             password_login_enabled: data_model.password_login_enabled,
-            password_change_allowed: data_model.password_change_allowed,
+            // Users can't change their password if they can't log in with it
+            password_change_allowed: data_model.password_change_allowed
+                && data_model.password_login_enabled,
             password_registration_enabled: data_model.password_registration_enabled,
             minimum_password_complexity: data_model.minimum_password_complexity,
+            activity_digest_enabled: data_model.activity_digest_interval.is_some(),
+            account_deletion_allowed: data_model.account_deletion_grace_period.is_some(),
         }
     }
 }
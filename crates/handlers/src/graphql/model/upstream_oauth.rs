@@ -5,9 +5,12 @@
 // Please see LICENSE in the repository root for full details.
 
 use anyhow::Context as _;
-use async_graphql::{Context, Object, ID};
+use async_graphql::{Context, Description, Enum, Object, SimpleObject, ID};
 use chrono::{DateTime, Utc};
-use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, user::UserRepository};
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthFunnelRepository, UpstreamOAuthProviderRepository},
+    user::UserRepository,
+};
 
 use super::{NodeType, User};
 use crate::graphql::state::ContextExt;
@@ -45,6 +48,120 @@ impl UpstreamOAuth2Provider {
     pub async fn client_id(&self) -> &str {
         &self.provider.client_id
     }
+
+    /// How many times users reached each stage of the signup funnel through
+    /// this provider, and how many dropped off at each stage.
+    ///
+    /// Only available to administrators.
+    pub async fn funnel(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "Only count the stages of the funnel reached at or after this time.")]
+        since: Option<DateTime<Utc>>,
+
+        #[graphql(desc = "Only count the stages of the funnel reached before this time.")]
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UpstreamOAuth2FunnelStageReport>, async_graphql::Error> {
+        if !ctx.requester().is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let report = repo
+            .upstream_oauth_funnel()
+            .report(&self.provider, since, until)
+            .await?;
+        repo.cancel().await?;
+
+        Ok(report
+            .into_iter()
+            .map(UpstreamOAuth2FunnelStageReport)
+            .collect())
+    }
+}
+
+/// A stage of the signup funnel of an upstream OAuth 2.0 provider.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum UpstreamOAuth2FunnelStage {
+    /// The user was redirected to the provider.
+    Redirected,
+
+    /// The provider redirected the user back with an authorization code.
+    CallbackReceived,
+
+    /// The user was shown the registration form, with the attributes mapped
+    /// from the claims of the provider.
+    ClaimsMapped,
+
+    /// The user account was created.
+    UserCreated,
+
+    /// The user was provisioned on the homeserver.
+    Provisioned,
+
+    /// A client got its first token on behalf of the user.
+    FirstTokenIssued,
+}
+
+impl From<mas_data_model::UpstreamOAuthFunnelStage> for UpstreamOAuth2FunnelStage {
+    fn from(stage: mas_data_model::UpstreamOAuthFunnelStage) -> Self {
+        match stage {
+            mas_data_model::UpstreamOAuthFunnelStage::Redirected => Self::Redirected,
+            mas_data_model::UpstreamOAuthFunnelStage::CallbackReceived => Self::CallbackReceived,
+            mas_data_model::UpstreamOAuthFunnelStage::ClaimsMapped => Self::ClaimsMapped,
+            mas_data_model::UpstreamOAuthFunnelStage::UserCreated => Self::UserCreated,
+            mas_data_model::UpstreamOAuthFunnelStage::Provisioned => Self::Provisioned,
+            mas_data_model::UpstreamOAuthFunnelStage::FirstTokenIssued => Self::FirstTokenIssued,
+        }
+    }
+}
+
+/// How many times users dropped off at a stage of the signup funnel for a
+/// given reason.
+#[derive(SimpleObject)]
+pub struct UpstreamOAuth2FunnelDropoff {
+    /// Why the users dropped off.
+    pub reason: String,
+
+    /// How many times users dropped off for this reason.
+    pub count: u64,
+}
+
+/// How many times a stage of the signup funnel was reached, and how many
+/// times users dropped off at that stage.
+#[derive(Description)]
+pub struct UpstreamOAuth2FunnelStageReport(mas_data_model::UpstreamOAuthFunnelStageReport);
+
+#[Object(use_type_description)]
+impl UpstreamOAuth2FunnelStageReport {
+    /// The stage of the funnel.
+    pub async fn stage(&self) -> UpstreamOAuth2FunnelStage {
+        self.0.stage.into()
+    }
+
+    /// How many times the stage was reached.
+    pub async fn reached(&self) -> u64 {
+        self.0.reached
+    }
+
+    /// How many times users dropped off at that stage, for any reason.
+    pub async fn dropped(&self) -> u64 {
+        self.0.dropped()
+    }
+
+    /// How many times users dropped off at that stage, by reason.
+    pub async fn dropoffs(&self) -> Vec<UpstreamOAuth2FunnelDropoff> {
+        self.0
+            .dropoffs
+            .iter()
+            .map(|(reason, count)| UpstreamOAuth2FunnelDropoff {
+                reason: reason.clone(),
+                count: *count,
+            })
+            .collect()
+    }
 }
 
 impl UpstreamOAuth2Link {
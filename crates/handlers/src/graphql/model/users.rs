@@ -7,24 +7,30 @@
 use anyhow::Context as _;
 use async_graphql::{
     connection::{query, Connection, Edge, OpaqueCursor},
-    Context, Description, Enum, Object, Union, ID,
+    Context, Description, Enum, Object, SimpleObject, Union, ID,
 };
 use chrono::{DateTime, Utc};
 use mas_data_model::Device;
 use mas_storage::{
     app_session::AppSessionFilter,
     compat::{CompatSessionFilter, CompatSsoLoginFilter, CompatSsoLoginRepository},
-    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    oauth2::{OAuth2ClientRepository, OAuth2SessionFilter, OAuth2SessionRepository},
+    pagination::Count,
     upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
-    user::{BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository},
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserActivityDigestRepository,
+        UserEmailFilter, UserEmailRepository, UserFilter, UserNotificationPreferencesRepository,
+        UserRepository, UserTermsRepository, UserTrustedBrowserRepository,
+    },
     Pagination, RepositoryAccess,
 };
+use url::Url;
 
 use super::{
     compat_sessions::{CompatSessionType, CompatSsoLogin},
     matrix::MatrixUser,
-    BrowserSession, CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session,
-    PreloadedTotalCount, SessionState, UpstreamOAuth2Link,
+    BrowserSession, CompatSession, CountMode, Cursor, NodeCursor, NodeType, OAuth2Consent,
+    OAuth2Session, PreloadedTotalCount, SessionState, UpstreamOAuth2Link, UserAgent,
 };
 use crate::graphql::{state::ContextExt, DateFilter};
 
@@ -66,16 +72,34 @@ impl User {
         self.0.locked_at
     }
 
+    /// When the account of the user will be deleted, if they asked for it.
+    pub async fn deletion_scheduled_at(&self) -> Option<DateTime<Utc>> {
+        self.0.deletion_scheduled_at
+    }
+
     /// Whether the user can request admin privileges.
     pub async fn can_request_admin(&self) -> bool {
         self.0.can_request_admin
     }
 
+    /// Server name of the homeserver the user belongs to.
+    async fn homeserver(&self, ctx: &Context<'_>) -> String {
+        match &self.0.homeserver {
+            Some(homeserver) => homeserver.clone(),
+            None => ctx.state().site_config().server_name.clone(),
+        }
+    }
+
+    /// Whether this is a bot account.
+    async fn bot(&self) -> bool {
+        self.0.is_bot
+    }
+
     /// Access to the user's Matrix account information.
     async fn matrix(&self, ctx: &Context<'_>) -> Result<MatrixUser, async_graphql::Error> {
         let state = ctx.state();
         let conn = state.homeserver_connection();
-        Ok(MatrixUser::load(conn, &self.0.username).await?)
+        Ok(MatrixUser::load(conn, &self.0).await?)
     }
 
     /// Primary email address of the user.
@@ -91,6 +115,31 @@ impl User {
         Ok(user_email)
     }
 
+    /// Whether the user receives a periodic email digest of the activity on
+    /// their account.
+    async fn activity_digest(&self, ctx: &Context<'_>) -> Result<bool, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        let digest = repo.user_activity_digest().find_by_user(&self.0).await?;
+        repo.cancel().await?;
+        Ok(digest.is_some())
+    }
+
+    /// The preferences of the user about the notifications they receive by
+    /// email.
+    async fn notification_preferences(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<NotificationPreferences, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        let preferences = repo.user_notification_preferences().get(&self.0).await?;
+        repo.cancel().await?;
+        Ok(preferences.into())
+    }
+
     /// Get the list of compatibility SSO logins, chronologically sorted
     async fn compat_sso_logins(
         &self,
@@ -126,7 +175,7 @@ impl User {
 
                 // Preload the total count if requested
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.compat_sso_login().count(filter).await?)
+                    Some(Count::Exact(repo.compat_sso_login().count(filter).await?))
                 } else {
                     None
                 };
@@ -169,6 +218,8 @@ impl User {
         )]
         last_active: Option<DateFilter>,
 
+        #[graphql(desc = "List only sessions with the given tag.")] tag: Option<String>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -177,7 +228,7 @@ impl User {
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, CompatSession, PreloadedTotalCount>, async_graphql::Error> {
         let state = ctx.state();
-        let mut repo = state.repository().await?;
+        let mut repo = state.read_only_repository().await?;
         let last_active = last_active.unwrap_or_default();
 
         query(
@@ -215,12 +266,16 @@ impl User {
                     Some(before) => filter.with_last_active_before(before),
                     None => filter,
                 };
+                let filter = match tag.as_deref() {
+                    Some(tag) => filter.with_tag(tag),
+                    None => filter,
+                };
 
                 let page = repo.compat_session().list(filter, pagination).await?;
 
                 // Preload the total count if requested
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.compat_session().count(filter).await?)
+                    Some(Count::Exact(repo.compat_session().count(filter).await?))
                 } else {
                     None
                 };
@@ -261,6 +316,8 @@ impl User {
         )]
         last_active: Option<DateFilter>,
 
+        #[graphql(desc = "List only sessions with the given tag.")] tag: Option<String>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -269,7 +326,7 @@ impl User {
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, BrowserSession, PreloadedTotalCount>, async_graphql::Error> {
         let state = ctx.state();
-        let mut repo = state.repository().await?;
+        let mut repo = state.read_only_repository().await?;
         let last_active = last_active.unwrap_or_default();
 
         query(
@@ -301,12 +358,16 @@ impl User {
                     Some(before) => filter.with_last_active_before(before),
                     None => filter,
                 };
+                let filter = match tag.as_deref() {
+                    Some(tag) => filter.with_tag(tag),
+                    None => filter,
+                };
 
                 let page = repo.browser_session().list(filter, pagination).await?;
 
                 // Preload the total count if requested
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.browser_session().count(filter).await?)
+                    Some(Count::Exact(repo.browser_session().count(filter).await?))
                 } else {
                     None
                 };
@@ -375,7 +436,7 @@ impl User {
 
                 // Preload the total count if requested
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.user_email().count(filter).await?)
+                    Some(Count::Exact(repo.user_email().count(filter).await?))
                 } else {
                     None
                 };
@@ -417,6 +478,11 @@ impl User {
         )]
         last_active: Option<DateFilter>,
 
+        #[graphql(desc = "List only sessions with the given tag.")] tag: Option<String>,
+
+        #[graphql(desc = "How to count the total number of items. Defaults to `EXACT`.")]
+        count_mode: Option<CountMode>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -425,7 +491,7 @@ impl User {
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
     ) -> Result<Connection<Cursor, OAuth2Session, PreloadedTotalCount>, async_graphql::Error> {
         let state = ctx.state();
-        let mut repo = state.repository().await?;
+        let mut repo = state.read_only_repository().await?;
         let last_active = last_active.unwrap_or_default();
 
         query(
@@ -477,11 +543,25 @@ impl User {
                     Some(before) => filter.with_last_active_before(before),
                     None => filter,
                 };
+                let filter = match tag.as_deref() {
+                    Some(tag) => filter.with_tag(tag),
+                    None => filter,
+                };
 
                 let page = repo.oauth2_session().list(filter, pagination).await?;
 
-                let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.oauth2_session().count(filter).await?)
+                let look_ahead = ctx.look_ahead();
+                let count = if look_ahead.field("totalCount").exists()
+                    || look_ahead.field("totalCountEstimated").exists()
+                {
+                    Some(match count_mode.unwrap_or_default() {
+                        CountMode::Exact => {
+                            Count::Exact(repo.oauth2_session().count(filter).await?)
+                        }
+                        CountMode::Estimated => {
+                            repo.oauth2_session().estimate_count(filter).await?
+                        }
+                    })
                 } else {
                     None
                 };
@@ -507,6 +587,138 @@ impl User {
         .await
     }
 
+    /// Get the list of OAuth 2.0 clients the user authorized, with the scope
+    /// they consented to, most recently authorized first
+    async fn oauth2_consents(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<OAuth2Consent>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.read_only_repository().await?;
+
+        let consents = repo.oauth2_client().list_consents_for_user(&self.0).await?;
+        repo.cancel().await?;
+
+        Ok(consents.into_iter().map(OAuth2Consent).collect())
+    }
+
+    /// Get the list of browsers the user chose to trust, which are not
+    /// challenged again for additional authentication factors, most recently
+    /// trusted first
+    async fn trusted_browsers(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<TrustedBrowser>, async_graphql::Error> {
+        let state = ctx.state();
+        let clock = state.clock();
+        let mut repo = state.read_only_repository().await?;
+
+        let trusted_browsers = repo
+            .user_trusted_browser()
+            .list_active(&clock, &self.0)
+            .await?;
+        repo.cancel().await?;
+
+        Ok(trusted_browsers.into_iter().map(TrustedBrowser).collect())
+    }
+
+    /// Get the versions of the terms of service the user accepted, most
+    /// recently accepted first
+    async fn terms_acceptances(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<TermsAcceptance>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.read_only_repository().await?;
+
+        let acceptances = repo.user_terms().all(&self.0).await?;
+        repo.cancel().await?;
+
+        Ok(acceptances.into_iter().map(TermsAcceptance).collect())
+    }
+
+    /// The terms of service the user has to accept before they can continue
+    /// using the service, if any.
+    async fn pending_terms_uri(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<Url>, async_graphql::Error> {
+        let state = ctx.state();
+        let site_config = state.site_config();
+        let Some(terms_url) = site_config.required_terms() else {
+            return Ok(None);
+        };
+
+        let mut repo = state.read_only_repository().await?;
+        let acceptance = repo
+            .user_terms()
+            .find_acceptance(&self.0, terms_url)
+            .await?;
+        repo.cancel().await?;
+
+        Ok(acceptance.is_none().then(|| terms_url.clone()))
+    }
+
+    /// Get the list of bot accounts this user owns, and whose sessions they
+    /// can manage
+    async fn bots(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, User, PreloadedTotalCount>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::User))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::User))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                let filter = UserFilter::new().for_bot_owner(&self.0).bots_only();
+
+                let page = repo.user().list(filter, pagination).await?;
+
+                // Preload the total count if requested
+                let count = if ctx.look_ahead().field("totalCount").exists() {
+                    Some(Count::Exact(repo.user().count(filter).await?))
+                } else {
+                    None
+                };
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::with_additional_fields(
+                    page.has_previous_page,
+                    page.has_next_page,
+                    PreloadedTotalCount(count),
+                );
+                connection.edges.extend(
+                    page.edges.into_iter().map(|u| {
+                        Edge::new(OpaqueCursor(NodeCursor(NodeType::User, u.id)), User(u))
+                    }),
+                );
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+
     /// Get the list of upstream OAuth 2.0 links
     async fn upstream_oauth2_links(
         &self,
@@ -549,7 +761,9 @@ impl User {
 
                 // Preload the total count if requested
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.upstream_oauth_link().count(filter).await?)
+                    Some(Count::Exact(
+                        repo.upstream_oauth_link().count(filter).await?,
+                    ))
                 } else {
                     None
                 };
@@ -599,6 +813,8 @@ impl User {
         )]
         browser_session_param: Option<ID>,
 
+        #[graphql(desc = "List only sessions with the given tag.")] tag: Option<String>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -608,7 +824,7 @@ impl User {
     ) -> Result<Connection<Cursor, AppSession, PreloadedTotalCount>, async_graphql::Error> {
         let state = ctx.state();
         let requester = ctx.requester();
-        let mut repo = state.repository().await?;
+        let mut repo = state.read_only_repository().await?;
         let last_active = last_active.unwrap_or_default();
 
         query(
@@ -662,7 +878,7 @@ impl User {
                             return Ok(Connection::with_additional_fields(
                                 false,
                                 false,
-                                PreloadedTotalCount(Some(0)),
+                                PreloadedTotalCount(Some(Count::Exact(0))),
                             ));
                         };
 
@@ -684,11 +900,15 @@ impl User {
                     Some(before) => filter.with_last_active_before(before),
                     None => filter,
                 };
+                let filter = match tag.as_deref() {
+                    Some(tag) => filter.with_tag(tag),
+                    None => filter,
+                };
 
                 let page = repo.app_session().list(filter, pagination).await?;
 
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.app_session().count(filter).await?)
+                    Some(Count::Exact(repo.app_session().count(filter).await?))
                 } else {
                     None
                 };
@@ -756,6 +976,55 @@ impl UserEmail {
     }
 }
 
+/// A browser the user chose to trust when logging in.
+#[derive(Description)]
+pub struct TrustedBrowser(pub mas_data_model::UserTrustedBrowser);
+
+#[Object(use_type_description)]
+impl TrustedBrowser {
+    /// ID of the object.
+    pub async fn id(&self) -> ID {
+        NodeType::TrustedBrowser.id(self.0.id)
+    }
+
+    /// When the browser was trusted.
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// When the browser was last used to log in.
+    pub async fn last_used_at(&self) -> DateTime<Utc> {
+        self.0.last_used_at
+    }
+
+    /// When the browser stops being trusted.
+    pub async fn expires_at(&self) -> DateTime<Utc> {
+        self.0.expires_at
+    }
+
+    /// The user agent of the browser when it was trusted.
+    pub async fn user_agent(&self) -> Option<UserAgent> {
+        self.0.user_agent.clone().map(UserAgent::from)
+    }
+}
+
+/// A version of the terms of service accepted by a user.
+#[derive(Description)]
+pub struct TermsAcceptance(pub mas_data_model::UserTermsAcceptance);
+
+#[Object(use_type_description)]
+impl TermsAcceptance {
+    /// The URL of the terms of service, which identifies their version.
+    pub async fn terms_uri(&self) -> &Url {
+        &self.0.terms_url
+    }
+
+    /// When the user accepted them.
+    pub async fn accepted_at(&self) -> DateTime<Utc> {
+        self.0.accepted_at
+    }
+}
+
 /// The state of a compatibility session.
 #[derive(Enum, Copy, Clone, Eq, PartialEq)]
 pub enum UserEmailState {
@@ -765,3 +1034,70 @@ pub enum UserEmailState {
     /// The email address has been confirmed.
     Confirmed,
 }
+
+/// How often a user receives the digest of their account activity.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum DigestFrequency {
+    /// At the interval configured on the server.
+    Default,
+
+    /// At most once a day.
+    Daily,
+
+    /// At most once a week.
+    Weekly,
+
+    /// At most once a month.
+    Monthly,
+}
+
+impl From<mas_data_model::DigestFrequency> for DigestFrequency {
+    fn from(frequency: mas_data_model::DigestFrequency) -> Self {
+        match frequency {
+            mas_data_model::DigestFrequency::Default => Self::Default,
+            mas_data_model::DigestFrequency::Daily => Self::Daily,
+            mas_data_model::DigestFrequency::Weekly => Self::Weekly,
+            mas_data_model::DigestFrequency::Monthly => Self::Monthly,
+        }
+    }
+}
+
+impl From<DigestFrequency> for mas_data_model::DigestFrequency {
+    fn from(frequency: DigestFrequency) -> Self {
+        match frequency {
+            DigestFrequency::Default => Self::Default,
+            DigestFrequency::Daily => Self::Daily,
+            DigestFrequency::Weekly => Self::Weekly,
+            DigestFrequency::Monthly => Self::Monthly,
+        }
+    }
+}
+
+/// The preferences of a user about the notifications they receive by email.
+///
+/// Emails sent as part of an action of the user, like email verification,
+/// are always sent.
+#[derive(SimpleObject)]
+pub struct NotificationPreferences {
+    /// Whether the user receives an alert when someone logs in to their
+    /// account.
+    pub new_login_alerts: bool,
+
+    /// How often the user receives the digest of their account activity, if
+    /// they subscribed to it.
+    pub digest_frequency: DigestFrequency,
+
+    /// Whether the user only receives notifications about the security of
+    /// their account.
+    pub security_only: bool,
+}
+
+impl From<mas_data_model::UserNotificationPreferences> for NotificationPreferences {
+    fn from(preferences: mas_data_model::UserNotificationPreferences) -> Self {
+        Self {
+            new_login_alerts: preferences.new_login_alerts,
+            digest_frequency: preferences.digest_frequency.into(),
+            security_only: preferences.security_only,
+        }
+    }
+}
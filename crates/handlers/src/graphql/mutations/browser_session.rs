@@ -5,8 +5,13 @@
 // Please see LICENSE in the repository root for full details.
 
 use async_graphql::{Context, Enum, InputObject, Object, ID};
-use mas_storage::RepositoryAccess;
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    RepositoryAccess,
+};
 
+use super::record_audit_event;
 use crate::graphql::{
     model::{BrowserSession, NodeType},
     state::ContextExt,
@@ -86,6 +91,25 @@ impl BrowserSessionMutations {
 
         let session = repo.browser_session().finish(&clock, session).await?;
 
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                &mut state.rng(),
+                &clock,
+                &session.user,
+                "browser",
+                session.id,
+            ))
+            .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::SessionEnded,
+            Some(session.user.id),
+            serde_json::json!({ "browser_session_id": session.id.to_string() }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(EndBrowserSessionPayload::Ended(Box::new(session)))
@@ -6,12 +6,15 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Enum, InputObject, Object, ID};
+use mas_data_model::{AuditEventKind, Device, TokenType};
 use mas_storage::{
-    compat::CompatSessionRepository,
-    job::{JobRepositoryExt, SyncDevicesJob},
+    compat::{CompatAccessTokenRepository, CompatSessionRepository},
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
+    user::UserRepository,
     RepositoryAccess,
 };
 
+use super::record_audit_event;
 use crate::graphql::{
     model::{CompatSession, NodeType},
     state::ContextExt,
@@ -64,8 +67,129 @@ impl EndCompatSessionPayload {
     }
 }
 
+/// The input of the `issueBotToken` mutation.
+#[derive(InputObject)]
+pub struct IssueBotTokenInput {
+    /// The ID of the bot to issue a token for.
+    bot_id: ID,
+}
+
+/// The payload of the `issueBotToken` mutation.
+pub enum IssueBotTokenPayload {
+    NotFound,
+    Issued {
+        session: Box<mas_data_model::CompatSession>,
+        access_token: String,
+    },
+}
+
+/// The status of the `issueBotToken` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum IssueBotTokenStatus {
+    /// The token was issued.
+    Issued,
+
+    /// The bot was not found.
+    NotFound,
+}
+
+#[Object]
+impl IssueBotTokenPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> IssueBotTokenStatus {
+        match self {
+            Self::Issued { .. } => IssueBotTokenStatus::Issued,
+            Self::NotFound => IssueBotTokenStatus::NotFound,
+        }
+    }
+
+    /// The session created for the bot.
+    async fn compat_session(&self) -> Option<CompatSession> {
+        match self {
+            Self::Issued { session, .. } => Some(CompatSession::new(*session.clone())),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The access token of the session. It does not expire, and is only
+    /// returned once.
+    async fn access_token(&self) -> Option<&str> {
+        match self {
+            Self::Issued { access_token, .. } => Some(access_token),
+            Self::NotFound => None,
+        }
+    }
+}
+
 #[Object]
 impl CompatSessionMutations {
+    /// Start a new session for a bot, and issue a Matrix access token for it.
+    ///
+    /// Only available to the owner of the bot, and to administrators.
+    async fn issue_bot_token(
+        &self,
+        ctx: &Context<'_>,
+        input: IssueBotTokenInput,
+    ) -> Result<IssueBotTokenPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let bot_id = NodeType::User.extract_ulid(&input.bot_id)?;
+        let requester = ctx.requester();
+        let homeserver = state.homeserver_connection();
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let bot = repo.user().lookup(bot_id).await?;
+        let Some(bot) = bot.filter(|bot| bot.is_bot && bot.is_valid()) else {
+            return Ok(IssueBotTokenPayload::NotFound);
+        };
+
+        if !requester.can_manage_sessions_of(&bot) {
+            return Ok(IssueBotTokenPayload::NotFound);
+        }
+
+        // Lock the user sync to make sure we don't get into a race condition
+        repo.user().acquire_lock_for_sync(&bot).await?;
+
+        let device = Device::generate(&mut rng);
+        let mxid = homeserver.mxid_on(bot.homeserver.as_deref(), &bot.username);
+        homeserver
+            .create_device(&mxid, device.as_str())
+            .await
+            .context("Failed to provision device")?;
+
+        let session = repo
+            .compat_session()
+            .add(&mut rng, &clock, &bot, device, None, false)
+            .await?;
+
+        let access_token = TokenType::CompatAccessToken.generate(&mut rng);
+        let access_token = repo
+            .compat_access_token()
+            .add(&mut rng, &clock, &session, access_token, None)
+            .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::Login,
+            Some(bot.id),
+            serde_json::json!({
+                "method": "bot_token",
+                "compat_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(IssueBotTokenPayload::Issued {
+            session: Box::new(session),
+            access_token: access_token.token,
+        })
+    }
+
     async fn end_compat_session(
         &self,
         ctx: &Context<'_>,
@@ -83,21 +207,40 @@ impl CompatSessionMutations {
             return Ok(EndCompatSessionPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&session) {
-            return Ok(EndCompatSessionPayload::NotFound);
-        }
-
         let user = repo
             .user()
             .lookup(session.user_id)
             .await?
             .context("Could not load user")?;
 
+        if !requester.can_manage_sessions_of(&user) {
+            return Ok(EndCompatSessionPayload::NotFound);
+        }
+
         // Schedule a job to sync the devices of the user with the homeserver
         repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
 
         let session = repo.compat_session().finish(&clock, session).await?;
 
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                &mut state.rng(),
+                &clock,
+                &user,
+                "compat",
+                session.id,
+            ))
+            .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::SessionEnded,
+            Some(session.user_id),
+            serde_json::json!({ "compat_session_id": session.id.to_string() }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(EndCompatSessionPayload::Ended(Box::new(session)))
@@ -0,0 +1,604 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use anyhow::Context as _;
+use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use mas_data_model::{AuditEventKind, Device, TokenType};
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
+    oauth2::{OAuth2ClientRepository, OAuth2KioskDeviceRepository, OAuth2SessionRepository},
+    user::{UserPasswordRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+};
+use oauth2_types::scope::{Scope, ScopeToken, OPENID};
+use zeroize::Zeroizing;
+
+use super::record_audit_event;
+use crate::graphql::{
+    model::{KioskDevice, NodeType, OAuth2Session},
+    state::ContextExt,
+};
+
+/// The scope giving access to the Matrix C-S API
+const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
+
+#[derive(Default)]
+pub struct KioskMutations {
+    _private: (),
+}
+
+/// The input of the `registerKioskDevice` mutation.
+#[derive(InputObject)]
+pub struct RegisterKioskDeviceInput {
+    /// The Matrix device ID to give to the sessions of the users logged in on
+    /// the kiosk device. A random one is generated if not provided.
+    device_id: Option<String>,
+}
+
+/// The status of the `registerKioskDevice` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum RegisterKioskDeviceStatus {
+    /// The kiosk device was registered.
+    Registered,
+
+    /// The kiosk device was already registered by this client.
+    Exists,
+
+    /// The device ID is invalid.
+    InvalidDeviceId,
+
+    /// The client is not allowed to register kiosk devices by the policy.
+    Denied,
+}
+
+/// The payload of the `registerKioskDevice` mutation.
+#[derive(Description)]
+enum RegisterKioskDevicePayload {
+    Registered(mas_data_model::KioskDevice),
+    Exists(mas_data_model::KioskDevice),
+    InvalidDeviceId,
+    Denied {
+        violations: Vec<mas_policy::Violation>,
+    },
+}
+
+#[Object(use_type_description)]
+impl RegisterKioskDevicePayload {
+    /// The status of the mutation.
+    async fn status(&self) -> RegisterKioskDeviceStatus {
+        match self {
+            Self::Registered(_) => RegisterKioskDeviceStatus::Registered,
+            Self::Exists(_) => RegisterKioskDeviceStatus::Exists,
+            Self::InvalidDeviceId => RegisterKioskDeviceStatus::InvalidDeviceId,
+            Self::Denied { .. } => RegisterKioskDeviceStatus::Denied,
+        }
+    }
+
+    /// The kiosk device which was registered.
+    async fn kiosk_device(&self) -> Option<KioskDevice> {
+        match self {
+            Self::Registered(kiosk_device) | Self::Exists(kiosk_device) => {
+                Some(KioskDevice(kiosk_device.clone()))
+            }
+            Self::InvalidDeviceId | Self::Denied { .. } => None,
+        }
+    }
+
+    /// The list of policy violations if the registration was denied.
+    async fn violations(&self) -> Option<Vec<String>> {
+        let Self::Denied { violations } = self else {
+            return None;
+        };
+
+        let messages = violations.iter().map(|v| v.msg.clone()).collect();
+        Some(messages)
+    }
+}
+
+/// The input of the `switchKioskUser` mutation.
+#[derive(InputObject)]
+pub struct SwitchKioskUserInput {
+    /// The ID of the kiosk device on which the user logs in.
+    kiosk_device_id: ID,
+
+    /// The username of the user logging in.
+    username: String,
+
+    /// The password of the user logging in.
+    password: String,
+}
+
+/// The status of the `switchKioskUser` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum SwitchKioskUserStatus {
+    /// The user is now logged in on the kiosk device.
+    Switched,
+
+    /// The kiosk device was not found.
+    NotFound,
+
+    /// Password login is disabled for this user.
+    PasswordLoginDisabled,
+
+    /// The username or password is invalid.
+    InvalidCredentials,
+
+    /// The user is not allowed to log in on this kiosk device by the policy.
+    Denied,
+}
+
+/// The payload of the `switchKioskUser` mutation.
+#[derive(Description)]
+enum SwitchKioskUserPayload {
+    Switched {
+        kiosk_device: mas_data_model::KioskDevice,
+        session: mas_data_model::Session,
+        access_token: String,
+        refresh_token: String,
+    },
+    NotFound,
+    PasswordLoginDisabled,
+    InvalidCredentials,
+    Denied {
+        violations: Vec<mas_policy::Violation>,
+    },
+}
+
+#[Object(use_type_description)]
+impl SwitchKioskUserPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> SwitchKioskUserStatus {
+        match self {
+            Self::Switched { .. } => SwitchKioskUserStatus::Switched,
+            Self::NotFound => SwitchKioskUserStatus::NotFound,
+            Self::PasswordLoginDisabled => SwitchKioskUserStatus::PasswordLoginDisabled,
+            Self::InvalidCredentials => SwitchKioskUserStatus::InvalidCredentials,
+            Self::Denied { .. } => SwitchKioskUserStatus::Denied,
+        }
+    }
+
+    /// The kiosk device the user logged in on.
+    async fn kiosk_device(&self) -> Option<KioskDevice> {
+        match self {
+            Self::Switched { kiosk_device, .. } => Some(KioskDevice(kiosk_device.clone())),
+            _ => None,
+        }
+    }
+
+    /// The OAuth 2.0 session of the user on the kiosk device.
+    async fn oauth2_session(&self) -> Option<OAuth2Session> {
+        match self {
+            Self::Switched { session, .. } => Some(OAuth2Session(session.clone())),
+            _ => None,
+        }
+    }
+
+    /// Access token for the session of the user.
+    async fn access_token(&self) -> Option<&str> {
+        match self {
+            Self::Switched { access_token, .. } => Some(access_token),
+            _ => None,
+        }
+    }
+
+    /// Refresh token for the session of the user.
+    async fn refresh_token(&self) -> Option<&str> {
+        match self {
+            Self::Switched { refresh_token, .. } => Some(refresh_token),
+            _ => None,
+        }
+    }
+
+    /// The list of policy violations if the login was denied.
+    async fn violations(&self) -> Option<Vec<String>> {
+        let Self::Denied { violations } = self else {
+            return None;
+        };
+
+        let messages = violations.iter().map(|v| v.msg.clone()).collect();
+        Some(messages)
+    }
+}
+
+/// The input of the `endKioskSession` mutation.
+#[derive(InputObject)]
+pub struct EndKioskSessionInput {
+    /// The ID of the kiosk device on which to log out the current user.
+    kiosk_device_id: ID,
+}
+
+/// The status of the `endKioskSession` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum EndKioskSessionStatus {
+    /// The session of the current user was ended.
+    Ended,
+
+    /// Nobody was logged in on the kiosk device.
+    NoSession,
+
+    /// The kiosk device was not found.
+    NotFound,
+}
+
+/// The payload of the `endKioskSession` mutation.
+enum EndKioskSessionPayload {
+    Ended(mas_data_model::KioskDevice),
+    NoSession(mas_data_model::KioskDevice),
+    NotFound,
+}
+
+#[Object]
+impl EndKioskSessionPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> EndKioskSessionStatus {
+        match self {
+            Self::Ended(_) => EndKioskSessionStatus::Ended,
+            Self::NoSession(_) => EndKioskSessionStatus::NoSession,
+            Self::NotFound => EndKioskSessionStatus::NotFound,
+        }
+    }
+
+    /// The kiosk device.
+    async fn kiosk_device(&self) -> Option<KioskDevice> {
+        match self {
+            Self::Ended(kiosk_device) | Self::NoSession(kiosk_device) => {
+                Some(KioskDevice(kiosk_device.clone()))
+            }
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// Get the kiosk client making the request
+///
+/// Kiosk mutations are only available to clients acting on their own behalf,
+/// not on behalf of a user.
+async fn kiosk_client(
+    ctx: &Context<'_>,
+    repo: &mut BoxRepository,
+) -> Result<mas_data_model::Client, async_graphql::Error> {
+    let requester = ctx.requester();
+
+    let Some(session) = requester.oauth2_session() else {
+        return Err(async_graphql::Error::new("Unauthorized"));
+    };
+
+    if requester.user().is_some() {
+        return Err(async_graphql::Error::new("Unauthorized"));
+    }
+
+    let client = repo
+        .oauth2_client()
+        .lookup(session.client_id)
+        .await?
+        .context("Client not found")?;
+
+    Ok(client)
+}
+
+/// Load a kiosk device, making sure it was registered by the given client
+async fn load_kiosk_device(
+    repo: &mut BoxRepository,
+    client: &mas_data_model::Client,
+    id: &ID,
+) -> Result<Option<mas_data_model::KioskDevice>, async_graphql::Error> {
+    let id = NodeType::KioskDevice.extract_ulid(id)?;
+
+    let kiosk_device = repo
+        .oauth2_kiosk_device()
+        .lookup(id)
+        .await?
+        .filter(|kiosk_device| kiosk_device.client_id == client.id);
+
+    Ok(kiosk_device)
+}
+
+/// End the session of the user currently logged in on a kiosk device, if any
+///
+/// Returns the updated kiosk device, and the session which was ended
+async fn end_current_session(
+    rng: &mut BoxRng,
+    clock: &BoxClock,
+    repo: &mut BoxRepository,
+    kiosk_device: mas_data_model::KioskDevice,
+) -> Result<(mas_data_model::KioskDevice, Option<mas_data_model::Session>), async_graphql::Error> {
+    let Some(session_id) = kiosk_device.current_session_id else {
+        return Ok((kiosk_device, None));
+    };
+
+    let session = repo
+        .oauth2_session()
+        .lookup(session_id)
+        .await?
+        .filter(mas_data_model::Session::is_valid);
+
+    let kiosk_device = repo
+        .oauth2_kiosk_device()
+        .set_current_session(kiosk_device, None)
+        .await?;
+
+    let Some(session) = session else {
+        // The session already expired or was ended by other means
+        return Ok((kiosk_device, None));
+    };
+
+    let session = repo.oauth2_session().finish(clock, session).await?;
+
+    if let Some(user_id) = session.user_id {
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .context("Could not load user")?;
+
+        // Schedule a job to sync the devices of the user with the homeserver
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                rng, clock, &user, "oauth2", session.id,
+            ))
+            .await?;
+    }
+
+    Ok((kiosk_device, Some(session)))
+}
+
+#[Object]
+impl KioskMutations {
+    /// Register a kiosk device, on which users can then log in one at a time.
+    ///
+    /// Only available to clients allowed to run in kiosk mode by the policy,
+    /// using a session without a user.
+    async fn register_kiosk_device(
+        &self,
+        ctx: &Context<'_>,
+        input: RegisterKioskDeviceInput,
+    ) -> Result<RegisterKioskDevicePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let client = kiosk_client(ctx, &mut repo).await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let device = match input.device_id {
+            Some(device_id) => {
+                let Ok(device) = Device::try_from(device_id) else {
+                    return Ok(RegisterKioskDevicePayload::InvalidDeviceId);
+                };
+                device
+            }
+            None => Device::generate(&mut rng),
+        };
+
+        let metadata = ctx.request_metadata();
+        let policy_requester =
+            mas_policy::Requester::new(metadata.ip_address, metadata.user_agent.clone());
+        let mut policy = state.policy().await?;
+        let res = policy
+            .evaluate_kiosk(&device, &client, None, &policy_requester)
+            .await?;
+        if !res.valid() {
+            return Ok(RegisterKioskDevicePayload::Denied {
+                violations: res.violations,
+            });
+        }
+
+        if let Some(kiosk_device) = repo
+            .oauth2_kiosk_device()
+            .find_by_device(&client, &device)
+            .await?
+        {
+            return Ok(RegisterKioskDevicePayload::Exists(kiosk_device));
+        }
+
+        let kiosk_device = repo
+            .oauth2_kiosk_device()
+            .add(&mut rng, &clock, &client, device)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(RegisterKioskDevicePayload::Registered(kiosk_device))
+    }
+
+    /// Log a user in on a kiosk device with their password, ending the
+    /// session of the user previously logged in on it.
+    ///
+    /// The session is short-lived, and ended after a period of inactivity.
+    async fn switch_kiosk_user(
+        &self,
+        ctx: &Context<'_>,
+        input: SwitchKioskUserInput,
+    ) -> Result<SwitchKioskUserPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let client = kiosk_client(ctx, &mut repo).await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+        let site_config = state.site_config();
+        let password_manager = state.password_manager();
+        let homeserver = state.homeserver_connection();
+
+        let Some(kiosk_device) =
+            load_kiosk_device(&mut repo, &client, &input.kiosk_device_id).await?
+        else {
+            return Ok(SwitchKioskUserPayload::NotFound);
+        };
+
+        if !password_manager.is_enabled() || !site_config.is_password_login_allowed(&input.username)
+        {
+            return Ok(SwitchKioskUserPayload::PasswordLoginDisabled);
+        }
+
+        let Some(user) = repo
+            .user()
+            .find_by_username(
+                site_config.homeserver_for_client(client.id).into(),
+                &input.username,
+            )
+            .await?
+            .filter(|user| user.is_valid() && !user.is_bot)
+        else {
+            return Ok(SwitchKioskUserPayload::InvalidCredentials);
+        };
+
+        let Some(active_password) = repo.user_password().active(&user).await? else {
+            return Ok(SwitchKioskUserPayload::InvalidCredentials);
+        };
+
+        if let Err(_err) = password_manager
+            .verify(
+                active_password.version,
+                Zeroizing::new(input.password.into_bytes()),
+                active_password.hashed_password,
+            )
+            .await
+        {
+            record_audit_event(
+                ctx,
+                &mut repo,
+                AuditEventKind::LoginFailed,
+                Some(user.id),
+                serde_json::json!({
+                    "method": "kiosk",
+                    "kiosk_device_id": kiosk_device.id.to_string(),
+                }),
+            )
+            .await?;
+
+            repo.save().await?;
+
+            return Ok(SwitchKioskUserPayload::InvalidCredentials);
+        }
+
+        let metadata = ctx.request_metadata();
+        let policy_requester =
+            mas_policy::Requester::new(metadata.ip_address, metadata.user_agent.clone());
+        let mut policy = state.policy().await?;
+        let res = policy
+            .evaluate_kiosk(
+                &kiosk_device.device,
+                &client,
+                Some(&user),
+                &policy_requester,
+            )
+            .await?;
+        if !res.valid() {
+            return Ok(SwitchKioskUserPayload::Denied {
+                violations: res.violations,
+            });
+        }
+
+        // Log out whoever was using the device before
+        let (kiosk_device, _) =
+            end_current_session(&mut rng, &clock, &mut repo, kiosk_device).await?;
+
+        let scope: Scope = [OPENID, API_SCOPE, kiosk_device.device.to_scope_token()]
+            .into_iter()
+            .collect();
+
+        let session = repo
+            .oauth2_session()
+            .add(&mut rng, &clock, &client, Some(&user), None, scope)
+            .await?;
+
+        // Lock the user sync to make sure we don't get into a race condition
+        repo.user().acquire_lock_for_sync(&user).await?;
+
+        let mxid = homeserver.mxid_on(user.homeserver.as_deref(), &user.username);
+        homeserver
+            .create_device(&mxid, kiosk_device.device.as_str())
+            .await
+            .context("Failed to provision device")?;
+
+        let ttl = client
+            .access_token_ttl
+            .unwrap_or(site_config.access_token_ttl);
+        let (access_token, refresh_token) = crate::oauth2::generate_token_pair(
+            &mut rng,
+            &clock,
+            &mut repo,
+            &client,
+            &session,
+            TokenType::AccessToken.generate(&mut rng),
+            ttl,
+            None,
+            None,
+        )
+        .await?;
+
+        let kiosk_device = repo
+            .oauth2_kiosk_device()
+            .set_current_session(kiosk_device, Some(&session))
+            .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::Login,
+            Some(user.id),
+            serde_json::json!({
+                "method": "kiosk",
+                "kiosk_device_id": kiosk_device.id.to_string(),
+                "kiosk_oauth2_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(SwitchKioskUserPayload::Switched {
+            kiosk_device,
+            session,
+            access_token: access_token.access_token,
+            refresh_token: refresh_token.refresh_token,
+        })
+    }
+
+    /// Log out the user currently logged in on a kiosk device.
+    async fn end_kiosk_session(
+        &self,
+        ctx: &Context<'_>,
+        input: EndKioskSessionInput,
+    ) -> Result<EndKioskSessionPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let client = kiosk_client(ctx, &mut repo).await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let Some(kiosk_device) =
+            load_kiosk_device(&mut repo, &client, &input.kiosk_device_id).await?
+        else {
+            return Ok(EndKioskSessionPayload::NotFound);
+        };
+
+        let (kiosk_device, session) =
+            end_current_session(&mut rng, &clock, &mut repo, kiosk_device).await?;
+
+        let Some(session) = session else {
+            repo.save().await?;
+            return Ok(EndKioskSessionPayload::NoSession(kiosk_device));
+        };
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::SessionEnded,
+            session.user_id,
+            serde_json::json!({
+                "method": "kiosk",
+                "kiosk_device_id": kiosk_device.id.to_string(),
+                "ended_oauth2_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(EndKioskSessionPayload::Ended(kiosk_device))
+    }
+}
@@ -93,7 +93,7 @@ impl MatrixMutations {
         repo.cancel().await?;
 
         let conn = state.homeserver_connection();
-        let mxid = conn.mxid(&user.username);
+        let mxid = conn.mxid_on(user.homeserver.as_deref(), &user.username);
 
         if let Some(display_name) = &input.display_name {
             // Let's do some basic validation on the display name
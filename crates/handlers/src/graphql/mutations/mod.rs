@@ -6,12 +6,20 @@
 
 mod browser_session;
 mod compat_session;
+mod kiosk;
 mod matrix;
 mod oauth2_session;
+mod session;
+mod trusted_browser;
 mod user;
 mod user_email;
 
-use async_graphql::MergedObject;
+use async_graphql::{Context, MergedObject};
+use mas_data_model::AuditEventKind;
+use mas_storage::{audit::AuditEventRepository, BoxRepository, RepositoryError};
+use ulid::Ulid;
+
+use crate::graphql::state::ContextExt;
 
 /// The mutations root of the GraphQL interface.
 #[derive(Default, MergedObject)]
@@ -21,7 +29,10 @@ pub struct Mutation(
     oauth2_session::OAuth2SessionMutations,
     compat_session::CompatSessionMutations,
     browser_session::BrowserSessionMutations,
+    trusted_browser::TrustedBrowserMutations,
+    session::SessionMutations,
     matrix::MatrixMutations,
+    kiosk::KioskMutations,
 );
 
 impl Mutation {
@@ -30,3 +41,41 @@ impl Mutation {
         Self::default()
     }
 }
+
+/// Record an event in the audit log on behalf of the requester
+///
+/// The OAuth 2.0 session used by the requester, if any, is added to the
+/// details of the event.
+async fn record_audit_event(
+    ctx: &Context<'_>,
+    repo: &mut BoxRepository,
+    kind: AuditEventKind,
+    user_id: Option<Ulid>,
+    mut details: serde_json::Value,
+) -> Result<(), RepositoryError> {
+    let state = ctx.state();
+    let requester = ctx.requester();
+    let metadata = ctx.request_metadata();
+
+    if let (Some(session), Some(details)) = (requester.oauth2_session(), details.as_object_mut()) {
+        details.insert(
+            "oauth2_session_id".to_owned(),
+            session.id.to_string().into(),
+        );
+    }
+
+    repo.audit_event()
+        .add(
+            &mut state.rng(),
+            &state.clock(),
+            kind,
+            requester.user().map(|user| user.id),
+            user_id,
+            metadata.ip_address,
+            metadata.user_agent.clone(),
+            details,
+        )
+        .await?;
+
+    Ok(())
+}
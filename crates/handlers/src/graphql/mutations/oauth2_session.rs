@@ -4,24 +4,28 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeSet;
+
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
 use chrono::Duration;
-use mas_data_model::{Device, TokenType};
+use mas_data_model::{AuditEventKind, Device, TokenType};
 use mas_storage::{
-    job::{JobRepositoryExt, SyncDevicesJob},
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2RefreshTokenRepository,
-        OAuth2SessionRepository,
+        OAuth2SessionFilter, OAuth2SessionRepository,
     },
     user::UserRepository,
-    RepositoryAccess,
+    Pagination, RepositoryAccess,
 };
 use oauth2_types::scope::Scope;
 
+use super::record_audit_event;
 use crate::graphql::{
-    model::{NodeType, OAuth2Session},
+    model::{NodeType, OAuth2Client, OAuth2Session, User},
     state::ContextExt,
+    UserId,
 };
 
 #[derive(Default)]
@@ -110,6 +114,190 @@ impl EndOAuth2SessionPayload {
     }
 }
 
+/// The input of the `revokeAllOauth2Access` mutation.
+#[derive(InputObject)]
+pub struct RevokeAllOAuth2AccessInput {
+    /// The ID of the user whose OAuth 2.0 access should be revoked.
+    user_id: ID,
+}
+
+/// The payload of the `revokeAllOauth2Access` mutation.
+pub enum RevokeAllOAuth2AccessPayload {
+    NotFound,
+    Revoked {
+        user: mas_data_model::User,
+        ended_sessions: usize,
+        revoked_consents: usize,
+    },
+}
+
+/// The status of the `revokeAllOauth2Access` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum RevokeAllOAuth2AccessStatus {
+    /// The access was revoked.
+    Revoked,
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object]
+impl RevokeAllOAuth2AccessPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> RevokeAllOAuth2AccessStatus {
+        match self {
+            Self::Revoked { .. } => RevokeAllOAuth2AccessStatus::Revoked,
+            Self::NotFound => RevokeAllOAuth2AccessStatus::NotFound,
+        }
+    }
+
+    /// The user whose access was revoked.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Revoked { user, .. } => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The number of OAuth 2.0 sessions which were ended.
+    async fn ended_sessions(&self) -> Option<usize> {
+        match self {
+            Self::Revoked { ended_sessions, .. } => Some(*ended_sessions),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The number of clients the user has to consent to again.
+    async fn revoked_consents(&self) -> Option<usize> {
+        match self {
+            Self::Revoked {
+                revoked_consents, ..
+            } => Some(*revoked_consents),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// The input of the `revokeOauth2ClientAccess` mutation.
+#[derive(InputObject)]
+pub struct RevokeOAuth2ClientAccessInput {
+    /// The ID of the client whose access should be revoked.
+    client_id: ID,
+}
+
+/// The payload of the `revokeOauth2ClientAccess` mutation.
+pub enum RevokeOAuth2ClientAccessPayload {
+    NotFound,
+    Revoked {
+        client: mas_data_model::Client,
+        ended_sessions: usize,
+        revoked_consents: usize,
+    },
+}
+
+/// The status of the `revokeOauth2ClientAccess` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum RevokeOAuth2ClientAccessStatus {
+    /// The access was revoked.
+    Revoked,
+
+    /// The client was not found.
+    NotFound,
+}
+
+#[Object]
+impl RevokeOAuth2ClientAccessPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> RevokeOAuth2ClientAccessStatus {
+        match self {
+            Self::Revoked { .. } => RevokeOAuth2ClientAccessStatus::Revoked,
+            Self::NotFound => RevokeOAuth2ClientAccessStatus::NotFound,
+        }
+    }
+
+    /// The client whose access was revoked.
+    async fn oauth2_client(&self) -> Option<OAuth2Client> {
+        match self {
+            Self::Revoked { client, .. } => Some(OAuth2Client(client.clone())),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The number of OAuth 2.0 sessions which were ended.
+    async fn ended_sessions(&self) -> Option<usize> {
+        match self {
+            Self::Revoked { ended_sessions, .. } => Some(*ended_sessions),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The number of users who have to consent to the client again.
+    async fn revoked_consents(&self) -> Option<usize> {
+        match self {
+            Self::Revoked {
+                revoked_consents, ..
+            } => Some(*revoked_consents),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// The input of the `revokeConsent` mutation.
+#[derive(InputObject)]
+pub struct RevokeConsentInput {
+    /// The ID of the user whose consent should be revoked.
+    user_id: ID,
+
+    /// The ID of the client the user consented to.
+    client_id: ID,
+}
+
+/// The payload of the `revokeConsent` mutation.
+pub enum RevokeConsentPayload {
+    NotFound,
+    Revoked {
+        user: mas_data_model::User,
+        client: mas_data_model::Client,
+    },
+}
+
+/// The status of the `revokeConsent` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum RevokeConsentStatus {
+    /// The consent was revoked.
+    Revoked,
+
+    /// The user, the client or the consent was not found.
+    NotFound,
+}
+
+#[Object]
+impl RevokeConsentPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> RevokeConsentStatus {
+        match self {
+            Self::Revoked { .. } => RevokeConsentStatus::Revoked,
+            Self::NotFound => RevokeConsentStatus::NotFound,
+        }
+    }
+
+    /// The user whose consent was revoked.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Revoked { user, .. } => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The client the user has to consent to again.
+    async fn oauth2_client(&self) -> Option<OAuth2Client> {
+        match self {
+            Self::Revoked { client, .. } => Some(OAuth2Client(client.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
 #[Object]
 impl OAuth2SessionMutations {
     /// Create a new arbitrary OAuth 2.0 Session.
@@ -164,7 +352,7 @@ impl OAuth2SessionMutations {
         repo.user().acquire_lock_for_sync(&user).await?;
 
         // Look for devices to provision
-        let mxid = homeserver.mxid(&user.username);
+        let mxid = homeserver.mxid_on(user.homeserver.as_deref(), &user.username);
         for scope in &*session.scope {
             if let Some(device) = Device::from_scope_token(scope) {
                 homeserver
@@ -181,7 +369,7 @@ impl OAuth2SessionMutations {
         };
         let access_token = repo
             .oauth2_access_token()
-            .add(&mut rng, &clock, &session, access_token, ttl)
+            .add(&mut rng, &clock, &session, access_token, ttl, None, None)
             .await?;
 
         let refresh_token = if permanent {
@@ -191,12 +379,32 @@ impl OAuth2SessionMutations {
 
             let refresh_token = repo
                 .oauth2_refresh_token()
-                .add(&mut rng, &clock, &session, &access_token, refresh_token)
+                .add(
+                    &mut rng,
+                    &clock,
+                    &session,
+                    &access_token,
+                    refresh_token,
+                    None,
+                )
                 .await?;
 
             Some(refresh_token)
         };
 
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({
+                "action": "create_oauth2_session",
+                "created_oauth2_session_id": session.id.to_string(),
+                "permanent": permanent,
+            }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(CreateOAuth2SessionPayload {
@@ -223,25 +431,249 @@ impl OAuth2SessionMutations {
             return Ok(EndOAuth2SessionPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&session) {
-            return Ok(EndOAuth2SessionPayload::NotFound);
-        }
-
-        if let Some(user_id) = session.user_id {
+        let user = if let Some(user_id) = session.user_id {
             let user = repo
                 .user()
                 .lookup(user_id)
                 .await?
                 .context("Could not load user")?;
 
+            if !requester.can_manage_sessions_of(&user) {
+                return Ok(EndOAuth2SessionPayload::NotFound);
+            }
+
             // Schedule a job to sync the devices of the user with the homeserver
             repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
-        }
+
+            Some(user)
+        } else {
+            // Sessions without a user can only be ended by admins
+            if !requester.is_admin() {
+                return Ok(EndOAuth2SessionPayload::NotFound);
+            }
+
+            None
+        };
 
         let session = repo.oauth2_session().finish(&clock, session).await?;
 
+        if let Some(user) = &user {
+            repo.job()
+                .schedule_job(DispatchWebhookJob::session_revoked(
+                    &mut state.rng(),
+                    &clock,
+                    user,
+                    "oauth2",
+                    session.id,
+                ))
+                .await?;
+        }
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::SessionEnded,
+            session.user_id,
+            serde_json::json!({ "ended_oauth2_session_id": session.id.to_string() }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(EndOAuth2SessionPayload::Ended(session))
     }
+
+    /// End all the OAuth 2.0 sessions of a user, and forget the consent they
+    /// gave to clients, so that they have to consent again on their next
+    /// authorization.
+    async fn revoke_all_oauth2_access(
+        &self,
+        ctx: &Context<'_>,
+        input: RevokeAllOAuth2AccessInput,
+    ) -> Result<RevokeAllOAuth2AccessPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(user_id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(RevokeAllOAuth2AccessPayload::NotFound);
+        };
+
+        let revoked_consents = repo
+            .oauth2_client()
+            .revoke_all_consents_for_user(&user)
+            .await?;
+
+        let ended_sessions = repo
+            .oauth2_session()
+            .finish_bulk(
+                &clock,
+                OAuth2SessionFilter::new().for_user(&user).active_only(),
+            )
+            .await?;
+
+        // Schedule a job to sync the devices of the user with the homeserver
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::SessionEnded,
+            Some(user.id),
+            serde_json::json!({
+                "action": "revoke_all_oauth2_access",
+                "ended_sessions": ended_sessions,
+                "revoked_consents": revoked_consents,
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(RevokeAllOAuth2AccessPayload::Revoked {
+            user,
+            ended_sessions,
+            revoked_consents,
+        })
+    }
+
+    /// Forget the consent a user gave to a client, so that they have to
+    /// consent again on their next authorization. Existing sessions are kept.
+    async fn revoke_consent(
+        &self,
+        ctx: &Context<'_>,
+        input: RevokeConsentInput,
+    ) -> Result<RevokeConsentPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let client_id = NodeType::OAuth2Client.extract_ulid(&input.client_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(user_id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(RevokeConsentPayload::NotFound);
+        };
+
+        let Some(client) = repo.oauth2_client().lookup(client_id).await? else {
+            return Ok(RevokeConsentPayload::NotFound);
+        };
+
+        let revoked = repo
+            .oauth2_client()
+            .revoke_consent_for_user(&client, &user)
+            .await?;
+
+        if !revoked {
+            return Ok(RevokeConsentPayload::NotFound);
+        }
+
+        if requester.is_admin() {
+            record_audit_event(
+                ctx,
+                &mut repo,
+                AuditEventKind::AdminAction,
+                Some(user.id),
+                serde_json::json!({
+                    "action": "revoke_consent",
+                    "client_id": client.client_id,
+                }),
+            )
+            .await?;
+        }
+
+        repo.save().await?;
+
+        Ok(RevokeConsentPayload::Revoked { user, client })
+    }
+
+    /// End the OAuth 2.0 sessions all the users have with a client, and
+    /// forget the consent they gave to it, so that they have to consent
+    /// again on their next authorization.
+    ///
+    /// Only available for administrators.
+    async fn revoke_oauth2_client_access(
+        &self,
+        ctx: &Context<'_>,
+        input: RevokeOAuth2ClientAccessInput,
+    ) -> Result<RevokeOAuth2ClientAccessPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let client_id = NodeType::OAuth2Client.extract_ulid(&input.client_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+
+        let Some(client) = repo.oauth2_client().lookup(client_id).await? else {
+            return Ok(RevokeOAuth2ClientAccessPayload::NotFound);
+        };
+
+        let revoked_consents = repo
+            .oauth2_client()
+            .revoke_all_consents_for_client(&client)
+            .await?;
+
+        let filter = OAuth2SessionFilter::new().for_client(&client).active_only();
+
+        // Collect the users who have a session with the client, to sync their
+        // devices once the sessions are ended
+        let mut user_ids = BTreeSet::new();
+        let mut cursor = Pagination::first(100);
+        loop {
+            let page = repo.oauth2_session().list(filter, cursor).await?;
+
+            for session in page.edges {
+                user_ids.extend(session.user_id);
+                cursor = cursor.after(session.id);
+            }
+
+            if !page.has_next_page {
+                break;
+            }
+        }
+
+        let ended_sessions = repo.oauth2_session().finish_bulk(&clock, filter).await?;
+
+        let users = repo.user().load_batch(user_ids).await?;
+        for user in users.values() {
+            repo.job().schedule_job(SyncDevicesJob::new(user)).await?;
+        }
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            None,
+            serde_json::json!({
+                "action": "revoke_oauth2_client_access",
+                "client_id": client.client_id,
+                "ended_sessions": ended_sessions,
+                "revoked_consents": revoked_consents,
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(RevokeOAuth2ClientAccessPayload::Revoked {
+            client,
+            ended_sessions,
+            revoked_consents,
+        })
+    }
 }
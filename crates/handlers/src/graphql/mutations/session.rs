@@ -0,0 +1,370 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{Context, Enum, InputObject, Object, ID};
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{JobRepositoryExt, SyncDevicesJob},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    user::{BrowserSessionFilter, BrowserSessionRepository, UserRepository},
+    RepositoryAccess,
+};
+
+use super::record_audit_event;
+use crate::graphql::{
+    model::{BrowserSession, CompatSession, Node, NodeType, OAuth2Session, User},
+    state::ContextExt,
+    UserId,
+};
+
+/// The maximum length of a session tag, in characters
+const MAX_TAG_LENGTH: usize = 64;
+
+/// Normalize a tag given by the user, returning `None` if it is not valid
+fn normalize_tag(tag: &str) -> Option<&str> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH || tag.chars().any(char::is_control) {
+        return None;
+    }
+
+    Some(tag)
+}
+
+#[derive(Default)]
+pub struct SessionMutations {
+    _private: (),
+}
+
+/// The input of the `addSessionTag` and `removeSessionTag` mutations.
+#[derive(InputObject)]
+pub struct SessionTagInput {
+    /// The ID of the session to update. This can be a browser session, a
+    /// compatibility session or an OAuth 2.0 session.
+    session_id: ID,
+
+    /// The tag to add or remove.
+    tag: String,
+}
+
+/// A session the user can tag
+pub enum TaggableSession {
+    Browser(mas_data_model::BrowserSession),
+    Compat(mas_data_model::CompatSession),
+    OAuth2(mas_data_model::Session),
+}
+
+/// The payload of the `addSessionTag` and `removeSessionTag` mutations.
+pub enum SessionTagPayload {
+    Invalid,
+    NotFound,
+    Updated(Box<TaggableSession>),
+}
+
+/// The status of the `addSessionTag` and `removeSessionTag` mutations.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum SessionTagStatus {
+    /// The tags of the session were updated.
+    Updated,
+
+    /// The session was not found.
+    NotFound,
+
+    /// The tag is empty, too long or contains control characters.
+    Invalid,
+}
+
+#[Object]
+impl SessionTagPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> SessionTagStatus {
+        match self {
+            Self::Updated(_) => SessionTagStatus::Updated,
+            Self::NotFound => SessionTagStatus::NotFound,
+            Self::Invalid => SessionTagStatus::Invalid,
+        }
+    }
+
+    /// The updated session.
+    async fn session(&self) -> Option<Node> {
+        match self {
+            Self::Updated(session) => Some(match &**session {
+                TaggableSession::Browser(session) => {
+                    Node::BrowserSession(Box::new(BrowserSession(session.clone())))
+                }
+                TaggableSession::Compat(session) => {
+                    Node::CompatSession(Box::new(CompatSession::new(session.clone())))
+                }
+                TaggableSession::OAuth2(session) => {
+                    Node::OAuth2Session(Box::new(OAuth2Session(session.clone())))
+                }
+            }),
+            Self::NotFound | Self::Invalid => None,
+        }
+    }
+
+    /// The tags of the session after the update.
+    async fn tags(&self) -> Option<&[String]> {
+        match self {
+            Self::Updated(session) => Some(match &**session {
+                TaggableSession::Browser(session) => &session.tags,
+                TaggableSession::Compat(session) => &session.tags,
+                TaggableSession::OAuth2(session) => &session.tags,
+            }),
+            Self::NotFound | Self::Invalid => None,
+        }
+    }
+}
+
+/// The input of the `endSessionsByTag` mutation.
+#[derive(InputObject)]
+pub struct EndSessionsByTagInput {
+    /// The ID of the user whose sessions should be ended.
+    user_id: ID,
+
+    /// End all the active sessions with this tag.
+    tag: String,
+}
+
+/// The payload of the `endSessionsByTag` mutation.
+pub enum EndSessionsByTagPayload {
+    Invalid,
+    NotFound,
+    Ended {
+        user: mas_data_model::User,
+        ended_sessions: usize,
+    },
+}
+
+/// The status of the `endSessionsByTag` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum EndSessionsByTagStatus {
+    /// The sessions were ended.
+    Ended,
+
+    /// The user was not found.
+    NotFound,
+
+    /// The tag is empty, too long or contains control characters.
+    Invalid,
+}
+
+#[Object]
+impl EndSessionsByTagPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> EndSessionsByTagStatus {
+        match self {
+            Self::Ended { .. } => EndSessionsByTagStatus::Ended,
+            Self::NotFound => EndSessionsByTagStatus::NotFound,
+            Self::Invalid => EndSessionsByTagStatus::Invalid,
+        }
+    }
+
+    /// The user whose sessions were ended.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Ended { user, .. } => Some(User(user.clone())),
+            Self::NotFound | Self::Invalid => None,
+        }
+    }
+
+    /// The number of sessions which were ended, across all session types.
+    async fn ended_sessions(&self) -> Option<usize> {
+        match self {
+            Self::Ended { ended_sessions, .. } => Some(*ended_sessions),
+            Self::NotFound | Self::Invalid => None,
+        }
+    }
+}
+
+impl SessionMutations {
+    async fn update_session_tag(
+        ctx: &Context<'_>,
+        input: SessionTagInput,
+        add: bool,
+    ) -> Result<SessionTagPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+        let (node_type, id) = NodeType::from_id(&input.session_id)?;
+
+        let Some(tag) = normalize_tag(&input.tag) else {
+            return Ok(SessionTagPayload::Invalid);
+        };
+
+        let mut repo = state.repository().await?;
+
+        // The tags are updated in a single statement by the repository, so that
+        // concurrent updates don't overwrite each other
+        let session = match node_type {
+            NodeType::BrowserSession => {
+                let Some(session) = repo
+                    .browser_session()
+                    .lookup(id)
+                    .await?
+                    .filter(|session| requester.is_owner_or_admin(session))
+                else {
+                    return Ok(SessionTagPayload::NotFound);
+                };
+
+                let session = if add {
+                    repo.browser_session().add_tag(session, tag).await?
+                } else {
+                    repo.browser_session().remove_tag(session, tag).await?
+                };
+                TaggableSession::Browser(session)
+            }
+
+            NodeType::CompatSession => {
+                let Some(session) = repo
+                    .compat_session()
+                    .lookup(id)
+                    .await?
+                    .filter(|session| requester.is_owner_or_admin(session))
+                else {
+                    return Ok(SessionTagPayload::NotFound);
+                };
+
+                let session = if add {
+                    repo.compat_session().add_tag(session, tag).await?
+                } else {
+                    repo.compat_session().remove_tag(session, tag).await?
+                };
+                TaggableSession::Compat(session)
+            }
+
+            NodeType::OAuth2Session => {
+                let Some(session) = repo
+                    .oauth2_session()
+                    .lookup(id)
+                    .await?
+                    .filter(|session| requester.is_owner_or_admin(session))
+                else {
+                    return Ok(SessionTagPayload::NotFound);
+                };
+
+                let session = if add {
+                    repo.oauth2_session().add_tag(session, tag).await?
+                } else {
+                    repo.oauth2_session().remove_tag(session, tag).await?
+                };
+                TaggableSession::OAuth2(session)
+            }
+
+            _ => return Ok(SessionTagPayload::NotFound),
+        };
+
+        repo.save().await?;
+
+        Ok(SessionTagPayload::Updated(Box::new(session)))
+    }
+}
+
+#[Object]
+impl SessionMutations {
+    /// Add a tag to a session, so that the user can group their sessions, for
+    /// example as 'work' or 'shared computer'. Adding a tag the session
+    /// already has does nothing.
+    async fn add_session_tag(
+        &self,
+        ctx: &Context<'_>,
+        input: SessionTagInput,
+    ) -> Result<SessionTagPayload, async_graphql::Error> {
+        Self::update_session_tag(ctx, input, true).await
+    }
+
+    /// Remove a tag from a session.
+    async fn remove_session_tag(
+        &self,
+        ctx: &Context<'_>,
+        input: SessionTagInput,
+    ) -> Result<SessionTagPayload, async_graphql::Error> {
+        Self::update_session_tag(ctx, input, false).await
+    }
+
+    /// End all the active sessions of a user with the given tag, whether they
+    /// are browser, compatibility or OAuth 2.0 sessions.
+    async fn end_sessions_by_tag(
+        &self,
+        ctx: &Context<'_>,
+        input: EndSessionsByTagInput,
+    ) -> Result<EndSessionsByTagPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(user_id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let Some(tag) = normalize_tag(&input.tag) else {
+            return Ok(EndSessionsByTagPayload::Invalid);
+        };
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(EndSessionsByTagPayload::NotFound);
+        };
+
+        let ended_browser_sessions = repo
+            .browser_session()
+            .finish_bulk(
+                &clock,
+                BrowserSessionFilter::new()
+                    .for_user(&user)
+                    .with_tag(tag)
+                    .active_only(),
+            )
+            .await?;
+
+        let ended_compat_sessions = repo
+            .compat_session()
+            .finish_bulk(
+                &clock,
+                CompatSessionFilter::new()
+                    .for_user(&user)
+                    .with_tag(tag)
+                    .active_only(),
+            )
+            .await?;
+
+        let ended_oauth2_sessions = repo
+            .oauth2_session()
+            .finish_bulk(
+                &clock,
+                OAuth2SessionFilter::new()
+                    .for_user(&user)
+                    .with_tag(tag)
+                    .active_only(),
+            )
+            .await?;
+
+        // Schedule a job to sync the devices of the user with the homeserver
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::SessionEnded,
+            Some(user.id),
+            serde_json::json!({
+                "action": "end_sessions_by_tag",
+                "tag": tag,
+                "ended_browser_sessions": ended_browser_sessions,
+                "ended_compat_sessions": ended_compat_sessions,
+                "ended_oauth2_sessions": ended_oauth2_sessions,
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(EndSessionsByTagPayload::Ended {
+            user,
+            ended_sessions: ended_browser_sessions + ended_compat_sessions + ended_oauth2_sessions,
+        })
+    }
+}
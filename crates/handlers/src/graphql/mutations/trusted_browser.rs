@@ -0,0 +1,102 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{Context, Enum, InputObject, Object, ID};
+use mas_storage::{user::UserTrustedBrowserRepository, Clock, RepositoryAccess};
+
+use crate::graphql::{
+    model::{NodeType, TrustedBrowser},
+    state::ContextExt,
+};
+
+#[derive(Default)]
+pub struct TrustedBrowserMutations {
+    _private: (),
+}
+
+/// The input of the `revokeTrustedBrowser` mutation.
+#[derive(InputObject)]
+pub struct RevokeTrustedBrowserInput {
+    /// The ID of the trusted browser to revoke.
+    trusted_browser_id: ID,
+}
+
+/// The payload of the `revokeTrustedBrowser` mutation.
+pub enum RevokeTrustedBrowserPayload {
+    NotFound,
+    Revoked(Box<mas_data_model::UserTrustedBrowser>),
+}
+
+/// The status of the `revokeTrustedBrowser` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum RevokeTrustedBrowserStatus {
+    /// The browser is not trusted anymore.
+    Revoked,
+
+    /// The trusted browser was not found.
+    NotFound,
+}
+
+#[Object]
+impl RevokeTrustedBrowserPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> RevokeTrustedBrowserStatus {
+        match self {
+            Self::Revoked(_) => RevokeTrustedBrowserStatus::Revoked,
+            Self::NotFound => RevokeTrustedBrowserStatus::NotFound,
+        }
+    }
+
+    /// Returns the revoked trusted browser.
+    async fn trusted_browser(&self) -> Option<TrustedBrowser> {
+        match self {
+            Self::Revoked(trusted_browser) => Some(TrustedBrowser(*trusted_browser.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+#[Object]
+impl TrustedBrowserMutations {
+    /// Stop trusting a browser, so that it is challenged again for additional
+    /// authentication factors on the next login.
+    async fn revoke_trusted_browser(
+        &self,
+        ctx: &Context<'_>,
+        input: RevokeTrustedBrowserInput,
+    ) -> Result<RevokeTrustedBrowserPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let trusted_browser_id =
+            NodeType::TrustedBrowser.extract_ulid(&input.trusted_browser_id)?;
+        let requester = ctx.requester();
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+
+        let trusted_browser = repo
+            .user_trusted_browser()
+            .lookup(trusted_browser_id)
+            .await?;
+
+        let Some(trusted_browser) = trusted_browser else {
+            return Ok(RevokeTrustedBrowserPayload::NotFound);
+        };
+
+        if !requester.is_owner_or_admin(&trusted_browser) || !trusted_browser.active(clock.now()) {
+            return Ok(RevokeTrustedBrowserPayload::NotFound);
+        }
+
+        let trusted_browser = repo
+            .user_trusted_browser()
+            .revoke(&clock, trusted_browser)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(RevokeTrustedBrowserPayload::Revoked(Box::new(
+            trusted_browser,
+        )))
+    }
+}
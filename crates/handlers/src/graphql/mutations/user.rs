@@ -6,15 +6,21 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use mas_data_model::AuditEventKind;
+use mas_i18n::DataLocale;
 use mas_storage::{
-    job::{DeactivateUserJob, JobRepositoryExt, ProvisionUserJob},
-    user::UserRepository,
+    job::{DeactivateUserJob, DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob},
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserActivityDigestRepository,
+        UserNotificationPreferencesRepository, UserRepository,
+    },
 };
 use tracing::{info, warn};
 use zeroize::Zeroizing;
 
+use super::record_audit_event;
 use crate::graphql::{
-    model::{NodeType, User},
+    model::{DigestFrequency, NodeType, NotificationPreferences, User},
     state::ContextExt,
     Requester, UserId,
 };
@@ -36,6 +42,10 @@ struct AddUserInput {
     /// by an application service needs to exist in MAS to craft special
     /// tokens (like with admin access) for them
     skip_homeserver_check: Option<bool>,
+
+    /// The server name of the homeserver the user belongs to. Defaults to the
+    /// main homeserver.
+    homeserver: Option<String>,
 }
 
 /// The status of the `addUser` mutation.
@@ -238,6 +248,151 @@ impl AllowUserCrossSigningResetPayload {
     }
 }
 
+/// The input for the `setActivityDigest` mutation.
+#[derive(InputObject)]
+struct SetActivityDigestInput {
+    /// The ID of the user to update.
+    user_id: ID,
+
+    /// Whether the user should receive the activity digest.
+    enabled: bool,
+
+    /// The language in which the digest should be sent, as a BCP 47 language
+    /// tag. Defaults to English.
+    locale: Option<String>,
+}
+
+/// The status of the `setActivityDigest` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum SetActivityDigestStatus {
+    /// The user will receive the activity digest.
+    Enabled,
+
+    /// The user will not receive the activity digest anymore.
+    Disabled,
+
+    /// Activity digests are not enabled on this server.
+    NotAllowed,
+
+    /// The locale is not a valid language tag.
+    InvalidLocale,
+
+    /// The user was not found.
+    NotFound,
+}
+
+/// The payload for the `setActivityDigest` mutation.
+#[derive(Description)]
+enum SetActivityDigestPayload {
+    /// The user will receive the activity digest.
+    Enabled(mas_data_model::User),
+
+    /// The user will not receive the activity digest anymore.
+    Disabled(mas_data_model::User),
+
+    /// Activity digests are not enabled on this server.
+    NotAllowed,
+
+    /// The locale is not a valid language tag.
+    InvalidLocale,
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetActivityDigestPayload {
+    /// Status of the operation
+    async fn status(&self) -> SetActivityDigestStatus {
+        match self {
+            Self::Enabled(_) => SetActivityDigestStatus::Enabled,
+            Self::Disabled(_) => SetActivityDigestStatus::Disabled,
+            Self::NotAllowed => SetActivityDigestStatus::NotAllowed,
+            Self::InvalidLocale => SetActivityDigestStatus::InvalidLocale,
+            Self::NotFound => SetActivityDigestStatus::NotFound,
+        }
+    }
+
+    /// The user that was updated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Enabled(user) | Self::Disabled(user) => Some(User(user.clone())),
+            Self::NotAllowed | Self::InvalidLocale | Self::NotFound => None,
+        }
+    }
+}
+
+/// The input for the `setNotificationPreferences` mutation.
+///
+/// The preferences which are not set are left unchanged.
+#[derive(InputObject)]
+struct SetNotificationPreferencesInput {
+    /// The ID of the user to update.
+    user_id: ID,
+
+    /// Whether the user should receive an alert when someone logs in to their
+    /// account.
+    new_login_alerts: Option<bool>,
+
+    /// How often the user should receive the digest of their account
+    /// activity.
+    digest_frequency: Option<DigestFrequency>,
+
+    /// Whether the user should only receive notifications about the security
+    /// of their account.
+    security_only: Option<bool>,
+}
+
+/// The status of the `setNotificationPreferences` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum SetNotificationPreferencesStatus {
+    /// The notification preferences were updated.
+    Updated,
+
+    /// The user was not found.
+    NotFound,
+}
+
+/// The payload for the `setNotificationPreferences` mutation.
+#[derive(Description)]
+enum SetNotificationPreferencesPayload {
+    /// The notification preferences were updated.
+    Updated(
+        mas_data_model::User,
+        mas_data_model::UserNotificationPreferences,
+    ),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetNotificationPreferencesPayload {
+    /// Status of the operation
+    async fn status(&self) -> SetNotificationPreferencesStatus {
+        match self {
+            Self::Updated(_, _) => SetNotificationPreferencesStatus::Updated,
+            Self::NotFound => SetNotificationPreferencesStatus::NotFound,
+        }
+    }
+
+    /// The user that was updated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Updated(user, _) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+
+    /// The new notification preferences of the user.
+    async fn notification_preferences(&self) -> Option<NotificationPreferences> {
+        match self {
+            Self::Updated(_, preferences) => Some((*preferences).into()),
+            Self::NotFound => None,
+        }
+    }
+}
+
 /// The input for the `setPassword` mutation.
 #[derive(InputObject)]
 struct SetPasswordInput {
@@ -323,6 +478,129 @@ impl SetPasswordPayload {
     }
 }
 
+/// The input for the `setUserPassword` mutation.
+#[derive(InputObject)]
+struct SetUserPasswordInput {
+    /// The ID of the user to set the password for.
+    user_id: ID,
+
+    /// The new password for the user.
+    password: String,
+
+    /// Require the user to change the password on their next login.
+    must_change: Option<bool>,
+}
+
+/// The status of the `setUserPassword` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum SetUserPasswordStatus {
+    /// The password was set.
+    Set,
+
+    /// The user was not found.
+    NotFound,
+
+    /// The password is invalid. For example, it may not meet configured
+    /// security requirements.
+    InvalidPassword,
+
+    /// Password support has been disabled.
+    PasswordsDisabled,
+}
+
+/// The payload for the `setUserPassword` mutation.
+#[derive(Description)]
+enum SetUserPasswordPayload {
+    /// The password was set.
+    Set(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+
+    /// The password is invalid.
+    InvalidPassword,
+
+    /// Password support has been disabled.
+    PasswordsDisabled,
+}
+
+#[Object(use_type_description)]
+impl SetUserPasswordPayload {
+    /// Status of the operation
+    async fn status(&self) -> SetUserPasswordStatus {
+        match self {
+            Self::Set(_) => SetUserPasswordStatus::Set,
+            Self::NotFound => SetUserPasswordStatus::NotFound,
+            Self::InvalidPassword => SetUserPasswordStatus::InvalidPassword,
+            Self::PasswordsDisabled => SetUserPasswordStatus::PasswordsDisabled,
+        }
+    }
+
+    /// The user whose password was set.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Set(user) => Some(User(user.clone())),
+            Self::NotFound | Self::InvalidPassword | Self::PasswordsDisabled => None,
+        }
+    }
+}
+
+/// The input for the `deactivateMyAccount` mutation.
+#[derive(InputObject)]
+struct DeactivateMyAccountInput {
+    /// The current password of the user. This is required if the user has a
+    /// password.
+    password: Option<String>,
+}
+
+/// The status of the `deactivateMyAccount` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum DeactivateMyAccountStatus {
+    /// The account is locked and will be deleted at the end of the grace
+    /// period.
+    Scheduled,
+
+    /// Users can't delete their own account on this server.
+    NotAllowed,
+
+    /// The supplied password was missing or wrong.
+    WrongPassword,
+}
+
+/// The payload for the `deactivateMyAccount` mutation.
+#[derive(Description)]
+enum DeactivateMyAccountPayload {
+    /// The account is locked and will be deleted at the end of the grace
+    /// period.
+    Scheduled(mas_data_model::User),
+
+    /// Users can't delete their own account on this server.
+    NotAllowed,
+
+    /// The supplied password was missing or wrong.
+    WrongPassword,
+}
+
+#[Object(use_type_description)]
+impl DeactivateMyAccountPayload {
+    /// Status of the operation
+    async fn status(&self) -> DeactivateMyAccountStatus {
+        match self {
+            Self::Scheduled(_) => DeactivateMyAccountStatus::Scheduled,
+            Self::NotAllowed => DeactivateMyAccountStatus::NotAllowed,
+            Self::WrongPassword => DeactivateMyAccountStatus::WrongPassword,
+        }
+    }
+
+    /// The user whose account will be deleted.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Scheduled(user) => Some(User(user.clone())),
+            Self::NotAllowed | Self::WrongPassword => None,
+        }
+    }
+}
+
 fn valid_username_character(c: char) -> bool {
     c.is_ascii_lowercase()
         || c.is_ascii_digit()
@@ -370,9 +648,24 @@ impl UserMutations {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
+        let site_config = state.site_config();
+        let homeserver = match input.homeserver {
+            Some(homeserver) if site_config.is_additional_homeserver(&homeserver) => {
+                Some(homeserver)
+            }
+            Some(homeserver) if homeserver != site_config.server_name => {
+                return Err(async_graphql::Error::new("Unknown homeserver"));
+            }
+            _ => None,
+        };
+
         let mut repo = state.repository().await?;
 
-        if let Some(user) = repo.user().find_by_username(&input.username).await? {
+        if let Some(user) = repo
+            .user()
+            .find_by_username(homeserver.as_deref().into(), &input.username)
+            .await?
+        {
             return Ok(AddUserPayload::Exists(user));
         }
 
@@ -384,7 +677,7 @@ impl UserMutations {
         // Ask the homeserver if the username is available
         let homeserver_available = state
             .homeserver_connection()
-            .is_localpart_available(&input.username)
+            .is_localpart_available_on(homeserver.as_deref(), &input.username)
             .await?;
 
         if !homeserver_available {
@@ -396,12 +689,33 @@ impl UserMutations {
             warn!("Skipped homeserver check for username {}", input.username);
         }
 
-        let user = repo.user().add(&mut rng, &clock, input.username).await?;
+        let user = repo
+            .user()
+            .add_for_homeserver(
+                &mut rng,
+                &clock,
+                homeserver.as_deref().into(),
+                input.username,
+            )
+            .await?;
 
         repo.job()
             .schedule_job(ProvisionUserJob::new(&user))
             .await?;
 
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_registered(&mut rng, &clock, &user))
+            .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "add_user" }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(AddUserPayload::Added(user))
@@ -431,8 +745,19 @@ impl UserMutations {
 
         let deactivate = input.deactivate.unwrap_or(false);
 
+        let was_locked = user.locked_at.is_some();
         let user = repo.user().lock(&state.clock(), user).await?;
 
+        if !was_locked {
+            repo.job()
+                .schedule_job(DispatchWebhookJob::user_updated(
+                    &mut state.rng(),
+                    &state.clock(),
+                    &user,
+                ))
+                .await?;
+        }
+
         if deactivate {
             info!("Scheduling deactivation of user {}", user.id);
             repo.job()
@@ -440,6 +765,15 @@ impl UserMutations {
                 .await?;
         }
 
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "lock_user", "deactivate": deactivate }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(LockUserPayload::Locked(user))
@@ -468,12 +802,32 @@ impl UserMutations {
         };
 
         // Call the homeserver synchronously to unlock the user
-        let mxid = matrix.mxid(&user.username);
+        let mxid = matrix.mxid_on(user.homeserver.as_deref(), &user.username);
         matrix.reactivate_user(&mxid).await?;
 
         // Now unlock the user in our database
+        let was_locked = user.locked_at.is_some();
         let user = repo.user().unlock(user).await?;
 
+        if was_locked {
+            repo.job()
+                .schedule_job(DispatchWebhookJob::user_updated(
+                    &mut state.rng(),
+                    &state.clock(),
+                    &user,
+                ))
+                .await?;
+        }
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({ "action": "unlock_user" }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(UnlockUserPayload::Unlocked(user))
@@ -502,11 +856,34 @@ impl UserMutations {
             return Ok(SetCanRequestAdminPayload::NotFound);
         };
 
+        let changed = user.can_request_admin != input.can_request_admin;
         let user = repo
             .user()
             .set_can_request_admin(user, input.can_request_admin)
             .await?;
 
+        if changed {
+            repo.job()
+                .schedule_job(DispatchWebhookJob::user_updated(
+                    &mut state.rng(),
+                    &state.clock(),
+                    &user,
+                ))
+                .await?;
+        }
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({
+                "action": "set_can_request_admin",
+                "can_request_admin": input.can_request_admin,
+            }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(SetCanRequestAdminPayload::Updated(user))
@@ -535,7 +912,7 @@ impl UserMutations {
         };
 
         let conn = state.homeserver_connection();
-        let mxid = conn.mxid(&user.username);
+        let mxid = conn.mxid_on(user.homeserver.as_deref(), &user.username);
 
         conn.allow_cross_signing_reset(&mxid)
             .await
@@ -544,6 +921,104 @@ impl UserMutations {
         Ok(AllowUserCrossSigningResetPayload::Allowed(user))
     }
 
+    /// Subscribe or unsubscribe a user from the periodic email digest of the
+    /// activity on their account.
+    async fn set_activity_digest(
+        &self,
+        ctx: &Context<'_>,
+        input: SetActivityDigestInput,
+    ) -> Result<SetActivityDigestPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(user_id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        if state.site_config().activity_digest_interval.is_none() {
+            return Ok(SetActivityDigestPayload::NotAllowed);
+        }
+
+        let locale = match input.locale.as_deref() {
+            Some(locale) => match locale.parse::<DataLocale>() {
+                Ok(locale) => locale.to_string(),
+                Err(_) => return Ok(SetActivityDigestPayload::InvalidLocale),
+            },
+            None => "en".to_owned(),
+        };
+
+        let mut repo = state.repository().await?;
+
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(SetActivityDigestPayload::NotFound);
+        };
+
+        if input.enabled {
+            let mut rng = state.rng();
+            let clock = state.clock();
+            repo.user_activity_digest()
+                .subscribe(&mut rng, &clock, &user, locale)
+                .await?;
+        } else {
+            repo.user_activity_digest().unsubscribe(&user).await?;
+        }
+
+        repo.save().await?;
+
+        if input.enabled {
+            Ok(SetActivityDigestPayload::Enabled(user))
+        } else {
+            Ok(SetActivityDigestPayload::Disabled(user))
+        }
+    }
+
+    /// Update the preferences of a user about the notifications they receive
+    /// by email.
+    async fn set_notification_preferences(
+        &self,
+        ctx: &Context<'_>,
+        input: SetNotificationPreferencesInput,
+    ) -> Result<SetNotificationPreferencesPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(user_id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(SetNotificationPreferencesPayload::NotFound);
+        };
+
+        let mut preferences = repo.user_notification_preferences().get(&user).await?;
+        if let Some(new_login_alerts) = input.new_login_alerts {
+            preferences.new_login_alerts = new_login_alerts;
+        }
+        if let Some(digest_frequency) = input.digest_frequency {
+            preferences.digest_frequency = digest_frequency.into();
+        }
+        if let Some(security_only) = input.security_only {
+            preferences.security_only = security_only;
+        }
+
+        let clock = state.clock();
+        let preferences = repo
+            .user_notification_preferences()
+            .set(&clock, &user, preferences)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetNotificationPreferencesPayload::Updated(
+            user,
+            preferences,
+        ))
+    }
+
     /// Set the password for a user.
     ///
     /// This can be used by server administrators to set any user's password,
@@ -596,10 +1071,14 @@ impl UserMutations {
 
         if !requester.is_admin() {
             // If the user isn't an admin, we:
-            // - check that password changes are enabled
+            // - check that password changes are enabled, and that they are allowed to log
+            //   in with a password
             // - check that they know their current password
 
-            if !state.site_config().password_change_allowed {
+            let site_config = state.site_config();
+            if !site_config.password_change_allowed
+                || !site_config.is_password_login_allowed(&user.username)
+            {
                 return Ok(SetPasswordPayload {
                     status: SetPasswordStatus::PasswordChangesDisabled,
                 });
@@ -650,6 +1129,26 @@ impl UserMutations {
             )
             .await?;
 
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::PasswordChanged,
+            Some(user.id),
+            serde_json::json!({ "method": "graphql" }),
+        )
+        .await?;
+
+        if requester.is_admin() {
+            record_audit_event(
+                ctx,
+                &mut repo,
+                AuditEventKind::AdminAction,
+                Some(user.id),
+                serde_json::json!({ "action": "set_password" }),
+            )
+            .await?;
+        }
+
         repo.save().await?;
 
         Ok(SetPasswordPayload {
@@ -657,6 +1156,96 @@ impl UserMutations {
         })
     }
 
+    /// Set the password of a user, and end all their browser sessions. This is
+    /// only available to administrators.
+    ///
+    /// This is meant for support staff to issue temporary passwords, which
+    /// users can be required to change on their next login.
+    async fn set_user_password(
+        &self,
+        ctx: &Context<'_>,
+        input: SetUserPasswordInput,
+    ) -> Result<SetUserPasswordPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let password_manager = state.password_manager();
+        if !password_manager.is_enabled() {
+            return Ok(SetUserPasswordPayload::PasswordsDisabled);
+        }
+
+        if input.password.is_empty()
+            || !password_manager.is_password_complex_enough(&input.password)?
+        {
+            return Ok(SetUserPasswordPayload::InvalidPassword);
+        }
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            return Ok(SetUserPasswordPayload::NotFound);
+        };
+
+        let (version, hashed_password) = password_manager
+            .hash(&mut rng, Zeroizing::new(input.password.into_bytes()))
+            .await?;
+
+        let password = repo
+            .user_password()
+            .add(&mut rng, &clock, &user, version, hashed_password, None)
+            .await?;
+
+        let must_change = input.must_change.unwrap_or(false);
+        if must_change {
+            repo.user_password()
+                .require_change(&clock, password)
+                .await?;
+        }
+
+        // Whoever was logged in with the previous password shouldn't stay
+        // logged in
+        let ended_browser_sessions = repo
+            .browser_session()
+            .finish_bulk(
+                &clock,
+                BrowserSessionFilter::new().for_user(&user).active_only(),
+            )
+            .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::PasswordChanged,
+            Some(user.id),
+            serde_json::json!({ "method": "graphql" }),
+        )
+        .await?;
+
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::AdminAction,
+            Some(user.id),
+            serde_json::json!({
+                "action": "set_user_password",
+                "must_change": must_change,
+                "ended_browser_sessions": ended_browser_sessions,
+            }),
+        )
+        .await?;
+
+        repo.save().await?;
+
+        Ok(SetUserPasswordPayload::Set(user))
+    }
+
     /// Set the password for yourself, using a recovery ticket sent by e-mail.
     async fn set_password_by_recovery(
         &self,
@@ -752,10 +1341,99 @@ impl UserMutations {
             .consume_ticket(&clock, ticket, session)
             .await?;
 
+        record_audit_event(
+            ctx,
+            &mut repo,
+            AuditEventKind::PasswordChanged,
+            Some(user.id),
+            serde_json::json!({ "method": "recovery" }),
+        )
+        .await?;
+
         repo.save().await?;
 
         Ok(SetPasswordPayload {
             status: SetPasswordStatus::Allowed,
         })
     }
+
+    /// Ask for the deletion of the account of the current user.
+    ///
+    /// The account is locked straight away, and deleted at the end of the
+    /// grace period configured on the server. Logging back in before then
+    /// cancels the deletion.
+    async fn deactivate_my_account(
+        &self,
+        ctx: &Context<'_>,
+        input: DeactivateMyAccountInput,
+    ) -> Result<DeactivateMyAccountPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        // This is only available from the account UI, not to OAuth 2.0 clients
+        let Some(browser_session) = requester.browser_session() else {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        };
+
+        let Some(grace_period) = state.site_config().account_deletion_grace_period else {
+            return Ok(DeactivateMyAccountPayload::NotAllowed);
+        };
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let user = repo
+            .user()
+            .lookup(browser_session.user.id)
+            .await?
+            .context("Could not load current user")?;
+
+        // Users who have a password have to confirm it
+        if let Some(active_password) = repo.user_password().active(&user).await? {
+            let Some(password) = input.password else {
+                return Ok(DeactivateMyAccountPayload::WrongPassword);
+            };
+
+            if let Err(_err) = state
+                .password_manager()
+                .verify(
+                    active_password.version,
+                    Zeroizing::new(password.into_bytes()),
+                    active_password.hashed_password,
+                )
+                .await
+            {
+                return Ok(DeactivateMyAccountPayload::WrongPassword);
+            }
+        }
+
+        let deletion_scheduled_at = clock.now() + grace_period;
+        let user = repo
+            .user()
+            .schedule_deletion(user, deletion_scheduled_at)
+            .await?;
+        let user = repo.user().lock(&clock, user).await?;
+
+        repo.browser_session()
+            .finish_bulk(
+                &clock,
+                BrowserSessionFilter::new().for_user(&user).active_only(),
+            )
+            .await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::user_updated(&mut rng, &clock, &user))
+            .await?;
+
+        info!(
+            user.id = %user.id,
+            %deletion_scheduled_at,
+            "User asked for the deletion of their account"
+        );
+
+        repo.save().await?;
+
+        Ok(DeactivateMyAccountPayload::Scheduled(user))
+    }
 }
@@ -413,8 +413,23 @@ impl UserEmailMutations {
         }
 
         if !skip_policy_check {
+            let metadata = ctx.request_metadata();
+            let last_authentication = match requester.browser_session() {
+                Some(browser_session) => {
+                    repo.browser_session()
+                        .get_last_authentication(browser_session)
+                        .await?
+                }
+                None => None,
+            };
+            let policy_requester =
+                mas_policy::Requester::new(metadata.ip_address, metadata.user_agent.clone())
+                    .with_authentication(last_authentication.as_ref());
+
             let mut policy = state.policy().await?;
-            let res = policy.evaluate_email(&input.email).await?;
+            let res = policy
+                .evaluate_email(&input.email, requester.user(), &policy_requester)
+                .await?;
             if !res.valid() {
                 return Ok(AddEmailPayload::Denied {
                     violations: res.violations,
@@ -0,0 +1,159 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_graphql::{
+    connection::{query, Connection, Edge, OpaqueCursor},
+    Context, Object, ID,
+};
+use chrono::{DateTime, Utc};
+use mas_storage::{audit::AuditEventFilter, pagination::Count, Pagination};
+
+use crate::graphql::{
+    model::{
+        AuditEvent, AuditEventKind, CountMode, Cursor, NodeCursor, NodeType, PreloadedTotalCount,
+    },
+    state::ContextExt as _,
+};
+
+#[derive(Default)]
+pub struct AuditQuery;
+
+#[Object]
+impl AuditQuery {
+    /// Get a list of audit events.
+    ///
+    /// This is only available to administrators.
+    #[allow(clippy::too_many_arguments)]
+    async fn audit_events(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "List only events about the given user.")] user: Option<ID>,
+        #[graphql(desc = "List only events performed by the given user.")] actor: Option<ID>,
+        #[graphql(desc = "List only events of the given kind.")] kind: Option<AuditEventKind>,
+        #[graphql(desc = "List only events which were recorded at or after the given time.")]
+        since: Option<DateTime<Utc>>,
+        #[graphql(desc = "List only events which were recorded before the given time.")]
+        until: Option<DateTime<Utc>>,
+
+        #[graphql(desc = "How to count the total number of items. Defaults to `EXACT`.")]
+        count_mode: Option<CountMode>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, AuditEvent, PreloadedTotalCount>, async_graphql::Error> {
+        let requester = ctx.requester();
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let state = ctx.state();
+        let mut repo = state.read_only_repository().await?;
+
+        let user_id = user
+            .map(|id| NodeType::User.extract_ulid(&id))
+            .transpose()?;
+        let actor_id = actor
+            .map(|id| NodeType::User.extract_ulid(&id))
+            .transpose()?;
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::AuditEvent))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::AuditEvent))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                let user = if let Some(user_id) = user_id {
+                    let user = repo
+                        .user()
+                        .lookup(user_id)
+                        .await?
+                        .ok_or(async_graphql::Error::new("Unknown user ID"))?;
+                    Some(user)
+                } else {
+                    None
+                };
+
+                let actor = if let Some(actor_id) = actor_id {
+                    let actor = repo
+                        .user()
+                        .lookup(actor_id)
+                        .await?
+                        .ok_or(async_graphql::Error::new("Unknown actor ID"))?;
+                    Some(actor)
+                } else {
+                    None
+                };
+
+                // Build the query filter
+                let filter = AuditEventFilter::new();
+                let filter = match &user {
+                    Some(user) => filter.for_user(user),
+                    None => filter,
+                };
+                let filter = match &actor {
+                    Some(actor) => filter.for_actor(actor),
+                    None => filter,
+                };
+                let filter = match kind {
+                    Some(kind) => filter.with_kind(kind.into()),
+                    None => filter,
+                };
+                let filter = match since {
+                    Some(since) => filter.since(since),
+                    None => filter,
+                };
+                let filter = match until {
+                    Some(until) => filter.until(until),
+                    None => filter,
+                };
+
+                let page = repo.audit_event().list(filter, pagination).await?;
+
+                // Preload the total count if requested
+                let look_ahead = ctx.look_ahead();
+                let count = if look_ahead.field("totalCount").exists()
+                    || look_ahead.field("totalCountEstimated").exists()
+                {
+                    Some(match count_mode.unwrap_or_default() {
+                        CountMode::Exact => Count::Exact(repo.audit_event().count(filter).await?),
+                        CountMode::Estimated => repo.audit_event().estimate_count(filter).await?,
+                    })
+                } else {
+                    None
+                };
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::with_additional_fields(
+                    page.has_previous_page,
+                    page.has_next_page,
+                    PreloadedTotalCount(count),
+                );
+                connection.edges.extend(page.edges.into_iter().map(|e| {
+                    Edge::new(
+                        OpaqueCursor(NodeCursor(NodeType::AuditEvent, e.id)),
+                        AuditEvent(e),
+                    )
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+}
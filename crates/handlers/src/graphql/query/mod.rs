@@ -14,13 +14,15 @@ use crate::graphql::{
     state::ContextExt,
 };
 
+mod audit;
 mod session;
 mod upstream_oauth;
 mod user;
 mod viewer;
 
 use self::{
-    session::SessionQuery, upstream_oauth::UpstreamOAuthQuery, user::UserQuery, viewer::ViewerQuery,
+    audit::AuditQuery, session::SessionQuery, upstream_oauth::UpstreamOAuthQuery, user::UserQuery,
+    viewer::ViewerQuery,
 };
 
 /// The query root of the GraphQL interface.
@@ -31,6 +33,7 @@ pub struct Query(
     UpstreamOAuthQuery,
     SessionQuery,
     ViewerQuery,
+    AuditQuery,
 );
 
 impl Query {
@@ -199,7 +202,11 @@ impl BaseQuery {
 
         let ret = match node_type {
             // TODO
-            NodeType::Authentication | NodeType::CompatSsoLogin => None,
+            NodeType::AuditEvent
+            | NodeType::Authentication
+            | NodeType::CompatSsoLogin
+            | NodeType::KioskDevice
+            | NodeType::TrustedBrowser => None,
 
             NodeType::UpstreamOAuth2Provider => UpstreamOAuthQuery
                 .upstream_oauth2_provider(ctx, id)
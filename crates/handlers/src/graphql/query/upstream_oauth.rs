@@ -8,7 +8,9 @@ use async_graphql::{
     connection::{query, Connection, Edge, OpaqueCursor},
     Context, Object, ID,
 };
-use mas_storage::{upstream_oauth2::UpstreamOAuthProviderFilter, Pagination, RepositoryAccess};
+use mas_storage::{
+    pagination::Count, upstream_oauth2::UpstreamOAuthProviderFilter, Pagination, RepositoryAccess,
+};
 
 use crate::graphql::{
     model::{
@@ -118,7 +120,9 @@ impl UpstreamOAuthQuery {
 
                 // Preload the total count if requested
                 let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.upstream_oauth_provider().count(filter).await?)
+                    Some(Count::Exact(
+                        repo.upstream_oauth_provider().count(filter).await?,
+                    ))
                 } else {
                     None
                 };
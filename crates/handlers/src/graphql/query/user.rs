@@ -8,12 +8,19 @@ use async_graphql::{
     connection::{query, Connection, Edge, OpaqueCursor},
     Context, Enum, Object, ID,
 };
-use mas_storage::{user::UserFilter, Pagination};
+use mas_storage::{
+    pagination::Count,
+    user::{UserFilter, UserHomeserver},
+    Pagination,
+};
 
-use crate::graphql::{
-    model::{Cursor, NodeCursor, NodeType, PreloadedTotalCount, User},
-    state::ContextExt as _,
-    UserId,
+use crate::{
+    graphql::{
+        model::{CountMode, Cursor, NodeCursor, NodeType, PreloadedTotalCount, User},
+        state::ContextExt as _,
+        UserId,
+    },
+    homeserver::user_homeserver,
 };
 
 #[derive(Default)]
@@ -38,7 +45,7 @@ impl UserQuery {
         // for but that would make the code more complex and we're not very
         // concerned about performance yet
         let state = ctx.state();
-        let mut repo = state.repository().await?;
+        let mut repo = state.read_only_repository().await?;
         let user = repo.user().lookup(id).await?;
         repo.cancel().await?;
 
@@ -50,12 +57,23 @@ impl UserQuery {
         &self,
         ctx: &Context<'_>,
         username: String,
+
+        #[graphql(
+            name = "homeserver",
+            desc = "The server name of the homeserver the user belongs to. Defaults to the main homeserver."
+        )]
+        homeserver_param: Option<String>,
     ) -> Result<Option<User>, async_graphql::Error> {
         let requester = ctx.requester();
         let state = ctx.state();
-        let mut repo = state.repository().await?;
-
-        let user = repo.user().find_by_username(&username).await?;
+        let homeserver = homeserver_param
+            .as_deref()
+            .map_or(UserHomeserver::Main, |server_name| {
+                user_homeserver(state.site_config(), server_name)
+            });
+        let mut repo = state.read_only_repository().await?;
+
+        let user = repo.user().find_by_username(homeserver, &username).await?;
         let Some(user) = user else {
             // We don't want to leak the existence of a user
             return Ok(None);
@@ -85,6 +103,15 @@ impl UserQuery {
         )]
         can_request_admin_param: Option<bool>,
 
+        #[graphql(
+            name = "homeserver",
+            desc = "List only users of the homeserver with the given server name."
+        )]
+        homeserver_param: Option<String>,
+
+        #[graphql(desc = "How to count the total number of items. Defaults to `EXACT`.")]
+        count_mode: Option<CountMode>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -98,7 +125,7 @@ impl UserQuery {
         }
 
         let state = ctx.state();
-        let mut repo = state.repository().await?;
+        let mut repo = state.read_only_repository().await?;
 
         query(
             after,
@@ -126,12 +153,27 @@ impl UserQuery {
                     Some(UserState::Locked) => filter.locked_only(),
                     None => filter,
                 };
+                let filter = match homeserver_param.as_deref() {
+                    Some(server_name) if server_name == state.site_config().server_name => {
+                        filter.for_homeserver(UserHomeserver::Main)
+                    }
+                    Some(server_name) => {
+                        filter.for_homeserver(UserHomeserver::Additional(server_name))
+                    }
+                    None => filter,
+                };
 
                 let page = repo.user().list(filter, pagination).await?;
 
                 // Preload the total count if requested
-                let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.user().count(filter).await?)
+                let look_ahead = ctx.look_ahead();
+                let count = if look_ahead.field("totalCount").exists()
+                    || look_ahead.field("totalCountEstimated").exists()
+                {
+                    Some(match count_mode.unwrap_or_default() {
+                        CountMode::Exact => Count::Exact(repo.user().count(filter).await?),
+                        CountMode::Estimated => repo.user().estimate_count(filter).await?,
+                    })
                 } else {
                     None
                 };
@@ -4,16 +4,24 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::sync::Arc;
+
 use mas_data_model::SiteConfig;
 use mas_matrix::HomeserverConnection;
 use mas_policy::Policy;
 use mas_storage::{BoxClock, BoxRepository, BoxRng, RepositoryError};
 
-use crate::{graphql::Requester, passwords::PasswordManager};
+use crate::{
+    graphql::{loaders::Loaders, RequestMetadata, Requester},
+    passwords::PasswordManager,
+};
 
 #[async_trait::async_trait]
 pub trait State {
     async fn repository(&self) -> Result<BoxRepository, RepositoryError>;
+    /// Get a repository for pure lookups, which may be backed by a read-only
+    /// replica of the database
+    async fn read_only_repository(&self) -> Result<BoxRepository, RepositoryError>;
     async fn policy(&self) -> Result<Policy, mas_policy::InstantiateError>;
     fn password_manager(&self) -> PasswordManager;
     fn homeserver_connection(&self) -> &dyn HomeserverConnection<Error = anyhow::Error>;
@@ -22,20 +30,32 @@ pub trait State {
     fn site_config(&self) -> &SiteConfig;
 }
 
-pub type BoxState = Box<dyn State + Send + Sync + 'static>;
+pub type SharedState = Arc<dyn State + Send + Sync + 'static>;
 
 pub trait ContextExt {
-    fn state(&self) -> &BoxState;
+    fn state(&self) -> &SharedState;
+
+    fn loaders(&self) -> &Loaders;
 
     fn requester(&self) -> &Requester;
+
+    fn request_metadata(&self) -> &RequestMetadata;
 }
 
 impl ContextExt for async_graphql::Context<'_> {
-    fn state(&self) -> &BoxState {
+    fn state(&self) -> &SharedState {
+        self.data_unchecked()
+    }
+
+    fn loaders(&self) -> &Loaders {
         self.data_unchecked()
     }
 
     fn requester(&self) -> &Requester {
         self.data_unchecked()
     }
+
+    fn request_metadata(&self) -> &RequestMetadata {
+        self.data_unchecked()
+    }
 }
@@ -5,12 +5,15 @@
 // Please see LICENSE in the repository root for full details.
 
 use axum::http::Request;
+use chrono::Duration;
 use hyper::StatusCode;
-use mas_data_model::{AccessToken, Client, TokenType, User};
+use mas_data_model::{AccessToken, Client, SiteConfig, TokenType, User};
 use mas_matrix::{HomeserverConnection, ProvisionRequest};
 use mas_router::SimpleRoute;
 use mas_storage::{
-    oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository},
+    compat::CompatAccessTokenRepository,
+    oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2SessionRepository},
+    user::{UserPasswordRepository, UserRepository, UserTrustedBrowserRepository},
     RepositoryAccess,
 };
 use oauth2_types::{
@@ -19,6 +22,7 @@ use oauth2_types::{
     scope::{Scope, ScopeToken, OPENID},
 };
 use sqlx::PgPool;
+use zeroize::Zeroizing;
 
 use crate::{
     test_utils,
@@ -50,6 +54,9 @@ async fn create_test_client(state: &TestState) -> Client {
             None,
             None,
             None,
+            false,
+            None,
+            false,
         )
         .await
         .unwrap();
@@ -100,7 +107,15 @@ async fn start_oauth_session(
 
     let access_token = repo
         .oauth2_access_token()
-        .add(&mut rng, &state.clock, &session, access_token_str, None)
+        .add(
+            &mut rng,
+            &state.clock,
+            &session,
+            access_token_str,
+            None,
+            None,
+            None,
+        )
         .await
         .unwrap();
 
@@ -346,6 +361,136 @@ async fn test_oauth2_admin(pool: PgPool) {
     );
 }
 
+/// Test that admin privileges expire when an admin elevation TTL is
+/// configured, until the user authenticates again.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_admin_elevation(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool_with_site_config(
+        pool,
+        SiteConfig {
+            admin_elevation_ttl: Some(Duration::try_minutes(10).unwrap()),
+            ..test_utils::test_site_config()
+        },
+    )
+    .await
+    .unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let user2 = create_test_user(&state, "bob").await;
+
+    // Start an admin session from a browser session where the user
+    // authenticated with a password
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let browser_session = repo
+        .browser_session()
+        .add(&mut rng, &state.clock, &user, None)
+        .await
+        .unwrap();
+    let password = repo
+        .user_password()
+        .add(&mut rng, &state.clock, &user, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+        .await
+        .unwrap();
+    let session = repo
+        .oauth2_session()
+        .add_from_browser_session(
+            &mut rng,
+            &state.clock,
+            &client,
+            &browser_session,
+            Scope::from_iter([GRAPHQL, ADMIN]),
+        )
+        .await
+        .unwrap();
+    let access_token_str = TokenType::AccessToken.generate(&mut rng);
+    let access_token = repo
+        .oauth2_access_token()
+        .add(
+            &mut rng,
+            &state.clock,
+            &session,
+            access_token_str,
+            Some(Duration::try_hours(1).unwrap()),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let query_user2 = || {
+        Request::post("/graphql")
+            .bearer(&access_token.access_token)
+            .json(serde_json::json!({
+                "query": r"
+                    query UserQuery($id: ID) {
+                        user(id: $id) {
+                            username
+                        }
+                    }
+                ",
+                "variables": {
+                    "id": format!("user:{id}", id = user2.id),
+                },
+            }))
+    };
+
+    // Right after authenticating, the session has admin privileges
+    let response = state.request(query_user2()).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "user": {
+                "username": "bob",
+            },
+        })
+    );
+
+    // After the TTL, the session drops back to normal privileges
+    state.clock.advance(Duration::try_minutes(11).unwrap());
+    let response = state.request(query_user2()).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "user": null,
+        })
+    );
+
+    // Authenticating again restores them
+    let mut repo = state.repository().await.unwrap();
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let response = state.request(query_user2()).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "user": {
+                "username": "bob",
+            },
+        })
+    );
+}
+
 /// Test that we can query the GraphQL endpoint with a token from a
 /// client_credentials grant.
 #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -775,3 +920,968 @@ async fn test_add_user(pool: PgPool) {
         })
     );
 }
+
+/// Test that administrators can set the password of a user, which ends their
+/// browser sessions.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_set_user_password(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+    let access_token_admin =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    // Give bob a browser session
+    let mut repo = state.repository().await.unwrap();
+    let bob_session = repo
+        .browser_session()
+        .add(&mut state.rng(), &state.clock, &bob, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let query = r"
+        mutation SetUserPassword($id: ID!, $password: String!) {
+            setUserPassword(input: {userId: $id, password: $password, mustChange: true}) {
+                status
+                user {
+                    username
+                }
+            }
+        }
+    ";
+
+    // Regular users can't set the password of others
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "id": format!("user:{id}", id = bob.id),
+                "password": "correct horse battery staple",
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    // Empty passwords are rejected
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "id": format!("user:{id}", id = bob.id),
+                "password": "",
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setUserPassword": {
+                "status": "INVALID_PASSWORD",
+                "user": null,
+            }
+        })
+    );
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "id": format!("user:{id}", id = bob.id),
+                "password": "correct horse battery staple",
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setUserPassword": {
+                "status": "SET",
+                "user": {
+                    "username": "bob",
+                },
+            }
+        })
+    );
+
+    // The password has to be changed on the next login, and the browser
+    // sessions of bob were ended
+    let mut repo = state.repository().await.unwrap();
+    let password = repo
+        .user_password()
+        .active(&bob)
+        .await
+        .unwrap()
+        .expect("bob should have a password");
+    assert!(password.must_change());
+
+    let bob_session = repo
+        .browser_session()
+        .lookup(bob_session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!bob_session.active());
+}
+
+/// Test that users can revoke the access of all their OAuth 2.0 sessions at
+/// once, which also forgets the consent they gave to clients.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_revoke_all_oauth2_access(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    start_oauth_session(&state, &client, &user, Scope::from_iter([OPENID])).await;
+
+    let mut repo = state.repository().await.unwrap();
+    repo.oauth2_client()
+        .give_consent_for_user(
+            &mut state.rng(),
+            &state.clock,
+            &client,
+            &user,
+            &Scope::from_iter([OPENID]),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({
+            "query": r"
+                mutation RevokeAll($userId: ID!) {
+                    revokeAllOauth2Access(input: {userId: $userId}) {
+                        status
+                        endedSessions
+                        revokedConsents
+                    }
+                }
+            ",
+            "variables": {
+                "userId": format!("user:{id}", id = user.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "revokeAllOauth2Access": {
+                "status": "REVOKED",
+                "endedSessions": 2,
+                "revokedConsents": 1,
+            }
+        })
+    );
+
+    // The consent is gone
+    let mut repo = state.repository().await.unwrap();
+    let consent = repo
+        .oauth2_client()
+        .get_consent_for_user(&client, &user, None)
+        .await
+        .unwrap();
+    assert!(consent.is_empty());
+}
+
+/// Test that users can see the consent they gave to a client and revoke it,
+/// without ending their sessions
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_revoke_consent(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+    let alice_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let bob_token = start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+
+    let mut repo = state.repository().await.unwrap();
+    repo.oauth2_client()
+        .give_consent_for_user(
+            &mut state.rng(),
+            &state.clock,
+            &client,
+            &alice,
+            &Scope::from_iter([OPENID]),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let client_id = format!("oauth2_client:{id}", id = client.id);
+    let alice_id = format!("user:{id}", id = alice.id);
+
+    // Alice sees the scope she consented to on the client
+    let request = Request::post("/graphql")
+        .bearer(&alice_token.access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query Client($id: ID!) {
+                    node(id: $id) {
+                        ... on Oauth2Client {
+                            viewerConsent {
+                                scope
+                            }
+                        }
+                    }
+                }
+            ",
+            "variables": {
+                "id": client_id,
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data["node"]["viewerConsent"],
+        serde_json::json!({ "scope": "openid" })
+    );
+
+    let revoke_query = serde_json::json!({
+        "query": r"
+            mutation RevokeConsent($userId: ID!, $clientId: ID!) {
+                revokeConsent(input: {userId: $userId, clientId: $clientId}) {
+                    status
+                }
+            }
+        ",
+        "variables": {
+            "userId": alice_id,
+            "clientId": client_id,
+        },
+    });
+
+    // Bob can't revoke the consent of Alice
+    let request = Request::post("/graphql")
+        .bearer(&bob_token.access_token)
+        .json(revoke_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token.access_token)
+        .json(revoke_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["revokeConsent"]["status"], "REVOKED");
+
+    // There is nothing left to revoke
+    let request = Request::post("/graphql")
+        .bearer(&alice_token.access_token)
+        .json(revoke_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["revokeConsent"]["status"], "NOT_FOUND");
+
+    // The consent is gone, but the session still works
+    let mut repo = state.repository().await.unwrap();
+    let consent = repo
+        .oauth2_client()
+        .get_consent_for_user(&client, &alice, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+    assert!(consent.is_empty());
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token.access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query Client($id: ID!) {
+                    node(id: $id) {
+                        ... on Oauth2Client {
+                            viewerConsent {
+                                scope
+                            }
+                        }
+                    }
+                }
+            ",
+            "variables": {
+                "id": client_id,
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.data["node"]["viewerConsent"].is_null());
+}
+
+/// Test that users can list the clients they authorized, and that
+/// administrators can revoke the access all the users gave to a client.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_revoke_oauth2_client_access(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let malicious_client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+    let access_token =
+        start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    start_oauth_session(
+        &state,
+        &malicious_client,
+        &alice,
+        Scope::from_iter([OPENID]),
+    )
+    .await;
+    start_oauth_session(&state, &malicious_client, &bob, Scope::from_iter([OPENID])).await;
+
+    let mut repo = state.repository().await.unwrap();
+    for user in [&alice, &bob] {
+        repo.oauth2_client()
+            .give_consent_for_user(
+                &mut state.rng(),
+                &state.clock,
+                &malicious_client,
+                user,
+                &Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+    }
+    repo.save().await.unwrap();
+
+    let consents_query = serde_json::json!({
+        "query": r"
+            query {
+                viewer {
+                    ... on User {
+                        oauth2Consents {
+                            scope
+                            lastActiveAt
+                            client {
+                                id
+                            }
+                        }
+                    }
+                }
+            }
+        ",
+    });
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(consents_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "oauth2Consents": [{
+                    "scope": "openid",
+                    "lastActiveAt": null,
+                    "client": {
+                        "id": format!("oauth2_client:{id}", id = malicious_client.id),
+                    },
+                }],
+            }
+        })
+    );
+
+    let query = r"
+        mutation RevokeClient($clientId: ID!) {
+            revokeOauth2ClientAccess(input: {clientId: $clientId}) {
+                status
+                endedSessions
+                revokedConsents
+            }
+        }
+    ";
+
+    // Regular users can't revoke the access of a client
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "clientId": format!("oauth2_client:{id}", id = malicious_client.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "clientId": format!("oauth2_client:{id}", id = malicious_client.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "revokeOauth2ClientAccess": {
+                "status": "REVOKED",
+                "endedSessions": 2,
+                "revokedConsents": 2,
+            }
+        })
+    );
+
+    // Bob doesn't have any authorized client anymore, and the other sessions
+    // are still valid
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(consents_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "oauth2Consents": [],
+            }
+        })
+    );
+}
+
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_revoke_trusted_browser(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    let alice_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let alice_token = alice_token.access_token;
+    let bob_token = start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+    let bob_token = bob_token.access_token;
+
+    let mut repo = state.repository().await.unwrap();
+    let trusted_browser = repo
+        .user_trusted_browser()
+        .add(
+            &mut state.rng(),
+            &state.clock,
+            &alice,
+            None,
+            Duration::try_days(30).unwrap(),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+    let trusted_browser_id = format!("trusted_browser:{}", trusted_browser.id);
+
+    let list_query = serde_json::json!({
+        "query": r"
+            query {
+                viewer {
+                    ... on User {
+                        trustedBrowsers {
+                            id
+                        }
+                    }
+                }
+            }
+        ",
+    });
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token)
+        .json(list_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "trustedBrowsers": [{
+                    "id": trusted_browser_id,
+                }],
+            }
+        })
+    );
+
+    let query = serde_json::json!({
+        "query": r"
+            mutation RevokeTrustedBrowser($id: ID!) {
+                revokeTrustedBrowser(input: {trustedBrowserId: $id}) {
+                    status
+                }
+            }
+        ",
+        "variables": {
+            "id": trusted_browser_id,
+        },
+    });
+
+    // Users can't revoke the browsers other users trust
+    let request = Request::post("/graphql")
+        .bearer(&bob_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "revokeTrustedBrowser": {
+                "status": "NOT_FOUND",
+            }
+        })
+    );
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "revokeTrustedBrowser": {
+                "status": "REVOKED",
+            }
+        })
+    );
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token)
+        .json(list_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "trustedBrowsers": [],
+            }
+        })
+    );
+}
+
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_bot_tokens(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+    let robot = create_test_user(&state, "robot").await;
+
+    let mut repo = state.repository().await.unwrap();
+    let robot = repo.user().set_bot(robot, Some(&alice)).await.unwrap();
+    repo.save().await.unwrap();
+    let robot_id = format!("user:{}", robot.id);
+
+    let alice_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let alice_token = alice_token.access_token;
+    let bob_token = start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+    let bob_token = bob_token.access_token;
+
+    // The owner sees their bots
+    let request = Request::post("/graphql")
+        .bearer(&alice_token)
+        .json(serde_json::json!({
+            "query": r"
+                query {
+                    viewer {
+                        ... on User {
+                            bots(first: 10) {
+                                nodes {
+                                    id
+                                    bot
+                                }
+                            }
+                        }
+                    }
+                }
+            ",
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "bots": {
+                    "nodes": [{
+                        "id": robot_id,
+                        "bot": true,
+                    }],
+                },
+            }
+        })
+    );
+
+    let issue_query = serde_json::json!({
+        "query": r"
+            mutation IssueBotToken($id: ID!) {
+                issueBotToken(input: {botId: $id}) {
+                    status
+                    accessToken
+                    compatSession {
+                        id
+                    }
+                }
+            }
+        ",
+        "variables": {
+            "id": robot_id,
+        },
+    });
+
+    // Other users can't issue tokens for the bot
+    let request = Request::post("/graphql")
+        .bearer(&bob_token)
+        .json(issue_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["issueBotToken"]["status"], "NOT_FOUND");
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token)
+        .json(issue_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["issueBotToken"]["status"], "ISSUED");
+    let access_token = response.data["issueBotToken"]["accessToken"]
+        .as_str()
+        .unwrap();
+    let compat_session_id = response.data["issueBotToken"]["compatSession"]["id"].clone();
+
+    // The token doesn't expire
+    let mut repo = state.repository().await.unwrap();
+    let token = repo
+        .compat_access_token()
+        .find_by_token(access_token)
+        .await
+        .unwrap()
+        .unwrap();
+    repo.save().await.unwrap();
+    assert!(token.expires_at.is_none());
+
+    let end_query = serde_json::json!({
+        "query": r"
+            mutation EndCompatSession($id: ID!) {
+                endCompatSession(input: {compatSessionId: $id}) {
+                    status
+                }
+            }
+        ",
+        "variables": {
+            "id": compat_session_id,
+        },
+    });
+
+    // Only the owner can end the sessions of the bot
+    let request = Request::post("/graphql")
+        .bearer(&bob_token)
+        .json(end_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["endCompatSession"]["status"], "NOT_FOUND");
+
+    let request = Request::post("/graphql")
+        .bearer(&alice_token)
+        .json(end_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["endCompatSession"]["status"], "ENDED");
+}
+
+/// Test registering a kiosk device and switching users on it
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_kiosk(pool: PgPool) {
+    setup();
+    let state = TestState::from_pool(pool).await.unwrap();
+    let mut rng = state.rng();
+
+    // Provision a client
+    let request =
+        Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+            "client_uri": "https://example.com/",
+            "token_endpoint_auth_method": "client_secret_post",
+            "grant_types": ["client_credentials"],
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::CREATED);
+    let response: ClientRegistrationResponse = response.json();
+    let client_id = response.client_id;
+    let client_secret = response.client_secret.expect("to have a client secret");
+
+    let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+        "grant_type": "client_credentials",
+        "client_id": client_id,
+        "client_secret": client_secret,
+        "scope": "urn:mas:graphql:*",
+    }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let AccessTokenResponse { access_token, .. } = response.json();
+
+    // Provision two users with a password
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+    let mut repo = state.repository().await.unwrap();
+    for user in [&alice, &bob] {
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new(b"hunter2".to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, user, version, hash, None)
+            .await
+            .unwrap();
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(mxid, user.sub.clone()))
+            .await
+            .unwrap();
+    }
+    repo.save().await.unwrap();
+
+    let register_query = serde_json::json!({
+        "query": r#"
+            mutation {
+                registerKioskDevice(input: {deviceId: "KIOSK1"}) {
+                    status
+                    kioskDevice {
+                        id
+                        deviceId
+                    }
+                }
+            }
+        "#,
+    });
+
+    // The client isn't allowed to act as a kiosk yet
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(register_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["registerKioskDevice"]["status"], "DENIED");
+
+    let state = {
+        let mut state = state;
+        state.policy_factory = test_utils::policy_factory(serde_json::json!({
+            "kiosk_clients": [client_id],
+        }))
+        .await
+        .unwrap();
+        state
+    };
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(register_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["registerKioskDevice"]["status"], "REGISTERED");
+    assert_eq!(
+        response.data["registerKioskDevice"]["kioskDevice"]["deviceId"],
+        "KIOSK1"
+    );
+    let kiosk_device_id = response.data["registerKioskDevice"]["kioskDevice"]["id"].clone();
+
+    // Registering it again returns the same device
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(register_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["registerKioskDevice"]["status"], "EXISTS");
+    assert_eq!(
+        response.data["registerKioskDevice"]["kioskDevice"]["id"],
+        kiosk_device_id
+    );
+
+    let switch_query = |username: &str, password: &str| {
+        serde_json::json!({
+            "query": r"
+                mutation SwitchKioskUser($id: ID!, $username: String!, $password: String!) {
+                    switchKioskUser(input: {kioskDeviceId: $id, username: $username, password: $password}) {
+                        status
+                        accessToken
+                        oauth2Session {
+                            id
+                        }
+                    }
+                }
+            ",
+            "variables": {
+                "id": kiosk_device_id,
+                "username": username,
+                "password": password,
+            },
+        })
+    };
+
+    // A wrong password is rejected
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(switch_query("alice", "wrong"));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data["switchKioskUser"]["status"],
+        "INVALID_CREDENTIALS"
+    );
+
+    // Alice logs in on the kiosk
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(switch_query("alice", "hunter2"));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["switchKioskUser"]["status"], "SWITCHED");
+    let alice_token = response.data["switchKioskUser"]["accessToken"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let mut repo = state.repository().await.unwrap();
+    let alice_token = repo
+        .oauth2_access_token()
+        .find_by_token(&alice_token)
+        .await
+        .unwrap()
+        .unwrap();
+    repo.save().await.unwrap();
+
+    // Bob takes over the kiosk, which logs out Alice
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(switch_query("bob", "hunter2"));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["switchKioskUser"]["status"], "SWITCHED");
+
+    let mut repo = state.repository().await.unwrap();
+    let alice_session = repo
+        .oauth2_session()
+        .lookup(alice_token.session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    repo.save().await.unwrap();
+    assert!(alice_session.is_finished());
+
+    let end_query = serde_json::json!({
+        "query": r"
+            mutation EndKioskSession($id: ID!) {
+                endKioskSession(input: {kioskDeviceId: $id}) {
+                    status
+                }
+            }
+        ",
+        "variables": {
+            "id": kiosk_device_id,
+        },
+    });
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(end_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["endKioskSession"]["status"], "ENDED");
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(end_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data["endKioskSession"]["status"], "NO_SESSION");
+}
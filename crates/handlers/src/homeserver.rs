@@ -0,0 +1,65 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use axum_extra::typed_header::TypedHeader;
+use headers::Host;
+use mas_data_model::SiteConfig;
+use mas_storage::user::UserHomeserver;
+
+/// The homeserver a request is made for, selected from the virtual host it was
+/// sent to
+///
+/// Holds the server name of the additional homeserver serving the host, or
+/// `None` for the main homeserver.
+pub struct RequestHomeserver(pub Option<String>);
+
+impl RequestHomeserver {
+    /// The homeserver on which users are looked up and registered for this
+    /// request
+    #[must_use]
+    pub fn user_homeserver(&self) -> UserHomeserver<'_> {
+        self.0.as_deref().into()
+    }
+}
+
+/// The homeserver users with the given server name belong to
+#[must_use]
+pub fn user_homeserver<'a>(site_config: &SiteConfig, server_name: &'a str) -> UserHomeserver<'a> {
+    if server_name == site_config.server_name {
+        UserHomeserver::Main
+    } else {
+        UserHomeserver::Additional(server_name)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestHomeserver
+where
+    S: Send + Sync,
+    SiteConfig: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let site_config = SiteConfig::from_ref(state);
+        let host: Option<TypedHeader<Host>> =
+            FromRequestParts::from_request_parts(parts, state).await?;
+
+        let homeserver = host.and_then(|TypedHeader(host)| {
+            site_config
+                .homeserver_for_host(host.hostname())
+                .map(ToOwned::to_owned)
+        });
+
+        Ok(RequestHomeserver(homeserver))
+    }
+}
@@ -29,11 +29,12 @@ use headers::HeaderName;
 use hyper::{
     header::{
         ACCEPT, ACCEPT_LANGUAGE, AUTHORIZATION, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE,
+        ETAG, EXPIRES, IF_MATCH, IF_NONE_MATCH, LAST_MODIFIED,
     },
     StatusCode, Version,
 };
 use mas_axum_utils::{cookies::CookieJar, FancyError};
-use mas_data_model::SiteConfig;
+use mas_data_model::{CompatEndpoint, SiteConfig};
 use mas_http::CorsLayerExt;
 use mas_keystore::{Encrypter, Keystore};
 use mas_matrix::BoxHomeserverConnection;
@@ -51,16 +52,22 @@ mod compat;
 mod graphql;
 mod health;
 mod oauth2;
+mod password_provider;
 pub mod passwords;
 pub mod upstream_oauth2;
 mod views;
 
+mod account_deletion;
 mod activity_tracker;
+mod admin_elevation;
 mod captcha;
+mod homeserver;
 mod preferred_language;
 mod rate_limit;
+mod session_limit;
 #[cfg(test)]
 mod test_utils;
+mod trusted_browser;
 
 /// Implement `From<E>` for `RouteError`, for "internal server error" kind of
 /// errors.
@@ -79,7 +86,8 @@ macro_rules! impl_from_error_for_route {
 }
 
 pub use mas_axum_utils::{
-    cookies::CookieManager, http_client_factory::HttpClientFactory, ErrorWrapper,
+    client_certificate::ClientCertificate, cookies::CookieManager,
+    http_client_factory::HttpClientFactory, ErrorWrapper,
 };
 
 pub use self::{
@@ -88,6 +96,7 @@ pub use self::{
     graphql::{
         schema as graphql_schema, schema_builder as graphql_schema_builder, Schema as GraphQLSchema,
     },
+    oauth2::client_logo::ClientLogoCache,
     preferred_language::PreferredLanguage,
     rate_limit::{Limiter, RequesterFingerprint},
     upstream_oauth2::cache::MetadataCache,
@@ -105,6 +114,7 @@ pub fn graphql_router<S>(playground: bool, undocumented_oauth2_access: bool) ->
 where
     S: Clone + Send + Sync + 'static,
     graphql::Schema: FromRef<S>,
+    SiteConfig: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
@@ -187,7 +197,9 @@ where
     BoundActivityTracker: FromRequestParts<S>,
     Encrypter: FromRef<S>,
     HttpClientFactory: FromRef<S>,
+    MetadataCache: FromRef<S>,
     SiteConfig: FromRef<S>,
+    Limiter: FromRef<S>,
     BoxHomeserverConnection: FromRef<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
@@ -219,10 +231,28 @@ where
             mas_router::OAuth2RegistrationEndpoint::route(),
             post(self::oauth2::registration::post),
         )
+        .route(
+            mas_router::OAuth2ClientConfigurationEndpoint::route(),
+            get(self::oauth2::registration::get)
+                .put(self::oauth2::registration::put)
+                .delete(self::oauth2::registration::delete),
+        )
         .route(
             mas_router::OAuth2DeviceAuthorizationEndpoint::route(),
             post(self::oauth2::device::authorize::post),
         )
+        .route(
+            mas_router::OAuth2BackchannelAuthenticationEndpoint::route(),
+            post(self::oauth2::ciba::authorize::post),
+        )
+        .route(
+            mas_router::OAuth2PushedAuthorizationRequestEndpoint::route(),
+            post(self::oauth2::par::post),
+        )
+        .route(
+            mas_router::OAuth2WidgetTokenEndpoint::route(),
+            post(self::oauth2::widget_token::post),
+        )
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -233,13 +263,14 @@ where
                     ACCEPT_LANGUAGE,
                     CONTENT_LANGUAGE,
                     CONTENT_TYPE,
+                    mas_axum_utils::dpop::DPOP.clone(),
                 ])
                 .max_age(Duration::from_secs(60 * 60)),
         )
 }
 
 #[allow(clippy::trait_duplication_in_bounds)]
-pub fn compat_router<S>() -> Router<S>
+pub fn compat_router<S>(site_config: &SiteConfig) -> Router<S>
 where
     S: Clone + Send + Sync + 'static,
     UrlBuilder: FromRef<S>,
@@ -252,31 +283,68 @@ where
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
+    Policy: FromRequestParts<S>,
 {
+    let deprecation = site_config.compat_deprecation.as_ref();
+    let sso_redirect = || {
+        self::compat::deprecated(
+            get(self::compat::login_sso_redirect::get),
+            deprecation,
+            CompatEndpoint::LoginSsoRedirect,
+        )
+    };
+
     Router::new()
         .route(
             mas_router::CompatLogin::route(),
-            get(self::compat::login::get).post(self::compat::login::post),
+            self::compat::deprecated(
+                get(self::compat::login::get).post(self::compat::login::post),
+                deprecation,
+                CompatEndpoint::Login,
+            ),
         )
         .route(
             mas_router::CompatLogout::route(),
-            post(self::compat::logout::post),
+            self::compat::deprecated(
+                post(self::compat::logout::post),
+                deprecation,
+                CompatEndpoint::Logout,
+            ),
         )
         .route(
             mas_router::CompatRefresh::route(),
-            post(self::compat::refresh::post),
+            self::compat::deprecated(
+                post(self::compat::refresh::post),
+                deprecation,
+                CompatEndpoint::Refresh,
+            ),
         )
         .route(
-            mas_router::CompatLoginSsoRedirect::route(),
-            get(self::compat::login_sso_redirect::get),
+            mas_router::CompatRegister::route(),
+            self::compat::deprecated(
+                post(self::compat::register::post),
+                deprecation,
+                CompatEndpoint::Register,
+            ),
+        )
+        .route(
+            mas_router::Rendezvous::route(),
+            post(self::compat::rendezvous::post),
+        )
+        .route(
+            mas_router::RendezvousSession::route(),
+            get(self::compat::rendezvous::get)
+                .put(self::compat::rendezvous::put)
+                .delete(self::compat::rendezvous::delete),
         )
+        .route(mas_router::CompatLoginSsoRedirect::route(), sso_redirect())
         .route(
             mas_router::CompatLoginSsoRedirectIdp::route(),
-            get(self::compat::login_sso_redirect::get),
+            sso_redirect(),
         )
         .route(
             mas_router::CompatLoginSsoRedirectSlash::route(),
-            get(self::compat::login_sso_redirect::get),
+            sso_redirect(),
         )
         .layer(
             CorsLayer::new()
@@ -288,12 +356,33 @@ where
                     ACCEPT_LANGUAGE,
                     CONTENT_LANGUAGE,
                     CONTENT_TYPE,
+                    IF_MATCH,
+                    IF_NONE_MATCH,
                     HeaderName::from_static("x-requested-with"),
                 ])
+                // Rendezvous sessions are polled using those
+                .expose_headers([ETAG, EXPIRES, LAST_MODIFIED])
                 .max_age(Duration::from_secs(60 * 60)),
         )
 }
 
+pub fn password_provider_router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    SiteConfig: FromRef<S>,
+    BoxHomeserverConnection: FromRef<S>,
+    PasswordManager: FromRef<S>,
+    Limiter: FromRef<S>,
+    BoxRepository: FromRequestParts<S>,
+    BoxClock: FromRequestParts<S>,
+    BoxRng: FromRequestParts<S>,
+{
+    Router::new().route(
+        mas_router::PasswordProviderCheckCredentials::route(),
+        post(self::password_provider::post),
+    )
+}
+
 #[allow(clippy::too_many_lines)]
 pub fn human_router<S>(templates: Templates) -> Router<S>
 where
@@ -310,9 +399,11 @@ where
     HttpClientFactory: FromRef<S>,
     PasswordManager: FromRef<S>,
     MetadataCache: FromRef<S>,
+    ClientLogoCache: FromRef<S>,
     SiteConfig: FromRef<S>,
     Limiter: FromRef<S>,
     BoxHomeserverConnection: FromRef<S>,
+    PgPool: FromRef<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
     Policy: FromRequestParts<S>,
@@ -360,6 +451,10 @@ where
             mas_router::Reauth::route(),
             get(self::views::reauth::get).post(self::views::reauth::post),
         )
+        .route(
+            mas_router::AcceptTerms::route(),
+            get(self::views::terms::get).post(self::views::terms::post),
+        )
         .route(
             mas_router::Register::route(),
             get(self::views::register::get).post(self::views::register::post),
@@ -382,6 +477,22 @@ where
             mas_router::AccountRecoveryProgress::route(),
             get(self::views::recovery::progress::get).post(self::views::recovery::progress::post),
         )
+        .route(
+            mas_router::SessionBinding::route(),
+            get(self::views::session_binding::get).post(self::views::session_binding::post),
+        )
+        .route(
+            mas_router::EmergencyAccess::route(),
+            get(self::views::emergency_access::get),
+        )
+        .route(
+            mas_router::Impersonate::route(),
+            get(self::views::impersonation::get).post(self::views::impersonation::post),
+        )
+        .route(
+            mas_router::EndImpersonation::route(),
+            post(self::views::impersonation::end),
+        )
         .route(
             mas_router::OAuth2AuthorizationEndpoint::route(),
             get(self::oauth2::authorization::get),
@@ -394,6 +505,10 @@ where
             mas_router::Consent::route(),
             get(self::oauth2::consent::get).post(self::oauth2::consent::post),
         )
+        .route(
+            mas_router::ClientLogo::route(),
+            get(self::oauth2::client_logo::get),
+        )
         .route(
             mas_router::CompatLoginSsoComplete::route(),
             get(self::compat::login_sso_complete::get).post(self::compat::login_sso_complete::post),
@@ -402,9 +517,18 @@ where
             mas_router::UpstreamOAuth2Authorize::route(),
             get(self::upstream_oauth2::authorize::get),
         )
+        .route(
+            mas_router::UpstreamOAuth2Initiate::route(),
+            get(self::upstream_oauth2::initiate::get).post(self::upstream_oauth2::initiate::post),
+        )
+        .route(
+            mas_router::ClientInitiateLogin::route(),
+            get(self::views::client_login::get),
+        )
         .route(
             mas_router::UpstreamOAuth2Callback::route(),
-            get(self::upstream_oauth2::callback::get),
+            get(self::upstream_oauth2::callback::handler)
+                .post(self::upstream_oauth2::callback::handler),
         )
         .route(
             mas_router::UpstreamOAuth2Link::route(),
@@ -418,6 +542,10 @@ where
             mas_router::DeviceCodeConsent::route(),
             get(self::oauth2::device::consent::get).post(self::oauth2::device::consent::post),
         )
+        .route(
+            mas_router::CibaConsent::route(),
+            get(self::oauth2::ciba::consent::get).post(self::oauth2::ciba::consent::post),
+        )
         .layer(AndThenLayer::new(
             move |response: axum::response::Response| async move {
                 if response.status().is_server_error() {
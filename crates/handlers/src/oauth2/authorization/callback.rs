@@ -9,13 +9,45 @@
 use std::collections::HashMap;
 
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use mas_data_model::AuthorizationGrant;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::Client;
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_jose::{
+    constraints::Constrainable,
+    jwt::{JsonWebSignatureHeader, Jwt},
+};
+use mas_keystore::Keystore;
+use mas_router::UrlBuilder;
+use mas_storage::Clock;
 use mas_templates::{FormPostContext, Templates};
 use oauth2_types::requests::ResponseMode;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
 use serde::Serialize;
 use thiserror::Error;
 use url::Url;
 
+/// The algorithm used to sign the authorization responses in the JWT response
+/// modes. Clients can't choose it, so this is the default one from the spec.
+const RESPONSE_SIGNING_ALG: JsonWebSignatureAlg = JsonWebSignatureAlg::Rs256;
+
+/// How long the signed authorization responses are valid
+const RESPONSE_TTL: Duration = Duration::minutes(10);
+
+/// The algorithms the authorization responses can be signed with, given the
+/// keys available in the keystore
+#[must_use]
+pub fn response_signing_alg_values_supported(key_store: &Keystore) -> Vec<JsonWebSignatureAlg> {
+    if key_store
+        .signing_key_for_algorithm(&RESPONSE_SIGNING_ALG)
+        .is_some()
+    {
+        vec![RESPONSE_SIGNING_ALG]
+    } else {
+        Vec::new()
+    }
+}
+
 #[derive(Debug, Clone)]
 enum CallbackDestinationMode {
     Query {
@@ -25,11 +57,87 @@ enum CallbackDestinationMode {
     FormPost,
 }
 
+/// Signs the authorization responses in the JWT response modes, as defined by
+/// [JARM](https://openid.net/specs/oauth-v2-jarm.html)
+#[derive(Clone)]
+pub struct ResponseSigner {
+    key_store: Keystore,
+    rng: ChaChaRng,
+    issuer: String,
+    audience: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl std::fmt::Debug for ResponseSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseSigner")
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseSigner {
+    #[must_use]
+    pub fn new(
+        rng: &mut (impl Rng + Send),
+        clock: &impl Clock,
+        key_store: &Keystore,
+        url_builder: &UrlBuilder,
+        client: &Client,
+    ) -> Self {
+        Self {
+            key_store: key_store.clone(),
+            rng: ChaChaRng::from_seed(rng.gen()),
+            issuer: url_builder.oidc_issuer().to_string(),
+            audience: client.client_id.clone(),
+            expires_at: clock.now() + RESPONSE_TTL,
+        }
+    }
+
+    fn sign<T: Serialize>(mut self, params: T) -> Result<String, CallbackDestinationError> {
+        #[derive(Serialize)]
+        struct ResponseClaims<T> {
+            iss: String,
+            aud: String,
+            #[serde(with = "chrono::serde::ts_seconds")]
+            exp: DateTime<Utc>,
+            #[serde(flatten)]
+            params: T,
+        }
+
+        let key = self
+            .key_store
+            .signing_key_for_algorithm(&RESPONSE_SIGNING_ALG)
+            .ok_or(CallbackDestinationError::InvalidSigningKey)?;
+        let signer = key.params().signing_key_for_alg(&RESPONSE_SIGNING_ALG)?;
+        let header = JsonWebSignatureHeader::new(RESPONSE_SIGNING_ALG).with_kid(
+            key.kid()
+                .ok_or(CallbackDestinationError::InvalidSigningKey)?,
+        );
+
+        let claims = ResponseClaims {
+            iss: self.issuer,
+            aud: self.audience,
+            exp: self.expires_at,
+            params,
+        };
+
+        let jwt = Jwt::sign_with_rng(&mut self.rng, header, claims, &signer)?;
+        Ok(jwt.into_string())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CallbackDestination {
     mode: CallbackDestinationMode,
     safe_redirect_uri: Url,
     state: Option<String>,
+
+    /// Set in the JWT response modes, where the parameters are wrapped in a
+    /// signed JWT
+    signer: Option<ResponseSigner>,
 }
 
 #[derive(Debug, Error)]
@@ -51,32 +159,42 @@ pub enum CallbackDestinationError {
 
     #[error("Failed to serialize parameters query string")]
     ParamsSerialization(#[from] serde_urlencoded::ser::Error),
-}
 
-impl TryFrom<&AuthorizationGrant> for CallbackDestination {
-    type Error = IntoCallbackDestinationError;
+    #[error("No suitable key to sign the response")]
+    InvalidSigningKey,
 
-    fn try_from(value: &AuthorizationGrant) -> Result<Self, Self::Error> {
-        Self::try_new(
-            &value.response_mode,
-            value.redirect_uri.clone(),
-            value.state.clone(),
-        )
-    }
+    #[error(transparent)]
+    WrongAlgorithm(#[from] mas_keystore::WrongAlgorithmError),
+
+    #[error("Failed to sign the response")]
+    ResponseSignature(#[from] mas_jose::jwt::JwtSignatureError),
 }
 
 impl CallbackDestination {
+    /// Figure out where and how to send the authorization response
+    ///
+    /// The `signer` is only used in the JWT response modes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the redirect URI or the response mode are not
+    /// valid
     pub fn try_new(
         mode: &ResponseMode,
         mut redirect_uri: Url,
         state: Option<String>,
+        signer: ResponseSigner,
     ) -> Result<Self, IntoCallbackDestinationError> {
         if redirect_uri.fragment().is_some() {
             return Err(IntoCallbackDestinationError::RedirectUriFragmentNotAllowed);
         }
 
+        let is_jwt = mode.is_jwt();
+
+        // The `jwt` response mode must have been resolved to either `query.jwt` or
+        // `fragment.jwt` beforehand, depending on the response type
         let mode = match mode {
-            ResponseMode::Query => {
+            ResponseMode::Query | ResponseMode::QueryJwt => {
                 let existing_params = redirect_uri
                     .query()
                     .map(serde_urlencoded::from_str)
@@ -88,15 +206,26 @@ impl CallbackDestination {
 
                 CallbackDestinationMode::Query { existing_params }
             }
-            ResponseMode::Fragment => CallbackDestinationMode::Fragment,
-            ResponseMode::FormPost => CallbackDestinationMode::FormPost,
+            ResponseMode::Fragment | ResponseMode::FragmentJwt => CallbackDestinationMode::Fragment,
+            ResponseMode::FormPost | ResponseMode::FormPostJwt => CallbackDestinationMode::FormPost,
             _ => return Err(IntoCallbackDestinationError::UnsupportedResponseMode),
         };
 
+        let signer = if is_jwt {
+            if response_signing_alg_values_supported(&signer.key_store).is_empty() {
+                return Err(IntoCallbackDestinationError::UnsupportedResponseMode);
+            }
+
+            Some(signer)
+        } else {
+            None
+        };
+
         Ok(Self {
             mode,
             safe_redirect_uri: redirect_uri,
             state,
+            signer,
         })
     }
 
@@ -106,10 +235,7 @@ impl CallbackDestination {
         params: T,
     ) -> Result<Response, CallbackDestinationError> {
         #[derive(Serialize)]
-        struct AllParams<'s, T> {
-            #[serde(flatten, skip_serializing_if = "Option::is_none")]
-            existing: Option<&'s HashMap<String, String>>,
-
+        struct ResponseParams<T> {
             #[serde(skip_serializing_if = "Option::is_none")]
             state: Option<String>,
 
@@ -117,14 +243,48 @@ impl CallbackDestination {
             params: T,
         }
 
-        let mut redirect_uri = self.safe_redirect_uri;
-        let state = self.state;
+        #[derive(Serialize)]
+        struct JwtResponseParams {
+            response: String,
+        }
+
+        let params = ResponseParams {
+            state: self.state,
+            params,
+        };
+
+        if let Some(signer) = self.signer {
+            let response = signer.sign(params)?;
+            Self::send(
+                self.mode,
+                self.safe_redirect_uri,
+                templates,
+                JwtResponseParams { response },
+            )
+        } else {
+            Self::send(self.mode, self.safe_redirect_uri, templates, params)
+        }
+    }
+
+    fn send<T: Serialize>(
+        mode: CallbackDestinationMode,
+        mut redirect_uri: Url,
+        templates: &Templates,
+        params: T,
+    ) -> Result<Response, CallbackDestinationError> {
+        #[derive(Serialize)]
+        struct AllParams<'s, T> {
+            #[serde(flatten, skip_serializing_if = "Option::is_none")]
+            existing: Option<&'s HashMap<String, String>>,
+
+            #[serde(flatten)]
+            params: T,
+        }
 
-        match self.mode {
+        match mode {
             CallbackDestinationMode::Query { existing_params } => {
                 let merged = AllParams {
                     existing: Some(&existing_params),
-                    state,
                     params,
                 };
 
@@ -136,13 +296,7 @@ impl CallbackDestination {
             }
 
             CallbackDestinationMode::Fragment => {
-                let merged = AllParams {
-                    existing: None,
-                    state,
-                    params,
-                };
-
-                let new_qs = serde_urlencoded::to_string(merged)?;
+                let new_qs = serde_urlencoded::to_string(params)?;
 
                 redirect_uri.set_fragment(Some(&new_qs));
 
@@ -150,12 +304,7 @@ impl CallbackDestination {
             }
 
             CallbackDestinationMode::FormPost => {
-                let merged = AllParams {
-                    existing: None,
-                    state,
-                    params,
-                };
-                let ctx = FormPostContext::new(redirect_uri, merged);
+                let ctx = FormPostContext::new(redirect_uri, params);
                 let rendered = templates.render_form_post(&ctx)?;
                 Ok(Html(rendered).into_response())
             }
@@ -8,11 +8,12 @@ use axum::{
     extract::{Path, State},
     response::{Html, IntoResponse, Response},
 };
+use axum_extra::typed_header::TypedHeader;
 use hyper::StatusCode;
 use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device};
+use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device, SiteConfig};
 use mas_keystore::Keystore;
-use mas_policy::{EvaluationResult, Policy};
+use mas_policy::{EvaluationResult, Policy, Requester};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository, OAuth2SessionRepository},
@@ -20,14 +21,18 @@ use mas_storage::{
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
-use oauth2_types::requests::AuthorizationResponse;
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::AuthorizationResponse,
+};
 use thiserror::Error;
 use tracing::warn;
 use ulid::Ulid;
 
-use super::callback::CallbackDestination;
+use super::callback::{CallbackDestination, ResponseSigner};
 use crate::{
-    impl_from_error_for_route, oauth2::generate_id_token, BoundActivityTracker, PreferredLanguage,
+    impl_from_error_for_route, oauth2::generate_id_token, session_limit::make_room_for_session,
+    BoundActivityTracker, PreferredLanguage,
 };
 
 #[derive(Debug, Error)]
@@ -87,15 +92,19 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(key_store): State<Keystore>,
+    State(site_config): State<SiteConfig>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -103,7 +112,19 @@ pub(crate) async fn get(
         .await?
         .ok_or(RouteError::NotFound)?;
 
-    let callback_destination = CallbackDestination::try_from(&grant)?;
+    let client = repo
+        .oauth2_client()
+        .lookup(grant.client_id)
+        .await?
+        .ok_or(RouteError::NoSuchClient)?;
+
+    let signer = ResponseSigner::new(&mut rng, &clock, &key_store, &url_builder, &client);
+    let callback_destination = CallbackDestination::try_new(
+        &grant.response_mode,
+        grant.redirect_uri.clone(),
+        grant.state.clone(),
+        signer,
+    )?;
     let continue_grant = PostAuthAction::continue_grant(grant.id);
 
     let Some(session) = maybe_session else {
@@ -120,20 +141,16 @@ pub(crate) async fn get(
         .record_browser_session(&clock, &session)
         .await;
 
-    let client = repo
-        .oauth2_client()
-        .lookup(grant.client_id)
-        .await?
-        .ok_or(RouteError::NoSuchClient)?;
-
     match complete(
         &mut rng,
         &clock,
         &activity_tracker,
+        user_agent.map(|ua| ua.as_str().to_owned()),
         repo,
         key_store,
         policy,
         &url_builder,
+        &site_config,
         grant,
         &client,
         &session,
@@ -149,6 +166,11 @@ pub(crate) async fn get(
             url_builder.redirect(&mas_router::Reauth::and_then(continue_grant)),
         )
             .into_response()),
+        Err(GrantCompletionError::RequiresTermsAcceptance) => Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::AcceptTerms::and_then(continue_grant)),
+        )
+            .into_response()),
         Err(GrantCompletionError::RequiresConsent) => {
             let next = mas_router::Consent(grant_id);
             Ok((cookie_jar, url_builder.redirect(&next)).into_response())
@@ -166,6 +188,18 @@ pub(crate) async fn get(
 
             Ok((cookie_jar, Html(content)).into_response())
         }
+        Err(GrantCompletionError::TooManySessions) => {
+            let res = callback_destination
+                .go(
+                    &templates,
+                    ClientError::new(
+                        ClientErrorCode::AccessDenied,
+                        "The user reached the maximum number of sessions",
+                    ),
+                )
+                .await?;
+            Ok((cookie_jar, res).into_response())
+        }
         Err(GrantCompletionError::NotPending) => Err(RouteError::NotPending),
         Err(GrantCompletionError::Internal(e)) => Err(RouteError::Internal(e)),
     }
@@ -182,11 +216,17 @@ pub enum GrantCompletionError {
     #[error("user needs to reauthenticate")]
     RequiresReauth,
 
+    #[error("user needs to accept the terms of service")]
+    RequiresTermsAcceptance,
+
     #[error("client lacks consent")]
     RequiresConsent,
 
     #[error("denied by the policy")]
     PolicyViolation(AuthorizationGrant, EvaluationResult),
+
+    #[error("user reached the maximum number of sessions")]
+    TooManySessions,
 }
 
 impl_from_error_for_route!(GrantCompletionError: mas_storage::RepositoryError);
@@ -199,10 +239,12 @@ pub(crate) async fn complete(
     rng: &mut (impl rand::RngCore + rand::CryptoRng + Send),
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
+    user_agent: Option<String>,
     mut repo: BoxRepository,
     key_store: Keystore,
     mut policy: Policy,
     url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
     grant: AuthorizationGrant,
     client: &Client,
     browser_session: &BrowserSession,
@@ -212,37 +254,60 @@ pub(crate) async fn complete(
         return Err(GrantCompletionError::NotPending);
     }
 
-    // Check if the authentication is fresh enough
+    // Check if the authentication is fresh enough, and of the class the client
+    // asked for
     let authentication = repo
         .browser_session()
         .get_last_authentication(browser_session)
         .await?;
-    let authentication = authentication.filter(|auth| auth.created_at > grant.max_auth_time());
+    let authentication = authentication.filter(|auth| grant.is_satisfied_by(auth));
 
     let Some(valid_authentication) = authentication else {
         repo.save().await?;
         return Err(GrantCompletionError::RequiresReauth);
     };
 
+    // Make sure the user accepted the current terms of service
+    let pending_terms =
+        crate::views::terms::pending_terms(&mut repo, site_config, &browser_session.user).await?;
+    if pending_terms.is_some() {
+        repo.save().await?;
+        return Err(GrantCompletionError::RequiresTermsAcceptance);
+    }
+
     // Run through the policy
+    let requester = Requester::new(activity_tracker.ip(), user_agent)
+        .with_authentication(Some(&valid_authentication));
+    let authorized_clients =
+        super::super::count_other_authorized_clients(&mut repo, client, &browser_session.user)
+            .await?;
     let res = policy
-        .evaluate_authorization_grant(&grant, client, &browser_session.user)
+        .evaluate_authorization_grant(
+            &grant,
+            client,
+            &browser_session.user,
+            authorized_clients,
+            &requester,
+        )
         .await?;
 
     if !res.valid() {
         return Err(GrantCompletionError::PolicyViolation(grant, res));
     }
 
+    // Consent expires after a while if configured so, to make sure users
+    // periodically review what they allowed clients to do
+    let consent_not_before = site_config.consent_ttl.map(|ttl| clock.now() - ttl);
     let current_consent = repo
         .oauth2_client()
-        .get_consent_for_user(client, &browser_session.user)
+        .get_consent_for_user(client, &browser_session.user, consent_not_before)
         .await?;
 
     let lacks_consent = grant
         .scope
         .difference(&current_consent)
         .filter(|scope| Device::from_scope_token(scope).is_none())
-        .any(|_| true);
+        .any(|scope| site_config.scope_requires_consent(scope));
 
     // Check if the client lacks consent *or* if consent was explicitly asked
     if lacks_consent || grant.requires_consent {
@@ -250,6 +315,12 @@ pub(crate) async fn complete(
         return Err(GrantCompletionError::RequiresConsent);
     }
 
+    // Make sure the user didn't reach the maximum number of sessions
+    if !make_room_for_session(rng, clock, &mut repo, site_config, &browser_session.user).await? {
+        repo.save().await?;
+        return Err(GrantCompletionError::TooManySessions);
+    }
+
     // All good, let's start the session
     let session = repo
         .oauth2_session()
@@ -4,20 +4,27 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeMap;
+
 use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Response},
 };
+use axum_extra::typed_header::TypedHeader;
 use hyper::StatusCode;
 use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationCode, Pkce};
+use mas_data_model::{AuthorizationCode, Pkce, SiteConfig, ACR_PASSWORD};
 use mas_keystore::Keystore;
 use mas_policy::Policy;
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
-    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
-    BoxClock, BoxRepository, BoxRng,
+    oauth2::{
+        OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
+        OAuth2PushedAuthorizationRequestRepository,
+    },
+    BoxClock, BoxRepository, BoxRng, RepositoryError,
 };
+use mas_storage_pg::PgRepository;
 use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
@@ -26,16 +33,22 @@ use oauth2_types::{
     response_type::ResponseType,
 };
 use rand::{distributions::Alphanumeric, Rng};
-use serde::Deserialize;
+use serde::{de::value::MapDeserializer, Deserialize};
+use sqlx::PgPool;
 use thiserror::Error;
 use tracing::warn;
 
-use self::{callback::CallbackDestination, complete::GrantCompletionError};
+use self::{
+    callback::{CallbackDestination, ResponseSigner},
+    complete::GrantCompletionError,
+};
 use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage};
 
 mod callback;
 pub mod complete;
 
+pub(crate) use self::callback::response_signing_alg_values_supported;
+
 #[derive(Debug, Error)]
 pub enum RouteError {
     #[error(transparent)]
@@ -47,6 +60,9 @@ pub enum RouteError {
     #[error("invalid response mode")]
     InvalidResponseMode,
 
+    #[error("invalid or expired request_uri")]
+    InvalidRequestUri,
+
     #[error("invalid parameters")]
     IntoCallbackDestination(#[from] self::callback::IntoCallbackDestinationError),
 
@@ -68,6 +84,9 @@ impl IntoResponse for RouteError {
             RouteError::InvalidResponseMode => {
                 (StatusCode::BAD_REQUEST, "invalid response mode").into_response()
             }
+            RouteError::InvalidRequestUri => {
+                (StatusCode::BAD_REQUEST, "invalid or expired request_uri").into_response()
+            }
             RouteError::IntoCallbackDestination(e) => {
                 (StatusCode::BAD_REQUEST, e.to_string()).into_response()
             }
@@ -91,12 +110,44 @@ impl_from_error_for_route!(mas_policy::EvaluationError);
 #[derive(Deserialize)]
 pub(crate) struct Params {
     #[serde(flatten)]
-    auth: AuthorizationRequest,
+    pub(crate) auth: AuthorizationRequest,
 
     #[serde(flatten)]
     pkce: Option<pkce::AuthorizationRequest>,
 }
 
+impl Params {
+    /// Parse the parameters sent to the pushed authorization request endpoint
+    pub(crate) fn from_pushed(
+        parameters: &BTreeMap<String, String>,
+    ) -> Result<Self, serde::de::value::Error> {
+        Self::deserialize(MapDeserializer::new(
+            parameters.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+        ))
+    }
+}
+
+/// The parameters of the authorization endpoint, either referencing a pushed
+/// authorization request, or carrying the whole request
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum RequestParams {
+    Pushed {
+        client_id: String,
+        request_uri: String,
+    },
+    Direct(Params),
+}
+
+impl RequestParams {
+    fn client_id(&self) -> &str {
+        match self {
+            Self::Pushed { client_id, .. } => client_id,
+            Self::Direct(params) => &params.auth.client_id,
+        }
+    }
+}
+
 /// Given a list of response types and an optional user-defined response mode,
 /// figure out what response mode must be used, and emit an error if the
 /// suggested response mode isn't allowed for the given response types.
@@ -107,23 +158,27 @@ fn resolve_response_mode(
     use ResponseMode as M;
 
     // If the response type includes either "token" or "id_token", the default
-    // response mode is "fragment" and the response mode "query" must not be
-    // used
+    // response mode is "fragment" and the response modes "query" and "query.jwt"
+    // must not be used
     if response_type.has_token() || response_type.has_id_token() {
         match suggested_response_mode {
             None => Ok(M::Fragment),
-            Some(M::Query) => Err(RouteError::InvalidResponseMode),
+            Some(M::Jwt) => Ok(M::FragmentJwt),
+            Some(M::Query | M::QueryJwt) => Err(RouteError::InvalidResponseMode),
             Some(mode) => Ok(mode),
         }
     } else {
         // In other cases, all response modes are allowed, defaulting to "query"
-        Ok(suggested_response_mode.unwrap_or(M::Query))
+        match suggested_response_mode {
+            Some(M::Jwt) => Ok(M::QueryJwt),
+            mode => Ok(mode.unwrap_or(M::Query)),
+        }
     }
 }
 
 #[tracing::instrument(
     name = "handlers.oauth2.authorization.get",
-    fields(client.id = %params.auth.client_id),
+    fields(client.id = %request.client_id()),
     skip_all,
     err,
 )]
@@ -135,31 +190,68 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    State(pool): State<PgPool>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
-    Form(params): Form<Params>,
+    Form(request): Form<RequestParams>,
 ) -> Result<Response, RouteError> {
     // First, figure out what client it is
     let client = repo
         .oauth2_client()
-        .find_by_client_id(&params.auth.client_id)
+        .find_by_client_id(request.client_id())
         .await?
         .ok_or(RouteError::ClientNotFound)?;
 
+    // If the request was pushed beforehand, load its parameters
+    let (params, is_pushed) = match request {
+        RequestParams::Direct(params) => (params, false),
+        RequestParams::Pushed { request_uri, .. } => {
+            let pushed_request = repo
+                .oauth2_pushed_authorization_request()
+                .find_by_request_uri(&request_uri)
+                .await?
+                .filter(|pushed_request| {
+                    pushed_request.client_id == client.id && pushed_request.is_valid(clock.now())
+                })
+                .ok_or(RouteError::InvalidRequestUri)?;
+
+            // Those were already checked when the request was pushed
+            let params = Params::from_pushed(&pushed_request.parameters)
+                .map_err(|e| RouteError::Internal(Box::new(e)))?;
+
+            // Pushed requests can only be used once, so commit this right away, as
+            // some paths below return without saving the repository
+            repo.oauth2_pushed_authorization_request()
+                .consume(&clock, pushed_request)
+                .await?;
+            repo.save().await?;
+            repo = PgRepository::from_pool(&pool)
+                .await
+                .map_err(RepositoryError::from_error)?
+                .boxed();
+
+            (params, true)
+        }
+    };
+
     // And resolve the redirect_uri and response_mode
     let redirect_uri = client
-        .resolve_redirect_uri(&params.auth.redirect_uri)?
+        .resolve_redirect_uri(&params.auth.redirect_uri, &site_config.redirect_uri_rules)?
         .clone();
     let response_type = params.auth.response_type;
     let response_mode = resolve_response_mode(&response_type, params.auth.response_mode)?;
 
     // Now we have a proper callback destination to go to on error
+    let signer = ResponseSigner::new(&mut rng, &clock, &key_store, &url_builder, &client);
     let callback_destination = CallbackDestination::try_new(
         &response_mode,
         redirect_uri.clone(),
         params.auth.state.clone(),
+        signer,
     )?;
 
     // Get the session info from the cookie
@@ -171,9 +263,24 @@ pub(crate) async fn get(
         let templates = templates.clone();
         let callback_destination = callback_destination.clone();
         async move {
-            let maybe_session = session_info.load_session(&mut repo).await?;
+            let maybe_session = session_info.load_session(&mut repo, &clock, &site_config).await?;
             let prompt = params.auth.prompt.as_deref().unwrap_or_default();
 
+            // Clients may be required to push their authorization requests first
+            if !is_pushed
+                && (site_config.require_pushed_authorization_requests
+                    || client.require_pushed_authorization_requests)
+            {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        ClientError::from(ClientErrorCode::InvalidRequest).with_description(
+                            "Pushed authorization requests are required".to_owned(),
+                        ),
+                    )
+                    .await?);
+            }
+
             // Check if the request/request_uri/registration params are used. If so, reply
             // with the right error since we don't support them.
             if params.auth.request.is_some() {
@@ -275,6 +382,18 @@ pub(crate) async fn get(
             };
 
             let requires_consent = prompt.contains(&Prompt::Consent);
+            let requires_login = prompt.contains(&Prompt::Login);
+
+            // The requested authentication context classes are only a preference of
+            // the client, so we ignore the ones we can't satisfy
+            let acr_values = params
+                .auth
+                .acr_values
+                .iter()
+                .flatten()
+                .filter(|acr| *acr == ACR_PASSWORD)
+                .cloned()
+                .collect();
 
             let grant = repo
                 .oauth2_authorization_grant()
@@ -291,6 +410,8 @@ pub(crate) async fn get(
                     response_mode,
                     response_type.has_id_token(),
                     requires_consent,
+                    requires_login,
+                    acr_values,
                 )
                 .await?;
             let continue_grant = PostAuthAction::continue_grant(grant.id);
@@ -339,10 +460,12 @@ pub(crate) async fn get(
                         &mut rng,
                         &clock,
                         &activity_tracker,
+                        user_agent.map(|ua| ua.as_str().to_owned()),
                         repo,
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
                         grant,
                         &client,
                         &user_session,
@@ -358,7 +481,10 @@ pub(crate) async fn get(
                                 )
                                 .await?
                         }
-                        Err(GrantCompletionError::RequiresReauth) => {
+                        Err(
+                            GrantCompletionError::RequiresReauth
+                            | GrantCompletionError::RequiresTermsAcceptance,
+                        ) => {
                             callback_destination
                                 .go(
                                     &templates,
@@ -371,6 +497,17 @@ pub(crate) async fn get(
                                 .go(&templates, ClientError::from(ClientErrorCode::AccessDenied))
                                 .await?
                         }
+                        Err(GrantCompletionError::TooManySessions) => {
+                            callback_destination
+                                .go(
+                                    &templates,
+                                    ClientError::new(
+                                        ClientErrorCode::AccessDenied,
+                                        "The user reached the maximum number of sessions",
+                                    ),
+                                )
+                                .await?
+                        }
                         Err(GrantCompletionError::Internal(e)) => {
                             return Err(RouteError::Internal(e))
                         }
@@ -389,10 +526,12 @@ pub(crate) async fn get(
                         &mut rng,
                         &clock,
                         &activity_tracker,
+                        user_agent.map(|ua| ua.as_str().to_owned()),
                         repo,
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
                         grant,
                         &client,
                         &user_session,
@@ -418,6 +557,21 @@ pub(crate) async fn get(
                             url_builder.redirect(&mas_router::Reauth::and_then(continue_grant))
                                 .into_response()
                         }
+                        Err(GrantCompletionError::RequiresTermsAcceptance) => {
+                            url_builder.redirect(&mas_router::AcceptTerms::and_then(continue_grant))
+                                .into_response()
+                        }
+                        Err(GrantCompletionError::TooManySessions) => {
+                            callback_destination
+                                .go(
+                                    &templates,
+                                    ClientError::new(
+                                        ClientErrorCode::AccessDenied,
+                                        "The user reached the maximum number of sessions",
+                                    ),
+                                )
+                                .await?
+                        }
                         Err(GrantCompletionError::Internal(e)) => {
                             return Err(RouteError::Internal(e))
                         }
@@ -446,3 +600,360 @@ pub(crate) async fn get(
 
     Ok((cookie_jar, response).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_data_model::ACR_PASSWORD;
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::jwt::Jwt;
+    use mas_router::{Route, SimpleRoute};
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        requests::{AccessTokenResponse, PushedAuthorizationResponse},
+        scope::{Scope, OPENID},
+    };
+    use sqlx::PgPool;
+    use url::Url;
+
+    use crate::test_utils::{setup, CookieHelper, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_jwt_response_mode(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback?foo=bar"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Without a session, asking for prompt=none fails right away, with the error
+        // wrapped in a signed JWT
+        let mut url = Url::parse("https://example.com/authorize").unwrap();
+        url.query_pairs_mut()
+            .append_pair("client_id", &client_id)
+            .append_pair("redirect_uri", "https://example.com/callback?foo=bar")
+            .append_pair("response_type", "code")
+            .append_pair("response_mode", "jwt")
+            .append_pair("scope", "openid")
+            .append_pair("state", "abcd")
+            .append_pair("prompt", "none");
+        let request = Request::get(format!(
+            "{}?{}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            url.query().unwrap()
+        ))
+        .empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // The `jwt` response mode resolves to `query.jwt` for the code flow, and the
+        // existing query parameters are kept outside of the JWT
+        let location = Url::parse(response.headers()[LOCATION].to_str().unwrap()).unwrap();
+        assert_eq!(location.path(), "/callback");
+        let params: std::collections::HashMap<String, String> =
+            location.query_pairs().into_owned().collect();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params["foo"], "bar");
+
+        let jwt: Jwt<'_, serde_json::Value> = Jwt::try_from(params["response"].as_str()).unwrap();
+        assert_eq!(jwt.header().alg(), &JsonWebSignatureAlg::Rs256);
+        let claims = jwt.payload();
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["aud"], client_id);
+        assert!(claims["exp"].is_i64());
+        assert_eq!(claims["state"], "abcd");
+        assert_eq!(claims["error"], "login_required");
+
+        // The `query.jwt` response mode can't be used with the implicit flow
+        let mut url = Url::parse("https://example.com/authorize").unwrap();
+        url.query_pairs_mut()
+            .append_pair("client_id", &client_id)
+            .append_pair("redirect_uri", "https://example.com/callback?foo=bar")
+            .append_pair("response_type", "id_token")
+            .append_pair("response_mode", "query.jwt")
+            .append_pair("scope", "openid");
+        let request = Request::get(format!(
+            "{}?{}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            url.query().unwrap()
+        ))
+        .empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pushed_authorization_requests(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "require_pushed_authorization_requests": true,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let authorization_params = [
+            ("client_id", client_id.as_str()),
+            ("redirect_uri", "https://example.com/callback"),
+            ("response_type", "code"),
+            ("scope", "openid"),
+            ("state", "abcd"),
+            ("prompt", "none"),
+        ];
+
+        // The client requires pushed requests, so a plain request is rejected
+        let mut url = Url::parse("https://example.com/authorize").unwrap();
+        url.query_pairs_mut().extend_pairs(authorization_params);
+        let request = Request::get(format!(
+            "{}?{}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            url.query().unwrap()
+        ))
+        .empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = Url::parse(response.headers()[LOCATION].to_str().unwrap()).unwrap();
+        let params: std::collections::HashMap<String, String> =
+            location.query_pairs().into_owned().collect();
+        assert_eq!(params["error"], "invalid_request");
+        assert_eq!(params["state"], "abcd");
+
+        // Push the request first
+        let request = Request::post(mas_router::OAuth2PushedAuthorizationRequestEndpoint::PATH)
+            .form(authorization_params);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let PushedAuthorizationResponse {
+            request_uri,
+            expires_in,
+        } = response.json();
+        assert!(request_uri.starts_with("urn:ietf:params:oauth:request_uri:"));
+        assert_eq!(expires_in.num_seconds(), 60);
+
+        // Then use it on the authorization endpoint. There is no session, so it fails
+        // with `login_required`, which means the request went through
+        let mut url = Url::parse("https://example.com/authorize").unwrap();
+        url.query_pairs_mut()
+            .append_pair("client_id", &client_id)
+            .append_pair("request_uri", &request_uri);
+        let request = Request::get(format!(
+            "{}?{}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            url.query().unwrap()
+        ))
+        .empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = Url::parse(response.headers()[LOCATION].to_str().unwrap()).unwrap();
+        let params: std::collections::HashMap<String, String> =
+            location.query_pairs().into_owned().collect();
+        assert_eq!(params["error"], "login_required");
+        assert_eq!(params["state"], "abcd");
+
+        // The request_uri can only be used once
+        let request = Request::get(format!(
+            "{}?{}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            url.query().unwrap()
+        ))
+        .empty();
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_step_up_authentication(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Provision a user who already consented to the client, with a session
+        // authenticated by their password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.oauth2_client()
+            .give_consent_for_user(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &user,
+                &Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                1,
+                "hashed".to_owned(),
+                None,
+            )
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut state.rng(), &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        cookies.import(state.cookie_jar().set_session(&browser_session));
+
+        let authorize = |params: &[(&str, &str)]| {
+            let mut url = Url::parse("https://example.com/authorize").unwrap();
+            url.query_pairs_mut()
+                .append_pair("client_id", &client_id)
+                .append_pair("redirect_uri", "https://example.com/callback")
+                .append_pair("response_type", "code")
+                .append_pair("scope", "openid")
+                .extend_pairs(params);
+            cookies.with_cookies(
+                Request::get(format!(
+                    "{}?{}",
+                    mas_router::OAuth2AuthorizationEndpoint::PATH,
+                    url.query().unwrap()
+                ))
+                .empty(),
+            )
+        };
+        let location_params = |response: &hyper::Response<String>| {
+            let location = response.headers()[LOCATION].to_str().unwrap();
+            let location = Url::parse("https://example.com/")
+                .unwrap()
+                .join(location)
+                .unwrap();
+            let params: std::collections::HashMap<String, String> =
+                location.query_pairs().into_owned().collect();
+            (location.path().to_owned(), params)
+        };
+
+        // The authentication is too old for the requested max_age
+        state.clock.advance(Duration::try_minutes(10).unwrap());
+        let response = state
+            .request(authorize(&[("max_age", "60"), ("prompt", "none")]))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let (path, params) = location_params(&response);
+        assert_eq!(path, "/callback");
+        assert_eq!(params["error"], "interaction_required");
+
+        // But it is fine without max_age, and the password satisfies the requested
+        // acr_values
+        let response = state
+            .request(authorize(&[
+                ("acr_values", ACR_PASSWORD),
+                ("prompt", "none"),
+            ]))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let (path, params) = location_params(&response);
+        assert_eq!(path, "/callback");
+        assert!(params.contains_key("code"));
+
+        // With prompt=login, the user has to enter their password again
+        let response = state.request(authorize(&[("prompt", "login")])).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let (path, params) = location_params(&response);
+        assert_eq!(path, mas_router::Reauth::route());
+        let grant_id = params["id"].parse().unwrap();
+
+        // Going straight to the grant doesn't skip it
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant_id)
+            .path()
+            .into_owned();
+        let response = state
+            .request(cookies.with_cookies(Request::get(&continue_grant).empty()))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let (path, _) = location_params(&response);
+        assert_eq!(path, mas_router::Reauth::route());
+
+        // Once they did, the grant completes
+        state.clock.advance(Duration::try_seconds(10).unwrap());
+        let mut repo = state.repository().await.unwrap();
+        let authentication = repo
+            .browser_session()
+            .authenticate_with_password(&mut state.rng(), &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let response = state
+            .request(cookies.with_cookies(Request::get(&continue_grant).empty()))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let (path, params) = location_params(&response);
+        assert_eq!(path, "/callback");
+
+        // The ID token tells how and when the user authenticated
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": params["code"],
+                "redirect_uri": "https://example.com/callback",
+                "client_id": client_id,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let AccessTokenResponse { id_token, .. } = response.json();
+        let id_token = id_token.unwrap();
+        let jwt: Jwt<'_, serde_json::Value> = Jwt::try_from(id_token.as_str()).unwrap();
+        let claims = jwt.payload();
+        assert_eq!(claims["acr"], ACR_PASSWORD);
+        assert_eq!(claims["auth_time"], authentication.created_at.timestamp());
+    }
+}
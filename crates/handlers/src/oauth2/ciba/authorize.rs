@@ -0,0 +1,259 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::typed_header::TypedHeader;
+use chrono::Duration;
+use headers::{CacheControl, Pragma};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    client_authorization::{ClientAuthorization, CredentialsVerificationError},
+    http_client_factory::HttpClientFactory,
+    sentry::SentryEventID,
+};
+use mas_data_model::{User, UserAgent};
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_keystore::Encrypter;
+use mas_matrix::BoxHomeserverConnection;
+use mas_storage::{
+    job::{JobRepositoryExt, SendCibaNotificationJob},
+    oauth2::OAuth2CibaGrantParams,
+    user::UserHomeserver,
+    BoxClock, BoxRepository, BoxRng,
+};
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::{BackchannelAuthenticationRequest, BackchannelAuthenticationResponse, GrantType},
+    scope::OPENID,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use thiserror::Error;
+
+use crate::{impl_from_error_for_route, rate_limit::CIBA_POLLING_INTERVAL, BoundActivityTracker};
+
+/// How long a request is valid for if the client didn't ask for a specific
+/// expiry
+const DEFAULT_EXPIRES_IN: Duration = Duration::minutes(10);
+
+/// The longest a client can ask a request to be valid for
+const MAX_EXPIRES_IN: Duration = Duration::minutes(30);
+
+/// The maximum length of the binding message, in characters
+const MAX_BINDING_MESSAGE_LENGTH: usize = 64;
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("bad request")]
+    BadRequest,
+
+    #[error("client not found")]
+    ClientNotFound,
+
+    #[error("client not allowed")]
+    ClientNotAllowed,
+
+    #[error("could not verify client credentials")]
+    ClientCredentialsVerification(#[from] CredentialsVerificationError),
+
+    #[error("the openid scope is required")]
+    MissingOpenIdScope,
+
+    #[error("the login hint is missing")]
+    MissingLoginHint,
+
+    #[error("unknown user")]
+    UnknownUser,
+
+    #[error("invalid binding message")]
+    InvalidBindingMessage,
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+
+        let response = match self {
+            Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ClientError::from(ClientErrorCode::ServerError)),
+            ),
+            Self::BadRequest => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidRequest)),
+            ),
+            Self::ClientNotFound | Self::ClientCredentialsVerification(_) => (
+                StatusCode::UNAUTHORIZED,
+                Json(ClientError::from(ClientErrorCode::InvalidClient)),
+            ),
+            Self::ClientNotAllowed => (
+                StatusCode::UNAUTHORIZED,
+                Json(ClientError::from(ClientErrorCode::UnauthorizedClient)),
+            ),
+            Self::MissingOpenIdScope => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidScope)
+                        .with_description("The openid scope is required".to_owned()),
+                ),
+            ),
+            Self::MissingLoginHint => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRequest)
+                        .with_description("The login_hint parameter is required".to_owned()),
+                ),
+            ),
+            Self::UnknownUser => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::UnknownUserId)),
+            ),
+            Self::InvalidBindingMessage => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidBindingMessage)),
+            ),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+/// Find the user designated by a login hint, which is either a username or a
+/// full Matrix ID on this homeserver
+async fn find_user(
+    repo: &mut BoxRepository,
+    homeserver: &BoxHomeserverConnection,
+    login_hint: &str,
+) -> Result<Option<User>, mas_storage::RepositoryError> {
+    let server_suffix = format!(":{}", homeserver.homeserver());
+    let username = login_hint
+        .strip_prefix('@')
+        .and_then(|mxid| mxid.strip_suffix(&server_suffix))
+        .unwrap_or(login_hint);
+
+    let user = repo
+        .user()
+        .find_by_username(UserHomeserver::Main, username)
+        .await?;
+    Ok(user.filter(User::is_valid))
+}
+
+#[tracing::instrument(
+    name = "handlers.oauth2.ciba.authorize.post",
+    fields(client.id = client_authorization.client_id()),
+    skip_all,
+    err,
+)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    activity_tracker: BoundActivityTracker,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(encrypter): State<Encrypter>,
+    State(homeserver): State<BoxHomeserverConnection>,
+    client_authorization: ClientAuthorization<BackchannelAuthenticationRequest>,
+) -> Result<impl IntoResponse, RouteError> {
+    let client = client_authorization
+        .credentials
+        .fetch(&mut repo)
+        .await?
+        .ok_or(RouteError::ClientNotFound)?;
+
+    // Reuse the token endpoint auth method to verify the client. Public clients
+    // can't use this endpoint, as anyone could otherwise start a request on their
+    // behalf.
+    let method = client
+        .token_endpoint_auth_method
+        .as_ref()
+        .filter(|method| **method != OAuthClientAuthenticationMethod::None)
+        .ok_or(RouteError::ClientNotAllowed)?;
+
+    client_authorization
+        .credentials
+        .verify(&http_client_factory, &encrypter, method, &client)
+        .await?;
+
+    if !client
+        .grant_types
+        .contains(&GrantType::ClientInitiatedBackchannelAuthentication)
+    {
+        return Err(RouteError::ClientNotAllowed);
+    }
+
+    let form = client_authorization.form.ok_or(RouteError::BadRequest)?;
+
+    if !form.scope.contains(&OPENID) {
+        return Err(RouteError::MissingOpenIdScope);
+    }
+
+    let login_hint = form.login_hint.ok_or(RouteError::MissingLoginHint)?;
+    let user = find_user(&mut repo, &homeserver, &login_hint)
+        .await?
+        .ok_or(RouteError::UnknownUser)?;
+
+    if let Some(binding_message) = &form.binding_message {
+        if binding_message.is_empty()
+            || binding_message.chars().count() > MAX_BINDING_MESSAGE_LENGTH
+        {
+            return Err(RouteError::InvalidBindingMessage);
+        }
+    }
+
+    let expires_in = form
+        .requested_expiry
+        .map_or(DEFAULT_EXPIRES_IN, |expiry| {
+            Duration::seconds(i64::from(expiry.get()))
+        })
+        .min(MAX_EXPIRES_IN);
+
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let ip_address = activity_tracker.ip();
+
+    let auth_req_id = Alphanumeric.sample_string(&mut rng, 32);
+
+    let grant = repo
+        .oauth2_ciba_grant()
+        .add(
+            &mut rng,
+            &clock,
+            OAuth2CibaGrantParams {
+                client: &client,
+                user: &user,
+                scope: form.scope,
+                auth_req_id,
+                binding_message: form.binding_message,
+                expires_in,
+                ip_address,
+                user_agent,
+            },
+        )
+        .await?;
+
+    // Let the user know that a request is waiting for their approval
+    repo.job()
+        .schedule_job(SendCibaNotificationJob::new(&grant))
+        .await?;
+
+    repo.save().await?;
+
+    let response = BackchannelAuthenticationResponse {
+        auth_req_id: grant.auth_req_id,
+        expires_in,
+        interval: Duration::from_std(CIBA_POLLING_INTERVAL).ok(),
+    };
+
+    Ok((
+        StatusCode::OK,
+        TypedHeader(CacheControl::new().with_no_store()),
+        TypedHeader(Pragma::no_cache()),
+        Json(response),
+    ))
+}
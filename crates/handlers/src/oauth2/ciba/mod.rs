@@ -0,0 +1,10 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! OpenID Connect Client-Initiated Backchannel Authentication (CIBA), in poll
+//! mode
+
+pub mod authorize;
+pub mod consent;
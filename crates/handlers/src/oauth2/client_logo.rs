@@ -0,0 +1,384 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Serve the logos of clients from the service itself, so that the consent
+//! screens don't make the browser of the user reach third-party hosts.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    Method, Request, StatusCode,
+};
+use mas_axum_utils::{http_client_factory::HttpClientFactory, sentry::SentryEventID};
+use mas_http::HttpServiceExt;
+use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository, Clock};
+use thiserror::Error;
+use tokio::sync::RwLock;
+use tower::{Service, ServiceExt};
+use ulid::Ulid;
+use url::Url;
+
+use crate::impl_from_error_for_route;
+
+/// The maximum size of a client logo
+const MAX_LOGO_SIZE: usize = 1024 * 1024;
+
+/// The content types accepted for client logos. SVG is left out on purpose, as
+/// it can embed scripts.
+const LOGO_CONTENT_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// How long a fetched logo is kept before being fetched again
+const LOGO_TTL: Duration = Duration::hours(1);
+
+/// How long a failure to fetch a logo is remembered, so that a broken logo
+/// doesn't trigger a request on every page load
+const FAILURE_TTL: Duration = Duration::minutes(5);
+
+/// The maximum number of logos kept in the cache
+const MAX_CACHED_LOGOS: usize = 1000;
+
+#[derive(Debug, Error)]
+enum FetchError {
+    #[error("invalid logo URL")]
+    InvalidUrl(#[from] hyper::http::Error),
+
+    #[error("failed to fetch the logo")]
+    Request(#[source] tower::BoxError),
+
+    #[error("logo URL responded with status {0}")]
+    Status(StatusCode),
+
+    #[error("logo has an unsupported content type")]
+    ContentType,
+
+    #[error("failed to read the logo, or it is too large")]
+    Body(#[source] axum::Error),
+}
+
+/// A logo fetched from the `logo_uri` of a client
+#[derive(Debug)]
+pub struct Logo {
+    content_type: &'static str,
+    content: Bytes,
+}
+
+#[derive(Debug)]
+struct CachedLogo {
+    logo: Option<Arc<Logo>>,
+    fetched_at: DateTime<Utc>,
+}
+
+impl CachedLogo {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        let ttl = if self.logo.is_some() {
+            LOGO_TTL
+        } else {
+            FAILURE_TTL
+        };
+
+        now - self.fetched_at < ttl
+    }
+}
+
+/// A cache of the client logos, keyed by their URL
+///
+/// Entries are kept for an hour, failures for five minutes. When the cache is
+/// full, stale entries are evicted, and new logos are served without being
+/// cached until there is room again.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Default)]
+pub struct ClientLogoCache {
+    cache: Arc<RwLock<HashMap<Url, CachedLogo>>>,
+}
+
+impl ClientLogoCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the logo at the given URL, fetching it if it is not in the cache.
+    ///
+    /// Returns `None` if the logo could not be fetched or is not a valid
+    /// image.
+    pub async fn get(
+        &self,
+        clock: &dyn Clock,
+        http_client_factory: &HttpClientFactory,
+        url: &Url,
+    ) -> Option<Arc<Logo>> {
+        if let Some(cached) = self.cache.read().await.get(url) {
+            if cached.is_fresh(clock.now()) {
+                return cached.logo.clone();
+            }
+        }
+
+        let logo = match fetch(http_client_factory, url).await {
+            Ok(logo) => Some(Arc::new(logo)),
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    %url,
+                    "Failed to fetch client logo"
+                );
+                None
+            }
+        };
+
+        let now = clock.now();
+        let mut cache = self.cache.write().await;
+        if cache.len() >= MAX_CACHED_LOGOS {
+            cache.retain(|_, cached| cached.is_fresh(now));
+        }
+
+        if cache.len() < MAX_CACHED_LOGOS || cache.contains_key(url) {
+            cache.insert(
+                url.clone(),
+                CachedLogo {
+                    logo: logo.clone(),
+                    fetched_at: now,
+                },
+            );
+        }
+
+        logo
+    }
+}
+
+#[tracing::instrument(name = "client_logo.fetch", fields(%url), skip_all, err)]
+async fn fetch(http_client_factory: &HttpClientFactory, url: &Url) -> Result<Logo, FetchError> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(url.as_str())
+        .body(Bytes::new())?;
+
+    // The URL is set by the client, so it must not be able to reach internal
+    // services
+    let mut client = http_client_factory
+        .restricted_client("client.fetch_logo")
+        .request_bytes_to_body();
+
+    let response = client
+        .ready()
+        .await
+        .map_err(|e| FetchError::Request(e.into()))?
+        .call(request)
+        .await
+        .map_err(|e| FetchError::Request(e.into()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(FetchError::Status(status));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+        .and_then(|value| {
+            LOGO_CONTENT_TYPES
+                .iter()
+                .find(|allowed| allowed.eq_ignore_ascii_case(value))
+                .copied()
+        })
+        .ok_or(FetchError::ContentType)?;
+
+    let content = axum::body::to_bytes(Body::new(response.into_body()), MAX_LOGO_SIZE)
+        .await
+        .map_err(FetchError::Body)?;
+
+    Ok(Logo {
+        content_type,
+        content,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("Client {0} not found")]
+    ClientNotFound(Ulid),
+
+    #[error("Client {0} has no logo")]
+    NoLogo(Ulid),
+
+    #[error("Could not fetch the logo of client {0}")]
+    FetchFailed(Ulid),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ClientNotFound(_) | Self::NoLogo(_) | Self::FetchFailed(_) => {
+                StatusCode::NOT_FOUND
+            }
+        };
+
+        (SentryEventID::from(event_id), status).into_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "handlers.oauth2.client_logo.get",
+    fields(client.id = %client_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(logo_cache): State<ClientLogoCache>,
+    State(http_client_factory): State<HttpClientFactory>,
+    Path(client_id): Path<Ulid>,
+) -> Result<Response, RouteError> {
+    let client = repo
+        .oauth2_client()
+        .lookup(client_id)
+        .await?
+        .ok_or(RouteError::ClientNotFound(client_id))?;
+
+    repo.cancel().await?;
+
+    let logo_uri = client.logo_uri.ok_or(RouteError::NoLogo(client_id))?;
+
+    let logo = logo_cache
+        .get(&clock, &http_client_factory, &logo_uri)
+        .await
+        .ok_or(RouteError::FetchFailed(client_id))?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, logo.content_type),
+            (CACHE_CONTROL, "public, max-age=3600"),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        logo.content.clone(),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Bytes;
+    use chrono::Duration;
+    use hyper::{Request, StatusCode};
+    use mas_data_model::Client;
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+    use sqlx::PgPool;
+    use ulid::Ulid;
+    use url::Url;
+
+    use super::{CachedLogo, Logo};
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    async fn add_client(state: &TestState, logo_uri: Option<Url>) -> Client {
+        let mut repo = state.repository().await.unwrap();
+        let mut rng = state.rng();
+
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &state.clock,
+                vec![Url::parse("https://example.com/callback").unwrap()],
+                None,
+                None,
+                vec![],
+                Some("Example".to_owned()),
+                logo_uri,
+                Some(Url::parse("https://example.com/").unwrap()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(OAuthClientAuthenticationMethod::None),
+                None,
+                None,
+                false,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        client
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_logo(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Unknown clients don't have a logo
+        let request = Request::get(format!("/clients/{}/logo", Ulid::nil())).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        // Neither do clients without a logo URI
+        let client = add_client(&state, None).await;
+        let request = Request::get(format!("/clients/{}/logo", client.id)).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        // Logos on internal addresses are never fetched
+        let client = add_client(
+            &state,
+            Some(Url::parse("http://127.0.0.1:1/logo.png").unwrap()),
+        )
+        .await;
+        let request = Request::get(format!("/clients/{}/logo", client.id)).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_cached_logo_freshness() {
+        let clock = MockClock::default();
+
+        // Failures are only remembered for a few minutes
+        let failure = CachedLogo {
+            logo: None,
+            fetched_at: clock.now(),
+        };
+        assert!(failure.is_fresh(clock.now()));
+        clock.advance(Duration::try_minutes(6).unwrap());
+        assert!(!failure.is_fresh(clock.now()));
+
+        // Logos are kept for an hour
+        let logo = CachedLogo {
+            logo: Some(Arc::new(Logo {
+                content_type: "image/png",
+                content: Bytes::new(),
+            })),
+            fetched_at: clock.now(),
+        };
+        clock.advance(Duration::try_minutes(30).unwrap());
+        assert!(logo.is_fresh(clock.now()));
+        clock.advance(Duration::try_minutes(31).unwrap());
+        assert!(!logo.is_fresh(clock.now()));
+    }
+}
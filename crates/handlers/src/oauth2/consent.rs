@@ -8,6 +8,7 @@ use axum::{
     extract::{Form, Path, State},
     response::{Html, IntoResponse, Response},
 };
+use axum_extra::typed_header::TypedHeader;
 use hyper::StatusCode;
 use mas_axum_utils::{
     cookies::CookieJar,
@@ -15,10 +16,11 @@ use mas_axum_utils::{
     sentry::SentryEventID,
     SessionInfoExt,
 };
-use mas_data_model::{AuthorizationGrantStage, Device};
-use mas_policy::Policy;
+use mas_data_model::{AuditEventKind, AuthorizationGrantStage, Device, SiteConfig};
+use mas_policy::{Policy, Requester};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
+    audit::AuditEventRepository,
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
     BoxClock, BoxRepository, BoxRng,
 };
@@ -74,18 +76,22 @@ impl IntoResponse for RouteError {
 pub(crate) async fn get(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     mut policy: Policy,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -110,8 +116,26 @@ pub(crate) async fn get(
 
         let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
+        let last_authentication = repo
+            .browser_session()
+            .get_last_authentication(&session)
+            .await?;
+        let requester = Requester::new(
+            activity_tracker.ip(),
+            user_agent.map(|ua| ua.as_str().to_owned()),
+        )
+        .with_authentication(last_authentication.as_ref());
+
+        let authorized_clients =
+            super::count_other_authorized_clients(&mut repo, &client, &session.user).await?;
         let res = policy
-            .evaluate_authorization_grant(&grant, &client, &session.user)
+            .evaluate_authorization_grant(
+                &grant,
+                &client,
+                &session.user,
+                authorized_clients,
+                &requester,
+            )
             .await?;
 
         if res.valid() {
@@ -148,9 +172,11 @@ pub(crate) async fn get(
 pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     mut policy: Policy,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
     Path(grant_id): Path<Ulid>,
@@ -160,7 +186,9 @@ pub(crate) async fn post(
 
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -184,8 +212,26 @@ pub(crate) async fn post(
         .await?
         .ok_or(RouteError::NoSuchClient)?;
 
+    let last_authentication = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await?;
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.as_ref().map(|ua| ua.as_str().to_owned()),
+    )
+    .with_authentication(last_authentication.as_ref());
+
+    let authorized_clients =
+        super::count_other_authorized_clients(&mut repo, &client, &session.user).await?;
     let res = policy
-        .evaluate_authorization_grant(&grant, &client, &session.user)
+        .evaluate_authorization_grant(
+            &grant,
+            &client,
+            &session.user,
+            authorized_clients,
+            &requester,
+        )
         .await?;
 
     if !res.valid() {
@@ -210,6 +256,24 @@ pub(crate) async fn post(
         )
         .await?;
 
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::ClientAuthorized,
+            Some(session.user.id),
+            Some(session.user.id),
+            activity_tracker.ip(),
+            user_agent.map(|ua| ua.as_str().to_owned()),
+            serde_json::json!({
+                "client_id": client.client_id,
+                "client_name": client.client_name,
+                "oauth2_authorization_grant_id": grant.id.to_string(),
+                "scope": grant.scope.to_string(),
+            }),
+        )
+        .await?;
+
     repo.oauth2_authorization_grant()
         .give_consent(grant)
         .await?;
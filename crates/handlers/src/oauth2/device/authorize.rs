@@ -14,7 +14,7 @@ use mas_axum_utils::{
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
 };
-use mas_data_model::UserAgent;
+use mas_data_model::{RuntimeResource, UserAgent};
 use mas_keystore::Encrypter;
 use mas_router::UrlBuilder;
 use mas_storage::{oauth2::OAuth2DeviceCodeGrantParams, BoxClock, BoxRepository, BoxRng};
@@ -26,7 +26,9 @@ use oauth2_types::{
 use rand::distributions::{Alphanumeric, DistString};
 use thiserror::Error;
 
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{
+    impl_from_error_for_route, rate_limit::DEVICE_CODE_POLLING_INTERVAL, BoundActivityTracker,
+};
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -41,6 +43,9 @@ pub(crate) enum RouteError {
 
     #[error("could not verify client credentials")]
     ClientCredentialsVerification(#[from] CredentialsVerificationError),
+
+    #[error("device authorization is disabled")]
+    Disabled,
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -62,6 +67,13 @@ impl IntoResponse for RouteError {
                 StatusCode::UNAUTHORIZED,
                 Json(ClientError::from(ClientErrorCode::UnauthorizedClient)),
             ),
+            Self::Disabled => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(
+                    ClientError::from(ClientErrorCode::TemporarilyUnavailable)
+                        .with_description("Device authorization is disabled".to_owned()),
+                ),
+            ),
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -85,6 +97,14 @@ pub(crate) async fn post(
     State(encrypter): State<Encrypter>,
     client_authorization: ClientAuthorization<DeviceAuthorizationRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
+    if repo
+        .runtime_resource()
+        .is_disabled(RuntimeResource::DeviceAuthorization)
+        .await?
+    {
+        return Err(RouteError::Disabled);
+    }
+
     let client = client_authorization
         .credentials
         .fetch(&mut repo)
@@ -150,7 +170,7 @@ pub(crate) async fn post(
         verification_uri: url_builder.device_code_link(),
         verification_uri_complete: Some(url_builder.device_code_link_full(device_code.user_code)),
         expires_in,
-        interval: Some(Duration::microseconds(5 * 1000 * 1000)),
+        interval: Duration::from_std(DEVICE_CODE_POLLING_INTERVAL).ok(),
     };
 
     Ok((
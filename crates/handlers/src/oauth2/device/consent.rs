@@ -10,13 +10,14 @@ use axum::{
     response::{IntoResponse, Response},
     Form,
 };
-use axum_extra::response::Html;
+use axum_extra::{response::Html, typed_header::TypedHeader};
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_policy::Policy;
+use mas_data_model::SiteConfig;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository, BoxRng};
 use mas_templates::{DeviceConsentContext, PolicyViolationContext, TemplateContext, Templates};
@@ -41,19 +42,23 @@ pub(crate) struct ConsentForm {
 pub(crate) async fn get(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     mut repo: BoxRepository,
     mut policy: Policy,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, FancyError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::and_continue_device_code_grant(grant_id);
@@ -82,8 +87,17 @@ pub(crate) async fn get(
         .context("Client not found")?;
 
     // Evaluate the policy
+    let last_authentication = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await?;
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.map(|ua| ua.as_str().to_owned()),
+    )
+    .with_authentication(last_authentication.as_ref());
     let res = policy
-        .evaluate_device_code_grant(&grant, &client, &session.user)
+        .evaluate_device_code_grant(&grant, &client, &session.user, &requester)
         .await?;
     if !res.valid() {
         warn!(violation = ?res, "Device code grant for client {} denied by policy", client.id);
@@ -114,12 +128,14 @@ pub(crate) async fn get(
 pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     mut repo: BoxRepository,
     mut policy: Policy,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
     Form(form): Form<ProtectedForm<ConsentForm>>,
@@ -128,7 +144,9 @@ pub(crate) async fn post(
     let form = cookie_jar.verify_form(&clock, form)?;
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::and_continue_device_code_grant(grant_id);
@@ -157,8 +175,17 @@ pub(crate) async fn post(
         .context("Client not found")?;
 
     // Evaluate the policy
+    let last_authentication = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await?;
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.map(|ua| ua.as_str().to_owned()),
+    )
+    .with_authentication(last_authentication.as_ref());
     let res = policy
-        .evaluate_device_code_grant(&grant, &client, &session.user)
+        .evaluate_device_code_grant(&grant, &client, &session.user, &requester)
         .await?;
     if !res.valid() {
         warn!(violation = ?res, "Device code grant for client {} denied by policy", client.id);
@@ -5,21 +5,52 @@
 // Please see LICENSE in the repository root for full details.
 
 use axum::{extract::State, response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{RuntimeResource, ACR_PASSWORD};
 use mas_iana::oauth::{
     OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod,
     PkceCodeChallengeMethod,
 };
-use mas_jose::jwa::SUPPORTED_SIGNING_ALGORITHMS;
+use mas_jose::jwa::{SUPPORTED_ASYMMETRIC_SIGNING_ALGORITHMS, SUPPORTED_SIGNING_ALGORITHMS};
 use mas_keystore::Keystore;
 use mas_router::UrlBuilder;
+use mas_storage::BoxRepository;
 use oauth2_types::{
-    oidc::{ClaimType, ProviderMetadata, SubjectType},
+    errors::{ClientError, ClientErrorCode},
+    oidc::{BackchannelTokenDeliveryMode, ClaimType, ProviderMetadata, SubjectType},
     requests::{Display, GrantType, Prompt, ResponseMode},
     scope,
 };
 use serde::Serialize;
+use thiserror::Error;
 
-use crate::SiteConfig;
+use crate::{
+    impl_from_error_for_route, oauth2::authorization::response_signing_alg_values_supported,
+    SiteConfig,
+};
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ClientError::from(ClientErrorCode::ServerError)),
+            ),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
 
 #[derive(Debug, Serialize)]
 struct DiscoveryResponse {
@@ -34,19 +65,33 @@ struct DiscoveryResponse {
     account_management_actions_supported: Vec<String>,
 }
 
-#[tracing::instrument(name = "handlers.oauth2.discovery.get", skip_all)]
+#[tracing::instrument(name = "handlers.oauth2.discovery.get", skip_all, err)]
 #[allow(clippy::too_many_lines)]
 pub(crate) async fn get(
+    mut repo: BoxRepository,
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, RouteError> {
+    // Resources disabled by administrators are not advertised
+    let disabled: Vec<RuntimeResource> = repo
+        .runtime_resource()
+        .list_disabled()
+        .await?
+        .into_iter()
+        .map(|disabled| disabled.resource)
+        .collect();
+    repo.cancel().await?;
+    let device_authorization_enabled = !disabled.contains(&RuntimeResource::DeviceAuthorization);
+
     // This is how clients can authenticate
     let client_auth_methods_supported = Some(vec![
         OAuthClientAuthenticationMethod::ClientSecretBasic,
         OAuthClientAuthenticationMethod::ClientSecretPost,
         OAuthClientAuthenticationMethod::ClientSecretJwt,
         OAuthClientAuthenticationMethod::PrivateKeyJwt,
+        OAuthClientAuthenticationMethod::TlsClientAuth,
+        OAuthClientAuthenticationMethod::SelfSignedTlsClientAuth,
         OAuthClientAuthenticationMethod::None,
     ]);
 
@@ -60,12 +105,16 @@ pub(crate) async fn get(
     let issuer = Some(url_builder.oidc_issuer().into());
     let authorization_endpoint = Some(url_builder.oauth_authorization_endpoint());
     let token_endpoint = Some(url_builder.oauth_token_endpoint());
-    let device_authorization_endpoint = Some(url_builder.oauth_device_authorization_endpoint());
+    let device_authorization_endpoint = Some(url_builder.oauth_device_authorization_endpoint())
+        .filter(|_| device_authorization_enabled);
+    let backchannel_authentication_endpoint =
+        Some(url_builder.oauth_backchannel_authentication_endpoint());
     let jwks_uri = Some(url_builder.jwks_uri());
     let introspection_endpoint = Some(url_builder.oauth_introspection_endpoint());
     let revocation_endpoint = Some(url_builder.oauth_revocation_endpoint());
     let userinfo_endpoint = Some(url_builder.oidc_userinfo_endpoint());
-    let registration_endpoint = Some(url_builder.oauth_registration_endpoint());
+    let registration_endpoint = Some(url_builder.oauth_registration_endpoint())
+        .filter(|_| !disabled.contains(&RuntimeResource::ClientRegistration));
 
     let scopes_supported = Some(vec![scope::OPENID.to_string(), scope::EMAIL.to_string()]);
 
@@ -75,18 +124,48 @@ pub(crate) async fn get(
         OAuthAuthorizationEndpointResponseType::CodeIdToken.into(),
     ]);
 
-    let response_modes_supported = Some(vec![
+    // The JWT response modes are only available if we have a key to sign the
+    // responses with
+    let authorization_signing_alg_values_supported =
+        response_signing_alg_values_supported(&key_store);
+
+    let mut response_modes_supported = vec![
         ResponseMode::FormPost,
         ResponseMode::Query,
         ResponseMode::Fragment,
-    ]);
+    ];
+
+    if !authorization_signing_alg_values_supported.is_empty() {
+        response_modes_supported.extend([
+            ResponseMode::FormPostJwt,
+            ResponseMode::QueryJwt,
+            ResponseMode::FragmentJwt,
+            ResponseMode::Jwt,
+        ]);
+    }
 
-    let grant_types_supported = Some(vec![
+    let response_modes_supported = Some(response_modes_supported);
+    let authorization_signing_alg_values_supported =
+        Some(authorization_signing_alg_values_supported).filter(|algs| !algs.is_empty());
+
+    let mut grant_types_supported = vec![
         GrantType::AuthorizationCode,
         GrantType::RefreshToken,
         GrantType::ClientCredentials,
         GrantType::DeviceCode,
-    ]);
+        GrantType::ClientInitiatedBackchannelAuthentication,
+    ];
+
+    if !device_authorization_enabled {
+        grant_types_supported.retain(|grant_type| *grant_type != GrantType::DeviceCode);
+    }
+
+    // Only advertise the JWT bearer grant if some assertion issuers are trusted
+    if !site_config.jwt_bearer_issuers.is_empty() {
+        grant_types_supported.push(GrantType::JwtBearer);
+    }
+
+    let grant_types_supported = Some(grant_types_supported);
 
     let token_endpoint_auth_methods_supported = client_auth_methods_supported.clone();
     let token_endpoint_auth_signing_alg_values_supported =
@@ -106,6 +185,9 @@ pub(crate) async fn get(
         PkceCodeChallengeMethod::S256,
     ]);
 
+    // Clients can ask for the user to enter their password again with acr_values
+    let acr_values_supported = Some(vec![ACR_PASSWORD.to_owned()]);
+
     let subject_types_supported = Some(vec![SubjectType::Public]);
 
     let id_token_signing_alg_values_supported = jwt_signing_alg_values_supported.clone();
@@ -123,6 +205,7 @@ pub(crate) async fn get(
         "exp".to_owned(),
         "nonce".to_owned(),
         "auth_time".to_owned(),
+        "acr".to_owned(),
         "at_hash".to_owned(),
         "c_hash".to_owned(),
     ]);
@@ -142,6 +225,20 @@ pub(crate) async fn get(
         v
     });
 
+    // Only the poll mode of CIBA is supported
+    let backchannel_token_delivery_modes_supported = Some(vec![BackchannelTokenDeliveryMode::Poll]);
+    let backchannel_user_code_parameter_supported = Some(false);
+
+    let dpop_signing_alg_values_supported = Some(SUPPORTED_ASYMMETRIC_SIGNING_ALGORITHMS.to_vec());
+
+    let pushed_authorization_request_endpoint =
+        Some(url_builder.oauth_pushed_authorization_request_endpoint());
+    let require_pushed_authorization_requests =
+        Some(site_config.require_pushed_authorization_requests);
+
+    // Clients can ask for access tokens bound to their TLS client certificate
+    let tls_client_certificate_bound_access_tokens = Some(true);
+
     let standard = ProviderMetadata {
         issuer,
         authorization_endpoint,
@@ -162,6 +259,7 @@ pub(crate) async fn get(
         introspection_endpoint_auth_signing_alg_values_supported,
         code_challenge_methods_supported,
         userinfo_endpoint,
+        acr_values_supported,
         subject_types_supported,
         id_token_signing_alg_values_supported,
         userinfo_signing_alg_values_supported,
@@ -173,10 +271,18 @@ pub(crate) async fn get(
         request_uri_parameter_supported,
         prompt_values_supported,
         device_authorization_endpoint,
+        backchannel_authentication_endpoint,
+        backchannel_token_delivery_modes_supported,
+        backchannel_user_code_parameter_supported,
+        dpop_signing_alg_values_supported,
+        authorization_signing_alg_values_supported,
+        pushed_authorization_request_endpoint,
+        require_pushed_authorization_requests,
+        tls_client_certificate_bound_access_tokens,
         ..ProviderMetadata::default()
     };
 
-    Json(DiscoveryResponse {
+    Ok(Json(DiscoveryResponse {
         standard,
         graphql_endpoint: url_builder.graphql_endpoint(),
         account_management_uri: url_builder.account_management_uri(),
@@ -189,7 +295,7 @@ pub(crate) async fn get(
             "org.matrix.session_end".to_owned(),
             "org.matrix.cross_signing_reset".to_owned(),
         ],
-    })
+    }))
 }
 
 #[cfg(test)]
@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::net::IpAddr;
+
 use axum::{extract::State, response::IntoResponse, Json};
 use hyper::StatusCode;
 use mas_axum_utils::{
@@ -11,22 +13,24 @@ use mas_axum_utils::{
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
 };
-use mas_data_model::{TokenFormatError, TokenType};
+use mas_data_model::{Authentication, Session, SiteConfig, TokenFormatError, TokenType};
 use mas_iana::oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint};
 use mas_keystore::Encrypter;
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
+    honeytoken::HoneytokenRepository,
     oauth2::{OAuth2AccessTokenRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository},
-    user::UserRepository,
-    BoxClock, BoxRepository, Clock,
+    user::{BrowserSessionRepository, UserRepository},
+    BoxClock, BoxRepository, Clock, ReadOnlyRepository,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
-    requests::{IntrospectionRequest, IntrospectionResponse},
+    requests::{Confirmation, IntrospectionRequest, IntrospectionResponse},
     scope::ScopeToken,
 };
 use thiserror::Error;
 
+use super::metrics::{Operation, RequestMetrics};
 use crate::{impl_from_error_for_route, ActivityTracker};
 
 #[derive(Debug, Error)]
@@ -47,6 +51,15 @@ pub enum RouteError {
     #[error("unexpected token type")]
     UnexpectedTokenType,
 
+    /// The client is not allowed to introspect this type of token.
+    #[error("client is not allowed to introspect {0}")]
+    TokenTypeNotAllowed(TokenType),
+
+    /// The token belongs to a user of a homeserver the client is not
+    /// associated with.
+    #[error("user belongs to another homeserver")]
+    WrongHomeserver,
+
     /// The overall token format is invalid.
     #[error("invalid token format")]
     InvalidTokenFormat(#[from] TokenFormatError),
@@ -117,6 +130,8 @@ impl IntoResponse for RouteError {
                 .into_response(),
             Self::UnknownToken(_)
             | Self::UnexpectedTokenType
+            | Self::TokenTypeNotAllowed(_)
+            | Self::WrongHomeserver
             | Self::InvalidToken(_)
             | Self::InvalidUser
             | Self::InvalidCompatSession
@@ -153,11 +168,52 @@ const INACTIVE: IntrospectionResponse = IntrospectionResponse {
     aud: None,
     iss: None,
     jti: None,
+    cnf: None,
+    acr: None,
+    auth_time: None,
+    bot: None,
 };
 
 const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
+const GUEST_SCOPE: ScopeToken =
+    ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:guest");
 const SYNAPSE_ADMIN_SCOPE: ScopeToken = ScopeToken::from_static("urn:synapse:admin:*");
 
+/// Report the use of a honeytoken, in case an unknown token is one
+async fn report_if_honeytoken(
+    repo: &mut BoxRepository,
+    activity_tracker: &ActivityTracker,
+    clock: &dyn Clock,
+    token: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), RouteError> {
+    if let Some(honeytoken) = repo.honeytoken().find_by_token(token).await? {
+        activity_tracker
+            .report_honeytoken(clock, &honeytoken, "introspection", ip, None)
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Find how the user authenticated before starting an OAuth 2.0 session, if it
+/// was started from a browser session
+async fn session_authentication(
+    repo: &mut BoxRepository,
+    session: &Session,
+) -> Result<Option<Authentication>, RouteError> {
+    let Some(user_session_id) = session.user_session_id else {
+        return Ok(None);
+    };
+
+    let authentication = repo
+        .browser_session()
+        .get_authentication_at(user_session_id, session.created_at)
+        .await?;
+
+    Ok(authentication)
+}
+
 #[tracing::instrument(
     name = "handlers.oauth2.introspection.post",
     fields(client.id = client_authorization.client_id()),
@@ -168,16 +224,20 @@ const SYNAPSE_ADMIN_SCOPE: ScopeToken = ScopeToken::from_static("urn:synapse:adm
 pub(crate) async fn post(
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
-    mut repo: BoxRepository,
+    ReadOnlyRepository(mut repo): ReadOnlyRepository,
     activity_tracker: ActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(site_config): State<SiteConfig>,
     client_authorization: ClientAuthorization<IntrospectionRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
+    let mut metrics = RequestMetrics::start(Operation::Introspection);
+
     let client = client_authorization
         .credentials
         .fetch(&mut repo)
         .await?
         .ok_or(RouteError::ClientNotFound)?;
+    metrics.set_client(&client);
 
     let method = match &client.token_endpoint_auth_method {
         None | Some(OAuthClientAuthenticationMethod::None) => {
@@ -203,18 +263,25 @@ pub(crate) async fn post(
         }
     }
 
+    if !site_config.can_introspect(client.id, token_type) {
+        return Err(RouteError::TokenTypeNotAllowed(token_type));
+    }
+
+    // Access tokens are still considered active for a short while after they
+    // expired, to absorb clock skew with the resource servers
+    let expiry_reference = clock.now() - site_config.introspection_grace_period;
+
     // XXX: we should get the IP from the client introspecting the token
     let ip = None;
 
     let reply = match token_type {
         TokenType::AccessToken => {
-            let access_token = repo
-                .oauth2_access_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::AccessToken))?;
+            let Some(access_token) = repo.oauth2_access_token().find_by_token(token).await? else {
+                report_if_honeytoken(&mut repo, &activity_tracker, &clock, token, ip).await?;
+                return Err(RouteError::UnknownToken(TokenType::AccessToken));
+            };
 
-            if !access_token.is_valid(clock.now()) {
+            if !access_token.is_valid(expiry_reference) {
                 return Err(RouteError::InvalidToken(TokenType::AccessToken));
             }
 
@@ -228,9 +295,13 @@ pub(crate) async fn post(
                 return Err(RouteError::InvalidOAuthSession);
             }
 
+            // Tokens restricted to a narrower scope, like the ones handed to
+            // widgets, only grant that scope
+            let session = access_token.restrict_session(session);
+
             // The session might not have a user on it (for Client Credentials grants for
             // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
+            let (sub, username, bot) = if let Some(user_id) = session.user_id {
                 let user = repo
                     .user()
                     .lookup(user_id)
@@ -241,11 +312,17 @@ pub(crate) async fn post(
                     return Err(RouteError::InvalidUser);
                 }
 
-                (Some(user.sub), Some(user.username))
+                if !site_config.client_sees_homeserver(client.id, user.homeserver.as_deref()) {
+                    return Err(RouteError::WrongHomeserver);
+                }
+
+                (Some(user.sub), Some(user.username), user.is_bot)
             } else {
-                (None, None)
+                (None, None, false)
             };
 
+            let authentication = session_authentication(&mut repo, &session).await?;
+
             activity_tracker
                 .record_oauth2_session(&clock, &session, ip)
                 .await;
@@ -263,15 +340,27 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: Some(access_token.jti()),
+                cnf: (access_token.dpop_jkt.is_some() || access_token.x5t_s256.is_some()).then(
+                    || Confirmation {
+                        jkt: access_token.dpop_jkt,
+                        x5t_s256: access_token.x5t_s256,
+                    },
+                ),
+                acr: authentication
+                    .as_ref()
+                    .and_then(Authentication::acr)
+                    .map(ToOwned::to_owned),
+                auth_time: authentication.map(|authentication| authentication.created_at),
+                bot: bot.then_some(true),
             }
         }
 
         TokenType::RefreshToken => {
-            let refresh_token = repo
-                .oauth2_refresh_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::RefreshToken))?;
+            let Some(refresh_token) = repo.oauth2_refresh_token().find_by_token(token).await?
+            else {
+                report_if_honeytoken(&mut repo, &activity_tracker, &clock, token, ip).await?;
+                return Err(RouteError::UnknownToken(TokenType::RefreshToken));
+            };
 
             if !refresh_token.is_valid() {
                 return Err(RouteError::InvalidToken(TokenType::RefreshToken));
@@ -289,7 +378,7 @@ pub(crate) async fn post(
 
             // The session might not have a user on it (for Client Credentials grants for
             // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
+            let (sub, username, bot) = if let Some(user_id) = session.user_id {
                 let user = repo
                     .user()
                     .lookup(user_id)
@@ -300,11 +389,17 @@ pub(crate) async fn post(
                     return Err(RouteError::InvalidUser);
                 }
 
-                (Some(user.sub), Some(user.username))
+                if !site_config.client_sees_homeserver(client.id, user.homeserver.as_deref()) {
+                    return Err(RouteError::WrongHomeserver);
+                }
+
+                (Some(user.sub), Some(user.username), user.is_bot)
             } else {
-                (None, None)
+                (None, None, false)
             };
 
+            let authentication = session_authentication(&mut repo, &session).await?;
+
             activity_tracker
                 .record_oauth2_session(&clock, &session, ip)
                 .await;
@@ -322,17 +417,26 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: Some(refresh_token.jti()),
+                cnf: refresh_token.dpop_jkt.map(|jkt| Confirmation {
+                    jkt: Some(jkt),
+                    ..Confirmation::default()
+                }),
+                acr: authentication
+                    .as_ref()
+                    .and_then(Authentication::acr)
+                    .map(ToOwned::to_owned),
+                auth_time: authentication.map(|authentication| authentication.created_at),
+                bot: bot.then_some(true),
             }
         }
 
         TokenType::CompatAccessToken => {
-            let access_token = repo
-                .compat_access_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::CompatAccessToken))?;
+            let Some(access_token) = repo.compat_access_token().find_by_token(token).await? else {
+                report_if_honeytoken(&mut repo, &activity_tracker, &clock, token, ip).await?;
+                return Err(RouteError::UnknownToken(TokenType::CompatAccessToken));
+            };
 
-            if !access_token.is_valid(clock.now()) {
+            if !access_token.is_valid(expiry_reference) {
                 return Err(RouteError::InvalidToken(TokenType::CompatAccessToken));
             }
 
@@ -352,14 +456,28 @@ pub(crate) async fn post(
                 .await?
                 .ok_or(RouteError::CantLoadUser)?;
 
+            if site_config.is_compat_session_expired(&session, &user, clock.now()) {
+                return Err(RouteError::InvalidCompatSession);
+            }
+
             if !user.is_valid() {
                 return Err(RouteError::InvalidUser)?;
             }
 
+            if !site_config.client_sees_homeserver(client.id, user.homeserver.as_deref()) {
+                return Err(RouteError::WrongHomeserver);
+            }
+
             // Grant the synapse admin scope if the session has the admin flag set.
             let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
             let device_scope = session.device.to_scope_token();
-            let scope = [API_SCOPE, device_scope]
+            // Guests only get access to the endpoints the homeserver opens to them
+            let api_scope = if user.is_guest {
+                GUEST_SCOPE
+            } else {
+                API_SCOPE
+            };
+            let scope = [api_scope, device_scope]
                 .into_iter()
                 .chain(synapse_admin)
                 .collect();
@@ -381,15 +499,19 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: None,
+                cnf: None,
+                acr: None,
+                auth_time: None,
+                bot: user.is_bot.then_some(true),
             }
         }
 
         TokenType::CompatRefreshToken => {
-            let refresh_token = repo
-                .compat_refresh_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::CompatRefreshToken))?;
+            let Some(refresh_token) = repo.compat_refresh_token().find_by_token(token).await?
+            else {
+                report_if_honeytoken(&mut repo, &activity_tracker, &clock, token, ip).await?;
+                return Err(RouteError::UnknownToken(TokenType::CompatRefreshToken));
+            };
 
             if !refresh_token.is_valid() {
                 return Err(RouteError::InvalidToken(TokenType::CompatRefreshToken));
@@ -411,14 +533,28 @@ pub(crate) async fn post(
                 .await?
                 .ok_or(RouteError::CantLoadUser)?;
 
+            if site_config.is_compat_session_expired(&session, &user, clock.now()) {
+                return Err(RouteError::InvalidCompatSession);
+            }
+
             if !user.is_valid() {
                 return Err(RouteError::InvalidUser)?;
             }
 
+            if !site_config.client_sees_homeserver(client.id, user.homeserver.as_deref()) {
+                return Err(RouteError::WrongHomeserver);
+            }
+
             // Grant the synapse admin scope if the session has the admin flag set.
             let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
             let device_scope = session.device.to_scope_token();
-            let scope = [API_SCOPE, device_scope]
+            // Guests only get access to the endpoints the homeserver opens to them
+            let api_scope = if user.is_guest {
+                GUEST_SCOPE
+            } else {
+                API_SCOPE
+            };
+            let scope = [api_scope, device_scope]
                 .into_iter()
                 .chain(synapse_admin)
                 .collect();
@@ -440,10 +576,16 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: None,
+                cnf: None,
+                acr: None,
+                auth_time: None,
+                bot: user.is_bot.then_some(true),
             }
         }
     };
 
+    metrics.success();
+
     Ok(Json(reply))
 }
 
@@ -451,7 +593,10 @@ pub(crate) async fn post(
 mod tests {
     use chrono::Duration;
     use hyper::{Request, StatusCode};
-    use mas_data_model::{AccessToken, RefreshToken};
+    use mas_data_model::{
+        AccessToken, HoneytokenKind, IntrospectionRestriction, RefreshToken, TokenType,
+        ACR_PASSWORD,
+    };
     use mas_iana::oauth::OAuthTokenTypeHint;
     use mas_matrix::{HomeserverConnection, ProvisionRequest};
     use mas_router::{OAuth2Introspection, OAuth2RegistrationEndpoint, SimpleRoute};
@@ -529,6 +674,25 @@ mod tests {
             .await
             .unwrap();
 
+        // The user entered their password before authorizing the client
+        let password = repo
+            .user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                1,
+                "hashed".to_owned(),
+                None,
+            )
+            .await
+            .unwrap();
+        let authentication = repo
+            .browser_session()
+            .authenticate_with_password(&mut state.rng(), &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+
         let session = repo
             .oauth2_session()
             .add_from_browser_session(
@@ -546,8 +710,12 @@ mod tests {
                 &mut state.rng(),
                 &state.clock,
                 &mut repo,
+                &client,
                 &session,
+                TokenType::AccessToken.generate(&mut state.rng()),
                 Duration::microseconds(5 * 60 * 1000 * 1000),
+                None,
+                None,
             )
             .await
             .unwrap();
@@ -566,6 +734,11 @@ mod tests {
         assert_eq!(response.client_id, Some(client_id.clone()));
         assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
         assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        assert_eq!(response.acr, Some(ACR_PASSWORD.to_owned()));
+        assert_eq!(
+            response.auth_time.map(|auth_time| auth_time.timestamp()),
+            Some(authentication.created_at.timestamp())
+        );
 
         // Do the same request, but with a token_type_hint
         let request = Request::post(OAuth2Introspection::PATH)
@@ -829,5 +1002,221 @@ mod tests {
         response.assert_status(StatusCode::OK);
         let response: IntrospectionResponse = response.json();
         assert!(response.active);
+        assert_eq!(response.bot, None);
+
+        // Once the user is turned into a bot, its tokens are flagged as such
+        let mut repo = state.repository().await.unwrap();
+        repo.user().set_bot(user, None).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": refresh_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.bot, Some(true));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_restrictions_and_grace_period(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client which will be used to do introspection requests
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "client_uri": "https://introspecting.com/",
+            "grant_types": [],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let introspecting_client_id = client.client_id;
+        let introspecting_client_secret = client.client_secret.unwrap();
+
+        // Provision a client which will be used to generate tokens
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "client_uri": "https://client.com/",
+            "redirect_uris": ["https://client.com/"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "token_endpoint_auth_method": "none",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (AccessToken { access_token, .. }, RefreshToken { refresh_token, .. }) =
+            generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &client,
+                &session,
+                TokenType::AccessToken.generate(&mut state.rng()),
+                Duration::microseconds(5 * 60 * 1000 * 1000),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Only allow the introspecting client to introspect access tokens, and give
+        // them a 30 seconds grace period
+        state.site_config.introspection_grace_period = Duration::try_seconds(30).unwrap();
+        state.site_config.introspection_restrictions = vec![IntrospectionRestriction {
+            client_id: introspecting_client_id.parse().unwrap(),
+            token_types: vec![TokenType::AccessToken],
+        }];
+
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+
+        // The refresh token should be reported as inactive to this client
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": refresh_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // Right after expiry, the access token is still active thanks to the grace
+        // period
+        state
+            .clock
+            .advance(Duration::try_seconds(5 * 60 + 10).unwrap());
+
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+
+        // But not once the grace period is over
+        state.clock.advance(Duration::try_seconds(30).unwrap());
+
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_honeytoken(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "client_uri": "https://introspecting.com/",
+            "grant_types": [],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let introspecting_client_id = client.client_id;
+        let introspecting_client_secret = client.client_secret.unwrap();
+
+        // Plant a decoy access token
+        let token = TokenType::AccessToken.generate(&mut state.rng());
+        let mut repo = state.repository().await.unwrap();
+        repo.honeytoken()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                HoneytokenKind::AccessToken,
+                token.clone(),
+                "CI secrets".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // It looks like any other unknown token
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // But its use was reported
+        state.activity_tracker.flush().await;
+        let jobs: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM apalis.jobs WHERE job_type = 'report-honeytoken-use'",
+        )
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(jobs, 1);
+
+        // Unknown tokens which are not honeytokens are not reported
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": TokenType::AccessToken.generate(&mut state.rng()) }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        state.activity_tracker.flush().await;
+        let jobs: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM apalis.jobs WHERE job_type = 'report-honeytoken-use'",
+        )
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(jobs, 1);
     }
 }
@@ -0,0 +1,138 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Metrics about the requests made to the OAuth 2.0 endpoints
+
+use std::{sync::LazyLock, time::Instant};
+
+use mas_data_model::Client;
+use oauth2_types::requests::GrantType;
+use opentelemetry::{
+    metrics::{Counter, Histogram},
+    Key,
+};
+
+const OPERATION: Key = Key::from_static_str("operation");
+const GRANT_TYPE: Key = Key::from_static_str("grant_type");
+const CLIENT_ID: Key = Key::from_static_str("client_id");
+const OUTCOME: Key = Key::from_static_str("outcome");
+
+struct Instruments {
+    requests: Counter<u64>,
+    duration: Histogram<u64>,
+}
+
+static INSTRUMENTS: LazyLock<Instruments> = LazyLock::new(|| {
+    let meter = opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    );
+
+    let requests = meter
+        .u64_counter("mas.oauth2.requests")
+        .with_description("The number of requests made to the OAuth 2.0 endpoints")
+        .with_unit("{requests}")
+        .init();
+
+    let duration = meter
+        .u64_histogram("mas.oauth2.request_duration")
+        .with_description("The time it took to handle requests to the OAuth 2.0 endpoints")
+        .with_unit("ms")
+        .init();
+
+    Instruments { requests, duration }
+});
+
+/// The OAuth 2.0 endpoint being measured
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operation {
+    Token,
+    Introspection,
+    Revocation,
+}
+
+impl Operation {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Token => "token",
+            Self::Introspection => "introspection",
+            Self::Revocation => "revocation",
+        }
+    }
+}
+
+/// Records the outcome and duration of a request to an OAuth 2.0 endpoint
+///
+/// The request is recorded when this guard is dropped. Unless [`Self::success`]
+/// was called, the request is recorded as an error, so that handlers can
+/// return early with `?`.
+pub(crate) struct RequestMetrics {
+    operation: Operation,
+    start: Instant,
+    grant_type: Option<GrantType>,
+    client_id: Option<String>,
+    success: bool,
+}
+
+impl RequestMetrics {
+    /// Start measuring a request to the given endpoint
+    pub(crate) fn start(operation: Operation) -> Self {
+        Self {
+            operation,
+            start: Instant::now(),
+            grant_type: None,
+            client_id: None,
+            success: false,
+        }
+    }
+
+    /// Set the client which made the request, once it is known
+    pub(crate) fn set_client(&mut self, client: &Client) {
+        self.client_id = Some(client.client_id.clone());
+    }
+
+    /// Set the grant type used in a token request, once it is known
+    pub(crate) fn set_grant_type(&mut self, grant_type: GrantType) {
+        self.grant_type = Some(grant_type);
+    }
+
+    /// Mark the request as successful
+    pub(crate) fn success(mut self) {
+        self.success = true;
+    }
+}
+
+impl Drop for RequestMetrics {
+    fn drop(&mut self) {
+        let elapsed = self
+            .start
+            .elapsed()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX);
+
+        let mut attributes = vec![
+            OPERATION.string(self.operation.as_str()),
+            OUTCOME.string(if self.success { "success" } else { "error" }),
+        ];
+
+        if let Some(grant_type) = &self.grant_type {
+            attributes.push(GRANT_TYPE.string(grant_type.to_string()));
+        }
+
+        // Requests from unknown clients share a label, so that arbitrary client IDs
+        // can't blow up the cardinality of the metrics
+        let client_id = self
+            .client_id
+            .take()
+            .unwrap_or_else(|| "unknown".to_owned());
+        attributes.push(CLIENT_ID.string(client_id));
+
+        INSTRUMENTS.requests.add(1, &attributes);
+        INSTRUMENTS.duration.record(elapsed, &attributes);
+    }
+}
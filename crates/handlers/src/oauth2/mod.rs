@@ -9,9 +9,9 @@ use std::collections::HashMap;
 use chrono::Duration;
 use mas_data_model::{
     AccessToken, Authentication, AuthorizationGrant, BrowserSession, Client, RefreshToken, Session,
-    TokenType,
+    TokenType, User,
 };
-use mas_iana::jose::JsonWebSignatureAlg;
+use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::{
     claims::{self, hash_token},
     constraints::Constrainable,
@@ -19,20 +19,26 @@ use mas_jose::{
 };
 use mas_keystore::Keystore;
 use mas_router::UrlBuilder;
-use mas_storage::{Clock, RepositoryAccess};
+use mas_storage::{oauth2::OAuth2ClientRepository, Clock, RepositoryAccess};
 use thiserror::Error;
+use ulid::Ulid;
 
 pub mod authorization;
+pub mod ciba;
+pub mod client_logo;
 pub mod consent;
 pub mod device;
 pub mod discovery;
 pub mod introspection;
 pub mod keys;
+mod metrics;
+pub mod par;
 pub mod registration;
 pub mod revoke;
 pub mod token;
 pub mod userinfo;
 pub mod webfinger;
+pub mod widget_token;
 
 #[derive(Debug, Error)]
 #[error(transparent)]
@@ -70,6 +76,10 @@ pub(crate) fn generate_id_token(
 
     if let Some(last_authentication) = last_authentication {
         claims::AUTH_TIME.insert(&mut claims, last_authentication.created_at)?;
+
+        if let Some(acr) = last_authentication.acr() {
+            claims::ACR.insert(&mut claims, acr)?;
+        }
     }
 
     let alg = client
@@ -96,25 +106,149 @@ pub(crate) fn generate_id_token(
     Ok(id_token.into_string())
 }
 
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub(crate) enum AccessTokenSignatureError {
+    #[error("The signing key is invalid")]
+    InvalidSigningKey,
+    Claim(#[from] mas_jose::claims::ClaimError),
+    JwtSignature(#[from] mas_jose::jwt::JwtSignatureError),
+    WrongAlgorithm(#[from] mas_keystore::WrongAlgorithmError),
+}
+
+/// Generate the string of a new access token for the given session
+///
+/// Clients which opted in get signed JWTs following RFC 9068, others get
+/// opaque tokens. In both cases, the token is then saved in the database, so
+/// that revoking it is effective on the introspection endpoint and all the
+/// other endpoints accepting access tokens.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn generate_access_token(
+    rng: &mut (impl rand::RngCore + rand::CryptoRng),
+    clock: &impl Clock,
+    url_builder: &UrlBuilder,
+    key_store: &Keystore,
+    client: &Client,
+    session: &Session,
+    ttl: Duration,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
+) -> Result<String, AccessTokenSignatureError> {
+    if !client.jwt_access_tokens {
+        return Ok(TokenType::AccessToken.generate(rng));
+    }
+
+    let mut claims = HashMap::new();
+    let now = clock.now();
+    claims::ISS.insert(&mut claims, url_builder.oidc_issuer().to_string())?;
+    // The subject of users is their ID, and sessions without a user are on
+    // behalf of the client itself
+    let sub = session
+        .user_id
+        .map_or_else(|| client.client_id.clone(), |user_id| user_id.to_string());
+    claims::SUB.insert(&mut claims, sub)?;
+    claims::AUD.insert(&mut claims, client.client_id.clone())?;
+    claims::CLIENT_ID.insert(&mut claims, client.client_id.clone())?;
+    claims::SCOPE.insert(&mut claims, session.scope.to_string())?;
+    claims::JTI.insert(
+        &mut claims,
+        Ulid::from_datetime_with_source(now.into(), rng).to_string(),
+    )?;
+    claims::IAT.insert(&mut claims, now)?;
+    claims::EXP.insert(&mut claims, now + ttl)?;
+
+    let mut confirmation = HashMap::new();
+    if let Some(dpop_jkt) = dpop_jkt {
+        confirmation.insert("jkt".to_owned(), dpop_jkt.to_owned());
+    }
+    if let Some(x5t_s256) = x5t_s256 {
+        confirmation.insert("x5t#S256".to_owned(), x5t_s256.to_owned());
+    }
+    if !confirmation.is_empty() {
+        claims::CNF.insert(&mut claims, confirmation)?;
+    }
+
+    let alg = JsonWebSignatureAlg::Rs256;
+    let key = key_store
+        .signing_key_for_algorithm(&alg)
+        .ok_or(AccessTokenSignatureError::InvalidSigningKey)?;
+    let signer = key.params().signing_key_for_alg(&alg)?;
+    let header = JsonWebSignatureHeader::new(alg)
+        .with_kid(
+            key.kid()
+                .ok_or(AccessTokenSignatureError::InvalidSigningKey)?,
+        )
+        .with_typ("at+jwt".to_owned());
+    let access_token = Jwt::sign_with_rng(rng, header, claims, &signer)?;
+
+    Ok(access_token.into_string())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn generate_token_pair<R: RepositoryAccess>(
     rng: &mut (impl rand::RngCore + Send),
     clock: &impl Clock,
     repo: &mut R,
+    client: &Client,
     session: &Session,
+    access_token_str: String,
     ttl: Duration,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
 ) -> Result<(AccessToken, RefreshToken), R::Error> {
-    let access_token_str = TokenType::AccessToken.generate(rng);
     let refresh_token_str = TokenType::RefreshToken.generate(rng);
 
     let access_token = repo
         .oauth2_access_token()
-        .add(rng, clock, session, access_token_str, Some(ttl))
+        .add(
+            rng,
+            clock,
+            session,
+            access_token_str,
+            Some(ttl),
+            dpop_jkt.map(ToOwned::to_owned),
+            x5t_s256.map(ToOwned::to_owned),
+        )
         .await?;
 
     let refresh_token = repo
         .oauth2_refresh_token()
-        .add(rng, clock, session, &access_token, refresh_token_str)
+        .add(
+            rng,
+            clock,
+            session,
+            &access_token,
+            refresh_token_str,
+            refresh_token_dpop_jkt(client, dpop_jkt),
+        )
         .await?;
 
     Ok((access_token, refresh_token))
 }
+
+/// Get the DPoP key thumbprint a new refresh token should be bound to
+///
+/// As per RFC 9449 section 5, only the refresh tokens issued to public clients
+/// are bound, as the ones issued to confidential clients are already
+/// sender-constrained by the client authentication.
+pub(crate) fn refresh_token_dpop_jkt(client: &Client, dpop_jkt: Option<&str>) -> Option<String> {
+    if client.token_endpoint_auth_method == Some(OAuthClientAuthenticationMethod::None) {
+        dpop_jkt.map(ToOwned::to_owned)
+    } else {
+        None
+    }
+}
+
+/// Count the clients, other than the given one, which the user has currently
+/// authorized, to check it against the limit set in the policy
+pub(crate) async fn count_other_authorized_clients<R: RepositoryAccess>(
+    repo: &mut R,
+    client: &Client,
+    user: &User,
+) -> Result<usize, R::Error> {
+    let consents = repo.oauth2_client().list_consents_for_user(user).await?;
+    Ok(consents
+        .iter()
+        .filter(|consent| consent.client_id != client.id)
+        .count())
+}
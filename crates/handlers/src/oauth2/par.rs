@@ -0,0 +1,208 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! The pushed authorization request endpoint, as defined by [RFC 9126]
+//!
+//! [RFC 9126]: https://www.rfc-editor.org/rfc/rfc9126.html
+
+use std::collections::BTreeMap;
+
+use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::typed_header::TypedHeader;
+use chrono::Duration;
+use headers::{CacheControl, Pragma};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    client_authorization::{ClientAuthorization, CredentialsVerificationError},
+    http_client_factory::HttpClientFactory,
+    sentry::SentryEventID,
+};
+use mas_data_model::SiteConfig;
+use mas_keystore::Encrypter;
+use mas_storage::{BoxClock, BoxRepository, BoxRng};
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::PushedAuthorizationResponse,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use thiserror::Error;
+
+use super::authorization::Params;
+use crate::impl_from_error_for_route;
+
+/// The prefix of the `request_uri` given back to clients
+pub(crate) const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+/// How long a pushed request can be used for
+const EXPIRES_IN: Duration = Duration::seconds(60);
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("bad request")]
+    BadRequest,
+
+    #[error("client not found")]
+    ClientNotFound,
+
+    #[error("client not allowed")]
+    ClientNotAllowed,
+
+    #[error("could not verify client credentials")]
+    ClientCredentialsVerification(#[from] CredentialsVerificationError),
+
+    #[error("the request_uri parameter can't be pushed")]
+    RequestUriNotAllowed,
+
+    #[error("request objects are not supported")]
+    RequestNotSupported,
+
+    #[error("invalid authorization request parameters")]
+    InvalidParameters(#[source] serde::de::value::Error),
+
+    #[error("invalid redirect uri")]
+    InvalidRedirectUri(#[from] mas_data_model::InvalidRedirectUriError),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+
+        let response = match self {
+            Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ClientError::from(ClientErrorCode::ServerError)),
+            ),
+            Self::BadRequest => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidRequest)),
+            ),
+            Self::ClientNotFound | Self::ClientCredentialsVerification(_) => (
+                StatusCode::UNAUTHORIZED,
+                Json(ClientError::from(ClientErrorCode::InvalidClient)),
+            ),
+            Self::ClientNotAllowed => (
+                StatusCode::UNAUTHORIZED,
+                Json(ClientError::from(ClientErrorCode::UnauthorizedClient)),
+            ),
+            Self::RequestUriNotAllowed => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRequest).with_description(
+                        "The request_uri parameter can't be used in pushed authorization requests"
+                            .to_owned(),
+                    ),
+                ),
+            ),
+            Self::RequestNotSupported => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::RequestNotSupported)),
+            ),
+            Self::InvalidParameters(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRequest)
+                        .with_description(e.to_string()),
+                ),
+            ),
+            Self::InvalidRedirectUri(e) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRequest)
+                        .with_description(format!("Invalid redirect URI ({e})")),
+                ),
+            ),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "handlers.oauth2.par.post",
+    fields(client.id = client_authorization.client_id()),
+    skip_all,
+    err,
+)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(encrypter): State<Encrypter>,
+    State(site_config): State<SiteConfig>,
+    client_authorization: ClientAuthorization<BTreeMap<String, String>>,
+) -> Result<impl IntoResponse, RouteError> {
+    let client = client_authorization
+        .credentials
+        .fetch(&mut repo)
+        .await?
+        .ok_or(RouteError::ClientNotFound)?;
+
+    // Clients authenticate here the same way as on the token endpoint
+    let method = client
+        .token_endpoint_auth_method
+        .as_ref()
+        .ok_or(RouteError::ClientNotAllowed)?;
+
+    client_authorization
+        .credentials
+        .verify(&http_client_factory, &encrypter, method, &client)
+        .await?;
+
+    let mut parameters = client_authorization.form.ok_or(RouteError::BadRequest)?;
+
+    if parameters.contains_key("request_uri") {
+        return Err(RouteError::RequestUriNotAllowed);
+    }
+
+    if parameters.contains_key("request") {
+        return Err(RouteError::RequestNotSupported);
+    }
+
+    // The client credentials are not part of the form, so put back the client ID
+    // of the authenticated client
+    parameters.insert("client_id".to_owned(), client.client_id.clone());
+
+    // Check the request now, so that the client gets the error right away,
+    // instead of once the user is redirected to the authorization endpoint
+    let params = Params::from_pushed(&parameters).map_err(RouteError::InvalidParameters)?;
+    client.resolve_redirect_uri(&params.auth.redirect_uri, &site_config.redirect_uri_rules)?;
+
+    let request_uri = format!(
+        "{REQUEST_URI_PREFIX}{}",
+        Alphanumeric.sample_string(&mut rng, 32)
+    );
+
+    let request = repo
+        .oauth2_pushed_authorization_request()
+        .add(
+            &mut rng,
+            &clock,
+            &client,
+            request_uri,
+            parameters,
+            EXPIRES_IN,
+        )
+        .await?;
+
+    repo.save().await?;
+
+    let response = PushedAuthorizationResponse {
+        request_uri: request.request_uri,
+        expires_in: EXPIRES_IN,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        TypedHeader(CacheControl::new().with_no_store()),
+        TypedHeader(Pragma::no_cache()),
+        Json(response),
+    ))
+}
@@ -4,12 +4,20 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use axum::{extract::State, response::IntoResponse, Json};
-use hyper::StatusCode;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::typed_header::TypedHeader;
+use headers::{authorization::Bearer, Authorization};
+use hyper::{header::WWW_AUTHENTICATE, StatusCode};
 use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{Client, JwksOrJwksUri, RuntimeResource};
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
 use mas_keystore::Encrypter;
-use mas_policy::{Policy, Violation};
+use mas_policy::{Policy, Requester, Violation};
+use mas_router::{OAuth2ClientConfigurationEndpoint, UrlBuilder};
 use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository, BoxRng};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
@@ -19,13 +27,17 @@ use oauth2_types::{
     },
 };
 use psl::Psl;
-use rand::distributions::{Alphanumeric, DistString};
-use serde::Serialize;
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    Rng,
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
 use tracing::info;
 use url::Url;
 
-use crate::impl_from_error_for_route;
+use crate::{impl_from_error_for_route, BoundActivityTracker};
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -43,12 +55,23 @@ pub(crate) enum RouteError {
 
     #[error("denied by the policy: {0:?}")]
     PolicyDenied(Vec<Violation>),
+
+    #[error("client registration is disabled")]
+    Disabled,
+
+    #[error("missing or invalid registration access token")]
+    InvalidRegistrationAccessToken,
+
+    #[error("client_id does not match the client being updated")]
+    ClientIdMismatch,
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::LoadError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(mas_keystore::aead::Error);
+impl_from_error_for_route!(mas_keystore::DecryptError);
+impl_from_error_for_route!(std::string::FromUtf8Error);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
@@ -60,6 +83,32 @@ impl IntoResponse for RouteError {
             )
                 .into_response(),
 
+            // Don't tell whether the client exists, as per RFC7592 section 2
+            Self::InvalidRegistrationAccessToken => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#)],
+            )
+                .into_response(),
+
+            Self::ClientIdMismatch => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRequest).with_description(
+                        "client_id does not match the client being updated".to_owned(),
+                    ),
+                ),
+            )
+                .into_response(),
+
+            Self::Disabled => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(
+                    ClientError::from(ClientErrorCode::TemporarilyUnavailable)
+                        .with_description("Client registration is disabled".to_owned()),
+                ),
+            )
+                .into_response(),
+
             // This error happens if we managed to parse the incomiong JSON but it can't be
             // deserialized to the expected type. In this case we return an
             // `invalid_client_metadata` error with the details of the error.
@@ -189,22 +238,23 @@ fn localised_url_has_public_suffix(url: &Localized<Url>) -> bool {
     url.iter().any(|(_lang, url)| host_is_public_suffix(url))
 }
 
-#[tracing::instrument(name = "handlers.oauth2.registration.post", skip_all, err)]
-pub(crate) async fn post(
-    mut rng: BoxRng,
-    clock: BoxClock,
-    mut repo: BoxRepository,
-    mut policy: Policy,
-    State(encrypter): State<Encrypter>,
-    body: Result<Json<ClientMetadata>, axum::extract::rejection::JsonRejection>,
-) -> Result<impl IntoResponse, RouteError> {
-    // Propagate any JSON extraction error
-    let Json(body) = body?;
+/// Body of the requests to update a client, which includes its ID
+#[derive(Deserialize)]
+pub(crate) struct UpdateRequest {
+    client_id: String,
 
-    info!(?body, "Client registration");
+    #[serde(flatten)]
+    metadata: ClientMetadata,
+}
 
+/// Validate the metadata of a client, both on registration and on update
+async fn validate_metadata(
+    metadata: ClientMetadata,
+    policy: &mut Policy,
+    requester: &Requester,
+) -> Result<VerifiedClientMetadata, RouteError> {
     // Validate the body
-    let metadata = body.validate()?;
+    let metadata = metadata.validate()?;
 
     // Some extra validation that is hard to do in OPA and not done by the
     // `validate` method either
@@ -244,25 +294,152 @@ pub(crate) async fn post(
         }
     }
 
-    let res = policy.evaluate_client_registration(&metadata).await?;
+    let res = policy
+        .evaluate_client_registration(&metadata, requester)
+        .await?;
     if !res.valid() {
         return Err(RouteError::PolicyDenied(res.violations));
     }
 
-    let (client_secret, encrypted_client_secret) = match metadata.token_endpoint_auth_method {
+    Ok(metadata)
+}
+
+/// Whether the given authentication method needs a client secret
+fn uses_client_secret(method: Option<&OAuthClientAuthenticationMethod>) -> bool {
+    matches!(
+        method,
         Some(
             OAuthClientAuthenticationMethod::ClientSecretJwt
-            | OAuthClientAuthenticationMethod::ClientSecretPost
-            | OAuthClientAuthenticationMethod::ClientSecretBasic,
-        ) => {
-            // Let's generate a random client secret
-            let client_secret = Alphanumeric.sample_string(&mut rng, 20);
-            let encrypted_client_secret = encrypter.encrypt_to_string(client_secret.as_bytes())?;
-            (Some(client_secret), Some(encrypted_client_secret))
-        }
-        _ => (None, None),
+                | OAuthClientAuthenticationMethod::ClientSecretPost
+                | OAuthClientAuthenticationMethod::ClientSecretBasic,
+        )
+    )
+}
+
+/// Generate a random client secret, and return it along with its encrypted
+/// form
+fn generate_secret(
+    rng: &mut impl Rng,
+    encrypter: &Encrypter,
+) -> Result<(String, String), RouteError> {
+    let secret = Alphanumeric.sample_string(rng, 20);
+    let encrypted_secret = encrypter.encrypt_to_string(secret.as_bytes())?;
+    Ok((secret, encrypted_secret))
+}
+
+/// Build the response describing the client and how to manage it
+fn client_response(
+    client: Client,
+    client_secret: Option<String>,
+    registration_access_token: String,
+    url_builder: &UrlBuilder,
+) -> Result<RouteResponse, RouteError> {
+    let response = ClientRegistrationResponse {
+        client_id: client.client_id.clone(),
+        client_secret,
+        // XXX: we should have a `created_at` field on the clients
+        client_id_issued_at: Some(client.id.datetime().into()),
+        client_secret_expires_at: None,
+        registration_access_token: Some(registration_access_token),
+        registration_client_uri: Some(
+            url_builder
+                .absolute_url_for(&OAuth2ClientConfigurationEndpoint::new(&client.client_id)),
+        ),
+    };
+
+    // We round-trip back to the metadata to output it in the response
+    // This should never fail, as the client is valid
+    let metadata = client.into_metadata().validate()?;
+
+    Ok(RouteResponse { response, metadata })
+}
+
+/// Find the client with the given ID, and check that the request is
+/// authenticated with its registration access token
+async fn authenticate_client(
+    repo: &mut BoxRepository,
+    encrypter: &Encrypter,
+    client_id: &str,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<(Client, String), RouteError> {
+    let Some(TypedHeader(authorization)) = authorization else {
+        return Err(RouteError::InvalidRegistrationAccessToken);
     };
 
+    let client = repo
+        .oauth2_client()
+        .find_by_client_id(client_id)
+        .await?
+        .ok_or(RouteError::InvalidRegistrationAccessToken)?;
+
+    // Static clients and clients registered before this was supported don't
+    // have a token
+    let encrypted_token = client
+        .encrypted_registration_access_token
+        .as_deref()
+        .ok_or(RouteError::InvalidRegistrationAccessToken)?;
+    let token = encrypter.decrypt_string(encrypted_token)?;
+
+    // Compare in constant time, so that the token can't be guessed byte by
+    // byte from the response times
+    if !bool::from(authorization.token().as_bytes().ct_eq(&token)) {
+        return Err(RouteError::InvalidRegistrationAccessToken);
+    }
+
+    let token = authorization.token().to_owned();
+    Ok((client, token))
+}
+
+/// Decrypt the secret of the client, to include it in the responses
+fn client_secret(client: &Client, encrypter: &Encrypter) -> Result<Option<String>, RouteError> {
+    let Some(encrypted_client_secret) = &client.encrypted_client_secret else {
+        return Ok(None);
+    };
+
+    let client_secret = encrypter.decrypt_string(encrypted_client_secret)?;
+    let client_secret = String::from_utf8(client_secret)?;
+    Ok(Some(client_secret))
+}
+
+#[tracing::instrument(name = "handlers.oauth2.registration.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    mut policy: Policy,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    body: Result<Json<ClientMetadata>, axum::extract::rejection::JsonRejection>,
+) -> Result<impl IntoResponse, RouteError> {
+    if repo
+        .runtime_resource()
+        .is_disabled(RuntimeResource::ClientRegistration)
+        .await?
+    {
+        return Err(RouteError::Disabled);
+    }
+
+    // Propagate any JSON extraction error
+    let Json(body) = body?;
+
+    info!(?body, "Client registration");
+
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.map(|ua| ua.as_str().to_owned()),
+    );
+    let metadata = validate_metadata(body, &mut policy, &requester).await?;
+
+    let (client_secret, encrypted_client_secret) =
+        if uses_client_secret(metadata.token_endpoint_auth_method.as_ref()) {
+            let (client_secret, encrypted_client_secret) = generate_secret(&mut rng, &encrypter)?;
+            (Some(client_secret), Some(encrypted_client_secret))
+        } else {
+            (None, None)
+        };
+
     let client = repo
         .oauth2_client()
         .add(
@@ -270,7 +447,9 @@ pub(crate) async fn post(
             &clock,
             metadata.redirect_uris().to_vec(),
             encrypted_client_secret,
-            metadata.application_type.clone(),
+            // Store the effective application type, so that the redirect URI rules
+            // apply to this client later on
+            Some(metadata.application_type()),
             //&metadata.response_types(),
             metadata.grant_types().to_vec(),
             metadata
@@ -289,26 +468,184 @@ pub(crate) async fn post(
             metadata.token_endpoint_auth_method.clone(),
             metadata.token_endpoint_auth_signing_alg.clone(),
             metadata.initiate_login_uri.clone(),
+            metadata.require_pushed_authorization_requests(),
+            metadata.tls_client_auth_subject_dn.clone(),
+            metadata.tls_client_certificate_bound_access_tokens(),
         )
         .await?;
 
-    let response = ClientRegistrationResponse {
-        client_id: client.client_id.clone(),
+    // The client uses this token to manage its registration later on
+    let (registration_access_token, encrypted_registration_access_token) =
+        generate_secret(&mut rng, &encrypter)?;
+    let client = repo
+        .oauth2_client()
+        .set_registration_access_token(client, Some(encrypted_registration_access_token))
+        .await?;
+
+    let response = client_response(
+        client,
         client_secret,
-        // XXX: we should have a `created_at` field on the clients
-        client_id_issued_at: Some(client.id.datetime().into()),
-        client_secret_expires_at: None,
+        registration_access_token,
+        &url_builder,
+    )?;
+
+    repo.save().await?;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[tracing::instrument(
+    name = "handlers.oauth2.registration.get",
+    fields(client.id = %client_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    mut repo: BoxRepository,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    Path(client_id): Path<String>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, RouteError> {
+    let (client, registration_access_token) =
+        authenticate_client(&mut repo, &encrypter, &client_id, authorization).await?;
+
+    let client_secret = client_secret(&client, &encrypter)?;
+    let response = client_response(
+        client,
+        client_secret,
+        registration_access_token,
+        &url_builder,
+    )?;
+
+    Ok(Json(response))
+}
+
+#[tracing::instrument(
+    name = "handlers.oauth2.registration.put",
+    fields(client.id = %client_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn put(
+    mut rng: BoxRng,
+    mut repo: BoxRepository,
+    mut policy: Policy,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    Path(client_id): Path<String>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    body: Result<Json<UpdateRequest>, axum::extract::rejection::JsonRejection>,
+) -> Result<impl IntoResponse, RouteError> {
+    let (client, registration_access_token) =
+        authenticate_client(&mut repo, &encrypter, &client_id, authorization).await?;
+
+    if repo
+        .runtime_resource()
+        .is_disabled(RuntimeResource::ClientRegistration)
+        .await?
+    {
+        return Err(RouteError::Disabled);
+    }
+
+    let Json(body) = body?;
+    if body.client_id != client.client_id {
+        return Err(RouteError::ClientIdMismatch);
+    }
+
+    info!(metadata = ?body.metadata, "Client registration update");
+
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.map(|ua| ua.as_str().to_owned()),
+    );
+    let metadata = validate_metadata(body.metadata, &mut policy, &requester).await?;
+
+    // Keep the existing secret if the client still needs one, so that updating
+    // the metadata doesn't break the running instances of the client
+    let encrypted_client_secret =
+        if uses_client_secret(metadata.token_endpoint_auth_method.as_ref()) {
+            match client.encrypted_client_secret.clone() {
+                Some(encrypted_client_secret) => Some(encrypted_client_secret),
+                None => Some(generate_secret(&mut rng, &encrypter)?.1),
+            }
+        } else {
+            None
+        };
+
+    let jwks = match (metadata.jwks.clone(), metadata.jwks_uri.clone()) {
+        (Some(jwks), _) => Some(JwksOrJwksUri::Jwks(jwks)),
+        (None, Some(jwks_uri)) => Some(JwksOrJwksUri::JwksUri(jwks_uri)),
+        (None, None) => None,
     };
 
-    // We round-trip back to the metadata to output it in the response
-    // This should never fail, as the client is valid
-    let metadata = client.into_metadata().validate()?;
+    let client = Client {
+        encrypted_client_secret,
+        // Store the effective application type, so that the redirect URI rules
+        // apply to this client later on
+        application_type: Some(metadata.application_type()),
+        redirect_uris: metadata.redirect_uris().to_vec(),
+        grant_types: metadata.grant_types().to_vec(),
+        client_name: metadata
+            .client_name
+            .clone()
+            .map(Localized::to_non_localized),
+        logo_uri: metadata.logo_uri.clone().map(Localized::to_non_localized),
+        client_uri: metadata.client_uri.clone().map(Localized::to_non_localized),
+        policy_uri: metadata.policy_uri.clone().map(Localized::to_non_localized),
+        tos_uri: metadata.tos_uri.clone().map(Localized::to_non_localized),
+        jwks,
+        id_token_signed_response_alg: metadata.id_token_signed_response_alg.clone(),
+        userinfo_signed_response_alg: metadata.userinfo_signed_response_alg.clone(),
+        token_endpoint_auth_method: metadata.token_endpoint_auth_method.clone(),
+        token_endpoint_auth_signing_alg: metadata.token_endpoint_auth_signing_alg.clone(),
+        initiate_login_uri: metadata.initiate_login_uri.clone(),
+        require_pushed_authorization_requests: metadata.require_pushed_authorization_requests(),
+        tls_client_auth_subject_dn: metadata.tls_client_auth_subject_dn.clone(),
+        tls_client_certificate_bound_access_tokens: metadata
+            .tls_client_certificate_bound_access_tokens(),
+        ..client
+    };
+
+    let client = repo.oauth2_client().update(client).await?;
+
+    let client_secret = client_secret(&client, &encrypter)?;
+    let response = client_response(
+        client,
+        client_secret,
+        registration_access_token,
+        &url_builder,
+    )?;
 
     repo.save().await?;
 
-    let response = RouteResponse { response, metadata };
+    Ok(Json(response))
+}
 
-    Ok((StatusCode::CREATED, Json(response)))
+#[tracing::instrument(
+    name = "handlers.oauth2.registration.delete",
+    fields(client.id = %client_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn delete(
+    mut repo: BoxRepository,
+    State(encrypter): State<Encrypter>,
+    Path(client_id): Path<String>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, RouteError> {
+    let (client, _) = authenticate_client(&mut repo, &encrypter, &client_id, authorization).await?;
+
+    info!(client.id = %client.id, "Client unregistering itself");
+
+    // This also ends the sessions of the client, and removes its grants
+    repo.oauth2_client().delete(client).await?;
+
+    repo.save().await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[cfg(test)]
@@ -476,5 +813,107 @@ mod tests {
         response.assert_status(StatusCode::CREATED);
         let response: ClientRegistrationResponse = response.json();
         assert!(response.client_secret.is_some());
+        assert!(response.registration_access_token.is_some());
+        assert!(response.registration_client_uri.is_some());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_registration_management(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "client_secret_basic",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let registration: ClientRegistrationResponse = response.json();
+        let token = registration.registration_access_token.unwrap();
+        let uri = registration.registration_client_uri.unwrap();
+        assert_eq!(
+            uri.path(),
+            format!("/oauth2/registration/{}", registration.client_id)
+        );
+        let path = uri.path();
+
+        // The registration access token is required
+        let request = Request::get(path).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        let request = Request::get(path).bearer("wrong").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // Read the registration back
+        let request = Request::get(path).bearer(&token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["client_id"], registration.client_id);
+        assert_eq!(
+            body["client_secret"],
+            registration.client_secret.clone().unwrap()
+        );
+        assert_eq!(
+            body["redirect_uris"],
+            serde_json::json!(["https://example.com/"])
+        );
+
+        // The client_id in the body has to match
+        let request = Request::put(path).bearer(&token).json(serde_json::json!({
+            "client_id": "01H8PKNWKKRPCBW4YGH1RWV279",
+            "client_uri": "https://example.com/",
+            "redirect_uris": ["https://example.com/callback"],
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // The new metadata goes through the same checks as on registration
+        let request = Request::put(path).bearer(&token).json(serde_json::json!({
+            "client_id": registration.client_id,
+            "client_uri": "https://example.com/",
+            "redirect_uris": ["http://this-is-insecure.com/"],
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::InvalidRedirectUri);
+
+        // Update the registration, the client keeps its secret
+        let request = Request::put(path).bearer(&token).json(serde_json::json!({
+            "client_id": registration.client_id,
+            "client_name": "Updated client",
+            "client_uri": "https://example.com/",
+            "redirect_uris": ["https://example.com/callback"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "token_endpoint_auth_method": "client_secret_post",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["client_name"], "Updated client");
+        assert_eq!(
+            body["redirect_uris"],
+            serde_json::json!(["https://example.com/callback"])
+        );
+        assert_eq!(body["client_secret"], registration.client_secret.unwrap());
+        assert_eq!(body["registration_access_token"], token);
+
+        // Delete the registration
+        let request = Request::delete(path).bearer(&token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+
+        let request = Request::get(path).bearer(&token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
     }
 }
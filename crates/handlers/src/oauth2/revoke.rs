@@ -5,18 +5,20 @@
 // Please see LICENSE in the repository root for full details.
 
 use axum::{extract::State, response::IntoResponse, Json};
+use axum_extra::typed_header::TypedHeader;
 use hyper::StatusCode;
 use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
 };
-use mas_data_model::TokenType;
+use mas_data_model::{AuditEventKind, TokenType};
 use mas_iana::oauth::OAuthTokenTypeHint;
 use mas_keystore::Encrypter;
 use mas_storage::{
-    job::{JobRepositoryExt, SyncDevicesJob},
-    BoxClock, BoxRepository, RepositoryAccess,
+    audit::AuditEventRepository,
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
@@ -24,6 +26,7 @@ use oauth2_types::{
 };
 use thiserror::Error;
 
+use super::metrics::{Operation, RequestMetrics};
 use crate::{impl_from_error_for_route, BoundActivityTracker};
 
 #[derive(Debug, Error)]
@@ -110,18 +113,23 @@ impl From<mas_data_model::TokenFormatError> for RouteError {
     err,
 )]
 pub(crate) async fn post(
+    mut rng: BoxRng,
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(encrypter): State<Encrypter>,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     client_authorization: ClientAuthorization<RevocationRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
+    let mut metrics = RequestMetrics::start(Operation::Revocation);
+
     let client = client_authorization
         .credentials
         .fetch(&mut repo)
         .await?
         .ok_or(RouteError::ClientNotFound)?;
+    metrics.set_client(&client);
 
     let method = client
         .token_endpoint_auth_method
@@ -201,7 +209,7 @@ pub(crate) async fn post(
 
     // If the session is associated with a user, make sure we schedule a device
     // deletion job for all the devices associated with the session.
-    if let Some(user_id) = session.user_id {
+    let user = if let Some(user_id) = session.user_id {
         // Fetch the user
         let user = repo
             .user()
@@ -211,12 +219,41 @@ pub(crate) async fn post(
 
         // Schedule a job to sync the devices of the user with the homeserver
         repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
-    }
+
+        Some(user)
+    } else {
+        None
+    };
 
     // Now that we checked everything, we can end the session.
-    repo.oauth2_session().finish(&clock, session).await?;
+    let session = repo.oauth2_session().finish(&clock, session).await?;
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::SessionEnded,
+            session.user_id,
+            session.user_id,
+            activity_tracker.ip(),
+            user_agent.map(|TypedHeader(ua)| ua.as_str().to_owned()),
+            serde_json::json!({
+                "oauth2_session_id": session.id.to_string(),
+                "client_id": client.client_id,
+            }),
+        )
+        .await?;
+
+    if let Some(user) = &user {
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                &mut rng, &clock, user, "oauth2", session.id,
+            ))
+            .await?;
+    }
 
     repo.save().await?;
+    metrics.success();
 
     Ok(())
 }
@@ -225,7 +262,7 @@ pub(crate) async fn post(
 mod tests {
     use chrono::Duration;
     use hyper::Request;
-    use mas_data_model::{AccessToken, RefreshToken};
+    use mas_data_model::{AccessToken, RefreshToken, TokenType};
     use mas_router::SimpleRoute;
     use mas_storage::RepositoryAccess;
     use oauth2_types::{
@@ -304,8 +341,12 @@ mod tests {
                 &mut state.rng(),
                 &state.clock,
                 &mut repo,
+                &client,
                 &session,
+                TokenType::AccessToken.generate(&mut state.rng()),
                 Duration::microseconds(5 * 60 * 1000 * 1000),
+                None,
+                None,
             )
             .await
             .unwrap();
@@ -371,8 +412,12 @@ mod tests {
                 &mut state.rng(),
                 &state.clock,
                 &mut repo,
+                &client,
                 &session,
+                TokenType::AccessToken.generate(&mut state.rng()),
                 Duration::microseconds(5 * 60 * 1000 * 1000),
+                None,
+                None,
             )
             .await
             .unwrap();
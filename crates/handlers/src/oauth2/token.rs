@@ -4,38 +4,54 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{extract::State, response::IntoResponse, Extension, Json};
 use axum_extra::typed_header::TypedHeader;
 use chrono::Duration;
 use headers::{CacheControl, HeaderMap, HeaderMapExt, Pragma};
-use hyper::StatusCode;
+use hyper::{Method, StatusCode};
 use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
+    client_certificate::ClientCertificate,
+    dpop::{DPoPProof, DPoPProofError},
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
 };
 use mas_data_model::{
-    AuthorizationGrantStage, Client, Device, DeviceCodeGrantState, SiteConfig, TokenType, UserAgent,
+    AuditEventKind, AuthorizationGrantStage, CibaGrantState, Client, Device, DeviceCodeGrantState,
+    JwksOrJwksUri, RefreshToken, Session, SiteConfig, TokenType, User, UserAgent,
+};
+use mas_iana::oauth::OAuthAccessTokenType;
+use mas_jose::{
+    claims::{self, TimeOptions},
+    jwt::Jwt,
 };
 use mas_keystore::{Encrypter, Keystore};
 use mas_matrix::BoxHomeserverConnection;
 use mas_oidc_client::types::scope::ScopeToken;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
+    audit::AuditEventRepository,
+    honeytoken::HoneytokenRepository,
+    job::{
+        DispatchWebhookJob, JobRepositoryExt, SendNewDeviceNotificationJob,
+        SendRefreshTokenReuseNotificationJob, SyncDevicesJob,
+    },
     oauth2::{
-        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
-        OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2CibaGrantRepository,
+        OAuth2JwtBearerAssertionRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
-    user::BrowserSessionRepository,
+    user::{BrowserSessionRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     pkce::CodeChallengeError,
     requests::{
-        AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, ClientCredentialsGrant,
-        DeviceCodeGrant, GrantType, RefreshTokenGrant,
+        AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, CibaGrant,
+        ClientCredentialsGrant, DeviceCodeGrant, GrantType, JwtBearerGrant, RefreshTokenGrant,
     },
     scope,
 };
@@ -43,8 +59,19 @@ use thiserror::Error;
 use tracing::debug;
 use ulid::Ulid;
 
-use super::{generate_id_token, generate_token_pair};
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use super::{
+    generate_access_token, generate_id_token, generate_token_pair,
+    metrics::{Operation, RequestMetrics},
+    refresh_token_dpop_jkt,
+};
+use crate::{
+    impl_from_error_for_route, session_limit::make_room_for_session,
+    upstream_oauth2::cache::MetadataCache, BoundActivityTracker, Limiter,
+};
+
+/// How long a JWT bearer assertion can be valid for, between when it was
+/// issued and when it expires
+const JWT_BEARER_ASSERTION_MAX_LIFETIME: Duration = Duration::hours(1);
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -78,9 +105,24 @@ pub(crate) enum RouteError {
     #[error("refresh token {0} is invalid")]
     RefreshTokenInvalid(Ulid),
 
+    #[error("refresh token {0} was already used")]
+    RefreshTokenReused(Ulid),
+
     #[error("session {0} is invalid")]
     SessionInvalid(Ulid),
 
+    #[error("refresh token {0} has expired")]
+    RefreshTokenExpired(Ulid),
+
+    #[error("session {0} has expired")]
+    SessionExpired(Ulid),
+
+    #[error("user {0} is locked")]
+    UserLocked(Ulid),
+
+    #[error("user {0} reached the maximum number of sessions")]
+    TooManySessions(Ulid),
+
     #[error("client id mismatch: expected {expected}, got {actual}")]
     ClientIDMismatch { expected: Ulid, actual: Ulid },
 
@@ -105,14 +147,47 @@ pub(crate) enum RouteError {
     #[error("device code grant is still pending")]
     DeviceCodePending,
 
+    #[error("device code grant is polled too often")]
+    DeviceCodeSlowDown,
+
     #[error("device code grant was rejected")]
     DeviceCodeRejected,
 
     #[error("device code grant was already exchanged")]
     DeviceCodeExchanged,
 
+    #[error("CIBA grant expired")]
+    CibaGrantExpired,
+
+    #[error("CIBA grant is still pending")]
+    CibaGrantPending,
+
+    #[error("CIBA grant is polled too often")]
+    CibaGrantSlowDown,
+
+    #[error("CIBA grant was rejected")]
+    CibaGrantRejected,
+
+    #[error("CIBA grant was already exchanged")]
+    CibaGrantExchanged,
+
     #[error("failed to provision device")]
     ProvisionDeviceFailed(#[source] anyhow::Error),
+
+    #[error("invalid assertion")]
+    InvalidAssertion,
+
+    #[error("assertion issuer is not trusted")]
+    UntrustedAssertionIssuer,
+
+    #[error("invalid DPoP proof")]
+    InvalidDPoPProof(#[from] DPoPProofError),
+
+    #[error("refresh token {0} is bound to another DPoP key")]
+    DPoPKeyMismatch(Ulid),
+
+    #[error("client certificate is required for certificate-bound access tokens")]
+    MissingClientCertificate,
 }
 
 impl IntoResponse for RouteError {
@@ -158,25 +233,43 @@ impl IntoResponse for RouteError {
                     ),
                 ),
             ),
-            Self::DeviceCodeRejected => (
+            Self::DeviceCodeRejected | Self::CibaGrantRejected => (
                 StatusCode::FORBIDDEN,
                 Json(ClientError::from(ClientErrorCode::AccessDenied)),
             ),
-            Self::DeviceCodeExpired => (
+            Self::TooManySessions(_) => (
+                StatusCode::FORBIDDEN,
+                Json(ClientError::new(
+                    ClientErrorCode::AccessDenied,
+                    "The user reached the maximum number of sessions",
+                )),
+            ),
+            Self::DeviceCodeExpired | Self::CibaGrantExpired => (
                 StatusCode::FORBIDDEN,
                 Json(ClientError::from(ClientErrorCode::ExpiredToken)),
             ),
-            Self::DeviceCodePending => (
+            Self::DeviceCodePending | Self::CibaGrantPending => (
                 StatusCode::FORBIDDEN,
                 Json(ClientError::from(ClientErrorCode::AuthorizationPending)),
             ),
+            Self::DeviceCodeSlowDown | Self::CibaGrantSlowDown => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::SlowDown)),
+            ),
             Self::InvalidGrant
             | Self::DeviceCodeExchanged
+            | Self::CibaGrantExchanged
             | Self::RefreshTokenNotFound
             | Self::RefreshTokenInvalid(_)
+            | Self::RefreshTokenReused(_)
             | Self::SessionInvalid(_)
+            | Self::RefreshTokenExpired(_)
+            | Self::SessionExpired(_)
+            | Self::UserLocked(_)
             | Self::ClientIDMismatch { .. }
-            | Self::GrantNotFound => (
+            | Self::GrantNotFound
+            | Self::InvalidAssertion
+            | Self::UntrustedAssertionIssuer => (
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::InvalidGrant)),
             ),
@@ -184,6 +277,24 @@ impl IntoResponse for RouteError {
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::UnsupportedGrantType)),
             ),
+            Self::InvalidDPoPProof(err) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidDpopProof)
+                        .with_description(err.to_string()),
+                ),
+            ),
+            Self::DPoPKeyMismatch(_) => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidDpopProof)),
+            ),
+            Self::MissingClientCertificate => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::new(
+                    ClientErrorCode::InvalidRequest,
+                    "A client certificate is required to get access tokens for this client",
+                )),
+            ),
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -193,6 +304,7 @@ impl IntoResponse for RouteError {
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(super::IdTokenSignatureError);
+impl_from_error_for_route!(super::AccessTokenSignatureError);
 
 #[tracing::instrument(
     name = "handlers.oauth2.token.post",
@@ -203,7 +315,10 @@ impl_from_error_for_route!(super::IdTokenSignatureError);
 pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
-    State(http_client_factory): State<HttpClientFactory>,
+    (State(http_client_factory), State(metadata_cache)): (
+        State<HttpClientFactory>,
+        State<MetadataCache>,
+    ),
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
     activity_tracker: BoundActivityTracker,
@@ -211,16 +326,22 @@ pub(crate) async fn post(
     State(homeserver): State<BoxHomeserverConnection>,
     State(site_config): State<SiteConfig>,
     State(encrypter): State<Encrypter>,
+    State(limiter): State<Limiter>,
     policy: Policy,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    request_headers: HeaderMap,
+    client_certificate: Option<Extension<ClientCertificate>>,
     client_authorization: ClientAuthorization<AccessTokenRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
+    let mut metrics = RequestMetrics::start(Operation::Token);
+
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
     let client = client_authorization
         .credentials
         .fetch(&mut repo)
         .await?
         .ok_or(RouteError::ClientNotFound)?;
+    metrics.set_client(&client);
 
     let method = client
         .token_endpoint_auth_method
@@ -233,8 +354,35 @@ pub(crate) async fn post(
         .await?;
 
     let form = client_authorization.form.ok_or(RouteError::BadRequest)?;
+    if let Some(grant_type) = form.grant_type() {
+        metrics.set_grant_type(grant_type);
+    }
+
+    // If the client sent a DPoP proof, the issued tokens are bound to its key
+    let dpop_jkt = DPoPProof::from_headers(&request_headers)?
+        .map(|proof| {
+            proof.verify(
+                clock.now(),
+                &Method::POST,
+                &url_builder.oauth_token_endpoint(),
+                None,
+            )
+        })
+        .transpose()?;
+
+    // If the client uses certificate-bound access tokens, they are bound to the
+    // certificate it presented
+    let x5t_s256 = if client.tls_client_certificate_bound_access_tokens {
+        let Some(Extension(certificate)) = &client_certificate else {
+            return Err(RouteError::MissingClientCertificate);
+        };
 
-    let (reply, repo) = match form {
+        Some(certificate.thumbprint())
+    } else {
+        None
+    };
+
+    let (mut reply, repo) = match form {
         AccessTokenRequest::AuthorizationCode(grant) => {
             authorization_code_grant(
                 &mut rng,
@@ -242,6 +390,8 @@ pub(crate) async fn post(
                 &activity_tracker,
                 &grant,
                 &client,
+                dpop_jkt.as_deref(),
+                x5t_s256.as_deref(),
                 &key_store,
                 &url_builder,
                 &site_config,
@@ -258,6 +408,10 @@ pub(crate) async fn post(
                 &activity_tracker,
                 &grant,
                 &client,
+                dpop_jkt.as_deref(),
+                x5t_s256.as_deref(),
+                &key_store,
+                &url_builder,
                 &site_config,
                 repo,
                 user_agent,
@@ -271,6 +425,10 @@ pub(crate) async fn post(
                 &activity_tracker,
                 &grant,
                 &client,
+                dpop_jkt.as_deref(),
+                x5t_s256.as_deref(),
+                &key_store,
+                &url_builder,
                 &site_config,
                 repo,
                 policy,
@@ -285,10 +443,53 @@ pub(crate) async fn post(
                 &activity_tracker,
                 &grant,
                 &client,
+                dpop_jkt.as_deref(),
+                x5t_s256.as_deref(),
+                &key_store,
+                &url_builder,
+                &site_config,
+                &limiter,
+                repo,
+                &homeserver,
+                user_agent,
+            )
+            .await?
+        }
+        AccessTokenRequest::Ciba(grant) => {
+            ciba_grant(
+                &mut rng,
+                &clock,
+                &activity_tracker,
+                &grant,
+                &client,
+                dpop_jkt.as_deref(),
+                x5t_s256.as_deref(),
+                &key_store,
+                &url_builder,
+                &site_config,
+                &limiter,
+                repo,
+                &homeserver,
+                user_agent,
+            )
+            .await?
+        }
+        AccessTokenRequest::JwtBearer(grant) => {
+            jwt_bearer_grant(
+                &mut rng,
+                &clock,
+                &activity_tracker,
+                &grant,
+                &client,
+                dpop_jkt.as_deref(),
+                x5t_s256.as_deref(),
+                &http_client_factory,
+                &metadata_cache,
                 &key_store,
                 &url_builder,
                 &site_config,
                 repo,
+                policy,
                 &homeserver,
                 user_agent,
             )
@@ -300,6 +501,11 @@ pub(crate) async fn post(
     };
 
     repo.save().await?;
+    metrics.success();
+
+    if dpop_jkt.is_some() {
+        reply.token_type = OAuthAccessTokenType::DPoP;
+    }
 
     let mut headers = HeaderMap::new();
     headers.typed_insert(CacheControl::new().with_no_store());
@@ -315,6 +521,8 @@ async fn authorization_code_grant(
     activity_tracker: &BoundActivityTracker,
     grant: &AuthorizationCodeGrant,
     client: &Client,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
     key_store: &Keystore,
     url_builder: &UrlBuilder,
     site_config: &SiteConfig,
@@ -419,14 +627,41 @@ async fn authorization_code_grant(
         .await?
         .ok_or(RouteError::NoSuchBrowserSession)?;
 
+    if !browser_session.user.is_valid() {
+        return Err(RouteError::UserLocked(browser_session.user.id));
+    }
+
     let last_authentication = repo
         .browser_session()
         .get_last_authentication(&browser_session)
         .await?;
 
-    let ttl = site_config.access_token_ttl;
-    let (access_token, refresh_token) =
-        generate_token_pair(&mut rng, clock, &mut repo, &session, ttl).await?;
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = generate_access_token(
+        &mut rng,
+        clock,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )?;
+    let (access_token, refresh_token) = generate_token_pair(
+        &mut rng,
+        clock,
+        &mut repo,
+        client,
+        &session,
+        access_token_str,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )
+    .await?;
 
     let id_token = if session.scope.contains(&scope::OPENID) {
         Some(generate_id_token(
@@ -459,7 +694,10 @@ async fn authorization_code_grant(
         .await?;
 
     // Look for device to provision
-    let mxid = homeserver.mxid(&browser_session.user.username);
+    let mxid = homeserver.mxid_on(
+        browser_session.user.homeserver.as_deref(),
+        &browser_session.user.username,
+    );
     for scope in &*session.scope {
         if let Some(device) = Device::from_scope_token(scope) {
             homeserver
@@ -473,6 +711,16 @@ async fn authorization_code_grant(
         .exchange(clock, authz_grant)
         .await?;
 
+    crate::upstream_oauth2::funnel::record_first_token(
+        &mut repo,
+        &mut rng,
+        clock,
+        &browser_session.user,
+    )
+    .await?;
+
+    notify_new_device(&mut repo, site_config, activity_tracker, &session).await?;
+
     // XXX: there is a potential (but unlikely) race here, where the activity for
     // the session is recorded before the transaction is committed. We would have to
     // save the repository here to fix that.
@@ -489,6 +737,10 @@ async fn refresh_token_grant(
     activity_tracker: &BoundActivityTracker,
     grant: &RefreshTokenGrant,
     client: &Client,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
     site_config: &SiteConfig,
     mut repo: BoxRepository,
     user_agent: Option<UserAgent>,
@@ -498,11 +750,25 @@ async fn refresh_token_grant(
         return Err(RouteError::UnauthorizedClient);
     }
 
-    let refresh_token = repo
+    let Some(refresh_token) = repo
         .oauth2_refresh_token()
         .find_by_token(&grant.refresh_token)
         .await?
-        .ok_or(RouteError::RefreshTokenNotFound)?;
+    else {
+        // Decoy refresh tokens are never valid, but someone trying to use one
+        // means it leaked
+        if let Some(honeytoken) = repo
+            .honeytoken()
+            .find_by_token(&grant.refresh_token)
+            .await?
+        {
+            activity_tracker
+                .report_honeytoken(clock, &honeytoken, "token", user_agent.map(|ua| ua.raw))
+                .await;
+        }
+
+        return Err(RouteError::RefreshTokenNotFound);
+    };
 
     let mut session = repo
         .oauth2_session()
@@ -510,6 +776,68 @@ async fn refresh_token_grant(
         .await?
         .ok_or(RouteError::NoSuchOAuthSession)?;
 
+    if !session.is_valid() {
+        return Err(RouteError::SessionInvalid(session.id));
+    }
+
+    if client.id != session.client_id {
+        // As per https://datatracker.ietf.org/doc/html/rfc6749#section-5.2
+        return Err(RouteError::ClientIDMismatch {
+            expected: session.client_id,
+            actual: client.id,
+        });
+    }
+
+    // Refresh tokens bound to a DPoP key can only be used with a proof from the
+    // same key. This is checked before looking for a reuse, so that someone
+    // replaying a stolen token without the key can't end the session.
+    if refresh_token.dpop_jkt.is_some() && refresh_token.dpop_jkt.as_deref() != dpop_jkt {
+        return Err(RouteError::DPoPKeyMismatch(refresh_token.id));
+    }
+
+    if refresh_token.is_consumed() {
+        // This refresh token was already exchanged, so either the client or someone
+        // else holds a stolen copy of it. We can't tell which one is legitimate, so
+        // we end the whole session, which invalidates all the tokens issued from
+        // this one.
+        end_session_on_refresh_token_reuse(
+            rng,
+            clock,
+            activity_tracker,
+            client,
+            site_config,
+            &mut repo,
+            session,
+            &refresh_token,
+            user_agent.as_ref().map(|ua| ua.raw.clone()),
+        )
+        .await?;
+
+        // Commit the transaction, as returning an error would otherwise roll it back
+        repo.save().await?;
+
+        return Err(RouteError::RefreshTokenReused(refresh_token.id));
+    }
+
+    if !refresh_token.is_valid() {
+        return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
+    }
+
+    // Locked users can't refresh their tokens anymore
+    let mut is_bot = false;
+    if let Some(user_id) = session.user_id {
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .ok_or(RouteError::SessionInvalid(session.id))?;
+
+        if !user.is_valid() {
+            return Err(RouteError::UserLocked(user_id));
+        }
+        is_bot = user.is_bot;
+    }
+
     // Let's for now record the user agent on each refresh, that should be
     // responsive enough and not too much of a burden on the database.
     if let Some(user_agent) = user_agent {
@@ -519,33 +847,63 @@ async fn refresh_token_grant(
             .await?;
     }
 
-    if !refresh_token.is_valid() {
-        return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
-    }
+    let now = clock.now();
 
-    if !session.is_valid() {
-        return Err(RouteError::SessionInvalid(session.id));
+    // Refresh tokens can only be used for a limited time after they were issued
+    let refresh_token_ttl = client.refresh_token_ttl.or(site_config.refresh_token_ttl);
+    if refresh_token_ttl.is_some_and(|ttl| refresh_token.created_at + ttl < now) {
+        return Err(RouteError::RefreshTokenExpired(refresh_token.id));
     }
 
-    if client.id != session.client_id {
-        // As per https://datatracker.ietf.org/doc/html/rfc6749#section-5.2
-        return Err(RouteError::ClientIDMismatch {
-            expected: session.client_id,
-            actual: client.id,
-        });
+    // Sessions past their maximum lifetime, or which stayed inactive for too long,
+    // can't be refreshed anymore. They are then ended by a background job. Bots
+    // are exempt from the inactivity timeout.
+    let session_ttl = client.session_ttl.or(site_config.session_ttl);
+    let session_inactivity_ttl = client
+        .session_inactivity_ttl
+        .or(site_config.session_inactivity_ttl)
+        .filter(|_| !is_bot);
+    let last_active_at = session.last_active_at.unwrap_or(session.created_at);
+    if session_ttl.is_some_and(|ttl| session.created_at + ttl < now)
+        || session_inactivity_ttl.is_some_and(|ttl| last_active_at + ttl < now)
+    {
+        return Err(RouteError::SessionExpired(session.id));
     }
 
     activity_tracker
         .record_oauth2_session(clock, &session)
         .await;
 
-    let ttl = site_config.access_token_ttl;
-    let (new_access_token, new_refresh_token) =
-        generate_token_pair(rng, clock, &mut repo, &session, ttl).await?;
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = generate_access_token(
+        rng,
+        clock,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )?;
+    let (new_access_token, new_refresh_token) = generate_token_pair(
+        rng,
+        clock,
+        &mut repo,
+        client,
+        &session,
+        access_token_str,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )
+    .await?;
 
     let refresh_token = repo
         .oauth2_refresh_token()
-        .consume(clock, refresh_token)
+        .consume(clock, refresh_token, &new_refresh_token)
         .await?;
 
     if let Some(access_token_id) = refresh_token.access_token_id {
@@ -565,12 +923,87 @@ async fn refresh_token_grant(
     Ok((params, repo))
 }
 
+/// End a session after one of its refresh tokens was used more than once
+async fn end_session_on_refresh_token_reuse(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    activity_tracker: &BoundActivityTracker,
+    client: &Client,
+    site_config: &SiteConfig,
+    repo: &mut BoxRepository,
+    session: Session,
+    refresh_token: &RefreshToken,
+    user_agent: Option<String>,
+) -> Result<(), RouteError> {
+    tracing::warn!(
+        oauth2_session.id = %session.id,
+        oauth2_refresh_token.id = %refresh_token.id,
+        "Refresh token was reused, ending the session"
+    );
+
+    let user = if let Some(user_id) = session.user_id {
+        let user = repo.user().lookup(user_id).await?;
+
+        if let Some(user) = &user {
+            // Schedule a job to sync the devices of the user with the homeserver
+            repo.job().schedule_job(SyncDevicesJob::new(user)).await?;
+        }
+
+        user
+    } else {
+        None
+    };
+
+    let session = repo.oauth2_session().finish(clock, session).await?;
+
+    repo.audit_event()
+        .add(
+            rng,
+            clock,
+            AuditEventKind::RefreshTokenReused,
+            None,
+            session.user_id,
+            activity_tracker.ip(),
+            user_agent,
+            serde_json::json!({
+                "oauth2_session_id": session.id.to_string(),
+                "oauth2_refresh_token_id": refresh_token.id.to_string(),
+                "next_oauth2_refresh_token_id": refresh_token
+                    .state
+                    .next_refresh_token_id()
+                    .map(|id| id.to_string()),
+                "client_id": client.client_id,
+            }),
+        )
+        .await?;
+
+    if let Some(user) = &user {
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                rng, clock, user, "oauth2", session.id,
+            ))
+            .await?;
+
+        if site_config.notify_refresh_token_reuse {
+            repo.job()
+                .schedule_job(SendRefreshTokenReuseNotificationJob::new(&session))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
 async fn client_credentials_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
     grant: &ClientCredentialsGrant,
     client: &Client,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
     site_config: &SiteConfig,
     mut repo: BoxRepository,
     mut policy: Policy,
@@ -588,8 +1021,12 @@ async fn client_credentials_grant(
         .unwrap_or_else(|| std::iter::empty::<ScopeToken>().collect());
 
     // Make the request go through the policy engine
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.as_ref().map(|ua| ua.raw.clone()),
+    );
     let res = policy
-        .evaluate_client_credentials_grant(&scope, client)
+        .evaluate_client_credentials_grant(&scope, client, &requester)
         .await?;
     if !res.valid() {
         return Err(RouteError::DeniedByPolicy(res.violations));
@@ -608,12 +1045,32 @@ async fn client_credentials_grant(
             .await?;
     }
 
-    let ttl = site_config.access_token_ttl;
-    let access_token_str = TokenType::AccessToken.generate(rng);
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = generate_access_token(
+        rng,
+        clock,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )?;
 
     let access_token = repo
         .oauth2_access_token()
-        .add(rng, clock, &session, access_token_str, Some(ttl))
+        .add(
+            rng,
+            clock,
+            &session,
+            access_token_str,
+            Some(ttl),
+            dpop_jkt.map(ToOwned::to_owned),
+            x5t_s256.map(ToOwned::to_owned),
+        )
         .await?;
 
     let mut params = AccessTokenResponse::new(access_token.access_token).with_expires_in(ttl);
@@ -639,9 +1096,12 @@ async fn device_code_grant(
     activity_tracker: &BoundActivityTracker,
     grant: &DeviceCodeGrant,
     client: &Client,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
     key_store: &Keystore,
     url_builder: &UrlBuilder,
     site_config: &SiteConfig,
+    limiter: &Limiter,
     mut repo: BoxRepository,
     homeserver: &BoxHomeserverConnection,
     user_agent: Option<UserAgent>,
@@ -671,6 +1131,12 @@ async fn device_code_grant(
 
     let browser_session_id = match &grant.state {
         DeviceCodeGrantState::Pending => {
+            // Tell clients polling faster than the advertised interval to slow down
+            if let Err(e) = limiter.check_device_code_polling(grant.id) {
+                tracing::warn!(error = &e as &dyn std::error::Error);
+                return Err(RouteError::DeviceCodeSlowDown);
+            }
+
             return Err(RouteError::DeviceCodePending);
         }
         DeviceCodeGrantState::Rejected { .. } => {
@@ -690,6 +1156,14 @@ async fn device_code_grant(
         .await?
         .ok_or(RouteError::NoSuchBrowserSession)?;
 
+    if !browser_session.user.is_valid() {
+        return Err(RouteError::UserLocked(browser_session.user.id));
+    }
+
+    if !make_room_for_session(rng, clock, &mut repo, site_config, &browser_session.user).await? {
+        return Err(RouteError::TooManySessions(browser_session.user.id));
+    }
+
     // Start the session
     let mut session = repo
         .oauth2_session()
@@ -704,12 +1178,32 @@ async fn device_code_grant(
             .await?;
     }
 
-    let ttl = site_config.access_token_ttl;
-    let access_token_str = TokenType::AccessToken.generate(rng);
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = generate_access_token(
+        rng,
+        clock,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )?;
 
     let access_token = repo
         .oauth2_access_token()
-        .add(rng, clock, &session, access_token_str, Some(ttl))
+        .add(
+            rng,
+            clock,
+            &session,
+            access_token_str,
+            Some(ttl),
+            dpop_jkt.map(ToOwned::to_owned),
+            x5t_s256.map(ToOwned::to_owned),
+        )
         .await?;
 
     let mut params =
@@ -722,7 +1216,14 @@ async fn device_code_grant(
 
         let refresh_token = repo
             .oauth2_refresh_token()
-            .add(rng, clock, &session, &access_token, refresh_token_str)
+            .add(
+                rng,
+                clock,
+                &session,
+                &access_token,
+                refresh_token_str,
+                refresh_token_dpop_jkt(client, dpop_jkt),
+            )
             .await?;
 
         params = params.with_refresh_token(refresh_token.refresh_token);
@@ -751,7 +1252,10 @@ async fn device_code_grant(
         .await?;
 
     // Look for device to provision
-    let mxid = homeserver.mxid(&browser_session.user.username);
+    let mxid = homeserver.mxid_on(
+        browser_session.user.homeserver.as_deref(),
+        &browser_session.user.username,
+    );
     for scope in &*session.scope {
         if let Some(device) = Device::from_scope_token(scope) {
             homeserver
@@ -761,6 +1265,8 @@ async fn device_code_grant(
         }
     }
 
+    notify_new_device(&mut repo, site_config, activity_tracker, &session).await?;
+
     // XXX: there is a potential (but unlikely) race here, where the activity for
     // the session is recorded before the transaction is committed. We would have to
     // save the repository here to fix that.
@@ -776,55 +1282,525 @@ async fn device_code_grant(
     Ok((params, repo))
 }
 
-#[cfg(test)]
-mod tests {
-    use hyper::Request;
-    use mas_data_model::{AccessToken, AuthorizationCode, RefreshToken};
-    use mas_router::SimpleRoute;
-    use oauth2_types::{
-        registration::ClientRegistrationResponse,
-        requests::{DeviceAuthorizationResponse, ResponseMode},
-        scope::{Scope, OPENID},
-    };
-    use sqlx::PgPool;
+#[allow(clippy::too_many_lines)]
+async fn ciba_grant(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    activity_tracker: &BoundActivityTracker,
+    grant: &CibaGrant,
+    client: &Client,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
+    limiter: &Limiter,
+    mut repo: BoxRepository,
+    homeserver: &BoxHomeserverConnection,
+    user_agent: Option<UserAgent>,
+) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
+    // Check that the client is allowed to use this grant type
+    if !client
+        .grant_types
+        .contains(&GrantType::ClientInitiatedBackchannelAuthentication)
+    {
+        return Err(RouteError::UnauthorizedClient);
+    }
 
-    use super::*;
-    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+    let grant = repo
+        .oauth2_ciba_grant()
+        .find_by_auth_req_id(&grant.auth_req_id)
+        .await?
+        .ok_or(RouteError::GrantNotFound)?;
 
-    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_auth_code_grant(pool: PgPool) {
-        setup();
-        let state = TestState::from_pool(pool).await.unwrap();
+    // Check that the client match
+    if client.id != grant.client_id {
+        return Err(RouteError::ClientIDMismatch {
+            expected: grant.client_id,
+            actual: client.id,
+        });
+    }
 
-        // Provision a client
-        let request =
-            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
-                "client_uri": "https://example.com/",
-                "redirect_uris": ["https://example.com/callback"],
-                "token_endpoint_auth_method": "none",
-                "response_types": ["code"],
-                "grant_types": ["authorization_code"],
-            }));
+    if grant.expires_at < clock.now() {
+        return Err(RouteError::CibaGrantExpired);
+    }
 
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::CREATED);
+    let browser_session_id = match &grant.state {
+        CibaGrantState::Pending => {
+            // Tell clients polling faster than the advertised interval to slow down
+            if let Err(e) = limiter.check_ciba_polling(grant.id) {
+                tracing::warn!(error = &e as &dyn std::error::Error);
+                return Err(RouteError::CibaGrantSlowDown);
+            }
 
-        let ClientRegistrationResponse { client_id, .. } = response.json();
+            return Err(RouteError::CibaGrantPending);
+        }
+        CibaGrantState::Rejected { .. } => {
+            return Err(RouteError::CibaGrantRejected);
+        }
+        CibaGrantState::Exchanged { .. } => {
+            return Err(RouteError::CibaGrantExchanged);
+        }
+        CibaGrantState::Fulfilled {
+            browser_session_id, ..
+        } => *browser_session_id,
+    };
 
-        // Let's provision a user and create a session for them. This part is hard to
-        // test with just HTTP requests, so we'll use the repository directly.
-        let mut repo = state.repository().await.unwrap();
+    let browser_session = repo
+        .browser_session()
+        .lookup(browser_session_id)
+        .await?
+        .ok_or(RouteError::NoSuchBrowserSession)?;
 
-        let user = repo
-            .user()
-            .add(&mut state.rng(), &state.clock, "alice".to_owned())
-            .await
-            .unwrap();
+    if !browser_session.user.is_valid() {
+        return Err(RouteError::UserLocked(browser_session.user.id));
+    }
 
-        let browser_session = repo
-            .browser_session()
-            .add(&mut state.rng(), &state.clock, &user, None)
-            .await
+    if !make_room_for_session(rng, clock, &mut repo, site_config, &browser_session.user).await? {
+        return Err(RouteError::TooManySessions(browser_session.user.id));
+    }
+
+    // Start the session
+    let mut session = repo
+        .oauth2_session()
+        .add_from_browser_session(rng, clock, client, &browser_session, grant.scope.clone())
+        .await?;
+
+    if let Some(user_agent) = user_agent {
+        session = repo
+            .oauth2_session()
+            .record_user_agent(session, user_agent)
+            .await?;
+    }
+
+    repo.oauth2_ciba_grant()
+        .exchange(clock, grant, &session)
+        .await?;
+
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = generate_access_token(
+        rng,
+        clock,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )?;
+
+    let access_token = repo
+        .oauth2_access_token()
+        .add(
+            rng,
+            clock,
+            &session,
+            access_token_str,
+            Some(ttl),
+            dpop_jkt.map(ToOwned::to_owned),
+            x5t_s256.map(ToOwned::to_owned),
+        )
+        .await?;
+
+    let mut params =
+        AccessTokenResponse::new(access_token.access_token.clone()).with_expires_in(ttl);
+
+    // If the client uses the refresh token grant type, we also generate a refresh
+    // token
+    if client.grant_types.contains(&GrantType::RefreshToken) {
+        let refresh_token_str = TokenType::RefreshToken.generate(rng);
+
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .add(
+                rng,
+                clock,
+                &session,
+                &access_token,
+                refresh_token_str,
+                refresh_token_dpop_jkt(client, dpop_jkt),
+            )
+            .await?;
+
+        params = params.with_refresh_token(refresh_token.refresh_token);
+    }
+
+    // The backchannel authentication endpoint requires the openid scope, so this
+    // should always be the case
+    if session.scope.contains(&scope::OPENID) {
+        let id_token = generate_id_token(
+            rng,
+            clock,
+            url_builder,
+            key_store,
+            client,
+            None,
+            &browser_session,
+            Some(&access_token),
+            None,
+        )?;
+
+        params = params.with_id_token(id_token);
+    }
+
+    // Lock the user sync to make sure we don't get into a race condition
+    repo.user()
+        .acquire_lock_for_sync(&browser_session.user)
+        .await?;
+
+    // Look for device to provision
+    let mxid = homeserver.mxid_on(
+        browser_session.user.homeserver.as_deref(),
+        &browser_session.user.username,
+    );
+    for scope in &*session.scope {
+        if let Some(device) = Device::from_scope_token(scope) {
+            homeserver
+                .create_device(&mxid, device.as_str())
+                .await
+                .map_err(RouteError::ProvisionDeviceFailed)?;
+        }
+    }
+
+    notify_new_device(&mut repo, site_config, activity_tracker, &session).await?;
+
+    // XXX: there is a potential (but unlikely) race here, where the activity for
+    // the session is recorded before the transaction is committed. We would have to
+    // save the repository here to fix that.
+    activity_tracker
+        .record_oauth2_session(clock, &session)
+        .await;
+
+    if !session.scope.is_empty() {
+        // We only return the scope if it's not empty
+        params = params.with_scope(session.scope);
+    }
+
+    Ok((params, repo))
+}
+
+#[allow(clippy::too_many_lines)]
+async fn jwt_bearer_grant(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    activity_tracker: &BoundActivityTracker,
+    grant: &JwtBearerGrant,
+    client: &Client,
+    dpop_jkt: Option<&str>,
+    x5t_s256: Option<&str>,
+    http_client_factory: &HttpClientFactory,
+    metadata_cache: &MetadataCache,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
+    mut repo: BoxRepository,
+    mut policy: Policy,
+    homeserver: &BoxHomeserverConnection,
+    user_agent: Option<UserAgent>,
+) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
+    // Check that the client is allowed to use this grant type
+    if !client.grant_types.contains(&GrantType::JwtBearer) {
+        return Err(RouteError::UnauthorizedClient);
+    }
+
+    let assertion: Jwt<'_, HashMap<String, serde_json::Value>> = grant
+        .assertion
+        .as_str()
+        .try_into()
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    // Find the trusted issuer of the assertion, to know which keys to use to
+    // verify it
+    let issuer = assertion
+        .payload()
+        .get("iss")
+        .and_then(serde_json::Value::as_str)
+        .ok_or(RouteError::InvalidAssertion)?;
+    let issuer = site_config
+        .jwt_bearer_issuers
+        .iter()
+        .find(|trusted| trusted.issuer == issuer)
+        .ok_or(RouteError::UntrustedAssertionIssuer)?;
+
+    // Keys fetched from the issuer are cached, and only fetched again if the
+    // assertion was signed with a key we don't know about yet
+    let jwks = match &issuer.jwks {
+        JwksOrJwksUri::Jwks(jwks) => Arc::new(jwks.clone()),
+        JwksOrJwksUri::JwksUri(jwks_uri) => metadata_cache
+            .get_jwks(
+                clock,
+                &http_client_factory.http_service("oauth2.jwt_bearer_fetch_jwks"),
+                jwks_uri,
+                assertion.header().kid(),
+            )
+            .await
+            .map_err(|e| RouteError::Internal(Box::new(e)))?,
+    };
+    assertion
+        .verify_with_jwks(&jwks)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    let mut claims = assertion.into_parts().1;
+    let time_options = TimeOptions::new(clock.now());
+    let expires_at = claims::EXP
+        .extract_required_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+    claims::NBF
+        .extract_optional_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+    let issued_at = claims::IAT
+        .extract_optional_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    // Assertions must be short-lived, as per RFC 7523 section 3, so that we only
+    // have to remember the ones we've seen for a short while
+    let issued_at = issued_at.map_or_else(|| clock.now(), |iat| *iat);
+    if *expires_at - issued_at > JWT_BEARER_ASSERTION_MAX_LIFETIME {
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    let jti = claims::JTI
+        .extract_required(&mut claims)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    // The audience must identify this server, either by its issuer or by its
+    // token endpoint, as per RFC 7523 section 3
+    let audiences = [
+        url_builder.oidc_issuer().to_string(),
+        url_builder.oauth_token_endpoint().to_string(),
+    ];
+    let audience_matches = audiences.iter().any(|audience| {
+        claims::AUD
+            .extract_required_with_options(&mut claims.clone(), audience)
+            .is_ok()
+    });
+    if !audience_matches {
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    // Each assertion can only be used once
+    let first_use = repo
+        .oauth2_jwt_bearer_assertion()
+        .record_use(clock, &issuer.issuer, &jti, *expires_at)
+        .await?;
+    if !first_use {
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    // The subject of the assertion is the username of the user, on the
+    // homeserver the client belongs to
+    let username = claims::SUB
+        .extract_required(&mut claims)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+    let user = repo
+        .user()
+        .find_by_username(
+            site_config.homeserver_for_client(client.id).into(),
+            &username,
+        )
+        .await?
+        .filter(User::is_valid)
+        .ok_or(RouteError::InvalidAssertion)?;
+
+    // Default to an empty scope if none is provided
+    let scope = grant
+        .scope
+        .clone()
+        .unwrap_or_else(|| std::iter::empty::<ScopeToken>().collect());
+
+    // Make the request go through the policy engine, which decides which issuers
+    // are allowed and for which scopes
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.as_ref().map(|ua| ua.raw.clone()),
+    );
+    let res = policy
+        .evaluate_jwt_bearer_grant(&scope, client, &user, &issuer.issuer, &requester)
+        .await?;
+    if !res.valid() {
+        return Err(RouteError::DeniedByPolicy(res.violations));
+    }
+
+    if !make_room_for_session(rng, clock, &mut repo, site_config, &user).await? {
+        return Err(RouteError::TooManySessions(user.id));
+    }
+
+    // Start the session
+    let mut session = repo
+        .oauth2_session()
+        .add(rng, clock, client, Some(&user), None, scope)
+        .await?;
+
+    if let Some(user_agent) = user_agent {
+        session = repo
+            .oauth2_session()
+            .record_user_agent(session, user_agent)
+            .await?;
+    }
+
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = generate_access_token(
+        rng,
+        clock,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        ttl,
+        dpop_jkt,
+        x5t_s256,
+    )?;
+
+    let access_token = repo
+        .oauth2_access_token()
+        .add(
+            rng,
+            clock,
+            &session,
+            access_token_str,
+            Some(ttl),
+            dpop_jkt.map(ToOwned::to_owned),
+            x5t_s256.map(ToOwned::to_owned),
+        )
+        .await?;
+
+    let mut params =
+        AccessTokenResponse::new(access_token.access_token.clone()).with_expires_in(ttl);
+
+    // If the client uses the refresh token grant type, we also generate a refresh
+    // token
+    if client.grant_types.contains(&GrantType::RefreshToken) {
+        let refresh_token_str = TokenType::RefreshToken.generate(rng);
+
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .add(
+                rng,
+                clock,
+                &session,
+                &access_token,
+                refresh_token_str,
+                refresh_token_dpop_jkt(client, dpop_jkt),
+            )
+            .await?;
+
+        params = params.with_refresh_token(refresh_token.refresh_token);
+    }
+
+    // Lock the user sync to make sure we don't get into a race condition
+    repo.user().acquire_lock_for_sync(&user).await?;
+
+    // Look for device to provision
+    let mxid = homeserver.mxid_on(user.homeserver.as_deref(), &user.username);
+    for scope in &*session.scope {
+        if let Some(device) = Device::from_scope_token(scope) {
+            homeserver
+                .create_device(&mxid, device.as_str())
+                .await
+                .map_err(RouteError::ProvisionDeviceFailed)?;
+        }
+    }
+
+    notify_new_device(&mut repo, site_config, activity_tracker, &session).await?;
+
+    // XXX: there is a potential (but unlikely) race here, where the activity for
+    // the session is recorded before the transaction is committed. We would have to
+    // save the repository here to fix that.
+    activity_tracker
+        .record_oauth2_session(clock, &session)
+        .await;
+
+    if !session.scope.is_empty() {
+        // We only return the scope if it's not empty
+        params = params.with_scope(session.scope);
+    }
+
+    Ok((params, repo))
+}
+
+/// Schedule the email telling the user they signed in from a new device, if
+/// enabled
+async fn notify_new_device(
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    activity_tracker: &BoundActivityTracker,
+    session: &Session,
+) -> Result<(), RouteError> {
+    if site_config.new_device_emails {
+        repo.job()
+            .schedule_job(
+                SendNewDeviceNotificationJob::oauth2(session).with_ip(activity_tracker.ip()),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use mas_data_model::{
+        AccessToken, AuthorizationCode, JwksOrJwksUri, JwtBearerIssuer, RefreshToken,
+    };
+    use mas_iana::{
+        jose::JsonWebSignatureAlg,
+        oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint},
+    };
+    use mas_jose::{constraints::Constrainable, jwt::JsonWebSignatureHeader};
+    use mas_router::SimpleRoute;
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        requests::{
+            BackchannelAuthenticationResponse, DeviceAuthorizationResponse, IntrospectionResponse,
+            ResponseMode,
+        },
+        scope::{Scope, OPENID},
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_auth_code_grant(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Let's provision a user and create a session for them. This part is hard to
+        // test with just HTTP requests, so we'll use the repository directly.
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
             .unwrap();
 
         // Lookup the client in the database.
@@ -855,6 +1831,8 @@ mod tests {
                 ResponseMode::Query,
                 false,
                 false,
+                false,
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -955,6 +1933,8 @@ mod tests {
                 ResponseMode::Query,
                 false,
                 false,
+                false,
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -1062,9 +2042,13 @@ mod tests {
                 &mut state.rng(),
                 &state.clock,
                 &mut repo,
+                &client,
                 &session,
+                TokenType::AccessToken.generate(&mut state.rng()),
                 Duration::microseconds(5 * 60 * 1000 * 1000),
-            )
+                None,
+                None,
+            )
             .await
             .unwrap();
 
@@ -1109,7 +2093,10 @@ mod tests {
         let ClientError { error, .. } = response.json();
         assert_eq!(error, ClientErrorCode::InvalidGrant);
 
-        // Call it again with the new token, it should work
+        // Reusing the old token ended the session, so the new tokens are no longer
+        // valid either
+        assert!(!state.is_access_token_valid(&access_token).await);
+
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
                 "grant_type": "refresh_token",
@@ -1118,8 +2105,95 @@ mod tests {
             }));
 
         let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
-        let _: AccessTokenResponse = response.json();
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_locked_user(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &client,
+            &session,
+            TokenType::AccessToken.generate(&mut state.rng()),
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Lock the user
+        repo.user().lock(&state.clock, user).await.unwrap();
+
+        repo.save().await.unwrap();
+
+        // Refreshing the token should fail
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -1249,17 +2323,149 @@ mod tests {
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_device_code_grant(pool: PgPool) {
+    async fn test_jwt_access_tokens(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a static client which gets JWT-formatted access tokens
+        let client_id = Ulid::from_string("01JAXKNVH9Z3QC6B2J4PXY8S4Q").unwrap();
+        let client_secret = "secret";
+        let encrypted_client_secret = state
+            .encrypter
+            .encrypt_to_string(client_secret.as_bytes())
+            .unwrap();
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .upsert_static(
+                client_id,
+                OAuthClientAuthenticationMethod::ClientSecretPost,
+                Some(encrypted_client_secret),
+                None,
+                None,
+                Vec::new(),
+                false,
+                None,
+                false,
+                true,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        let access_token = response.access_token;
+
+        // The access token is a JWT signed by the server, following RFC 9068
+        let jwt: Jwt<'_, HashMap<String, serde_json::Value>> =
+            Jwt::try_from(access_token.as_str()).unwrap();
+        jwt.verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+        assert_eq!(jwt.header().typ(), Some("at+jwt"));
+        let claims = jwt.payload();
+        assert_eq!(
+            claims["iss"],
+            serde_json::json!(state.url_builder.oidc_issuer().as_str())
+        );
+        assert_eq!(claims["sub"], serde_json::json!(client_id.to_string()));
+        assert_eq!(claims["aud"], serde_json::json!(client_id.to_string()));
+        assert_eq!(
+            claims["client_id"],
+            serde_json::json!(client_id.to_string())
+        );
+        assert_eq!(claims["scope"], serde_json::json!("urn:mas:graphql:*"));
+        assert!(claims["jti"].is_string());
+        assert!(claims["exp"].is_number());
+
+        // It is still tracked by the server, and can be introspected…
+        let request =
+            Request::post(mas_router::OAuth2Introspection::PATH).form(serde_json::json!({
+                "token": access_token,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
+
+        // …and revoked
+        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+            "token": access_token,
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let request =
+            Request::post(mas_router::OAuth2Introspection::PATH).form(serde_json::json!({
+                "token": access_token,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_dpop_bound_tokens(pool: PgPool) {
         setup();
         let state = TestState::from_pool(pool).await.unwrap();
 
+        // Make DPoP proofs with one of the keys of the key store
+        let key = state
+            .key_store
+            .signing_key_for_algorithm(&JsonWebSignatureAlg::Rs256)
+            .unwrap();
+        let public_jwks = state.key_store.public_jwks();
+        let public_key = public_jwks
+            .iter()
+            .find(|public_key| public_key.kid() == key.kid())
+            .unwrap()
+            .clone();
+        let jkt = public_key.params().thumbprint();
+        let proof = |htu: &str| {
+            let signer = key
+                .params()
+                .signing_key_for_alg(&JsonWebSignatureAlg::Rs256)
+                .unwrap();
+            let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Rs256)
+                .with_typ("dpop+jwt".to_owned())
+                .with_jwk(public_key.clone());
+            let claims = serde_json::json!({
+                "jti": Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng()).to_string(),
+                "htm": "POST",
+                "htu": htu,
+                "iat": state.clock.now().timestamp(),
+            });
+            Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
         // Provision a client
         let request =
             Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
                 "client_uri": "https://example.com/",
-                "token_endpoint_auth_method": "none",
-                "grant_types": ["urn:ietf:params:oauth:grant-type:device_code", "refresh_token"],
-                "response_types": [],
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["client_credentials"],
             }));
 
         let response = state.request(request).await;
@@ -1267,35 +2473,115 @@ mod tests {
 
         let response: ClientRegistrationResponse = response.json();
         let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
 
-        // Start a device code grant
-        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
-            serde_json::json!({
+        // A proof made for another URL is rejected
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("DPoP", proof("https://example.com/token"))
+            .form(serde_json::json!({
+                "grant_type": "client_credentials",
                 "client_id": client_id,
-                "scope": "openid",
-            }),
-        );
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*"
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidDpopProof);
+
+        // A valid proof binds the access token to its key
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header(
+                "DPoP",
+                proof(state.url_builder.oauth_token_endpoint().as_str()),
+            )
+            .form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*"
+            }));
+
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
 
-        let device_grant: DeviceAuthorizationResponse = response.json();
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.token_type, OAuthAccessTokenType::DPoP);
 
-        // Poll the token endpoint, it should be pending
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(access_token.dpop_jkt, Some(jkt));
+        repo.save().await.unwrap();
+
+        // The bound token can't be used as a bearer token
+        let request = Request::post("/graphql")
+            .bearer(&response.access_token)
+            .json(serde_json::json!({
+                "query": "{ viewer { __typename } }",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_dpop_bound_refresh_token_reuse(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Make DPoP proofs with one of the keys of the key store
+        let key = state
+            .key_store
+            .signing_key_for_algorithm(&JsonWebSignatureAlg::Rs256)
+            .unwrap();
+        let public_jwks = state.key_store.public_jwks();
+        let public_key = public_jwks
+            .iter()
+            .find(|public_key| public_key.kid() == key.kid())
+            .unwrap()
+            .clone();
+        let jkt = public_key.params().thumbprint();
+        let proof = || {
+            let signer = key
+                .params()
+                .signing_key_for_alg(&JsonWebSignatureAlg::Rs256)
+                .unwrap();
+            let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Rs256)
+                .with_typ("dpop+jwt".to_owned())
+                .with_jwk(public_key.clone());
+            let claims = serde_json::json!({
+                "jti": Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng()).to_string(),
+                "htm": "POST",
+                "htu": state.url_builder.oauth_token_endpoint().as_str(),
+                "iat": state.clock.now().timestamp(),
+            });
+            Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
+        // Provision a client
         let request =
-            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
-                "device_code": device_grant.device_code,
-                "client_id": client_id,
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
             }));
+
         let response = state.request(request).await;
-        response.assert_status(StatusCode::FORBIDDEN);
+        response.assert_status(StatusCode::CREATED);
 
-        let ClientError { error, .. } = response.json();
-        assert_eq!(error, ClientErrorCode::AuthorizationPending);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
 
-        // Let's provision a user and create a browser session for them. This part is
-        // hard to test with just HTTP requests, so we'll use the repository
-        // directly.
+        // Provision a user and a session with a token pair bound to the key
         let mut repo = state.repository().await.unwrap();
 
         let user = repo
@@ -1310,46 +2596,211 @@ mod tests {
             .await
             .unwrap();
 
-        // Find the grant
-        let grant = repo
-            .oauth2_device_code_grant()
-            .find_by_user_code(&device_grant.user_code)
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
             .await
             .unwrap()
             .unwrap();
 
-        // And fulfill it
-        let grant = repo
-            .oauth2_device_code_grant()
-            .fulfill(&state.clock, grant, &browser_session)
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
             .await
             .unwrap();
 
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &client,
+            &session,
+            TokenType::AccessToken.generate(&mut state.rng()),
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+            Some(jkt.as_str()),
+            None,
+        )
+        .await
+        .unwrap();
+
         repo.save().await.unwrap();
 
-        // Now call the token endpoint to get an access token.
-        let request =
-            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
-                "device_code": grant.device_code,
-                "client_id": client_id,
+        // Exchange the refresh token with a proof from the key
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("DPoP", proof())
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
             }));
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
 
+        let old_refresh_token = refresh_token;
         let response: AccessTokenResponse = response.json();
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
 
-        // Check that the token is valid
-        assert!(state.is_access_token_valid(&response.access_token).await);
-        // We advertised the refresh token grant type, so we should have a refresh token
-        assert!(response.refresh_token.is_some());
-        // We asked for the openid scope, so we should have an ID token
-        assert!(response.id_token.is_some());
-
-        // Do another grant and make it expire
-        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
-            serde_json::json!({
+        // Replaying the old refresh token without the key is rejected, but doesn't
+        // end the session
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": old_refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidDpopProof);
+
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(session.is_valid());
+        repo.save().await.unwrap();
+
+        // Replaying it with the key is a reuse, which ends the session
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("DPoP", proof())
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": old_refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+            .header("DPoP", proof())
+            .form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_device_code_grant(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "none",
+                "grant_types": ["urn:ietf:params:oauth:grant-type:device_code", "refresh_token"],
+                "response_types": [],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+
+        // Start a device code grant
+        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "scope": "openid",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let device_grant: DeviceAuthorizationResponse = response.json();
+
+        // Poll the token endpoint, it should be pending
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                "device_code": device_grant.device_code,
+                "client_id": client_id,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::AuthorizationPending);
+
+        // Let's provision a user and create a browser session for them. This part is
+        // hard to test with just HTTP requests, so we'll use the repository
+        // directly.
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        // Find the grant
+        let grant = repo
+            .oauth2_device_code_grant()
+            .find_by_user_code(&device_grant.user_code)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // And fulfill it
+        let grant = repo
+            .oauth2_device_code_grant()
+            .fulfill(&state.clock, grant, &browser_session)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Now call the token endpoint to get an access token.
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                "device_code": grant.device_code,
+                "client_id": client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+
+        // Check that the token is valid
+        assert!(state.is_access_token_valid(&response.access_token).await);
+        // We advertised the refresh token grant type, so we should have a refresh token
+        assert!(response.refresh_token.is_some());
+        // We asked for the openid scope, so we should have an ID token
+        assert!(response.id_token.is_some());
+
+        // Do another grant and make it expire
+        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
                 "client_id": client_id,
                 "scope": "openid",
             }),
@@ -1387,6 +2838,46 @@ mod tests {
         let ClientError { error, .. } = response.json();
         assert_eq!(error, ClientErrorCode::ExpiredToken);
 
+        // Do another grant and poll it too often
+        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "scope": "openid",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let device_grant: DeviceAuthorizationResponse = response.json();
+
+        // The first two polls are allowed, to account for network jitter
+        for _ in 0..2 {
+            let request =
+                Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                    "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                    "device_code": device_grant.device_code,
+                    "client_id": client_id,
+                }));
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::FORBIDDEN);
+
+            let ClientError { error, .. } = response.json();
+            assert_eq!(error, ClientErrorCode::AuthorizationPending);
+        }
+
+        // The third one should tell the client to slow down
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                "device_code": device_grant.device_code,
+                "client_id": client_id,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::SlowDown);
+
         // Do another grant and reject it
         let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
             serde_json::json!({
@@ -1433,6 +2924,182 @@ mod tests {
         assert_eq!(error, ClientErrorCode::AccessDenied);
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_ciba_grant(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["urn:openid:params:grant-type:ciba", "refresh_token"],
+                "response_types": [],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        // Let's provision a user and create a browser session for them
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Unknown users are rejected
+        let request = Request::post(mas_router::OAuth2BackchannelAuthenticationEndpoint::PATH)
+            .form(serde_json::json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "openid",
+                "login_hint": "bob",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::UnknownUserId);
+
+        // The openid scope is required
+        let request = Request::post(mas_router::OAuth2BackchannelAuthenticationEndpoint::PATH)
+            .form(serde_json::json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "email",
+                "login_hint": "alice",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // Start a CIBA request, designating the user with their MXID
+        let request = Request::post(mas_router::OAuth2BackchannelAuthenticationEndpoint::PATH)
+            .form(serde_json::json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "openid",
+                "login_hint": "@alice:example.com",
+                "binding_message": "W4SCT",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let ciba_response: BackchannelAuthenticationResponse = response.json();
+        assert_eq!(ciba_response.auth_req_id.len(), 32);
+
+        // Poll the token endpoint, it should be pending
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:openid:params:grant-type:ciba",
+                "auth_req_id": ciba_response.auth_req_id,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::AuthorizationPending);
+
+        // Approve the request. This part is hard to test with just HTTP requests, so
+        // we'll use the repository directly.
+        let mut repo = state.repository().await.unwrap();
+
+        let grant = repo
+            .oauth2_ciba_grant()
+            .find_by_auth_req_id(&ciba_response.auth_req_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(grant.user_id, user.id);
+        assert_eq!(grant.binding_message.as_deref(), Some("W4SCT"));
+
+        repo.oauth2_ciba_grant()
+            .fulfill(&state.clock, grant, &browser_session)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Now call the token endpoint to get an access token
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:openid:params:grant-type:ciba",
+                "auth_req_id": ciba_response.auth_req_id,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(state.is_access_token_valid(&response.access_token).await);
+        assert!(response.refresh_token.is_some());
+        assert!(response.id_token.is_some());
+
+        // The request can't be exchanged twice
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:openid:params:grant-type:ciba",
+                "auth_req_id": ciba_response.auth_req_id,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Start another request and make it expire
+        let request = Request::post(mas_router::OAuth2BackchannelAuthenticationEndpoint::PATH)
+            .form(serde_json::json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "openid",
+                "login_hint": "alice",
+                "requested_expiry": "60",
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let ciba_response: BackchannelAuthenticationResponse = response.json();
+        assert_eq!(ciba_response.expires_in, Duration::try_minutes(1).unwrap());
+
+        state.clock.advance(Duration::try_minutes(2).unwrap());
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:openid:params:grant-type:ciba",
+                "auth_req_id": ciba_response.auth_req_id,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::ExpiredToken);
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unsupported_grant(pool: PgPool) {
         setup();
@@ -1470,4 +3137,223 @@ mod tests {
         let ClientError { error, .. } = response.json();
         assert_eq!(error, ClientErrorCode::UnsupportedGrantType);
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_jwt_bearer_grant(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+
+        // Trust assertions signed with the keys of the key store, both in the
+        // configuration and in the policy
+        state.site_config.jwt_bearer_issuers = vec![JwtBearerIssuer {
+            issuer: "https://hr.example.com".to_owned(),
+            jwks: JwksOrJwksUri::Jwks(state.key_store.public_jwks()),
+        }];
+        state.policy_factory = crate::test_utils::policy_factory(serde_json::json!({
+            "jwt_bearer_issuers": ["https://hr.example.com"],
+        }))
+        .await
+        .unwrap();
+
+        let mut rng = state.rng();
+        let sign = |claims: serde_json::Value| {
+            let key = state
+                .key_store
+                .signing_key_for_algorithm(&JsonWebSignatureAlg::Rs256)
+                .unwrap();
+            let signer = key
+                .params()
+                .signing_key_for_alg(&JsonWebSignatureAlg::Rs256)
+                .unwrap();
+            let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Rs256);
+            Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["urn:ietf:params:oauth:grant-type:jwt-bearer"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        // Provision a user
+        let mut repo = state.repository().await.unwrap();
+        repo.user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let iat = state.clock.now().timestamp();
+        let exp = (state.clock.now() + Duration::try_minutes(5).unwrap()).timestamp();
+        let assertion = sign(serde_json::json!({
+            "iss": "https://hr.example.com",
+            "sub": "alice",
+            "aud": "https://example.com/",
+            "iat": iat,
+            "exp": exp,
+            "jti": "assertion-1",
+        }));
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+                "scope": "urn:matrix:org.matrix.msc2967.client:api:*",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert_eq!(
+            response.scope,
+            Some(
+                "urn:matrix:org.matrix.msc2967.client:api:*"
+                    .parse()
+                    .unwrap()
+            )
+        );
+
+        // The same assertion can't be used twice
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions for unknown users are rejected
+        let assertion = sign(serde_json::json!({
+            "iss": "https://hr.example.com",
+            "sub": "bob",
+            "aud": "https://example.com/",
+            "iat": iat,
+            "exp": exp,
+            "jti": "assertion-2",
+        }));
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions meant for another audience are rejected
+        let assertion = sign(serde_json::json!({
+            "iss": "https://hr.example.com",
+            "sub": "alice",
+            "aud": "https://other.example.com/",
+            "iat": iat,
+            "exp": exp,
+            "jti": "assertion-3",
+        }));
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions from untrusted issuers are rejected
+        let assertion = sign(serde_json::json!({
+            "iss": "https://evil.example.com",
+            "sub": "alice",
+            "aud": "https://example.com/",
+            "iat": iat,
+            "exp": exp,
+            "jti": "assertion-4",
+        }));
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions which are valid for too long are rejected
+        let assertion = sign(serde_json::json!({
+            "iss": "https://hr.example.com",
+            "sub": "alice",
+            "aud": "https://example.com/",
+            "iat": iat,
+            "exp": (state.clock.now() + Duration::try_hours(2).unwrap()).timestamp(),
+            "jti": "assertion-5",
+        }));
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions without an ID are rejected, as they can't be tracked
+        let assertion = sign(serde_json::json!({
+            "iss": "https://hr.example.com",
+            "sub": "alice",
+            "aud": "https://example.com/",
+            "iat": iat,
+            "exp": exp,
+        }));
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "assertion": assertion,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
 }
@@ -101,7 +101,9 @@ pub async fn get(
     State(key_store): State<Keystore>,
     user_authorization: UserAuthorization,
 ) -> Result<Response, RouteError> {
-    let session = user_authorization.protected(&mut repo, &clock).await?;
+    let session = user_authorization
+        .protected(&mut repo, &clock, &url_builder.oidc_userinfo_endpoint())
+        .await?;
 
     // This endpoint requires the `openid` scope.
     if !session.scope.contains("openid") {
@@ -0,0 +1,294 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Short-lived tokens for widgets and embedded integrations
+//!
+//! Clients can exchange their access token for a token restricted to a subset
+//! of its scope, which expires after a few minutes. Those tokens belong to the
+//! session of the client, so they stop working as soon as it ends, and they
+//! can't be used to get other tokens.
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Duration;
+use hyper::StatusCode;
+use mas_axum_utils::{
+    sentry::SentryEventID,
+    user_authorization::{AuthorizationVerificationError, UserAuthorization},
+};
+use mas_data_model::TokenType;
+use mas_router::UrlBuilder;
+use mas_storage::{BoxClock, BoxRepository, BoxRng};
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::AccessTokenResponse,
+    scope::Scope,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{impl_from_error_for_route, BoundActivityTracker};
+
+/// How long the tokens are valid for, unless the client asks for less
+const MAX_TTL: Duration = Duration::minutes(5);
+
+#[derive(Deserialize)]
+pub(crate) struct WidgetTokenRequest {
+    /// The scope the token should be restricted to. It must be a subset of the
+    /// scope of the session.
+    scope: Scope,
+
+    /// How long the token should be valid for, in seconds
+    expires_in: Option<u32>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("failed to authenticate")]
+    AuthorizationVerificationError(
+        #[from] AuthorizationVerificationError<mas_storage::RepositoryError>,
+    ),
+
+    #[error("widget tokens can't be used to get other tokens")]
+    RestrictedToken,
+
+    #[error("the requested scope is empty or not granted to the session")]
+    InvalidScope,
+
+    #[error("the requested lifetime is invalid")]
+    InvalidExpiresIn,
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ClientError::from(ClientErrorCode::ServerError)),
+            )
+                .into_response(),
+
+            Self::AuthorizationVerificationError(e) => e.into_response(),
+
+            Self::RestrictedToken => (
+                StatusCode::FORBIDDEN,
+                Json(
+                    ClientError::from(ClientErrorCode::AccessDenied)
+                        .with_description("Widget tokens can't be used to get other tokens".to_owned()),
+                ),
+            )
+                .into_response(),
+
+            Self::InvalidScope => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidScope).with_description(
+                        "The requested scope must be a non-empty subset of the scope of the session"
+                            .to_owned(),
+                    ),
+                ),
+            )
+                .into_response(),
+
+            Self::InvalidExpiresIn => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRequest)
+                        .with_description("The requested lifetime must be positive".to_owned()),
+                ),
+            )
+                .into_response(),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+#[tracing::instrument(name = "handlers.oauth2.widget_token.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    user_authorization: UserAuthorization<WidgetTokenRequest>,
+) -> Result<Response, RouteError> {
+    let (token, session, request) = user_authorization
+        .protected_form_token(
+            &mut repo,
+            &clock,
+            &url_builder.oauth_widget_token_endpoint(),
+        )
+        .await?;
+
+    // Widget tokens are handed to less trusted content, so they shouldn't be
+    // able to get more tokens
+    if token.is_restricted() {
+        return Err(RouteError::RestrictedToken);
+    }
+
+    if request.scope.is_empty() || !request.scope.is_subset(&session.scope) {
+        return Err(RouteError::InvalidScope);
+    }
+
+    let ttl = match request.expires_in {
+        Some(0) => return Err(RouteError::InvalidExpiresIn),
+        Some(expires_in) => Duration::seconds(expires_in.into()).min(MAX_TTL),
+        None => MAX_TTL,
+    };
+
+    activity_tracker
+        .record_oauth2_session(&clock, &session)
+        .await;
+
+    let access_token_str = TokenType::AccessToken.generate(&mut rng);
+    let access_token = repo
+        .oauth2_access_token()
+        .add_restricted(
+            &mut rng,
+            &clock,
+            &session,
+            access_token_str,
+            ttl,
+            request.scope.clone(),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    tracing::info!(
+        oauth2_session.id = %session.id,
+        access_token.id = %access_token.id,
+        "Issued a widget token"
+    );
+
+    let response = AccessTokenResponse::new(access_token.access_token)
+        .with_expires_in(ttl)
+        .with_scope(request.scope);
+
+    Ok((StatusCode::OK, Json(response)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use oauth2_types::requests::AccessTokenResponse;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    async fn widget_token(
+        state: &TestState,
+        token: &str,
+        form: serde_json::Value,
+    ) -> hyper::Response<String> {
+        let request = Request::post(mas_router::OAuth2WidgetTokenEndpoint::PATH)
+            .bearer(token)
+            .form(form);
+        state.request(request).await
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_widget_token(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state
+            .token_with_scope("urn:mas:admin urn:mas:graphql:*")
+            .await;
+
+        let response = widget_token(
+            &state,
+            &token,
+            serde_json::json!({
+                "scope": "urn:mas:graphql:*",
+                "expires_in": 60,
+            }),
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.expires_in, Some(chrono::Duration::seconds(60)));
+        assert_eq!(
+            response.scope.map(|scope| scope.to_string()),
+            Some("urn:mas:graphql:*".to_owned())
+        );
+        let widget = response.access_token;
+
+        // The parent token can use the admin API, but not the widget token
+        let request = Request::get("/api/admin/v1/users").bearer(&token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let request = Request::get("/api/admin/v1/users").bearer(&widget).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // The widget token can't be used to get other tokens
+        let response = widget_token(
+            &state,
+            &widget,
+            serde_json::json!({ "scope": "urn:mas:graphql:*" }),
+        )
+        .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        // The lifetime is capped
+        let response = widget_token(
+            &state,
+            &token,
+            serde_json::json!({
+                "scope": "urn:mas:graphql:*",
+                "expires_in": 3600,
+            }),
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.expires_in, Some(chrono::Duration::minutes(5)));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_widget_token_invalid(pool: PgPool) {
+        setup();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let token = state.token_with_scope("urn:mas:admin").await;
+
+        // Scopes which were not granted to the session are rejected
+        let response = widget_token(
+            &state,
+            &token,
+            serde_json::json!({ "scope": "urn:mas:admin urn:mas:graphql:*" }),
+        )
+        .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // So is a zero lifetime
+        let response = widget_token(
+            &state,
+            &token,
+            serde_json::json!({ "scope": "urn:mas:admin", "expires_in": 0 }),
+        )
+        .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // And requests with an unknown token
+        let response = widget_token(
+            &state,
+            "invalid",
+            serde_json::json!({ "scope": "urn:mas:admin" }),
+        )
+        .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,406 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Password provider API for Synapse
+//!
+//! This lets Synapse keep handling `/login` while delegating the verification
+//! of the passwords to MAS, through the `matrix-synapse-rest-password-provider`
+//! module. It makes it possible to manage the credentials in MAS before
+//! switching to the full OAuth 2.0 delegation.
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{AuditEventKind, SiteConfig, User};
+use mas_matrix::BoxHomeserverConnection;
+use mas_storage::{
+    audit::AuditEventRepository,
+    user::{UserPasswordRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, Clock,
+};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::{
+    homeserver::user_homeserver, impl_from_error_for_route, passwords::PasswordManager,
+    rate_limit::PasswordCheckLimitedError, Limiter,
+};
+
+#[derive(Deserialize)]
+pub(crate) struct RequestBody {
+    user: RequestUser,
+}
+
+#[derive(Deserialize)]
+struct RequestUser {
+    /// The full Matrix ID of the user
+    id: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct ResponseBody {
+    auth: ResponseAuth,
+}
+
+#[derive(Serialize)]
+struct ResponseAuth {
+    success: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mxid: Option<String>,
+}
+
+impl ResponseBody {
+    fn success(mxid: String) -> Self {
+        Self {
+            auth: ResponseAuth {
+                success: true,
+                mxid: Some(mxid),
+            },
+        }
+    }
+
+    fn failure() -> Self {
+        Self {
+            auth: ResponseAuth {
+                success: false,
+                mxid: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error(transparent)]
+    RateLimited(#[from] PasswordCheckLimitedError),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let status = match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        };
+
+        (
+            SentryEventID::from(event_id),
+            status,
+            Json(ResponseBody::failure()),
+        )
+            .into_response()
+    }
+}
+
+/// Why the credentials were rejected
+#[derive(Debug, Error)]
+enum CheckError {
+    #[error("password login is disabled")]
+    PasswordLoginDisabled,
+
+    #[error("user not found")]
+    UserNotFound,
+
+    #[error("user has no password")]
+    NoPassword,
+
+    #[error("password verification failed")]
+    PasswordVerificationFailed(#[source] anyhow::Error),
+
+    #[error(transparent)]
+    Route(#[from] RouteError),
+}
+
+impl From<mas_storage::RepositoryError> for CheckError {
+    fn from(e: mas_storage::RepositoryError) -> Self {
+        Self::Route(e.into())
+    }
+}
+
+#[tracing::instrument(name = "handlers.password_provider.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(password_manager): State<PasswordManager>,
+    State(limiter): State<Limiter>,
+    State(homeserver): State<BoxHomeserverConnection>,
+    State(site_config): State<SiteConfig>,
+    mut repo: BoxRepository,
+    Json(input): Json<RequestBody>,
+) -> Result<Response, RouteError> {
+    let RequestUser { id, password } = input.user;
+
+    let res = check_credentials(
+        &mut rng,
+        &clock,
+        &password_manager,
+        &limiter,
+        &mut repo,
+        &homeserver,
+        &site_config,
+        &id,
+        password,
+    )
+    .await;
+
+    // The requests come from the homeserver, so we don't know the IP address of
+    // the user
+    match res {
+        Ok(user) => {
+            repo.audit_event()
+                .add(
+                    &mut rng,
+                    &clock,
+                    AuditEventKind::Login,
+                    Some(user.id),
+                    Some(user.id),
+                    None,
+                    None,
+                    serde_json::json!({
+                        "method": "password_provider",
+                    }),
+                )
+                .await?;
+            repo.save().await?;
+
+            tracing::info!(user.id = %user.id, "Password checked on behalf of the homeserver");
+
+            Ok(Json(ResponseBody::success(id)).into_response())
+        }
+
+        Err(CheckError::Route(RouteError::Internal(e))) => Err(RouteError::Internal(e)),
+
+        Err(e) => {
+            let target = match split_mxid(&id) {
+                Some((localpart, server_name)) => {
+                    repo.user()
+                        .find_by_username(user_homeserver(&site_config, server_name), localpart)
+                        .await?
+                }
+                None => None,
+            };
+
+            repo.audit_event()
+                .add(
+                    &mut rng,
+                    &clock,
+                    AuditEventKind::LoginFailed,
+                    None,
+                    target.map(|user| user.id),
+                    None,
+                    None,
+                    serde_json::json!({
+                        "method": "password_provider",
+                        "username": id,
+                        "reason": e.to_string(),
+                    }),
+                )
+                .await?;
+            repo.save().await?;
+
+            if let CheckError::Route(e) = e {
+                return Err(e);
+            }
+
+            tracing::info!(error = &e as &dyn std::error::Error, "Rejected credentials");
+
+            Ok(Json(ResponseBody::failure()).into_response())
+        }
+    }
+}
+
+/// Split a Matrix ID into its localpart and server name
+fn split_mxid(mxid: &str) -> Option<(&str, &str)> {
+    mxid.strip_prefix('@')?.split_once(':')
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn check_credentials(
+    mut rng: &mut (impl RngCore + CryptoRng + Send),
+    clock: &impl Clock,
+    password_manager: &PasswordManager,
+    limiter: &Limiter,
+    repo: &mut BoxRepository,
+    homeserver: &BoxHomeserverConnection,
+    site_config: &SiteConfig,
+    mxid: &str,
+    password: String,
+) -> Result<User, CheckError> {
+    if !password_manager.is_enabled() {
+        return Err(CheckError::PasswordLoginDisabled);
+    }
+
+    let (localpart, server_name) = split_mxid(mxid).ok_or(CheckError::UserNotFound)?;
+
+    if !site_config.is_password_login_allowed(localpart) {
+        return Err(CheckError::PasswordLoginDisabled);
+    }
+
+    // Find the user. Bots can't log in with a password
+    let user = repo
+        .user()
+        .find_by_username(user_homeserver(site_config, server_name), localpart)
+        .await?
+        .filter(|user| user.is_valid() && !user.is_bot)
+        .ok_or(CheckError::UserNotFound)?;
+
+    // The user must belong to the homeserver in the Matrix ID
+    if homeserver.mxid_on(user.homeserver.as_deref(), &user.username) != mxid {
+        return Err(CheckError::UserNotFound);
+    }
+
+    limiter
+        .check_password_for_user(&user)
+        .map_err(RouteError::from)?;
+
+    let password = Zeroizing::new(password.into_bytes());
+
+    // Users who don't have a password yet may still have one in Synapse
+    let user_password = match repo.user_password().active(&user).await? {
+        Some(user_password) => user_password,
+        None => password_manager
+            .import_from_synapse(&mut rng, clock, repo, &user, password.clone())
+            .await
+            .map_err(|e| RouteError::Internal(e.into()))?
+            .ok_or(CheckError::NoPassword)?,
+    };
+
+    let new_password_hash = password_manager
+        .verify_and_upgrade(
+            &mut rng,
+            user_password.version,
+            password,
+            user_password.hashed_password.clone(),
+        )
+        .await
+        .map_err(CheckError::PasswordVerificationFailed)?;
+
+    if let Some((version, hashed_password)) = new_password_hash {
+        // Save the upgraded password if needed
+        repo.user_password()
+            .add(
+                &mut rng,
+                clock,
+                &user,
+                version,
+                hashed_password,
+                Some(&user_password),
+            )
+            .await?;
+    }
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use mas_storage::{audit::AuditEventFilter, Pagination};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    async fn check(state: &TestState, id: &str, password: &str) -> hyper::Response<String> {
+        let request = Request::post(mas_router::PasswordProviderCheckCredentials::PATH).json(
+            serde_json::json!({
+                "user": {
+                    "id": id,
+                    "password": password,
+                },
+            }),
+        );
+        state.request(request).await
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_check_credentials(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(
+                &mut state.rng(),
+                Zeroizing::new("password".to_owned().into_bytes()),
+            )
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let response = check(&state, "@alice:example.com", "password").await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "auth": {
+                    "success": true,
+                    "mxid": "@alice:example.com",
+                },
+            })
+        );
+
+        let failure = serde_json::json!({ "auth": { "success": false } });
+
+        // Wrong password
+        let response = check(&state, "@alice:example.com", "hunter2").await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body, failure);
+
+        // Wrong homeserver
+        let response = check(&state, "@alice:other.com", "password").await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body, failure);
+
+        // Unknown user
+        let response = check(&state, "@bob:example.com", "password").await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body, failure);
+
+        // Both the successful and the failed attempts are in the audit log
+        let mut repo = state.repository().await.unwrap();
+        let filter = AuditEventFilter::new().for_user(&user);
+        let events = repo
+            .audit_event()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(events.edges.len(), 3);
+    }
+}
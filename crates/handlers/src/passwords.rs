@@ -9,8 +9,11 @@ use std::{collections::HashMap, sync::Arc};
 use anyhow::Context;
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use futures_util::future::OptionFuture;
+use mas_data_model::{Password, User};
+use mas_storage::{user::UserPasswordRepository, Clock, RepositoryAccess};
 use pbkdf2::Pbkdf2;
 use rand::{CryptoRng, Rng, RngCore, SeedableRng};
+use sqlx::PgPool;
 use thiserror::Error;
 use zeroize::Zeroizing;
 use zxcvbn::zxcvbn;
@@ -24,6 +27,7 @@ pub struct PasswordManagerDisabledError;
 #[derive(Clone)]
 pub struct PasswordManager {
     inner: Option<Arc<InnerPasswordManager>>,
+    synapse_fallback: Option<Arc<SynapsePasswordFallback>>,
 }
 
 struct InnerPasswordManager {
@@ -78,13 +82,25 @@ impl PasswordManager {
                 current_version,
                 other_hashers,
             })),
+            synapse_fallback: None,
         })
     }
 
     /// Creates a new disabled password manager
     #[must_use]
     pub const fn disabled() -> Self {
-        Self { inner: None }
+        Self {
+            inner: None,
+            synapse_fallback: None,
+        }
+    }
+
+    /// Verify the passwords of users which don't have one yet against the
+    /// Synapse database, see [`Self::import_from_synapse`]
+    #[must_use]
+    pub fn with_synapse_fallback(mut self, fallback: SynapsePasswordFallback) -> Self {
+        self.synapse_fallback = Some(Arc::new(fallback));
+        self
     }
 
     /// Checks if the password manager is enabled or not
@@ -215,6 +231,106 @@ impl PasswordManager {
     }
 }
 
+impl PasswordManager {
+    /// Verify the password of a user which doesn't have one yet against the
+    /// Synapse database, and import its hash if it matches.
+    ///
+    /// This lets users log in with their Synapse password while migrating
+    /// from Synapse, without migrating all the password hashes up front.
+    ///
+    /// Returns `None` if the Synapse fallback is not set up, if the user has no
+    /// password in Synapse, or if the password doesn't match.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Synapse database or the repository fails
+    pub async fn import_from_synapse(
+        &self,
+        rng: &mut (impl RngCore + CryptoRng + Send),
+        clock: &impl Clock,
+        repo: &mut impl RepositoryAccess,
+        user: &User,
+        password: Zeroizing<Vec<u8>>,
+    ) -> Result<Option<Password>, anyhow::Error> {
+        let Some(fallback) = &self.synapse_fallback else {
+            return Ok(None);
+        };
+
+        // Only users of the main homeserver can have a password in Synapse
+        if user.homeserver.is_some() {
+            return Ok(None);
+        }
+
+        let Some(hashed_password) = fallback.lookup(&user.username).await? else {
+            return Ok(None);
+        };
+
+        if self
+            .verify(fallback.version, password, hashed_password.clone())
+            .await
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        let password = repo
+            .user_password()
+            .add(rng, clock, user, fallback.version, hashed_password, None)
+            .await?;
+
+        tracing::info!(%user.id, %user.username, "Imported the password of the user from Synapse");
+
+        Ok(Some(password))
+    }
+}
+
+/// Where to look up the passwords of users which don't have one yet, while
+/// migrating from Synapse
+pub struct SynapsePasswordFallback {
+    pool: PgPool,
+    server_name: String,
+    version: SchemeVersion,
+}
+
+impl SynapsePasswordFallback {
+    /// Creates a new [`SynapsePasswordFallback`]
+    ///
+    /// # Parameters
+    ///
+    /// * `pool`: A connection pool to the Synapse database
+    /// * `server_name`: The server name of the homeserver
+    /// * `version`: The version of the hashing scheme matching the hashes in
+    ///   the Synapse database
+    #[must_use]
+    pub fn new(pool: PgPool, server_name: String, version: SchemeVersion) -> Self {
+        Self {
+            pool,
+            server_name,
+            version,
+        }
+    }
+
+    #[tracing::instrument(name = "passwords.synapse_fallback.lookup", skip_all)]
+    async fn lookup(&self, localpart: &str) -> Result<Option<String>, sqlx::Error> {
+        let mxid = format!("@{localpart}:{}", self.server_name);
+        let hashed_password: Option<Option<String>> = sqlx::query_scalar(
+            r"
+                SELECT password_hash
+                FROM users
+                WHERE name = $1
+                  AND deactivated = 0
+            ",
+        )
+        .bind(mxid)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(hashed_password
+            .flatten()
+            .filter(|hashed_password| !hashed_password.is_empty()))
+    }
+}
+
 /// A hashing scheme, with an optional pepper
 pub struct Hasher {
     algorithm: Algorithm,
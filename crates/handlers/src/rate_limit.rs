@@ -4,11 +4,20 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::{net::IpAddr, sync::Arc, time::Duration};
-
-use governor::{clock::QuantaClock, state::keyed::DashMapStateStore, RateLimiter};
+use std::{net::IpAddr, num::NonZeroU32, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use governor::{
+    clock::{Clock as _, QuantaClock, QuantaInstant, Reference},
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::keyed::DashMapStateStore,
+    Quota, RateLimiter,
+};
 use mas_config::RateLimitingConfig;
 use mas_data_model::User;
+use mas_storage::Clock;
 use ulid::Ulid;
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -35,6 +44,22 @@ pub enum RegistrationLimitedError {
     Requester(RequesterFingerprint),
 }
 
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Device code grant {0} was polled too often")]
+pub struct DeviceCodePollingLimitedError(Ulid);
+
+/// The minimum interval between two polls of the token endpoint with the same
+/// device code, as advertised in the device authorization response
+pub const DEVICE_CODE_POLLING_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("CIBA grant {0} was polled too often")]
+pub struct CibaPollingLimitedError(Ulid);
+
+/// The minimum interval between two polls of the token endpoint with the same
+/// CIBA request, as advertised in the backchannel authentication response
+pub const CIBA_POLLING_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Key used to rate limit requests per requester
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct RequesterFingerprint {
@@ -63,13 +88,68 @@ impl RequesterFingerprint {
     }
 }
 
+/// The clock the rate limiters measure time with
+///
+/// It is monotonic by default, but can follow a [`Clock`] instead, so that
+/// tests can advance time deterministically.
+#[derive(Clone)]
+enum LimiterClock {
+    Monotonic {
+        clock: QuantaClock,
+        start: QuantaInstant,
+    },
+    Custom {
+        clock: Arc<dyn Clock + Send>,
+        start: DateTime<Utc>,
+    },
+}
+
+impl LimiterClock {
+    fn monotonic() -> Self {
+        let clock = QuantaClock::default();
+        let start = clock.now();
+        Self::Monotonic { clock, start }
+    }
+
+    fn custom(clock: Arc<dyn Clock + Send>) -> Self {
+        let start = clock.now();
+        Self::Custom { clock, start }
+    }
+}
+
+impl std::fmt::Debug for LimiterClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Monotonic { .. } => f.write_str("Monotonic"),
+            Self::Custom { .. } => f.write_str("Custom"),
+        }
+    }
+}
+
+impl governor::clock::Clock for LimiterClock {
+    type Instant = Nanos;
+
+    fn now(&self) -> Nanos {
+        match self {
+            Self::Monotonic { clock, start } => clock.now().duration_since(*start),
+            Self::Custom { clock, start } => {
+                // Time going backwards is treated as no time passing
+                let elapsed = (clock.now() - *start).to_std().unwrap_or_default();
+                Nanos::from(elapsed)
+            }
+        }
+    }
+}
+
 /// Rate limiters for the different operations
 #[derive(Debug, Clone)]
 pub struct Limiter {
-    inner: Arc<LimiterInner>,
+    inner: Arc<ArcSwap<LimiterInner>>,
+    clock: LimiterClock,
 }
 
-type KeyedRateLimiter<K> = RateLimiter<K, DashMapStateStore<K>, QuantaClock>;
+type KeyedRateLimiter<K> =
+    RateLimiter<K, DashMapStateStore<K>, LimiterClock, NoOpMiddleware<Nanos>>;
 
 #[derive(Debug)]
 struct LimiterInner {
@@ -78,20 +158,43 @@ struct LimiterInner {
     password_check_for_requester: KeyedRateLimiter<RequesterFingerprint>,
     password_check_for_user: KeyedRateLimiter<Ulid>,
     registration_per_requester: KeyedRateLimiter<RequesterFingerprint>,
+    device_code_polling: KeyedRateLimiter<Ulid>,
+    ciba_polling: KeyedRateLimiter<Ulid>,
 }
 
 impl LimiterInner {
-    fn new(config: &RateLimitingConfig) -> Option<Self> {
+    fn new(config: &RateLimitingConfig, clock: &LimiterClock) -> Option<Self> {
         Some(Self {
-            account_recovery_per_requester: RateLimiter::keyed(
+            account_recovery_per_requester: RateLimiter::dashmap_with_clock(
                 config.account_recovery.per_ip.to_quota()?,
+                clock,
             ),
-            account_recovery_per_email: RateLimiter::keyed(
+            account_recovery_per_email: RateLimiter::dashmap_with_clock(
                 config.account_recovery.per_address.to_quota()?,
+                clock,
+            ),
+            password_check_for_requester: RateLimiter::dashmap_with_clock(
+                config.login.per_ip.to_quota()?,
+                clock,
+            ),
+            password_check_for_user: RateLimiter::dashmap_with_clock(
+                config.login.per_account.to_quota()?,
+                clock,
+            ),
+            registration_per_requester: RateLimiter::dashmap_with_clock(
+                config.registration.to_quota()?,
+                clock,
+            ),
+            // Allow a burst of two polls, so that clients polling at exactly the
+            // advertised interval don't get rejected because of network jitter
+            device_code_polling: RateLimiter::dashmap_with_clock(
+                Quota::with_period(DEVICE_CODE_POLLING_INTERVAL)?.allow_burst(NonZeroU32::new(2)?),
+                clock,
+            ),
+            ciba_polling: RateLimiter::dashmap_with_clock(
+                Quota::with_period(CIBA_POLLING_INTERVAL)?.allow_burst(NonZeroU32::new(2)?),
+                clock,
             ),
-            password_check_for_requester: RateLimiter::keyed(config.login.per_ip.to_quota()?),
-            password_check_for_user: RateLimiter::keyed(config.login.per_account.to_quota()?),
-            registration_per_requester: RateLimiter::keyed(config.registration.to_quota()?),
         })
     }
 }
@@ -103,11 +206,43 @@ impl Limiter {
     /// (This should not happen if the config was validated, though.)
     #[must_use]
     pub fn new(config: &RateLimitingConfig) -> Option<Self> {
+        Self::from_limiter_clock(config, LimiterClock::monotonic())
+    }
+
+    /// Creates a new `Limiter` based on a `RateLimitingConfig`, which
+    /// measures time with the given [`Clock`] instead of a monotonic clock.
+    ///
+    /// This is useful in tests, to advance time deterministically.
+    ///
+    /// If the config is not valid, returns `None`.
+    #[must_use]
+    pub fn with_clock(config: &RateLimitingConfig, clock: Arc<dyn Clock + Send>) -> Option<Self> {
+        Self::from_limiter_clock(config, LimiterClock::custom(clock))
+    }
+
+    fn from_limiter_clock(config: &RateLimitingConfig, clock: LimiterClock) -> Option<Self> {
+        let inner = LimiterInner::new(config, &clock)?;
         Some(Self {
-            inner: Arc::new(LimiterInner::new(config)?),
+            inner: Arc::new(ArcSwap::from_pointee(inner)),
+            clock,
         })
     }
 
+    /// Replace the rate limiters with ones built from a new
+    /// `RateLimitingConfig`
+    ///
+    /// This resets the state of all the rate limiters. If the config is not
+    /// valid, the current rate limiters are kept and this returns `false`.
+    #[must_use]
+    pub fn reload(&self, config: &RateLimitingConfig) -> bool {
+        let Some(inner) = LimiterInner::new(config, &self.clock) else {
+            return false;
+        };
+
+        self.inner.store(Arc::new(inner));
+        true
+    }
+
     /// Start the rate limiter housekeeping task
     ///
     /// This task will periodically remove old entries from the rate limiters,
@@ -122,11 +257,16 @@ impl Limiter {
 
             loop {
                 // Call the retain_recent method on each rate limiter
-                this.inner.account_recovery_per_email.retain_recent();
-                this.inner.account_recovery_per_requester.retain_recent();
-                this.inner.password_check_for_requester.retain_recent();
-                this.inner.password_check_for_user.retain_recent();
-                this.inner.registration_per_requester.retain_recent();
+                {
+                    let inner = this.inner.load();
+                    inner.account_recovery_per_email.retain_recent();
+                    inner.account_recovery_per_requester.retain_recent();
+                    inner.password_check_for_requester.retain_recent();
+                    inner.password_check_for_user.retain_recent();
+                    inner.registration_per_requester.retain_recent();
+                    inner.device_code_polling.retain_recent();
+                    inner.ciba_polling.retain_recent();
+                }
 
                 interval.tick().await;
             }
@@ -144,6 +284,7 @@ impl Limiter {
         email_address: &str,
     ) -> Result<(), AccountRecoveryLimitedError> {
         self.inner
+            .load()
             .account_recovery_per_requester
             .check_key(&requester)
             .map_err(|_| AccountRecoveryLimitedError::Requester(requester))?;
@@ -153,6 +294,7 @@ impl Limiter {
         // A case-folding transformation may be more proper.
         let canonical_email = email_address.to_lowercase();
         self.inner
+            .load()
             .account_recovery_per_email
             .check_key(&canonical_email)
             .map_err(|_| AccountRecoveryLimitedError::Email(canonical_email))?;
@@ -171,11 +313,32 @@ impl Limiter {
         user: &User,
     ) -> Result<(), PasswordCheckLimitedError> {
         self.inner
+            .load()
             .password_check_for_requester
             .check_key(&key)
             .map_err(|_| PasswordCheckLimitedError::Requester(key))?;
 
         self.inner
+            .load()
+            .password_check_for_user
+            .check_key(&user.id)
+            .map_err(|_| PasswordCheckLimitedError::User(user.id))?;
+
+        Ok(())
+    }
+
+    /// Check if a password check can be performed on behalf of the
+    /// homeserver
+    ///
+    /// All those checks come from the homeserver, so only the per-user limit
+    /// applies.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the operation is rate limited
+    pub fn check_password_for_user(&self, user: &User) -> Result<(), PasswordCheckLimitedError> {
+        self.inner
+            .load()
             .password_check_for_user
             .check_key(&user.id)
             .map_err(|_| PasswordCheckLimitedError::User(user.id))?;
@@ -193,12 +356,46 @@ impl Limiter {
         requester: RequesterFingerprint,
     ) -> Result<(), RegistrationLimitedError> {
         self.inner
+            .load()
             .registration_per_requester
             .check_key(&requester)
             .map_err(|_| RegistrationLimitedError::Requester(requester))?;
 
         Ok(())
     }
+
+    /// Check if the token endpoint can be polled with a device code grant
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device code grant is polled too often
+    pub fn check_device_code_polling(
+        &self,
+        device_code_grant_id: Ulid,
+    ) -> Result<(), DeviceCodePollingLimitedError> {
+        self.inner
+            .load()
+            .device_code_polling
+            .check_key(&device_code_grant_id)
+            .map_err(|_| DeviceCodePollingLimitedError(device_code_grant_id))?;
+
+        Ok(())
+    }
+
+    /// Check if the token endpoint can be polled with a CIBA grant
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CIBA grant is polled too often
+    pub fn check_ciba_polling(&self, ciba_grant_id: Ulid) -> Result<(), CibaPollingLimitedError> {
+        self.inner
+            .load()
+            .ciba_polling
+            .check_key(&ciba_grant_id)
+            .map_err(|_| CibaPollingLimitedError(ciba_grant_id))?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -231,6 +428,12 @@ mod tests {
             created_at: now,
             locked_at: None,
             can_request_admin: false,
+            legal_hold_at: None,
+            homeserver: None,
+            is_bot: false,
+            bot_owner_id: None,
+            is_guest: false,
+            deletion_scheduled_at: None,
         };
 
         let bob = User {
@@ -241,6 +444,12 @@ mod tests {
             created_at: now,
             locked_at: None,
             can_request_admin: false,
+            legal_hold_at: None,
+            homeserver: None,
+            is_bot: false,
+            bot_owner_id: None,
+            is_guest: false,
+            deletion_scheduled_at: None,
         };
 
         // Three times the same IP address should be allowed
@@ -274,4 +483,53 @@ mod tests {
         // The other account isn't rate-limited
         assert!(limiter.check_password(requesters[603], &bob).is_ok());
     }
+
+    #[test]
+    fn test_limiter_reload() {
+        let limiter = Limiter::new(&RateLimitingConfig::default()).unwrap();
+        let requester = RequesterFingerprint::new([192, 0, 2, 1].into());
+
+        // The default configuration allows three registrations in a burst
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_err());
+
+        // Allow a bigger burst, which also resets the state of the limiters
+        let mut config = RateLimitingConfig::default();
+        config.registration.burst = NonZeroU32::new(5).unwrap();
+        assert!(limiter.reload(&config));
+
+        // The clones share the new limiters
+        let clone = limiter.clone();
+        for _ in 0..5 {
+            assert!(clone.check_registration(requester).is_ok());
+        }
+        assert!(limiter.check_registration(requester).is_err());
+    }
+
+    #[test]
+    fn test_limiter_with_clock() {
+        let clock = Arc::new(MockClock::default());
+        let limiter = Limiter::with_clock(&RateLimitingConfig::default(), clock.clone()).unwrap();
+        let requester = RequesterFingerprint::new([192, 0, 2, 1].into());
+
+        // Exhaust the burst of registrations
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_err());
+
+        // One registration is allowed again once the clock moves forward
+        clock.advance(chrono::Duration::try_minutes(30).unwrap());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_err());
+
+        // The reloaded limiters keep using the same clock
+        assert!(limiter.reload(&RateLimitingConfig::default()));
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_ok());
+        assert!(limiter.check_registration(requester).is_err());
+    }
 }
@@ -0,0 +1,126 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Per-user session limits
+//!
+//! If a session limit is configured, users can only have so many active OAuth
+//! 2.0 and compatibility sessions at the same time. When a user who reached the
+//! limit starts a new session, depending on the configuration, either the new
+//! session is refused, or the least recently active session of the user is
+//! ended, and its device deleted from the homeserver, to make room for it.
+
+use mas_data_model::{AuditEventKind, SessionLimitAction, SiteConfig, User};
+use mas_storage::{
+    app_session::{AppSession, AppSessionFilter, AppSessionRepository},
+    audit::AuditEventRepository,
+    compat::CompatSessionRepository,
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
+    oauth2::OAuth2SessionRepository,
+    Clock, RepositoryAccess,
+};
+use rand::RngCore;
+
+/// Make sure the user can start a new session, ending their least recently
+/// active sessions if the limit is configured to do so
+///
+/// Returns `false` if the user reached the maximum number of sessions and the
+/// new session should be refused.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn make_room_for_session<R: RepositoryAccess>(
+    rng: &mut (impl RngCore + Send),
+    clock: &dyn Clock,
+    repo: &mut R,
+    site_config: &SiteConfig,
+    user: &User,
+) -> Result<bool, R::Error> {
+    let Some(limit) = site_config.session_limit else {
+        return Ok(true);
+    };
+
+    let filter = AppSessionFilter::new().for_user(user).active_only();
+    let mut count = repo.app_session().count(filter).await?;
+    if count < limit.max_sessions {
+        return Ok(true);
+    }
+
+    if limit.action == SessionLimitAction::Refuse {
+        return Ok(false);
+    }
+
+    while count >= limit.max_sessions {
+        let Some(session) = repo
+            .app_session()
+            .find_least_recently_active(filter)
+            .await?
+        else {
+            break;
+        };
+
+        let (session_type, session_id, details) = match session {
+            AppSession::Compat(session) => {
+                let session = repo.compat_session().finish(clock, *session).await?;
+                (
+                    "compat",
+                    session.id,
+                    serde_json::json!({
+                        "compat_session_id": session.id.to_string(),
+                        "reason": "session_limit",
+                    }),
+                )
+            }
+            AppSession::OAuth2(session) => {
+                let session = repo.oauth2_session().finish(clock, *session).await?;
+                (
+                    "oauth2",
+                    session.id,
+                    serde_json::json!({
+                        "ended_oauth2_session_id": session.id.to_string(),
+                        "reason": "session_limit",
+                    }),
+                )
+            }
+        };
+
+        tracing::info!(
+            user.id = %user.id,
+            session.id = %session_id,
+            "Ending the least recently active session of the user to respect the session limit"
+        );
+
+        repo.audit_event()
+            .add(
+                rng,
+                clock,
+                AuditEventKind::SessionEnded,
+                None,
+                Some(user.id),
+                None,
+                None,
+                details,
+            )
+            .await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                rng,
+                clock,
+                user,
+                session_type,
+                session_id,
+            ))
+            .await?;
+
+        count -= 1;
+    }
+
+    // Schedule a job to sync the devices of the user with the homeserver, which
+    // deletes the devices of the sessions we just ended
+    repo.job().schedule_job(SyncDevicesJob::new(user)).await?;
+
+    Ok(true)
+}
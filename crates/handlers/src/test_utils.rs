@@ -5,7 +5,9 @@
 // Please see LICENSE in the repository root for full details.
 
 use std::{
+    collections::BTreeMap,
     convert::Infallible,
+    net::IpAddr,
     sync::{Arc, Mutex, RwLock},
     task::{Context, Poll},
 };
@@ -30,13 +32,14 @@ use mas_axum_utils::{
     ErrorWrapper,
 };
 use mas_config::RateLimitingConfig;
-use mas_data_model::SiteConfig;
+use mas_data_model::{RedirectUriRules, SiteConfig};
+use mas_email::{MailTransport, Mailer};
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
 use mas_matrix::{BoxHomeserverConnection, HomeserverConnection, MockHomeserverConnection};
 use mas_policy::{InstantiateError, Policy, PolicyFactory};
 use mas_router::{SimpleRoute, UrlBuilder};
-use mas_storage::{clock::MockClock, BoxClock, BoxRepository, BoxRng};
+use mas_storage::{clock::MockClock, BoxClock, BoxRepository, BoxRng, ReadOnlyRepository};
 use mas_storage_pg::{DatabaseError, PgRepository};
 use mas_templates::{SiteConfigExt, Templates};
 use oauth2_types::{registration::ClientRegistrationResponse, requests::AccessTokenResponse};
@@ -55,7 +58,7 @@ use crate::{
     graphql,
     passwords::{Hasher, PasswordManager},
     upstream_oauth2::cache::MetadataCache,
-    ActivityTracker, BoundActivityTracker, Limiter, RequesterFingerprint,
+    ActivityTracker, BoundActivityTracker, ClientLogoCache, Limiter, RequesterFingerprint,
 };
 
 /// Setup rustcrypto and tracing for tests.
@@ -83,6 +86,8 @@ pub(crate) async fn policy_factory(
         client_registration: "client_registration/violation".to_owned(),
         authorization_grant: "authorization_grant/violation".to_owned(),
         email: "email/violation".to_owned(),
+        impersonation: "impersonation/violation".to_owned(),
+        kiosk: "kiosk/violation".to_owned(),
     };
 
     let policy_factory = PolicyFactory::load(file, data, entrypoints).await?;
@@ -97,6 +102,8 @@ pub(crate) struct TestState {
     pub key_store: Keystore,
     pub cookie_manager: CookieManager,
     pub metadata_cache: MetadataCache,
+    pub client_logo_cache: ClientLogoCache,
+    pub mailer: Mailer,
     pub encrypter: Encrypter,
     pub url_builder: UrlBuilder,
     pub homeserver_connection: Arc<MockHomeserverConnection>,
@@ -125,19 +132,56 @@ fn workspace_root() -> camino::Utf8PathBuf {
 pub fn test_site_config() -> SiteConfig {
     SiteConfig {
         access_token_ttl: Duration::try_minutes(5).unwrap(),
+        refresh_token_ttl: None,
+        session_ttl: None,
+        session_inactivity_ttl: None,
+        compat_session_ttl: None,
+        compat_session_inactivity_ttl: None,
+        browser_session_ttl: None,
+        browser_session_inactivity_ttl: None,
         compat_token_ttl: Duration::try_minutes(5).unwrap(),
         server_name: "example.com".to_owned(),
         policy_uri: Some("https://example.com/policy".parse().unwrap()),
         tos_uri: Some("https://example.com/tos".parse().unwrap()),
+        terms_acceptance_required: false,
         imprint: None,
+        service_name: None,
+        logo_uri: None,
+        theme_colors: BTreeMap::new(),
         password_login_enabled: true,
+        password_login_exceptions: Vec::new(),
         password_registration_enabled: true,
+        guest_registration_enabled: false,
+        guest_inactivity_ttl: Duration::try_days(7).unwrap(),
         email_change_allowed: true,
         displayname_change_allowed: true,
         password_change_allowed: true,
         account_recovery_allowed: true,
+        admin_elevation_ttl: None,
+        consent_ttl: None,
+        activity_digest_interval: None,
+        account_deletion_grace_period: None,
+        new_device_emails: false,
+        session_limit: None,
         captcha: None,
         minimum_password_complexity: 1,
+        jwt_bearer_issuers: Vec::new(),
+        compat_deprecation: None,
+        compat_disabled_login_types: Vec::new(),
+        require_pushed_authorization_requests: false,
+        notify_refresh_token_reuse: false,
+        browser_session_binding_ttl: None,
+        impersonation_ttl: None,
+        trusted_browser_ttl: None,
+        kiosk_session_ttl: Duration::try_hours(1).unwrap(),
+        kiosk_session_inactivity_ttl: Duration::try_minutes(5).unwrap(),
+        rendezvous_ttl: None,
+        introspection_grace_period: Duration::zero(),
+        introspection_restrictions: Vec::new(),
+        additional_homeservers: Vec::new(),
+        custom_scopes: Vec::new(),
+        redirect_uri_rules: RedirectUriRules::default(),
+        appservices: Vec::new(),
     }
 }
 
@@ -161,9 +205,12 @@ impl TestState {
 
         let templates = Templates::load(
             workspace_root.join("templates"),
+            None,
             url_builder.clone(),
             workspace_root.join("frontend/dist/manifest.json"),
             workspace_root.join("translations"),
+            None,
+            BTreeMap::new(),
             site_config.templates_branding(),
             site_config.templates_features(),
         )
@@ -181,6 +228,14 @@ impl TestState {
         let cookie_manager = CookieManager::derive_from(url_builder.http_base(), &[0x42; 32]);
 
         let metadata_cache = MetadataCache::new();
+        let client_logo_cache = ClientLogoCache::new();
+
+        let mailer = Mailer::new(
+            templates.clone(),
+            MailTransport::blackhole(),
+            "Matrix Authentication Service <noreply@example.com>".parse()?,
+            "Matrix Authentication Service <noreply@example.com>".parse()?,
+        );
 
         let password_manager = if site_config.password_login_enabled {
             PasswordManager::new(
@@ -210,9 +265,10 @@ impl TestState {
             clock: Arc::clone(&clock),
             password_manager: password_manager.clone(),
         };
-        let state: crate::graphql::BoxState = Box::new(graphql_state);
+        let state: crate::graphql::SharedState = Arc::new(graphql_state);
+        let loaders = crate::graphql::Loaders::new(&state);
 
-        let graphql_schema = graphql::schema_builder().data(state).finish();
+        let graphql_schema = graphql::schema_builder().data(state).data(loaders).finish();
 
         let activity_tracker = ActivityTracker::new(
             pool.clone(),
@@ -221,7 +277,7 @@ impl TestState {
             shutdown_token.child_token(),
         );
 
-        let limiter = Limiter::new(&RateLimitingConfig::default()).unwrap();
+        let limiter = Limiter::with_clock(&RateLimitingConfig::default(), clock.clone()).unwrap();
 
         Ok(Self {
             pool,
@@ -229,6 +285,8 @@ impl TestState {
             key_store,
             cookie_manager,
             metadata_cache,
+            client_logo_cache,
+            mailer,
             encrypter,
             url_builder,
             homeserver_connection,
@@ -255,7 +313,8 @@ impl TestState {
         let app = crate::healthcheck_router()
             .merge(crate::discovery_router())
             .merge(crate::api_router())
-            .merge(crate::compat_router())
+            .merge(crate::compat_router(&self.site_config))
+            .merge(crate::password_provider_router())
             .merge(crate::human_router(self.templates.clone()))
             // We enable undocumented_oauth2_access for the tests, as it is easier to query the API
             // with it
@@ -301,11 +360,12 @@ impl TestState {
         let client_id = response.client_id;
         let client_secret = response.client_secret.expect("to have a client secret");
 
-        // Make the client admin
+        // Make the client admin, and allow it to manage legal holds
         let state = {
             let mut state = self.clone();
             state.policy_factory = policy_factory(serde_json::json!({
                 "admin_clients": [client_id],
+                "legal_hold_admin_clients": [client_id],
             }))
             .await
             .unwrap();
@@ -389,6 +449,14 @@ impl graphql::State for TestGraphQLState {
         Ok(repo.boxed())
     }
 
+    async fn read_only_repository(&self) -> Result<BoxRepository, mas_storage::RepositoryError> {
+        let repo = PgRepository::from_pool_read_only(&self.pool)
+            .await
+            .map_err(mas_storage::RepositoryError::from_error)?;
+
+        Ok(repo.boxed())
+    }
+
     async fn policy(&self) -> Result<Policy, InstantiateError> {
         self.policy_factory.instantiate().await
     }
@@ -482,6 +550,18 @@ impl FromRef<TestState> for MetadataCache {
     }
 }
 
+impl FromRef<TestState> for ClientLogoCache {
+    fn from_ref(input: &TestState) -> Self {
+        input.client_logo_cache.clone()
+    }
+}
+
+impl FromRef<TestState> for Mailer {
+    fn from_ref(input: &TestState) -> Self {
+        input.mailer.clone()
+    }
+}
+
 impl FromRef<TestState> for SiteConfig {
     fn from_ref(input: &TestState) -> Self {
         input.site_config.clone()
@@ -517,10 +597,11 @@ impl FromRequestParts<TestState> for BoundActivityTracker {
     type Rejection = Infallible;
 
     async fn from_request_parts(
-        _parts: &mut axum::http::request::Parts,
+        parts: &mut axum::http::request::Parts,
         state: &TestState,
     ) -> Result<Self, Self::Rejection> {
-        let ip = None;
+        // Tests can set the client IP address as a request extension
+        let ip = parts.extensions.get::<IpAddr>().copied();
         Ok(state.activity_tracker.clone().bind(ip))
     }
 }
@@ -576,6 +657,19 @@ impl FromRequestParts<TestState> for BoxRepository {
     }
 }
 
+#[async_trait]
+impl FromRequestParts<TestState> for ReadOnlyRepository {
+    type Rejection = ErrorWrapper<mas_storage_pg::DatabaseError>;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &TestState,
+    ) -> Result<Self, Self::Rejection> {
+        let repo = PgRepository::from_pool_read_only(&state.pool).await?;
+        Ok(ReadOnlyRepository(repo.boxed()))
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<TestState> for Policy {
     type Rejection = ErrorWrapper<mas_policy::InstantiateError>;
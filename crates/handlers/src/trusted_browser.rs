@@ -0,0 +1,141 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Browsers users chose to trust when logging in
+//!
+//! If a trusted browser TTL is configured, users can ask to remember the
+//! browser they log in from. The browser then holds the ID of a
+//! [`UserTrustedBrowser`] in an encrypted cookie, one per user who trusted it.
+//! Additional authentication factors are not asked again from a trusted
+//! browser, as long as the trust was not revoked, has not expired, and the
+//! browser still looks like the one which was trusted.
+
+use mas_axum_utils::cookies::CookieJar;
+use mas_data_model::{BrowserSession, SiteConfig, User, UserAgent, UserTrustedBrowser};
+use mas_storage::{user::UserTrustedBrowserRepository, Clock, RepositoryAccess};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+/// Name of the cookie
+static COOKIE_NAME: &str = "trusted-browsers";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Payload {
+    user: Ulid,
+    trusted_browser: Ulid,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct TrustedBrowsers(Vec<Payload>);
+
+impl TrustedBrowsers {
+    /// Load the trusted browsers cookie
+    pub fn load(cookie_jar: &CookieJar) -> Self {
+        match cookie_jar.load(COOKIE_NAME) {
+            Ok(Some(trusted_browsers)) => trusted_browsers,
+            Ok(None) => Self::default(),
+            Err(e) => {
+                tracing::warn!("Invalid trusted browsers cookie: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Save the trusted browsers to the cookie jar
+    ///
+    /// The cookie itself never expires, the trust is checked against the
+    /// storage on each use
+    pub fn save(self, cookie_jar: CookieJar) -> CookieJar {
+        cookie_jar.save(COOKIE_NAME, &self, true)
+    }
+
+    /// Remember that the browser was trusted by a user, replacing a previous
+    /// trust of the same user
+    pub fn add(mut self, trusted_browser: &UserTrustedBrowser) -> Self {
+        self.0.retain(|p| p.user != trusted_browser.user_id);
+        self.0.push(Payload {
+            user: trusted_browser.user_id,
+            trusted_browser: trusted_browser.id,
+        });
+        self
+    }
+
+    /// Find the trust the given user gave to this browser, if it is still
+    /// valid
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository fails
+    pub async fn find<R: RepositoryAccess>(
+        &self,
+        repo: &mut R,
+        clock: &dyn Clock,
+        user: &User,
+        user_agent: Option<&UserAgent>,
+    ) -> Result<Option<UserTrustedBrowser>, R::Error> {
+        let Some(payload) = self.0.iter().find(|p| p.user == user.id) else {
+            return Ok(None);
+        };
+
+        let trusted_browser = repo
+            .user_trusted_browser()
+            .lookup(payload.trusted_browser)
+            .await?
+            .filter(|trusted_browser| {
+                trusted_browser.user_id == user.id
+                    && trusted_browser.active(clock.now())
+                    && trusted_browser.matches(user_agent)
+            });
+
+        Ok(trusted_browser)
+    }
+}
+
+/// Record that a browser the user trusted was used to log in again, or trust
+/// this one if they asked for it
+///
+/// Returns the trust which applies to this login, if any
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn on_login<R: RepositoryAccess>(
+    repo: &mut R,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    site_config: &SiteConfig,
+    browser_session: &BrowserSession,
+    remember: bool,
+    cookie_jar: CookieJar,
+) -> Result<(Option<UserTrustedBrowser>, CookieJar), R::Error> {
+    let Some(ttl) = site_config.trusted_browser_ttl else {
+        return Ok((None, cookie_jar));
+    };
+
+    let user = &browser_session.user;
+    let user_agent = browser_session.user_agent.as_ref();
+    let trusted_browsers = TrustedBrowsers::load(&cookie_jar);
+
+    if let Some(trusted_browser) = trusted_browsers.find(repo, clock, user, user_agent).await? {
+        let trusted_browser = repo
+            .user_trusted_browser()
+            .touch(clock, trusted_browser)
+            .await?;
+        return Ok((Some(trusted_browser), cookie_jar));
+    }
+
+    if !remember {
+        return Ok((None, cookie_jar));
+    }
+
+    let trusted_browser = repo
+        .user_trusted_browser()
+        .add(rng, clock, user, user_agent.cloned(), ttl)
+        .await?;
+    let cookie_jar = trusted_browsers.add(&trusted_browser).save(cookie_jar);
+
+    Ok((Some(trusted_browser), cookie_jar))
+}
@@ -12,17 +12,21 @@ use hyper::StatusCode;
 use mas_axum_utils::{
     cookies::CookieJar, http_client_factory::HttpClientFactory, sentry::SentryEventID,
 };
-use mas_data_model::UpstreamOAuthProvider;
-use mas_oidc_client::requests::authorization_code::AuthorizationRequestData;
-use mas_router::UrlBuilder;
+use mas_data_model::{UpstreamOAuthFunnelStage, UpstreamOAuthProvider};
+use mas_keystore::{Encrypter, Keystore};
+use mas_oidc_client::{
+    requests::authorization_code::{AuthorizationRequestData, RequestObjectSigningData},
+    types::client_credentials::JwtSigningMethod,
+};
+use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     upstream_oauth2::{UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository},
-    BoxClock, BoxRepository, BoxRng,
+    BoxClock, BoxRepository, BoxRng, Clock,
 };
 use thiserror::Error;
 use ulid::Ulid;
 
-use super::{cache::LazyProviderInfos, UpstreamSessionsCookie};
+use super::{cache::LazyProviderInfos, client_credentials_for_provider, UpstreamSessionsCookie};
 use crate::{
     impl_from_error_for_route, upstream_oauth2::cache::MetadataCache,
     views::shared::OptionalPostAuthAction,
@@ -39,6 +43,7 @@ pub(crate) enum RouteError {
 
 impl_from_error_for_route!(mas_oidc_client::error::DiscoveryError);
 impl_from_error_for_route!(mas_oidc_client::error::AuthorizationError);
+impl_from_error_for_route!(super::ProviderCredentialsError);
 impl_from_error_for_route!(mas_storage::RepositoryError);
 
 impl IntoResponse for RouteError {
@@ -65,6 +70,8 @@ pub(crate) async fn get(
     State(http_client_factory): State<HttpClientFactory>,
     State(metadata_cache): State<MetadataCache>,
     mut repo: BoxRepository,
+    State(keystore): State<Keystore>,
+    State(encrypter): State<Encrypter>,
     State(url_builder): State<UrlBuilder>,
     cookie_jar: CookieJar,
     Path(provider_id): Path<Ulid>,
@@ -77,12 +84,52 @@ pub(crate) async fn get(
         .filter(UpstreamOAuthProvider::enabled)
         .ok_or(RouteError::ProviderNotFound)?;
 
+    let (cookie_jar, redirect) = start_authorization(
+        &mut rng,
+        &clock,
+        &http_client_factory,
+        &metadata_cache,
+        &mut repo,
+        &keystore,
+        &encrypter,
+        &url_builder,
+        cookie_jar,
+        &provider,
+        query.post_auth_action,
+        None,
+    )
+    .await?;
+
+    repo.save().await?;
+
+    Ok((cookie_jar, redirect))
+}
+
+/// Build an authorization request to the given provider, and remember it in
+/// the upstream sessions cookie
+///
+/// Returns the updated cookie jar and the redirection to the provider. The
+/// caller is responsible for saving the repository.
+pub(super) async fn start_authorization(
+    rng: &mut BoxRng,
+    clock: &BoxClock,
+    http_client_factory: &HttpClientFactory,
+    metadata_cache: &MetadataCache,
+    repo: &mut BoxRepository,
+    keystore: &Keystore,
+    encrypter: &Encrypter,
+    url_builder: &UrlBuilder,
+    cookie_jar: CookieJar,
+    provider: &UpstreamOAuthProvider,
+    post_auth_action: Option<PostAuthAction>,
+    login_hint: Option<String>,
+) -> Result<(CookieJar, Redirect), RouteError> {
     let http_service = http_client_factory.http_service("upstream_oauth2.authorize");
 
     // First, discover the provider
     // This is done lazyly according to provider.discovery_mode and the various
     // endpoint overrides
-    let mut lazy_metadata = LazyProviderInfos::new(&metadata_cache, &provider, &http_service);
+    let mut lazy_metadata = LazyProviderInfos::new(metadata_cache, provider, &http_service);
     lazy_metadata.maybe_discover().await?;
 
     let redirect_uri = url_builder.upstream_oauth_callback(provider.id);
@@ -91,7 +138,10 @@ pub(crate) async fn get(
         provider.client_id.clone(),
         provider.scope.clone(),
         redirect_uri,
-    );
+    )
+    // Providers queried through their userinfo endpoint may be plain OAuth 2.0
+    // providers, so the scope is sent as configured
+    .with_openid_scope(!provider.fetch_userinfo);
 
     let data = if let Some(methods) = lazy_metadata.pkce_methods().await? {
         data.with_code_challenge_methods_supported(methods)
@@ -99,28 +149,74 @@ pub(crate) async fn get(
         data
     };
 
-    // Build an authorization request for it
-    let (mut url, data) = mas_oidc_client::requests::authorization_code::build_authorization_url(
-        lazy_metadata.authorization_endpoint().await?.clone(),
-        data,
-        &mut rng,
-    )?;
+    let data = if let Some(response_mode) = provider.response_mode {
+        data.with_response_mode(response_mode.into())
+    } else {
+        data
+    };
 
-    // We do that in a block because params borrows url mutably
+    // Pass along the login hint, e.g. from a login initiated by the provider
+    let data = if let Some(login_hint) = login_hint {
+        data.with_login_hint(login_hint)
+    } else {
+        data
+    };
+
+    // Add any additional parameters to the request
+    let data =
+        data.with_additional_parameters(provider.additional_authorization_parameters.clone());
+
+    // Sign the request as a request object if required
+    let data = if let Some(alg) = lazy_metadata.request_object_signing_alg().await? {
+        data.with_request_object_signing(RequestObjectSigningData::new(
+            provider.issuer.clone(),
+            JwtSigningMethod::with_keystore(keystore.clone()),
+            alg,
+        ))
+    } else {
+        data
+    };
+
+    let authorization_endpoint = lazy_metadata.authorization_endpoint().await?.clone();
+
+    // Build an authorization request for it, pushing it first if required
+    let (url, data) = if let Some(par_endpoint) = lazy_metadata
+        .pushed_authorization_request_endpoint()
+        .await?
+        .cloned()
     {
-        // Add any additional parameters to the query
-        let mut params = url.query_pairs_mut();
-        for (key, value) in &provider.additional_authorization_parameters {
-            params.append_pair(key, value);
-        }
-    }
+        let client_credentials = client_credentials_for_provider(
+            provider,
+            lazy_metadata.token_endpoint().await?,
+            keystore,
+            encrypter,
+        )?;
+
+        mas_oidc_client::requests::authorization_code::build_par_authorization_url(
+            &http_service,
+            client_credentials,
+            &par_endpoint,
+            authorization_endpoint,
+            data,
+            clock.now(),
+            rng,
+        )
+        .await?
+    } else {
+        mas_oidc_client::requests::authorization_code::build_authorization_url(
+            authorization_endpoint,
+            data,
+            clock.now(),
+            rng,
+        )?
+    };
 
     let session = repo
         .upstream_oauth_session()
         .add(
-            &mut rng,
-            &clock,
-            &provider,
+            rng,
+            clock,
+            provider,
             data.state.clone(),
             data.code_challenge_verifier,
             data.nonce,
@@ -128,10 +224,18 @@ pub(crate) async fn get(
         .await?;
 
     let cookie_jar = UpstreamSessionsCookie::load(&cookie_jar)
-        .add(session.id, provider.id, data.state, query.post_auth_action)
-        .save(cookie_jar, &clock);
-
-    repo.save().await?;
+        .add(session.id, provider.id, data.state, post_auth_action)
+        .save(cookie_jar, clock);
+
+    super::funnel::record_stage(
+        repo,
+        rng,
+        clock,
+        provider.id,
+        None,
+        UpstreamOAuthFunnelStage::Redirected,
+    )
+    .await?;
 
     Ok((cookie_jar, Redirect::temporary(url.as_str())))
 }
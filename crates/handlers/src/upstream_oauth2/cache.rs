@@ -6,17 +6,26 @@
 
 use std::{collections::HashMap, sync::Arc};
 
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
-    UpstreamOAuthProvider, UpstreamOAuthProviderDiscoveryMode, UpstreamOAuthProviderPkceMode,
+    UpstreamOAuthProvider, UpstreamOAuthProviderDiscoveryMode, UpstreamOAuthProviderParMode,
+    UpstreamOAuthProviderPkceMode,
 };
 use mas_http::HttpService;
-use mas_iana::oauth::PkceCodeChallengeMethod;
-use mas_oidc_client::error::DiscoveryError;
-use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, RepositoryAccess};
-use oauth2_types::oidc::VerifiedProviderMetadata;
+use mas_iana::{jose::JsonWebSignatureAlg, oauth::PkceCodeChallengeMethod};
+use mas_jose::{constraints::Constrainable, jwk::PublicJsonWebKeySet};
+use mas_oidc_client::error::{DiscoveryError, JwksError};
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthProviderRepository, BoxClock, Clock, RepositoryAccess,
+};
+use oauth2_types::oidc::{ProviderMetadataVerificationError, VerifiedProviderMetadata};
 use tokio::sync::RwLock;
 use url::Url;
 
+/// Minimum time between two fetches of the same JWKS triggered by an unknown
+/// key ID, so that tokens signed with unknown keys don't hammer the provider
+const JWKS_REFETCH_COOLDOWN: Duration = Duration::seconds(30);
+
 /// A high-level layer over metadata cache and provider configuration, which
 /// resolves endpoint overrides and discovery modes.
 pub struct LazyProviderInfos<'a> {
@@ -109,6 +118,95 @@ impl<'a> LazyProviderInfos<'a> {
         Ok(self.load().await?.token_endpoint())
     }
 
+    /// Get the end session endpoint for the provider, if it has one.
+    ///
+    /// Uses [`UpstreamOAuthProvider.end_session_endpoint_override`] if set,
+    /// otherwise uses the one from discovery, if discovery is enabled.
+    pub async fn end_session_endpoint(&mut self) -> Result<Option<&Url>, DiscoveryError> {
+        if let Some(endpoint) = &self.provider.end_session_endpoint_override {
+            return Ok(Some(endpoint));
+        }
+
+        Ok(self
+            .maybe_discover()
+            .await?
+            .and_then(|metadata| metadata.end_session_endpoint.as_ref()))
+    }
+
+    /// Get the userinfo endpoint for the provider, if it has one.
+    ///
+    /// Uses [`UpstreamOAuthProvider.userinfo_endpoint_override`] if set,
+    /// otherwise uses the one from discovery, if discovery is enabled.
+    pub async fn userinfo_endpoint(&mut self) -> Result<Option<&Url>, DiscoveryError> {
+        if let Some(endpoint) = &self.provider.userinfo_endpoint_override {
+            return Ok(Some(endpoint));
+        }
+
+        Ok(self
+            .maybe_discover()
+            .await?
+            .and_then(|metadata| metadata.userinfo_endpoint.as_ref()))
+    }
+
+    /// Get the pushed authorization request endpoint for the provider, if
+    /// pushed authorization requests should be used.
+    ///
+    /// Uses
+    /// [`UpstreamOAuthProvider.pushed_authorization_request_endpoint_override`]
+    /// if set, otherwise uses the one from discovery. If the mode is set to
+    /// auto, pushed authorization requests are only used if the provider
+    /// requires them.
+    pub async fn pushed_authorization_request_endpoint(
+        &mut self,
+    ) -> Result<Option<&Url>, DiscoveryError> {
+        let required = match self.provider.par_mode {
+            UpstreamOAuthProviderParMode::Auto => self
+                .maybe_discover()
+                .await?
+                .is_some_and(|metadata| metadata.require_pushed_authorization_requests()),
+            UpstreamOAuthProviderParMode::Always => true,
+            UpstreamOAuthProviderParMode::Disabled => false,
+        };
+
+        if !required {
+            return Ok(None);
+        }
+
+        if let Some(endpoint) = &self.provider.pushed_authorization_request_endpoint_override {
+            return Ok(Some(endpoint));
+        }
+
+        let endpoint = self
+            .load()
+            .await?
+            .pushed_authorization_request_endpoint
+            .as_ref()
+            .ok_or(ProviderMetadataVerificationError::MissingPushedAuthorizationRequestEndpoint)?;
+
+        Ok(Some(endpoint))
+    }
+
+    /// Get the algorithm to use to sign the authorization request as a request
+    /// object, if it should be signed.
+    ///
+    /// Uses [`UpstreamOAuthProvider.request_object_signing_alg`] if set.
+    /// Otherwise, the request is signed with `RS256` only if the provider
+    /// requires signed request objects.
+    pub async fn request_object_signing_alg(
+        &mut self,
+    ) -> Result<Option<JsonWebSignatureAlg>, DiscoveryError> {
+        if let Some(alg) = &self.provider.request_object_signing_alg {
+            return Ok(Some(alg.clone()));
+        }
+
+        let required = self
+            .maybe_discover()
+            .await?
+            .is_some_and(|metadata| metadata.require_signed_request_object());
+
+        Ok(required.then_some(JsonWebSignatureAlg::Rs256))
+    }
+
     /// Get the PKCE methods supported by the provider.
     ///
     /// If the mode is set to auto, it will use the ones from discovery,
@@ -129,7 +227,14 @@ impl<'a> LazyProviderInfos<'a> {
     }
 }
 
-/// A simple OIDC metadata cache
+/// A JWKS in the cache, along with when it was fetched
+#[derive(Debug)]
+struct CachedJwks {
+    jwks: Arc<PublicJsonWebKeySet>,
+    fetched_at: DateTime<Utc>,
+}
+
+/// A simple OIDC metadata and JWKS cache
 ///
 /// It never evicts entries, does not cache failures and has no locking.
 /// It can also be refreshed in the background, and warmed up on startup.
@@ -139,6 +244,7 @@ impl<'a> LazyProviderInfos<'a> {
 pub struct MetadataCache {
     cache: Arc<RwLock<HashMap<String, Arc<VerifiedProviderMetadata>>>>,
     insecure_cache: Arc<RwLock<HashMap<String, Arc<VerifiedProviderMetadata>>>>,
+    jwks_cache: Arc<RwLock<HashMap<Url, CachedJwks>>>,
 }
 
 impl MetadataCache {
@@ -147,14 +253,16 @@ impl MetadataCache {
         Self::default()
     }
 
-    /// Warm up the cache by fetching all the known providers from the database
-    /// and inserting them into the cache.
+    /// Warm up the cache by fetching the metadata and JWKS of all the known
+    /// providers from the database and inserting them into the cache.
     ///
     /// This spawns a background task that will refresh the cache at the given
-    /// interval.
+    /// interval, so that changes in the metadata and key rotations are picked
+    /// up without a restart.
     #[tracing::instrument(name = "metadata_cache.warm_up_and_run", skip_all, err)]
     pub async fn warm_up_and_run<R: RepositoryAccess>(
         &self,
+        clock: BoxClock,
         http_service: HttpService,
         interval: std::time::Duration,
         repository: &mut R,
@@ -162,14 +270,19 @@ impl MetadataCache {
         let providers = repository.upstream_oauth_provider().all_enabled().await?;
 
         for provider in providers {
-            let verify = match provider.discovery_mode {
-                UpstreamOAuthProviderDiscoveryMode::Oidc => true,
-                UpstreamOAuthProviderDiscoveryMode::Insecure => false,
-                UpstreamOAuthProviderDiscoveryMode::Disabled => continue,
+            let mut lazy_metadata = LazyProviderInfos::new(self, &provider, &http_service);
+            if let Err(e) = lazy_metadata.maybe_discover().await {
+                tracing::error!(issuer = %provider.issuer, error = &e as &dyn std::error::Error, "Failed to fetch provider metadata");
+                continue;
+            }
+
+            // This fails if discovery is disabled and there is no JWKS URI override
+            let Ok(jwks_uri) = lazy_metadata.jwks_uri().await else {
+                continue;
             };
 
-            if let Err(e) = self.fetch(&http_service, &provider.issuer, verify).await {
-                tracing::error!(issuer = %provider.issuer, error = &e as &dyn std::error::Error, "Failed to fetch provider metadata");
+            if let Err(e) = self.fetch_jwks(&clock, &http_service, jwks_uri).await {
+                tracing::error!(issuer = %provider.issuer, error = &e as &dyn std::error::Error, "Failed to fetch provider JWKS");
             }
         }
 
@@ -179,7 +292,7 @@ impl MetadataCache {
             loop {
                 // Re-fetch the known metadata at the given interval
                 tokio::time::sleep(interval).await;
-                cache.refresh_all(&http_service).await;
+                cache.refresh_all(&clock, &http_service).await;
             }
         }))
     }
@@ -241,8 +354,61 @@ impl MetadataCache {
         Ok(metadata)
     }
 
+    #[tracing::instrument(name = "metadata_cache.fetch_jwks", fields(%jwks_uri), skip_all, err)]
+    async fn fetch_jwks(
+        &self,
+        clock: &dyn Clock,
+        http_service: &HttpService,
+        jwks_uri: &Url,
+    ) -> Result<Arc<PublicJsonWebKeySet>, JwksError> {
+        let jwks = mas_oidc_client::requests::jose::fetch_jwks(http_service, jwks_uri).await?;
+        let jwks = Arc::new(jwks);
+
+        self.jwks_cache.write().await.insert(
+            jwks_uri.clone(),
+            CachedJwks {
+                jwks: jwks.clone(),
+                fetched_at: clock.now(),
+            },
+        );
+
+        Ok(jwks)
+    }
+
+    /// Get the JWKS at the given URI, which should contain the key with the
+    /// given ID.
+    ///
+    /// If the cached JWKS doesn't have a key with this ID, it is fetched again
+    /// to pick up a key rotation, at most once every 30 seconds.
+    #[tracing::instrument(name = "metadata_cache.get_jwks", fields(%jwks_uri), skip_all, err)]
+    pub async fn get_jwks(
+        &self,
+        clock: &dyn Clock,
+        http_service: &HttpService,
+        jwks_uri: &Url,
+        kid: Option<&str>,
+    ) -> Result<Arc<PublicJsonWebKeySet>, JwksError> {
+        let cache = self.jwks_cache.read().await;
+
+        if let Some(cached) = cache.get(jwks_uri) {
+            let has_key = kid.map_or(true, |kid| {
+                cached.jwks.iter().any(|key| key.kid() == Some(kid))
+            });
+
+            if has_key || clock.now() - cached.fetched_at < JWKS_REFETCH_COOLDOWN {
+                return Ok(Arc::clone(&cached.jwks));
+            }
+
+            tracing::info!(kid, "Unknown key ID, fetching the JWKS again");
+        }
+        // Drop the cache guard so that we don't deadlock when we try to fetch
+        drop(cache);
+
+        self.fetch_jwks(clock, http_service, jwks_uri).await
+    }
+
     #[tracing::instrument(name = "metadata_cache.refresh_all", skip_all)]
-    async fn refresh_all(&self, http_service: &HttpService) {
+    async fn refresh_all(&self, clock: &dyn Clock, http_service: &HttpService) {
         // Grab all the keys first to avoid locking the cache for too long
         let keys: Vec<String> = {
             let cache = self.cache.read().await;
@@ -266,6 +432,18 @@ impl MetadataCache {
                 tracing::error!(issuer = %issuer, error = &e as &dyn std::error::Error, "Failed to refresh provider metadata");
             }
         }
+
+        // And refresh the JWKS, to pick up key rotations
+        let keys: Vec<Url> = {
+            let cache = self.jwks_cache.read().await;
+            cache.keys().cloned().collect()
+        };
+
+        for jwks_uri in keys {
+            if let Err(e) = self.fetch_jwks(clock, http_service, &jwks_uri).await {
+                tracing::error!(%jwks_uri, error = &e as &dyn std::error::Error, "Failed to refresh provider JWKS");
+            }
+        }
     }
 }
 
@@ -276,9 +454,10 @@ mod tests {
     use std::sync::atomic::{AtomicUsize, Ordering};
 
     use hyper::{body::Bytes, Request, Response, StatusCode};
-    use mas_data_model::UpstreamOAuthProviderClaimsImports;
+    use mas_data_model::{
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderTokenAuthMethod,
+    };
     use mas_http::BoxCloneSyncService;
-    use mas_iana::oauth::OAuthClientAuthenticationMethod;
     use mas_storage::{clock::MockClock, Clock};
     use oauth2_types::scope::{Scope, OPENID};
     use tower::BoxError;
@@ -359,6 +538,7 @@ mod tests {
 
         let service = BoxCloneSyncService::new(tower::service_fn(handler));
         let cache = MetadataCache::new();
+        let clock = MockClock::default();
 
         // An inexistant issuer should fail
         cache
@@ -404,10 +584,76 @@ mod tests {
         assert_eq!(calls.load(Ordering::SeqCst), 4);
 
         // Calling refresh should refresh all the known valid issuers
-        cache.refresh_all(&service).await;
+        cache.refresh_all(&clock, &service).await;
         assert_eq!(calls.load(Ordering::SeqCst), 6);
     }
 
+    #[tokio::test]
+    async fn test_jwks_cache() {
+        setup();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let closure_calls = Arc::clone(&calls);
+        let handler = move |_req: Request<Bytes>| {
+            let calls = Arc::clone(&closure_calls);
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                let body = Bytes::from_static(
+                    br#"{
+                        "keys": [
+                            {
+                                "kty": "EC",
+                                "kid": "known",
+                                "crv": "P-256",
+                                "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+                                "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0"
+                            }
+                        ]
+                    }"#,
+                );
+
+                let mut response = Response::new(body);
+                *response.status_mut() = StatusCode::OK;
+                Ok::<_, BoxError>(response)
+            }
+        };
+
+        let service = BoxCloneSyncService::new(tower::service_fn(handler));
+        let cache = MetadataCache::new();
+        let clock = MockClock::default();
+        let jwks_uri: Url = "https://valid.example.com/jwks".parse().unwrap();
+
+        // The first call fetches the JWKS
+        let jwks = cache
+            .get_jwks(&clock, &service, &jwks_uri, None)
+            .await
+            .unwrap();
+        assert_eq!(jwks.len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Asking for a known key uses the cache
+        cache
+            .get_jwks(&clock, &service, &jwks_uri, Some("known"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Asking for an unknown key right after a fetch also uses the cache
+        cache
+            .get_jwks(&clock, &service, &jwks_uri, Some("unknown"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // But once the cooldown is over, it fetches the JWKS again
+        clock.advance(JWKS_REFETCH_COOLDOWN);
+        cache
+            .get_jwks(&clock, &service, &jwks_uri, Some("unknown"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
     #[tokio::test]
     async fn test_lazy_provider_infos() {
         setup();
@@ -487,6 +733,10 @@ mod tests {
             brand_name: None,
             discovery_mode: UpstreamOAuthProviderDiscoveryMode::Oidc,
             pkce_mode: UpstreamOAuthProviderPkceMode::Auto,
+            par_mode: UpstreamOAuthProviderParMode::Auto,
+            pushed_authorization_request_endpoint_override: None,
+            request_object_signing_alg: None,
+            response_mode: None,
             jwks_uri_override: None,
             authorization_endpoint_override: None,
             token_endpoint_override: None,
@@ -494,11 +744,17 @@ mod tests {
             client_id: "client_id".to_owned(),
             encrypted_client_secret: None,
             token_endpoint_signing_alg: None,
-            token_endpoint_auth_method: OAuthClientAuthenticationMethod::None,
+            token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
             created_at: clock.now(),
             disabled_at: None,
             claims_imports: UpstreamOAuthProviderClaimsImports::default(),
             additional_authorization_parameters: Vec::new(),
+            allow_idp_initiated_login: false,
+            idp_initiated_login_client_id: None,
+            propagate_logout: false,
+            end_session_endpoint_override: None,
+            fetch_userinfo: false,
+            userinfo_endpoint_override: None,
         };
 
         // Without any override, it should just use discovery
@@ -5,17 +5,21 @@
 // Please see LICENSE in the repository root for full details.
 
 use axum::{
-    extract::{Path, Query, State},
-    response::IntoResponse,
+    extract::{Path, State},
+    response::{Html, IntoResponse, Response},
+    Form,
 };
-use hyper::StatusCode;
+use hyper::{Method, StatusCode};
 use mas_axum_utils::{
     cookies::CookieJar, http_client_factory::HttpClientFactory, sentry::SentryEventID,
 };
-use mas_data_model::UpstreamOAuthProvider;
+use mas_data_model::{UpstreamOAuthFunnelStage, UpstreamOAuthProvider};
+use mas_jose::jwt::Jwt;
 use mas_keystore::{Encrypter, Keystore};
 use mas_oidc_client::requests::{
-    authorization_code::AuthorizationValidationData, jose::JwtVerificationData,
+    authorization_code::{verify_authorization_code_id_token, AuthorizationValidationData},
+    jose::JwtVerificationData,
+    userinfo::fetch_userinfo,
 };
 use mas_router::UrlBuilder;
 use mas_storage::{
@@ -25,8 +29,10 @@ use mas_storage::{
     },
     BoxClock, BoxRepository, BoxRng, Clock,
 };
+use mas_templates::{FormPostContext, Templates};
 use oauth2_types::errors::ClientErrorCode;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
 use thiserror::Error;
 use ulid::Ulid;
 
@@ -36,26 +42,26 @@ use super::{
 };
 use crate::{impl_from_error_for_route, upstream_oauth2::cache::MetadataCache};
 
-#[derive(Deserialize)]
-pub struct QueryParams {
+/// The parameters sent back by the provider, either in the query string or
+/// in a form body, depending on the response mode
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize)]
+pub struct Params {
     state: String,
 
-    #[serde(flatten)]
-    code_or_error: CodeOrError,
-}
+    /// Set when we re-submitted the form to ourselves, so that we don't loop
+    /// if the session cookie is really missing
+    did_mas_repost_to_itself: Option<String>,
 
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum CodeOrError {
-    Code {
-        code: String,
-    },
-    Error {
-        error: ClientErrorCode,
-        error_description: Option<String>,
-        #[allow(dead_code)]
-        error_uri: Option<String>,
-    },
+    code: Option<String>,
+    error: Option<ClientErrorCode>,
+    error_description: Option<String>,
+    error_uri: Option<String>,
+
+    /// Any other parameter sent by the provider, like the `user` parameter
+    /// sent by Sign in with Apple
+    #[serde(flatten)]
+    extra_callback_parameters: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Error)]
@@ -78,6 +84,9 @@ pub(crate) enum RouteError {
     #[error("Missing ID token")]
     MissingIDToken,
 
+    #[error("Provider has no userinfo endpoint")]
+    MissingUserinfoEndpoint,
+
     #[error("Could not extract subject from ID token")]
     ExtractSubject(#[source] minijinja::Error),
 
@@ -93,6 +102,9 @@ pub(crate) enum RouteError {
     #[error("Missing session cookie")]
     MissingCookie,
 
+    #[error("Missing code or error parameter")]
+    MissingCodeOrError,
+
     #[error(transparent)]
     Internal(Box<dyn std::error::Error>),
 }
@@ -101,8 +113,11 @@ impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_oidc_client::error::DiscoveryError);
 impl_from_error_for_route!(mas_oidc_client::error::JwksError);
 impl_from_error_for_route!(mas_oidc_client::error::TokenAuthorizationCodeError);
+impl_from_error_for_route!(mas_oidc_client::error::IdTokenError);
+impl_from_error_for_route!(mas_oidc_client::error::UserInfoError);
 impl_from_error_for_route!(super::ProviderCredentialsError);
 impl_from_error_for_route!(super::cookie::UpstreamSessionNotFound);
+impl_from_error_for_route!(mas_templates::TemplateError);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
@@ -119,13 +134,13 @@ impl IntoResponse for RouteError {
 }
 
 #[tracing::instrument(
-    name = "handlers.upstream_oauth2.callback.get",
+    name = "handlers.upstream_oauth2.callback.handler",
     fields(upstream_oauth_provider.id = %provider_id),
     skip_all,
     err,
 )]
 #[allow(clippy::too_many_lines, clippy::too_many_arguments)]
-pub(crate) async fn get(
+pub(crate) async fn handler(
     mut rng: BoxRng,
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
@@ -134,10 +149,12 @@ pub(crate) async fn get(
     State(url_builder): State<UrlBuilder>,
     State(encrypter): State<Encrypter>,
     State(keystore): State<Keystore>,
+    State(templates): State<Templates>,
+    method: Method,
     cookie_jar: CookieJar,
     Path(provider_id): Path<Ulid>,
-    Query(params): Query<QueryParams>,
-) -> Result<impl IntoResponse, RouteError> {
+    Form(params): Form<Params>,
+) -> Result<Response, RouteError> {
     let provider = repo
         .upstream_oauth_provider()
         .lookup(provider_id)
@@ -146,9 +163,26 @@ pub(crate) async fn get(
         .ok_or(RouteError::ProviderNotFound)?;
 
     let sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar);
-    let (session_id, _post_auth_action) = sessions_cookie
-        .find_session(provider_id, &params.state)
-        .map_err(|_| RouteError::MissingCookie)?;
+    let Ok((session_id, _post_auth_action)) =
+        sessions_cookie.find_session(provider_id, &params.state)
+    else {
+        // When the provider uses the `form_post` response mode, the browser
+        // doesn't send our `SameSite=Lax` cookies along with the cross-site
+        // POST request. We re-submit the form to ourselves, from our own
+        // origin, so that the cookies are sent this time.
+        if method == Method::POST && params.did_mas_repost_to_itself.is_none() {
+            let params = Params {
+                did_mas_repost_to_itself: Some("true".to_owned()),
+                ..params
+            };
+            let ctx =
+                FormPostContext::new(url_builder.upstream_oauth_callback(provider_id), params);
+            let rendered = templates.render_form_post(&ctx)?;
+            return Ok(Html(rendered).into_response());
+        }
+
+        return Err(RouteError::MissingCookie);
+    };
 
     let session = repo
         .upstream_oauth_session()
@@ -172,28 +206,46 @@ pub(crate) async fn get(
     }
 
     // Let's extract the code from the params, and return if there was an error
-    let code = match params.code_or_error {
-        CodeOrError::Error {
+    if let Some(error) = params.error {
+        // Keep track of users who, for example, declined the authorization. Unknown
+        // error codes share a reason, so that they can't blow up the cardinality of
+        // the metrics.
+        let reason = match &error {
+            ClientErrorCode::AccessDenied => "access_denied",
+            ClientErrorCode::LoginRequired => "login_required",
+            ClientErrorCode::InteractionRequired => "interaction_required",
+            ClientErrorCode::ConsentRequired => "consent_required",
+            _ => "provider_error",
+        };
+        super::funnel::record_dropoff(
+            &mut repo,
+            &mut rng,
+            &clock,
+            provider.id,
+            None,
+            UpstreamOAuthFunnelStage::CallbackReceived,
+            reason,
+        )
+        .await?;
+        repo.save().await?;
+
+        return Err(RouteError::ClientError {
             error,
-            error_description,
-            ..
-        } => {
-            return Err(RouteError::ClientError {
-                error,
-                error_description,
-            })
-        }
-        CodeOrError::Code { code } => code,
-    };
+            error_description: params.error_description,
+        });
+    }
+
+    let code = params.code.ok_or(RouteError::MissingCodeOrError)?;
+
+    // Keep the extra parameters sent by the provider around, so that they can
+    // be used when importing the user attributes
+    let extra_callback_parameters = params
+        .extra_callback_parameters
+        .filter(|value| value.as_object().is_some_and(|object| !object.is_empty()));
 
     let http_service = http_client_factory.http_service("upstream_oauth2.callback");
     let mut lazy_metadata = LazyProviderInfos::new(&metadata_cache, &provider, &http_service);
 
-    // Fetch the JWKS
-    let jwks =
-        mas_oidc_client::requests::jose::fetch_jwks(&http_service, lazy_metadata.jwks_uri().await?)
-            .await?;
-
     // Figure out the client credentials
     let client_credentials = client_credentials_for_provider(
         &provider,
@@ -212,32 +264,91 @@ pub(crate) async fn get(
         redirect_uri,
     };
 
-    let id_token_verification_data = JwtVerificationData {
-        issuer: &provider.issuer,
-        jwks: &jwks,
-        // TODO: make that configurable
-        signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
-        client_id: &provider.client_id,
-    };
-
-    let (response, id_token) =
+    // The ID token is verified separately, once we know which key signed it
+    let (response, _) =
         mas_oidc_client::requests::authorization_code::access_token_with_authorization_code(
             &http_service,
             client_credentials,
             lazy_metadata.token_endpoint().await?,
-            code,
+            code.clone(),
             validation_data,
-            Some(id_token_verification_data),
+            None,
             clock.now(),
             &mut rng,
         )
         .await?;
 
-    let (_header, id_token) = id_token.ok_or(RouteError::MissingIDToken)?.into_parts();
+    // Plain OAuth 2.0 providers don't issue ID tokens, in which case the user
+    // claims only come from the userinfo endpoint
+    let id_token = if let Some(raw_id_token) = response.id_token.as_deref() {
+        // Fetch the JWKS. The cached one is refreshed if it doesn't have the key
+        // which signed the ID token, in case the provider rotated its keys.
+        let kid = Jwt::<serde_json::Value>::try_from(raw_id_token)
+            .ok()
+            .and_then(|jwt| jwt.header().kid().map(ToOwned::to_owned));
+        let jwks = metadata_cache
+            .get_jwks(
+                &clock,
+                &http_service,
+                lazy_metadata.jwks_uri().await?,
+                kid.as_deref(),
+            )
+            .await?;
+
+        let id_token_verification_data = JwtVerificationData {
+            issuer: &provider.issuer,
+            jwks: &jwks,
+            // TODO: make that configurable
+            signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
+            client_id: &provider.client_id,
+        };
+
+        let id_token = verify_authorization_code_id_token(
+            &response,
+            &code,
+            &session.nonce,
+            id_token_verification_data,
+            clock.now(),
+        )?;
+
+        Some(id_token)
+    } else if provider.fetch_userinfo {
+        None
+    } else {
+        return Err(RouteError::MissingIDToken);
+    };
+
+    let userinfo = if provider.fetch_userinfo {
+        let userinfo_endpoint = lazy_metadata
+            .userinfo_endpoint()
+            .await?
+            .ok_or(RouteError::MissingUserinfoEndpoint)?;
+
+        let userinfo = fetch_userinfo(
+            &http_service,
+            userinfo_endpoint,
+            &response.access_token,
+            None,
+            id_token.as_ref(),
+        )
+        .await?;
+
+        Some(userinfo)
+    } else {
+        None
+    };
+
+    // Claims from the ID token take precedence over the ones from the userinfo
+    // endpoint
+    let mut claims = userinfo.clone().unwrap_or_default();
+    if let Some(id_token) = id_token {
+        let (_header, id_token) = id_token.into_parts();
+        claims.extend(id_token);
+    }
 
     let env = {
         let mut env = environment();
-        env.add_global("user", minijinja::Value::from_serialize(&id_token));
+        env.add_global("user", minijinja::Value::from_serialize(&claims));
         env
     };
 
@@ -271,9 +382,26 @@ pub(crate) async fn get(
 
     let session = repo
         .upstream_oauth_session()
-        .complete_with_link(&clock, session, &link, response.id_token)
+        .complete_with_link(
+            &clock,
+            session,
+            &link,
+            response.id_token,
+            extra_callback_parameters,
+            userinfo.map(|userinfo| serde_json::Value::Object(userinfo.into_iter().collect())),
+        )
         .await?;
 
+    super::funnel::record_stage(
+        &mut repo,
+        &mut rng,
+        &clock,
+        provider.id,
+        None,
+        UpstreamOAuthFunnelStage::CallbackReceived,
+    )
+    .await?;
+
     let cookie_jar = sessions_cookie
         .add_link_to_session(session.id, link.id)?
         .save(cookie_jar, &clock);
@@ -283,5 +411,6 @@ pub(crate) async fn get(
     Ok((
         cookie_jar,
         url_builder.redirect(&mas_router::UpstreamOAuth2Link::new(link.id)),
-    ))
+    )
+        .into_response())
 }
@@ -0,0 +1,222 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Detect when the email address asserted by an upstream provider changes for
+//! a linked account, and either apply the change or ask an administrator to
+//! review it
+
+use mas_data_model::{
+    UpstreamOAuthLink, UpstreamOAuthProvider, UpstreamOAuthProviderOnEmailChange, User,
+};
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
+    upstream_oauth2::{UpstreamOAuthEmailChangeRepository, UpstreamOAuthLinkRepository},
+    user::{UserEmailFilter, UserEmailRepository, UserRepository},
+    BoxRepository, Clock, Pagination, RepositoryAccess, RepositoryError,
+};
+use rand::RngCore;
+
+/// Find another account which already has the given email address verified
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn find_conflicting_user(
+    repo: &mut BoxRepository,
+    user: &User,
+    email: &str,
+) -> Result<Option<User>, RepositoryError> {
+    let filter = UserEmailFilter::new().for_email(email).verified_only();
+    let page = repo
+        .user_email()
+        .list(filter, Pagination::first(10))
+        .await?;
+
+    let Some(user_email) = page.edges.into_iter().find(|e| e.user_id != user.id) else {
+        return Ok(None);
+    };
+
+    repo.user().lookup(user_email.user_id).await
+}
+
+/// Apply an email address change to the user
+///
+/// The new email address is added to the user if needed. If it is verified,
+/// it becomes the primary email address, and the previous one is removed.
+/// Otherwise, a verification email is sent to the new address.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn apply(
+    repo: &mut BoxRepository,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    user: &User,
+    previous_email: Option<&str>,
+    new_email: &str,
+    verified: bool,
+) -> Result<(), RepositoryError> {
+    let user_email = match repo.user_email().find(user, new_email).await? {
+        Some(user_email) => user_email,
+        None => {
+            repo.user_email()
+                .add(rng, clock, user, new_email.to_owned())
+                .await?
+        }
+    };
+
+    let user_email = match (user_email.confirmed_at, verified) {
+        (Some(_), _) => user_email,
+        (None, true) => {
+            repo.user_email()
+                .mark_as_verified(clock, user_email)
+                .await?
+        }
+        (None, false) => {
+            // The user will have to verify the address themselves before it can
+            // replace the previous one
+            repo.job()
+                .schedule_job(VerifyEmailJob::new(&user_email))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    repo.user_email().set_as_primary(&user_email).await?;
+
+    if let Some(previous_email) = previous_email.filter(|e| *e != new_email) {
+        if let Some(previous) = repo.user_email().find(user, previous_email).await? {
+            repo.user_email().remove(previous).await?;
+        }
+    }
+
+    repo.job().schedule_job(ProvisionUserJob::new(user)).await?;
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_updated(rng, clock, user))
+        .await?;
+
+    Ok(())
+}
+
+/// Keep track of the email address asserted by the provider on a link, and
+/// act on it if it changed since the last login, according to the provider
+/// configuration
+///
+/// Returns the updated link
+///
+/// # Parameters
+///
+/// * `asserted_email`: The email address the provider asserted on this login,
+///   if any
+/// * `email_verified`: Whether the provider claims the address is verified
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn sync(
+    repo: &mut BoxRepository,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    provider: &UpstreamOAuthProvider,
+    link: UpstreamOAuthLink,
+    user: &User,
+    asserted_email: Option<String>,
+    email_verified: bool,
+) -> Result<UpstreamOAuthLink, RepositoryError> {
+    // If the provider stopped asserting an email address, keep the last one
+    // we know about, so that we don't consider it as new if it comes back
+    let Some(new_email) = asserted_email else {
+        return Ok(link);
+    };
+
+    if link.asserted_email.as_deref() == Some(new_email.as_str()) {
+        return Ok(link);
+    }
+
+    let previous_email = link.asserted_email.clone();
+    let link = repo
+        .upstream_oauth_link()
+        .set_asserted_email(link, Some(new_email.clone()))
+        .await?;
+
+    // This is the first time we see an email address on this link, there is
+    // nothing to compare it to
+    let Some(previous_email) = previous_email else {
+        return Ok(link);
+    };
+
+    let conflicting_user = match provider.claims_imports.on_email_change {
+        UpstreamOAuthProviderOnEmailChange::Ignore => {
+            tracing::info!(
+                user.id = %user.id,
+                upstream_oauth_provider.id = %provider.id,
+                upstream_oauth_link.id = %link.id,
+                "The upstream provider asserted a different email address, ignoring",
+            );
+            return Ok(link);
+        }
+
+        UpstreamOAuthProviderOnEmailChange::Review => {
+            find_conflicting_user(repo, user, &new_email).await?
+        }
+
+        UpstreamOAuthProviderOnEmailChange::Update => {
+            let conflicting_user = find_conflicting_user(repo, user, &new_email).await?;
+            if conflicting_user.is_none() {
+                tracing::info!(
+                    user.id = %user.id,
+                    upstream_oauth_provider.id = %provider.id,
+                    upstream_oauth_link.id = %link.id,
+                    "Updating the email address of the user from the upstream provider",
+                );
+
+                let verified = provider
+                    .claims_imports
+                    .verify_email
+                    .should_mark_as_verified(email_verified);
+                apply(
+                    repo,
+                    rng,
+                    clock,
+                    user,
+                    Some(&previous_email),
+                    &new_email,
+                    verified,
+                )
+                .await?;
+
+                return Ok(link);
+            }
+
+            conflicting_user
+        }
+    };
+
+    let change = repo
+        .upstream_oauth_email_change()
+        .add(
+            rng,
+            clock,
+            &link,
+            user,
+            conflicting_user.as_ref(),
+            Some(previous_email),
+            new_email,
+        )
+        .await?;
+
+    tracing::info!(
+        user.id = %user.id,
+        upstream_oauth_provider.id = %provider.id,
+        upstream_oauth_link.id = %link.id,
+        upstream_oauth_email_change.id = %change.id,
+        conflicting_user.id = ?change.conflicting_user_id,
+        "The upstream provider asserted a different email address, waiting for a review",
+    );
+
+    Ok(link)
+}
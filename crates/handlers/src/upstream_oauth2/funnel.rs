@@ -0,0 +1,139 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Keep track of the stages of the signup funnel users go through with the
+//! upstream OAuth 2.0 providers, and of where they drop off
+//!
+//! Each stage is both counted in the metrics and recorded in the database, so
+//! that the funnel can be reported through the admin and GraphQL APIs.
+
+use std::sync::LazyLock;
+
+use mas_data_model::{UpstreamOAuthFunnelStage, User};
+use mas_storage::{BoxRepository, Clock, RepositoryAccess, RepositoryError};
+use opentelemetry::{metrics::Counter, Key};
+use rand::RngCore;
+use ulid::Ulid;
+
+const PROVIDER: Key = Key::from_static_str("provider");
+const STAGE: Key = Key::from_static_str("stage");
+const REASON: Key = Key::from_static_str("reason");
+
+struct Instruments {
+    stages: Counter<u64>,
+    dropoffs: Counter<u64>,
+}
+
+static INSTRUMENTS: LazyLock<Instruments> = LazyLock::new(|| {
+    let meter = opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    );
+
+    let stages = meter
+        .u64_counter("mas.upstream_oauth2.funnel.stages")
+        .with_description(
+            "The number of times a stage of the upstream OAuth 2.0 signup funnel was reached",
+        )
+        .with_unit("{stages}")
+        .init();
+
+    let dropoffs = meter
+        .u64_counter("mas.upstream_oauth2.funnel.dropoffs")
+        .with_description(
+            "The number of times users dropped off the upstream OAuth 2.0 signup funnel",
+        )
+        .with_unit("{dropoffs}")
+        .init();
+
+    Instruments { stages, dropoffs }
+});
+
+/// Record that a stage of the funnel was reached
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn record_stage(
+    repo: &mut BoxRepository,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    provider_id: Ulid,
+    user: Option<&User>,
+    stage: UpstreamOAuthFunnelStage,
+) -> Result<(), RepositoryError> {
+    repo.upstream_oauth_funnel()
+        .record(rng, clock, provider_id, user, stage, None)
+        .await?;
+
+    INSTRUMENTS.stages.add(
+        1,
+        &[
+            PROVIDER.string(provider_id.to_string()),
+            STAGE.string(stage.as_str()),
+        ],
+    );
+
+    Ok(())
+}
+
+/// Record that a user dropped off at a stage of the funnel
+///
+/// The reason should come from a small set of values, as it is used as a
+/// label in the metrics.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn record_dropoff(
+    repo: &mut BoxRepository,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    provider_id: Ulid,
+    user: Option<&User>,
+    stage: UpstreamOAuthFunnelStage,
+    reason: &'static str,
+) -> Result<(), RepositoryError> {
+    repo.upstream_oauth_funnel()
+        .record(rng, clock, provider_id, user, stage, Some(reason))
+        .await?;
+
+    INSTRUMENTS.dropoffs.add(
+        1,
+        &[
+            PROVIDER.string(provider_id.to_string()),
+            STAGE.string(stage.as_str()),
+            REASON.string(reason),
+        ],
+    );
+
+    Ok(())
+}
+
+/// Record that a client got its first token on behalf of the user, if they
+/// signed up through an upstream provider
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn record_first_token(
+    repo: &mut BoxRepository,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    user: &User,
+) -> Result<(), RepositoryError> {
+    let stage = UpstreamOAuthFunnelStage::FirstTokenIssued;
+    let Some(provider_id) = repo
+        .upstream_oauth_funnel()
+        .signup_provider_pending(user, stage)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    record_stage(repo, rng, clock, provider_id, Some(user), stage).await
+}
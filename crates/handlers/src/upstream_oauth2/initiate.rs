@@ -0,0 +1,199 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Logins initiated by the upstream provider, as described in the
+//! [OpenID Connect Core specification](https://openid.net/specs/openid-connect-core-1_0.html#ThirdPartyInitiatedLogin)
+
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Form,
+};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar, http_client_factory::HttpClientFactory, sentry::SentryEventID,
+};
+use mas_data_model::UpstreamOAuthProvider;
+use mas_keystore::{Encrypter, Keystore};
+use mas_router::{PostAuthAction, UrlBuilder};
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthProviderRepository, BoxClock, BoxRepository, BoxRng,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use ulid::Ulid;
+
+use super::{authorize::start_authorization, cache::MetadataCache};
+use crate::impl_from_error_for_route;
+
+/// The parameters sent by the upstream provider to initiate a login
+#[derive(Deserialize)]
+pub(crate) struct InitiateLoginParams {
+    /// The issuer of the provider initiating the login
+    iss: String,
+
+    /// A hint about the user to log in
+    login_hint: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error("Provider not found")]
+    ProviderNotFound,
+
+    #[error("Provider does not allow initiating logins")]
+    NotAllowed,
+
+    #[error("Issuer {got:?} does not match the provider issuer")]
+    IssuerMismatch { got: String },
+
+    #[error(transparent)]
+    Authorize(#[from] super::authorize::RouteError),
+
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        // Errors while starting the authorization are already reported there
+        if let Self::Authorize(e) = self {
+            return e.into_response();
+        }
+
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::ProviderNotFound => (StatusCode::NOT_FOUND, "Provider not found").into_response(),
+            Self::NotAllowed | Self::IssuerMismatch { .. } => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            Self::Authorize(_) => unreachable!(),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.initiate.get",
+    fields(upstream_oauth_provider.id = %provider_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(metadata_cache): State<MetadataCache>,
+    mut repo: BoxRepository,
+    State(keystore): State<Keystore>,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    cookie_jar: CookieJar,
+    Path(provider_id): Path<Ulid>,
+    Query(params): Query<InitiateLoginParams>,
+) -> Result<impl IntoResponse, RouteError> {
+    let provider = lookup_provider(&mut repo, provider_id, &params).await?;
+
+    let (cookie_jar, redirect) = start_authorization(
+        &mut rng,
+        &clock,
+        &http_client_factory,
+        &metadata_cache,
+        &mut repo,
+        &keystore,
+        &encrypter,
+        &url_builder,
+        cookie_jar,
+        &provider,
+        Some(post_auth_action(&provider)),
+        params.login_hint,
+    )
+    .await?;
+
+    repo.save().await?;
+
+    Ok((cookie_jar, redirect))
+}
+
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.initiate.post",
+    fields(upstream_oauth_provider.id = %provider_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(metadata_cache): State<MetadataCache>,
+    mut repo: BoxRepository,
+    State(keystore): State<Keystore>,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    cookie_jar: CookieJar,
+    Path(provider_id): Path<Ulid>,
+    Form(params): Form<InitiateLoginParams>,
+) -> Result<impl IntoResponse, RouteError> {
+    let provider = lookup_provider(&mut repo, provider_id, &params).await?;
+
+    let (cookie_jar, redirect) = start_authorization(
+        &mut rng,
+        &clock,
+        &http_client_factory,
+        &metadata_cache,
+        &mut repo,
+        &keystore,
+        &encrypter,
+        &url_builder,
+        cookie_jar,
+        &provider,
+        Some(post_auth_action(&provider)),
+        params.login_hint,
+    )
+    .await?;
+
+    repo.save().await?;
+
+    Ok((cookie_jar, redirect))
+}
+
+/// Lookup the provider initiating the login, and check that it is allowed to
+/// do so
+async fn lookup_provider(
+    repo: &mut BoxRepository,
+    provider_id: Ulid,
+    params: &InitiateLoginParams,
+) -> Result<UpstreamOAuthProvider, RouteError> {
+    let provider = repo
+        .upstream_oauth_provider()
+        .lookup(provider_id)
+        .await?
+        .filter(UpstreamOAuthProvider::enabled)
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    if !provider.allow_idp_initiated_login {
+        return Err(RouteError::NotAllowed);
+    }
+
+    if provider.issuer != params.iss {
+        return Err(RouteError::IssuerMismatch {
+            got: params.iss.clone(),
+        });
+    }
+
+    Ok(provider)
+}
+
+/// Where to send the user once they logged in through the provider
+fn post_auth_action(provider: &UpstreamOAuthProvider) -> PostAuthAction {
+    match provider.idp_initiated_login_client_id {
+        Some(client_id) => PostAuthAction::initiate_client_login(client_id),
+        None => PostAuthAction::manage_account(None),
+    }
+}
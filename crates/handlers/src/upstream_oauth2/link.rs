@@ -17,13 +17,17 @@ use mas_axum_utils::{
     sentry::SentryEventID,
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{User, UserAgent};
+use mas_data_model::{
+    AuditEventKind, UpstreamOAuthAuthorizationSession, UpstreamOAuthFunnelStage, UpstreamOAuthLink,
+    UpstreamOAuthProvider, UpstreamOAuthProviderTokenAuthMethod, User, UserAgent,
+};
 use mas_jose::jwt::Jwt;
 use mas_matrix::BoxHomeserverConnection;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob},
+    audit::AuditEventRepository,
+    job::{CheckSuspiciousLoginJob, DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob},
     upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthSessionRepository},
     user::{BrowserSessionRepository, UserEmailRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, RepositoryAccess,
@@ -38,14 +42,41 @@ use thiserror::Error;
 use tracing::warn;
 use ulid::Ulid;
 
-use super::{template::environment, UpstreamSessionsCookie};
+use super::{email_change, funnel, template::environment, UpstreamSessionsCookie};
 use crate::{
-    impl_from_error_for_route, views::shared::OptionalPostAuthAction, PreferredLanguage, SiteConfig,
+    account_deletion::cancel_pending_deletion,
+    homeserver::RequestHomeserver,
+    impl_from_error_for_route,
+    views::{shared::OptionalPostAuthAction, terms::go_next_or_accept_terms},
+    BoundActivityTracker, PreferredLanguage, SiteConfig,
 };
 
 const DEFAULT_LOCALPART_TEMPLATE: &str = "{{ user.preferred_username }}";
 const DEFAULT_DISPLAYNAME_TEMPLATE: &str = "{{ user.name }}";
 const DEFAULT_EMAIL_TEMPLATE: &str = "{{ user.email }}";
+const DEFAULT_AVATAR_TEMPLATE: &str = "{{ user.picture }}";
+
+/// The reason recorded when a required claim is missing from the response of
+/// the provider
+const CLAIMS_MISSING: &str = "required_claim_missing";
+
+/// Sign in with Apple doesn't include the user's name in the ID token, but
+/// sends it as a JSON-encoded `user` parameter to the callback, and only on
+/// the first login
+const SIGN_IN_WITH_APPLE_DISPLAYNAME_TEMPLATE: &str = r#"
+    {%- set apple_user = extra_callback_parameters.user | from_json -%}
+    {{- [apple_user.name.firstName, apple_user.name.lastName] | select | join(" ") -}}
+"#;
+
+/// Get the template to use for the display name if none is configured
+fn default_displayname_template(provider: &UpstreamOAuthProvider) -> &'static str {
+    if provider.token_endpoint_auth_method == UpstreamOAuthProviderTokenAuthMethod::SignInWithApple
+    {
+        SIGN_IN_WITH_APPLE_DISPLAYNAME_TEMPLATE
+    } else {
+        DEFAULT_DISPLAYNAME_TEMPLATE
+    }
+}
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -78,6 +109,10 @@ pub(crate) enum RouteError {
         source: minijinja::Error,
     },
 
+    /// Boolean attribute rendered to something else than `true` or `false`
+    #[error("Template {template:?} rendered to {value:?}, expected `true` or `false`")]
+    InvalidBooleanAttribute { template: String, value: String },
+
     /// Session was already consumed
     #[error("Session already consumed")]
     SessionConsumed,
@@ -159,6 +194,189 @@ fn render_attribute_template(
     }
 }
 
+/// Get the claims of the upstream session, merged from the userinfo endpoint
+/// response and the ID token. Claims from the ID token take precedence.
+fn upstream_claims(
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+) -> Result<minijinja::Value, RouteError> {
+    let mut claims = match upstream_session.userinfo() {
+        Some(serde_json::Value::Object(userinfo)) => userinfo.clone(),
+        _ => serde_json::Map::new(),
+    };
+
+    if let Some(id_token) = upstream_session.id_token() {
+        let id_token = Jwt::<'_, serde_json::Map<String, serde_json::Value>>::try_from(id_token)?;
+        claims.extend(id_token.into_parts().1);
+    }
+
+    Ok(minijinja::Value::from_serialize(&claims))
+}
+
+/// Build the environment used to render the attribute templates, with the
+/// claims of the upstream session
+fn claims_environment(
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+) -> Result<Environment<'static>, RouteError> {
+    let mut env = environment();
+    env.add_global("user", upstream_claims(upstream_session)?);
+    if let Some(extra_callback_parameters) = upstream_session.extra_callback_parameters() {
+        env.add_global(
+            "extra_callback_parameters",
+            minijinja::Value::from_serialize(extra_callback_parameters),
+        );
+    }
+
+    Ok(env)
+}
+
+/// Set whether the user can request admin from the upstream provider's claims,
+/// if the provider is configured to import it.
+///
+/// This happens on each login, so that the flag stays in sync with the
+/// upstream provider.
+///
+/// # Errors
+///
+/// Returns an error if the attribute is required but fails to render to a
+/// boolean, or if the repository fails
+async fn sync_admin_attribute(
+    repo: &mut BoxRepository,
+    rng: &mut BoxRng,
+    clock: &BoxClock,
+    provider: &UpstreamOAuthProvider,
+    env: &Environment<'_>,
+    user: User,
+) -> Result<User, RouteError> {
+    let preference = &provider.claims_imports.admin;
+    if preference.ignore() {
+        return Ok(user);
+    }
+
+    // The configuration requires a template if the attribute is not ignored
+    let Some(template) = preference.template.as_deref() else {
+        return Ok(user);
+    };
+
+    let Some(value) = render_attribute_template(env, template, preference.is_required())? else {
+        return Ok(user);
+    };
+
+    let can_request_admin = match value.trim() {
+        "true" => true,
+        "false" => false,
+        _ if preference.is_required() => {
+            return Err(RouteError::InvalidBooleanAttribute {
+                template: template.to_owned(),
+                value,
+            });
+        }
+        _ => {
+            warn!(%template, %value, "Admin attribute template did not render to a boolean");
+            return Ok(user);
+        }
+    };
+
+    if user.can_request_admin == can_request_admin {
+        return Ok(user);
+    }
+
+    tracing::info!(
+        user.id = %user.id,
+        upstream_oauth_provider.id = %provider.id,
+        can_request_admin,
+        "Updating the admin flag of the user from the upstream provider",
+    );
+
+    let user = repo
+        .user()
+        .set_can_request_admin(user, can_request_admin)
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_updated(rng, clock, &user))
+        .await?;
+
+    Ok(user)
+}
+
+/// Whether the upstream provider claims the email address is verified
+fn is_email_verified(claims: &minijinja::Value) -> bool {
+    claims
+        .get_item(&minijinja::Value::from("email_verified"))
+        .map(|v| v.is_true())
+        .unwrap_or(false)
+}
+
+/// Render the email address asserted by the upstream provider, and act on it
+/// if it changed since the last login through this link.
+///
+/// Nothing is done if the provider is configured to ignore the email claim.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+#[allow(clippy::too_many_arguments)]
+async fn sync_asserted_email(
+    repo: &mut BoxRepository,
+    rng: &mut BoxRng,
+    clock: &BoxClock,
+    provider: &UpstreamOAuthProvider,
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+    env: &Environment<'_>,
+    link: UpstreamOAuthLink,
+    user: &User,
+) -> Result<UpstreamOAuthLink, RouteError> {
+    if provider.claims_imports.email.ignore() {
+        return Ok(link);
+    }
+
+    let template = provider
+        .claims_imports
+        .email
+        .template
+        .as_deref()
+        .unwrap_or(DEFAULT_EMAIL_TEMPLATE);
+    let asserted_email = render_attribute_template(env, template, false)?;
+    let email_verified = is_email_verified(&upstream_claims(upstream_session)?);
+
+    let link = email_change::sync(
+        repo,
+        rng,
+        clock,
+        provider,
+        link,
+        user,
+        asserted_email,
+        email_verified,
+    )
+    .await?;
+
+    Ok(link)
+}
+
+/// Record that the user dropped off the signup funnel because the claims of
+/// the provider could not be mapped to a new account, and save the repository
+async fn record_dropoff_and_save(
+    mut repo: BoxRepository,
+    rng: &mut BoxRng,
+    clock: &BoxClock,
+    provider: &UpstreamOAuthProvider,
+    reason: &'static str,
+) -> Result<(), RouteError> {
+    funnel::record_dropoff(
+        &mut repo,
+        rng,
+        clock,
+        provider.id,
+        None,
+        UpstreamOAuthFunnelStage::ClaimsMapped,
+        reason,
+    )
+    .await?;
+    repo.save().await?;
+    Ok(())
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "lowercase", tag = "action")]
 pub(crate) enum FormData {
@@ -188,17 +406,21 @@ impl ToFormState for FormData {
 pub(crate) async fn get(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     mut repo: BoxRepository,
     mut policy: Policy,
     PreferredLanguage(locale): PreferredLanguage,
+    request_homeserver: RequestHomeserver,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(homeserver): State<BoxHomeserverConnection>,
+    activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     Path(link_id): Path<Ulid>,
 ) -> Result<impl IntoResponse, RouteError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
     let sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar);
     let (session_id, post_auth_action) = sessions_cookie
         .lookup_link(link_id)
@@ -232,12 +454,37 @@ pub(crate) async fn get(
 
     let (user_session_info, cookie_jar) = cookie_jar.session_info();
     let (csrf_token, mut cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let maybe_user_session = user_session_info.load_session(&mut repo).await?;
+    let maybe_user_session = user_session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let response = match (maybe_user_session, link.user_id) {
-        (Some(session), Some(user_id)) if session.user.id == user_id => {
+        (Some(mut session), Some(user_id)) if session.user.id == user_id => {
             // Session already linked, and link matches the currently logged
             // user. Mark the session as consumed and renew the authentication.
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(link.provider_id)
+                .await?
+                .ok_or(RouteError::ProviderNotFound)?;
+
+            let env = claims_environment(&upstream_session)?;
+            session.user =
+                sync_admin_attribute(&mut repo, &mut rng, &clock, &provider, &env, session.user)
+                    .await?;
+
+            sync_asserted_email(
+                &mut repo,
+                &mut rng,
+                &clock,
+                &provider,
+                &upstream_session,
+                &env,
+                link,
+                &session.user,
+            )
+            .await?;
+
             let upstream_session = repo
                 .upstream_oauth_session()
                 .consume(&clock, upstream_session)
@@ -249,9 +496,18 @@ pub(crate) async fn get(
 
             cookie_jar = cookie_jar.set_session(&session);
 
+            let next = go_next_or_accept_terms(
+                &mut repo,
+                &site_config,
+                &session.user,
+                &post_auth_action,
+                &url_builder,
+            )
+            .await?;
+
             repo.save().await?;
 
-            post_auth_action.go_next(&url_builder).into_response()
+            next.into_response()
         }
 
         (Some(user_session), Some(user_id)) => {
@@ -285,13 +541,37 @@ pub(crate) async fn get(
         }
 
         (None, Some(user_id)) => {
-            // Session linked, but user not logged in: do the login
+            // Session linked, but user not logged in: do the login. Users waiting for
+            // the deletion of their account can still log in to cancel it
             let user = repo
                 .user()
                 .lookup(user_id)
                 .await?
-                .filter(mas_data_model::User::is_valid)
+                .filter(|user| user.is_valid() || user.is_pending_deletion(clock.now()))
                 .ok_or(RouteError::UserNotFound)?;
+            let user = cancel_pending_deletion(&mut rng, &clock, &mut repo, user).await?;
+
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(link.provider_id)
+                .await?
+                .ok_or(RouteError::ProviderNotFound)?;
+
+            let env = claims_environment(&upstream_session)?;
+            let user =
+                sync_admin_attribute(&mut repo, &mut rng, &clock, &provider, &env, user).await?;
+
+            let link = sync_asserted_email(
+                &mut repo,
+                &mut rng,
+                &clock,
+                &provider,
+                &upstream_session,
+                &env,
+                link,
+                &user,
+            )
+            .await?;
 
             let session = repo
                 .browser_session()
@@ -307,41 +587,65 @@ pub(crate) async fn get(
                 .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
                 .await?;
 
+            let event = repo
+                .audit_event()
+                .add(
+                    &mut rng,
+                    &clock,
+                    AuditEventKind::Login,
+                    Some(user.id),
+                    Some(user.id),
+                    activity_tracker.ip(),
+                    raw_user_agent,
+                    serde_json::json!({
+                        "method": "upstream_oauth2",
+                        "upstream_oauth_provider_id": link.provider_id.to_string(),
+                        "browser_session_id": session.id.to_string(),
+                    }),
+                )
+                .await?;
+
+            repo.job()
+                .schedule_job(CheckSuspiciousLoginJob::new(&event))
+                .await?;
+
+            repo.job()
+                .schedule_job(DispatchWebhookJob::session_created(
+                    &mut rng, &clock, &user, "browser", session.id,
+                ))
+                .await?;
+
             cookie_jar = sessions_cookie
                 .consume_link(link_id)?
                 .save(cookie_jar, &clock);
             cookie_jar = cookie_jar.set_session(&session);
 
+            let next = go_next_or_accept_terms(
+                &mut repo,
+                &site_config,
+                &session.user,
+                &post_auth_action,
+                &url_builder,
+            )
+            .await?;
+
             repo.save().await?;
 
-            post_auth_action.go_next(&url_builder).into_response()
+            next.into_response()
         }
 
         (None, None) => {
             // Session not linked and used not logged in: suggest creating an
             // account or logging in an existing user
-            let id_token = upstream_session
-                .id_token()
-                .map(Jwt::<'_, minijinja::Value>::try_from)
-                .transpose()?;
-
             let provider = repo
                 .upstream_oauth_provider()
                 .lookup(link.provider_id)
                 .await?
                 .ok_or(RouteError::ProviderNotFound)?;
 
-            let payload = id_token
-                .map(|id_token| id_token.into_parts().1)
-                .unwrap_or_default();
-
             let ctx = UpstreamRegister::default();
 
-            let env = {
-                let mut e = environment();
-                e.add_global("user", payload);
-                e
-            };
+            let env = claims_environment(&upstream_session)?;
 
             let ctx = if provider.claims_imports.displayname.ignore() {
                 ctx
@@ -351,13 +655,22 @@ pub(crate) async fn get(
                     .displayname
                     .template
                     .as_deref()
-                    .unwrap_or(DEFAULT_DISPLAYNAME_TEMPLATE);
+                    .unwrap_or_else(|| default_displayname_template(&provider));
 
-                match render_attribute_template(
+                let value = match render_attribute_template(
                     &env,
                     template,
                     provider.claims_imports.displayname.is_required(),
-                )? {
+                ) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        record_dropoff_and_save(repo, &mut rng, &clock, &provider, CLAIMS_MISSING)
+                            .await?;
+                        return Err(e);
+                    }
+                };
+
+                match value {
                     Some(value) => ctx
                         .with_display_name(value, provider.claims_imports.displayname.is_forced()),
                     None => ctx,
@@ -374,11 +687,20 @@ pub(crate) async fn get(
                     .as_deref()
                     .unwrap_or(DEFAULT_EMAIL_TEMPLATE);
 
-                match render_attribute_template(
+                let value = match render_attribute_template(
                     &env,
                     template,
                     provider.claims_imports.email.is_required(),
-                )? {
+                ) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        record_dropoff_and_save(repo, &mut rng, &clock, &provider, CLAIMS_MISSING)
+                            .await?;
+                        return Err(e);
+                    }
+                };
+
+                match value {
                     Some(value) => ctx.with_email(value, provider.claims_imports.email.is_forced()),
                     None => ctx,
                 }
@@ -394,18 +716,30 @@ pub(crate) async fn get(
                     .as_deref()
                     .unwrap_or(DEFAULT_LOCALPART_TEMPLATE);
 
-                match render_attribute_template(
+                let localpart = match render_attribute_template(
                     &env,
                     template,
                     provider.claims_imports.localpart.is_required(),
-                )? {
+                ) {
+                    Ok(localpart) => localpart,
+                    Err(e) => {
+                        record_dropoff_and_save(repo, &mut rng, &clock, &provider, CLAIMS_MISSING)
+                            .await?;
+                        return Err(e);
+                    }
+                };
+
+                match localpart {
                     Some(localpart) => {
                         // We could run policy & existing user checks when the user submits the
                         // form, but this lead to poor UX. This is why we do
                         // it ahead of time here.
-                        let maybe_existing_user = repo.user().find_by_username(&localpart).await?;
+                        let maybe_existing_user = repo
+                            .user()
+                            .find_by_username(request_homeserver.user_homeserver(), &localpart)
+                            .await?;
                         let is_available = homeserver
-                            .is_localpart_available(&localpart)
+                            .is_localpart_available_on(request_homeserver.0.as_deref(), &localpart)
                             .await
                             .map_err(RouteError::HomeserverConnection)?;
 
@@ -424,15 +758,27 @@ pub(crate) async fn get(
                             which is not linked to that upstream account"#
                                 ))
                                 .with_language(&locale);
+                            let content = templates.render_error(&ctx)?;
+
+                            record_dropoff_and_save(
+                                repo,
+                                &mut rng,
+                                &clock,
+                                &provider,
+                                "username_unavailable",
+                            )
+                            .await?;
 
-                            return Ok((
-                                cookie_jar,
-                                Html(templates.render_error(&ctx)?).into_response(),
-                            ));
+                            return Ok((cookie_jar, Html(content).into_response()));
                         }
 
+                        let requester =
+                            Requester::new(activity_tracker.ip(), raw_user_agent.clone())
+                                .with_amr("fed");
                         let res = policy
-                            .evaluate_upstream_oauth_register(&localpart, None)
+                            .evaluate_upstream_oauth_register(
+                                &localpart, None, &provider, &requester,
+                            )
                             .await?;
 
                         if !res.valid() {
@@ -444,11 +790,18 @@ pub(crate) async fn get(
                             which does not pass the policy check: {res}"#
                                 ))
                                 .with_language(&locale);
+                            let content = templates.render_error(&ctx)?;
+
+                            record_dropoff_and_save(
+                                repo,
+                                &mut rng,
+                                &clock,
+                                &provider,
+                                "policy_violation",
+                            )
+                            .await?;
 
-                            return Ok((
-                                cookie_jar,
-                                Html(templates.render_error(&ctx)?).into_response(),
-                            ));
+                            return Ok((cookie_jar, Html(content).into_response()));
                         }
 
                         ctx.with_localpart(localpart, provider.claims_imports.localpart.is_forced())
@@ -458,8 +811,20 @@ pub(crate) async fn get(
             };
 
             let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+            let content = templates.render_upstream_oauth2_do_register(&ctx)?;
 
-            Html(templates.render_upstream_oauth2_do_register(&ctx)?).into_response()
+            funnel::record_stage(
+                &mut repo,
+                &mut rng,
+                &clock,
+                provider.id,
+                None,
+                UpstreamOAuthFunnelStage::ClaimsMapped,
+            )
+            .await?;
+            repo.save().await?;
+
+            Html(content).into_response()
         }
     };
 
@@ -476,10 +841,12 @@ pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
     mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     mut policy: Policy,
     PreferredLanguage(locale): PreferredLanguage,
+    request_homeserver: RequestHomeserver,
     State(templates): State<Templates>,
     State(homeserver): State<BoxHomeserverConnection>,
     State(url_builder): State<UrlBuilder>,
@@ -488,6 +855,7 @@ pub(crate) async fn post(
     Form(form): Form<ProtectedForm<FormData>>,
 ) -> Result<Response, RouteError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
     let form = cookie_jar.verify_form(&clock, form)?;
 
     let sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar);
@@ -523,17 +891,44 @@ pub(crate) async fn post(
 
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (user_session_info, cookie_jar) = cookie_jar.session_info();
-    let maybe_user_session = user_session_info.load_session(&mut repo).await?;
+    let maybe_user_session = user_session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
     let form_state = form.to_form_state();
 
     let session = match (maybe_user_session, link.user_id, form) {
-        (Some(session), None, FormData::Link) => {
+        (Some(mut session), None, FormData::Link) => {
             // The user is already logged in, the link is not linked to any user, and the
             // user asked to link their account.
             repo.upstream_oauth_link()
                 .associate_to_user(&link, &session.user)
                 .await?;
 
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(link.provider_id)
+                .await?
+                .ok_or(RouteError::ProviderNotFound)?;
+
+            let env = claims_environment(&upstream_session)?;
+            session.user =
+                sync_admin_attribute(&mut repo, &mut rng, &clock, &provider, &env, session.user)
+                    .await?;
+
+            // Remember the email address asserted by the provider, to detect when it
+            // changes on the next logins
+            sync_asserted_email(
+                &mut repo,
+                &mut rng,
+                &clock,
+                &provider,
+                &upstream_session,
+                &env,
+                link.clone(),
+                &session.user,
+            )
+            .await?;
+
             session
         }
 
@@ -557,31 +952,29 @@ pub(crate) async fn post(
             let import_display_name = import_display_name.is_some();
             let accept_terms = accept_terms.is_some();
 
-            let id_token = upstream_session
-                .id_token()
-                .map(Jwt::<'_, minijinja::Value>::try_from)
-                .transpose()?;
-
             let provider = repo
                 .upstream_oauth_provider()
                 .lookup(link.provider_id)
                 .await?
                 .ok_or(RouteError::ProviderNotFound)?;
 
-            let payload = id_token
-                .map(|id_token| id_token.into_parts().1)
-                .unwrap_or_default();
+            let payload = upstream_claims(&upstream_session)?;
 
             // Is the email verified according to the upstream provider?
-            let provider_email_verified = payload
-                .get_item(&minijinja::Value::from("email_verified"))
-                .map(|v| v.is_true())
-                .unwrap_or(false);
+            let provider_email_verified = is_email_verified(&payload);
 
-            // Let's try to import the claims from the ID token
+            // Let's try to import the claims from the ID token and userinfo
             let env = {
                 let mut e = environment();
                 e.add_global("user", payload);
+                if let Some(extra_callback_parameters) =
+                    upstream_session.extra_callback_parameters()
+                {
+                    e.add_global(
+                        "extra_callback_parameters",
+                        minijinja::Value::from_serialize(extra_callback_parameters),
+                    );
+                }
                 e
             };
 
@@ -598,7 +991,7 @@ pub(crate) async fn post(
                     .displayname
                     .template
                     .as_deref()
-                    .unwrap_or(DEFAULT_DISPLAYNAME_TEMPLATE);
+                    .unwrap_or_else(|| default_displayname_template(&provider));
 
                 render_attribute_template(
                     &env,
@@ -618,6 +1011,25 @@ pub(crate) async fn post(
                 ctx
             };
 
+            // There is no checkbox for the avatar on the registration form, so it is
+            // always imported unless it's ignored
+            let avatar_url = if provider.claims_imports.avatar.ignore() {
+                None
+            } else {
+                let template = provider
+                    .claims_imports
+                    .avatar
+                    .template
+                    .as_deref()
+                    .unwrap_or(DEFAULT_AVATAR_TEMPLATE);
+
+                render_attribute_template(
+                    &env,
+                    template,
+                    provider.claims_imports.avatar.is_required(),
+                )?
+            };
+
             let email = if provider.claims_imports.email.should_import(import_email) {
                 let template = provider
                     .claims_imports
@@ -687,11 +1099,14 @@ pub(crate) async fn post(
             );
 
             // Check if there is an existing user
-            let existing_user = repo.user().find_by_username(&username).await?;
+            let existing_user = repo
+                .user()
+                .find_by_username(request_homeserver.user_homeserver(), &username)
+                .await?;
 
             // Ask the homeserver to make sure the username is valid
             let is_available = homeserver
-                .is_localpart_available(&username)
+                .is_localpart_available_on(request_homeserver.0.as_deref(), &username)
                 .await
                 .map_err(RouteError::HomeserverConnection)?;
 
@@ -734,8 +1149,15 @@ pub(crate) async fn post(
             }
 
             // Policy check
+            let requester =
+                Requester::new(activity_tracker.ip(), raw_user_agent.clone()).with_amr("fed");
             let res = policy
-                .evaluate_upstream_oauth_register(&username, email.as_deref())
+                .evaluate_upstream_oauth_register(
+                    &username,
+                    email.as_deref(),
+                    &provider,
+                    &requester,
+                )
                 .await?;
             if !res.valid() {
                 let form_state =
@@ -767,7 +1189,28 @@ pub(crate) async fn post(
             }
 
             // Now we can create the user
-            let user = repo.user().add(&mut rng, &clock, username).await?;
+            let user = repo
+                .user()
+                .add_for_homeserver(
+                    &mut rng,
+                    &clock,
+                    request_homeserver.user_homeserver(),
+                    username,
+                )
+                .await?;
+
+            let user =
+                sync_admin_attribute(&mut repo, &mut rng, &clock, &provider, &env, user).await?;
+
+            funnel::record_stage(
+                &mut repo,
+                &mut rng,
+                &clock,
+                provider.id,
+                Some(&user),
+                UpstreamOAuthFunnelStage::UserCreated,
+            )
+            .await?;
 
             if let Some(terms_url) = &site_config.tos_uri {
                 repo.user_terms()
@@ -783,8 +1226,17 @@ pub(crate) async fn post(
                 job = job.set_display_name(name);
             }
 
+            // Same for the avatar, which gets fetched and re-hosted by the job if needed
+            if let Some(avatar_url) = avatar_url {
+                job = job.set_avatar_url(avatar_url);
+            }
+
             repo.job().schedule_job(job).await?;
 
+            repo.job()
+                .schedule_job(DispatchWebhookJob::user_registered(&mut rng, &clock, &user))
+                .await?;
+
             // If we have an email, add it to the user
             if let Some(email) = email {
                 let user_email = repo
@@ -811,6 +1263,20 @@ pub(crate) async fn post(
                 .associate_to_user(&link, &user)
                 .await?;
 
+            // Remember the email address asserted by the provider, to detect when it
+            // changes on the next logins
+            sync_asserted_email(
+                &mut repo,
+                &mut rng,
+                &clock,
+                &provider,
+                &upstream_session,
+                &env,
+                link.clone(),
+                &user,
+            )
+            .await?;
+
             repo.browser_session()
                 .add(&mut rng, &clock, &user, user_agent)
                 .await?
@@ -828,14 +1294,55 @@ pub(crate) async fn post(
         .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
         .await?;
 
+    let event = repo
+        .audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::Login,
+            Some(session.user.id),
+            Some(session.user.id),
+            activity_tracker.ip(),
+            raw_user_agent,
+            serde_json::json!({
+                "method": "upstream_oauth2",
+                "upstream_oauth_provider_id": link.provider_id.to_string(),
+                "browser_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(CheckSuspiciousLoginJob::new(&event))
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_created(
+            &mut rng,
+            &clock,
+            &session.user,
+            "browser",
+            session.id,
+        ))
+        .await?;
+
     let cookie_jar = sessions_cookie
         .consume_link(link_id)?
         .save(cookie_jar, &clock);
     let cookie_jar = cookie_jar.set_session(&session);
 
+    let next = go_next_or_accept_terms(
+        &mut repo,
+        &site_config,
+        &session.user,
+        &post_auth_action,
+        &url_builder,
+    )
+    .await?;
+
     repo.save().await?;
 
-    Ok((cookie_jar, post_auth_action.go_next(&url_builder)).into_response())
+    Ok((cookie_jar, next).into_response())
 }
 
 #[cfg(test)]
@@ -843,11 +1350,12 @@ mod tests {
     use hyper::{header::CONTENT_TYPE, Request, StatusCode};
     use mas_data_model::{
         UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderImportPreference,
+        UpstreamOAuthProviderTokenAuthMethod,
     };
-    use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+    use mas_iana::jose::JsonWebSignatureAlg;
     use mas_jose::jwt::{JsonWebSignatureHeader, Jwt};
     use mas_router::Route;
-    use mas_storage::upstream_oauth2::UpstreamOAuthProviderParams;
+    use mas_storage::{upstream_oauth2::UpstreamOAuthProviderParams, user::UserHomeserver};
     use oauth2_types::scope::{Scope, OPENID};
     use sqlx::PgPool;
 
@@ -906,7 +1414,7 @@ mod tests {
                     human_name: Some("Example Ltd.".to_owned()),
                     brand_name: None,
                     scope: Scope::from_iter([OPENID]),
-                    token_endpoint_auth_method: OAuthClientAuthenticationMethod::None,
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
                     token_endpoint_signing_alg: None,
                     client_id: "client".to_owned(),
                     encrypted_client_secret: None,
@@ -916,7 +1424,17 @@ mod tests {
                     jwks_uri_override: None,
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
                     additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
                 },
             )
             .await
@@ -943,7 +1461,14 @@ mod tests {
 
         let session = repo
             .upstream_oauth_session()
-            .complete_with_link(&state.clock, session, &link, Some(id_token.into_string()))
+            .complete_with_link(
+                &state.clock,
+                session,
+                &link,
+                Some(id_token.into_string()),
+                None,
+                None,
+            )
             .await
             .unwrap();
 
@@ -991,7 +1516,7 @@ mod tests {
         let mut repo = state.repository().await.unwrap();
         let user = repo
             .user()
-            .find_by_username("john")
+            .find_by_username(UserHomeserver::Main, "john")
             .await
             .unwrap()
             .expect("user exists");
@@ -1015,4 +1540,138 @@ mod tests {
         assert_eq!(email.email, "john@example.com");
         assert!(email.confirmed_at.is_some());
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_login_syncs_admin(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        let claims_imports = UpstreamOAuthProviderClaimsImports {
+            admin: UpstreamOAuthProviderImportPreference {
+                action: mas_data_model::UpstreamOAuthProviderImportAction::Force,
+                template: Some(r#"{{ "admins" in user.groups }}"#.to_owned()),
+            },
+            ..UpstreamOAuthProviderClaimsImports::default()
+        };
+
+        // The user is not in the admins group anymore
+        let id_token = serde_json::json!({
+            "groups": ["users"],
+        });
+
+        let key = state
+            .key_store
+            .signing_key_for_algorithm(&JsonWebSignatureAlg::Rs256)
+            .unwrap();
+
+        let signer = key
+            .params()
+            .signing_key_for_alg(&JsonWebSignatureAlg::Rs256)
+            .unwrap();
+        let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Rs256);
+        let id_token = Jwt::sign_with_rng(&mut rng, header, id_token, &signer).unwrap();
+
+        // Provision a provider, an admin user and a link to it
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: Some("Example Ltd.".to_owned()),
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports,
+                    authorization_endpoint_override: None,
+                    token_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let user = repo.user().set_can_request_admin(user, true).await.unwrap();
+
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "subject".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &user)
+            .await
+            .unwrap();
+
+        let session = repo
+            .upstream_oauth_session()
+            .add(
+                &mut rng,
+                &state.clock,
+                &provider,
+                "state".to_owned(),
+                None,
+                "nonce".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        let session = repo
+            .upstream_oauth_session()
+            .complete_with_link(
+                &state.clock,
+                session,
+                &link,
+                Some(id_token.into_string()),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        let cookie_jar = state.cookie_jar();
+        let upstream_sessions = UpstreamSessionsCookie::default()
+            .add(session.id, provider.id, "state".to_owned(), None)
+            .add_link_to_session(session.id, link.id)
+            .unwrap();
+        let cookie_jar = upstream_sessions.save(cookie_jar, &state.clock);
+        cookies.import(cookie_jar);
+
+        // Logging in through the link should clear the admin flag
+        let request = Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        assert!(!user.can_request_admin);
+    }
 }
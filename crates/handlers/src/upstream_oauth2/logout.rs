@@ -0,0 +1,108 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Propagation of the logouts to the upstream providers, through
+//! [RP-Initiated Logout](https://openid.net/specs/openid-connect-rpinitiated-1_0.html)
+
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_data_model::{AuthenticationMethod, BrowserSession};
+use mas_oidc_client::{
+    error::DiscoveryError,
+    requests::rp_initiated_logout::{build_end_session_url, LogoutData},
+};
+use mas_router::UrlBuilder;
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository},
+    user::BrowserSessionRepository,
+    BoxRepository, BoxRng, RepositoryError,
+};
+use thiserror::Error;
+use url::Url;
+
+use super::cache::{LazyProviderInfos, MetadataCache};
+
+#[derive(Debug, Error)]
+pub(crate) enum UpstreamLogoutError {
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+
+    #[error(transparent)]
+    Discovery(#[from] DiscoveryError),
+
+    #[error("Could not build the end session URL")]
+    EndSessionUrl(#[from] serde_urlencoded::ser::Error),
+}
+
+/// Get the URL where the user should be sent to log out of the upstream
+/// provider which last authenticated the given browser session
+///
+/// Returns `None` if the session was not authenticated through an upstream
+/// provider, if the provider doesn't propagate logouts, or if it has no end
+/// session endpoint. Once logged out, the provider sends the user back to the
+/// login page.
+pub(crate) async fn upstream_logout_url(
+    rng: &mut BoxRng,
+    http_client_factory: &HttpClientFactory,
+    metadata_cache: &MetadataCache,
+    repo: &mut BoxRepository,
+    url_builder: &UrlBuilder,
+    browser_session: &BrowserSession,
+) -> Result<Option<Url>, UpstreamLogoutError> {
+    let Some(authentication) = repo
+        .browser_session()
+        .get_last_authentication(browser_session)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let AuthenticationMethod::UpstreamOAuth2 {
+        upstream_oauth2_session_id,
+    } = authentication.authentication_method
+    else {
+        return Ok(None);
+    };
+
+    let Some(session) = repo
+        .upstream_oauth_session()
+        .lookup(upstream_oauth2_session_id)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let Some(provider) = repo
+        .upstream_oauth_provider()
+        .lookup(session.provider_id)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    if !provider.propagate_logout {
+        return Ok(None);
+    }
+
+    let http_service = http_client_factory.http_service("upstream_oauth2.logout");
+    let mut lazy_metadata = LazyProviderInfos::new(metadata_cache, &provider, &http_service);
+    let Some(end_session_endpoint) = lazy_metadata.end_session_endpoint().await?.cloned() else {
+        tracing::warn!(
+            upstream_oauth_provider.id = %provider.id,
+            "Provider has no end session endpoint, not propagating the logout"
+        );
+        return Ok(None);
+    };
+
+    let logout_data = LogoutData {
+        id_token_hint: session.id_token().map(ToOwned::to_owned),
+        client_id: Some(provider.client_id.clone()),
+        post_logout_redirect_uri: Some(url_builder.absolute_url_for(&mas_router::Login::default())),
+        ..LogoutData::default()
+    };
+
+    let (url, _state) = build_end_session_url(end_session_endpoint, logout_data, rng)?;
+
+    Ok(Some(url))
+}
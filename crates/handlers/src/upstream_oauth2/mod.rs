@@ -6,10 +6,11 @@
 
 use std::string::FromUtf8Error;
 
-use mas_data_model::UpstreamOAuthProvider;
-use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
-use mas_keystore::{DecryptError, Encrypter, Keystore};
+use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderTokenAuthMethod};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_keystore::{DecryptError, Encrypter, Keystore, PrivateKey};
 use mas_oidc_client::types::client_credentials::{ClientCredentials, JwtSigningMethod};
+use serde::Deserialize;
 use thiserror::Error;
 use url::Url;
 
@@ -17,7 +18,11 @@ pub(crate) mod authorize;
 pub(crate) mod cache;
 pub(crate) mod callback;
 mod cookie;
+pub(crate) mod email_change;
+pub(crate) mod funnel;
+pub(crate) mod initiate;
 pub(crate) mod link;
+pub(crate) mod logout;
 mod template;
 
 use self::cookie::UpstreamSessions as UpstreamSessionsCookie;
@@ -39,6 +44,24 @@ enum ProviderCredentialsError {
         #[from]
         inner: FromUtf8Error,
     },
+
+    #[error("Invalid Sign in with Apple parameters")]
+    InvalidSignInWithAppleParameters {
+        #[from]
+        inner: serde_json::Error,
+    },
+
+    #[error("Invalid Sign in with Apple private key")]
+    InvalidSignInWithAppleKey,
+}
+
+/// The parameters used to generate the client secret for Sign in with Apple,
+/// stored encrypted in place of the client secret
+#[derive(Deserialize)]
+struct SignInWithAppleParameters {
+    private_key: String,
+    team_id: String,
+    key_id: String,
 }
 
 fn client_credentials_for_provider(
@@ -61,28 +84,34 @@ fn client_credentials_for_provider(
         .transpose()?;
 
     let client_credentials = match provider.token_endpoint_auth_method {
-        OAuthClientAuthenticationMethod::None => ClientCredentials::None { client_id },
-        OAuthClientAuthenticationMethod::ClientSecretPost => ClientCredentials::ClientSecretPost {
-            client_id,
-            client_secret: client_secret.ok_or(ProviderCredentialsError::MissingClientSecret)?,
-        },
-        OAuthClientAuthenticationMethod::ClientSecretBasic => {
+        UpstreamOAuthProviderTokenAuthMethod::None => ClientCredentials::None { client_id },
+        UpstreamOAuthProviderTokenAuthMethod::ClientSecretPost => {
+            ClientCredentials::ClientSecretPost {
+                client_id,
+                client_secret: client_secret
+                    .ok_or(ProviderCredentialsError::MissingClientSecret)?,
+            }
+        }
+        UpstreamOAuthProviderTokenAuthMethod::ClientSecretBasic => {
             ClientCredentials::ClientSecretBasic {
                 client_id,
                 client_secret: client_secret
                     .ok_or(ProviderCredentialsError::MissingClientSecret)?,
             }
         }
-        OAuthClientAuthenticationMethod::ClientSecretJwt => ClientCredentials::ClientSecretJwt {
-            client_id,
-            client_secret: client_secret.ok_or(ProviderCredentialsError::MissingClientSecret)?,
-            signing_algorithm: provider
-                .token_endpoint_signing_alg
-                .clone()
-                .unwrap_or(JsonWebSignatureAlg::Rs256),
-            token_endpoint: token_endpoint.clone(),
-        },
-        OAuthClientAuthenticationMethod::PrivateKeyJwt => ClientCredentials::PrivateKeyJwt {
+        UpstreamOAuthProviderTokenAuthMethod::ClientSecretJwt => {
+            ClientCredentials::ClientSecretJwt {
+                client_id,
+                client_secret: client_secret
+                    .ok_or(ProviderCredentialsError::MissingClientSecret)?,
+                signing_algorithm: provider
+                    .token_endpoint_signing_alg
+                    .clone()
+                    .unwrap_or(JsonWebSignatureAlg::Rs256),
+                token_endpoint: token_endpoint.clone(),
+            }
+        }
+        UpstreamOAuthProviderTokenAuthMethod::PrivateKeyJwt => ClientCredentials::PrivateKeyJwt {
             client_id,
             jwt_signing_method: JwtSigningMethod::Keystore(keystore.clone()),
             signing_algorithm: provider
@@ -91,8 +120,23 @@ fn client_credentials_for_provider(
                 .unwrap_or(JsonWebSignatureAlg::Rs256),
             token_endpoint: token_endpoint.clone(),
         },
-        // XXX: The database should never have an unsupported method in it
-        _ => unreachable!(),
+        UpstreamOAuthProviderTokenAuthMethod::SignInWithApple => {
+            let params = client_secret.ok_or(ProviderCredentialsError::MissingClientSecret)?;
+            let params: SignInWithAppleParameters = serde_json::from_str(&params)?;
+
+            let PrivateKey::EcP256(key) = PrivateKey::load_pem(&params.private_key)
+                .map_err(|_| ProviderCredentialsError::InvalidSignInWithAppleKey)?
+            else {
+                return Err(ProviderCredentialsError::InvalidSignInWithAppleKey);
+            };
+
+            ClientCredentials::SignInWithApple {
+                client_id,
+                key: *key,
+                key_id: params.key_id,
+                team_id: params.team_id,
+            }
+        }
     };
 
     Ok(client_credentials)
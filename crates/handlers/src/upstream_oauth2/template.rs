@@ -75,6 +75,14 @@ fn string(value: &Value) -> String {
     value.to_string()
 }
 
+fn from_json(value: &str) -> Result<Value, Error> {
+    let value: serde_json::Value = serde_json::from_str(value).map_err(|e| {
+        Error::new(ErrorKind::InvalidOperation, "Failed to decode JSON string").with_source(e)
+    })?;
+
+    Ok(Value::from_serialize(value))
+}
+
 pub fn environment() -> Environment<'static> {
     let mut env = Environment::new();
 
@@ -83,6 +91,7 @@ pub fn environment() -> Environment<'static> {
     env.add_filter("b64encode", b64encode);
     env.add_filter("tlvdecode", tlvdecode);
     env.add_filter("string", string);
+    env.add_filter("from_json", from_json);
 
     env
 }
@@ -132,4 +141,20 @@ mod tests {
             .unwrap();
         assert_eq!(res, "unpadded");
     }
+
+    #[test]
+    fn test_from_json() {
+        let env = environment();
+
+        let res = env
+            .render_str(
+                r#"
+                    {%- set user = '{"name":{"firstName":"John","lastName":"Doe"}}' | from_json -%}
+                    {{- [user.name.firstName, user.name.lastName] | select | join(" ") -}}
+                "#,
+                (),
+            )
+            .unwrap();
+        assert_eq!(res, "John Doe");
+    }
 }
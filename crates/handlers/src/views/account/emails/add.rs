@@ -8,13 +8,14 @@ use axum::{
     extract::{Form, Query, State},
     response::{Html, IntoResponse, Response},
 };
+use axum_extra::typed_header::TypedHeader;
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
 use mas_data_model::SiteConfig;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
     job::{JobRepositoryExt, VerifyEmailJob},
@@ -46,7 +47,9 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -87,13 +90,16 @@ pub(crate) async fn post(
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     Query(query): Query<OptionalPostAuthAction>,
     Form(form): Form<ProtectedForm<EmailForm>>,
 ) -> Result<Response, FancyError> {
     let form = cookie_jar.verify_form(&clock, form)?;
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -115,7 +121,18 @@ pub(crate) async fn post(
     }
 
     // Run the email policy
-    let res = policy.evaluate_email(&form.email).await?;
+    let last_authentication = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await?;
+    let requester = Requester::new(
+        activity_tracker.ip(),
+        user_agent.map(|ua| ua.as_str().to_owned()),
+    )
+    .with_authentication(last_authentication.as_ref());
+    let res = policy
+        .evaluate_email(&form.email, Some(&session.user), &requester)
+        .await?;
     if !res.valid() {
         return Err(FancyError::new(
             ErrorContext::new()
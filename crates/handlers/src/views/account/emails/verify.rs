@@ -14,6 +14,7 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
+use mas_data_model::SiteConfig;
 use mas_router::UrlBuilder;
 use mas_storage::{
     job::{JobRepositoryExt, ProvisionUserJob},
@@ -40,6 +41,7 @@ pub struct CodeForm {
 pub(crate) async fn get(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
@@ -52,7 +54,9 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -94,6 +98,7 @@ pub(crate) async fn get(
 )]
 pub(crate) async fn post(
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
@@ -105,7 +110,9 @@ pub(crate) async fn post(
     let form = cookie_jar.verify_form(&clock, form)?;
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
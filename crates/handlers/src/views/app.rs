@@ -8,9 +8,10 @@ use axum::{
     extract::{Query, State},
     response::{Html, IntoResponse},
 };
-use mas_axum_utils::{cookies::CookieJar, FancyError, SessionInfoExt};
+use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, FancyError, SessionInfoExt};
+use mas_data_model::SiteConfig;
 use mas_router::{PostAuthAction, UrlBuilder};
-use mas_storage::{BoxClock, BoxRepository};
+use mas_storage::{BoxClock, BoxRepository, BoxRng};
 use mas_templates::{AppContext, TemplateContext, Templates};
 
 use crate::{BoundActivityTracker, PreferredLanguage};
@@ -23,11 +24,15 @@ pub async fn get(
     State(url_builder): State<UrlBuilder>,
     action: Option<Query<mas_router::AccountAction>>,
     mut repo: BoxRepository,
+    mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
 ) -> Result<impl IntoResponse, FancyError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
-    let session = session_info.load_session(&mut repo).await?;
+    let session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
     let action = action.map(|Query(a)| a);
 
     // TODO: keep the full path, not just the action
@@ -45,7 +50,18 @@ pub async fn get(
         .record_browser_session(&clock, &session)
         .await;
 
-    let ctx = AppContext::from_url_builder(&url_builder).with_language(locale);
+    let ctx = AppContext::from_url_builder(&url_builder);
+
+    // Show the banner ending the impersonation, which needs a CSRF token
+    let (ctx, cookie_jar) = if session.impersonation.is_some() {
+        let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+        let ctx = ctx.with_impersonation(session, csrf_token.form_value());
+        (ctx, cookie_jar)
+    } else {
+        (ctx, cookie_jar)
+    };
+
+    let ctx = ctx.with_language(locale);
     let content = templates.render_app(&ctx)?;
 
     Ok((cookie_jar, Html(content)).into_response())
@@ -0,0 +1,72 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Redirect},
+};
+use mas_axum_utils::{cookies::CookieJar, FancyError, SessionInfoExt};
+use mas_data_model::SiteConfig;
+use mas_router::{PostAuthAction, UrlBuilder};
+use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository};
+use ulid::Ulid;
+
+use crate::BoundActivityTracker;
+
+/// Send a logged in user to a client, so that the client starts a login
+///
+/// This uses the client `initiate_login_uri` if it has one, falling back to the
+/// client URI, and then to the account management page.
+#[tracing::instrument(
+    name = "handlers.views.client_login.get",
+    fields(client.id = %client_id),
+    skip_all,
+    err,
+)]
+pub async fn get(
+    clock: BoxClock,
+    State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Path(client_id): Path<Ulid>,
+) -> Result<impl IntoResponse, FancyError> {
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
+
+    let Some(session) = session else {
+        return Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::Login::and_then(
+                PostAuthAction::initiate_client_login(client_id),
+            )),
+        )
+            .into_response());
+    };
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let client = repo.oauth2_client().lookup(client_id).await?;
+
+    let redirect = if let Some(mut uri) = client
+        .as_ref()
+        .and_then(|client| client.initiate_login_uri.clone())
+    {
+        uri.query_pairs_mut()
+            .append_pair("iss", url_builder.oidc_issuer().as_str());
+        Redirect::to(uri.as_str())
+    } else if let Some(uri) = client.and_then(|client| client.client_uri) {
+        Redirect::to(uri.as_str())
+    } else {
+        PostAuthAction::manage_account(None).go_next(&url_builder)
+    };
+
+    Ok((cookie_jar, redirect).into_response())
+}
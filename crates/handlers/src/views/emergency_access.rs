@@ -0,0 +1,293 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Start a browser session with a one-time emergency access link, issued from
+//! the CLI for administrators when the usual login methods are unavailable
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use axum_extra::typed_header::TypedHeader;
+use hyper::StatusCode;
+use mas_axum_utils::{cookies::CookieJar, sentry::SentryEventID, SessionInfoExt};
+use mas_data_model::{AuditEventKind, UserAgent};
+use mas_router::UrlBuilder;
+use mas_storage::{
+    audit::AuditEventRepository,
+    job::{CheckSuspiciousLoginJob, DispatchWebhookJob, JobRepositoryExt},
+    user::{BrowserSessionRepository, UserEmergencyAccessRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use thiserror::Error;
+
+use crate::{impl_from_error_for_route, BoundActivityTracker};
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    /// The link is unknown, expired, already used, or used from a network it
+    /// wasn't issued for. We don't tell which to the requester.
+    #[error("Invalid emergency access link")]
+    InvalidLink,
+
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::InvalidLink => (
+                StatusCode::FORBIDDEN,
+                "This emergency access link is invalid or has expired",
+            )
+                .into_response(),
+            Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+#[tracing::instrument(name = "handlers.views.emergency_access.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    cookie_jar: CookieJar,
+    Path(token): Path<String>,
+) -> Result<Response, RouteError> {
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
+
+    let Some(access_token) = repo.user_emergency_access().find_by_token(&token).await? else {
+        return Err(RouteError::InvalidLink);
+    };
+
+    let user = repo
+        .user()
+        .lookup(access_token.user_id)
+        .await?
+        .ok_or(RouteError::InvalidLink)?;
+
+    // The link must be used from the network it was issued for, so that it
+    // doesn't get consumed by link previews or leaked to anyone else
+    let ip = activity_tracker.ip();
+    let reason = if !access_token.active(clock.now()) {
+        Some("expired")
+    } else if !ip.is_some_and(|ip| access_token.is_allowed_from(ip)) {
+        Some("network_not_allowed")
+    } else if !user.is_valid() || !user.can_request_admin {
+        Some("not_allowed")
+    } else {
+        None
+    };
+
+    if let Some(reason) = reason {
+        tracing::warn!(
+            user_emergency_access_token.id = %access_token.id,
+            %user.id,
+            reason,
+            "Rejected an emergency access attempt"
+        );
+
+        repo.audit_event()
+            .add(
+                &mut rng,
+                &clock,
+                AuditEventKind::LoginFailed,
+                None,
+                Some(user.id),
+                ip,
+                raw_user_agent,
+                serde_json::json!({
+                    "method": "emergency_access",
+                    "user_emergency_access_token_id": access_token.id.to_string(),
+                    "reason": reason,
+                }),
+            )
+            .await?;
+
+        repo.save().await?;
+
+        return Err(RouteError::InvalidLink);
+    }
+
+    let browser_session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, user_agent)
+        .await?;
+
+    let access_token = repo
+        .user_emergency_access()
+        .consume(&clock, access_token, &browser_session)
+        .await?;
+
+    tracing::warn!(
+        user_emergency_access_token.id = %access_token.id,
+        %user.id,
+        %user.username,
+        %browser_session.id,
+        "Started a browser session with an emergency access link"
+    );
+
+    let event = repo
+        .audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::Login,
+            Some(user.id),
+            Some(user.id),
+            ip,
+            raw_user_agent,
+            serde_json::json!({
+                "method": "emergency_access",
+                "user_emergency_access_token_id": access_token.id.to_string(),
+                "browser_session_id": browser_session.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(CheckSuspiciousLoginJob::new(&event))
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_created(
+            &mut rng,
+            &clock,
+            &user,
+            "browser",
+            browser_session.id,
+        ))
+        .await?;
+
+    repo.save().await?;
+
+    activity_tracker
+        .record_browser_session(&clock, &browser_session)
+        .await;
+
+    let cookie_jar = cookie_jar.set_session(&browser_session);
+    Ok((
+        cookie_jar,
+        url_builder.redirect(&mas_router::Account::default()),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_storage::{user::UserEmergencyAccessRepository, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Make the request come from 127.0.0.1
+    fn from_localhost<B>(mut request: Request<B>) -> Request<B> {
+        request
+            .extensions_mut()
+            .insert(IpAddr::from([127, 0, 0, 1]));
+        request
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_emergency_access(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let user = repo.user().set_can_request_admin(user, true).await.unwrap();
+
+        let allowed = repo
+            .user_emergency_access()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                "allowed".to_owned(),
+                "127.0.0.0/8".parse().unwrap(),
+                Duration::try_minutes(10).unwrap(),
+            )
+            .await
+            .unwrap();
+        let other_network = repo
+            .user_emergency_access()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                "other-network".to_owned(),
+                "192.0.2.0/24".parse().unwrap(),
+                Duration::try_minutes(10).unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Unknown links are rejected
+        let request = from_localhost(Request::get("/emergency-access/unknown").empty());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        // Links used from another network are rejected, and not consumed
+        let request = from_localhost(
+            Request::get(format!("/emergency-access/{}", other_network.token)).empty(),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let mut repo = state.repository().await.unwrap();
+        let other_network = repo
+            .user_emergency_access()
+            .lookup(other_network.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(other_network.consumed_at.is_none());
+        repo.save().await.unwrap();
+
+        // The right link starts a session
+        let request =
+            from_localhost(Request::get(format!("/emergency-access/{}", allowed.token)).empty());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[LOCATION], "/account/");
+
+        let mut repo = state.repository().await.unwrap();
+        let allowed = repo
+            .user_emergency_access()
+            .lookup(allowed.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(allowed.consumed_at.is_some());
+        assert!(allowed.user_session_id.is_some());
+        repo.save().await.unwrap();
+
+        // It can only be used once
+        let request =
+            from_localhost(Request::get(format!("/emergency-access/{}", allowed.token)).empty());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,543 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Let administrators start a time-limited browser session as another user,
+//! for example to debug an issue with their account
+//!
+//! The impersonation session remembers the session of the administrator, which
+//! is restored when the impersonation ends. Starting and ending an
+//! impersonation are recorded in the audit log.
+
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::typed_header::TypedHeader;
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    sentry::SentryEventID,
+    SessionInfoExt,
+};
+use mas_data_model::{AuditEventKind, BrowserSession, SiteConfig, User, UserAgent};
+use mas_policy::{Policy, Requester};
+use mas_router::UrlBuilder;
+use mas_storage::{
+    audit::AuditEventRepository,
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{DispatchWebhookJob, JobRepositoryExt, SyncDevicesJob},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    user::{BrowserSessionRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use mas_templates::{ImpersonationContext, TemplateContext, Templates};
+use thiserror::Error;
+use ulid::Ulid;
+
+use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage};
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error("Impersonation is disabled")]
+    Disabled,
+
+    #[error("User not found")]
+    UserNotFound,
+
+    #[error("Impersonation denied by policy: {0}")]
+    Denied(String),
+
+    #[error("The current session is not impersonating a user")]
+    NotImpersonating,
+
+    #[error(transparent)]
+    Csrf(#[from] mas_axum_utils::csrf::CsrfError),
+
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl_from_error_for_route!(mas_templates::TemplateError);
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_policy::EvaluationError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let status = match self {
+            Self::Disabled | Self::UserNotFound => StatusCode::NOT_FOUND,
+            Self::Denied(_) => StatusCode::FORBIDDEN,
+            Self::NotImpersonating | Self::Csrf(_) => StatusCode::BAD_REQUEST,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (SentryEventID::from(event_id), status, self.to_string()).into_response()
+    }
+}
+
+/// Check with the policy that the administrator can impersonate the user
+async fn check_policy(
+    repo: &mut BoxRepository,
+    policy: &mut Policy,
+    activity_tracker: &BoundActivityTracker,
+    user_agent: Option<String>,
+    session: &BrowserSession,
+    user: &User,
+) -> Result<(), RouteError> {
+    let last_authentication = repo
+        .browser_session()
+        .get_last_authentication(session)
+        .await?;
+    let requester = Requester::new(activity_tracker.ip(), user_agent)
+        .with_authentication(last_authentication.as_ref());
+
+    let res = policy
+        .evaluate_impersonation(user, &session.user, &requester)
+        .await?;
+
+    if res.valid() {
+        Ok(())
+    } else {
+        Err(RouteError::Denied(res.to_string()))
+    }
+}
+
+#[tracing::instrument(
+    name = "handlers.views.impersonation.get",
+    fields(user.id = %user_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    mut policy: Policy,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    cookie_jar: CookieJar,
+    Path(user_id): Path<Ulid>,
+) -> Result<Response, RouteError> {
+    let ttl = site_config.impersonation_ttl.ok_or(RouteError::Disabled)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let Some(session) = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?
+    else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    let user = repo
+        .user()
+        .lookup(user_id)
+        .await?
+        .filter(User::is_valid)
+        .ok_or(RouteError::UserNotFound)?;
+
+    check_policy(
+        &mut repo,
+        &mut policy,
+        &activity_tracker,
+        user_agent.map(|ua| ua.as_str().to_owned()),
+        &session,
+        &user,
+    )
+    .await?;
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let ctx = ImpersonationContext::new(user, clock.now() + ttl)
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_impersonate(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(
+    name = "handlers.views.impersonation.post",
+    fields(user.id = %user_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    mut policy: Policy,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    cookie_jar: CookieJar,
+    Path(user_id): Path<Ulid>,
+    Form(form): Form<ProtectedForm<()>>,
+) -> Result<Response, RouteError> {
+    let ttl = site_config.impersonation_ttl.ok_or(RouteError::Disabled)?;
+    cookie_jar.verify_form(&clock, form)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let Some(session) = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?
+    else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    let user = repo
+        .user()
+        .lookup(user_id)
+        .await?
+        .filter(User::is_valid)
+        .ok_or(RouteError::UserNotFound)?;
+
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
+
+    check_policy(
+        &mut repo,
+        &mut policy,
+        &activity_tracker,
+        raw_user_agent.clone(),
+        &session,
+        &user,
+    )
+    .await?;
+
+    let impersonation_session = repo
+        .browser_session()
+        .add_impersonation(
+            &mut rng,
+            &clock,
+            &user,
+            &session,
+            clock.now() + ttl,
+            user_agent,
+        )
+        .await?;
+
+    tracing::warn!(
+        %user.id,
+        %user.username,
+        impersonator.id = %session.user.id,
+        impersonator.username = %session.user.username,
+        user_session.id = %impersonation_session.id,
+        "Started impersonating a user"
+    );
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            Some(session.user.id),
+            Some(user.id),
+            activity_tracker.ip(),
+            raw_user_agent,
+            serde_json::json!({
+                "action": "start_impersonation",
+                "browser_session_id": impersonation_session.id.to_string(),
+                "impersonator_browser_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_created(
+            &mut rng,
+            &clock,
+            &user,
+            "browser",
+            impersonation_session.id,
+        ))
+        .await?;
+
+    repo.save().await?;
+
+    activity_tracker
+        .record_browser_session(&clock, &impersonation_session)
+        .await;
+
+    let cookie_jar = cookie_jar.set_session(&impersonation_session);
+    Ok((
+        cookie_jar,
+        url_builder.redirect(&mas_router::Account::default()),
+    )
+        .into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.impersonation.end", skip_all, err)]
+pub(crate) async fn end(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(site_config): State<SiteConfig>,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<()>>,
+) -> Result<Response, RouteError> {
+    cookie_jar.verify_form(&clock, form)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let Some(session) = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?
+    else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    let Some(impersonation) = session.impersonation.clone() else {
+        return Err(RouteError::NotImpersonating);
+    };
+
+    // End the sessions the administrator started while impersonating the user,
+    // so that they don't outlive the impersonation
+    let oauth2_filter = OAuth2SessionFilter::new()
+        .for_browser_session(&session)
+        .active_only();
+    let oauth2_count = repo
+        .oauth2_session()
+        .finish_bulk(&clock, oauth2_filter)
+        .await?;
+    let compat_filter = CompatSessionFilter::new()
+        .for_browser_session(&session)
+        .active_only();
+    let compat_count = repo
+        .compat_session()
+        .finish_bulk(&clock, compat_filter)
+        .await?;
+    if oauth2_count + compat_count > 0 {
+        repo.job()
+            .schedule_job(SyncDevicesJob::new(&session.user))
+            .await?;
+    }
+
+    let session = repo.browser_session().finish(&clock, session).await?;
+
+    tracing::info!(
+        %session.user.id,
+        impersonator.id = ?impersonation.impersonator_user_id,
+        user_session.id = %session.id,
+        "Ended an impersonation"
+    );
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::AdminAction,
+            impersonation.impersonator_user_id,
+            Some(session.user.id),
+            activity_tracker.ip(),
+            user_agent.map(|TypedHeader(ua)| ua.as_str().to_owned()),
+            serde_json::json!({
+                "action": "end_impersonation",
+                "browser_session_id": session.id.to_string(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::session_revoked(
+            &mut rng,
+            &clock,
+            &session.user,
+            "browser",
+            session.id,
+        ))
+        .await?;
+
+    // Restore the session of the administrator, if it is still active
+    let impersonator_session = if let Some(id) = impersonation.impersonator_session_id {
+        repo.browser_session()
+            .lookup(id)
+            .await?
+            .filter(BrowserSession::active)
+    } else {
+        None
+    };
+
+    repo.save().await?;
+
+    let (cookie_jar, destination) = if let Some(impersonator_session) = impersonator_session {
+        activity_tracker
+            .record_browser_session(&clock, &impersonator_session)
+            .await;
+
+        (
+            cookie_jar.set_session(&impersonator_session),
+            url_builder.redirect(&mas_router::Account::default()),
+        )
+    } else {
+        (
+            cookie_jar.update_session_info(&session_info.mark_session_ended()),
+            url_builder.redirect(&mas_router::Login::default()),
+        )
+    };
+
+    Ok((cookie_jar, destination).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_axum_utils::SessionInfoExt;
+    use mas_data_model::SiteConfig;
+    use mas_router::Route;
+    use mas_storage::{
+        user::{BrowserSessionFilter, BrowserSessionRepository, UserRepository},
+        Pagination, RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{
+        setup, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Extract the CSRF token from a rendered form
+    fn csrf_token(body: &str) -> String {
+        body.split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_impersonation(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                impersonation_ttl: Some(Duration::try_minutes(30).unwrap()),
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        let mut repo = state.repository().await.unwrap();
+        let admin = repo
+            .user()
+            .add(&mut rng, &state.clock, "admin".to_owned())
+            .await
+            .unwrap();
+        let admin = repo
+            .user()
+            .set_can_request_admin(admin, true)
+            .await
+            .unwrap();
+        let alice = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let admin_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &admin, None)
+            .await
+            .unwrap();
+        let alice_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &alice, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Users who can't request admin privileges can't impersonate others
+        cookies.import(state.cookie_jar().set_session(&alice_session));
+        let path = mas_router::Impersonate::new(admin.id).path().into_owned();
+        let request = cookies.with_cookies(Request::get(&path).empty());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        // The administrator confirms they want to impersonate alice
+        cookies.import(state.cookie_jar().set_session(&admin_session));
+        let path = mas_router::Impersonate::new(alice.id).path().into_owned();
+        let request = cookies.with_cookies(Request::get(&path).empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("alice"));
+        let csrf = csrf_token(response.body());
+
+        let request = Request::post(&path).form(serde_json::json!({ "csrf": csrf }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/account/");
+
+        // Pages now show the impersonation banner
+        let request = cookies.with_cookies(Request::get("/").empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("impersonation-banner"));
+        let csrf = csrf_token(response.body());
+
+        // Ending the impersonation restores the session of the administrator
+        let request = Request::post(mas_router::EndImpersonation::route())
+            .form(serde_json::json!({ "csrf": csrf }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/account/");
+
+        let request = cookies.with_cookies(Request::get("/").empty());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response.body().contains("impersonation-banner"));
+        assert!(response.body().contains("admin"));
+
+        // The impersonation session is finished, and remembers who started it
+        let mut repo = state.repository().await.unwrap();
+        let page = repo
+            .browser_session()
+            .list(
+                BrowserSessionFilter::new().for_user(&alice),
+                Pagination::first(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 2);
+        let impersonation = page
+            .edges
+            .iter()
+            .find(|session| session.impersonation.is_some())
+            .unwrap();
+        assert!(impersonation.finished_at.is_some());
+        assert_eq!(
+            impersonation
+                .impersonation
+                .as_ref()
+                .unwrap()
+                .impersonator_user_id,
+            Some(admin.id)
+        );
+    }
+}
@@ -9,6 +9,7 @@ use axum::{
     response::{Html, IntoResponse},
 };
 use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, FancyError, SessionInfoExt};
+use mas_data_model::SiteConfig;
 use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository, BoxRng};
 use mas_templates::{IndexContext, TemplateContext, Templates};
@@ -19,6 +20,7 @@ use crate::{preferred_language::PreferredLanguage, BoundActivityTracker};
 pub async fn get(
     mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     activity_tracker: BoundActivityTracker,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
@@ -29,7 +31,9 @@ pub async fn get(
     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
-    let session = session_info.load_session(&mut repo).await?;
+    let session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     if let Some(session) = session.as_ref() {
         activity_tracker
@@ -15,12 +15,15 @@ use mas_axum_utils::{
     csrf::{CsrfExt, CsrfToken, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{BrowserSession, UserAgent};
+use mas_data_model::{AuditEventKind, BrowserSession, Password, UserAgent};
 use mas_i18n::DataLocale;
-use mas_router::{UpstreamOAuth2Authorize, UrlBuilder};
+use mas_router::{AccountPasswordChange, UpstreamOAuth2Authorize, UrlBuilder};
 use mas_storage::{
+    audit::AuditEventRepository,
+    honeytoken::HoneytokenRepository,
+    job::{CheckSuspiciousLoginJob, DispatchWebhookJob, JobRepositoryExt},
     upstream_oauth2::UpstreamOAuthProviderRepository,
-    user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
+    user::{BrowserSessionRepository, UserHomeserver, UserPasswordRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{
@@ -32,7 +35,8 @@ use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
 use crate::{
-    passwords::PasswordManager, BoundActivityTracker, Limiter, PreferredLanguage,
+    account_deletion::cancel_pending_deletion, homeserver::RequestHomeserver,
+    passwords::PasswordManager, trusted_browser, BoundActivityTracker, Limiter, PreferredLanguage,
     RequesterFingerprint, SiteConfig,
 };
 
@@ -40,12 +44,22 @@ use crate::{
 pub(crate) struct LoginForm {
     username: String,
     password: String,
+    #[serde(default)]
+    remember_browser: String,
 }
 
 impl ToFormState for LoginForm {
     type Field = LoginFormField;
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct LoginParams {
+    /// Show the password form even if password login is disabled, for the
+    /// users who are still allowed to use it
+    #[serde(default)]
+    password: bool,
+}
+
 #[tracing::instrument(name = "handlers.views.login.get", skip_all, err)]
 pub(crate) async fn get(
     mut rng: BoxRng,
@@ -57,12 +71,15 @@ pub(crate) async fn get(
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     Query(query): Query<OptionalPostAuthAction>,
+    Query(params): Query<LoginParams>,
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     if let Some(session) = maybe_session {
         activity_tracker
@@ -75,9 +92,15 @@ pub(crate) async fn get(
 
     let providers = repo.upstream_oauth_provider().all_enabled().await?;
 
+    // Break-glass accounts can still log in with their password, but the form is
+    // only shown if explicitly asked for
+    let password_login_exception = !site_config.password_login_enabled
+        && !site_config.password_login_exceptions.is_empty()
+        && params.password;
+
     // If password-based login is disabled, and there is only one upstream provider,
     // we can directly start an authorization flow
-    if !site_config.password_login_enabled && providers.len() == 1 {
+    if !site_config.password_login_enabled && !password_login_exception && providers.len() == 1 {
         let provider = providers.into_iter().next().unwrap();
 
         let mut destination = UpstreamOAuth2Authorize::new(provider.id);
@@ -91,7 +114,9 @@ pub(crate) async fn get(
 
     let content = render(
         locale,
-        LoginContext::default().with_upstream_providers(providers),
+        LoginContext::default()
+            .with_upstream_providers(providers)
+            .with_password_login_exception(password_login_exception),
         query,
         csrf_token,
         &mut repo,
@@ -106,7 +131,7 @@ pub(crate) async fn get(
 pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
-    PreferredLanguage(locale): PreferredLanguage,
+    (PreferredLanguage(locale), request_homeserver): (PreferredLanguage, RequestHomeserver),
     State(password_manager): State<PasswordManager>,
     State(site_config): State<SiteConfig>,
     State(templates): State<Templates>,
@@ -121,10 +146,11 @@ pub(crate) async fn post(
     Form(form): Form<ProtectedForm<LoginForm>>,
 ) -> Result<Response, FancyError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
-    if !site_config.password_login_enabled {
+    if !site_config.password_login_enabled && site_config.password_login_exceptions.is_empty() {
         // XXX: is it necessary to have better errors here?
         return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
     }
+    let password_login_exception = !site_config.password_login_enabled;
 
     let form = cookie_jar.verify_form(&clock, form)?;
 
@@ -151,7 +177,8 @@ pub(crate) async fn post(
             locale,
             LoginContext::default()
                 .with_form_state(state)
-                .with_upstream_providers(providers),
+                .with_upstream_providers(providers)
+                .with_password_login_exception(password_login_exception),
             query,
             csrf_token,
             &mut repo,
@@ -162,20 +189,86 @@ pub(crate) async fn post(
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
-    match login(
-        password_manager,
-        &mut repo,
-        rng,
-        &clock,
-        limiter,
-        requester,
-        &form.username,
-        &form.password,
-        user_agent,
-    )
-    .await
-    {
-        Ok(session_info) => {
+    let raw_user_agent = user_agent.as_ref().map(|ua| ua.raw.clone());
+
+    // Users who aren't allowed to log in with their password get the same error
+    // as if their password was wrong
+    let res = if site_config.is_password_login_allowed(&form.username) {
+        login(
+            password_manager,
+            &mut repo,
+            &mut rng,
+            &clock,
+            limiter,
+            requester,
+            request_homeserver.user_homeserver(),
+            &form.username,
+            &form.password,
+            user_agent,
+        )
+        .await
+    } else {
+        Err(FormError::InvalidCredentials)
+    };
+
+    match res {
+        Ok((session_info, user_password)) => {
+            let (trusted_browser, cookie_jar) = trusted_browser::on_login(
+                &mut repo,
+                &mut rng,
+                &clock,
+                &site_config,
+                &session_info,
+                form.remember_browser == "on",
+                cookie_jar,
+            )
+            .await?;
+
+            let mut data = serde_json::json!({
+                "method": "password",
+                "browser_session_id": session_info.id.to_string(),
+            });
+            if let Some(trusted_browser) = &trusted_browser {
+                data["trusted_browser_id"] = trusted_browser.id.to_string().into();
+            }
+
+            let event = repo
+                .audit_event()
+                .add(
+                    &mut rng,
+                    &clock,
+                    AuditEventKind::Login,
+                    Some(session_info.user.id),
+                    Some(session_info.user.id),
+                    activity_tracker.ip(),
+                    raw_user_agent,
+                    data,
+                )
+                .await?;
+
+            repo.job()
+                .schedule_job(CheckSuspiciousLoginJob::new(&event))
+                .await?;
+
+            repo.job()
+                .schedule_job(DispatchWebhookJob::session_created(
+                    &mut rng,
+                    &clock,
+                    &session_info.user,
+                    "browser",
+                    session_info.id,
+                ))
+                .await?;
+
+            let next = super::terms::go_next_or_accept_terms(
+                &mut repo,
+                &site_config,
+                &session_info.user,
+                &query,
+                &url_builder,
+            )
+            .await?;
+
             repo.save().await?;
 
             activity_tracker
@@ -183,15 +276,68 @@ pub(crate) async fn post(
                 .await;
 
             let cookie_jar = cookie_jar.set_session(&session_info);
-            let reply = query.go_next(&url_builder);
+            let reply = if user_password.must_change() {
+                // The user has to pick a new password before going anywhere else
+                url_builder.redirect(&AccountPasswordChange)
+            } else {
+                next
+            };
             Ok((cookie_jar, reply).into_response())
         }
         Err(e) => {
+            // Record the failed attempt. Internal errors are not recorded, as
+            // the transaction might not be usable anymore
+            let record = matches!(
+                e,
+                FormError::InvalidCredentials | FormError::RateLimitExceeded
+            );
+            if record {
+                let user = repo
+                    .user()
+                    .find_by_username(request_homeserver.user_homeserver(), &form.username)
+                    .await?;
+
+                // Nobody should ever try to log in with a decoy account
+                if user.is_none() {
+                    if let Some(honeytoken) = repo.honeytoken().find_account(&form.username).await?
+                    {
+                        activity_tracker
+                            .report_honeytoken(&clock, &honeytoken, "login", raw_user_agent.clone())
+                            .await;
+                    }
+                }
+
+                let reason = if matches!(e, FormError::RateLimitExceeded) {
+                    "rate_limited"
+                } else {
+                    "invalid_credentials"
+                };
+
+                repo.audit_event()
+                    .add(
+                        &mut rng,
+                        &clock,
+                        AuditEventKind::LoginFailed,
+                        None,
+                        user.map(|user| user.id),
+                        activity_tracker.ip(),
+                        raw_user_agent,
+                        serde_json::json!({
+                            "method": "password",
+                            "username": form.username,
+                            "reason": reason,
+                        }),
+                    )
+                    .await?;
+            }
+
             let state = state.with_error_on_form(e);
 
             let content = render(
                 locale,
-                LoginContext::default().with_form_state(state),
+                LoginContext::default()
+                    .with_form_state(state)
+                    .with_password_login_exception(password_login_exception),
                 query,
                 csrf_token,
                 &mut repo,
@@ -199,6 +345,10 @@ pub(crate) async fn post(
             )
             .await?;
 
+            if record {
+                repo.save().await?;
+            }
+
             Ok((cookie_jar, Html(content)).into_response())
         }
     }
@@ -212,18 +362,21 @@ async fn login(
     clock: &impl Clock,
     limiter: Limiter,
     requester: RequesterFingerprint,
+    homeserver: UserHomeserver<'_>,
     username: &str,
     password: &str,
     user_agent: Option<UserAgent>,
-) -> Result<BrowserSession, FormError> {
+) -> Result<(BrowserSession, Password), FormError> {
     // XXX: we're loosing the error context here
-    // First, lookup the user
+    // First, lookup the user. Bots don't log in with a password, they use the
+    // tokens issued for them by their owner or an admin. Users waiting for the
+    // deletion of their account can still log in to cancel it
     let user = repo
         .user()
-        .find_by_username(username)
+        .find_by_username(homeserver, username)
         .await
         .map_err(|_e| FormError::Internal)?
-        .filter(mas_data_model::User::is_valid)
+        .filter(|user| (user.is_valid() || user.is_pending_deletion(clock.now())) && !user.is_bot)
         .ok_or(FormError::InvalidCredentials)?;
 
     // Check the rate limit
@@ -232,15 +385,23 @@ async fn login(
         FormError::RateLimitExceeded
     })?;
 
-    // And its password
+    let password = Zeroizing::new(password.as_bytes().to_vec());
+
+    // And its password. Users who don't have one yet may still have one in
+    // Synapse, if we're migrating from it
     let user_password = repo
         .user_password()
         .active(&user)
         .await
-        .map_err(|_e| FormError::Internal)?
-        .ok_or(FormError::InvalidCredentials)?;
-
-    let password = Zeroizing::new(password.as_bytes().to_vec());
+        .map_err(|_e| FormError::Internal)?;
+    let user_password = match user_password {
+        Some(user_password) => user_password,
+        None => password_manager
+            .import_from_synapse(&mut rng, clock, repo, &user, password.clone())
+            .await
+            .map_err(|_e| FormError::Internal)?
+            .ok_or(FormError::InvalidCredentials)?,
+    };
 
     // Verify the password, and upgrade it on-the-fly if needed
     let new_password_hash = password_manager
@@ -270,6 +431,11 @@ async fn login(
         user_password
     };
 
+    // The user authenticated, so they don't want their account deleted anymore
+    let user = cancel_pending_deletion(&mut rng, clock, repo, user)
+        .await
+        .map_err(|_| FormError::Internal)?;
+
     // Start a new session
     let user_session = repo
         .browser_session()
@@ -283,7 +449,7 @@ async fn login(
         .await
         .map_err(|_| FormError::Internal)?;
 
-    Ok(user_session)
+    Ok((user_session, user_password))
 }
 
 async fn render(
@@ -308,16 +474,19 @@ async fn render(
 
 #[cfg(test)]
 mod test {
+    use chrono::Duration;
     use hyper::{
         header::{CONTENT_TYPE, LOCATION},
         Request, StatusCode,
     };
-    use mas_data_model::UpstreamOAuthProviderClaimsImports;
-    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_data_model::{
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderTokenAuthMethod,
+    };
     use mas_router::Route;
     use mas_storage::{
         upstream_oauth2::{UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository},
-        RepositoryAccess,
+        user::UserTrustedBrowserRepository,
+        Clock, RepositoryAccess,
     };
     use mas_templates::escape_html;
     use oauth2_types::scope::OPENID;
@@ -369,7 +538,7 @@ mod test {
                     human_name: Some("First Ltd.".to_owned()),
                     brand_name: None,
                     scope: [OPENID].into_iter().collect(),
-                    token_endpoint_auth_method: OAuthClientAuthenticationMethod::None,
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
                     token_endpoint_signing_alg: None,
                     client_id: "client".to_owned(),
                     encrypted_client_secret: None,
@@ -379,7 +548,17 @@ mod test {
                     jwks_uri_override: None,
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
                     additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
                 },
             )
             .await
@@ -404,7 +583,7 @@ mod test {
                     human_name: None,
                     brand_name: None,
                     scope: [OPENID].into_iter().collect(),
-                    token_endpoint_auth_method: OAuthClientAuthenticationMethod::None,
+                    token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod::None,
                     token_endpoint_signing_alg: None,
                     client_id: "client".to_owned(),
                     encrypted_client_secret: None,
@@ -414,7 +593,17 @@ mod test {
                     jwks_uri_override: None,
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
                     additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
                 },
             )
             .await
@@ -489,6 +678,15 @@ mod test {
         cookies.save_cookies(&response);
         response.assert_status(StatusCode::SEE_OTHER);
 
+        // The login is checked for suspicious activity in the background
+        let jobs: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM apalis.jobs WHERE job_type = 'check-suspicious-login'",
+        )
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(jobs, 1);
+
         // Now if we get to the home page, we should see the user's username
         let request = Request::get("/").empty();
         let request = cookies.with_cookies(request);
@@ -499,6 +697,309 @@ mod test {
         assert!(response.body().contains("john"));
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_remember_browser(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                trusted_browser_ttl: Some(Duration::try_days(30).unwrap()),
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The login form lets the user remember the browser
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("name=\"remember_browser\""));
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+            "remember_browser": "on",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        let trusted_browsers = repo
+            .user_trusted_browser()
+            .list_active(&state.clock, &user)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        assert_eq!(trusted_browsers.len(), 1);
+        let trusted_browser = &trusted_browsers[0];
+        assert_eq!(
+            trusted_browser.expires_at,
+            state.clock.now() + Duration::try_days(30).unwrap()
+        );
+
+        // Logging in again from the same browser uses the existing trust
+        state.clock.advance(Duration::try_minutes(10).unwrap());
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        let trusted_browsers = repo
+            .user_trusted_browser()
+            .list_active(&state.clock, &user)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        assert_eq!(trusted_browsers.len(), 1);
+        assert_eq!(trusted_browsers[0].id, trusted_browser.id);
+        assert_eq!(trusted_browsers[0].last_used_at, state.clock.now());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_must_change(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password which has to be changed
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user_password()
+            .require_change(&state.clock, password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        // Logging in sends the user to the password change page
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/account/password/change");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_cancels_deletion(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password, who asked for the deletion of their
+        // account
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        let user = repo
+            .user()
+            .schedule_deletion(user, state.clock.now() + Duration::try_days(14).unwrap())
+            .await
+            .unwrap();
+        let user = repo.user().lock(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        // The user can log in despite the account being locked
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // And the account isn't going to be deleted anymore
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        repo.save().await.unwrap();
+        assert!(user.locked_at.is_none());
+        assert!(user.deletion_scheduled_at.is_none());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_exceptions(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                password_login_enabled: false,
+                password_login_exceptions: vec!["admin".to_owned()],
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision two users with the same password
+        let mut repo = state.repository().await.unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        for username in ["admin", "john"] {
+            let user = repo
+                .user()
+                .add(&mut rng, &state.clock, username.to_owned())
+                .await
+                .unwrap();
+            repo.user_password()
+                .add(&mut rng, &state.clock, &user, version, hash.clone(), None)
+                .await
+                .unwrap();
+        }
+        repo.save().await.unwrap();
+
+        // The password form is hidden by default
+        let response = state.request(Request::get("/login").empty()).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response.body().contains("name=\"password\""));
+
+        // But can be shown for break-glass accounts
+        let request = Request::get("/login?password=true").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("name=\"password\""));
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // Users which are not in the exceptions can't log in with their password
+        let request = Request::post("/login?password=true").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("Invalid credentials"));
+
+        // But the break-glass account can
+        let request = Request::post("/login?password=true").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "admin",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_password_login_rate_limit(pool: PgPool) {
         setup();
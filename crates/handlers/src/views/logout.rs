@@ -6,45 +6,111 @@
 
 use axum::{
     extract::{Form, State},
-    response::IntoResponse,
+    response::{IntoResponse, Redirect},
 };
+use axum_extra::typed_header::TypedHeader;
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, ProtectedForm},
+    http_client_factory::HttpClientFactory,
     FancyError, SessionInfoExt,
 };
+use mas_data_model::{AuditEventKind, SiteConfig};
 use mas_router::{PostAuthAction, UrlBuilder};
-use mas_storage::{user::BrowserSessionRepository, BoxClock, BoxRepository};
+use mas_storage::{
+    audit::AuditEventRepository,
+    job::{DispatchWebhookJob, JobRepositoryExt},
+    user::BrowserSessionRepository,
+    BoxClock, BoxRepository, BoxRng,
+};
 
-use crate::BoundActivityTracker;
+use crate::{
+    upstream_oauth2::{cache::MetadataCache, logout::upstream_logout_url},
+    BoundActivityTracker,
+};
 
 #[tracing::instrument(name = "handlers.views.logout.post", skip_all, err)]
 pub(crate) async fn post(
+    mut rng: BoxRng,
     clock: BoxClock,
+    State(site_config): State<SiteConfig>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(metadata_cache): State<MetadataCache>,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     Form(form): Form<ProtectedForm<Option<PostAuthAction>>>,
 ) -> Result<impl IntoResponse, FancyError> {
     let form = cookie_jar.verify_form(&clock, form)?;
 
     let (session_info, mut cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
+    let mut upstream_logout = None;
 
     if let Some(session) = maybe_session {
         activity_tracker
             .record_browser_session(&clock, &session)
             .await;
 
-        repo.browser_session().finish(&clock, session).await?;
+        let session = repo.browser_session().finish(&clock, session).await?;
+
+        repo.audit_event()
+            .add(
+                &mut rng,
+                &clock,
+                AuditEventKind::SessionEnded,
+                Some(session.user.id),
+                Some(session.user.id),
+                activity_tracker.ip(),
+                user_agent.map(|TypedHeader(ua)| ua.as_str().to_owned()),
+                serde_json::json!({ "browser_session_id": session.id.to_string() }),
+            )
+            .await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                &mut rng,
+                &clock,
+                &session.user,
+                "browser",
+                session.id,
+            ))
+            .await?;
+
+        // Log the user out of the upstream provider as well, unless they are in
+        // the middle of a flow which we would lose track of
+        if form.is_none() {
+            upstream_logout = upstream_logout_url(
+                &mut rng,
+                &http_client_factory,
+                &metadata_cache,
+                &mut repo,
+                &url_builder,
+                &session,
+            )
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Could not propagate the logout to the upstream provider"
+                );
+                None
+            });
+        }
+
         cookie_jar = cookie_jar.update_session_info(&session_info.mark_session_ended());
     }
 
     repo.save().await?;
 
-    let destination = if let Some(action) = form {
+    let destination = if let Some(url) = upstream_logout {
+        Redirect::to(url.as_str())
+    } else if let Some(action) = form {
         action.go_next(&url_builder)
     } else {
         url_builder.redirect(&mas_router::Login::default())
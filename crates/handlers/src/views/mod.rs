@@ -6,10 +6,15 @@
 
 pub mod account;
 pub mod app;
+pub mod client_login;
+pub mod emergency_access;
+pub mod impersonation;
 pub mod index;
 pub mod login;
 pub mod logout;
 pub mod reauth;
 pub mod recovery;
 pub mod register;
+pub mod session_binding;
 pub mod shared;
+pub mod terms;
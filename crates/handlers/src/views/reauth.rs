@@ -45,17 +45,12 @@ pub(crate) async fn get(
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
-    if !site_config.password_login_enabled {
-        // XXX: do something better here
-        return Ok(url_builder
-            .redirect(&mas_router::Account::default())
-            .into_response());
-    }
-
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login screen, keeping the
@@ -64,6 +59,15 @@ pub(crate) async fn get(
         return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
     };
 
+    if !site_config.is_password_login_allowed(&session.user.username) {
+        // XXX: do something better here
+        return Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::Account::default()),
+        )
+            .into_response());
+    }
+
     activity_tracker
         .record_browser_session(&clock, &session)
         .await;
@@ -97,16 +101,13 @@ pub(crate) async fn post(
     cookie_jar: CookieJar,
     Form(form): Form<ProtectedForm<ReauthForm>>,
 ) -> Result<Response, FancyError> {
-    if !site_config.password_login_enabled {
-        // XXX: do something better here
-        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
-    }
-
     let form = cookie_jar.verify_form(&clock, form)?;
 
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login screen, keeping the
@@ -115,6 +116,11 @@ pub(crate) async fn post(
         return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
     };
 
+    if !site_config.is_password_login_allowed(&session.user.username) {
+        // XXX: do something better here
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+
     // Load the user password
     let user_password = repo
         .user_password()
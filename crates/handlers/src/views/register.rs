@@ -22,10 +22,11 @@ use mas_axum_utils::{
 use mas_data_model::{CaptchaConfig, UserAgent};
 use mas_i18n::DataLocale;
 use mas_matrix::BoxHomeserverConnection;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
+    honeytoken::HoneytokenRepository,
+    job::{DispatchWebhookJob, JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
     user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, RepositoryAccess,
 };
@@ -38,8 +39,8 @@ use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
 use crate::{
-    captcha::Form as CaptchaForm, passwords::PasswordManager, BoundActivityTracker, Limiter,
-    PreferredLanguage, RequesterFingerprint, SiteConfig,
+    captcha::Form as CaptchaForm, homeserver::RequestHomeserver, passwords::PasswordManager,
+    BoundActivityTracker, Limiter, PreferredLanguage, RequesterFingerprint, SiteConfig,
 };
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -74,7 +75,9 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
 
     if maybe_session.is_some() {
         let reply = query.go_next(&url_builder);
@@ -107,7 +110,7 @@ pub(crate) async fn get(
 pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
-    PreferredLanguage(locale): PreferredLanguage,
+    (PreferredLanguage(locale), request_homeserver): (PreferredLanguage, RequestHomeserver),
     State(password_manager): State<PasswordManager>,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
@@ -157,10 +160,22 @@ pub(crate) async fn post(
 
         if form.username.is_empty() {
             state.add_error_on_field(RegisterFormField::Username, FieldError::Required);
-        } else if repo.user().exists(&form.username).await? {
-            // The user already exists in the database
+        } else if repo
+            .user()
+            .exists(request_homeserver.user_homeserver(), &form.username)
+            .await?
+            || repo
+                .honeytoken()
+                .find_account(&form.username)
+                .await?
+                .is_some()
+        {
+            // The user already exists in the database, or it is a decoy account
             state.add_error_on_field(RegisterFormField::Username, FieldError::Exists);
-        } else if !homeserver.is_localpart_available(&form.username).await? {
+        } else if !homeserver
+            .is_localpart_available_on(request_homeserver.0.as_deref(), &form.username)
+            .await?
+        {
             // The user already exists on the homeserver
             // XXX: we may want to return different errors like "this username is reserved"
             tracing::warn!(
@@ -209,7 +224,14 @@ pub(crate) async fn post(
         }
 
         let res = policy
-            .evaluate_register(&form.username, &form.email)
+            .evaluate_register(
+                &form.username,
+                &form.email,
+                &Requester::new(
+                    activity_tracker.ip(),
+                    user_agent.as_ref().map(|ua| ua.raw.clone()),
+                ),
+            )
             .await?;
 
         for violation in res.violations {
@@ -264,7 +286,15 @@ pub(crate) async fn post(
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
-    let user = repo.user().add(&mut rng, &clock, form.username).await?;
+    let user = repo
+        .user()
+        .add_for_homeserver(
+            &mut rng,
+            &clock,
+            request_homeserver.user_homeserver(),
+            form.username,
+        )
+        .await?;
 
     if let Some(tos_uri) = &site_config.tos_uri {
         repo.user_terms()
@@ -303,6 +333,10 @@ pub(crate) async fn post(
         .schedule_job(ProvisionUserJob::new(&user))
         .await?;
 
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_registered(&mut rng, &clock, &user))
+        .await?;
+
     repo.save().await?;
 
     activity_tracker
@@ -0,0 +1,755 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Bind browser sessions to a WebAuthn credential
+//!
+//! When enabled, browsers create a platform credential for their session, and
+//! then regularly prove that they still hold it. Each proof rotates a token
+//! saved in the session cookie, so that a cookie copied out of the browser
+//! stops working once the browser renews the binding, or once the binding
+//! expires.
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use mas_axum_utils::{cookies::CookieJar, sentry::SentryEventID, SessionInfo, SessionInfoExt};
+use mas_data_model::{BrowserSessionBinding, SiteConfig};
+use mas_router::UrlBuilder;
+use mas_storage::{
+    user::BrowserSessionRepository, BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use ulid::Ulid;
+
+use crate::impl_from_error_for_route;
+
+static COOKIE_NAME: &str = "session-binding";
+
+/// How long the browser has to answer a challenge
+const CHALLENGE_TTL: Duration = Duration::minutes(5);
+
+/// The authenticator data flag set when it includes attested credential data
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error("Session binding is disabled")]
+    Disabled,
+
+    #[error("No active session")]
+    NoSession,
+
+    #[error("Missing or expired challenge")]
+    MissingChallenge,
+
+    #[error("The session is already bound to a credential")]
+    AlreadyBound,
+
+    #[error("The session is not bound to this credential")]
+    CredentialMismatch,
+
+    #[error("Invalid WebAuthn response")]
+    InvalidResponse(#[from] VerificationError),
+
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_axum_utils::cookies::CookieDecodeError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let status = match self {
+            Self::Disabled => StatusCode::NOT_FOUND,
+            Self::NoSession => StatusCode::UNAUTHORIZED,
+            Self::MissingChallenge
+            | Self::AlreadyBound
+            | Self::CredentialMismatch
+            | Self::InvalidResponse(_) => StatusCode::BAD_REQUEST,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (SentryEventID::from(event_id), status, self.to_string()).into_response()
+    }
+}
+
+/// Why a WebAuthn response was rejected
+#[derive(Debug, Error)]
+pub(crate) enum VerificationError {
+    #[error("invalid base64 encoding")]
+    Encoding,
+
+    #[error("invalid client data")]
+    ClientData,
+
+    #[error("unexpected ceremony type")]
+    CeremonyType,
+
+    #[error("challenge mismatch")]
+    Challenge,
+
+    #[error("origin mismatch")]
+    Origin,
+
+    #[error("invalid authenticator data")]
+    AuthenticatorData,
+
+    #[error("relying party mismatch")]
+    RelyingParty,
+
+    #[error("unsupported public key")]
+    PublicKey,
+
+    #[error("invalid signature")]
+    Signature,
+
+    #[error("signature counter did not increase")]
+    SignCount,
+}
+
+impl From<base64ct::Error> for VerificationError {
+    fn from(_: base64ct::Error) -> Self {
+        Self::Encoding
+    }
+}
+
+/// The challenge sent to the browser, saved in a cookie until it answers
+#[derive(Serialize, Deserialize)]
+struct Challenge {
+    session_id: Ulid,
+    challenge: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct GetResponse {
+    /// In how many seconds the browser should come back to renew the binding
+    renew_in: i64,
+
+    /// The parameters of the WebAuthn ceremony to perform, if the binding has
+    /// to be renewed now
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ceremony: Option<Ceremony>,
+}
+
+#[derive(Serialize)]
+struct Ceremony {
+    challenge: String,
+    rp_id: String,
+    user_id: String,
+    user_name: String,
+
+    /// The credential to use, or `None` if a new one has to be created
+    credential_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum PostRequest {
+    /// The browser created a new credential
+    Create {
+        credential_id: String,
+        client_data_json: String,
+        authenticator_data: String,
+        public_key: String,
+    },
+
+    /// The browser proved it holds the credential
+    Get {
+        credential_id: String,
+        client_data_json: String,
+        authenticator_data: String,
+        signature: String,
+    },
+}
+
+#[derive(Serialize)]
+pub(crate) struct PostResponse {
+    renew_in: i64,
+}
+
+/// When the browser should renew the binding, halfway through its lifetime
+fn renew_at(binding: &BrowserSessionBinding, ttl: Duration) -> DateTime<Utc> {
+    binding.expires_at - ttl / 2
+}
+
+#[tracing::instrument(name = "handlers.views.session_binding.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(site_config): State<SiteConfig>,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+) -> Result<Response, RouteError> {
+    let ttl = site_config
+        .browser_session_binding_ttl
+        .ok_or(RouteError::Disabled)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?
+        .ok_or(RouteError::NoSession)?;
+
+    let now = clock.now();
+    if let Some(binding) = &session.binding {
+        let renew_at = renew_at(binding, ttl);
+        if now < renew_at {
+            let response = GetResponse {
+                renew_in: (renew_at - now).num_seconds(),
+                ceremony: None,
+            };
+            return Ok((cookie_jar, Json(response)).into_response());
+        }
+    }
+
+    let challenge: [u8; 32] = rng.gen();
+    let challenge = Base64UrlUnpadded::encode_string(&challenge);
+    let cookie_jar = cookie_jar.save(
+        COOKIE_NAME,
+        &Challenge {
+            session_id: session.id,
+            challenge: challenge.clone(),
+            expires_at: now + CHALLENGE_TTL,
+        },
+        false,
+    );
+
+    let response = GetResponse {
+        renew_in: 0,
+        ceremony: Some(Ceremony {
+            challenge,
+            rp_id: url_builder.public_hostname().to_owned(),
+            user_id: Base64UrlUnpadded::encode_string(&session.user.id.to_bytes()),
+            user_name: session.user.username.clone(),
+            credential_id: session
+                .binding
+                .as_ref()
+                .map(|binding| binding.credential_id.clone()),
+        }),
+    };
+
+    Ok((cookie_jar, Json(response)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.session_binding.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(site_config): State<SiteConfig>,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Json(request): Json<PostRequest>,
+) -> Result<Response, RouteError> {
+    let ttl = site_config
+        .browser_session_binding_ttl
+        .ok_or(RouteError::Disabled)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?
+        .ok_or(RouteError::NoSession)?;
+
+    let now = clock.now();
+
+    // The challenge can only be used once
+    let challenge: Option<Challenge> = cookie_jar.load(COOKIE_NAME)?;
+    let cookie_jar = cookie_jar.remove(COOKIE_NAME);
+    let challenge = challenge
+        .filter(|challenge| challenge.session_id == session.id && now < challenge.expires_at)
+        .ok_or(RouteError::MissingChallenge)?;
+
+    let relying_party = RelyingParty {
+        id: url_builder.public_hostname(),
+        origin: url_builder.http_base().origin().ascii_serialization(),
+    };
+
+    let (credential_id, public_key, sign_count) = match request {
+        PostRequest::Create {
+            credential_id,
+            client_data_json,
+            authenticator_data,
+            public_key,
+        } => {
+            if session.binding.is_some() {
+                return Err(RouteError::AlreadyBound);
+            }
+
+            let public_key =
+                Base64UrlUnpadded::decode_vec(&public_key).map_err(VerificationError::from)?;
+            let sign_count = relying_party.verify_creation(
+                &challenge.challenge,
+                &credential_id,
+                &Base64UrlUnpadded::decode_vec(&client_data_json)
+                    .map_err(VerificationError::from)?,
+                &Base64UrlUnpadded::decode_vec(&authenticator_data)
+                    .map_err(VerificationError::from)?,
+                &public_key,
+            )?;
+
+            (credential_id, public_key, sign_count)
+        }
+
+        PostRequest::Get {
+            credential_id,
+            client_data_json,
+            authenticator_data,
+            signature,
+        } => {
+            let binding = session
+                .binding
+                .as_ref()
+                .filter(|binding| binding.credential_id == credential_id)
+                .ok_or(RouteError::CredentialMismatch)?;
+
+            let sign_count = relying_party.verify_assertion(
+                &challenge.challenge,
+                binding,
+                &Base64UrlUnpadded::decode_vec(&client_data_json)
+                    .map_err(VerificationError::from)?,
+                &Base64UrlUnpadded::decode_vec(&authenticator_data)
+                    .map_err(VerificationError::from)?,
+                &Base64UrlUnpadded::decode_vec(&signature).map_err(VerificationError::from)?,
+            )?;
+
+            (credential_id, binding.public_key.clone(), sign_count)
+        }
+    };
+
+    let token: String = (&mut rng)
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    let binding = BrowserSessionBinding {
+        credential_id,
+        public_key,
+        sign_count,
+        token,
+        // The cookies of requests made concurrently to this one hold the current token
+        previous_token: session
+            .binding
+            .as_ref()
+            .map(|binding| binding.token.clone()),
+        expires_at: now + ttl,
+    };
+    let renew_in = (renew_at(&binding, ttl) - now).num_seconds();
+
+    let session = repo.browser_session().bind(session, binding).await?;
+    repo.save().await?;
+
+    let cookie_jar = cookie_jar.update_session_info(&SessionInfo::from_session(&session));
+
+    Ok((cookie_jar, Json(PostResponse { renew_in })).into_response())
+}
+
+/// The relying party, as seen from the browser
+struct RelyingParty<'a> {
+    /// The relying party ID, which is the public hostname of the service
+    id: &'a str,
+
+    /// The origin the WebAuthn ceremonies are performed from
+    origin: String,
+}
+
+/// The parts of the client data we check
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The parts of the authenticator data we check
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    sign_count: u32,
+    attested_credential_id: Option<&'a [u8]>,
+}
+
+impl<'a> AuthenticatorData<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, VerificationError> {
+        if data.len() < 37 {
+            return Err(VerificationError::AuthenticatorData);
+        }
+
+        let rp_id_hash = &data[..32];
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+        // The attested credential data is made of the AAGUID, the length of the
+        // credential ID on two bytes, the credential ID, and the public key
+        let attested_credential_id = if flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+            None
+        } else {
+            let length = data
+                .get(53..55)
+                .ok_or(VerificationError::AuthenticatorData)?;
+            let length = usize::from(u16::from_be_bytes([length[0], length[1]]));
+            let credential_id = data
+                .get(55..55 + length)
+                .ok_or(VerificationError::AuthenticatorData)?;
+            Some(credential_id)
+        };
+
+        Ok(Self {
+            rp_id_hash,
+            sign_count,
+            attested_credential_id,
+        })
+    }
+}
+
+impl RelyingParty<'_> {
+    fn verify_client_data(
+        &self,
+        ceremony_type: &str,
+        challenge: &str,
+        client_data_json: &[u8],
+    ) -> Result<(), VerificationError> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).map_err(|_| VerificationError::ClientData)?;
+
+        if client_data.ceremony_type != ceremony_type {
+            return Err(VerificationError::CeremonyType);
+        }
+
+        if client_data.challenge != challenge {
+            return Err(VerificationError::Challenge);
+        }
+
+        if client_data.origin != self.origin {
+            return Err(VerificationError::Origin);
+        }
+
+        Ok(())
+    }
+
+    fn verify_authenticator_data<'d>(
+        &self,
+        authenticator_data: &'d [u8],
+    ) -> Result<AuthenticatorData<'d>, VerificationError> {
+        let authenticator_data = AuthenticatorData::parse(authenticator_data)?;
+
+        // User presence is deliberately not checked, so that authenticators
+        // which allow it can renew the binding without interrupting the user
+        if authenticator_data.rp_id_hash != Sha256::digest(self.id.as_bytes()).as_slice() {
+            return Err(VerificationError::RelyingParty);
+        }
+
+        Ok(authenticator_data)
+    }
+
+    /// Verify the response to a credential creation, returning the initial
+    /// signature counter of the credential
+    ///
+    /// The attestation is not checked, only the public key reported by the
+    /// browser is kept.
+    fn verify_creation(
+        &self,
+        challenge: &str,
+        credential_id: &str,
+        client_data_json: &[u8],
+        authenticator_data: &[u8],
+        public_key: &[u8],
+    ) -> Result<u32, VerificationError> {
+        self.verify_client_data("webauthn.create", challenge, client_data_json)?;
+        let authenticator_data = self.verify_authenticator_data(authenticator_data)?;
+
+        let attested_credential_id = authenticator_data
+            .attested_credential_id
+            .ok_or(VerificationError::AuthenticatorData)?;
+        if Base64UrlUnpadded::decode_vec(credential_id)? != attested_credential_id {
+            return Err(VerificationError::AuthenticatorData);
+        }
+
+        VerifyingKey::from_public_key_der(public_key).map_err(|_| VerificationError::PublicKey)?;
+
+        Ok(authenticator_data.sign_count)
+    }
+
+    /// Verify an assertion of the credential the session is bound to,
+    /// returning the new signature counter
+    fn verify_assertion(
+        &self,
+        challenge: &str,
+        binding: &BrowserSessionBinding,
+        client_data_json: &[u8],
+        authenticator_data: &[u8],
+        signature: &[u8],
+    ) -> Result<u32, VerificationError> {
+        self.verify_client_data("webauthn.get", challenge, client_data_json)?;
+        let parsed = self.verify_authenticator_data(authenticator_data)?;
+
+        let key = VerifyingKey::from_public_key_der(&binding.public_key)
+            .map_err(|_| VerificationError::PublicKey)?;
+        let signature = Signature::from_der(signature).map_err(|_| VerificationError::Signature)?;
+
+        let mut message = authenticator_data.to_vec();
+        message.extend_from_slice(&Sha256::digest(client_data_json));
+        key.verify(&message, &signature)
+            .map_err(|_| VerificationError::Signature)?;
+
+        // Authenticators which don't implement the counter always report zero.
+        // Otherwise, a counter which didn't increase hints at a cloned
+        // authenticator.
+        if (parsed.sign_count != 0 || binding.sign_count != 0)
+            && parsed.sign_count <= binding.sign_count
+        {
+            return Err(VerificationError::SignCount);
+        }
+
+        Ok(parsed.sign_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use p256::{
+        ecdsa::{signature::Signer, SigningKey},
+        pkcs8::EncodePublicKey,
+    };
+    use rand::SeedableRng;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{setup, RequestBuilderExt, ResponseExt, TestState};
+
+    const RP_ID: &str = "example.com";
+    const ORIGIN: &str = "https://example.com";
+
+    fn relying_party() -> RelyingParty<'static> {
+        RelyingParty {
+            id: RP_ID,
+            origin: ORIGIN.to_owned(),
+        }
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": ceremony_type,
+            "challenge": challenge,
+            "origin": origin,
+            "crossOrigin": false,
+        }))
+        .unwrap()
+    }
+
+    fn authenticator_data(rp_id: &str, sign_count: u32, credential_id: Option<&[u8]>) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        let flags = if credential_id.is_some() {
+            FLAG_ATTESTED_CREDENTIAL_DATA
+        } else {
+            0
+        };
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        if let Some(credential_id) = credential_id {
+            data.extend_from_slice(&[0; 16]);
+            data.extend_from_slice(&u16::try_from(credential_id.len()).unwrap().to_be_bytes());
+            data.extend_from_slice(credential_id);
+        }
+        data
+    }
+
+    fn binding(key: &SigningKey, sign_count: u32) -> BrowserSessionBinding {
+        BrowserSessionBinding {
+            credential_id: Base64UrlUnpadded::encode_string(b"credential"),
+            public_key: key.verifying_key().to_public_key_der().unwrap().into_vec(),
+            sign_count,
+            token: "token".to_owned(),
+            previous_token: None,
+            expires_at: DateTime::default(),
+        }
+    }
+
+    fn sign(key: &SigningKey, authenticator_data: &[u8], client_data_json: &[u8]) -> Vec<u8> {
+        let mut message = authenticator_data.to_vec();
+        message.extend_from_slice(&Sha256::digest(client_data_json));
+        let signature: Signature = key.sign(&message);
+        signature.to_der().as_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_verify_creation() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let key = SigningKey::random(&mut rng);
+        let public_key = key.verifying_key().to_public_key_der().unwrap().into_vec();
+        let credential_id = Base64UrlUnpadded::encode_string(b"credential");
+        let rp = relying_party();
+
+        let client_data_json = client_data("webauthn.create", "challenge", ORIGIN);
+        let data = authenticator_data(RP_ID, 3, Some(b"credential"));
+        let sign_count = rp
+            .verify_creation(
+                "challenge",
+                &credential_id,
+                &client_data_json,
+                &data,
+                &public_key,
+            )
+            .unwrap();
+        assert_eq!(sign_count, 3);
+
+        // The credential ID must match the attested one
+        let other_id = Base64UrlUnpadded::encode_string(b"other");
+        assert!(matches!(
+            rp.verify_creation(
+                "challenge",
+                &other_id,
+                &client_data_json,
+                &data,
+                &public_key
+            ),
+            Err(VerificationError::AuthenticatorData)
+        ));
+
+        // The ceremony must be a creation
+        let get_client_data = client_data("webauthn.get", "challenge", ORIGIN);
+        assert!(matches!(
+            rp.verify_creation(
+                "challenge",
+                &credential_id,
+                &get_client_data,
+                &data,
+                &public_key
+            ),
+            Err(VerificationError::CeremonyType)
+        ));
+
+        // The public key must be a P-256 key
+        assert!(matches!(
+            rp.verify_creation(
+                "challenge",
+                &credential_id,
+                &client_data_json,
+                &data,
+                b"not a key"
+            ),
+            Err(VerificationError::PublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_verify_assertion() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let key = SigningKey::random(&mut rng);
+        let binding = binding(&key, 3);
+        let rp = relying_party();
+
+        let client_data_json = client_data("webauthn.get", "challenge", ORIGIN);
+        let data = authenticator_data(RP_ID, 4, None);
+        let signature = sign(&key, &data, &client_data_json);
+        let sign_count = rp
+            .verify_assertion("challenge", &binding, &client_data_json, &data, &signature)
+            .unwrap();
+        assert_eq!(sign_count, 4);
+
+        // Another challenge
+        assert!(matches!(
+            rp.verify_assertion("other", &binding, &client_data_json, &data, &signature),
+            Err(VerificationError::Challenge)
+        ));
+
+        // Another origin
+        let other_origin = client_data("webauthn.get", "challenge", "https://evil.com");
+        let other_signature = sign(&key, &data, &other_origin);
+        assert!(matches!(
+            rp.verify_assertion(
+                "challenge",
+                &binding,
+                &other_origin,
+                &data,
+                &other_signature
+            ),
+            Err(VerificationError::Origin)
+        ));
+
+        // Another relying party
+        let other_data = authenticator_data("evil.com", 4, None);
+        let other_signature = sign(&key, &other_data, &client_data_json);
+        assert!(matches!(
+            rp.verify_assertion(
+                "challenge",
+                &binding,
+                &client_data_json,
+                &other_data,
+                &other_signature
+            ),
+            Err(VerificationError::RelyingParty)
+        ));
+
+        // Signed by another key
+        let other_key = SigningKey::random(&mut rng);
+        let other_signature = sign(&other_key, &data, &client_data_json);
+        assert!(matches!(
+            rp.verify_assertion(
+                "challenge",
+                &binding,
+                &client_data_json,
+                &data,
+                &other_signature
+            ),
+            Err(VerificationError::Signature)
+        ));
+
+        // The counter didn't increase
+        let stale_data = authenticator_data(RP_ID, 3, None);
+        let stale_signature = sign(&key, &stale_data, &client_data_json);
+        assert!(matches!(
+            rp.verify_assertion(
+                "challenge",
+                &binding,
+                &client_data_json,
+                &stale_data,
+                &stale_signature
+            ),
+            Err(VerificationError::SignCount)
+        ));
+
+        // Authenticators without a counter always report zero
+        let binding = BrowserSessionBinding {
+            sign_count: 0,
+            ..binding
+        };
+        let data = authenticator_data(RP_ID, 0, None);
+        let signature = sign(&key, &data, &client_data_json);
+        let sign_count = rp
+            .verify_assertion("challenge", &binding, &client_data_json, &data, &signature)
+            .unwrap();
+        assert_eq!(sign_count, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_disabled(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get(mas_router::SessionBinding::PATH).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -8,7 +8,7 @@ use anyhow::Context;
 use mas_router::{PostAuthAction, Route, UrlBuilder};
 use mas_storage::{
     compat::CompatSsoLoginRepository,
-    oauth2::OAuth2AuthorizationGrantRepository,
+    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
     upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
     RepositoryAccess,
 };
@@ -65,6 +65,16 @@ impl OptionalPostAuthAction {
                 PostAuthContextInner::ContinueDeviceCodeGrant { grant }
             }
 
+            PostAuthAction::ContinueCibaGrant { id } => {
+                let grant = repo
+                    .oauth2_ciba_grant()
+                    .lookup(id)
+                    .await?
+                    .context("Failed to load CIBA grant")?;
+                let grant = Box::new(grant);
+                PostAuthContextInner::ContinueCibaGrant { grant }
+            }
+
             PostAuthAction::ContinueCompatSsoLogin { id } => {
                 let login = repo
                     .compat_sso_login()
@@ -96,6 +106,16 @@ impl OptionalPostAuthAction {
             }
 
             PostAuthAction::ManageAccount { .. } => PostAuthContextInner::ManageAccount,
+
+            PostAuthAction::InitiateClientLogin { id } => {
+                let client = repo
+                    .oauth2_client()
+                    .lookup(id)
+                    .await?
+                    .context("Failed to load client")?;
+                let client = Box::new(client);
+                PostAuthContextInner::InitiateClientLogin { client }
+            }
         };
 
         Ok(Some(PostAuthContext {
@@ -0,0 +1,308 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Ask users to accept the current terms of service before they continue
+//!
+//! The URL of the terms identifies their version, so users who accepted a
+//! previous version are asked again when new terms are published under a new
+//! URL.
+
+use axum::{
+    extract::{Form, Query, State},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    FancyError, SessionInfoExt,
+};
+use mas_data_model::{SiteConfig, User};
+use mas_router::UrlBuilder;
+use mas_storage::{BoxClock, BoxRepository, BoxRng, RepositoryAccess, RepositoryError};
+use mas_templates::{TemplateContext, Templates, TermsContext};
+use serde::Deserialize;
+use url::Url;
+
+use super::shared::OptionalPostAuthAction;
+use crate::{BoundActivityTracker, PreferredLanguage};
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct TermsForm {
+    #[serde(default)]
+    accept_terms: String,
+}
+
+/// Get the terms of service the user has to accept before continuing, if any
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn pending_terms<'a>(
+    repo: &mut BoxRepository,
+    site_config: &'a SiteConfig,
+    user: &User,
+) -> Result<Option<&'a Url>, RepositoryError> {
+    let Some(terms_url) = site_config.required_terms() else {
+        return Ok(None);
+    };
+
+    let acceptance = repo.user_terms().find_acceptance(user, terms_url).await?;
+
+    Ok(acceptance.is_none().then_some(terms_url))
+}
+
+/// Redirect to the page asking the user to accept the terms of service if
+/// they have to, or to the next step otherwise
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub(crate) async fn go_next_or_accept_terms(
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    user: &User,
+    query: &OptionalPostAuthAction,
+    url_builder: &UrlBuilder,
+) -> Result<Redirect, RepositoryError> {
+    if pending_terms(repo, site_config, user).await?.is_some() {
+        let destination = mas_router::AcceptTerms::from(query.post_auth_action.clone());
+        Ok(url_builder.redirect(&destination))
+    } else {
+        Ok(query.go_next(url_builder))
+    }
+}
+
+#[tracing::instrument(name = "handlers.views.terms.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
+
+    let Some(session) = maybe_session else {
+        // If there is no session, redirect to the login screen, keeping the
+        // PostAuthAction
+        let login = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let Some(terms_url) = pending_terms(&mut repo, &site_config, &session.user).await? else {
+        // Nothing to accept, carry on
+        return Ok((cookie_jar, query.go_next(&url_builder)).into_response());
+    };
+
+    let previously_accepted = !repo.user_terms().all(&session.user).await?.is_empty();
+
+    let ctx = TermsContext::new(terms_url.clone(), previously_accepted);
+    let next = query.load_context(&mut repo).await?;
+    let ctx = if let Some(next) = next {
+        ctx.with_post_action(next)
+    } else {
+        ctx
+    };
+    let ctx = ctx
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_terms(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.terms.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<TermsForm>>,
+) -> Result<Response, FancyError> {
+    let form = cookie_jar.verify_form(&clock, form)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info
+        .load_session(&mut repo, &clock, &site_config)
+        .await?;
+
+    let Some(session) = maybe_session else {
+        // If there is no session, redirect to the login screen, keeping the
+        // PostAuthAction
+        let login = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    if let Some(terms_url) = pending_terms(&mut repo, &site_config, &session.user).await? {
+        if form.accept_terms != "on" {
+            // The browser should not have let the form through, show it again
+            let destination = mas_router::AcceptTerms::from(query.post_auth_action);
+            return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+        }
+
+        repo.user_terms()
+            .accept_terms(&mut rng, &clock, &session.user, terms_url.clone())
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok((cookie_jar, query.go_next(&url_builder)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_data_model::SiteConfig;
+    use mas_storage::RepositoryAccess;
+    use sqlx::PgPool;
+    use zeroize::Zeroizing;
+
+    use crate::test_utils::{
+        setup, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    fn extract_csrf_token(body: &str) -> String {
+        body.split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_terms_reacceptance(pool: PgPool) {
+        setup();
+        let state = TestState::from_pool_with_site_config(
+            pool,
+            SiteConfig {
+                tos_uri: Some("https://example.com/tos?v=2".parse().unwrap()),
+                terms_acceptance_required: true,
+                ..test_site_config()
+            },
+        )
+        .await
+        .unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user who accepted a previous version of the terms
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user_terms()
+            .accept_terms(
+                &mut rng,
+                &state.clock,
+                &user,
+                "https://example.com/tos?v=1".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        state.clock.advance(Duration::try_days(30).unwrap());
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = extract_csrf_token(response.body());
+
+        // Logging in sends the user to the terms page
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/terms");
+
+        // The page tells the user the terms changed
+        let request = Request::get("/terms").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("https://example.com/tos?v=2"));
+        let csrf_token = extract_csrf_token(response.body());
+
+        // Not ticking the box shows the page again
+        let request = Request::post("/terms").form(serde_json::json!({
+            "csrf": csrf_token,
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/terms");
+
+        // Accepting the terms lets the user continue
+        let request = Request::post("/terms").form(serde_json::json!({
+            "csrf": csrf_token,
+            "accept_terms": "on",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/");
+
+        let mut repo = state.repository().await.unwrap();
+        let acceptances = repo.user_terms().all(&user).await.unwrap();
+        assert_eq!(acceptances.len(), 2);
+        assert_eq!(
+            acceptances[0].terms_url.as_str(),
+            "https://example.com/tos?v=2"
+        );
+
+        // The page now sends the user on their way
+        let request = Request::get("/terms").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/");
+    }
+}
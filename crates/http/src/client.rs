@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::sync::Arc;
+
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 pub use hyper_util::client::legacy::Client;
 use hyper_util::{
@@ -21,6 +23,8 @@ use opentelemetry_semantic_conventions::trace::SERVER_ADDRESS;
 use tower::Layer;
 use tracing::Span;
 
+use crate::outbound_policy::{OutboundPolicy, OutboundPolicyConnector, OutboundPolicyResolver};
+
 pub type UntracedClient<B> = Client<UntracedConnector, B>;
 pub type TracedClient<B> = Client<TracedConnector, B>;
 
@@ -38,13 +42,24 @@ where
 pub type TraceResolver<S> =
     InFlightCounterService<DurationRecorderService<TraceService<S, FnWrapper<fn(&Name) -> Span>>>>;
 pub type UntracedConnector = HttpsConnector<HttpConnector<GaiResolver>>;
-pub type TracedConnector = HttpsConnector<HttpConnector<TraceResolver<GaiResolver>>>;
+pub type TracedConnector = HttpsConnector<
+    OutboundPolicyConnector<HttpConnector<OutboundPolicyResolver<TraceResolver<GaiResolver>>>>,
+>;
 
 /// Create a traced HTTP and HTTPS connector
 #[must_use]
-pub fn make_traced_connector() -> TracedConnector
-where
-{
+pub fn make_traced_connector() -> TracedConnector {
+    make_traced_connector_with_policy(None)
+}
+
+/// Create a traced HTTP and HTTPS connector, which can only reach the hosts
+/// and addresses allowed by the given [`OutboundPolicy`]
+#[must_use]
+pub fn make_restricted_traced_connector(policy: OutboundPolicy) -> TracedConnector {
+    make_traced_connector_with_policy(Some(Arc::new(policy)))
+}
+
+fn make_traced_connector_with_policy(policy: Option<Arc<OutboundPolicy>>) -> TracedConnector {
     let in_flight_counter = InFlightCounterLayer::new("dns.resolve.active_requests");
     let duration_recorder = DurationRecorderLayer::new("dns.resolve.duration");
     let trace_layer = TraceLayer::from_fn(
@@ -59,9 +74,11 @@ where
     );
 
     let resolver = (in_flight_counter, duration_recorder, trace_layer).layer(GaiResolver::new());
+    let resolver = OutboundPolicyResolver::new(resolver, policy.clone());
+    let http = OutboundPolicyConnector::new(make_http_connector(resolver), policy);
 
     let tls_config = rustls_platform_verifier::tls_config();
-    make_connector(resolver, tls_config)
+    make_https_connector(http, tls_config)
 }
 
 fn make_untraced_connector() -> UntracedConnector
@@ -69,16 +86,16 @@ where
 {
     let resolver = GaiResolver::new();
     let tls_config = rustls_platform_verifier::tls_config();
-    make_connector(resolver, tls_config)
+    make_https_connector(make_http_connector(resolver), tls_config)
 }
 
-fn make_connector<R>(
-    resolver: R,
-    tls_config: rustls::ClientConfig,
-) -> HttpsConnector<HttpConnector<R>> {
+fn make_http_connector<R>(resolver: R) -> HttpConnector<R> {
     let mut http = HttpConnector::new_with_resolver(resolver);
     http.enforce_http(false);
+    http
+}
 
+fn make_https_connector<C>(http: C, tls_config: rustls::ClientConfig) -> HttpsConnector<C> {
     HttpsConnectorBuilder::new()
         .with_tls_config(tls_config)
         .https_or_http()
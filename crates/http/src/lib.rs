@@ -13,15 +13,20 @@
 mod client;
 mod ext;
 mod layers;
+#[cfg(feature = "client")]
+mod outbound_policy;
 mod service;
 
 #[cfg(feature = "client")]
 pub use self::{
     client::{
-        make_traced_connector, make_untraced_client, Client, TracedClient, TracedConnector,
-        UntracedClient, UntracedConnector,
+        make_restricted_traced_connector, make_traced_connector, make_untraced_client, Client,
+        TracedClient, TracedConnector, UntracedClient, UntracedConnector,
     },
     layers::client::{ClientLayer, ClientService},
+    outbound_policy::{
+        OutboundPolicy, OutboundPolicyConnector, OutboundPolicyError, OutboundPolicyResolver,
+    },
 };
 pub use self::{
     ext::{set_propagator, CorsLayerExt, ServiceExt as HttpServiceExt},
@@ -0,0 +1,328 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Restrictions on the addresses outbound requests can reach, to protect
+//! against server-side request forgery through URLs set by users or operators
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+use http::Uri;
+use hyper_util::client::legacy::connect::dns::Name;
+use ipnetwork::IpNetwork;
+use thiserror::Error;
+use tower::{BoxError, Service};
+
+/// Addresses of the instance metadata endpoints of cloud providers.
+///
+/// Those are never reachable, even when private networks are allowed, unless
+/// explicitly listed in the allowed networks.
+const METADATA_ADDRESSES: [IpAddr; 3] = [
+    // AWS, GCP, Azure, OpenStack and most others
+    IpAddr::V4(Ipv4Addr::new(169, 254, 169, 254)),
+    // Alibaba Cloud
+    IpAddr::V4(Ipv4Addr::new(100, 100, 100, 200)),
+    // AWS, over IPv6
+    IpAddr::V6(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)),
+];
+
+/// The error returned when an outbound request is denied by an
+/// [`OutboundPolicy`]
+#[derive(Debug, Error)]
+#[error("outbound requests to {host:?} are not allowed")]
+pub struct OutboundPolicyError {
+    host: String,
+}
+
+/// A policy deciding which hosts and addresses outbound requests can reach
+///
+/// By default, only publicly routable addresses are allowed.
+#[derive(Debug, Clone, Default)]
+pub struct OutboundPolicy {
+    allow_private_networks: bool,
+    allowed_networks: Vec<IpNetwork>,
+    allowed_hosts: Vec<String>,
+}
+
+impl OutboundPolicy {
+    /// Create a new policy, only allowing publicly routable addresses
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow or deny requests to private, loopback and link-local addresses.
+    ///
+    /// Cloud metadata endpoints stay denied unless they are explicitly part of
+    /// the allowed networks.
+    #[must_use]
+    pub fn with_private_networks_allowed(mut self, allowed: bool) -> Self {
+        self.allow_private_networks = allowed;
+        self
+    }
+
+    /// Add networks which can always be reached
+    #[must_use]
+    pub fn with_allowed_networks(mut self, networks: impl IntoIterator<Item = IpNetwork>) -> Self {
+        self.allowed_networks.extend(networks);
+        self
+    }
+
+    /// Add hosts which can always be reached, whatever address they resolve
+    /// to.
+    ///
+    /// Hosts starting with `*.` match all their subdomains.
+    #[must_use]
+    pub fn with_allowed_hosts(mut self, hosts: impl IntoIterator<Item = String>) -> Self {
+        self.allowed_hosts
+            .extend(hosts.into_iter().map(|host| host.to_ascii_lowercase()));
+        self
+    }
+
+    /// Check if the given host name is explicitly allowed
+    #[must_use]
+    pub fn is_host_allowed(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.allowed_hosts.iter().any(|allowed| {
+            if let Some(suffix) = allowed.strip_prefix("*.") {
+                host.strip_suffix(suffix)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+            } else {
+                *allowed == host
+            }
+        })
+    }
+
+    /// Check if the given address can be reached
+    #[must_use]
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+
+        if self
+            .allowed_networks
+            .iter()
+            .any(|network| network.contains(ip))
+        {
+            return true;
+        }
+
+        if METADATA_ADDRESSES.contains(&ip) {
+            return false;
+        }
+
+        self.allow_private_networks || is_public_ip(ip)
+    }
+}
+
+/// Check that an IP address is publicly routable
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => !is_non_public_ipv4(ip),
+        IpAddr::V6(ip) => !is_non_public_ipv6(ip),
+    }
+}
+
+fn is_non_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Shared address space, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+}
+
+fn is_non_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local addresses, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local addresses, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// A DNS resolver which drops the resolved addresses denied by an
+/// [`OutboundPolicy`]
+///
+/// Filtering the addresses at resolution time means the connection is made to
+/// an address which was checked, so that DNS rebinding can't be used to bypass
+/// the policy.
+#[derive(Debug, Clone)]
+pub struct OutboundPolicyResolver<R> {
+    inner: R,
+    policy: Option<Arc<OutboundPolicy>>,
+}
+
+impl<R> OutboundPolicyResolver<R> {
+    /// Wrap a resolver, applying the given policy if any
+    pub fn new(inner: R, policy: Option<Arc<OutboundPolicy>>) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl<R> Service<Name> for OutboundPolicyResolver<R>
+where
+    R: Service<Name>,
+    R::Response: Iterator<Item = SocketAddr>,
+    R::Error: Into<BoxError>,
+    R::Future: Send + 'static,
+{
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let policy = self.policy.clone();
+        let host = name.as_str().to_owned();
+        let future = self.inner.call(name);
+
+        Box::pin(async move {
+            let addrs = future.await.map_err(Into::into)?;
+
+            let addrs: Vec<SocketAddr> = match policy {
+                Some(policy) if !policy.is_host_allowed(&host) => addrs
+                    .filter(|addr| policy.is_ip_allowed(addr.ip()))
+                    .collect(),
+                _ => addrs.collect(),
+            };
+
+            if addrs.is_empty() {
+                return Err(OutboundPolicyError { host }.into());
+            }
+
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// A connector which denies connections to IP literals denied by an
+/// [`OutboundPolicy`]
+///
+/// Connections to IP literals don't go through the resolver, so they have to
+/// be checked separately.
+#[derive(Debug, Clone)]
+pub struct OutboundPolicyConnector<C> {
+    inner: C,
+    policy: Option<Arc<OutboundPolicy>>,
+}
+
+impl<C> OutboundPolicyConnector<C> {
+    /// Wrap a connector, applying the given policy if any
+    pub fn new(inner: C, policy: Option<Arc<OutboundPolicy>>) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl<C> Service<Uri> for OutboundPolicyConnector<C>
+where
+    C: Service<Uri>,
+    C::Response: Send + 'static,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        if let Some(policy) = &self.policy {
+            let host = uri.host().unwrap_or_default();
+            let literal = host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>();
+
+            if let Ok(ip) = literal {
+                if !policy.is_ip_allowed(ip) {
+                    let error = OutboundPolicyError {
+                        host: host.to_owned(),
+                    };
+                    return Box::pin(std::future::ready(Err(error.into())));
+                }
+            }
+        }
+
+        let future = self.inner.call(uri);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = OutboundPolicy::new();
+
+        assert!(policy.is_ip_allowed("93.184.215.14".parse().unwrap()));
+        assert!(policy.is_ip_allowed("2606:2800:21f:cb07::1".parse().unwrap()));
+
+        assert!(!policy.is_ip_allowed("127.0.0.1".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("10.1.2.3".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("192.168.1.1".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("100.64.0.1".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("169.254.169.254".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("::1".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("fd12::1".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("fe80::1".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!policy.is_host_allowed("example.com"));
+    }
+
+    #[test]
+    fn test_private_networks_allowed() {
+        let policy = OutboundPolicy::new().with_private_networks_allowed(true);
+
+        assert!(policy.is_ip_allowed("127.0.0.1".parse().unwrap()));
+        assert!(policy.is_ip_allowed("10.1.2.3".parse().unwrap()));
+        assert!(policy.is_ip_allowed("fd12::1".parse().unwrap()));
+
+        // Metadata endpoints stay denied
+        assert!(!policy.is_ip_allowed("169.254.169.254".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("::ffff:169.254.169.254".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("100.100.100.200".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("fd00:ec2::254".parse().unwrap()));
+
+        // Unless they are explicitly allowed
+        let policy = policy.with_allowed_networks(["169.254.169.254/32".parse().unwrap()]);
+        assert!(policy.is_ip_allowed("169.254.169.254".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_allow_lists() {
+        let policy = OutboundPolicy::new()
+            .with_allowed_networks(["10.0.0.0/8".parse().unwrap()])
+            .with_allowed_hosts(["hooks.internal".to_owned(), "*.Example.com".to_owned()]);
+
+        assert!(policy.is_ip_allowed("10.1.2.3".parse().unwrap()));
+        assert!(!policy.is_ip_allowed("192.168.1.1".parse().unwrap()));
+
+        assert!(policy.is_host_allowed("hooks.internal"));
+        assert!(policy.is_host_allowed("HOOKS.internal."));
+        assert!(policy.is_host_allowed("auth.example.com"));
+        assert!(policy.is_host_allowed("a.b.example.com"));
+        assert!(!policy.is_host_allowed("example.com"));
+        assert!(!policy.is_host_allowed("badexample.com"));
+        assert!(!policy.is_host_allowed("other.internal"));
+    }
+}
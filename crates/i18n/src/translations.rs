@@ -18,6 +18,9 @@ use serde::{
 
 use crate::sprintf::Message;
 
+/// The names of the plural categories, as used as keys in the tree
+const PLURAL_CATEGORIES: [&str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
 fn plural_category_as_str(category: PluralCategory) -> &'static str {
     match category {
         PluralCategory::Zero => "zero",
@@ -197,6 +200,66 @@ impl Tree {
         Some(message)
     }
 
+    /// Merge another tree into this one.
+    ///
+    /// Messages from the other tree replace the ones with the same key in this
+    /// tree, and subtrees are merged recursively.
+    pub fn merge(&mut self, other: Tree) {
+        for (key, node) in other.inner {
+            match (self.inner.get_mut(&key), node) {
+                (
+                    Some(Node {
+                        value: Value::Tree(tree),
+                        ..
+                    }),
+                    Node {
+                        value: Value::Tree(other),
+                        ..
+                    },
+                ) => tree.merge(other),
+                (_, node) => {
+                    self.inner.insert(key, node);
+                }
+            }
+        }
+    }
+
+    /// List the keys of the messages which are defined in the `reference`
+    /// tree but not in this one.
+    ///
+    /// Plural variants are not compared, as the categories used depend on the
+    /// language: a pluralized message is only missing if none of its variants
+    /// are defined.
+    #[must_use]
+    pub fn missing_keys(&self, reference: &Tree) -> Vec<String> {
+        let mut missing = Vec::new();
+        self.missing_keys_inner(reference, "", &mut missing);
+        missing
+    }
+
+    fn missing_keys_inner(&self, reference: &Tree, prefix: &str, missing: &mut Vec<String>) {
+        for (key, node) in &reference.inner {
+            let path = format!("{prefix}{key}");
+            match (&node.value, self.inner.get(key).map(|node| &node.value)) {
+                (Value::Leaf(_), Some(_)) => {}
+                (Value::Tree(tree), Some(_)) if tree.is_plural() => {}
+                (Value::Tree(tree), Some(Value::Tree(own))) => {
+                    own.missing_keys_inner(tree, &format!("{path}."), missing);
+                }
+                _ => missing.push(path),
+            }
+        }
+    }
+
+    /// Whether this tree holds the variants of a pluralized message
+    fn is_plural(&self) -> bool {
+        !self.inner.is_empty()
+            && self
+                .inner
+                .keys()
+                .all(|key| PLURAL_CATEGORIES.contains(&key.as_str()))
+    }
+
     #[doc(hidden)]
     pub fn set_if_not_defined<K: Deref<Target = str>, I: IntoIterator<Item = K>>(
         &mut self,
@@ -335,4 +398,67 @@ mod tests {
             "about 2 hours ago"
         );
     }
+
+    #[test]
+    fn test_merge() {
+        let mut tree: TranslationTree = serde_json::from_value(serde_json::json!({
+            "hello": "world",
+            "nested": {
+              "one": "first",
+              "two": "second"
+            }
+        }))
+        .unwrap();
+
+        let overrides: TranslationTree = serde_json::from_value(serde_json::json!({
+            "nested": {
+              "two": "overridden"
+            },
+            "extra": "added"
+        }))
+        .unwrap();
+
+        tree.merge(overrides);
+
+        let format = |key| tree.message(key).unwrap().format(&arg_list!()).unwrap();
+        assert_eq!(format("hello"), "world");
+        assert_eq!(format("nested.one"), "first");
+        assert_eq!(format("nested.two"), "overridden");
+        assert_eq!(format("extra"), "added");
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let reference: TranslationTree = serde_json::from_value(serde_json::json!({
+            "hello": "world",
+            "goodbye": "world",
+            "nested": {
+              "key": "value",
+              "other_key": "value"
+            },
+            "sessions": {
+              "one": "one session",
+              "other": "%(count)s sessions"
+            }
+        }))
+        .unwrap();
+
+        // Plural categories depend on the language, so they aren't compared
+        let tree: TranslationTree = serde_json::from_value(serde_json::json!({
+            "hello": "monde",
+            "nested": {
+              "key": "valeur"
+            },
+            "sessions": {
+              "other": "%(count)s sessions"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            tree.missing_keys(&reference),
+            vec!["goodbye".to_owned(), "nested.other_key".to_owned()]
+        );
+        assert!(reference.missing_keys(&reference).is_empty());
+    }
 }
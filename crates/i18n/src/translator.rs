@@ -4,7 +4,11 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::{collections::HashMap, fs::File, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    str::FromStr,
+};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use icu_list::{ListError, ListFormatter, ListLength};
@@ -51,6 +55,7 @@ pub struct Translator {
     plural_provider: LocaleFallbackProvider<icu_plurals::provider::Baked>,
     list_provider: LocaleFallbackProvider<icu_list::provider::Baked>,
     default_locale: DataLocale,
+    fallbacks: HashMap<DataLocale, Vec<DataLocale>>,
 }
 
 impl Translator {
@@ -71,6 +76,7 @@ impl Translator {
             list_provider,
             // TODO: make this configurable
             default_locale: icu_locid::locale!("en").into(),
+            fallbacks: HashMap::new(),
         }
     }
 
@@ -88,24 +94,112 @@ impl Translator {
     /// Returns an error if the directory cannot be read, or if any of the files
     /// cannot be parsed.
     pub fn load_from_path(path: &Utf8Path) -> Result<Self, LoadError> {
-        let mut translations = HashMap::new();
+        let translations = read_translations(path)?;
+        Ok(Self::new(translations))
+    }
 
-        let dir = path.read_dir_utf8()?;
-        for entry in dir {
-            let entry = entry?;
-            let path = entry.into_path();
-            let Some(name) = path.file_stem() else {
-                return Err(LoadError::InvalidFileName(path));
-            };
+    /// Load additional translations from a directory, on top of the ones
+    /// already loaded.
+    ///
+    /// The directory has the same layout as the one passed to
+    /// [`Translator::load_from_path`]. Messages found there replace the
+    /// existing ones with the same key, and locales which were not loaded yet
+    /// are added.
+    ///
+    /// Returns the locales which were found in the directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be read, or if any of the files
+    /// cannot be parsed.
+    pub fn load_overrides_from_path(
+        &mut self,
+        path: &Utf8Path,
+    ) -> Result<Vec<DataLocale>, LoadError> {
+        let overrides = read_translations(path)?;
+        let mut locales = Vec::with_capacity(overrides.len());
+
+        for (locale, tree) in overrides {
+            self.translations
+                .entry(locale.clone())
+                .or_default()
+                .merge(tree);
+            locales.push(locale);
+        }
 
-            let locale: Locale = Locale::from_str(name)?;
+        Ok(locales)
+    }
 
-            let mut file = File::open(path)?;
-            let content = serde_json::from_reader(&mut file)?;
-            translations.insert(locale.into(), content);
+    /// Set additional fallbacks between locales.
+    ///
+    /// Messages missing in a locale are first looked up in its parent locales,
+    /// e.g. `fr-CA` then `fr`. The locales configured here are tried next,
+    /// before the default locale. This is useful for locales which are close
+    /// to another one, but not derived from it, e.g. `gsw` to `de`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the locales is invalid.
+    pub fn with_fallbacks(
+        mut self,
+        fallbacks: &BTreeMap<String, Vec<String>>,
+    ) -> Result<Self, LoadError> {
+        for (locale, fallbacks) in fallbacks {
+            let locale = Locale::from_str(locale)?.into();
+            let fallbacks = fallbacks
+                .iter()
+                .map(|fallback| Locale::from_str(fallback).map(DataLocale::from))
+                .collect::<Result<_, _>>()?;
+            self.fallbacks.insert(locale, fallbacks);
         }
 
-        Ok(Self::new(translations))
+        Ok(self)
+    }
+
+    /// Get the chain of locales to look up messages in for a given locale,
+    /// not including the default locale.
+    fn fallback_chain(&self, locale: DataLocale) -> Vec<DataLocale> {
+        let mut chain = Vec::new();
+        self.extend_fallback_chain(locale, &mut chain);
+        chain
+    }
+
+    fn extend_fallback_chain(&self, locale: DataLocale, chain: &mut Vec<DataLocale>) {
+        let mut configured = Vec::new();
+        let mut iter = FALLBACKER.fallback_for(locale);
+
+        while !iter.get().is_und() {
+            let locale = iter.get();
+            if !chain.contains(locale) {
+                chain.push(locale.clone());
+            }
+
+            if let Some(fallbacks) = self.fallbacks.get(locale) {
+                configured.extend(fallbacks.iter().cloned());
+            }
+
+            iter.step();
+        }
+
+        for locale in configured {
+            if !chain.contains(&locale) {
+                self.extend_fallback_chain(locale, chain);
+            }
+        }
+    }
+
+    /// List the keys which are defined in the default locale but missing in
+    /// the given locale.
+    #[must_use]
+    pub fn missing_keys(&self, locale: &DataLocale) -> Vec<String> {
+        let (Some(tree), Some(reference)) = (
+            self.translations.get(locale),
+            self.translations.get(&self.default_locale),
+        ) else {
+            return Vec::new();
+        };
+
+        tree.missing_keys(reference)
     }
 
     /// Get a message from the tree by key, with locale fallback.
@@ -123,27 +217,15 @@ impl Translator {
         locale: DataLocale,
         key: &str,
     ) -> Option<(&Message, DataLocale)> {
-        if let Ok(message) = self.message(&locale, key) {
-            return Some((message, locale));
-        }
-
-        let mut iter = FALLBACKER.fallback_for(locale);
-
-        loop {
-            let locale = iter.get();
-
-            if let Ok(message) = self.message(locale, key) {
-                return Some((message, iter.take()));
-            }
-
-            // Try the defaut locale if we hit the `und` locale
-            if locale.is_und() {
-                let message = self.message(&self.default_locale, key).ok()?;
-                return Some((message, self.default_locale.clone()));
+        for locale in self.fallback_chain(locale) {
+            if let Ok(message) = self.message(&locale, key) {
+                return Some((message, locale));
             }
-
-            iter.step();
         }
+
+        // Try the default locale last
+        let message = self.message(&self.default_locale, key).ok()?;
+        Some((message, self.default_locale.clone()))
     }
 
     /// Get a message from the tree by key.
@@ -192,22 +274,15 @@ impl Translator {
         key: &str,
         count: usize,
     ) -> Option<(&Message, DataLocale)> {
-        let mut iter = FALLBACKER.fallback_for(locale);
-
-        loop {
-            let locale = iter.get();
-
-            if let Ok(message) = self.plural(locale, key, count) {
-                return Some((message, iter.take()));
-            }
-
-            // Stop if we hit the `und` locale
-            if locale.is_und() {
-                return None;
+        for locale in self.fallback_chain(locale) {
+            if let Ok(message) = self.plural(&locale, key, count) {
+                return Some((message, locale));
             }
-
-            iter.step();
         }
+
+        // Try the default locale last
+        let message = self.plural(&self.default_locale, key, count).ok()?;
+        Some((message, self.default_locale.clone()))
     }
 
     /// Get a plural message from the tree by key.
@@ -366,26 +441,39 @@ impl Translator {
     #[must_use]
     pub fn choose_locale(&self, iter: impl Iterator<Item = DataLocale>) -> DataLocale {
         for locale in iter {
-            if self.has_locale(&locale) {
+            if let Some(locale) = self
+                .fallback_chain(locale)
+                .into_iter()
+                .find(|locale| self.has_locale(locale))
+            {
                 return locale;
             }
+        }
 
-            let mut fallbacker = FALLBACKER.fallback_for(locale);
+        self.default_locale.clone()
+    }
+}
 
-            loop {
-                if fallbacker.get().is_und() {
-                    break;
-                }
+/// Read the translations from a directory, with one JSON file per locale
+fn read_translations(path: &Utf8Path) -> Result<HashMap<DataLocale, TranslationTree>, LoadError> {
+    let mut translations = HashMap::new();
 
-                if self.has_locale(fallbacker.get()) {
-                    return fallbacker.take();
-                }
-                fallbacker.step();
-            }
-        }
+    let dir = path.read_dir_utf8()?;
+    for entry in dir {
+        let entry = entry?;
+        let path = entry.into_path();
+        let Some(name) = path.file_stem() else {
+            return Err(LoadError::InvalidFileName(path));
+        };
 
-        self.default_locale.clone()
+        let locale: Locale = Locale::from_str(name)?;
+
+        let mut file = File::open(path)?;
+        let content = serde_json::from_reader(&mut file)?;
+        translations.insert(locale.into(), content);
     }
+
+    Ok(translations)
 }
 
 #[cfg(test)]
@@ -509,4 +597,60 @@ mod tests {
             .unwrap();
         assert_eq!(list, "un, deux ou trois");
     }
+
+    #[test]
+    fn test_overrides() {
+        let root: Utf8PathBuf = env!("CARGO_MANIFEST_DIR").parse().unwrap();
+        let mut translator = translator();
+        let mut locales = translator
+            .load_overrides_from_path(&root.join("test_overrides"))
+            .unwrap();
+        locales.sort_by_key(ToString::to_string);
+        assert_eq!(locales, vec![locale!("fr").into(), locale!("oc").into()]);
+
+        // Overridden messages replace the shipped ones, the others are kept
+        let message = translator.message(&locale!("fr").into(), "hello").unwrap();
+        assert_eq!(message.format(&arg_list!()).unwrap(), "Salut !");
+        let message = translator
+            .message(&locale!("fr").into(), "goodbye")
+            .unwrap();
+        assert_eq!(message.format(&arg_list!()).unwrap(), "Au revoir !");
+
+        // New locales can be added
+        assert!(translator.has_locale(&locale!("oc").into()));
+        let message = translator.message(&locale!("oc").into(), "hello").unwrap();
+        assert_eq!(message.format(&arg_list!()).unwrap(), "Adiu !");
+        assert_eq!(
+            translator.missing_keys(&locale!("oc").into()),
+            vec!["active_sessions".to_owned(), "goodbye".to_owned()]
+        );
+        assert!(translator.missing_keys(&locale!("fr").into()).is_empty());
+
+        // Without a configured fallback, they fall back to the default locale
+        let (_message, locale) = translator
+            .message_with_fallback(locale!("oc").into(), "goodbye")
+            .unwrap();
+        assert_eq!(locale, locale!("en").into());
+
+        let fallbacks = [("oc".to_owned(), vec!["fr".to_owned()])].into();
+        let translator = translator.with_fallbacks(&fallbacks).unwrap();
+
+        let (message, locale) = translator
+            .message_with_fallback(locale!("oc-FR").into(), "goodbye")
+            .unwrap();
+        assert_eq!(message.format(&arg_list!()).unwrap(), "Au revoir !");
+        assert_eq!(locale, locale!("fr").into());
+
+        let (message, locale) = translator
+            .plural_with_fallback(locale!("oc").into(), "active_sessions", 2)
+            .unwrap();
+        assert_eq!(
+            message.format(&arg_list!(count = 2)).unwrap(),
+            "2 sessions actives."
+        );
+        assert_eq!(locale, locale!("fr").into());
+
+        let invalid = [("oc".to_owned(), vec!["not a locale".to_owned()])].into();
+        assert!(translator.with_fallbacks(&invalid).is_err());
+    }
 }
@@ -495,6 +495,7 @@ mod oidc_core {
     use super::{Claim, Equality, Timestamp, TokenHash};
 
     pub const AUTH_TIME: Claim<Timestamp> = Claim::new("auth_time");
+    pub const ACR: Claim<String> = Claim::new("acr");
     pub const NONCE: Claim<String, Equality<str>> = Claim::new("nonce");
     pub const AT_HASH: Claim<String, TokenHash> = Claim::new("at_hash");
     pub const C_HASH: Claim<String, TokenHash> = Claim::new("c_hash");
@@ -525,7 +526,26 @@ mod oidc_core {
     pub const UPDATED_AT: Claim<Timestamp> = Claim::new("updated_at");
 }
 
-pub use self::{oidc_core::*, rfc7519::*};
+/// Claims defined in RFC8693 sec. 4, used by RFC9068
+/// <https://www.rfc-editor.org/rfc/rfc8693.html#section-4>
+mod rfc8693 {
+    use super::Claim;
+
+    pub const SCOPE: Claim<String> = Claim::new("scope");
+    pub const CLIENT_ID: Claim<String> = Claim::new("client_id");
+}
+
+/// Claims defined in RFC7800 sec. 3.1
+/// <https://www.rfc-editor.org/rfc/rfc7800.html#section-3.1>
+mod rfc7800 {
+    use std::collections::HashMap;
+
+    use super::Claim;
+
+    pub const CNF: Claim<HashMap<String, String>> = Claim::new("cnf");
+}
+
+pub use self::{oidc_core::*, rfc7519::*, rfc7800::*, rfc8693::*};
 
 #[cfg(test)]
 mod tests {
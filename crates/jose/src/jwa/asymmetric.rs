@@ -4,6 +4,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::sync::Arc;
+
 use digest::Digest;
 use mas_iana::jose::{JsonWebKeyEcEllipticCurve, JsonWebSignatureAlg};
 use sha2::{Sha256, Sha384, Sha512};
@@ -34,6 +36,17 @@ pub enum AsymmetricKeyFromJwkError {
     KeyNotSuitable { alg: JsonWebSignatureAlg },
 }
 
+/// A signing key held outside of the process, for example in a hardware
+/// security module
+pub trait ExternalSigningKey: Send + Sync {
+    /// Sign the message, returning the signature as encoded in a JWS
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key could not sign the message
+    fn try_sign(&self, msg: &[u8]) -> Result<Vec<u8>, signature::Error>;
+}
+
 /// An enum of all supported asymmetric signature algorithms verifying keys
 #[non_exhaustive]
 pub enum AsymmetricSigningKey {
@@ -46,9 +59,17 @@ pub enum AsymmetricSigningKey {
     Es256(super::Es256SigningKey),
     Es384(super::Es384SigningKey),
     Es256K(super::Es256KSigningKey),
+    External(Arc<dyn ExternalSigningKey>),
 }
 
 impl AsymmetricSigningKey {
+    /// Create a new signing key which signs through the given
+    /// [`ExternalSigningKey`].
+    #[must_use]
+    pub fn external(key: Arc<dyn ExternalSigningKey>) -> Self {
+        Self::External(key)
+    }
+
     /// Create a new signing key with the RS256 algorithm from the given RSA
     /// private key.
     #[must_use]
@@ -273,6 +294,7 @@ impl signature::RandomizedSigner<Signature> for AsymmetricSigningKey {
                 let signature: ecdsa::Signature<_> = key.try_sign_with_rng(rng, msg)?;
                 Ok(Signature::from_signature(&signature))
             }
+            Self::External(key) => Ok(Signature::new(key.try_sign(msg)?)),
         }
     }
 }
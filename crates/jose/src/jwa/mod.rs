@@ -13,7 +13,9 @@ mod signature;
 mod symmetric;
 
 pub use self::{
-    asymmetric::{AsymmetricKeyFromJwkError, AsymmetricSigningKey, AsymmetricVerifyingKey},
+    asymmetric::{
+        AsymmetricKeyFromJwkError, AsymmetricSigningKey, AsymmetricVerifyingKey, ExternalSigningKey,
+    },
     symmetric::{InvalidAlgorithm, SymmetricKey},
 };
 
@@ -57,3 +59,16 @@ pub const SUPPORTED_SIGNING_ALGORITHMS: [JsonWebSignatureAlg; 12] = [
     JsonWebSignatureAlg::Es384,
     JsonWebSignatureAlg::Es256K,
 ];
+
+/// The signing algorithms supported by this crate which use asymmetric keys.
+pub const SUPPORTED_ASYMMETRIC_SIGNING_ALGORITHMS: [JsonWebSignatureAlg; 9] = [
+    JsonWebSignatureAlg::Rs256,
+    JsonWebSignatureAlg::Rs384,
+    JsonWebSignatureAlg::Rs512,
+    JsonWebSignatureAlg::Ps256,
+    JsonWebSignatureAlg::Ps384,
+    JsonWebSignatureAlg::Ps512,
+    JsonWebSignatureAlg::Es256,
+    JsonWebSignatureAlg::Es384,
+    JsonWebSignatureAlg::Es256K,
+];
@@ -237,6 +237,15 @@ impl<P> JsonWebKey<P> {
     pub const fn params(&self) -> &P {
         &self.parameters
     }
+
+    /// Get the DER-encoded certificate chain (`x5c` field) of this
+    /// [`JsonWebKey`], if set.
+    ///
+    /// The first certificate of the chain contains the key.
+    #[must_use]
+    pub fn x5c(&self) -> Option<&[Base64]> {
+        self.x5c.as_deref()
+    }
 }
 
 impl<P> Constrainable for JsonWebKey<P>
@@ -368,6 +377,23 @@ mod tests {
     use super::*;
     use crate::constraints::ConstraintSet;
 
+    #[test]
+    fn thumbprint() {
+        // Example from RFC 7638, section 3.1
+        let jwk = serde_json::json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        });
+        let jwk: PublicJsonWebKey = serde_json::from_value(jwk).unwrap();
+        assert_eq!(
+            jwk.params().thumbprint(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
     #[test]
     fn load_google_keys() {
         let jwks = serde_json::json!({
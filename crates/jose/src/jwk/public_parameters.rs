@@ -9,6 +9,7 @@ use mas_iana::jose::{
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use super::ParametersInfo;
 use crate::base64::Base64UrlNoPad;
@@ -50,6 +51,34 @@ impl JsonWebKeyPublicParameters {
             _ => None,
         }
     }
+
+    /// Compute the SHA-256 thumbprint of this key, base64url-encoded
+    ///
+    /// Ref: <https://www.rfc-editor.org/rfc/rfc7638.html>
+    #[must_use]
+    pub fn thumbprint(&self) -> String {
+        // The required members, in lexicographic order and without whitespace
+        let canonical = match self {
+            Self::Rsa(params) => format!(
+                r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#,
+                e = params.e.encode(),
+                n = params.n.encode(),
+            ),
+            Self::Ec(params) => format!(
+                r#"{{"crv":"{crv}","kty":"EC","x":"{x}","y":"{y}"}}"#,
+                crv = params.crv,
+                x = params.x.encode(),
+                y = params.y.encode(),
+            ),
+            Self::Okp(params) => format!(
+                r#"{{"crv":"{crv}","kty":"OKP","x":"{x}"}}"#,
+                crv = params.crv,
+                x = params.x.encode(),
+            ),
+        };
+
+        Base64UrlNoPad::new(Sha256::digest(canonical).to_vec()).encode()
+    }
 }
 
 impl ParametersInfo for JsonWebKeyPublicParameters {
@@ -0,0 +1,116 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::sync::Arc;
+
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_kms::{
+    primitives::Blob,
+    types::{MessageType, SigningAlgorithmSpec},
+    Client,
+};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_keystore::{
+    external::{digest, signature_from_der, ExternalKey, ExternalSigner},
+    PrivateKey,
+};
+
+use crate::{block_on, KmsError};
+
+/// A client to AWS KMS
+#[derive(Debug, Clone)]
+pub struct AwsKms {
+    client: Client,
+}
+
+impl AwsKms {
+    /// Create a client, with the credentials loaded from the environment
+    ///
+    /// The region is also loaded from the environment, unless one is given.
+    pub async fn from_env(region: Option<String>) -> Self {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(Region::new(region));
+        }
+
+        let config = loader.load().await;
+        Self {
+            client: Client::new(&config),
+        }
+    }
+
+    /// Load an asymmetric signing key
+    ///
+    /// The key ID can be a key ID, a key ARN, an alias name prefixed with
+    /// `alias/`, or an alias ARN. An alias is resolved once, so the key keeps
+    /// being used even if the alias is later moved to another key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the public key could not be fetched, or if its type
+    /// is not supported
+    pub async fn load_key(&self, key_id: &str) -> Result<PrivateKey, KmsError> {
+        let output = self
+            .client
+            .get_public_key()
+            .key_id(key_id)
+            .send()
+            .await
+            .map_err(aws_sdk_kms::Error::from)?;
+
+        let public_key = output
+            .public_key()
+            .ok_or(KmsError::MissingField("public key"))?;
+
+        let signer = AwsKmsSigner {
+            client: self.client.clone(),
+            key_id: output.key_id().unwrap_or(key_id).to_owned(),
+        };
+
+        let key = ExternalKey::from_public_key_der(public_key.as_ref(), Arc::new(signer))?;
+        Ok(PrivateKey::External(Box::new(key)))
+    }
+}
+
+struct AwsKmsSigner {
+    client: Client,
+    key_id: String,
+}
+
+impl ExternalSigner for AwsKmsSigner {
+    fn sign(&self, alg: &JsonWebSignatureAlg, msg: &[u8]) -> Result<Vec<u8>, signature::Error> {
+        let signing_algorithm = match alg {
+            JsonWebSignatureAlg::Rs256 => SigningAlgorithmSpec::RsassaPkcs1V15Sha256,
+            JsonWebSignatureAlg::Rs384 => SigningAlgorithmSpec::RsassaPkcs1V15Sha384,
+            JsonWebSignatureAlg::Rs512 => SigningAlgorithmSpec::RsassaPkcs1V15Sha512,
+            JsonWebSignatureAlg::Ps256 => SigningAlgorithmSpec::RsassaPssSha256,
+            JsonWebSignatureAlg::Ps384 => SigningAlgorithmSpec::RsassaPssSha384,
+            JsonWebSignatureAlg::Ps512 => SigningAlgorithmSpec::RsassaPssSha512,
+            JsonWebSignatureAlg::Es256 => SigningAlgorithmSpec::EcdsaSha256,
+            JsonWebSignatureAlg::Es384 => SigningAlgorithmSpec::EcdsaSha384,
+            _ => return Err(signature::Error::new()),
+        };
+
+        // Send the digest instead of the message, as messages are limited to 4KB
+        let digest = digest(alg, msg).ok_or_else(signature::Error::new)?;
+
+        let output = block_on(
+            self.client
+                .sign()
+                .key_id(&self.key_id)
+                .message(Blob::new(digest))
+                .message_type(MessageType::Digest)
+                .signing_algorithm(signing_algorithm)
+                .send(),
+        )?
+        .map_err(|e| signature::Error::from_source(aws_sdk_kms::Error::from(e)))?;
+
+        let signature = output
+            .signature()
+            .ok_or_else(|| signature::Error::from_source(KmsError::MissingField("signature")))?;
+
+        signature_from_der(alg, signature.as_ref().to_vec())
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{fmt, sync::Arc};
+
+use base64ct::{Base64, Encoding};
+use gcp_auth::TokenProvider;
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_keystore::{
+    external::{digest, signature_from_der, ExternalKey, ExternalSigner},
+    PrivateKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{block_on, KmsError};
+
+const BASE_URL: &str = "https://cloudkms.googleapis.com/v1";
+const SCOPES: &[&str] = &["https://www.googleapis.com/auth/cloudkms"];
+
+#[derive(Deserialize)]
+struct PublicKeyResponse {
+    pem: String,
+    algorithm: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Digest {
+    Sha256(String),
+    Sha384(String),
+    Sha512(String),
+}
+
+#[derive(Serialize)]
+struct AsymmetricSignRequest {
+    digest: Digest,
+}
+
+#[derive(Deserialize)]
+struct AsymmetricSignResponse {
+    signature: String,
+}
+
+/// A client to GCP Cloud KMS
+#[derive(Clone)]
+pub struct GcpKms {
+    http_client: reqwest::Client,
+    auth: Arc<dyn TokenProvider>,
+}
+
+impl fmt::Debug for GcpKms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcpKms").finish_non_exhaustive()
+    }
+}
+
+impl GcpKms {
+    /// Create a client, with the credentials loaded from the environment
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no credentials could be found
+    pub async fn from_env() -> Result<Self, KmsError> {
+        let auth = gcp_auth::provider().await?;
+        Ok(Self {
+            http_client: reqwest::Client::new(),
+            auth,
+        })
+    }
+
+    /// Load an asymmetric signing key version
+    ///
+    /// The name is the full resource name of the key version, like
+    /// `projects/*/locations/*/keyRings/*/cryptoKeys/*/cryptoKeyVersions/*`.
+    /// The key can only be used with the algorithm it was created for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the public key could not be fetched, or if its type
+    /// is not supported
+    pub async fn load_key(&self, name: &str) -> Result<PrivateKey, KmsError> {
+        let token = self.auth.token(SCOPES).await?;
+        let response: PublicKeyResponse = self
+            .http_client
+            .get(format!("{BASE_URL}/{name}/publicKey"))
+            .bearer_auth(token.as_str())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let alg: &'static [JsonWebSignatureAlg] = match response.algorithm.as_str() {
+            "RSA_SIGN_PKCS1_2048_SHA256"
+            | "RSA_SIGN_PKCS1_3072_SHA256"
+            | "RSA_SIGN_PKCS1_4096_SHA256" => &[JsonWebSignatureAlg::Rs256],
+            "RSA_SIGN_PKCS1_4096_SHA512" => &[JsonWebSignatureAlg::Rs512],
+            "RSA_SIGN_PSS_2048_SHA256"
+            | "RSA_SIGN_PSS_3072_SHA256"
+            | "RSA_SIGN_PSS_4096_SHA256" => &[JsonWebSignatureAlg::Ps256],
+            "RSA_SIGN_PSS_4096_SHA512" => &[JsonWebSignatureAlg::Ps512],
+            "EC_SIGN_P256_SHA256" => &[JsonWebSignatureAlg::Es256],
+            "EC_SIGN_P384_SHA384" => &[JsonWebSignatureAlg::Es384],
+            _ => return Err(KmsError::UnsupportedAlgorithm(response.algorithm)),
+        };
+
+        let (_label, der) = pem_rfc7468::decode_vec(response.pem.as_bytes())
+            .map_err(mas_keystore::LoadError::from)?;
+
+        let signer = GcpKmsSigner {
+            client: self.clone(),
+            name: name.to_owned(),
+        };
+
+        let key = ExternalKey::from_public_key_der(&der, Arc::new(signer))?.with_algs(alg);
+        Ok(PrivateKey::External(Box::new(key)))
+    }
+
+    async fn sign(&self, name: &str, digest: Digest) -> Result<Vec<u8>, KmsError> {
+        let token = self.auth.token(SCOPES).await?;
+        let response: AsymmetricSignResponse = self
+            .http_client
+            .post(format!("{BASE_URL}/{name}:asymmetricSign"))
+            .bearer_auth(token.as_str())
+            .json(&AsymmetricSignRequest { digest })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Base64::decode_vec(&response.signature).map_err(|_| KmsError::Base64)
+    }
+}
+
+struct GcpKmsSigner {
+    client: GcpKms,
+    name: String,
+}
+
+impl ExternalSigner for GcpKmsSigner {
+    fn sign(&self, alg: &JsonWebSignatureAlg, msg: &[u8]) -> Result<Vec<u8>, signature::Error> {
+        let hash = digest(alg, msg).ok_or_else(signature::Error::new)?;
+        let hash = Base64::encode_string(&hash);
+        let digest = match alg {
+            JsonWebSignatureAlg::Rs256
+            | JsonWebSignatureAlg::Ps256
+            | JsonWebSignatureAlg::Es256 => Digest::Sha256(hash),
+            JsonWebSignatureAlg::Rs384
+            | JsonWebSignatureAlg::Ps384
+            | JsonWebSignatureAlg::Es384 => Digest::Sha384(hash),
+            JsonWebSignatureAlg::Rs512 | JsonWebSignatureAlg::Ps512 => Digest::Sha512(hash),
+            _ => return Err(signature::Error::new()),
+        };
+
+        let signature = block_on(self.client.sign(&self.name, digest))?
+            .map_err(signature::Error::from_source)?;
+
+        signature_from_der(alg, signature)
+    }
+}
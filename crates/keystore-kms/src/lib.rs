@@ -0,0 +1,86 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Signing keys held in a cloud key management service
+//!
+//! The public keys are fetched once when the keys are loaded, so that the JWKS
+//! can be served without calling the service. Signing operations are delegated
+//! to the service.
+//!
+//! This crate is kept out of the workspace, so that default builds don't have
+//! to resolve the cloud SDKs. Each service is behind a feature: `aws` for AWS
+//! KMS, and `gcp` for GCP Cloud KMS.
+
+use std::future::Future;
+
+use mas_keystore::LoadError;
+use thiserror::Error;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "gcp")]
+mod gcp;
+
+#[cfg(feature = "aws")]
+pub use self::aws::AwsKms;
+#[cfg(feature = "gcp")]
+pub use self::gcp::GcpKms;
+
+/// Error returned when interacting with a cloud key management service
+#[derive(Debug, Error)]
+pub enum KmsError {
+    #[cfg(feature = "aws")]
+    #[error(transparent)]
+    Aws {
+        #[from]
+        inner: aws_sdk_kms::Error,
+    },
+
+    #[cfg(feature = "gcp")]
+    #[error("Failed to authenticate to GCP")]
+    GcpAuth {
+        #[from]
+        inner: gcp_auth::Error,
+    },
+
+    #[cfg(feature = "gcp")]
+    #[error(transparent)]
+    Http {
+        #[from]
+        inner: reqwest::Error,
+    },
+
+    #[error("The service did not return the {0}")]
+    MissingField(&'static str),
+
+    #[error("Invalid base64 in the response")]
+    Base64,
+
+    #[error("Unsupported key algorithm {0:?}")]
+    UnsupportedAlgorithm(String),
+
+    #[error("Invalid public key")]
+    PublicKey {
+        #[from]
+        inner: LoadError,
+    },
+
+    #[error("Signing through a cloud KMS requires a multi-threaded runtime")]
+    UnsupportedRuntime,
+}
+
+/// Run a future to completion from a synchronous signing operation
+///
+/// This blocks the current worker thread, letting the runtime move other tasks
+/// to the other workers in the meantime.
+fn block_on<F: Future>(future: F) -> Result<F::Output, signature::Error> {
+    let handle = Handle::try_current().map_err(signature::Error::from_source)?;
+    if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+        return Err(signature::Error::from_source(KmsError::UnsupportedRuntime));
+    }
+
+    Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+}
@@ -0,0 +1,309 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Signing keys held in a PKCS#11 token, like a hardware security module
+//!
+//! The private keys never leave the token: signing operations are delegated to
+//! the PKCS#11 module, and only the public halves are read, to be published in
+//! the JWKS.
+//!
+//! This crate is kept out of the workspace, so that default builds don't have
+//! to resolve `cryptoki`.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use camino::Utf8Path;
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    error::RvError,
+    mechanism::{
+        rsa::{PkcsMgfType, PkcsPssParams},
+        Mechanism, MechanismType,
+    },
+    object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use der::{asn1::OctetStringRef, Decode};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_keystore::{
+    external::{digest, ExternalKey, ExternalSigner},
+    LoadError, PrivateKey,
+};
+use pkcs8::{AssociatedOid, EncodePublicKey};
+use rsa::BigUint;
+use thiserror::Error;
+
+/// Error returned when a key could not be loaded from a PKCS#11 token
+#[derive(Debug, Error)]
+pub enum Pkcs11Error {
+    #[error(transparent)]
+    Pkcs11 {
+        #[from]
+        inner: cryptoki::error::Error,
+    },
+
+    #[error("No token in slot {slot}")]
+    SlotNotFound { slot: u64 },
+
+    #[error("No key pair labelled {label:?} in the token")]
+    KeyNotFound { label: String },
+
+    #[error("Missing attribute {attribute} on key {label:?}")]
+    MissingAttribute {
+        attribute: AttributeType,
+        label: String,
+    },
+
+    #[error("Unsupported key type {key_type}")]
+    UnsupportedKeyType { key_type: KeyType },
+
+    #[error("Unsupported Elliptic Curve OID {oid}")]
+    UnsupportedEllipticCurve { oid: const_oid::ObjectIdentifier },
+
+    #[error(transparent)]
+    Der {
+        #[from]
+        inner: der::Error,
+    },
+
+    #[error("Invalid RSA public key")]
+    Rsa {
+        #[from]
+        inner: rsa::errors::Error,
+    },
+
+    #[error("Invalid Elliptic Curve public key")]
+    EllipticCurve {
+        #[from]
+        inner: p256::elliptic_curve::Error,
+    },
+
+    #[error(transparent)]
+    Spki {
+        #[from]
+        inner: pkcs8::spki::Error,
+    },
+
+    #[error("Invalid public key")]
+    PublicKey {
+        #[from]
+        inner: LoadError,
+    },
+}
+
+/// A logged in session on a PKCS#11 token
+///
+/// Operations on a session can't run concurrently, so they are serialized.
+pub struct Pkcs11Session {
+    session: Mutex<Session>,
+}
+
+impl fmt::Debug for Pkcs11Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11Session").finish_non_exhaustive()
+    }
+}
+
+impl Pkcs11Session {
+    /// Load the PKCS#11 module at the given path, open a session on the token
+    /// in the given slot, and log in as the user with the given PIN
+    ///
+    /// This does blocking calls to the module.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module could not be loaded, if there is no
+    /// token in the slot, or if the login failed
+    pub fn open(module: &Utf8Path, slot: u64, pin: String) -> Result<Arc<Self>, Pkcs11Error> {
+        let pkcs11 = Pkcs11::new(module)?;
+
+        // The module might already have been initialized if it was loaded before,
+        // e.g. when the configuration is reloaded
+        match pkcs11.initialize(CInitializeArgs::OsThreads) {
+            Ok(())
+            | Err(cryptoki::error::Error::Pkcs11(RvError::CryptokiAlreadyInitialized, ..)) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let token_slot = pkcs11
+            .get_slots_with_token()?
+            .into_iter()
+            .find(|s| s.id() == slot)
+            .ok_or(Pkcs11Error::SlotNotFound { slot })?;
+
+        let session = pkcs11.open_rw_session(token_slot)?;
+        session.login(UserType::User, Some(&AuthPin::new(pin)))?;
+
+        Ok(Arc::new(Self {
+            session: Mutex::new(session),
+        }))
+    }
+
+    /// Find the key pair with the given label in the token
+    ///
+    /// Both the private key and the public key objects must have this label.
+    /// RSA keys, and Elliptic Curve keys on the P-256 and P-384 curves are
+    /// supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key pair could not be found, or if its public
+    /// key could not be read
+    pub fn find_key(self: &Arc<Self>, label: &str) -> Result<PrivateKey, Pkcs11Error> {
+        let session = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let find = |class| -> Result<ObjectHandle, Pkcs11Error> {
+            session
+                .find_objects(&[
+                    Attribute::Class(class),
+                    Attribute::Label(label.as_bytes().to_vec()),
+                ])?
+                .into_iter()
+                .next()
+                .ok_or_else(|| Pkcs11Error::KeyNotFound {
+                    label: label.to_owned(),
+                })
+        };
+
+        let private_key = find(ObjectClass::PRIVATE_KEY)?;
+        let public_key = find(ObjectClass::PUBLIC_KEY)?;
+
+        let attributes = session.get_attributes(
+            public_key,
+            &[
+                AttributeType::KeyType,
+                AttributeType::Modulus,
+                AttributeType::PublicExponent,
+                AttributeType::EcParams,
+                AttributeType::EcPoint,
+            ],
+        )?;
+        drop(session);
+
+        let mut key_type = None;
+        let mut modulus = None;
+        let mut public_exponent = None;
+        let mut ec_params = None;
+        let mut ec_point = None;
+        for attribute in attributes {
+            match attribute {
+                Attribute::KeyType(value) => key_type = Some(value),
+                Attribute::Modulus(value) => modulus = Some(value),
+                Attribute::PublicExponent(value) => public_exponent = Some(value),
+                Attribute::EcParams(value) => ec_params = Some(value),
+                Attribute::EcPoint(value) => ec_point = Some(value),
+                _ => {}
+            }
+        }
+
+        let missing = |attribute| Pkcs11Error::MissingAttribute {
+            attribute,
+            label: label.to_owned(),
+        };
+
+        let key_type = key_type.ok_or_else(|| missing(AttributeType::KeyType))?;
+        let public_key_der = if key_type == KeyType::RSA {
+            let n = modulus.ok_or_else(|| missing(AttributeType::Modulus))?;
+            let e = public_exponent.ok_or_else(|| missing(AttributeType::PublicExponent))?;
+            let key =
+                rsa::RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e))?;
+            key.to_public_key_der()?
+        } else if key_type == KeyType::EC {
+            let params = ec_params.ok_or_else(|| missing(AttributeType::EcParams))?;
+            let oid = const_oid::ObjectIdentifier::from_der(&params)?;
+
+            // The point should be wrapped in a DER octet string, but some modules
+            // return it as-is
+            let point = ec_point.ok_or_else(|| missing(AttributeType::EcPoint))?;
+            let point = OctetStringRef::from_der(&point)
+                .map_or(&point[..], |octet_string| octet_string.as_bytes());
+
+            match oid {
+                p256::NistP256::OID => {
+                    p256::PublicKey::from_sec1_bytes(point)?.to_public_key_der()?
+                }
+                p384::NistP384::OID => {
+                    p384::PublicKey::from_sec1_bytes(point)?.to_public_key_der()?
+                }
+                oid => return Err(Pkcs11Error::UnsupportedEllipticCurve { oid }),
+            }
+        } else {
+            return Err(Pkcs11Error::UnsupportedKeyType { key_type });
+        };
+
+        let signer = Pkcs11Signer {
+            session: self.clone(),
+            private_key,
+        };
+
+        let key = ExternalKey::from_public_key_der(public_key_der.as_bytes(), Arc::new(signer))?;
+        Ok(PrivateKey::External(Box::new(key)))
+    }
+
+    fn sign(
+        &self,
+        mechanism: &Mechanism,
+        key: ObjectHandle,
+        data: &[u8],
+    ) -> Result<Vec<u8>, cryptoki::error::Error> {
+        let session = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+        session.sign(mechanism, key, data)
+    }
+}
+
+/// Signs with a private key held in a PKCS#11 token
+struct Pkcs11Signer {
+    session: Arc<Pkcs11Session>,
+    private_key: ObjectHandle,
+}
+
+impl ExternalSigner for Pkcs11Signer {
+    fn sign(&self, alg: &JsonWebSignatureAlg, msg: &[u8]) -> Result<Vec<u8>, signature::Error> {
+        let pss = |hash_alg, mgf, s_len: u64| PkcsPssParams {
+            hash_alg,
+            mgf,
+            s_len: s_len.into(),
+        };
+
+        // The ECDSA mechanism signs a digest computed beforehand, and returns the
+        // signature as the concatenation of `r` and `s`, like JWS expects
+        let mechanism = match alg {
+            JsonWebSignatureAlg::Rs256 => Mechanism::Sha256RsaPkcs,
+            JsonWebSignatureAlg::Rs384 => Mechanism::Sha384RsaPkcs,
+            JsonWebSignatureAlg::Rs512 => Mechanism::Sha512RsaPkcs,
+            JsonWebSignatureAlg::Ps256 => Mechanism::Sha256RsaPkcsPss(pss(
+                MechanismType::SHA256,
+                PkcsMgfType::MGF1_SHA256,
+                32,
+            )),
+            JsonWebSignatureAlg::Ps384 => Mechanism::Sha384RsaPkcsPss(pss(
+                MechanismType::SHA384,
+                PkcsMgfType::MGF1_SHA384,
+                48,
+            )),
+            JsonWebSignatureAlg::Ps512 => Mechanism::Sha512RsaPkcsPss(pss(
+                MechanismType::SHA512,
+                PkcsMgfType::MGF1_SHA512,
+                64,
+            )),
+            JsonWebSignatureAlg::Es256 | JsonWebSignatureAlg::Es384 => Mechanism::Ecdsa,
+            _ => return Err(signature::Error::new()),
+        };
+
+        let digest = match mechanism {
+            Mechanism::Ecdsa => digest(alg, msg),
+            _ => None,
+        };
+        let data = digest.as_deref().unwrap_or(msg);
+
+        self.session
+            .sign(&mechanism, self.private_key, data)
+            .map_err(signature::Error::from_source)
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Keys held outside of the process, like in a hardware security module or in
+//! a cloud key management service
+//!
+//! Only the public half of those keys is known, and signing is delegated to an
+//! [`ExternalSigner`]. The backends, like PKCS#11 tokens or cloud key
+//! management services, live in their own crates outside of the workspace, so
+//! that their dependencies are not pulled by default builds.
+
+use std::{fmt, sync::Arc};
+
+use der::Decode;
+use mas_iana::jose::{JsonWebKeyType, JsonWebSignatureAlg};
+use mas_jose::{
+    jwa::{AsymmetricSigningKey, AsymmetricVerifyingKey, ExternalSigningKey},
+    jwk::JsonWebKeyPublicParameters,
+};
+use pkcs8::AssociatedOid;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::LoadError;
+
+/// Something which can sign messages with a key it holds
+pub trait ExternalSigner: Send + Sync {
+    /// Sign the message with the given algorithm, returning the signature as
+    /// encoded in a JWS
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message could not be signed
+    fn sign(&self, alg: &JsonWebSignatureAlg, msg: &[u8]) -> Result<Vec<u8>, signature::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ExternalPublicKey {
+    Rsa(Box<rsa::RsaPublicKey>),
+    EcP256(Box<p256::PublicKey>),
+    EcP384(Box<p384::PublicKey>),
+}
+
+impl ExternalPublicKey {
+    fn from_public_key_der(der: &[u8]) -> Result<Self, LoadError> {
+        let info = spki::SubjectPublicKeyInfoRef::from_der(der)?;
+        match info.algorithm.oid {
+            pkcs1::ALGORITHM_OID => Ok(Self::Rsa(Box::new(info.try_into()?))),
+            elliptic_curve::ALGORITHM_OID => match info.algorithm.parameters_oid()? {
+                p256::NistP256::OID => Ok(Self::EcP256(Box::new(info.try_into()?))),
+                p384::NistP384::OID => Ok(Self::EcP384(Box::new(info.try_into()?))),
+                oid => Err(LoadError::UnknownEllipticCurveOid { oid }),
+            },
+            oid => Err(LoadError::UnknownAlgorithmOid { oid }),
+        }
+    }
+}
+
+/// A key pair held outside of the process
+#[derive(Clone)]
+pub struct ExternalKey {
+    public: ExternalPublicKey,
+    algs: Option<&'static [JsonWebSignatureAlg]>,
+    signer: Arc<dyn ExternalSigner>,
+}
+
+impl fmt::Debug for ExternalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalKey")
+            .field("public", &self.public)
+            .field("algs", &self.algs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExternalKey {
+    pub(crate) fn new(public: ExternalPublicKey, signer: Arc<dyn ExternalSigner>) -> Self {
+        Self {
+            public,
+            algs: None,
+            signer,
+        }
+    }
+
+    /// Restrict the algorithms the key can be used with, for backends which
+    /// bind a key to a single algorithm
+    #[must_use]
+    pub fn with_algs(mut self, algs: &'static [JsonWebSignatureAlg]) -> Self {
+        self.algs = Some(algs);
+        self
+    }
+
+    /// Create a key out of its DER-encoded SPKI public key, and the signer
+    /// holding its private key
+    ///
+    /// RSA keys, and Elliptic Curve keys on the P-256 and P-384 curves are
+    /// supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the public key could not be decoded, or if its type
+    /// is not supported
+    pub fn from_public_key_der(
+        der: &[u8],
+        signer: Arc<dyn ExternalSigner>,
+    ) -> Result<Self, LoadError> {
+        let public = ExternalPublicKey::from_public_key_der(der)?;
+        Ok(Self::new(public, signer))
+    }
+
+    pub(crate) fn public_parameters(&self) -> JsonWebKeyPublicParameters {
+        match &self.public {
+            ExternalPublicKey::Rsa(key) => key.as_ref().into(),
+            ExternalPublicKey::EcP256(key) => (**key).into(),
+            ExternalPublicKey::EcP384(key) => (**key).into(),
+        }
+    }
+
+    pub(crate) fn kty(&self) -> JsonWebKeyType {
+        match self.public {
+            ExternalPublicKey::Rsa(_) => JsonWebKeyType::Rsa,
+            ExternalPublicKey::EcP256(_) | ExternalPublicKey::EcP384(_) => JsonWebKeyType::Ec,
+        }
+    }
+
+    pub(crate) fn possible_algs(&self) -> &'static [JsonWebSignatureAlg] {
+        if let Some(algs) = self.algs {
+            return algs;
+        }
+
+        match self.public {
+            ExternalPublicKey::Rsa(_) => &[
+                JsonWebSignatureAlg::Rs256,
+                JsonWebSignatureAlg::Rs384,
+                JsonWebSignatureAlg::Rs512,
+                JsonWebSignatureAlg::Ps256,
+                JsonWebSignatureAlg::Ps384,
+                JsonWebSignatureAlg::Ps512,
+            ],
+            ExternalPublicKey::EcP256(_) => &[JsonWebSignatureAlg::Es256],
+            ExternalPublicKey::EcP384(_) => &[JsonWebSignatureAlg::Es384],
+        }
+    }
+
+    pub(crate) fn verifying_key_for_alg(
+        &self,
+        alg: &JsonWebSignatureAlg,
+    ) -> Option<AsymmetricVerifyingKey> {
+        if !self.possible_algs().contains(alg) {
+            return None;
+        }
+
+        let key = match (&self.public, alg) {
+            (ExternalPublicKey::Rsa(key), JsonWebSignatureAlg::Rs256) => {
+                AsymmetricVerifyingKey::rs256(*key.clone())
+            }
+            (ExternalPublicKey::Rsa(key), JsonWebSignatureAlg::Rs384) => {
+                AsymmetricVerifyingKey::rs384(*key.clone())
+            }
+            (ExternalPublicKey::Rsa(key), JsonWebSignatureAlg::Rs512) => {
+                AsymmetricVerifyingKey::rs512(*key.clone())
+            }
+            (ExternalPublicKey::Rsa(key), JsonWebSignatureAlg::Ps256) => {
+                AsymmetricVerifyingKey::ps256(*key.clone())
+            }
+            (ExternalPublicKey::Rsa(key), JsonWebSignatureAlg::Ps384) => {
+                AsymmetricVerifyingKey::ps384(*key.clone())
+            }
+            (ExternalPublicKey::Rsa(key), JsonWebSignatureAlg::Ps512) => {
+                AsymmetricVerifyingKey::ps512(*key.clone())
+            }
+            (ExternalPublicKey::EcP256(key), JsonWebSignatureAlg::Es256) => {
+                AsymmetricVerifyingKey::es256(**key)
+            }
+            (ExternalPublicKey::EcP384(key), JsonWebSignatureAlg::Es384) => {
+                AsymmetricVerifyingKey::es384(**key)
+            }
+            _ => return None,
+        };
+
+        Some(key)
+    }
+
+    pub(crate) fn signing_key_for_alg(
+        &self,
+        alg: &JsonWebSignatureAlg,
+    ) -> Option<AsymmetricSigningKey> {
+        if !self.possible_algs().contains(alg) {
+            return None;
+        }
+
+        Some(AsymmetricSigningKey::external(Arc::new(BoundSigner {
+            signer: self.signer.clone(),
+            alg: alg.clone(),
+        })))
+    }
+}
+
+/// An [`ExternalSigner`] bound to a specific algorithm
+struct BoundSigner {
+    signer: Arc<dyn ExternalSigner>,
+    alg: JsonWebSignatureAlg,
+}
+
+impl ExternalSigningKey for BoundSigner {
+    fn try_sign(&self, msg: &[u8]) -> Result<Vec<u8>, signature::Error> {
+        self.signer.sign(&self.alg, msg)
+    }
+}
+
+/// Hash the message with the digest algorithm used by the given signature
+/// algorithm
+///
+/// Returns `None` if the algorithm is not supported by external keys.
+#[must_use]
+pub fn digest(alg: &JsonWebSignatureAlg, msg: &[u8]) -> Option<Vec<u8>> {
+    let digest = match alg {
+        JsonWebSignatureAlg::Rs256 | JsonWebSignatureAlg::Ps256 | JsonWebSignatureAlg::Es256 => {
+            Sha256::digest(msg).to_vec()
+        }
+        JsonWebSignatureAlg::Rs384 | JsonWebSignatureAlg::Ps384 | JsonWebSignatureAlg::Es384 => {
+            Sha384::digest(msg).to_vec()
+        }
+        JsonWebSignatureAlg::Rs512 | JsonWebSignatureAlg::Ps512 => Sha512::digest(msg).to_vec(),
+        _ => return None,
+    };
+
+    Some(digest)
+}
+
+/// Convert a signature returned by a backend to the format used in a JWS
+///
+/// ECDSA signatures are usually DER-encoded, whereas a JWS has the fixed-size
+/// concatenation of `r` and `s`. Other signatures are left as-is.
+///
+/// # Errors
+///
+/// Returns an error if an ECDSA signature is not valid DER
+pub fn signature_from_der(
+    alg: &JsonWebSignatureAlg,
+    signature: Vec<u8>,
+) -> Result<Vec<u8>, signature::Error> {
+    let signature = match alg {
+        JsonWebSignatureAlg::Es256 => ecdsa::Signature::<p256::NistP256>::from_der(&signature)?
+            .to_bytes()
+            .to_vec(),
+        JsonWebSignatureAlg::Es384 => ecdsa::Signature::<p384::NistP384>::from_der(&signature)?
+            .to_bytes()
+            .to_vec(),
+        _ => signature,
+    };
+
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_jose::jwt::{JsonWebSignatureHeader, Jwt};
+    use rand::SeedableRng;
+    use signature::{RandomizedDigestSigner, SignatureEncoding};
+
+    use super::*;
+
+    /// A signer which behaves like a cloud KMS: it receives a digest, and
+    /// returns DER-encoded ECDSA signatures
+    struct DerSigner(ecdsa::SigningKey<p256::NistP256>);
+
+    impl ExternalSigner for DerSigner {
+        fn sign(&self, alg: &JsonWebSignatureAlg, msg: &[u8]) -> Result<Vec<u8>, signature::Error> {
+            assert_eq!(alg, &JsonWebSignatureAlg::Es256);
+            let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+            let digest = Sha256::new_with_prefix(msg);
+            let signature: ecdsa::Signature<p256::NistP256> =
+                self.0.try_sign_digest_with_rng(&mut rng, digest)?;
+            signature_from_der(alg, signature.to_der().to_vec())
+        }
+    }
+
+    #[test]
+    fn sign_with_der_signer() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let secret = p256::SecretKey::random(&mut rng);
+        let public_key_der = pkcs8::EncodePublicKey::to_public_key_der(&secret.public_key())
+            .unwrap()
+            .to_vec();
+
+        let key = ExternalKey::from_public_key_der(
+            &public_key_der,
+            Arc::new(DerSigner(ecdsa::SigningKey::from(secret))),
+        )
+        .unwrap();
+        assert_eq!(key.kty(), JsonWebKeyType::Ec);
+        assert_eq!(key.possible_algs(), &[JsonWebSignatureAlg::Es256]);
+        assert!(key
+            .signing_key_for_alg(&JsonWebSignatureAlg::Rs256)
+            .is_none());
+
+        let alg = JsonWebSignatureAlg::Es256;
+        let signer = key.signing_key_for_alg(&alg).unwrap();
+        let jwt = Jwt::sign(JsonWebSignatureHeader::new(alg.clone()), "hello", &signer).unwrap();
+        let verifier = key.verifying_key_for_alg(&alg).unwrap();
+        jwt.verify(&verifier).unwrap();
+    }
+}
@@ -24,10 +24,14 @@ use rsa::BigUint;
 use thiserror::Error;
 
 mod encrypter;
+pub mod external;
 
 pub use aead;
 
-pub use self::encrypter::{DecryptError, Encrypter};
+pub use self::{
+    encrypter::{DecryptError, Encrypter},
+    external::{ExternalKey, ExternalSigner},
+};
 
 /// Error type used when a key could not be loaded
 #[derive(Debug, Error)]
@@ -119,12 +123,16 @@ impl LoadError {
 
 /// A single private key
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PrivateKey {
     Rsa(Box<rsa::RsaPrivateKey>),
     EcP256(Box<elliptic_curve::SecretKey<p256::NistP256>>),
     EcP384(Box<elliptic_curve::SecretKey<p384::NistP384>>),
     EcK256(Box<elliptic_curve::SecretKey<k256::Secp256k1>>),
+
+    /// A key held outside of the process, like in a hardware security module,
+    /// which can't be exported
+    External(Box<ExternalKey>),
 }
 
 /// Error returned when the key can't be used for the requested algorithm
@@ -132,6 +140,16 @@ pub enum PrivateKey {
 #[error("Wrong algorithm for key")]
 pub struct WrongAlgorithmError;
 
+/// Error returned when a key could not be generated
+#[derive(Debug, Error)]
+pub enum GenerateError {
+    #[error("Keys can't be generated for the {0} algorithm")]
+    UnsupportedAlgorithm(JsonWebSignatureAlg),
+
+    #[error(transparent)]
+    Rsa(#[from] rsa::errors::Error),
+}
+
 impl PrivateKey {
     fn from_pkcs1_private_key(pkcs1_key: &pkcs1::RsaPrivateKey) -> Result<Self, LoadError> {
         // Taken from `TryFrom<pkcs8::PrivateKeyInfo<'_>> for RsaPrivateKey`
@@ -186,13 +204,15 @@ impl PrivateKey {
     ///
     /// # Errors
     ///
-    /// Returns an error if the encoding failed
+    /// Returns an error if the encoding failed, or if the key is held outside
+    /// of the process
     pub fn to_der(&self) -> Result<Zeroizing<Vec<u8>>, pkcs1::Error> {
         let der = match self {
             PrivateKey::Rsa(key) => key.to_pkcs1_der()?.to_bytes(),
             PrivateKey::EcP256(key) => to_sec1_der(key)?,
             PrivateKey::EcP384(key) => to_sec1_der(key)?,
             PrivateKey::EcK256(key) => to_sec1_der(key)?,
+            PrivateKey::External(_) => return Err(pkcs1::Error::Crypto),
         };
 
         Ok(der)
@@ -202,13 +222,15 @@ impl PrivateKey {
     ///
     /// # Errors
     ///
-    /// Returns an error if the encoding failed
+    /// Returns an error if the encoding failed, or if the key is held outside
+    /// of the process
     pub fn to_pkcs8_der(&self) -> Result<Zeroizing<Vec<u8>>, pkcs8::Error> {
         let der = match self {
             PrivateKey::Rsa(key) => key.to_pkcs8_der()?,
             PrivateKey::EcP256(key) => key.to_pkcs8_der()?,
             PrivateKey::EcP384(key) => key.to_pkcs8_der()?,
             PrivateKey::EcK256(key) => key.to_pkcs8_der()?,
+            PrivateKey::External(_) => return Err(pkcs8::Error::KeyMalformed),
         };
 
         Ok(der.to_bytes())
@@ -221,7 +243,8 @@ impl PrivateKey {
     ///
     /// # Errors
     ///
-    /// Returns an error if the encoding failed
+    /// Returns an error if the encoding failed, or if the key is held outside
+    /// of the process
     pub fn to_pem(
         &self,
         line_ending: pem_rfc7468::LineEnding,
@@ -231,6 +254,7 @@ impl PrivateKey {
             PrivateKey::EcP256(key) => to_sec1_pem(key, line_ending)?,
             PrivateKey::EcP384(key) => to_sec1_pem(key, line_ending)?,
             PrivateKey::EcK256(key) => to_sec1_pem(key, line_ending)?,
+            PrivateKey::External(_) => return Err(pkcs1::Error::Crypto),
         };
 
         Ok(pem)
@@ -444,6 +468,10 @@ impl PrivateKey {
                 AsymmetricVerifyingKey::es256k(key.public_key())
             }
 
+            (Self::External(key), _) => {
+                key.verifying_key_for_alg(alg).ok_or(WrongAlgorithmError)?
+            }
+
             _ => return Err(WrongAlgorithmError),
         };
 
@@ -486,6 +514,8 @@ impl PrivateKey {
                 AsymmetricSigningKey::es256k(*key.clone())
             }
 
+            (Self::External(key), _) => key.signing_key_for_alg(alg).ok_or(WrongAlgorithmError)?,
+
             _ => return Err(WrongAlgorithmError),
         };
 
@@ -519,6 +549,52 @@ impl PrivateKey {
         let key = elliptic_curve::SecretKey::random(&mut rng);
         Self::EcK256(Box::new(key))
     }
+
+    /// Check if keys can be generated for the given signing algorithm with
+    /// [`Self::generate_for_alg`]
+    #[must_use]
+    pub fn can_generate_for_alg(alg: &JsonWebSignatureAlg) -> bool {
+        matches!(
+            alg,
+            JsonWebSignatureAlg::Rs256
+                | JsonWebSignatureAlg::Rs384
+                | JsonWebSignatureAlg::Rs512
+                | JsonWebSignatureAlg::Ps256
+                | JsonWebSignatureAlg::Ps384
+                | JsonWebSignatureAlg::Ps512
+                | JsonWebSignatureAlg::Es256
+                | JsonWebSignatureAlg::Es384
+                | JsonWebSignatureAlg::Es256K
+        )
+    }
+
+    /// Generate a key suited for the given signing algorithm
+    ///
+    /// RSA keys are 2048 bit long, like with [`Self::generate_rsa`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if keys can't be generated for this algorithm, or if
+    /// the underlying key generator failed
+    pub fn generate_for_alg<R: RngCore + CryptoRng>(
+        rng: R,
+        alg: &JsonWebSignatureAlg,
+    ) -> Result<Self, GenerateError> {
+        let key = match alg {
+            JsonWebSignatureAlg::Rs256
+            | JsonWebSignatureAlg::Rs384
+            | JsonWebSignatureAlg::Rs512
+            | JsonWebSignatureAlg::Ps256
+            | JsonWebSignatureAlg::Ps384
+            | JsonWebSignatureAlg::Ps512 => Self::generate_rsa(rng)?,
+            JsonWebSignatureAlg::Es256 => Self::generate_ec_p256(rng),
+            JsonWebSignatureAlg::Es384 => Self::generate_ec_p384(rng),
+            JsonWebSignatureAlg::Es256K => Self::generate_ec_k256(rng),
+            _ => return Err(GenerateError::UnsupportedAlgorithm(alg.clone())),
+        };
+
+        Ok(key)
+    }
 }
 
 // The default implementation of SecretKey::to_sec1_pem/der do not include the
@@ -570,6 +646,7 @@ impl From<&PrivateKey> for JsonWebKeyPublicParameters {
             PrivateKey::EcP256(key) => key.public_key().into(),
             PrivateKey::EcP384(key) => key.public_key().into(),
             PrivateKey::EcK256(key) => key.public_key().into(),
+            PrivateKey::External(key) => key.public_parameters(),
         }
     }
 }
@@ -581,6 +658,7 @@ impl ParametersInfo for PrivateKey {
             PrivateKey::EcP256(_) | PrivateKey::EcP384(_) | PrivateKey::EcK256(_) => {
                 JsonWebKeyType::Ec
             }
+            PrivateKey::External(key) => key.kty(),
         }
     }
 
@@ -597,6 +675,7 @@ impl ParametersInfo for PrivateKey {
             PrivateKey::EcP256(_) => &[JsonWebSignatureAlg::Es256],
             PrivateKey::EcP384(_) => &[JsonWebSignatureAlg::Es384],
             PrivateKey::EcK256(_) => &[JsonWebSignatureAlg::Es256K],
+            PrivateKey::External(key) => key.possible_algs(),
         }
     }
 }
@@ -607,6 +686,7 @@ impl ParametersInfo for PrivateKey {
 #[derive(Clone, Default)]
 pub struct Keystore {
     keys: Arc<JsonWebKeySet<PrivateKey>>,
+    published_keys: Arc<JsonWebKeySet<PrivateKey>>,
 }
 
 impl Keystore {
@@ -632,7 +712,21 @@ impl Keystore {
     #[must_use]
     pub fn new(keys: JsonWebKeySet<PrivateKey>) -> Self {
         let keys = Arc::new(keys);
-        Self { keys }
+        Self {
+            keys,
+            published_keys: Arc::default(),
+        }
+    }
+
+    /// Add keys which are published in the public JSON Web Key Set, but never
+    /// used to sign
+    ///
+    /// This is useful for keys which will soon be used to sign, or which signed
+    /// tokens which are still valid.
+    #[must_use]
+    pub fn with_published_keys(mut self, keys: JsonWebKeySet<PrivateKey>) -> Self {
+        self.published_keys = Arc::new(keys);
+        self
     }
 
     /// Get the public JSON Web Key Set for the keys stored in this [`Keystore`]
@@ -640,6 +734,7 @@ impl Keystore {
     pub fn public_jwks(&self) -> PublicJsonWebKeySet {
         self.keys
             .iter()
+            .chain(self.published_keys.iter())
             .map(|key| {
                 key.cloned_map(|params: &PrivateKey| JsonWebKeyPublicParameters::from(params))
             })
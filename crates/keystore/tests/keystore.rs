@@ -7,6 +7,7 @@
 use der::pem::LineEnding;
 use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
+    constraints::Constrainable,
     jwk::ParametersInfo,
     jwt::{JsonWebSignatureHeader, Jwt},
 };
@@ -204,3 +205,46 @@ fn generate_sign_and_verify() {
         token.verify_with_jwks(&jwks).unwrap();
     }
 }
+
+#[test]
+fn generate_for_alg_and_publish() {
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    for alg in [
+        JsonWebSignatureAlg::Rs256,
+        JsonWebSignatureAlg::Ps512,
+        JsonWebSignatureAlg::Es256,
+        JsonWebSignatureAlg::Es384,
+        JsonWebSignatureAlg::Es256K,
+    ] {
+        assert!(PrivateKey::can_generate_for_alg(&alg));
+        let key = PrivateKey::generate_for_alg(&mut rng, &alg).unwrap();
+        assert!(key.signing_key_for_alg(&alg).is_ok());
+    }
+
+    assert!(!PrivateKey::can_generate_for_alg(
+        &JsonWebSignatureAlg::Hs256
+    ));
+    assert!(PrivateKey::generate_for_alg(&mut rng, &JsonWebSignatureAlg::Hs256).is_err());
+
+    // Published keys end up in the public JWKS, but are never used to sign
+    let active = PrivateKey::generate_ec_p256(&mut rng);
+    let pending = PrivateKey::generate_ec_p384(&mut rng);
+    let keystore = Keystore::new(JsonWebKeySet::new(vec![
+        JsonWebKey::new(active).with_kid("active")
+    ]))
+    .with_published_keys(JsonWebKeySet::new(vec![
+        JsonWebKey::new(pending).with_kid("pending")
+    ]));
+
+    let jwks = keystore.public_jwks();
+    let kids: Vec<_> = jwks.iter().map(|key| key.kid()).collect();
+    assert_eq!(kids, vec![Some("active"), Some("pending")]);
+
+    assert!(keystore
+        .signing_key_for_algorithm(&JsonWebSignatureAlg::Es256)
+        .is_some());
+    assert!(keystore
+        .signing_key_for_algorithm(&JsonWebSignatureAlg::Es384)
+        .is_none());
+}
@@ -9,13 +9,20 @@
 use std::collections::HashSet;
 
 use anyhow::{bail, Context};
-use http::{header::AUTHORIZATION, request::Builder, Method, Request, StatusCode};
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    request::Builder,
+    Method, Request, StatusCode,
+};
 use mas_axum_utils::http_client_factory::HttpClientFactory;
 use mas_http::{catch_http_codes, json_response, EmptyBody, HttpServiceExt};
 use mas_matrix::{HomeserverConnection, MatrixUser, ProvisionRequest};
+use mas_tower::FaultInjector;
+use opentelemetry_http::HeaderInjector;
 use serde::{Deserialize, Serialize};
 use tower::{Service, ServiceExt};
-use tracing::debug;
+use tracing::{debug, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use url::Url;
 
 use self::error::catch_homeserver_error;
@@ -37,6 +44,7 @@ pub struct SynapseConnection {
     endpoint: Url,
     access_token: String,
     http_client_factory: HttpClientFactory,
+    fault_injector: FaultInjector,
 }
 
 impl SynapseConnection {
@@ -52,18 +60,38 @@ impl SynapseConnection {
             endpoint,
             access_token,
             http_client_factory,
+            fault_injector: FaultInjector::disabled(),
         }
     }
 
+    /// Set the [`FaultInjector`] used to inject faults in calls to the
+    /// homeserver
+    #[must_use]
+    pub fn with_fault_injector(mut self, fault_injector: FaultInjector) -> Self {
+        self.fault_injector = fault_injector;
+        self
+    }
+
     fn builder(&self, url: &str) -> Builder {
-        Request::builder()
+        let mut builder = Request::builder()
             .uri(
                 self.endpoint
                     .join(url)
                     .map(String::from)
                     .unwrap_or_default(),
             )
-            .header(AUTHORIZATION, format!("Bearer {}", self.access_token))
+            .header(AUTHORIZATION, format!("Bearer {}", self.access_token));
+
+        // Propagate the trace context to Synapse with the configured propagators, so
+        // that operations can be traced across both services
+        if let Some(headers) = builder.headers_mut() {
+            let context = Span::current().context();
+            opentelemetry::global::get_text_map_propagator(|propagator| {
+                propagator.inject_context(&context, &mut HeaderInjector(headers));
+            });
+        }
+
+        builder
     }
 
     #[must_use]
@@ -156,6 +184,12 @@ struct SynapseDeactivateUserRequest {
 #[derive(Serialize)]
 struct SynapseAllowCrossSigningResetRequest {}
 
+/// Response body of `/_matrix/media/v3/upload`
+#[derive(Deserialize)]
+struct UploadMediaResponse {
+    content_uri: String,
+}
+
 /// Response body of
 /// `/_synapse/admin/v1/username_available?username={localpart}`
 #[derive(Deserialize)]
@@ -181,6 +215,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mxid = urlencoding::encode(mxid);
         let mut client = self
             .http_client_factory
@@ -223,6 +259,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn is_localpart_available(&self, localpart: &str) -> Result<bool, Self::Error> {
+        self.fault_injector.inject().await?;
+
         let localpart = urlencoding::encode(localpart);
         let mut client = self
             .http_client_factory
@@ -284,6 +322,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mut body = SynapseUser {
             external_ids: Some(vec![ExternalID {
                 auth_provider: SYNAPSE_AUTH_PROVIDER.to_owned(),
@@ -353,6 +393,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mxid = urlencoding::encode(mxid);
         let mut client = self
             .http_client_factory
@@ -393,6 +435,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mxid = urlencoding::encode(mxid);
         let device_id = urlencoding::encode(device_id);
         let mut client = self
@@ -431,6 +475,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn sync_devices(&self, mxid: &str, devices: HashSet<String>) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
         // Get the list of current devices
         let mxid_url = urlencoding::encode(mxid);
         let mut client = self
@@ -508,6 +554,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mxid = urlencoding::encode(mxid);
         let mut client = self
             .http_client_factory
@@ -545,6 +593,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn reactivate_user(&self, mxid: &str) -> Result<(), anyhow::Error> {
+        self.fault_injector.inject().await?;
+
         let body = SynapseUser {
             deactivated: Some(false),
             ..SynapseUser::default()
@@ -590,6 +640,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mxid = urlencoding::encode(mxid);
         let mut client = self
             .http_client_factory
@@ -640,6 +692,8 @@ impl HomeserverConnection for SynapseConnection {
         err(Debug),
     )]
     async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
         let mxid = urlencoding::encode(mxid);
         let mut client = self
             .http_client_factory
@@ -671,4 +725,148 @@ impl HomeserverConnection for SynapseConnection {
 
         Ok(())
     }
+
+    #[tracing::instrument(
+        name = "homeserver.upload_media",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.media.content_type = content_type,
+            matrix.media.size = content.len(),
+        ),
+        err(Debug),
+    )]
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        content: Vec<u8>,
+    ) -> Result<String, Self::Error> {
+        self.fault_injector.inject().await?;
+
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.upload_media")
+            .request_bytes_to_body()
+            .response_body_to_bytes()
+            .catch_http_errors(catch_homeserver_error)
+            .json_response();
+
+        let request = self
+            .post("_matrix/media/v3/upload")
+            .header(CONTENT_TYPE, content_type)
+            .body(content.into())?;
+
+        let response = client
+            .ready()
+            .await?
+            .call(request)
+            .await
+            .context("Failed to upload media to Synapse")?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(
+                "Failed to upload media to Synapse: {}",
+                response.status()
+            ));
+        }
+
+        let body: UploadMediaResponse = response.into_body();
+
+        Ok(body.content_uri)
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.health_check",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+        ),
+        err(Debug),
+    )]
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.fault_injector.inject().await?;
+
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.health_check")
+            .response_body_to_bytes()
+            .catch_http_errors(catch_homeserver_error);
+
+        // This checks both that Synapse is reachable and that it accepts our
+        // access token
+        let request = self
+            .get("_matrix/client/v3/account/whoami")
+            .body(EmptyBody::new())?;
+
+        let response = client
+            .ready()
+            .await?
+            .call(request)
+            .await
+            .context("Failed to reach Synapse")?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(
+                "Unexpected response from Synapse: {}",
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_axum_utils::http_client_factory::HttpClientFactory;
+    use opentelemetry::{
+        propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+        Context,
+    };
+    use url::Url;
+
+    use super::SynapseConnection;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    /// A propagator which always injects the same `traceparent` header
+    #[derive(Debug)]
+    struct FixedPropagator {
+        fields: [String; 1],
+    }
+
+    impl TextMapPropagator for FixedPropagator {
+        fn inject_context(&self, _cx: &Context, injector: &mut dyn Injector) {
+            injector.set("traceparent", TRACEPARENT.to_owned());
+        }
+
+        fn extract_with_context(&self, cx: &Context, _extractor: &dyn Extractor) -> Context {
+            cx.clone()
+        }
+
+        fn fields(&self) -> FieldIter<'_> {
+            FieldIter::new(&self.fields)
+        }
+    }
+
+    #[test]
+    fn test_trace_context_propagation() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        opentelemetry::global::set_text_map_propagator(FixedPropagator {
+            fields: ["traceparent".to_owned()],
+        });
+
+        let connection = SynapseConnection::new(
+            "example.com".to_owned(),
+            Url::parse("https://synapse.example.com/").unwrap(),
+            "secret".to_owned(),
+            HttpClientFactory::new(),
+        );
+
+        let request = connection
+            .get("_synapse/admin/v2/users/@alice:example.com")
+            .body(())
+            .unwrap();
+        assert_eq!(request.headers()["traceparent"], TRACEPARENT);
+    }
 }
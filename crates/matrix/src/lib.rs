@@ -5,10 +5,13 @@
 // Please see LICENSE in the repository root for full details.
 
 mod mock;
+mod multi;
 
 use std::{collections::HashSet, sync::Arc};
 
-pub use self::mock::HomeserverConnection as MockHomeserverConnection;
+pub use self::{
+    mock::HomeserverConnection as MockHomeserverConnection, multi::MultiHomeserverConnection,
+};
 
 // TODO: this should probably be another error type by default
 pub type BoxHomeserverConnection<Error = anyhow::Error> =
@@ -193,6 +196,22 @@ pub trait HomeserverConnection: Send + Sync {
         format!("@{}:{}", localpart, self.homeserver())
     }
 
+    /// Get the Matrix ID of the user with the given localpart, on the given
+    /// homeserver.
+    ///
+    /// # Parameters
+    ///
+    /// * `homeserver` - The server name of the homeserver of the user, or
+    ///   `None` for this homeserver.
+    /// * `localpart` - The localpart of the user.
+    fn mxid_on(&self, homeserver: Option<&str>, localpart: &str) -> String {
+        format!(
+            "@{}:{}",
+            localpart,
+            homeserver.unwrap_or_else(|| self.homeserver())
+        )
+    }
+
     /// Query the state of a user on the homeserver.
     ///
     /// # Parameters
@@ -229,6 +248,26 @@ pub trait HomeserverConnection: Send + Sync {
     /// Returns an error if the homeserver is unreachable.
     async fn is_localpart_available(&self, localpart: &str) -> Result<bool, Self::Error>;
 
+    /// Check whether a given username is available on the given homeserver.
+    ///
+    /// # Parameters
+    ///
+    /// * `homeserver` - The server name of the homeserver, or `None` for this
+    ///   homeserver.
+    /// * `localpart` - The localpart to check.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable.
+    async fn is_localpart_available_on(
+        &self,
+        homeserver: Option<&str>,
+        localpart: &str,
+    ) -> Result<bool, Self::Error> {
+        let _ = homeserver;
+        self.is_localpart_available(localpart).await
+    }
+
     /// Create a device for a user on the homeserver.
     ///
     /// # Parameters
@@ -329,6 +368,33 @@ pub trait HomeserverConnection: Send + Sync {
     /// Returns an error if the homeserver is unreachable or the cross-signing
     /// reset could not be allowed.
     async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error>;
+
+    /// Upload a file to the homeserver media repository.
+    ///
+    /// Returns the `mxc://` URI of the uploaded file.
+    ///
+    /// # Parameters
+    ///
+    /// * `content_type` - The MIME type of the file.
+    /// * `content` - The content of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the file could not
+    /// be uploaded.
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        content: Vec<u8>,
+    ) -> Result<String, Self::Error>;
+
+    /// Check that the homeserver is reachable and accepts our credentials.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or rejected the
+    /// request.
+    async fn health_check(&self) -> Result<(), Self::Error>;
 }
 
 #[async_trait::async_trait]
@@ -351,6 +417,16 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
         (**self).is_localpart_available(localpart).await
     }
 
+    async fn is_localpart_available_on(
+        &self,
+        homeserver: Option<&str>,
+        localpart: &str,
+    ) -> Result<bool, Self::Error> {
+        (**self)
+            .is_localpart_available_on(homeserver, localpart)
+            .await
+    }
+
     async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
         (**self).create_device(mxid, device_id).await
     }
@@ -382,6 +458,18 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
     async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
         (**self).allow_cross_signing_reset(mxid).await
     }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        content: Vec<u8>,
+    ) -> Result<String, Self::Error> {
+        (**self).upload_media(content_type, content).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        (**self).health_check().await
+    }
 }
 
 // Implement for Arc<T> where T: HomeserverConnection
@@ -405,6 +493,16 @@ impl<T: HomeserverConnection + ?Sized> HomeserverConnection for Arc<T> {
         (**self).is_localpart_available(localpart).await
     }
 
+    async fn is_localpart_available_on(
+        &self,
+        homeserver: Option<&str>,
+        localpart: &str,
+    ) -> Result<bool, Self::Error> {
+        (**self)
+            .is_localpart_available_on(homeserver, localpart)
+            .await
+    }
+
     async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
         (**self).create_device(mxid, device_id).await
     }
@@ -436,4 +534,16 @@ impl<T: HomeserverConnection + ?Sized> HomeserverConnection for Arc<T> {
     async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
         (**self).allow_cross_signing_reset(mxid).await
     }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        content: Vec<u8>,
+    ) -> Result<String, Self::Error> {
+        (**self).upload_media(content_type, content).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        (**self).health_check().await
+    }
 }
@@ -28,6 +28,7 @@ pub struct HomeserverConnection {
     homeserver: String,
     users: RwLock<HashMap<String, MockUser>>,
     reserved_localparts: RwLock<HashSet<&'static str>>,
+    media: RwLock<Vec<(String, Vec<u8>)>>,
 }
 
 impl HomeserverConnection {
@@ -40,6 +41,7 @@ impl HomeserverConnection {
             homeserver: homeserver.into(),
             users: RwLock::new(HashMap::new()),
             reserved_localparts: RwLock::new(HashSet::new()),
+            media: RwLock::new(Vec::new()),
         }
     }
 
@@ -172,6 +174,20 @@ impl crate::HomeserverConnection for HomeserverConnection {
         user.cross_signing_reset_allowed = true;
         Ok(())
     }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        content: Vec<u8>,
+    ) -> Result<String, Self::Error> {
+        let mut media = self.media.write().await;
+        media.push((content_type.to_owned(), content));
+        Ok(format!("mxc://{}/{}", self.homeserver, media.len()))
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -216,6 +232,13 @@ mod tests {
         let user = conn.query_user(mxid).await.unwrap();
         assert_eq!(user.displayname, None);
 
+        // Upload some media
+        let mxc = conn
+            .upload_media("image/png", b"not really a png".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(mxc, "mxc://example.org/1");
+
         // Deleting a non-existent device should not fail
         assert!(conn.delete_device(mxid, device).await.is_ok());
 
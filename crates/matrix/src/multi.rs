@@ -0,0 +1,228 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+
+use crate::{HomeserverConnection, MatrixUser, ProvisionRequest};
+
+/// A connection to multiple homeservers, which sends the requests about a
+/// user to the homeserver of their Matrix ID.
+///
+/// The main homeserver is the one returned by
+/// [`HomeserverConnection::homeserver`], and is used for the requests which
+/// are not about a specific user.
+#[derive(Clone)]
+pub struct MultiHomeserverConnection<C> {
+    main: C,
+    additional: Arc<HashMap<String, C>>,
+}
+
+impl<C> MultiHomeserverConnection<C>
+where
+    C: HomeserverConnection<Error = anyhow::Error>,
+{
+    /// Create a new connection, with only the main homeserver
+    pub fn new(main: C) -> Self {
+        Self {
+            main,
+            additional: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Create a new connection, with the main homeserver and additional
+    /// homeservers
+    pub fn with_additional(main: C, additional: impl IntoIterator<Item = C>) -> Self {
+        let additional = additional
+            .into_iter()
+            .map(|connection| (connection.homeserver().to_owned(), connection))
+            .collect();
+
+        Self {
+            main,
+            additional: Arc::new(additional),
+        }
+    }
+
+    /// Get the connection to the homeserver of the given Matrix ID
+    fn connection_for(&self, mxid: &str) -> Result<&C, anyhow::Error> {
+        let (_localpart, server_name) = mxid
+            .split_once(':')
+            .with_context(|| format!("Invalid Matrix ID {mxid:?}"))?;
+
+        self.connection_on(server_name)
+    }
+
+    /// Get the connection to the homeserver with the given server name
+    fn connection_on(&self, server_name: &str) -> Result<&C, anyhow::Error> {
+        if server_name == self.main.homeserver() {
+            return Ok(&self.main);
+        }
+
+        self.additional
+            .get(server_name)
+            .with_context(|| format!("Unknown homeserver {server_name:?}"))
+    }
+}
+
+#[async_trait]
+impl<C> HomeserverConnection for MultiHomeserverConnection<C>
+where
+    C: HomeserverConnection<Error = anyhow::Error>,
+{
+    type Error = anyhow::Error;
+
+    fn homeserver(&self) -> &str {
+        self.main.homeserver()
+    }
+
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        self.connection_for(mxid)?.query_user(mxid).await
+    }
+
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        self.connection_for(request.mxid())?
+            .provision_user(request)
+            .await
+    }
+
+    /// Usernames are only unique within a homeserver, so this only checks
+    /// the main homeserver
+    async fn is_localpart_available(&self, localpart: &str) -> Result<bool, Self::Error> {
+        self.main.is_localpart_available(localpart).await
+    }
+
+    async fn is_localpart_available_on(
+        &self,
+        homeserver: Option<&str>,
+        localpart: &str,
+    ) -> Result<bool, Self::Error> {
+        match homeserver {
+            Some(server_name) => {
+                self.connection_on(server_name)?
+                    .is_localpart_available(localpart)
+                    .await
+            }
+            None => self.main.is_localpart_available(localpart).await,
+        }
+    }
+
+    async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?
+            .create_device(mxid, device_id)
+            .await
+    }
+
+    async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?
+            .delete_device(mxid, device_id)
+            .await
+    }
+
+    async fn sync_devices(&self, mxid: &str, devices: HashSet<String>) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?.sync_devices(mxid, devices).await
+    }
+
+    async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?.delete_user(mxid, erase).await
+    }
+
+    async fn reactivate_user(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?.reactivate_user(mxid).await
+    }
+
+    async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?
+            .set_displayname(mxid, displayname)
+            .await
+    }
+
+    async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?.unset_displayname(mxid).await
+    }
+
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.connection_for(mxid)?
+            .allow_cross_signing_reset(mxid)
+            .await
+    }
+
+    async fn upload_media(
+        &self,
+        content_type: &str,
+        content: Vec<u8>,
+    ) -> Result<String, Self::Error> {
+        self.main.upload_media(content_type, content).await
+    }
+
+    async fn health_check(&self) -> Result<(), Self::Error> {
+        self.main.health_check().await?;
+
+        for (server_name, connection) in self.additional.iter() {
+            connection
+                .health_check()
+                .await
+                .with_context(|| format!("Homeserver {server_name:?} is unhealthy"))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockHomeserverConnection;
+
+    #[tokio::test]
+    async fn test_routing() {
+        let conn = MultiHomeserverConnection::with_additional(
+            MockHomeserverConnection::new("example.com"),
+            [MockHomeserverConnection::new("example.org")],
+        );
+
+        assert_eq!(conn.homeserver(), "example.com");
+        assert_eq!(conn.mxid("alice"), "@alice:example.com");
+        assert_eq!(conn.mxid_on(Some("example.org"), "bob"), "@bob:example.org");
+
+        let request = ProvisionRequest::new("@bob:example.org", "bob");
+        assert!(conn.provision_user(&request).await.unwrap());
+
+        // The user only exists on the additional homeserver
+        assert!(conn.query_user("@bob:example.org").await.is_ok());
+        assert!(conn.main.query_user("@bob:example.com").await.is_err());
+        assert!(conn.additional["example.org"]
+            .query_user("@bob:example.org")
+            .await
+            .is_ok());
+
+        // Unknown homeservers are rejected
+        let request = ProvisionRequest::new("@carol:example.net", "carol");
+        assert!(conn.provision_user(&request).await.is_err());
+
+        // Localparts are checked on the homeserver they would be used on
+        conn.additional["example.org"]
+            .reserve_localpart("admin")
+            .await;
+        assert!(conn.is_localpart_available("admin").await.unwrap());
+        assert!(!conn
+            .is_localpart_available_on(Some("example.org"), "admin")
+            .await
+            .unwrap());
+        assert!(conn
+            .is_localpart_available_on(Some("example.com"), "admin")
+            .await
+            .unwrap());
+        assert!(conn
+            .is_localpart_available_on(Some("example.net"), "admin")
+            .await
+            .is_err());
+    }
+}
@@ -265,6 +265,30 @@ pub enum ClientErrorCode {
     /// From [RFC7009](https://www.rfc-editor.org/rfc/rfc7009#section-2.2.1).
     UnsupportedTokenType,
 
+    /// `unknown_user_id`
+    ///
+    /// The OpenID Provider is not able to identify which end-user the client
+    /// wishes to be authenticated by means of the hint provided in the
+    /// backchannel authentication request.
+    ///
+    /// From [OpenID Connect Client-Initiated Backchannel Authentication Flow](https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#auth_error_response).
+    UnknownUserId,
+
+    /// `invalid_binding_message`
+    ///
+    /// The binding message is invalid or unacceptable for use in the context of
+    /// the given request.
+    ///
+    /// From [OpenID Connect Client-Initiated Backchannel Authentication Flow](https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#auth_error_response).
+    InvalidBindingMessage,
+
+    /// `invalid_dpop_proof`
+    ///
+    /// The DPoP proof is missing or invalid.
+    ///
+    /// From [RFC9449](https://www.rfc-editor.org/rfc/rfc9449#section-5).
+    InvalidDpopProof,
+
     /// Another error code.
     Unknown(String),
 }
@@ -297,6 +321,9 @@ impl core::fmt::Display for ClientErrorCode {
             ClientErrorCode::SlowDown => f.write_str("slow_down"),
             ClientErrorCode::ExpiredToken => f.write_str("expired_token"),
             ClientErrorCode::UnsupportedTokenType => f.write_str("unsupported_token_type"),
+            ClientErrorCode::UnknownUserId => f.write_str("unknown_user_id"),
+            ClientErrorCode::InvalidBindingMessage => f.write_str("invalid_binding_message"),
+            ClientErrorCode::InvalidDpopProof => f.write_str("invalid_dpop_proof"),
             ClientErrorCode::Unknown(value) => f.write_str(value),
         }
     }
@@ -332,6 +359,9 @@ impl core::str::FromStr for ClientErrorCode {
             "slow_down" => Ok(ClientErrorCode::SlowDown),
             "expired_token" => Ok(ClientErrorCode::ExpiredToken),
             "unsupported_token_type" => Ok(ClientErrorCode::UnsupportedTokenType),
+            "unknown_user_id" => Ok(ClientErrorCode::UnknownUserId),
+            "invalid_binding_message" => Ok(ClientErrorCode::InvalidBindingMessage),
+            "invalid_dpop_proof" => Ok(ClientErrorCode::InvalidDpopProof),
             _ => Ok(ClientErrorCode::Unknown(s.to_owned())),
         }
     }
@@ -423,6 +453,13 @@ impl ClientErrorCode {
             ClientErrorCode::UnsupportedTokenType => {
                 "The authorization server does not support the revocation of the presented token type."
             },
+            ClientErrorCode::UnknownUserId => {
+                "The end-user the client wishes to be authenticated could not be identified"
+            }
+            ClientErrorCode::InvalidBindingMessage => {
+                "The binding message is invalid or unacceptable"
+            }
+            ClientErrorCode::InvalidDpopProof => "The DPoP proof is missing or invalid",
             ClientErrorCode::Unknown(_) => "",
         }
     }
@@ -298,6 +298,59 @@ impl core::str::FromStr for AccountManagementAction {
     }
 }
 
+/// A mode used to deliver the tokens of a [Client-Initiated Backchannel
+/// Authentication] request to the client.
+///
+/// [Client-Initiated Backchannel Authentication]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html
+#[derive(
+    SerializeDisplay, DeserializeFromStr, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[non_exhaustive]
+pub enum BackchannelTokenDeliveryMode {
+    /// `poll`
+    ///
+    /// The client polls the token endpoint to get the tokens.
+    Poll,
+
+    /// `ping`
+    ///
+    /// The client is notified on its callback endpoint, and then gets the
+    /// tokens from the token endpoint.
+    Ping,
+
+    /// `push`
+    ///
+    /// The tokens are sent to the callback endpoint of the client.
+    Push,
+
+    /// An unknown value.
+    Unknown(String),
+}
+
+impl core::fmt::Display for BackchannelTokenDeliveryMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Poll => write!(f, "poll"),
+            Self::Ping => write!(f, "ping"),
+            Self::Push => write!(f, "push"),
+            Self::Unknown(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl core::str::FromStr for BackchannelTokenDeliveryMode {
+    type Err = core::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "poll" => Ok(Self::Poll),
+            "ping" => Ok(Self::Ping),
+            "push" => Ok(Self::Push),
+            value => Ok(Self::Unknown(value.to_owned())),
+        }
+    }
+}
+
 /// The default value of `response_modes_supported` if it is not set.
 pub static DEFAULT_RESPONSE_MODES_SUPPORTED: &[ResponseMode] =
     &[ResponseMode::Query, ResponseMode::Fragment];
@@ -609,6 +662,44 @@ pub struct ProviderMetadata {
     /// [device authorization endpoint]: https://www.rfc-editor.org/rfc/rfc8628
     pub device_authorization_endpoint: Option<Url>,
 
+    /// URL of the authorization server's [backchannel authentication
+    /// endpoint].
+    ///
+    /// [backchannel authentication endpoint]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#auth_backchannel_endpoint
+    pub backchannel_authentication_endpoint: Option<Url>,
+
+    /// JSON array containing the list of [backchannel token delivery modes]
+    /// supported by the OP.
+    ///
+    /// [backchannel token delivery modes]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#rfc.section.4
+    pub backchannel_token_delivery_modes_supported: Option<Vec<BackchannelTokenDeliveryMode>>,
+
+    /// Indicates whether the OP supports the `user_code` parameter in
+    /// backchannel authentication requests.
+    ///
+    /// Defaults to `false`.
+    pub backchannel_user_code_parameter_supported: Option<bool>,
+
+    /// JSON array containing a list of the JWS algorithms supported for
+    /// [DPoP] proof JWTs.
+    ///
+    /// [DPoP]: https://www.rfc-editor.org/rfc/rfc9449
+    pub dpop_signing_alg_values_supported: Option<Vec<JsonWebSignatureAlg>>,
+
+    /// Indicates whether the authorization server supports [mutual-TLS
+    /// client certificate-bound access tokens].
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [mutual-TLS client certificate-bound access tokens]: https://www.rfc-editor.org/rfc/rfc8705.html#section-3
+    pub tls_client_certificate_bound_access_tokens: Option<bool>,
+
+    /// JSON array containing a list of the JWS algorithms supported for
+    /// signing [JWT-secured authorization responses].
+    ///
+    /// [JWT-secured authorization responses]: https://openid.net/specs/oauth-v2-jarm.html
+    pub authorization_signing_alg_values_supported: Option<Vec<JsonWebSignatureAlg>>,
+
     /// URL of the authorization server's [RP-Initiated Logout endpoint].
     ///
     /// [RP-Initiated Logout endpoint]: https://openid.net/specs/openid-connect-rpinitiated-1_0.html
@@ -746,10 +837,40 @@ impl ProviderMetadata {
             )?;
         }
 
+        if let Some(url) = &metadata.backchannel_authentication_endpoint {
+            validate_url(
+                "backchannel_authentication_endpoint",
+                url,
+                ExtraUrlRestrictions::None,
+            )?;
+        }
+
         if let Some(url) = &metadata.end_session_endpoint {
             validate_url("end_session_endpoint", url, ExtraUrlRestrictions::None)?;
         }
 
+        if metadata
+            .dpop_signing_alg_values_supported
+            .iter()
+            .flatten()
+            .any(|alg| *alg == JsonWebSignatureAlg::None)
+        {
+            return Err(ProviderMetadataVerificationError::SigningAlgValuesWithNone(
+                "dpop",
+            ));
+        }
+
+        if metadata
+            .authorization_signing_alg_values_supported
+            .iter()
+            .flatten()
+            .any(|alg| *alg == JsonWebSignatureAlg::None)
+        {
+            return Err(ProviderMetadataVerificationError::SigningAlgValuesWithNone(
+                "authorization",
+            ));
+        }
+
         Ok(metadata)
     }
 
@@ -916,6 +1037,16 @@ impl ProviderMetadata {
     pub fn require_pushed_authorization_requests(&self) -> bool {
         self.require_pushed_authorization_requests.unwrap_or(false)
     }
+
+    /// Indicates whether the authorization server supports mutual-TLS client
+    /// certificate-bound access tokens.
+    ///
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn tls_client_certificate_bound_access_tokens(&self) -> bool {
+        self.tls_client_certificate_bound_access_tokens
+            .unwrap_or(false)
+    }
 }
 
 /// The verified authorization server metadata.
@@ -1056,6 +1187,10 @@ pub enum ProviderMetadataVerificationError {
     #[error("JWK Set URI is missing")]
     MissingJwksUri,
 
+    /// The pushed authorization request endpoint is missing.
+    #[error("pushed authorization request endpoint is missing")]
+    MissingPushedAuthorizationRequestEndpoint,
+
     /// The supported response types are missing.
     #[error("supported response types are missing")]
     MissingResponseTypesSupported,
@@ -1774,6 +1909,52 @@ mod tests {
         metadata.validate(&issuer).unwrap();
     }
 
+    #[test]
+    fn validate_backchannel_authentication_endpoint() {
+        let (mut metadata, issuer) = valid_provider_metadata();
+
+        // Ok - Missing
+        metadata.backchannel_authentication_endpoint = None;
+        metadata.clone().validate(&issuer).unwrap();
+
+        // Err - Not https
+        let endpoint = Url::parse("http://localhost/bc-authorize").unwrap();
+        metadata.backchannel_authentication_endpoint = Some(endpoint.clone());
+        let (field, url) = assert_matches!(
+            metadata.clone().validate(&issuer),
+            Err(ProviderMetadataVerificationError::UrlNonHttpsScheme(field, url)) => (field, url)
+        );
+        assert_eq!(field, "backchannel_authentication_endpoint");
+        assert_eq!(url, endpoint);
+
+        // Ok - https
+        metadata.backchannel_authentication_endpoint =
+            Some(Url::parse("https://localhost/bc-authorize").unwrap());
+        metadata.validate(&issuer).unwrap();
+    }
+
+    #[test]
+    fn validate_authorization_signing_alg_values_supported() {
+        let (mut metadata, issuer) = valid_provider_metadata();
+
+        // Ok - Missing
+        metadata.authorization_signing_alg_values_supported = None;
+        metadata.clone().validate(&issuer).unwrap();
+
+        // Ok - Asymmetric algorithms
+        metadata.authorization_signing_alg_values_supported =
+            Some(vec![JsonWebSignatureAlg::Rs256, JsonWebSignatureAlg::Es256]);
+        metadata.clone().validate(&issuer).unwrap();
+
+        // Err - With `none`
+        metadata.authorization_signing_alg_values_supported = Some(vec![JsonWebSignatureAlg::None]);
+        let endpoint = assert_matches!(
+            metadata.validate(&issuer),
+            Err(ProviderMetadataVerificationError::SigningAlgValuesWithNone(endpoint)) => endpoint
+        );
+        assert_eq!(endpoint, "authorization");
+    }
+
     #[test]
     fn serialize_application_type() {
         assert_eq!(
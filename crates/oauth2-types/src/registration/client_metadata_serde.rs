@@ -115,6 +115,8 @@ pub struct ClientMetadataSerdeHelper {
     request_uris: Option<Vec<Url>>,
     require_signed_request_object: Option<bool>,
     require_pushed_authorization_requests: Option<bool>,
+    tls_client_auth_subject_dn: Option<String>,
+    tls_client_certificate_bound_access_tokens: Option<bool>,
     introspection_signed_response_alg: Option<JsonWebSignatureAlg>,
     introspection_encrypted_response_alg: Option<JsonWebEncryptionAlg>,
     introspection_encrypted_response_enc: Option<JsonWebEncryptionEnc>,
@@ -162,6 +164,8 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
                     request_uris,
                     require_signed_request_object,
                     require_pushed_authorization_requests,
+                    tls_client_auth_subject_dn,
+                    tls_client_certificate_bound_access_tokens,
                     introspection_signed_response_alg,
                     introspection_encrypted_response_alg,
                     introspection_encrypted_response_enc,
@@ -199,6 +203,8 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
             request_uris,
             require_signed_request_object,
             require_pushed_authorization_requests,
+            tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens,
             introspection_signed_response_alg,
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
@@ -246,6 +252,8 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             request_uris,
             require_signed_request_object,
             require_pushed_authorization_requests,
+            tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens,
             introspection_signed_response_alg,
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
@@ -295,6 +303,8 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             request_uris,
             require_signed_request_object,
             require_pushed_authorization_requests,
+            tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens,
             introspection_signed_response_alg,
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
@@ -390,6 +390,23 @@ pub struct ClientMetadata {
     /// [pushed authorization request endpoint]: https://www.rfc-editor.org/rfc/rfc9126.html
     pub require_pushed_authorization_requests: Option<bool>,
 
+    /// The expected subject distinguished name of the certificate the client
+    /// uses for [mutual-TLS client authentication].
+    ///
+    /// This field is required if the `token_endpoint_auth_method` is
+    /// [`OAuthClientAuthenticationMethod::TlsClientAuth`].
+    ///
+    /// [mutual-TLS client authentication]: https://www.rfc-editor.org/rfc/rfc8705.html#section-2.1.2
+    pub tls_client_auth_subject_dn: Option<String>,
+
+    /// Whether the access tokens issued to the client are bound to the
+    /// [certificate] it used on the token endpoint.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [certificate]: https://www.rfc-editor.org/rfc/rfc8705.html#section-3
+    pub tls_client_certificate_bound_access_tokens: Option<bool>,
+
     /// [JWS] `alg` algorithm for signing responses of the [introspection
     /// endpoint].
     ///
@@ -496,13 +513,22 @@ impl ClientMetadata {
             ));
         }
 
-        if *self.token_endpoint_auth_method() == OAuthClientAuthenticationMethod::PrivateKeyJwt
-            && self.jwks_uri.is_none()
+        if matches!(
+            self.token_endpoint_auth_method(),
+            OAuthClientAuthenticationMethod::PrivateKeyJwt
+                | OAuthClientAuthenticationMethod::SelfSignedTlsClientAuth
+        ) && self.jwks_uri.is_none()
             && self.jwks.is_none()
         {
             return Err(ClientMetadataVerificationError::MissingJwksForTokenMethod);
         }
 
+        if *self.token_endpoint_auth_method() == OAuthClientAuthenticationMethod::TlsClientAuth
+            && self.tls_client_auth_subject_dn.is_none()
+        {
+            return Err(ClientMetadataVerificationError::MissingTlsClientAuthSubjectDn);
+        }
+
         if let Some(alg) = &self.token_endpoint_auth_signing_alg {
             if *alg == JsonWebSignatureAlg::None {
                 return Err(ClientMetadataVerificationError::UnauthorizedSigningAlgNone(
@@ -736,6 +762,18 @@ impl ClientMetadata {
             .unwrap_or_default()
     }
 
+    /// Whether the access tokens issued to the client are bound to the
+    /// [certificate] it used on the token endpoint.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [certificate]: https://www.rfc-editor.org/rfc/rfc8705.html#section-3
+    #[must_use]
+    pub fn tls_client_certificate_bound_access_tokens(&self) -> bool {
+        self.tls_client_certificate_bound_access_tokens
+            .unwrap_or_default()
+    }
+
     /// [JWE] `alg` and `enc` algorithms for encrypting responses of the
     /// [introspection endpoint].
     ///
@@ -874,6 +912,11 @@ pub enum ClientMetadataVerificationError {
     /// The given encryption field has an `enc` value but not `alg` value.
     #[error("{0} missing encryption alg value")]
     MissingEncryptionAlg(&'static str),
+
+    /// The `tls_client_auth` authentication method is used, but the expected
+    /// subject of the certificate is missing.
+    #[error("missing tls_client_auth_subject_dn for tls_client_auth")]
+    MissingTlsClientAuthSubjectDn,
 }
 
 /// The issuer response to dynamic client registration.
@@ -899,6 +942,18 @@ pub struct ClientRegistrationResponse {
     #[serde(default)]
     #[serde_as(as = "Option<TimestampSeconds<i64>>")]
     pub client_secret_expires_at: Option<DateTime<Utc>>,
+
+    /// The token with which the client can read, update and delete its
+    /// registration, as defined in [RFC7592].
+    ///
+    /// [RFC7592]: https://www.rfc-editor.org/rfc/rfc7592.html
+    #[serde(default)]
+    pub registration_access_token: Option<String>,
+
+    /// The location of the client configuration endpoint, with which the
+    /// client can manage its registration.
+    #[serde(default)]
+    pub registration_client_uri: Option<Url>,
 }
 
 #[cfg(test)]
@@ -1262,6 +1317,35 @@ mod tests {
 
         // Ok - Has token_endpoint_auth_signing_alg
         metadata.token_endpoint_auth_signing_alg = Some(JsonWebSignatureAlg::Rs256);
+        metadata.clone().validate().unwrap();
+
+        // tls_client_auth
+        metadata.token_endpoint_auth_method = Some(OAuthClientAuthenticationMethod::TlsClientAuth);
+        metadata.token_endpoint_auth_signing_alg = None;
+
+        // Err - No tls_client_auth_subject_dn
+        assert_matches!(
+            metadata.clone().validate(),
+            Err(ClientMetadataVerificationError::MissingTlsClientAuthSubjectDn)
+        );
+
+        // Ok - Has tls_client_auth_subject_dn
+        metadata.tls_client_auth_subject_dn = Some("CN=client,O=Example".to_owned());
+        metadata.clone().validate().unwrap();
+
+        // self_signed_tls_client_auth
+        metadata.token_endpoint_auth_method =
+            Some(OAuthClientAuthenticationMethod::SelfSignedTlsClientAuth);
+        metadata.tls_client_auth_subject_dn = None;
+
+        // Err - No JWKS
+        assert_matches!(
+            metadata.clone().validate(),
+            Err(ClientMetadataVerificationError::MissingJwksForTokenMethod)
+        );
+
+        // Ok - jwks
+        metadata.jwks = Some(jwks());
         metadata.validate().unwrap();
     }
 
@@ -50,16 +50,62 @@ pub enum ResponseMode {
     /// Defined in [OAuth 2.0 Form Post Response Mode](https://openid.net/specs/oauth-v2-form-post-response-mode-1_0.html).
     FormPost,
 
+    /// Authorization Response parameters are wrapped in a signed JWT, which is
+    /// passed in the `response` query parameter added to the `redirect_uri`.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0 (JARM)](https://openid.net/specs/oauth-v2-jarm.html).
+    QueryJwt,
+
+    /// Authorization Response parameters are wrapped in a signed JWT, which is
+    /// passed in the `response` parameter of the fragment added to the
+    /// `redirect_uri`.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0 (JARM)](https://openid.net/specs/oauth-v2-jarm.html).
+    FragmentJwt,
+
+    /// Authorization Response parameters are wrapped in a signed JWT, which is
+    /// passed in the `response` HTML form value, like in the
+    /// [`ResponseMode::FormPost`] response mode.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0 (JARM)](https://openid.net/specs/oauth-v2-jarm.html).
+    FormPostJwt,
+
+    /// Authorization Response parameters are wrapped in a signed JWT, using
+    /// the default JWT response mode of the response type: either
+    /// [`ResponseMode::QueryJwt`] or [`ResponseMode::FragmentJwt`].
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0 (JARM)](https://openid.net/specs/oauth-v2-jarm.html).
+    Jwt,
+
     /// An unknown value.
     Unknown(String),
 }
 
+impl ResponseMode {
+    /// Whether the Authorization Response parameters are wrapped in a signed
+    /// JWT with this response mode.
+    #[must_use]
+    pub fn is_jwt(&self) -> bool {
+        matches!(
+            self,
+            ResponseMode::QueryJwt
+                | ResponseMode::FragmentJwt
+                | ResponseMode::FormPostJwt
+                | ResponseMode::Jwt
+        )
+    }
+}
+
 impl core::fmt::Display for ResponseMode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             ResponseMode::Query => f.write_str("query"),
             ResponseMode::Fragment => f.write_str("fragment"),
             ResponseMode::FormPost => f.write_str("form_post"),
+            ResponseMode::QueryJwt => f.write_str("query.jwt"),
+            ResponseMode::FragmentJwt => f.write_str("fragment.jwt"),
+            ResponseMode::FormPostJwt => f.write_str("form_post.jwt"),
+            ResponseMode::Jwt => f.write_str("jwt"),
             ResponseMode::Unknown(s) => f.write_str(s),
         }
     }
@@ -73,6 +119,10 @@ impl core::str::FromStr for ResponseMode {
             "query" => Ok(ResponseMode::Query),
             "fragment" => Ok(ResponseMode::Fragment),
             "form_post" => Ok(ResponseMode::FormPost),
+            "query.jwt" => Ok(ResponseMode::QueryJwt),
+            "fragment.jwt" => Ok(ResponseMode::FragmentJwt),
+            "form_post.jwt" => Ok(ResponseMode::FormPostJwt),
+            "jwt" => Ok(ResponseMode::Jwt),
             s => Ok(ResponseMode::Unknown(s.to_owned())),
         }
     }
@@ -459,6 +509,75 @@ impl fmt::Debug for DeviceAuthorizationResponse {
     }
 }
 
+/// A request to the [Backchannel Authentication Endpoint].
+///
+/// [Backchannel Authentication Endpoint]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#auth_request
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BackchannelAuthenticationRequest {
+    /// The scope of the access request.
+    ///
+    /// It must contain the `openid` scope.
+    pub scope: Scope,
+
+    /// A hint to the authorization server about the end-user whose
+    /// authentication is being requested.
+    pub login_hint: Option<String>,
+
+    /// A human-readable message displayed both on the consumption device and
+    /// on the authentication device, to let the user make sure they are
+    /// approving the right request.
+    pub binding_message: Option<String>,
+
+    /// The lifetime of the authentication request requested by the client, in
+    /// seconds.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub requested_expiry: Option<NonZeroU32>,
+}
+
+impl fmt::Debug for BackchannelAuthenticationRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackchannelAuthenticationRequest")
+            .field("scope", &self.scope)
+            .field("binding_message", &self.binding_message)
+            .field("requested_expiry", &self.requested_expiry)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A successful response from the [Backchannel Authentication Endpoint].
+///
+/// [Backchannel Authentication Endpoint]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#auth_request
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BackchannelAuthenticationResponse {
+    /// The identifier of the authentication request, used by the client to
+    /// poll the token endpoint.
+    pub auth_req_id: String,
+
+    /// The lifetime of the `auth_req_id`.
+    #[serde_as(as = "DurationSeconds<i64>")]
+    pub expires_in: Duration,
+
+    /// The minimum amount of time in seconds that the client should wait
+    /// between polling requests to the token endpoint.
+    ///
+    /// Defaults to 5 seconds.
+    #[serde_as(as = "Option<DurationSeconds<i64>>")]
+    pub interval: Option<Duration>,
+}
+
+impl fmt::Debug for BackchannelAuthenticationResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackchannelAuthenticationResponse")
+            .field("expires_in", &self.expires_in)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
 /// A request to the [Token Endpoint] for the [Authorization Code] grant type.
 ///
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
@@ -542,6 +661,45 @@ impl fmt::Debug for DeviceCodeGrant {
     }
 }
 
+/// A request to the [Token Endpoint] for the [CIBA] grant type.
+///
+/// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
+/// [CIBA]: https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html#token_request
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct CibaGrant {
+    /// The identifier of the authentication request, from the backchannel
+    /// authentication response.
+    pub auth_req_id: String,
+}
+
+impl fmt::Debug for CibaGrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CibaGrant").finish_non_exhaustive()
+    }
+}
+
+/// A request to the [Token Endpoint] for the [JWT Bearer] grant type.
+///
+/// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
+/// [JWT Bearer]: https://www.rfc-editor.org/rfc/rfc7523#section-2.1
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct JwtBearerGrant {
+    /// The signed JWT asserting the identity of the user.
+    pub assertion: String,
+
+    /// The scope of the access request.
+    pub scope: Option<Scope>,
+}
+
+impl fmt::Debug for JwtBearerGrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtBearerGrant")
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
 /// All possible values for the `grant_type` parameter.
 #[derive(
     Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, SerializeDisplay, DeserializeFromStr,
@@ -633,11 +791,35 @@ pub enum AccessTokenRequest {
     #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
     DeviceCode(DeviceCodeGrant),
 
+    /// A request in the JWT Bearer assertion flow.
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:jwt-bearer")]
+    JwtBearer(JwtBearerGrant),
+
+    /// A request in the Client-Initiated Backchannel Authentication flow.
+    #[serde(rename = "urn:openid:params:grant-type:ciba")]
+    Ciba(CibaGrant),
+
     /// An unsupported request.
     #[serde(skip_serializing, other)]
     Unsupported,
 }
 
+impl AccessTokenRequest {
+    /// The grant type of this request, or `None` if it is unsupported.
+    #[must_use]
+    pub fn grant_type(&self) -> Option<GrantType> {
+        match self {
+            Self::AuthorizationCode(_) => Some(GrantType::AuthorizationCode),
+            Self::RefreshToken(_) => Some(GrantType::RefreshToken),
+            Self::ClientCredentials(_) => Some(GrantType::ClientCredentials),
+            Self::DeviceCode(_) => Some(GrantType::DeviceCode),
+            Self::JwtBearer(_) => Some(GrantType::JwtBearer),
+            Self::Ciba(_) => Some(GrantType::ClientInitiatedBackchannelAuthentication),
+            Self::Unsupported => None,
+        }
+    }
+}
+
 /// A successful response from the [Token Endpoint].
 ///
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
@@ -786,6 +968,47 @@ pub struct IntrospectionResponse {
 
     /// String identifier for the token.
     pub jti: Option<String>,
+
+    /// Confirmation of the key the token is bound to.
+    pub cnf: Option<Confirmation>,
+
+    /// Authentication Context Class Reference of the authentication of the
+    /// resource owner, as defined in [RFC 9470].
+    ///
+    /// [RFC 9470]: https://www.rfc-editor.org/rfc/rfc9470#section-6.2
+    pub acr: Option<String>,
+
+    /// Timestamp indicating when the resource owner last authenticated, as
+    /// defined in [RFC 9470].
+    ///
+    /// [RFC 9470]: https://www.rfc-editor.org/rfc/rfc9470#section-6.2
+    #[serde_as(as = "Option<TimestampSeconds>")]
+    pub auth_time: Option<DateTime<Utc>>,
+
+    /// Whether the token was issued to a bot account.
+    ///
+    /// This is not part of the specification, it lets the resource server
+    /// treat bots differently, for example with specific rate limits.
+    pub bot: Option<bool>,
+}
+
+/// The [confirmation claim] of a sender-constrained token.
+///
+/// [confirmation claim]: https://www.rfc-editor.org/rfc/rfc7800
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Confirmation {
+    /// The JWK SHA-256 thumbprint of the [DPoP] key the token is bound to.
+    ///
+    /// [DPoP]: https://www.rfc-editor.org/rfc/rfc9449#section-6.1
+    pub jkt: Option<String>,
+
+    /// The base64url-encoded SHA-256 thumbprint of the [client certificate]
+    /// the token is bound to.
+    ///
+    /// [client certificate]: https://www.rfc-editor.org/rfc/rfc8705.html#section-3.1
+    #[serde(rename = "x5t#S256")]
+    pub x5t_s256: Option<String>,
 }
 
 /// A request to the [Revocation Endpoint].
@@ -872,6 +1095,38 @@ mod tests {
         assert_serde_json(&req, expected);
     }
 
+    #[test]
+    fn serde_jwt_bearer_grant() {
+        let expected = json!({
+            "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+            "assertion": "eyJhbGciOiJFUzI1NiJ9.e30.c2ln",
+            "scope": "openid",
+        });
+
+        let scope: Option<Scope> = Some(vec![OPENID].into_iter().collect());
+
+        let req = AccessTokenRequest::JwtBearer(JwtBearerGrant {
+            assertion: "eyJhbGciOiJFUzI1NiJ9.e30.c2ln".into(),
+            scope,
+        });
+
+        assert_serde_json(&req, expected);
+    }
+
+    #[test]
+    fn serde_ciba_grant() {
+        let expected = json!({
+            "grant_type": "urn:openid:params:grant-type:ciba",
+            "auth_req_id": "abcd",
+        });
+
+        let req = AccessTokenRequest::Ciba(CibaGrant {
+            auth_req_id: "abcd".into(),
+        });
+
+        assert_serde_json(&req, expected);
+    }
+
     #[test]
     fn serialize_grant_type() {
         assert_eq!(
@@ -951,6 +1206,22 @@ mod tests {
             serde_json::to_string(&ResponseMode::FormPost).unwrap(),
             "\"form_post\""
         );
+        assert_eq!(
+            serde_json::to_string(&ResponseMode::QueryJwt).unwrap(),
+            "\"query.jwt\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ResponseMode::FragmentJwt).unwrap(),
+            "\"fragment.jwt\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ResponseMode::FormPostJwt).unwrap(),
+            "\"form_post.jwt\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ResponseMode::Jwt).unwrap(),
+            "\"jwt\""
+        );
     }
 
     #[test]
@@ -967,6 +1238,22 @@ mod tests {
             serde_json::from_str::<ResponseMode>("\"form_post\"").unwrap(),
             ResponseMode::FormPost
         );
+        assert_eq!(
+            serde_json::from_str::<ResponseMode>("\"query.jwt\"").unwrap(),
+            ResponseMode::QueryJwt
+        );
+        assert_eq!(
+            serde_json::from_str::<ResponseMode>("\"fragment.jwt\"").unwrap(),
+            ResponseMode::FragmentJwt
+        );
+        assert_eq!(
+            serde_json::from_str::<ResponseMode>("\"form_post.jwt\"").unwrap(),
+            ResponseMode::FormPostJwt
+        );
+        assert_eq!(
+            serde_json::from_str::<ResponseMode>("\"jwt\"").unwrap(),
+            ResponseMode::Jwt
+        );
     }
 
     #[test]
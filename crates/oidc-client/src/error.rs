@@ -262,6 +262,14 @@ pub enum AuthorizationError {
     /// An error occurred making the PAR request.
     #[error(transparent)]
     PushedAuthorization(#[from] PushedAuthorizationError),
+
+    /// An error occurred serializing the request object.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// An error occurred signing the request object.
+    #[error(transparent)]
+    RequestObject(#[from] CredentialsError),
 }
 
 /// All possible errors when requesting an access token.
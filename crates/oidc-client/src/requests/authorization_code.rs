@@ -8,29 +8,37 @@
 //!
 //! [Authorization Code flow]: https://openid.net/specs/openid-connect-core-1_0.html#CodeFlowAuth
 
-use std::{collections::HashSet, num::NonZeroU32};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    num::NonZeroU32,
+};
 
 use base64ct::{Base64UrlUnpadded, Encoding};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use http::header::CONTENT_TYPE;
 use language_tags::LanguageTag;
 use mas_http::{CatchHttpCodesLayer, FormUrlencodedRequestLayer, JsonResponseLayer};
-use mas_iana::oauth::{OAuthAuthorizationEndpointResponseType, PkceCodeChallengeMethod};
-use mas_jose::claims::{self, TokenHash};
+use mas_iana::{
+    jose::JsonWebSignatureAlg,
+    oauth::{OAuthAuthorizationEndpointResponseType, PkceCodeChallengeMethod},
+};
+use mas_jose::claims::{self, ClaimError, TokenHash};
 use oauth2_types::{
     pkce,
     prelude::CodeChallengeMethodExt,
     requests::{
         AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, AuthorizationRequest,
-        Display, Prompt, PushedAuthorizationResponse,
+        Display, Prompt, PushedAuthorizationResponse, ResponseMode,
     },
+    response_type::ResponseType,
     scope::Scope,
 };
 use rand::{
     distributions::{Alphanumeric, DistString},
     Rng,
 };
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 use serde_with::skip_serializing_none;
 use tower::{Layer, Service, ServiceExt};
 use url::Url;
@@ -38,12 +46,13 @@ use url::Url;
 use super::jose::JwtVerificationData;
 use crate::{
     error::{
-        AuthorizationError, IdTokenError, PushedAuthorizationError, TokenAuthorizationCodeError,
+        AuthorizationError, CredentialsError, IdTokenError, PushedAuthorizationError,
+        TokenAuthorizationCodeError,
     },
     http_service::HttpService,
     requests::{jose::verify_id_token, token::request_access_token},
     types::{
-        client_credentials::ClientCredentials,
+        client_credentials::{ClientCredentials, JwtSigningMethod},
         scope::{ScopeExt, ScopeToken},
         IdToken,
     },
@@ -59,9 +68,18 @@ pub struct AuthorizationRequestData {
     /// The scope to authorize.
     ///
     /// If the OpenID Connect scope token (`openid`) is not included, it will be
-    /// added.
+    /// added, unless [`openid_scope`] is `false`.
+    ///
+    /// [`openid_scope`]: AuthorizationRequestData::openid_scope
     pub scope: Scope,
 
+    /// Whether the OpenID Connect scope token (`openid`) should be added to
+    /// the scope.
+    ///
+    /// This should only be disabled for plain OAuth 2.0 authorization servers.
+    /// Defaults to `true`.
+    pub openid_scope: bool,
+
     /// The URI to redirect the end-user to after the authorization.
     ///
     /// It must be one of the redirect URIs provided during registration.
@@ -101,6 +119,19 @@ pub struct AuthorizationRequestData {
 
     /// Requested Authentication Context Class Reference values.
     pub acr_values: Option<HashSet<String>>,
+
+    /// How the authorization response should be returned.
+    ///
+    /// If it is not set, the default for the response type is used.
+    pub response_mode: Option<ResponseMode>,
+
+    /// Additional parameters to include in the authorization request.
+    pub additional_parameters: Vec<(String, String)>,
+
+    /// How to sign the authorization request as a request object.
+    ///
+    /// If it is not set, the request is not signed.
+    pub request_object_signing: Option<RequestObjectSigningData>,
 }
 
 impl AuthorizationRequestData {
@@ -111,6 +142,7 @@ impl AuthorizationRequestData {
         Self {
             client_id,
             scope,
+            openid_scope: true,
             redirect_uri,
             code_challenge_methods_supported: None,
             display: None,
@@ -120,6 +152,9 @@ impl AuthorizationRequestData {
             id_token_hint: None,
             login_hint: None,
             acr_values: None,
+            response_mode: None,
+            additional_parameters: Vec::new(),
+            request_object_signing: None,
         }
     }
 
@@ -182,6 +217,85 @@ impl AuthorizationRequestData {
         self.acr_values = Some(acr_values);
         self
     }
+
+    /// Set the `response_mode` field of this `AuthorizationRequestData`.
+    #[must_use]
+    pub fn with_response_mode(mut self, response_mode: ResponseMode) -> Self {
+        self.response_mode = Some(response_mode);
+        self
+    }
+
+    /// Set the `additional_parameters` field of this
+    /// `AuthorizationRequestData`.
+    #[must_use]
+    pub fn with_additional_parameters(
+        mut self,
+        additional_parameters: Vec<(String, String)>,
+    ) -> Self {
+        self.additional_parameters = additional_parameters;
+        self
+    }
+
+    /// Set the `request_object_signing` field of this
+    /// `AuthorizationRequestData`.
+    #[must_use]
+    pub fn with_request_object_signing(
+        mut self,
+        request_object_signing: RequestObjectSigningData,
+    ) -> Self {
+        self.request_object_signing = Some(request_object_signing);
+        self
+    }
+
+    /// Set the `openid_scope` field of this `AuthorizationRequestData`.
+    #[must_use]
+    pub fn with_openid_scope(mut self, openid_scope: bool) -> Self {
+        self.openid_scope = openid_scope;
+        self
+    }
+}
+
+/// The data necessary to sign an authorization request as a [JWT-Secured
+/// Authorization Request].
+///
+/// [JWT-Secured Authorization Request]: https://www.rfc-editor.org/rfc/rfc9101
+#[derive(Clone)]
+pub struct RequestObjectSigningData {
+    /// The audience of the request object.
+    ///
+    /// This should be the issuer identifier of the authorization server.
+    pub audience: String,
+
+    /// The method used to sign the request object.
+    pub signing_method: JwtSigningMethod,
+
+    /// The algorithm used to sign the request object.
+    pub signing_algorithm: JsonWebSignatureAlg,
+}
+
+impl RequestObjectSigningData {
+    /// Constructs a new `RequestObjectSigningData`.
+    #[must_use]
+    pub fn new(
+        audience: String,
+        signing_method: JwtSigningMethod,
+        signing_algorithm: JsonWebSignatureAlg,
+    ) -> Self {
+        Self {
+            audience,
+            signing_method,
+            signing_algorithm,
+        }
+    }
+}
+
+impl fmt::Debug for RequestObjectSigningData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestObjectSigningData")
+            .field("audience", &self.audience)
+            .field("signing_algorithm", &self.signing_algorithm)
+            .finish_non_exhaustive()
+    }
 }
 
 /// The data necessary to validate a response from the Token endpoint in the
@@ -201,6 +315,17 @@ pub struct AuthorizationValidationData {
     pub code_challenge_verifier: Option<String>,
 }
 
+/// Additional parameters of the authorization request, serialized as a map
+/// while keeping their order.
+#[derive(Clone)]
+struct AdditionalParameters(Vec<(String, String)>);
+
+impl Serialize for AdditionalParameters {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
 #[skip_serializing_none]
 #[derive(Clone, Serialize)]
 struct FullAuthorizationRequest {
@@ -208,16 +333,40 @@ struct FullAuthorizationRequest {
     inner: AuthorizationRequest,
     #[serde(flatten)]
     pkce: Option<pkce::AuthorizationRequest>,
+    #[serde(flatten)]
+    additional_parameters: AdditionalParameters,
+}
+
+/// An authorization request signed as a request object.
+///
+/// The `response_type` and `scope` parameters are repeated outside of the
+/// request object, as required by OpenID Connect.
+#[derive(Clone, Serialize)]
+struct SignedAuthorizationRequest {
+    client_id: String,
+    response_type: ResponseType,
+    scope: Scope,
+    request: String,
+}
+
+/// The parameters sent to the authorization server.
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+enum AuthorizationRequestParameters {
+    Plain(FullAuthorizationRequest),
+    Signed(SignedAuthorizationRequest),
 }
 
 /// Build the authorization request.
 fn build_authorization_request(
     authorization_data: AuthorizationRequestData,
+    now: DateTime<Utc>,
     rng: &mut impl Rng,
-) -> Result<(FullAuthorizationRequest, AuthorizationValidationData), AuthorizationError> {
+) -> Result<(AuthorizationRequestParameters, AuthorizationValidationData), AuthorizationError> {
     let AuthorizationRequestData {
         client_id,
         mut scope,
+        openid_scope,
         redirect_uri,
         code_challenge_methods_supported,
         display,
@@ -227,6 +376,9 @@ fn build_authorization_request(
         id_token_hint,
         login_hint,
         acr_values,
+        response_mode,
+        additional_parameters,
+        request_object_signing,
     } = authorization_data;
 
     // Generate a random CSRF "state" token and a nonce.
@@ -255,7 +407,9 @@ fn build_authorization_request(
         (None, None)
     };
 
-    scope.insert_token(ScopeToken::Openid);
+    if openid_scope {
+        scope.insert_token(ScopeToken::Openid);
+    }
 
     let auth_request = FullAuthorizationRequest {
         inner: AuthorizationRequest {
@@ -264,7 +418,7 @@ fn build_authorization_request(
             redirect_uri: Some(redirect_uri.clone()),
             scope,
             state: Some(state.clone()),
-            response_mode: None,
+            response_mode,
             nonce: Some(nonce.clone()),
             display,
             prompt,
@@ -278,6 +432,18 @@ fn build_authorization_request(
             registration: None,
         },
         pkce,
+        additional_parameters: AdditionalParameters(additional_parameters),
+    };
+
+    let auth_request = if let Some(request_object_signing) = request_object_signing {
+        AuthorizationRequestParameters::Signed(sign_authorization_request(
+            auth_request,
+            request_object_signing,
+            now,
+            rng,
+        )?)
+    } else {
+        AuthorizationRequestParameters::Plain(auth_request)
     };
 
     let auth_data = AuthorizationValidationData {
@@ -290,6 +456,60 @@ fn build_authorization_request(
     Ok((auth_request, auth_data))
 }
 
+/// Sign the authorization request as a request object.
+fn sign_authorization_request(
+    auth_request: FullAuthorizationRequest,
+    request_object_signing: RequestObjectSigningData,
+    now: DateTime<Utc>,
+    rng: &mut impl Rng,
+) -> Result<SignedAuthorizationRequest, AuthorizationError> {
+    let RequestObjectSigningData {
+        audience,
+        signing_method,
+        signing_algorithm,
+    } = request_object_signing;
+
+    let client_id = auth_request.inner.client_id.clone();
+    let response_type = auth_request.inner.response_type.clone();
+    let scope = auth_request.inner.scope.clone();
+
+    let mut request_claims: HashMap<String, serde_json::Value> =
+        serde_json::from_value(serde_json::to_value(auth_request)?)?;
+
+    add_request_object_claims(&mut request_claims, client_id.clone(), audience, now, rng)
+        .map_err(CredentialsError::from)?;
+
+    let request = signing_method.sign_claims(request_claims, signing_algorithm)?;
+
+    Ok(SignedAuthorizationRequest {
+        client_id,
+        response_type,
+        scope,
+        request,
+    })
+}
+
+/// Add the claims identifying the request object to the given claims.
+fn add_request_object_claims(
+    request_claims: &mut HashMap<String, serde_json::Value>,
+    iss: String,
+    aud: String,
+    now: DateTime<Utc>,
+    rng: &mut impl Rng,
+) -> Result<(), ClaimError> {
+    claims::ISS.insert(request_claims, iss)?;
+    claims::AUD.insert(request_claims, aud)?;
+    claims::IAT.insert(request_claims, now)?;
+    claims::EXP.insert(request_claims, now + Duration::minutes(5))?;
+
+    let mut jti = [0u8; 16];
+    rng.fill(&mut jti);
+    let jti = Base64UrlUnpadded::encode_string(&jti);
+    claims::JTI.insert(request_claims, jti)?;
+
+    Ok(())
+}
+
 /// Build the URL for authenticating at the Authorization endpoint.
 ///
 /// # Arguments
@@ -299,6 +519,8 @@ fn build_authorization_request(
 /// * `authorization_data` - The data necessary to build the authorization
 ///   request.
 ///
+/// * `now` - The current time.
+///
 /// * `rng` - A random number generator.
 ///
 /// # Returns
@@ -324,6 +546,7 @@ fn build_authorization_request(
 pub fn build_authorization_url(
     authorization_endpoint: Url,
     authorization_data: AuthorizationRequestData,
+    now: DateTime<Utc>,
     rng: &mut impl Rng,
 ) -> Result<(Url, AuthorizationValidationData), AuthorizationError> {
     tracing::debug!(
@@ -332,7 +555,7 @@ pub fn build_authorization_url(
     );
 
     let (authorization_request, validation_data) =
-        build_authorization_request(authorization_data, rng)?;
+        build_authorization_request(authorization_data, now, rng)?;
 
     let authorization_query = serde_urlencoded::to_string(authorization_request)?;
 
@@ -413,7 +636,7 @@ pub async fn build_par_authorization_url(
     let client_id = client_credentials.client_id().to_owned();
 
     let (authorization_request, validation_data) =
-        build_authorization_request(authorization_data, rng)?;
+        build_authorization_request(authorization_data, now, rng)?;
 
     let par_request = http::Request::post(par_endpoint.as_str())
         .header(CONTENT_TYPE, mime::APPLICATION_WWW_FORM_URLENCODED.as_ref())
@@ -528,39 +751,79 @@ pub async fn access_token_with_authorization_code(
     .await?;
 
     let id_token = if let Some(verification_data) = id_token_verification_data {
-        let signing_alg = verification_data.signing_algorithm;
+        Some(verify_authorization_code_id_token(
+            &token_response,
+            &code,
+            &validation_data.nonce,
+            verification_data,
+            now,
+        )?)
+    } else {
+        None
+    };
 
-        let id_token = token_response
-            .id_token
-            .as_deref()
-            .ok_or(IdTokenError::MissingIdToken)?;
+    Ok((token_response, id_token))
+}
+
+/// Verify the ID Token of a response to an access token request made with an
+/// authorization code.
+///
+/// This is done by [`access_token_with_authorization_code()`] when it is given
+/// the verification data. It can be used directly to verify the ID Token
+/// separately from the request, for example to pick the JWKS depending on the
+/// key ID in the ID Token header.
+///
+/// # Arguments
+///
+/// * `token_response` - The response of the access token request.
+///
+/// * `code` - The authorization code that was exchanged.
+///
+/// * `nonce` - The nonce of the validation data that was returned when building
+///   the Authorization URL.
+///
+/// * `verification_data` - The data required to verify the ID Token.
+///
+/// * `now` - The current time.
+///
+/// # Errors
+///
+/// Returns an error if the ID Token is missing or its verification fails.
+pub fn verify_authorization_code_id_token(
+    token_response: &AccessTokenResponse,
+    code: &str,
+    nonce: &str,
+    verification_data: JwtVerificationData<'_>,
+    now: DateTime<Utc>,
+) -> Result<IdToken<'static>, IdTokenError> {
+    let signing_alg = verification_data.signing_algorithm;
 
-        let id_token = verify_id_token(id_token, verification_data, None, now)?;
+    let id_token = token_response
+        .id_token
+        .as_deref()
+        .ok_or(IdTokenError::MissingIdToken)?;
 
-        let mut claims = id_token.payload().clone();
+    let id_token = verify_id_token(id_token, verification_data, None, now)?;
 
-        // Access token hash must match.
-        claims::AT_HASH
-            .extract_optional_with_options(
-                &mut claims,
-                TokenHash::new(signing_alg, &token_response.access_token),
-            )
-            .map_err(IdTokenError::from)?;
+    let mut claims = id_token.payload().clone();
 
-        // Code hash must match.
-        claims::C_HASH
-            .extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, &code))
-            .map_err(IdTokenError::from)?;
+    // Access token hash must match.
+    claims::AT_HASH
+        .extract_optional_with_options(
+            &mut claims,
+            TokenHash::new(signing_alg, &token_response.access_token),
+        )
+        .map_err(IdTokenError::from)?;
 
-        // Nonce must match.
-        claims::NONCE
-            .extract_required_with_options(&mut claims, validation_data.nonce.as_str())
-            .map_err(IdTokenError::from)?;
+    // Code hash must match.
+    claims::C_HASH
+        .extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, code))
+        .map_err(IdTokenError::from)?;
 
-        Some(id_token.into_owned())
-    } else {
-        None
-    };
+    // Nonce must match.
+    claims::NONCE
+        .extract_required_with_options(&mut claims, nonce)
+        .map_err(IdTokenError::from)?;
 
-    Ok((token_response, id_token))
+    Ok(id_token.into_owned())
 }
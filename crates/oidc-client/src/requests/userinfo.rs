@@ -49,7 +49,9 @@ use crate::{
 ///   field in the client metadata.
 ///
 /// * `auth_id_token` - The ID token that was returned from the latest
-///   authorization request.
+///   authorization request, if any. When present, the subject identifier of the
+///   response must match the one of the ID token. Plain OAuth 2.0 providers
+///   don't return ID tokens.
 ///
 /// # Errors
 ///
@@ -63,7 +65,7 @@ pub async fn fetch_userinfo(
     userinfo_endpoint: &Url,
     access_token: &str,
     jwt_verification_data: Option<JwtVerificationData<'_>>,
-    auth_id_token: &IdToken<'_>,
+    auth_id_token: Option<&IdToken<'_>>,
 ) -> Result<HashMap<String, Value>, UserInfoError> {
     tracing::debug!("Obtaining user info…");
 
@@ -116,17 +118,19 @@ pub async fn fetch_userinfo(
         serde_json::from_str(response_body)?
     };
 
-    let mut auth_claims = auth_id_token.payload().clone();
-
-    // Subject identifier must always be the same.
-    let sub = claims::SUB
-        .extract_required(&mut claims)
-        .map_err(IdTokenError::from)?;
-    let auth_sub = claims::SUB
-        .extract_required(&mut auth_claims)
-        .map_err(IdTokenError::from)?;
-    if sub != auth_sub {
-        return Err(IdTokenError::WrongSubjectIdentifier.into());
+    if let Some(auth_id_token) = auth_id_token {
+        let mut auth_claims = auth_id_token.payload().clone();
+
+        // Subject identifier must always be the same.
+        let sub = claims::SUB
+            .extract_required(&mut claims)
+            .map_err(IdTokenError::from)?;
+        let auth_sub = claims::SUB
+            .extract_required(&mut auth_claims)
+            .map_err(IdTokenError::from)?;
+        if sub != auth_sub {
+            return Err(IdTokenError::WrongSubjectIdentifier.into());
+        }
     }
 
     Ok(claims)
@@ -17,7 +17,7 @@ use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod
 use mas_jose::constraints::Constrainable;
 use mas_jose::{
     claims::{self, ClaimError},
-    jwa::SymmetricKey,
+    jwa::{AsymmetricSigningKey, SymmetricKey},
     jwt::{JsonWebSignatureHeader, Jwt},
 };
 #[cfg(feature = "keystore")]
@@ -43,6 +43,9 @@ pub const CLIENT_SUPPORTED_AUTH_METHODS: &[OAuthClientAuthenticationMethod] = &[
     OAuthClientAuthenticationMethod::PrivateKeyJwt,
 ];
 
+/// The audience of the client secret JWT expected by Sign in with Apple.
+const APPLE_AUDIENCE: &str = "https://appleid.apple.com";
+
 /// A function that takes a map of claims and a signing algorithm and returns a
 /// signed JWT.
 pub type JwtSigningFn =
@@ -98,6 +101,36 @@ impl JwtSigningMethod {
             JwtSigningMethod::Keystore(_) => None,
         }
     }
+
+    /// Sign the given claims with this [`JwtSigningMethod`].
+    pub(crate) fn sign_claims(
+        &self,
+        claims: HashMap<String, Value>,
+        signing_algorithm: JsonWebSignatureAlg,
+    ) -> Result<String, CredentialsError> {
+        match self {
+            #[cfg(feature = "keystore")]
+            JwtSigningMethod::Keystore(keystore) => {
+                let key = keystore
+                    .signing_key_for_algorithm(&signing_algorithm)
+                    .ok_or(CredentialsError::NoPrivateKeyFound)?;
+                let signer = key
+                    .params()
+                    .signing_key_for_alg(&signing_algorithm)
+                    .map_err(|_| CredentialsError::JwtWrongAlgorithm)?;
+                let mut header = JsonWebSignatureHeader::new(signing_algorithm);
+
+                if let Some(kid) = key.kid() {
+                    header = header.with_kid(kid);
+                }
+
+                Ok(Jwt::sign(header, claims, &signer)?.to_string())
+            }
+            JwtSigningMethod::Custom(jwt_signing_fn) => {
+                jwt_signing_fn(claims, signing_algorithm).map_err(CredentialsError::Custom)
+            }
+        }
+    }
 }
 
 /// The credentials obtained during registration, to authenticate a client on
@@ -160,6 +193,27 @@ pub enum ClientCredentials {
         /// The URL of the issuer's Token endpoint.
         token_endpoint: Url,
     },
+
+    /// The client authentication uses a client secret generated as a JWT
+    /// signed with the key issued by Apple, as required by [Sign in with
+    /// Apple].
+    ///
+    /// The client secret is sent with the body of the request.
+    ///
+    /// [Sign in with Apple]: https://developer.apple.com/documentation/accountorganizationaldatasharing/creating-a-client-secret
+    SignInWithApple {
+        /// The unique ID for the client, also known as the Services ID.
+        client_id: String,
+
+        /// The private key issued by Apple.
+        key: elliptic_curve::SecretKey<p256::NistP256>,
+
+        /// The ID of the private key.
+        key_id: String,
+
+        /// The ID of the team which owns the client.
+        team_id: String,
+    },
 }
 
 impl ClientCredentials {
@@ -171,7 +225,8 @@ impl ClientCredentials {
             | ClientCredentials::ClientSecretBasic { client_id, .. }
             | ClientCredentials::ClientSecretPost { client_id, .. }
             | ClientCredentials::ClientSecretJwt { client_id, .. }
-            | ClientCredentials::PrivateKeyJwt { client_id, .. } => client_id,
+            | ClientCredentials::PrivateKeyJwt { client_id, .. }
+            | ClientCredentials::SignInWithApple { client_id, .. } => client_id,
         }
     }
 
@@ -257,6 +312,17 @@ impl fmt::Debug for ClientCredentials {
                 .field("signing_algorithm", signing_algorithm)
                 .field("token_endpoint", token_endpoint)
                 .finish_non_exhaustive(),
+            Self::SignInWithApple {
+                client_id,
+                key_id,
+                team_id,
+                ..
+            } => f
+                .debug_struct("SignInWithApple")
+                .field("client_id", client_id)
+                .field("key_id", key_id)
+                .field("team_id", team_id)
+                .finish_non_exhaustive(),
         }
     }
 }
@@ -328,29 +394,7 @@ impl RequestClientCredentials {
                 let claims =
                     prepare_claims(client_id.clone(), token_endpoint.to_string(), now, rng)?;
 
-                let client_assertion = match jwt_signing_method {
-                    #[cfg(feature = "keystore")]
-                    JwtSigningMethod::Keystore(keystore) => {
-                        let key = keystore
-                            .signing_key_for_algorithm(&signing_algorithm)
-                            .ok_or(CredentialsError::NoPrivateKeyFound)?;
-                        let signer = key
-                            .params()
-                            .signing_key_for_alg(&signing_algorithm)
-                            .map_err(|_| CredentialsError::JwtWrongAlgorithm)?;
-                        let mut header = JsonWebSignatureHeader::new(signing_algorithm);
-
-                        if let Some(kid) = key.kid() {
-                            header = header.with_kid(kid);
-                        }
-
-                        Jwt::sign(header, claims, &signer)?.to_string()
-                    }
-                    JwtSigningMethod::Custom(jwt_signing_fn) => {
-                        jwt_signing_fn(claims, signing_algorithm)
-                            .map_err(CredentialsError::Custom)?
-                    }
-                };
+                let client_assertion = jwt_signing_method.sign_claims(claims, signing_algorithm)?;
 
                 Self::Body(BodyClientCredentials {
                     client_id,
@@ -359,6 +403,35 @@ impl RequestClientCredentials {
                     client_assertion_type: Some(JwtBearerClientAssertionType),
                 })
             }
+            ClientCredentials::SignInWithApple {
+                client_id,
+                key,
+                key_id,
+                team_id,
+            } => {
+                let mut claims = HashMap::new();
+                claims::ISS.insert(&mut claims, team_id)?;
+                claims::SUB.insert(&mut claims, client_id.clone())?;
+                claims::AUD.insert(&mut claims, APPLE_AUDIENCE.to_owned())?;
+                claims::IAT.insert(&mut claims, now)?;
+                claims::EXP.insert(
+                    &mut claims,
+                    now + Duration::microseconds(5 * 60 * 1000 * 1000),
+                )?;
+
+                let signer = AsymmetricSigningKey::es256(key);
+                let header =
+                    JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256).with_kid(key_id);
+
+                let client_secret = Jwt::sign(header, claims, &signer)?;
+
+                Self::Body(BodyClientCredentials {
+                    client_id,
+                    client_secret: Some(client_secret.to_string()),
+                    client_assertion: None,
+                    client_assertion_type: None,
+                })
+            }
         };
 
         Ok(res)
@@ -12,10 +12,11 @@ use std::{
 
 use assert_matches::assert_matches;
 use chrono::Duration;
-use mas_iana::oauth::{
-    OAuthAccessTokenType, OAuthClientAuthenticationMethod, PkceCodeChallengeMethod,
+use mas_iana::{
+    jose::JsonWebSignatureAlg,
+    oauth::{OAuthAccessTokenType, OAuthClientAuthenticationMethod, PkceCodeChallengeMethod},
 };
-use mas_jose::{claims::ClaimError, jwk::PublicJsonWebKeySet};
+use mas_jose::{claims::ClaimError, jwk::PublicJsonWebKeySet, jwt::Jwt};
 use mas_oidc_client::{
     error::{
         AuthorizationError, IdTokenError, PushedAuthorizationError, TokenAuthorizationCodeError,
@@ -24,10 +25,14 @@ use mas_oidc_client::{
         authorization_code::{
             access_token_with_authorization_code, build_authorization_url,
             build_par_authorization_url, AuthorizationRequestData, AuthorizationValidationData,
+            RequestObjectSigningData,
         },
         jose::JwtVerificationData,
     },
-    types::scope::{ScopeExt, ScopeToken},
+    types::{
+        client_credentials::JwtSigningMethod,
+        scope::{ScopeExt, ScopeToken},
+    },
 };
 use oauth2_types::requests::{AccessTokenResponse, Display, Prompt, PushedAuthorizationResponse};
 use rand::SeedableRng;
@@ -39,8 +44,8 @@ use wiremock::{
 };
 
 use crate::{
-    client_credentials, id_token, init_test, now, ACCESS_TOKEN, AUTHORIZATION_CODE, CLIENT_ID,
-    CODE_VERIFIER, ID_TOKEN_SIGNING_ALG, NONCE, REDIRECT_URI, REQUEST_URI,
+    client_credentials, id_token, init_test, keystore, now, ACCESS_TOKEN, AUTHORIZATION_CODE,
+    CLIENT_ID, CODE_VERIFIER, ID_TOKEN_SIGNING_ALG, NONCE, REDIRECT_URI, REQUEST_URI,
 };
 
 #[test]
@@ -58,6 +63,7 @@ fn pass_authorization_url() {
             redirect_uri,
         )
         .with_code_challenge_methods_supported(vec![PkceCodeChallengeMethod::S256]),
+        now(),
         &mut rng,
     )
     .unwrap();
@@ -89,6 +95,29 @@ fn pass_authorization_url() {
     assert_eq!(query_pairs.get("code_challenge_method").unwrap(), "S256");
 }
 
+#[test]
+fn pass_authorization_url_without_openid_scope() {
+    let issuer = Url::parse("http://localhost/").unwrap();
+    let authorization_endpoint = issuer.join("authorize").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let (url, _validation_data) = build_authorization_url(
+        authorization_endpoint,
+        AuthorizationRequestData::new(
+            CLIENT_ID.to_owned(),
+            "read:user user:email".parse().unwrap(),
+            Url::parse(REDIRECT_URI).unwrap(),
+        )
+        .with_openid_scope(false),
+        now(),
+        &mut rng,
+    )
+    .unwrap();
+
+    let query_pairs = url.query_pairs().collect::<HashMap<_, _>>();
+    assert_eq!(query_pairs.get("scope").unwrap(), "read:user user:email");
+}
+
 #[test]
 fn pass_full_authorization_url() {
     let issuer = Url::parse("http://localhost/").unwrap();
@@ -109,7 +138,8 @@ fn pass_full_authorization_url() {
     .with_acr_values(["custom".to_owned()].into());
 
     let (url, validation_data) =
-        build_authorization_url(authorization_endpoint, authorization_data, &mut rng).unwrap();
+        build_authorization_url(authorization_endpoint, authorization_data, now(), &mut rng)
+            .unwrap();
 
     assert_eq!(validation_data.state, "OrJ8xbWovSpJUTKz");
     assert_eq!(validation_data.code_challenge_verifier, None);
@@ -137,6 +167,57 @@ fn pass_full_authorization_url() {
     assert_eq!(query_pairs.get("code_challenge_method"), None);
 }
 
+#[test]
+fn pass_signed_authorization_url() {
+    let issuer = Url::parse("http://localhost/").unwrap();
+    let authorization_endpoint = issuer.join("authorize").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+    let keystore = keystore(&JsonWebSignatureAlg::Rs256);
+
+    let authorization_data = AuthorizationRequestData::new(
+        CLIENT_ID.to_owned(),
+        [ScopeToken::Openid].into_iter().collect(),
+        Url::parse(REDIRECT_URI).unwrap(),
+    )
+    .with_login_hint("mxid:@user:localhost".to_owned())
+    .with_additional_parameters(vec![("foo".to_owned(), "bar".to_owned())])
+    .with_request_object_signing(RequestObjectSigningData::new(
+        issuer.to_string(),
+        JwtSigningMethod::with_keystore(keystore.clone()),
+        JsonWebSignatureAlg::Rs256,
+    ));
+
+    let (url, validation_data) =
+        build_authorization_url(authorization_endpoint, authorization_data, now(), &mut rng)
+            .unwrap();
+
+    assert_eq!(url.path(), "/authorize");
+
+    let query_pairs = url.query_pairs().collect::<HashMap<_, _>>();
+    assert_eq!(query_pairs.get("scope").unwrap(), "openid");
+    assert_eq!(query_pairs.get("response_type").unwrap(), "code");
+    assert_eq!(query_pairs.get("client_id").unwrap(), CLIENT_ID);
+    assert_eq!(query_pairs.get("redirect_uri"), None);
+    assert_eq!(query_pairs.get("state"), None);
+    assert_eq!(query_pairs.get("login_hint"), None);
+    assert_eq!(query_pairs.get("foo"), None);
+
+    let request = query_pairs.get("request").unwrap();
+    let jwt = Jwt::<HashMap<String, serde_json::Value>>::try_from(request.as_ref()).unwrap();
+    jwt.verify_with_jwks(&keystore.public_jwks()).unwrap();
+
+    let claims = jwt.into_parts().1;
+    assert_eq!(claims.get("iss").unwrap(), CLIENT_ID);
+    assert_eq!(claims.get("aud").unwrap(), issuer.as_str());
+    assert_eq!(claims.get("client_id").unwrap(), CLIENT_ID);
+    assert_eq!(claims.get("redirect_uri").unwrap(), REDIRECT_URI);
+    assert_eq!(claims.get("state").unwrap(), validation_data.state.as_str());
+    assert_eq!(claims.get("login_hint").unwrap(), "mxid:@user:localhost");
+    assert_eq!(claims.get("foo").unwrap(), "bar");
+    assert!(claims.contains_key("exp"));
+    assert!(claims.contains_key("jti"));
+}
+
 #[tokio::test]
 async fn pass_pushed_authorization_request() {
     let (http_service, mock_server, issuer) = init_test().await;
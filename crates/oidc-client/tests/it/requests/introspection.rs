@@ -74,6 +74,10 @@ async fn pass_introspect_token() {
                 aud: Some(CLIENT_ID.to_owned()),
                 iss: Some(issuer.to_string()),
                 jti: None,
+                cnf: None,
+                acr: None,
+                auth_time: None,
+                bot: None,
             }),
         )
         .mount(&mock_server)
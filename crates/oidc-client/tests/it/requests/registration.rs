@@ -64,6 +64,8 @@ async fn pass_register_client_none() {
                 client_secret: None,
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
@@ -95,6 +97,8 @@ async fn pass_register_client_client_secret_basic() {
                 client_secret: Some(CLIENT_SECRET.to_owned()),
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
@@ -126,6 +130,8 @@ async fn pass_register_client_client_secret_post() {
                 client_secret: Some(CLIENT_SECRET.to_owned()),
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
@@ -158,6 +164,8 @@ async fn pass_register_client_client_secret_jwt() {
                 client_secret: Some(CLIENT_SECRET.to_owned()),
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
@@ -194,6 +202,8 @@ async fn pass_register_client_private_key_jwt() {
                 client_secret: None,
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
@@ -237,6 +247,8 @@ async fn fail_register_client_missing_secret() {
                 client_secret: None,
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
@@ -7,7 +7,8 @@
 use std::path::{Path, PathBuf};
 
 use mas_policy::model::{
-    AuthorizationGrantInput, ClientRegistrationInput, EmailInput, PasswordInput, RegisterInput,
+    AuthorizationGrantInput, ClientRegistrationInput, EmailInput, ImpersonationInput, KioskInput,
+    PasswordInput, RegisterInput,
 };
 use schemars::{gen::SchemaSettings, JsonSchema};
 
@@ -45,5 +46,7 @@ fn main() {
     write_schema::<ClientRegistrationInput>(output_root, "client_registration_input.json");
     write_schema::<AuthorizationGrantInput>(output_root, "authorization_grant_input.json");
     write_schema::<EmailInput>(output_root, "email_input.json");
+    write_schema::<ImpersonationInput>(output_root, "impersonation_input.json");
+    write_schema::<KioskInput>(output_root, "kiosk_input.json");
     write_schema::<PasswordInput>(output_root, "password_input.json");
 }
@@ -0,0 +1,587 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Loading of signed [OPA bundles](https://www.openpolicyagent.org/docs/latest/management-bundles/)
+//!
+//! A bundle is a gzipped tarball with the compiled policy, the data it is
+//! evaluated with, a `.manifest` file, and a `.signatures.json` file which
+//! has the hashes of all the other files, signed as a JWT.
+
+use std::{collections::BTreeMap, io::Read};
+
+use flate2::read::GzDecoder;
+use mas_jose::{jwk::PublicJsonWebKeySet, jwt::Jwt};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Name of the file with the signatures of the bundle
+const SIGNATURES_FILE: &str = ".signatures.json";
+
+/// Name of the manifest of the bundle
+const MANIFEST_FILE: &str = ".manifest";
+
+/// Name of the compiled policy, if the manifest doesn't say otherwise
+const DEFAULT_WASM_MODULE: &str = "policy.wasm";
+
+/// Name of the files with the data of the policy
+const DATA_FILE: &str = "data.json";
+
+/// Don't uncompress bundles bigger than this, to protect against gzip bombs
+const MAX_BUNDLE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("failed to read the bundle")]
+    Read(#[from] std::io::Error),
+
+    #[error("the bundle is too big")]
+    TooBig,
+
+    #[error("the bundle is not a valid tarball")]
+    InvalidArchive,
+
+    #[error("file {name:?} in the bundle is not valid JSON")]
+    InvalidJson {
+        name: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("the bundle has no {name:?} policy module")]
+    MissingModule { name: String },
+
+    #[error("the bundle is not signed")]
+    MissingSignature,
+
+    #[error("the signature of the bundle is not valid")]
+    InvalidSignature,
+
+    #[error("the bundle was signed for the scope {scope:?}")]
+    ScopeMismatch { scope: Option<String> },
+
+    #[error("file {name:?} in the bundle is not signed")]
+    UnsignedFile { name: String },
+
+    #[error("file {name:?} in the bundle doesn't match its signature")]
+    HashMismatch { name: String },
+
+    #[error("file {name:?} is signed but is not in the bundle")]
+    MissingFile { name: String },
+}
+
+/// The key used to verify the signature of bundles
+#[derive(Debug, Clone)]
+pub enum BundleVerificationKey {
+    /// Public keys, for bundles signed with an asymmetric algorithm like
+    /// `RS256` or `ES256`
+    Jwks(PublicJsonWebKeySet),
+
+    /// A shared secret, for bundles signed with an HMAC algorithm like `HS256`
+    SharedSecret(Vec<u8>),
+}
+
+/// How to verify the signature of bundles
+#[derive(Debug, Clone)]
+pub struct BundleVerification {
+    key: BundleVerificationKey,
+    scope: Option<String>,
+}
+
+impl BundleVerification {
+    /// Verify the bundles with the given key
+    #[must_use]
+    pub fn new(key: BundleVerificationKey) -> Self {
+        Self { key, scope: None }
+    }
+
+    /// Only accept the bundles signed for the given scope
+    #[must_use]
+    pub fn with_scope(mut self, scope: String) -> Self {
+        self.scope = Some(scope);
+        self
+    }
+}
+
+#[derive(Deserialize)]
+struct Signatures {
+    signatures: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SignedFiles {
+    files: Vec<SignedFile>,
+
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SignedFile {
+    name: String,
+    hash: String,
+    algorithm: String,
+}
+
+#[derive(Deserialize, Default)]
+struct Manifest {
+    #[serde(default)]
+    revision: Option<String>,
+
+    #[serde(default)]
+    wasm: Vec<ManifestWasm>,
+}
+
+#[derive(Deserialize)]
+struct ManifestWasm {
+    module: String,
+}
+
+/// A verified bundle
+#[derive(Debug)]
+pub struct Bundle {
+    wasm: Vec<u8>,
+    data: serde_json::Value,
+    revision: Option<String>,
+}
+
+impl Bundle {
+    /// Load and verify a bundle from a gzipped tarball
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bundle could not be read, if it has no policy
+    /// module, or if its signature is not valid
+    pub fn from_tar_gz(
+        bytes: &[u8],
+        verification: &BundleVerification,
+    ) -> Result<Self, BundleError> {
+        let mut tarball = Vec::new();
+        GzDecoder::new(bytes)
+            .take(MAX_BUNDLE_SIZE + 1)
+            .read_to_end(&mut tarball)?;
+        if tarball.len() as u64 > MAX_BUNDLE_SIZE {
+            return Err(BundleError::TooBig);
+        }
+
+        let mut files = read_tar(&tarball)?;
+
+        let signatures = files
+            .remove(SIGNATURES_FILE)
+            .ok_or(BundleError::MissingSignature)?;
+        verify(&files, &signatures, verification)?;
+
+        let manifest: Manifest = match files.get(MANIFEST_FILE) {
+            Some(manifest) => parse_json(MANIFEST_FILE, manifest)?,
+            None => Manifest::default(),
+        };
+
+        let module = manifest
+            .wasm
+            .first()
+            .map_or(DEFAULT_WASM_MODULE, |wasm| normalize(&wasm.module))
+            .to_owned();
+        let wasm = files
+            .remove(&module)
+            .ok_or(BundleError::MissingModule { name: module })?;
+
+        // Data files are merged at the path of their directory
+        let mut data = serde_json::Value::Object(serde_json::Map::new());
+        for (name, content) in &files {
+            let Some(dir) = name.strip_suffix(DATA_FILE) else {
+                continue;
+            };
+            if !dir.is_empty() && !dir.ends_with('/') {
+                continue;
+            }
+
+            let mut value = parse_json(name, content)?;
+            for segment in dir.rsplit('/').filter(|segment| !segment.is_empty()) {
+                value = serde_json::json!({ segment: value });
+            }
+            merge(&mut data, value);
+        }
+
+        Ok(Self {
+            wasm,
+            data,
+            revision: manifest.revision,
+        })
+    }
+
+    /// The compiled policy
+    #[must_use]
+    pub fn wasm(&self) -> &[u8] {
+        &self.wasm
+    }
+
+    /// The data of the policy, from the `data.json` files of the bundle
+    #[must_use]
+    pub fn data(&self) -> &serde_json::Value {
+        &self.data
+    }
+
+    /// The revision of the bundle, as set in its manifest
+    #[must_use]
+    pub fn revision(&self) -> Option<&str> {
+        self.revision.as_deref()
+    }
+}
+
+/// Check that all the files of the bundle are signed, and that the signature
+/// is valid
+fn verify(
+    files: &BTreeMap<String, Vec<u8>>,
+    signatures: &[u8],
+    verification: &BundleVerification,
+) -> Result<(), BundleError> {
+    let signatures: Signatures = parse_json(SIGNATURES_FILE, signatures)?;
+
+    // OPA only supports bundles with a single signature
+    let [signature] = &signatures.signatures[..] else {
+        return Err(BundleError::InvalidSignature);
+    };
+
+    let jwt: Jwt<'_, SignedFiles> = signature
+        .as_str()
+        .try_into()
+        .map_err(|_| BundleError::InvalidSignature)?;
+
+    match &verification.key {
+        BundleVerificationKey::Jwks(jwks) => jwt.verify_with_jwks(jwks),
+        BundleVerificationKey::SharedSecret(secret) => {
+            jwt.verify_with_shared_secret(secret.clone())
+        }
+    }
+    .map_err(|_| BundleError::InvalidSignature)?;
+
+    let signed = jwt.payload();
+
+    if let Some(scope) = &verification.scope {
+        if signed.scope.as_ref() != Some(scope) {
+            return Err(BundleError::ScopeMismatch {
+                scope: signed.scope.clone(),
+            });
+        }
+    }
+
+    let hashes: BTreeMap<String, &SignedFile> = signed
+        .files
+        .iter()
+        .map(|file| (normalize(&file.name).to_owned(), file))
+        .collect();
+
+    for (name, content) in files {
+        let signed = hashes
+            .get(name)
+            .ok_or_else(|| BundleError::UnsignedFile { name: name.clone() })?;
+
+        if !signed.algorithm.eq_ignore_ascii_case("SHA-256") || hash(name, content)? != signed.hash
+        {
+            return Err(BundleError::HashMismatch { name: name.clone() });
+        }
+    }
+
+    if let Some(name) = hashes.keys().find(|name| !files.contains_key(*name)) {
+        return Err(BundleError::MissingFile { name: name.clone() });
+    }
+
+    Ok(())
+}
+
+/// Hash a file of the bundle the way OPA does
+///
+/// JSON files are hashed in their compact form with sorted keys, so that they
+/// can be reformatted without breaking the signature.
+fn hash(name: &str, content: &[u8]) -> Result<String, BundleError> {
+    let is_json = name == MANIFEST_FILE
+        || name
+            .rsplit_once('.')
+            .is_some_and(|(_, extension)| extension.eq_ignore_ascii_case("json"));
+    let digest = if is_json {
+        let mut value: serde_json::Value = parse_json(name, content)?;
+        sort_keys(&mut value);
+        Sha256::digest(value.to_string())
+    } else {
+        Sha256::digest(content)
+    };
+
+    Ok(format!("{digest:x}"))
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(
+    name: &str,
+    content: &[u8],
+) -> Result<T, BundleError> {
+    serde_json::from_slice(content).map_err(|source| BundleError::InvalidJson {
+        name: name.to_owned(),
+        source,
+    })
+}
+
+/// Sort the keys of the objects in the given value
+fn sort_keys(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = std::mem::take(map).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (_, value) in &mut entries {
+                sort_keys(value);
+            }
+            map.extend(entries);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
+
+/// Merge the `source` value in the `target` one, recursively for objects
+fn merge(target: &mut serde_json::Value, source: serde_json::Value) {
+    match (target, source) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(source)) => {
+            for (key, value) in source {
+                merge(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (target, source) => *target = source,
+    }
+}
+
+/// Remove the leading `/` or `./` of a path in the bundle
+fn normalize(name: &str) -> &str {
+    name.trim_start_matches("./").trim_start_matches('/')
+}
+
+/// Read the regular files of a tarball, by path
+///
+/// Bundles are built with `opa build`, which writes POSIX tarballs, so only
+/// the `ustar` format and PAX path records are supported.
+fn read_tar(mut tarball: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, BundleError> {
+    const BLOCK: usize = 512;
+
+    let mut files = BTreeMap::new();
+    let mut long_name: Option<String> = None;
+
+    while tarball.len() >= BLOCK {
+        let (header, rest) = tarball.split_at(BLOCK);
+
+        // The archive ends with empty blocks
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+
+        let size = octal(&header[124..136]).ok_or(BundleError::InvalidArchive)?;
+        let padded = size.div_ceil(BLOCK) * BLOCK;
+        if rest.len() < padded {
+            return Err(BundleError::InvalidArchive);
+        }
+        let content = &rest[..size];
+        tarball = &rest[padded..];
+
+        let name = if let Some(name) = long_name.take() {
+            name
+        } else {
+            let name = string(&header[0..100])?;
+            let prefix = if &header[257..262] == b"ustar" {
+                string(&header[345..500])?
+            } else {
+                ""
+            };
+            if prefix.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{prefix}/{name}")
+            }
+        };
+
+        match header[156] {
+            b'0' | 0 => {
+                files.insert(normalize(&name).to_owned(), content.to_vec());
+            }
+
+            // PAX extended header, with records like `30 path=some/long/name\n`
+            b'x' => {
+                let records =
+                    std::str::from_utf8(content).map_err(|_| BundleError::InvalidArchive)?;
+                long_name = records
+                    .lines()
+                    .filter_map(|record| record.split_once(' ')?.1.strip_prefix("path="))
+                    .next_back()
+                    .map(ToOwned::to_owned);
+            }
+
+            // Directories, links and global headers are ignored
+            _ => {}
+        }
+    }
+
+    Ok(files)
+}
+
+/// Parse a NUL-terminated string field of a tar header
+fn string(field: &[u8]) -> Result<&str, BundleError> {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).map_err(|_| BundleError::InvalidArchive)
+}
+
+/// Parse an octal number field of a tar header
+fn octal(field: &[u8]) -> Option<usize> {
+    let field = string(field)
+        .ok()?
+        .trim_matches(|c: char| c == ' ' || c == '\0');
+    usize::from_str_radix(field, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{jwa::SymmetricKey, jwt::JsonWebSignatureHeader};
+
+    use super::*;
+
+    const SECRET: &[u8] = b"a very secret key used for the tests";
+
+    /// Build a tarball in the `ustar` format
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tarball = Vec::new();
+        for (name, content) in files {
+            let mut header = [0; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+            header[156] = b'0';
+            header[257..263].copy_from_slice(b"ustar\0");
+            tarball.extend_from_slice(&header);
+            tarball.extend_from_slice(content);
+            tarball.resize(tarball.len().div_ceil(512) * 512, 0);
+        }
+        tarball.resize(tarball.len() + 1024, 0);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tarball).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Sign the given files
+    fn sign(files: &[(&str, &[u8])], scope: Option<&str>) -> Vec<u8> {
+        let files: Vec<_> = files
+            .iter()
+            .map(|(name, content)| {
+                serde_json::json!({
+                    "name": name,
+                    "hash": hash(normalize(name), content).unwrap(),
+                    "algorithm": "SHA-256",
+                })
+            })
+            .collect();
+        let payload = serde_json::json!({ "files": files, "scope": scope });
+
+        let key = SymmetricKey::new_for_alg(SECRET.to_vec(), &JsonWebSignatureAlg::Hs256).unwrap();
+        let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Hs256);
+        let jwt = Jwt::sign(header, payload, &key).unwrap();
+
+        serde_json::to_vec(&serde_json::json!({ "signatures": [jwt.as_str()] })).unwrap()
+    }
+
+    fn verification() -> BundleVerification {
+        BundleVerification::new(BundleVerificationKey::SharedSecret(SECRET.to_vec()))
+    }
+
+    #[test]
+    fn test_bundle() {
+        let files: &[(&str, &[u8])] = &[
+            ("/.manifest", br#"{"revision": "42", "roots": [""]}"#),
+            ("/data.json", br#"{"banned_domains": ["example.com"]}"#),
+            (
+                "/client_registration/data.json",
+                br#"{"allow_insecure_uris": true}"#,
+            ),
+            ("/policy.wasm", b"\0asm"),
+        ];
+        let signatures = sign(files, None);
+
+        let mut with_signatures = files.to_vec();
+        with_signatures.push(("/.signatures.json", &signatures));
+        let bundle = Bundle::from_tar_gz(&tar(&with_signatures), &verification()).unwrap();
+
+        assert_eq!(bundle.wasm(), b"\0asm");
+        assert_eq!(bundle.revision(), Some("42"));
+        assert_eq!(
+            bundle.data(),
+            &serde_json::json!({
+                "banned_domains": ["example.com"],
+                "client_registration": { "allow_insecure_uris": true },
+            })
+        );
+
+        // The bundle is rejected if it was signed for another scope
+        let err = Bundle::from_tar_gz(
+            &tar(&with_signatures),
+            &verification().with_scope("production".to_owned()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, BundleError::ScopeMismatch { .. }));
+
+        // Or with another key
+        let err = Bundle::from_tar_gz(
+            &tar(&with_signatures),
+            &BundleVerification::new(BundleVerificationKey::SharedSecret(b"another key".to_vec())),
+        )
+        .unwrap_err();
+        assert!(matches!(err, BundleError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_tampered_bundle() {
+        let files: &[(&str, &[u8])] = &[
+            ("/data.json", br#"{"banned_domains": ["example.com"]}"#),
+            ("/policy.wasm", b"\0asm"),
+        ];
+        let signatures = sign(files, None);
+
+        // The data was changed after signing the bundle
+        let tampered: &[(&str, &[u8])] = &[
+            ("/data.json", br#"{"banned_domains": []}"#),
+            ("/policy.wasm", b"\0asm"),
+            ("/.signatures.json", &signatures),
+        ];
+        let err = Bundle::from_tar_gz(&tar(tampered), &verification()).unwrap_err();
+        assert!(matches!(err, BundleError::HashMismatch { .. }));
+
+        // A file was added
+        let added: &[(&str, &[u8])] = &[
+            ("/data.json", br#"{"banned_domains": ["example.com"]}"#),
+            ("/extra/data.json", b"{}"),
+            ("/policy.wasm", b"\0asm"),
+            ("/.signatures.json", &signatures),
+        ];
+        let err = Bundle::from_tar_gz(&tar(added), &verification()).unwrap_err();
+        assert!(matches!(err, BundleError::UnsignedFile { .. }));
+
+        // The signature is missing
+        let unsigned: &[(&str, &[u8])] = &[
+            ("/data.json", br#"{"banned_domains": ["example.com"]}"#),
+            ("/policy.wasm", b"\0asm"),
+        ];
+        let err = Bundle::from_tar_gz(&tar(unsigned), &verification()).unwrap_err();
+        assert!(matches!(err, BundleError::MissingSignature));
+    }
+
+    #[test]
+    fn test_hash_json() {
+        // JSON files are hashed in their canonical form
+        assert_eq!(
+            hash("data.json", br#"{ "b": 1,  "a": [1, 2] }"#).unwrap(),
+            hash("data.json", br#"{"a":[1,2],"b":1}"#).unwrap(),
+        );
+        assert_ne!(
+            hash("policy.wasm", b"{ }").unwrap(),
+            hash("policy.wasm", b"{}").unwrap(),
+        );
+    }
+}
@@ -4,9 +4,15 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+pub mod bundle;
 pub mod model;
 
-use mas_data_model::{AuthorizationGrant, Client, DeviceCodeGrant, User};
+use std::{borrow::Cow, collections::BTreeMap, sync::Arc};
+
+use arc_swap::ArcSwap;
+use mas_data_model::{
+    AuthorizationGrant, CibaGrant, Client, Device, DeviceCodeGrant, UpstreamOAuthProvider, User,
+};
 use oauth2_types::{registration::VerifiedClientMetadata, scope::Scope};
 use opa_wasm::{
     wasmtime::{Config, Engine, Module, OptLevel, Store},
@@ -15,8 +21,11 @@ use opa_wasm::{
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use self::model::{AuthorizationGrantInput, ClientRegistrationInput, EmailInput, RegisterInput};
-pub use self::model::{EvaluationResult, Violation};
+use self::model::{
+    Action, AuthorizationGrantInput, ClientRegistrationInput, Context, EmailInput,
+    ImpersonationInput, KioskInput, RegisterInput,
+};
+pub use self::model::{EvaluationResult, Requester, Violation};
 use crate::model::GrantType;
 
 #[derive(Debug, Error)]
@@ -56,30 +65,46 @@ pub struct Entrypoints {
     pub client_registration: String,
     pub authorization_grant: String,
     pub email: String,
+    pub impersonation: String,
+    pub kiosk: String,
 }
 
 impl Entrypoints {
-    fn all(&self) -> [&str; 4] {
+    fn all(&self) -> [&str; 6] {
         [
             self.register.as_str(),
             self.client_registration.as_str(),
             self.authorization_grant.as_str(),
             self.email.as_str(),
+            self.impersonation.as_str(),
+            self.kiosk.as_str(),
         ]
     }
 }
 
-pub struct PolicyFactory {
-    engine: Engine,
+/// The compiled policy module, along with the data and entrypoints it is
+/// evaluated with
+struct LoadedPolicy {
     module: Module,
     data: serde_json::Value,
     entrypoints: Entrypoints,
 }
 
+/// A policy compiled and checked ahead of replacing the current one
+///
+/// See [`PolicyFactory::prepare_reload`].
+pub struct PreparedPolicy(LoadedPolicy);
+
+pub struct PolicyFactory {
+    engine: Engine,
+    loaded: ArcSwap<LoadedPolicy>,
+    request_headers: ArcSwap<Vec<String>>,
+}
+
 impl PolicyFactory {
     #[tracing::instrument(name = "policy.load", skip(source), err)]
     pub async fn load(
-        mut source: impl AsyncRead + std::marker::Unpin,
+        source: impl AsyncRead + std::marker::Unpin,
         data: serde_json::Value,
         entrypoints: Entrypoints,
     ) -> Result<Self, LoadError> {
@@ -89,44 +114,131 @@ impl PolicyFactory {
 
         let engine = Engine::new(&config).map_err(LoadError::Engine)?;
 
+        let loaded = Self::compile(&engine, source, data, entrypoints).await?;
+
+        Ok(Self {
+            engine,
+            loaded: ArcSwap::from_pointee(loaded),
+            request_headers: ArcSwap::default(),
+        })
+    }
+
+    /// Set the names of the request headers which are passed to the policies
+    pub fn set_request_headers(&self, request_headers: Vec<String>) {
+        let request_headers = request_headers
+            .into_iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        self.request_headers.store(Arc::new(request_headers));
+    }
+
+    /// The names of the request headers which are passed to the policies, in
+    /// lowercase
+    #[must_use]
+    pub fn request_headers(&self) -> Arc<Vec<String>> {
+        self.request_headers.load_full()
+    }
+
+    /// Replace the policy with a new one
+    ///
+    /// The new policy is compiled and instantiated before replacing the
+    /// current one, so that the current policy is kept if the new one is not
+    /// valid. Policies instantiated before the reload keep using the previous
+    /// policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the new policy could not be read, compiled or
+    /// instantiated
+    #[tracing::instrument(name = "policy.reload", skip_all, err)]
+    pub async fn reload(
+        &self,
+        source: impl AsyncRead + std::marker::Unpin,
+        data: serde_json::Value,
+        entrypoints: Entrypoints,
+    ) -> Result<(), LoadError> {
+        let policy = self.prepare_reload(source, data, entrypoints).await?;
+        self.apply_reload(policy);
+        Ok(())
+    }
+
+    /// Compile and instantiate a new policy, without replacing the current
+    /// one yet
+    ///
+    /// This lets callers check that other parts of a reload succeed before
+    /// applying it with [`Self::apply_reload`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the new policy could not be read, compiled or
+    /// instantiated
+    pub async fn prepare_reload(
+        &self,
+        source: impl AsyncRead + std::marker::Unpin,
+        data: serde_json::Value,
+        entrypoints: Entrypoints,
+    ) -> Result<PreparedPolicy, LoadError> {
+        let loaded = Self::compile(&self.engine, source, data, entrypoints).await?;
+        Ok(PreparedPolicy(loaded))
+    }
+
+    /// Replace the current policy with one prepared by
+    /// [`Self::prepare_reload`]
+    pub fn apply_reload(&self, policy: PreparedPolicy) {
+        self.loaded.store(Arc::new(policy.0));
+    }
+
+    /// Compile a policy module, and check that it can be instantiated
+    async fn compile(
+        engine: &Engine,
+        mut source: impl AsyncRead + std::marker::Unpin,
+        data: serde_json::Value,
+        entrypoints: Entrypoints,
+    ) -> Result<LoadedPolicy, LoadError> {
         // Read and compile the module
         let mut buf = Vec::new();
         source.read_to_end(&mut buf).await?;
         // Compilation is CPU-bound, so spawn that in a blocking task
-        let (engine, module) = tokio::task::spawn_blocking(move || {
-            let module = Module::new(&engine, buf)?;
-            anyhow::Ok((engine, module))
-        })
+        let module = {
+            let engine = engine.clone();
+            tokio::task::spawn_blocking(move || Module::new(&engine, buf))
+        }
         .await?
         .map_err(LoadError::Compilation)?;
 
-        let factory = Self {
-            engine,
+        let loaded = LoadedPolicy {
             module,
             data,
             entrypoints,
         };
 
         // Try to instantiate
-        factory
-            .instantiate()
+        Self::instantiate_loaded(engine, &loaded)
             .await
             .map_err(LoadError::Instantiate)?;
 
-        Ok(factory)
+        Ok(loaded)
     }
 
     #[tracing::instrument(name = "policy.instantiate", skip_all, err)]
     pub async fn instantiate(&self) -> Result<Policy, InstantiateError> {
-        let mut store = Store::new(&self.engine, ());
-        let runtime = Runtime::new(&mut store, &self.module)
+        let loaded = self.loaded.load_full();
+        Self::instantiate_loaded(&self.engine, &loaded).await
+    }
+
+    async fn instantiate_loaded(
+        engine: &Engine,
+        loaded: &LoadedPolicy,
+    ) -> Result<Policy, InstantiateError> {
+        let mut store = Store::new(engine, ());
+        let runtime = Runtime::new(&mut store, &loaded.module)
             .await
             .map_err(InstantiateError::Runtime)?;
 
         // Check that we have the required entrypoints
         let policy_entrypoints = runtime.entrypoints();
 
-        for e in self.entrypoints.all() {
+        for e in loaded.entrypoints.all() {
             if !policy_entrypoints.contains(e) {
                 return Err(InstantiateError::MissingEntrypoint {
                     entrypoint: e.to_owned(),
@@ -135,14 +247,15 @@ impl PolicyFactory {
         }
 
         let instance = runtime
-            .with_data(&mut store, &self.data)
+            .with_data(&mut store, &loaded.data)
             .await
             .map_err(InstantiateError::LoadData)?;
 
         Ok(Policy {
             store,
             instance,
-            entrypoints: self.entrypoints.clone(),
+            entrypoints: loaded.entrypoints.clone(),
+            request_headers: BTreeMap::new(),
         })
     }
 }
@@ -151,6 +264,7 @@ pub struct Policy {
     store: Store<()>,
     instance: opa_wasm::Policy<opa_wasm::DefaultContext>,
     entrypoints: Entrypoints,
+    request_headers: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Error)]
@@ -161,6 +275,32 @@ pub enum EvaluationError {
 }
 
 impl Policy {
+    /// Set the request headers which are added to the requester passed to the
+    /// policies
+    #[must_use]
+    pub fn with_request_headers(
+        mut self,
+        headers: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        self.request_headers.extend(headers);
+        self
+    }
+
+    /// Add the request headers to the given requester
+    fn requester<'a>(&self, requester: &'a Requester) -> Cow<'a, Requester> {
+        if self.request_headers.is_empty() {
+            return Cow::Borrowed(requester);
+        }
+
+        let mut requester = requester.clone();
+        requester.headers.extend(
+            self.request_headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        Cow::Owned(requester)
+    }
+
     #[tracing::instrument(
         name = "policy.evaluate_email",
         skip_all,
@@ -172,8 +312,19 @@ impl Policy {
     pub async fn evaluate_email(
         &mut self,
         email: &str,
+        actor: Option<&User>,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
-        let input = EmailInput { email };
+        let requester = self.requester(requester);
+        let input = EmailInput {
+            email,
+            context: Context {
+                action: Action::AddEmail,
+                actor,
+                client: None,
+                requester: &requester,
+            },
+        };
 
         let [res]: [EvaluationResult; 1] = self
             .instance
@@ -197,8 +348,19 @@ impl Policy {
         &mut self,
         username: &str,
         email: &str,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
-        let input = RegisterInput::Password { username, email };
+        let requester = self.requester(requester);
+        let input = RegisterInput::Password {
+            username,
+            email,
+            context: Context {
+                action: Action::Register,
+                actor: None,
+                client: None,
+                requester: &requester,
+            },
+        };
 
         let [res]: [EvaluationResult; 1] = self
             .instance
@@ -212,9 +374,10 @@ impl Policy {
         name = "policy.evaluate.upstream_oauth_register",
         skip_all,
         fields(
-            input.registration_method = "password",
+            input.registration_method = "upstream-oauth2",
             input.user.username = username,
             input.user.email = email,
+            input.upstream_provider.id = %provider.id,
         ),
         err,
     )]
@@ -222,8 +385,21 @@ impl Policy {
         &mut self,
         username: &str,
         email: Option<&str>,
+        provider: &UpstreamOAuthProvider,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
-        let input = RegisterInput::UpstreamOAuth2 { username, email };
+        let requester = self.requester(requester);
+        let input = RegisterInput::UpstreamOAuth2 {
+            username,
+            email,
+            upstream_provider: provider.into(),
+            context: Context {
+                action: Action::Register,
+                actor: None,
+                client: None,
+                requester: &requester,
+            },
+        };
 
         let [res]: [EvaluationResult; 1] = self
             .instance
@@ -233,12 +409,55 @@ impl Policy {
         Ok(res)
     }
 
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(
+        name = "policy.evaluate.guest_register",
+        skip_all,
+        fields(
+            input.registration_method = "guest",
+            input.user.username = username,
+        ),
+        err,
+    )]
+    pub async fn evaluate_guest_register(
+        &mut self,
+        username: &str,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
+        let input = RegisterInput::Guest {
+            username,
+            context: Context {
+                action: Action::Register,
+                actor: None,
+                client: None,
+                requester: &requester,
+            },
+        };
+
+        let [res]: [EvaluationResult; 1] = self
+            .instance
+            .evaluate(&mut self.store, &self.entrypoints.register, &input)
+            .await?;
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(skip(self, requester))]
     pub async fn evaluate_client_registration(
         &mut self,
         client_metadata: &VerifiedClientMetadata,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
-        let input = ClientRegistrationInput { client_metadata };
+        let requester = self.requester(requester);
+        let input = ClientRegistrationInput {
+            client_metadata,
+            context: Context {
+                action: Action::ClientRegistration,
+                actor: None,
+                client: None,
+                requester: &requester,
+            },
+        };
 
         let [res]: [EvaluationResult; 1] = self
             .instance
@@ -260,6 +479,7 @@ impl Policy {
             input.scope = %authorization_grant.scope,
             input.client.id = %client.id,
             input.user.id = %user.id,
+            input.authorized_clients = authorized_clients,
         ),
         err,
     )]
@@ -268,12 +488,23 @@ impl Policy {
         authorization_grant: &AuthorizationGrant,
         client: &Client,
         user: &User,
+        authorized_clients: usize,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
         let input = AuthorizationGrantInput {
             user: Some(user),
             client,
             scope: &authorization_grant.scope,
             grant_type: GrantType::AuthorizationCode,
+            assertion_issuer: None,
+            authorized_clients: Some(authorized_clients),
+            context: Context {
+                action: Action::AuthorizationGrant,
+                actor: Some(user),
+                client: Some(client),
+                requester: &requester,
+            },
         };
 
         let [res]: [EvaluationResult; 1] = self
@@ -301,12 +532,22 @@ impl Policy {
         &mut self,
         scope: &Scope,
         client: &Client,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
         let input = AuthorizationGrantInput {
             user: None,
             client,
             scope,
             grant_type: GrantType::ClientCredentials,
+            assertion_issuer: None,
+            authorized_clients: None,
+            context: Context {
+                action: Action::AuthorizationGrant,
+                actor: None,
+                client: Some(client),
+                requester: &requester,
+            },
         };
 
         let [res]: [EvaluationResult; 1] = self
@@ -337,12 +578,115 @@ impl Policy {
         device_code_grant: &DeviceCodeGrant,
         client: &Client,
         user: &User,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
         let input = AuthorizationGrantInput {
             user: Some(user),
             client,
             scope: &device_code_grant.scope,
             grant_type: GrantType::DeviceCode,
+            assertion_issuer: None,
+            authorized_clients: None,
+            context: Context {
+                action: Action::AuthorizationGrant,
+                actor: Some(user),
+                client: Some(client),
+                requester: &requester,
+            },
+        };
+
+        let [res]: [EvaluationResult; 1] = self
+            .instance
+            .evaluate(
+                &mut self.store,
+                &self.entrypoints.authorization_grant,
+                &input,
+            )
+            .await?;
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.ciba_grant",
+        skip_all,
+        fields(
+            input.ciba_grant.id = %ciba_grant.id,
+            input.scope = %ciba_grant.scope,
+            input.client.id = %client.id,
+            input.user.id = %user.id,
+        ),
+        err,
+    )]
+    pub async fn evaluate_ciba_grant(
+        &mut self,
+        ciba_grant: &CibaGrant,
+        client: &Client,
+        user: &User,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
+        let input = AuthorizationGrantInput {
+            user: Some(user),
+            client,
+            scope: &ciba_grant.scope,
+            grant_type: GrantType::Ciba,
+            assertion_issuer: None,
+            authorized_clients: None,
+            context: Context {
+                action: Action::AuthorizationGrant,
+                actor: Some(user),
+                client: Some(client),
+                requester: &requester,
+            },
+        };
+
+        let [res]: [EvaluationResult; 1] = self
+            .instance
+            .evaluate(
+                &mut self.store,
+                &self.entrypoints.authorization_grant,
+                &input,
+            )
+            .await?;
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.jwt_bearer_grant",
+        skip_all,
+        fields(
+            input.scope = %scope,
+            input.client.id = %client.id,
+            input.user.id = %user.id,
+            input.assertion_issuer = assertion_issuer,
+        ),
+        err,
+    )]
+    pub async fn evaluate_jwt_bearer_grant(
+        &mut self,
+        scope: &Scope,
+        client: &Client,
+        user: &User,
+        assertion_issuer: &str,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
+        let input = AuthorizationGrantInput {
+            user: Some(user),
+            client,
+            scope,
+            grant_type: GrantType::JwtBearer,
+            assertion_issuer: Some(assertion_issuer),
+            authorized_clients: None,
+            context: Context {
+                action: Action::AuthorizationGrant,
+                actor: Some(user),
+                client: Some(client),
+                requester: &requester,
+            },
         };
 
         let [res]: [EvaluationResult; 1] = self
@@ -356,6 +700,76 @@ impl Policy {
 
         Ok(res)
     }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.impersonation",
+        skip_all,
+        fields(
+            input.user.id = %user.id,
+            input.actor.id = %actor.id,
+        ),
+        err,
+    )]
+    pub async fn evaluate_impersonation(
+        &mut self,
+        user: &User,
+        actor: &User,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
+        let input = ImpersonationInput {
+            user,
+            context: Context {
+                action: Action::Impersonate,
+                actor: Some(actor),
+                client: None,
+                requester: &requester,
+            },
+        };
+
+        let [res]: [EvaluationResult; 1] = self
+            .instance
+            .evaluate(&mut self.store, &self.entrypoints.impersonation, &input)
+            .await?;
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.kiosk",
+        skip_all,
+        fields(
+            input.device_id = device.as_str(),
+            input.client.id = %client.id,
+        ),
+        err,
+    )]
+    pub async fn evaluate_kiosk(
+        &mut self,
+        device: &Device,
+        client: &Client,
+        user: Option<&User>,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let requester = self.requester(requester);
+        let input = KioskInput {
+            device_id: device.as_str(),
+            user,
+            context: Context {
+                action: Action::Kiosk,
+                actor: user,
+                client: Some(client),
+                requester: &requester,
+            },
+        };
+
+        let [res]: [EvaluationResult; 1] = self
+            .instance
+            .evaluate(&mut self.store, &self.entrypoints.kiosk, &input)
+            .await?;
+
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
@@ -383,6 +797,8 @@ mod tests {
             client_registration: "client_registration/violation".to_owned(),
             authorization_grant: "authorization_grant/violation".to_owned(),
             email: "email/violation".to_owned(),
+            impersonation: "impersonation/violation".to_owned(),
+            kiosk: "kiosk/violation".to_owned(),
         };
 
         let factory = PolicyFactory::load(file, data, entrypoints).await.unwrap();
@@ -390,21 +806,133 @@ mod tests {
         let mut policy = factory.instantiate().await.unwrap();
 
         let res = policy
-            .evaluate_register("hello", "hello@example.com")
+            .evaluate_register("hello", "hello@example.com", &Requester::default())
             .await
             .unwrap();
         assert!(!res.valid());
 
         let res = policy
-            .evaluate_register("hello", "hello@foo.element.io")
+            .evaluate_register("hello", "hello@foo.element.io", &Requester::default())
             .await
             .unwrap();
         assert!(res.valid());
 
         let res = policy
-            .evaluate_register("hello", "hello@staging.element.io")
+            .evaluate_register("hello", "hello@staging.element.io", &Requester::default())
             .await
             .unwrap();
         assert!(!res.valid());
     }
+
+    #[tokio::test]
+    async fn test_reload() {
+        #[allow(clippy::disallowed_types)]
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("..")
+            .join("policies")
+            .join("policy.wasm");
+
+        let entrypoints = Entrypoints {
+            register: "register/violation".to_owned(),
+            client_registration: "client_registration/violation".to_owned(),
+            authorization_grant: "authorization_grant/violation".to_owned(),
+            email: "email/violation".to_owned(),
+            impersonation: "impersonation/violation".to_owned(),
+            kiosk: "kiosk/violation".to_owned(),
+        };
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let factory = PolicyFactory::load(file, serde_json::json!({}), entrypoints.clone())
+            .await
+            .unwrap();
+
+        let mut before = factory.instantiate().await.unwrap();
+
+        // Reload with new data
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let data = serde_json::json!({
+            "banned_domains": ["example.com"],
+        });
+        factory
+            .reload(file, data, entrypoints.clone())
+            .await
+            .unwrap();
+
+        let mut after = factory.instantiate().await.unwrap();
+
+        // The existing instance still uses the previous policy
+        let res = before
+            .evaluate_register("hello", "hello@example.com", &Requester::default())
+            .await
+            .unwrap();
+        assert!(res.valid());
+
+        let res = after
+            .evaluate_register("hello", "hello@example.com", &Requester::default())
+            .await
+            .unwrap();
+        assert!(!res.valid());
+
+        // An invalid policy is rejected, and the current one is kept
+        let res = factory
+            .reload(
+                &b"not a WASM module"[..],
+                serde_json::json!({}),
+                entrypoints,
+            )
+            .await;
+        assert!(res.is_err());
+
+        let mut policy = factory.instantiate().await.unwrap();
+        let res = policy
+            .evaluate_register("hello", "hello@example.com", &Requester::default())
+            .await
+            .unwrap();
+        assert!(!res.valid());
+    }
+
+    #[tokio::test]
+    async fn test_request_headers() {
+        #[allow(clippy::disallowed_types)]
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("..")
+            .join("policies")
+            .join("policy.wasm");
+
+        let entrypoints = Entrypoints {
+            register: "register/violation".to_owned(),
+            client_registration: "client_registration/violation".to_owned(),
+            authorization_grant: "authorization_grant/violation".to_owned(),
+            email: "email/violation".to_owned(),
+            impersonation: "impersonation/violation".to_owned(),
+            kiosk: "kiosk/violation".to_owned(),
+        };
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let factory = PolicyFactory::load(file, serde_json::json!({}), entrypoints)
+            .await
+            .unwrap();
+
+        factory.set_request_headers(vec!["X-Country".to_owned()]);
+        assert_eq!(*factory.request_headers(), vec!["x-country".to_owned()]);
+
+        let policy = factory
+            .instantiate()
+            .await
+            .unwrap()
+            .with_request_headers([("x-country".to_owned(), "FR".to_owned())]);
+
+        let requester = policy.requester(&Requester::default());
+        assert_eq!(requester.headers["x-country"], "FR");
+
+        // The policy still evaluates with the extra input
+        let mut policy = policy;
+        let res = policy
+            .evaluate_register("hello", "hello@example.com", &Requester::default())
+            .await
+            .unwrap();
+        assert!(res.valid());
+    }
 }
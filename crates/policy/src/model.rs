@@ -8,8 +8,15 @@
 //!
 //! This is useful to generate JSON schemas for each input type, which can then
 //! be type-checked by Open Policy Agent.
+//!
+//! Every input carries a [`Context`] in its `context` field, with the same
+//! shape across all entrypoints: the action being evaluated, who performs it,
+//! through which client, from where, and how they authenticated. The other
+//! fields of each input describe the resource the action is about.
+
+use std::{collections::BTreeMap, net::IpAddr};
 
-use mas_data_model::{Client, User};
+use mas_data_model::{Authentication, AuthenticationMethod, Client, UpstreamOAuthProvider, User};
 use oauth2_types::{registration::VerifiedClientMetadata, scope::Scope};
 use serde::{Deserialize, Serialize};
 
@@ -52,13 +59,131 @@ impl EvaluationResult {
     }
 }
 
+/// The action being evaluated by a policy.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub enum Action {
+    /// Registering a new user, evaluated by the register policy
+    Register,
+
+    /// Registering a new OAuth 2.0 client, evaluated by the client
+    /// registration policy
+    ClientRegistration,
+
+    /// Granting a scope to a client, evaluated by the authorization grant
+    /// policy
+    AuthorizationGrant,
+
+    /// Adding an email address to a user, evaluated by the email policy
+    AddEmail,
+
+    /// Starting a browser session as another user, evaluated by the
+    /// impersonation policy
+    Impersonate,
+
+    /// Registering a kiosk device, or logging a user in on one, evaluated by
+    /// the kiosk policy
+    Kiosk,
+}
+
+/// Information about the entity making the request.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct Requester {
+    /// The IP address of the requester, if known
+    pub ip_address: Option<IpAddr>,
+
+    /// The user agent of the requester, if known
+    pub user_agent: Option<String>,
+
+    /// The authentication methods used by the requester in their current
+    /// session, as Authentication Method Reference values (RFC 8176)
+    pub amr: Vec<String>,
+
+    /// The request headers listed in the `policy.request_headers` setting, by
+    /// lowercase name. Reverse proxies can use them to pass information like
+    /// the country or the autonomous system of the requester.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Requester {
+    /// Create a new [`Requester`] from its IP address and user agent.
+    #[must_use]
+    pub fn new(ip_address: Option<IpAddr>, user_agent: Option<String>) -> Self {
+        Self {
+            ip_address,
+            user_agent,
+            amr: Vec::new(),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Record the authentication the requester last did in their session.
+    ///
+    /// Password authentications are reported as `pwd`, and authentications
+    /// through an upstream provider as `fed`.
+    #[must_use]
+    pub fn with_authentication(mut self, authentication: Option<&Authentication>) -> Self {
+        let amr =
+            authentication.and_then(
+                |authentication| match authentication.authentication_method {
+                    AuthenticationMethod::Password { .. } => Some("pwd"),
+                    AuthenticationMethod::UpstreamOAuth2 { .. } => Some("fed"),
+                    AuthenticationMethod::Unknown => None,
+                },
+            );
+        self.amr.extend(amr.map(ToOwned::to_owned));
+        self
+    }
+
+    /// Record an authentication method used by the requester, as an
+    /// Authentication Method Reference value (RFC 8176).
+    #[must_use]
+    pub fn with_amr(mut self, amr: &str) -> Self {
+        self.amr.push(amr.to_owned());
+        self
+    }
+}
+
+/// The context of a policy evaluation, common to all policy inputs.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct Context<'a> {
+    /// The action being evaluated
+    pub action: Action,
+
+    /// The user performing the action, if any
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "Option<std::collections::HashMap<String, serde_json::Value>>")
+    )]
+    pub actor: Option<&'a User>,
+
+    /// The client through which the action is performed, if any
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "Option<std::collections::HashMap<String, serde_json::Value>>")
+    )]
+    pub client: Option<&'a Client>,
+
+    /// The entity making the request
+    pub requester: &'a Requester,
+}
+
 /// Input for the user registration policy.
 #[derive(Serialize, Debug)]
 #[serde(tag = "registration_method")]
 #[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
 pub enum RegisterInput<'a> {
     #[serde(rename = "password")]
-    Password { username: &'a str, email: &'a str },
+    Password {
+        username: &'a str,
+        email: &'a str,
+        context: Context<'a>,
+    },
 
     #[serde(rename = "upstream-oauth2")]
     UpstreamOAuth2 {
@@ -66,9 +191,44 @@ pub enum RegisterInput<'a> {
 
         #[serde(skip_serializing_if = "Option::is_none")]
         email: Option<&'a str>,
+
+        /// The upstream provider through which the user registers
+        upstream_provider: UpstreamProviderInput<'a>,
+
+        context: Context<'a>,
+    },
+
+    /// A guest account, registered without any credentials
+    #[serde(rename = "guest")]
+    Guest {
+        /// The generated username of the guest
+        username: &'a str,
+
+        context: Context<'a>,
     },
 }
 
+/// The upstream provider through which a user registers
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct UpstreamProviderInput<'a> {
+    /// The ID of the provider
+    pub id: String,
+
+    /// The issuer of the provider
+    pub issuer: &'a str,
+}
+
+impl<'a> From<&'a UpstreamOAuthProvider> for UpstreamProviderInput<'a> {
+    fn from(provider: &'a UpstreamOAuthProvider) -> Self {
+        Self {
+            id: provider.id.to_string(),
+            issuer: &provider.issuer,
+        }
+    }
+}
+
 /// Input for the client registration policy.
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -79,6 +239,8 @@ pub struct ClientRegistrationInput<'a> {
         schemars(with = "std::collections::HashMap<String, serde_json::Value>")
     )]
     pub client_metadata: &'a VerifiedClientMetadata,
+
+    pub context: Context<'a>,
 }
 
 #[derive(Serialize, Debug)]
@@ -89,6 +251,10 @@ pub enum GrantType {
     ClientCredentials,
     #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
     DeviceCode,
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:jwt-bearer")]
+    JwtBearer,
+    #[serde(rename = "urn:openid:params:grant-type:ciba")]
+    Ciba,
 }
 
 /// Input for the authorization grant policy.
@@ -112,6 +278,15 @@ pub struct AuthorizationGrantInput<'a> {
     pub scope: &'a Scope,
 
     pub grant_type: GrantType,
+
+    /// The issuer of the assertion, for the JWT bearer grant
+    pub assertion_issuer: Option<&'a str>,
+
+    /// The number of other clients the user has currently authorized, for the
+    /// authorization code grant
+    pub authorized_clients: Option<usize>,
+
+    pub context: Context<'a>,
 }
 
 /// Input for the email add policy.
@@ -120,6 +295,47 @@ pub struct AuthorizationGrantInput<'a> {
 #[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
 pub struct EmailInput<'a> {
     pub email: &'a str,
+
+    pub context: Context<'a>,
+}
+
+/// Input for the impersonation policy.
+///
+/// The administrator who wants to impersonate the user is the actor in the
+/// context.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct ImpersonationInput<'a> {
+    /// The user to impersonate
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "std::collections::HashMap<String, serde_json::Value>")
+    )]
+    pub user: &'a User,
+
+    pub context: Context<'a>,
+}
+
+/// Input for the kiosk policy.
+///
+/// The kiosk client is the client in the context.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct KioskInput<'a> {
+    /// The Matrix device ID of the kiosk device
+    pub device_id: &'a str,
+
+    /// The user logging in on the kiosk device, absent when the device is
+    /// being registered
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "Option<std::collections::HashMap<String, serde_json::Value>>")
+    )]
+    pub user: Option<&'a User>,
+
+    pub context: Context<'a>,
 }
 
 /// Input for the password set policy.
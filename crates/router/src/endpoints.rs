@@ -19,6 +19,9 @@ pub enum PostAuthAction {
     ContinueDeviceCodeGrant {
         id: Ulid,
     },
+    ContinueCibaGrant {
+        id: Ulid,
+    },
     ContinueCompatSsoLogin {
         id: Ulid,
     },
@@ -30,6 +33,9 @@ pub enum PostAuthAction {
         #[serde(flatten)]
         action: Option<AccountAction>,
     },
+    InitiateClientLogin {
+        id: Ulid,
+    },
 }
 
 impl PostAuthAction {
@@ -43,6 +49,11 @@ impl PostAuthAction {
         PostAuthAction::ContinueDeviceCodeGrant { id }
     }
 
+    #[must_use]
+    pub const fn continue_ciba_grant(id: Ulid) -> Self {
+        PostAuthAction::ContinueCibaGrant { id }
+    }
+
     #[must_use]
     pub const fn continue_compat_sso_login(id: Ulid) -> Self {
         PostAuthAction::ContinueCompatSsoLogin { id }
@@ -58,6 +69,11 @@ impl PostAuthAction {
         PostAuthAction::ManageAccount { action }
     }
 
+    #[must_use]
+    pub const fn initiate_client_login(id: Ulid) -> Self {
+        PostAuthAction::InitiateClientLogin { id }
+    }
+
     pub fn go_next(&self, url_builder: &UrlBuilder) -> axum::response::Redirect {
         match self {
             Self::ContinueAuthorizationGrant { id } => {
@@ -66,6 +82,7 @@ impl PostAuthAction {
             Self::ContinueDeviceCodeGrant { id } => {
                 url_builder.redirect(&DeviceCodeConsent::new(*id))
             }
+            Self::ContinueCibaGrant { id } => url_builder.redirect(&CibaConsent::new(*id)),
             Self::ContinueCompatSsoLogin { id } => {
                 url_builder.redirect(&CompatLoginSsoComplete::new(*id, None))
             }
@@ -74,6 +91,9 @@ impl PostAuthAction {
             Self::ManageAccount { action } => url_builder.redirect(&Account {
                 action: action.clone(),
             }),
+            Self::InitiateClientLogin { id } => {
+                url_builder.redirect(&ClientInitiateLogin::new(*id))
+            }
         }
     }
 }
@@ -149,6 +169,56 @@ impl SimpleRoute for OAuth2RegistrationEndpoint {
     const PATH: &'static str = "/oauth2/registration";
 }
 
+/// `GET|PUT|DELETE /oauth2/registration/:client_id`
+#[derive(Debug, Clone)]
+pub struct OAuth2ClientConfigurationEndpoint {
+    client_id: String,
+}
+
+impl OAuth2ClientConfigurationEndpoint {
+    #[must_use]
+    pub fn new(client_id: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+        }
+    }
+}
+
+impl Route for OAuth2ClientConfigurationEndpoint {
+    type Query = ();
+    fn route() -> &'static str {
+        "/oauth2/registration/:client_id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/oauth2/registration/{}", self.client_id).into()
+    }
+}
+
+/// `GET /clients/:client_id/logo`
+#[derive(Debug, Clone)]
+pub struct ClientLogo {
+    id: Ulid,
+}
+
+impl ClientLogo {
+    #[must_use]
+    pub const fn new(id: Ulid) -> Self {
+        Self { id }
+    }
+}
+
+impl Route for ClientLogo {
+    type Query = ();
+    fn route() -> &'static str {
+        "/clients/:client_id/logo"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/clients/{}/logo", self.id).into()
+    }
+}
+
 /// `GET /authorize`
 #[derive(Default, Debug, Clone)]
 pub struct OAuth2AuthorizationEndpoint;
@@ -173,6 +243,15 @@ impl SimpleRoute for Healthcheck {
     const PATH: &'static str = "/health";
 }
 
+/// `POST /_matrix-internal/identity/v1/check_credentials`
+///
+/// Compatible with the `matrix-synapse-rest-password-provider` Synapse module
+pub struct PasswordProviderCheckCredentials;
+
+impl SimpleRoute for PasswordProviderCheckCredentials {
+    const PATH: &'static str = "/_matrix-internal/identity/v1/check_credentials";
+}
+
 /// `GET|POST /login`
 #[derive(Default, Debug, Clone)]
 pub struct Login {
@@ -213,6 +292,13 @@ impl Login {
         }
     }
 
+    #[must_use]
+    pub const fn and_continue_ciba_grant(id: Ulid) -> Self {
+        Self {
+            post_auth_action: Some(PostAuthAction::continue_ciba_grant(id)),
+        }
+    }
+
     #[must_use]
     pub const fn and_continue_compat_sso_login(id: Ulid) -> Self {
         Self {
@@ -315,6 +401,39 @@ impl From<Option<PostAuthAction>> for Reauth {
     }
 }
 
+/// `GET|POST /terms`
+#[derive(Default, Debug, Clone)]
+pub struct AcceptTerms {
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl AcceptTerms {
+    #[must_use]
+    pub fn and_then(action: PostAuthAction) -> Self {
+        Self {
+            post_auth_action: Some(action),
+        }
+    }
+}
+
+impl Route for AcceptTerms {
+    type Query = PostAuthAction;
+
+    fn route() -> &'static str {
+        "/terms"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl From<Option<PostAuthAction>> for AcceptTerms {
+    fn from(post_auth_action: Option<PostAuthAction>) -> Self {
+        Self { post_auth_action }
+    }
+}
+
 /// `GET|POST /register`
 #[derive(Default, Debug, Clone)]
 pub struct Register {
@@ -560,6 +679,47 @@ impl SimpleRoute for CompatRefresh {
     const PATH: &'static str = "/_matrix/client/:version/refresh";
 }
 
+/// `POST /_matrix/client/v3/register`
+pub struct CompatRegister;
+
+impl SimpleRoute for CompatRegister {
+    const PATH: &'static str = "/_matrix/client/:version/register";
+}
+
+/// `POST /_matrix/client/unstable/org.matrix.msc4108/rendezvous`
+pub struct Rendezvous;
+
+impl SimpleRoute for Rendezvous {
+    const PATH: &'static str = "/_matrix/client/unstable/org.matrix.msc4108/rendezvous";
+}
+
+/// `GET|PUT|DELETE /_matrix/client/unstable/org.matrix.msc4108/rendezvous/:id`
+pub struct RendezvousSession {
+    id: Ulid,
+}
+
+impl RendezvousSession {
+    #[must_use]
+    pub const fn new(id: Ulid) -> Self {
+        Self { id }
+    }
+}
+
+impl Route for RendezvousSession {
+    type Query = ();
+    fn route() -> &'static str {
+        "/_matrix/client/unstable/org.matrix.msc4108/rendezvous/:id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!(
+            "/_matrix/client/unstable/org.matrix.msc4108/rendezvous/{}",
+            self.id
+        )
+        .into()
+    }
+}
+
 /// `GET /_matrix/client/v3/login/sso/redirect`
 pub struct CompatLoginSsoRedirect;
 
@@ -689,6 +849,52 @@ impl Route for UpstreamOAuth2Callback {
     }
 }
 
+/// `GET|POST /upstream/initiate/:id`
+pub struct UpstreamOAuth2Initiate {
+    id: Ulid,
+}
+
+impl UpstreamOAuth2Initiate {
+    #[must_use]
+    pub const fn new(id: Ulid) -> Self {
+        Self { id }
+    }
+}
+
+impl Route for UpstreamOAuth2Initiate {
+    type Query = ();
+    fn route() -> &'static str {
+        "/upstream/initiate/:provider_id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/initiate/{}", self.id).into()
+    }
+}
+
+/// `GET /clients/:id/login`
+pub struct ClientInitiateLogin {
+    id: Ulid,
+}
+
+impl ClientInitiateLogin {
+    #[must_use]
+    pub const fn new(id: Ulid) -> Self {
+        Self { id }
+    }
+}
+
+impl Route for ClientInitiateLogin {
+    type Query = ();
+    fn route() -> &'static str {
+        "/clients/:client_id/login"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/clients/{}/login", self.id).into()
+    }
+}
+
 /// `GET /upstream/link/:id`
 pub struct UpstreamOAuth2Link {
     id: Ulid,
@@ -768,6 +974,54 @@ impl SimpleRoute for OAuth2DeviceAuthorizationEndpoint {
     const PATH: &'static str = "/oauth2/device";
 }
 
+/// `POST /oauth2/par`
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct OAuth2PushedAuthorizationRequestEndpoint;
+
+impl SimpleRoute for OAuth2PushedAuthorizationRequestEndpoint {
+    const PATH: &'static str = "/oauth2/par";
+}
+
+/// `POST /oauth2/widget-token`
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct OAuth2WidgetTokenEndpoint;
+
+impl SimpleRoute for OAuth2WidgetTokenEndpoint {
+    const PATH: &'static str = "/oauth2/widget-token";
+}
+
+/// `POST /oauth2/bc-authorize`
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct OAuth2BackchannelAuthenticationEndpoint;
+
+impl SimpleRoute for OAuth2BackchannelAuthenticationEndpoint {
+    const PATH: &'static str = "/oauth2/bc-authorize";
+}
+
+/// `GET|POST /ciba/:ciba_grant_id`
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct CibaConsent {
+    id: Ulid,
+}
+
+impl Route for CibaConsent {
+    type Query = ();
+    fn route() -> &'static str {
+        "/ciba/:ciba_grant_id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/ciba/{}", self.id).into()
+    }
+}
+
+impl CibaConsent {
+    #[must_use]
+    pub fn new(id: Ulid) -> Self {
+        Self { id }
+    }
+}
+
 /// `GET|POST /recover`
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct AccountRecoveryStart;
@@ -826,6 +1080,70 @@ impl Route for AccountRecoveryFinish {
     }
 }
 
+/// `GET|POST /session/binding`
+#[derive(Default, Debug, Clone)]
+pub struct SessionBinding;
+
+impl SimpleRoute for SessionBinding {
+    const PATH: &'static str = "/session/binding";
+}
+
+/// `GET /emergency-access/:token`
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct EmergencyAccess {
+    token: String,
+}
+
+impl EmergencyAccess {
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+}
+
+impl Route for EmergencyAccess {
+    type Query = ();
+    fn route() -> &'static str {
+        "/emergency-access/:token"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/emergency-access/{}", self.token).into()
+    }
+}
+
+/// `GET|POST /impersonate/:user_id`
+#[derive(Debug, Clone)]
+pub struct Impersonate {
+    user_id: Ulid,
+}
+
+impl Impersonate {
+    #[must_use]
+    pub fn new(user_id: Ulid) -> Self {
+        Self { user_id }
+    }
+}
+
+impl Route for Impersonate {
+    type Query = ();
+    fn route() -> &'static str {
+        "/impersonate/:user_id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/impersonate/{}", self.user_id).into()
+    }
+}
+
+/// `POST /impersonate/end`
+#[derive(Default, Debug, Clone)]
+pub struct EndImpersonation;
+
+impl SimpleRoute for EndImpersonation {
+    const PATH: &'static str = "/impersonate/end";
+}
+
 /// `GET /assets`
 pub struct StaticAsset {
     path: String,
@@ -849,6 +1167,29 @@ impl Route for StaticAsset {
     }
 }
 
+/// `GET /theme`
+pub struct ThemeAsset {
+    path: String,
+}
+
+impl ThemeAsset {
+    #[must_use]
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+}
+
+impl Route for ThemeAsset {
+    type Query = ();
+    fn route() -> &'static str {
+        "/theme/"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/theme/{}", self.path).into()
+    }
+}
+
 /// `GET|POST /graphql`
 pub struct GraphQL;
 
@@ -172,6 +172,24 @@ impl UrlBuilder {
         self.absolute_url_for(&crate::endpoints::OAuth2DeviceAuthorizationEndpoint)
     }
 
+    /// OAuth 2.0 pushed authorization request endpoint
+    #[must_use]
+    pub fn oauth_pushed_authorization_request_endpoint(&self) -> Url {
+        self.absolute_url_for(&crate::endpoints::OAuth2PushedAuthorizationRequestEndpoint)
+    }
+
+    /// Endpoint issuing short-lived tokens for widgets
+    #[must_use]
+    pub fn oauth_widget_token_endpoint(&self) -> Url {
+        self.absolute_url_for(&crate::endpoints::OAuth2WidgetTokenEndpoint)
+    }
+
+    /// OpenID Connect CIBA backchannel authentication endpoint
+    #[must_use]
+    pub fn oauth_backchannel_authentication_endpoint(&self) -> Url {
+        self.absolute_url_for(&crate::endpoints::OAuth2BackchannelAuthenticationEndpoint)
+    }
+
     /// OAuth 2.0 device code link
     #[must_use]
     pub fn device_code_link(&self) -> Url {
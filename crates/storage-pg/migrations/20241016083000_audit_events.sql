@@ -0,0 +1,64 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Stores the audit log of security-relevant events
+CREATE TABLE "audit_events" (
+  "audit_event_id" UUID NOT NULL
+    CONSTRAINT "audit_events_pkey"
+    PRIMARY KEY,
+
+  -- The position of the event in the hash chain
+  "sequence" BIGINT GENERATED ALWAYS AS IDENTITY
+    CONSTRAINT "audit_events_sequence_unique"
+    UNIQUE,
+
+  -- When the event was recorded
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The kind of event, e.g. 'login' or 'admin_action'
+  "kind" TEXT NOT NULL,
+
+  -- The user who performed the action, if any. This is intentionally not a
+  -- foreign key, so that the audit log is independent from the rest of the data
+  "actor_user_id" UUID,
+
+  -- The user the action was performed on, if any
+  "user_id" UUID,
+
+  -- The IP address and user agent of the requester, if known
+  "ip_address" INET,
+  "user_agent" TEXT,
+
+  -- Additional details about the event
+  "details" JSONB NOT NULL DEFAULT '{}'::jsonb,
+
+  -- The hash of the previous event in the chain, and of this event
+  "previous_hash" TEXT,
+  "hash" TEXT NOT NULL
+);
+
+CREATE INDEX "audit_events_user_id_idx"
+  ON "audit_events" ("user_id");
+
+CREATE INDEX "audit_events_actor_user_id_idx"
+  ON "audit_events" ("actor_user_id");
+
+CREATE INDEX "audit_events_created_at_idx"
+  ON "audit_events" ("created_at");
+
+-- Audit events are never updated
+CREATE FUNCTION "audit_events_prevent_update"()
+  RETURNS TRIGGER
+  LANGUAGE plpgsql
+AS $$
+BEGIN
+  RAISE EXCEPTION 'audit events can not be modified';
+END;
+$$;
+
+CREATE TRIGGER "audit_events_prevent_update"
+  BEFORE UPDATE ON "audit_events"
+  FOR EACH ROW
+  EXECUTE FUNCTION "audit_events_prevent_update"();
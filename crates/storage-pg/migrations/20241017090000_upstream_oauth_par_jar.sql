@@ -0,0 +1,12 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds columns to the upstream_oauth_providers table to control whether
+-- pushed authorization requests (PAR) and signed request objects (JAR) are used
+-- when redirecting users to the provider.
+ALTER TABLE upstream_oauth_providers
+    ADD COLUMN par_mode TEXT NOT NULL DEFAULT 'auto',
+    ADD COLUMN pushed_authorization_request_endpoint_override TEXT,
+    ADD COLUMN request_object_signing_alg TEXT;
@@ -0,0 +1,14 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds a column to the upstream_oauth_providers table to choose how the
+-- provider sends the authorization response back
+ALTER TABLE upstream_oauth_providers
+    ADD COLUMN response_mode TEXT;
+
+-- Stores the extra parameters the provider sent back to the callback, like the
+-- user's name sent by Sign in with Apple on the first login
+ALTER TABLE upstream_oauth_authorization_sessions
+    ADD COLUMN extra_callback_parameters JSONB;
@@ -0,0 +1,22 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Events waiting to be published to the event stream. Events are inserted in
+-- the same transaction as the change they describe, and removed once they
+-- were published.
+CREATE TABLE "event_outbox" (
+  "event_outbox_id" UUID NOT NULL
+    CONSTRAINT "event_outbox_pkey"
+    PRIMARY KEY,
+
+  -- When the event happened
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The kind of event, e.g. 'user.registered'
+  "kind" TEXT NOT NULL,
+
+  -- Event-specific data
+  "data" JSONB NOT NULL
+);
@@ -0,0 +1,41 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Stores the one-time emergency access tokens, issued from the CLI to let an
+-- administrator start a browser session when the usual login methods are
+-- unavailable
+CREATE TABLE "user_emergency_access_tokens" (
+  "user_emergency_access_token_id" UUID NOT NULL
+    CONSTRAINT "user_emergency_access_tokens_pkey"
+    PRIMARY KEY,
+
+  -- The user the token was issued for
+  "user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- The token itself
+  "token" TEXT NOT NULL
+    CONSTRAINT "user_emergency_access_tokens_token_unique"
+    UNIQUE,
+
+  -- The network from which the token can be used
+  "allowed_network" CIDR NOT NULL,
+
+  -- When the token was issued
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the token expires
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the token was used, and the browser session it started
+  "consumed_at" TIMESTAMP WITH TIME ZONE,
+  "user_session_id" UUID
+    REFERENCES "user_sessions" ("user_session_id")
+    ON DELETE SET NULL
+);
+
+CREATE INDEX "user_emergency_access_tokens_user_id_idx"
+  ON "user_emergency_access_tokens" ("user_id");
@@ -0,0 +1,86 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds a table to store the authentication requests of the OpenID Connect
+-- Client-Initiated Backchannel Authentication (CIBA) flow, in poll mode
+--
+-- This has 4 possible states, only going in one direction, like the device
+-- code grants:
+--
+--     [[ Pending ]]
+--       |       |
+--       |  [ Rejected ] -- The `rejected_at` and `user_session_id` fields are set
+--       |
+-- [ Fulfilled ] -- The `fulfilled_at` and `user_session_id` fields are set
+--       |
+-- [ Exchanged ] -- The `exchanged_at` and `oauth2_session_id` fields are also set
+--
+CREATE TABLE "oauth2_ciba_grants" (
+    "oauth2_ciba_grant_id" UUID NOT NULL
+        PRIMARY KEY,
+
+    -- The client who initiated the request
+    "oauth2_client_id" UUID NOT NULL
+        REFERENCES "oauth2_clients" ("oauth2_client_id")
+        ON DELETE CASCADE,
+
+    -- The user whose approval is requested
+    "user_id" UUID NOT NULL
+        REFERENCES "users" ("user_id")
+        ON DELETE CASCADE,
+
+    -- The scope requested
+    "scope" TEXT NOT NULL,
+
+    -- The random identifier that the client uses to poll for the access token
+    "auth_req_id" TEXT NOT NULL
+        UNIQUE,
+
+    -- The message displayed both by the client and to the user
+    "binding_message" TEXT,
+
+    -- Timestamp when the request was created
+    "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+    -- Timestamp when the request expires
+    "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+    -- When the request was approved by the user
+    -- This is mutually exclusive with rejected_at
+    "fulfilled_at" TIMESTAMP WITH TIME ZONE,
+
+    -- When the request was rejected by the user
+    -- This is mutually exclusive with fulfilled_at
+    "rejected_at" TIMESTAMP WITH TIME ZONE,
+
+    -- When the request was exchanged
+    -- This means "fulfilled_at" has also been set
+    "exchanged_at" TIMESTAMP WITH TIME ZONE,
+
+    -- The OAuth 2.0 session generated for this request
+    -- This means "exchanged_at" has also been set
+    "oauth2_session_id" UUID
+        REFERENCES "oauth2_sessions" ("oauth2_session_id")
+        ON DELETE CASCADE,
+
+    -- The browser session ID that the user used to approve or reject
+    -- This means "fulfilled_at" or "rejected_at" has also been set
+    "user_session_id" UUID
+        REFERENCES "user_sessions" ("user_session_id"),
+
+    -- The IP address of the client when it made the request
+    "ip_address" INET,
+
+    -- The user agent of the client when it made the request
+    "user_agent" TEXT
+);
+
+CREATE INDEX "oauth2_ciba_grants_user_id_idx"
+    ON "oauth2_ciba_grants" ("user_id");
+
+-- Add a flag on oauth_clients to indicate whether they support the CIBA grant
+ALTER TABLE oauth2_clients
+    ADD COLUMN grant_type_ciba BOOLEAN
+        NOT NULL DEFAULT FALSE;
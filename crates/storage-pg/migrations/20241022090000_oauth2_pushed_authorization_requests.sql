@@ -0,0 +1,40 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds a flag on clients to only allow them to start authorization grants
+-- through pushed authorization requests (RFC 9126)
+ALTER TABLE oauth2_clients
+    ADD COLUMN require_pushed_authorization_requests BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Adds a table to store the authorization requests pushed by clients, until
+-- they are used in the authorization endpoint
+CREATE TABLE "oauth2_pushed_authorization_requests" (
+    "oauth2_pushed_authorization_request_id" UUID NOT NULL
+        PRIMARY KEY,
+
+    -- The client which pushed the request
+    "oauth2_client_id" UUID NOT NULL
+        REFERENCES "oauth2_clients" ("oauth2_client_id")
+        ON DELETE CASCADE,
+
+    -- The `request_uri` given back to the client
+    "request_uri" TEXT NOT NULL
+        UNIQUE,
+
+    -- The parameters of the authorization request, as a JSON object of strings
+    "parameters" JSONB NOT NULL,
+
+    -- Timestamp when the request was pushed
+    "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+    -- Timestamp when the request expires
+    "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+    -- When the request was used in the authorization endpoint
+    "consumed_at" TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX "oauth2_pushed_authorization_requests_oauth2_client_id_idx"
+    ON "oauth2_pushed_authorization_requests" ("oauth2_client_id");
@@ -0,0 +1,15 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds the settings for mutual-TLS client authentication and
+-- certificate-bound access tokens on clients (RFC 8705)
+ALTER TABLE oauth2_clients
+    ADD COLUMN tls_client_auth_subject_dn TEXT,
+    ADD COLUMN tls_client_certificate_bound_access_tokens BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Adds the SHA-256 thumbprint of the client certificate to which access tokens
+-- are bound, if any
+ALTER TABLE oauth2_access_tokens
+    ADD COLUMN x5t_s256 TEXT;
@@ -0,0 +1,9 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds a setting on clients to get access tokens formatted as signed JWTs
+-- (RFC 9068) instead of opaque tokens
+ALTER TABLE oauth2_clients
+    ADD COLUMN jwt_access_tokens BOOLEAN NOT NULL DEFAULT FALSE;
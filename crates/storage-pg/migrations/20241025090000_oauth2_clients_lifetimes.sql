@@ -0,0 +1,11 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds per-client overrides of the token and session lifetimes, in seconds
+ALTER TABLE oauth2_clients
+    ADD COLUMN access_token_ttl BIGINT,
+    ADD COLUMN refresh_token_ttl BIGINT,
+    ADD COLUMN session_ttl BIGINT,
+    ADD COLUMN session_inactivity_ttl BIGINT;
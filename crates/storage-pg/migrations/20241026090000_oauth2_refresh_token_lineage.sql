@@ -0,0 +1,11 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record which refresh token replaced a consumed one, so that the lineage of
+-- the refresh tokens of a session can be followed when a consumed token is
+-- presented again
+ALTER TABLE "oauth2_refresh_tokens"
+  ADD COLUMN "next_oauth2_refresh_token_id" UUID
+    REFERENCES "oauth2_refresh_tokens" ("oauth2_refresh_token_id");
@@ -0,0 +1,36 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Signing keys generated by the automated key rotation. They are published in
+-- the JWKS from their creation, used to sign once active, and removed from the
+-- JWKS once retired.
+CREATE TABLE "signing_keys" (
+  "signing_key_id" UUID NOT NULL
+    CONSTRAINT "signing_keys_pkey"
+    PRIMARY KEY,
+
+  -- The key ID, as published in the JWKS
+  "kid" TEXT NOT NULL
+    CONSTRAINT "signing_keys_kid_unique"
+    UNIQUE,
+
+  -- The signing algorithm this key is used for, e.g. 'RS256'
+  "algorithm" TEXT NOT NULL,
+
+  -- The private key, PEM-encoded and encrypted with the encryption secret
+  "encrypted_key" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the key starts being used to sign
+  "activates_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the key was removed from the JWKS
+  "retired_at" TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX "signing_keys_activates_at_idx"
+  ON "signing_keys" ("activates_at")
+  WHERE "retired_at" IS NULL;
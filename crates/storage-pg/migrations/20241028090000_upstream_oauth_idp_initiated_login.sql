@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds columns to the upstream_oauth_providers table to allow logins initiated
+-- by the provider, and to choose the client the user lands on afterwards
+ALTER TABLE upstream_oauth_providers
+    ADD COLUMN allow_idp_initiated_login BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN idp_initiated_login_client_id UUID;
@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds columns to the upstream_oauth_providers table to log users out of the
+-- provider when their session ends, and to override its end session endpoint
+ALTER TABLE upstream_oauth_providers
+  ADD COLUMN propagate_logout BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN end_session_endpoint_override TEXT;
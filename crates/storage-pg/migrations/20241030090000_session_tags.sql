@@ -0,0 +1,14 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds a column to the session tables to let users tag their sessions
+ALTER TABLE oauth2_sessions
+  ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE compat_sessions
+  ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE user_sessions
+  ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,28 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Stores the subscriptions of users to the periodic email digest of their
+-- account activity
+CREATE TABLE "user_activity_digests" (
+  "user_activity_digest_id" UUID NOT NULL
+    CONSTRAINT "user_activity_digests_pkey"
+    PRIMARY KEY,
+
+  -- The user who subscribed. A user has at most one subscription
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_activity_digests_user_id_unique"
+    UNIQUE
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- The locale in which the digest is sent
+  "locale" TEXT NOT NULL,
+
+  -- When the user subscribed
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the last digest was sent, if any
+  "last_sent_at" TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,28 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Stores the notification preferences of users. Users who never changed their
+-- preferences don't have a row here, and use the default ones
+CREATE TABLE "user_notification_preferences" (
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_notification_preferences_pkey"
+    PRIMARY KEY
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- Whether the user receives an alert when someone logs in to their account
+  "new_login_alerts" BOOLEAN NOT NULL,
+
+  -- How often the user receives the digest of their account activity:
+  -- 'default', 'daily', 'weekly' or 'monthly'
+  "digest_frequency" TEXT NOT NULL,
+
+  -- Whether the user only receives notifications about the security of their
+  -- account
+  "security_only" BOOLEAN NOT NULL,
+
+  -- When the preferences were last changed
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record when an account was placed under legal hold, which prevents its data
+-- from being pruned or erased until the hold is released
+ALTER TABLE "users"
+    ADD COLUMN "legal_hold_at"
+        TIMESTAMP WITH TIME ZONE
+        DEFAULT NULL;
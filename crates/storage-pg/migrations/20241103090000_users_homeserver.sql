@@ -0,0 +1,10 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record which homeserver a user belongs to, when the service serves multiple
+-- homeservers. NULL means the main homeserver.
+ALTER TABLE "users"
+    ADD COLUMN "homeserver" TEXT
+        DEFAULT NULL;
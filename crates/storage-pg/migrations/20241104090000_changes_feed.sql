@@ -0,0 +1,34 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Webhook events recorded so that they can be polled incrementally. The IDs
+-- of the entries are strictly increasing in commit order, so that they can be
+-- used as cursors.
+CREATE TABLE "changes_feed" (
+  "changes_feed_entry_id" UUID NOT NULL
+    CONSTRAINT "changes_feed_pkey"
+    PRIMARY KEY,
+
+  -- The ID of the recorded event, so that an event is only recorded once
+  "event_id" UUID NOT NULL
+    CONSTRAINT "changes_feed_event_id_unique"
+    UNIQUE,
+
+  -- When the event happened
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The kind of event, e.g. 'user.registered'
+  "kind" TEXT NOT NULL,
+
+  -- Event-specific data
+  "data" JSONB NOT NULL
+);
+
+CREATE INDEX "changes_feed_kind_idx"
+  ON "changes_feed" ("kind", "changes_feed_entry_id");
+
+-- Used when cleaning up old entries
+CREATE INDEX "changes_feed_created_at_idx"
+  ON "changes_feed" ("created_at");
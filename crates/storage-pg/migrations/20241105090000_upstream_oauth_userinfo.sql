@@ -0,0 +1,15 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds columns to the upstream_oauth_providers table to fetch the user profile
+-- from the userinfo endpoint, and to override that endpoint
+ALTER TABLE upstream_oauth_providers
+  ADD COLUMN fetch_userinfo BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN userinfo_endpoint_override TEXT;
+
+-- Keep the userinfo response around, so that it can be used when importing
+-- the user attributes
+ALTER TABLE upstream_oauth_authorization_sessions
+  ADD COLUMN userinfo JSONB;
@@ -0,0 +1,19 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Browser sessions can be bound to a WebAuthn credential, which the browser
+-- has to regularly prove it holds
+ALTER TABLE user_sessions
+  ADD COLUMN binding_credential_id TEXT,
+  ADD COLUMN binding_public_key BYTEA,
+  ADD COLUMN binding_sign_count BIGINT,
+  ADD COLUMN binding_token TEXT,
+  ADD COLUMN binding_previous_token TEXT,
+  ADD COLUMN binding_expires_at TIMESTAMP WITH TIME ZONE;
+
+-- Used to find the active sessions whose binding expired
+CREATE INDEX user_sessions_binding_expires_at_idx
+  ON user_sessions (binding_expires_at)
+  WHERE finished_at IS NULL AND binding_expires_at IS NOT NULL;
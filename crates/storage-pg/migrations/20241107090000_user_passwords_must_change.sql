@@ -0,0 +1,9 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record when a password was flagged as having to be changed on the next login
+ALTER TABLE "user_passwords"
+    ADD COLUMN "must_change_at" TIMESTAMP WITH TIME ZONE
+        DEFAULT NULL;
@@ -0,0 +1,35 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Emails which could not be delivered. Addresses which hard-bounced are not
+-- sent any more emails.
+CREATE TABLE "email_delivery_failures" (
+  "email_delivery_failure_id" UUID NOT NULL
+    CONSTRAINT "email_delivery_failures_pkey"
+    PRIMARY KEY,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The address the email was sent to
+  "email" TEXT NOT NULL,
+
+  -- The domain of the address, lowercased
+  "domain" TEXT NOT NULL,
+
+  -- Whether the mail server rejected the email permanently (hard bounce), or
+  -- only deferred it
+  "permanent" BOOLEAN NOT NULL,
+
+  -- The error returned by the mail server
+  "reason" TEXT NOT NULL
+);
+
+CREATE INDEX "email_delivery_failures_domain_idx"
+  ON "email_delivery_failures" ("domain", "email_delivery_failure_id");
+
+-- Used to check whether an address is suppressed
+CREATE INDEX "email_delivery_failures_suppressed_idx"
+  ON "email_delivery_failures" (LOWER("email"))
+  WHERE "permanent";
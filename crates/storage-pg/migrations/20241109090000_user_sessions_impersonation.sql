@@ -0,0 +1,18 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Browser sessions can be started by an administrator to impersonate a user,
+-- in which case they are time-limited
+ALTER TABLE user_sessions
+  ADD COLUMN impersonator_user_id UUID
+    REFERENCES users (user_id) ON DELETE SET NULL,
+  ADD COLUMN impersonator_user_session_id UUID
+    REFERENCES user_sessions (user_session_id) ON DELETE SET NULL,
+  ADD COLUMN impersonation_expires_at TIMESTAMP WITH TIME ZONE;
+
+-- Used to find the active impersonation sessions which expired
+CREATE INDEX user_sessions_impersonation_expires_at_idx
+  ON user_sessions (impersonation_expires_at)
+  WHERE finished_at IS NULL AND impersonation_expires_at IS NOT NULL;
@@ -0,0 +1,30 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Decoy tokens and accounts, planted to detect credential leaks. They never
+-- grant access to anything, and any attempt to use them is reported.
+CREATE TABLE "honeytokens" (
+  "honeytoken_id" UUID NOT NULL
+    CONSTRAINT "honeytokens_pkey"
+    PRIMARY KEY,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- What the honeytoken impersonates: one of 'access_token',
+  -- 'refresh_token', 'compat_access_token', 'compat_refresh_token' or
+  -- 'account'
+  "kind" TEXT NOT NULL,
+
+  -- The decoy token, or the username of the decoy account
+  "value" TEXT NOT NULL
+    CONSTRAINT "honeytokens_value_unique"
+    UNIQUE,
+
+  -- A free-form label, to remember where the honeytoken was planted
+  "label" TEXT NOT NULL,
+
+  -- When the honeytoken was last used
+  "last_used_at" TIMESTAMP WITH TIME ZONE
+);
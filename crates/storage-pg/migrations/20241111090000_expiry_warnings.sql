@@ -0,0 +1,25 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Keeps track of when the administrators were last warned about an expiring
+-- credential, so that every worker doesn't send the same warning
+CREATE TABLE "expiry_warnings" (
+  -- What kind of credential is expiring: one of 'tls_certificate',
+  -- 'upstream_client_secret' or 'signing_key'
+  "kind" TEXT NOT NULL,
+
+  -- The name of the credential, like the listener name, the provider ID or
+  -- the key ID
+  "name" TEXT NOT NULL,
+
+  -- When the credential expires, as of the last warning
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the administrators were last warned
+  "notified_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "expiry_warnings_pkey"
+    PRIMARY KEY ("kind", "name")
+);
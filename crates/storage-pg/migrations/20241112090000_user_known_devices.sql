@@ -0,0 +1,29 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The browsers and devices each user signed in from, to notify them when they
+-- sign in from a new one
+CREATE TABLE "user_known_devices" (
+  "user_known_device_id" UUID NOT NULL
+    CONSTRAINT "user_known_devices_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- Identifies the browser or device, derived from its user agent, without
+  -- its version
+  "fingerprint" TEXT NOT NULL,
+
+  -- When the user first signed in from this device
+  "first_seen_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the user last signed in from this device
+  "last_seen_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "user_known_devices_user_id_fingerprint_unique"
+    UNIQUE ("user_id", "fingerprint")
+);
@@ -0,0 +1,20 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The resources administrators disabled at runtime, for example during an
+-- incident. A resource is enabled unless it has a row here.
+CREATE TABLE "disabled_resources" (
+  -- The resource: one of 'client_registration', 'compat_login' or
+  -- 'device_authorization'
+  "resource" TEXT NOT NULL
+    CONSTRAINT "disabled_resources_pkey"
+    PRIMARY KEY,
+
+  -- When the resource was disabled
+  "disabled_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- Why the resource was disabled, if the administrator said so
+  "reason" TEXT
+);
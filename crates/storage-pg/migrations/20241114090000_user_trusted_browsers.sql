@@ -0,0 +1,31 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The browsers users chose to trust when logging in, which are not challenged
+-- again for additional authentication factors until the trust expires
+CREATE TABLE "user_trusted_browsers" (
+  "user_trusted_browser_id" UUID NOT NULL
+    CONSTRAINT "user_trusted_browsers_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- The user agent of the browser when it was trusted
+  "user_agent" TEXT,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the trust was last used to log in
+  "last_used_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "revoked_at" TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX "user_trusted_browsers_user_id_idx"
+  ON "user_trusted_browsers" ("user_id");
@@ -0,0 +1,18 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Flag the bot accounts, and the human account which owns them
+ALTER TABLE "users"
+    ADD COLUMN "is_bot"
+        BOOLEAN NOT NULL
+        DEFAULT FALSE,
+    ADD COLUMN "bot_owner_id"
+        UUID
+        REFERENCES "users" ("user_id") ON DELETE SET NULL
+        DEFAULT NULL;
+
+CREATE INDEX "users_bot_owner_id_idx"
+    ON "users" ("bot_owner_id")
+    WHERE "bot_owner_id" IS NOT NULL;
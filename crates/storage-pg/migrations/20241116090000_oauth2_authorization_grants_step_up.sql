@@ -0,0 +1,14 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Remember whether the client asked the user to log in again, and which
+-- authentication context classes it asked for
+ALTER TABLE "oauth2_authorization_grants"
+    ADD COLUMN "requires_login"
+        BOOLEAN NOT NULL
+        DEFAULT FALSE,
+    ADD COLUMN "acr_values"
+        TEXT[] NOT NULL
+        DEFAULT '{}';
@@ -0,0 +1,32 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Shared devices registered by a kiosk client, on which users log in one at a
+-- time with short-lived sessions
+CREATE TABLE "oauth2_kiosk_devices" (
+  "oauth2_kiosk_device_id" UUID NOT NULL
+    CONSTRAINT "oauth2_kiosk_devices_pkey"
+    PRIMARY KEY,
+
+  "oauth2_client_id" UUID NOT NULL
+    REFERENCES "oauth2_clients" ("oauth2_client_id")
+    ON DELETE CASCADE,
+
+  -- The Matrix device ID given to the sessions of the users on this device
+  "device_id" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The session of the user currently logged in on the device
+  "current_oauth2_session_id" UUID
+    REFERENCES "oauth2_sessions" ("oauth2_session_id")
+    ON DELETE SET NULL,
+
+  CONSTRAINT "oauth2_kiosk_devices_client_device_unique"
+    UNIQUE ("oauth2_client_id", "device_id")
+);
+
+CREATE INDEX "oauth2_kiosk_devices_current_oauth2_session_id_idx"
+  ON "oauth2_kiosk_devices" ("current_oauth2_session_id");
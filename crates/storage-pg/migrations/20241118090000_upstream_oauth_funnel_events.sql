@@ -0,0 +1,42 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The stages of the signup funnel reached through upstream OAuth 2.0
+-- providers, and where users dropped off, so that operators can find where
+-- new users get stuck
+CREATE TABLE "upstream_oauth_funnel_events" (
+  "upstream_oauth_funnel_event_id" UUID NOT NULL
+    CONSTRAINT "upstream_oauth_funnel_events_pkey"
+    PRIMARY KEY,
+
+  "upstream_oauth_provider_id" UUID NOT NULL
+    REFERENCES "upstream_oauth_providers" ("upstream_oauth_provider_id")
+    ON DELETE CASCADE,
+
+  -- The user who reached the stage, once the account was created
+  "user_id" UUID
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The stage of the funnel, e.g. 'callback_received'
+  "stage" TEXT NOT NULL,
+
+  -- Why the user dropped off at this stage. NULL if the stage was reached
+  "dropoff_reason" TEXT
+);
+
+CREATE INDEX "upstream_oauth_funnel_events_provider_idx"
+  ON "upstream_oauth_funnel_events" ("upstream_oauth_provider_id", "created_at");
+
+-- Used to find the stages reached by a user
+CREATE INDEX "upstream_oauth_funnel_events_user_idx"
+  ON "upstream_oauth_funnel_events" ("user_id", "stage")
+  WHERE "user_id" IS NOT NULL;
+
+-- Used when cleaning up old events
+CREATE INDEX "upstream_oauth_funnel_events_created_at_idx"
+  ON "upstream_oauth_funnel_events" ("created_at");
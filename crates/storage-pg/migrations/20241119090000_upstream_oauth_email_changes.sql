@@ -0,0 +1,50 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The email address the upstream provider last asserted for the linked
+-- subject, used to detect when it changes
+ALTER TABLE "upstream_oauth_links"
+  ADD COLUMN "asserted_email" TEXT;
+
+-- Changes of the email address asserted by an upstream provider, which need
+-- to be reviewed by an administrator
+CREATE TABLE "upstream_oauth_email_changes" (
+  "upstream_oauth_email_change_id" UUID NOT NULL
+    CONSTRAINT "upstream_oauth_email_changes_pkey"
+    PRIMARY KEY,
+
+  "upstream_oauth_link_id" UUID NOT NULL
+    REFERENCES "upstream_oauth_links" ("upstream_oauth_link_id")
+    ON DELETE CASCADE,
+
+  "user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  -- The other account which already has the new email address, if any
+  "conflicting_user_id" UUID
+    REFERENCES "users" ("user_id")
+    ON DELETE SET NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "previous_email" TEXT,
+  "new_email" TEXT NOT NULL,
+
+  "resolved_at" TIMESTAMP WITH TIME ZONE,
+
+  -- How the change was resolved: one of 'applied' or 'dismissed'
+  "resolution" TEXT
+);
+
+CREATE INDEX "upstream_oauth_email_changes_link_idx"
+  ON "upstream_oauth_email_changes" ("upstream_oauth_link_id");
+
+CREATE INDEX "upstream_oauth_email_changes_user_idx"
+  ON "upstream_oauth_email_changes" ("user_id");
+
+CREATE INDEX "upstream_oauth_email_changes_conflicting_user_idx"
+  ON "upstream_oauth_email_changes" ("conflicting_user_id")
+  WHERE "conflicting_user_id" IS NOT NULL;
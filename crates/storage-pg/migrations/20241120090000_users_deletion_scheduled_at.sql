@@ -0,0 +1,16 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record when the account of a user who asked for its deletion is due to be
+-- erased. The deletion can be cancelled until then by logging back in.
+ALTER TABLE "users"
+    ADD COLUMN "deletion_scheduled_at"
+        TIMESTAMP WITH TIME ZONE
+        DEFAULT NULL;
+
+-- Used by the task which erases the accounts once the grace period elapsed
+CREATE INDEX "users_deletion_scheduled_at_idx"
+    ON "users" ("deletion_scheduled_at")
+    WHERE "deletion_scheduled_at" IS NOT NULL;
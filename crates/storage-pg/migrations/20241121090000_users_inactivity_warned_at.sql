@@ -0,0 +1,17 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Record when a user was warned that their account would be deactivated
+-- because they didn't use it for a long time
+ALTER TABLE "users"
+    ADD COLUMN "inactivity_warned_at"
+        TIMESTAMP WITH TIME ZONE
+        DEFAULT NULL;
+
+-- Used by the task which deactivates the accounts once the grace period
+-- elapsed
+CREATE INDEX "users_inactivity_warned_at_idx"
+    ON "users" ("inactivity_warned_at")
+    WHERE "inactivity_warned_at" IS NOT NULL;
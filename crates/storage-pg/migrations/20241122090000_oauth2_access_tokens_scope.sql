@@ -0,0 +1,9 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Adds the scope to which an access token is restricted, if it is narrower
+-- than the scope of its session, like the short-lived tokens handed to widgets
+ALTER TABLE oauth2_access_tokens
+    ADD COLUMN scope_list TEXT[];
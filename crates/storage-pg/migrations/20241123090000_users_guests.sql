@@ -0,0 +1,15 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Flag the guest accounts, which were registered without any credentials
+ALTER TABLE "users"
+    ADD COLUMN "is_guest"
+        BOOLEAN NOT NULL
+        DEFAULT FALSE;
+
+-- Used to find stale guest accounts
+CREATE INDEX "users_guests_created_at_idx"
+    ON "users" ("created_at")
+    WHERE "is_guest";
@@ -0,0 +1,30 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- In-flight rendezvous sessions, through which two devices exchange messages
+-- to sign in one of them by scanning a QR code (MSC4108). The content is
+-- end-to-end encrypted by the devices, and opaque to the service.
+CREATE TABLE "rendezvous_sessions" (
+  "rendezvous_session_id" UUID NOT NULL
+    CONSTRAINT "rendezvous_sessions_pkey"
+    PRIMARY KEY,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the content was last replaced
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- Changes every time the content is replaced, so that the devices don't
+  -- overwrite each other's messages
+  "etag" TEXT NOT NULL,
+
+  "content_type" TEXT NOT NULL,
+  "content" BYTEA NOT NULL
+);
+
+CREATE INDEX "rendezvous_sessions_expires_at_idx"
+  ON "rendezvous_sessions" ("expires_at");
@@ -0,0 +1,9 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The token with which dynamically registered clients can read, update and
+-- delete their registration (RFC7592), encrypted like the client secrets
+ALTER TABLE "oauth2_clients"
+    ADD COLUMN "encrypted_registration_access_token" TEXT;
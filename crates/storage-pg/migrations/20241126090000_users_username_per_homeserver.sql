@@ -0,0 +1,14 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- Usernames are only unique within a homeserver, so that the same username can
+-- be used on the main homeserver and on each of the additional ones.
+-- The main homeserver is stored as NULL, which we map to an empty server name
+-- so that it is covered by the unique index.
+ALTER TABLE "users"
+    DROP CONSTRAINT "users_username_unique";
+
+CREATE UNIQUE INDEX "users_username_homeserver_unique"
+    ON "users" ("username", COALESCE("homeserver", ''));
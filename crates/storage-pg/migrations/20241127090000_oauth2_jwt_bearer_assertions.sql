@@ -0,0 +1,23 @@
+-- Copyright 2024 New Vector Ltd.
+--
+-- SPDX-License-Identifier: AGPL-3.0-only
+-- Please see LICENSE in the repository root for full details.
+
+-- The JWT bearer assertions which were used to get tokens, remembered until
+-- they expire so that they can't be replayed (RFC 7523 section 3)
+CREATE TABLE "oauth2_jwt_bearer_assertions" (
+  -- The `iss` and `jti` claims of the assertion
+  "issuer" TEXT NOT NULL,
+  "jti" TEXT NOT NULL,
+
+  "used_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The `exp` claim of the assertion
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "oauth2_jwt_bearer_assertions_pkey"
+    PRIMARY KEY ("issuer", "jti")
+);
+
+CREATE INDEX "oauth2_jwt_bearer_assertions_expires_at_idx"
+  ON "oauth2_jwt_bearer_assertions" ("expires_at");
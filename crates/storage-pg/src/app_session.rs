@@ -16,7 +16,8 @@ use mas_storage::{
 };
 use oauth2_types::scope::{Scope, ScopeToken};
 use sea_query::{
-    Alias, ColumnRef, CommonTableExpression, Expr, PostgresQueryBuilder, Query, UnionType,
+    Alias, ColumnRef, CommonTableExpression, Expr, Order, PostgresQueryBuilder, Query, UnionType,
+    WithClause,
 };
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
@@ -70,6 +71,7 @@ mod priv_ {
         pub(super) user_agent: Option<String>,
         pub(super) last_active_at: Option<DateTime<Utc>>,
         pub(super) last_active_ip: Option<IpAddr>,
+        pub(super) tags: Vec<String>,
     }
 }
 
@@ -97,6 +99,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
             user_agent,
             last_active_at,
             last_active_ip,
+            tags,
         } = value;
 
         let user_agent = user_agent.map(UserAgent::parse);
@@ -144,6 +147,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
                     user_agent,
                     last_active_at,
                     last_active_ip,
+                    tags,
                 };
 
                 Ok(AppSession::Compat(Box::new(session)))
@@ -184,6 +188,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
                     user_agent,
                     last_active_at,
                     last_active_ip,
+                    tags,
                 };
 
                 Ok(AppSession::OAuth2(Box::new(session)))
@@ -241,14 +246,148 @@ fn split_filter(
         oauth2_filter = oauth2_filter.with_last_active_after(last_active_after);
     }
 
+    if let Some(tag) = filter.tag() {
+        compat_filter = compat_filter.with_tag(tag);
+        oauth2_filter = oauth2_filter.with_tag(tag);
+    }
+
     (compat_filter, oauth2_filter)
 }
 
+/// Build a `WITH` clause selecting both the compatibility and the OAuth 2.0
+/// sessions matching the given filter into a `sessions` table
+#[allow(clippy::too_many_lines)]
+fn sessions_with_clause(filter: AppSessionFilter<'_>) -> WithClause {
+    let (compat_filter, oauth2_filter) = split_filter(filter);
+
+    let mut oauth2_session_select = Query::select()
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2SessionId)),
+            AppSessionLookupIden::Cursor,
+        )
+        .expr_as(Expr::cust("NULL"), AppSessionLookupIden::CompatSessionId)
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2SessionId)),
+            AppSessionLookupIden::Oauth2SessionId,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2ClientId)),
+            AppSessionLookupIden::Oauth2ClientId,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserSessionId)),
+            AppSessionLookupIden::UserSessionId,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId)),
+            AppSessionLookupIden::UserId,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::ScopeList)),
+            AppSessionLookupIden::ScopeList,
+        )
+        .expr_as(Expr::cust("NULL"), AppSessionLookupIden::DeviceId)
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt)),
+            AppSessionLookupIden::CreatedAt,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::FinishedAt)),
+            AppSessionLookupIden::FinishedAt,
+        )
+        .expr_as(Expr::cust("NULL"), AppSessionLookupIden::IsSynapseAdmin)
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserAgent)),
+            AppSessionLookupIden::UserAgent,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveAt)),
+            AppSessionLookupIden::LastActiveAt,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
+            AppSessionLookupIden::LastActiveIp,
+        )
+        .expr_as(
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::Tags)),
+            AppSessionLookupIden::Tags,
+        )
+        .from(OAuth2Sessions::Table)
+        .apply_filter(oauth2_filter)
+        .clone();
+
+    let compat_session_select = Query::select()
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::CompatSessionId)),
+            AppSessionLookupIden::Cursor,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::CompatSessionId)),
+            AppSessionLookupIden::CompatSessionId,
+        )
+        .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Oauth2SessionId)
+        .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Oauth2ClientId)
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::UserSessionId)),
+            AppSessionLookupIden::UserSessionId,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::UserId)),
+            AppSessionLookupIden::UserId,
+        )
+        .expr_as(Expr::cust("NULL"), AppSessionLookupIden::ScopeList)
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::DeviceId)),
+            AppSessionLookupIden::DeviceId,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::CreatedAt)),
+            AppSessionLookupIden::CreatedAt,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::FinishedAt)),
+            AppSessionLookupIden::FinishedAt,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::IsSynapseAdmin)),
+            AppSessionLookupIden::IsSynapseAdmin,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::UserAgent)),
+            AppSessionLookupIden::UserAgent,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::LastActiveAt)),
+            AppSessionLookupIden::LastActiveAt,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::LastActiveIp)),
+            AppSessionLookupIden::LastActiveIp,
+        )
+        .expr_as(
+            Expr::col((CompatSessions::Table, CompatSessions::Tags)),
+            AppSessionLookupIden::Tags,
+        )
+        .from(CompatSessions::Table)
+        .apply_filter(compat_filter)
+        .clone();
+
+    let common_table_expression = CommonTableExpression::new()
+        .query(
+            oauth2_session_select
+                .union(UnionType::All, compat_session_select)
+                .clone(),
+        )
+        .table_name(Alias::new("sessions"))
+        .clone();
+
+    Query::with().cte(common_table_expression).clone()
+}
+
 #[async_trait]
 impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
     type Error = DatabaseError;
 
-    #[allow(clippy::too_many_lines)]
     #[tracing::instrument(
         name = "db.app_session.list",
         fields(
@@ -262,122 +401,7 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
         filter: AppSessionFilter<'_>,
         pagination: Pagination,
     ) -> Result<Page<AppSession>, Self::Error> {
-        let (compat_filter, oauth2_filter) = split_filter(filter);
-
-        let mut oauth2_session_select = Query::select()
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2SessionId)),
-                AppSessionLookupIden::Cursor,
-            )
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::CompatSessionId)
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2SessionId)),
-                AppSessionLookupIden::Oauth2SessionId,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2ClientId)),
-                AppSessionLookupIden::Oauth2ClientId,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserSessionId)),
-                AppSessionLookupIden::UserSessionId,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId)),
-                AppSessionLookupIden::UserId,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::ScopeList)),
-                AppSessionLookupIden::ScopeList,
-            )
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::DeviceId)
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt)),
-                AppSessionLookupIden::CreatedAt,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::FinishedAt)),
-                AppSessionLookupIden::FinishedAt,
-            )
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::IsSynapseAdmin)
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserAgent)),
-                AppSessionLookupIden::UserAgent,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveAt)),
-                AppSessionLookupIden::LastActiveAt,
-            )
-            .expr_as(
-                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
-                AppSessionLookupIden::LastActiveIp,
-            )
-            .from(OAuth2Sessions::Table)
-            .apply_filter(oauth2_filter)
-            .clone();
-
-        let compat_session_select = Query::select()
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::CompatSessionId)),
-                AppSessionLookupIden::Cursor,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::CompatSessionId)),
-                AppSessionLookupIden::CompatSessionId,
-            )
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Oauth2SessionId)
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Oauth2ClientId)
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::UserSessionId)),
-                AppSessionLookupIden::UserSessionId,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::UserId)),
-                AppSessionLookupIden::UserId,
-            )
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::ScopeList)
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::DeviceId)),
-                AppSessionLookupIden::DeviceId,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::CreatedAt)),
-                AppSessionLookupIden::CreatedAt,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::FinishedAt)),
-                AppSessionLookupIden::FinishedAt,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::IsSynapseAdmin)),
-                AppSessionLookupIden::IsSynapseAdmin,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::UserAgent)),
-                AppSessionLookupIden::UserAgent,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::LastActiveAt)),
-                AppSessionLookupIden::LastActiveAt,
-            )
-            .expr_as(
-                Expr::col((CompatSessions::Table, CompatSessions::LastActiveIp)),
-                AppSessionLookupIden::LastActiveIp,
-            )
-            .from(CompatSessions::Table)
-            .apply_filter(compat_filter)
-            .clone();
-
-        let common_table_expression = CommonTableExpression::new()
-            .query(
-                oauth2_session_select
-                    .union(UnionType::All, compat_session_select)
-                    .clone(),
-            )
-            .table_name(Alias::new("sessions"))
-            .clone();
-
-        let with_clause = Query::with().cte(common_table_expression).clone();
+        let with_clause = sessions_with_clause(filter);
 
         let select = Query::select()
             .column(ColumnRef::Asterisk)
@@ -446,6 +470,41 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
             .try_into()
             .map_err(DatabaseError::to_invalid_operation)
     }
+
+    #[tracing::instrument(
+        name = "db.app_session.find_least_recently_active",
+        fields(
+            db.query.text,
+        ),
+        skip_all,
+        err,
+    )]
+    async fn find_least_recently_active(
+        &mut self,
+        filter: AppSessionFilter<'_>,
+    ) -> Result<Option<AppSession>, Self::Error> {
+        let with_clause = sessions_with_clause(filter);
+
+        let select = Query::select()
+            .column(ColumnRef::Asterisk)
+            .from(Alias::new("sessions"))
+            .order_by_expr(
+                Expr::cust("COALESCE(last_active_at, created_at)"),
+                Order::Asc,
+            )
+            .order_by(AppSessionLookupIden::Cursor, Order::Asc)
+            .limit(1)
+            .clone();
+
+        let (sql, arguments) = with_clause.query(select).build_sqlx(PostgresQueryBuilder);
+
+        let res: Option<AppSessionLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_optional(&mut *self.conn)
+            .await?;
+
+        res.map(TryFrom::try_from).transpose()
+    }
 }
 
 #[cfg(test)]
@@ -456,7 +515,7 @@ mod tests {
         app_session::{AppSession, AppSessionFilter},
         clock::MockClock,
         oauth2::OAuth2SessionRepository,
-        Pagination, RepositoryAccess,
+        Clock, Pagination, RepositoryAccess,
     };
     use oauth2_types::{
         requests::GrantType,
@@ -572,6 +631,9 @@ mod tests {
                 None,
                 None,
                 Some("https://example.com/login".parse().unwrap()),
+                false,
+                None,
+                false,
             )
             .await
             .unwrap();
@@ -686,4 +748,88 @@ mod tests {
         let list = repo.app_session().list(filter, pagination).await.unwrap();
         assert!(list.edges.is_empty());
     }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_find_least_recently_active(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "john".to_owned())
+            .await
+            .unwrap();
+
+        let filter = AppSessionFilter::new().for_user(&user).active_only();
+        assert!(repo
+            .app_session()
+            .find_least_recently_active(filter)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Start two compat sessions, a minute apart
+        let first = repo
+            .compat_session()
+            .add(
+                &mut rng,
+                &clock,
+                &user,
+                Device::generate(&mut rng),
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        clock.advance(Duration::try_minutes(1).unwrap());
+        let second = repo
+            .compat_session()
+            .add(
+                &mut rng,
+                &clock,
+                &user,
+                Device::generate(&mut rng),
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+
+        // The oldest session is the least recently active one
+        let session = repo
+            .app_session()
+            .find_least_recently_active(filter)
+            .await
+            .unwrap();
+        assert_eq!(session, Some(AppSession::Compat(Box::new(first.clone()))));
+
+        // Once the first session was used, the second one is the least recently
+        // active
+        clock.advance(Duration::try_minutes(1).unwrap());
+        repo.compat_session()
+            .record_batch_activity(vec![(first.id, clock.now(), None)])
+            .await
+            .unwrap();
+
+        let session = repo
+            .app_session()
+            .find_least_recently_active(filter)
+            .await
+            .unwrap();
+        assert_eq!(session, Some(AppSession::Compat(Box::new(second.clone()))));
+
+        // Finished sessions are ignored
+        repo.compat_session().finish(&clock, second).await.unwrap();
+        let session = repo
+            .app_session()
+            .find_least_recently_active(filter)
+            .await
+            .unwrap()
+            .unwrap();
+        let AppSession::Compat(session) = session else {
+            panic!("expected a compat session");
+        };
+        assert_eq!(session.id, first.id);
+    }
 }
@@ -0,0 +1,701 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`AuditEventRepository`]
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{AuditEvent, AuditEventKind};
+use mas_storage::{
+    audit::{AuditEventFilter, AuditEventRepository},
+    pagination::Count,
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    filter::{Filter, StatementExt},
+    iden::AuditEvents,
+    pagination::{estimate_count, QueryBuilderExt},
+    DatabaseError, DatabaseInconsistencyError, ExecuteExt,
+};
+
+/// The key of the advisory lock used to serialize the insertion of events in
+/// the hash chain
+const AUDIT_EVENTS_LOCK_KEY: i64 = 0x6d61_735f_6175_6469;
+
+/// How many events to load at once when verifying the chain
+const VERIFY_BATCH_SIZE: i64 = 1000;
+
+/// An implementation of [`AuditEventRepository`] for a PostgreSQL connection
+pub struct PgAuditEventRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgAuditEventRepository<'c> {
+    /// Create a new [`PgAuditEventRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+mod priv_ {
+    // The enum_def macro generates a public enum, which we don't want, because it
+    // triggers the missing docs warning
+
+    use std::net::IpAddr;
+
+    use chrono::{DateTime, Utc};
+    use sea_query::enum_def;
+    use uuid::Uuid;
+
+    #[derive(sqlx::FromRow)]
+    #[enum_def]
+    pub(super) struct AuditEventLookup {
+        pub(super) audit_event_id: Uuid,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) kind: String,
+        pub(super) actor_user_id: Option<Uuid>,
+        pub(super) user_id: Option<Uuid>,
+        pub(super) ip_address: Option<IpAddr>,
+        pub(super) user_agent: Option<String>,
+        pub(super) details: serde_json::Value,
+        pub(super) previous_hash: Option<String>,
+        pub(super) hash: String,
+    }
+}
+
+use priv_::{AuditEventLookup, AuditEventLookupIden};
+
+impl TryFrom<AuditEventLookup> for AuditEvent {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: AuditEventLookup) -> Result<Self, Self::Error> {
+        let id = value.audit_event_id.into();
+        let kind = value.kind.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("audit_events")
+                .column("kind")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(AuditEvent {
+            id,
+            created_at: value.created_at,
+            kind,
+            actor_user_id: value.actor_user_id.map(Ulid::from),
+            user_id: value.user_id.map(Ulid::from),
+            ip_address: value.ip_address,
+            user_agent: value.user_agent,
+            details: value.details,
+            previous_hash: value.previous_hash,
+            hash: value.hash,
+        })
+    }
+}
+
+impl Filter for AuditEventFilter<'_> {
+    fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
+        sea_query::Condition::all()
+            .add_option(self.user().map(|user| {
+                Expr::col((AuditEvents::Table, AuditEvents::UserId)).eq(Uuid::from(user.id))
+            }))
+            .add_option(self.actor().map(|actor| {
+                Expr::col((AuditEvents::Table, AuditEvents::ActorUserId)).eq(Uuid::from(actor.id))
+            }))
+            .add_option(
+                self.kind().map(|kind| {
+                    Expr::col((AuditEvents::Table, AuditEvents::Kind)).eq(kind.as_str())
+                }),
+            )
+            .add_option(
+                self.since_bound().map(|since| {
+                    Expr::col((AuditEvents::Table, AuditEvents::CreatedAt)).gte(since)
+                }),
+            )
+            .add_option(
+                self.until_bound()
+                    .map(|until| Expr::col((AuditEvents::Table, AuditEvents::CreatedAt)).lt(until)),
+            )
+    }
+}
+
+#[async_trait]
+impl<'c> AuditEventRepository for PgAuditEventRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.audit_event.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            audit_event.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<AuditEvent>, Self::Error> {
+        let res = sqlx::query_as!(
+            AuditEventLookup,
+            r#"
+                SELECT audit_event_id
+                     , created_at
+                     , kind
+                     , actor_user_id
+                     , user_id
+                     , ip_address as "ip_address: IpAddr"
+                     , user_agent
+                     , details
+                     , previous_hash
+                     , hash
+                FROM audit_events
+                WHERE audit_event_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_event.add",
+        skip_all,
+        fields(
+            db.query.text,
+            audit_event.id,
+            audit_event.kind = %kind,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: AuditEventKind,
+        actor_user_id: Option<Ulid>,
+        user_id: Option<Ulid>,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+        details: serde_json::Value,
+    ) -> Result<AuditEvent, Self::Error> {
+        // Serialize the insertions, so that two concurrent transactions don't
+        // chain their event to the same previous event. The lock is released at
+        // the end of the transaction.
+        sqlx::query!(
+            r#"
+                SELECT pg_advisory_xact_lock($1)
+            "#,
+            AUDIT_EVENTS_LOCK_KEY,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        let previous_hash = sqlx::query_scalar!(
+            r#"
+                SELECT hash
+                FROM audit_events
+                ORDER BY sequence DESC
+                LIMIT 1
+            "#,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        // The timestamp is truncated to the precision of the database, so that
+        // the hash can be verified after a round-trip
+        let now = clock.now();
+        let created_at = DateTime::from_timestamp_micros(now.timestamp_micros()).unwrap_or(now);
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("audit_event.id", tracing::field::display(id));
+
+        let mut event = AuditEvent {
+            id,
+            created_at,
+            kind,
+            actor_user_id,
+            user_id,
+            ip_address,
+            user_agent,
+            details,
+            previous_hash,
+            hash: String::new(),
+        };
+        event.hash = event.compute_hash();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO audit_events
+                    ( audit_event_id
+                    , created_at
+                    , kind
+                    , actor_user_id
+                    , user_id
+                    , ip_address
+                    , user_agent
+                    , details
+                    , previous_hash
+                    , hash
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+            Uuid::from(event.id),
+            event.created_at,
+            event.kind.as_str(),
+            event.actor_user_id.map(Uuid::from),
+            event.user_id.map(Uuid::from),
+            event.ip_address as Option<IpAddr>,
+            event.user_agent.as_deref(),
+            &event.details,
+            event.previous_hash.as_deref(),
+            &event.hash,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(event)
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_event.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: AuditEventFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<AuditEvent>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::AuditEventId)),
+                AuditEventLookupIden::AuditEventId,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::CreatedAt)),
+                AuditEventLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::Kind)),
+                AuditEventLookupIden::Kind,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::ActorUserId)),
+                AuditEventLookupIden::ActorUserId,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::UserId)),
+                AuditEventLookupIden::UserId,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::IpAddress)),
+                AuditEventLookupIden::IpAddress,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::UserAgent)),
+                AuditEventLookupIden::UserAgent,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::Details)),
+                AuditEventLookupIden::Details,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::PreviousHash)),
+                AuditEventLookupIden::PreviousHash,
+            )
+            .expr_as(
+                Expr::col((AuditEvents::Table, AuditEvents::Hash)),
+                AuditEventLookupIden::Hash,
+            )
+            .from(AuditEvents::Table)
+            .apply_filter(filter)
+            .generate_pagination((AuditEvents::Table, AuditEvents::AuditEventId), pagination)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<AuditEventLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).try_map(AuditEvent::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_event.count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn count(&mut self, filter: AuditEventFilter<'_>) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(Expr::col((AuditEvents::Table, AuditEvents::AuditEventId)).count())
+            .from(AuditEvents::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_event.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(&mut self, filter: AuditEventFilter<'_>) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((AuditEvents::Table, AuditEvents::AuditEventId)))
+            .from(AuditEvents::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_event.verify_chain",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn verify_chain(&mut self) -> Result<Option<Ulid>, Self::Error> {
+        let mut last_sequence: i64 = 0;
+        let mut previous_hash: Option<String> = None;
+        let mut first = true;
+
+        loop {
+            let rows = sqlx::query!(
+                r#"
+                    SELECT sequence
+                         , audit_event_id
+                         , created_at
+                         , kind
+                         , actor_user_id
+                         , user_id
+                         , ip_address as "ip_address: IpAddr"
+                         , user_agent
+                         , details
+                         , previous_hash
+                         , hash
+                    FROM audit_events
+                    WHERE sequence > $1
+                    ORDER BY sequence ASC
+                    LIMIT $2
+                "#,
+                last_sequence,
+                VERIFY_BATCH_SIZE,
+            )
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+            if rows.is_empty() {
+                return Ok(None);
+            }
+
+            for row in rows {
+                last_sequence = row.sequence;
+                let event = AuditEvent::try_from(AuditEventLookup {
+                    audit_event_id: row.audit_event_id,
+                    created_at: row.created_at,
+                    kind: row.kind,
+                    actor_user_id: row.actor_user_id,
+                    user_id: row.user_id,
+                    ip_address: row.ip_address,
+                    user_agent: row.user_agent,
+                    details: row.details,
+                    previous_hash: row.previous_hash,
+                    hash: row.hash,
+                })?;
+
+                // The first remaining event is the anchor of the chain: the
+                // events before it may have been removed by the retention policy
+                let chained = first || event.previous_hash == previous_hash;
+                if !chained || !event.is_hash_valid() {
+                    return Ok(Some(event.id));
+                }
+
+                first = false;
+                previous_hash = Some(event.hash);
+            }
+        }
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_event.cleanup_before",
+        skip_all,
+        fields(
+            db.query.text,
+            %before,
+        ),
+        err,
+    )]
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error> {
+        // Take the same lock as when inserting, so that the chain is not
+        // modified while we delete its head
+        sqlx::query!(
+            r#"
+                SELECT pg_advisory_xact_lock($1)
+            "#,
+            AUDIT_EVENTS_LOCK_KEY,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        // Events about users under legal hold must be kept. As only the head of
+        // the chain can be removed without breaking it, stop at the first of
+        // those events.
+        let first_held = sqlx::query_scalar!(
+            r#"
+                SELECT MIN(e.sequence)
+                FROM audit_events e
+                INNER JOIN users u
+                    ON u.user_id = e.user_id
+                    OR u.user_id = e.actor_user_id
+                WHERE u.legal_hold_at IS NOT NULL
+            "#,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM audit_events
+                WHERE created_at < $1
+                  AND ($2::bigint IS NULL OR sequence < $2)
+            "#,
+            before,
+            first_held,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        res.rows_affected()
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::AuditEventKind;
+    use mas_storage::{
+        audit::AuditEventFilter, clock::MockClock, user::UserRepository, Clock, Pagination,
+        RepositoryAccess,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_audit_event_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let alice = repo
+            .user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let bob = repo
+            .user()
+            .add(&mut rng, &clock, "bob".to_owned())
+            .await
+            .unwrap();
+
+        // An empty log is valid
+        assert_eq!(repo.audit_event().verify_chain().await.unwrap(), None);
+
+        let login = repo
+            .audit_event()
+            .add(
+                &mut rng,
+                &clock,
+                AuditEventKind::Login,
+                Some(alice.id),
+                Some(alice.id),
+                Some("127.0.0.1".parse().unwrap()),
+                Some("Mozilla/5.0".to_owned()),
+                serde_json::json!({ "method": "password" }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(login.previous_hash, None);
+
+        clock.advance(Duration::microseconds(10 * 1000 * 1000 + 1234));
+
+        let admin_action = repo
+            .audit_event()
+            .add(
+                &mut rng,
+                &clock,
+                AuditEventKind::AdminAction,
+                Some(alice.id),
+                Some(bob.id),
+                None,
+                None,
+                serde_json::json!({ "action": "lock" }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(admin_action.previous_hash.as_ref(), Some(&login.hash));
+
+        // Lookup the events
+        let lookup = repo.audit_event().lookup(login.id).await.unwrap().unwrap();
+        assert_eq!(lookup, login);
+        let lookup = repo
+            .audit_event()
+            .lookup(admin_action.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup, admin_action);
+
+        assert_eq!(repo.audit_event().verify_chain().await.unwrap(), None);
+
+        // Filter the events
+        let all = AuditEventFilter::new();
+        assert_eq!(repo.audit_event().count(all).await.unwrap(), 2);
+
+        let about_bob = all.for_user(&bob);
+        assert_eq!(repo.audit_event().count(about_bob).await.unwrap(), 1);
+
+        let by_alice = all.for_actor(&alice);
+        assert_eq!(repo.audit_event().count(by_alice).await.unwrap(), 2);
+
+        let logins = all.with_kind(AuditEventKind::Login);
+        let page = repo
+            .audit_event()
+            .list(logins, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges, vec![login.clone()]);
+
+        let recent = all.since(admin_action.created_at);
+        let page = repo
+            .audit_event()
+            .list(recent, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges, vec![admin_action.clone()]);
+
+        // Cleaning up old events keeps the rest of the chain valid
+        let deleted = repo
+            .audit_event()
+            .cleanup_before(admin_action.created_at)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+        assert_eq!(repo.audit_event().count(all).await.unwrap(), 1);
+        assert_eq!(repo.audit_event().verify_chain().await.unwrap(), None);
+
+        repo.save().await.unwrap();
+
+        // Updating an event is not allowed
+        let res = sqlx::query("UPDATE audit_events SET kind = 'login_failed'")
+            .execute(&pool)
+            .await;
+        assert!(res.is_err());
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_audit_event_cleanup_legal_hold(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let alice = repo
+            .user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let bob = repo
+            .user()
+            .add(&mut rng, &clock, "bob".to_owned())
+            .await
+            .unwrap();
+
+        for user in [&alice, &bob, &alice] {
+            repo.audit_event()
+                .add(
+                    &mut rng,
+                    &clock,
+                    AuditEventKind::Login,
+                    Some(user.id),
+                    Some(user.id),
+                    None,
+                    None,
+                    serde_json::json!({}),
+                )
+                .await
+                .unwrap();
+            clock.advance(Duration::minutes(1));
+        }
+
+        let bob = repo.user().place_legal_hold(&clock, bob).await.unwrap();
+        assert!(bob.is_under_legal_hold());
+
+        // Only the events before the first one about bob are deleted
+        let all = AuditEventFilter::new();
+        let deleted = repo
+            .audit_event()
+            .cleanup_before(clock.now())
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+        assert_eq!(repo.audit_event().count(all).await.unwrap(), 2);
+        assert_eq!(repo.audit_event().verify_chain().await.unwrap(), None);
+
+        // Once the hold is released, the rest can be deleted
+        repo.user().release_legal_hold(bob).await.unwrap();
+        let deleted = repo
+            .audit_event()
+            .cleanup_before(clock.now())
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(repo.audit_event().count(all).await.unwrap(), 0);
+    }
+}
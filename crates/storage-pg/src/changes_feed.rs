@@ -0,0 +1,402 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`ChangesFeedRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{ChangesFeedEntry, WebhookEvent};
+use mas_storage::{
+    changes_feed::{ChangesFeedFilter, ChangesFeedRepository},
+    pagination::Count,
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    filter::{Filter, StatementExt},
+    iden::ChangesFeed,
+    pagination::{estimate_count, QueryBuilderExt},
+    DatabaseError, DatabaseInconsistencyError, ExecuteExt,
+};
+
+/// The key of the advisory lock used to serialize the insertion of entries, so
+/// that their IDs are increasing in commit order
+const CHANGES_FEED_LOCK_KEY: i64 = 0x6d61_735f_6368_6e67;
+
+/// An implementation of [`ChangesFeedRepository`] for a PostgreSQL connection
+pub struct PgChangesFeedRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgChangesFeedRepository<'c> {
+    /// Create a new [`PgChangesFeedRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+mod priv_ {
+    // The enum_def macro generates a public enum, which we don't want, because it
+    // triggers the missing docs warning
+
+    use chrono::{DateTime, Utc};
+    use sea_query::enum_def;
+    use uuid::Uuid;
+
+    #[derive(sqlx::FromRow)]
+    #[enum_def]
+    pub(super) struct ChangesFeedEntryLookup {
+        pub(super) changes_feed_entry_id: Uuid,
+        pub(super) event_id: Uuid,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) kind: String,
+        pub(super) data: serde_json::Value,
+    }
+}
+
+use priv_::{ChangesFeedEntryLookup, ChangesFeedEntryLookupIden};
+
+impl TryFrom<ChangesFeedEntryLookup> for ChangesFeedEntry {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: ChangesFeedEntryLookup) -> Result<Self, Self::Error> {
+        let id = value.changes_feed_entry_id.into();
+        let kind = value.kind.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("changes_feed")
+                .column("kind")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(ChangesFeedEntry {
+            id,
+            event: WebhookEvent {
+                id: value.event_id.into(),
+                kind,
+                created_at: value.created_at,
+                data: value.data,
+            },
+        })
+    }
+}
+
+impl Filter for ChangesFeedFilter {
+    fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
+        sea_query::Condition::all().add_option(
+            self.kind()
+                .map(|kind| Expr::col((ChangesFeed::Table, ChangesFeed::Kind)).eq(kind.as_str())),
+        )
+    }
+}
+
+#[async_trait]
+impl<'c> ChangesFeedRepository for PgChangesFeedRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.changes_feed.add",
+        skip_all,
+        fields(
+            db.query.text,
+            changes_feed_entry.id,
+            webhook_event.id = %event.id,
+            webhook_event.kind = %event.kind,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        event: &WebhookEvent,
+    ) -> Result<(), Self::Error> {
+        // Serialize the insertions, so that an entry is never committed after
+        // an entry with a greater ID, which would make consumers miss it. The
+        // lock is released at the end of the transaction.
+        sqlx::query!(
+            r#"
+                SELECT pg_advisory_xact_lock($1)
+            "#,
+            CHANGES_FEED_LOCK_KEY,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        let last_id = sqlx::query_scalar!(
+            r#"
+                SELECT changes_feed_entry_id
+                FROM changes_feed
+                ORDER BY changes_feed_entry_id DESC
+                LIMIT 1
+            "#,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        // Make sure the ID is greater than the last one, even if the clocks of
+        // two workers are not in sync
+        let id = Ulid::from_datetime_with_source(clock.now().into(), rng);
+        let id = last_id
+            .map(Ulid::from)
+            .and_then(|last| last.increment())
+            .map_or(id, |next| id.max(next));
+        tracing::Span::current().record("changes_feed_entry.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO changes_feed
+                    ( changes_feed_entry_id
+                    , event_id
+                    , created_at
+                    , kind
+                    , data
+                    )
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (event_id) DO NOTHING
+            "#,
+            Uuid::from(id),
+            Uuid::from(event.id),
+            event.created_at,
+            event.kind.as_str(),
+            &event.data,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.changes_feed.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: ChangesFeedFilter,
+        pagination: Pagination,
+    ) -> Result<Page<ChangesFeedEntry>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((ChangesFeed::Table, ChangesFeed::ChangesFeedEntryId)),
+                ChangesFeedEntryLookupIden::ChangesFeedEntryId,
+            )
+            .expr_as(
+                Expr::col((ChangesFeed::Table, ChangesFeed::EventId)),
+                ChangesFeedEntryLookupIden::EventId,
+            )
+            .expr_as(
+                Expr::col((ChangesFeed::Table, ChangesFeed::CreatedAt)),
+                ChangesFeedEntryLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((ChangesFeed::Table, ChangesFeed::Kind)),
+                ChangesFeedEntryLookupIden::Kind,
+            )
+            .expr_as(
+                Expr::col((ChangesFeed::Table, ChangesFeed::Data)),
+                ChangesFeedEntryLookupIden::Data,
+            )
+            .from(ChangesFeed::Table)
+            .apply_filter(filter)
+            .generate_pagination(
+                (ChangesFeed::Table, ChangesFeed::ChangesFeedEntryId),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<ChangesFeedEntryLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination
+            .process(edges)
+            .try_map(ChangesFeedEntry::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.changes_feed.count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn count(&mut self, filter: ChangesFeedFilter) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(Expr::col((ChangesFeed::Table, ChangesFeed::ChangesFeedEntryId)).count())
+            .from(ChangesFeed::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.changes_feed.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(&mut self, filter: ChangesFeedFilter) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((
+                ChangesFeed::Table,
+                ChangesFeed::ChangesFeedEntryId,
+            )))
+            .from(ChangesFeed::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+
+    #[tracing::instrument(
+        name = "db.changes_feed.cleanup_before",
+        skip_all,
+        fields(
+            db.query.text,
+            %before,
+        ),
+        err,
+    )]
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM changes_feed
+                WHERE created_at < $1
+            "#,
+            before,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        res.rows_affected()
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::{WebhookEvent, WebhookEventKind};
+    use mas_storage::{
+        changes_feed::ChangesFeedFilter, clock::MockClock, pagination::Count, Clock, Pagination,
+        RepositoryAccess,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_changes_feed_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let registered = WebhookEvent {
+            id: Ulid::from_datetime_with_source(clock.now().into(), &mut rng),
+            kind: WebhookEventKind::UserRegistered,
+            created_at: clock.now(),
+            data: serde_json::json!({ "username": "alice" }),
+        };
+        repo.changes_feed()
+            .add(&mut rng, &clock, &registered)
+            .await
+            .unwrap();
+
+        // Recording the same event twice is a no-op
+        repo.changes_feed()
+            .add(&mut rng, &clock, &registered)
+            .await
+            .unwrap();
+
+        clock.advance(Duration::minutes(1));
+
+        let updated = WebhookEvent {
+            id: Ulid::from_datetime_with_source(clock.now().into(), &mut rng),
+            kind: WebhookEventKind::UserUpdated,
+            created_at: clock.now(),
+            data: serde_json::json!({ "username": "alice", "locked": true }),
+        };
+
+        // Even if the clock goes backwards, the entries are ordered
+        let late_clock = MockClock::default();
+        repo.changes_feed()
+            .add(&mut rng, &late_clock, &updated)
+            .await
+            .unwrap();
+
+        let all = ChangesFeedFilter::new();
+        assert_eq!(repo.changes_feed().count(all).await.unwrap(), 2);
+
+        let page = repo
+            .changes_feed()
+            .list(all, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 2);
+        assert_eq!(page.edges[0].event, registered);
+        assert_eq!(page.edges[1].event, updated);
+        assert!(page.edges[0].id < page.edges[1].id);
+
+        // Poll from the first entry
+        let page = repo
+            .changes_feed()
+            .list(all, Pagination::first(10).after(page.edges[0].id))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        assert_eq!(page.edges[0].event, updated);
+
+        let updates = all.with_kind(WebhookEventKind::UserUpdated);
+        assert_eq!(repo.changes_feed().count(updates).await.unwrap(), 1);
+        assert_eq!(
+            repo.changes_feed().estimate_count(updates).await.unwrap(),
+            Count::Exact(1)
+        );
+
+        let deleted = repo
+            .changes_feed()
+            .cleanup_before(updated.created_at)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+        assert_eq!(repo.changes_feed().count(all).await.unwrap(), 1);
+
+        repo.save().await.unwrap();
+    }
+}
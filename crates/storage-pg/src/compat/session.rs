@@ -7,7 +7,7 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
     BrowserSession, CompatSession, CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device,
     User, UserAgent,
@@ -17,7 +17,7 @@ use mas_storage::{
     Clock, Page, Pagination,
 };
 use rand::RngCore;
-use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
+use sea_query::{enum_def, Expr, PgFunc, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
 use ulid::Ulid;
@@ -56,6 +56,7 @@ struct CompatSessionLookup {
     user_agent: Option<String>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
+    tags: Vec<String>,
 }
 
 impl TryFrom<CompatSessionLookup> for CompatSession {
@@ -86,6 +87,7 @@ impl TryFrom<CompatSessionLookup> for CompatSession {
             user_agent: value.user_agent.map(UserAgent::parse),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
+            tags: value.tags,
         };
 
         Ok(session)
@@ -105,6 +107,7 @@ struct CompatSessionAndSsoLoginLookup {
     user_agent: Option<String>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
+    tags: Vec<String>,
     compat_sso_login_id: Option<Uuid>,
     compat_sso_login_token: Option<String>,
     compat_sso_login_redirect_uri: Option<String>,
@@ -141,6 +144,7 @@ impl TryFrom<CompatSessionAndSsoLoginLookup> for (CompatSession, Option<CompatSs
             user_agent: value.user_agent.map(UserAgent::parse),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
+            tags: value.tags,
         };
 
         match (
@@ -256,6 +260,12 @@ impl Filter for CompatSessionFilter<'_> {
             .add_option(self.device().map(|device| {
                 Expr::col((CompatSessions::Table, CompatSessions::DeviceId)).eq(device.as_str())
             }))
+            .add_option(self.tag().map(|tag| {
+                Expr::val(tag).eq(PgFunc::any(Expr::col((
+                    CompatSessions::Table,
+                    CompatSessions::Tags,
+                ))))
+            }))
     }
 }
 
@@ -286,6 +296,7 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
                      , user_agent
                      , last_active_at
                      , last_active_ip as "last_active_ip: IpAddr"
+                     , tags
                 FROM compat_sessions
                 WHERE compat_session_id = $1
             "#,
@@ -354,6 +365,7 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
             user_agent: None,
             last_active_at: None,
             last_active_ip: None,
+            tags: Vec::new(),
         })
     }
 
@@ -423,6 +435,71 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
         Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
     }
 
+    #[tracing::instrument(
+        name = "db.compat_session.finish_expired",
+        skip_all,
+        fields(db.query.text),
+        err,
+    )]
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Option<Duration>,
+        inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<Vec<CompatSession>, Self::Error> {
+        let finished_at = clock.now();
+        let limit: i64 = limit.try_into().unwrap_or(i64::MAX);
+        let res = sqlx::query_as!(
+            CompatSessionLookup,
+            r#"
+                UPDATE compat_sessions
+                SET finished_at = $1
+                WHERE compat_session_id IN (
+                    SELECT s.compat_session_id
+                    FROM compat_sessions s
+                    INNER JOIN users u USING (user_id)
+                    WHERE s.finished_at IS NULL
+                      AND (
+                        s.created_at + make_interval(secs => $2::BIGINT) < $1
+                        OR (
+                          NOT u.is_bot
+                          AND COALESCE(s.last_active_at, s.created_at)
+                            + make_interval(secs => $3::BIGINT)
+                            < $1
+                        )
+                      )
+                    LIMIT $4
+                )
+                RETURNING compat_session_id
+                        , device_id
+                        , user_id
+                        , user_session_id
+                        , created_at
+                        , finished_at
+                        , is_synapse_admin
+                        , user_agent
+                        , last_active_at
+                        , last_active_ip as "last_active_ip: IpAddr"
+                        , tags
+            "#,
+            finished_at,
+            session_ttl.map(|ttl| ttl.num_seconds()),
+            inactivity_ttl.map(|ttl| ttl.num_seconds()),
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let mut sessions = Vec::with_capacity(res.len());
+        for row in res {
+            sessions.push(row.try_into()?);
+        }
+
+        Ok(sessions)
+    }
+
     #[tracing::instrument(
         name = "db.compat_session.list",
         skip_all,
@@ -477,6 +554,10 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
                 Expr::col((CompatSessions::Table, CompatSessions::LastActiveIp)),
                 CompatSessionAndSsoLoginLookupIden::LastActiveIp,
             )
+            .expr_as(
+                Expr::col((CompatSessions::Table, CompatSessions::Tags)),
+                CompatSessionAndSsoLoginLookupIden::Tags,
+            )
             .expr_as(
                 Expr::col((CompatSsoLogins::Table, CompatSsoLogins::CompatSsoLoginId)),
                 CompatSessionAndSsoLoginLookupIden::CompatSsoLoginId,
@@ -629,4 +710,77 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
 
         Ok(compat_session)
     }
+
+    #[tracing::instrument(
+        name = "db.compat_session.add_tag",
+        skip_all,
+        fields(
+            db.query.text,
+            %compat_session.id,
+            compat_session.tag = tag,
+        ),
+        err,
+    )]
+    async fn add_tag(
+        &mut self,
+        mut compat_session: CompatSession,
+        tag: &str,
+    ) -> Result<CompatSession, Self::Error> {
+        // Do the change in a single statement, so that concurrent changes to the
+        // tags are not lost
+        let tags = sqlx::query_scalar!(
+            r#"
+                UPDATE compat_sessions
+                SET tags = CASE
+                    WHEN $2 = ANY(tags) THEN tags
+                    ELSE array_append(tags, $2)
+                END
+                WHERE compat_session_id = $1
+                RETURNING tags
+            "#,
+            Uuid::from(compat_session.id),
+            tag,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        compat_session.tags = tags;
+
+        Ok(compat_session)
+    }
+
+    #[tracing::instrument(
+        name = "db.compat_session.remove_tag",
+        skip_all,
+        fields(
+            db.query.text,
+            %compat_session.id,
+            compat_session.tag = tag,
+        ),
+        err,
+    )]
+    async fn remove_tag(
+        &mut self,
+        mut compat_session: CompatSession,
+        tag: &str,
+    ) -> Result<CompatSession, Self::Error> {
+        let tags = sqlx::query_scalar!(
+            r#"
+                UPDATE compat_sessions
+                SET tags = array_remove(tags, $2)
+                WHERE compat_session_id = $1
+                RETURNING tags
+            "#,
+            Uuid::from(compat_session.id),
+            tag,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        compat_session.tags = tags;
+
+        Ok(compat_session)
+    }
 }
@@ -0,0 +1,412 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`EmailDeliveryFailureRepository`]
+
+use async_trait::async_trait;
+use mas_data_model::EmailDeliveryFailure;
+use mas_storage::{
+    email_delivery::{EmailDeliveryFailureFilter, EmailDeliveryFailureRepository},
+    pagination::Count,
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    filter::{Filter, StatementExt},
+    iden::EmailDeliveryFailures,
+    pagination::{estimate_count, QueryBuilderExt},
+    DatabaseError, ExecuteExt,
+};
+
+/// An implementation of [`EmailDeliveryFailureRepository`] for a PostgreSQL
+/// connection
+pub struct PgEmailDeliveryFailureRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgEmailDeliveryFailureRepository<'c> {
+    /// Create a new [`PgEmailDeliveryFailureRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+mod priv_ {
+    // The enum_def macro generates a public enum, which we don't want, because it
+    // triggers the missing docs warning
+
+    use chrono::{DateTime, Utc};
+    use sea_query::enum_def;
+    use uuid::Uuid;
+
+    #[derive(sqlx::FromRow)]
+    #[enum_def]
+    pub(super) struct EmailDeliveryFailureLookup {
+        pub(super) email_delivery_failure_id: Uuid,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) email: String,
+        pub(super) domain: String,
+        pub(super) permanent: bool,
+        pub(super) reason: String,
+    }
+}
+
+use priv_::{EmailDeliveryFailureLookup, EmailDeliveryFailureLookupIden};
+
+impl From<EmailDeliveryFailureLookup> for EmailDeliveryFailure {
+    fn from(value: EmailDeliveryFailureLookup) -> Self {
+        Self {
+            id: value.email_delivery_failure_id.into(),
+            created_at: value.created_at,
+            email: value.email,
+            domain: value.domain,
+            permanent: value.permanent,
+            reason: value.reason,
+        }
+    }
+}
+
+impl Filter for EmailDeliveryFailureFilter<'_> {
+    fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
+        sea_query::Condition::all()
+            .add_option(self.domain().map(|domain| {
+                Expr::col((EmailDeliveryFailures::Table, EmailDeliveryFailures::Domain))
+                    .eq(domain.to_lowercase())
+            }))
+            .add_option(self.permanent().map(|permanent| {
+                Expr::col((
+                    EmailDeliveryFailures::Table,
+                    EmailDeliveryFailures::Permanent,
+                ))
+                .eq(permanent)
+            }))
+    }
+}
+
+#[async_trait]
+impl<'c> EmailDeliveryFailureRepository for PgEmailDeliveryFailureRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.email_delivery_failure.add",
+        skip_all,
+        fields(
+            db.query.text,
+            email_delivery_failure.id,
+            email_delivery_failure.permanent = permanent,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        email: &str,
+        permanent: bool,
+        reason: String,
+    ) -> Result<EmailDeliveryFailure, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("email_delivery_failure.id", tracing::field::display(id));
+
+        let domain = email
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+            .to_lowercase();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO email_delivery_failures
+                    ( email_delivery_failure_id
+                    , created_at
+                    , email
+                    , domain
+                    , permanent
+                    , reason
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            created_at,
+            email,
+            &domain,
+            permanent,
+            &reason,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(EmailDeliveryFailure {
+            id,
+            created_at,
+            email: email.to_owned(),
+            domain,
+            permanent,
+            reason,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.email_delivery_failure.is_suppressed",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn is_suppressed(&mut self, email: &str) -> Result<bool, Self::Error> {
+        let suppressed = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1
+                    FROM email_delivery_failures
+                    WHERE LOWER(email) = LOWER($1)
+                      AND permanent
+                ) AS "suppressed!"
+            "#,
+            email,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(suppressed)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_delivery_failure.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<EmailDeliveryFailure>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((
+                    EmailDeliveryFailures::Table,
+                    EmailDeliveryFailures::EmailDeliveryFailureId,
+                )),
+                EmailDeliveryFailureLookupIden::EmailDeliveryFailureId,
+            )
+            .expr_as(
+                Expr::col((
+                    EmailDeliveryFailures::Table,
+                    EmailDeliveryFailures::CreatedAt,
+                )),
+                EmailDeliveryFailureLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((EmailDeliveryFailures::Table, EmailDeliveryFailures::Email)),
+                EmailDeliveryFailureLookupIden::Email,
+            )
+            .expr_as(
+                Expr::col((EmailDeliveryFailures::Table, EmailDeliveryFailures::Domain)),
+                EmailDeliveryFailureLookupIden::Domain,
+            )
+            .expr_as(
+                Expr::col((
+                    EmailDeliveryFailures::Table,
+                    EmailDeliveryFailures::Permanent,
+                )),
+                EmailDeliveryFailureLookupIden::Permanent,
+            )
+            .expr_as(
+                Expr::col((EmailDeliveryFailures::Table, EmailDeliveryFailures::Reason)),
+                EmailDeliveryFailureLookupIden::Reason,
+            )
+            .from(EmailDeliveryFailures::Table)
+            .apply_filter(filter)
+            .generate_pagination(
+                (
+                    EmailDeliveryFailures::Table,
+                    EmailDeliveryFailures::EmailDeliveryFailureId,
+                ),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<EmailDeliveryFailureLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).map(EmailDeliveryFailure::from);
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_delivery_failure.count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn count(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+    ) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(
+                Expr::col((
+                    EmailDeliveryFailures::Table,
+                    EmailDeliveryFailures::EmailDeliveryFailureId,
+                ))
+                .count(),
+            )
+            .from(EmailDeliveryFailures::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.email_delivery_failure.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+    ) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((
+                EmailDeliveryFailures::Table,
+                EmailDeliveryFailures::EmailDeliveryFailureId,
+            )))
+            .from(EmailDeliveryFailures::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::{
+        clock::MockClock, email_delivery::EmailDeliveryFailureFilter, Pagination, RepositoryAccess,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_email_delivery_failure_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        assert!(!repo
+            .email_delivery_failure()
+            .is_suppressed("alice@example.com")
+            .await
+            .unwrap());
+
+        // A deferred email doesn't suppress the address
+        let deferred = repo
+            .email_delivery_failure()
+            .add(
+                &mut rng,
+                &clock,
+                "alice@Example.com",
+                false,
+                "421 Try again later".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(deferred.domain, "example.com");
+        assert!(!repo
+            .email_delivery_failure()
+            .is_suppressed("alice@example.com")
+            .await
+            .unwrap());
+
+        // A hard bounce does, regardless of the case
+        clock.advance(chrono::Duration::microseconds(10));
+        repo.email_delivery_failure()
+            .add(
+                &mut rng,
+                &clock,
+                "alice@example.com",
+                true,
+                "550 No such user".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert!(repo
+            .email_delivery_failure()
+            .is_suppressed("Alice@Example.com")
+            .await
+            .unwrap());
+
+        repo.email_delivery_failure()
+            .add(
+                &mut rng,
+                &clock,
+                "bob@example.org",
+                false,
+                "Connection refused".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        let all = EmailDeliveryFailureFilter::new();
+        assert_eq!(repo.email_delivery_failure().count(all).await.unwrap(), 3);
+
+        let filter = EmailDeliveryFailureFilter::new().for_domain("EXAMPLE.COM");
+        assert_eq!(
+            repo.email_delivery_failure().count(filter).await.unwrap(),
+            2
+        );
+
+        let filter = filter.permanent_only();
+        let page = repo
+            .email_delivery_failure()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        assert_eq!(page.edges[0].reason, "550 No such user");
+
+        let filter = EmailDeliveryFailureFilter::new().deferred_only();
+        let page = repo
+            .email_delivery_failure()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 2);
+        assert_eq!(page.edges[0], deferred);
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`EventOutboxRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::WebhookEvent;
+use mas_storage::event_outbox::EventOutboxRepository;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, DatabaseInconsistencyError, ExecuteExt};
+
+/// An implementation of [`EventOutboxRepository`] for a PostgreSQL connection
+pub struct PgEventOutboxRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgEventOutboxRepository<'c> {
+    /// Create a new [`PgEventOutboxRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct EventOutboxLookup {
+    event_outbox_id: Uuid,
+    created_at: DateTime<Utc>,
+    kind: String,
+    data: serde_json::Value,
+}
+
+impl TryFrom<EventOutboxLookup> for WebhookEvent {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: EventOutboxLookup) -> Result<Self, Self::Error> {
+        let id = value.event_outbox_id.into();
+        let kind = value.kind.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("event_outbox")
+                .column("kind")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(WebhookEvent {
+            id,
+            kind,
+            created_at: value.created_at,
+            data: value.data,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> EventOutboxRepository for PgEventOutboxRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.event_outbox.add",
+        skip_all,
+        fields(
+            db.query.text,
+            event.id = %event.id,
+            event.kind = %event.kind,
+        ),
+        err,
+    )]
+    async fn add(&mut self, event: &WebhookEvent) -> Result<(), Self::Error> {
+        sqlx::query!(
+            r#"
+                INSERT INTO event_outbox
+                    (event_outbox_id, created_at, kind, data)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (event_outbox_id) DO NOTHING
+            "#,
+            Uuid::from(event.id),
+            event.created_at,
+            event.kind.as_str(),
+            &event.data,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.event_outbox.fetch_batch",
+        skip_all,
+        fields(
+            db.query.text,
+            limit = limit,
+        ),
+        err,
+    )]
+    async fn fetch_batch(&mut self, limit: usize) -> Result<Vec<WebhookEvent>, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let res = sqlx::query_as!(
+            EventOutboxLookup,
+            r#"
+                SELECT event_outbox_id
+                     , created_at
+                     , kind
+                     , data
+                FROM event_outbox
+                ORDER BY event_outbox_id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            "#,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let events = res
+            .into_iter()
+            .map(WebhookEvent::try_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(events)
+    }
+
+    #[tracing::instrument(
+        name = "db.event_outbox.remove",
+        skip_all,
+        fields(
+            db.query.text,
+            count = ids.len(),
+        ),
+        err,
+    )]
+    async fn remove(&mut self, ids: &[Ulid]) -> Result<usize, Self::Error> {
+        let ids: Vec<Uuid> = ids.iter().copied().map(Uuid::from).collect();
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM event_outbox
+                WHERE event_outbox_id = ANY($1)
+            "#,
+            &ids,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::WebhookEventKind;
+    use mas_storage::{clock::MockClock, job::DispatchWebhookJob, Clock, RepositoryAccess};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_event_outbox_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let first = DispatchWebhookJob::new(
+            &mut rng,
+            &clock,
+            WebhookEventKind::UserRegistered,
+            serde_json::json!({ "username": "alice" }),
+        )
+        .event()
+        .clone();
+        clock.advance(Duration::microseconds(1000));
+        let second = DispatchWebhookJob::new(
+            &mut rng,
+            &clock,
+            WebhookEventKind::SessionCreated,
+            serde_json::json!({ "username": "alice" }),
+        )
+        .event()
+        .clone();
+
+        assert!(repo
+            .event_outbox()
+            .fetch_batch(10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        repo.event_outbox().add(&second).await.unwrap();
+        repo.event_outbox().add(&first).await.unwrap();
+        // Adding the same event twice is a no-op
+        repo.event_outbox().add(&first).await.unwrap();
+
+        // Events are returned in order
+        let batch = repo.event_outbox().fetch_batch(10).await.unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].id, first.id);
+        assert_eq!(batch[0].kind, WebhookEventKind::UserRegistered);
+        assert_eq!(
+            batch[0].created_at,
+            clock.now() - Duration::microseconds(1000)
+        );
+        assert_eq!(batch[1].id, second.id);
+
+        let batch = repo.event_outbox().fetch_batch(1).await.unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].id, first.id);
+
+        let removed = repo.event_outbox().remove(&[first.id]).await.unwrap();
+        assert_eq!(removed, 1);
+
+        let batch = repo.event_outbox().fetch_batch(10).await.unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].id, second.id);
+
+        repo.save().await.unwrap();
+    }
+}
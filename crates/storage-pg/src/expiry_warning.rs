@@ -0,0 +1,157 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`ExpiryWarningRepository`]
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::ExpiringCredential;
+use mas_storage::{expiry_warning::ExpiryWarningRepository, Clock};
+use sqlx::PgConnection;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`ExpiryWarningRepository`] for a PostgreSQL
+/// connection
+pub struct PgExpiryWarningRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgExpiryWarningRepository<'c> {
+    /// Create a new [`PgExpiryWarningRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl<'c> ExpiryWarningRepository for PgExpiryWarningRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.expiry_warning.mark_notified",
+        skip_all,
+        fields(
+            db.query.text,
+            credential.kind = %credential.kind,
+            credential.name = credential.name,
+        ),
+        err,
+    )]
+    async fn mark_notified(
+        &mut self,
+        clock: &dyn Clock,
+        credential: &ExpiringCredential,
+        renotify_after: Duration,
+    ) -> Result<bool, Self::Error> {
+        let now = clock.now();
+
+        // The row is only updated if the expiry date changed, or if the last
+        // warning is old enough. Concurrent workers are serialized by the row
+        // lock, so only one of them gets to send the warning
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO expiry_warnings
+                    ( kind
+                    , name
+                    , expires_at
+                    , notified_at
+                    )
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (kind, name) DO UPDATE
+                SET expires_at = EXCLUDED.expires_at
+                  , notified_at = EXCLUDED.notified_at
+                WHERE expiry_warnings.expires_at <> EXCLUDED.expires_at
+                   OR expiry_warnings.notified_at <= $5
+            "#,
+            credential.kind.as_str(),
+            &credential.name,
+            credential.expires_at,
+            now,
+            now - renotify_after,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::{ExpiringCredential, ExpiringCredentialKind};
+    use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_expiry_warning_repo(pool: PgPool) {
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+        let week = Duration::try_weeks(1).unwrap();
+
+        let credential = ExpiringCredential::new(
+            ExpiringCredentialKind::TlsCertificate,
+            "web",
+            clock.now() + Duration::try_days(20).unwrap(),
+        );
+
+        // The first warning is always sent, but not the following ones
+        assert!(repo
+            .expiry_warning()
+            .mark_notified(&clock, &credential, week)
+            .await
+            .unwrap());
+        assert!(!repo
+            .expiry_warning()
+            .mark_notified(&clock, &credential, week)
+            .await
+            .unwrap());
+
+        // Other credentials are tracked separately
+        let other = ExpiringCredential::new(
+            ExpiringCredentialKind::SigningKey,
+            "web",
+            credential.expires_at,
+        );
+        assert!(repo
+            .expiry_warning()
+            .mark_notified(&clock, &other, week)
+            .await
+            .unwrap());
+
+        // A new expiry date, e.g. after the certificate was renewed, is warned
+        // about immediately
+        let renewed = ExpiringCredential::new(
+            credential.kind,
+            credential.name.clone(),
+            credential.expires_at + Duration::try_days(90).unwrap(),
+        );
+        assert!(repo
+            .expiry_warning()
+            .mark_notified(&clock, &renewed, week)
+            .await
+            .unwrap());
+
+        // The warning is sent again once the delay passed
+        clock.advance(Duration::try_days(6).unwrap());
+        assert!(!repo
+            .expiry_warning()
+            .mark_notified(&clock, &renewed, week)
+            .await
+            .unwrap());
+        clock.advance(Duration::try_days(1).unwrap());
+        assert!(repo
+            .expiry_warning()
+            .mark_notified(&clock, &renewed, week)
+            .await
+            .unwrap());
+    }
+}
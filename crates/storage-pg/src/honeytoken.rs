@@ -0,0 +1,529 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`HoneytokenRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{Honeytoken, HoneytokenKind};
+use mas_storage::{
+    honeytoken::{HoneytokenFilter, HoneytokenRepository},
+    pagination::Count,
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    filter::{Filter, StatementExt},
+    iden::Honeytokens,
+    pagination::{estimate_count, QueryBuilderExt},
+    DatabaseError, DatabaseInconsistencyError, ExecuteExt,
+};
+
+/// An implementation of [`HoneytokenRepository`] for a PostgreSQL connection
+pub struct PgHoneytokenRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgHoneytokenRepository<'c> {
+    /// Create a new [`PgHoneytokenRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+mod priv_ {
+    // The enum_def macro generates a public enum, which we don't want, because it
+    // triggers the missing docs warning
+
+    use chrono::{DateTime, Utc};
+    use sea_query::enum_def;
+    use uuid::Uuid;
+
+    #[derive(sqlx::FromRow)]
+    #[enum_def]
+    pub(super) struct HoneytokenLookup {
+        pub(super) honeytoken_id: Uuid,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) kind: String,
+        pub(super) value: String,
+        pub(super) label: String,
+        pub(super) last_used_at: Option<DateTime<Utc>>,
+    }
+}
+
+use priv_::{HoneytokenLookup, HoneytokenLookupIden};
+
+impl TryFrom<HoneytokenLookup> for Honeytoken {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: HoneytokenLookup) -> Result<Self, Self::Error> {
+        let id = value.honeytoken_id.into();
+        let kind = value.kind.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("honeytokens")
+                .column("kind")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(Honeytoken {
+            id,
+            created_at: value.created_at,
+            kind,
+            value: value.value,
+            label: value.label,
+            last_used_at: value.last_used_at,
+        })
+    }
+}
+
+impl Filter for HoneytokenFilter {
+    fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
+        sea_query::Condition::all()
+            .add_option(
+                self.kind().map(|kind| {
+                    Expr::col((Honeytokens::Table, Honeytokens::Kind)).eq(kind.as_str())
+                }),
+            )
+            .add_option(self.used().map(|used| {
+                if used {
+                    Expr::col((Honeytokens::Table, Honeytokens::LastUsedAt)).is_not_null()
+                } else {
+                    Expr::col((Honeytokens::Table, Honeytokens::LastUsedAt)).is_null()
+                }
+            }))
+    }
+}
+
+#[async_trait]
+impl<'c> HoneytokenRepository for PgHoneytokenRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.honeytoken.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            honeytoken.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<Honeytoken>, Self::Error> {
+        let res = sqlx::query_as!(
+            HoneytokenLookup,
+            r#"
+                SELECT honeytoken_id
+                     , created_at
+                     , kind
+                     , value
+                     , label
+                     , last_used_at
+                FROM honeytokens
+                WHERE honeytoken_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.find_by_token",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn find_by_token(&mut self, token: &str) -> Result<Option<Honeytoken>, Self::Error> {
+        let res = sqlx::query_as!(
+            HoneytokenLookup,
+            r#"
+                SELECT honeytoken_id
+                     , created_at
+                     , kind
+                     , value
+                     , label
+                     , last_used_at
+                FROM honeytokens
+                WHERE value = $1
+                  AND kind <> 'account'
+            "#,
+            token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.find_account",
+        skip_all,
+        fields(
+            db.query.text,
+            honeytoken.value = username,
+        ),
+        err,
+    )]
+    async fn find_account(&mut self, username: &str) -> Result<Option<Honeytoken>, Self::Error> {
+        let res = sqlx::query_as!(
+            HoneytokenLookup,
+            r#"
+                SELECT honeytoken_id
+                     , created_at
+                     , kind
+                     , value
+                     , label
+                     , last_used_at
+                FROM honeytokens
+                WHERE value = $1
+                  AND kind = 'account'
+            "#,
+            username,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.add",
+        skip_all,
+        fields(
+            db.query.text,
+            honeytoken.id,
+            honeytoken.kind = %kind,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: HoneytokenKind,
+        value: String,
+        label: String,
+    ) -> Result<Honeytoken, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("honeytoken.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO honeytokens
+                    ( honeytoken_id
+                    , created_at
+                    , kind
+                    , value
+                    , label
+                    )
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            Uuid::from(id),
+            created_at,
+            kind.as_str(),
+            &value,
+            &label,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(Honeytoken {
+            id,
+            created_at,
+            kind,
+            value,
+            label,
+            last_used_at: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.record_use",
+        skip_all,
+        fields(
+            db.query.text,
+            %honeytoken.id,
+        ),
+        err,
+    )]
+    async fn record_use(
+        &mut self,
+        mut honeytoken: Honeytoken,
+        used_at: DateTime<Utc>,
+    ) -> Result<Honeytoken, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE honeytokens
+                SET last_used_at = GREATEST(last_used_at, $2)
+                WHERE honeytoken_id = $1
+            "#,
+            Uuid::from(honeytoken.id),
+            used_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        honeytoken.last_used_at = honeytoken.last_used_at.max(Some(used_at));
+        Ok(honeytoken)
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.remove",
+        skip_all,
+        fields(
+            db.query.text,
+            %honeytoken.id,
+        ),
+        err,
+    )]
+    async fn remove(&mut self, honeytoken: Honeytoken) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM honeytokens
+                WHERE honeytoken_id = $1
+            "#,
+            Uuid::from(honeytoken.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: HoneytokenFilter,
+        pagination: Pagination,
+    ) -> Result<Page<Honeytoken>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((Honeytokens::Table, Honeytokens::HoneytokenId)),
+                HoneytokenLookupIden::HoneytokenId,
+            )
+            .expr_as(
+                Expr::col((Honeytokens::Table, Honeytokens::CreatedAt)),
+                HoneytokenLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((Honeytokens::Table, Honeytokens::Kind)),
+                HoneytokenLookupIden::Kind,
+            )
+            .expr_as(
+                Expr::col((Honeytokens::Table, Honeytokens::Value)),
+                HoneytokenLookupIden::Value,
+            )
+            .expr_as(
+                Expr::col((Honeytokens::Table, Honeytokens::Label)),
+                HoneytokenLookupIden::Label,
+            )
+            .expr_as(
+                Expr::col((Honeytokens::Table, Honeytokens::LastUsedAt)),
+                HoneytokenLookupIden::LastUsedAt,
+            )
+            .from(Honeytokens::Table)
+            .apply_filter(filter)
+            .generate_pagination((Honeytokens::Table, Honeytokens::HoneytokenId), pagination)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<HoneytokenLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).try_map(Honeytoken::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn count(&mut self, filter: HoneytokenFilter) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(Expr::col((Honeytokens::Table, Honeytokens::HoneytokenId)).count())
+            .from(Honeytokens::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.honeytoken.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(&mut self, filter: HoneytokenFilter) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((Honeytokens::Table, Honeytokens::HoneytokenId)))
+            .from(Honeytokens::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::HoneytokenKind;
+    use mas_storage::{
+        clock::MockClock, honeytoken::HoneytokenFilter, Clock, Pagination, RepositoryAccess,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_honeytoken_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let token = repo
+            .honeytoken()
+            .add(
+                &mut rng,
+                &clock,
+                HoneytokenKind::AccessToken,
+                "mat_decoy".to_owned(),
+                "CI secrets".to_owned(),
+            )
+            .await
+            .unwrap();
+        let account = repo
+            .honeytoken()
+            .add(
+                &mut rng,
+                &clock,
+                HoneytokenKind::Account,
+                "backup-admin".to_owned(),
+                "Wiki page".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        // Tokens and accounts are looked up separately
+        assert_eq!(
+            repo.honeytoken().find_by_token("mat_decoy").await.unwrap(),
+            Some(token.clone())
+        );
+        assert!(repo
+            .honeytoken()
+            .find_by_token("backup-admin")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            repo.honeytoken()
+                .find_account("backup-admin")
+                .await
+                .unwrap(),
+            Some(account.clone())
+        );
+        assert!(repo
+            .honeytoken()
+            .find_account("mat_decoy")
+            .await
+            .unwrap()
+            .is_none());
+
+        let unused = HoneytokenFilter::new().unused_only();
+        assert_eq!(repo.honeytoken().count(unused).await.unwrap(), 2);
+
+        // Record a use, which never moves the last use back in time
+        clock.advance(Duration::try_minutes(5).unwrap());
+        let used_at = clock.now();
+        let token = repo.honeytoken().record_use(token, used_at).await.unwrap();
+        assert_eq!(token.last_used_at, Some(used_at));
+        let token = repo
+            .honeytoken()
+            .record_use(token, used_at - Duration::try_minutes(1).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(token.last_used_at, Some(used_at));
+        let lookup = repo.honeytoken().lookup(token.id).await.unwrap().unwrap();
+        assert_eq!(lookup, token);
+
+        let used = HoneytokenFilter::new().used_only();
+        let page = repo
+            .honeytoken()
+            .list(used, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges, vec![token.clone()]);
+
+        let accounts = HoneytokenFilter::new().with_kind(HoneytokenKind::Account);
+        let page = repo
+            .honeytoken()
+            .list(accounts, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges, vec![account.clone()]);
+
+        repo.honeytoken().remove(account).await.unwrap();
+        assert_eq!(
+            repo.honeytoken()
+                .count(HoneytokenFilter::new())
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
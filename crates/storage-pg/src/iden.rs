@@ -16,6 +16,16 @@ pub enum UserSessions {
     UserAgent,
     LastActiveAt,
     LastActiveIp,
+    Tags,
+    BindingCredentialId,
+    BindingPublicKey,
+    BindingSignCount,
+    BindingToken,
+    BindingPreviousToken,
+    BindingExpiresAt,
+    ImpersonatorUserId,
+    ImpersonatorUserSessionId,
+    ImpersonationExpiresAt,
 }
 
 #[derive(sea_query::Iden)]
@@ -27,6 +37,13 @@ pub enum Users {
     CreatedAt,
     LockedAt,
     CanRequestAdmin,
+    LegalHoldAt,
+    Homeserver,
+    IsBot,
+    BotOwnerId,
+    IsGuest,
+    DeletionScheduledAt,
+    InactivityWarnedAt,
 }
 
 #[derive(sea_query::Iden)]
@@ -39,6 +56,53 @@ pub enum UserEmails {
     ConfirmedAt,
 }
 
+#[derive(sea_query::Iden)]
+pub enum AuditEvents {
+    Table,
+    AuditEventId,
+    CreatedAt,
+    Kind,
+    ActorUserId,
+    UserId,
+    IpAddress,
+    UserAgent,
+    Details,
+    PreviousHash,
+    Hash,
+}
+
+#[derive(sea_query::Iden)]
+pub enum ChangesFeed {
+    Table,
+    ChangesFeedEntryId,
+    EventId,
+    CreatedAt,
+    Kind,
+    Data,
+}
+
+#[derive(sea_query::Iden)]
+pub enum EmailDeliveryFailures {
+    Table,
+    EmailDeliveryFailureId,
+    CreatedAt,
+    Email,
+    Domain,
+    Permanent,
+    Reason,
+}
+
+#[derive(sea_query::Iden)]
+pub enum Honeytokens {
+    Table,
+    HoneytokenId,
+    CreatedAt,
+    Kind,
+    Value,
+    Label,
+    LastUsedAt,
+}
+
 #[derive(sea_query::Iden)]
 pub enum CompatSessions {
     Table,
@@ -52,6 +116,7 @@ pub enum CompatSessions {
     UserAgent,
     LastActiveAt,
     LastActiveIp,
+    Tags,
 }
 
 #[derive(sea_query::Iden)]
@@ -82,6 +147,7 @@ pub enum OAuth2Sessions {
     UserAgent,
     LastActiveAt,
     LastActiveIp,
+    Tags,
 }
 
 #[derive(sea_query::Iden)]
@@ -104,9 +170,19 @@ pub enum UpstreamOAuthProviders {
     DiscoveryMode,
     PkceMode,
     AdditionalParameters,
+    ParMode,
+    PushedAuthorizationRequestEndpointOverride,
+    RequestObjectSigningAlg,
+    ResponseMode,
     JwksUriOverride,
     TokenEndpointOverride,
     AuthorizationEndpointOverride,
+    AllowIdpInitiatedLogin,
+    IdpInitiatedLoginClientId,
+    PropagateLogout,
+    EndSessionEndpointOverride,
+    FetchUserinfo,
+    UserinfoEndpointOverride,
 }
 
 #[derive(sea_query::Iden)]
@@ -119,5 +195,23 @@ pub enum UpstreamOAuthLinks {
     UpstreamOAuthProviderId,
     UserId,
     Subject,
+    AssertedEmail,
+    CreatedAt,
+}
+
+#[derive(sea_query::Iden)]
+#[iden = "upstream_oauth_email_changes"]
+pub enum UpstreamOAuthEmailChanges {
+    Table,
+    #[iden = "upstream_oauth_email_change_id"]
+    UpstreamOAuthEmailChangeId,
+    #[iden = "upstream_oauth_link_id"]
+    UpstreamOAuthLinkId,
+    UserId,
+    ConflictingUserId,
     CreatedAt,
+    PreviousEmail,
+    NewEmail,
+    ResolvedAt,
+    Resolution,
 }
@@ -163,9 +163,18 @@
 use sqlx::migrate::Migrator;
 
 pub mod app_session;
+pub mod audit;
+pub mod changes_feed;
 pub mod compat;
+pub mod email_delivery;
+pub mod event_outbox;
+pub mod expiry_warning;
+pub mod honeytoken;
 pub mod job;
 pub mod oauth2;
+pub mod rendezvous;
+pub mod runtime_resource;
+pub mod signing_key;
 pub mod upstream_oauth2;
 pub mod user;
 
@@ -177,7 +186,11 @@ pub(crate) mod repository;
 pub(crate) mod tracing;
 
 pub(crate) use self::errors::DatabaseInconsistencyError;
-pub use self::{errors::DatabaseError, repository::PgRepository, tracing::ExecuteExt};
+pub use self::{
+    errors::DatabaseError,
+    repository::PgRepository,
+    tracing::{count_queries, ExecuteExt},
+};
 
 /// Embedded migrations, allowing them to run on startup
 pub static MIGRATOR: Migrator = {
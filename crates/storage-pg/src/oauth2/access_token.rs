@@ -8,12 +8,13 @@ use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{AccessToken, AccessTokenState, Session};
 use mas_storage::{oauth2::OAuth2AccessTokenRepository, Clock};
+use oauth2_types::scope::{Scope, ScopeToken};
 use rand::RngCore;
 use sqlx::PgConnection;
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{tracing::ExecuteExt, DatabaseError};
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
 
 /// An implementation of [`OAuth2AccessTokenRepository`] for a PostgreSQL
 /// connection
@@ -36,23 +37,48 @@ struct OAuth2AccessTokenLookup {
     created_at: DateTime<Utc>,
     expires_at: Option<DateTime<Utc>>,
     revoked_at: Option<DateTime<Utc>>,
+    dpop_jkt: Option<String>,
+    x5t_s256: Option<String>,
+    scope_list: Option<Vec<String>>,
 }
 
-impl From<OAuth2AccessTokenLookup> for AccessToken {
-    fn from(value: OAuth2AccessTokenLookup) -> Self {
+impl TryFrom<OAuth2AccessTokenLookup> for AccessToken {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: OAuth2AccessTokenLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.oauth2_access_token_id);
+        let scope = value
+            .scope_list
+            .map(|scope_list| {
+                scope_list
+                    .iter()
+                    .map(|s| s.parse::<ScopeToken>())
+                    .collect::<Result<Scope, _>>()
+            })
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_access_tokens")
+                    .column("scope_list")
+                    .row(id)
+                    .source(e)
+            })?;
+
         let state = match value.revoked_at {
             None => AccessTokenState::Valid,
             Some(revoked_at) => AccessTokenState::Revoked { revoked_at },
         };
 
-        Self {
-            id: value.oauth2_access_token_id.into(),
+        Ok(Self {
+            id,
             state,
             session_id: value.oauth2_session_id.into(),
             access_token: value.access_token,
             created_at: value.created_at,
             expires_at: value.expires_at,
-        }
+            dpop_jkt: value.dpop_jkt,
+            x5t_s256: value.x5t_s256,
+            scope,
+        })
     }
 }
 
@@ -70,6 +96,9 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
                      , expires_at
                      , revoked_at
                      , oauth2_session_id
+                     , dpop_jkt
+                     , x5t_s256
+                     , scope_list
 
                 FROM oauth2_access_tokens
 
@@ -82,7 +111,7 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
 
         let Some(res) = res else { return Ok(None) };
 
-        Ok(Some(res.into()))
+        Ok(Some(res.try_into()?))
     }
 
     #[tracing::instrument(
@@ -106,6 +135,9 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
                      , expires_at
                      , revoked_at
                      , oauth2_session_id
+                     , dpop_jkt
+                     , x5t_s256
+                     , scope_list
 
                 FROM oauth2_access_tokens
 
@@ -118,7 +150,7 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
 
         let Some(res) = res else { return Ok(None) };
 
-        Ok(Some(res.into()))
+        Ok(Some(res.try_into()?))
     }
 
     #[tracing::instrument(
@@ -139,6 +171,8 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
         session: &Session,
         access_token: String,
         expires_after: Option<Duration>,
+        dpop_jkt: Option<String>,
+        x5t_s256: Option<String>,
     ) -> Result<AccessToken, Self::Error> {
         let created_at = clock.now();
         let expires_at = expires_after.map(|d| created_at + d);
@@ -149,15 +183,18 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
         sqlx::query!(
             r#"
                 INSERT INTO oauth2_access_tokens
-                    (oauth2_access_token_id, oauth2_session_id, access_token, created_at, expires_at)
+                    (oauth2_access_token_id, oauth2_session_id, access_token, created_at, expires_at,
+                     dpop_jkt, x5t_s256)
                 VALUES
-                    ($1, $2, $3, $4, $5)
+                    ($1, $2, $3, $4, $5, $6, $7)
             "#,
             Uuid::from(id),
             Uuid::from(session.id),
             &access_token,
             created_at,
             expires_at,
+            dpop_jkt.as_deref(),
+            x5t_s256.as_deref(),
         )
             .traced()
         .execute(&mut *self.conn)
@@ -170,6 +207,70 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
             session_id: session.id,
             created_at,
             expires_at,
+            dpop_jkt,
+            x5t_s256,
+            scope: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.add_restricted",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+            client.id = %session.client_id,
+            access_token.id,
+            access_token.scope = %scope,
+        ),
+        err,
+    )]
+    async fn add_restricted(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: String,
+        expires_after: Duration,
+        scope: Scope,
+    ) -> Result<AccessToken, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + expires_after;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        tracing::Span::current().record("access_token.id", tracing::field::display(id));
+
+        let scope_list: Vec<String> = scope.iter().map(|s| s.as_str().to_owned()).collect();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO oauth2_access_tokens
+                    (oauth2_access_token_id, oauth2_session_id, access_token, created_at, expires_at,
+                     scope_list)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            Uuid::from(session.id),
+            &access_token,
+            created_at,
+            expires_at,
+            &scope_list,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(AccessToken {
+            id,
+            state: AccessTokenState::default(),
+            access_token,
+            session_id: session.id,
+            created_at,
+            expires_at: Some(expires_at),
+            dpop_jkt: None,
+            x5t_s256: None,
+            scope: Some(scope),
         })
     }
 
@@ -55,6 +55,8 @@ struct GrantLookup {
     code_challenge: Option<String>,
     code_challenge_method: Option<String>,
     requires_consent: bool,
+    requires_login: bool,
+    acr_values: Vec<String>,
     oauth2_client_id: Uuid,
     oauth2_session_id: Option<Uuid>,
 }
@@ -185,6 +187,8 @@ impl TryFrom<GrantLookup> for AuthorizationGrant {
             created_at: value.created_at,
             response_type_id_token: value.response_type_id_token,
             requires_consent: value.requires_consent,
+            requires_login: value.requires_login,
+            acr_values: value.acr_values,
         })
     }
 }
@@ -218,6 +222,8 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        requires_login: bool,
+        acr_values: Vec<String>,
     ) -> Result<AuthorizationGrant, Self::Error> {
         let code_challenge = code
             .as_ref()
@@ -252,10 +258,12 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      response_type_id_token,
                      authorization_code,
                      requires_consent,
+                     requires_login,
+                     acr_values,
                      created_at
                 )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
             "#,
             Uuid::from(id),
             Uuid::from(client.id),
@@ -271,6 +279,8 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             response_type_id_token,
             code_str,
             requires_consent,
+            requires_login,
+            &acr_values,
             created_at,
         )
         .traced()
@@ -291,6 +301,8 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             created_at,
             response_type_id_token,
             requires_consent,
+            requires_login,
+            acr_values,
         })
     }
 
@@ -325,6 +337,8 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , code_challenge
                      , code_challenge_method
                      , requires_consent
+                     , requires_login
+                     , acr_values
                      , oauth2_session_id
                 FROM
                     oauth2_authorization_grants
@@ -375,6 +389,8 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , code_challenge
                      , code_challenge_method
                      , requires_consent
+                     , requires_login
+                     , acr_values
                      , oauth2_session_id
                 FROM
                     oauth2_authorization_grants
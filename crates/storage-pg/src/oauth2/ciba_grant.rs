@@ -0,0 +1,438 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{BrowserSession, CibaGrant, CibaGrantState, Session, UserAgent};
+use mas_storage::{
+    oauth2::{OAuth2CibaGrantParams, OAuth2CibaGrantRepository},
+    Clock,
+};
+use oauth2_types::scope::Scope;
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{errors::DatabaseInconsistencyError, DatabaseError, ExecuteExt};
+
+/// An implementation of [`OAuth2CibaGrantRepository`] for a PostgreSQL
+/// connection
+pub struct PgOAuth2CibaGrantRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgOAuth2CibaGrantRepository<'c> {
+    /// Create a new [`PgOAuth2CibaGrantRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct OAuth2CibaGrantLookup {
+    oauth2_ciba_grant_id: Uuid,
+    oauth2_client_id: Uuid,
+    user_id: Uuid,
+    scope: String,
+    auth_req_id: String,
+    binding_message: Option<String>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    fulfilled_at: Option<DateTime<Utc>>,
+    rejected_at: Option<DateTime<Utc>>,
+    exchanged_at: Option<DateTime<Utc>>,
+    user_session_id: Option<Uuid>,
+    oauth2_session_id: Option<Uuid>,
+    ip_address: Option<IpAddr>,
+    user_agent: Option<String>,
+}
+
+impl TryFrom<OAuth2CibaGrantLookup> for CibaGrant {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(
+        OAuth2CibaGrantLookup {
+            oauth2_ciba_grant_id,
+            oauth2_client_id,
+            user_id,
+            scope,
+            auth_req_id,
+            binding_message,
+            created_at,
+            expires_at,
+            fulfilled_at,
+            rejected_at,
+            exchanged_at,
+            user_session_id,
+            oauth2_session_id,
+            ip_address,
+            user_agent,
+        }: OAuth2CibaGrantLookup,
+    ) -> Result<Self, Self::Error> {
+        let id = Ulid::from(oauth2_ciba_grant_id);
+        let client_id = Ulid::from(oauth2_client_id);
+        let user_id = Ulid::from(user_id);
+
+        let scope: Scope = scope.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_ciba_grants")
+                .column("scope")
+                .row(id)
+                .source(e)
+        })?;
+
+        let state = match (
+            fulfilled_at,
+            rejected_at,
+            exchanged_at,
+            user_session_id,
+            oauth2_session_id,
+        ) {
+            (None, None, None, None, None) => CibaGrantState::Pending,
+
+            (Some(fulfilled_at), None, None, Some(user_session_id), None) => {
+                CibaGrantState::Fulfilled {
+                    browser_session_id: Ulid::from(user_session_id),
+                    fulfilled_at,
+                }
+            }
+
+            (None, Some(rejected_at), None, Some(user_session_id), None) => {
+                CibaGrantState::Rejected {
+                    browser_session_id: Ulid::from(user_session_id),
+                    rejected_at,
+                }
+            }
+
+            (
+                Some(fulfilled_at),
+                None,
+                Some(exchanged_at),
+                Some(user_session_id),
+                Some(oauth2_session_id),
+            ) => CibaGrantState::Exchanged {
+                browser_session_id: Ulid::from(user_session_id),
+                session_id: Ulid::from(oauth2_session_id),
+                fulfilled_at,
+                exchanged_at,
+            },
+
+            _ => return Err(DatabaseInconsistencyError::on("oauth2_ciba_grants").row(id)),
+        };
+
+        Ok(CibaGrant {
+            id,
+            state,
+            client_id,
+            user_id,
+            scope,
+            auth_req_id,
+            binding_message,
+            created_at,
+            expires_at,
+            ip_address,
+            user_agent: user_agent.map(UserAgent::parse),
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2CibaGrantRepository for PgOAuth2CibaGrantRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.oauth2_ciba_grant.add",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_ciba_grant.id,
+            oauth2_ciba_grant.scope = %params.scope,
+            oauth2_client.id = %params.client.id,
+            user.id = %params.user.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: OAuth2CibaGrantParams<'_>,
+    ) -> Result<CibaGrant, Self::Error> {
+        let now = clock.now();
+        let id = Ulid::from_datetime_with_source(now.into(), rng);
+        tracing::Span::current().record("oauth2_ciba_grant.id", tracing::field::display(id));
+
+        let created_at = now;
+        let expires_at = now + params.expires_in;
+        let client_id = params.client.id;
+        let user_id = params.user.id;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO "oauth2_ciba_grants"
+                    ( oauth2_ciba_grant_id
+                    , oauth2_client_id
+                    , user_id
+                    , scope
+                    , auth_req_id
+                    , binding_message
+                    , created_at
+                    , expires_at
+                    , ip_address
+                    , user_agent
+                    )
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+            Uuid::from(id),
+            Uuid::from(client_id),
+            Uuid::from(user_id),
+            params.scope.to_string(),
+            &params.auth_req_id,
+            params.binding_message.as_deref(),
+            created_at,
+            expires_at,
+            params.ip_address as Option<IpAddr>,
+            params.user_agent.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(CibaGrant {
+            id,
+            state: CibaGrantState::Pending,
+            client_id,
+            user_id,
+            scope: params.scope,
+            auth_req_id: params.auth_req_id,
+            binding_message: params.binding_message,
+            created_at,
+            expires_at,
+            ip_address: params.ip_address,
+            user_agent: params.user_agent,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_ciba_grant.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_ciba_grant.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CibaGrant>, Self::Error> {
+        let res = sqlx::query_as!(
+            OAuth2CibaGrantLookup,
+            r#"
+                SELECT oauth2_ciba_grant_id
+                     , oauth2_client_id
+                     , user_id
+                     , scope
+                     , auth_req_id
+                     , binding_message
+                     , created_at
+                     , expires_at
+                     , fulfilled_at
+                     , rejected_at
+                     , exchanged_at
+                     , user_session_id
+                     , oauth2_session_id
+                     , ip_address as "ip_address: IpAddr"
+                     , user_agent
+                FROM
+                    oauth2_ciba_grants
+
+                WHERE oauth2_ciba_grant_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_ciba_grant.find_by_auth_req_id",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn find_by_auth_req_id(
+        &mut self,
+        auth_req_id: &str,
+    ) -> Result<Option<CibaGrant>, Self::Error> {
+        let res = sqlx::query_as!(
+            OAuth2CibaGrantLookup,
+            r#"
+                SELECT oauth2_ciba_grant_id
+                     , oauth2_client_id
+                     , user_id
+                     , scope
+                     , auth_req_id
+                     , binding_message
+                     , created_at
+                     , expires_at
+                     , fulfilled_at
+                     , rejected_at
+                     , exchanged_at
+                     , user_session_id
+                     , oauth2_session_id
+                     , ip_address as "ip_address: IpAddr"
+                     , user_agent
+                FROM
+                    oauth2_ciba_grants
+
+                WHERE auth_req_id = $1
+            "#,
+            auth_req_id,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_ciba_grant.fulfill",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_ciba_grant.id = %ciba_grant.id,
+            oauth2_client.id = %ciba_grant.client_id,
+            browser_session.id = %browser_session.id,
+            user.id = %browser_session.user.id,
+        ),
+        err,
+    )]
+    async fn fulfill(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<CibaGrant, Self::Error> {
+        let fulfilled_at = clock.now();
+        let ciba_grant = ciba_grant
+            .fulfill(browser_session, fulfilled_at)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_ciba_grants
+                SET fulfilled_at = $1
+                  , user_session_id = $2
+                WHERE oauth2_ciba_grant_id = $3
+            "#,
+            fulfilled_at,
+            Uuid::from(browser_session.id),
+            Uuid::from(ciba_grant.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(ciba_grant)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_ciba_grant.reject",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_ciba_grant.id = %ciba_grant.id,
+            oauth2_client.id = %ciba_grant.client_id,
+            browser_session.id = %browser_session.id,
+            user.id = %browser_session.user.id,
+        ),
+        err,
+    )]
+    async fn reject(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<CibaGrant, Self::Error> {
+        let rejected_at = clock.now();
+        let ciba_grant = ciba_grant
+            .reject(browser_session, rejected_at)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_ciba_grants
+                SET rejected_at = $1
+                  , user_session_id = $2
+                WHERE oauth2_ciba_grant_id = $3
+            "#,
+            rejected_at,
+            Uuid::from(browser_session.id),
+            Uuid::from(ciba_grant.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(ciba_grant)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_ciba_grant.exchange",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_ciba_grant.id = %ciba_grant.id,
+            oauth2_client.id = %ciba_grant.client_id,
+            oauth2_session.id = %session.id,
+        ),
+        err,
+    )]
+    async fn exchange(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        session: &Session,
+    ) -> Result<CibaGrant, Self::Error> {
+        let exchanged_at = clock.now();
+        let ciba_grant = ciba_grant
+            .exchange(session, exchanged_at)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_ciba_grants
+                SET exchanged_at = $1
+                  , oauth2_session_id = $2
+                WHERE oauth2_ciba_grant_id = $3
+            "#,
+            exchanged_at,
+            Uuid::from(session.id),
+            Uuid::from(ciba_grant.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(ciba_grant)
+    }
+}
@@ -11,7 +11,8 @@ use std::{
 };
 
 use async_trait::async_trait;
-use mas_data_model::{Client, JwksOrJwksUri, User};
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{Client, ClientConsent, JwksOrJwksUri, User};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
 use mas_storage::{oauth2::OAuth2ClientRepository, Clock};
@@ -48,12 +49,15 @@ impl<'c> PgOAuth2ClientRepository<'c> {
 struct OAuth2ClientLookup {
     oauth2_client_id: Uuid,
     encrypted_client_secret: Option<String>,
+    encrypted_registration_access_token: Option<String>,
     application_type: Option<String>,
     redirect_uris: Vec<String>,
     grant_type_authorization_code: bool,
     grant_type_refresh_token: bool,
     grant_type_client_credentials: bool,
     grant_type_device_code: bool,
+    grant_type_jwt_bearer: bool,
+    grant_type_ciba: bool,
     client_name: Option<String>,
     logo_uri: Option<String>,
     client_uri: Option<String>,
@@ -66,6 +70,14 @@ struct OAuth2ClientLookup {
     token_endpoint_auth_method: Option<String>,
     token_endpoint_auth_signing_alg: Option<String>,
     initiate_login_uri: Option<String>,
+    require_pushed_authorization_requests: bool,
+    tls_client_auth_subject_dn: Option<String>,
+    tls_client_certificate_bound_access_tokens: bool,
+    jwt_access_tokens: bool,
+    access_token_ttl: Option<i64>,
+    refresh_token_ttl: Option<i64>,
+    session_ttl: Option<i64>,
+    session_inactivity_ttl: Option<i64>,
 }
 
 impl TryInto<Client> for OAuth2ClientLookup {
@@ -108,6 +120,12 @@ impl TryInto<Client> for OAuth2ClientLookup {
         if self.grant_type_device_code {
             grant_types.push(GrantType::DeviceCode);
         }
+        if self.grant_type_jwt_bearer {
+            grant_types.push(GrantType::JwtBearer);
+        }
+        if self.grant_type_ciba {
+            grant_types.push(GrantType::ClientInitiatedBackchannelAuthentication);
+        }
 
         let logo_uri = self.logo_uri.map(|s| s.parse()).transpose().map_err(|e| {
             DatabaseInconsistencyError::on("oauth2_clients")
@@ -232,6 +250,7 @@ impl TryInto<Client> for OAuth2ClientLookup {
             id,
             client_id: id.to_string(),
             encrypted_client_secret: self.encrypted_client_secret,
+            encrypted_registration_access_token: self.encrypted_registration_access_token,
             application_type,
             redirect_uris,
             grant_types,
@@ -246,6 +265,15 @@ impl TryInto<Client> for OAuth2ClientLookup {
             token_endpoint_auth_method,
             token_endpoint_auth_signing_alg,
             initiate_login_uri,
+            require_pushed_authorization_requests: self.require_pushed_authorization_requests,
+            tls_client_auth_subject_dn: self.tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens: self
+                .tls_client_certificate_bound_access_tokens,
+            jwt_access_tokens: self.jwt_access_tokens,
+            access_token_ttl: self.access_token_ttl.map(Duration::seconds),
+            refresh_token_ttl: self.refresh_token_ttl.map(Duration::seconds),
+            session_ttl: self.session_ttl.map(Duration::seconds),
+            session_inactivity_ttl: self.session_inactivity_ttl.map(Duration::seconds),
         })
     }
 }
@@ -269,12 +297,15 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             r#"
                 SELECT oauth2_client_id
                      , encrypted_client_secret
+                     , encrypted_registration_access_token
                      , application_type
                      , redirect_uris
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
                      , grant_type_device_code
+                     , grant_type_jwt_bearer
+                     , grant_type_ciba
                      , client_name
                      , logo_uri
                      , client_uri
@@ -287,6 +318,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , token_endpoint_auth_method
                      , token_endpoint_auth_signing_alg
                      , initiate_login_uri
+                     , require_pushed_authorization_requests
+                     , tls_client_auth_subject_dn
+                     , tls_client_certificate_bound_access_tokens
+                     , jwt_access_tokens
+                     , access_token_ttl
+                     , refresh_token_ttl
+                     , session_ttl
+                     , session_inactivity_ttl
                 FROM oauth2_clients c
 
                 WHERE oauth2_client_id = $1
@@ -320,12 +359,15 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             r#"
                 SELECT oauth2_client_id
                      , encrypted_client_secret
+                     , encrypted_registration_access_token
                      , application_type
                      , redirect_uris
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
                      , grant_type_device_code
+                     , grant_type_jwt_bearer
+                     , grant_type_ciba
                      , client_name
                      , logo_uri
                      , client_uri
@@ -338,6 +380,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , token_endpoint_auth_method
                      , token_endpoint_auth_signing_alg
                      , initiate_login_uri
+                     , require_pushed_authorization_requests
+                     , tls_client_auth_subject_dn
+                     , tls_client_certificate_bound_access_tokens
+                     , jwt_access_tokens
+                     , access_token_ttl
+                     , refresh_token_ttl
+                     , session_ttl
+                     , session_inactivity_ttl
                 FROM oauth2_clients c
 
                 WHERE oauth2_client_id = ANY($1::uuid[])
@@ -388,6 +438,9 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
         initiate_login_uri: Option<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
     ) -> Result<Client, Self::Error> {
         let now = clock.now();
         let id = Ulid::from_datetime_with_source(now.into(), rng);
@@ -412,6 +465,8 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , grant_type_refresh_token
                     , grant_type_client_credentials
                     , grant_type_device_code
+                    , grant_type_jwt_bearer
+                    , grant_type_ciba
                     , client_name
                     , logo_uri
                     , client_uri
@@ -424,10 +479,13 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , token_endpoint_auth_method
                     , token_endpoint_auth_signing_alg
                     , initiate_login_uri
+                    , require_pushed_authorization_requests
+                    , tls_client_auth_subject_dn
+                    , tls_client_certificate_bound_access_tokens
                     , is_static
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, FALSE)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, FALSE)
             "#,
             Uuid::from(id),
             encrypted_client_secret,
@@ -437,6 +495,8 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             grant_types.contains(&GrantType::RefreshToken),
             grant_types.contains(&GrantType::ClientCredentials),
             grant_types.contains(&GrantType::DeviceCode),
+            grant_types.contains(&GrantType::JwtBearer),
+            grant_types.contains(&GrantType::ClientInitiatedBackchannelAuthentication),
             client_name,
             logo_uri.as_ref().map(Url::as_str),
             client_uri.as_ref().map(Url::as_str),
@@ -455,6 +515,9 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                 .as_ref()
                 .map(ToString::to_string),
             initiate_login_uri.as_ref().map(Url::as_str),
+            require_pushed_authorization_requests,
+            tls_client_auth_subject_dn.as_deref(),
+            tls_client_certificate_bound_access_tokens,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -471,6 +534,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             id,
             client_id: id.to_string(),
             encrypted_client_secret,
+            encrypted_registration_access_token: None,
             application_type,
             redirect_uris,
             grant_types,
@@ -485,6 +549,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             token_endpoint_auth_method,
             token_endpoint_auth_signing_alg,
             initiate_login_uri,
+            require_pushed_authorization_requests,
+            tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens,
+            jwt_access_tokens: false,
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            session_ttl: None,
+            session_inactivity_ttl: None,
         })
     }
 
@@ -505,6 +577,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         jwks: Option<PublicJsonWebKeySet>,
         jwks_uri: Option<Url>,
         redirect_uris: Vec<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
+        jwt_access_tokens: bool,
+        access_token_ttl: Option<Duration>,
+        refresh_token_ttl: Option<Duration>,
+        session_ttl: Option<Duration>,
+        session_inactivity_ttl: Option<Duration>,
     ) -> Result<Client, Self::Error> {
         let jwks_json = jwks
             .as_ref()
@@ -525,13 +605,25 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , grant_type_refresh_token
                     , grant_type_client_credentials
                     , grant_type_device_code
+                    , grant_type_jwt_bearer
+                    , grant_type_ciba
                     , token_endpoint_auth_method
                     , jwks
                     , jwks_uri
+                    , require_pushed_authorization_requests
+                    , tls_client_auth_subject_dn
+                    , tls_client_certificate_bound_access_tokens
+                    , jwt_access_tokens
+                    , access_token_ttl
+                    , refresh_token_ttl
+                    , session_ttl
+                    , session_inactivity_ttl
                     , is_static
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, TRUE)
+                    ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16
+                    , $17, $18, $19, $20, TRUE
+                    )
                 ON CONFLICT (oauth2_client_id)
                 DO
                     UPDATE SET encrypted_client_secret = EXCLUDED.encrypted_client_secret
@@ -540,9 +632,19 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                              , grant_type_refresh_token = EXCLUDED.grant_type_refresh_token
                              , grant_type_client_credentials = EXCLUDED.grant_type_client_credentials
                              , grant_type_device_code = EXCLUDED.grant_type_device_code
+                             , grant_type_jwt_bearer = EXCLUDED.grant_type_jwt_bearer
+                             , grant_type_ciba = EXCLUDED.grant_type_ciba
                              , token_endpoint_auth_method = EXCLUDED.token_endpoint_auth_method
                              , jwks = EXCLUDED.jwks
                              , jwks_uri = EXCLUDED.jwks_uri
+                             , require_pushed_authorization_requests = EXCLUDED.require_pushed_authorization_requests
+                             , tls_client_auth_subject_dn = EXCLUDED.tls_client_auth_subject_dn
+                             , tls_client_certificate_bound_access_tokens = EXCLUDED.tls_client_certificate_bound_access_tokens
+                             , jwt_access_tokens = EXCLUDED.jwt_access_tokens
+                             , access_token_ttl = EXCLUDED.access_token_ttl
+                             , refresh_token_ttl = EXCLUDED.refresh_token_ttl
+                             , session_ttl = EXCLUDED.session_ttl
+                             , session_inactivity_ttl = EXCLUDED.session_inactivity_ttl
                              , is_static = TRUE
             "#,
             Uuid::from(client_id),
@@ -552,9 +654,19 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             true,
             true,
             true,
+            true,
+            true,
             client_auth_method,
             jwks_json,
             jwks_uri.as_ref().map(Url::as_str),
+            require_pushed_authorization_requests,
+            tls_client_auth_subject_dn.as_deref(),
+            tls_client_certificate_bound_access_tokens,
+            jwt_access_tokens,
+            access_token_ttl.map(|ttl| ttl.num_seconds()),
+            refresh_token_ttl.map(|ttl| ttl.num_seconds()),
+            session_ttl.map(|ttl| ttl.num_seconds()),
+            session_inactivity_ttl.map(|ttl| ttl.num_seconds()),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -571,6 +683,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             id: client_id,
             client_id: client_id.to_string(),
             encrypted_client_secret,
+            encrypted_registration_access_token: None,
             application_type: None,
             redirect_uris,
             grant_types: vec![
@@ -589,9 +702,156 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             token_endpoint_auth_method: None,
             token_endpoint_auth_signing_alg: None,
             initiate_login_uri: None,
+            require_pushed_authorization_requests,
+            tls_client_auth_subject_dn,
+            tls_client_certificate_bound_access_tokens,
+            jwt_access_tokens,
+            access_token_ttl,
+            refresh_token_ttl,
+            session_ttl,
+            session_inactivity_ttl,
         })
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_registration_access_token",
+        skip_all,
+        fields(
+            db.query.text,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn set_registration_access_token(
+        &mut self,
+        mut client: Client,
+        encrypted_registration_access_token: Option<String>,
+    ) -> Result<Client, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET encrypted_registration_access_token = $2
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            encrypted_registration_access_token,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        client.encrypted_registration_access_token = encrypted_registration_access_token;
+        Ok(client)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.update",
+        skip_all,
+        fields(
+            db.query.text,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn update(&mut self, client: Client) -> Result<Client, Self::Error> {
+        let (jwks, jwks_uri) = match &client.jwks {
+            Some(JwksOrJwksUri::Jwks(jwks)) => (Some(jwks), None),
+            Some(JwksOrJwksUri::JwksUri(jwks_uri)) => (None, Some(jwks_uri)),
+            None => (None, None),
+        };
+
+        let jwks_json = jwks
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let redirect_uris_array = client
+            .redirect_uris
+            .iter()
+            .map(Url::to_string)
+            .collect::<Vec<_>>();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET encrypted_client_secret = $2
+                  , application_type = $3
+                  , redirect_uris = $4
+                  , grant_type_authorization_code = $5
+                  , grant_type_refresh_token = $6
+                  , grant_type_client_credentials = $7
+                  , grant_type_device_code = $8
+                  , grant_type_jwt_bearer = $9
+                  , grant_type_ciba = $10
+                  , client_name = $11
+                  , logo_uri = $12
+                  , client_uri = $13
+                  , policy_uri = $14
+                  , tos_uri = $15
+                  , jwks_uri = $16
+                  , jwks = $17
+                  , id_token_signed_response_alg = $18
+                  , userinfo_signed_response_alg = $19
+                  , token_endpoint_auth_method = $20
+                  , token_endpoint_auth_signing_alg = $21
+                  , initiate_login_uri = $22
+                  , require_pushed_authorization_requests = $23
+                  , tls_client_auth_subject_dn = $24
+                  , tls_client_certificate_bound_access_tokens = $25
+                WHERE oauth2_client_id = $1
+                  AND is_static = FALSE
+            "#,
+            Uuid::from(client.id),
+            client.encrypted_client_secret,
+            client.application_type.as_ref().map(ToString::to_string),
+            &redirect_uris_array,
+            client.grant_types.contains(&GrantType::AuthorizationCode),
+            client.grant_types.contains(&GrantType::RefreshToken),
+            client.grant_types.contains(&GrantType::ClientCredentials),
+            client.grant_types.contains(&GrantType::DeviceCode),
+            client.grant_types.contains(&GrantType::JwtBearer),
+            client
+                .grant_types
+                .contains(&GrantType::ClientInitiatedBackchannelAuthentication),
+            client.client_name,
+            client.logo_uri.as_ref().map(Url::as_str),
+            client.client_uri.as_ref().map(Url::as_str),
+            client.policy_uri.as_ref().map(Url::as_str),
+            client.tos_uri.as_ref().map(Url::as_str),
+            jwks_uri.map(Url::as_str),
+            jwks_json,
+            client
+                .id_token_signed_response_alg
+                .as_ref()
+                .map(ToString::to_string),
+            client
+                .userinfo_signed_response_alg
+                .as_ref()
+                .map(ToString::to_string),
+            client
+                .token_endpoint_auth_method
+                .as_ref()
+                .map(ToString::to_string),
+            client
+                .token_endpoint_auth_signing_alg
+                .as_ref()
+                .map(ToString::to_string),
+            client.initiate_login_uri.as_ref().map(Url::as_str),
+            client.require_pushed_authorization_requests,
+            client.tls_client_auth_subject_dn.as_deref(),
+            client.tls_client_certificate_bound_access_tokens,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(client)
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_client.all_static",
         skip_all,
@@ -606,12 +866,15 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             r#"
                 SELECT oauth2_client_id
                      , encrypted_client_secret
+                     , encrypted_registration_access_token
                      , application_type
                      , redirect_uris
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
                      , grant_type_device_code
+                     , grant_type_jwt_bearer
+                     , grant_type_ciba
                      , client_name
                      , logo_uri
                      , client_uri
@@ -624,6 +887,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , token_endpoint_auth_method
                      , token_endpoint_auth_signing_alg
                      , initiate_login_uri
+                     , require_pushed_authorization_requests
+                     , tls_client_auth_subject_dn
+                     , tls_client_certificate_bound_access_tokens
+                     , jwt_access_tokens
+                     , access_token_ttl
+                     , refresh_token_ttl
+                     , session_ttl
+                     , session_inactivity_ttl
                 FROM oauth2_clients c
                 WHERE is_static = TRUE
             "#,
@@ -651,15 +922,18 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         &mut self,
         client: &Client,
         user: &User,
+        not_before: Option<DateTime<Utc>>,
     ) -> Result<Scope, Self::Error> {
         let scope_tokens: Vec<String> = sqlx::query_scalar!(
             r#"
                 SELECT scope_token
                 FROM oauth2_consents
                 WHERE user_id = $1 AND oauth2_client_id = $2
+                  AND ($3::timestamptz IS NULL OR COALESCE(refreshed_at, created_at) >= $3)
             "#,
             Uuid::from(user.id),
             Uuid::from(client.id),
+            not_before,
         )
         .fetch_all(&mut *self.conn)
         .await?;
@@ -728,6 +1002,158 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         Ok(())
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_client.revoke_all_consents_for_user",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn revoke_all_consents_for_user(&mut self, user: &User) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                WITH deleted AS (
+                    DELETE FROM oauth2_consents
+                    WHERE user_id = $1
+                    RETURNING oauth2_client_id
+                )
+                SELECT COUNT(DISTINCT oauth2_client_id) AS "count!"
+                FROM deleted
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(count.try_into().unwrap_or(usize::MAX))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.revoke_consent_for_user",
+        skip_all,
+        fields(
+            db.query.text,
+            %client.id,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn revoke_consent_for_user(
+        &mut self,
+        client: &Client,
+        user: &User,
+    ) -> Result<bool, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM oauth2_consents
+                WHERE oauth2_client_id = $1
+                  AND user_id = $2
+            "#,
+            Uuid::from(client.id),
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.list_consents_for_user",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn list_consents_for_user(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ClientConsent>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT c.oauth2_client_id
+                     , ARRAY_AGG(c.scope_token) AS "scope_tokens!"
+                     , MAX(COALESCE(c.refreshed_at, c.created_at)) AS "consented_at!"
+                     , ( SELECT MAX(s.last_active_at)
+                         FROM oauth2_sessions s
+                         WHERE s.user_id = c.user_id
+                           AND s.oauth2_client_id = c.oauth2_client_id
+                       ) AS last_active_at
+                FROM oauth2_consents c
+                WHERE c.user_id = $1
+                GROUP BY c.user_id, c.oauth2_client_id
+                ORDER BY 3 DESC
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let mut consents = Vec::with_capacity(res.len());
+        for r in res {
+            let scope: Result<Scope, _> = r
+                .scope_tokens
+                .iter()
+                .map(|s| ScopeToken::from_str(s))
+                .collect();
+
+            let scope = scope.map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_consents")
+                    .column("scope_token")
+                    .row(r.oauth2_client_id.into())
+                    .source(e)
+            })?;
+
+            consents.push(ClientConsent {
+                client_id: r.oauth2_client_id.into(),
+                scope,
+                consented_at: r.consented_at,
+                last_active_at: r.last_active_at,
+            });
+        }
+
+        Ok(consents)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.revoke_all_consents_for_client",
+        skip_all,
+        fields(
+            db.query.text,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn revoke_all_consents_for_client(
+        &mut self,
+        client: &Client,
+    ) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                WITH deleted AS (
+                    DELETE FROM oauth2_consents
+                    WHERE oauth2_client_id = $1
+                    RETURNING user_id
+                )
+                SELECT COUNT(DISTINCT user_id) AS "count!"
+                FROM deleted
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(count.try_into().unwrap_or(usize::MAX))
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_client.delete_by_id",
         skip_all,
@@ -0,0 +1,160 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_storage::{oauth2::OAuth2JwtBearerAssertionRepository, Clock};
+use sqlx::PgConnection;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`OAuth2JwtBearerAssertionRepository`] for a
+/// PostgreSQL connection
+pub struct PgOAuth2JwtBearerAssertionRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgOAuth2JwtBearerAssertionRepository<'c> {
+    /// Create a new [`PgOAuth2JwtBearerAssertionRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2JwtBearerAssertionRepository for PgOAuth2JwtBearerAssertionRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_assertion.record_use",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_jwt_bearer_assertion.issuer = issuer,
+            oauth2_jwt_bearer_assertion.jti = jti,
+        ),
+        err,
+    )]
+    async fn record_use(
+        &mut self,
+        clock: &dyn Clock,
+        issuer: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error> {
+        // An assertion which expired but wasn't cleaned up yet doesn't prevent
+        // a new one with the same ID from being used
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO oauth2_jwt_bearer_assertions
+                    (issuer, jti, used_at, expires_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (issuer, jti) DO UPDATE
+                SET used_at = EXCLUDED.used_at
+                  , expires_at = EXCLUDED.expires_at
+                WHERE oauth2_jwt_bearer_assertions.expires_at <= EXCLUDED.used_at
+            "#,
+            issuer,
+            jti,
+            clock.now(),
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_assertion.cleanup_expired",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM oauth2_jwt_bearer_assertions
+                WHERE expires_at <= $1
+            "#,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_jwt_bearer_assertion_repo(pool: PgPool) {
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+        let expires_at = clock.now() + Duration::try_minutes(5).unwrap();
+
+        // The first use is recorded
+        assert!(repo
+            .oauth2_jwt_bearer_assertion()
+            .record_use(&clock, "https://issuer.example.com/", "abc", expires_at)
+            .await
+            .unwrap());
+
+        // Using it again is a replay
+        assert!(!repo
+            .oauth2_jwt_bearer_assertion()
+            .record_use(&clock, "https://issuer.example.com/", "abc", expires_at)
+            .await
+            .unwrap());
+
+        // IDs are scoped to their issuer
+        assert!(repo
+            .oauth2_jwt_bearer_assertion()
+            .record_use(&clock, "https://other.example.com/", "abc", expires_at)
+            .await
+            .unwrap());
+
+        // Nothing expired yet
+        assert_eq!(
+            repo.oauth2_jwt_bearer_assertion()
+                .cleanup_expired(&clock)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Once expired, the ID can be used again, even before cleanup
+        clock.advance(Duration::try_minutes(10).unwrap());
+        let expires_at = clock.now() + Duration::try_minutes(5).unwrap();
+        assert!(repo
+            .oauth2_jwt_bearer_assertion()
+            .record_use(&clock, "https://issuer.example.com/", "abc", expires_at)
+            .await
+            .unwrap());
+
+        // Only the other one is cleaned up
+        assert_eq!(
+            repo.oauth2_jwt_bearer_assertion()
+                .cleanup_expired(&clock)
+                .await
+                .unwrap(),
+            1
+        );
+
+        repo.save().await.unwrap();
+    }
+}
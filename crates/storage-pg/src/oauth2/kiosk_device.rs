@@ -0,0 +1,221 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{Client, Device, KioskDevice, Session};
+use mas_storage::{oauth2::OAuth2KioskDeviceRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{errors::DatabaseInconsistencyError, DatabaseError, ExecuteExt};
+
+/// An implementation of [`OAuth2KioskDeviceRepository`] for a PostgreSQL
+/// connection
+pub struct PgOAuth2KioskDeviceRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgOAuth2KioskDeviceRepository<'c> {
+    /// Create a new [`PgOAuth2KioskDeviceRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct OAuth2KioskDeviceLookup {
+    oauth2_kiosk_device_id: Uuid,
+    oauth2_client_id: Uuid,
+    device_id: String,
+    created_at: DateTime<Utc>,
+    current_oauth2_session_id: Option<Uuid>,
+}
+
+impl TryFrom<OAuth2KioskDeviceLookup> for KioskDevice {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: OAuth2KioskDeviceLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.oauth2_kiosk_device_id);
+        let device = Device::try_from(value.device_id).map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_kiosk_devices")
+                .column("device_id")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(KioskDevice {
+            id,
+            client_id: Ulid::from(value.oauth2_client_id),
+            device,
+            created_at: value.created_at,
+            current_session_id: value.current_oauth2_session_id.map(Ulid::from),
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2KioskDeviceRepository for PgOAuth2KioskDeviceRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.oauth2_kiosk_device.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_kiosk_device.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<KioskDevice>, Self::Error> {
+        let res = sqlx::query_as!(
+            OAuth2KioskDeviceLookup,
+            r#"
+                SELECT oauth2_kiosk_device_id
+                     , oauth2_client_id
+                     , device_id
+                     , created_at
+                     , current_oauth2_session_id
+                FROM oauth2_kiosk_devices
+                WHERE oauth2_kiosk_device_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_kiosk_device.find_by_device",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_client.id = %client.id,
+            device.id = device.as_str(),
+        ),
+        err,
+    )]
+    async fn find_by_device(
+        &mut self,
+        client: &Client,
+        device: &Device,
+    ) -> Result<Option<KioskDevice>, Self::Error> {
+        let res = sqlx::query_as!(
+            OAuth2KioskDeviceLookup,
+            r#"
+                SELECT oauth2_kiosk_device_id
+                     , oauth2_client_id
+                     , device_id
+                     , created_at
+                     , current_oauth2_session_id
+                FROM oauth2_kiosk_devices
+                WHERE oauth2_client_id = $1
+                  AND device_id = $2
+            "#,
+            Uuid::from(client.id),
+            device.as_str(),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_kiosk_device.add",
+        skip_all,
+        fields(
+            db.query.text,
+            oauth2_kiosk_device.id,
+            oauth2_client.id = %client.id,
+            device.id = device.as_str(),
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        device: Device,
+    ) -> Result<KioskDevice, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("oauth2_kiosk_device.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO oauth2_kiosk_devices
+                    ( oauth2_kiosk_device_id
+                    , oauth2_client_id
+                    , device_id
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(client.id),
+            device.as_str(),
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(KioskDevice {
+            id,
+            client_id: client.id,
+            device,
+            created_at,
+            current_session_id: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_kiosk_device.set_current_session",
+        skip_all,
+        fields(
+            db.query.text,
+            %kiosk_device.id,
+        ),
+        err,
+    )]
+    async fn set_current_session(
+        &mut self,
+        mut kiosk_device: KioskDevice,
+        session: Option<&Session>,
+    ) -> Result<KioskDevice, Self::Error> {
+        let current_session_id = session.map(|session| session.id);
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_kiosk_devices
+                SET current_oauth2_session_id = $2
+                WHERE oauth2_kiosk_device_id = $1
+            "#,
+            Uuid::from(kiosk_device.id),
+            current_session_id.map(Uuid::from),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        kiosk_device.current_session_id = current_session_id;
+
+        Ok(kiosk_device)
+    }
+}
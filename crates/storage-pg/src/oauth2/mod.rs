@@ -9,25 +9,39 @@
 
 mod access_token;
 mod authorization_grant;
+mod ciba_grant;
 mod client;
 mod device_code_grant;
+mod jwt_bearer_assertion;
+mod kiosk_device;
+mod pushed_authorization_request;
 mod refresh_token;
 mod session;
 
 pub use self::{
     access_token::PgOAuth2AccessTokenRepository,
-    authorization_grant::PgOAuth2AuthorizationGrantRepository, client::PgOAuth2ClientRepository,
+    authorization_grant::PgOAuth2AuthorizationGrantRepository,
+    ciba_grant::PgOAuth2CibaGrantRepository, client::PgOAuth2ClientRepository,
     device_code_grant::PgOAuth2DeviceCodeGrantRepository,
+    jwt_bearer_assertion::PgOAuth2JwtBearerAssertionRepository,
+    kiosk_device::PgOAuth2KioskDeviceRepository,
+    pushed_authorization_request::PgOAuth2PushedAuthorizationRequestRepository,
     refresh_token::PgOAuth2RefreshTokenRepository, session::PgOAuth2SessionRepository,
 };
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use chrono::Duration;
-    use mas_data_model::{AuthorizationCode, UserAgent};
+    use mas_data_model::{AuthorizationCode, Device, UserAgent, ACR_PASSWORD};
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
     use mas_storage::{
         clock::MockClock,
-        oauth2::{OAuth2DeviceCodeGrantParams, OAuth2SessionFilter, OAuth2SessionRepository},
+        oauth2::{
+            OAuth2CibaGrantParams, OAuth2DeviceCodeGrantParams, OAuth2SessionFilter,
+            OAuth2SessionRepository,
+        },
         Clock, Pagination,
     };
     use oauth2_types::{
@@ -81,6 +95,9 @@ mod tests {
                 None,
                 None,
                 Some("https://example.com/login".parse().unwrap()),
+                false,
+                None,
+                false,
             )
             .await
             .unwrap();
@@ -138,10 +155,14 @@ mod tests {
                 ResponseMode::Query,
                 true,
                 false,
+                true,
+                vec![ACR_PASSWORD.to_owned()],
             )
             .await
             .unwrap();
         assert!(grant.is_pending());
+        assert!(grant.requires_login);
+        assert_eq!(grant.acr_values, vec![ACR_PASSWORD.to_owned()]);
 
         // Lookup the same grant by id
         let grant_lookup = repo
@@ -176,7 +197,7 @@ mod tests {
         // Lookup the consent the user gave to the client
         let consent = repo
             .oauth2_client()
-            .get_consent_for_user(&client, &user)
+            .get_consent_for_user(&client, &user, None)
             .await
             .unwrap();
         assert!(consent.is_empty());
@@ -191,11 +212,114 @@ mod tests {
         // Lookup the consent the user gave to the client
         let consent = repo
             .oauth2_client()
-            .get_consent_for_user(&client, &user)
+            .get_consent_for_user(&client, &user, None)
+            .await
+            .unwrap();
+        assert_eq!(scope, consent);
+
+        // Consent given before the cutoff is ignored
+        let consent = repo
+            .oauth2_client()
+            .get_consent_for_user(
+                &client,
+                &user,
+                Some(clock.now() + Duration::try_minutes(1).unwrap()),
+            )
+            .await
+            .unwrap();
+        assert!(consent.is_empty());
+
+        let consent = repo
+            .oauth2_client()
+            .get_consent_for_user(
+                &client,
+                &user,
+                Some(clock.now() - Duration::try_minutes(1).unwrap()),
+            )
             .await
             .unwrap();
         assert_eq!(scope, consent);
 
+        // Forget the consent, and give it again
+        let revoked = repo
+            .oauth2_client()
+            .revoke_all_consents_for_user(&user)
+            .await
+            .unwrap();
+        assert_eq!(revoked, 1);
+
+        let consent = repo
+            .oauth2_client()
+            .get_consent_for_user(&client, &user, None)
+            .await
+            .unwrap();
+        assert!(consent.is_empty());
+
+        repo.oauth2_client()
+            .give_consent_for_user(&mut rng, &clock, &client, &user, &scope)
+            .await
+            .unwrap();
+
+        // Forget the consent the user gave to this client only
+        let revoked = repo
+            .oauth2_client()
+            .revoke_consent_for_user(&client, &user)
+            .await
+            .unwrap();
+        assert!(revoked);
+
+        let consent = repo
+            .oauth2_client()
+            .get_consent_for_user(&client, &user, None)
+            .await
+            .unwrap();
+        assert!(consent.is_empty());
+
+        // Revoking it again does nothing
+        let revoked = repo
+            .oauth2_client()
+            .revoke_consent_for_user(&client, &user)
+            .await
+            .unwrap();
+        assert!(!revoked);
+
+        repo.oauth2_client()
+            .give_consent_for_user(&mut rng, &clock, &client, &user, &scope)
+            .await
+            .unwrap();
+
+        // List the clients the user consented to
+        let consents = repo
+            .oauth2_client()
+            .list_consents_for_user(&user)
+            .await
+            .unwrap();
+        assert_eq!(consents.len(), 1);
+        assert_eq!(consents[0].client_id, client.id);
+        assert_eq!(consents[0].scope, scope);
+        assert_eq!(consents[0].consented_at, clock.now());
+        assert_eq!(consents[0].last_active_at, None);
+
+        // Forget the consent all the users gave to the client, and give it again
+        let revoked = repo
+            .oauth2_client()
+            .revoke_all_consents_for_client(&client)
+            .await
+            .unwrap();
+        assert_eq!(revoked, 1);
+
+        let consents = repo
+            .oauth2_client()
+            .list_consents_for_user(&user)
+            .await
+            .unwrap();
+        assert!(consents.is_empty());
+
+        repo.oauth2_client()
+            .give_consent_for_user(&mut rng, &clock, &client, &user, &scope)
+            .await
+            .unwrap();
+
         // Lookup a non-existing session
         let session = repo.oauth2_session().lookup(Ulid::nil()).await.unwrap();
         assert_eq!(session, None);
@@ -263,6 +387,8 @@ mod tests {
                 &session,
                 "aabbcc".to_owned(),
                 Some(Duration::try_minutes(5).unwrap()),
+                Some("some-jkt".to_owned()),
+                None,
             )
             .await
             .unwrap();
@@ -275,6 +401,7 @@ mod tests {
             .unwrap()
             .expect("token not found");
         assert_eq!(access_token, access_token_lookup);
+        assert_eq!(access_token_lookup.dpop_jkt.as_deref(), Some("some-jkt"));
 
         // Find the same token by token
         let access_token_lookup = repo
@@ -285,6 +412,35 @@ mod tests {
             .expect("token not found");
         assert_eq!(access_token, access_token_lookup);
 
+        // Create an access token restricted to a narrower scope
+        let restricted_scope: Scope = [OPENID].into_iter().collect();
+        let restricted_token = repo
+            .oauth2_access_token()
+            .add_restricted(
+                &mut rng,
+                &clock,
+                &session,
+                "ddeeff".to_owned(),
+                Duration::try_minutes(1).unwrap(),
+                restricted_scope.clone(),
+            )
+            .await
+            .unwrap();
+        assert!(restricted_token.is_restricted());
+        assert!(!access_token.is_restricted());
+
+        let restricted_lookup = repo
+            .oauth2_access_token()
+            .find_by_token("ddeeff")
+            .await
+            .unwrap()
+            .expect("token not found");
+        assert_eq!(restricted_token, restricted_lookup);
+        assert_eq!(
+            restricted_lookup.restrict_session(session.clone()).scope,
+            restricted_scope
+        );
+
         // Lookup a non-existing refresh token
         let refresh_token = repo
             .oauth2_refresh_token()
@@ -310,6 +466,7 @@ mod tests {
                 &session,
                 &access_token,
                 "aabbcc".to_owned(),
+                None,
             )
             .await
             .unwrap();
@@ -348,14 +505,39 @@ mod tests {
             .unwrap();
         assert!(!access_token.is_valid(clock.now()));
 
-        // Mark the refresh token as consumed
+        // Mark the refresh token as consumed, replaced by a new one
+        let next_refresh_token = repo
+            .oauth2_refresh_token()
+            .add(
+                &mut rng,
+                &clock,
+                &session,
+                &access_token,
+                "ddeeff".to_owned(),
+                None,
+            )
+            .await
+            .unwrap();
         assert!(refresh_token.is_valid());
         let refresh_token = repo
             .oauth2_refresh_token()
-            .consume(&clock, refresh_token)
+            .consume(&clock, refresh_token, &next_refresh_token)
             .await
             .unwrap();
         assert!(!refresh_token.is_valid());
+        assert_eq!(
+            refresh_token.next_refresh_token_id(),
+            Some(next_refresh_token.id)
+        );
+
+        // The lineage is persisted
+        let refresh_token_lookup = repo
+            .oauth2_refresh_token()
+            .lookup(refresh_token.id)
+            .await
+            .unwrap()
+            .expect("refresh token not found");
+        assert_eq!(refresh_token, refresh_token_lookup);
 
         // Record the user-agent on the session
         assert!(session.user_agent.is_none());
@@ -381,6 +563,97 @@ mod tests {
         assert!(!session.is_valid());
     }
 
+    /// Test managing the registration of a dynamically registered client
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_client_registration_management(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                vec!["https://example.com/redirect".parse().unwrap()],
+                None,
+                None,
+                vec![GrantType::AuthorizationCode],
+                Some("Test client".to_owned()),
+                None,
+                Some("https://example.com/".parse().unwrap()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(client.encrypted_registration_access_token.is_none());
+
+        let client = repo
+            .oauth2_client()
+            .set_registration_access_token(client, Some("encrypted".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.encrypted_registration_access_token.as_deref(),
+            Some("encrypted")
+        );
+
+        // Replace the metadata of the client
+        let mut client = client;
+        client.client_name = Some("Renamed client".to_owned());
+        client.redirect_uris = vec!["https://example.com/other".parse().unwrap()];
+        client.grant_types = vec![GrantType::AuthorizationCode, GrantType::RefreshToken];
+        client.token_endpoint_auth_method = Some(OAuthClientAuthenticationMethod::None);
+        let client = repo.oauth2_client().update(client).await.unwrap();
+
+        let client_lookup = repo
+            .oauth2_client()
+            .lookup(client.id)
+            .await
+            .unwrap()
+            .expect("client not found");
+        assert_eq!(client, client_lookup);
+        assert_eq!(client_lookup.client_name.as_deref(), Some("Renamed client"));
+        assert_eq!(
+            client_lookup.encrypted_registration_access_token.as_deref(),
+            Some("encrypted")
+        );
+
+        // Static clients can't be updated
+        let static_client = repo
+            .oauth2_client()
+            .upsert_static(
+                Ulid::from_datetime_with_source(clock.now().into(), &mut rng),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                None,
+                None,
+                Vec::new(),
+                false,
+                None,
+                false,
+                false,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(repo.oauth2_client().update(static_client).await.is_err());
+    }
+
     /// Test the [`OAuth2SessionRepository::list`] and
     /// [`OAuth2SessionRepository::count`] methods.
     #[sqlx::test(migrator = "crate::MIGRATOR")]
@@ -434,6 +707,9 @@ mod tests {
                 None,
                 None,
                 Some("https://first.example.com/login".parse().unwrap()),
+                false,
+                None,
+                false,
             )
             .await
             .unwrap();
@@ -458,6 +734,9 @@ mod tests {
                 None,
                 None,
                 Some("https://second.example.com/login".parse().unwrap()),
+                false,
+                None,
+                false,
             )
             .await
             .unwrap();
@@ -757,6 +1036,9 @@ mod tests {
                 None,
                 None,
                 Some("https://example.com/login".parse().unwrap()),
+                false,
+                None,
+                false,
             )
             .await
             .unwrap();
@@ -951,4 +1233,600 @@ mod tests {
             .await;
         assert!(res.is_err());
     }
+
+    /// Test the [`OAuth2CibaGrantRepository`] implementation
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_ciba_grant_repository(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        // Provision a client
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                vec![],
+                None,
+                None,
+                vec![GrantType::ClientInitiatedBackchannelAuthentication],
+                Some("Example".to_owned()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            client.grant_types,
+            vec![GrantType::ClientInitiatedBackchannelAuthentication]
+        );
+
+        // Provision a user
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "john".to_owned())
+            .await
+            .unwrap();
+
+        // Provision a browser session
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None)
+            .await
+            .unwrap();
+
+        let auth_req_id = "authreqid";
+        let scope = Scope::from_iter([OPENID, EMAIL]);
+
+        // Create a CIBA grant
+        let grant = repo
+            .oauth2_ciba_grant()
+            .add(
+                &mut rng,
+                &clock,
+                OAuth2CibaGrantParams {
+                    client: &client,
+                    user: &user,
+                    scope: scope.clone(),
+                    auth_req_id: auth_req_id.to_owned(),
+                    binding_message: Some("W4SCT".to_owned()),
+                    expires_in: Duration::try_minutes(5).unwrap(),
+                    ip_address: None,
+                    user_agent: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(grant.is_pending());
+        assert_eq!(grant.user_id, user.id);
+
+        // Check that we can find the grant by ID
+        let id = grant.id;
+        let lookup = repo.oauth2_ciba_grant().lookup(id).await.unwrap();
+        assert_eq!(lookup.as_ref(), Some(&grant));
+
+        // Check that we can find the grant by its auth_req_id
+        let lookup = repo
+            .oauth2_ciba_grant()
+            .find_by_auth_req_id(auth_req_id)
+            .await
+            .unwrap();
+        assert_eq!(lookup.as_ref(), Some(&grant));
+
+        // Let's mark it as fulfilled
+        let grant = repo
+            .oauth2_ciba_grant()
+            .fulfill(&clock, grant, &browser_session)
+            .await
+            .unwrap();
+        assert!(grant.is_fulfilled());
+
+        // Check that we can't mark it as rejected now
+        let res = repo
+            .oauth2_ciba_grant()
+            .reject(&clock, grant, &browser_session)
+            .await;
+        assert!(res.is_err());
+
+        // Look it up again
+        let grant = repo.oauth2_ciba_grant().lookup(id).await.unwrap().unwrap();
+        assert!(grant.is_fulfilled());
+
+        // Create an OAuth 2.0 session
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(&mut rng, &clock, &client, &browser_session, scope.clone())
+            .await
+            .unwrap();
+
+        // We can mark it as exchanged
+        let grant = repo
+            .oauth2_ciba_grant()
+            .exchange(&clock, grant, &session)
+            .await
+            .unwrap();
+        assert!(grant.is_exchanged());
+
+        // We can't mark it as exchanged again
+        let res = repo
+            .oauth2_ciba_grant()
+            .exchange(&clock, grant, &session)
+            .await;
+        assert!(res.is_err());
+
+        // Do a new grant to reject it
+        let grant = repo
+            .oauth2_ciba_grant()
+            .add(
+                &mut rng,
+                &clock,
+                OAuth2CibaGrantParams {
+                    client: &client,
+                    user: &user,
+                    scope,
+                    auth_req_id: "second_authreqid".to_owned(),
+                    binding_message: None,
+                    expires_in: Duration::try_minutes(5).unwrap(),
+                    ip_address: None,
+                    user_agent: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let id = grant.id;
+
+        // We can mark it as rejected
+        let grant = repo
+            .oauth2_ciba_grant()
+            .reject(&clock, grant, &browser_session)
+            .await
+            .unwrap();
+        assert!(grant.is_rejected());
+
+        // Look it up again
+        let grant = repo.oauth2_ciba_grant().lookup(id).await.unwrap().unwrap();
+        assert!(grant.is_rejected());
+
+        // We can't mark it as exchanged
+        let res = repo
+            .oauth2_ciba_grant()
+            .exchange(&clock, grant, &session)
+            .await;
+        assert!(res.is_err());
+    }
+
+    /// Test the [`OAuth2PushedAuthorizationRequestRepository`] implementation
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_pushed_authorization_request_repository(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        // Provision a client which must use pushed authorization requests
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                vec!["https://example.com/redirect".parse().unwrap()],
+                None,
+                None,
+                vec![GrantType::AuthorizationCode],
+                Some("Example".to_owned()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                true,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(client.require_pushed_authorization_requests);
+
+        // The flag is saved
+        let client = repo
+            .oauth2_client()
+            .lookup(client.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(client.require_pushed_authorization_requests);
+
+        let request_uri = "urn:ietf:params:oauth:request_uri:abcdef";
+        let parameters: BTreeMap<String, String> = [
+            ("response_type".to_owned(), "code".to_owned()),
+            ("client_id".to_owned(), client.client_id.clone()),
+            ("scope".to_owned(), "openid".to_owned()),
+        ]
+        .into();
+
+        let request = repo
+            .oauth2_pushed_authorization_request()
+            .add(
+                &mut rng,
+                &clock,
+                &client,
+                request_uri.to_owned(),
+                parameters.clone(),
+                Duration::try_seconds(60).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(request.client_id, client.id);
+        assert_eq!(request.parameters, parameters);
+        assert!(request.is_valid(clock.now()));
+
+        // Look it up by ID and by request_uri
+        let lookup = repo
+            .oauth2_pushed_authorization_request()
+            .lookup(request.id)
+            .await
+            .unwrap();
+        assert_eq!(lookup.as_ref(), Some(&request));
+
+        let lookup = repo
+            .oauth2_pushed_authorization_request()
+            .find_by_request_uri(request_uri)
+            .await
+            .unwrap();
+        assert_eq!(lookup.as_ref(), Some(&request));
+
+        let lookup = repo
+            .oauth2_pushed_authorization_request()
+            .find_by_request_uri("urn:ietf:params:oauth:request_uri:unknown")
+            .await
+            .unwrap();
+        assert_eq!(lookup, None);
+
+        // It expires after a while
+        clock.advance(Duration::try_minutes(2).unwrap());
+        assert!(!request.is_valid(clock.now()));
+
+        // It can be used only once
+        let request = repo
+            .oauth2_pushed_authorization_request()
+            .consume(&clock, request)
+            .await
+            .unwrap();
+        assert!(request.consumed_at.is_some());
+
+        let request = repo
+            .oauth2_pushed_authorization_request()
+            .lookup(request.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(request.consumed_at.is_some());
+
+        let res = repo
+            .oauth2_pushed_authorization_request()
+            .consume(&clock, request)
+            .await;
+        assert!(res.is_err());
+    }
+
+    /// Test that sessions are finished once expired, according to the
+    /// lifetimes set on their client or the defaults
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_finish_expired_sessions(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        // A client with a short inactivity timeout, and one using the defaults
+        let short_lived_client = repo
+            .oauth2_client()
+            .upsert_static(
+                Ulid::from_datetime_with_source(clock.now().into(), &mut rng),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                None,
+                None,
+                Vec::new(),
+                false,
+                None,
+                false,
+                false,
+                None,
+                None,
+                None,
+                Some(Duration::try_hours(1).unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            short_lived_client.session_inactivity_ttl,
+            Some(Duration::try_hours(1).unwrap())
+        );
+
+        let default_client = repo
+            .oauth2_client()
+            .upsert_static(
+                Ulid::from_datetime_with_source(clock.now().into(), &mut rng),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                None,
+                None,
+                Vec::new(),
+                false,
+                None,
+                false,
+                false,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        // The overrides are saved
+        let lookup = repo
+            .oauth2_client()
+            .lookup(short_lived_client.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup, short_lived_client);
+
+        let short_lived_session = repo
+            .oauth2_session()
+            .add_from_client_credentials(
+                &mut rng,
+                &clock,
+                &short_lived_client,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+        let default_session = repo
+            .oauth2_session()
+            .add_from_client_credentials(
+                &mut rng,
+                &clock,
+                &default_client,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        // Bots are not subject to the inactivity timeout
+        let bot = repo
+            .user()
+            .add(&mut rng, &clock, "robot".to_owned())
+            .await
+            .unwrap();
+        let bot = repo.user().set_bot(bot, None).await.unwrap();
+        let bot_session = repo
+            .oauth2_session()
+            .add(
+                &mut rng,
+                &clock,
+                &short_lived_client,
+                Some(&bot),
+                None,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let session_ttl = Some(Duration::try_days(1).unwrap());
+
+        // Nothing expired yet
+        let finished = repo
+            .oauth2_session()
+            .finish_expired(&clock, session_ttl, None, 100)
+            .await
+            .unwrap();
+        assert!(finished.is_empty());
+
+        // The session of the short-lived client expires first
+        clock.advance(Duration::try_hours(2).unwrap());
+        let finished = repo
+            .oauth2_session()
+            .finish_expired(&clock, session_ttl, None, 100)
+            .await
+            .unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].id, short_lived_session.id);
+        assert!(finished[0].is_finished());
+
+        // Then the other ones, once past the default maximum lifetime
+        clock.advance(Duration::try_days(1).unwrap());
+        let finished = repo
+            .oauth2_session()
+            .finish_expired(&clock, session_ttl, None, 100)
+            .await
+            .unwrap();
+        let mut finished: Vec<_> = finished.into_iter().map(|s| s.id).collect();
+        finished.sort();
+        assert_eq!(finished, [default_session.id, bot_session.id]);
+
+        // Without defaults, nothing else is finished
+        let finished = repo
+            .oauth2_session()
+            .finish_expired(&clock, None, None, 100)
+            .await
+            .unwrap();
+        assert!(finished.is_empty());
+    }
+
+    /// Test the [`OAuth2KioskDeviceRepository`] implementation, and that the
+    /// sessions of the users logged in on kiosk devices expire
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_kiosk_device_repository(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                Vec::new(),
+                None,
+                None,
+                vec![GrantType::ClientCredentials],
+                Some("Kiosk".to_owned()),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "john".to_owned())
+            .await
+            .unwrap();
+
+        let device = Device::generate(&mut rng);
+        let kiosk_device = repo
+            .oauth2_kiosk_device()
+            .add(&mut rng, &clock, &client, device.clone())
+            .await
+            .unwrap();
+        assert_eq!(kiosk_device.client_id, client.id);
+        assert_eq!(kiosk_device.device, device);
+        assert_eq!(kiosk_device.current_session_id, None);
+
+        // Look it up by ID and by device
+        let lookup = repo
+            .oauth2_kiosk_device()
+            .lookup(kiosk_device.id)
+            .await
+            .unwrap();
+        assert_eq!(lookup.as_ref(), Some(&kiosk_device));
+
+        let lookup = repo
+            .oauth2_kiosk_device()
+            .find_by_device(&client, &device)
+            .await
+            .unwrap();
+        assert_eq!(lookup.as_ref(), Some(&kiosk_device));
+
+        let lookup = repo
+            .oauth2_kiosk_device()
+            .find_by_device(&client, &Device::generate(&mut rng))
+            .await
+            .unwrap();
+        assert_eq!(lookup, None);
+
+        // Log a user in on the device
+        let session = repo
+            .oauth2_session()
+            .add(
+                &mut rng,
+                &clock,
+                &client,
+                Some(&user),
+                None,
+                Scope::from_iter([OPENID, device.to_scope_token()]),
+            )
+            .await
+            .unwrap();
+        let kiosk_device = repo
+            .oauth2_kiosk_device()
+            .set_current_session(kiosk_device, Some(&session))
+            .await
+            .unwrap();
+        assert_eq!(kiosk_device.current_session_id, Some(session.id));
+
+        let lookup = repo
+            .oauth2_kiosk_device()
+            .lookup(kiosk_device.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup.current_session_id, Some(session.id));
+
+        // Other sessions are not affected by the kiosk lifetimes
+        let other_session = repo
+            .oauth2_session()
+            .add(
+                &mut rng,
+                &clock,
+                &client,
+                Some(&user),
+                None,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let session_ttl = Duration::try_hours(1).unwrap();
+        let inactivity_ttl = Duration::try_minutes(5).unwrap();
+
+        // Nothing expired yet
+        let finished = repo
+            .oauth2_session()
+            .finish_expired_on_kiosks(&clock, session_ttl, inactivity_ttl, 100)
+            .await
+            .unwrap();
+        assert!(finished.is_empty());
+
+        // The session of the user on the kiosk expires after a short inactivity
+        clock.advance(Duration::try_minutes(10).unwrap());
+        let finished = repo
+            .oauth2_session()
+            .finish_expired_on_kiosks(&clock, session_ttl, inactivity_ttl, 100)
+            .await
+            .unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].id, session.id);
+        assert!(finished[0].is_finished());
+
+        let other_session = repo
+            .oauth2_session()
+            .lookup(other_session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(other_session.is_valid());
+
+        // Nobody is logged in on the device anymore
+        let kiosk_device = repo
+            .oauth2_kiosk_device()
+            .set_current_session(kiosk_device, None)
+            .await
+            .unwrap();
+        assert_eq!(kiosk_device.current_session_id, None);
+    }
 }
@@ -34,15 +34,20 @@ struct OAuth2RefreshTokenLookup {
     refresh_token: String,
     created_at: DateTime<Utc>,
     consumed_at: Option<DateTime<Utc>>,
+    next_oauth2_refresh_token_id: Option<Uuid>,
     oauth2_access_token_id: Option<Uuid>,
     oauth2_session_id: Uuid,
+    dpop_jkt: Option<String>,
 }
 
 impl From<OAuth2RefreshTokenLookup> for RefreshToken {
     fn from(value: OAuth2RefreshTokenLookup) -> Self {
         let state = match value.consumed_at {
             None => RefreshTokenState::Valid,
-            Some(consumed_at) => RefreshTokenState::Consumed { consumed_at },
+            Some(consumed_at) => RefreshTokenState::Consumed {
+                consumed_at,
+                next_refresh_token_id: value.next_oauth2_refresh_token_id.map(Ulid::from),
+            },
         };
 
         RefreshToken {
@@ -52,6 +57,7 @@ impl From<OAuth2RefreshTokenLookup> for RefreshToken {
             refresh_token: value.refresh_token,
             created_at: value.created_at,
             access_token_id: value.oauth2_access_token_id.map(Ulid::from),
+            dpop_jkt: value.dpop_jkt,
         }
     }
 }
@@ -77,8 +83,10 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
                      , refresh_token
                      , created_at
                      , consumed_at
+                     , next_oauth2_refresh_token_id
                      , oauth2_access_token_id
                      , oauth2_session_id
+                     , dpop_jkt
                 FROM oauth2_refresh_tokens
 
                 WHERE oauth2_refresh_token_id = $1
@@ -112,8 +120,10 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
                      , refresh_token
                      , created_at
                      , consumed_at
+                     , next_oauth2_refresh_token_id
                      , oauth2_access_token_id
                      , oauth2_session_id
+                     , dpop_jkt
                 FROM oauth2_refresh_tokens
 
                 WHERE refresh_token = $1
@@ -147,6 +157,7 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
         session: &Session,
         access_token: &AccessToken,
         refresh_token: String,
+        dpop_jkt: Option<String>,
     ) -> Result<RefreshToken, Self::Error> {
         let created_at = clock.now();
         let id = Ulid::from_datetime_with_source(created_at.into(), rng);
@@ -156,15 +167,16 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
             r#"
                 INSERT INTO oauth2_refresh_tokens
                     (oauth2_refresh_token_id, oauth2_session_id, oauth2_access_token_id,
-                     refresh_token, created_at)
+                     refresh_token, created_at, dpop_jkt)
                 VALUES
-                    ($1, $2, $3, $4, $5)
+                    ($1, $2, $3, $4, $5, $6)
             "#,
             Uuid::from(id),
             Uuid::from(session.id),
             Uuid::from(access_token.id),
             refresh_token,
             created_at,
+            dpop_jkt.as_deref(),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -177,6 +189,7 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
             refresh_token,
             access_token_id: Some(access_token.id),
             created_at,
+            dpop_jkt,
         })
     }
 
@@ -187,6 +200,7 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
             db.query.text,
             %refresh_token.id,
             session.id = %refresh_token.session_id,
+            next_refresh_token.id = %replaced_by.id,
         ),
         err,
     )]
@@ -194,16 +208,19 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
         &mut self,
         clock: &dyn Clock,
         refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
     ) -> Result<RefreshToken, Self::Error> {
         let consumed_at = clock.now();
         let res = sqlx::query!(
             r#"
                 UPDATE oauth2_refresh_tokens
                 SET consumed_at = $2
+                  , next_oauth2_refresh_token_id = $3
                 WHERE oauth2_refresh_token_id = $1
             "#,
             Uuid::from(refresh_token.id),
             consumed_at,
+            Uuid::from(replaced_by.id),
         )
         .execute(&mut *self.conn)
         .await?;
@@ -211,7 +228,7 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
         DatabaseError::ensure_affected_rows(&res, 1)?;
 
         refresh_token
-            .consume(consumed_at)
+            .consume(consumed_at, replaced_by)
             .map_err(DatabaseError::to_invalid_operation)
     }
 }
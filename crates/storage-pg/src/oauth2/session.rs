@@ -7,10 +7,11 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{BrowserSession, Client, Session, SessionState, User, UserAgent};
 use mas_storage::{
     oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    pagination::Count,
     Clock, Page, Pagination,
 };
 use oauth2_types::scope::{Scope, ScopeToken};
@@ -24,7 +25,7 @@ use uuid::Uuid;
 use crate::{
     filter::{Filter, StatementExt},
     iden::OAuth2Sessions,
-    pagination::QueryBuilderExt,
+    pagination::{estimate_count, QueryBuilderExt},
     tracing::ExecuteExt,
     DatabaseError, DatabaseInconsistencyError,
 };
@@ -55,6 +56,7 @@ struct OAuthSessionLookup {
     user_agent: Option<String>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
+    tags: Vec<String>,
 }
 
 impl TryFrom<OAuthSessionLookup> for Session {
@@ -90,6 +92,7 @@ impl TryFrom<OAuthSessionLookup> for Session {
             user_agent: value.user_agent.map(UserAgent::parse),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
+            tags: value.tags,
         })
     }
 }
@@ -133,6 +136,12 @@ impl Filter for OAuth2SessionFilter<'_> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveAt))
                     .lt(last_active_before)
             }))
+            .add_option(self.tag().map(|tag| {
+                Expr::val(tag).eq(PgFunc::any(Expr::col((
+                    OAuth2Sessions::Table,
+                    OAuth2Sessions::Tags,
+                ))))
+            }))
     }
 }
 
@@ -163,6 +172,7 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                      , user_agent
                      , last_active_at
                      , last_active_ip as "last_active_ip: IpAddr"
+                     , tags
                 FROM oauth2_sessions
 
                 WHERE oauth2_session_id = $1
@@ -238,6 +248,7 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
             user_agent: None,
             last_active_at: None,
             last_active_ip: None,
+            tags: Vec::new(),
         })
     }
 
@@ -269,6 +280,134 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
         Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_session.finish_expired",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        default_session_ttl: Option<Duration>,
+        default_inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<Vec<Session>, Self::Error> {
+        let finished_at = clock.now();
+        let limit: i64 = limit.try_into().unwrap_or(i64::MAX);
+        let res = sqlx::query_as!(
+            OAuthSessionLookup,
+            r#"
+                UPDATE oauth2_sessions
+                SET finished_at = $1
+                WHERE oauth2_session_id IN (
+                    SELECT s.oauth2_session_id
+                    FROM oauth2_sessions s
+                    INNER JOIN oauth2_clients c USING (oauth2_client_id)
+                    LEFT JOIN users u USING (user_id)
+                    WHERE s.finished_at IS NULL
+                      AND (
+                        s.created_at
+                            + make_interval(secs => COALESCE(c.session_ttl, $2))
+                            < $1
+                        OR (
+                          u.is_bot IS NOT TRUE
+                          AND COALESCE(s.last_active_at, s.created_at)
+                            + make_interval(secs => COALESCE(c.session_inactivity_ttl, $3))
+                            < $1
+                        )
+                      )
+                    LIMIT $4
+                )
+                RETURNING oauth2_session_id
+                        , user_id
+                        , user_session_id
+                        , oauth2_client_id
+                        , scope_list
+                        , created_at
+                        , finished_at
+                        , user_agent
+                        , last_active_at
+                        , last_active_ip as "last_active_ip: IpAddr"
+                        , tags
+            "#,
+            finished_at,
+            default_session_ttl.map(|ttl| ttl.num_seconds()),
+            default_inactivity_ttl.map(|ttl| ttl.num_seconds()),
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| r.try_into().map_err(DatabaseError::from))
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.finish_expired_on_kiosks",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn finish_expired_on_kiosks(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Duration,
+        inactivity_ttl: Duration,
+        limit: usize,
+    ) -> Result<Vec<Session>, Self::Error> {
+        let finished_at = clock.now();
+        let limit: i64 = limit.try_into().unwrap_or(i64::MAX);
+        let res = sqlx::query_as!(
+            OAuthSessionLookup,
+            r#"
+                UPDATE oauth2_sessions
+                SET finished_at = $1
+                WHERE oauth2_session_id IN (
+                    SELECT s.oauth2_session_id
+                    FROM oauth2_sessions s
+                    INNER JOIN oauth2_kiosk_devices k
+                      ON k.current_oauth2_session_id = s.oauth2_session_id
+                    WHERE s.finished_at IS NULL
+                      AND (
+                        s.created_at + make_interval(secs => $2::BIGINT) < $1
+                        OR COALESCE(s.last_active_at, s.created_at)
+                          + make_interval(secs => $3::BIGINT) < $1
+                      )
+                    LIMIT $4
+                )
+                RETURNING oauth2_session_id
+                        , user_id
+                        , user_session_id
+                        , oauth2_client_id
+                        , scope_list
+                        , created_at
+                        , finished_at
+                        , user_agent
+                        , last_active_at
+                        , last_active_ip as "last_active_ip: IpAddr"
+                        , tags
+            "#,
+            finished_at,
+            session_ttl.num_seconds(),
+            inactivity_ttl.num_seconds(),
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| r.try_into().map_err(DatabaseError::from))
+            .collect()
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_session.finish",
         skip_all,
@@ -360,6 +499,10 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
                 OAuthSessionLookupIden::LastActiveIp,
             )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::Tags)),
+                OAuthSessionLookupIden::Tags,
+            )
             .from(OAuth2Sessions::Table)
             .apply_filter(filter)
             .generate_pagination(
@@ -403,6 +546,30 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
             .map_err(DatabaseError::to_invalid_operation)
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_session.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(
+        &mut self,
+        filter: OAuth2SessionFilter<'_>,
+    ) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((
+                OAuth2Sessions::Table,
+                OAuth2Sessions::OAuth2SessionId,
+            )))
+            .from(OAuth2Sessions::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_session.record_batch_activity",
         skip_all,
@@ -486,4 +653,73 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
 
         Ok(session)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.add_tag",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+            session.tag = tag,
+        ),
+        err,
+    )]
+    async fn add_tag(&mut self, mut session: Session, tag: &str) -> Result<Session, Self::Error> {
+        // Do the change in a single statement, so that concurrent changes to the
+        // tags are not lost
+        let tags = sqlx::query_scalar!(
+            r#"
+                UPDATE oauth2_sessions
+                SET tags = CASE
+                    WHEN $2 = ANY(tags) THEN tags
+                    ELSE array_append(tags, $2)
+                END
+                WHERE oauth2_session_id = $1
+                RETURNING tags
+            "#,
+            Uuid::from(session.id),
+            tag,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        session.tags = tags;
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.remove_tag",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+            session.tag = tag,
+        ),
+        err,
+    )]
+    async fn remove_tag(
+        &mut self,
+        mut session: Session,
+        tag: &str,
+    ) -> Result<Session, Self::Error> {
+        let tags = sqlx::query_scalar!(
+            r#"
+                UPDATE oauth2_sessions
+                SET tags = array_remove(tags, $2)
+                WHERE oauth2_session_id = $1
+                RETURNING tags
+            "#,
+            Uuid::from(session.id),
+            tag,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        session.tags = tags;
+
+        Ok(session)
+    }
 }
@@ -6,10 +6,21 @@
 
 //! Utilities to manage paginated queries.
 
-use mas_storage::{pagination::PaginationDirection, Pagination};
-use sea_query::IntoColumnRef;
+use mas_storage::{
+    pagination::{Count, PaginationDirection},
+    Pagination,
+};
+use sea_query::{Alias, Expr, IntoColumnRef, PostgresQueryBuilder, Query, SelectStatement};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
 use uuid::Uuid;
 
+use crate::{DatabaseError, ExecuteExt};
+
+/// Above this estimated number of rows, counting them exactly is considered too
+/// expensive, and the estimation of the query planner is used instead
+const EXACT_COUNT_THRESHOLD: usize = 10_000;
+
 /// An extension trait to the `sqlx` [`QueryBuilder`], to help adding pagination
 /// to a query
 pub trait QueryBuilderExt {
@@ -63,3 +74,53 @@ impl QueryBuilderExt for sea_query::SelectStatement {
         self
     }
 }
+
+/// Count the rows matched by a query, falling back to the estimation of the
+/// query planner if there are too many of them to count them exactly
+///
+/// # Parameters
+///
+/// * `conn`: The connection to run the queries on
+/// * `statement`: A query selecting the ID column of the rows to count, with
+///   the filters applied
+///
+/// # Errors
+///
+/// Returns an error if the queries fail, or if the query plan can't be parsed
+pub(crate) async fn estimate_count(
+    conn: &mut PgConnection,
+    statement: &SelectStatement,
+) -> Result<Count, DatabaseError> {
+    let (sql, arguments) = statement.build_sqlx(PostgresQueryBuilder);
+    let plan: serde_json::Value =
+        sqlx::query_scalar_with(&format!("EXPLAIN (FORMAT JSON) {sql}"), arguments)
+            .traced()
+            .fetch_one(&mut *conn)
+            .await?;
+
+    let estimated = plan
+        .pointer("/0/Plan/Plan Rows")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_else(DatabaseError::invalid_operation)?;
+    let estimated = usize::try_from(estimated).map_err(DatabaseError::to_invalid_operation)?;
+
+    if estimated > EXACT_COUNT_THRESHOLD {
+        return Ok(Count::Estimated(estimated));
+    }
+
+    let (sql, arguments) = Query::select()
+        .expr(Expr::cust("COUNT(*)"))
+        .from_subquery(statement.clone(), Alias::new("rows"))
+        .build_sqlx(PostgresQueryBuilder);
+
+    let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+        .traced()
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let count = count
+        .try_into()
+        .map_err(DatabaseError::to_invalid_operation)?;
+
+    Ok(Count::Exact(count))
+}
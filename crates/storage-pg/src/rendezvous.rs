@@ -0,0 +1,348 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`RendezvousSessionRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::RendezvousSession;
+use mas_storage::{rendezvous::RendezvousSessionRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`RendezvousSessionRepository`] for a PostgreSQL
+/// connection
+pub struct PgRendezvousSessionRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgRendezvousSessionRepository<'c> {
+    /// Create a new [`PgRendezvousSessionRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct RendezvousSessionLookup {
+    rendezvous_session_id: Uuid,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    etag: String,
+    content_type: String,
+    content: Vec<u8>,
+}
+
+impl From<RendezvousSessionLookup> for RendezvousSession {
+    fn from(value: RendezvousSessionLookup) -> Self {
+        RendezvousSession {
+            id: value.rendezvous_session_id.into(),
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            expires_at: value.expires_at,
+            etag: value.etag,
+            content_type: value.content_type,
+            content: value.content,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> RendezvousSessionRepository for PgRendezvousSessionRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.rendezvous_session.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            rendezvous_session.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<RendezvousSession>, Self::Error> {
+        let res = sqlx::query_as!(
+            RendezvousSessionLookup,
+            r#"
+                SELECT rendezvous_session_id
+                     , created_at
+                     , updated_at
+                     , expires_at
+                     , etag
+                     , content_type
+                     , content
+                FROM rendezvous_sessions
+                WHERE rendezvous_session_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.rendezvous_session.add",
+        skip_all,
+        fields(
+            db.query.text,
+            rendezvous_session.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        ttl: Duration,
+        content_type: String,
+        content: Vec<u8>,
+    ) -> Result<RendezvousSession, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + ttl;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        let etag = Ulid::from_datetime_with_source(created_at.into(), rng).to_string();
+        tracing::Span::current().record("rendezvous_session.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO rendezvous_sessions
+                    ( rendezvous_session_id
+                    , created_at
+                    , updated_at
+                    , expires_at
+                    , etag
+                    , content_type
+                    , content
+                    )
+                VALUES ($1, $2, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            created_at,
+            expires_at,
+            &etag,
+            &content_type,
+            &content,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(RendezvousSession {
+            id,
+            created_at,
+            updated_at: created_at,
+            expires_at,
+            etag,
+            content_type,
+            content,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.rendezvous_session.update",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+        ),
+        err,
+    )]
+    async fn update(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        mut session: RendezvousSession,
+        content_type: String,
+        content: Vec<u8>,
+    ) -> Result<Option<RendezvousSession>, Self::Error> {
+        let updated_at = clock.now();
+        let etag = Ulid::from_datetime_with_source(updated_at.into(), rng).to_string();
+
+        // Only replace the content if nobody else did since it was looked up
+        let res = sqlx::query!(
+            r#"
+                UPDATE rendezvous_sessions
+                SET updated_at = $3
+                  , etag = $4
+                  , content_type = $5
+                  , content = $6
+                WHERE rendezvous_session_id = $1
+                  AND etag = $2
+            "#,
+            Uuid::from(session.id),
+            &session.etag,
+            updated_at,
+            &etag,
+            &content_type,
+            &content,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        session.updated_at = updated_at;
+        session.etag = etag;
+        session.content_type = content_type;
+        session.content = content;
+        Ok(Some(session))
+    }
+
+    #[tracing::instrument(
+        name = "db.rendezvous_session.remove",
+        skip_all,
+        fields(
+            db.query.text,
+            %session.id,
+        ),
+        err,
+    )]
+    async fn remove(&mut self, session: RendezvousSession) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM rendezvous_sessions
+                WHERE rendezvous_session_id = $1
+            "#,
+            Uuid::from(session.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.rendezvous_session.cleanup_expired",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM rendezvous_sessions
+                WHERE expires_at <= $1
+            "#,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_rendezvous_session_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let session = repo
+            .rendezvous_session()
+            .add(
+                &mut rng,
+                &clock,
+                Duration::try_minutes(1).unwrap(),
+                "text/plain".to_owned(),
+                b"hello".to_vec(),
+            )
+            .await
+            .unwrap();
+        assert!(!session.is_expired(clock.now()));
+
+        let lookup = repo
+            .rendezvous_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup, session);
+
+        // Replacing the content changes the ETag
+        clock.advance(Duration::try_seconds(10).unwrap());
+        let updated = repo
+            .rendezvous_session()
+            .update(
+                &mut rng,
+                &clock,
+                session.clone(),
+                "text/plain".to_owned(),
+                b"world".to_vec(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(updated.etag, session.etag);
+        assert_eq!(updated.content, b"world");
+        assert_eq!(updated.updated_at, clock.now());
+
+        // Updating from a stale copy doesn't overwrite the content
+        let stale = repo
+            .rendezvous_session()
+            .update(
+                &mut rng,
+                &clock,
+                session,
+                "text/plain".to_owned(),
+                b"stale".to_vec(),
+            )
+            .await
+            .unwrap();
+        assert!(stale.is_none());
+        let lookup = repo
+            .rendezvous_session()
+            .lookup(updated.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup, updated);
+
+        // Expired sessions are cleaned up
+        clock.advance(Duration::try_minutes(1).unwrap());
+        assert!(updated.is_expired(clock.now()));
+        assert_eq!(
+            repo.rendezvous_session()
+                .cleanup_expired(&clock)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(repo
+            .rendezvous_session()
+            .lookup(updated.id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -9,16 +9,33 @@ use std::ops::{Deref, DerefMut};
 use futures_util::{future::BoxFuture, FutureExt, TryFutureExt};
 use mas_storage::{
     app_session::AppSessionRepository,
+    audit::AuditEventRepository,
+    cache::{
+        CacheTransaction, CachedCompatAccessTokenRepository, CachedOAuth2AccessTokenRepository,
+        CachedOAuth2ClientRepository, RepositoryCache,
+    },
+    changes_feed::ChangesFeedRepository,
     compat::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
     },
+    email_delivery::EmailDeliveryFailureRepository,
+    event_outbox::EventOutboxRepository,
+    expiry_warning::ExpiryWarningRepository,
+    honeytoken::HoneytokenRepository,
     job::JobRepository,
     oauth2::{
-        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
-        OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2CibaGrantRepository,
+        OAuth2ClientRepository, OAuth2DeviceCodeGrantRepository,
+        OAuth2JwtBearerAssertionRepository, OAuth2KioskDeviceRepository,
+        OAuth2PushedAuthorizationRequestRepository, OAuth2RefreshTokenRepository,
+        OAuth2SessionRepository,
     },
+    rendezvous::RendezvousSessionRepository,
+    runtime_resource::RuntimeResourceRepository,
+    signing_key::SigningKeyRepository,
     upstream_oauth2::{
+        UpstreamOAuthEmailChangeRepository, UpstreamOAuthFunnelRepository,
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
@@ -30,23 +47,38 @@ use tracing::Instrument;
 
 use crate::{
     app_session::PgAppSessionRepository,
+    audit::PgAuditEventRepository,
+    changes_feed::PgChangesFeedRepository,
     compat::{
         PgCompatAccessTokenRepository, PgCompatRefreshTokenRepository, PgCompatSessionRepository,
         PgCompatSsoLoginRepository,
     },
+    email_delivery::PgEmailDeliveryFailureRepository,
+    event_outbox::PgEventOutboxRepository,
+    expiry_warning::PgExpiryWarningRepository,
+    honeytoken::PgHoneytokenRepository,
     job::PgJobRepository,
     oauth2::{
         PgOAuth2AccessTokenRepository, PgOAuth2AuthorizationGrantRepository,
-        PgOAuth2ClientRepository, PgOAuth2DeviceCodeGrantRepository,
-        PgOAuth2RefreshTokenRepository, PgOAuth2SessionRepository,
+        PgOAuth2CibaGrantRepository, PgOAuth2ClientRepository, PgOAuth2DeviceCodeGrantRepository,
+        PgOAuth2JwtBearerAssertionRepository, PgOAuth2KioskDeviceRepository,
+        PgOAuth2PushedAuthorizationRequestRepository, PgOAuth2RefreshTokenRepository,
+        PgOAuth2SessionRepository,
     },
+    rendezvous::PgRendezvousSessionRepository,
+    runtime_resource::PgRuntimeResourceRepository,
+    signing_key::PgSigningKeyRepository,
     upstream_oauth2::{
+        PgUpstreamOAuthEmailChangeRepository, PgUpstreamOAuthFunnelRepository,
         PgUpstreamOAuthLinkRepository, PgUpstreamOAuthProviderRepository,
         PgUpstreamOAuthSessionRepository,
     },
     user::{
-        PgBrowserSessionRepository, PgUserEmailRepository, PgUserPasswordRepository,
+        PgBrowserSessionRepository, PgUserActivityDigestRepository, PgUserEmailRepository,
+        PgUserEmergencyAccessRepository, PgUserKnownDeviceRepository,
+        PgUserNotificationPreferencesRepository, PgUserPasswordRepository,
         PgUserRecoveryRepository, PgUserRepository, PgUserTermsRepository,
+        PgUserTrustedBrowserRepository,
     },
     DatabaseError,
 };
@@ -55,6 +87,7 @@ use crate::{
 /// transaction.
 pub struct PgRepository<C = Transaction<'static, Postgres>> {
     conn: C,
+    cache: Option<CacheTransaction>,
 }
 
 impl PgRepository {
@@ -69,6 +102,24 @@ impl PgRepository {
         Ok(Self::from_conn(txn))
     }
 
+    /// Create a new [`PgRepository`] from a PostgreSQL connection pool,
+    /// starting a read-only transaction.
+    ///
+    /// This is meant to be used with a pool connected to a read-only replica
+    /// of the database, for pure lookups. Any write done through this
+    /// repository will fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DatabaseError`] if the transaction could not be started.
+    pub async fn from_pool_read_only(pool: &PgPool) -> Result<Self, DatabaseError> {
+        let mut txn = pool.begin().await?;
+        sqlx::query("SET TRANSACTION READ ONLY")
+            .execute(&mut *txn)
+            .await?;
+        Ok(Self::from_conn(txn))
+    }
+
     /// Transform the repository into a type-erased [`BoxRepository`]
     pub fn boxed(self) -> BoxRepository {
         Box::new(MapErr::new(self, RepositoryError::from_error))
@@ -79,7 +130,18 @@ impl<C> PgRepository<C> {
     /// Create a new [`PgRepository`] from an existing PostgreSQL connection
     /// with a transaction
     pub fn from_conn(conn: C) -> Self {
-        PgRepository { conn }
+        PgRepository { conn, cache: None }
+    }
+
+    /// Use the given [`RepositoryCache`] for OAuth 2.0 client and access token
+    /// lookups
+    ///
+    /// Cached records changed through this repository are invalidated once
+    /// the transaction is saved.
+    #[must_use]
+    pub fn with_cache(mut self, cache: RepositoryCache) -> Self {
+        self.cache = Some(cache.transaction());
+        self
     }
 
     /// Consume this [`PgRepository`], returning the underlying connection.
@@ -121,11 +183,16 @@ impl RepositoryTransaction for PgRepository {
 
     fn save(self: Box<Self>) -> BoxFuture<'static, Result<(), Self::Error>> {
         let span = tracing::info_span!("db.save");
-        self.conn
-            .commit()
-            .map_err(DatabaseError::from)
-            .instrument(span)
-            .boxed()
+        let Self { conn, cache } = *self;
+        async move {
+            conn.commit().await?;
+            if let Some(cache) = cache {
+                cache.commit();
+            }
+            Ok::<_, DatabaseError>(())
+        }
+        .instrument(span)
+        .boxed()
     }
 
     fn cancel(self: Box<Self>) -> BoxFuture<'static, Result<(), Self::Error>> {
@@ -162,6 +229,20 @@ where
         Box::new(PgUpstreamOAuthSessionRepository::new(self.conn.as_mut()))
     }
 
+    fn upstream_oauth_funnel<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthFunnelRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUpstreamOAuthFunnelRepository::new(self.conn.as_mut()))
+    }
+
+    fn upstream_oauth_email_change<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthEmailChangeRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUpstreamOAuthEmailChangeRepository::new(
+            self.conn.as_mut(),
+        ))
+    }
+
     fn user<'c>(&'c mut self) -> Box<dyn UserRepository<Error = Self::Error> + 'c> {
         Box::new(PgUserRepository::new(self.conn.as_mut()))
     }
@@ -188,6 +269,39 @@ where
         Box::new(PgUserTermsRepository::new(self.conn.as_mut()))
     }
 
+    fn user_emergency_access<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserEmergencyAccessRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserEmergencyAccessRepository::new(self.conn.as_mut()))
+    }
+
+    fn user_activity_digest<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserActivityDigestRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserActivityDigestRepository::new(self.conn.as_mut()))
+    }
+
+    fn user_known_device<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserKnownDeviceRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserKnownDeviceRepository::new(self.conn.as_mut()))
+    }
+
+    fn user_notification_preferences<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserNotificationPreferencesRepository<Error = Self::Error> + 'c>
+    {
+        Box::new(PgUserNotificationPreferencesRepository::new(
+            self.conn.as_mut(),
+        ))
+    }
+
+    fn user_trusted_browser<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserTrustedBrowserRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserTrustedBrowserRepository::new(self.conn.as_mut()))
+    }
+
     fn browser_session<'c>(
         &'c mut self,
     ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -198,10 +312,58 @@ where
         Box::new(PgAppSessionRepository::new(self.conn.as_mut()))
     }
 
+    fn audit_event<'c>(&'c mut self) -> Box<dyn AuditEventRepository<Error = Self::Error> + 'c> {
+        Box::new(PgAuditEventRepository::new(self.conn.as_mut()))
+    }
+
+    fn email_delivery_failure<'c>(
+        &'c mut self,
+    ) -> Box<dyn EmailDeliveryFailureRepository<Error = Self::Error> + 'c> {
+        Box::new(PgEmailDeliveryFailureRepository::new(self.conn.as_mut()))
+    }
+
+    fn honeytoken<'c>(&'c mut self) -> Box<dyn HoneytokenRepository<Error = Self::Error> + 'c> {
+        Box::new(PgHoneytokenRepository::new(self.conn.as_mut()))
+    }
+
+    fn expiry_warning<'c>(
+        &'c mut self,
+    ) -> Box<dyn ExpiryWarningRepository<Error = Self::Error> + 'c> {
+        Box::new(PgExpiryWarningRepository::new(self.conn.as_mut()))
+    }
+
+    fn event_outbox<'c>(&'c mut self) -> Box<dyn EventOutboxRepository<Error = Self::Error> + 'c> {
+        Box::new(PgEventOutboxRepository::new(self.conn.as_mut()))
+    }
+
+    fn changes_feed<'c>(&'c mut self) -> Box<dyn ChangesFeedRepository<Error = Self::Error> + 'c> {
+        Box::new(PgChangesFeedRepository::new(self.conn.as_mut()))
+    }
+
+    fn signing_key<'c>(&'c mut self) -> Box<dyn SigningKeyRepository<Error = Self::Error> + 'c> {
+        Box::new(PgSigningKeyRepository::new(self.conn.as_mut()))
+    }
+
+    fn runtime_resource<'c>(
+        &'c mut self,
+    ) -> Box<dyn RuntimeResourceRepository<Error = Self::Error> + 'c> {
+        Box::new(PgRuntimeResourceRepository::new(self.conn.as_mut()))
+    }
+
+    fn rendezvous_session<'c>(
+        &'c mut self,
+    ) -> Box<dyn RendezvousSessionRepository<Error = Self::Error> + 'c> {
+        Box::new(PgRendezvousSessionRepository::new(self.conn.as_mut()))
+    }
+
     fn oauth2_client<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c> {
-        Box::new(PgOAuth2ClientRepository::new(self.conn.as_mut()))
+        let repo = PgOAuth2ClientRepository::new(self.conn.as_mut());
+        match &self.cache {
+            Some(cache) => Box::new(CachedOAuth2ClientRepository::new(repo, cache.clone())),
+            None => Box::new(repo),
+        }
     }
 
     fn oauth2_authorization_grant<'c>(
@@ -221,7 +383,11 @@ where
     fn oauth2_access_token<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2AccessTokenRepository<Error = Self::Error> + 'c> {
-        Box::new(PgOAuth2AccessTokenRepository::new(self.conn.as_mut()))
+        let repo = PgOAuth2AccessTokenRepository::new(self.conn.as_mut());
+        match &self.cache {
+            Some(cache) => Box::new(CachedOAuth2AccessTokenRepository::new(repo, cache.clone())),
+            None => Box::new(repo),
+        }
     }
 
     fn oauth2_refresh_token<'c>(
@@ -236,6 +402,34 @@ where
         Box::new(PgOAuth2DeviceCodeGrantRepository::new(self.conn.as_mut()))
     }
 
+    fn oauth2_ciba_grant<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2CibaGrantRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2CibaGrantRepository::new(self.conn.as_mut()))
+    }
+
+    fn oauth2_pushed_authorization_request<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2PushedAuthorizationRequestRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2PushedAuthorizationRequestRepository::new(
+            self.conn.as_mut(),
+        ))
+    }
+
+    fn oauth2_jwt_bearer_assertion<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2JwtBearerAssertionRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2JwtBearerAssertionRepository::new(
+            self.conn.as_mut(),
+        ))
+    }
+
+    fn oauth2_kiosk_device<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2KioskDeviceRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2KioskDeviceRepository::new(self.conn.as_mut()))
+    }
+
     fn compat_session<'c>(
         &'c mut self,
     ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
@@ -251,7 +445,11 @@ where
     fn compat_access_token<'c>(
         &'c mut self,
     ) -> Box<dyn CompatAccessTokenRepository<Error = Self::Error> + 'c> {
-        Box::new(PgCompatAccessTokenRepository::new(self.conn.as_mut()))
+        let repo = PgCompatAccessTokenRepository::new(self.conn.as_mut());
+        match &self.cache {
+            Some(cache) => Box::new(CachedCompatAccessTokenRepository::new(repo, cache.clone())),
+            None => Box::new(repo),
+        }
     }
 
     fn compat_refresh_token<'c>(
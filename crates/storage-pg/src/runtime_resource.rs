@@ -0,0 +1,257 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`RuntimeResourceRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{DisabledResource, RuntimeResource};
+use mas_storage::{runtime_resource::RuntimeResourceRepository, Clock};
+use sqlx::PgConnection;
+
+use crate::{DatabaseError, DatabaseInconsistencyError, ExecuteExt};
+
+/// An implementation of [`RuntimeResourceRepository`] for a PostgreSQL
+/// connection
+pub struct PgRuntimeResourceRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgRuntimeResourceRepository<'c> {
+    /// Create a new [`PgRuntimeResourceRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct DisabledResourceRow {
+    resource: String,
+    disabled_at: DateTime<Utc>,
+    reason: Option<String>,
+}
+
+impl TryFrom<DisabledResourceRow> for DisabledResource {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(row: DisabledResourceRow) -> Result<Self, Self::Error> {
+        let resource = row.resource.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("disabled_resources")
+                .column("resource")
+                .source(e)
+        })?;
+
+        Ok(DisabledResource {
+            resource,
+            disabled_at: row.disabled_at,
+            reason: row.reason,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> RuntimeResourceRepository for PgRuntimeResourceRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.runtime_resource.list_disabled",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list_disabled(&mut self) -> Result<Vec<DisabledResource>, Self::Error> {
+        let rows = sqlx::query_as!(
+            DisabledResourceRow,
+            r#"
+                SELECT resource
+                     , disabled_at
+                     , reason
+                FROM disabled_resources
+                ORDER BY resource ASC
+            "#,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| row.try_into().map_err(DatabaseError::from))
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.runtime_resource.is_disabled",
+        skip_all,
+        fields(
+            db.query.text,
+            %resource,
+        ),
+        err,
+    )]
+    async fn is_disabled(&mut self, resource: RuntimeResource) -> Result<bool, Self::Error> {
+        let disabled = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1
+                    FROM disabled_resources
+                    WHERE resource = $1
+                ) AS "disabled!"
+            "#,
+            resource.as_str(),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(disabled)
+    }
+
+    #[tracing::instrument(
+        name = "db.runtime_resource.disable",
+        skip_all,
+        fields(
+            db.query.text,
+            %resource,
+        ),
+        err,
+    )]
+    async fn disable(
+        &mut self,
+        clock: &dyn Clock,
+        resource: RuntimeResource,
+        reason: Option<String>,
+    ) -> Result<DisabledResource, Self::Error> {
+        // Keep the original time if the resource was already disabled
+        let row = sqlx::query_as!(
+            DisabledResourceRow,
+            r#"
+                INSERT INTO disabled_resources (resource, disabled_at, reason)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (resource) DO UPDATE
+                    SET reason = EXCLUDED.reason
+                RETURNING resource
+                        , disabled_at
+                        , reason
+            "#,
+            resource.as_str(),
+            clock.now(),
+            reason,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(row.try_into()?)
+    }
+
+    #[tracing::instrument(
+        name = "db.runtime_resource.enable",
+        skip_all,
+        fields(
+            db.query.text,
+            %resource,
+        ),
+        err,
+    )]
+    async fn enable(&mut self, resource: RuntimeResource) -> Result<bool, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM disabled_resources
+                WHERE resource = $1
+            "#,
+            resource.as_str(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::RuntimeResource;
+    use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_runtime_resource_repo(pool: PgPool) {
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        // Everything is enabled by default
+        assert!(repo
+            .runtime_resource()
+            .list_disabled()
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(!repo
+            .runtime_resource()
+            .is_disabled(RuntimeResource::CompatLogin)
+            .await
+            .unwrap());
+
+        let disabled = repo
+            .runtime_resource()
+            .disable(
+                &clock,
+                RuntimeResource::CompatLogin,
+                Some("Credential stuffing".to_owned()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(disabled.resource, RuntimeResource::CompatLogin);
+        assert_eq!(disabled.disabled_at, clock.now());
+        assert!(repo
+            .runtime_resource()
+            .is_disabled(RuntimeResource::CompatLogin)
+            .await
+            .unwrap());
+        assert!(!repo
+            .runtime_resource()
+            .is_disabled(RuntimeResource::ClientRegistration)
+            .await
+            .unwrap());
+
+        // Disabling it again only updates the reason
+        clock.advance(Duration::try_minutes(5).unwrap());
+        let updated = repo
+            .runtime_resource()
+            .disable(&clock, RuntimeResource::CompatLogin, None)
+            .await
+            .unwrap();
+        assert_eq!(updated.disabled_at, disabled.disabled_at);
+        assert_eq!(updated.reason, None);
+        assert_eq!(
+            repo.runtime_resource().list_disabled().await.unwrap(),
+            vec![updated]
+        );
+
+        assert!(repo
+            .runtime_resource()
+            .enable(RuntimeResource::CompatLogin)
+            .await
+            .unwrap());
+        assert!(!repo
+            .runtime_resource()
+            .enable(RuntimeResource::CompatLogin)
+            .await
+            .unwrap());
+        assert!(repo
+            .runtime_resource()
+            .list_disabled()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
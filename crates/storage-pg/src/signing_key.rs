@@ -0,0 +1,305 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`SigningKeyRepository`]
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::SigningKey;
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_storage::{signing_key::SigningKeyRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, DatabaseInconsistencyError, ExecuteExt};
+
+/// The key of the advisory lock used to serialize the rotation of the signing
+/// keys
+const SIGNING_KEYS_LOCK_KEY: i64 = 0x6d61_735f_6b65_7973;
+
+/// An implementation of [`SigningKeyRepository`] for a PostgreSQL connection
+pub struct PgSigningKeyRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgSigningKeyRepository<'c> {
+    /// Create a new [`PgSigningKeyRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct SigningKeyLookup {
+    signing_key_id: Uuid,
+    kid: String,
+    algorithm: String,
+    encrypted_key: String,
+    created_at: DateTime<Utc>,
+    activates_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<SigningKeyLookup> for SigningKey {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: SigningKeyLookup) -> Result<Self, Self::Error> {
+        let id = value.signing_key_id.into();
+        let algorithm = value.algorithm.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("signing_keys")
+                .column("algorithm")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(SigningKey {
+            id,
+            kid: value.kid,
+            algorithm,
+            encrypted_key: value.encrypted_key,
+            created_at: value.created_at,
+            activates_at: value.activates_at,
+            retired_at: value.retired_at,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> SigningKeyRepository for PgSigningKeyRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.signing_key.lock",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn lock(&mut self) -> Result<(), Self::Error> {
+        sqlx::query!(
+            r#"
+                SELECT pg_advisory_xact_lock($1)
+            "#,
+            SIGNING_KEYS_LOCK_KEY,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.signing_key.list_unretired",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list_unretired(&mut self) -> Result<Vec<SigningKey>, Self::Error> {
+        let res = sqlx::query_as!(
+            SigningKeyLookup,
+            r#"
+                SELECT signing_key_id
+                     , kid
+                     , algorithm
+                     , encrypted_key
+                     , created_at
+                     , activates_at
+                     , retired_at
+                FROM signing_keys
+                WHERE retired_at IS NULL
+                ORDER BY activates_at ASC
+            "#,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let keys = res
+            .into_iter()
+            .map(SigningKey::try_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(keys)
+    }
+
+    #[tracing::instrument(
+        name = "db.signing_key.add",
+        skip_all,
+        fields(
+            db.query.text,
+            signing_key.id,
+            signing_key.kid = kid,
+            signing_key.algorithm = %algorithm,
+            signing_key.activates_at = %activates_at,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kid: String,
+        algorithm: JsonWebSignatureAlg,
+        encrypted_key: String,
+        activates_at: DateTime<Utc>,
+    ) -> Result<SigningKey, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("signing_key.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO signing_keys
+                    ( signing_key_id
+                    , kid
+                    , algorithm
+                    , encrypted_key
+                    , created_at
+                    , activates_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            &kid,
+            algorithm.to_string(),
+            &encrypted_key,
+            created_at,
+            activates_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(SigningKey {
+            id,
+            kid,
+            algorithm,
+            encrypted_key,
+            created_at,
+            activates_at,
+            retired_at: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.signing_key.retire",
+        skip_all,
+        fields(
+            db.query.text,
+            %signing_key.id,
+            signing_key.kid = signing_key.kid,
+        ),
+        err,
+    )]
+    async fn retire(
+        &mut self,
+        clock: &dyn Clock,
+        mut signing_key: SigningKey,
+    ) -> Result<SigningKey, Self::Error> {
+        let retired_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE signing_keys
+                SET retired_at = $2
+                WHERE signing_key_id = $1
+            "#,
+            Uuid::from(signing_key.id),
+            retired_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        signing_key.retired_at = Some(retired_at);
+        Ok(signing_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_data_model::SigningKeyState;
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_storage::{clock::MockClock, Clock, RepositoryAccess};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_signing_key_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        repo.signing_key().lock().await.unwrap();
+        assert!(repo
+            .signing_key()
+            .list_unretired()
+            .await
+            .unwrap()
+            .is_empty());
+
+        let first = repo
+            .signing_key()
+            .add(
+                &mut rng,
+                &clock,
+                "first".to_owned(),
+                JsonWebSignatureAlg::Rs256,
+                "encrypted-first".to_owned(),
+                clock.now(),
+            )
+            .await
+            .unwrap();
+
+        let second = repo
+            .signing_key()
+            .add(
+                &mut rng,
+                &clock,
+                "second".to_owned(),
+                JsonWebSignatureAlg::Es256,
+                "encrypted-second".to_owned(),
+                clock.now() + Duration::days(1),
+            )
+            .await
+            .unwrap();
+
+        // Keys are listed in the order they activate
+        let keys = repo.signing_key().list_unretired().await.unwrap();
+        assert_eq!(keys, vec![first.clone(), second.clone()]);
+        assert_eq!(first.state(&keys, clock.now()), SigningKeyState::Active);
+        assert_eq!(second.state(&keys, clock.now()), SigningKeyState::Pending);
+
+        // Once the second key activates, the first one is superseded
+        clock.advance(Duration::days(2));
+        let keys = repo.signing_key().list_unretired().await.unwrap();
+        assert_eq!(
+            first.state(&keys, clock.now()),
+            SigningKeyState::Superseded {
+                superseded_at: second.activates_at
+            }
+        );
+
+        let first = repo.signing_key().retire(&clock, first).await.unwrap();
+        assert_eq!(first.retired_at, Some(clock.now()));
+
+        let keys = repo.signing_key().list_unretired().await.unwrap();
+        assert_eq!(keys, vec![second]);
+
+        repo.save().await.unwrap();
+    }
+}
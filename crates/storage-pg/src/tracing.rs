@@ -4,9 +4,30 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::{cell::Cell, future::Future};
+
 use opentelemetry_semantic_conventions::attribute::DB_QUERY_TEXT;
 use tracing::Span;
 
+tokio::task_local! {
+    /// Number of queries done within the current [`count_queries`] scope
+    static QUERY_COUNT: Cell<u64>;
+}
+
+/// Run the given future, counting the number of database queries it does
+///
+/// Queries are counted when they are recorded through the [`ExecuteExt`]
+/// trait. Queries done in tasks spawned by the future are not counted.
+pub async fn count_queries<F: Future>(future: F) -> (F::Output, u64) {
+    QUERY_COUNT
+        .scope(Cell::new(0), async move {
+            let output = future.await;
+            let count = QUERY_COUNT.with(Cell::get);
+            (output, count)
+        })
+        .await
+}
+
 /// An extension trait for [`sqlx::Execute`] that records the SQL statement as
 /// `db.query.text` in a tracing span
 pub trait ExecuteExt<'q, DB>: Sized {
@@ -28,6 +49,8 @@ where
 {
     fn record(self, span: &Span) -> Self {
         span.record(DB_QUERY_TEXT, self.sql());
+        // This is outside of a `count_queries` scope when not handling a request
+        let _ = QUERY_COUNT.try_with(|count| count.set(count.get() + 1));
         self
     }
 }
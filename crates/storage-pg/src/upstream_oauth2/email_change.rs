@@ -0,0 +1,422 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    UpstreamOAuthEmailChange, UpstreamOAuthEmailChangeResolution, UpstreamOAuthLink, User,
+};
+use mas_storage::{
+    pagination::Count,
+    upstream_oauth2::{UpstreamOAuthEmailChangeFilter, UpstreamOAuthEmailChangeRepository},
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    errors::DatabaseInconsistencyError,
+    filter::{Filter, StatementExt},
+    iden::UpstreamOAuthEmailChanges,
+    pagination::{estimate_count, QueryBuilderExt},
+    tracing::ExecuteExt,
+    DatabaseError,
+};
+
+/// An implementation of [`UpstreamOAuthEmailChangeRepository`] for a
+/// PostgreSQL connection
+pub struct PgUpstreamOAuthEmailChangeRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUpstreamOAuthEmailChangeRepository<'c> {
+    /// Create a new [`PgUpstreamOAuthEmailChangeRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+#[enum_def]
+struct EmailChangeLookup {
+    upstream_oauth_email_change_id: Uuid,
+    upstream_oauth_link_id: Uuid,
+    user_id: Uuid,
+    conflicting_user_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+    previous_email: Option<String>,
+    new_email: String,
+    resolved_at: Option<DateTime<Utc>>,
+    resolution: Option<String>,
+}
+
+impl TryFrom<EmailChangeLookup> for UpstreamOAuthEmailChange {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: EmailChangeLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.upstream_oauth_email_change_id);
+        let resolution = value
+            .resolution
+            .map(|resolution| resolution.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_email_changes")
+                    .column("resolution")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        Ok(UpstreamOAuthEmailChange {
+            id,
+            created_at: value.created_at,
+            link_id: Ulid::from(value.upstream_oauth_link_id),
+            user_id: Ulid::from(value.user_id),
+            conflicting_user_id: value.conflicting_user_id.map(Ulid::from),
+            previous_email: value.previous_email,
+            new_email: value.new_email,
+            resolved_at: value.resolved_at,
+            resolution,
+        })
+    }
+}
+
+impl Filter for UpstreamOAuthEmailChangeFilter<'_> {
+    fn generate_condition(&self, _has_joins: bool) -> impl sea_query::IntoCondition {
+        sea_query::Condition::all()
+            .add_option(self.user().map(|user| {
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::UserId,
+                ))
+                .eq(Uuid::from(user.id))
+            }))
+            .add_option(self.state().map(|state| {
+                if state.is_pending() {
+                    Expr::col((
+                        UpstreamOAuthEmailChanges::Table,
+                        UpstreamOAuthEmailChanges::ResolvedAt,
+                    ))
+                    .is_null()
+                } else {
+                    Expr::col((
+                        UpstreamOAuthEmailChanges::Table,
+                        UpstreamOAuthEmailChanges::ResolvedAt,
+                    ))
+                    .is_not_null()
+                }
+            }))
+    }
+}
+
+#[async_trait]
+impl<'c> UpstreamOAuthEmailChangeRepository for PgUpstreamOAuthEmailChangeRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_email_change.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            upstream_oauth_email_change.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthEmailChange>, Self::Error> {
+        let res = sqlx::query_as!(
+            EmailChangeLookup,
+            r#"
+                SELECT upstream_oauth_email_change_id
+                     , upstream_oauth_link_id
+                     , user_id
+                     , conflicting_user_id
+                     , created_at
+                     , previous_email
+                     , new_email
+                     , resolved_at
+                     , resolution
+                FROM upstream_oauth_email_changes
+                WHERE upstream_oauth_email_change_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_email_change.add",
+        skip_all,
+        fields(
+            db.query.text,
+            upstream_oauth_email_change.id,
+            %upstream_oauth_link.id,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        user: &User,
+        conflicting_user: Option<&User>,
+        previous_email: Option<String>,
+        new_email: String,
+    ) -> Result<UpstreamOAuthEmailChange, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "upstream_oauth_email_change.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO upstream_oauth_email_changes
+                    ( upstream_oauth_email_change_id
+                    , upstream_oauth_link_id
+                    , user_id
+                    , conflicting_user_id
+                    , created_at
+                    , previous_email
+                    , new_email
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::from(id),
+            Uuid::from(upstream_oauth_link.id),
+            Uuid::from(user.id),
+            conflicting_user.map(|user| Uuid::from(user.id)),
+            created_at,
+            previous_email.as_deref(),
+            &new_email,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(UpstreamOAuthEmailChange {
+            id,
+            created_at,
+            link_id: upstream_oauth_link.id,
+            user_id: user.id,
+            conflicting_user_id: conflicting_user.map(|user| user.id),
+            previous_email,
+            new_email,
+            resolved_at: None,
+            resolution: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_email_change.resolve",
+        skip_all,
+        fields(
+            db.query.text,
+            %change.id,
+            upstream_oauth_email_change.resolution = %resolution,
+        ),
+        err,
+    )]
+    async fn resolve(
+        &mut self,
+        clock: &dyn Clock,
+        mut change: UpstreamOAuthEmailChange,
+        resolution: UpstreamOAuthEmailChangeResolution,
+    ) -> Result<UpstreamOAuthEmailChange, Self::Error> {
+        let resolved_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_email_changes
+                SET resolved_at = $2
+                  , resolution = $3
+                WHERE upstream_oauth_email_change_id = $1
+                  AND resolved_at IS NULL
+            "#,
+            Uuid::from(change.id),
+            resolved_at,
+            resolution.as_str(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        change.resolved_at = Some(resolved_at);
+        change.resolution = Some(resolution);
+        Ok(change)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_email_change.list",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<UpstreamOAuthEmailChange>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::UpstreamOAuthEmailChangeId,
+                )),
+                EmailChangeLookupIden::UpstreamOauthEmailChangeId,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::UpstreamOAuthLinkId,
+                )),
+                EmailChangeLookupIden::UpstreamOauthLinkId,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::UserId,
+                )),
+                EmailChangeLookupIden::UserId,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::ConflictingUserId,
+                )),
+                EmailChangeLookupIden::ConflictingUserId,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::CreatedAt,
+                )),
+                EmailChangeLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::PreviousEmail,
+                )),
+                EmailChangeLookupIden::PreviousEmail,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::NewEmail,
+                )),
+                EmailChangeLookupIden::NewEmail,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::ResolvedAt,
+                )),
+                EmailChangeLookupIden::ResolvedAt,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::Resolution,
+                )),
+                EmailChangeLookupIden::Resolution,
+            )
+            .from(UpstreamOAuthEmailChanges::Table)
+            .apply_filter(filter)
+            .generate_pagination(
+                (
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::UpstreamOAuthEmailChangeId,
+                ),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<EmailChangeLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination
+            .process(edges)
+            .try_map(UpstreamOAuthEmailChange::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_email_change.count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn count(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+    ) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(
+                Expr::col((
+                    UpstreamOAuthEmailChanges::Table,
+                    UpstreamOAuthEmailChanges::UpstreamOAuthEmailChangeId,
+                ))
+                .count(),
+            )
+            .from(UpstreamOAuthEmailChanges::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_email_change.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+    ) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((
+                UpstreamOAuthEmailChanges::Table,
+                UpstreamOAuthEmailChanges::UpstreamOAuthEmailChangeId,
+            )))
+            .from(UpstreamOAuthEmailChanges::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    UpstreamOAuthFunnelStage, UpstreamOAuthFunnelStageReport, UpstreamOAuthProvider, User,
+};
+use mas_storage::{upstream_oauth2::UpstreamOAuthFunnelRepository, Clock};
+use rand::RngCore;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
+
+/// An implementation of [`UpstreamOAuthFunnelRepository`] for a PostgreSQL
+/// connection
+pub struct PgUpstreamOAuthFunnelRepository<'c> {
+    conn: &'c mut sqlx::PgConnection,
+}
+
+impl<'c> PgUpstreamOAuthFunnelRepository<'c> {
+    /// Create a new [`PgUpstreamOAuthFunnelRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut sqlx::PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct StageCountLookup {
+    stage: String,
+    dropoff_reason: Option<String>,
+    count: i64,
+}
+
+#[async_trait]
+impl<'c> UpstreamOAuthFunnelRepository for PgUpstreamOAuthFunnelRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_funnel.record",
+        skip_all,
+        fields(
+            db.query.text,
+            upstream_oauth_provider.id = %provider_id,
+            upstream_oauth_funnel_event.id,
+            upstream_oauth_funnel_event.stage = %stage,
+        ),
+        err,
+    )]
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        provider_id: Ulid,
+        user: Option<&User>,
+        stage: UpstreamOAuthFunnelStage,
+        dropoff_reason: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "upstream_oauth_funnel_event.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO upstream_oauth_funnel_events
+                    ( upstream_oauth_funnel_event_id
+                    , upstream_oauth_provider_id
+                    , user_id
+                    , created_at
+                    , stage
+                    , dropoff_reason
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            Uuid::from(provider_id),
+            user.map(|user| Uuid::from(user.id)),
+            created_at,
+            stage.as_str(),
+            dropoff_reason,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_funnel.signup_provider_pending",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            upstream_oauth_funnel_event.stage = %stage,
+        ),
+        err,
+    )]
+    async fn signup_provider_pending(
+        &mut self,
+        user: &User,
+        stage: UpstreamOAuthFunnelStage,
+    ) -> Result<Option<Ulid>, Self::Error> {
+        let provider_id = sqlx::query_scalar!(
+            r#"
+                SELECT upstream_oauth_provider_id
+                FROM upstream_oauth_funnel_events
+                WHERE user_id = $1
+                  AND stage = $2
+                  AND dropoff_reason IS NULL
+                  AND NOT EXISTS (
+                      SELECT 1
+                      FROM upstream_oauth_funnel_events
+                      WHERE user_id = $1
+                        AND stage = $3
+                        AND dropoff_reason IS NULL
+                  )
+                LIMIT 1
+            "#,
+            Uuid::from(user.id),
+            UpstreamOAuthFunnelStage::UserCreated.as_str(),
+            stage.as_str(),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(provider_id.map(Ulid::from))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_funnel.report",
+        skip_all,
+        fields(
+            db.query.text,
+            %provider.id,
+        ),
+        err,
+    )]
+    async fn report(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UpstreamOAuthFunnelStageReport>, Self::Error> {
+        let rows = sqlx::query_as!(
+            StageCountLookup,
+            r#"
+                SELECT stage
+                     , dropoff_reason
+                     , COUNT(*) AS "count!"
+                FROM upstream_oauth_funnel_events
+                WHERE upstream_oauth_provider_id = $1
+                  AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+                  AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+                GROUP BY stage, dropoff_reason
+            "#,
+            Uuid::from(provider.id),
+            since,
+            until,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let mut report: Vec<_> = UpstreamOAuthFunnelStage::ALL
+            .into_iter()
+            .map(UpstreamOAuthFunnelStageReport::new)
+            .collect();
+
+        for row in rows {
+            let stage: UpstreamOAuthFunnelStage = row.stage.parse().map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_funnel_events")
+                    .column("stage")
+                    .source(e)
+            })?;
+            let count = row
+                .count
+                .try_into()
+                .map_err(DatabaseError::to_invalid_operation)?;
+
+            // The report has one entry per stage, in the same order as the stages
+            let Some(entry) = report.iter_mut().find(|entry| entry.stage == stage) else {
+                continue;
+            };
+
+            match row.dropoff_reason {
+                Some(reason) => {
+                    *entry.dropoffs.entry(reason).or_default() += count;
+                }
+                None => entry.reached += count,
+            }
+        }
+
+        Ok(report)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_funnel.cleanup_before",
+        skip_all,
+        fields(
+            db.query.text,
+            %before,
+        ),
+        err,
+    )]
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM upstream_oauth_funnel_events
+                WHERE created_at < $1
+            "#,
+            before,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        res.rows_affected()
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+}
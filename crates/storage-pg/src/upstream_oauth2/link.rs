@@ -47,6 +47,7 @@ struct LinkLookup {
     upstream_oauth_provider_id: Uuid,
     user_id: Option<Uuid>,
     subject: String,
+    asserted_email: Option<String>,
     created_at: DateTime<Utc>,
 }
 
@@ -57,6 +58,7 @@ impl From<LinkLookup> for UpstreamOAuthLink {
             provider_id: Ulid::from(value.upstream_oauth_provider_id),
             user_id: value.user_id.map(Ulid::from),
             subject: value.subject,
+            asserted_email: value.asserted_email,
             created_at: value.created_at,
         }
     }
@@ -124,6 +126,7 @@ impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
                     upstream_oauth_provider_id,
                     user_id,
                     subject,
+                    asserted_email,
                     created_at
                 FROM upstream_oauth_links
                 WHERE upstream_oauth_link_id = $1
@@ -163,6 +166,7 @@ impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
                     upstream_oauth_provider_id,
                     user_id,
                     subject,
+                    asserted_email,
                     created_at
                 FROM upstream_oauth_links
                 WHERE upstream_oauth_provider_id = $1
@@ -227,6 +231,7 @@ impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
             provider_id: upstream_oauth_provider.id,
             user_id: None,
             subject,
+            asserted_email: None,
             created_at,
         })
     }
@@ -264,6 +269,38 @@ impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
         Ok(())
     }
 
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.set_asserted_email",
+        skip_all,
+        fields(
+            db.query.text,
+            %upstream_oauth_link.id,
+            %upstream_oauth_link.subject,
+        ),
+        err,
+    )]
+    async fn set_asserted_email(
+        &mut self,
+        mut upstream_oauth_link: UpstreamOAuthLink,
+        asserted_email: Option<String>,
+    ) -> Result<UpstreamOAuthLink, Self::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_links
+                SET asserted_email = $1
+                WHERE upstream_oauth_link_id = $2
+            "#,
+            asserted_email.as_deref(),
+            Uuid::from(upstream_oauth_link.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        upstream_oauth_link.asserted_email = asserted_email;
+        Ok(upstream_oauth_link)
+    }
+
     #[tracing::instrument(
         name = "db.upstream_oauth_link.list",
         skip_all,
@@ -300,6 +337,10 @@ impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
                 Expr::col((UpstreamOAuthLinks::Table, UpstreamOAuthLinks::Subject)),
                 LinkLookupIden::Subject,
             )
+            .expr_as(
+                Expr::col((UpstreamOAuthLinks::Table, UpstreamOAuthLinks::AssertedEmail)),
+                LinkLookupIden::AssertedEmail,
+            )
             .expr_as(
                 Expr::col((UpstreamOAuthLinks::Table, UpstreamOAuthLinks::CreatedAt)),
                 LinkLookupIden::CreatedAt,
@@ -7,11 +7,14 @@
 //! A module containing the PostgreSQL implementation of the repositories
 //! related to the upstream OAuth 2.0 providers
 
+mod email_change;
+mod funnel;
 mod link;
 mod provider;
 mod session;
 
 pub use self::{
+    email_change::PgUpstreamOAuthEmailChangeRepository, funnel::PgUpstreamOAuthFunnelRepository,
     link::PgUpstreamOAuthLinkRepository, provider::PgUpstreamOAuthProviderRepository,
     session::PgUpstreamOAuthSessionRepository,
 };
@@ -19,16 +22,20 @@ pub use self::{
 #[cfg(test)]
 mod tests {
     use chrono::Duration;
-    use mas_data_model::UpstreamOAuthProviderClaimsImports;
+    use mas_data_model::{
+        UpstreamOAuthEmailChangeResolution, UpstreamOAuthFunnelStage,
+        UpstreamOAuthProviderClaimsImports,
+    };
     use mas_storage::{
         clock::MockClock,
         upstream_oauth2::{
-            UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository, UpstreamOAuthProviderFilter,
-            UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository,
-            UpstreamOAuthSessionRepository,
+            UpstreamOAuthEmailChangeFilter, UpstreamOAuthEmailChangeRepository,
+            UpstreamOAuthFunnelRepository, UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository,
+            UpstreamOAuthProviderFilter, UpstreamOAuthProviderParams,
+            UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository,
         },
         user::UserRepository,
-        Pagination, RepositoryAccess,
+        Clock, Pagination, RepositoryAccess,
     };
     use oauth2_types::scope::{Scope, OPENID};
     use rand::SeedableRng;
@@ -58,7 +65,7 @@ mod tests {
                     brand_name: None,
                     scope: Scope::from_iter([OPENID]),
                     token_endpoint_auth_method:
-                        mas_iana::oauth::OAuthClientAuthenticationMethod::None,
+                        mas_data_model::UpstreamOAuthProviderTokenAuthMethod::None,
                     token_endpoint_signing_alg: None,
                     client_id: "client-id".to_owned(),
                     encrypted_client_secret: None,
@@ -68,7 +75,17 @@ mod tests {
                     jwks_uri_override: None,
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
                     additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
                 },
             )
             .await
@@ -143,7 +160,7 @@ mod tests {
 
         let session = repo
             .upstream_oauth_session()
-            .complete_with_link(&clock, session, &link, None)
+            .complete_with_link(&clock, session, &link, None, None, None)
             .await
             .unwrap();
         // Reload the session
@@ -300,7 +317,7 @@ mod tests {
                         brand_name: None,
                         scope: scope.clone(),
                         token_endpoint_auth_method:
-                            mas_iana::oauth::OAuthClientAuthenticationMethod::None,
+                            mas_data_model::UpstreamOAuthProviderTokenAuthMethod::None,
                         token_endpoint_signing_alg: None,
                         client_id,
                         encrypted_client_secret: None,
@@ -310,7 +327,17 @@ mod tests {
                         jwks_uri_override: None,
                         discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                         pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                        par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                        pushed_authorization_request_endpoint_override: None,
+                        request_object_signing_alg: None,
+                        response_mode: None,
                         additional_authorization_parameters: Vec::new(),
+                        allow_idp_initiated_login: false,
+                        idp_initiated_login_client_id: None,
+                        propagate_logout: false,
+                        end_session_endpoint_override: None,
+                        fetch_userinfo: false,
+                        userinfo_endpoint_override: None,
                     },
                 )
                 .await
@@ -407,4 +434,386 @@ mod tests {
             .edges
             .is_empty());
     }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_funnel_repository(pool: PgPool) {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: None,
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method:
+                        mas_data_model::UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client-id".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    token_endpoint_override: None,
+                    authorization_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // Two users are redirected, one of them declines at the provider
+        for _ in 0..2 {
+            repo.upstream_oauth_funnel()
+                .record(
+                    &mut rng,
+                    &clock,
+                    provider.id,
+                    None,
+                    UpstreamOAuthFunnelStage::Redirected,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &clock,
+                provider.id,
+                None,
+                UpstreamOAuthFunnelStage::CallbackReceived,
+                Some("access_denied"),
+            )
+            .await
+            .unwrap();
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &clock,
+                provider.id,
+                None,
+                UpstreamOAuthFunnelStage::CallbackReceived,
+                None,
+            )
+            .await
+            .unwrap();
+
+        // The other one signs up
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "john".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.upstream_oauth_funnel()
+                .signup_provider_pending(&user, UpstreamOAuthFunnelStage::Provisioned)
+                .await
+                .unwrap(),
+            None
+        );
+
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &clock,
+                provider.id,
+                Some(&user),
+                UpstreamOAuthFunnelStage::UserCreated,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.upstream_oauth_funnel()
+                .signup_provider_pending(&user, UpstreamOAuthFunnelStage::Provisioned)
+                .await
+                .unwrap(),
+            Some(provider.id)
+        );
+
+        // A failed attempt doesn't count as reaching the stage
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &clock,
+                provider.id,
+                Some(&user),
+                UpstreamOAuthFunnelStage::Provisioned,
+                Some("homeserver_error"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.upstream_oauth_funnel()
+                .signup_provider_pending(&user, UpstreamOAuthFunnelStage::Provisioned)
+                .await
+                .unwrap(),
+            Some(provider.id)
+        );
+
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut rng,
+                &clock,
+                provider.id,
+                Some(&user),
+                UpstreamOAuthFunnelStage::Provisioned,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.upstream_oauth_funnel()
+                .signup_provider_pending(&user, UpstreamOAuthFunnelStage::Provisioned)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let report = repo
+            .upstream_oauth_funnel()
+            .report(&provider, None, None)
+            .await
+            .unwrap();
+        assert_eq!(report.len(), UpstreamOAuthFunnelStage::ALL.len());
+        assert_eq!(report[0].stage, UpstreamOAuthFunnelStage::Redirected);
+        assert_eq!(report[0].reached, 2);
+        assert_eq!(report[1].stage, UpstreamOAuthFunnelStage::CallbackReceived);
+        assert_eq!(report[1].reached, 1);
+        assert_eq!(report[1].dropoffs.get("access_denied"), Some(&1));
+        assert_eq!(report[1].dropped(), 1);
+        assert_eq!(report[2].reached, 0);
+        assert_eq!(report[3].reached, 1);
+        assert_eq!(report[4].reached, 1);
+        assert_eq!(report[4].dropoffs.get("homeserver_error"), Some(&1));
+        assert_eq!(report[5].reached, 0);
+
+        // Nothing was recorded in the future
+        let report = repo
+            .upstream_oauth_funnel()
+            .report(&provider, Some(clock.now() + Duration::seconds(1)), None)
+            .await
+            .unwrap();
+        assert!(report.iter().all(|entry| entry.reached == 0));
+
+        // Cleaning up removes the old events
+        clock.advance(Duration::days(1));
+        let count = repo
+            .upstream_oauth_funnel()
+            .cleanup_before(clock.now())
+            .await
+            .unwrap();
+        assert_eq!(count, 7);
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_email_change_repository(pool: PgPool) {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &clock,
+                UpstreamOAuthProviderParams {
+                    issuer: "https://example.com/".to_owned(),
+                    human_name: None,
+                    brand_name: None,
+                    scope: Scope::from_iter([OPENID]),
+                    token_endpoint_auth_method:
+                        mas_data_model::UpstreamOAuthProviderTokenAuthMethod::None,
+                    token_endpoint_signing_alg: None,
+                    client_id: "client-id".to_owned(),
+                    encrypted_client_secret: None,
+                    claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+                    token_endpoint_override: None,
+                    authorization_endpoint_override: None,
+                    jwks_uri_override: None,
+                    discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+                    pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+                    par_mode: mas_data_model::UpstreamOAuthProviderParMode::Auto,
+                    pushed_authorization_request_endpoint_override: None,
+                    request_object_signing_alg: None,
+                    response_mode: None,
+                    additional_authorization_parameters: Vec::new(),
+                    allow_idp_initiated_login: false,
+                    idp_initiated_login_client_id: None,
+                    propagate_logout: false,
+                    end_session_endpoint_override: None,
+                    fetch_userinfo: false,
+                    userinfo_endpoint_override: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let john = repo
+            .user()
+            .add(&mut rng, &clock, "john".to_owned())
+            .await
+            .unwrap();
+        let alice = repo
+            .user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &clock, &provider, "a-subject".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &john)
+            .await
+            .unwrap();
+        assert_eq!(link.asserted_email, None);
+
+        // Remember the email address asserted by the provider
+        let link = repo
+            .upstream_oauth_link()
+            .set_asserted_email(link, Some("john@example.com".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(link.asserted_email.as_deref(), Some("john@example.com"));
+
+        let link = repo
+            .upstream_oauth_link()
+            .lookup(link.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(link.asserted_email.as_deref(), Some("john@example.com"));
+
+        // Record two changes, one of them conflicting with another account
+        let change = repo
+            .upstream_oauth_email_change()
+            .add(
+                &mut rng,
+                &clock,
+                &link,
+                &john,
+                None,
+                Some("john@example.com".to_owned()),
+                "john@example.org".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert!(change.is_pending());
+        assert_eq!(change.conflicting_user_id, None);
+
+        let conflicting = repo
+            .upstream_oauth_email_change()
+            .add(
+                &mut rng,
+                &clock,
+                &link,
+                &john,
+                Some(&alice),
+                Some("john@example.org".to_owned()),
+                "alice@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(conflicting.conflicting_user_id, Some(alice.id));
+
+        let all = UpstreamOAuthEmailChangeFilter::new();
+        let pending = all.pending_only();
+        let resolved = all.resolved_only();
+
+        assert_eq!(
+            repo.upstream_oauth_email_change().count(all).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            repo.upstream_oauth_email_change()
+                .count(pending)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            repo.upstream_oauth_email_change()
+                .count(resolved)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            repo.upstream_oauth_email_change()
+                .count(all.for_user(&alice))
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Resolve the first change
+        let change = repo
+            .upstream_oauth_email_change()
+            .resolve(&clock, change, UpstreamOAuthEmailChangeResolution::Applied)
+            .await
+            .unwrap();
+        assert!(!change.is_pending());
+        assert_eq!(
+            change.resolution,
+            Some(UpstreamOAuthEmailChangeResolution::Applied)
+        );
+
+        // It can't be resolved twice
+        assert!(repo
+            .upstream_oauth_email_change()
+            .resolve(
+                &clock,
+                change.clone(),
+                UpstreamOAuthEmailChangeResolution::Dismissed
+            )
+            .await
+            .is_err());
+
+        let page = repo
+            .upstream_oauth_email_change()
+            .list(pending, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        assert_eq!(page.edges[0].id, conflicting.id);
+
+        let page = repo
+            .upstream_oauth_email_change()
+            .list(resolved, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        assert_eq!(page.edges[0], change);
+
+        let change = repo
+            .upstream_oauth_email_change()
+            .lookup(change.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            change.resolution,
+            Some(UpstreamOAuthEmailChangeResolution::Applied)
+        );
+    }
 }
@@ -65,6 +65,16 @@ struct ProviderLookup {
     discovery_mode: String,
     pkce_mode: String,
     additional_parameters: Option<Json<Vec<(String, String)>>>,
+    par_mode: String,
+    pushed_authorization_request_endpoint_override: Option<String>,
+    request_object_signing_alg: Option<String>,
+    response_mode: Option<String>,
+    allow_idp_initiated_login: bool,
+    idp_initiated_login_client_id: Option<Uuid>,
+    propagate_logout: bool,
+    end_session_endpoint_override: Option<String>,
+    fetch_userinfo: bool,
+    userinfo_endpoint_override: Option<String>,
 }
 
 impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
@@ -146,6 +156,68 @@ impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
             .map(|Json(x)| x)
             .unwrap_or_default();
 
+        let par_mode = value.par_mode.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("upstream_oauth_providers")
+                .column("par_mode")
+                .row(id)
+                .source(e)
+        })?;
+
+        let pushed_authorization_request_endpoint_override = value
+            .pushed_authorization_request_endpoint_override
+            .map(|x| x.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("pushed_authorization_request_endpoint_override")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        let request_object_signing_alg = value
+            .request_object_signing_alg
+            .map(|x| x.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("request_object_signing_alg")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        let response_mode = value
+            .response_mode
+            .map(|x| x.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("response_mode")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        let end_session_endpoint_override = value
+            .end_session_endpoint_override
+            .map(|x| x.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("end_session_endpoint_override")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        let userinfo_endpoint_override = value
+            .userinfo_endpoint_override
+            .map(|x| x.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("userinfo_endpoint_override")
+                    .row(id)
+                    .source(e)
+            })?;
+
         Ok(UpstreamOAuthProvider {
             id,
             issuer: value.issuer,
@@ -156,6 +228,7 @@ impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
             encrypted_client_secret: value.encrypted_client_secret,
             token_endpoint_auth_method,
             token_endpoint_signing_alg,
+            response_mode,
             created_at: value.created_at,
             disabled_at: value.disabled_at,
             claims_imports: value.claims_imports.0,
@@ -164,7 +237,16 @@ impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
             jwks_uri_override,
             discovery_mode,
             pkce_mode,
+            par_mode,
+            pushed_authorization_request_endpoint_override,
+            request_object_signing_alg,
             additional_authorization_parameters,
+            allow_idp_initiated_login: value.allow_idp_initiated_login,
+            idp_initiated_login_client_id: value.idp_initiated_login_client_id.map(Ulid::from),
+            propagate_logout: value.propagate_logout,
+            end_session_endpoint_override,
+            fetch_userinfo: value.fetch_userinfo,
+            userinfo_endpoint_override,
         })
     }
 }
@@ -217,7 +299,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     token_endpoint_override,
                     discovery_mode,
                     pkce_mode,
-                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>"
+                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>",
+                    par_mode,
+                    pushed_authorization_request_endpoint_override,
+                    request_object_signing_alg,
+                    response_mode,
+                    allow_idp_initiated_login,
+                    idp_initiated_login_client_id,
+                    propagate_logout,
+                    end_session_endpoint_override,
+                    fetch_userinfo,
+                    userinfo_endpoint_override
                 FROM upstream_oauth_providers
                 WHERE upstream_oauth_provider_id = $1
             "#,
@@ -274,9 +366,20 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                 jwks_uri_override,
                 discovery_mode,
                 pkce_mode,
+                par_mode,
+                pushed_authorization_request_endpoint_override,
+                request_object_signing_alg,
+                response_mode,
+                allow_idp_initiated_login,
+                idp_initiated_login_client_id,
+                propagate_logout,
+                end_session_endpoint_override,
+                fetch_userinfo,
+                userinfo_endpoint_override,
                 created_at
             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9,
-                      $10, $11, $12, $13, $14, $15, $16)
+                      $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22,
+                      $23, $24, $25, $26)
         "#,
             Uuid::from(id),
             &params.issuer,
@@ -302,6 +405,28 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             params.jwks_uri_override.as_ref().map(ToString::to_string),
             params.discovery_mode.as_str(),
             params.pkce_mode.as_str(),
+            params.par_mode.as_str(),
+            params
+                .pushed_authorization_request_endpoint_override
+                .as_ref()
+                .map(ToString::to_string),
+            params
+                .request_object_signing_alg
+                .as_ref()
+                .map(ToString::to_string),
+            params.response_mode.map(|x| x.as_str()),
+            params.allow_idp_initiated_login,
+            params.idp_initiated_login_client_id.map(Uuid::from),
+            params.propagate_logout,
+            params
+                .end_session_endpoint_override
+                .as_ref()
+                .map(ToString::to_string),
+            params.fetch_userinfo,
+            params
+                .userinfo_endpoint_override
+                .as_ref()
+                .map(ToString::to_string),
             created_at,
         )
         .traced()
@@ -318,6 +443,7 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             encrypted_client_secret: params.encrypted_client_secret,
             token_endpoint_signing_alg: params.token_endpoint_signing_alg,
             token_endpoint_auth_method: params.token_endpoint_auth_method,
+            response_mode: params.response_mode,
             created_at,
             disabled_at: None,
             claims_imports: params.claims_imports,
@@ -326,7 +452,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             jwks_uri_override: params.jwks_uri_override,
             discovery_mode: params.discovery_mode,
             pkce_mode: params.pkce_mode,
+            par_mode: params.par_mode,
+            pushed_authorization_request_endpoint_override: params
+                .pushed_authorization_request_endpoint_override,
+            request_object_signing_alg: params.request_object_signing_alg,
             additional_authorization_parameters: params.additional_authorization_parameters,
+            allow_idp_initiated_login: params.allow_idp_initiated_login,
+            idp_initiated_login_client_id: params.idp_initiated_login_client_id,
+            propagate_logout: params.propagate_logout,
+            end_session_endpoint_override: params.end_session_endpoint_override,
+            fetch_userinfo: params.fetch_userinfo,
+            userinfo_endpoint_override: params.userinfo_endpoint_override,
         })
     }
 
@@ -434,9 +570,20 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     discovery_mode,
                     pkce_mode,
                     additional_parameters,
+                    par_mode,
+                    pushed_authorization_request_endpoint_override,
+                    request_object_signing_alg,
+                    response_mode,
+                    allow_idp_initiated_login,
+                    idp_initiated_login_client_id,
+                    propagate_logout,
+                    end_session_endpoint_override,
+                    fetch_userinfo,
+                    userinfo_endpoint_override,
                     created_at
                 ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9,
-                          $10, $11, $12, $13, $14, $15, $16, $17)
+                          $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22,
+                          $23, $24, $25, $26, $27)
                 ON CONFLICT (upstream_oauth_provider_id)
                     DO UPDATE
                     SET
@@ -455,7 +602,18 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                         jwks_uri_override = EXCLUDED.jwks_uri_override,
                         discovery_mode = EXCLUDED.discovery_mode,
                         pkce_mode = EXCLUDED.pkce_mode,
-                        additional_parameters = EXCLUDED.additional_parameters
+                        additional_parameters = EXCLUDED.additional_parameters,
+                        par_mode = EXCLUDED.par_mode,
+                        pushed_authorization_request_endpoint_override =
+                            EXCLUDED.pushed_authorization_request_endpoint_override,
+                        request_object_signing_alg = EXCLUDED.request_object_signing_alg,
+                        response_mode = EXCLUDED.response_mode,
+                        allow_idp_initiated_login = EXCLUDED.allow_idp_initiated_login,
+                        idp_initiated_login_client_id = EXCLUDED.idp_initiated_login_client_id,
+                        propagate_logout = EXCLUDED.propagate_logout,
+                        end_session_endpoint_override = EXCLUDED.end_session_endpoint_override,
+                        fetch_userinfo = EXCLUDED.fetch_userinfo,
+                        userinfo_endpoint_override = EXCLUDED.userinfo_endpoint_override
                 RETURNING created_at
             "#,
             Uuid::from(id),
@@ -483,6 +641,28 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             params.discovery_mode.as_str(),
             params.pkce_mode.as_str(),
             Json(&params.additional_authorization_parameters) as _,
+            params.par_mode.as_str(),
+            params
+                .pushed_authorization_request_endpoint_override
+                .as_ref()
+                .map(ToString::to_string),
+            params
+                .request_object_signing_alg
+                .as_ref()
+                .map(ToString::to_string),
+            params.response_mode.map(|x| x.as_str()),
+            params.allow_idp_initiated_login,
+            params.idp_initiated_login_client_id.map(Uuid::from),
+            params.propagate_logout,
+            params
+                .end_session_endpoint_override
+                .as_ref()
+                .map(ToString::to_string),
+            params.fetch_userinfo,
+            params
+                .userinfo_endpoint_override
+                .as_ref()
+                .map(ToString::to_string),
             created_at,
         )
         .traced()
@@ -499,6 +679,7 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             encrypted_client_secret: params.encrypted_client_secret,
             token_endpoint_signing_alg: params.token_endpoint_signing_alg,
             token_endpoint_auth_method: params.token_endpoint_auth_method,
+            response_mode: params.response_mode,
             created_at,
             disabled_at: None,
             claims_imports: params.claims_imports,
@@ -507,7 +688,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             jwks_uri_override: params.jwks_uri_override,
             discovery_mode: params.discovery_mode,
             pkce_mode: params.pkce_mode,
+            par_mode: params.par_mode,
+            pushed_authorization_request_endpoint_override: params
+                .pushed_authorization_request_endpoint_override,
+            request_object_signing_alg: params.request_object_signing_alg,
             additional_authorization_parameters: params.additional_authorization_parameters,
+            allow_idp_initiated_login: params.allow_idp_initiated_login,
+            idp_initiated_login_client_id: params.idp_initiated_login_client_id,
+            propagate_logout: params.propagate_logout,
+            end_session_endpoint_override: params.end_session_endpoint_override,
+            fetch_userinfo: params.fetch_userinfo,
+            userinfo_endpoint_override: params.userinfo_endpoint_override,
         })
     }
 
@@ -683,6 +874,76 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                 )),
                 ProviderLookupIden::AdditionalParameters,
             )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::ParMode,
+                )),
+                ProviderLookupIden::ParMode,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::PushedAuthorizationRequestEndpointOverride,
+                )),
+                ProviderLookupIden::PushedAuthorizationRequestEndpointOverride,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::RequestObjectSigningAlg,
+                )),
+                ProviderLookupIden::RequestObjectSigningAlg,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::ResponseMode,
+                )),
+                ProviderLookupIden::ResponseMode,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::AllowIdpInitiatedLogin,
+                )),
+                ProviderLookupIden::AllowIdpInitiatedLogin,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::IdpInitiatedLoginClientId,
+                )),
+                ProviderLookupIden::IdpInitiatedLoginClientId,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::PropagateLogout,
+                )),
+                ProviderLookupIden::PropagateLogout,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::EndSessionEndpointOverride,
+                )),
+                ProviderLookupIden::EndSessionEndpointOverride,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::FetchUserinfo,
+                )),
+                ProviderLookupIden::FetchUserinfo,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::UserinfoEndpointOverride,
+                )),
+                ProviderLookupIden::UserinfoEndpointOverride,
+            )
             .from(UpstreamOAuthProviders::Table)
             .apply_filter(filter)
             .generate_pagination(
@@ -770,7 +1031,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     token_endpoint_override,
                     discovery_mode,
                     pkce_mode,
-                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>"
+                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>",
+                    par_mode,
+                    pushed_authorization_request_endpoint_override,
+                    request_object_signing_alg,
+                    response_mode,
+                    allow_idp_initiated_login,
+                    idp_initiated_login_client_id,
+                    propagate_logout,
+                    end_session_endpoint_override,
+                    fetch_userinfo,
+                    userinfo_endpoint_override
                 FROM upstream_oauth_providers
                 WHERE disabled_at IS NULL
             "#,
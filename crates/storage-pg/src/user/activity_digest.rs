@@ -0,0 +1,252 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{DigestFrequency, User, UserActivityDigest};
+use mas_storage::{user::UserActivityDigestRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`UserActivityDigestRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserActivityDigestRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserActivityDigestRepository<'c> {
+    /// Create a new [`PgUserActivityDigestRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserActivityDigestRow {
+    user_activity_digest_id: Uuid,
+    user_id: Uuid,
+    locale: String,
+    created_at: DateTime<Utc>,
+    last_sent_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserActivityDigestRow> for UserActivityDigest {
+    fn from(row: UserActivityDigestRow) -> Self {
+        Self {
+            id: row.user_activity_digest_id.into(),
+            user_id: row.user_id.into(),
+            locale: row.locale,
+            created_at: row.created_at,
+            last_sent_at: row.last_sent_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> UserActivityDigestRepository for PgUserActivityDigestRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_activity_digest.find_by_user",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn find_by_user(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<UserActivityDigest>, Self::Error> {
+        let row = sqlx::query_as!(
+            UserActivityDigestRow,
+            r#"
+                SELECT user_activity_digest_id
+                     , user_id
+                     , locale
+                     , created_at
+                     , last_sent_at
+                FROM user_activity_digests
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(row.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_activity_digest.subscribe",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user_activity_digest.id,
+            user_activity_digest.locale = locale,
+        ),
+        err,
+    )]
+    async fn subscribe(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        locale: String,
+    ) -> Result<UserActivityDigest, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        // If the user is already subscribed, keep the existing subscription so
+        // that the period of the next digest doesn't change
+        let row = sqlx::query_as!(
+            UserActivityDigestRow,
+            r#"
+                INSERT INTO user_activity_digests
+                    (user_activity_digest_id, user_id, locale, created_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id) DO UPDATE
+                    SET locale = EXCLUDED.locale
+                RETURNING user_activity_digest_id
+                        , user_id
+                        , locale
+                        , created_at
+                        , last_sent_at
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            &locale,
+            created_at,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let digest = UserActivityDigest::from(row);
+        tracing::Span::current().record(
+            "user_activity_digest.id",
+            tracing::field::display(digest.id),
+        );
+
+        Ok(digest)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_activity_digest.unsubscribe",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn unsubscribe(&mut self, user: &User) -> Result<(), Self::Error> {
+        sqlx::query!(
+            r#"
+                DELETE FROM user_activity_digests
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_activity_digest.list_due",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn list_due(
+        &mut self,
+        now: DateTime<Utc>,
+        interval: Duration,
+        limit: usize,
+    ) -> Result<Vec<UserActivityDigest>, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        // The digests are due if their period started before those times,
+        // depending on the frequency the user chose
+        let due_before = |frequency: DigestFrequency| now - frequency.interval(interval);
+        let rows = sqlx::query_as!(
+            UserActivityDigestRow,
+            r#"
+                SELECT d.user_activity_digest_id
+                     , d.user_id
+                     , d.locale
+                     , d.created_at
+                     , d.last_sent_at
+                FROM user_activity_digests d
+                LEFT JOIN user_notification_preferences p
+                  USING (user_id)
+                WHERE COALESCE(d.last_sent_at, d.created_at) <= CASE p.digest_frequency
+                    WHEN 'daily' THEN $2::timestamptz
+                    WHEN 'weekly' THEN $3::timestamptz
+                    WHEN 'monthly' THEN $4::timestamptz
+                    ELSE $1::timestamptz
+                END
+                ORDER BY COALESCE(d.last_sent_at, d.created_at) ASC
+                LIMIT $5
+            "#,
+            due_before(DigestFrequency::Default),
+            due_before(DigestFrequency::Daily),
+            due_before(DigestFrequency::Weekly),
+            due_before(DigestFrequency::Monthly),
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_activity_digest.mark_sent",
+        skip_all,
+        fields(
+            db.query.text,
+            %digest.id,
+        ),
+        err,
+    )]
+    async fn mark_sent(
+        &mut self,
+        clock: &dyn Clock,
+        mut digest: UserActivityDigest,
+    ) -> Result<UserActivityDigest, Self::Error> {
+        let last_sent_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_activity_digests
+                SET last_sent_at = $2
+                WHERE user_activity_digest_id = $1
+            "#,
+            Uuid::from(digest.id),
+            last_sent_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        digest.last_sent_at = Some(last_sent_at);
+
+        Ok(digest)
+    }
+}
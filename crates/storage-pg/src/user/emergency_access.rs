@@ -0,0 +1,245 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{BrowserSession, User, UserEmergencyAccessToken};
+use mas_storage::{user::UserEmergencyAccessRepository, Clock};
+use rand::RngCore;
+use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`UserEmergencyAccessRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserEmergencyAccessRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserEmergencyAccessRepository<'c> {
+    /// Create a new [`PgUserEmergencyAccessRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserEmergencyAccessTokenRow {
+    user_emergency_access_token_id: Uuid,
+    user_id: Uuid,
+    token: String,
+    allowed_network: IpNetwork,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    user_session_id: Option<Uuid>,
+}
+
+impl From<UserEmergencyAccessTokenRow> for UserEmergencyAccessToken {
+    fn from(row: UserEmergencyAccessTokenRow) -> Self {
+        Self {
+            id: row.user_emergency_access_token_id.into(),
+            user_id: row.user_id.into(),
+            token: row.token,
+            allowed_network: row.allowed_network,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            consumed_at: row.consumed_at,
+            user_session_id: row.user_session_id.map(Ulid::from),
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> UserEmergencyAccessRepository for PgUserEmergencyAccessRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_emergency_access.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            user_emergency_access_token.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserEmergencyAccessToken>, Self::Error> {
+        let row = sqlx::query_as!(
+            UserEmergencyAccessTokenRow,
+            r#"
+                SELECT user_emergency_access_token_id
+                     , user_id
+                     , token
+                     , allowed_network
+                     , created_at
+                     , expires_at
+                     , consumed_at
+                     , user_session_id
+                FROM user_emergency_access_tokens
+                WHERE user_emergency_access_token_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(row.into()))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_emergency_access.find_by_token",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn find_by_token(
+        &mut self,
+        token: &str,
+    ) -> Result<Option<UserEmergencyAccessToken>, Self::Error> {
+        let row = sqlx::query_as!(
+            UserEmergencyAccessTokenRow,
+            r#"
+                SELECT user_emergency_access_token_id
+                     , user_id
+                     , token
+                     , allowed_network
+                     , created_at
+                     , expires_at
+                     , consumed_at
+                     , user_session_id
+                FROM user_emergency_access_tokens
+                WHERE token = $1
+            "#,
+            token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(row.into()))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_emergency_access.add",
+        skip_all,
+        fields(
+            db.query.text,
+            user_emergency_access_token.id,
+            user_emergency_access_token.allowed_network = %allowed_network,
+            %user.id,
+            %user.username,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        token: String,
+        allowed_network: IpNetwork,
+        expires_after: Duration,
+    ) -> Result<UserEmergencyAccessToken, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + expires_after;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "user_emergency_access_token.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_emergency_access_tokens (
+                      user_emergency_access_token_id
+                    , user_id
+                    , token
+                    , allowed_network
+                    , created_at
+                    , expires_at
+                )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            &token,
+            allowed_network,
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(UserEmergencyAccessToken {
+            id,
+            user_id: user.id,
+            token,
+            allowed_network,
+            created_at,
+            expires_at,
+            consumed_at: None,
+            user_session_id: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_emergency_access.consume",
+        skip_all,
+        fields(
+            db.query.text,
+            %token.id,
+            %browser_session.id,
+            user.id = %token.user_id,
+        ),
+        err,
+    )]
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        mut token: UserEmergencyAccessToken,
+        browser_session: &BrowserSession,
+    ) -> Result<UserEmergencyAccessToken, Self::Error> {
+        let consumed_at = clock.now();
+
+        // Only update the token if it wasn't used yet, so that two concurrent
+        // requests can't both start a session with the same token
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_emergency_access_tokens
+                SET consumed_at = $2
+                  , user_session_id = $3
+                WHERE user_emergency_access_token_id = $1
+                  AND consumed_at IS NULL
+            "#,
+            Uuid::from(token.id),
+            consumed_at,
+            Uuid::from(browser_session.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        token.consumed_at = Some(consumed_at);
+        token.user_session_id = Some(browser_session.id);
+
+        Ok(token)
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use mas_data_model::User;
+use mas_storage::{user::UserKnownDeviceRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`UserKnownDeviceRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserKnownDeviceRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserKnownDeviceRepository<'c> {
+    /// Create a new [`PgUserKnownDeviceRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl<'c> UserKnownDeviceRepository for PgUserKnownDeviceRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_known_device.count",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn count(&mut self, user: &User) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM user_known_devices
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_known_device.remember",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user_known_device.fingerprint = fingerprint,
+        ),
+        err,
+    )]
+    async fn remember(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        fingerprint: &str,
+    ) -> Result<bool, Self::Error> {
+        let now = clock.now();
+        let id = Ulid::from_datetime_with_source(now.into(), rng);
+
+        // The row keeps its original ID if the device was already known, which
+        // tells us whether it was inserted
+        let inserted = sqlx::query_scalar!(
+            r#"
+                INSERT INTO user_known_devices
+                    (user_known_device_id, user_id, fingerprint, first_seen_at, last_seen_at)
+                VALUES ($1, $2, $3, $4, $4)
+                ON CONFLICT (user_id, fingerprint) DO UPDATE
+                    SET last_seen_at = EXCLUDED.last_seen_at
+                RETURNING user_known_device_id = $1 AS "inserted!"
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            fingerprint,
+            now,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(inserted)
+    }
+}
@@ -7,14 +7,18 @@
 //! A module containing the PostgreSQL implementation of the user-related
 //! repositories
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use mas_data_model::User;
 use mas_storage::{
-    user::{UserFilter, UserRepository},
+    pagination::Count,
+    user::{UserFilter, UserHomeserver, UserRepository},
     Clock,
 };
 use rand::RngCore;
-use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query::{Expr, PostgresQueryBuilder, Query, SimpleExpr};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
 use ulid::Ulid;
@@ -22,25 +26,33 @@ use uuid::Uuid;
 
 use crate::{
     filter::{Filter, StatementExt},
-    iden::Users,
-    pagination::QueryBuilderExt,
+    iden::{CompatSessions, OAuth2Sessions, UserSessions, Users},
+    pagination::{estimate_count, QueryBuilderExt},
     tracing::ExecuteExt,
     DatabaseError,
 };
 
+mod activity_digest;
 mod email;
+mod emergency_access;
+mod known_device;
+mod notification_preferences;
 mod password;
 mod recovery;
 mod session;
 mod terms;
+mod trusted_browser;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::{
-    email::PgUserEmailRepository, password::PgUserPasswordRepository,
-    recovery::PgUserRecoveryRepository, session::PgBrowserSessionRepository,
-    terms::PgUserTermsRepository,
+    activity_digest::PgUserActivityDigestRepository, email::PgUserEmailRepository,
+    emergency_access::PgUserEmergencyAccessRepository, known_device::PgUserKnownDeviceRepository,
+    notification_preferences::PgUserNotificationPreferencesRepository,
+    password::PgUserPasswordRepository, recovery::PgUserRecoveryRepository,
+    session::PgBrowserSessionRepository, terms::PgUserTermsRepository,
+    trusted_browser::PgUserTrustedBrowserRepository,
 };
 
 /// An implementation of [`UserRepository`] for a PostgreSQL connection
@@ -73,6 +85,12 @@ mod priv_ {
         pub(super) created_at: DateTime<Utc>,
         pub(super) locked_at: Option<DateTime<Utc>>,
         pub(super) can_request_admin: bool,
+        pub(super) legal_hold_at: Option<DateTime<Utc>>,
+        pub(super) homeserver: Option<String>,
+        pub(super) is_bot: bool,
+        pub(super) bot_owner_id: Option<Uuid>,
+        pub(super) is_guest: bool,
+        pub(super) deletion_scheduled_at: Option<DateTime<Utc>>,
     }
 }
 
@@ -89,6 +107,12 @@ impl From<UserLookup> for User {
             created_at: value.created_at,
             locked_at: value.locked_at,
             can_request_admin: value.can_request_admin,
+            legal_hold_at: value.legal_hold_at,
+            homeserver: value.homeserver,
+            is_bot: value.is_bot,
+            bot_owner_id: value.bot_owner_id.map(Ulid::from),
+            is_guest: value.is_guest,
+            deletion_scheduled_at: value.deletion_scheduled_at,
         }
     }
 }
@@ -106,9 +130,140 @@ impl Filter for UserFilter<'_> {
             .add_option(self.can_request_admin().map(|can_request_admin| {
                 Expr::col((Users::Table, Users::CanRequestAdmin)).eq(can_request_admin)
             }))
+            .add_option(self.legal_hold().map(|legal_hold| {
+                if legal_hold {
+                    Expr::col((Users::Table, Users::LegalHoldAt)).is_not_null()
+                } else {
+                    Expr::col((Users::Table, Users::LegalHoldAt)).is_null()
+                }
+            }))
+            .add_option(self.homeserver().map(|homeserver| match homeserver {
+                UserHomeserver::Main => Expr::col((Users::Table, Users::Homeserver)).is_null(),
+                UserHomeserver::Additional(server_name) => {
+                    Expr::col((Users::Table, Users::Homeserver)).eq(server_name)
+                }
+            }))
+            .add_option(
+                self.bot()
+                    .map(|bot| Expr::col((Users::Table, Users::IsBot)).eq(bot)),
+            )
+            .add_option(
+                self.bot_owner().map(|owner| {
+                    Expr::col((Users::Table, Users::BotOwnerId)).eq(Uuid::from(owner.id))
+                }),
+            )
+            .add_option(
+                self.guest()
+                    .map(|guest| Expr::col((Users::Table, Users::IsGuest)).eq(guest)),
+            )
+            .add_option(
+                self.deletion_due()
+                    .map(|now| Expr::col((Users::Table, Users::DeletionScheduledAt)).lte(now)),
+            )
+            .add_option(self.inactive().map(|since| {
+                Expr::col((Users::Table, Users::CreatedAt))
+                    .lt(since)
+                    .and(used_since(since).not())
+            }))
+            .add_option(self.used().map(used_since))
+            .add_option(self.idle().map(|since| {
+                Expr::col((Users::Table, Users::CreatedAt))
+                    .lt(since)
+                    .and(sessions_used_since(since, false).not())
+            }))
+            .add_option(self.inactivity_warned().map(|inactivity_warned| {
+                if inactivity_warned {
+                    Expr::col((Users::Table, Users::InactivityWarnedAt)).is_not_null()
+                } else {
+                    Expr::col((Users::Table, Users::InactivityWarnedAt)).is_null()
+                }
+            }))
+            .add_option(
+                self.inactivity_warned_cutoff()
+                    .map(|before| Expr::col((Users::Table, Users::InactivityWarnedAt)).lt(before)),
+            )
+            .add_option(
+                self.excluded_usernames()
+                    .filter(|usernames| !usernames.is_empty())
+                    .map(|usernames| {
+                        Expr::col((Users::Table, Users::Username))
+                            .is_not_in(usernames.iter().cloned())
+                    }),
+            )
     }
 }
 
+/// Condition matching the users who have an active session, or a session which
+/// was started or used since the given instant
+fn used_since(since: DateTime<Utc>) -> SimpleExpr {
+    sessions_used_since(since, true)
+}
+
+/// Condition matching the users who have a session which was started or used
+/// since the given instant, and, if `include_active` is set, the users who
+/// have an active session
+fn sessions_used_since(since: DateTime<Utc>, include_active: bool) -> SimpleExpr {
+    let user_sessions = Expr::col((UserSessions::Table, UserSessions::CreatedAt))
+        .gte(since)
+        .or(Expr::col((UserSessions::Table, UserSessions::LastActiveAt)).gte(since));
+    let compat_sessions = Expr::col((CompatSessions::Table, CompatSessions::CreatedAt))
+        .gte(since)
+        .or(Expr::col((CompatSessions::Table, CompatSessions::LastActiveAt)).gte(since));
+    let oauth2_sessions = Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt))
+        .gte(since)
+        .or(Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveAt)).gte(since));
+
+    let (user_sessions, compat_sessions, oauth2_sessions) = if include_active {
+        (
+            Expr::col((UserSessions::Table, UserSessions::FinishedAt))
+                .is_null()
+                .or(user_sessions),
+            Expr::col((CompatSessions::Table, CompatSessions::FinishedAt))
+                .is_null()
+                .or(compat_sessions),
+            Expr::col((OAuth2Sessions::Table, OAuth2Sessions::FinishedAt))
+                .is_null()
+                .or(oauth2_sessions),
+        )
+    } else {
+        (user_sessions, compat_sessions, oauth2_sessions)
+    };
+
+    Expr::exists(
+        Query::select()
+            .expr(Expr::cust("1"))
+            .from(UserSessions::Table)
+            .and_where(
+                Expr::col((UserSessions::Table, UserSessions::UserId))
+                    .equals((Users::Table, Users::UserId)),
+            )
+            .and_where(user_sessions)
+            .take(),
+    )
+    .or(Expr::exists(
+        Query::select()
+            .expr(Expr::cust("1"))
+            .from(CompatSessions::Table)
+            .and_where(
+                Expr::col((CompatSessions::Table, CompatSessions::UserId))
+                    .equals((Users::Table, Users::UserId)),
+            )
+            .and_where(compat_sessions)
+            .take(),
+    ))
+    .or(Expr::exists(
+        Query::select()
+            .expr(Expr::cust("1"))
+            .from(OAuth2Sessions::Table)
+            .and_where(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId))
+                    .equals((Users::Table, Users::UserId)),
+            )
+            .and_where(oauth2_sessions)
+            .take(),
+    ))
+}
+
 #[async_trait]
 impl<'c> UserRepository for PgUserRepository<'c> {
     type Error = DatabaseError;
@@ -132,6 +287,12 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , created_at
                      , locked_at
                      , can_request_admin
+                     , legal_hold_at
+                     , homeserver
+                     , is_bot
+                     , bot_owner_id
+                     , is_guest
+                     , deletion_scheduled_at
                 FROM users
                 WHERE user_id = $1
             "#,
@@ -146,16 +307,67 @@ impl<'c> UserRepository for PgUserRepository<'c> {
         Ok(Some(res.into()))
     }
 
+    #[tracing::instrument(
+        name = "db.user.load_batch",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, User>, Self::Error> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query_as!(
+            UserLookup,
+            r#"
+                SELECT user_id
+                     , username
+                     , primary_user_email_id
+                     , created_at
+                     , locked_at
+                     , can_request_admin
+                     , legal_hold_at
+                     , homeserver
+                     , is_bot
+                     , bot_owner_id
+                     , is_guest
+                     , deletion_scheduled_at
+                FROM users
+                WHERE user_id = ANY($1::uuid[])
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .map(|r| {
+                let user = User::from(r);
+                (user.id, user)
+            })
+            .collect())
+    }
+
     #[tracing::instrument(
         name = "db.user.find_by_username",
         skip_all,
         fields(
             db.query.text,
             user.username = username,
+            user.homeserver = homeserver.server_name(),
         ),
         err,
     )]
-    async fn find_by_username(&mut self, username: &str) -> Result<Option<User>, Self::Error> {
+    async fn find_by_username(
+        &mut self,
+        homeserver: UserHomeserver<'_>,
+        username: &str,
+    ) -> Result<Option<User>, Self::Error> {
         let res = sqlx::query_as!(
             UserLookup,
             r#"
@@ -165,10 +377,18 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , created_at
                      , locked_at
                      , can_request_admin
+                     , legal_hold_at
+                     , homeserver
+                     , is_bot
+                     , bot_owner_id
+                     , is_guest
+                     , deletion_scheduled_at
                 FROM users
                 WHERE username = $1
+                  AND COALESCE(homeserver, '') = COALESCE($2, '')
             "#,
             username,
+            homeserver.server_name(),
         )
         .traced()
         .fetch_optional(&mut *self.conn)
@@ -182,17 +402,37 @@ impl<'c> UserRepository for PgUserRepository<'c> {
     #[tracing::instrument(
         name = "db.user.add",
         skip_all,
+        fields(
+            user.username = username,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        username: String,
+    ) -> Result<User, Self::Error> {
+        self.add_for_homeserver(rng, clock, UserHomeserver::Main, username)
+            .await
+    }
+
+    #[tracing::instrument(
+        name = "db.user.add_for_homeserver",
+        skip_all,
         fields(
             db.query.text,
             user.username = username,
+            user.homeserver = homeserver.server_name(),
             user.id,
         ),
         err,
     )]
-    async fn add(
+    async fn add_for_homeserver(
         &mut self,
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
+        homeserver: UserHomeserver<'_>,
         username: String,
     ) -> Result<User, Self::Error> {
         let created_at = clock.now();
@@ -201,12 +441,13 @@ impl<'c> UserRepository for PgUserRepository<'c> {
 
         let res = sqlx::query!(
             r#"
-                INSERT INTO users (user_id, username, created_at)
-                VALUES ($1, $2, $3)
-                ON CONFLICT (username) DO NOTHING
+                INSERT INTO users (user_id, username, homeserver, created_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO NOTHING
             "#,
             Uuid::from(id),
             username,
+            homeserver.server_name(),
             created_at,
         )
         .traced()
@@ -225,6 +466,12 @@ impl<'c> UserRepository for PgUserRepository<'c> {
             created_at,
             locked_at: None,
             can_request_admin: false,
+            legal_hold_at: None,
+            homeserver: homeserver.server_name().map(ToOwned::to_owned),
+            is_bot: false,
+            bot_owner_id: None,
+            is_guest: false,
+            deletion_scheduled_at: None,
         })
     }
 
@@ -234,17 +481,25 @@ impl<'c> UserRepository for PgUserRepository<'c> {
         fields(
             db.query.text,
             user.username = username,
+            user.homeserver = homeserver.server_name(),
         ),
         err,
     )]
-    async fn exists(&mut self, username: &str) -> Result<bool, Self::Error> {
+    async fn exists(
+        &mut self,
+        homeserver: UserHomeserver<'_>,
+        username: &str,
+    ) -> Result<bool, Self::Error> {
         let exists = sqlx::query_scalar!(
             r#"
                 SELECT EXISTS(
-                    SELECT 1 FROM users WHERE username = $1
+                    SELECT 1 FROM users
+                    WHERE username = $1
+                      AND COALESCE(homeserver, '') = COALESCE($2, '')
                 ) AS "exists!"
             "#,
-            username
+            username,
+            homeserver.server_name(),
         )
         .traced()
         .fetch_one(&mut *self.conn)
@@ -356,6 +611,302 @@ impl<'c> UserRepository for PgUserRepository<'c> {
         Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "db.user.place_legal_hold",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn place_legal_hold(
+        &mut self,
+        clock: &dyn Clock,
+        mut user: User,
+    ) -> Result<User, Self::Error> {
+        if user.legal_hold_at.is_some() {
+            return Ok(user);
+        }
+
+        let legal_hold_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET legal_hold_at = $1
+                WHERE user_id = $2
+            "#,
+            legal_hold_at,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.legal_hold_at = Some(legal_hold_at);
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.release_legal_hold",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn release_legal_hold(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.legal_hold_at.is_none() {
+            return Ok(user);
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET legal_hold_at = NULL
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.legal_hold_at = None;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_homeserver",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user.homeserver = homeserver,
+        ),
+        err,
+    )]
+    async fn set_homeserver(
+        &mut self,
+        mut user: User,
+        homeserver: Option<String>,
+    ) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET homeserver = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            homeserver.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.homeserver = homeserver;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_bot",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn set_bot(&mut self, mut user: User, owner: Option<&User>) -> Result<User, Self::Error> {
+        let bot_owner_id = owner.map(|owner| owner.id);
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET is_bot = TRUE
+                  , bot_owner_id = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            bot_owner_id.map(Uuid::from),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.is_bot = true;
+        user.bot_owner_id = bot_owner_id;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_guest",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn set_guest(&mut self, mut user: User) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET is_guest = TRUE
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.is_guest = true;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.schedule_deletion",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn schedule_deletion(
+        &mut self,
+        mut user: User,
+        deletion_scheduled_at: DateTime<Utc>,
+    ) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET deletion_scheduled_at = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            deletion_scheduled_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.deletion_scheduled_at = Some(deletion_scheduled_at);
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.clear_scheduled_deletion",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn clear_scheduled_deletion(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.deletion_scheduled_at.is_none() {
+            return Ok(user);
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET deletion_scheduled_at = NULL
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.deletion_scheduled_at = None;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.mark_inactivity_warned",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn mark_inactivity_warned(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET inactivity_warned_at = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.clear_inactivity_warning",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn clear_inactivity_warning(&mut self, user: &User) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET inactivity_warned_at = NULL
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
     #[tracing::instrument(
         name = "db.user.list",
         skip_all,
@@ -394,6 +945,30 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                 Expr::col((Users::Table, Users::CanRequestAdmin)),
                 UserLookupIden::CanRequestAdmin,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::LegalHoldAt)),
+                UserLookupIden::LegalHoldAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::Homeserver)),
+                UserLookupIden::Homeserver,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsBot)),
+                UserLookupIden::IsBot,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::BotOwnerId)),
+                UserLookupIden::BotOwnerId,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsGuest)),
+                UserLookupIden::IsGuest,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::DeletionScheduledAt)),
+                UserLookupIden::DeletionScheduledAt,
+            )
             .from(Users::Table)
             .apply_filter(filter)
             .generate_pagination((Users::Table, Users::UserId), pagination)
@@ -434,6 +1009,24 @@ impl<'c> UserRepository for PgUserRepository<'c> {
             .map_err(DatabaseError::to_invalid_operation)
     }
 
+    #[tracing::instrument(
+        name = "db.user.estimate_count",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn estimate_count(&mut self, filter: UserFilter<'_>) -> Result<Count, Self::Error> {
+        let statement = Query::select()
+            .expr(Expr::col((Users::Table, Users::UserId)))
+            .from(Users::Table)
+            .apply_filter(filter)
+            .take();
+
+        estimate_count(&mut *self.conn, &statement).await
+    }
+
     #[tracing::instrument(
         name = "db.user.acquire_lock_for_sync",
         skip_all,
@@ -0,0 +1,132 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use mas_data_model::{User, UserNotificationPreferences};
+use mas_storage::{user::UserNotificationPreferencesRepository, Clock};
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, DatabaseInconsistencyError, ExecuteExt};
+
+/// An implementation of [`UserNotificationPreferencesRepository`] for a
+/// PostgreSQL connection
+pub struct PgUserNotificationPreferencesRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserNotificationPreferencesRepository<'c> {
+    /// Create a new [`PgUserNotificationPreferencesRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserNotificationPreferencesRow {
+    user_id: Uuid,
+    new_login_alerts: bool,
+    digest_frequency: String,
+    security_only: bool,
+}
+
+impl TryFrom<UserNotificationPreferencesRow> for UserNotificationPreferences {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(row: UserNotificationPreferencesRow) -> Result<Self, Self::Error> {
+        let digest_frequency = row.digest_frequency.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("user_notification_preferences")
+                .column("digest_frequency")
+                .row(Ulid::from(row.user_id))
+                .source(e)
+        })?;
+
+        Ok(Self {
+            new_login_alerts: row.new_login_alerts,
+            digest_frequency,
+            security_only: row.security_only,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> UserNotificationPreferencesRepository for PgUserNotificationPreferencesRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_notification_preferences.get",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn get(&mut self, user: &User) -> Result<UserNotificationPreferences, Self::Error> {
+        let row = sqlx::query_as!(
+            UserNotificationPreferencesRow,
+            r#"
+                SELECT user_id
+                     , new_login_alerts
+                     , digest_frequency
+                     , security_only
+                FROM user_notification_preferences
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(UserNotificationPreferences::default());
+        };
+
+        Ok(row.try_into()?)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_notification_preferences.set",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn set(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        preferences: UserNotificationPreferences,
+    ) -> Result<UserNotificationPreferences, Self::Error> {
+        let updated_at = clock.now();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_notification_preferences
+                    (user_id, new_login_alerts, digest_frequency, security_only, updated_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (user_id) DO UPDATE
+                    SET new_login_alerts = EXCLUDED.new_login_alerts
+                      , digest_frequency = EXCLUDED.digest_frequency
+                      , security_only = EXCLUDED.security_only
+                      , updated_at = EXCLUDED.updated_at
+            "#,
+            Uuid::from(user.id),
+            preferences.new_login_alerts,
+            preferences.digest_frequency.as_str(),
+            preferences.security_only,
+            updated_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(preferences)
+    }
+}
@@ -34,6 +34,7 @@ struct UserPasswordLookup {
     version: i32,
     upgraded_from_id: Option<Uuid>,
     created_at: DateTime<Utc>,
+    must_change_at: Option<DateTime<Utc>>,
 }
 
 #[async_trait]
@@ -59,6 +60,7 @@ impl<'c> UserPasswordRepository for PgUserPasswordRepository<'c> {
                      , up.version
                      , up.upgraded_from_id
                      , up.created_at
+                     , up.must_change_at
                 FROM user_passwords up
                 WHERE up.user_id = $1
                 ORDER BY up.created_at DESC
@@ -91,6 +93,7 @@ impl<'c> UserPasswordRepository for PgUserPasswordRepository<'c> {
             version,
             upgraded_from_id,
             created_at,
+            must_change_at: res.must_change_at,
         }))
     }
 
@@ -120,12 +123,14 @@ impl<'c> UserPasswordRepository for PgUserPasswordRepository<'c> {
         tracing::Span::current().record("user_password.id", tracing::field::display(id));
 
         let upgraded_from_id = upgraded_from.map(|p| p.id);
+        // Upgrading the hash doesn't change the password itself
+        let must_change_at = upgraded_from.and_then(|p| p.must_change_at);
 
         sqlx::query!(
             r#"
                 INSERT INTO user_passwords
-                    (user_password_id, user_id, hashed_password, version, upgraded_from_id, created_at)
-                VALUES ($1, $2, $3, $4, $5, $6)
+                    (user_password_id, user_id, hashed_password, version, upgraded_from_id, created_at, must_change_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
             "#,
             Uuid::from(id),
             Uuid::from(user.id),
@@ -133,6 +138,7 @@ impl<'c> UserPasswordRepository for PgUserPasswordRepository<'c> {
             i32::from(version),
             upgraded_from_id.map(Uuid::from),
             created_at,
+            must_change_at,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -144,6 +150,42 @@ impl<'c> UserPasswordRepository for PgUserPasswordRepository<'c> {
             version,
             upgraded_from_id,
             created_at,
+            must_change_at,
         })
     }
+
+    #[tracing::instrument(
+        name = "db.user_password.require_change",
+        skip_all,
+        fields(
+            db.query.text,
+            %password.id,
+        ),
+        err,
+    )]
+    async fn require_change(
+        &mut self,
+        clock: &dyn Clock,
+        mut password: Password,
+    ) -> Result<Password, Self::Error> {
+        let must_change_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_passwords
+                SET must_change_at = $2
+                WHERE user_password_id = $1
+            "#,
+            Uuid::from(password.id),
+            must_change_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        password.must_change_at = Some(must_change_at);
+        Ok(password)
+    }
 }
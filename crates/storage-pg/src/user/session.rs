@@ -4,20 +4,23 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+};
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
-    Authentication, AuthenticationMethod, BrowserSession, Password,
-    UpstreamOAuthAuthorizationSession, User, UserAgent,
+    Authentication, AuthenticationMethod, BrowserSession, BrowserSessionBinding,
+    BrowserSessionImpersonation, Password, UpstreamOAuthAuthorizationSession, User, UserAgent,
 };
 use mas_storage::{
     user::{BrowserSessionFilter, BrowserSessionRepository},
     Clock, Page, Pagination,
 };
 use rand::RngCore;
-use sea_query::{Expr, PostgresQueryBuilder};
+use sea_query::{Expr, PgFunc, PostgresQueryBuilder};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
 use ulid::Ulid;
@@ -55,12 +58,28 @@ struct SessionLookup {
     user_session_user_agent: Option<String>,
     user_session_last_active_at: Option<DateTime<Utc>>,
     user_session_last_active_ip: Option<IpAddr>,
+    user_session_tags: Vec<String>,
+    user_session_binding_credential_id: Option<String>,
+    user_session_binding_public_key: Option<Vec<u8>>,
+    user_session_binding_sign_count: Option<i64>,
+    user_session_binding_token: Option<String>,
+    user_session_binding_previous_token: Option<String>,
+    user_session_binding_expires_at: Option<DateTime<Utc>>,
+    user_session_impersonator_user_id: Option<Uuid>,
+    user_session_impersonator_user_session_id: Option<Uuid>,
+    user_session_impersonation_expires_at: Option<DateTime<Utc>>,
     user_id: Uuid,
     user_username: String,
     user_primary_user_email_id: Option<Uuid>,
     user_created_at: DateTime<Utc>,
     user_locked_at: Option<DateTime<Utc>>,
     user_can_request_admin: bool,
+    user_legal_hold_at: Option<DateTime<Utc>>,
+    user_homeserver: Option<String>,
+    user_is_bot: bool,
+    user_bot_owner_id: Option<Uuid>,
+    user_is_guest: bool,
+    user_deletion_scheduled_at: Option<DateTime<Utc>>,
 }
 
 impl TryFrom<SessionLookup> for BrowserSession {
@@ -68,6 +87,52 @@ impl TryFrom<SessionLookup> for BrowserSession {
 
     fn try_from(value: SessionLookup) -> Result<Self, Self::Error> {
         let id = Ulid::from(value.user_id);
+        let session_id = Ulid::from(value.user_session_id);
+
+        let binding = match (
+            value.user_session_binding_credential_id,
+            value.user_session_binding_public_key,
+            value.user_session_binding_sign_count,
+            value.user_session_binding_token,
+            value.user_session_binding_expires_at,
+        ) {
+            (
+                Some(credential_id),
+                Some(public_key),
+                Some(sign_count),
+                Some(token),
+                Some(expires_at),
+            ) => {
+                let sign_count = sign_count.try_into().map_err(|e| {
+                    DatabaseInconsistencyError::on("user_sessions")
+                        .column("binding_sign_count")
+                        .row(session_id)
+                        .source(e)
+                })?;
+
+                Some(BrowserSessionBinding {
+                    credential_id,
+                    public_key,
+                    sign_count,
+                    token,
+                    previous_token: value.user_session_binding_previous_token,
+                    expires_at,
+                })
+            }
+            (None, None, None, None, None) => None,
+            _ => return Err(DatabaseInconsistencyError::on("user_sessions").row(session_id)),
+        };
+
+        let impersonation = value
+            .user_session_impersonation_expires_at
+            .map(|expires_at| BrowserSessionImpersonation {
+                impersonator_user_id: value.user_session_impersonator_user_id.map(Ulid::from),
+                impersonator_session_id: value
+                    .user_session_impersonator_user_session_id
+                    .map(Ulid::from),
+                expires_at,
+            });
+
         let user = User {
             id,
             username: value.user_username,
@@ -76,16 +141,25 @@ impl TryFrom<SessionLookup> for BrowserSession {
             created_at: value.user_created_at,
             locked_at: value.user_locked_at,
             can_request_admin: value.user_can_request_admin,
+            legal_hold_at: value.user_legal_hold_at,
+            homeserver: value.user_homeserver,
+            is_bot: value.user_is_bot,
+            bot_owner_id: value.user_bot_owner_id.map(Ulid::from),
+            is_guest: value.user_is_guest,
+            deletion_scheduled_at: value.user_deletion_scheduled_at,
         };
 
         Ok(BrowserSession {
-            id: value.user_session_id.into(),
+            id: session_id,
             user,
             created_at: value.user_session_created_at,
             finished_at: value.user_session_finished_at,
             user_agent: value.user_session_user_agent.map(UserAgent::parse),
             last_active_at: value.user_session_last_active_at,
             last_active_ip: value.user_session_last_active_ip,
+            tags: value.user_session_tags,
+            binding,
+            impersonation,
         })
     }
 }
@@ -145,6 +219,23 @@ impl crate::filter::Filter for BrowserSessionFilter<'_> {
             .add_option(self.last_active_before().map(|last_active_before| {
                 Expr::col((UserSessions::Table, UserSessions::LastActiveAt)).lt(last_active_before)
             }))
+            .add_option(self.tag().map(|tag| {
+                Expr::val(tag).eq(PgFunc::any(Expr::col((
+                    UserSessions::Table,
+                    UserSessions::Tags,
+                ))))
+            }))
+            .add_option(self.binding_expired_before().map(|binding_expired_before| {
+                Expr::col((UserSessions::Table, UserSessions::BindingExpiresAt))
+                    .lt(binding_expired_before)
+            }))
+            .add_option(
+                self.impersonation_expired_before()
+                    .map(|impersonation_expired_before| {
+                        Expr::col((UserSessions::Table, UserSessions::ImpersonationExpiresAt))
+                            .lt(impersonation_expired_before)
+                    }),
+            )
     }
 }
 
@@ -171,12 +262,28 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , s.user_agent            AS "user_session_user_agent"
                      , s.last_active_at        AS "user_session_last_active_at"
                      , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
+                     , s.tags                  AS "user_session_tags"
+                     , s.binding_credential_id AS "user_session_binding_credential_id"
+                     , s.binding_public_key    AS "user_session_binding_public_key"
+                     , s.binding_sign_count    AS "user_session_binding_sign_count"
+                     , s.binding_token         AS "user_session_binding_token"
+                     , s.binding_previous_token AS "user_session_binding_previous_token"
+                     , s.binding_expires_at    AS "user_session_binding_expires_at"
+                     , s.impersonator_user_id  AS "user_session_impersonator_user_id"
+                     , s.impersonator_user_session_id AS "user_session_impersonator_user_session_id"
+                     , s.impersonation_expires_at AS "user_session_impersonation_expires_at"
                      , u.user_id
                      , u.username              AS "user_username"
                      , u.primary_user_email_id AS "user_primary_user_email_id"
                      , u.created_at            AS "user_created_at"
                      , u.locked_at             AS "user_locked_at"
                      , u.can_request_admin     AS "user_can_request_admin"
+                     , u.legal_hold_at         AS "user_legal_hold_at"
+                     , u.homeserver            AS "user_homeserver"
+                     , u.is_bot                AS "user_is_bot"
+                     , u.bot_owner_id          AS "user_bot_owner_id"
+                     , u.is_guest              AS "user_is_guest"
+                     , u.deletion_scheduled_at AS "user_deletion_scheduled_at"
                 FROM user_sessions s
                 INNER JOIN users u
                     USING (user_id)
@@ -193,6 +300,70 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(Some(res.try_into()?))
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.load_batch",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, BrowserSession>, Self::Error> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query_as!(
+            SessionLookup,
+            r#"
+                SELECT s.user_session_id
+                     , s.created_at            AS "user_session_created_at"
+                     , s.finished_at           AS "user_session_finished_at"
+                     , s.user_agent            AS "user_session_user_agent"
+                     , s.last_active_at        AS "user_session_last_active_at"
+                     , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
+                     , s.tags                  AS "user_session_tags"
+                     , s.binding_credential_id AS "user_session_binding_credential_id"
+                     , s.binding_public_key    AS "user_session_binding_public_key"
+                     , s.binding_sign_count    AS "user_session_binding_sign_count"
+                     , s.binding_token         AS "user_session_binding_token"
+                     , s.binding_previous_token AS "user_session_binding_previous_token"
+                     , s.binding_expires_at    AS "user_session_binding_expires_at"
+                     , s.impersonator_user_id  AS "user_session_impersonator_user_id"
+                     , s.impersonator_user_session_id AS "user_session_impersonator_user_session_id"
+                     , s.impersonation_expires_at AS "user_session_impersonation_expires_at"
+                     , u.user_id
+                     , u.username              AS "user_username"
+                     , u.primary_user_email_id AS "user_primary_user_email_id"
+                     , u.created_at            AS "user_created_at"
+                     , u.locked_at             AS "user_locked_at"
+                     , u.can_request_admin     AS "user_can_request_admin"
+                     , u.legal_hold_at         AS "user_legal_hold_at"
+                     , u.homeserver            AS "user_homeserver"
+                     , u.is_bot                AS "user_is_bot"
+                     , u.bot_owner_id          AS "user_bot_owner_id"
+                     , u.is_guest              AS "user_is_guest"
+                     , u.deletion_scheduled_at AS "user_deletion_scheduled_at"
+                FROM user_sessions s
+                INNER JOIN users u
+                    USING (user_id)
+                WHERE s.user_session_id = ANY($1::uuid[])
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| {
+                BrowserSession::try_from(r)
+                    .map(|s| (s.id, s))
+                    .map_err(DatabaseError::from)
+            })
+            .collect()
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.add",
         skip_all,
@@ -237,6 +408,79 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
             user_agent,
             last_active_at: None,
             last_active_ip: None,
+            tags: Vec::new(),
+            binding: None,
+            impersonation: None,
+        };
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.add_impersonation",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            %impersonator_session.id,
+            impersonator.id = %impersonator_session.user.id,
+            user_session.id,
+        ),
+        err,
+    )]
+    async fn add_impersonation(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        impersonator_session: &BrowserSession,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<UserAgent>,
+    ) -> Result<BrowserSession, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_session.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_sessions
+                    ( user_session_id
+                    , user_id
+                    , created_at
+                    , user_agent
+                    , impersonator_user_id
+                    , impersonator_user_session_id
+                    , impersonation_expires_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            created_at,
+            user_agent.as_deref(),
+            Uuid::from(impersonator_session.user.id),
+            Uuid::from(impersonator_session.id),
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        let session = BrowserSession {
+            id,
+            user: user.clone(),
+            created_at,
+            finished_at: None,
+            user_agent,
+            last_active_at: None,
+            last_active_ip: None,
+            tags: Vec::new(),
+            binding: None,
+            impersonation: Some(BrowserSessionImpersonation {
+                impersonator_user_id: Some(impersonator_session.user.id),
+                impersonator_session_id: Some(impersonator_session.id),
+                expires_at,
+            }),
         };
 
         Ok(session)
@@ -277,6 +521,51 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(user_session)
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.bind",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+            user_session.binding.credential_id = %binding.credential_id,
+        ),
+        err,
+    )]
+    async fn bind(
+        &mut self,
+        mut user_session: BrowserSession,
+        binding: BrowserSessionBinding,
+    ) -> Result<BrowserSession, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_sessions
+                SET binding_credential_id = $1
+                  , binding_public_key = $2
+                  , binding_sign_count = $3
+                  , binding_token = $4
+                  , binding_previous_token = $5
+                  , binding_expires_at = $6
+                WHERE user_session_id = $7
+            "#,
+            binding.credential_id,
+            binding.public_key,
+            i64::from(binding.sign_count),
+            binding.token,
+            binding.previous_token,
+            binding.expires_at,
+            Uuid::from(user_session.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user_session.binding = Some(binding);
+
+        Ok(user_session)
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.finish_bulk",
         skip_all,
@@ -305,6 +594,52 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.finish_expired",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Option<Duration>,
+        inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<usize, Self::Error> {
+        let finished_at = clock.now();
+        let limit: i64 = limit.try_into().unwrap_or(i64::MAX);
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_sessions
+                SET finished_at = $1
+                WHERE user_session_id IN (
+                    SELECT user_session_id
+                    FROM user_sessions
+                    WHERE finished_at IS NULL
+                      AND (
+                        created_at + make_interval(secs => $2::BIGINT) < $1
+                        OR COALESCE(last_active_at, created_at)
+                            + make_interval(secs => $3::BIGINT)
+                            < $1
+                      )
+                    LIMIT $4
+                )
+            "#,
+            finished_at,
+            session_ttl.map(|ttl| ttl.num_seconds()),
+            inactivity_ttl.map(|ttl| ttl.num_seconds()),
+            limit,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.list",
         skip_all,
@@ -343,6 +678,46 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((UserSessions::Table, UserSessions::LastActiveIp)),
                 SessionLookupIden::UserSessionLastActiveIp,
             )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::Tags)),
+                SessionLookupIden::UserSessionTags,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::BindingCredentialId)),
+                SessionLookupIden::UserSessionBindingCredentialId,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::BindingPublicKey)),
+                SessionLookupIden::UserSessionBindingPublicKey,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::BindingSignCount)),
+                SessionLookupIden::UserSessionBindingSignCount,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::BindingToken)),
+                SessionLookupIden::UserSessionBindingToken,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::BindingPreviousToken)),
+                SessionLookupIden::UserSessionBindingPreviousToken,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::BindingExpiresAt)),
+                SessionLookupIden::UserSessionBindingExpiresAt,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::ImpersonatorUserId)),
+                SessionLookupIden::UserSessionImpersonatorUserId,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::ImpersonatorUserSessionId)),
+                SessionLookupIden::UserSessionImpersonatorUserSessionId,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::ImpersonationExpiresAt)),
+                SessionLookupIden::UserSessionImpersonationExpiresAt,
+            )
             .expr_as(
                 Expr::col((Users::Table, Users::UserId)),
                 SessionLookupIden::UserId,
@@ -367,6 +742,30 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((Users::Table, Users::CanRequestAdmin)),
                 SessionLookupIden::UserCanRequestAdmin,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::LegalHoldAt)),
+                SessionLookupIden::UserLegalHoldAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::Homeserver)),
+                SessionLookupIden::UserHomeserver,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsBot)),
+                SessionLookupIden::UserIsBot,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::BotOwnerId)),
+                SessionLookupIden::UserBotOwnerId,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsGuest)),
+                SessionLookupIden::UserIsGuest,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::DeletionScheduledAt)),
+                SessionLookupIden::UserDeletionScheduledAt,
+            )
             .from(UserSessions::Table)
             .inner_join(
                 Users::Table,
@@ -554,6 +953,94 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(Some(authentication))
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.get_authentication_at",
+        skip_all,
+        fields(
+            db.query.text,
+            user_session.id = %user_session_id,
+        ),
+        err,
+    )]
+    async fn get_authentication_at(
+        &mut self,
+        user_session_id: Ulid,
+        at: DateTime<Utc>,
+    ) -> Result<Option<Authentication>, Self::Error> {
+        let authentication = sqlx::query_as!(
+            AuthenticationLookup,
+            r#"
+                SELECT user_session_authentication_id
+                     , created_at
+                     , user_password_id
+                     , upstream_oauth_authorization_session_id
+                FROM user_session_authentications
+                WHERE user_session_id = $1
+                  AND created_at <= $2
+                ORDER BY created_at DESC
+                LIMIT 1
+            "#,
+            Uuid::from(user_session_id),
+            at,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(authentication) = authentication else {
+            return Ok(None);
+        };
+
+        let authentication = Authentication::try_from(authentication)?;
+        Ok(Some(authentication))
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.get_last_authentication_batch",
+        skip_all,
+        fields(
+            db.query.text,
+        ),
+        err,
+    )]
+    async fn get_last_authentication_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error> {
+        let ids: Vec<Uuid> = ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query!(
+            r#"
+                SELECT DISTINCT ON (user_session_id)
+                       user_session_id
+                     , user_session_authentication_id
+                     , created_at
+                     , user_password_id
+                     , upstream_oauth_authorization_session_id
+                FROM user_session_authentications
+                WHERE user_session_id = ANY($1::uuid[])
+                ORDER BY user_session_id, created_at DESC
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| {
+                Authentication::try_from(AuthenticationLookup {
+                    user_session_authentication_id: r.user_session_authentication_id,
+                    created_at: r.created_at,
+                    user_password_id: r.user_password_id,
+                    upstream_oauth_authorization_session_id: r
+                        .upstream_oauth_authorization_session_id,
+                })
+                .map(|authentication| (r.user_session_id.into(), authentication))
+                .map_err(DatabaseError::from)
+            })
+            .collect()
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.record_batch_activity",
         skip_all,
@@ -600,4 +1087,77 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
 
         Ok(())
     }
+
+    #[tracing::instrument(
+        name = "db.browser_session.add_tag",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+            user_session.tag = tag,
+        ),
+        err,
+    )]
+    async fn add_tag(
+        &mut self,
+        mut user_session: BrowserSession,
+        tag: &str,
+    ) -> Result<BrowserSession, Self::Error> {
+        // Do the change in a single statement, so that concurrent changes to the
+        // tags are not lost
+        let tags = sqlx::query_scalar!(
+            r#"
+                UPDATE user_sessions
+                SET tags = CASE
+                    WHEN $2 = ANY(tags) THEN tags
+                    ELSE array_append(tags, $2)
+                END
+                WHERE user_session_id = $1
+                RETURNING tags
+            "#,
+            Uuid::from(user_session.id),
+            tag,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        user_session.tags = tags;
+
+        Ok(user_session)
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.remove_tag",
+        skip_all,
+        fields(
+            db.query.text,
+            %user_session.id,
+            user_session.tag = tag,
+        ),
+        err,
+    )]
+    async fn remove_tag(
+        &mut self,
+        mut user_session: BrowserSession,
+        tag: &str,
+    ) -> Result<BrowserSession, Self::Error> {
+        let tags = sqlx::query_scalar!(
+            r#"
+                UPDATE user_sessions
+                SET tags = array_remove(tags, $2)
+                WHERE user_session_id = $1
+                RETURNING tags
+            "#,
+            Uuid::from(user_session.id),
+            tag,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        user_session.tags = tags;
+
+        Ok(user_session)
+    }
 }
@@ -5,7 +5,8 @@
 // Please see LICENSE in the repository root for full details.
 
 use async_trait::async_trait;
-use mas_data_model::User;
+use chrono::{DateTime, Utc};
+use mas_data_model::{User, UserTermsAcceptance};
 use mas_storage::{user::UserTermsRepository, Clock};
 use rand::RngCore;
 use sqlx::PgConnection;
@@ -13,7 +14,7 @@ use ulid::Ulid;
 use url::Url;
 use uuid::Uuid;
 
-use crate::{tracing::ExecuteExt, DatabaseError};
+use crate::{errors::DatabaseInconsistencyError, tracing::ExecuteExt, DatabaseError};
 
 /// An implementation of [`UserTermsRepository`] for a PostgreSQL connection
 pub struct PgUserTermsRepository<'c> {
@@ -28,6 +29,34 @@ impl<'c> PgUserTermsRepository<'c> {
     }
 }
 
+struct UserTermsLookup {
+    user_terms_id: Uuid,
+    user_id: Uuid,
+    terms_url: String,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<UserTermsLookup> for UserTermsAcceptance {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: UserTermsLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.user_terms_id);
+        let terms_url = value.terms_url.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("user_terms")
+                .column("terms_url")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(UserTermsAcceptance {
+            id,
+            user_id: Ulid::from(value.user_id),
+            terms_url,
+            accepted_at: value.created_at,
+        })
+    }
+}
+
 #[async_trait]
 impl<'c> UserTermsRepository for PgUserTermsRepository<'c> {
     type Error = DatabaseError;
@@ -71,4 +100,74 @@ impl<'c> UserTermsRepository for PgUserTermsRepository<'c> {
 
         Ok(())
     }
+    #[tracing::instrument(
+        name = "db.user_terms.find_acceptance",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+            user_terms.url = terms_url.as_str(),
+        ),
+        err,
+    )]
+    async fn find_acceptance(
+        &mut self,
+        user: &User,
+        terms_url: &Url,
+    ) -> Result<Option<UserTermsAcceptance>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserTermsLookup,
+            r#"
+                SELECT user_terms_id
+                     , user_id
+                     , terms_url
+                     , created_at
+                FROM user_terms
+                WHERE user_id = $1
+                  AND terms_url = $2
+            "#,
+            Uuid::from(user.id),
+            terms_url.as_str(),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_terms.all",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn all(&mut self, user: &User) -> Result<Vec<UserTermsAcceptance>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserTermsLookup,
+            r#"
+                SELECT user_terms_id
+                     , user_id
+                     , terms_url
+                     , created_at
+                FROM user_terms
+                WHERE user_id = $1
+                ORDER BY created_at DESC, user_terms_id DESC
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, _>>()
+            .map_err(Into::into)
+    }
 }
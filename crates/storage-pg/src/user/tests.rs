@@ -4,18 +4,27 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::collections::BTreeSet;
+
 use chrono::Duration;
+use mas_data_model::{
+    BrowserSessionBinding, Device, DigestFrequency, UserAgent, UserNotificationPreferences,
+};
 use mas_storage::{
     clock::MockClock,
+    compat::CompatSessionRepository,
+    pagination::Count,
     user::{
         BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
-        UserFilter, UserPasswordRepository, UserRepository,
+        UserFilter, UserHomeserver, UserPasswordRepository, UserRepository,
     },
-    Pagination, RepositoryAccess,
+    Clock, Pagination, RepositoryAccess,
 };
 use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 use sqlx::PgPool;
+use ulid::Ulid;
+use url::Url;
 
 use crate::PgRepository;
 
@@ -35,10 +44,14 @@ async fn test_user_repo(pool: PgPool) {
     let locked = all.locked_only();
 
     // Initially, the user shouldn't exist
-    assert!(!repo.user().exists(USERNAME).await.unwrap());
+    assert!(!repo
+        .user()
+        .exists(UserHomeserver::Main, USERNAME)
+        .await
+        .unwrap());
     assert!(repo
         .user()
-        .find_by_username(USERNAME)
+        .find_by_username(UserHomeserver::Main, USERNAME)
         .await
         .unwrap()
         .is_none());
@@ -57,10 +70,14 @@ async fn test_user_repo(pool: PgPool) {
         .unwrap();
 
     // And now it should exist
-    assert!(repo.user().exists(USERNAME).await.unwrap());
     assert!(repo
         .user()
-        .find_by_username(USERNAME)
+        .exists(UserHomeserver::Main, USERNAME)
+        .await
+        .unwrap());
+    assert!(repo
+        .user()
+        .find_by_username(UserHomeserver::Main, USERNAME)
         .await
         .unwrap()
         .is_some());
@@ -72,6 +89,16 @@ async fn test_user_repo(pool: PgPool) {
     assert_eq!(repo.user().count(active).await.unwrap(), 1);
     assert_eq!(repo.user().count(locked).await.unwrap(), 0);
 
+    // There are few enough users to count them exactly
+    assert_eq!(
+        repo.user().estimate_count(all).await.unwrap(),
+        Count::Exact(1)
+    );
+    assert_eq!(
+        repo.user().estimate_count(admin).await.unwrap(),
+        Count::Exact(0)
+    );
+
     // Adding a second time should give a conflict
     // It should not poison the transaction though
     assert!(repo
@@ -178,6 +205,197 @@ async fn test_user_repo(pool: PgPool) {
         .unwrap();
     assert_eq!(list.edges.len(), 0);
 
+    // Place the user under legal hold
+    let legal_hold = all.legal_hold_only();
+    let no_legal_hold = all.no_legal_hold_only();
+    assert_eq!(repo.user().count(legal_hold).await.unwrap(), 0);
+    assert_eq!(repo.user().count(no_legal_hold).await.unwrap(), 1);
+
+    let user = repo.user().place_legal_hold(&clock, user).await.unwrap();
+    assert_eq!(user.legal_hold_at, Some(clock.now()));
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_under_legal_hold());
+    assert_eq!(repo.user().count(legal_hold).await.unwrap(), 1);
+    assert_eq!(repo.user().count(no_legal_hold).await.unwrap(), 0);
+
+    // Release the hold
+    let user = repo.user().release_legal_hold(user).await.unwrap();
+    assert!(!user.is_under_legal_hold());
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_under_legal_hold());
+    assert_eq!(repo.user().count(legal_hold).await.unwrap(), 0);
+
+    // Move the user to an additional homeserver
+    let main = all.for_homeserver(UserHomeserver::Main);
+    let other = all.for_homeserver(UserHomeserver::Additional("example.org"));
+    assert_eq!(repo.user().count(main).await.unwrap(), 1);
+    assert_eq!(repo.user().count(other).await.unwrap(), 0);
+
+    let user = repo
+        .user()
+        .set_homeserver(user, Some("example.org".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(user.homeserver.as_deref(), Some("example.org"));
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert_eq!(user.homeserver.as_deref(), Some("example.org"));
+    assert_eq!(repo.user().count(main).await.unwrap(), 0);
+    assert_eq!(repo.user().count(other).await.unwrap(), 1);
+
+    let page = repo
+        .user()
+        .list(other, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].homeserver.as_deref(), Some("example.org"));
+
+    // The username is now free on the main homeserver, and only found on the
+    // additional one
+    let other_homeserver = UserHomeserver::Additional("example.org");
+    assert!(!repo
+        .user()
+        .exists(UserHomeserver::Main, &user.username)
+        .await
+        .unwrap());
+    assert!(repo
+        .user()
+        .exists(other_homeserver, &user.username)
+        .await
+        .unwrap());
+    let found = repo
+        .user()
+        .find_by_username(other_homeserver, &user.username)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, user.id);
+
+    // Turn a new user into a bot owned by the first one
+    let bots = all.bots_only();
+    let humans = all.humans_only();
+    let owned = all.for_bot_owner(&user);
+    assert_eq!(repo.user().count(bots).await.unwrap(), 0);
+    assert_eq!(repo.user().count(humans).await.unwrap(), 1);
+
+    let bot = repo
+        .user()
+        .add(&mut rng, &clock, "robot".to_owned())
+        .await
+        .unwrap();
+    assert!(!bot.is_bot);
+    let bot = repo.user().set_bot(bot, Some(&user)).await.unwrap();
+    assert!(bot.is_bot_owned_by(&user));
+
+    // Check that the property is retrieved on lookup
+    let bot = repo.user().lookup(bot.id).await.unwrap().unwrap();
+    assert!(bot.is_bot);
+    assert_eq!(bot.bot_owner_id, Some(user.id));
+    assert_eq!(repo.user().count(bots).await.unwrap(), 1);
+    assert_eq!(repo.user().count(humans).await.unwrap(), 1);
+
+    let page = repo
+        .user()
+        .list(owned, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].id, bot.id);
+
+    // Schedule the deletion of the user in a day
+    let due = all.deletion_due_at(clock.now());
+    assert_eq!(repo.user().count(due).await.unwrap(), 0);
+    let deletion_scheduled_at = clock.now() + Duration::try_days(1).unwrap();
+    let user = repo
+        .user()
+        .schedule_deletion(user, deletion_scheduled_at)
+        .await
+        .unwrap();
+    assert!(user.is_pending_deletion(clock.now()));
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert_eq!(user.deletion_scheduled_at, Some(deletion_scheduled_at));
+
+    // The deletion is only due once the grace period elapsed
+    assert_eq!(repo.user().count(due).await.unwrap(), 0);
+    clock.advance(Duration::try_days(2).unwrap());
+    assert!(!user.is_pending_deletion(clock.now()));
+    let due = all.deletion_due_at(clock.now());
+    let page = repo.user().list(due, Pagination::first(10)).await.unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].id, user.id);
+
+    let user = repo.user().clear_scheduled_deletion(user).await.unwrap();
+    assert_eq!(user.deletion_scheduled_at, None);
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert_eq!(user.deletion_scheduled_at, None);
+    assert_eq!(repo.user().count(due).await.unwrap(), 0);
+
+    repo.save().await.unwrap();
+}
+
+/// Test that usernames are only unique within a homeserver
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_username_per_homeserver(pool: PgPool) {
+    const USERNAME: &str = "john";
+
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let other_homeserver = UserHomeserver::Additional("example.org");
+
+    let main_user = repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .unwrap();
+    assert_eq!(main_user.homeserver, None);
+
+    // The same username can be used on another homeserver
+    let other_user = repo
+        .user()
+        .add_for_homeserver(&mut rng, &clock, other_homeserver, USERNAME.to_owned())
+        .await
+        .unwrap();
+    assert_eq!(other_user.homeserver.as_deref(), Some("example.org"));
+
+    let found = repo
+        .user()
+        .find_by_username(UserHomeserver::Main, USERNAME)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, main_user.id);
+    let found = repo
+        .user()
+        .find_by_username(other_homeserver, USERNAME)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, other_user.id);
+    assert!(!repo
+        .user()
+        .exists(UserHomeserver::Additional("example.com"), USERNAME)
+        .await
+        .unwrap());
+
+    // But only once on each homeserver
+    assert!(repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .is_err());
+    assert!(repo
+        .user()
+        .add_for_homeserver(&mut rng, &clock, other_homeserver, USERNAME.to_owned())
+        .await
+        .is_err());
+
     repo.save().await.unwrap();
 }
 
@@ -487,6 +705,23 @@ async fn test_user_password_repo(pool: PgPool) {
     assert_eq!(first_password_lookup.hashed_password, FIRST_PASSWORD_HASH);
     assert_eq!(first_password_lookup.version, 1);
     assert_eq!(first_password_lookup.upgraded_from_id, None);
+    assert!(!first_password_lookup.must_change());
+
+    // Flag the password as having to be changed
+    let first_password = repo
+        .user_password()
+        .require_change(&clock, first_password)
+        .await
+        .unwrap();
+    assert!(first_password.must_change());
+
+    let first_password_lookup = repo
+        .user_password()
+        .active(&user)
+        .await
+        .unwrap()
+        .expect("user should have an active password");
+    assert!(first_password_lookup.must_change());
 
     // Getting the last inserted password is based on the clock, so we need to
     // advance it
@@ -520,6 +755,31 @@ async fn test_user_password_repo(pool: PgPool) {
         second_password_lookup.upgraded_from_id,
         Some(first_password.id)
     );
+    // The flag is kept when the password is upgraded
+    assert!(second_password_lookup.must_change());
+
+    clock.advance(Duration::microseconds(10 * 1000 * 1000));
+
+    // But not when the password is changed
+    repo.user_password()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            2,
+            FIRST_PASSWORD_HASH.to_owned(),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let third_password_lookup = repo
+        .user_password()
+        .active(&user)
+        .await
+        .unwrap()
+        .expect("user should have an active password");
+    assert!(!third_password_lookup.must_change());
 
     repo.save().await.unwrap();
 }
@@ -661,57 +921,1127 @@ async fn test_user_session(pool: PgPool) {
     assert_eq!(repo.browser_session().count(finished).await.unwrap(), 11);
 }
 
+/// Test tagging browser sessions, and ending them by tag
 #[sqlx::test(migrator = "crate::MIGRATOR")]
-async fn test_user_terms(pool: PgPool) {
+async fn test_user_session_tags(pool: PgPool) {
     let mut repo = PgRepository::from_pool(&pool).await.unwrap();
     let mut rng = ChaChaRng::seed_from_u64(42);
     let clock = MockClock::default();
 
-    let user = repo
+    let alice = repo
         .user()
-        .add(&mut rng, &clock, "john".to_owned())
+        .add(&mut rng, &clock, "alice".to_owned())
         .await
         .unwrap();
 
-    // Accepting the terms should work
-    repo.user_terms()
-        .accept_terms(
-            &mut rng,
-            &clock,
-            &user,
-            "https://example.com/terms".parse().unwrap(),
-        )
+    let work = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    let personal = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
         .await
         .unwrap();
+    assert!(work.tags.is_empty());
 
-    // Accepting a second time should also work
-    repo.user_terms()
-        .accept_terms(
-            &mut rng,
-            &clock,
-            &user,
-            "https://example.com/terms".parse().unwrap(),
-        )
+    let work = repo.browser_session().add_tag(work, "work").await.unwrap();
+    assert_eq!(work.tags, vec!["work".to_owned()]);
+
+    // Adding the same tag twice doesn't duplicate it
+    let work = repo.browser_session().add_tag(work, "work").await.unwrap();
+    let work = repo
+        .browser_session()
+        .add_tag(work, "shared computer")
         .await
         .unwrap();
+    assert_eq!(
+        work.tags,
+        vec!["work".to_owned(), "shared computer".to_owned()]
+    );
 
-    // Accepting a different terms should also work
-    repo.user_terms()
-        .accept_terms(
-            &mut rng,
-            &clock,
-            &user,
-            "https://example.com/terms?v=2".parse().unwrap(),
-        )
+    // Tags added from a stale copy of the session are not lost
+    let personal_stale = personal.clone();
+    repo.browser_session()
+        .add_tag(personal, "personal")
+        .await
+        .unwrap();
+    let personal = repo
+        .browser_session()
+        .add_tag(personal_stale, "laptop")
         .await
         .unwrap();
+    assert_eq!(
+        personal.tags,
+        vec!["personal".to_owned(), "laptop".to_owned()]
+    );
 
-    let mut conn = repo.into_inner();
+    let lookup = repo
+        .browser_session()
+        .lookup(work.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup.tags, work.tags);
 
-    // We should have two rows, as the first terms was deduped
-    let res: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_terms")
-        .fetch_one(&mut *conn)
+    let tagged_work = BrowserSessionFilter::new()
+        .for_user(&alice)
+        .with_tag("work");
+    assert_eq!(repo.browser_session().count(tagged_work).await.unwrap(), 1);
+    let list = repo
+        .browser_session()
+        .list(tagged_work, Pagination::first(10))
         .await
         .unwrap();
-    assert_eq!(res, 2);
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(list.edges[0].id, work.id);
+
+    let work = repo
+        .browser_session()
+        .remove_tag(work, "shared computer")
+        .await
+        .unwrap();
+    assert_eq!(work.tags, vec!["work".to_owned()]);
+
+    // End the sessions tagged 'work'
+    let affected = repo
+        .browser_session()
+        .finish_bulk(&clock, tagged_work.active_only())
+        .await
+        .unwrap();
+    assert_eq!(affected, 1);
+
+    let active = BrowserSessionFilter::new().for_user(&alice).active_only();
+    let list = repo
+        .browser_session()
+        .list(active, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list.edges.len(), 1);
+    assert_eq!(list.edges[0].id, personal.id);
+}
+
+/// Test binding browser sessions to a credential, and ending the sessions whose
+/// binding expired
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_binding(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    let bound = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    let unbound = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    assert!(bound.binding.is_none());
+
+    let binding = BrowserSessionBinding {
+        credential_id: "credential".to_owned(),
+        public_key: vec![1, 2, 3],
+        sign_count: 0,
+        token: "first".to_owned(),
+        previous_token: None,
+        expires_at: clock.now() + Duration::try_hours(1).unwrap(),
+    };
+    let bound = repo.browser_session().bind(bound, binding).await.unwrap();
+
+    let lookup = repo
+        .browser_session()
+        .lookup(bound.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup.binding, bound.binding);
+    let binding = lookup.binding.unwrap();
+    assert!(binding.accepts(Some("first")));
+    assert!(!binding.accepts(Some("second")));
+    assert!(!binding.accepts(None));
+
+    // Renew the binding, the previous token is still accepted
+    let binding = BrowserSessionBinding {
+        sign_count: 1,
+        token: "second".to_owned(),
+        previous_token: Some(binding.token),
+        expires_at: clock.now() + Duration::try_hours(2).unwrap(),
+        ..binding
+    };
+    let bound = repo.browser_session().bind(bound, binding).await.unwrap();
+    let lookup = repo
+        .browser_session()
+        .lookup(bound.id)
+        .await
+        .unwrap()
+        .unwrap();
+    let binding = lookup.binding.unwrap();
+    assert_eq!(binding.sign_count, 1);
+    assert!(binding.accepts(Some("first")));
+    assert!(binding.accepts(Some("second")));
+
+    // Nothing expired yet
+    let expired = BrowserSessionFilter::new()
+        .active_only()
+        .with_binding_expired_before(clock.now());
+    assert_eq!(repo.browser_session().count(expired).await.unwrap(), 0);
+
+    // Once the binding expires, the bound session is ended, but not the other
+    clock.advance(Duration::try_hours(3).unwrap());
+    let expired = BrowserSessionFilter::new()
+        .active_only()
+        .with_binding_expired_before(clock.now());
+    let affected = repo
+        .browser_session()
+        .finish_bulk(&clock, expired)
+        .await
+        .unwrap();
+    assert_eq!(affected, 1);
+
+    let lookup = repo
+        .browser_session()
+        .lookup(bound.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(lookup.finished_at.is_some());
+    let lookup = repo
+        .browser_session()
+        .lookup(unbound.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(lookup.finished_at.is_none());
+}
+
+/// Test starting impersonation sessions, and listing the ones which expired
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_impersonation(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let admin = repo
+        .user()
+        .add(&mut rng, &clock, "admin".to_owned())
+        .await
+        .unwrap();
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    let admin_session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &admin, None)
+        .await
+        .unwrap();
+    let regular = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    assert!(regular.impersonation.is_none());
+
+    let expires_at = clock.now() + Duration::try_minutes(30).unwrap();
+    let impersonation = repo
+        .browser_session()
+        .add_impersonation(&mut rng, &clock, &alice, &admin_session, expires_at, None)
+        .await
+        .unwrap();
+    assert_eq!(impersonation.user.id, alice.id);
+
+    let lookup = repo
+        .browser_session()
+        .lookup(impersonation.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup, impersonation);
+    let details = lookup.impersonation.unwrap();
+    assert_eq!(details.impersonator_user_id, Some(admin.id));
+    assert_eq!(details.impersonator_session_id, Some(admin_session.id));
+    assert!(details.active(clock.now()));
+
+    // Nothing expired yet
+    let expired = BrowserSessionFilter::new()
+        .active_only()
+        .with_impersonation_expired_before(clock.now());
+    assert_eq!(repo.browser_session().count(expired).await.unwrap(), 0);
+
+    // Once it expires, only the impersonation session is listed
+    clock.advance(Duration::try_hours(1).unwrap());
+    assert!(!details.active(clock.now()));
+    let expired = BrowserSessionFilter::new()
+        .active_only()
+        .with_impersonation_expired_before(clock.now());
+    let page = repo
+        .browser_session()
+        .list(expired, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].id, impersonation.id);
+}
+
+/// Test ending the browser sessions which are too old or were idle for too
+/// long
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_finish_expired(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    let old = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    clock.advance(Duration::try_hours(2).unwrap());
+    let recent = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+
+    // Without any TTL, nothing expires
+    let finished = repo
+        .browser_session()
+        .finish_expired(&clock, None, None, 100)
+        .await
+        .unwrap();
+    assert_eq!(finished, 0);
+
+    // The old session was used recently, so it isn't idle
+    repo.browser_session()
+        .record_batch_activity(vec![(old.id, clock.now(), None)])
+        .await
+        .unwrap();
+    clock.advance(Duration::try_minutes(30).unwrap());
+    let finished = repo
+        .browser_session()
+        .finish_expired(&clock, None, Some(Duration::try_hours(1).unwrap()), 100)
+        .await
+        .unwrap();
+    assert_eq!(finished, 0);
+
+    // But it is older than 2 hours
+    let finished = repo
+        .browser_session()
+        .finish_expired(&clock, Some(Duration::try_hours(2).unwrap()), None, 100)
+        .await
+        .unwrap();
+    assert_eq!(finished, 1);
+    let old = repo
+        .browser_session()
+        .lookup(old.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(old.finished_at.is_some());
+
+    // Once the recent session is idle for too long, it expires too
+    clock.advance(Duration::try_hours(1).unwrap());
+    let finished = repo
+        .browser_session()
+        .finish_expired(&clock, None, Some(Duration::try_hours(1).unwrap()), 100)
+        .await
+        .unwrap();
+    assert_eq!(finished, 1);
+    let recent = repo
+        .browser_session()
+        .lookup(recent.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(recent.finished_at.is_some());
+}
+
+/// Test the filters used to find the users who didn't use their account for a
+/// long time
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_inactivity(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &bob, None)
+        .await
+        .unwrap();
+
+    clock.advance(Duration::try_days(10).unwrap());
+    let since = clock.now() - Duration::try_days(5).unwrap();
+
+    // Alice never logged in, and Bob still has an active session
+    let inactive = UserFilter::new().inactive_since(since);
+    let used = UserFilter::new().used_since(since);
+    assert_eq!(repo.user().count(inactive).await.unwrap(), 1);
+    assert_eq!(repo.user().count(used).await.unwrap(), 1);
+
+    // Once his session is finished, Bob is inactive too
+    repo.browser_session()
+        .finish(&clock, session)
+        .await
+        .unwrap();
+    clock.advance(Duration::try_days(10).unwrap());
+    let since = clock.now() - Duration::try_days(5).unwrap();
+    let inactive = UserFilter::new().inactive_since(since);
+    assert_eq!(repo.user().count(inactive).await.unwrap(), 2);
+
+    // Unless he is excluded
+    let excluded = ["bob".to_owned()];
+    let inactive = inactive.excluding_usernames(&excluded);
+    assert_eq!(repo.user().count(inactive).await.unwrap(), 1);
+
+    // Warn alice
+    let warned = UserFilter::new().inactivity_warned_only();
+    let not_warned = UserFilter::new().not_inactivity_warned_only();
+    assert_eq!(repo.user().count(warned).await.unwrap(), 0);
+    repo.user()
+        .mark_inactivity_warned(&clock, &alice)
+        .await
+        .unwrap();
+    assert_eq!(repo.user().count(warned).await.unwrap(), 1);
+    assert_eq!(repo.user().count(not_warned).await.unwrap(), 1);
+
+    // The warning is only old enough after some time
+    let warned_before = warned.inactivity_warned_before(clock.now());
+    assert_eq!(repo.user().count(warned_before).await.unwrap(), 0);
+    clock.advance(Duration::try_days(1).unwrap());
+    let warned_before = warned.inactivity_warned_before(clock.now());
+    let page = repo
+        .user()
+        .list(warned_before, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].id, alice.id);
+
+    // Logging in makes alice active again
+    repo.browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+    let since = clock.now() - Duration::try_days(5).unwrap();
+    let used = warned.used_since(since);
+    assert_eq!(repo.user().count(used).await.unwrap(), 1);
+
+    repo.user().clear_inactivity_warning(&alice).await.unwrap();
+    assert_eq!(repo.user().count(warned).await.unwrap(), 0);
+}
+
+/// Test flagging guest accounts, and finding the ones which are not used
+/// anymore
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_guests(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let guests = UserFilter::new().guests_only();
+    let non_guests = UserFilter::new().non_guests_only();
+
+    repo.user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let first = repo
+        .user()
+        .add(&mut rng, &clock, "guest-first".to_owned())
+        .await
+        .unwrap();
+    let first = repo.user().set_guest(first).await.unwrap();
+    assert!(first.is_guest);
+    let second = repo
+        .user()
+        .add(&mut rng, &clock, "guest-second".to_owned())
+        .await
+        .unwrap();
+    let second = repo.user().set_guest(second).await.unwrap();
+
+    // Check that the property is retrieved on lookup
+    let first = repo.user().lookup(first.id).await.unwrap().unwrap();
+    assert!(first.is_guest);
+    assert_eq!(repo.user().count(guests).await.unwrap(), 2);
+    assert_eq!(repo.user().count(non_guests).await.unwrap(), 1);
+
+    // Both guests get a session which never ends
+    let device = Device::generate(&mut rng);
+    let first_session = repo
+        .compat_session()
+        .add(&mut rng, &clock, &first, device, None, false)
+        .await
+        .unwrap();
+    let device = Device::generate(&mut rng);
+    repo.compat_session()
+        .add(&mut rng, &clock, &second, device, None, false)
+        .await
+        .unwrap();
+
+    // Only the first guest keeps using their session
+    clock.advance(Duration::try_days(10).unwrap());
+    repo.compat_session()
+        .record_batch_activity(vec![(first_session.id, clock.now(), None)])
+        .await
+        .unwrap();
+
+    // Active sessions don't prevent guests from being idle
+    let since = clock.now() - Duration::try_days(5).unwrap();
+    let idle = guests.idle_since(since);
+    assert_eq!(
+        repo.user()
+            .count(guests.inactive_since(since))
+            .await
+            .unwrap(),
+        0
+    );
+    let page = repo.user().list(idle, Pagination::first(10)).await.unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].id, second.id);
+}
+
+/// Test loading users, browser sessions and their last authentication in
+/// batches
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_batch_lookups(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    // Unknown IDs are left out of the results
+    let unknown = Ulid::from_datetime_with_source(clock.now().into(), &mut rng);
+    let users = repo
+        .user()
+        .load_batch(BTreeSet::from([alice.id, bob.id, unknown]))
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[&alice.id], alice);
+    assert_eq!(users[&bob.id], bob);
+
+    let alice_session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None)
+        .await
+        .unwrap();
+
+    let bob_session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &bob, None)
+        .await
+        .unwrap();
+
+    let sessions = repo
+        .browser_session()
+        .load_batch(BTreeSet::from([alice_session.id, bob_session.id, unknown]))
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[&alice_session.id], alice_session);
+    assert_eq!(sessions[&bob_session.id], bob_session);
+
+    // Authenticate alice's session twice, the last one should be returned
+    let password = repo
+        .user_password()
+        .add(&mut rng, &clock, &alice, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+    let first_authentication = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &clock, &alice_session, &password)
+        .await
+        .unwrap();
+    let first_authenticated_at = clock.now();
+    clock.advance(Duration::try_minutes(1).unwrap());
+    let last_authentication = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &clock, &alice_session, &password)
+        .await
+        .unwrap();
+
+    // Bob's session never authenticated, so it is not in the results
+    let authentications = repo
+        .browser_session()
+        .get_last_authentication_batch(BTreeSet::from([alice_session.id, bob_session.id]))
+        .await
+        .unwrap();
+    assert_eq!(authentications.len(), 1);
+    assert_eq!(authentications[&alice_session.id], last_authentication);
+
+    // Looking at the authentication at a given time ignores the later ones
+    let authentication = repo
+        .browser_session()
+        .get_authentication_at(alice_session.id, first_authenticated_at)
+        .await
+        .unwrap();
+    assert_eq!(authentication, Some(first_authentication));
+
+    let authentication = repo
+        .browser_session()
+        .get_authentication_at(
+            alice_session.id,
+            first_authenticated_at - Duration::try_seconds(1).unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(authentication, None);
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_terms(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let terms_url: Url = "https://example.com/terms".parse().unwrap();
+    let new_terms_url: Url = "https://example.com/terms?v=2".parse().unwrap();
+
+    // The user didn't accept any terms yet
+    assert!(repo
+        .user_terms()
+        .find_acceptance(&user, &terms_url)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(repo.user_terms().all(&user).await.unwrap().is_empty());
+
+    // Accepting the terms should work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Accepting a second time should also work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let acceptance = repo
+        .user_terms()
+        .find_acceptance(&user, &terms_url)
+        .await
+        .unwrap()
+        .expect("the terms should have been accepted");
+    assert_eq!(acceptance.user_id, user.id);
+    assert_eq!(acceptance.terms_url, terms_url);
+    assert_eq!(acceptance.accepted_at, clock.now());
+
+    // The new version of the terms isn't accepted yet
+    assert!(repo
+        .user_terms()
+        .find_acceptance(&user, &new_terms_url)
+        .await
+        .unwrap()
+        .is_none());
+
+    clock.advance(Duration::try_minutes(1).unwrap());
+
+    // Accepting a different terms should also work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms?v=2".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // The most recent acceptance is listed first
+    let acceptances = repo.user_terms().all(&user).await.unwrap();
+    assert_eq!(acceptances.len(), 2);
+    assert_eq!(acceptances[0].terms_url, new_terms_url);
+    assert_eq!(acceptances[1].terms_url, terms_url);
+
+    let mut conn = repo.into_inner();
+
+    // We should have two rows, as the first terms was deduped
+    let res: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_terms")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    assert_eq!(res, 2);
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_emergency_access(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    // Unknown tokens should not be found
+    assert!(repo
+        .user_emergency_access()
+        .find_by_token("unknown")
+        .await
+        .unwrap()
+        .is_none());
+
+    let token = repo
+        .user_emergency_access()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            "sometoken".to_owned(),
+            "192.0.2.0/24".parse().unwrap(),
+            Duration::try_minutes(10).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert!(token.active(clock.now()));
+    assert!(token.is_allowed_from("192.0.2.42".parse().unwrap()));
+    assert!(!token.is_allowed_from("198.51.100.1".parse().unwrap()));
+
+    // Lookup the token, by ID and by token
+    let lookup = repo
+        .user_emergency_access()
+        .lookup(token.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup, token);
+
+    let lookup = repo
+        .user_emergency_access()
+        .find_by_token("sometoken")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup, token);
+
+    // The token expires after 10 minutes
+    clock.advance(Duration::try_minutes(11).unwrap());
+    assert!(!token.active(clock.now()));
+
+    // Consume the token
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None)
+        .await
+        .unwrap();
+
+    let token = repo
+        .user_emergency_access()
+        .consume(&clock, token, &session)
+        .await
+        .unwrap();
+    assert!(!token.active(clock.now()));
+    assert_eq!(token.user_session_id, Some(session.id));
+
+    let lookup = repo
+        .user_emergency_access()
+        .lookup(token.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup, token);
+
+    // Consuming it again should fail
+    assert!(repo
+        .user_emergency_access()
+        .consume(&clock, token, &session)
+        .await
+        .is_err());
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_activity_digest(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    assert!(repo
+        .user_activity_digest()
+        .find_by_user(&alice)
+        .await
+        .unwrap()
+        .is_none());
+
+    let digest = repo
+        .user_activity_digest()
+        .subscribe(&mut rng, &clock, &alice, "en".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(digest.user_id, alice.id);
+    assert_eq!(digest.period_start(), clock.now());
+
+    // Subscribing again only updates the locale
+    clock.advance(Duration::try_hours(1).unwrap());
+    let digest = repo
+        .user_activity_digest()
+        .subscribe(&mut rng, &clock, &alice, "fr".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(digest.locale, "fr");
+    assert!(digest.created_at < clock.now());
+
+    let lookup = repo
+        .user_activity_digest()
+        .find_by_user(&alice)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup, digest);
+
+    repo.user_activity_digest()
+        .subscribe(&mut rng, &clock, &bob, "en".to_owned())
+        .await
+        .unwrap();
+
+    // Only alice's digest is due, as bob subscribed later
+    let due = repo
+        .user_activity_digest()
+        .list_due(clock.now(), Duration::try_minutes(30).unwrap(), 10)
+        .await
+        .unwrap();
+    assert_eq!(due, vec![digest.clone()]);
+
+    // Once sent, a new period starts
+    let digest = repo
+        .user_activity_digest()
+        .mark_sent(&clock, digest)
+        .await
+        .unwrap();
+    assert_eq!(digest.period_start(), clock.now());
+
+    let due = repo
+        .user_activity_digest()
+        .list_due(clock.now(), Duration::zero(), 10)
+        .await
+        .unwrap();
+    assert_eq!(due.len(), 2);
+
+    // Bob only wants a digest every week
+    repo.user_notification_preferences()
+        .set(
+            &clock,
+            &bob,
+            UserNotificationPreferences {
+                digest_frequency: DigestFrequency::Weekly,
+                ..UserNotificationPreferences::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let due = repo
+        .user_activity_digest()
+        .list_due(clock.now(), Duration::zero(), 10)
+        .await
+        .unwrap();
+    assert_eq!(due, vec![digest.clone()]);
+
+    clock.advance(Duration::try_weeks(1).unwrap());
+    let due = repo
+        .user_activity_digest()
+        .list_due(clock.now(), Duration::zero(), 10)
+        .await
+        .unwrap();
+    assert_eq!(due.len(), 2);
+
+    // Unsubscribing removes the subscription
+    repo.user_activity_digest()
+        .unsubscribe(&alice)
+        .await
+        .unwrap();
+    assert!(repo
+        .user_activity_digest()
+        .find_by_user(&alice)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_known_device(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    assert_eq!(repo.user_known_device().count(&alice).await.unwrap(), 0);
+
+    // The first time a device is seen, it is new
+    assert!(repo
+        .user_known_device()
+        .remember(&mut rng, &clock, &alice, "Firefox/Linux/pc")
+        .await
+        .unwrap());
+    assert_eq!(repo.user_known_device().count(&alice).await.unwrap(), 1);
+
+    // Seeing it again doesn't add it twice
+    clock.advance(Duration::try_hours(1).unwrap());
+    assert!(!repo
+        .user_known_device()
+        .remember(&mut rng, &clock, &alice, "Firefox/Linux/pc")
+        .await
+        .unwrap());
+    assert_eq!(repo.user_known_device().count(&alice).await.unwrap(), 1);
+
+    // Devices are remembered per user
+    assert!(repo
+        .user_known_device()
+        .remember(&mut rng, &clock, &bob, "Firefox/Linux/pc")
+        .await
+        .unwrap());
+    assert!(repo
+        .user_known_device()
+        .remember(&mut rng, &clock, &alice, "Element X/iOS/mobile")
+        .await
+        .unwrap());
+    assert_eq!(repo.user_known_device().count(&alice).await.unwrap(), 2);
+    assert_eq!(repo.user_known_device().count(&bob).await.unwrap(), 1);
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_notification_preferences(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+
+    // Users start with the default preferences
+    let preferences = repo
+        .user_notification_preferences()
+        .get(&alice)
+        .await
+        .unwrap();
+    assert_eq!(preferences, UserNotificationPreferences::default());
+
+    let preferences = UserNotificationPreferences {
+        new_login_alerts: false,
+        digest_frequency: DigestFrequency::Monthly,
+        security_only: true,
+    };
+    repo.user_notification_preferences()
+        .set(&clock, &alice, preferences)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user_notification_preferences()
+            .get(&alice)
+            .await
+            .unwrap(),
+        preferences
+    );
+
+    // Saving them again replaces them
+    let preferences = UserNotificationPreferences {
+        security_only: false,
+        ..preferences
+    };
+    repo.user_notification_preferences()
+        .set(&clock, &alice, preferences)
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.user_notification_preferences()
+            .get(&alice)
+            .await
+            .unwrap(),
+        preferences
+    );
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_trusted_browser(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    assert!(repo
+        .user_trusted_browser()
+        .list_active(&clock, &alice)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let user_agent = UserAgent::parse(
+        "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0".to_owned(),
+    );
+    let laptop = repo
+        .user_trusted_browser()
+        .add(
+            &mut rng,
+            &clock,
+            &alice,
+            Some(user_agent.clone()),
+            Duration::try_days(30).unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(laptop.active(clock.now()));
+    assert!(laptop.matches(Some(&user_agent)));
+    assert!(!laptop.matches(None));
+    assert_eq!(
+        repo.user_trusted_browser()
+            .lookup(laptop.id)
+            .await
+            .unwrap()
+            .as_ref(),
+        Some(&laptop)
+    );
+
+    clock.advance(Duration::try_days(1).unwrap());
+    let phone = repo
+        .user_trusted_browser()
+        .add(
+            &mut rng,
+            &clock,
+            &alice,
+            None,
+            Duration::try_days(7).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Most recently trusted first, and only for that user
+    let active = repo
+        .user_trusted_browser()
+        .list_active(&clock, &alice)
+        .await
+        .unwrap();
+    assert_eq!(active, vec![phone.clone(), laptop.clone()]);
+    assert!(repo
+        .user_trusted_browser()
+        .list_active(&clock, &bob)
+        .await
+        .unwrap()
+        .is_empty());
+
+    clock.advance(Duration::try_hours(1).unwrap());
+    let laptop = repo
+        .user_trusted_browser()
+        .touch(&clock, laptop)
+        .await
+        .unwrap();
+    assert_eq!(laptop.last_used_at, clock.now());
+
+    let laptop = repo
+        .user_trusted_browser()
+        .revoke(&clock, laptop)
+        .await
+        .unwrap();
+    assert!(!laptop.active(clock.now()));
+    assert_eq!(
+        repo.user_trusted_browser()
+            .lookup(laptop.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .revoked_at,
+        Some(clock.now())
+    );
+
+    // Expired trusts are not listed either
+    clock.advance(Duration::try_days(7).unwrap());
+    assert!(repo
+        .user_trusted_browser()
+        .list_active(&clock, &alice)
+        .await
+        .unwrap()
+        .is_empty());
 }
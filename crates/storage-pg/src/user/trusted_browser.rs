@@ -0,0 +1,260 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{User, UserAgent, UserTrustedBrowser};
+use mas_storage::{user::UserTrustedBrowserRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`UserTrustedBrowserRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserTrustedBrowserRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserTrustedBrowserRepository<'c> {
+    /// Create a new [`PgUserTrustedBrowserRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserTrustedBrowserRow {
+    user_trusted_browser_id: Uuid,
+    user_id: Uuid,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserTrustedBrowserRow> for UserTrustedBrowser {
+    fn from(row: UserTrustedBrowserRow) -> Self {
+        Self {
+            id: row.user_trusted_browser_id.into(),
+            user_id: row.user_id.into(),
+            user_agent: row.user_agent.map(UserAgent::parse),
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            expires_at: row.expires_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> UserTrustedBrowserRepository for PgUserTrustedBrowserRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_trusted_browser.lookup",
+        skip_all,
+        fields(
+            db.query.text,
+            user_trusted_browser.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserTrustedBrowser>, Self::Error> {
+        let row = sqlx::query_as!(
+            UserTrustedBrowserRow,
+            r#"
+                SELECT user_trusted_browser_id
+                     , user_id
+                     , user_agent
+                     , created_at
+                     , last_used_at
+                     , expires_at
+                     , revoked_at
+                FROM user_trusted_browsers
+                WHERE user_trusted_browser_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(row.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_trusted_browser.list_active",
+        skip_all,
+        fields(
+            db.query.text,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<Vec<UserTrustedBrowser>, Self::Error> {
+        let rows = sqlx::query_as!(
+            UserTrustedBrowserRow,
+            r#"
+                SELECT user_trusted_browser_id
+                     , user_id
+                     , user_agent
+                     , created_at
+                     , last_used_at
+                     , expires_at
+                     , revoked_at
+                FROM user_trusted_browsers
+                WHERE user_id = $1
+                  AND revoked_at IS NULL
+                  AND expires_at > $2
+                ORDER BY user_trusted_browser_id DESC
+            "#,
+            Uuid::from(user.id),
+            clock.now(),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_trusted_browser.add",
+        skip_all,
+        fields(
+            db.query.text,
+            user_trusted_browser.id,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        user_agent: Option<UserAgent>,
+        expires_after: Duration,
+    ) -> Result<UserTrustedBrowser, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + expires_after;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_trusted_browser.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_trusted_browsers (
+                      user_trusted_browser_id
+                    , user_id
+                    , user_agent
+                    , created_at
+                    , last_used_at
+                    , expires_at
+                )
+                VALUES ($1, $2, $3, $4, $4, $5)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            user_agent.as_deref(),
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(UserTrustedBrowser {
+            id,
+            user_id: user.id,
+            user_agent,
+            created_at,
+            last_used_at: created_at,
+            expires_at,
+            revoked_at: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_trusted_browser.touch",
+        skip_all,
+        fields(
+            db.query.text,
+            %trusted_browser.id,
+            user.id = %trusted_browser.user_id,
+        ),
+        err,
+    )]
+    async fn touch(
+        &mut self,
+        clock: &dyn Clock,
+        mut trusted_browser: UserTrustedBrowser,
+    ) -> Result<UserTrustedBrowser, Self::Error> {
+        let last_used_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_trusted_browsers
+                SET last_used_at = $2
+                WHERE user_trusted_browser_id = $1
+            "#,
+            Uuid::from(trusted_browser.id),
+            last_used_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        trusted_browser.last_used_at = last_used_at;
+
+        Ok(trusted_browser)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_trusted_browser.revoke",
+        skip_all,
+        fields(
+            db.query.text,
+            %trusted_browser.id,
+            user.id = %trusted_browser.user_id,
+        ),
+        err,
+    )]
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        mut trusted_browser: UserTrustedBrowser,
+    ) -> Result<UserTrustedBrowser, Self::Error> {
+        let revoked_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_trusted_browsers
+                SET revoked_at = $2
+                WHERE user_trusted_browser_id = $1
+            "#,
+            Uuid::from(trusted_browser.id),
+            revoked_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        trusted_browser.revoked_at = Some(revoked_at);
+
+        Ok(trusted_browser)
+    }
+}
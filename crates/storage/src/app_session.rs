@@ -54,6 +54,7 @@ pub struct AppSessionFilter<'a> {
     device_id: Option<&'a Device>,
     last_active_before: Option<DateTime<Utc>>,
     last_active_after: Option<DateTime<Utc>>,
+    tag: Option<&'a str>,
 }
 
 impl<'a> AppSessionFilter<'a> {
@@ -151,6 +152,21 @@ impl<'a> AppSessionFilter<'a> {
     pub fn state(&self) -> Option<AppSessionState> {
         self.state
     }
+
+    /// Only return sessions with the given tag
+    #[must_use]
+    pub fn with_tag(mut self, tag: &'a str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Get the tag filter
+    ///
+    /// Returns [`None`] if no tag filter was set
+    #[must_use]
+    pub fn tag(&self) -> Option<&'a str> {
+        self.tag
+    }
 }
 
 /// A [`AppSessionRepository`] helps interacting with both [`CompatSession`] and
@@ -188,6 +204,26 @@ pub trait AppSessionRepository: Send + Sync {
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn count(&mut self, filter: AppSessionFilter<'_>) -> Result<usize, Self::Error>;
+
+    /// Find the [`AppSession`] matching the given filter which was the least
+    /// recently active
+    ///
+    /// Sessions which were never active are considered last active when they
+    /// were created.
+    ///
+    /// Returns `None` if no session matches the filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter to apply
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_least_recently_active(
+        &mut self,
+        filter: AppSessionFilter<'_>,
+    ) -> Result<Option<AppSession>, Self::Error>;
 }
 
 repository_impl!(AppSessionRepository:
@@ -198,4 +234,9 @@ repository_impl!(AppSessionRepository:
     ) -> Result<Page<AppSession>, Self::Error>;
 
     async fn count(&mut self, filter: AppSessionFilter<'_>) -> Result<usize, Self::Error>;
+
+    async fn find_least_recently_active(
+        &mut self,
+        filter: AppSessionFilter<'_>,
+    ) -> Result<Option<AppSession>, Self::Error>;
 );
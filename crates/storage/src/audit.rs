@@ -0,0 +1,249 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to interact with the audit log
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{AuditEvent, AuditEventKind, User};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::Count, repository_impl, Clock, Page, Pagination};
+
+/// Filter parameters for listing audit events
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct AuditEventFilter<'a> {
+    user: Option<&'a User>,
+    actor: Option<&'a User>,
+    kind: Option<AuditEventKind>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl<'a> AuditEventFilter<'a> {
+    /// Create a new [`AuditEventFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// List events about the given user
+    #[must_use]
+    pub fn for_user(mut self, user: &'a User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Get the user filter
+    #[must_use]
+    pub fn user(&self) -> Option<&User> {
+        self.user
+    }
+
+    /// List events performed by the given user
+    #[must_use]
+    pub fn for_actor(mut self, actor: &'a User) -> Self {
+        self.actor = Some(actor);
+        self
+    }
+
+    /// Get the actor filter
+    #[must_use]
+    pub fn actor(&self) -> Option<&User> {
+        self.actor
+    }
+
+    /// Only list events of the given kind
+    #[must_use]
+    pub fn with_kind(mut self, kind: AuditEventKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Get the kind filter
+    #[must_use]
+    pub fn kind(&self) -> Option<AuditEventKind> {
+        self.kind
+    }
+
+    /// Only list events recorded at or after the given time
+    #[must_use]
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Get the lower time bound
+    #[must_use]
+    pub fn since_bound(&self) -> Option<DateTime<Utc>> {
+        self.since
+    }
+
+    /// Only list events recorded before the given time
+    #[must_use]
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Get the upper time bound
+    #[must_use]
+    pub fn until_bound(&self) -> Option<DateTime<Utc>> {
+        self.until
+    }
+}
+
+/// An [`AuditEventRepository`] helps interacting with [`AuditEvent`] saved in
+/// the storage backend
+#[async_trait]
+pub trait AuditEventRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup an [`AuditEvent`] by its ID
+    ///
+    /// Returns `None` if no [`AuditEvent`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`AuditEvent`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<AuditEvent>, Self::Error>;
+
+    /// Record a new [`AuditEvent`]
+    ///
+    /// The event is chained to the last recorded event. Recording events is
+    /// serialized, so that the chain stays consistent.
+    ///
+    /// Returns the newly recorded [`AuditEvent`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `kind`: The kind of event
+    /// * `actor_user_id`: The ID of the user who performed the action, if any
+    /// * `user_id`: The ID of the user the action was performed on, if any
+    /// * `ip_address`: The IP address of the requester, if known
+    /// * `user_agent`: The user agent of the requester, if known
+    /// * `details`: Additional details about the event
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    #[allow(clippy::too_many_arguments)]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: AuditEventKind,
+        actor_user_id: Option<Ulid>,
+        user_id: Option<Ulid>,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+        details: serde_json::Value,
+    ) -> Result<AuditEvent, Self::Error>;
+
+    /// List [`AuditEvent`] with the given filter and pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: AuditEventFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<AuditEvent>, Self::Error>;
+
+    /// Count the [`AuditEvent`] with the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, filter: AuditEventFilter<'_>) -> Result<usize, Self::Error>;
+
+    /// Count the [`AuditEvent`] with the given filter, falling back to an
+    /// estimation if there are too many of them to count them exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(&mut self, filter: AuditEventFilter<'_>) -> Result<Count, Self::Error>;
+
+    /// Verify the integrity of the whole audit log
+    ///
+    /// Returns the ID of the first event which was tampered with, or which
+    /// doesn't follow the previous event in the chain, or `None` if the chain
+    /// is intact.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn verify_chain(&mut self) -> Result<Option<Ulid>, Self::Error>;
+
+    /// Delete the events recorded before the given time
+    ///
+    /// Events about users under legal hold are kept, as well as all the events
+    /// recorded after them, so that the chain stays intact.
+    ///
+    /// Returns the number of events deleted
+    ///
+    /// # Parameters
+    ///
+    /// * `before`: Events recorded before this time are deleted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(AuditEventRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<AuditEvent>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: AuditEventKind,
+        actor_user_id: Option<Ulid>,
+        user_id: Option<Ulid>,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+        details: serde_json::Value,
+    ) -> Result<AuditEvent, Self::Error>;
+
+    async fn list(
+        &mut self,
+        filter: AuditEventFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<AuditEvent>, Self::Error>;
+
+    async fn count(&mut self, filter: AuditEventFilter<'_>) -> Result<usize, Self::Error>;
+    async fn estimate_count(&mut self, filter: AuditEventFilter<'_>) -> Result<Count, Self::Error>;
+
+    async fn verify_chain(&mut self) -> Result<Option<Ulid>, Self::Error>;
+
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+);
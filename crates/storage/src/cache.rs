@@ -0,0 +1,695 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! An in-process cache for hot, slow-changing lookups
+//!
+//! The [`RepositoryCache`] is shared across requests, and the `Cached*`
+//! repository wrappers use it to avoid hitting the storage backend on every
+//! OAuth 2.0 client or access token lookup. Entries are evicted after a TTL,
+//! and explicitly invalidated when they are changed through a wrapped
+//! repository, once the change is committed.
+//!
+//! Changes made by other instances or by other tools are only picked up once
+//! the TTL expires, so the TTLs should be kept short.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    hash::Hash,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    AccessToken, Client, ClientConsent, CompatAccessToken, CompatSession, Session, User,
+};
+use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use mas_jose::jwk::PublicJsonWebKeySet;
+use oauth2_types::{oidc::ApplicationType, requests::GrantType, scope::Scope};
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{
+    compat::CompatAccessTokenRepository,
+    oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository},
+    BoxClock, Clock,
+};
+
+/// A cache whose entries expire after a fixed TTL, as measured by a [`Clock`]
+struct TtlCache<K, V> {
+    ttl: chrono::Duration,
+    entries: Option<moka::sync::Cache<K, (DateTime<Utc>, V)>>,
+
+    /// Bumped on every invalidation, so that a lookup which raced with one
+    /// doesn't put a stale entry back in the cache
+    generation: RwLock<u64>,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn new(ttl: Duration, max_entries: usize) -> Self {
+        let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+        let entries = (ttl > chrono::Duration::zero() && max_entries > 0)
+            .then(|| moka::sync::Cache::new(max_entries as u64));
+
+        Self {
+            ttl,
+            entries,
+            generation: RwLock::new(0),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        *self
+            .generation
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get<Q>(&self, clock: &dyn Clock, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let entries = self.entries.as_ref()?;
+        let (expires_at, value) = entries.get(key)?;
+        if expires_at > clock.now() {
+            Some(value)
+        } else {
+            entries.invalidate(key);
+            None
+        }
+    }
+
+    /// Insert an entry, unless something was invalidated since `generation`
+    /// was read
+    fn insert(&self, clock: &dyn Clock, generation: u64, key: K, value: V) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        let current = self
+            .generation
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *current == generation {
+            entries.insert(key, (clock.now() + self.ttl, value));
+        }
+    }
+
+    fn remove<Q>(&self, key: &Q)
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let mut generation = self
+            .generation
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *generation += 1;
+        if let Some(entries) = &self.entries {
+            entries.invalidate(key);
+        }
+    }
+
+    fn clear(&self) {
+        let mut generation = self
+            .generation
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *generation += 1;
+        if let Some(entries) = &self.entries {
+            entries.invalidate_all();
+        }
+    }
+}
+
+struct Inner {
+    clock: BoxClock,
+    clients: TtlCache<Ulid, Client>,
+    oauth2_access_tokens: TtlCache<String, AccessToken>,
+    compat_access_tokens: TtlCache<String, CompatAccessToken>,
+}
+
+/// A cache shared across repositories, for records which rarely change and
+/// are looked up on almost every request
+#[derive(Clone)]
+pub struct RepositoryCache {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for RepositoryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RepositoryCache").finish_non_exhaustive()
+    }
+}
+
+impl RepositoryCache {
+    /// Create a new [`RepositoryCache`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to expire entries
+    /// * `max_entries`: The maximum number of entries to keep for each kind of
+    ///   record
+    /// * `client_ttl`: How long OAuth 2.0 clients are kept in the cache
+    /// * `access_token_ttl`: How long OAuth 2.0 and compatibility access tokens
+    ///   are kept in the cache
+    #[must_use]
+    pub fn new(
+        clock: BoxClock,
+        max_entries: usize,
+        client_ttl: Duration,
+        access_token_ttl: Duration,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                clock,
+                clients: TtlCache::new(client_ttl, max_entries),
+                oauth2_access_tokens: TtlCache::new(access_token_ttl, max_entries),
+                compat_access_tokens: TtlCache::new(access_token_ttl, max_entries),
+            }),
+        }
+    }
+
+    /// Start tracking the changes made by a single repository transaction
+    #[must_use]
+    pub fn transaction(&self) -> CacheTransaction {
+        CacheTransaction {
+            cache: self.clone(),
+            pending: Arc::default(),
+        }
+    }
+
+    /// Invalidate a cached OAuth 2.0 client
+    pub fn invalidate_client(&self, id: Ulid) {
+        self.inner.clients.remove(&id);
+    }
+
+    /// Invalidate a cached OAuth 2.0 access token
+    pub fn invalidate_oauth2_access_token(&self, access_token: &str) {
+        self.inner.oauth2_access_tokens.remove(access_token);
+    }
+
+    /// Invalidate a cached compatibility access token
+    pub fn invalidate_compat_access_token(&self, access_token: &str) {
+        self.inner.compat_access_tokens.remove(access_token);
+    }
+
+    /// Invalidate everything in the cache
+    pub fn clear(&self) {
+        self.inner.clients.clear();
+        self.inner.oauth2_access_tokens.clear();
+        self.inner.compat_access_tokens.clear();
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    clients: HashSet<Ulid>,
+    oauth2_access_tokens: HashSet<String>,
+    compat_access_tokens: HashSet<String>,
+}
+
+/// A view of a [`RepositoryCache`] for a single repository transaction
+///
+/// Records changed through the `Cached*` wrappers are bypassed for the rest
+/// of the transaction, and only invalidated in the shared cache once
+/// [`CacheTransaction::commit`] is called. Invalidating them earlier would let
+/// a concurrent lookup put the old record back in the cache before the change
+/// is visible.
+#[derive(Clone)]
+pub struct CacheTransaction {
+    cache: RepositoryCache,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl std::fmt::Debug for CacheTransaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheTransaction").finish_non_exhaustive()
+    }
+}
+
+impl CacheTransaction {
+    fn pending(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Apply the invalidations recorded during the transaction, once it has
+    /// been committed
+    pub fn commit(self) {
+        let pending = std::mem::take(&mut *self.pending());
+        for id in pending.clients {
+            self.cache.invalidate_client(id);
+        }
+        for access_token in pending.oauth2_access_tokens {
+            self.cache.invalidate_oauth2_access_token(&access_token);
+        }
+        for access_token in pending.compat_access_tokens {
+            self.cache.invalidate_compat_access_token(&access_token);
+        }
+    }
+}
+
+/// An [`OAuth2ClientRepository`] which caches client lookups in a
+/// [`RepositoryCache`]
+pub struct CachedOAuth2ClientRepository<R> {
+    inner: R,
+    cache: CacheTransaction,
+}
+
+impl<R> CachedOAuth2ClientRepository<R> {
+    /// Wrap an [`OAuth2ClientRepository`] with a cache
+    pub fn new(inner: R, cache: CacheTransaction) -> Self {
+        Self { inner, cache }
+    }
+}
+
+#[async_trait]
+impl<R> OAuth2ClientRepository for CachedOAuth2ClientRepository<R>
+where
+    R: OAuth2ClientRepository,
+{
+    type Error = R::Error;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<Client>, Self::Error> {
+        let changed = self.cache.pending().clients.contains(&id);
+        if changed {
+            return self.inner.lookup(id).await;
+        }
+
+        let cache = &self.cache.cache.inner;
+        if let Some(client) = cache.clients.get(&cache.clock, &id) {
+            return Ok(Some(client));
+        }
+
+        let generation = cache.clients.generation();
+        let client = self.inner.lookup(id).await?;
+        if let Some(client) = &client {
+            cache
+                .clients
+                .insert(&cache.clock, generation, id, client.clone());
+        }
+
+        Ok(client)
+    }
+
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Client>, Self::Error> {
+        self.inner.load_batch(ids).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        redirect_uris: Vec<Url>,
+        encrypted_client_secret: Option<String>,
+        application_type: Option<ApplicationType>,
+        grant_types: Vec<GrantType>,
+        client_name: Option<String>,
+        logo_uri: Option<Url>,
+        client_uri: Option<Url>,
+        policy_uri: Option<Url>,
+        tos_uri: Option<Url>,
+        jwks_uri: Option<Url>,
+        jwks: Option<PublicJsonWebKeySet>,
+        id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
+        userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
+        token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
+        token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
+        initiate_login_uri: Option<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
+    ) -> Result<Client, Self::Error> {
+        self.inner
+            .add(
+                rng,
+                clock,
+                redirect_uris,
+                encrypted_client_secret,
+                application_type,
+                grant_types,
+                client_name,
+                logo_uri,
+                client_uri,
+                policy_uri,
+                tos_uri,
+                jwks_uri,
+                jwks,
+                id_token_signed_response_alg,
+                userinfo_signed_response_alg,
+                token_endpoint_auth_method,
+                token_endpoint_auth_signing_alg,
+                initiate_login_uri,
+                require_pushed_authorization_requests,
+                tls_client_auth_subject_dn,
+                tls_client_certificate_bound_access_tokens,
+            )
+            .await
+    }
+
+    async fn upsert_static(
+        &mut self,
+        client_id: Ulid,
+        client_auth_method: OAuthClientAuthenticationMethod,
+        encrypted_client_secret: Option<String>,
+        jwks: Option<PublicJsonWebKeySet>,
+        jwks_uri: Option<Url>,
+        redirect_uris: Vec<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
+        jwt_access_tokens: bool,
+        access_token_ttl: Option<chrono::Duration>,
+        refresh_token_ttl: Option<chrono::Duration>,
+        session_ttl: Option<chrono::Duration>,
+        session_inactivity_ttl: Option<chrono::Duration>,
+    ) -> Result<Client, Self::Error> {
+        self.cache.pending().clients.insert(client_id);
+        self.inner
+            .upsert_static(
+                client_id,
+                client_auth_method,
+                encrypted_client_secret,
+                jwks,
+                jwks_uri,
+                redirect_uris,
+                require_pushed_authorization_requests,
+                tls_client_auth_subject_dn,
+                tls_client_certificate_bound_access_tokens,
+                jwt_access_tokens,
+                access_token_ttl,
+                refresh_token_ttl,
+                session_ttl,
+                session_inactivity_ttl,
+            )
+            .await
+    }
+
+    async fn set_registration_access_token(
+        &mut self,
+        client: Client,
+        encrypted_registration_access_token: Option<String>,
+    ) -> Result<Client, Self::Error> {
+        self.cache.pending().clients.insert(client.id);
+        self.inner
+            .set_registration_access_token(client, encrypted_registration_access_token)
+            .await
+    }
+
+    async fn update(&mut self, client: Client) -> Result<Client, Self::Error> {
+        self.cache.pending().clients.insert(client.id);
+        self.inner.update(client).await
+    }
+
+    async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error> {
+        self.inner.all_static().await
+    }
+
+    async fn get_consent_for_user(
+        &mut self,
+        client: &Client,
+        user: &User,
+        not_before: Option<DateTime<Utc>>,
+    ) -> Result<Scope, Self::Error> {
+        self.inner
+            .get_consent_for_user(client, user, not_before)
+            .await
+    }
+
+    async fn give_consent_for_user(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        user: &User,
+        scope: &Scope,
+    ) -> Result<(), Self::Error> {
+        self.inner
+            .give_consent_for_user(rng, clock, client, user, scope)
+            .await
+    }
+
+    async fn revoke_all_consents_for_user(&mut self, user: &User) -> Result<usize, Self::Error> {
+        self.inner.revoke_all_consents_for_user(user).await
+    }
+
+    async fn list_consents_for_user(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ClientConsent>, Self::Error> {
+        self.inner.list_consents_for_user(user).await
+    }
+
+    async fn revoke_all_consents_for_client(
+        &mut self,
+        client: &Client,
+    ) -> Result<usize, Self::Error> {
+        self.inner.revoke_all_consents_for_client(client).await
+    }
+
+    async fn delete_by_id(&mut self, id: Ulid) -> Result<(), Self::Error> {
+        self.cache.pending().clients.insert(id);
+        self.inner.delete_by_id(id).await
+    }
+}
+
+/// An [`OAuth2AccessTokenRepository`] which caches token lookups in a
+/// [`RepositoryCache`]
+pub struct CachedOAuth2AccessTokenRepository<R> {
+    inner: R,
+    cache: CacheTransaction,
+}
+
+impl<R> CachedOAuth2AccessTokenRepository<R> {
+    /// Wrap an [`OAuth2AccessTokenRepository`] with a cache
+    pub fn new(inner: R, cache: CacheTransaction) -> Self {
+        Self { inner, cache }
+    }
+}
+
+#[async_trait]
+impl<R> OAuth2AccessTokenRepository for CachedOAuth2AccessTokenRepository<R>
+where
+    R: OAuth2AccessTokenRepository,
+{
+    type Error = R::Error;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<AccessToken>, Self::Error> {
+        self.inner.lookup(id).await
+    }
+
+    async fn find_by_token(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<AccessToken>, Self::Error> {
+        let changed = self
+            .cache
+            .pending()
+            .oauth2_access_tokens
+            .contains(access_token);
+        if changed {
+            return self.inner.find_by_token(access_token).await;
+        }
+
+        let cache = &self.cache.cache.inner;
+        if let Some(token) = cache.oauth2_access_tokens.get(&cache.clock, access_token) {
+            return Ok(Some(token));
+        }
+
+        let generation = cache.oauth2_access_tokens.generation();
+        let token = self.inner.find_by_token(access_token).await?;
+        if let Some(token) = &token {
+            cache.oauth2_access_tokens.insert(
+                &cache.clock,
+                generation,
+                access_token.to_owned(),
+                token.clone(),
+            );
+        }
+
+        Ok(token)
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: String,
+        expires_after: Option<chrono::Duration>,
+        dpop_jkt: Option<String>,
+        x5t_s256: Option<String>,
+    ) -> Result<AccessToken, Self::Error> {
+        self.inner
+            .add(
+                rng,
+                clock,
+                session,
+                access_token,
+                expires_after,
+                dpop_jkt,
+                x5t_s256,
+            )
+            .await
+    }
+
+    async fn add_restricted(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: String,
+        expires_after: chrono::Duration,
+        scope: Scope,
+    ) -> Result<AccessToken, Self::Error> {
+        self.inner
+            .add_restricted(rng, clock, session, access_token, expires_after, scope)
+            .await
+    }
+
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        access_token: AccessToken,
+    ) -> Result<AccessToken, Self::Error> {
+        self.cache
+            .pending()
+            .oauth2_access_tokens
+            .insert(access_token.access_token.clone());
+        self.inner.revoke(clock, access_token).await
+    }
+
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
+        self.inner.cleanup_expired(clock).await
+    }
+}
+
+/// A [`CompatAccessTokenRepository`] which caches token lookups in a
+/// [`RepositoryCache`]
+pub struct CachedCompatAccessTokenRepository<R> {
+    inner: R,
+    cache: CacheTransaction,
+}
+
+impl<R> CachedCompatAccessTokenRepository<R> {
+    /// Wrap a [`CompatAccessTokenRepository`] with a cache
+    pub fn new(inner: R, cache: CacheTransaction) -> Self {
+        Self { inner, cache }
+    }
+}
+
+#[async_trait]
+impl<R> CompatAccessTokenRepository for CachedCompatAccessTokenRepository<R>
+where
+    R: CompatAccessTokenRepository,
+{
+    type Error = R::Error;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CompatAccessToken>, Self::Error> {
+        self.inner.lookup(id).await
+    }
+
+    async fn find_by_token(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<CompatAccessToken>, Self::Error> {
+        let changed = self
+            .cache
+            .pending()
+            .compat_access_tokens
+            .contains(access_token);
+        if changed {
+            return self.inner.find_by_token(access_token).await;
+        }
+
+        let cache = &self.cache.cache.inner;
+        if let Some(token) = cache.compat_access_tokens.get(&cache.clock, access_token) {
+            return Ok(Some(token));
+        }
+
+        let generation = cache.compat_access_tokens.generation();
+        let token = self.inner.find_by_token(access_token).await?;
+        if let Some(token) = &token {
+            cache.compat_access_tokens.insert(
+                &cache.clock,
+                generation,
+                access_token.to_owned(),
+                token.clone(),
+            );
+        }
+
+        Ok(token)
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        compat_session: &CompatSession,
+        token: String,
+        expires_after: Option<chrono::Duration>,
+    ) -> Result<CompatAccessToken, Self::Error> {
+        self.inner
+            .add(rng, clock, compat_session, token, expires_after)
+            .await
+    }
+
+    async fn expire(
+        &mut self,
+        clock: &dyn Clock,
+        compat_access_token: CompatAccessToken,
+    ) -> Result<CompatAccessToken, Self::Error> {
+        self.cache
+            .pending()
+            .compat_access_tokens
+            .insert(compat_access_token.token.clone());
+        self.inner.expire(clock, compat_access_token).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_ttl_cache() {
+        let clock = MockClock::default();
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+
+        let generation = cache.generation();
+        cache.insert(&clock, generation, "a".to_owned(), 1);
+        cache.insert(&clock, generation, "b".to_owned(), 2);
+        assert_eq!(cache.get(&clock, "a"), Some(1));
+        assert_eq!(cache.get(&clock, "b"), Some(2));
+
+        cache.remove("a");
+        assert_eq!(cache.get(&clock, "a"), None);
+
+        // A lookup which started before an invalidation doesn't fill the cache
+        cache.insert(&clock, generation, "a".to_owned(), 1);
+        assert_eq!(cache.get(&clock, "a"), None);
+
+        // Entries expire according to the clock
+        clock.advance(chrono::Duration::microseconds(61 * 1000 * 1000));
+        assert_eq!(cache.get(&clock, "b"), None);
+
+        // A zero TTL disables the cache
+        let cache = TtlCache::new(Duration::ZERO, 10);
+        cache.insert(&clock, cache.generation(), "a".to_owned(), 1);
+        assert_eq!(cache.get(&clock, "a"), None);
+    }
+}
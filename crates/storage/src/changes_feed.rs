@@ -0,0 +1,142 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to interact with the changes feed
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{ChangesFeedEntry, WebhookEvent, WebhookEventKind};
+use rand_core::RngCore;
+
+use crate::{pagination::Count, repository_impl, Clock, Page, Pagination};
+
+/// Filter parameters for listing changes feed entries
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct ChangesFeedFilter {
+    kind: Option<WebhookEventKind>,
+}
+
+impl ChangesFeedFilter {
+    /// Create a new [`ChangesFeedFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only list entries of the given kind of event
+    #[must_use]
+    pub fn with_kind(mut self, kind: WebhookEventKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Get the kind filter
+    #[must_use]
+    pub fn kind(&self) -> Option<WebhookEventKind> {
+        self.kind
+    }
+}
+
+/// A [`ChangesFeedRepository`] helps interacting with the
+/// [`ChangesFeedEntry`] saved in the storage backend
+#[async_trait]
+pub trait ChangesFeedRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record an event in the changes feed
+    ///
+    /// Recording entries is serialized, so that the IDs of the entries are
+    /// strictly increasing in the order in which they are committed, and can
+    /// be used as cursors. Recording the same event twice is a no-op.
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate the entry ID
+    /// * `event`: The event to record
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        event: &WebhookEvent,
+    ) -> Result<(), Self::Error>;
+
+    /// List [`ChangesFeedEntry`] with the given filter and pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: ChangesFeedFilter,
+        pagination: Pagination,
+    ) -> Result<Page<ChangesFeedEntry>, Self::Error>;
+
+    /// Count the [`ChangesFeedEntry`] with the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, filter: ChangesFeedFilter) -> Result<usize, Self::Error>;
+
+    /// Count the [`ChangesFeedEntry`] with the given filter, falling back to
+    /// an estimation if there are too many of them to count them exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(&mut self, filter: ChangesFeedFilter) -> Result<Count, Self::Error>;
+
+    /// Delete the entries recorded before the given time
+    ///
+    /// Returns the number of entries deleted
+    ///
+    /// # Parameters
+    ///
+    /// * `before`: Entries recorded before this time are deleted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(ChangesFeedRepository:
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        event: &WebhookEvent,
+    ) -> Result<(), Self::Error>;
+
+    async fn list(
+        &mut self,
+        filter: ChangesFeedFilter,
+        pagination: Pagination,
+    ) -> Result<Page<ChangesFeedEntry>, Self::Error>;
+
+    async fn count(&mut self, filter: ChangesFeedFilter) -> Result<usize, Self::Error>;
+    async fn estimate_count(&mut self, filter: ChangesFeedFilter) -> Result<Count, Self::Error>;
+
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+);
@@ -7,7 +7,7 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{BrowserSession, CompatSession, CompatSsoLogin, Device, User, UserAgent};
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -64,6 +64,7 @@ pub struct CompatSessionFilter<'a> {
     device: Option<&'a Device>,
     last_active_before: Option<DateTime<Utc>>,
     last_active_after: Option<DateTime<Utc>>,
+    tag: Option<&'a str>,
 }
 
 impl<'a> CompatSessionFilter<'a> {
@@ -181,6 +182,21 @@ impl<'a> CompatSessionFilter<'a> {
     pub fn auth_type(&self) -> Option<CompatSessionType> {
         self.auth_type
     }
+
+    /// Only return sessions with the given tag
+    #[must_use]
+    pub fn with_tag(mut self, tag: &'a str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Get the tag filter
+    ///
+    /// Returns [`None`] if no tag filter was set
+    #[must_use]
+    pub fn tag(&self) -> Option<&'a str> {
+        self.tag
+    }
 }
 
 /// A [`CompatSessionRepository`] helps interacting with
@@ -265,6 +281,30 @@ pub trait CompatSessionRepository: Send + Sync {
         filter: CompatSessionFilter<'_>,
     ) -> Result<usize, Self::Error>;
 
+    /// Mark as finished the active [`CompatSession`]s which went past their
+    /// maximum lifetime, or which stayed inactive for too long
+    ///
+    /// Sessions of bot accounts are never finished for inactivity. Returns the
+    /// sessions which were finished.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session_ttl`: The maximum lifetime of sessions, if limited
+    /// * `inactivity_ttl`: How long sessions can stay inactive, if limited
+    /// * `limit`: The maximum number of sessions to finish
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Option<Duration>,
+        inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<Vec<CompatSession>, Self::Error>;
+
     /// List [`CompatSession`] with the given filter and pagination
     ///
     /// Returns a page of compat sessions, with the associated SSO logins if any
@@ -324,6 +364,44 @@ pub trait CompatSessionRepository: Send + Sync {
         compat_session: CompatSession,
         user_agent: UserAgent,
     ) -> Result<CompatSession, Self::Error>;
+
+    /// Add a tag to a [`CompatSession`], if it doesn't have it already
+    ///
+    /// The tags are updated atomically, so that concurrent changes to the tags
+    /// of the session are not lost.
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`CompatSession`] to tag
+    /// * `tag`: The tag to add
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_tag(
+        &mut self,
+        session: CompatSession,
+        tag: &str,
+    ) -> Result<CompatSession, Self::Error>;
+
+    /// Remove a tag from a [`CompatSession`]
+    ///
+    /// The tags are updated atomically, so that concurrent changes to the tags
+    /// of the session are not lost.
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`CompatSession`] to untag
+    /// * `tag`: The tag to remove
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove_tag(
+        &mut self,
+        session: CompatSession,
+        tag: &str,
+    ) -> Result<CompatSession, Self::Error>;
 }
 
 repository_impl!(CompatSessionRepository:
@@ -351,6 +429,14 @@ repository_impl!(CompatSessionRepository:
         filter: CompatSessionFilter<'_>,
     ) -> Result<usize, Self::Error>;
 
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Option<Duration>,
+        inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<Vec<CompatSession>, Self::Error>;
+
     async fn list(
         &mut self,
         filter: CompatSessionFilter<'_>,
@@ -369,4 +455,8 @@ repository_impl!(CompatSessionRepository:
         compat_session: CompatSession,
         user_agent: UserAgent,
     ) -> Result<CompatSession, Self::Error>;
+
+    async fn add_tag(&mut self, session: CompatSession, tag: &str) -> Result<CompatSession, Self::Error>;
+
+    async fn remove_tag(&mut self, session: CompatSession, tag: &str) -> Result<CompatSession, Self::Error>;
 );
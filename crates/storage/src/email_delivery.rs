@@ -0,0 +1,176 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to keep track of the emails which could not be delivered
+
+use async_trait::async_trait;
+use mas_data_model::EmailDeliveryFailure;
+use rand_core::RngCore;
+
+use crate::{pagination::Count, repository_impl, Clock, Page, Pagination};
+
+/// Filter parameters for listing email delivery failures
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct EmailDeliveryFailureFilter<'a> {
+    domain: Option<&'a str>,
+    permanent: Option<bool>,
+}
+
+impl<'a> EmailDeliveryFailureFilter<'a> {
+    /// Create a new [`EmailDeliveryFailureFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// List failures for addresses of the given domain
+    #[must_use]
+    pub fn for_domain(mut self, domain: &'a str) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Get the domain filter
+    #[must_use]
+    pub fn domain(&self) -> Option<&str> {
+        self.domain
+    }
+
+    /// Only list hard bounces
+    #[must_use]
+    pub fn permanent_only(mut self) -> Self {
+        self.permanent = Some(true);
+        self
+    }
+
+    /// Only list deferred emails
+    #[must_use]
+    pub fn deferred_only(mut self) -> Self {
+        self.permanent = Some(false);
+        self
+    }
+
+    /// Get the permanent filter
+    #[must_use]
+    pub fn permanent(&self) -> Option<bool> {
+        self.permanent
+    }
+}
+
+/// An [`EmailDeliveryFailureRepository`] helps interacting with
+/// [`EmailDeliveryFailure`] saved in the storage backend
+#[async_trait]
+pub trait EmailDeliveryFailureRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record a new [`EmailDeliveryFailure`]
+    ///
+    /// Returns the newly recorded [`EmailDeliveryFailure`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `email`: The address the email was sent to
+    /// * `permanent`: Whether the mail server rejected the email permanently
+    /// * `reason`: The error returned by the mail server
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        email: &str,
+        permanent: bool,
+        reason: String,
+    ) -> Result<EmailDeliveryFailure, Self::Error>;
+
+    /// Check whether emails to the given address hard-bounced before, in
+    /// which case no more emails should be sent to it
+    ///
+    /// Addresses are compared case-insensitively.
+    ///
+    /// # Parameters
+    ///
+    /// * `email`: The address to check
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn is_suppressed(&mut self, email: &str) -> Result<bool, Self::Error>;
+
+    /// List [`EmailDeliveryFailure`] with the given filter and pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<EmailDeliveryFailure>, Self::Error>;
+
+    /// Count the [`EmailDeliveryFailure`] with the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, filter: EmailDeliveryFailureFilter<'_>)
+        -> Result<usize, Self::Error>;
+
+    /// Count the [`EmailDeliveryFailure`] with the given filter, falling back
+    /// to an estimation if there are too many of them to count them exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+    ) -> Result<Count, Self::Error>;
+}
+
+repository_impl!(EmailDeliveryFailureRepository:
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        email: &str,
+        permanent: bool,
+        reason: String,
+    ) -> Result<EmailDeliveryFailure, Self::Error>;
+
+    async fn is_suppressed(&mut self, email: &str) -> Result<bool, Self::Error>;
+
+    async fn list(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<EmailDeliveryFailure>, Self::Error>;
+
+    async fn count(&mut self, filter: EmailDeliveryFailureFilter<'_>)
+        -> Result<usize, Self::Error>;
+
+    async fn estimate_count(
+        &mut self,
+        filter: EmailDeliveryFailureFilter<'_>,
+    ) -> Result<Count, Self::Error>;
+);
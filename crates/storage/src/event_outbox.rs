@@ -0,0 +1,70 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to interact with the outbox of events to publish to the event
+//! stream
+
+use async_trait::async_trait;
+use mas_data_model::WebhookEvent;
+use ulid::Ulid;
+
+use crate::repository_impl;
+
+/// An [`EventOutboxRepository`] helps interacting with the events waiting to
+/// be published to the event stream
+#[async_trait]
+pub trait EventOutboxRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Add an event to the outbox
+    ///
+    /// Adding the same event twice is a no-op.
+    ///
+    /// # Parameters
+    ///
+    /// * `event`: The event to add
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(&mut self, event: &WebhookEvent) -> Result<(), Self::Error>;
+
+    /// Fetch the oldest events in the outbox, and lock them until the end of
+    /// the transaction
+    ///
+    /// Events locked by another transaction are skipped, so that multiple
+    /// workers can publish events concurrently.
+    ///
+    /// # Parameters
+    ///
+    /// * `limit`: The maximum number of events to fetch
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn fetch_batch(&mut self, limit: usize) -> Result<Vec<WebhookEvent>, Self::Error>;
+
+    /// Remove events from the outbox, once they were published
+    ///
+    /// Returns the number of events removed
+    ///
+    /// # Parameters
+    ///
+    /// * `ids`: The IDs of the events to remove
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove(&mut self, ids: &[Ulid]) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(EventOutboxRepository:
+    async fn add(&mut self, event: &WebhookEvent) -> Result<(), Self::Error>;
+
+    async fn fetch_batch(&mut self, limit: usize) -> Result<Vec<WebhookEvent>, Self::Error>;
+
+    async fn remove(&mut self, ids: &[Ulid]) -> Result<usize, Self::Error>;
+);
@@ -0,0 +1,52 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to keep track of the warnings sent about expiring credentials
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::ExpiringCredential;
+
+use crate::{repository_impl, Clock};
+
+/// An [`ExpiryWarningRepository`] records when the administrators were last
+/// warned about an expiring credential, so that they are not warned by every
+/// worker on every run
+#[async_trait]
+pub trait ExpiryWarningRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record that the administrators are being warned about a credential
+    ///
+    /// Returns `false` if they were already warned about the same expiry date
+    /// less than `renotify_after` ago, in which case nothing is recorded
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `credential`: The credential about to expire
+    /// * `renotify_after`: How long to wait before warning again about the same
+    ///   expiry date
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn mark_notified(
+        &mut self,
+        clock: &dyn Clock,
+        credential: &ExpiringCredential,
+        renotify_after: Duration,
+    ) -> Result<bool, Self::Error>;
+}
+
+repository_impl!(ExpiryWarningRepository:
+    async fn mark_notified(
+        &mut self,
+        clock: &dyn Clock,
+        credential: &ExpiringCredential,
+        renotify_after: Duration,
+    ) -> Result<bool, Self::Error>;
+);
@@ -0,0 +1,236 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to manage the decoy tokens and accounts
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{Honeytoken, HoneytokenKind};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::Count, repository_impl, Clock, Page, Pagination};
+
+/// Filter parameters for listing honeytokens
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct HoneytokenFilter {
+    kind: Option<HoneytokenKind>,
+    used: Option<bool>,
+}
+
+impl HoneytokenFilter {
+    /// Create a new [`HoneytokenFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// List honeytokens of the given kind
+    #[must_use]
+    pub fn with_kind(mut self, kind: HoneytokenKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Get the kind filter
+    #[must_use]
+    pub fn kind(&self) -> Option<HoneytokenKind> {
+        self.kind
+    }
+
+    /// Only list the honeytokens which were used at least once
+    #[must_use]
+    pub fn used_only(mut self) -> Self {
+        self.used = Some(true);
+        self
+    }
+
+    /// Only list the honeytokens which were never used
+    #[must_use]
+    pub fn unused_only(mut self) -> Self {
+        self.used = Some(false);
+        self
+    }
+
+    /// Get the used filter
+    #[must_use]
+    pub fn used(&self) -> Option<bool> {
+        self.used
+    }
+}
+
+/// A [`HoneytokenRepository`] helps interacting with [`Honeytoken`] saved in
+/// the storage backend
+#[async_trait]
+pub trait HoneytokenRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a [`Honeytoken`] by its ID
+    ///
+    /// Returns `None` if no [`Honeytoken`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`Honeytoken`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<Honeytoken>, Self::Error>;
+
+    /// Find a decoy token
+    ///
+    /// Returns `None` if the token is not a honeytoken
+    ///
+    /// # Parameters
+    ///
+    /// * `token`: The token presented by the client
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_token(&mut self, token: &str) -> Result<Option<Honeytoken>, Self::Error>;
+
+    /// Find a decoy account by its username
+    ///
+    /// Returns `None` if there is no decoy account with this username
+    ///
+    /// # Parameters
+    ///
+    /// * `username`: The username to look for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_account(&mut self, username: &str) -> Result<Option<Honeytoken>, Self::Error>;
+
+    /// Plant a new [`Honeytoken`]
+    ///
+    /// Returns the newly created [`Honeytoken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `kind`: What the honeytoken impersonates
+    /// * `value`: The decoy token, or the username of the decoy account
+    /// * `label`: A free-form label, to remember where it was planted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: HoneytokenKind,
+        value: String,
+        label: String,
+    ) -> Result<Honeytoken, Self::Error>;
+
+    /// Record that a [`Honeytoken`] was used
+    ///
+    /// Returns the updated [`Honeytoken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `honeytoken`: The [`Honeytoken`] which was used
+    /// * `used_at`: When it was used
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_use(
+        &mut self,
+        honeytoken: Honeytoken,
+        used_at: DateTime<Utc>,
+    ) -> Result<Honeytoken, Self::Error>;
+
+    /// Delete a [`Honeytoken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `honeytoken`: The [`Honeytoken`] to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove(&mut self, honeytoken: Honeytoken) -> Result<(), Self::Error>;
+
+    /// List [`Honeytoken`] with the given filter and pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: HoneytokenFilter,
+        pagination: Pagination,
+    ) -> Result<Page<Honeytoken>, Self::Error>;
+
+    /// Count the [`Honeytoken`] with the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, filter: HoneytokenFilter) -> Result<usize, Self::Error>;
+
+    /// Count the [`Honeytoken`] with the given filter, falling back to an
+    /// estimation if there are too many of them to count them exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(&mut self, filter: HoneytokenFilter) -> Result<Count, Self::Error>;
+}
+
+repository_impl!(HoneytokenRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<Honeytoken>, Self::Error>;
+
+    async fn find_by_token(&mut self, token: &str) -> Result<Option<Honeytoken>, Self::Error>;
+
+    async fn find_account(&mut self, username: &str) -> Result<Option<Honeytoken>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kind: HoneytokenKind,
+        value: String,
+        label: String,
+    ) -> Result<Honeytoken, Self::Error>;
+
+    async fn record_use(
+        &mut self,
+        honeytoken: Honeytoken,
+        used_at: DateTime<Utc>,
+    ) -> Result<Honeytoken, Self::Error>;
+
+    async fn remove(&mut self, honeytoken: Honeytoken) -> Result<(), Self::Error>;
+
+    async fn list(
+        &mut self,
+        filter: HoneytokenFilter,
+        pagination: Pagination,
+    ) -> Result<Page<Honeytoken>, Self::Error>;
+
+    async fn count(&mut self, filter: HoneytokenFilter) -> Result<usize, Self::Error>;
+
+    async fn estimate_count(&mut self, filter: HoneytokenFilter) -> Result<Count, Self::Error>;
+);
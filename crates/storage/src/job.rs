@@ -10,6 +10,8 @@ use std::{num::ParseIntError, ops::Deref};
 
 pub use apalis_core::job::{Job, JobId};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_tower::RequestId;
 use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -21,6 +23,7 @@ use crate::repository_impl;
 pub struct JobSubmission {
     name: &'static str,
     payload: Value,
+    run_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -55,12 +58,16 @@ impl TryFrom<&SerializableSpanContext> for SpanContext {
     }
 }
 
-/// A wrapper for [`Job`] which adds the span context in the payload.
+/// A wrapper for [`Job`] which adds the span context and the ID of the
+/// request which scheduled it in the payload.
 #[derive(Serialize, Deserialize)]
 pub struct JobWithSpanContext<T> {
     #[serde(skip_serializing_if = "Option::is_none")]
     span_context: Option<SerializableSpanContext>,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+
     #[serde(flatten)]
     payload: T,
 }
@@ -69,6 +76,7 @@ impl<J> From<J> for JobWithSpanContext<J> {
     fn from(payload: J) -> Self {
         Self {
             span_context: None,
+            request_id: None,
             payload,
         }
     }
@@ -99,6 +107,17 @@ impl<T> JobWithSpanContext<T> {
             .as_ref()
             .and_then(|ctx| ctx.try_into().ok())
     }
+
+    /// Get the ID of the request which scheduled the job.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`None`] if the job was not scheduled from a request, or if the
+    /// request ID is invalid.
+    #[must_use]
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.request_id.as_deref().and_then(|id| id.parse().ok())
+    }
 }
 
 impl JobSubmission {
@@ -114,11 +133,15 @@ impl JobSubmission {
         Self {
             name: J::NAME,
             payload,
+            run_at: None,
         }
     }
 
     /// Create a new job submission out of a [`Job`] and a [`SpanContext`].
     ///
+    /// The ID of the request currently being processed, if any, is also
+    /// recorded in the job payload.
+    ///
     /// # Panics
     ///
     /// Panics if the job cannot be serialized.
@@ -130,6 +153,7 @@ impl JobSubmission {
         Self::new(JobWithSpanContext {
             payload: job,
             span_context: Some(span_context),
+            request_id: RequestId::current().map(|id| id.to_string()),
         })
     }
 
@@ -144,6 +168,20 @@ impl JobSubmission {
     pub fn payload(&self) -> &Value {
         &self.payload
     }
+
+    /// Delay the job until the given time.
+    #[must_use]
+    pub fn with_run_at(mut self, run_at: DateTime<Utc>) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+
+    /// When the job should run, or [`None`] if it should run as soon as
+    /// possible.
+    #[must_use]
+    pub fn run_at(&self) -> Option<DateTime<Utc>> {
+        self.run_at
+    }
 }
 
 /// A [`JobRepository`] is used to schedule jobs to be executed by a worker.
@@ -190,6 +228,22 @@ pub trait JobRepositoryExt {
         &mut self,
         job: J,
     ) -> Result<JobId, Self::Error>;
+
+    /// Schedule a job to be executed once the given time is reached.
+    ///
+    /// # Parameters
+    ///
+    /// * `job` - The job to schedule.
+    /// * `run_at` - The time at which the job should run.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn schedule_job_at<J: Job + Serialize + Send>(
+        &mut self,
+        job: J,
+        run_at: DateTime<Utc>,
+    ) -> Result<JobId, Self::Error>;
 }
 
 #[async_trait]
@@ -218,14 +272,48 @@ where
         self.schedule_submission(JobSubmission::new_with_span_context(job, span_context))
             .await
     }
+
+    #[tracing::instrument(
+        name = "db.job.schedule_job_at",
+        skip_all,
+        fields(
+            job.name = J::NAME,
+            job.run_at = %run_at,
+        ),
+    )]
+    async fn schedule_job_at<J: Job + Serialize + Send>(
+        &mut self,
+        job: J,
+        run_at: DateTime<Utc>,
+    ) -> Result<JobId, Self::Error> {
+        let span = tracing::Span::current();
+        let ctx = span.context();
+        let span = ctx.span();
+        let span_context = span.span_context();
+
+        self.schedule_submission(
+            JobSubmission::new_with_span_context(job, span_context).with_run_at(run_at),
+        )
+        .await
+    }
 }
 
 mod jobs {
     // XXX: Move this somewhere else?
+    use std::net::IpAddr;
+
     use apalis_core::job::Job;
-    use mas_data_model::{Device, User, UserEmail, UserRecoverySession};
+    use chrono::{DateTime, Utc};
+    use mas_data_model::{
+        AuditEvent, CibaGrant, CompatSession, Device, Honeytoken, Session, User, UserEmail,
+        UserRecoverySession, WebhookEvent, WebhookEventKind,
+    };
+    use rand_core::RngCore;
     use serde::{Deserialize, Serialize};
     use ulid::Ulid;
+    use url::Url;
+
+    use crate::Clock;
 
     /// A job to verify an email address.
     #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -273,6 +361,7 @@ mod jobs {
     pub struct ProvisionUserJob {
         user_id: Ulid,
         set_display_name: Option<String>,
+        set_avatar_url: Option<String>,
     }
 
     impl ProvisionUserJob {
@@ -282,6 +371,7 @@ mod jobs {
             Self {
                 user_id: user.id,
                 set_display_name: None,
+                set_avatar_url: None,
             }
         }
 
@@ -291,6 +381,7 @@ mod jobs {
             Self {
                 user_id,
                 set_display_name: None,
+                set_avatar_url: None,
             }
         }
 
@@ -307,6 +398,22 @@ mod jobs {
             self.set_display_name.as_deref()
         }
 
+        /// Set the avatar of the user.
+        ///
+        /// This can either be an `mxc://` URI, or an HTTP URL to an image which
+        /// is uploaded to the homeserver media repository.
+        #[must_use]
+        pub fn set_avatar_url(mut self, avatar_url: String) -> Self {
+            self.set_avatar_url = Some(avatar_url);
+            self
+        }
+
+        /// Get the avatar URL to be set.
+        #[must_use]
+        pub fn avatar_url_to_set(&self) -> Option<&str> {
+            self.set_avatar_url.as_deref()
+        }
+
         /// The ID of the user to provision.
         #[must_use]
         pub fn user_id(&self) -> Ulid {
@@ -506,9 +613,595 @@ mod jobs {
     impl Job for SendAccountRecoveryEmailsJob {
         const NAME: &'static str = "send-account-recovery-email";
     }
+
+    /// Notify a user by email that a client asked them to approve a login
+    /// through the Client-Initiated Backchannel Authentication flow
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendCibaNotificationJob {
+        ciba_grant_id: Ulid,
+    }
+
+    impl SendCibaNotificationJob {
+        /// Create a new job to notify the user of a CIBA grant
+        ///
+        /// # Parameters
+        ///
+        /// * `ciba_grant` - The CIBA grant to notify the user of
+        #[must_use]
+        pub fn new(ciba_grant: &CibaGrant) -> Self {
+            Self {
+                ciba_grant_id: ciba_grant.id,
+            }
+        }
+
+        /// The ID of the CIBA grant to notify the user of
+        #[must_use]
+        pub fn ciba_grant_id(&self) -> Ulid {
+            self.ciba_grant_id
+        }
+    }
+
+    impl Job for SendCibaNotificationJob {
+        const NAME: &'static str = "send-ciba-notification";
+    }
+
+    /// Notify a user by email that one of their sessions was ended because
+    /// one of its refresh tokens was reused
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendRefreshTokenReuseNotificationJob {
+        session_id: Ulid,
+    }
+
+    impl SendRefreshTokenReuseNotificationJob {
+        /// Create a new job to notify the user of a refresh token reuse
+        ///
+        /// # Parameters
+        ///
+        /// * `session` - The OAuth 2.0 session which was ended
+        #[must_use]
+        pub fn new(session: &Session) -> Self {
+            Self {
+                session_id: session.id,
+            }
+        }
+
+        /// The ID of the OAuth 2.0 session which was ended
+        #[must_use]
+        pub fn session_id(&self) -> Ulid {
+            self.session_id
+        }
+    }
+
+    impl Job for SendRefreshTokenReuseNotificationJob {
+        const NAME: &'static str = "send-refresh-token-reuse-notification";
+    }
+
+    /// Warn a user by email that their account will be deactivated because
+    /// they didn't use it for a long time
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendInactivityWarningJob {
+        user_id: Ulid,
+        deactivation_at: DateTime<Utc>,
+    }
+
+    impl SendInactivityWarningJob {
+        /// Create a new job to warn a user about their inactivity
+        ///
+        /// # Parameters
+        ///
+        /// * `user` - The user to warn
+        /// * `deactivation_at` - When the account will be deactivated if the
+        ///   user doesn't log in before
+        #[must_use]
+        pub fn new(user: &User, deactivation_at: DateTime<Utc>) -> Self {
+            Self {
+                user_id: user.id,
+                deactivation_at,
+            }
+        }
+
+        /// The ID of the user to warn
+        #[must_use]
+        pub fn user_id(&self) -> Ulid {
+            self.user_id
+        }
+
+        /// When the account will be deactivated
+        #[must_use]
+        pub fn deactivation_at(&self) -> DateTime<Utc> {
+            self.deactivation_at
+        }
+    }
+
+    impl Job for SendInactivityWarningJob {
+        const NAME: &'static str = "send-inactivity-warning";
+    }
+
+    /// Record that a honeytoken was used and alert the administrators
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct ReportHoneytokenUseJob {
+        honeytoken_id: Ulid,
+        used_at: DateTime<Utc>,
+        endpoint: String,
+        ip: Option<IpAddr>,
+        user_agent: Option<String>,
+    }
+
+    impl ReportHoneytokenUseJob {
+        /// Create a new job to report the use of a honeytoken
+        ///
+        /// # Parameters
+        ///
+        /// * `honeytoken` - The honeytoken which was used
+        /// * `used_at` - When it was used
+        /// * `endpoint` - Where it was used, e.g. `introspection` or
+        ///   `compat_login`
+        #[must_use]
+        pub fn new(honeytoken: &Honeytoken, used_at: DateTime<Utc>, endpoint: &str) -> Self {
+            Self {
+                honeytoken_id: honeytoken.id,
+                used_at,
+                endpoint: endpoint.to_owned(),
+                ip: None,
+                user_agent: None,
+            }
+        }
+
+        /// Set the IP address from which the honeytoken was used
+        #[must_use]
+        pub fn with_ip(mut self, ip: Option<IpAddr>) -> Self {
+            self.ip = ip;
+            self
+        }
+
+        /// Set the user agent which used the honeytoken
+        #[must_use]
+        pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+            self.user_agent = user_agent;
+            self
+        }
+
+        /// The ID of the honeytoken which was used
+        #[must_use]
+        pub fn honeytoken_id(&self) -> Ulid {
+            self.honeytoken_id
+        }
+
+        /// When the honeytoken was used
+        #[must_use]
+        pub fn used_at(&self) -> DateTime<Utc> {
+            self.used_at
+        }
+
+        /// Where the honeytoken was used
+        #[must_use]
+        pub fn endpoint(&self) -> &str {
+            &self.endpoint
+        }
+
+        /// The IP address from which the honeytoken was used, if known
+        #[must_use]
+        pub fn ip(&self) -> Option<IpAddr> {
+            self.ip
+        }
+
+        /// The user agent which used the honeytoken, if known
+        #[must_use]
+        pub fn user_agent(&self) -> Option<&str> {
+            self.user_agent.as_deref()
+        }
+    }
+
+    impl Job for ReportHoneytokenUseJob {
+        const NAME: &'static str = "report-honeytoken-use";
+    }
+
+    /// Compare a login with the previous ones of the same user, and notify
+    /// them if it looks suspicious
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct CheckSuspiciousLoginJob {
+        audit_event_id: Ulid,
+    }
+
+    impl CheckSuspiciousLoginJob {
+        /// Create a new job to check a login
+        ///
+        /// # Parameters
+        ///
+        /// * `event` - The audit event recorded for the login
+        #[must_use]
+        pub fn new(event: &AuditEvent) -> Self {
+            Self {
+                audit_event_id: event.id,
+            }
+        }
+
+        /// The ID of the audit event recorded for the login
+        #[must_use]
+        pub fn audit_event_id(&self) -> Ulid {
+            self.audit_event_id
+        }
+    }
+
+    impl Job for CheckSuspiciousLoginJob {
+        const NAME: &'static str = "check-suspicious-login";
+    }
+
+    /// The session started by a sign-in, in a [`SendNewDeviceNotificationJob`]
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(tag = "type", content = "id", rename_all = "snake_case")]
+    pub enum NewDeviceSession {
+        /// An OAuth 2.0 session
+        #[serde(rename = "oauth2")]
+        OAuth2(Ulid),
+
+        /// A compatibility session
+        Compat(Ulid),
+    }
+
+    /// Notify a user by email that they signed in from a browser or device
+    /// they never used before
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendNewDeviceNotificationJob {
+        session: NewDeviceSession,
+        ip: Option<IpAddr>,
+    }
+
+    impl SendNewDeviceNotificationJob {
+        /// Create a new job for a sign-in which started an OAuth 2.0 session
+        ///
+        /// # Parameters
+        ///
+        /// * `session` - The OAuth 2.0 session which was started
+        #[must_use]
+        pub fn oauth2(session: &Session) -> Self {
+            Self {
+                session: NewDeviceSession::OAuth2(session.id),
+                ip: None,
+            }
+        }
+
+        /// Create a new job for a sign-in which started a compatibility
+        /// session
+        ///
+        /// # Parameters
+        ///
+        /// * `session` - The compatibility session which was started
+        #[must_use]
+        pub fn compat(session: &CompatSession) -> Self {
+            Self {
+                session: NewDeviceSession::Compat(session.id),
+                ip: None,
+            }
+        }
+
+        /// Set the IP address from which the user signed in
+        #[must_use]
+        pub fn with_ip(mut self, ip: Option<IpAddr>) -> Self {
+            self.ip = ip;
+            self
+        }
+
+        /// The session which was started
+        #[must_use]
+        pub fn session(&self) -> NewDeviceSession {
+            self.session
+        }
+
+        /// The IP address from which the user signed in, if known
+        #[must_use]
+        pub fn ip(&self) -> Option<IpAddr> {
+            self.ip
+        }
+    }
+
+    impl Job for SendNewDeviceNotificationJob {
+        const NAME: &'static str = "send-new-device-notification";
+    }
+
+    /// A job to dispatch a webhook event to all the endpoints interested in it
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct DispatchWebhookJob {
+        event: WebhookEvent,
+    }
+
+    impl DispatchWebhookJob {
+        /// Create a new job to dispatch a webhook event
+        ///
+        /// # Parameters
+        ///
+        /// * `rng` - The random number generator used to generate the event ID
+        /// * `clock` - The clock used to timestamp the event
+        /// * `kind` - The kind of event
+        /// * `data` - Event-specific data
+        #[must_use]
+        pub fn new(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            kind: WebhookEventKind,
+            data: serde_json::Value,
+        ) -> Self {
+            let created_at = clock.now();
+            let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+            Self {
+                event: WebhookEvent {
+                    id,
+                    kind,
+                    created_at,
+                    data,
+                },
+            }
+        }
+
+        /// Create a job for the `user.registered` event
+        #[must_use]
+        pub fn user_registered(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            user: &User,
+        ) -> Self {
+            Self::new(
+                rng,
+                clock,
+                WebhookEventKind::UserRegistered,
+                user_data(user),
+            )
+        }
+
+        /// Create a job for the `user.deactivated` event
+        #[must_use]
+        pub fn user_deactivated(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            user: &User,
+        ) -> Self {
+            Self::new(
+                rng,
+                clock,
+                WebhookEventKind::UserDeactivated,
+                user_data(user),
+            )
+        }
+
+        /// Create a job for the `user.reactivated` event
+        #[must_use]
+        pub fn user_reactivated(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            user: &User,
+        ) -> Self {
+            Self::new(
+                rng,
+                clock,
+                WebhookEventKind::UserReactivated,
+                user_data(user),
+            )
+        }
+
+        /// Create a job for the `user.updated` event, which carries the
+        /// current lock and admin state of the user
+        #[must_use]
+        pub fn user_updated(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            user: &User,
+        ) -> Self {
+            let mut data = user_data(user);
+            data["locked"] = serde_json::Value::Bool(user.locked_at.is_some());
+            data["can_request_admin"] = serde_json::Value::Bool(user.can_request_admin);
+            Self::new(rng, clock, WebhookEventKind::UserUpdated, data)
+        }
+
+        /// Create a job for the `session.created` event
+        ///
+        /// # Parameters
+        ///
+        /// * `user` - The user who owns the session
+        /// * `session_type` - The type of session, e.g. `browser` or `compat`
+        /// * `session_id` - The ID of the session
+        #[must_use]
+        pub fn session_created(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            user: &User,
+            session_type: &str,
+            session_id: Ulid,
+        ) -> Self {
+            Self::new(
+                rng,
+                clock,
+                WebhookEventKind::SessionCreated,
+                session_data(user, session_type, session_id),
+            )
+        }
+
+        /// Create a job for the `session.revoked` event
+        ///
+        /// # Parameters
+        ///
+        /// * `user` - The user who owns the session
+        /// * `session_type` - The type of session, e.g. `browser`, `compat` or
+        ///   `oauth2`
+        /// * `session_id` - The ID of the session
+        #[must_use]
+        pub fn session_revoked(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            user: &User,
+            session_type: &str,
+            session_id: Ulid,
+        ) -> Self {
+            Self::new(
+                rng,
+                clock,
+                WebhookEventKind::SessionRevoked,
+                session_data(user, session_type, session_id),
+            )
+        }
+
+        /// Create a job for the `honeytoken.used` event
+        ///
+        /// # Parameters
+        ///
+        /// * `honeytoken` - The honeytoken which was used
+        /// * `endpoint` - Where it was used
+        /// * `ip` - The IP address from which it was used, if known
+        #[must_use]
+        pub fn honeytoken_used(
+            rng: &mut (impl RngCore + ?Sized),
+            clock: &dyn Clock,
+            honeytoken: &Honeytoken,
+            endpoint: &str,
+            ip: Option<IpAddr>,
+        ) -> Self {
+            Self::new(
+                rng,
+                clock,
+                WebhookEventKind::HoneytokenUsed,
+                serde_json::json!({
+                    "honeytoken_id": honeytoken.id,
+                    "kind": honeytoken.kind,
+                    "label": honeytoken.label,
+                    "endpoint": endpoint,
+                    "ip_address": ip,
+                }),
+            )
+        }
+
+        /// The event to dispatch
+        #[must_use]
+        pub fn event(&self) -> &WebhookEvent {
+            &self.event
+        }
+    }
+
+    fn user_data(user: &User) -> serde_json::Value {
+        serde_json::json!({
+            "user_id": user.id,
+            "username": user.username,
+        })
+    }
+
+    fn session_data(user: &User, session_type: &str, session_id: Ulid) -> serde_json::Value {
+        serde_json::json!({
+            "user_id": user.id,
+            "username": user.username,
+            "session_type": session_type,
+            "session_id": session_id,
+        })
+    }
+
+    impl Job for DispatchWebhookJob {
+        const NAME: &'static str = "dispatch-webhook";
+    }
+
+    /// A job to deliver a webhook event to a single endpoint
+    ///
+    /// Failed deliveries are retried by the job queue.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendWebhookJob {
+        endpoint: Url,
+        event: WebhookEvent,
+    }
+
+    impl SendWebhookJob {
+        /// Create a new job to deliver a webhook event to an endpoint
+        #[must_use]
+        pub fn new(endpoint: Url, event: WebhookEvent) -> Self {
+            Self { endpoint, event }
+        }
+
+        /// The URL of the endpoint to deliver the event to
+        #[must_use]
+        pub fn endpoint(&self) -> &Url {
+            &self.endpoint
+        }
+
+        /// The event to deliver
+        #[must_use]
+        pub fn event(&self) -> &WebhookEvent {
+            &self.event
+        }
+    }
+
+    impl Job for SendWebhookJob {
+        const NAME: &'static str = "send-webhook";
+    }
+
+    /// A job to hand a rendered email to the mail server
+    ///
+    /// If the mail server is unavailable, sending is retried with an
+    /// exponential backoff, by scheduling the job again with [`Self::retry`].
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendEmailJob {
+        sender: Option<String>,
+        recipients: Vec<String>,
+        content: String,
+        #[serde(default)]
+        attempt: u32,
+    }
+
+    impl SendEmailJob {
+        /// Create a new job to send an email
+        ///
+        /// # Parameters
+        ///
+        /// * `sender` - The address the email is sent from
+        /// * `recipients` - The addresses the email is sent to
+        /// * `content` - The formatted email, with its headers
+        #[must_use]
+        pub fn new(sender: Option<String>, recipients: Vec<String>, content: String) -> Self {
+            Self {
+                sender,
+                recipients,
+                content,
+                attempt: 0,
+            }
+        }
+
+        /// The address the email is sent from
+        #[must_use]
+        pub fn sender(&self) -> Option<&str> {
+            self.sender.as_deref()
+        }
+
+        /// The addresses the email is sent to
+        #[must_use]
+        pub fn recipients(&self) -> &[String] {
+            &self.recipients
+        }
+
+        /// The formatted email, with its headers
+        #[must_use]
+        pub fn content(&self) -> &str {
+            &self.content
+        }
+
+        /// How many times sending this email was attempted before
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+
+        /// The job to schedule to try sending the email again
+        #[must_use]
+        pub fn retry(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+    }
+
+    impl Job for SendEmailJob {
+        const NAME: &'static str = "send-email";
+    }
 }
 
 pub use self::jobs::{
-    DeactivateUserJob, DeleteDeviceJob, ProvisionDeviceJob, ProvisionUserJob, ReactivateUserJob,
-    SendAccountRecoveryEmailsJob, SyncDevicesJob, VerifyEmailJob,
+    CheckSuspiciousLoginJob, DeactivateUserJob, DeleteDeviceJob, DispatchWebhookJob,
+    NewDeviceSession, ProvisionDeviceJob, ProvisionUserJob, ReactivateUserJob,
+    ReportHoneytokenUseJob, SendAccountRecoveryEmailsJob, SendCibaNotificationJob, SendEmailJob,
+    SendInactivityWarningJob, SendNewDeviceNotificationJob, SendRefreshTokenReuseNotificationJob,
+    SendWebhookJob, SyncDevicesJob, VerifyEmailJob,
 };
@@ -128,15 +128,25 @@
 #![deny(clippy::future_not_send, missing_docs)]
 #![allow(clippy::module_name_repetitions)]
 
+pub mod cache;
 pub mod clock;
 pub mod pagination;
 pub(crate) mod repository;
 mod utils;
 
 pub mod app_session;
+pub mod audit;
+pub mod changes_feed;
 pub mod compat;
+pub mod email_delivery;
+pub mod event_outbox;
+pub mod expiry_warning;
+pub mod honeytoken;
 pub mod job;
 pub mod oauth2;
+pub mod rendezvous;
+pub mod runtime_resource;
+pub mod signing_key;
 pub mod upstream_oauth2;
 pub mod user;
 
@@ -144,7 +154,8 @@ pub use self::{
     clock::{Clock, SystemClock},
     pagination::{Page, Pagination},
     repository::{
-        BoxRepository, Repository, RepositoryAccess, RepositoryError, RepositoryTransaction,
+        BoxRepository, ReadOnlyRepository, Repository, RepositoryAccess, RepositoryError,
+        RepositoryTransaction,
     },
     utils::{BoxClock, BoxRng, MapErr},
 };
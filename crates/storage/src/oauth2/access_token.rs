@@ -7,6 +7,7 @@
 use async_trait::async_trait;
 use chrono::Duration;
 use mas_data_model::{AccessToken, Session};
+use oauth2_types::scope::Scope;
 use rand_core::RngCore;
 use ulid::Ulid;
 
@@ -60,6 +61,10 @@ pub trait OAuth2AccessTokenRepository: Send + Sync {
     /// * `access_token`: The access token to add
     /// * `expires_after`: The duration after which the access token expires. If
     ///   [`None`] the access token never expires
+    /// * `dpop_jkt`: The JWK SHA-256 thumbprint of the DPoP key to bind the
+    ///   access token to, if any
+    /// * `x5t_s256`: The SHA-256 thumbprint of the client certificate to bind
+    ///   the access token to, if any
     ///
     /// # Errors
     ///
@@ -71,6 +76,35 @@ pub trait OAuth2AccessTokenRepository: Send + Sync {
         session: &Session,
         access_token: String,
         expires_after: Option<Duration>,
+        dpop_jkt: Option<String>,
+        x5t_s256: Option<String>,
+    ) -> Result<AccessToken, Self::Error>;
+
+    /// Add a new access token restricted to a narrower scope than the one of
+    /// its session, for example to hand it to a widget
+    ///
+    /// Returns the newly created access token
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session`: The session the access token is associated with
+    /// * `access_token`: The access token to add
+    /// * `expires_after`: The duration after which the access token expires
+    /// * `scope`: The scope the access token is restricted to
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_restricted(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: String,
+        expires_after: Duration,
+        scope: Scope,
     ) -> Result<AccessToken, Self::Error>;
 
     /// Revoke an access token
@@ -120,6 +154,18 @@ repository_impl!(OAuth2AccessTokenRepository:
         session: &Session,
         access_token: String,
         expires_after: Option<Duration>,
+        dpop_jkt: Option<String>,
+        x5t_s256: Option<String>,
+    ) -> Result<AccessToken, Self::Error>;
+
+    async fn add_restricted(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: String,
+        expires_after: Duration,
+        scope: Scope,
     ) -> Result<AccessToken, Self::Error>;
 
     async fn revoke(
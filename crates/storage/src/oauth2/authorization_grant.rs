@@ -43,6 +43,10 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
     /// * `response_type_id_token`: Whether the `id_token` `response_type` was
     ///   requested
     /// * `requires_consent`: Whether the client explicitly requested consent
+    /// * `requires_login`: Whether the client explicitly requested the user to
+    ///   log in again
+    /// * `acr_values`: The supported Authentication Context Class References
+    ///   the client requested
     ///
     /// # Errors
     ///
@@ -62,6 +66,8 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        requires_login: bool,
+        acr_values: Vec<String>,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     /// Lookup an authorization grant by its ID
@@ -162,6 +168,8 @@ repository_impl!(OAuth2AuthorizationGrantRepository:
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        requires_login: bool,
+        acr_values: Vec<String>,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     async fn lookup(&mut self, id: Ulid) -> Result<Option<AuthorizationGrant>, Self::Error>;
@@ -0,0 +1,209 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{BrowserSession, CibaGrant, Client, Session, User, UserAgent};
+use oauth2_types::scope::Scope;
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// Parameters used to create a new [`CibaGrant`]
+pub struct OAuth2CibaGrantParams<'a> {
+    /// The client which requested the CIBA grant
+    pub client: &'a Client,
+
+    /// The user whose approval is requested
+    pub user: &'a User,
+
+    /// The scope requested by the client
+    pub scope: Scope,
+
+    /// The identifier which the client uses to poll for authorisation
+    pub auth_req_id: String,
+
+    /// The message shown both by the client and to the user
+    pub binding_message: Option<String>,
+
+    /// After how long the request expires
+    pub expires_in: Duration,
+
+    /// IP address from which the request was made
+    pub ip_address: Option<IpAddr>,
+
+    /// The user agent from which the request was made
+    pub user_agent: Option<UserAgent>,
+}
+
+/// An [`OAuth2CibaGrantRepository`] helps interacting with
+/// [`CibaGrant`] saved in the storage backend.
+#[async_trait]
+pub trait OAuth2CibaGrantRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Create a new CIBA grant
+    ///
+    /// Returns the newly created CIBA grant
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator
+    /// * `clock`: The clock used to generate timestamps
+    /// * `params`: The parameters used to create the CIBA grant. See the fields
+    ///   of [`OAuth2CibaGrantParams`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: OAuth2CibaGrantParams<'_>,
+    ) -> Result<CibaGrant, Self::Error>;
+
+    /// Lookup a CIBA grant by its ID
+    ///
+    /// Returns the CIBA grant if found, [`None`] otherwise
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the CIBA grant
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CibaGrant>, Self::Error>;
+
+    /// Lookup a CIBA grant by its `auth_req_id`
+    ///
+    /// Returns the CIBA grant if found, [`None`] otherwise
+    ///
+    /// # Parameters
+    ///
+    /// * `auth_req_id`: The identifier of the CIBA grant given to the client
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_auth_req_id(
+        &mut self,
+        auth_req_id: &str,
+    ) -> Result<Option<CibaGrant>, Self::Error>;
+
+    /// Mark the CIBA grant as approved with the given browser session
+    ///
+    /// Returns the updated CIBA grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `ciba_grant`: The CIBA grant to fulfill
+    /// * `browser_session`: The browser session which was used to approve the
+    ///   CIBA grant
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails or if the
+    /// CIBA grant is not in the [`Pending`] state
+    ///
+    /// [`Pending`]: mas_data_model::CibaGrantState::Pending
+    async fn fulfill(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<CibaGrant, Self::Error>;
+
+    /// Mark the CIBA grant as rejected with the given browser session
+    ///
+    /// Returns the updated CIBA grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `ciba_grant`: The CIBA grant to reject
+    /// * `browser_session`: The browser session which was used to reject the
+    ///   CIBA grant
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails or if the
+    /// CIBA grant is not in the [`Pending`] state
+    ///
+    /// [`Pending`]: mas_data_model::CibaGrantState::Pending
+    async fn reject(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<CibaGrant, Self::Error>;
+
+    /// Mark the CIBA grant as exchanged and store the session which was
+    /// created
+    ///
+    /// Returns the updated CIBA grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `ciba_grant`: The CIBA grant to exchange
+    /// * `session`: The OAuth 2.0 session which was created
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails or if the
+    /// CIBA grant is not in the [`Fulfilled`] state
+    ///
+    /// [`Fulfilled`]: mas_data_model::CibaGrantState::Fulfilled
+    async fn exchange(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        session: &Session,
+    ) -> Result<CibaGrant, Self::Error>;
+}
+
+repository_impl!(OAuth2CibaGrantRepository:
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: OAuth2CibaGrantParams<'_>,
+    ) -> Result<CibaGrant, Self::Error>;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CibaGrant>, Self::Error>;
+
+    async fn find_by_auth_req_id(
+        &mut self,
+        auth_req_id: &str,
+    ) -> Result<Option<CibaGrant>, Self::Error>;
+
+    async fn fulfill(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<CibaGrant, Self::Error>;
+
+    async fn reject(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<CibaGrant, Self::Error>;
+
+    async fn exchange(
+        &mut self,
+        clock: &dyn Clock,
+        ciba_grant: CibaGrant,
+        session: &Session,
+    ) -> Result<CibaGrant, Self::Error>;
+);
@@ -7,7 +7,8 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use async_trait::async_trait;
-use mas_data_model::{Client, User};
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{Client, ClientConsent, User};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
 use oauth2_types::{oidc::ApplicationType, requests::GrantType, scope::Scope};
@@ -91,6 +92,12 @@ pub trait OAuth2ClientRepository: Send + Sync {
     ///   when using the `client_secret_jwt` or `private_key_jwt` authentication
     ///   methods
     /// * `initiate_login_uri`: The URI used to initiate a login, if given
+    /// * `require_pushed_authorization_requests`: Whether the client must use
+    ///   pushed authorization requests
+    /// * `tls_client_auth_subject_dn`: The expected subject DN of the client
+    ///   certificate, when using the `tls_client_auth` authentication method
+    /// * `tls_client_certificate_bound_access_tokens`: Whether the access
+    ///   tokens issued to the client are bound to its certificate
     ///
     /// # Errors
     ///
@@ -116,6 +123,9 @@ pub trait OAuth2ClientRepository: Send + Sync {
         token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
         initiate_login_uri: Option<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
     ) -> Result<Client, Self::Error>;
 
     /// Add or replace a static client
@@ -130,6 +140,22 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// * `jwks`: The client JWKS, if any
     /// * `jwks_uri`: The client JWKS URI, if any
     /// * `redirect_uris`: The list of redirect URIs used by this client
+    /// * `require_pushed_authorization_requests`: Whether the client must use
+    ///   pushed authorization requests
+    /// * `tls_client_auth_subject_dn`: The expected subject DN of the client
+    ///   certificate, when using the `tls_client_auth` authentication method
+    /// * `tls_client_certificate_bound_access_tokens`: Whether the access
+    ///   tokens issued to the client are bound to its certificate
+    /// * `jwt_access_tokens`: Whether the access tokens issued to the client
+    ///   are signed JWTs
+    /// * `access_token_ttl`: The lifetime of the access tokens issued to the
+    ///   client, if overridden
+    /// * `refresh_token_ttl`: How long the refresh tokens issued to the client
+    ///   can be used, if overridden
+    /// * `session_ttl`: The maximum lifetime of the sessions of the client, if
+    ///   overridden
+    /// * `session_inactivity_ttl`: How long the sessions of the client can stay
+    ///   inactive, if overridden
     ///
     /// # Errors
     ///
@@ -143,8 +169,54 @@ pub trait OAuth2ClientRepository: Send + Sync {
         jwks: Option<PublicJsonWebKeySet>,
         jwks_uri: Option<Url>,
         redirect_uris: Vec<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
+        jwt_access_tokens: bool,
+        access_token_ttl: Option<Duration>,
+        refresh_token_ttl: Option<Duration>,
+        session_ttl: Option<Duration>,
+        session_inactivity_ttl: Option<Duration>,
     ) -> Result<Client, Self::Error>;
 
+    /// Set the token with which a dynamically registered client can manage
+    /// its own registration
+    ///
+    /// Returns the updated client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to update
+    /// * `encrypted_registration_access_token`: The encrypted registration
+    ///   access token, or `None` to remove it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_registration_access_token(
+        &mut self,
+        client: Client,
+        encrypted_registration_access_token: Option<String>,
+    ) -> Result<Client, Self::Error>;
+
+    /// Replace the metadata of a dynamically registered client
+    ///
+    /// The redirect URIs, client secret, application type, grant types,
+    /// URIs, keys, algorithms and authentication parameters of the given
+    /// client are saved, everything else is left untouched.
+    ///
+    /// Returns the updated client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client, with its new metadata
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// client is a static client
+    async fn update(&mut self, client: Client) -> Result<Client, Self::Error>;
+
     /// List all static clients
     ///
     /// # Errors
@@ -159,6 +231,8 @@ pub trait OAuth2ClientRepository: Send + Sync {
     ///
     /// * `client`: The client to get the consent for
     /// * `user`: The user to get the consent for
+    /// * `not_before`: If set, consent given or refreshed before this time is
+    ///   ignored
     ///
     /// # Errors
     ///
@@ -167,6 +241,7 @@ pub trait OAuth2ClientRepository: Send + Sync {
         &mut self,
         client: &Client,
         user: &User,
+        not_before: Option<DateTime<Utc>>,
     ) -> Result<Scope, Self::Error>;
 
     /// Give consent for a set of scopes for the given client and user
@@ -191,6 +266,70 @@ pub trait OAuth2ClientRepository: Send + Sync {
         scope: &Scope,
     ) -> Result<(), Self::Error>;
 
+    /// Forget the consent the user has given to all clients
+    ///
+    /// Returns the number of clients the user had given consent to
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The user to forget the consent of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn revoke_all_consents_for_user(&mut self, user: &User) -> Result<usize, Self::Error>;
+
+    /// Forget the consent the user has given to a client, so that they have
+    /// to consent again on their next authorization
+    ///
+    /// Returns `true` if the user had given consent to the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to forget the consent for
+    /// * `user`: The user to forget the consent of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn revoke_consent_for_user(
+        &mut self,
+        client: &Client,
+        user: &User,
+    ) -> Result<bool, Self::Error>;
+
+    /// List the clients the user has given consent to, with the scope they
+    /// consented to and when they last used them, most recently consented
+    /// first
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The user to list the consents of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_consents_for_user(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ClientConsent>, Self::Error>;
+
+    /// Forget the consent all the users have given to a client
+    ///
+    /// Returns the number of users who had given consent to the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to forget the consents for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn revoke_all_consents_for_client(
+        &mut self,
+        client: &Client,
+    ) -> Result<usize, Self::Error>;
+
     /// Delete a client
     ///
     /// # Parameters
@@ -246,6 +385,9 @@ repository_impl!(OAuth2ClientRepository:
         token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
         initiate_login_uri: Option<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
     ) -> Result<Client, Self::Error>;
 
     async fn upsert_static(
@@ -256,8 +398,24 @@ repository_impl!(OAuth2ClientRepository:
         jwks: Option<PublicJsonWebKeySet>,
         jwks_uri: Option<Url>,
         redirect_uris: Vec<Url>,
+        require_pushed_authorization_requests: bool,
+        tls_client_auth_subject_dn: Option<String>,
+        tls_client_certificate_bound_access_tokens: bool,
+        jwt_access_tokens: bool,
+        access_token_ttl: Option<Duration>,
+        refresh_token_ttl: Option<Duration>,
+        session_ttl: Option<Duration>,
+        session_inactivity_ttl: Option<Duration>,
     ) -> Result<Client, Self::Error>;
 
+    async fn set_registration_access_token(
+        &mut self,
+        client: Client,
+        encrypted_registration_access_token: Option<String>,
+    ) -> Result<Client, Self::Error>;
+
+    async fn update(&mut self, client: Client) -> Result<Client, Self::Error>;
+
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
 
     async fn delete(&mut self, client: Client) -> Result<(), Self::Error>;
@@ -268,6 +426,7 @@ repository_impl!(OAuth2ClientRepository:
         &mut self,
         client: &Client,
         user: &User,
+        not_before: Option<DateTime<Utc>>,
     ) -> Result<Scope, Self::Error>;
 
     async fn give_consent_for_user(
@@ -278,4 +437,20 @@ repository_impl!(OAuth2ClientRepository:
         user: &User,
         scope: &Scope,
     ) -> Result<(), Self::Error>;
+
+    async fn revoke_all_consents_for_user(&mut self, user: &User) -> Result<usize, Self::Error>;
+
+    async fn revoke_consent_for_user(
+        &mut self,
+        client: &Client,
+        user: &User,
+    ) -> Result<bool, Self::Error>;
+
+    async fn list_consents_for_user(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ClientConsent>, Self::Error>;
+
+    async fn revoke_all_consents_for_client(&mut self, client: &Client)
+        -> Result<usize, Self::Error>;
 );
@@ -0,0 +1,66 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{repository_impl, Clock};
+
+/// An [`OAuth2JwtBearerAssertionRepository`] keeps track of the JWT bearer
+/// assertions which were used to get tokens, so that they can't be replayed
+#[async_trait]
+pub trait OAuth2JwtBearerAssertionRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record that the assertion with the given issuer and ID was used
+    ///
+    /// Returns `false` if an assertion with the same issuer and ID was
+    /// already used and did not expire yet, in which case it is a replay.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `issuer`: The issuer of the assertion
+    /// * `jti`: The ID of the assertion
+    /// * `expires_at`: When the assertion expires, after which it doesn't need
+    ///   to be remembered
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_use(
+        &mut self,
+        clock: &dyn Clock,
+        issuer: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+
+    /// Forget the assertions which expired
+    ///
+    /// Returns the number of forgotten assertions
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(OAuth2JwtBearerAssertionRepository:
+    async fn record_use(
+        &mut self,
+        clock: &dyn Clock,
+        issuer: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error>;
+);
@@ -0,0 +1,116 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use mas_data_model::{Client, Device, KioskDevice, Session};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// An [`OAuth2KioskDeviceRepository`] helps interacting with [`KioskDevice`]
+/// saved in the storage backend.
+#[async_trait]
+pub trait OAuth2KioskDeviceRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a kiosk device by its ID
+    ///
+    /// Returns the kiosk device if found, [`None`] otherwise
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the kiosk device
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<KioskDevice>, Self::Error>;
+
+    /// Find the kiosk device a client registered with the given Matrix device
+    ///
+    /// Returns the kiosk device if found, [`None`] otherwise
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The kiosk client which registered the device
+    /// * `device`: The Matrix device of the kiosk device
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_device(
+        &mut self,
+        client: &Client,
+        device: &Device,
+    ) -> Result<Option<KioskDevice>, Self::Error>;
+
+    /// Register a new kiosk device for a client
+    ///
+    /// Returns the newly created kiosk device
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator
+    /// * `clock`: The clock used to generate timestamps
+    /// * `client`: The kiosk client registering the device
+    /// * `device`: The Matrix device given to the sessions of the users logged
+    ///   in on the device
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        device: Device,
+    ) -> Result<KioskDevice, Self::Error>;
+
+    /// Set the session of the user currently logged in on a kiosk device
+    ///
+    /// Returns the updated kiosk device
+    ///
+    /// # Parameters
+    ///
+    /// * `kiosk_device`: The kiosk device to update
+    /// * `session`: The session of the user now logged in on the device, or
+    ///   [`None`] if nobody is
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_current_session(
+        &mut self,
+        kiosk_device: KioskDevice,
+        session: Option<&Session>,
+    ) -> Result<KioskDevice, Self::Error>;
+}
+
+repository_impl!(OAuth2KioskDeviceRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<KioskDevice>, Self::Error>;
+
+    async fn find_by_device(
+        &mut self,
+        client: &Client,
+        device: &Device,
+    ) -> Result<Option<KioskDevice>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        device: Device,
+    ) -> Result<KioskDevice, Self::Error>;
+
+    async fn set_current_session(
+        &mut self,
+        kiosk_device: KioskDevice,
+        session: Option<&Session>,
+    ) -> Result<KioskDevice, Self::Error>;
+);
@@ -8,16 +8,24 @@
 
 mod access_token;
 mod authorization_grant;
+mod ciba_grant;
 mod client;
 mod device_code_grant;
+mod jwt_bearer_assertion;
+mod kiosk_device;
+mod pushed_authorization_request;
 mod refresh_token;
 mod session;
 
 pub use self::{
     access_token::OAuth2AccessTokenRepository,
     authorization_grant::OAuth2AuthorizationGrantRepository,
+    ciba_grant::{OAuth2CibaGrantParams, OAuth2CibaGrantRepository},
     client::OAuth2ClientRepository,
     device_code_grant::{OAuth2DeviceCodeGrantParams, OAuth2DeviceCodeGrantRepository},
+    jwt_bearer_assertion::OAuth2JwtBearerAssertionRepository,
+    kiosk_device::OAuth2KioskDeviceRepository,
+    pushed_authorization_request::OAuth2PushedAuthorizationRequestRepository,
     refresh_token::OAuth2RefreshTokenRepository,
     session::{OAuth2SessionFilter, OAuth2SessionRepository},
 };
@@ -59,6 +59,8 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
     /// * `access_token`: The [`AccessToken`] created alongside this
     ///   [`RefreshToken`]
     /// * `refresh_token`: The refresh token to store
+    /// * `dpop_jkt`: The JWK SHA-256 thumbprint of the DPoP key to bind the
+    ///   refresh token to, if any
     ///
     /// # Errors
     ///
@@ -70,6 +72,7 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
         session: &Session,
         access_token: &AccessToken,
         refresh_token: String,
+        dpop_jkt: Option<String>,
     ) -> Result<RefreshToken, Self::Error>;
 
     /// Consume a refresh token
@@ -80,6 +83,7 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
     ///
     /// * `clock`: The clock used to generate timestamps
     /// * `refresh_token`: The [`RefreshToken`] to consume
+    /// * `replaced_by`: The [`RefreshToken`] issued to replace the consumed one
     ///
     /// # Errors
     ///
@@ -89,6 +93,7 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
         &mut self,
         clock: &dyn Clock,
         refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
     ) -> Result<RefreshToken, Self::Error>;
 }
 
@@ -107,11 +112,13 @@ repository_impl!(OAuth2RefreshTokenRepository:
         session: &Session,
         access_token: &AccessToken,
         refresh_token: String,
+        dpop_jkt: Option<String>,
     ) -> Result<RefreshToken, Self::Error>;
 
     async fn consume(
         &mut self,
         clock: &dyn Clock,
         refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
     ) -> Result<RefreshToken, Self::Error>;
 );
@@ -7,13 +7,16 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{BrowserSession, Client, Device, Session, User, UserAgent};
 use oauth2_types::scope::Scope;
 use rand_core::RngCore;
 use ulid::Ulid;
 
-use crate::{pagination::Page, repository_impl, Clock, Pagination};
+use crate::{
+    pagination::{Count, Page},
+    repository_impl, Clock, Pagination,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OAuth2SessionState {
@@ -42,6 +45,7 @@ pub struct OAuth2SessionFilter<'a> {
     scope: Option<&'a Scope>,
     last_active_before: Option<DateTime<Utc>>,
     last_active_after: Option<DateTime<Utc>>,
+    tag: Option<&'a str>,
 }
 
 impl<'a> OAuth2SessionFilter<'a> {
@@ -177,6 +181,21 @@ impl<'a> OAuth2SessionFilter<'a> {
     pub fn device(&self) -> Option<&'a Device> {
         self.device
     }
+
+    /// Only return sessions with the given tag
+    #[must_use]
+    pub fn with_tag(mut self, tag: &'a str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Get the tag filter
+    ///
+    /// Returns [`None`] if no tag filter was set
+    #[must_use]
+    pub fn tag(&self) -> Option<&'a str> {
+        self.tag
+    }
 }
 
 /// An [`OAuth2SessionRepository`] helps interacting with [`Session`]
@@ -319,6 +338,57 @@ pub trait OAuth2SessionRepository: Send + Sync {
         filter: OAuth2SessionFilter<'_>,
     ) -> Result<usize, Self::Error>;
 
+    /// Mark as finished the active [`Session`]s which went past their maximum
+    /// lifetime, or which stayed inactive for too long
+    ///
+    /// The lifetimes set on the clients take precedence over the given
+    /// defaults. Sessions of bot accounts are never finished for inactivity.
+    /// Returns the sessions which were finished.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `default_session_ttl`: The maximum lifetime of sessions, for clients
+    ///   which don't set their own
+    /// * `default_inactivity_ttl`: How long sessions can stay inactive, for
+    ///   clients which don't set their own
+    /// * `limit`: The maximum number of sessions to finish
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        default_session_ttl: Option<Duration>,
+        default_inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<Vec<Session>, Self::Error>;
+
+    /// Mark as finished the active [`Session`]s of the users logged in on
+    /// kiosk devices which went past their maximum lifetime, or which stayed
+    /// inactive for too long
+    ///
+    /// Returns the sessions which were finished.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session_ttl`: The maximum lifetime of the sessions
+    /// * `inactivity_ttl`: How long the sessions can stay inactive
+    /// * `limit`: The maximum number of sessions to finish
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn finish_expired_on_kiosks(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Duration,
+        inactivity_ttl: Duration,
+        limit: usize,
+    ) -> Result<Vec<Session>, Self::Error>;
+
     /// List [`Session`]s matching the given filter and pagination parameters
     ///
     /// # Parameters
@@ -346,6 +416,21 @@ pub trait OAuth2SessionRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn count(&mut self, filter: OAuth2SessionFilter<'_>) -> Result<usize, Self::Error>;
 
+    /// Count the [`Session`] with the given filter, falling back to an
+    /// estimation if there are too many of them to count them exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(
+        &mut self,
+        filter: OAuth2SessionFilter<'_>,
+    ) -> Result<Count, Self::Error>;
+
     /// Record a batch of [`Session`] activity
     ///
     /// # Parameters
@@ -372,6 +457,36 @@ pub trait OAuth2SessionRepository: Send + Sync {
         session: Session,
         user_agent: UserAgent,
     ) -> Result<Session, Self::Error>;
+
+    /// Add a tag to a [`Session`], if it doesn't have it already
+    ///
+    /// The tags are updated atomically, so that concurrent changes to the tags
+    /// of the session are not lost.
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to tag
+    /// * `tag`: The tag to add
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_tag(&mut self, session: Session, tag: &str) -> Result<Session, Self::Error>;
+
+    /// Remove a tag from a [`Session`]
+    ///
+    /// The tags are updated atomically, so that concurrent changes to the tags
+    /// of the session are not lost.
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to untag
+    /// * `tag`: The tag to remove
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove_tag(&mut self, session: Session, tag: &str) -> Result<Session, Self::Error>;
 }
 
 repository_impl!(OAuth2SessionRepository:
@@ -413,6 +528,22 @@ repository_impl!(OAuth2SessionRepository:
         filter: OAuth2SessionFilter<'_>,
     ) -> Result<usize, Self::Error>;
 
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        default_session_ttl: Option<Duration>,
+        default_inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<Vec<Session>, Self::Error>;
+
+    async fn finish_expired_on_kiosks(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Duration,
+        inactivity_ttl: Duration,
+        limit: usize,
+    ) -> Result<Vec<Session>, Self::Error>;
+
     async fn list(
         &mut self,
         filter: OAuth2SessionFilter<'_>,
@@ -420,6 +551,7 @@ repository_impl!(OAuth2SessionRepository:
     ) -> Result<Page<Session>, Self::Error>;
 
     async fn count(&mut self, filter: OAuth2SessionFilter<'_>) -> Result<usize, Self::Error>;
+    async fn estimate_count(&mut self, filter: OAuth2SessionFilter<'_>) -> Result<Count, Self::Error>;
 
     async fn record_batch_activity(
         &mut self,
@@ -431,4 +563,8 @@ repository_impl!(OAuth2SessionRepository:
         session: Session,
         user_agent: UserAgent,
     ) -> Result<Session, Self::Error>;
+
+    async fn add_tag(&mut self, session: Session, tag: &str) -> Result<Session, Self::Error>;
+
+    async fn remove_tag(&mut self, session: Session, tag: &str) -> Result<Session, Self::Error>;
 );
@@ -5,6 +5,11 @@
 // Please see LICENSE in the repository root for full details.
 
 //! Utilities to manage paginated queries.
+//!
+//! Both the GraphQL API and the admin API use cursor-based pagination over the
+//! ULID of the items. As ULIDs are unique and sort by creation time, the order
+//! of the items is total and stable: items inserted concurrently never shift
+//! the pages already returned, they only appear at their place in the ordering.
 
 use thiserror::Error;
 use ulid::Ulid;
@@ -142,6 +147,33 @@ impl Pagination {
     }
 }
 
+/// The total number of items matched by a paginated query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Count {
+    /// The exact number of items
+    Exact(usize),
+
+    /// An estimation of the number of items, used when counting them exactly
+    /// would be too expensive
+    Estimated(usize),
+}
+
+impl Count {
+    /// The number of items, exact or estimated
+    #[must_use]
+    pub const fn value(self) -> usize {
+        match self {
+            Self::Exact(count) | Self::Estimated(count) => count,
+        }
+    }
+
+    /// Whether this count is an estimation
+    #[must_use]
+    pub const fn is_estimated(self) -> bool {
+        matches!(self, Self::Estimated(_))
+    }
+}
+
 /// A page of results returned by a paginated query
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Page<T> {
@@ -0,0 +1,137 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to manage the rendezvous sessions used to sign in with a QR
+//! code
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::RendezvousSession;
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// A [`RendezvousSessionRepository`] helps interacting with
+/// [`RendezvousSession`] saved in the storage backend
+#[async_trait]
+pub trait RendezvousSessionRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a [`RendezvousSession`] by its ID
+    ///
+    /// Returns `None` if no [`RendezvousSession`] was found. Expired sessions
+    /// are returned until they are cleaned up.
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`RendezvousSession`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<RendezvousSession>, Self::Error>;
+
+    /// Start a new [`RendezvousSession`]
+    ///
+    /// Returns the newly created [`RendezvousSession`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `ttl`: How long the session lasts
+    /// * `content_type`: The content type of the first message
+    /// * `content`: The first message
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        ttl: Duration,
+        content_type: String,
+        content: Vec<u8>,
+    ) -> Result<RendezvousSession, Self::Error>;
+
+    /// Replace the content of a [`RendezvousSession`], if it didn't change
+    /// since it was looked up
+    ///
+    /// Returns the updated [`RendezvousSession`], or `None` if its content
+    /// was replaced in the meantime
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session`: The [`RendezvousSession`] to update
+    /// * `content_type`: The content type of the new message
+    /// * `content`: The new message
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn update(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: RendezvousSession,
+        content_type: String,
+        content: Vec<u8>,
+    ) -> Result<Option<RendezvousSession>, Self::Error>;
+
+    /// Delete a [`RendezvousSession`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`RendezvousSession`] to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove(&mut self, session: RendezvousSession) -> Result<(), Self::Error>;
+
+    /// Delete the [`RendezvousSession`]s which expired
+    ///
+    /// Returns the number of deleted sessions
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(RendezvousSessionRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<RendezvousSession>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        ttl: Duration,
+        content_type: String,
+        content: Vec<u8>,
+    ) -> Result<RendezvousSession, Self::Error>;
+
+    async fn update(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: RendezvousSession,
+        content_type: String,
+        content: Vec<u8>,
+    ) -> Result<Option<RendezvousSession>, Self::Error>;
+
+    async fn remove(&mut self, session: RendezvousSession) -> Result<(), Self::Error>;
+
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error>;
+);
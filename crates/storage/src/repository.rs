@@ -9,22 +9,37 @@ use thiserror::Error;
 
 use crate::{
     app_session::AppSessionRepository,
+    audit::AuditEventRepository,
+    changes_feed::ChangesFeedRepository,
     compat::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
     },
+    email_delivery::EmailDeliveryFailureRepository,
+    event_outbox::EventOutboxRepository,
+    expiry_warning::ExpiryWarningRepository,
+    honeytoken::HoneytokenRepository,
     job::JobRepository,
     oauth2::{
-        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
-        OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2CibaGrantRepository,
+        OAuth2ClientRepository, OAuth2DeviceCodeGrantRepository,
+        OAuth2JwtBearerAssertionRepository, OAuth2KioskDeviceRepository,
+        OAuth2PushedAuthorizationRequestRepository, OAuth2RefreshTokenRepository,
+        OAuth2SessionRepository,
     },
+    rendezvous::RendezvousSessionRepository,
+    runtime_resource::RuntimeResourceRepository,
+    signing_key::SigningKeyRepository,
     upstream_oauth2::{
+        UpstreamOAuthEmailChangeRepository, UpstreamOAuthFunnelRepository,
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
     user::{
-        BrowserSessionRepository, UserEmailRepository, UserPasswordRepository,
-        UserRecoveryRepository, UserRepository, UserTermsRepository,
+        BrowserSessionRepository, UserActivityDigestRepository, UserEmailRepository,
+        UserEmergencyAccessRepository, UserKnownDeviceRepository,
+        UserNotificationPreferencesRepository, UserPasswordRepository, UserRecoveryRepository,
+        UserRepository, UserTermsRepository, UserTrustedBrowserRepository,
     },
 };
 
@@ -58,6 +73,21 @@ impl RepositoryError {
 /// A type-erased [`Repository`]
 pub type BoxRepository = Box<dyn Repository<RepositoryError> + Send + Sync + 'static>;
 
+/// A [`BoxRepository`] meant for pure lookups.
+///
+/// It may be backed by a read-only replica of the database, which can lag
+/// slightly behind the primary. Any write done through it will fail, and
+/// there is no need to save it.
+pub struct ReadOnlyRepository(pub BoxRepository);
+
+impl ReadOnlyRepository {
+    /// Get the inner [`BoxRepository`]
+    #[must_use]
+    pub fn into_inner(self) -> BoxRepository {
+        self.0
+    }
+}
+
 /// A [`RepositoryTransaction`] can be saved or cancelled, after a series
 /// of operations.
 pub trait RepositoryTransaction {
@@ -115,6 +145,16 @@ pub trait RepositoryAccess: Send {
         &'c mut self,
     ) -> Box<dyn UpstreamOAuthSessionRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`UpstreamOAuthFunnelRepository`]
+    fn upstream_oauth_funnel<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthFunnelRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UpstreamOAuthEmailChangeRepository`]
+    fn upstream_oauth_email_change<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthEmailChangeRepository<Error = Self::Error> + 'c>;
+
     /// Get an [`UserRepository`]
     fn user<'c>(&'c mut self) -> Box<dyn UserRepository<Error = Self::Error> + 'c>;
 
@@ -132,6 +172,31 @@ pub trait RepositoryAccess: Send {
     /// Get an [`UserTermsRepository`]
     fn user_terms<'c>(&'c mut self) -> Box<dyn UserTermsRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`UserEmergencyAccessRepository`]
+    fn user_emergency_access<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserEmergencyAccessRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserActivityDigestRepository`]
+    fn user_activity_digest<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserActivityDigestRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserKnownDeviceRepository`]
+    fn user_known_device<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserKnownDeviceRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserNotificationPreferencesRepository`]
+    fn user_notification_preferences<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserNotificationPreferencesRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserTrustedBrowserRepository`]
+    fn user_trusted_browser<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserTrustedBrowserRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`BrowserSessionRepository`]
     fn browser_session<'c>(
         &'c mut self,
@@ -169,6 +234,26 @@ pub trait RepositoryAccess: Send {
         &'c mut self,
     ) -> Box<dyn OAuth2DeviceCodeGrantRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`OAuth2CibaGrantRepository`]
+    fn oauth2_ciba_grant<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2CibaGrantRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`OAuth2PushedAuthorizationRequestRepository`]
+    fn oauth2_pushed_authorization_request<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2PushedAuthorizationRequestRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`OAuth2JwtBearerAssertionRepository`]
+    fn oauth2_jwt_bearer_assertion<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2JwtBearerAssertionRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`OAuth2KioskDeviceRepository`]
+    fn oauth2_kiosk_device<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2KioskDeviceRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`CompatSessionRepository`]
     fn compat_session<'c>(
         &'c mut self,
@@ -191,6 +276,41 @@ pub trait RepositoryAccess: Send {
 
     /// Get a [`JobRepository`]
     fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`AuditEventRepository`]
+    fn audit_event<'c>(&'c mut self) -> Box<dyn AuditEventRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`EmailDeliveryFailureRepository`]
+    fn email_delivery_failure<'c>(
+        &'c mut self,
+    ) -> Box<dyn EmailDeliveryFailureRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`HoneytokenRepository`]
+    fn honeytoken<'c>(&'c mut self) -> Box<dyn HoneytokenRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`ExpiryWarningRepository`]
+    fn expiry_warning<'c>(
+        &'c mut self,
+    ) -> Box<dyn ExpiryWarningRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`EventOutboxRepository`]
+    fn event_outbox<'c>(&'c mut self) -> Box<dyn EventOutboxRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`ChangesFeedRepository`]
+    fn changes_feed<'c>(&'c mut self) -> Box<dyn ChangesFeedRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`SigningKeyRepository`]
+    fn signing_key<'c>(&'c mut self) -> Box<dyn SigningKeyRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`RuntimeResourceRepository`]
+    fn runtime_resource<'c>(
+        &'c mut self,
+    ) -> Box<dyn RuntimeResourceRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`RendezvousSessionRepository`]
+    fn rendezvous_session<'c>(
+        &'c mut self,
+    ) -> Box<dyn RendezvousSessionRepository<Error = Self::Error> + 'c>;
 }
 
 /// Implementations of the [`RepositoryAccess`], [`RepositoryTransaction`] and
@@ -201,17 +321,29 @@ mod impls {
     use super::RepositoryAccess;
     use crate::{
         app_session::AppSessionRepository,
+        audit::AuditEventRepository,
+        changes_feed::ChangesFeedRepository,
         compat::{
             CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
             CompatSsoLoginRepository,
         },
+        email_delivery::EmailDeliveryFailureRepository,
+        event_outbox::EventOutboxRepository,
+        expiry_warning::ExpiryWarningRepository,
+        honeytoken::HoneytokenRepository,
         job::JobRepository,
         oauth2::{
             OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
-            OAuth2ClientRepository, OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository,
+            OAuth2CibaGrantRepository, OAuth2ClientRepository, OAuth2DeviceCodeGrantRepository,
+            OAuth2JwtBearerAssertionRepository, OAuth2KioskDeviceRepository,
+            OAuth2PushedAuthorizationRequestRepository, OAuth2RefreshTokenRepository,
             OAuth2SessionRepository,
         },
+        rendezvous::RendezvousSessionRepository,
+        runtime_resource::RuntimeResourceRepository,
+        signing_key::SigningKeyRepository,
         upstream_oauth2::{
+            UpstreamOAuthEmailChangeRepository, UpstreamOAuthFunnelRepository,
             UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
             UpstreamOAuthSessionRepository,
         },
@@ -288,6 +420,24 @@ mod impls {
             ))
         }
 
+        fn upstream_oauth_funnel<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthFunnelRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.upstream_oauth_funnel(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn upstream_oauth_email_change<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthEmailChangeRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.upstream_oauth_email_change(),
+                &mut self.mapper,
+            ))
+        }
+
         fn user<'c>(&'c mut self) -> Box<dyn UserRepository<Error = Self::Error> + 'c> {
             Box::new(MapErr::new(self.inner.user(), &mut self.mapper))
         }
@@ -312,6 +462,52 @@ mod impls {
             Box::new(MapErr::new(self.inner.user_terms(), &mut self.mapper))
         }
 
+        fn user_emergency_access<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserEmergencyAccessRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_emergency_access(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn user_activity_digest<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserActivityDigestRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_activity_digest(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn user_known_device<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserKnownDeviceRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_known_device(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn user_notification_preferences<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserNotificationPreferencesRepository<Error = Self::Error> + 'c>
+        {
+            Box::new(MapErr::new(
+                self.inner.user_notification_preferences(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn user_trusted_browser<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserTrustedBrowserRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_trusted_browser(),
+                &mut self.mapper,
+            ))
+        }
+
         fn browser_session<'c>(
             &'c mut self,
         ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -372,6 +568,42 @@ mod impls {
             ))
         }
 
+        fn oauth2_ciba_grant<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2CibaGrantRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.oauth2_ciba_grant(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn oauth2_pushed_authorization_request<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2PushedAuthorizationRequestRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.oauth2_pushed_authorization_request(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn oauth2_jwt_bearer_assertion<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2JwtBearerAssertionRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.oauth2_jwt_bearer_assertion(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn oauth2_kiosk_device<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2KioskDeviceRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.oauth2_kiosk_device(),
+                &mut self.mapper,
+            ))
+        }
+
         fn compat_session<'c>(
             &'c mut self,
         ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
@@ -405,6 +637,64 @@ mod impls {
         fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
             Box::new(MapErr::new(self.inner.job(), &mut self.mapper))
         }
+
+        fn audit_event<'c>(
+            &'c mut self,
+        ) -> Box<dyn AuditEventRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.audit_event(), &mut self.mapper))
+        }
+
+        fn email_delivery_failure<'c>(
+            &'c mut self,
+        ) -> Box<dyn EmailDeliveryFailureRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.email_delivery_failure(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn honeytoken<'c>(&'c mut self) -> Box<dyn HoneytokenRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.honeytoken(), &mut self.mapper))
+        }
+
+        fn expiry_warning<'c>(
+            &'c mut self,
+        ) -> Box<dyn ExpiryWarningRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.expiry_warning(), &mut self.mapper))
+        }
+
+        fn event_outbox<'c>(
+            &'c mut self,
+        ) -> Box<dyn EventOutboxRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.event_outbox(), &mut self.mapper))
+        }
+
+        fn changes_feed<'c>(
+            &'c mut self,
+        ) -> Box<dyn ChangesFeedRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.changes_feed(), &mut self.mapper))
+        }
+
+        fn signing_key<'c>(
+            &'c mut self,
+        ) -> Box<dyn SigningKeyRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.signing_key(), &mut self.mapper))
+        }
+
+        fn runtime_resource<'c>(
+            &'c mut self,
+        ) -> Box<dyn RuntimeResourceRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.runtime_resource(), &mut self.mapper))
+        }
+
+        fn rendezvous_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn RendezvousSessionRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.rendezvous_session(),
+                &mut self.mapper,
+            ))
+        }
     }
 
     impl<R: RepositoryAccess + ?Sized> RepositoryAccess for Box<R> {
@@ -428,6 +718,18 @@ mod impls {
             (**self).upstream_oauth_session()
         }
 
+        fn upstream_oauth_funnel<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthFunnelRepository<Error = Self::Error> + 'c> {
+            (**self).upstream_oauth_funnel()
+        }
+
+        fn upstream_oauth_email_change<'c>(
+            &'c mut self,
+        ) -> Box<dyn UpstreamOAuthEmailChangeRepository<Error = Self::Error> + 'c> {
+            (**self).upstream_oauth_email_change()
+        }
+
         fn user<'c>(&'c mut self) -> Box<dyn UserRepository<Error = Self::Error> + 'c> {
             (**self).user()
         }
@@ -452,6 +754,37 @@ mod impls {
             (**self).user_terms()
         }
 
+        fn user_emergency_access<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserEmergencyAccessRepository<Error = Self::Error> + 'c> {
+            (**self).user_emergency_access()
+        }
+
+        fn user_activity_digest<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserActivityDigestRepository<Error = Self::Error> + 'c> {
+            (**self).user_activity_digest()
+        }
+
+        fn user_known_device<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserKnownDeviceRepository<Error = Self::Error> + 'c> {
+            (**self).user_known_device()
+        }
+
+        fn user_notification_preferences<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserNotificationPreferencesRepository<Error = Self::Error> + 'c>
+        {
+            (**self).user_notification_preferences()
+        }
+
+        fn user_trusted_browser<'c>(
+            &'c mut self,
+        ) -> Box<dyn crate::user::UserTrustedBrowserRepository<Error = Self::Error> + 'c> {
+            (**self).user_trusted_browser()
+        }
+
         fn browser_session<'c>(
             &'c mut self,
         ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -500,6 +833,30 @@ mod impls {
             (**self).oauth2_device_code_grant()
         }
 
+        fn oauth2_ciba_grant<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2CibaGrantRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_ciba_grant()
+        }
+
+        fn oauth2_pushed_authorization_request<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2PushedAuthorizationRequestRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_pushed_authorization_request()
+        }
+
+        fn oauth2_jwt_bearer_assertion<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2JwtBearerAssertionRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_jwt_bearer_assertion()
+        }
+
+        fn oauth2_kiosk_device<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2KioskDeviceRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_kiosk_device()
+        }
+
         fn compat_session<'c>(
             &'c mut self,
         ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
@@ -527,5 +884,57 @@ mod impls {
         fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
             (**self).job()
         }
+
+        fn audit_event<'c>(
+            &'c mut self,
+        ) -> Box<dyn AuditEventRepository<Error = Self::Error> + 'c> {
+            (**self).audit_event()
+        }
+
+        fn email_delivery_failure<'c>(
+            &'c mut self,
+        ) -> Box<dyn EmailDeliveryFailureRepository<Error = Self::Error> + 'c> {
+            (**self).email_delivery_failure()
+        }
+
+        fn honeytoken<'c>(&'c mut self) -> Box<dyn HoneytokenRepository<Error = Self::Error> + 'c> {
+            (**self).honeytoken()
+        }
+
+        fn expiry_warning<'c>(
+            &'c mut self,
+        ) -> Box<dyn ExpiryWarningRepository<Error = Self::Error> + 'c> {
+            (**self).expiry_warning()
+        }
+
+        fn event_outbox<'c>(
+            &'c mut self,
+        ) -> Box<dyn EventOutboxRepository<Error = Self::Error> + 'c> {
+            (**self).event_outbox()
+        }
+
+        fn changes_feed<'c>(
+            &'c mut self,
+        ) -> Box<dyn ChangesFeedRepository<Error = Self::Error> + 'c> {
+            (**self).changes_feed()
+        }
+
+        fn signing_key<'c>(
+            &'c mut self,
+        ) -> Box<dyn SigningKeyRepository<Error = Self::Error> + 'c> {
+            (**self).signing_key()
+        }
+
+        fn runtime_resource<'c>(
+            &'c mut self,
+        ) -> Box<dyn RuntimeResourceRepository<Error = Self::Error> + 'c> {
+            (**self).runtime_resource()
+        }
+
+        fn rendezvous_session<'c>(
+            &'c mut self,
+        ) -> Box<dyn RendezvousSessionRepository<Error = Self::Error> + 'c> {
+            (**self).rendezvous_session()
+        }
     }
 }
@@ -0,0 +1,86 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to keep track of the resources disabled at runtime
+
+use async_trait::async_trait;
+use mas_data_model::{DisabledResource, RuntimeResource};
+
+use crate::{repository_impl, Clock};
+
+/// A [`RuntimeResourceRepository`] keeps track of the [`RuntimeResource`]s
+/// administrators disabled, so that all the instances of the service agree on
+/// it and it survives restarts
+#[async_trait]
+pub trait RuntimeResourceRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// List all the disabled resources
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_disabled(&mut self) -> Result<Vec<DisabledResource>, Self::Error>;
+
+    /// Check whether a resource is disabled
+    ///
+    /// # Parameters
+    ///
+    /// * `resource`: The resource to check
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn is_disabled(&mut self, resource: RuntimeResource) -> Result<bool, Self::Error>;
+
+    /// Disable a resource
+    ///
+    /// If the resource is already disabled, only the reason is updated
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `resource`: The resource to disable
+    /// * `reason`: Why the resource is disabled
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn disable(
+        &mut self,
+        clock: &dyn Clock,
+        resource: RuntimeResource,
+        reason: Option<String>,
+    ) -> Result<DisabledResource, Self::Error>;
+
+    /// Enable a resource again
+    ///
+    /// Returns `false` if the resource was not disabled
+    ///
+    /// # Parameters
+    ///
+    /// * `resource`: The resource to enable
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn enable(&mut self, resource: RuntimeResource) -> Result<bool, Self::Error>;
+}
+
+repository_impl!(RuntimeResourceRepository:
+    async fn list_disabled(&mut self) -> Result<Vec<DisabledResource>, Self::Error>;
+
+    async fn is_disabled(&mut self, resource: RuntimeResource) -> Result<bool, Self::Error>;
+
+    async fn disable(
+        &mut self,
+        clock: &dyn Clock,
+        resource: RuntimeResource,
+        reason: Option<String>,
+    ) -> Result<DisabledResource, Self::Error>;
+
+    async fn enable(&mut self, resource: RuntimeResource) -> Result<bool, Self::Error>;
+);
@@ -0,0 +1,107 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Repositories to interact with the signing keys generated by the automated
+//! key rotation
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::SigningKey;
+use mas_iana::jose::JsonWebSignatureAlg;
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`SigningKeyRepository`] helps interacting with the signing keys generated
+/// by the automated key rotation
+#[async_trait]
+pub trait SigningKeyRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lock the signing keys until the end of the transaction, so that only
+    /// one worker rotates them at a time
+    ///
+    /// Reading the keys is not blocked by this lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lock(&mut self) -> Result<(), Self::Error>;
+
+    /// List all the signing keys which were not retired yet, ordered by the
+    /// time they activate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_unretired(&mut self) -> Result<Vec<SigningKey>, Self::Error>;
+
+    /// Add a new signing key
+    ///
+    /// Returns the newly created signing key
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `kid`: The key ID, as published in the JWKS
+    /// * `algorithm`: The signing algorithm this key is used for
+    /// * `encrypted_key`: The private key, PEM-encoded and encrypted
+    /// * `activates_at`: When the key starts being used to sign
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kid: String,
+        algorithm: JsonWebSignatureAlg,
+        encrypted_key: String,
+        activates_at: DateTime<Utc>,
+    ) -> Result<SigningKey, Self::Error>;
+
+    /// Retire a signing key, removing it from the JWKS
+    ///
+    /// Returns the retired signing key
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `signing_key`: The signing key to retire
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn retire(
+        &mut self,
+        clock: &dyn Clock,
+        signing_key: SigningKey,
+    ) -> Result<SigningKey, Self::Error>;
+}
+
+repository_impl!(SigningKeyRepository:
+    async fn lock(&mut self) -> Result<(), Self::Error>;
+
+    async fn list_unretired(&mut self) -> Result<Vec<SigningKey>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        kid: String,
+        algorithm: JsonWebSignatureAlg,
+        encrypted_key: String,
+        activates_at: DateTime<Utc>,
+    ) -> Result<SigningKey, Self::Error>;
+
+    async fn retire(
+        &mut self,
+        clock: &dyn Clock,
+        signing_key: SigningKey,
+    ) -> Result<SigningKey, Self::Error>;
+);
@@ -0,0 +1,252 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use mas_data_model::{
+    UpstreamOAuthEmailChange, UpstreamOAuthEmailChangeResolution, UpstreamOAuthLink, User,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{
+    pagination::{Count, Page},
+    repository_impl, Clock, Pagination,
+};
+
+/// The state of an upstream email change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamOAuthEmailChangeState {
+    /// The change is waiting for an administrator to review it
+    Pending,
+
+    /// The change was either applied or dismissed
+    Resolved,
+}
+
+impl UpstreamOAuthEmailChangeState {
+    /// Returns `true` if the state is [`Pending`].
+    ///
+    /// [`Pending`]: UpstreamOAuthEmailChangeState::Pending
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Pending)
+    }
+
+    /// Returns `true` if the state is [`Resolved`].
+    ///
+    /// [`Resolved`]: UpstreamOAuthEmailChangeState::Resolved
+    #[must_use]
+    pub fn is_resolved(&self) -> bool {
+        matches!(self, Self::Resolved)
+    }
+}
+
+/// Filter parameters for listing upstream email changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct UpstreamOAuthEmailChangeFilter<'a> {
+    user: Option<&'a User>,
+    state: Option<UpstreamOAuthEmailChangeState>,
+}
+
+impl<'a> UpstreamOAuthEmailChangeFilter<'a> {
+    /// Create a new [`UpstreamOAuthEmailChangeFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the user whose email address changed
+    #[must_use]
+    pub fn for_user(mut self, user: &'a User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Get the user filter
+    ///
+    /// Returns [`None`] if no filter was set
+    #[must_use]
+    pub fn user(&self) -> Option<&User> {
+        self.user
+    }
+
+    /// Only return changes waiting for a review
+    #[must_use]
+    pub fn pending_only(mut self) -> Self {
+        self.state = Some(UpstreamOAuthEmailChangeState::Pending);
+        self
+    }
+
+    /// Only return changes which were applied or dismissed
+    #[must_use]
+    pub fn resolved_only(mut self) -> Self {
+        self.state = Some(UpstreamOAuthEmailChangeState::Resolved);
+        self
+    }
+
+    /// Get the state filter
+    ///
+    /// Returns [`None`] if no filter was set
+    #[must_use]
+    pub fn state(&self) -> Option<UpstreamOAuthEmailChangeState> {
+        self.state
+    }
+}
+
+/// An [`UpstreamOAuthEmailChangeRepository`] helps interacting with the email
+/// address changes asserted by upstream providers which need a review
+#[async_trait]
+pub trait UpstreamOAuthEmailChangeRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup an upstream email change by its ID
+    ///
+    /// Returns `None` if the change does not exist
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the change to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthEmailChange>, Self::Error>;
+
+    /// Record an email address change which needs a review
+    ///
+    /// Returns the newly created change
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `upstream_oauth_link`: The link on which the change was detected
+    /// * `user`: The user the link belongs to
+    /// * `conflicting_user`: Another user who already has the new email
+    ///   address, if any
+    /// * `previous_email`: The email address previously asserted by the
+    ///   provider
+    /// * `new_email`: The email address now asserted by the provider
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    #[allow(clippy::too_many_arguments)]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        user: &User,
+        conflicting_user: Option<&User>,
+        previous_email: Option<String>,
+        new_email: String,
+    ) -> Result<UpstreamOAuthEmailChange, Self::Error>;
+
+    /// Mark an email address change as resolved
+    ///
+    /// Returns the updated change
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `change`: The change to resolve
+    /// * `resolution`: Whether the change was applied or dismissed
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn resolve(
+        &mut self,
+        clock: &dyn Clock,
+        change: UpstreamOAuthEmailChange,
+        resolution: UpstreamOAuthEmailChangeResolution,
+    ) -> Result<UpstreamOAuthEmailChange, Self::Error>;
+
+    /// List [`UpstreamOAuthEmailChange`] with the given filter and pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter to apply
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<UpstreamOAuthEmailChange>, Self::Error>;
+
+    /// Count the number of [`UpstreamOAuthEmailChange`] with the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter to apply
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+    ) -> Result<usize, Self::Error>;
+
+    /// Count the [`UpstreamOAuthEmailChange`] with the given filter, falling
+    /// back to an estimation if there are too many of them to count them
+    /// exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter to apply
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+    ) -> Result<Count, Self::Error>;
+}
+
+repository_impl!(UpstreamOAuthEmailChangeRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthEmailChange>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        user: &User,
+        conflicting_user: Option<&User>,
+        previous_email: Option<String>,
+        new_email: String,
+    ) -> Result<UpstreamOAuthEmailChange, Self::Error>;
+
+    async fn resolve(
+        &mut self,
+        clock: &dyn Clock,
+        change: UpstreamOAuthEmailChange,
+        resolution: UpstreamOAuthEmailChangeResolution,
+    ) -> Result<UpstreamOAuthEmailChange, Self::Error>;
+
+    async fn list(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<UpstreamOAuthEmailChange>, Self::Error>;
+
+    async fn count(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+    ) -> Result<usize, Self::Error>;
+
+    async fn estimate_count(
+        &mut self,
+        filter: UpstreamOAuthEmailChangeFilter<'_>,
+    ) -> Result<Count, Self::Error>;
+);
@@ -0,0 +1,129 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    UpstreamOAuthFunnelStage, UpstreamOAuthFunnelStageReport, UpstreamOAuthProvider, User,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// An [`UpstreamOAuthFunnelRepository`] helps keeping track of the stages of
+/// the signup funnel users go through with upstream OAuth 2.0 providers
+#[async_trait]
+pub trait UpstreamOAuthFunnelRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record that a stage of the funnel was reached, or that a user dropped
+    /// off at that stage
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `provider_id`: The ID of the upstream OAuth 2.0 provider
+    /// * `user`: The user who reached the stage, if the account exists
+    /// * `stage`: The stage of the funnel
+    /// * `dropoff_reason`: Why the user dropped off at this stage, `None` if
+    ///   the stage was reached
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        provider_id: Ulid,
+        user: Option<&User>,
+        stage: UpstreamOAuthFunnelStage,
+        dropoff_reason: Option<&str>,
+    ) -> Result<(), Self::Error>;
+
+    /// Find the provider the user signed up with, if they did not reach the
+    /// given stage yet
+    ///
+    /// Returns `None` if the user did not sign up through an upstream
+    /// provider, or if they already reached the stage
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The user to look for
+    /// * `stage`: The stage the user should not have reached yet
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn signup_provider_pending(
+        &mut self,
+        user: &User,
+        stage: UpstreamOAuthFunnelStage,
+    ) -> Result<Option<Ulid>, Self::Error>;
+
+    /// Count how many times each stage of the funnel was reached through the
+    /// given provider, and how many users dropped off at each stage
+    ///
+    /// Returns one entry per stage, in the order users go through them
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The upstream OAuth 2.0 provider
+    /// * `since`: Only count events recorded at or after this time
+    /// * `until`: Only count events recorded before this time
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn report(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UpstreamOAuthFunnelStageReport>, Self::Error>;
+
+    /// Delete the events recorded before the given time
+    ///
+    /// Returns the number of events deleted
+    ///
+    /// # Parameters
+    ///
+    /// * `before`: Events recorded before this time are deleted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(UpstreamOAuthFunnelRepository:
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        provider_id: Ulid,
+        user: Option<&User>,
+        stage: UpstreamOAuthFunnelStage,
+        dropoff_reason: Option<&str>,
+    ) -> Result<(), Self::Error>;
+
+    async fn signup_provider_pending(
+        &mut self,
+        user: &User,
+        stage: UpstreamOAuthFunnelStage,
+    ) -> Result<Option<Ulid>, Self::Error>;
+
+    async fn report(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UpstreamOAuthFunnelStageReport>, Self::Error>;
+
+    async fn cleanup_before(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+);
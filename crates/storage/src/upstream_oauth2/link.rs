@@ -158,6 +158,25 @@ pub trait UpstreamOAuthLinkRepository: Send + Sync {
         user: &User,
     ) -> Result<(), Self::Error>;
 
+    /// Set the email address last asserted by the upstream provider for this
+    /// link
+    ///
+    /// Returns the updated upstream OAuth link
+    ///
+    /// # Parameters
+    ///
+    /// * `upstream_oauth_link`: The upstream OAuth link to update
+    /// * `asserted_email`: The email address asserted by the provider, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_asserted_email(
+        &mut self,
+        upstream_oauth_link: UpstreamOAuthLink,
+        asserted_email: Option<String>,
+    ) -> Result<UpstreamOAuthLink, Self::Error>;
+
     /// List [`UpstreamOAuthLink`] with the given filter and pagination
     ///
     /// # Parameters
@@ -209,6 +228,12 @@ repository_impl!(UpstreamOAuthLinkRepository:
         user: &User,
     ) -> Result<(), Self::Error>;
 
+    async fn set_asserted_email(
+        &mut self,
+        upstream_oauth_link: UpstreamOAuthLink,
+        asserted_email: Option<String>,
+    ) -> Result<UpstreamOAuthLink, Self::Error>;
+
     async fn list(
         &mut self,
         filter: UpstreamOAuthLinkFilter<'_>,
@@ -7,11 +7,18 @@
 //! Repositories to interact with entities related to the upstream OAuth 2.0
 //! providers
 
+mod email_change;
+mod funnel;
 mod link;
 mod provider;
 mod session;
 
 pub use self::{
+    email_change::{
+        UpstreamOAuthEmailChangeFilter, UpstreamOAuthEmailChangeRepository,
+        UpstreamOAuthEmailChangeState,
+    },
+    funnel::UpstreamOAuthFunnelRepository,
     link::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
     provider::{
         UpstreamOAuthProviderFilter, UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository,
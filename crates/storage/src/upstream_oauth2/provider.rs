@@ -9,9 +9,10 @@ use std::marker::PhantomData;
 use async_trait::async_trait;
 use mas_data_model::{
     UpstreamOAuthProvider, UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderDiscoveryMode,
-    UpstreamOAuthProviderPkceMode,
+    UpstreamOAuthProviderParMode, UpstreamOAuthProviderPkceMode, UpstreamOAuthProviderResponseMode,
+    UpstreamOAuthProviderTokenAuthMethod,
 };
-use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use mas_iana::jose::JsonWebSignatureAlg;
 use oauth2_types::scope::Scope;
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -35,7 +36,7 @@ pub struct UpstreamOAuthProviderParams {
     pub scope: Scope,
 
     /// The token endpoint authentication method
-    pub token_endpoint_auth_method: OAuthClientAuthenticationMethod,
+    pub token_endpoint_auth_method: UpstreamOAuthProviderTokenAuthMethod,
 
     /// The JWT signing algorithm to use when then `client_secret_jwt` or
     /// `private_key_jwt` authentication methods are used
@@ -67,8 +68,45 @@ pub struct UpstreamOAuthProviderParams {
     /// How should PKCE be used
     pub pkce_mode: UpstreamOAuthProviderPkceMode,
 
+    /// When should pushed authorization requests be used
+    pub par_mode: UpstreamOAuthProviderParMode,
+
+    /// The URL to use as the pushed authorization request endpoint. If `None`,
+    /// the URL will be discovered
+    pub pushed_authorization_request_endpoint_override: Option<Url>,
+
+    /// The JWT signing algorithm to use to sign the authorization request as a
+    /// request object. If `None`, the request is only signed if the provider
+    /// requires it
+    pub request_object_signing_alg: Option<JsonWebSignatureAlg>,
+
+    /// How the provider should send the authorization response back. If
+    /// `None`, the provider default is used
+    pub response_mode: Option<UpstreamOAuthProviderResponseMode>,
+
     /// Additional parameters to include in the authorization request
     pub additional_authorization_parameters: Vec<(String, String)>,
+
+    /// Whether the provider can initiate a login
+    pub allow_idp_initiated_login: bool,
+
+    /// The ID of the client on which the user lands after a login initiated by
+    /// the provider. If `None`, the user lands on the account page.
+    pub idp_initiated_login_client_id: Option<Ulid>,
+
+    /// Whether to log the user out of the provider when their session ends
+    pub propagate_logout: bool,
+
+    /// The URL to use as the end session endpoint. If `None`, the URL will be
+    /// discovered
+    pub end_session_endpoint_override: Option<Url>,
+
+    /// Whether to fetch the user profile from the userinfo endpoint
+    pub fetch_userinfo: bool,
+
+    /// The URL to use as the userinfo endpoint. If `None`, the URL will be
+    /// discovered
+    pub userinfo_endpoint_override: Option<Url>,
 }
 
 /// Filter parameters for listing upstream OAuth 2.0 providers
@@ -0,0 +1,146 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{User, UserActivityDigest};
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserActivityDigestRepository`] helps interacting with the
+/// [`UserActivityDigest`] subscriptions saved in the storage backend
+#[async_trait]
+pub trait UserActivityDigestRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Find the [`UserActivityDigest`] subscription of a [`User`]
+    ///
+    /// Returns `None` if the user is not subscribed
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to lookup the subscription for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_user(
+        &mut self,
+        user: &User,
+    ) -> Result<Option<UserActivityDigest>, Self::Error>;
+
+    /// Subscribe a [`User`] to the activity digest
+    ///
+    /// If the user is already subscribed, only the locale of the subscription
+    /// is updated
+    ///
+    /// Returns the [`UserActivityDigest`] subscription
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator used to generate IDs
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to subscribe
+    /// * `locale`: The locale in which the digest should be sent
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn subscribe(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        locale: String,
+    ) -> Result<UserActivityDigest, Self::Error>;
+
+    /// Unsubscribe a [`User`] from the activity digest
+    ///
+    /// This does nothing if the user is not subscribed
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to unsubscribe
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn unsubscribe(&mut self, user: &User) -> Result<(), Self::Error>;
+
+    /// List the [`UserActivityDigest`] subscriptions for which a digest is
+    /// due, meaning that the period they cover is longer than the interval
+    /// between two digests
+    ///
+    /// The interval is the configured one, unless the user chose a less
+    /// frequent [`DigestFrequency`](mas_data_model::DigestFrequency) in their
+    /// notification preferences.
+    ///
+    /// The subscriptions with the oldest period start are returned first
+    ///
+    /// # Parameters
+    ///
+    /// * `now`: The current time
+    /// * `interval`: The configured interval between two digests
+    /// * `limit`: The maximum number of subscriptions to return
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_due(
+        &mut self,
+        now: DateTime<Utc>,
+        interval: Duration,
+        limit: usize,
+    ) -> Result<Vec<UserActivityDigest>, Self::Error>;
+
+    /// Record that a digest was sent for a [`UserActivityDigest`]
+    /// subscription, which starts a new period
+    ///
+    /// Returns the updated [`UserActivityDigest`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `digest`: The [`UserActivityDigest`] to update
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn mark_sent(
+        &mut self,
+        clock: &dyn Clock,
+        digest: UserActivityDigest,
+    ) -> Result<UserActivityDigest, Self::Error>;
+}
+
+repository_impl!(UserActivityDigestRepository:
+    async fn find_by_user(&mut self, user: &User)
+        -> Result<Option<UserActivityDigest>, Self::Error>;
+
+    async fn subscribe(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        locale: String,
+    ) -> Result<UserActivityDigest, Self::Error>;
+
+    async fn unsubscribe(&mut self, user: &User) -> Result<(), Self::Error>;
+
+    async fn list_due(
+        &mut self,
+        now: DateTime<Utc>,
+        interval: Duration,
+        limit: usize,
+    ) -> Result<Vec<UserActivityDigest>, Self::Error>;
+
+    async fn mark_sent(
+        &mut self,
+        clock: &dyn Clock,
+        digest: UserActivityDigest,
+    ) -> Result<UserActivityDigest, Self::Error>;
+);
@@ -0,0 +1,119 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use ipnetwork::IpNetwork;
+use mas_data_model::{BrowserSession, User, UserEmergencyAccessToken};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserEmergencyAccessRepository`] helps interacting with
+/// [`UserEmergencyAccessToken`] saved in the storage backend
+#[async_trait]
+pub trait UserEmergencyAccessRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup an [`UserEmergencyAccessToken`] by its ID
+    ///
+    /// Returns `None` if no [`UserEmergencyAccessToken`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`UserEmergencyAccessToken`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserEmergencyAccessToken>, Self::Error>;
+
+    /// Find an [`UserEmergencyAccessToken`] by its token
+    ///
+    /// Returns `None` if no [`UserEmergencyAccessToken`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `token`: The token of the [`UserEmergencyAccessToken`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_token(
+        &mut self,
+        token: &str,
+    ) -> Result<Option<UserEmergencyAccessToken>, Self::Error>;
+
+    /// Issue a new [`UserEmergencyAccessToken`] for the given [`User`]
+    ///
+    /// Returns the newly created [`UserEmergencyAccessToken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock to use
+    /// * `user`: The [`User`] to issue the token for
+    /// * `token`: The token to issue
+    /// * `allowed_network`: The network from which the token can be used
+    /// * `expires_after`: How long the token is valid for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        token: String,
+        allowed_network: IpNetwork,
+        expires_after: Duration,
+    ) -> Result<UserEmergencyAccessToken, Self::Error>;
+
+    /// Consume a [`UserEmergencyAccessToken`], recording the
+    /// [`BrowserSession`] it started
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use to record the time of consumption
+    /// * `token`: The [`UserEmergencyAccessToken`] to consume
+    /// * `browser_session`: The [`BrowserSession`] started with the token
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails or if the
+    /// token was already used
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        token: UserEmergencyAccessToken,
+        browser_session: &BrowserSession,
+    ) -> Result<UserEmergencyAccessToken, Self::Error>;
+}
+
+repository_impl!(UserEmergencyAccessRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserEmergencyAccessToken>, Self::Error>;
+    async fn find_by_token(
+        &mut self,
+        token: &str,
+    ) -> Result<Option<UserEmergencyAccessToken>, Self::Error>;
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        token: String,
+        allowed_network: IpNetwork,
+        expires_after: Duration,
+    ) -> Result<UserEmergencyAccessToken, Self::Error>;
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        token: UserEmergencyAccessToken,
+        browser_session: &BrowserSession,
+    ) -> Result<UserEmergencyAccessToken, Self::Error>;
+);
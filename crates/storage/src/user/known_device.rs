@@ -0,0 +1,67 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use mas_data_model::User;
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserKnownDeviceRepository`] helps interacting with the browsers and
+/// devices each [`User`] signed in from, as saved in the storage backend
+///
+/// Devices are identified by a fingerprint derived from their user agent, see
+/// [`UserAgent::device_fingerprint`](mas_data_model::UserAgent::device_fingerprint)
+#[async_trait]
+pub trait UserKnownDeviceRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Count the devices a [`User`] signed in from
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to count the devices of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, user: &User) -> Result<usize, Self::Error>;
+
+    /// Remember that a [`User`] signed in from a device
+    ///
+    /// Returns `true` if the device was not known yet. Otherwise, only the
+    /// time the device was last seen is updated.
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator used to generate IDs
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] who signed in
+    /// * `fingerprint`: The fingerprint of the device
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remember(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        fingerprint: &str,
+    ) -> Result<bool, Self::Error>;
+}
+
+repository_impl!(UserKnownDeviceRepository:
+    async fn count(&mut self, user: &User) -> Result<usize, Self::Error>;
+
+    async fn remember(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        fingerprint: &str,
+    ) -> Result<bool, Self::Error>;
+);
@@ -6,25 +6,38 @@
 
 //! Repositories to interact with entities related to user accounts
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use mas_data_model::User;
 use rand_core::RngCore;
 use ulid::Ulid;
 
-use crate::{repository_impl, Clock, Page, Pagination};
+use crate::{pagination::Count, repository_impl, Clock, Page, Pagination};
 
+mod activity_digest;
 mod email;
+mod emergency_access;
+mod known_device;
+mod notification_preferences;
 mod password;
 mod recovery;
 mod session;
 mod terms;
+mod trusted_browser;
 
 pub use self::{
+    activity_digest::UserActivityDigestRepository,
     email::{UserEmailFilter, UserEmailRepository},
+    emergency_access::UserEmergencyAccessRepository,
+    known_device::UserKnownDeviceRepository,
+    notification_preferences::UserNotificationPreferencesRepository,
     password::UserPasswordRepository,
     recovery::UserRecoveryRepository,
     session::{BrowserSessionFilter, BrowserSessionRepository},
     terms::UserTermsRepository,
+    trusted_browser::UserTrustedBrowserRepository,
 };
 
 /// The state of a user account
@@ -55,12 +68,50 @@ impl UserState {
     }
 }
 
+/// The homeserver a user belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserHomeserver<'a> {
+    /// The main homeserver
+    Main,
+
+    /// An additional homeserver, with its server name
+    Additional(&'a str),
+}
+
+impl<'a> UserHomeserver<'a> {
+    /// Get the server name of the homeserver, or `None` for the main one
+    #[must_use]
+    pub fn server_name(self) -> Option<&'a str> {
+        match self {
+            Self::Main => None,
+            Self::Additional(server_name) => Some(server_name),
+        }
+    }
+}
+
+impl<'a> From<Option<&'a str>> for UserHomeserver<'a> {
+    fn from(server_name: Option<&'a str>) -> Self {
+        server_name.map_or(Self::Main, Self::Additional)
+    }
+}
+
 /// Filter parameters for listing users
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct UserFilter<'a> {
     state: Option<UserState>,
     can_request_admin: Option<bool>,
-    _phantom: std::marker::PhantomData<&'a ()>,
+    legal_hold: Option<bool>,
+    homeserver: Option<UserHomeserver<'a>>,
+    bot: Option<bool>,
+    bot_owner: Option<&'a User>,
+    guest: Option<bool>,
+    deletion_due_at: Option<DateTime<Utc>>,
+    inactive_since: Option<DateTime<Utc>>,
+    used_since: Option<DateTime<Utc>>,
+    idle_since: Option<DateTime<Utc>>,
+    inactivity_warned: Option<bool>,
+    inactivity_warned_before: Option<DateTime<Utc>>,
+    excluded_usernames: Option<&'a [String]>,
 }
 
 impl<'a> UserFilter<'a> {
@@ -98,6 +149,127 @@ impl<'a> UserFilter<'a> {
         self
     }
 
+    /// Filter for users under legal hold
+    #[must_use]
+    pub fn legal_hold_only(mut self) -> Self {
+        self.legal_hold = Some(true);
+        self
+    }
+
+    /// Filter for users not under legal hold
+    #[must_use]
+    pub fn no_legal_hold_only(mut self) -> Self {
+        self.legal_hold = Some(false);
+        self
+    }
+
+    /// Filter for users of the given homeserver
+    #[must_use]
+    pub fn for_homeserver(mut self, homeserver: UserHomeserver<'a>) -> Self {
+        self.homeserver = Some(homeserver);
+        self
+    }
+
+    /// Filter for bot accounts
+    #[must_use]
+    pub fn bots_only(mut self) -> Self {
+        self.bot = Some(true);
+        self
+    }
+
+    /// Filter for accounts which are not bots
+    #[must_use]
+    pub fn humans_only(mut self) -> Self {
+        self.bot = Some(false);
+        self
+    }
+
+    /// Filter for bot accounts owned by the given user
+    #[must_use]
+    pub fn for_bot_owner(mut self, owner: &'a User) -> Self {
+        self.bot_owner = Some(owner);
+        self
+    }
+
+    /// Filter for guest accounts
+    #[must_use]
+    pub fn guests_only(mut self) -> Self {
+        self.guest = Some(true);
+        self
+    }
+
+    /// Filter for accounts which are not guests
+    #[must_use]
+    pub fn non_guests_only(mut self) -> Self {
+        self.guest = Some(false);
+        self
+    }
+
+    /// Filter for users who asked for their account to be deleted, and whose
+    /// grace period elapsed at the given instant
+    #[must_use]
+    pub fn deletion_due_at(mut self, now: DateTime<Utc>) -> Self {
+        self.deletion_due_at = Some(now);
+        self
+    }
+
+    /// Filter for users who didn't use their account since the given instant:
+    /// they have no active session, and none of their sessions was started or
+    /// used since then
+    #[must_use]
+    pub fn inactive_since(mut self, since: DateTime<Utc>) -> Self {
+        self.inactive_since = Some(since);
+        self
+    }
+
+    /// Filter for users who used their account since the given instant: they
+    /// have an active session, or one of their sessions was started or used
+    /// since then
+    #[must_use]
+    pub fn used_since(mut self, since: DateTime<Utc>) -> Self {
+        self.used_since = Some(since);
+        self
+    }
+
+    /// Filter for users who didn't use any of their sessions since the given
+    /// instant, even if some of them are still active
+    #[must_use]
+    pub fn idle_since(mut self, since: DateTime<Utc>) -> Self {
+        self.idle_since = Some(since);
+        self
+    }
+
+    /// Filter for users who were warned that their account would be
+    /// deactivated because of inactivity
+    #[must_use]
+    pub fn inactivity_warned_only(mut self) -> Self {
+        self.inactivity_warned = Some(true);
+        self
+    }
+
+    /// Filter for users who weren't warned that their account would be
+    /// deactivated because of inactivity
+    #[must_use]
+    pub fn not_inactivity_warned_only(mut self) -> Self {
+        self.inactivity_warned = Some(false);
+        self
+    }
+
+    /// Filter for users who were warned about their inactivity before the
+    /// given instant
+    #[must_use]
+    pub fn inactivity_warned_before(mut self, before: DateTime<Utc>) -> Self {
+        self.inactivity_warned_before = Some(before);
+        self
+    }
+
+    /// Filter out the users with the given usernames
+    #[must_use]
+    pub fn excluding_usernames(mut self, usernames: &'a [String]) -> Self {
+        self.excluded_usernames = Some(usernames);
+        self
+    }
+
     /// Get the state filter
     ///
     /// Returns [`None`] if no state filter was set
@@ -113,6 +285,106 @@ impl<'a> UserFilter<'a> {
     pub fn can_request_admin(&self) -> Option<bool> {
         self.can_request_admin
     }
+
+    /// Get the legal hold filter
+    ///
+    /// Returns [`None`] if no legal hold filter was set
+    #[must_use]
+    pub fn legal_hold(&self) -> Option<bool> {
+        self.legal_hold
+    }
+
+    /// Get the homeserver filter
+    ///
+    /// Returns [`None`] if no homeserver filter was set
+    #[must_use]
+    pub fn homeserver(&self) -> Option<UserHomeserver<'a>> {
+        self.homeserver
+    }
+
+    /// Get the bot filter
+    ///
+    /// Returns [`None`] if no bot filter was set
+    #[must_use]
+    pub fn bot(&self) -> Option<bool> {
+        self.bot
+    }
+
+    /// Get the bot owner filter
+    ///
+    /// Returns [`None`] if no bot owner filter was set
+    #[must_use]
+    pub fn bot_owner(&self) -> Option<&'a User> {
+        self.bot_owner
+    }
+
+    /// Get the guest filter
+    ///
+    /// Returns [`None`] if no guest filter was set
+    #[must_use]
+    pub fn guest(&self) -> Option<bool> {
+        self.guest
+    }
+
+    /// Get the deletion due filter
+    ///
+    /// Returns [`None`] if no deletion due filter was set
+    #[must_use]
+    pub fn deletion_due(&self) -> Option<DateTime<Utc>> {
+        self.deletion_due_at
+    }
+
+    /// Get the inactivity filter, as the instant since which the users must
+    /// not have used their account
+    ///
+    /// Returns [`None`] if no inactivity filter was set
+    #[must_use]
+    pub fn inactive(&self) -> Option<DateTime<Utc>> {
+        self.inactive_since
+    }
+
+    /// Get the usage filter, as the instant since which the users must have
+    /// used their account
+    ///
+    /// Returns [`None`] if no usage filter was set
+    #[must_use]
+    pub fn used(&self) -> Option<DateTime<Utc>> {
+        self.used_since
+    }
+
+    /// Get the idleness filter, as the instant since which the users must not
+    /// have used any of their sessions
+    ///
+    /// Returns [`None`] if no idleness filter was set
+    #[must_use]
+    pub fn idle(&self) -> Option<DateTime<Utc>> {
+        self.idle_since
+    }
+
+    /// Get the inactivity warned filter
+    ///
+    /// Returns [`None`] if no inactivity warned filter was set
+    #[must_use]
+    pub fn inactivity_warned(&self) -> Option<bool> {
+        self.inactivity_warned
+    }
+
+    /// Get the instant before which the users must have been warned about
+    /// their inactivity
+    ///
+    /// Returns [`None`] if no such filter was set
+    #[must_use]
+    pub fn inactivity_warned_cutoff(&self) -> Option<DateTime<Utc>> {
+        self.inactivity_warned_before
+    }
+
+    /// Get the excluded usernames
+    ///
+    /// Returns [`None`] if no usernames were excluded
+    #[must_use]
+    pub fn excluded_usernames(&self) -> Option<&'a [String]> {
+        self.excluded_usernames
+    }
 }
 
 /// A [`UserRepository`] helps interacting with [`User`] saved in the storage
@@ -135,20 +407,42 @@ pub trait UserRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn lookup(&mut self, id: Ulid) -> Result<Option<User>, Self::Error>;
 
-    /// Find a [`User`] by its username
+    /// Load a batch of [`User`]s by their IDs
+    ///
+    /// Returns a map of user IDs to users. If a user does not exist, it is not
+    /// present in the map.
+    ///
+    /// # Parameters
+    ///
+    /// * `ids`: The IDs of the users to load
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, User>, Self::Error>;
+
+    /// Find a [`User`] of a homeserver by its username
     ///
     /// Returns `None` if no [`User`] was found
     ///
     /// # Parameters
     ///
+    /// * `homeserver`: The homeserver the [`User`] belongs to
     /// * `username`: The username of the [`User`] to lookup
     ///
     /// # Errors
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
-    async fn find_by_username(&mut self, username: &str) -> Result<Option<User>, Self::Error>;
+    async fn find_by_username(
+        &mut self,
+        homeserver: UserHomeserver<'_>,
+        username: &str,
+    ) -> Result<Option<User>, Self::Error>;
 
-    /// Create a new [`User`]
+    /// Create a new [`User`] on the main homeserver
     ///
     /// Returns the newly created [`User`]
     ///
@@ -168,18 +462,45 @@ pub trait UserRepository: Send + Sync {
         username: String,
     ) -> Result<User, Self::Error>;
 
-    /// Check if a [`User`] exists
+    /// Create a new [`User`] on the given homeserver
+    ///
+    /// Returns the newly created [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator to generate the [`User`] ID
+    /// * `clock`: The clock used to generate timestamps
+    /// * `homeserver`: The homeserver the [`User`] belongs to
+    /// * `username`: The username of the [`User`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_for_homeserver(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        homeserver: UserHomeserver<'_>,
+        username: String,
+    ) -> Result<User, Self::Error>;
+
+    /// Check if a [`User`] exists on a homeserver
     ///
     /// Returns `true` if the [`User`] exists, `false` otherwise
     ///
     /// # Parameters
     ///
+    /// * `homeserver`: The homeserver the [`User`] would belong to
     /// * `username`: The username of the [`User`] to lookup
     ///
     /// # Errors
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
-    async fn exists(&mut self, username: &str) -> Result<bool, Self::Error>;
+    async fn exists(
+        &mut self,
+        homeserver: UserHomeserver<'_>,
+        username: &str,
+    ) -> Result<bool, Self::Error>;
 
     /// Lock a [`User`]
     ///
@@ -225,6 +546,151 @@ pub trait UserRepository: Send + Sync {
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
 
+    /// Place a [`User`] under legal hold
+    ///
+    /// While under legal hold, the data of the user can't be pruned or erased.
+    ///
+    /// Returns the [`User`] with the `legal_hold_at` timestamp set
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to place under legal hold
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn place_legal_hold(
+        &mut self,
+        clock: &dyn Clock,
+        user: User,
+    ) -> Result<User, Self::Error>;
+
+    /// Release the legal hold on a [`User`]
+    ///
+    /// Returns the [`User`] without the `legal_hold_at` timestamp
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to release
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn release_legal_hold(&mut self, user: User) -> Result<User, Self::Error>;
+
+    /// Set the homeserver a [`User`] belongs to
+    ///
+    /// Returns the [`User`] with the new homeserver
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to update
+    /// * `homeserver`: The server name of the homeserver, or `None` for the
+    ///   main homeserver
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_homeserver(
+        &mut self,
+        user: User,
+        homeserver: Option<String>,
+    ) -> Result<User, Self::Error>;
+
+    /// Turn a [`User`] into a bot account
+    ///
+    /// Returns the [`User`] flagged as a bot, with the new owner
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to turn into a bot
+    /// * `owner`: The human [`User`] who owns the bot, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_bot(&mut self, user: User, owner: Option<&User>) -> Result<User, Self::Error>;
+
+    /// Flag a [`User`] as a guest account
+    ///
+    /// Returns the [`User`] flagged as a guest
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to flag as a guest
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_guest(&mut self, user: User) -> Result<User, Self::Error>;
+
+    /// Schedule the deletion of a [`User`] who asked for their account to be
+    /// deleted
+    ///
+    /// This doesn't lock the user, which has to be done separately.
+    ///
+    /// Returns the [`User`] with the `deletion_scheduled_at` timestamp set
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to schedule the deletion of
+    /// * `deletion_scheduled_at`: When the account should be erased
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn schedule_deletion(
+        &mut self,
+        user: User,
+        deletion_scheduled_at: DateTime<Utc>,
+    ) -> Result<User, Self::Error>;
+
+    /// Clear the scheduled deletion of a [`User`], either because it was
+    /// cancelled or because the account is being erased
+    ///
+    /// This doesn't unlock the user, which has to be done separately.
+    ///
+    /// Returns the [`User`] without the `deletion_scheduled_at` timestamp
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to clear the scheduled deletion of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn clear_scheduled_deletion(&mut self, user: User) -> Result<User, Self::Error>;
+
+    /// Record that a [`User`] was warned that their account would be
+    /// deactivated because they didn't use it for a long time
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate the timestamp
+    /// * `user`: The [`User`] who was warned
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn mark_inactivity_warned(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<(), Self::Error>;
+
+    /// Forget that a [`User`] was warned about their inactivity, because they
+    /// used their account again
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] who used their account again
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn clear_inactivity_warning(&mut self, user: &User) -> Result<(), Self::Error>;
+
     /// List [`User`] with the given filter and pagination
     ///
     /// # Parameters
@@ -252,6 +718,18 @@ pub trait UserRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn count(&mut self, filter: UserFilter<'_>) -> Result<usize, Self::Error>;
 
+    /// Count the [`User`] with the given filter, falling back to an
+    /// estimation if there are too many of them to count them exactly
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn estimate_count(&mut self, filter: UserFilter<'_>) -> Result<Count, Self::Error>;
+
     /// Acquire a lock on the user to make sure device operations are done in a
     /// sequential way. The lock is released when the repository is saved or
     /// rolled back.
@@ -268,14 +746,30 @@ pub trait UserRepository: Send + Sync {
 
 repository_impl!(UserRepository:
     async fn lookup(&mut self, id: Ulid) -> Result<Option<User>, Self::Error>;
-    async fn find_by_username(&mut self, username: &str) -> Result<Option<User>, Self::Error>;
+    async fn load_batch(&mut self, ids: BTreeSet<Ulid>) -> Result<BTreeMap<Ulid, User>, Self::Error>;
+    async fn find_by_username(
+        &mut self,
+        homeserver: UserHomeserver<'_>,
+        username: &str,
+    ) -> Result<Option<User>, Self::Error>;
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
         username: String,
     ) -> Result<User, Self::Error>;
-    async fn exists(&mut self, username: &str) -> Result<bool, Self::Error>;
+    async fn add_for_homeserver(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        homeserver: UserHomeserver<'_>,
+        username: String,
+    ) -> Result<User, Self::Error>;
+    async fn exists(
+        &mut self,
+        homeserver: UserHomeserver<'_>,
+        username: &str,
+    ) -> Result<bool, Self::Error>;
     async fn lock(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
     async fn set_can_request_admin(
@@ -283,11 +777,34 @@ repository_impl!(UserRepository:
         user: User,
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
+    async fn place_legal_hold(&mut self, clock: &dyn Clock, user: User)
+        -> Result<User, Self::Error>;
+    async fn release_legal_hold(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn set_homeserver(
+        &mut self,
+        user: User,
+        homeserver: Option<String>,
+    ) -> Result<User, Self::Error>;
+    async fn set_bot(&mut self, user: User, owner: Option<&User>) -> Result<User, Self::Error>;
+    async fn set_guest(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn schedule_deletion(
+        &mut self,
+        user: User,
+        deletion_scheduled_at: DateTime<Utc>,
+    ) -> Result<User, Self::Error>;
+    async fn clear_scheduled_deletion(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn mark_inactivity_warned(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<(), Self::Error>;
+    async fn clear_inactivity_warning(&mut self, user: &User) -> Result<(), Self::Error>;
     async fn list(
         &mut self,
         filter: UserFilter<'_>,
         pagination: Pagination,
     ) -> Result<Page<User>, Self::Error>;
     async fn count(&mut self, filter: UserFilter<'_>) -> Result<usize, Self::Error>;
+    async fn estimate_count(&mut self, filter: UserFilter<'_>) -> Result<Count, Self::Error>;
     async fn acquire_lock_for_sync(&mut self, user: &User) -> Result<(), Self::Error>;
 );
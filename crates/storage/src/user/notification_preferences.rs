@@ -0,0 +1,61 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use mas_data_model::{User, UserNotificationPreferences};
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserNotificationPreferencesRepository`] helps interacting with the
+/// [`UserNotificationPreferences`] saved in the storage backend
+#[async_trait]
+pub trait UserNotificationPreferencesRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Get the [`UserNotificationPreferences`] of a [`User`]
+    ///
+    /// Returns the default preferences if the user never changed them
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to get the preferences of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get(&mut self, user: &User) -> Result<UserNotificationPreferences, Self::Error>;
+
+    /// Save the [`UserNotificationPreferences`] of a [`User`]
+    ///
+    /// Returns the saved preferences
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to save the preferences of
+    /// * `preferences`: The new preferences of the user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        preferences: UserNotificationPreferences,
+    ) -> Result<UserNotificationPreferences, Self::Error>;
+}
+
+repository_impl!(UserNotificationPreferencesRepository:
+    async fn get(&mut self, user: &User) -> Result<UserNotificationPreferences, Self::Error>;
+
+    async fn set(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        preferences: UserNotificationPreferences,
+    ) -> Result<UserNotificationPreferences, Self::Error>;
+);
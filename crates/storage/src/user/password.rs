@@ -32,7 +32,9 @@ pub trait UserPasswordRepository: Send + Sync {
 
     /// Set a new password for a user
     ///
-    /// Returns the newly created [`Password`]
+    /// Returns the newly created [`Password`]. A password upgraded from
+    /// another one has to be changed on the next login if the previous one
+    /// had to.
     ///
     /// # Parameters
     ///
@@ -55,6 +57,24 @@ pub trait UserPasswordRepository: Send + Sync {
         hashed_password: String,
         upgraded_from: Option<&Password>,
     ) -> Result<Password, Self::Error>;
+
+    /// Flag a password as having to be changed on the next login
+    ///
+    /// Returns the updated [`Password`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `password`: The password to flag
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if underlying repository fails
+    async fn require_change(
+        &mut self,
+        clock: &dyn Clock,
+        password: Password,
+    ) -> Result<Password, Self::Error>;
 }
 
 repository_impl!(UserPasswordRepository:
@@ -68,4 +88,9 @@ repository_impl!(UserPasswordRepository:
         hashed_password: String,
         upgraded_from: Option<&Password>,
     ) -> Result<Password, Self::Error>;
+    async fn require_change(
+        &mut self,
+        clock: &dyn Clock,
+        password: Password,
+    ) -> Result<Password, Self::Error>;
 );
@@ -4,12 +4,16 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+};
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
-    Authentication, BrowserSession, Password, UpstreamOAuthAuthorizationSession, User, UserAgent,
+    Authentication, BrowserSession, BrowserSessionBinding, Password,
+    UpstreamOAuthAuthorizationSession, User, UserAgent,
 };
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -39,6 +43,9 @@ pub struct BrowserSessionFilter<'a> {
     state: Option<BrowserSessionState>,
     last_active_before: Option<DateTime<Utc>>,
     last_active_after: Option<DateTime<Utc>>,
+    tag: Option<&'a str>,
+    binding_expired_before: Option<DateTime<Utc>>,
+    impersonation_expired_before: Option<DateTime<Utc>>,
 }
 
 impl<'a> BrowserSessionFilter<'a> {
@@ -110,6 +117,56 @@ impl<'a> BrowserSessionFilter<'a> {
     pub fn state(&self) -> Option<BrowserSessionState> {
         self.state
     }
+
+    /// Only return sessions with the given tag
+    #[must_use]
+    pub fn with_tag(mut self, tag: &'a str) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Get the tag filter
+    ///
+    /// Returns [`None`] if no tag filter was set
+    #[must_use]
+    pub fn tag(&self) -> Option<&'a str> {
+        self.tag
+    }
+
+    /// Only return sessions bound to a credential, whose binding expired
+    /// before the given time
+    #[must_use]
+    pub fn with_binding_expired_before(mut self, binding_expired_before: DateTime<Utc>) -> Self {
+        self.binding_expired_before = Some(binding_expired_before);
+        self
+    }
+
+    /// Get the binding expired before filter
+    ///
+    /// Returns [`None`] if no binding expiration filter was set
+    #[must_use]
+    pub fn binding_expired_before(&self) -> Option<DateTime<Utc>> {
+        self.binding_expired_before
+    }
+
+    /// Only return impersonation sessions, which expired before the given
+    /// time
+    #[must_use]
+    pub fn with_impersonation_expired_before(
+        mut self,
+        impersonation_expired_before: DateTime<Utc>,
+    ) -> Self {
+        self.impersonation_expired_before = Some(impersonation_expired_before);
+        self
+    }
+
+    /// Get the impersonation expired before filter
+    ///
+    /// Returns [`None`] if no impersonation expiration filter was set
+    #[must_use]
+    pub fn impersonation_expired_before(&self) -> Option<DateTime<Utc>> {
+        self.impersonation_expired_before
+    }
 }
 
 /// A [`BrowserSessionRepository`] helps interacting with [`BrowserSession`]
@@ -132,6 +189,23 @@ pub trait BrowserSessionRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error>;
 
+    /// Load a batch of [`BrowserSession`]s by their IDs
+    ///
+    /// Returns a map of session IDs to sessions. If a session does not exist,
+    /// it is not present in the map.
+    ///
+    /// # Parameters
+    ///
+    /// * `ids`: The IDs of the sessions to load
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, BrowserSession>, Self::Error>;
+
     /// Create a new [`BrowserSession`] for a [`User`]
     ///
     /// Returns the newly created [`BrowserSession`]
@@ -154,6 +228,33 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_agent: Option<UserAgent>,
     ) -> Result<BrowserSession, Self::Error>;
 
+    /// Create a new [`BrowserSession`] for a [`User`], on behalf of an
+    /// administrator impersonating them
+    ///
+    /// Returns the newly created [`BrowserSession`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The user to impersonate
+    /// * `impersonator_session`: The browser session of the administrator
+    /// * `expires_at`: When the impersonation session ends
+    /// * `user_agent`: If available, the user agent of the browser
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_impersonation(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        impersonator_session: &BrowserSession,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<UserAgent>,
+    ) -> Result<BrowserSession, Self::Error>;
+
     /// Finish a [`BrowserSession`]
     ///
     /// Returns the finished session
@@ -172,6 +273,25 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_session: BrowserSession,
     ) -> Result<BrowserSession, Self::Error>;
 
+    /// Bind a [`BrowserSession`] to a credential, replacing the previous
+    /// binding if any
+    ///
+    /// Returns the updated session
+    ///
+    /// # Parameters
+    ///
+    /// * `user_session`: The session to bind
+    /// * `binding`: The new binding of the session
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn bind(
+        &mut self,
+        user_session: BrowserSession,
+        binding: BrowserSessionBinding,
+    ) -> Result<BrowserSession, Self::Error>;
+
     /// Mark all the [`BrowserSession`] matching the given filter as finished
     ///
     /// Returns the number of sessions affected
@@ -190,6 +310,29 @@ pub trait BrowserSessionRepository: Send + Sync {
         filter: BrowserSessionFilter<'_>,
     ) -> Result<usize, Self::Error>;
 
+    /// Mark as finished the active [`BrowserSession`]s which went past their
+    /// maximum lifetime, or which stayed inactive for too long
+    ///
+    /// Returns the number of sessions affected
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `session_ttl`: The maximum lifetime of sessions, if limited
+    /// * `inactivity_ttl`: How long sessions can stay inactive, if limited
+    /// * `limit`: The maximum number of sessions to finish
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Option<Duration>,
+        inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<usize, Self::Error>;
+
     /// List [`BrowserSession`] with the given filter and pagination
     ///
     /// # Parameters
@@ -273,6 +416,46 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_session: &BrowserSession,
     ) -> Result<Option<Authentication>, Self::Error>;
 
+    /// Get the last successful authentication for a [`BrowserSession`] as of
+    /// a given time
+    ///
+    /// This is useful to tell how the user authenticated when something was
+    /// done with the session, like starting an OAuth 2.0 session.
+    ///
+    /// # Params
+    ///
+    /// * `user_session_id`: The ID of the session for which to get the
+    ///   authentication
+    /// * `at`: The time as of which to get the last authentication
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_authentication_at(
+        &mut self,
+        user_session_id: Ulid,
+        at: DateTime<Utc>,
+    ) -> Result<Option<Authentication>, Self::Error>;
+
+    /// Get the last successful authentication for a batch of
+    /// [`BrowserSession`]s
+    ///
+    /// Returns a map of session IDs to their last authentication. Sessions
+    /// which never authenticated are not present in the map.
+    ///
+    /// # Params
+    ///
+    /// * `ids`: The IDs of the sessions for which to get the last
+    ///   authentication
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_last_authentication_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error>;
+
     /// Record a batch of [`BrowserSession`] activity
     ///
     /// # Parameters
@@ -287,10 +470,52 @@ pub trait BrowserSessionRepository: Send + Sync {
         &mut self,
         activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
     ) -> Result<(), Self::Error>;
+
+    /// Add a tag to a [`BrowserSession`], if it doesn't have it already
+    ///
+    /// The tags are updated atomically, so that concurrent changes to the tags
+    /// of the session are not lost.
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`BrowserSession`] to tag
+    /// * `tag`: The tag to add
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_tag(
+        &mut self,
+        session: BrowserSession,
+        tag: &str,
+    ) -> Result<BrowserSession, Self::Error>;
+
+    /// Remove a tag from a [`BrowserSession`]
+    ///
+    /// The tags are updated atomically, so that concurrent changes to the tags
+    /// of the session are not lost.
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`BrowserSession`] to untag
+    /// * `tag`: The tag to remove
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove_tag(
+        &mut self,
+        session: BrowserSession,
+        tag: &str,
+    ) -> Result<BrowserSession, Self::Error>;
 }
 
 repository_impl!(BrowserSessionRepository:
     async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error>;
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, BrowserSession>, Self::Error>;
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
@@ -298,18 +523,41 @@ repository_impl!(BrowserSessionRepository:
         user: &User,
         user_agent: Option<UserAgent>,
     ) -> Result<BrowserSession, Self::Error>;
+    async fn add_impersonation(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        impersonator_session: &BrowserSession,
+        expires_at: DateTime<Utc>,
+        user_agent: Option<UserAgent>,
+    ) -> Result<BrowserSession, Self::Error>;
     async fn finish(
         &mut self,
         clock: &dyn Clock,
         user_session: BrowserSession,
     ) -> Result<BrowserSession, Self::Error>;
 
+    async fn bind(
+        &mut self,
+        user_session: BrowserSession,
+        binding: BrowserSessionBinding,
+    ) -> Result<BrowserSession, Self::Error>;
+
     async fn finish_bulk(
         &mut self,
         clock: &dyn Clock,
         filter: BrowserSessionFilter<'_>,
     ) -> Result<usize, Self::Error>;
 
+    async fn finish_expired(
+        &mut self,
+        clock: &dyn Clock,
+        session_ttl: Option<Duration>,
+        inactivity_ttl: Option<Duration>,
+        limit: usize,
+    ) -> Result<usize, Self::Error>;
+
     async fn list(
         &mut self,
         filter: BrowserSessionFilter<'_>,
@@ -339,8 +587,23 @@ repository_impl!(BrowserSessionRepository:
         user_session: &BrowserSession,
     ) -> Result<Option<Authentication>, Self::Error>;
 
+    async fn get_authentication_at(
+        &mut self,
+        user_session_id: Ulid,
+        at: DateTime<Utc>,
+    ) -> Result<Option<Authentication>, Self::Error>;
+
+    async fn get_last_authentication_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error>;
+
     async fn record_batch_activity(
         &mut self,
         activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
     ) -> Result<(), Self::Error>;
+
+    async fn add_tag(&mut self, session: BrowserSession, tag: &str) -> Result<BrowserSession, Self::Error>;
+
+    async fn remove_tag(&mut self, session: BrowserSession, tag: &str) -> Result<BrowserSession, Self::Error>;
 );
@@ -5,7 +5,7 @@
 // Please see LICENSE in the repository root for full details.
 
 use async_trait::async_trait;
-use mas_data_model::User;
+use mas_data_model::{User, UserTermsAcceptance};
 use rand_core::RngCore;
 use url::Url;
 
@@ -37,6 +37,36 @@ pub trait UserTermsRepository: Send + Sync {
         user: &User,
         terms_url: Url,
     ) -> Result<(), Self::Error>;
+
+    /// Find when a [`User`] accepted the terms of service at the given URL
+    ///
+    /// Returns [`None`] if the user never accepted them
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to look up
+    /// * `terms_url`: The URL of the terms of service
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_acceptance(
+        &mut self,
+        user: &User,
+        terms_url: &Url,
+    ) -> Result<Option<UserTermsAcceptance>, Self::Error>;
+
+    /// Get all the versions of the terms of service a [`User`] accepted,
+    /// ordered from the most recent acceptance to the oldest
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to look up
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn all(&mut self, user: &User) -> Result<Vec<UserTermsAcceptance>, Self::Error>;
 }
 
 repository_impl!(UserTermsRepository:
@@ -47,4 +77,12 @@ repository_impl!(UserTermsRepository:
         user: &User,
         terms_url: Url,
     ) -> Result<(), Self::Error>;
+
+    async fn find_acceptance(
+        &mut self,
+        user: &User,
+        terms_url: &Url,
+    ) -> Result<Option<UserTermsAcceptance>, Self::Error>;
+
+    async fn all(&mut self, user: &User) -> Result<Vec<UserTermsAcceptance>, Self::Error>;
 );
@@ -0,0 +1,137 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{User, UserAgent, UserTrustedBrowser};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserTrustedBrowserRepository`] helps interacting with the
+/// [`UserTrustedBrowser`] saved in the storage backend
+#[async_trait]
+pub trait UserTrustedBrowserRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a [`UserTrustedBrowser`] by its ID
+    ///
+    /// Returns `None` if no [`UserTrustedBrowser`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`UserTrustedBrowser`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserTrustedBrowser>, Self::Error>;
+
+    /// List the active [`UserTrustedBrowser`] of a [`User`], most recently
+    /// trusted first
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to tell which ones expired
+    /// * `user`: The [`User`] to list the trusted browsers of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<Vec<UserTrustedBrowser>, Self::Error>;
+
+    /// Trust a browser of a [`User`]
+    ///
+    /// Returns the newly created [`UserTrustedBrowser`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator used to generate IDs
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] who trusts the browser
+    /// * `user_agent`: The user agent of the browser
+    /// * `expires_after`: How long the browser stays trusted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        user_agent: Option<UserAgent>,
+        expires_after: Duration,
+    ) -> Result<UserTrustedBrowser, Self::Error>;
+
+    /// Record that a [`UserTrustedBrowser`] was used to log in
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `trusted_browser`: The [`UserTrustedBrowser`] which was used
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn touch(
+        &mut self,
+        clock: &dyn Clock,
+        trusted_browser: UserTrustedBrowser,
+    ) -> Result<UserTrustedBrowser, Self::Error>;
+
+    /// Revoke a [`UserTrustedBrowser`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `trusted_browser`: The [`UserTrustedBrowser`] to revoke
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        trusted_browser: UserTrustedBrowser,
+    ) -> Result<UserTrustedBrowser, Self::Error>;
+}
+
+repository_impl!(UserTrustedBrowserRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserTrustedBrowser>, Self::Error>;
+
+    async fn list_active(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<Vec<UserTrustedBrowser>, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        user_agent: Option<UserAgent>,
+        expires_after: Duration,
+    ) -> Result<UserTrustedBrowser, Self::Error>;
+
+    async fn touch(
+        &mut self,
+        clock: &dyn Clock,
+        trusted_browser: UserTrustedBrowser,
+    ) -> Result<UserTrustedBrowser, Self::Error>;
+
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        trusted_browser: UserTrustedBrowser,
+    ) -> Result<UserTrustedBrowser, Self::Error>;
+);
@@ -6,7 +6,7 @@
 
 //! Database-related tasks
 
-use std::str::FromStr;
+use std::{collections::BTreeSet, str::FromStr};
 
 use apalis_core::{
     builder::{WorkerBuilder, WorkerFactoryFn},
@@ -18,11 +18,24 @@ use apalis_core::{
 };
 use apalis_cron::CronStream;
 use chrono::{DateTime, Utc};
-use mas_storage::{oauth2::OAuth2AccessTokenRepository, RepositoryAccess};
+use mas_data_model::AuditEventKind;
+use mas_storage::{
+    audit::AuditEventRepository,
+    changes_feed::ChangesFeedRepository,
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{DispatchWebhookJob, JobRepositoryExt as _, SyncDevicesJob},
+    oauth2::{
+        OAuth2AccessTokenRepository, OAuth2JwtBearerAssertionRepository, OAuth2SessionFilter,
+        OAuth2SessionRepository,
+    },
+    rendezvous::RendezvousSessionRepository,
+    user::{BrowserSessionFilter, BrowserSessionRepository, UserRepository},
+    Clock, Pagination, RepositoryAccess,
+};
 use tracing::{debug, info};
 
 use crate::{
-    utils::{metrics_layer, trace_layer, TracedJob},
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
     JobContextExt, State,
 };
 
@@ -65,6 +78,643 @@ pub async fn cleanup_expired_tokens(
     Ok(())
 }
 
+#[derive(Default, Clone)]
+pub struct CleanupExpiredJwtBearerAssertionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupExpiredJwtBearerAssertionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupExpiredJwtBearerAssertionsJob {
+    const NAME: &'static str = "cleanup-expired-jwt-bearer-assertions";
+}
+
+impl TracedJob for CleanupExpiredJwtBearerAssertionsJob {}
+
+pub async fn cleanup_expired_jwt_bearer_assertions(
+    job: CleanupExpiredJwtBearerAssertionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "cleanup expired JWT bearer assertions job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let count = repo
+        .oauth2_jwt_bearer_assertion()
+        .cleanup_expired(&clock)
+        .await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no JWT bearer assertion to clean up");
+    } else {
+        info!(count, "cleaned up expired JWT bearer assertions");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct CleanupExpiredRendezvousSessionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupExpiredRendezvousSessionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupExpiredRendezvousSessionsJob {
+    const NAME: &'static str = "cleanup-expired-rendezvous-sessions";
+}
+
+impl TracedJob for CleanupExpiredRendezvousSessionsJob {}
+
+pub async fn cleanup_expired_rendezvous_sessions(
+    job: CleanupExpiredRendezvousSessionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "cleanup expired rendezvous sessions job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let count = repo.rendezvous_session().cleanup_expired(&clock).await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no rendezvous session to clean up");
+    } else {
+        info!(count, "cleaned up expired rendezvous sessions");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ExpireOAuth2SessionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ExpireOAuth2SessionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ExpireOAuth2SessionsJob {
+    const NAME: &'static str = "expire-oauth2-sessions";
+}
+
+impl TracedJob for ExpireOAuth2SessionsJob {}
+
+/// The maximum number of sessions ended in one run of the job
+const EXPIRED_SESSIONS_BATCH_SIZE: usize = 1000;
+
+pub async fn expire_oauth2_sessions(
+    job: ExpireOAuth2SessionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "expire OAuth 2.0 sessions job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let sessions = repo
+        .oauth2_session()
+        .finish_expired(
+            &clock,
+            state.session_ttl(),
+            state.session_inactivity_ttl(),
+            EXPIRED_SESSIONS_BATCH_SIZE,
+        )
+        .await?;
+
+    // Sync the devices of the users, so that the devices of the ended sessions
+    // are removed from the homeserver
+    let user_ids: BTreeSet<_> = sessions
+        .iter()
+        .filter_map(|session| session.user_id)
+        .collect();
+    for user_id in user_ids {
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            continue;
+        };
+
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+    }
+
+    repo.save().await?;
+
+    if sessions.is_empty() {
+        debug!("no OAuth 2.0 session to expire");
+    } else {
+        info!(count = sessions.len(), "expired OAuth 2.0 sessions");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ExpireCompatSessionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ExpireCompatSessionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ExpireCompatSessionsJob {
+    const NAME: &'static str = "expire-compat-sessions";
+}
+
+impl TracedJob for ExpireCompatSessionsJob {}
+
+/// End the compatibility sessions which went past their maximum lifetime, or
+/// which stayed inactive for too long
+pub async fn expire_compat_sessions(
+    job: ExpireCompatSessionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "expire compatibility sessions job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let session_ttl = state.compat_session_ttl();
+    let inactivity_ttl = state.compat_session_inactivity_ttl();
+    if session_ttl.is_none() && inactivity_ttl.is_none() {
+        return Ok(());
+    }
+
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let sessions = repo
+        .compat_session()
+        .finish_expired(
+            &clock,
+            session_ttl,
+            inactivity_ttl,
+            EXPIRED_SESSIONS_BATCH_SIZE,
+        )
+        .await?;
+
+    // Sync the devices of the users, so that the devices of the ended sessions
+    // are removed from the homeserver
+    let user_ids: BTreeSet<_> = sessions.iter().map(|session| session.user_id).collect();
+    for user_id in user_ids {
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            continue;
+        };
+
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+    }
+
+    repo.save().await?;
+
+    if sessions.is_empty() {
+        debug!("no compatibility session to expire");
+    } else {
+        info!(count = sessions.len(), "expired compatibility sessions");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ExpireBrowserSessionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ExpireBrowserSessionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ExpireBrowserSessionsJob {
+    const NAME: &'static str = "expire-browser-sessions";
+}
+
+impl TracedJob for ExpireBrowserSessionsJob {}
+
+/// End the browser sessions which went past their maximum lifetime, or which
+/// stayed inactive for too long
+pub async fn expire_browser_sessions(
+    job: ExpireBrowserSessionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("expire browser sessions job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let session_ttl = state.browser_session_ttl();
+    let inactivity_ttl = state.browser_session_inactivity_ttl();
+    if session_ttl.is_none() && inactivity_ttl.is_none() {
+        return Ok(());
+    }
+
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let count = repo
+        .browser_session()
+        .finish_expired(
+            &clock,
+            session_ttl,
+            inactivity_ttl,
+            EXPIRED_SESSIONS_BATCH_SIZE,
+        )
+        .await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no browser session to expire");
+    } else {
+        info!(count, "expired browser sessions");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ExpireBrowserSessionBindingsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ExpireBrowserSessionBindingsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ExpireBrowserSessionBindingsJob {
+    const NAME: &'static str = "expire-browser-session-bindings";
+}
+
+impl TracedJob for ExpireBrowserSessionBindingsJob {}
+
+/// End the browser sessions whose browser didn't prove in time that it still
+/// holds the credential the session is bound to
+pub async fn expire_browser_session_bindings(
+    job: ExpireBrowserSessionBindingsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "expire browser session bindings job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let filter = BrowserSessionFilter::new()
+        .active_only()
+        .with_binding_expired_before(clock.now());
+    let count = repo.browser_session().finish_bulk(&clock, filter).await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no browser session binding expired");
+    } else {
+        info!(count, "ended browser sessions with an expired binding");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ExpireImpersonationSessionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ExpireImpersonationSessionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ExpireImpersonationSessionsJob {
+    const NAME: &'static str = "expire-impersonation-sessions";
+}
+
+impl TracedJob for ExpireImpersonationSessionsJob {}
+
+/// End the impersonation sessions which expired, along with the sessions
+/// started from them
+pub async fn expire_impersonation_sessions(
+    job: ExpireImpersonationSessionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "expire impersonation sessions job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let mut repo = state.repository().await?;
+
+    let filter = BrowserSessionFilter::new()
+        .active_only()
+        .with_impersonation_expired_before(clock.now());
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(EXPIRED_SESSIONS_BATCH_SIZE))
+        .await?;
+
+    for session in &page.edges {
+        let oauth2_filter = OAuth2SessionFilter::new()
+            .for_browser_session(session)
+            .active_only();
+        let oauth2_count = repo
+            .oauth2_session()
+            .finish_bulk(&clock, oauth2_filter)
+            .await?;
+        let compat_filter = CompatSessionFilter::new()
+            .for_browser_session(session)
+            .active_only();
+        let compat_count = repo
+            .compat_session()
+            .finish_bulk(&clock, compat_filter)
+            .await?;
+        if oauth2_count + compat_count > 0 {
+            repo.job()
+                .schedule_job(SyncDevicesJob::new(&session.user))
+                .await?;
+        }
+
+        let session = repo
+            .browser_session()
+            .finish(&clock, session.clone())
+            .await?;
+
+        repo.audit_event()
+            .add(
+                &mut rng,
+                &clock,
+                AuditEventKind::AdminAction,
+                session
+                    .impersonation
+                    .as_ref()
+                    .and_then(|impersonation| impersonation.impersonator_user_id),
+                Some(session.user.id),
+                None,
+                None,
+                serde_json::json!({
+                    "action": "end_impersonation",
+                    "browser_session_id": session.id.to_string(),
+                    "reason": "expired",
+                }),
+            )
+            .await?;
+
+        repo.job()
+            .schedule_job(DispatchWebhookJob::session_revoked(
+                &mut rng,
+                &clock,
+                &session.user,
+                "browser",
+                session.id,
+            ))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    if page.edges.is_empty() {
+        debug!("no impersonation session expired");
+    } else {
+        info!(
+            count = page.edges.len(),
+            "ended expired impersonation sessions"
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ExpireKioskSessionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ExpireKioskSessionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ExpireKioskSessionsJob {
+    const NAME: &'static str = "expire-kiosk-sessions";
+}
+
+impl TracedJob for ExpireKioskSessionsJob {}
+
+/// End the sessions of the users logged in on kiosk devices which went past
+/// their maximum lifetime, or which stayed inactive for too long
+pub async fn expire_kiosk_sessions(
+    job: ExpireKioskSessionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("expire kiosk sessions job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let sessions = repo
+        .oauth2_session()
+        .finish_expired_on_kiosks(
+            &clock,
+            state.kiosk_session_ttl(),
+            state.kiosk_session_inactivity_ttl(),
+            EXPIRED_SESSIONS_BATCH_SIZE,
+        )
+        .await?;
+
+    // Sync the devices of the users, so that they are logged out of the kiosk
+    // device on the homeserver
+    let user_ids: BTreeSet<_> = sessions
+        .iter()
+        .filter_map(|session| session.user_id)
+        .collect();
+    for user_id in user_ids {
+        let Some(user) = repo.user().lookup(user_id).await? else {
+            continue;
+        };
+
+        repo.job().schedule_job(SyncDevicesJob::new(&user)).await?;
+    }
+
+    repo.save().await?;
+
+    if sessions.is_empty() {
+        debug!("no kiosk session to expire");
+    } else {
+        info!(count = sessions.len(), "expired kiosk sessions");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct CleanupAuditEventsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupAuditEventsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupAuditEventsJob {
+    const NAME: &'static str = "cleanup-audit-events";
+}
+
+impl TracedJob for CleanupAuditEventsJob {}
+
+pub async fn cleanup_audit_events(
+    job: CleanupAuditEventsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("cleanup audit events job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let Some(retention) = state.audit_retention() else {
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let before = clock.now() - chrono::Duration::from_std(retention)?;
+    let mut repo = state.repository().await?;
+
+    let count = repo.audit_event().cleanup_before(before).await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no audit event to clean up");
+    } else {
+        info!(count, "cleaned up audit events");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct CleanupChangesFeedJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupChangesFeedJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupChangesFeedJob {
+    const NAME: &'static str = "cleanup-changes-feed";
+}
+
+impl TracedJob for CleanupChangesFeedJob {}
+
+pub async fn cleanup_changes_feed(
+    job: CleanupChangesFeedJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("cleanup changes feed job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let Some(retention) = state.changes_feed_retention() else {
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let before = clock.now() - chrono::Duration::from_std(retention)?;
+    let mut repo = state.repository().await?;
+
+    let count = repo.changes_feed().cleanup_before(before).await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no changes feed entry to clean up");
+    } else {
+        info!(count, "cleaned up changes feed entries");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct CleanupUpstreamOAuthFunnelJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupUpstreamOAuthFunnelJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupUpstreamOAuthFunnelJob {
+    const NAME: &'static str = "cleanup-upstream-oauth-funnel";
+}
+
+impl TracedJob for CleanupUpstreamOAuthFunnelJob {}
+
+/// How long the events of the upstream OAuth 2.0 signup funnel are kept
+const UPSTREAM_OAUTH_FUNNEL_RETENTION: chrono::Duration = chrono::Duration::days(90);
+
+pub async fn cleanup_upstream_oauth_funnel(
+    job: CleanupUpstreamOAuthFunnelJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "cleanup upstream OAuth 2.0 funnel job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let before = clock.now() - UPSTREAM_OAUTH_FUNNEL_RETENTION;
+    let mut repo = state.repository().await?;
+
+    let count = repo.upstream_oauth_funnel().cleanup_before(before).await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no upstream OAuth 2.0 funnel event to clean up");
+    } else {
+        info!(count, "cleaned up upstream OAuth 2.0 funnel events");
+    }
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -77,7 +727,148 @@ pub(crate) fn register(
         .layer(state.inject())
         .layer(metrics_layer())
         .layer(trace_layer())
+        .layer(request_id_layer())
         .build_fn(cleanup_expired_tokens);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("5 * * * * *").unwrap();
+    let worker_name = format!(
+        "{job}-{suffix}",
+        job = CleanupExpiredRendezvousSessionsJob::NAME
+    );
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(cleanup_expired_rendezvous_sessions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("35 * * * * *").unwrap();
+    let worker_name = format!(
+        "{job}-{suffix}",
+        job = CleanupExpiredJwtBearerAssertionsJob::NAME
+    );
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(cleanup_expired_jwt_bearer_assertions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ExpireOAuth2SessionsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(expire_oauth2_sessions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("10 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ExpireCompatSessionsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(expire_compat_sessions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("20 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ExpireBrowserSessionsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(expire_browser_sessions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("30 * * * * *").unwrap();
+    let worker_name = format!(
+        "{job}-{suffix}",
+        job = ExpireBrowserSessionBindingsJob::NAME
+    );
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(expire_browser_session_bindings);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("45 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ExpireImpersonationSessionsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(expire_impersonation_sessions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("50 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ExpireKioskSessionsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(expire_kiosk_sessions);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 15 3 * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CleanupUpstreamOAuthFunnelJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(cleanup_upstream_oauth_funnel);
+    let monitor = monitor.register(worker);
+
+    // Only schedule the audit log cleanup if a retention period is configured
+    let monitor = if state.audit_retention().is_some() {
+        let schedule = apalis_cron::Schedule::from_str("0 0 * * * *").unwrap();
+        let worker_name = format!("{job}-{suffix}", job = CleanupAuditEventsJob::NAME);
+        let worker = WorkerBuilder::new(worker_name)
+            .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+            .layer(state.inject())
+            .layer(metrics_layer())
+            .layer(trace_layer())
+            .layer(request_id_layer())
+            .build_fn(cleanup_audit_events);
+        monitor.register(worker)
+    } else {
+        monitor
+    };
+
+    // Only schedule the changes feed cleanup if the feed is enabled
+    if state.changes_feed_retention().is_none() {
+        return monitor;
+    }
+
+    let schedule = apalis_cron::Schedule::from_str("0 30 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CleanupChangesFeedJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(cleanup_changes_feed);
 
     monitor.register(worker)
 }
@@ -0,0 +1,201 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Periodic digest of the activity on user accounts
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::NotificationKind;
+use mas_email::{Address, Mailbox};
+use mas_i18n::locale;
+use mas_storage::{
+    audit::AuditEventFilter, user::UserActivityDigestRepository, Clock, Pagination,
+    RepositoryAccess,
+};
+use mas_templates::{EmailActivityDigestContext, TemplateContext};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    email::deliver,
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// The maximum number of digests sent in one run of the job
+const DIGESTS_BATCH_SIZE: usize = 100;
+
+/// The maximum number of audit events included in a single digest
+const MAX_EVENTS_PER_DIGEST: usize = 100;
+
+#[derive(Default, Clone)]
+pub struct SendActivityDigestsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for SendActivityDigestsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for SendActivityDigestsJob {
+    const NAME: &'static str = "send-activity-digests";
+}
+
+impl TracedJob for SendActivityDigestsJob {}
+
+pub async fn send_activity_digests(
+    job: SendActivityDigestsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("send activity digests job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let Some(interval) = state.activity_digest_interval() else {
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let mailer = state.mailer();
+    let account_link = state.url_builder().account_management_uri();
+    let mut repo = state.repository().await?;
+
+    let now = clock.now();
+    let digests = repo
+        .user_activity_digest()
+        .list_due(now, interval, DIGESTS_BATCH_SIZE)
+        .await?;
+
+    let mut sent = 0;
+    for digest in digests {
+        let user = repo
+            .user()
+            .lookup(digest.user_id)
+            .await?
+            .context("User not found")?;
+
+        let since = digest.period_start();
+
+        // Whatever happens next, this period is over
+        let digest = repo
+            .user_activity_digest()
+            .mark_sent(&clock, digest)
+            .await?;
+
+        if !user.is_valid() {
+            debug!(%user.id, "User is locked, not sending activity digest");
+            continue;
+        }
+
+        let preferences = repo.user_notification_preferences().get(&user).await?;
+        if !preferences.allows(NotificationKind::ActivityDigest) {
+            debug!(%user.id, "User only wants security notifications, not sending activity digest");
+            continue;
+        }
+
+        let Some(user_email_id) = user.primary_user_email_id else {
+            warn!(%user.id, "User has no primary email, can't send activity digest");
+            continue;
+        };
+
+        let user_email = repo
+            .user_email()
+            .lookup(user_email_id)
+            .await?
+            .context("User email not found")?;
+
+        let filter = AuditEventFilter::new()
+            .for_user(&user)
+            .since(since)
+            .until(now);
+        let events = repo
+            .audit_event()
+            .list(filter, Pagination::first(MAX_EVENTS_PER_DIGEST))
+            .await?
+            .edges;
+
+        let context =
+            EmailActivityDigestContext::new(user.clone(), since, events, account_link.clone());
+        if context.is_empty() {
+            debug!(%user.id, "No activity to report, not sending activity digest");
+            continue;
+        }
+
+        let language = digest.locale.parse().unwrap_or(locale!("en").into());
+        let context = context.with_language(language);
+
+        let address: Address = user_email.email.parse()?;
+        let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+        // XXX: we only log if the email fails to be queued, to avoid stopping the loop
+        match deliver(
+            &state,
+            &address,
+            mailer.render_activity_digest_email(mailbox, &context),
+        )
+        .await
+        {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                error!(
+                    error = &*e as &dyn std::error::Error,
+                    "Failed to queue activity digest email"
+                );
+                continue;
+            }
+        }
+
+        info!(
+            email.id = %user_email.id,
+            "Activity digest email queued"
+        );
+        sent += 1;
+    }
+
+    repo.save().await?;
+
+    if sent == 0 {
+        debug!("no activity digest to send");
+    } else {
+        info!(count = sent, "sent activity digests");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    // Only schedule the digests if an interval is configured
+    if state.activity_digest_interval().is_none() {
+        return monitor;
+    }
+
+    let schedule = apalis_cron::Schedule::from_str("0 0 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = SendActivityDigestsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(send_activity_digests);
+
+    monitor.register(worker)
+}
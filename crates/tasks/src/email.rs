@@ -4,18 +4,195 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::sync::LazyLock;
+
 use anyhow::Context;
 use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
 use chrono::Duration;
-use mas_email::{Address, Mailbox};
+use mas_data_model::NotificationKind;
+use mas_email::{Address, Email, Mailbox};
 use mas_i18n::locale;
-use mas_storage::job::{JobWithSpanContext, VerifyEmailJob};
-use mas_templates::{EmailVerificationContext, TemplateContext};
+use mas_router::{CibaConsent, Login};
+use mas_storage::{
+    job::{
+        JobRepositoryExt, JobWithSpanContext, NewDeviceSession, SendCibaNotificationJob,
+        SendEmailJob, SendInactivityWarningJob, SendNewDeviceNotificationJob,
+        SendRefreshTokenReuseNotificationJob, VerifyEmailJob,
+    },
+    Clock, RepositoryAccess,
+};
+use mas_templates::{
+    EmailCibaContext, EmailInactiveAccountContext, EmailNewDeviceContext,
+    EmailRefreshTokenReuseContext, EmailVerificationContext, TemplateContext,
+};
+use opentelemetry::metrics::Counter;
 use rand::{distributions::Uniform, Rng};
-use tracing::info;
+use tracing::{error, info, warn};
 
 use crate::{storage::PostgresStorageFactory, JobContextExt, State};
 
+/// How many times sending an email is attempted before giving up on it
+const MAX_SEND_ATTEMPTS: u32 = 10;
+
+/// How long to wait before trying to send an email again for the first time.
+/// The delay doubles after each attempt.
+const SEND_RETRY_DELAY: Duration = Duration::seconds(30);
+
+/// The longest delay between two attempts to send an email
+const SEND_RETRY_MAX_DELAY: Duration = Duration::hours(6);
+
+static DEAD_LETTERS: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .u64_counter("mas.email.dead_letters")
+    .with_description("The number of emails which could not be sent after all the attempts")
+    .with_unit("{email}")
+    .init()
+});
+
+/// The delay before the next attempt to send an email, after the given
+/// number of failed attempts
+fn send_retry_delay(attempt: u32) -> Duration {
+    // Past 2^16, the delay is capped anyway
+    let factor = 1 << attempt.min(16);
+    (SEND_RETRY_DELAY * factor).min(SEND_RETRY_MAX_DELAY)
+}
+
+/// Queue a rendered email to be sent by the [`send_email`] job, unless emails
+/// to the given address hard-bounced before.
+///
+/// Returns `false` if the email was not queued because the address is
+/// suppressed, and an error if it could not be rendered or queued.
+pub(crate) async fn deliver(
+    state: &State,
+    address: &Address,
+    email: Result<Email, mas_email::Error>,
+) -> Result<bool, anyhow::Error> {
+    let email = email?;
+    let address: &str = address.as_ref();
+
+    let mut repo = state.repository().await?;
+    if repo.email_delivery_failure().is_suppressed(address).await? {
+        repo.cancel().await?;
+        warn!(
+            email = address,
+            "Not sending email to an address which hard-bounced before"
+        );
+        return Ok(false);
+    }
+
+    let content =
+        String::from_utf8(email.content().to_vec()).context("Email content is not UTF-8")?;
+    repo.job()
+        .schedule_job(SendEmailJob::new(
+            email.sender().map(ToString::to_string),
+            email.recipients().iter().map(ToString::to_string).collect(),
+            content,
+        ))
+        .await?;
+    repo.save().await?;
+
+    Ok(true)
+}
+
+/// Job to hand a queued email to the mail server.
+///
+/// Delivery failures are recorded, and sending is tried again later with an
+/// exponential backoff, until the mail server accepts the email, rejects it
+/// permanently, or [`MAX_SEND_ATTEMPTS`] is reached. In the last case, the job
+/// fails, and is kept in the job queue as a dead letter.
+#[tracing::instrument(
+    name = "job.send_email",
+    fields(email.attempt = job.attempt()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_email(
+    job: JobWithSpanContext<SendEmailJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mailer = state.mailer();
+    let clock = state.clock();
+    let mut rng = state.rng();
+
+    let sender = job.sender().map(str::parse).transpose()?;
+    let recipients: Vec<Address> = job
+        .recipients()
+        .iter()
+        .map(|recipient| recipient.parse())
+        .collect::<Result<_, _>>()?;
+    let email = Email::new(sender, recipients, job.content().as_bytes().to_vec())?;
+
+    // An earlier email to the same address might have bounced since this one
+    // was queued
+    let mut repo = state.repository().await?;
+    for recipient in job.recipients() {
+        if repo
+            .email_delivery_failure()
+            .is_suppressed(recipient)
+            .await?
+        {
+            repo.cancel().await?;
+            warn!(
+                email = recipient,
+                "Not sending email to an address which hard-bounced before"
+            );
+            return Ok(());
+        }
+    }
+    repo.cancel().await?;
+
+    let Err(e) = mailer.send(&email).await else {
+        return Ok(());
+    };
+
+    let permanent = e.is_bounce();
+    let mut repo = state.repository().await?;
+    for recipient in job.recipients() {
+        repo.email_delivery_failure()
+            .add(&mut rng, &clock, recipient, permanent, e.to_string())
+            .await?;
+    }
+
+    if permanent {
+        repo.save().await?;
+        warn!(
+            error = &e as &dyn std::error::Error,
+            "Email bounced, no more emails will be sent to this address"
+        );
+        return Ok(());
+    }
+
+    let attempt = job.attempt() + 1;
+    if attempt >= MAX_SEND_ATTEMPTS {
+        repo.save().await?;
+        DEAD_LETTERS.add(1, &[]);
+        error!(
+            error = &e as &dyn std::error::Error,
+            attempts = attempt,
+            "Giving up sending email"
+        );
+        return Err(e).context("Giving up sending email");
+    }
+
+    let run_at = clock.now() + send_retry_delay(job.attempt());
+    repo.job().schedule_job_at(job.retry(), run_at).await?;
+    repo.save().await?;
+
+    warn!(
+        error = &e as &dyn std::error::Error,
+        %run_at,
+        "Failed to send email, will try again later"
+    );
+
+    Ok(())
+}
+
 #[tracing::instrument(
     name = "job.verify_email",
     fields(user_email.id = %job.user_email_id()),
@@ -71,17 +248,402 @@ async fn verify_email(
         .await?;
 
     // And send the verification email
-    let mailbox = Mailbox::new(Some(user.username.clone()), address);
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
 
     let context =
         EmailVerificationContext::new(user.clone(), verification.clone()).with_language(language);
 
-    mailer.send_verification_email(mailbox, &context).await?;
+    let sent = deliver(
+        &state,
+        &address,
+        mailer.render_verification_email(mailbox, &context),
+    )
+    .await?;
 
-    info!(
-        email.id = %user_email.id,
-        "Verification email sent"
-    );
+    if sent {
+        info!(
+            email.id = %user_email.id,
+            "Verification email queued"
+        );
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+/// Job to ask a user to approve a CIBA request, by sending them an email with
+/// a link to the approval page.
+#[tracing::instrument(
+    name = "job.send_ciba_notification",
+    fields(oauth2_ciba_grant.id = %job.ciba_grant_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_ciba_notification(
+    job: JobWithSpanContext<SendCibaNotificationJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mailer = state.mailer();
+    let url_builder = state.url_builder();
+    let clock = state.clock();
+
+    let grant = repo
+        .oauth2_ciba_grant()
+        .lookup(job.ciba_grant_id())
+        .await?
+        .context("CIBA grant not found")?;
+
+    if !grant.is_pending() || grant.expires_at < clock.now() {
+        info!("CIBA grant is not pending anymore, not sending notification");
+        return Ok(());
+    }
+
+    let client = repo
+        .oauth2_client()
+        .lookup(grant.client_id)
+        .await?
+        .context("Client not found")?;
+
+    let user = repo
+        .user()
+        .lookup(grant.user_id)
+        .await?
+        .context("User not found")?;
+
+    let Some(user_email_id) = user.primary_user_email_id else {
+        warn!(%user.id, "User has no primary email, can't send CIBA notification");
+        return Ok(());
+    };
+
+    let user_email = repo
+        .user_email()
+        .lookup(user_email_id)
+        .await?
+        .context("User email not found")?;
+
+    let address: Address = user_email.email.parse()?;
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+    let approval_link = url_builder.absolute_url_for(&CibaConsent::new(grant.id));
+
+    // XXX: we don't know the language of the user, so we default to English
+    let context = EmailCibaContext::new(user, grant, client, approval_link)
+        .with_language(locale!("en").into());
+
+    let sent = deliver(
+        &state,
+        &address,
+        mailer.render_ciba_email(mailbox, &context),
+    )
+    .await?;
+
+    if sent {
+        info!(
+            email.id = %user_email.id,
+            "CIBA notification email queued"
+        );
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+/// Job to notify a user that one of their OAuth 2.0 sessions was ended because
+/// one of its refresh tokens was reused.
+#[tracing::instrument(
+    name = "job.send_refresh_token_reuse_notification",
+    fields(oauth2_session.id = %job.session_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_refresh_token_reuse_notification(
+    job: JobWithSpanContext<SendRefreshTokenReuseNotificationJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mailer = state.mailer();
+    let url_builder = state.url_builder();
+
+    let session = repo
+        .oauth2_session()
+        .lookup(job.session_id())
+        .await?
+        .context("OAuth 2.0 session not found")?;
+
+    let Some(user_id) = session.user_id else {
+        info!("Session has no user, not sending notification");
+        return Ok(());
+    };
+
+    let client = repo
+        .oauth2_client()
+        .lookup(session.client_id)
+        .await?
+        .context("Client not found")?;
+
+    let user = repo
+        .user()
+        .lookup(user_id)
+        .await?
+        .context("User not found")?;
+
+    let preferences = repo.user_notification_preferences().get(&user).await?;
+    if !preferences.allows(NotificationKind::SecurityAlert) {
+        info!(%user.id, "User doesn't want security alerts, not sending refresh token reuse notification");
+        return Ok(());
+    }
+
+    let Some(user_email_id) = user.primary_user_email_id else {
+        warn!(%user.id, "User has no primary email, can't send refresh token reuse notification");
+        return Ok(());
+    };
+
+    let user_email = repo
+        .user_email()
+        .lookup(user_email_id)
+        .await?
+        .context("User email not found")?;
+
+    let address: Address = user_email.email.parse()?;
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+    let account_link = url_builder.account_management_uri();
+
+    // XXX: we don't know the language of the user, so we default to English
+    let context = EmailRefreshTokenReuseContext::new(user, session, client, account_link)
+        .with_language(locale!("en").into());
+
+    let sent = deliver(
+        &state,
+        &address,
+        mailer.render_refresh_token_reuse_email(mailbox, &context),
+    )
+    .await?;
+
+    if sent {
+        info!(
+            email.id = %user_email.id,
+            "Refresh token reuse notification email queued"
+        );
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+/// Job to notify a user by email that they signed in from a browser or device
+/// they never used before.
+#[tracing::instrument(
+    name = "job.send_new_device_notification",
+    fields(session = ?job.session()),
+    skip_all,
+    err(Debug),
+)]
+#[allow(clippy::too_many_lines)]
+async fn send_new_device_notification(
+    job: JobWithSpanContext<SendNewDeviceNotificationJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let mailer = state.mailer();
+    let url_builder = state.url_builder();
+    let mut repo = state.repository().await?;
+
+    let (user_id, user_agent, signed_in_at, client) = match job.session() {
+        NewDeviceSession::OAuth2(session_id) => {
+            let session = repo
+                .oauth2_session()
+                .lookup(session_id)
+                .await?
+                .context("OAuth 2.0 session not found")?;
+
+            let Some(user_id) = session.user_id else {
+                info!("Session has no user, not sending notification");
+                return Ok(());
+            };
+
+            let client = repo
+                .oauth2_client()
+                .lookup(session.client_id)
+                .await?
+                .context("Client not found")?;
+
+            (
+                user_id,
+                session.user_agent,
+                session.created_at,
+                Some(client),
+            )
+        }
+
+        NewDeviceSession::Compat(session_id) => {
+            let session = repo
+                .compat_session()
+                .lookup(session_id)
+                .await?
+                .context("Compatibility session not found")?;
+
+            (
+                session.user_id,
+                session.user_agent,
+                session.created_at,
+                None,
+            )
+        }
+    };
+
+    let Some((user_agent, fingerprint)) = user_agent.and_then(|user_agent| {
+        let fingerprint = user_agent.device_fingerprint()?;
+        Some((user_agent, fingerprint))
+    }) else {
+        info!("Device can't be identified, not sending notification");
+        return Ok(());
+    };
+
+    let user = repo
+        .user()
+        .lookup(user_id)
+        .await?
+        .context("User not found")?;
+
+    // There is nothing to compare the first device of a user with
+    let first_device = repo.user_known_device().count(&user).await? == 0;
+    let new_device = repo
+        .user_known_device()
+        .remember(&mut rng, &clock, &user, &fingerprint)
+        .await?;
+
+    if first_device || !new_device {
+        repo.save().await?;
+        return Ok(());
+    }
+
+    let preferences = repo.user_notification_preferences().get(&user).await?;
+    if !preferences.allows(NotificationKind::NewLogin) {
+        info!(%user.id, "User doesn't want new login alerts, not sending new device notification");
+        repo.save().await?;
+        return Ok(());
+    }
+
+    let Some(user_email_id) = user.primary_user_email_id else {
+        warn!(%user.id, "User has no primary email, can't send new device notification");
+        repo.save().await?;
+        return Ok(());
+    };
+
+    let user_email = repo
+        .user_email()
+        .lookup(user_email_id)
+        .await?
+        .context("User email not found")?;
+
+    let address: Address = user_email.email.parse()?;
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+    let ip_address = job.ip();
+    let location = state
+        .geoip()
+        .zip(ip_address)
+        .and_then(|(geoip, ip)| geoip.locate(ip))
+        .and_then(|location| location.describe());
+
+    let account_link = url_builder.account_management_uri();
+
+    // XXX: we don't know the language of the user, so we default to English
+    let context = EmailNewDeviceContext::new(user, user_agent, signed_in_at, account_link)
+        .with_client(client)
+        .with_ip_address(ip_address)
+        .with_location(location)
+        .with_language(locale!("en").into());
+
+    let sent = deliver(
+        &state,
+        &address,
+        mailer.render_new_device_email(mailbox, &context),
+    )
+    .await?;
+
+    if sent {
+        info!(
+            email.id = %user_email.id,
+            "New device notification email queued"
+        );
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+/// Job to warn a user by email that their account will be deactivated because
+/// they didn't use it for a long time.
+#[tracing::instrument(
+    name = "job.send_inactivity_warning",
+    fields(user.id = %job.user_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_inactivity_warning(
+    job: JobWithSpanContext<SendInactivityWarningJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mailer = state.mailer();
+    let url_builder = state.url_builder();
+
+    let user = repo
+        .user()
+        .lookup(job.user_id())
+        .await?
+        .context("User not found")?;
+
+    if !user.is_valid() {
+        info!(%user.id, "User is locked, not sending inactivity warning");
+        return Ok(());
+    }
+
+    let Some(user_email_id) = user.primary_user_email_id else {
+        warn!(%user.id, "User has no primary email, can't send inactivity warning");
+        return Ok(());
+    };
+
+    let user_email = repo
+        .user_email()
+        .lookup(user_email_id)
+        .await?
+        .context("User email not found")?;
+
+    let address: Address = user_email.email.parse()?;
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+    let login_link = url_builder.absolute_url_for(&Login::default());
+
+    // XXX: we don't know the language of the user, so we default to English
+    let context = EmailInactiveAccountContext::new(user, job.deactivation_at(), login_link)
+        .with_language(locale!("en").into());
+
+    let sent = deliver(
+        &state,
+        &address,
+        mailer.render_inactive_account_email(mailbox, &context),
+    )
+    .await?;
+
+    if sent {
+        info!(
+            email.id = %user_email.id,
+            "Inactivity warning email queued"
+        );
+    }
 
     repo.save().await?;
 
@@ -94,8 +656,20 @@ pub(crate) fn register(
     state: &State,
     storage_factory: &PostgresStorageFactory,
 ) -> Monitor<TokioExecutor> {
+    let send_email_worker =
+        crate::build!(SendEmailJob => send_email, suffix, state, storage_factory);
     let verify_email_worker =
         crate::build!(VerifyEmailJob => verify_email, suffix, state, storage_factory);
+    let send_ciba_notification_worker = crate::build!(SendCibaNotificationJob => send_ciba_notification, suffix, state, storage_factory);
+    let send_refresh_token_reuse_notification_worker = crate::build!(SendRefreshTokenReuseNotificationJob => send_refresh_token_reuse_notification, suffix, state, storage_factory);
+    let send_new_device_notification_worker = crate::build!(SendNewDeviceNotificationJob => send_new_device_notification, suffix, state, storage_factory);
+    let send_inactivity_warning_worker = crate::build!(SendInactivityWarningJob => send_inactivity_warning, suffix, state, storage_factory);
 
-    monitor.register(verify_email_worker)
+    monitor
+        .register(send_email_worker)
+        .register(verify_email_worker)
+        .register(send_ciba_notification_worker)
+        .register(send_refresh_token_reuse_notification_worker)
+        .register(send_new_device_notification_worker)
+        .register(send_inactivity_warning_worker)
 }
@@ -0,0 +1,312 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Publishing of events to an event streaming platform
+
+use std::str::FromStr;
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::WebhookEvent;
+use mas_storage::RepositoryAccess;
+use tracing::{debug, info};
+
+use crate::{
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How many events are published in a single transaction
+const BATCH_SIZE: usize = 100;
+
+/// A sink to which events stored in the outbox are published
+///
+/// Each platform is behind a cargo feature, `kafka` and `nats`, so that their
+/// client libraries are only built when needed.
+pub struct EventStream {
+    sink: Sink,
+}
+
+enum Sink {
+    /// Publish events to a Kafka topic
+    #[cfg(feature = "kafka")]
+    Kafka(kafka::Sink),
+
+    /// Publish events to subjects of a NATS JetStream
+    #[cfg(feature = "nats")]
+    Nats(nats::Sink),
+}
+
+impl std::fmt::Debug for EventStream {
+    #[cfg_attr(not(any(feature = "kafka", feature = "nats")), allow(unused_variables))]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.sink {
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(ref sink) => f.debug_struct("Kafka").field("topic", &sink.topic).finish(),
+            #[cfg(feature = "nats")]
+            Sink::Nats(ref sink) => f
+                .debug_struct("Nats")
+                .field("subject", &sink.subject)
+                .finish(),
+        }
+    }
+}
+
+impl EventStream {
+    /// Create a sink which publishes events to a Kafka topic
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Kafka producer could not be created
+    #[cfg(feature = "kafka")]
+    pub fn kafka(brokers: &[String], topic: String) -> Result<Self, anyhow::Error> {
+        let sink = kafka::Sink::new(brokers, topic)?;
+        Ok(Self {
+            sink: Sink::Kafka(sink),
+        })
+    }
+
+    /// Create a sink which publishes events to a NATS JetStream subject
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection to the NATS server failed
+    #[cfg(feature = "nats")]
+    pub async fn nats(url: &url::Url, subject: String) -> Result<Self, anyhow::Error> {
+        let sink = nats::Sink::connect(url, subject).await?;
+        Ok(Self {
+            sink: Sink::Nats(sink),
+        })
+    }
+
+    /// Publish a single event, waiting for the platform to acknowledge it
+    #[cfg_attr(
+        not(any(feature = "kafka", feature = "nats")),
+        allow(unused_variables, clippy::unused_async)
+    )]
+    async fn publish(&self, event: &WebhookEvent) -> Result<(), anyhow::Error> {
+        match self.sink {
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(ref sink) => sink.publish(event).await,
+            #[cfg(feature = "nats")]
+            Sink::Nats(ref sink) => sink.publish(event).await,
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use mas_data_model::WebhookEvent;
+    use rdkafka::{
+        message::{Header, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord},
+        ClientConfig,
+    };
+
+    /// How long to wait for Kafka to acknowledge a message
+    const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub(super) struct Sink {
+        producer: FutureProducer,
+        pub(super) topic: String,
+    }
+
+    impl Sink {
+        pub(super) fn new(brokers: &[String], topic: String) -> Result<Self, anyhow::Error> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers.join(","))
+                // Make sure retries from the producer don't introduce duplicates
+                .set("enable.idempotence", "true")
+                .create()
+                .context("Failed to create the Kafka producer")?;
+
+            Ok(Self { producer, topic })
+        }
+
+        pub(super) async fn publish(&self, event: &WebhookEvent) -> Result<(), anyhow::Error> {
+            let payload = serde_json::to_vec(event)?;
+            let event_id = event.id.to_string();
+
+            // Key the messages by user, so that all the events of a user end up in the
+            // same partition and are consumed in order
+            let key = event
+                .data
+                .get("user_id")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or(event_id.as_str());
+
+            let headers = OwnedHeaders::new()
+                .insert(Header {
+                    key: "id",
+                    value: Some(&event_id),
+                })
+                .insert(Header {
+                    key: "type",
+                    value: Some(event.kind.as_str()),
+                });
+
+            let record = FutureRecord::to(&self.topic)
+                .key(key)
+                .payload(&payload)
+                .headers(headers);
+
+            self.producer
+                .send(record, SEND_TIMEOUT)
+                .await
+                .map_err(|(error, _message)| error)
+                .context("Failed to publish the event to Kafka")?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use anyhow::Context;
+    use mas_data_model::WebhookEvent;
+    use url::Url;
+
+    pub(super) struct Sink {
+        jetstream: async_nats::jetstream::Context,
+        pub(super) subject: String,
+    }
+
+    impl Sink {
+        pub(super) async fn connect(url: &Url, subject: String) -> Result<Self, anyhow::Error> {
+            let client = async_nats::connect(url.as_str())
+                .await
+                .context("Failed to connect to the NATS server")?;
+            let jetstream = async_nats::jetstream::new(client);
+
+            Ok(Self { jetstream, subject })
+        }
+
+        pub(super) async fn publish(&self, event: &WebhookEvent) -> Result<(), anyhow::Error> {
+            let payload = serde_json::to_vec(event)?;
+            let event_id = event.id.to_string();
+
+            let mut headers = async_nats::HeaderMap::new();
+            // Lets JetStream deduplicate events which are published more than once
+            headers.insert("Nats-Msg-Id", event_id.as_str());
+
+            self.jetstream
+                .publish_with_headers(
+                    format!(
+                        "{subject}.{kind}",
+                        subject = self.subject,
+                        kind = event.kind
+                    ),
+                    headers,
+                    payload.into(),
+                )
+                .await
+                .context("Failed to publish the event to NATS")?
+                .await
+                .context("NATS did not acknowledge the event")?;
+
+            Ok(())
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct PublishEventsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for PublishEventsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for PublishEventsJob {
+    const NAME: &'static str = "publish-events";
+}
+
+impl TracedJob for PublishEventsJob {}
+
+/// Publish the events waiting in the outbox to the event stream.
+///
+/// Events are only removed from the outbox once they were acknowledged, in
+/// the same transaction which locked them, which gives at-least-once delivery.
+pub async fn publish_events(
+    job: PublishEventsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("publish events job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let Some(stream) = state.event_stream() else {
+        return Ok(());
+    };
+
+    let mut total = 0;
+    loop {
+        let mut repo = state.repository().await?;
+        let events = repo.event_outbox().fetch_batch(BATCH_SIZE).await?;
+
+        let mut published = Vec::with_capacity(events.len());
+        let mut result = Ok(());
+        for event in &events {
+            if let Err(e) = stream.publish(event).await {
+                result = Err(e);
+                break;
+            }
+            published.push(event.id);
+        }
+
+        // Remove the events which were published, even if a later one failed, so
+        // that they don't get published again on the next run
+        total += repo.event_outbox().remove(&published).await?;
+        repo.save().await?;
+        result?;
+
+        if events.len() < BATCH_SIZE {
+            break;
+        }
+    }
+
+    if total > 0 {
+        info!(count = total, "published events to the event stream");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    // Only publish events if an event stream is configured
+    if state.event_stream().is_none() {
+        return monitor;
+    }
+
+    let schedule = apalis_cron::Schedule::from_str("*/5 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = PublishEventsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(publish_events);
+
+    monitor.register(worker)
+}
@@ -0,0 +1,226 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Warnings about credentials which are about to expire
+
+use std::{str::FromStr, sync::LazyLock};
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::{ExpiringCredential, ExpiringCredentialKind};
+use mas_email::{Address, Mailbox};
+use mas_i18n::locale;
+use mas_storage::{Clock, RepositoryAccess};
+use mas_templates::{EmailCredentialsExpiringContext, TemplateContext};
+use opentelemetry::{metrics::Gauge, Key, KeyValue};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    email::deliver,
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+const KIND: Key = Key::from_static_str("kind");
+const NAME: Key = Key::from_static_str("name");
+
+static CREDENTIAL_EXPIRY: LazyLock<Gauge<i64>> = LazyLock::new(|| {
+    opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    )
+    .i64_gauge("mas.credential.expiry")
+    .with_description("Time left before a credential expires, negative if it already expired")
+    .with_unit("s")
+    .init()
+});
+
+/// How long to wait before warning the administrators again about the same
+/// credential
+const RENOTIFY_INTERVAL: chrono::Duration = chrono::Duration::weeks(1);
+
+/// Settings for the warnings about expiring credentials
+#[derive(Clone)]
+pub struct ExpiryWarnings {
+    threshold: chrono::Duration,
+    emails: Vec<Address>,
+    credentials: Vec<ExpiringCredential>,
+}
+
+impl ExpiryWarnings {
+    /// Create new expiry warnings settings
+    ///
+    /// # Parameters
+    ///
+    /// * `threshold` - How long before a credential expires to start warning
+    ///   about it
+    /// * `emails` - The addresses of the administrators to warn by email
+    /// * `credentials` - The credentials loaded from the configuration, like
+    ///   the TLS certificates and the upstream client secrets. The signing keys
+    ///   are read from the database.
+    #[must_use]
+    pub fn new(
+        threshold: chrono::Duration,
+        emails: Vec<Address>,
+        credentials: Vec<ExpiringCredential>,
+    ) -> Self {
+        Self {
+            threshold,
+            emails,
+            credentials,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct CheckCredentialsExpiryJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CheckCredentialsExpiryJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CheckCredentialsExpiryJob {
+    const NAME: &'static str = "check-credentials-expiry";
+}
+
+impl TracedJob for CheckCredentialsExpiryJob {}
+
+pub async fn check_credentials_expiry(
+    job: CheckCredentialsExpiryJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "check credentials expiry job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let settings = state.expiry_warnings();
+    let clock = state.clock();
+    let mailer = state.mailer();
+    let mut repo = state.repository().await?;
+    let now = clock.now();
+
+    let mut credentials = settings.credentials.clone();
+
+    // The signing keys superseded by a newer one are retired once the tokens
+    // they signed expired
+    if let Some(rotation) = state.key_rotation() {
+        let keys = repo.signing_key().list_unretired().await?;
+        credentials.extend(keys.iter().filter_map(|key| {
+            let retires_at = rotation.planned_retirement(key, &keys, now)?;
+            Some(ExpiringCredential::new(
+                ExpiringCredentialKind::SigningKey,
+                key.kid.clone(),
+                retires_at,
+            ))
+        }));
+    }
+
+    let mut to_notify = Vec::new();
+    for credential in credentials {
+        CREDENTIAL_EXPIRY.record(
+            (credential.expires_at - now).num_seconds(),
+            &[
+                KeyValue::new(KIND, credential.kind.as_str()),
+                KeyValue::new(NAME, credential.name.clone()),
+            ],
+        );
+
+        if credential.is_expired(now) {
+            error!(
+                credential.kind = %credential.kind,
+                credential.name = credential.name,
+                credential.expires_at = %credential.expires_at,
+                "Credential expired"
+            );
+        } else if credential.expires_within(settings.threshold, now) {
+            warn!(
+                credential.kind = %credential.kind,
+                credential.name = credential.name,
+                credential.expires_at = %credential.expires_at,
+                "Credential is about to expire"
+            );
+        } else {
+            continue;
+        }
+
+        if !settings.emails.is_empty()
+            && repo
+                .expiry_warning()
+                .mark_notified(&clock, &credential, RENOTIFY_INTERVAL)
+                .await?
+        {
+            to_notify.push(credential);
+        }
+    }
+
+    // Commit before sending the warnings, so that other workers don't send them
+    // as well
+    repo.save().await?;
+
+    if to_notify.is_empty() {
+        debug!("no expiring credential to warn about");
+        return Ok(());
+    }
+
+    // XXX: we don't know the language of the administrators, so we default to
+    // English
+    let context =
+        EmailCredentialsExpiringContext::new(to_notify).with_language(locale!("en").into());
+
+    for address in &settings.emails {
+        let mailbox = Mailbox::new(None, address.clone());
+        match deliver(
+            &state,
+            address,
+            mailer.render_credentials_expiring_email(mailbox, &context),
+        )
+        .await
+        {
+            Ok(true) => info!(email = %address, "Expiring credentials warning email queued"),
+            Ok(false) => {}
+            Err(e) => warn!(
+                error = &*e as &dyn std::error::Error,
+                email = %address,
+                "Failed to queue expiring credentials warning email"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    let schedule = apalis_cron::Schedule::from_str("0 30 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CheckCredentialsExpiryJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(check_credentials_expiry);
+
+    monitor.register(worker)
+}
@@ -0,0 +1,126 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Approximate location of IP addresses, using a `MaxMind` database
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use camino::Utf8Path;
+use maxminddb::{MaxMindDBError, Reader};
+use serde::Deserialize;
+use tracing::warn;
+
+/// The mean radius of the Earth, in kilometres
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// The subset of a `GeoIP2` City or Country record we're interested in
+#[derive(Deserialize)]
+struct Record {
+    city: Option<City>,
+    country: Option<Country>,
+    location: Option<Coordinates>,
+}
+
+#[derive(Deserialize)]
+struct City {
+    names: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Coordinates {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+/// The approximate location of an IP address
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Location {
+    /// The English name of the city, only available in City databases
+    pub city: Option<String>,
+
+    /// The ISO 3166-1 code of the country
+    pub country: Option<String>,
+
+    /// The latitude and longitude, in degrees
+    pub coordinates: Option<(f64, f64)>,
+}
+
+impl Location {
+    /// A human-readable description of the location, like `Paris, FR`
+    pub fn describe(&self) -> Option<String> {
+        match (self.city.as_deref(), self.country.as_deref()) {
+            (Some(city), Some(country)) => Some(format!("{city}, {country}")),
+            (Some(place), None) | (None, Some(place)) => Some(place.to_owned()),
+            (None, None) => None,
+        }
+    }
+
+    /// The great-circle distance to another location, in kilometres, if both
+    /// have coordinates
+    pub fn distance_km(&self, other: &Self) -> Option<f64> {
+        let (lat1, lon1) = self.coordinates?;
+        let (lat2, lon2) = other.coordinates?;
+        let (lat1, lon1, lat2, lon2) = (
+            lat1.to_radians(),
+            lon1.to_radians(),
+            lat2.to_radians(),
+            lon2.to_radians(),
+        );
+
+        // Haversine formula
+        let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+        Some(2.0 * EARTH_RADIUS_KM * a.sqrt().asin())
+    }
+}
+
+/// A `MaxMind` `GeoIP2` or `GeoLite2` database
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    /// Load a `GeoIP2` or `GeoLite2` City or Country database
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database could not be read or is invalid
+    pub fn open(path: &Utf8Path) -> Result<Self, MaxMindDBError> {
+        let reader = Reader::open_readfile(path)?;
+        Ok(Self { reader })
+    }
+
+    /// Locate an IP address, returning `None` if it is not in the database
+    pub(crate) fn locate(&self, ip: IpAddr) -> Option<Location> {
+        let record: Record = match self.reader.lookup(ip) {
+            Ok(record) => record,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return None,
+            Err(e) => {
+                warn!(error = &e as &dyn std::error::Error, %ip, "Failed to locate IP address");
+                return None;
+            }
+        };
+
+        let city = record
+            .city
+            .and_then(|city| city.names)
+            .and_then(|mut names| names.remove("en"));
+        let country = record.country.and_then(|country| country.iso_code);
+        let coordinates = record
+            .location
+            .and_then(|location| Some((location.latitude?, location.longitude?)));
+
+        Some(Location {
+            city,
+            country,
+            coordinates,
+        })
+    }
+}
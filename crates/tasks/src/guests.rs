@@ -0,0 +1,116 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Deactivation of the guest accounts which are not used anymore
+
+use std::str::FromStr;
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_storage::{
+    job::{DeactivateUserJob, JobRepositoryExt},
+    user::{UserFilter, UserRepository},
+    Pagination, RepositoryAccess,
+};
+use tracing::{debug, info};
+
+use crate::{
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How many guests are processed at once
+const BATCH_SIZE: usize = 100;
+
+#[derive(Default, Clone)]
+pub struct CleanupStaleGuestsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupStaleGuestsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupStaleGuestsJob {
+    const NAME: &'static str = "cleanup-stale-guests";
+}
+
+impl TracedJob for CleanupStaleGuestsJob {}
+
+pub async fn cleanup_stale_guests(
+    job: CleanupStaleGuestsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("cleanup stale guests job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    // Guests keep their sessions forever, so look at when they were last used
+    // instead of whether they are still active
+    let filter = UserFilter::new()
+        .guests_only()
+        .active_only()
+        .idle_since(clock.now() - state.guest_inactivity_ttl());
+
+    let mut count = 0;
+    loop {
+        let page = repo
+            .user()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for user in page.edges {
+            // Locking the guest right away makes sure they are not picked up
+            // again before the deactivation job runs
+            let user = repo.user().lock(&clock, user).await?;
+            repo.job()
+                .schedule_job(DeactivateUserJob::new(&user, false))
+                .await?;
+            count += 1;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    repo.save().await?;
+
+    if count > 0 {
+        info!(count, "Deactivated stale guest accounts");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    let schedule = apalis_cron::Schedule::from_str("0 45 3 * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CleanupStaleGuestsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(cleanup_stale_guests);
+
+    monitor.register(worker)
+}
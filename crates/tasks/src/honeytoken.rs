@@ -0,0 +1,126 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
+use mas_data_model::AuditEventKind;
+use mas_email::Mailbox;
+use mas_i18n::locale;
+use mas_storage::{
+    audit::AuditEventRepository,
+    honeytoken::HoneytokenRepository,
+    job::{DispatchWebhookJob, JobRepositoryExt, JobWithSpanContext, ReportHoneytokenUseJob},
+    RepositoryAccess,
+};
+use mas_templates::{EmailHoneytokenUsedContext, TemplateContext};
+use tracing::{info, warn};
+
+use crate::{email::deliver, storage::PostgresStorageFactory, JobContextExt, State};
+
+/// Job to record the use of a honeytoken, and to alert the administrators
+/// about it.
+#[tracing::instrument(
+    name = "job.report_honeytoken_use",
+    fields(
+        honeytoken.id = %job.honeytoken_id(),
+        honeytoken.endpoint = job.endpoint(),
+    ),
+    skip_all,
+    err(Debug),
+)]
+async fn report_honeytoken_use(
+    job: JobWithSpanContext<ReportHoneytokenUseJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let mailer = state.mailer();
+    let mut repo = state.repository().await?;
+
+    let Some(honeytoken) = repo.honeytoken().lookup(job.honeytoken_id()).await? else {
+        info!("Honeytoken was removed, not reporting its use");
+        return Ok(());
+    };
+
+    let honeytoken = repo
+        .honeytoken()
+        .record_use(honeytoken, job.used_at())
+        .await?;
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::HoneytokenUsed,
+            None,
+            None,
+            job.ip(),
+            job.user_agent().map(ToOwned::to_owned),
+            serde_json::json!({
+                "honeytoken_id": honeytoken.id,
+                "kind": honeytoken.kind,
+                "label": honeytoken.label,
+                "endpoint": job.endpoint(),
+            }),
+        )
+        .await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::honeytoken_used(
+            &mut rng,
+            &clock,
+            &honeytoken,
+            job.endpoint(),
+            job.ip(),
+        ))
+        .await?;
+
+    // Commit before sending the alerts, so that retrying the job because of a
+    // failed delivery doesn't record the use twice
+    repo.save().await?;
+
+    // XXX: we don't know the language of the administrators, so we default to
+    // English
+    let context = EmailHoneytokenUsedContext::new(
+        honeytoken,
+        job.used_at(),
+        job.endpoint().to_owned(),
+        job.ip(),
+        job.user_agent().map(ToOwned::to_owned),
+    )
+    .with_language(locale!("en").into());
+
+    for address in state.honeytoken_alert_emails() {
+        let mailbox = Mailbox::new(None, address.clone());
+        match deliver(
+            &state,
+            address,
+            mailer.render_honeytoken_used_email(mailbox, &context),
+        )
+        .await
+        {
+            Ok(true) => info!(email = %address, "Honeytoken alert email queued"),
+            Ok(false) => {}
+            Err(e) => warn!(
+                error = &*e as &dyn std::error::Error,
+                email = %address,
+                "Failed to queue honeytoken alert email"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+    storage_factory: &PostgresStorageFactory,
+) -> Monitor<TokioExecutor> {
+    let report_honeytoken_use_worker = crate::build!(ReportHoneytokenUseJob => report_honeytoken_use, suffix, state, storage_factory);
+
+    monitor.register(report_honeytoken_use_worker)
+}
@@ -0,0 +1,271 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Deactivation of the accounts which were not used for a long time
+
+use std::str::FromStr;
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::User;
+use mas_storage::{
+    job::{DeactivateUserJob, JobRepositoryExt, SendInactivityWarningJob},
+    user::{UserFilter, UserRepository},
+    Clock, Pagination, RepositoryAccess,
+};
+use tracing::{debug, info};
+
+use crate::{
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How many users are processed at once
+const BATCH_SIZE: usize = 100;
+
+/// Settings for the deactivation of inactive accounts
+#[derive(Clone)]
+pub struct InactiveAccounts {
+    inactivity_period: chrono::Duration,
+    grace_period: chrono::Duration,
+    exclude_usernames: Vec<String>,
+    exclude_admins: bool,
+}
+
+impl InactiveAccounts {
+    /// Create new settings for the deactivation of inactive accounts
+    ///
+    /// # Parameters
+    ///
+    /// * `inactivity_period` - How long users must not have used their account
+    ///   before they are warned
+    /// * `grace_period` - How long to wait after the warning before
+    ///   deactivating the account
+    /// * `exclude_usernames` - The users whose account is never deactivated
+    /// * `exclude_admins` - Whether the users who can request admin privileges
+    ///   are never deactivated
+    #[must_use]
+    pub fn new(
+        inactivity_period: chrono::Duration,
+        grace_period: chrono::Duration,
+        exclude_usernames: Vec<String>,
+        exclude_admins: bool,
+    ) -> Self {
+        Self {
+            inactivity_period,
+            grace_period,
+            exclude_usernames,
+            exclude_admins,
+        }
+    }
+
+    /// The users which can be deactivated, before looking at their activity
+    fn filter(&self) -> UserFilter<'_> {
+        let filter = UserFilter::new()
+            .active_only()
+            .humans_only()
+            .non_guests_only()
+            .no_legal_hold_only()
+            .excluding_usernames(&self.exclude_usernames);
+
+        if self.exclude_admins {
+            filter.cannot_request_admin_only()
+        } else {
+            filter
+        }
+    }
+}
+
+/// What was done by [`process_inactive_accounts`]
+#[derive(Debug, Default)]
+pub struct InactiveAccountsReport {
+    /// The users who used their account again after being warned
+    pub cleared: Vec<User>,
+
+    /// The users who were warned that their account will be deactivated
+    pub warned: Vec<User>,
+
+    /// The users whose account was deactivated
+    pub deactivated: Vec<User>,
+}
+
+/// Warn the users who didn't use their account for the inactivity period, and
+/// deactivate the account of those who were warned more than the grace period
+/// ago.
+///
+/// The changes are made through the given repository, so that the caller can
+/// decide whether to save them or not.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub async fn process_inactive_accounts<R: RepositoryAccess>(
+    repo: &mut R,
+    clock: &dyn Clock,
+    settings: &InactiveAccounts,
+) -> Result<InactiveAccountsReport, R::Error> {
+    let now = clock.now();
+    let inactive_since = now - settings.inactivity_period;
+    let mut report = InactiveAccountsReport::default();
+
+    // Forget about the warning of the users who came back since
+    let filter = settings
+        .filter()
+        .inactivity_warned_only()
+        .used_since(inactive_since);
+    loop {
+        let page = repo
+            .user()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for user in page.edges {
+            repo.user().clear_inactivity_warning(&user).await?;
+            report.cleared.push(user);
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    // Deactivate the users who were warned and didn't come back in time
+    let filter = settings
+        .filter()
+        .inactivity_warned_only()
+        .inactivity_warned_before(now - settings.grace_period)
+        .inactive_since(inactive_since);
+    loop {
+        let page = repo
+            .user()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for user in page.edges {
+            // Locking the user right away makes sure they are not picked up
+            // again before the deactivation job runs
+            let user = repo.user().lock(clock, user).await?;
+            repo.user().clear_inactivity_warning(&user).await?;
+            repo.job()
+                .schedule_job(DeactivateUserJob::new(&user, false))
+                .await?;
+            report.deactivated.push(user);
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    // Warn the users who just became inactive
+    let deactivation_at = now + settings.grace_period;
+    let filter = settings
+        .filter()
+        .not_inactivity_warned_only()
+        .inactive_since(inactive_since);
+    loop {
+        let page = repo
+            .user()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for user in page.edges {
+            repo.user().mark_inactivity_warned(clock, &user).await?;
+            repo.job()
+                .schedule_job(SendInactivityWarningJob::new(&user, deactivation_at))
+                .await?;
+            report.warned.push(user);
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
+#[derive(Default, Clone)]
+pub struct ProcessInactiveAccountsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ProcessInactiveAccountsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ProcessInactiveAccountsJob {
+    const NAME: &'static str = "process-inactive-accounts";
+}
+
+impl TracedJob for ProcessInactiveAccountsJob {}
+
+pub async fn process_inactive_accounts_job(
+    job: ProcessInactiveAccountsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "process inactive accounts job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let Some(settings) = state.inactive_accounts() else {
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let report = process_inactive_accounts(&mut repo, &clock, settings).await?;
+
+    repo.save().await?;
+
+    for user in &report.deactivated {
+        info!(user.id = %user.id, "User didn't log in after the inactivity warning, deactivating their account");
+    }
+
+    info!(
+        cleared = report.cleared.len(),
+        warned = report.warned.len(),
+        deactivated = report.deactivated.len(),
+        "processed inactive accounts"
+    );
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    // Only look for inactive accounts if it is enabled
+    if state.inactive_accounts().is_none() {
+        return monitor;
+    }
+
+    let schedule = apalis_cron::Schedule::from_str("0 15 3 * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ProcessInactiveAccountsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(process_inactive_accounts_job);
+
+    monitor.register(worker)
+}
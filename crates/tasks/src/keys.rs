@@ -0,0 +1,210 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Automated rotation of the signing keys
+
+use std::str::FromStr;
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::{SigningKey, SigningKeyState};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_keystore::{Encrypter, PrivateKey};
+use mas_storage::{Clock, RepositoryAccess};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    SeedableRng,
+};
+use tracing::{debug, info};
+
+use crate::{
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// Settings for the automated rotation of signing keys
+#[derive(Clone)]
+pub struct KeyRotation {
+    algorithm: JsonWebSignatureAlg,
+    encrypter: Encrypter,
+    interval: chrono::Duration,
+    grace_period: chrono::Duration,
+    retention: chrono::Duration,
+}
+
+impl KeyRotation {
+    /// Create new key rotation settings
+    ///
+    /// # Parameters
+    ///
+    /// * `algorithm` - The algorithm the generated keys are used for
+    /// * `encrypter` - The encrypter used to store the generated keys
+    /// * `interval` - How often a new key is used to sign
+    /// * `grace_period` - How long a new key is published before it is used to
+    ///   sign
+    /// * `retention` - How long a key stays published after a newer key
+    ///   replaced it
+    #[must_use]
+    pub fn new(
+        algorithm: JsonWebSignatureAlg,
+        encrypter: Encrypter,
+        interval: chrono::Duration,
+        grace_period: chrono::Duration,
+        retention: chrono::Duration,
+    ) -> Self {
+        Self {
+            algorithm,
+            encrypter,
+            interval,
+            grace_period,
+            retention,
+        }
+    }
+
+    /// When a signing key is planned to be retired, if it was superseded by a
+    /// newer one
+    pub(crate) fn planned_retirement(
+        &self,
+        key: &SigningKey,
+        keys: &[SigningKey],
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match key.state(keys, now) {
+            SigningKeyState::Superseded { superseded_at } => Some(superseded_at + self.retention),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct RotateSigningKeysJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for RotateSigningKeysJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for RotateSigningKeysJob {
+    const NAME: &'static str = "rotate-signing-keys";
+}
+
+impl TracedJob for RotateSigningKeysJob {}
+
+pub async fn rotate_signing_keys(
+    job: RotateSigningKeysJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("rotate signing keys job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let Some(rotation) = state.key_rotation() else {
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let mut repo = state.repository().await?;
+
+    // Only one worker should rotate the keys at a time
+    repo.signing_key().lock().await?;
+    let keys = repo.signing_key().list_unretired().await?;
+    let now = clock.now();
+
+    // Retire the keys which were superseded long enough ago that the tokens they
+    // signed have expired
+    for key in &keys {
+        if let Some(retires_at) = rotation.planned_retirement(key, &keys, now) {
+            if retires_at <= now {
+                info!(kid = key.kid, "Retiring signing key");
+                repo.signing_key().retire(&clock, key.clone()).await?;
+            }
+        }
+    }
+
+    // Generate a new key so that it is published for the grace period before the
+    // latest key reaches the end of its interval
+    let latest = keys
+        .iter()
+        .rev()
+        .find(|key| key.algorithm == rotation.algorithm);
+    let needs_new_key = latest.map_or(true, |latest| {
+        latest.activates_at + rotation.interval - rotation.grace_period <= now
+    });
+
+    if needs_new_key {
+        let algorithm = rotation.algorithm.clone();
+        let key_rng = rand_chacha::ChaChaRng::from_rng(&mut rng)?;
+        let span = tracing::info_span!("generate", %algorithm);
+        let key = tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            PrivateKey::generate_for_alg(key_rng, &algorithm)
+        })
+        .await??;
+
+        let pem = key.to_pem(pem_rfc7468::LineEnding::LF)?;
+        let encrypted_key = rotation.encrypter.encrypt_to_string(pem.as_bytes())?;
+        let kid = Alphanumeric.sample_string(&mut rng, 10);
+        let activates_at = now + rotation.grace_period;
+
+        let key = repo
+            .signing_key()
+            .add(
+                &mut rng,
+                &clock,
+                kid,
+                rotation.algorithm.clone(),
+                encrypted_key,
+                activates_at,
+            )
+            .await?;
+
+        info!(
+            kid = key.kid,
+            algorithm = %key.algorithm,
+            %activates_at,
+            "Generated a new signing key"
+        );
+    } else {
+        debug!("no signing key to generate");
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    // Only schedule the rotation if it is enabled
+    if state.key_rotation().is_none() {
+        return monitor;
+    }
+
+    let schedule = apalis_cron::Schedule::from_str("0 */5 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = RotateSigningKeysJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(rotate_signing_keys);
+
+    monitor.register(worker)
+}
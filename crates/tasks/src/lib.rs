@@ -4,51 +4,145 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use apalis_core::{executor::TokioExecutor, layers::extensions::Extension, monitor::Monitor};
-use mas_email::Mailer;
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_email::{Address, Mailer};
 use mas_matrix::HomeserverConnection;
 use mas_router::UrlBuilder;
-use mas_storage::{BoxClock, BoxRepository, SystemClock};
+use mas_storage::{BoxClock, BoxRepository, BoxRng, Clock};
 use mas_storage_pg::{DatabaseError, PgRepository};
+use mas_tower::FaultInjector;
 use rand::SeedableRng;
 use sqlx::{Pool, Postgres};
 use tracing::debug;
 
 use crate::storage::PostgresStorageFactory;
+pub use crate::{
+    event_stream::EventStream,
+    expiry::ExpiryWarnings,
+    geoip::GeoIp,
+    inactive::{process_inactive_accounts, InactiveAccounts, InactiveAccountsReport},
+    keys::KeyRotation,
+    suspicious_login::SuspiciousLoginDetection,
+    webhooks::WebhookEndpoint,
+};
 
 mod database;
+mod digest;
 mod email;
+mod event_stream;
+mod expiry;
+mod geoip;
+mod guests;
+mod honeytoken;
+mod inactive;
+mod keys;
 mod matrix;
 mod recovery;
 mod storage;
+mod suspicious_login;
 mod user;
 mod utils;
+mod webhooks;
 
 #[derive(Clone)]
 struct State {
     pool: Pool<Postgres>,
     mailer: Mailer,
-    clock: SystemClock,
+    clock: Arc<dyn Clock + Send>,
+    rng: Arc<Mutex<BoxRng>>,
     homeserver: Arc<dyn HomeserverConnection<Error = anyhow::Error>>,
     url_builder: UrlBuilder,
+    database_fault_injector: FaultInjector,
+    audit_retention: Option<Duration>,
+    honeytoken_alert_emails: Arc<[Address]>,
+    session_ttl: Option<chrono::Duration>,
+    session_inactivity_ttl: Option<chrono::Duration>,
+    compat_session_ttl: Option<chrono::Duration>,
+    compat_session_inactivity_ttl: Option<chrono::Duration>,
+    browser_session_ttl: Option<chrono::Duration>,
+    browser_session_inactivity_ttl: Option<chrono::Duration>,
+    kiosk_session_ttl: chrono::Duration,
+    kiosk_session_inactivity_ttl: chrono::Duration,
+    activity_digest_interval: Option<chrono::Duration>,
+    http_client_factory: HttpClientFactory,
+    webhook_endpoints: Arc<[WebhookEndpoint]>,
+    event_stream: Option<Arc<EventStream>>,
+    key_rotation: Option<KeyRotation>,
+    changes_feed_retention: Option<Duration>,
+    expiry_warnings: Arc<ExpiryWarnings>,
+    suspicious_login_detection: Option<Arc<SuspiciousLoginDetection>>,
+    geoip: Option<Arc<GeoIp>>,
+    inactive_accounts: Option<Arc<InactiveAccounts>>,
+    guest_inactivity_ttl: chrono::Duration,
 }
 
 impl State {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pool: Pool<Postgres>,
-        clock: SystemClock,
+        clock: BoxClock,
+        rng: BoxRng,
         mailer: Mailer,
         homeserver: impl HomeserverConnection<Error = anyhow::Error> + 'static,
         url_builder: UrlBuilder,
+        database_fault_injector: FaultInjector,
+        audit_retention: Option<Duration>,
+        honeytoken_alert_emails: Vec<Address>,
+        session_ttl: Option<chrono::Duration>,
+        session_inactivity_ttl: Option<chrono::Duration>,
+        compat_session_ttl: Option<chrono::Duration>,
+        compat_session_inactivity_ttl: Option<chrono::Duration>,
+        browser_session_ttl: Option<chrono::Duration>,
+        browser_session_inactivity_ttl: Option<chrono::Duration>,
+        kiosk_session_ttl: chrono::Duration,
+        kiosk_session_inactivity_ttl: chrono::Duration,
+        activity_digest_interval: Option<chrono::Duration>,
+        http_client_factory: HttpClientFactory,
+        webhook_endpoints: Vec<WebhookEndpoint>,
+        event_stream: Option<EventStream>,
+        key_rotation: Option<KeyRotation>,
+        changes_feed_retention: Option<Duration>,
+        expiry_warnings: ExpiryWarnings,
+        suspicious_login_detection: Option<SuspiciousLoginDetection>,
+        geoip: Option<GeoIp>,
+        inactive_accounts: Option<InactiveAccounts>,
+        guest_inactivity_ttl: chrono::Duration,
     ) -> Self {
         Self {
             pool,
             mailer,
-            clock,
+            clock: Arc::from(clock),
+            rng: Arc::new(Mutex::new(rng)),
             homeserver: Arc::new(homeserver),
             url_builder,
+            database_fault_injector,
+            audit_retention,
+            honeytoken_alert_emails: honeytoken_alert_emails.into(),
+            session_ttl,
+            session_inactivity_ttl,
+            compat_session_ttl,
+            compat_session_inactivity_ttl,
+            browser_session_ttl,
+            browser_session_inactivity_ttl,
+            kiosk_session_ttl,
+            kiosk_session_inactivity_ttl,
+            activity_digest_interval,
+            http_client_factory,
+            webhook_endpoints: webhook_endpoints.into(),
+            event_stream: event_stream.map(Arc::new),
+            key_rotation,
+            changes_feed_retention,
+            expiry_warnings: Arc::new(expiry_warnings),
+            suspicious_login_detection: suspicious_login_detection.map(Arc::new),
+            geoip: geoip.map(Arc::new),
+            inactive_accounts: inactive_accounts.map(Arc::new),
+            guest_inactivity_ttl,
         }
     }
 
@@ -68,13 +162,19 @@ impl State {
         &self.mailer
     }
 
-    // This is fine for now, we may move that to a trait at some point.
-    #[allow(clippy::unused_self, clippy::disallowed_methods)]
+    /// Get a new random number generator, seeded from the one given to the
+    /// workers
     pub fn rng(&self) -> rand_chacha::ChaChaRng {
-        rand_chacha::ChaChaRng::from_rng(rand::thread_rng()).expect("failed to seed rng")
+        let mut parent_rng = self.rng.lock().expect("failed to lock rng");
+        rand_chacha::ChaChaRng::from_rng(&mut *parent_rng).expect("failed to seed rng")
     }
 
     pub async fn repository(&self) -> Result<BoxRepository, DatabaseError> {
+        self.database_fault_injector
+            .inject()
+            .await
+            .map_err(|_| sqlx::Error::PoolTimedOut)?;
+
         let repo = PgRepository::from_pool(self.pool()).await?.boxed();
 
         Ok(repo)
@@ -87,6 +187,90 @@ impl State {
     pub fn url_builder(&self) -> &UrlBuilder {
         &self.url_builder
     }
+
+    pub fn audit_retention(&self) -> Option<Duration> {
+        self.audit_retention
+    }
+
+    pub fn honeytoken_alert_emails(&self) -> &[Address] {
+        &self.honeytoken_alert_emails
+    }
+
+    pub fn session_ttl(&self) -> Option<chrono::Duration> {
+        self.session_ttl
+    }
+
+    pub fn session_inactivity_ttl(&self) -> Option<chrono::Duration> {
+        self.session_inactivity_ttl
+    }
+
+    pub fn compat_session_ttl(&self) -> Option<chrono::Duration> {
+        self.compat_session_ttl
+    }
+
+    pub fn compat_session_inactivity_ttl(&self) -> Option<chrono::Duration> {
+        self.compat_session_inactivity_ttl
+    }
+
+    pub fn browser_session_ttl(&self) -> Option<chrono::Duration> {
+        self.browser_session_ttl
+    }
+
+    pub fn browser_session_inactivity_ttl(&self) -> Option<chrono::Duration> {
+        self.browser_session_inactivity_ttl
+    }
+
+    pub fn kiosk_session_ttl(&self) -> chrono::Duration {
+        self.kiosk_session_ttl
+    }
+
+    pub fn kiosk_session_inactivity_ttl(&self) -> chrono::Duration {
+        self.kiosk_session_inactivity_ttl
+    }
+
+    pub fn activity_digest_interval(&self) -> Option<chrono::Duration> {
+        self.activity_digest_interval
+    }
+
+    pub fn http_client_factory(&self) -> &HttpClientFactory {
+        &self.http_client_factory
+    }
+
+    pub fn webhook_endpoints(&self) -> &[WebhookEndpoint] {
+        &self.webhook_endpoints
+    }
+
+    pub fn event_stream(&self) -> Option<&EventStream> {
+        self.event_stream.as_deref()
+    }
+
+    pub fn key_rotation(&self) -> Option<&KeyRotation> {
+        self.key_rotation.as_ref()
+    }
+
+    pub fn changes_feed_retention(&self) -> Option<Duration> {
+        self.changes_feed_retention
+    }
+
+    pub fn expiry_warnings(&self) -> &ExpiryWarnings {
+        &self.expiry_warnings
+    }
+
+    pub fn suspicious_login_detection(&self) -> Option<&SuspiciousLoginDetection> {
+        self.suspicious_login_detection.as_deref()
+    }
+
+    pub fn geoip(&self) -> Option<&GeoIp> {
+        self.geoip.as_deref()
+    }
+
+    pub fn inactive_accounts(&self) -> Option<&InactiveAccounts> {
+        self.inactive_accounts.as_deref()
+    }
+
+    pub fn guest_inactivity_ttl(&self) -> chrono::Duration {
+        self.guest_inactivity_ttl
+    }
 }
 
 trait JobContextExt {
@@ -114,7 +298,8 @@ macro_rules! build {
         let builder = ::apalis_core::builder::WorkerBuilder::new(worker_name)
             .layer($state.inject())
             .layer(crate::utils::trace_layer())
-            .layer(crate::utils::metrics_layer());
+            .layer(crate::utils::metrics_layer())
+            .layer(crate::utils::request_id_layer());
 
         let builder = ::apalis_core::storage::builder::WithStorage::with_storage_config(
             builder,
@@ -129,30 +314,91 @@ pub(crate) use build;
 
 /// Initialise the workers.
 ///
+/// The jobs source the current time from the given `clock`, and their random
+/// number generators are seeded from the given `rng`, so that tests can run
+/// them deterministically.
+///
 /// # Errors
 ///
 /// This function can fail if the database connection fails.
+#[allow(clippy::too_many_arguments)]
 pub async fn init(
     name: &str,
     pool: &Pool<Postgres>,
+    clock: BoxClock,
+    rng: BoxRng,
     mailer: &Mailer,
     homeserver: impl HomeserverConnection<Error = anyhow::Error> + 'static,
     url_builder: UrlBuilder,
+    database_fault_injector: FaultInjector,
+    audit_retention: Option<Duration>,
+    honeytoken_alert_emails: Vec<Address>,
+    session_ttl: Option<chrono::Duration>,
+    session_inactivity_ttl: Option<chrono::Duration>,
+    compat_session_ttl: Option<chrono::Duration>,
+    compat_session_inactivity_ttl: Option<chrono::Duration>,
+    browser_session_ttl: Option<chrono::Duration>,
+    browser_session_inactivity_ttl: Option<chrono::Duration>,
+    kiosk_session_ttl: chrono::Duration,
+    kiosk_session_inactivity_ttl: chrono::Duration,
+    activity_digest_interval: Option<chrono::Duration>,
+    http_client_factory: HttpClientFactory,
+    webhook_endpoints: Vec<WebhookEndpoint>,
+    event_stream: Option<EventStream>,
+    key_rotation: Option<KeyRotation>,
+    changes_feed_retention: Option<Duration>,
+    expiry_warnings: ExpiryWarnings,
+    suspicious_login_detection: Option<SuspiciousLoginDetection>,
+    geoip: Option<GeoIp>,
+    inactive_accounts: Option<InactiveAccounts>,
+    guest_inactivity_ttl: chrono::Duration,
 ) -> Result<Monitor<TokioExecutor>, sqlx::Error> {
     let state = State::new(
         pool.clone(),
-        SystemClock::default(),
+        clock,
+        rng,
         mailer.clone(),
         homeserver,
         url_builder,
+        database_fault_injector,
+        audit_retention,
+        honeytoken_alert_emails,
+        session_ttl,
+        session_inactivity_ttl,
+        compat_session_ttl,
+        compat_session_inactivity_ttl,
+        browser_session_ttl,
+        browser_session_inactivity_ttl,
+        kiosk_session_ttl,
+        kiosk_session_inactivity_ttl,
+        activity_digest_interval,
+        http_client_factory,
+        webhook_endpoints,
+        event_stream,
+        key_rotation,
+        changes_feed_retention,
+        expiry_warnings,
+        suspicious_login_detection,
+        geoip,
+        inactive_accounts,
+        guest_inactivity_ttl,
     );
-    let factory = PostgresStorageFactory::new(pool.clone());
+    let factory = PostgresStorageFactory::new(pool.clone(), Arc::clone(&state.clock));
     let monitor = Monitor::new().executor(TokioExecutor::new());
     let monitor = self::database::register(name, monitor, &state);
     let monitor = self::email::register(name, monitor, &state, &factory);
+    let monitor = self::honeytoken::register(name, monitor, &state, &factory);
+    let monitor = self::suspicious_login::register(name, monitor, &state, &factory);
+    let monitor = self::digest::register(name, monitor, &state);
     let monitor = self::matrix::register(name, monitor, &state, &factory);
     let monitor = self::user::register(name, monitor, &state, &factory);
     let monitor = self::recovery::register(name, monitor, &state, &factory);
+    let monitor = self::webhooks::register(name, monitor, &state, &factory);
+    let monitor = self::event_stream::register(name, monitor, &state);
+    let monitor = self::keys::register(name, monitor, &state);
+    let monitor = self::expiry::register(name, monitor, &state);
+    let monitor = self::inactive::register(name, monitor, &state);
+    let monitor = self::guests::register(name, monitor, &state);
     // TODO: we might want to grab the join handle here
     factory.listen().await?;
     debug!(?monitor, "workers registered");
@@ -4,11 +4,15 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::LazyLock};
 
 use anyhow::Context;
 use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
-use mas_data_model::Device;
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, Method, Request};
+use http_body_util::{BodyExt, Limited};
+use mas_data_model::{Device, UpstreamOAuthFunnelStage};
+use mas_http::HttpServiceExt;
 use mas_matrix::ProvisionRequest;
 use mas_storage::{
     compat::CompatSessionFilter,
@@ -20,10 +24,117 @@ use mas_storage::{
     user::{UserEmailRepository, UserRepository},
     Pagination, RepositoryAccess,
 };
-use tracing::info;
+use opentelemetry::{metrics::Counter, Key};
+use tower::{Service, ServiceExt};
+use tracing::{info, warn};
+use url::Url;
 
 use crate::{storage::PostgresStorageFactory, JobContextExt, State};
 
+const PROVIDER: Key = Key::from_static_str("provider");
+const STAGE: Key = Key::from_static_str("stage");
+const REASON: Key = Key::from_static_str("reason");
+
+struct FunnelInstruments {
+    stages: Counter<u64>,
+    dropoffs: Counter<u64>,
+}
+
+/// The counters of the upstream OAuth 2.0 signup funnel. The other stages are
+/// recorded by the handlers, under the same metric names
+static FUNNEL_INSTRUMENTS: LazyLock<FunnelInstruments> = LazyLock::new(|| {
+    let meter = opentelemetry::global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+        None,
+    );
+
+    let stages = meter
+        .u64_counter("mas.upstream_oauth2.funnel.stages")
+        .with_description(
+            "The number of times a stage of the upstream OAuth 2.0 signup funnel was reached",
+        )
+        .with_unit("{stages}")
+        .init();
+
+    let dropoffs = meter
+        .u64_counter("mas.upstream_oauth2.funnel.dropoffs")
+        .with_description(
+            "The number of times users dropped off the upstream OAuth 2.0 signup funnel",
+        )
+        .with_unit("{dropoffs}")
+        .init();
+
+    FunnelInstruments { stages, dropoffs }
+});
+
+/// The maximum size of an avatar fetched from a remote URL
+const MAX_AVATAR_SIZE: usize = 5 * 1024 * 1024;
+
+/// The content types accepted for avatars fetched from a remote URL
+const AVATAR_CONTENT_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Get the `mxc://` URI to use as avatar from the avatar URL given by an
+/// upstream provider.
+///
+/// `mxc://` URIs are used as-is, while images at HTTP URLs are downloaded
+/// and uploaded to the homeserver media repository.
+async fn rehost_avatar(state: &State, avatar_url: &str) -> Result<String, anyhow::Error> {
+    if avatar_url.starts_with("mxc://") {
+        return Ok(avatar_url.to_owned());
+    }
+
+    let url = Url::parse(avatar_url).context("Invalid avatar URL")?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        anyhow::bail!("Unsupported avatar URL scheme {:?}", url.scheme());
+    }
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(url.as_str())
+        .body(Bytes::new())?;
+
+    let mut client = state
+        .http_client_factory()
+        .restricted_client("avatar")
+        .request_bytes_to_body();
+
+    let response = client
+        .ready()
+        .await?
+        .call(request)
+        .await
+        .context("Failed to fetch avatar")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Avatar URL responded with status {status}");
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+        .filter(|value| AVATAR_CONTENT_TYPES.contains(value))
+        .context("Avatar has an unsupported content type")?
+        .to_owned();
+
+    let content = Limited::new(response.into_body(), MAX_AVATAR_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to read the avatar, or it is too large")?
+        .to_bytes();
+
+    state
+        .matrix_connection()
+        .upload_media(&content_type, content.to_vec())
+        .await
+}
+
 /// Job to provision a user on the Matrix homeserver.
 /// This works by doing a PUT request to the /_synapse/admin/v2/users/{user_id}
 /// endpoint.
@@ -47,7 +158,7 @@ async fn provision_user(
         .await?
         .context("User not found")?;
 
-    let mxid = matrix.mxid(&user.username);
+    let mxid = matrix.mxid_on(user.homeserver.as_deref(), &user.username);
     let emails = repo
         .user_email()
         .all(&user)
@@ -62,7 +173,75 @@ async fn provision_user(
         request = request.set_displayname(display_name.to_owned());
     }
 
-    let created = matrix.provision_user(&request).await?;
+    // Failing to get the avatar shouldn't prevent the user from being provisioned
+    if let Some(avatar_url) = job.avatar_url_to_set() {
+        match rehost_avatar(&state, avatar_url).await {
+            Ok(avatar_url) => request = request.set_avatar_url(avatar_url),
+            Err(e) => {
+                warn!(error = &*e as &dyn std::error::Error, %user.id, "Failed to import the avatar");
+            }
+        }
+    }
+
+    // If the user signed up through an upstream provider and was not
+    // provisioned yet, this is a stage of the signup funnel
+    let stage = UpstreamOAuthFunnelStage::Provisioned;
+    let funnel_provider = repo
+        .upstream_oauth_funnel()
+        .signup_provider_pending(&user, stage)
+        .await?;
+
+    let created = match matrix.provision_user(&request).await {
+        Ok(created) => created,
+        Err(e) => {
+            if let Some(provider_id) = funnel_provider {
+                let reason = "homeserver_error";
+                repo.upstream_oauth_funnel()
+                    .record(
+                        &mut state.rng(),
+                        &state.clock(),
+                        provider_id,
+                        Some(&user),
+                        stage,
+                        Some(reason),
+                    )
+                    .await?;
+                repo.save().await?;
+
+                FUNNEL_INSTRUMENTS.dropoffs.add(
+                    1,
+                    &[
+                        PROVIDER.string(provider_id.to_string()),
+                        STAGE.string(stage.as_str()),
+                        REASON.string(reason),
+                    ],
+                );
+            }
+
+            return Err(e);
+        }
+    };
+
+    if let Some(provider_id) = funnel_provider {
+        repo.upstream_oauth_funnel()
+            .record(
+                &mut state.rng(),
+                &state.clock(),
+                provider_id,
+                Some(&user),
+                stage,
+                None,
+            )
+            .await?;
+
+        FUNNEL_INSTRUMENTS.stages.add(
+            1,
+            &[
+                PROVIDER.string(provider_id.to_string()),
+                STAGE.string(stage.as_str()),
+            ],
+        );
+    }
 
     if created {
         info!(%user.id, %mxid, "User created");
@@ -214,7 +393,7 @@ async fn sync_devices(
         }
     }
 
-    let mxid = matrix.mxid(&user.username);
+    let mxid = matrix.mxid_on(user.homeserver.as_deref(), &user.username);
     matrix.sync_devices(&mxid, devices).await?;
 
     // We kept the connection until now, so that we still hold the lock on the user
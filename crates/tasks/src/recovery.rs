@@ -17,7 +17,7 @@ use mas_templates::{EmailRecoveryContext, TemplateContext};
 use rand::distributions::{Alphanumeric, DistString};
 use tracing::{error, info};
 
-use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+use crate::{email::deliver, storage::PostgresStorageFactory, JobContextExt, State};
 
 /// Job to send account recovery emails for a given recovery session.
 #[tracing::instrument(
@@ -94,17 +94,23 @@ async fn send_account_recovery_email_job(
             let url = url_builder.account_recovery_link(ticket.ticket);
 
             let address: Address = user_email.email.parse()?;
-            let mailbox = Mailbox::new(Some(user.username.clone()), address);
+            let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
 
             info!("Sending recovery email to {}", mailbox);
             let context =
                 EmailRecoveryContext::new(user, session.clone(), url).with_language(lang.clone());
 
-            // XXX: we only log if the email fails to send, to avoid stopping the loop
-            if let Err(e) = mailer.send_recovery_email(mailbox, &context).await {
+            // XXX: we only log if the email fails to be queued, to avoid stopping the loop
+            if let Err(e) = deliver(
+                &state,
+                &address,
+                mailer.render_recovery_email(mailbox, &context),
+            )
+            .await
+            {
                 error!(
-                    error = &e as &dyn std::error::Error,
-                    "Failed to send recovery email"
+                    error = &*e as &dyn std::error::Error,
+                    "Failed to queue recovery email"
                 );
             }
 
@@ -18,6 +18,7 @@ use async_stream::try_stream;
 use chrono::{DateTime, Utc};
 use event_listener::Event;
 use futures_lite::{Stream, StreamExt};
+use mas_storage::Clock;
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::{postgres::PgListener, PgPool, Pool, Postgres, Row};
 use tokio::task::JoinHandle;
@@ -27,13 +28,15 @@ use super::SqlJobRequest;
 pub struct StorageFactory {
     pool: PgPool,
     event: Arc<Event>,
+    clock: Arc<dyn Clock + Send>,
 }
 
 impl StorageFactory {
-    pub fn new(pool: Pool<Postgres>) -> Self {
+    pub fn new(pool: Pool<Postgres>, clock: Arc<dyn Clock + Send>) -> Self {
         StorageFactory {
             pool,
             event: Arc::new(Event::new()),
+            clock,
         }
     }
 
@@ -56,24 +59,35 @@ impl StorageFactory {
         Storage {
             pool: self.pool.clone(),
             event: self.event.clone(),
+            clock: self.clock.clone(),
             job_type: PhantomData,
         }
     }
 }
 
 /// Represents a [`apalis_core::storage::Storage`] that persists to Postgres
-#[derive(Debug)]
 pub struct Storage<T> {
     pool: PgPool,
     event: Arc<Event>,
+    clock: Arc<dyn Clock + Send>,
     job_type: PhantomData<T>,
 }
 
+impl<T> std::fmt::Debug for Storage<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Storage")
+            .field("pool", &self.pool)
+            .field("event", &self.event)
+            .finish_non_exhaustive()
+    }
+}
+
 impl<T> Clone for Storage<T> {
     fn clone(&self) -> Self {
         Storage {
             pool: self.pool.clone(),
             event: self.event.clone(),
+            clock: self.clock.clone(),
             job_type: PhantomData,
         }
     }
@@ -330,9 +344,7 @@ where
             .try_into()
             .map_err(|e| StorageError::Database(Box::new(e)))?;
         let wait = chrono::Duration::microseconds(wait * 1000 * 1000);
-        // TODO: should we use a clock here?
-        #[allow(clippy::disallowed_methods)]
-        let run_at = Utc::now().add(wait);
+        let run_at = self.clock.now().add(wait);
 
         let mut conn = pool
             .acquire()
@@ -383,8 +395,7 @@ where
     }
 
     async fn keep_alive<Service>(&mut self, worker_id: &WorkerId) -> StorageResult<()> {
-        #[allow(clippy::disallowed_methods)]
-        let now = Utc::now();
+        let now = self.clock.now();
 
         self.keep_alive_at::<Service>(worker_id, now).await
     }
@@ -0,0 +1,298 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+//! Detection of suspicious logins
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
+use mas_data_model::{
+    AuditEvent, AuditEventKind, NotificationKind, SuspiciousLoginReason, UserAgent,
+};
+use mas_email::{Address, Mailbox};
+use mas_i18n::locale;
+use mas_storage::{
+    audit::AuditEventFilter,
+    job::{CheckSuspiciousLoginJob, JobWithSpanContext},
+    Pagination, RepositoryAccess,
+};
+use mas_templates::{EmailSuspiciousLoginContext, TemplateContext};
+use tracing::{debug, info, warn};
+
+use crate::{
+    email::deliver,
+    geoip::{GeoIp, Location},
+    storage::PostgresStorageFactory,
+    JobContextExt, State,
+};
+
+/// How many previous logins a login is compared with
+const HISTORY_SIZE: usize = 50;
+
+/// Below this distance, in kilometres, travelling between two logins is never
+/// considered impossible, as `GeoIP` databases are not precise enough
+const MIN_TRAVEL_DISTANCE_KM: f64 = 300.0;
+
+/// Settings for the detection of suspicious logins
+pub struct SuspiciousLoginDetection {
+    impossible_travel_speed: u32,
+    failed_attempts: u32,
+    new_device: bool,
+}
+
+impl SuspiciousLoginDetection {
+    /// Create new suspicious login detection settings
+    ///
+    /// # Parameters
+    ///
+    /// * `impossible_travel_speed` - The speed above which travelling between
+    ///   two logins is considered impossible, in km/h, or 0 to disable the
+    ///   check
+    /// * `failed_attempts` - How many failed attempts since the previous login
+    ///   make a login suspicious, or 0 to disable the check
+    /// * `new_device` - Whether logins from new devices are suspicious
+    #[must_use]
+    pub fn new(impossible_travel_speed: u32, failed_attempts: u32, new_device: bool) -> Self {
+        Self {
+            impossible_travel_speed,
+            failed_attempts,
+            new_device,
+        }
+    }
+
+    /// Compare a login with the previous logins of the same user, ordered from
+    /// the oldest to the most recent one
+    ///
+    /// Logins from new countries and impossible travels are only detected if a
+    /// `GeoIP` database is available
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn evaluate(
+        &self,
+        geoip: Option<&GeoIp>,
+        login: &AuditEvent,
+        location: Option<&Location>,
+        previous: &[AuditEvent],
+        failed_attempts: usize,
+    ) -> Vec<SuspiciousLoginReason> {
+        let mut reasons = Vec::new();
+
+        if let (Some(geoip), Some(location)) = (geoip, location) {
+            let previous_locations: Vec<_> = previous
+                .iter()
+                .filter_map(|event| Some((event, geoip.locate(event.ip_address?)?)))
+                .collect();
+
+            // Only flag a new country if we know at least one country the user
+            // logged in from before
+            let known_countries: HashSet<_> = previous_locations
+                .iter()
+                .filter_map(|(_, location)| location.country.as_deref())
+                .collect();
+            if let Some(country) = location.country.as_deref() {
+                if !known_countries.is_empty() && !known_countries.contains(country) {
+                    reasons.push(SuspiciousLoginReason::NewCountry {
+                        country: country.to_owned(),
+                    });
+                }
+            }
+
+            let last_located = previous_locations
+                .iter()
+                .rev()
+                .find_map(|(event, previous)| Some((*event, location.distance_km(previous)?)));
+            if let Some((last, distance)) = last_located {
+                let hours = (login.created_at - last.created_at).num_seconds() as f64 / 3600.0;
+                let speed = distance / hours;
+                if self.impossible_travel_speed > 0
+                    && distance >= MIN_TRAVEL_DISTANCE_KM
+                    && speed > f64::from(self.impossible_travel_speed)
+                {
+                    reasons.push(SuspiciousLoginReason::ImpossibleTravel {
+                        distance_km: distance.round() as u64,
+                        // Saturates if both logins happened at the same time
+                        speed_kmh: speed.round() as u64,
+                    });
+                }
+            }
+        }
+
+        if self.new_device {
+            let fingerprint = |event: &AuditEvent| {
+                UserAgent::parse(event.user_agent.clone()?).device_fingerprint()
+            };
+
+            let known_devices: HashSet<_> = previous.iter().filter_map(fingerprint).collect();
+            if let Some(device) = fingerprint(login) {
+                if !known_devices.is_empty() && !known_devices.contains(&device) {
+                    reasons.push(SuspiciousLoginReason::NewDevice);
+                }
+            }
+        }
+
+        if self.failed_attempts > 0 && failed_attempts >= self.failed_attempts as usize {
+            reasons.push(SuspiciousLoginReason::FailedAttempts {
+                count: failed_attempts,
+            });
+        }
+
+        reasons
+    }
+}
+
+/// Job to check whether a login looks suspicious, and to notify the user if it
+/// does.
+#[tracing::instrument(
+    name = "job.check_suspicious_login",
+    fields(audit_event.id = %job.audit_event_id()),
+    skip_all,
+    err(Debug),
+)]
+#[allow(clippy::too_many_lines)]
+async fn check_suspicious_login(
+    job: JobWithSpanContext<CheckSuspiciousLoginJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let Some(settings) = state.suspicious_login_detection() else {
+        debug!("Suspicious login detection is disabled");
+        return Ok(());
+    };
+
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let mailer = state.mailer();
+    let url_builder = state.url_builder();
+    let mut repo = state.repository().await?;
+
+    let login = repo
+        .audit_event()
+        .lookup(job.audit_event_id())
+        .await?
+        .context("Audit event not found")?;
+
+    let user_id = login.user_id.context("Login has no user")?;
+    let user = repo
+        .user()
+        .lookup(user_id)
+        .await?
+        .context("User not found")?;
+
+    let previous = repo
+        .audit_event()
+        .list(
+            AuditEventFilter::new()
+                .for_user(&user)
+                .with_kind(AuditEventKind::Login),
+            Pagination::last(HISTORY_SIZE).before(login.id),
+        )
+        .await?
+        .edges;
+
+    let failed_attempts = if settings.failed_attempts > 0 {
+        let mut filter = AuditEventFilter::new()
+            .for_user(&user)
+            .with_kind(AuditEventKind::LoginFailed)
+            .until(login.created_at);
+        if let Some(last) = previous.last() {
+            filter = filter.since(last.created_at);
+        }
+        repo.audit_event().count(filter).await?
+    } else {
+        0
+    };
+
+    let geoip = state.geoip();
+    let location = geoip
+        .zip(login.ip_address)
+        .and_then(|(geoip, ip)| geoip.locate(ip));
+    let reasons = settings.evaluate(geoip, &login, location.as_ref(), &previous, failed_attempts);
+    if reasons.is_empty() {
+        debug!("Login doesn't look suspicious");
+        return Ok(());
+    }
+
+    info!(%user.id, ?reasons, "Suspicious login detected");
+
+    let country = location.and_then(|location| location.country);
+
+    repo.audit_event()
+        .add(
+            &mut rng,
+            &clock,
+            AuditEventKind::SuspiciousLogin,
+            None,
+            Some(user.id),
+            login.ip_address,
+            login.user_agent.clone(),
+            serde_json::json!({
+                "login_event_id": login.id.to_string(),
+                "country": country,
+                "reasons": reasons,
+            }),
+        )
+        .await?;
+
+    let preferences = repo.user_notification_preferences().get(&user).await?;
+    if !preferences.allows(NotificationKind::SecurityAlert) {
+        info!(%user.id, "User doesn't want security alerts, not sending suspicious login notification");
+        repo.save().await?;
+        return Ok(());
+    }
+
+    let Some(user_email_id) = user.primary_user_email_id else {
+        warn!(%user.id, "User has no primary email, can't send suspicious login notification");
+        repo.save().await?;
+        return Ok(());
+    };
+
+    let user_email = repo
+        .user_email()
+        .lookup(user_email_id)
+        .await?
+        .context("User email not found")?;
+
+    let address: Address = user_email.email.parse()?;
+    let mailbox = Mailbox::new(Some(user.username.clone()), address.clone());
+
+    let account_link = url_builder.account_management_uri();
+
+    // XXX: we don't know the language of the user, so we default to English
+    let context = EmailSuspiciousLoginContext::new(user, login, country, reasons, account_link)
+        .with_language(locale!("en").into());
+
+    let sent = deliver(
+        &state,
+        &address,
+        mailer.render_suspicious_login_email(mailbox, &context),
+    )
+    .await?;
+
+    if sent {
+        info!(
+            email.id = %user_email.id,
+            "Suspicious login notification email queued"
+        );
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+    storage_factory: &PostgresStorageFactory,
+) -> Monitor<TokioExecutor> {
+    let check_suspicious_login_worker = crate::build!(CheckSuspiciousLoginJob => check_suspicious_login, suffix, state, storage_factory);
+
+    monitor.register(check_suspicious_login_worker)
+}
@@ -4,18 +4,41 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
+use std::str::FromStr;
+
 use anyhow::Context;
-use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use chrono::{DateTime, Utc};
+use mas_data_model::AuditEventKind;
 use mas_storage::{
+    audit::AuditEventRepository,
     compat::CompatSessionFilter,
-    job::{DeactivateUserJob, JobWithSpanContext, ReactivateUserJob},
+    job::{
+        DeactivateUserJob, DispatchWebhookJob, JobRepositoryExt, JobWithSpanContext,
+        ReactivateUserJob,
+    },
     oauth2::OAuth2SessionFilter,
-    user::{BrowserSessionFilter, UserRepository},
-    RepositoryAccess,
+    user::{BrowserSessionFilter, UserFilter, UserRepository},
+    Clock, Pagination, RepositoryAccess,
 };
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+use crate::{
+    storage::PostgresStorageFactory,
+    utils::{metrics_layer, request_id_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+/// How many accounts to delete at most on each run
+const MAX_DELETIONS_PER_RUN: usize = 100;
 
 /// Job to deactivate a user, both locally and on the Matrix homeserver.
 #[tracing::instrument(
@@ -30,6 +53,7 @@ async fn deactivate_user(
 ) -> Result<(), anyhow::Error> {
     let state = ctx.state();
     let clock = state.clock();
+    let mut rng = state.rng();
     let matrix = state.matrix_connection();
     let mut repo = state.repository().await?;
 
@@ -39,6 +63,27 @@ async fn deactivate_user(
         .await?
         .context("User not found")?;
 
+    // The data of users under legal hold must be preserved, so they are only
+    // deactivated, not erased
+    let erase = if job.hs_erase() && user.is_under_legal_hold() {
+        warn!("User is under legal hold, not erasing their data on the homeserver");
+        repo.audit_event()
+            .add(
+                &mut rng,
+                &clock,
+                AuditEventKind::AdminAction,
+                None,
+                Some(user.id),
+                None,
+                None,
+                serde_json::json!({ "action": "erase_skipped", "reason": "legal_hold" }),
+            )
+            .await?;
+        false
+    } else {
+        job.hs_erase()
+    };
+
     // Let's first lock the user
     let user = repo
         .user()
@@ -74,13 +119,19 @@ async fn deactivate_user(
         .await?;
     info!(affected = n, "Killed all compatibility sessions for user");
 
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_deactivated(
+            &mut rng, &clock, &user,
+        ))
+        .await?;
+
     // Before calling back to the homeserver, commit the changes to the database, as
     // we want the user to be locked out as soon as possible
     repo.save().await?;
 
-    let mxid = matrix.mxid(&user.username);
+    let mxid = matrix.mxid_on(user.homeserver.as_deref(), &user.username);
     info!("Deactivating user {} on homeserver", mxid);
-    matrix.delete_user(&mxid, job.hs_erase()).await?;
+    matrix.delete_user(&mxid, erase).await?;
 
     Ok(())
 }
@@ -97,6 +148,8 @@ pub async fn reactivate_user(
     ctx: JobContext,
 ) -> Result<(), anyhow::Error> {
     let state = ctx.state();
+    let clock = state.clock();
+    let mut rng = state.rng();
     let matrix = state.matrix_connection();
     let mut repo = state.repository().await?;
 
@@ -106,13 +159,77 @@ pub async fn reactivate_user(
         .await?
         .context("User not found")?;
 
-    let mxid = matrix.mxid(&user.username);
+    let mxid = matrix.mxid_on(user.homeserver.as_deref(), &user.username);
     info!("Reactivating user {} on homeserver", mxid);
     matrix.reactivate_user(&mxid).await?;
 
     // We want to unlock the user from our side only once it has been reactivated on
     // the homeserver
-    let _user = repo.user().unlock(user).await?;
+    let user = repo.user().unlock(user).await?;
+
+    repo.job()
+        .schedule_job(DispatchWebhookJob::user_reactivated(
+            &mut rng, &clock, &user,
+        ))
+        .await?;
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct ProcessScheduledDeletionsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for ProcessScheduledDeletionsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for ProcessScheduledDeletionsJob {
+    const NAME: &'static str = "process-scheduled-deletions";
+}
+
+impl TracedJob for ProcessScheduledDeletionsJob {}
+
+/// Delete the accounts whose owners asked for their deletion, once the grace
+/// period is over.
+///
+/// The accounts are deactivated and erased from the homeserver by the
+/// [`DeactivateUserJob`], the same way an administrator would.
+pub async fn process_scheduled_deletions(
+    job: ProcessScheduledDeletionsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "process scheduled deletions job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let filter = UserFilter::new().deletion_due_at(clock.now());
+    let page = repo
+        .user()
+        .list(filter, Pagination::first(MAX_DELETIONS_PER_RUN))
+        .await?;
+
+    for user in page.edges {
+        // Clearing the deletion makes sure the user isn't picked up again on the
+        // next run
+        let user = repo.user().clear_scheduled_deletion(user).await?;
+
+        info!(user.id = %user.id, "Grace period is over, deleting the account of the user");
+        repo.job()
+            .schedule_job(DeactivateUserJob::new(&user, true))
+            .await?;
+    }
+
     repo.save().await?;
 
     Ok(())
@@ -130,7 +247,18 @@ pub(crate) fn register(
     let reactivate_user_worker =
         crate::build!(ReactivateUserJob => reactivate_user, suffix, state, storage_factory);
 
+    let schedule = apalis_cron::Schedule::from_str("0 */10 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = ProcessScheduledDeletionsJob::NAME);
+    let process_scheduled_deletions_worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .layer(request_id_layer())
+        .build_fn(process_scheduled_deletions);
+
     monitor
         .register(deactivate_user_worker)
         .register(reactivate_user_worker)
+        .register(process_scheduled_deletions_worker)
 }
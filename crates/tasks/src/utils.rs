@@ -7,10 +7,12 @@
 use apalis_core::{job::Job, request::JobRequest};
 use mas_storage::job::JobWithSpanContext;
 use mas_tower::{
-    make_span_fn, DurationRecorderLayer, FnWrapper, IdentityLayer, InFlightCounterLayer,
+    make_span_fn, DurationRecorderLayer, FnWrapper, IdentityLayer, InFlightCounterLayer, RequestId,
     TraceLayer, KV,
 };
 use opentelemetry::{trace::SpanContext, Key, KeyValue};
+use tokio::task::futures::TaskLocalFuture;
+use tower::{Layer, Service};
 use tracing::info_span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
@@ -25,6 +27,13 @@ pub trait TracedJob: Job {
     fn span_context(&self) -> Option<SpanContext> {
         None
     }
+
+    /// Returns the ID of the request which scheduled this job, if any.
+    ///
+    /// The default implementation returns `None`.
+    fn request_id(&self) -> Option<RequestId> {
+        None
+    }
 }
 
 /// Implements [`TracedJob`] for any job with the [`JobWithSpanContext`]
@@ -33,6 +42,10 @@ impl<J: Job> TracedJob for JobWithSpanContext<J> {
     fn span_context(&self) -> Option<SpanContext> {
         JobWithSpanContext::span_context(self)
     }
+
+    fn request_id(&self) -> Option<RequestId> {
+        JobWithSpanContext::request_id(self)
+    }
 }
 
 fn make_span_for_job_request<J: TracedJob>(req: &JobRequest<J>) -> tracing::Span {
@@ -43,12 +56,17 @@ fn make_span_for_job_request<J: TracedJob>(req: &JobRequest<J>) -> tracing::Span
         "job.id" = %req.id(),
         "job.attempts" = req.attempts(),
         "job.name" = J::NAME,
+        "mas.request.id" = tracing::field::Empty,
     );
 
     if let Some(context) = req.inner().span_context() {
         span.add_link(context);
     }
 
+    if let Some(request_id) = req.inner().request_id() {
+        span.record("mas.request.id", request_id.as_str());
+    }
+
     span
 }
 
@@ -89,3 +107,51 @@ where
         in_flight_counter,
     )
 }
+
+/// A layer which sets the request ID which scheduled the job as the current
+/// one while the job runs, so that it gets propagated to outgoing emails and
+/// homeserver calls.
+///
+/// Jobs which were not scheduled from a request get a fresh request ID.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RequestIdScopeLayer;
+
+impl<S> Layer<S> for RequestIdScopeLayer {
+    type Service = RequestIdScopeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdScopeService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RequestIdScopeService<S> {
+    inner: S,
+}
+
+impl<S, J> Service<JobRequest<J>> for RequestIdScopeService<S>
+where
+    S: Service<JobRequest<J>>,
+    J: TracedJob,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TaskLocalFuture<RequestId, S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: JobRequest<J>) -> Self::Future {
+        let request_id = req.inner().request_id().unwrap_or_else(RequestId::generate);
+
+        request_id.scope(self.inner.call(req))
+    }
+}
+
+pub(crate) fn request_id_layer() -> RequestIdScopeLayer {
+    RequestIdScopeLayer
+}
@@ -0,0 +1,215 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
+use base64ct::{Base64, Encoding};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use http::{header::CONTENT_TYPE, Method, Request};
+use mas_data_model::{WebhookEvent, WebhookEventKind};
+use mas_http::HttpServiceExt;
+use mas_storage::{
+    job::{DispatchWebhookJob, JobRepositoryExt, JobWithSpanContext, SendWebhookJob},
+    Clock, RepositoryAccess,
+};
+use sha2::Sha256;
+use tower::{Service, ServiceExt};
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+
+/// An endpoint to which webhook events are delivered
+#[derive(Debug, Clone)]
+pub struct WebhookEndpoint {
+    url: Url,
+    secret: String,
+    events: Option<HashSet<WebhookEventKind>>,
+}
+
+impl WebhookEndpoint {
+    /// Create a new endpoint which receives all events
+    ///
+    /// # Parameters
+    ///
+    /// * `url` - The URL to which events are POSTed
+    /// * `secret` - The secret used to sign the requests
+    #[must_use]
+    pub fn new(url: Url, secret: String) -> Self {
+        Self {
+            url,
+            secret,
+            events: None,
+        }
+    }
+
+    /// Only send the given events to this endpoint
+    #[must_use]
+    pub fn with_events(mut self, events: impl IntoIterator<Item = WebhookEventKind>) -> Self {
+        self.events = Some(events.into_iter().collect());
+        self
+    }
+
+    fn wants(&self, kind: WebhookEventKind) -> bool {
+        self.events
+            .as_ref()
+            .map_or(true, |events| events.contains(&kind))
+    }
+}
+
+/// Compute the signature of a webhook request, following the [Standard
+/// Webhooks] scheme: the ID of the event, the timestamp and the body are
+/// joined with dots and signed with HMAC-SHA256.
+///
+/// [Standard Webhooks]: https://www.standardwebhooks.com/
+fn sign(secret: &str, event_id: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take keys of any size");
+    mac.update(format!("{event_id}.{timestamp}.").as_bytes());
+    mac.update(body);
+    let signature = mac.finalize().into_bytes();
+    format!("v1,{}", Base64::encode_string(&signature))
+}
+
+/// Job to fan out a webhook event to all the endpoints interested in it, to the
+/// event stream outbox if one is configured, and to the changes feed if it is
+/// enabled.
+#[tracing::instrument(
+    name = "job.dispatch_webhook",
+    fields(
+        webhook.event.id = %job.event().id,
+        webhook.event.kind = %job.event().kind,
+    ),
+    skip_all,
+    err(Debug),
+)]
+async fn dispatch_webhook(
+    job: JobWithSpanContext<DispatchWebhookJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let event = job.event();
+
+    let endpoints: Vec<_> = state
+        .webhook_endpoints()
+        .iter()
+        .filter(|endpoint| endpoint.wants(event.kind))
+        .collect();
+
+    if endpoints.is_empty()
+        && state.event_stream().is_none()
+        && state.changes_feed_retention().is_none()
+    {
+        debug!("No webhook endpoint is interested in this event");
+        return Ok(());
+    }
+
+    let mut repo = state.repository().await?;
+
+    if state.changes_feed_retention().is_some() {
+        let clock = state.clock();
+        let mut rng = state.rng();
+        repo.changes_feed().add(&mut rng, &clock, event).await?;
+    }
+
+    if state.event_stream().is_some() {
+        repo.event_outbox().add(event).await?;
+    }
+
+    for endpoint in endpoints {
+        repo.job()
+            .schedule_job(SendWebhookJob::new(endpoint.url.clone(), event.clone()))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+/// Job to deliver a webhook event to a single endpoint.
+#[tracing::instrument(
+    name = "job.send_webhook",
+    fields(
+        webhook.event.id = %job.event().id,
+        webhook.event.kind = %job.event().kind,
+        webhook.endpoint = %job.endpoint(),
+    ),
+    skip_all,
+    err(Debug),
+)]
+async fn send_webhook(
+    job: JobWithSpanContext<SendWebhookJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let clock = state.clock();
+    let event: &WebhookEvent = job.event();
+
+    // Look up the endpoint again, as the configuration might have changed since
+    // the job was scheduled, and we don't want to store the secret in the job
+    let Some(endpoint) = state
+        .webhook_endpoints()
+        .iter()
+        .find(|endpoint| &endpoint.url == job.endpoint())
+    else {
+        info!("Webhook endpoint is not configured anymore, dropping the event");
+        return Ok(());
+    };
+
+    let body = serde_json::to_vec(event)?;
+    let event_id = event.id.to_string();
+    let timestamp = clock.now().timestamp();
+    let signature = sign(&endpoint.secret, &event_id, timestamp, &body);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint.url.as_str())
+        .header(CONTENT_TYPE, "application/json")
+        .header("webhook-id", &event_id)
+        .header("webhook-timestamp", timestamp.to_string())
+        .header("webhook-signature", signature)
+        .body(Bytes::from(body))?;
+
+    let mut client = state
+        .http_client_factory()
+        .restricted_client("webhook")
+        .request_bytes_to_body();
+
+    let response = client
+        .ready()
+        .await?
+        .call(request)
+        .await
+        .context("Failed to send webhook")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        // Failing the job makes the job queue retry it later
+        anyhow::bail!("Webhook endpoint responded with status {status}");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+    storage_factory: &PostgresStorageFactory,
+) -> Monitor<TokioExecutor> {
+    let dispatch_webhook_worker =
+        crate::build!(DispatchWebhookJob => dispatch_webhook, suffix, state, storage_factory);
+
+    let send_webhook_worker =
+        crate::build!(SendWebhookJob => send_webhook, suffix, state, storage_factory);
+
+    monitor
+        .register(dispatch_webhook_worker)
+        .register(send_webhook_worker)
+}
@@ -19,8 +19,10 @@ use std::{
 use chrono::{DateTime, Duration, Utc};
 use http::{Method, Uri, Version};
 use mas_data_model::{
-    AuthorizationGrant, BrowserSession, Client, CompatSsoLogin, CompatSsoLoginState,
-    DeviceCodeGrant, UpstreamOAuthLink, UpstreamOAuthProvider, User, UserAgent, UserEmail,
+    AuditEvent, AuditEventKind, AuthorizationGrant, BrowserSession, CibaGrant, Client,
+    CompatSsoLogin, CompatSsoLoginState, DeviceCodeGrant, ExpiringCredential,
+    ExpiringCredentialKind, Honeytoken, HoneytokenKind, Session, SessionState,
+    SuspiciousLoginReason, UpstreamOAuthLink, UpstreamOAuthProvider, User, UserAgent, UserEmail,
     UserEmailVerification, UserRecoverySession,
 };
 use mas_i18n::DataLocale;
@@ -298,10 +300,18 @@ pub struct AppConfig {
     graphql_endpoint: String,
 }
 
+/// An ongoing impersonation, shown as a banner by the `app.html` template
+#[derive(Serialize)]
+struct AppImpersonation {
+    current_session: BrowserSession,
+    csrf_token: String,
+}
+
 /// Context used by the `app.html` template
 #[derive(Serialize)]
 pub struct AppContext {
     app_config: AppConfig,
+    impersonating: Option<AppImpersonation>,
 }
 
 impl AppContext {
@@ -315,6 +325,24 @@ impl AppContext {
                 root,
                 graphql_endpoint,
             },
+            impersonating: None,
+        }
+    }
+
+    /// Show the impersonation banner if the given session is impersonating a
+    /// user, with the CSRF token used by the form ending the impersonation
+    #[must_use]
+    pub fn with_impersonation(self, current_session: BrowserSession, csrf_token: String) -> Self {
+        if current_session.impersonation.is_none() {
+            return self;
+        }
+
+        Self {
+            impersonating: Some(AppImpersonation {
+                current_session,
+                csrf_token,
+            }),
+            ..self
         }
     }
 }
@@ -367,12 +395,15 @@ pub enum LoginFormField {
 
     /// The password field
     Password,
+
+    /// The checkbox to remember the browser
+    RememberBrowser,
 }
 
 impl FormField for LoginFormField {
     fn keep(&self) -> bool {
         match self {
-            Self::Username => true,
+            Self::Username | Self::RememberBrowser => true,
             Self::Password => false,
         }
     }
@@ -394,6 +425,12 @@ pub enum PostAuthContextInner {
         grant: Box<DeviceCodeGrant>,
     },
 
+    /// Continue a CIBA grant
+    ContinueCibaGrant {
+        /// The CIBA grant that will be continued after authentication
+        grant: Box<CibaGrant>,
+    },
+
     /// Continue legacy login
     /// TODO: add the login context in there
     ContinueCompatSsoLogin {
@@ -415,6 +452,12 @@ pub enum PostAuthContextInner {
 
     /// Go to the account management page
     ManageAccount,
+
+    /// Log in to a client, through its `initiate_login_uri`
+    InitiateClientLogin {
+        /// The client to log in to
+        client: Box<Client>,
+    },
 }
 
 /// Context used in login and reauth screens, for the post-auth action to do
@@ -434,6 +477,7 @@ pub struct LoginContext {
     form: FormState<LoginFormField>,
     next: Option<PostAuthContext>,
     providers: Vec<UpstreamOAuthProvider>,
+    password_login_exception: bool,
 }
 
 impl TemplateContext for LoginContext {
@@ -447,11 +491,13 @@ impl TemplateContext for LoginContext {
                 form: FormState::default(),
                 next: None,
                 providers: Vec::new(),
+                password_login_exception: false,
             },
             LoginContext {
                 form: FormState::default(),
                 next: None,
                 providers: Vec::new(),
+                password_login_exception: false,
             },
             LoginContext {
                 form: FormState::default()
@@ -464,12 +510,14 @@ impl TemplateContext for LoginContext {
                     ),
                 next: None,
                 providers: Vec::new(),
+                password_login_exception: false,
             },
             LoginContext {
                 form: FormState::default()
                     .with_error_on_field(LoginFormField::Username, FieldError::Exists),
                 next: None,
                 providers: Vec::new(),
+                password_login_exception: false,
             },
         ]
     }
@@ -496,6 +544,16 @@ impl LoginContext {
             ..self
         }
     }
+
+    /// Show the password form even though password login is disabled, for
+    /// the users which are still allowed to use it
+    #[must_use]
+    pub fn with_password_login_exception(self, password_login_exception: bool) -> Self {
+        Self {
+            password_login_exception,
+            ..self
+        }
+    }
 }
 
 /// Fields of the registration form
@@ -614,6 +672,8 @@ enum PolicyViolationGrant {
     Authorization(AuthorizationGrant),
     #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
     DeviceCode(DeviceCodeGrant),
+    #[serde(rename = "urn:openid:params:grant-type:ciba")]
+    Ciba(CibaGrant),
 }
 
 /// Context used by the `policy_violation.html` template
@@ -629,9 +689,11 @@ impl TemplateContext for PolicyViolationContext {
     where
         Self: Sized,
     {
+        let users = User::samples(now, rng);
         Client::samples(now, rng)
             .into_iter()
-            .flat_map(|client| {
+            .zip(users.into_iter().cycle())
+            .flat_map(|(client, user)| {
                 let mut grant = AuthorizationGrant::sample(now, rng);
                 // XXX
                 grant.client_id = client.id;
@@ -651,10 +713,14 @@ impl TemplateContext for PolicyViolationContext {
                         ip_address: None,
                         user_agent: None,
                     },
+                    client.clone(),
+                );
+                let ciba_grant = PolicyViolationContext::for_ciba_grant(
+                    CibaGrant::sample(now, rng, &client, &user),
                     client,
                 );
 
-                [authorization_grant, device_code_grant]
+                [authorization_grant, device_code_grant, ciba_grant]
             })
             .collect()
     }
@@ -684,6 +750,17 @@ impl PolicyViolationContext {
             action,
         }
     }
+
+    /// Constructs a context for the policy violation page for a CIBA grant
+    #[must_use]
+    pub const fn for_ciba_grant(grant: CibaGrant, client: Client) -> Self {
+        let action = PostAuthAction::continue_ciba_grant(grant.id);
+        Self {
+            grant: PolicyViolationGrant::Ciba(grant),
+            client,
+            action,
+        }
+    }
 }
 
 /// Fields of the reauthentication form
@@ -739,6 +816,80 @@ impl ReauthContext {
     }
 }
 
+/// Context used by the `pages/terms.html` template
+#[derive(Serialize)]
+pub struct TermsContext {
+    terms_url: Url,
+    previously_accepted: bool,
+    next: Option<PostAuthContext>,
+}
+
+impl TemplateContext for TermsContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let terms_url = Url::parse("https://example.com/terms?v=2").unwrap();
+        vec![
+            Self::new(terms_url.clone(), false),
+            Self::new(terms_url, true),
+        ]
+    }
+}
+
+impl TermsContext {
+    /// Constructs a context for the page asking the user to accept the terms
+    /// of service at the given URL
+    ///
+    /// `previously_accepted` tells whether the user accepted a previous
+    /// version of the terms.
+    #[must_use]
+    pub fn new(terms_url: Url, previously_accepted: bool) -> Self {
+        Self {
+            terms_url,
+            previously_accepted,
+            next: None,
+        }
+    }
+
+    /// Add a post authentication action to the context
+    #[must_use]
+    pub fn with_post_action(self, next: PostAuthContext) -> Self {
+        Self {
+            next: Some(next),
+            ..self
+        }
+    }
+}
+
+/// Context used by the `pages/impersonate.html` template
+#[derive(Serialize)]
+pub struct ImpersonationContext {
+    user: User,
+    expires_at: DateTime<Utc>,
+}
+
+impl TemplateContext for ImpersonationContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .map(|user| Self::new(user, now + Duration::try_minutes(30).unwrap()))
+            .collect()
+    }
+}
+
+impl ImpersonationContext {
+    /// Constructs a context for the page confirming the impersonation of the
+    /// given user, until the given time
+    #[must_use]
+    pub fn new(user: User, expires_at: DateTime<Utc>) -> Self {
+        Self { user, expires_at }
+    }
+}
+
 /// Context used by the `sso.html` template
 #[derive(Serialize)]
 pub struct CompatSsoContext {
@@ -827,6 +978,593 @@ impl TemplateContext for EmailRecoveryContext {
     }
 }
 
+/// Context used by the `emails/ciba.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailCibaContext {
+    user: User,
+    grant: CibaGrant,
+    client: Client,
+    approval_link: Url,
+}
+
+impl EmailCibaContext {
+    /// Constructs a context for the CIBA approval email
+    #[must_use]
+    pub fn new(user: User, grant: CibaGrant, client: Client, approval_link: Url) -> Self {
+        Self {
+            user,
+            grant,
+            client,
+            approval_link,
+        }
+    }
+
+    /// Returns the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Returns the CIBA grant which needs to be approved
+    #[must_use]
+    pub fn grant(&self) -> &CibaGrant {
+        &self.grant
+    }
+}
+
+impl TemplateContext for EmailCibaContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let clients = Client::samples(now, rng);
+        User::samples(now, rng)
+            .into_iter()
+            .zip(clients)
+            .map(|(user, client)| {
+                let grant = CibaGrant::sample(now, rng, &client, &user);
+                let link = format!("https://example.com/ciba/{}", grant.id)
+                    .parse()
+                    .unwrap();
+
+                Self::new(user, grant, client, link)
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/refresh_token_reused.{txt,html,subject}`
+/// templates
+#[derive(Serialize)]
+pub struct EmailRefreshTokenReuseContext {
+    user: User,
+    session: Session,
+    client: Client,
+    account_link: Url,
+}
+
+impl EmailRefreshTokenReuseContext {
+    /// Constructs a context for the refresh token reuse notification email
+    #[must_use]
+    pub fn new(user: User, session: Session, client: Client, account_link: Url) -> Self {
+        Self {
+            user,
+            session,
+            client,
+            account_link,
+        }
+    }
+
+    /// Returns the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Returns the OAuth 2.0 session which was ended
+    #[must_use]
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl TemplateContext for EmailRefreshTokenReuseContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let clients = Client::samples(now, rng);
+        User::samples(now, rng)
+            .into_iter()
+            .zip(clients)
+            .map(|(user, client)| {
+                let session = Session {
+                    id: Ulid::from_datetime_with_source(now.into(), rng),
+                    state: SessionState::Finished { finished_at: now },
+                    created_at: now - Duration::days(2),
+                    user_id: Some(user.id),
+                    user_session_id: None,
+                    client_id: client.id,
+                    scope: [OPENID].into_iter().collect(),
+                    user_agent: None,
+                    last_active_at: Some(now - Duration::minutes(5)),
+                    last_active_ip: None,
+                    tags: Vec::new(),
+                };
+                let link = "https://example.com/account/".parse().unwrap();
+
+                Self::new(user, session, client, link)
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/honeytoken_used.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailHoneytokenUsedContext {
+    honeytoken: Honeytoken,
+    used_at: DateTime<Utc>,
+    endpoint: String,
+    ip_address: Option<IpAddr>,
+    user_agent: Option<String>,
+}
+
+impl EmailHoneytokenUsedContext {
+    /// Constructs a context for the honeytoken alert email
+    #[must_use]
+    pub fn new(
+        honeytoken: Honeytoken,
+        used_at: DateTime<Utc>,
+        endpoint: String,
+        ip_address: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Self {
+        Self {
+            honeytoken,
+            used_at,
+            endpoint,
+            ip_address,
+            user_agent,
+        }
+    }
+
+    /// Returns the honeytoken which was used
+    #[must_use]
+    pub fn honeytoken(&self) -> &Honeytoken {
+        &self.honeytoken
+    }
+}
+
+impl TemplateContext for EmailHoneytokenUsedContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let token = Honeytoken {
+            id: Ulid::from_datetime_with_source(now.into(), rng),
+            created_at: now - Duration::days(30),
+            kind: HoneytokenKind::AccessToken,
+            value: "mat_aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa_000000".to_owned(),
+            label: "CI secrets".to_owned(),
+            last_used_at: Some(now),
+        };
+        let account = Honeytoken {
+            id: Ulid::from_datetime_with_source(now.into(), rng),
+            created_at: now - Duration::days(30),
+            kind: HoneytokenKind::Account,
+            value: "backup-admin".to_owned(),
+            label: String::new(),
+            last_used_at: Some(now),
+        };
+
+        vec![
+            Self::new(
+                token,
+                now,
+                "introspection".to_owned(),
+                Some(Ipv4Addr::new(192, 0, 2, 1).into()),
+                None,
+            ),
+            Self::new(
+                account,
+                now,
+                "login".to_owned(),
+                None,
+                Some("Mozilla/5.0".to_owned()),
+            ),
+        ]
+    }
+}
+
+/// Context used by the `emails/credentials_expiring.{txt,html,subject}`
+/// templates
+#[derive(Serialize)]
+pub struct EmailCredentialsExpiringContext {
+    credentials: Vec<ExpiringCredential>,
+}
+
+impl EmailCredentialsExpiringContext {
+    /// Constructs a context for the expiring credentials warning email
+    #[must_use]
+    pub fn new(credentials: Vec<ExpiringCredential>) -> Self {
+        Self { credentials }
+    }
+
+    /// Returns the credentials which are about to expire
+    #[must_use]
+    pub fn credentials(&self) -> &[ExpiringCredential] {
+        &self.credentials
+    }
+}
+
+impl TemplateContext for EmailCredentialsExpiringContext {
+    fn sample(now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![Self::new(vec![
+            ExpiringCredential::new(
+                ExpiringCredentialKind::TlsCertificate,
+                "web",
+                now + Duration::days(12),
+            ),
+            ExpiringCredential::new(
+                ExpiringCredentialKind::UpstreamClientSecret,
+                "01H8PKNWKKRPCBW4YGH1RWV279",
+                now + Duration::days(25),
+            ),
+            ExpiringCredential::new(
+                ExpiringCredentialKind::SigningKey,
+                "xxyyzz1234",
+                now - Duration::hours(1),
+            ),
+        ])]
+    }
+}
+
+/// Context used by the `emails/suspicious_login.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailSuspiciousLoginContext {
+    user: User,
+    login: AuditEvent,
+    country: Option<String>,
+    reasons: Vec<SuspiciousLoginReason>,
+    account_link: Url,
+}
+
+impl EmailSuspiciousLoginContext {
+    /// Constructs a context for the suspicious login notification email
+    ///
+    /// # Parameters
+    ///
+    /// * `user` - The user who logged in
+    /// * `login` - The audit event recorded for the login
+    /// * `country` - The country the login came from, if known
+    /// * `reasons` - Why the login was considered suspicious
+    /// * `account_link` - The link to the page where the user can review their
+    ///   sessions
+    #[must_use]
+    pub fn new(
+        user: User,
+        login: AuditEvent,
+        country: Option<String>,
+        reasons: Vec<SuspiciousLoginReason>,
+        account_link: Url,
+    ) -> Self {
+        Self {
+            user,
+            login,
+            country,
+            reasons,
+            account_link,
+        }
+    }
+
+    /// Returns the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+}
+
+impl TemplateContext for EmailSuspiciousLoginContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .map(|user| {
+                let login = AuditEvent {
+                    id: Ulid::from_datetime_with_source(now.into(), rng),
+                    created_at: now,
+                    kind: AuditEventKind::Login,
+                    actor_user_id: Some(user.id),
+                    user_id: Some(user.id),
+                    ip_address: Some(Ipv4Addr::new(192, 0, 2, 1).into()),
+                    user_agent: Some("Mozilla/5.0".to_owned()),
+                    details: serde_json::json!({ "method": "password" }),
+                    previous_hash: None,
+                    hash: String::new(),
+                };
+                let link = "https://example.com/account/".parse().unwrap();
+
+                Self::new(
+                    user,
+                    login,
+                    Some("FR".to_owned()),
+                    vec![
+                        SuspiciousLoginReason::NewCountry {
+                            country: "FR".to_owned(),
+                        },
+                        SuspiciousLoginReason::ImpossibleTravel {
+                            distance_km: 5837,
+                            speed_kmh: 2918,
+                        },
+                        SuspiciousLoginReason::NewDevice,
+                        SuspiciousLoginReason::FailedAttempts { count: 12 },
+                    ],
+                    link,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/new_device.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailNewDeviceContext {
+    user: User,
+    user_agent: UserAgent,
+    signed_in_at: DateTime<Utc>,
+    client: Option<Client>,
+    ip_address: Option<IpAddr>,
+    location: Option<String>,
+    account_link: Url,
+}
+
+impl EmailNewDeviceContext {
+    /// Constructs a context for the new device notification email
+    ///
+    /// # Parameters
+    ///
+    /// * `user` - The user who signed in
+    /// * `user_agent` - The user agent of the new device
+    /// * `signed_in_at` - When the user signed in
+    /// * `account_link` - The link to the page where the user can review their
+    ///   sessions
+    #[must_use]
+    pub fn new(
+        user: User,
+        user_agent: UserAgent,
+        signed_in_at: DateTime<Utc>,
+        account_link: Url,
+    ) -> Self {
+        Self {
+            user,
+            user_agent,
+            signed_in_at,
+            client: None,
+            ip_address: None,
+            location: None,
+            account_link,
+        }
+    }
+
+    /// Set the OAuth 2.0 client the user signed in to, if any
+    #[must_use]
+    pub fn with_client(mut self, client: Option<Client>) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the IP address the user signed in from, if known
+    #[must_use]
+    pub fn with_ip_address(mut self, ip_address: Option<IpAddr>) -> Self {
+        self.ip_address = ip_address;
+        self
+    }
+
+    /// Set the approximate location the user signed in from, if known
+    #[must_use]
+    pub fn with_location(mut self, location: Option<String>) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Returns the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+}
+
+impl TemplateContext for EmailNewDeviceContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let clients = Client::samples(now, rng);
+        User::samples(now, rng)
+            .into_iter()
+            .zip(clients)
+            .map(|(user, client)| {
+                let user_agent = UserAgent::parse(
+                    "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"
+                        .to_owned(),
+                );
+                let link = "https://example.com/account/".parse().unwrap();
+
+                Self::new(user, user_agent, now, link)
+                    .with_client(Some(client))
+                    .with_ip_address(Some(Ipv4Addr::new(192, 0, 2, 1).into()))
+                    .with_location(Some("Paris, FR".to_owned()))
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/inactive_account.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailInactiveAccountContext {
+    user: User,
+    deactivation_at: DateTime<Utc>,
+    login_link: Url,
+}
+
+impl EmailInactiveAccountContext {
+    /// Constructs a context for the inactive account warning email
+    ///
+    /// # Parameters
+    ///
+    /// * `user` - The user who didn't use their account for a long time
+    /// * `deactivation_at` - When their account will be deactivated if they
+    ///   don't log in before
+    /// * `login_link` - The link to the page where the user can log in
+    #[must_use]
+    pub fn new(user: User, deactivation_at: DateTime<Utc>, login_link: Url) -> Self {
+        Self {
+            user,
+            deactivation_at,
+            login_link,
+        }
+    }
+
+    /// Returns the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+}
+
+impl TemplateContext for EmailInactiveAccountContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .map(|user| {
+                Self::new(
+                    user,
+                    now + Duration::days(30),
+                    "https://example.com/login".parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/activity_digest.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailActivityDigestContext {
+    user: User,
+    since: DateTime<Utc>,
+    new_sessions: Vec<AuditEvent>,
+    client_authorizations: Vec<AuditEvent>,
+    security_events: Vec<AuditEvent>,
+    account_link: Url,
+}
+
+impl EmailActivityDigestContext {
+    /// Constructs a context for the activity digest email, out of the audit
+    /// events concerning the user since the given time
+    #[must_use]
+    pub fn new(
+        user: User,
+        since: DateTime<Utc>,
+        events: Vec<AuditEvent>,
+        account_link: Url,
+    ) -> Self {
+        let mut new_sessions = Vec::new();
+        let mut client_authorizations = Vec::new();
+        let mut security_events = Vec::new();
+        for event in events {
+            match event.kind {
+                AuditEventKind::Login => new_sessions.push(event),
+                AuditEventKind::ClientAuthorized => client_authorizations.push(event),
+                AuditEventKind::LoginFailed
+                | AuditEventKind::PasswordChanged
+                | AuditEventKind::SessionEnded
+                | AuditEventKind::AdminAction
+                | AuditEventKind::EmergencyAccessIssued
+                | AuditEventKind::RefreshTokenReused
+                | AuditEventKind::SuspiciousLogin => security_events.push(event),
+                // Honeytokens are not tied to real accounts
+                AuditEventKind::HoneytokenUsed => {}
+            }
+        }
+
+        Self {
+            user,
+            since,
+            new_sessions,
+            client_authorizations,
+            security_events,
+            account_link,
+        }
+    }
+
+    /// Returns the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Returns `true` if there was no activity to report
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.new_sessions.is_empty()
+            && self.client_authorizations.is_empty()
+            && self.security_events.is_empty()
+    }
+}
+
+impl TemplateContext for EmailActivityDigestContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .map(|user| {
+                let event = |kind, details, rng: &mut _| AuditEvent {
+                    id: Ulid::from_datetime_with_source(now.into(), rng),
+                    created_at: now - Duration::hours(5),
+                    kind,
+                    actor_user_id: Some(user.id),
+                    user_id: Some(user.id),
+                    ip_address: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                    user_agent: None,
+                    details,
+                    previous_hash: None,
+                    hash: String::new(),
+                };
+
+                let events = vec![
+                    event(
+                        AuditEventKind::Login,
+                        serde_json::json!({ "method": "password" }),
+                        rng,
+                    ),
+                    event(
+                        AuditEventKind::ClientAuthorized,
+                        serde_json::json!({
+                            "client_id": "client",
+                            "client_name": "Client",
+                        }),
+                        rng,
+                    ),
+                    event(AuditEventKind::PasswordChanged, serde_json::json!({}), rng),
+                ];
+                let link = "https://example.com/account/".parse().unwrap();
+
+                Self::new(user.clone(), now - Duration::days(7), events, link)
+            })
+            .collect()
+    }
+}
+
 /// Context used by the `emails/verification.{txt,html,subject}` templates
 #[derive(Serialize)]
 pub struct EmailVerificationContext {
@@ -1452,6 +2190,38 @@ impl TemplateContext for DeviceConsentContext {
     }
 }
 
+/// Context used by the `ciba_consent.html` template
+#[derive(Serialize, Debug)]
+pub struct CibaConsentContext {
+    grant: CibaGrant,
+    client: Client,
+}
+
+impl CibaConsentContext {
+    /// Constructs a new context for the CIBA consent page
+    #[must_use]
+    pub fn new(grant: CibaGrant, client: Client) -> Self {
+        Self { grant, client }
+    }
+}
+
+impl TemplateContext for CibaConsentContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let users = User::samples(now, rng);
+        Client::samples(now, rng)
+            .into_iter()
+            .zip(users)
+            .map(|(client, user)| {
+                let grant = CibaGrant::sample(now, rng, &client, &user);
+                Self { grant, client }
+            })
+            .collect()
+    }
+}
+
 /// Context used by the `form_post.html` template
 #[derive(Serialize)]
 pub struct FormPostContext<T> {
@@ -1491,6 +2261,7 @@ pub struct ErrorContext {
     code: Option<&'static str>,
     description: Option<String>,
     details: Option<String>,
+    request_id: Option<String>,
     lang: Option<String>,
 }
 
@@ -1507,6 +2278,10 @@ impl std::fmt::Display for ErrorContext {
             writeln!(f, "details: {details}")?;
         }
 
+        if let Some(request_id) = &self.request_id {
+            writeln!(f, "request ID: {request_id}")?;
+        }
+
         Ok(())
     }
 }
@@ -1521,7 +2296,9 @@ impl TemplateContext for ErrorContext {
                 .with_code("sample_error")
                 .with_description("A fancy description".into())
                 .with_details("Something happened".into()),
-            Self::new().with_code("another_error"),
+            Self::new()
+                .with_code("another_error")
+                .with_request_id("01J9Z6K3V2W1QF0XKQ4Y5T6R7S".into()),
             Self::new(),
         ]
     }
@@ -1555,6 +2332,13 @@ impl ErrorContext {
         self
     }
 
+    /// Add the ID of the request which failed to the context
+    #[must_use]
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
     /// Add the language to the context
     #[must_use]
     pub fn with_language(mut self, lang: &DataLocale) -> Self {
@@ -1579,6 +2363,12 @@ impl ErrorContext {
     pub fn details(&self) -> Option<&str> {
         self.details.as_deref()
     }
+
+    /// Get the request ID, if any
+    #[must_use]
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
 }
 
 /// Context used by the not found (`404.html`) template
@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 // Please see LICENSE in the repository root for full details.
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use minijinja::{
     value::{Enumerator, Object},
@@ -18,6 +18,10 @@ pub struct SiteBranding {
     policy_uri: Option<Arc<str>>,
     tos_uri: Option<Arc<str>>,
     imprint: Option<Arc<str>>,
+    service_name: Option<Arc<str>>,
+    logo_uri: Option<Arc<str>>,
+    colors: BTreeMap<Arc<str>, Arc<str>>,
+    scope_descriptions: BTreeMap<Arc<str>, Arc<str>>,
 }
 
 impl SiteBranding {
@@ -29,6 +33,10 @@ impl SiteBranding {
             policy_uri: None,
             tos_uri: None,
             imprint: None,
+            service_name: None,
+            logo_uri: None,
+            colors: BTreeMap::new(),
+            scope_descriptions: BTreeMap::new(),
         }
     }
 
@@ -52,6 +60,41 @@ impl SiteBranding {
         self.imprint = Some(imprint.into());
         self
     }
+
+    /// Set the human-readable name of the service.
+    #[must_use]
+    pub fn with_service_name(mut self, service_name: impl Into<Arc<str>>) -> Self {
+        self.service_name = Some(service_name.into());
+        self
+    }
+
+    /// Set the URI of the logo.
+    #[must_use]
+    pub fn with_logo_uri(mut self, logo_uri: impl Into<Arc<str>>) -> Self {
+        self.logo_uri = Some(logo_uri.into());
+        self
+    }
+
+    /// Replace a color of the default theme, given the name of its Compound
+    /// color token.
+    #[must_use]
+    pub fn with_color(mut self, name: impl Into<Arc<str>>, value: impl Into<Arc<str>>) -> Self {
+        self.colors.insert(name.into(), value.into());
+        self
+    }
+
+    /// Set how a deployment-specific scope is described on the consent
+    /// screen.
+    #[must_use]
+    pub fn with_scope_description(
+        mut self,
+        scope: impl Into<Arc<str>>,
+        description: impl Into<Arc<str>>,
+    ) -> Self {
+        self.scope_descriptions
+            .insert(scope.into(), description.into());
+        self
+    }
 }
 
 impl Object for SiteBranding {
@@ -61,11 +104,32 @@ impl Object for SiteBranding {
             "policy_uri" => self.policy_uri.clone().map(Value::from),
             "tos_uri" => self.tos_uri.clone().map(Value::from),
             "imprint" => self.imprint.clone().map(Value::from),
+            "service_name" => self.service_name.clone().map(Value::from),
+            "logo_uri" => self.logo_uri.clone().map(Value::from),
+            "colors" => Some(Value::from_iter(
+                self.colors
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            )),
+            "scope_descriptions" => {
+                Some(Value::from_iter(self.scope_descriptions.iter().map(
+                    |(scope, description)| (scope.clone(), description.clone()),
+                )))
+            }
             _ => None,
         }
     }
 
     fn enumerate(self: &Arc<Self>) -> Enumerator {
-        Enumerator::Str(&["server_name", "policy_uri", "tos_uri", "imprint"])
+        Enumerator::Str(&[
+            "server_name",
+            "policy_uri",
+            "tos_uri",
+            "imprint",
+            "service_name",
+            "logo_uri",
+            "colors",
+            "scope_descriptions",
+        ])
     }
 }
@@ -39,6 +39,25 @@ impl SiteConfigExt for SiteConfig {
             branding = branding.with_imprint(imprint.as_str());
         }
 
+        if let Some(service_name) = &self.service_name {
+            branding = branding.with_service_name(service_name.as_str());
+        }
+
+        if let Some(logo_uri) = &self.logo_uri {
+            branding = branding.with_logo_uri(logo_uri.as_str());
+        }
+
+        for (name, value) in &self.theme_colors {
+            branding = branding.with_color(name.as_str(), value.as_str());
+        }
+
+        for custom_scope in &self.custom_scopes {
+            if let Some(description) = &custom_scope.description {
+                branding = branding
+                    .with_scope_description(custom_scope.scope.as_str(), description.as_str());
+            }
+        }
+
         branding
     }
 
@@ -47,6 +66,8 @@ impl SiteConfigExt for SiteConfig {
             password_registration: self.password_registration_enabled,
             password_login: self.password_login_enabled,
             account_recovery: self.account_recovery_allowed,
+            session_binding: self.browser_session_binding_ttl.is_some(),
+            remember_browser: self.trusted_browser_ttl.is_some(),
         }
     }
 }
@@ -22,6 +22,12 @@ pub struct SiteFeatures {
 
     /// Whether email-based account recovery is enabled.
     pub account_recovery: bool,
+
+    /// Whether browser sessions are bound to a WebAuthn credential.
+    pub session_binding: bool,
+
+    /// Whether users can ask to remember the browser they log in from.
+    pub remember_browser: bool,
 }
 
 impl Object for SiteFeatures {
@@ -30,6 +36,8 @@ impl Object for SiteFeatures {
             "password_registration" => Some(Value::from(self.password_registration)),
             "password_login" => Some(Value::from(self.password_login)),
             "account_recovery" => Some(Value::from(self.account_recovery)),
+            "session_binding" => Some(Value::from(self.session_binding)),
+            "remember_browser" => Some(Value::from(self.remember_browser)),
             _ => None,
         }
     }
@@ -39,6 +47,8 @@ impl Object for SiteFeatures {
             "password_registration",
             "password_login",
             "account_recovery",
+            "session_binding",
+            "remember_browser",
         ])
     }
 }
@@ -9,7 +9,11 @@
 
 //! Templates rendering
 
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
 
 use anyhow::Context as _;
 use arc_swap::ArcSwap;
@@ -22,7 +26,7 @@ use rand::Rng;
 use serde::Serialize;
 use thiserror::Error;
 use tokio::task::JoinError;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use walkdir::DirEntry;
 
 mod context;
@@ -34,16 +38,20 @@ mod macros;
 
 pub use self::{
     context::{
-        ApiDocContext, AppContext, CompatSsoContext, ConsentContext, DeviceConsentContext,
-        DeviceLinkContext, DeviceLinkFormField, EmailAddContext, EmailRecoveryContext,
+        ApiDocContext, AppContext, CibaConsentContext, CompatSsoContext, ConsentContext,
+        DeviceConsentContext, DeviceLinkContext, DeviceLinkFormField, EmailActivityDigestContext,
+        EmailAddContext, EmailCibaContext, EmailCredentialsExpiringContext,
+        EmailHoneytokenUsedContext, EmailInactiveAccountContext, EmailNewDeviceContext,
+        EmailRecoveryContext, EmailRefreshTokenReuseContext, EmailSuspiciousLoginContext,
         EmailVerificationContext, EmailVerificationPageContext, EmptyContext, ErrorContext,
-        FormPostContext, IndexContext, LoginContext, LoginFormField, NotFoundContext,
-        PolicyViolationContext, PostAuthContext, PostAuthContextInner, ReauthContext,
-        ReauthFormField, RecoveryExpiredContext, RecoveryFinishContext, RecoveryFinishFormField,
-        RecoveryProgressContext, RecoveryStartContext, RecoveryStartFormField, RegisterContext,
-        RegisterFormField, SiteBranding, SiteConfigExt, SiteFeatures, TemplateContext,
-        UpstreamExistingLinkContext, UpstreamRegister, UpstreamRegisterFormField,
-        UpstreamSuggestLink, WithCaptcha, WithCsrf, WithLanguage, WithOptionalSession, WithSession,
+        FormPostContext, ImpersonationContext, IndexContext, LoginContext, LoginFormField,
+        NotFoundContext, PolicyViolationContext, PostAuthContext, PostAuthContextInner,
+        ReauthContext, ReauthFormField, RecoveryExpiredContext, RecoveryFinishContext,
+        RecoveryFinishFormField, RecoveryProgressContext, RecoveryStartContext,
+        RecoveryStartFormField, RegisterContext, RegisterFormField, SiteBranding, SiteConfigExt,
+        SiteFeatures, TemplateContext, TermsContext, UpstreamExistingLinkContext, UpstreamRegister,
+        UpstreamRegisterFormField, UpstreamSuggestLink, WithCaptcha, WithCsrf, WithLanguage,
+        WithOptionalSession, WithSession,
     },
     forms::{FieldError, FormError, FormField, FormState, ToFormState},
 };
@@ -63,11 +71,24 @@ pub struct Templates {
     environment: Arc<ArcSwap<minijinja::Environment<'static>>>,
     translator: Arc<ArcSwap<Translator>>,
     url_builder: UrlBuilder,
-    branding: SiteBranding,
-    features: SiteFeatures,
+    branding: Arc<ArcSwap<SiteBranding>>,
+    features: Arc<ArcSwap<SiteFeatures>>,
     vite_manifest_path: Utf8PathBuf,
     translations_path: Utf8PathBuf,
+    translations_overrides_path: Option<Utf8PathBuf>,
+    translations_fallbacks: BTreeMap<String, Vec<String>>,
     path: Utf8PathBuf,
+    theme_path: Option<Utf8PathBuf>,
+}
+
+/// Templates loaded from disk ahead of replacing the current ones
+///
+/// See [`Templates::prepare_reload`].
+pub struct PreparedTemplates {
+    translator: Arc<Translator>,
+    environment: Arc<minijinja::Environment<'static>>,
+    branding: SiteBranding,
+    features: SiteFeatures,
 }
 
 /// There was an issue while loading the templates
@@ -130,6 +151,38 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .is_some_and(|s| s.starts_with('.'))
 }
 
+/// Register the templates found in the given directory, replacing the
+/// templates already registered with the same name
+fn add_templates_from_dir(
+    env: &mut minijinja::Environment<'static>,
+    loaded: &mut HashSet<String>,
+    root: &Utf8Path,
+) -> Result<(), TemplateLoadingError> {
+    for entry in walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| !is_hidden(e))
+    {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let path = Utf8PathBuf::try_from(entry.into_path())?;
+            let Some(ext) = path.extension() else {
+                continue;
+            };
+
+            if ext == "html" || ext == "txt" || ext == "subject" {
+                let relative = path.strip_prefix(root)?;
+                debug!(%relative, "Registering template");
+                let template = std::fs::read_to_string(&path)?;
+                env.add_template_owned(relative.as_str().to_owned(), template)?;
+                loaded.insert(relative.as_str().to_owned());
+            }
+        }
+    }
+
+    Ok(())
+}
+
 impl Templates {
     /// Load the templates from the given config
     #[tracing::instrument(
@@ -138,19 +191,26 @@ impl Templates {
         fields(%path),
         err,
     )]
+    #[allow(clippy::too_many_arguments)]
     pub async fn load(
         path: Utf8PathBuf,
+        theme_path: Option<Utf8PathBuf>,
         url_builder: UrlBuilder,
         vite_manifest_path: Utf8PathBuf,
         translations_path: Utf8PathBuf,
+        translations_overrides_path: Option<Utf8PathBuf>,
+        translations_fallbacks: BTreeMap<String, Vec<String>>,
         branding: SiteBranding,
         features: SiteFeatures,
     ) -> Result<Self, TemplateLoadingError> {
         let (translator, environment) = Self::load_(
             &path,
+            theme_path.as_deref(),
             url_builder.clone(),
             &vite_manifest_path,
             &translations_path,
+            translations_overrides_path.as_deref(),
+            &translations_fallbacks,
             branding.clone(),
             features,
         )
@@ -159,23 +219,31 @@ impl Templates {
             environment: Arc::new(ArcSwap::new(environment)),
             translator: Arc::new(ArcSwap::new(translator)),
             path,
+            theme_path,
             url_builder,
             vite_manifest_path,
             translations_path,
-            branding,
-            features,
+            translations_overrides_path,
+            translations_fallbacks,
+            branding: Arc::new(ArcSwap::from_pointee(branding)),
+            features: Arc::new(ArcSwap::from_pointee(features)),
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn load_(
         path: &Utf8Path,
+        theme_path: Option<&Utf8Path>,
         url_builder: UrlBuilder,
         vite_manifest_path: &Utf8Path,
         translations_path: &Utf8Path,
+        translations_overrides_path: Option<&Utf8Path>,
+        translations_fallbacks: &BTreeMap<String, Vec<String>>,
         branding: SiteBranding,
         features: SiteFeatures,
     ) -> Result<(Arc<Translator>, Arc<minijinja::Environment<'static>>), TemplateLoadingError> {
         let path = path.to_owned();
+        let theme_path = theme_path.map(ToOwned::to_owned);
         let span = tracing::Span::current();
 
         // Read the assets manifest from disk
@@ -188,9 +256,29 @@ impl Templates {
             serde_json::from_slice(&vite_manifest).map_err(TemplateLoadingError::ViteManifest)?;
 
         let translations_path = translations_path.to_owned();
-        let translator =
-            tokio::task::spawn_blocking(move || Translator::load_from_path(&translations_path))
-                .await??;
+        let translations_overrides_path = translations_overrides_path.map(ToOwned::to_owned);
+        let translations_fallbacks = translations_fallbacks.clone();
+        let translator = tokio::task::spawn_blocking(move || {
+            let mut translator = Translator::load_from_path(&translations_path)?;
+
+            if let Some(overrides_path) = translations_overrides_path {
+                info!(%overrides_path, "Loading translation overrides");
+                for locale in translator.load_overrides_from_path(&overrides_path)? {
+                    let missing = translator.missing_keys(&locale);
+                    if !missing.is_empty() {
+                        warn!(
+                            %locale,
+                            count = missing.len(),
+                            keys = ?missing,
+                            "Translations are missing some keys, they will fall back to other locales"
+                        );
+                    }
+                }
+            }
+
+            translator.with_fallbacks(&translations_fallbacks)
+        })
+        .await??;
         let translator = Arc::new(translator);
 
         debug!(locales = ?translator.available_locales(), "Loaded translations");
@@ -201,25 +289,15 @@ impl Templates {
                 let mut env = minijinja::Environment::new();
                 let root = path.canonicalize_utf8()?;
                 info!(%root, "Loading templates from filesystem");
-                for entry in walkdir::WalkDir::new(&root)
-                    .min_depth(1)
-                    .into_iter()
-                    .filter_entry(|e| !is_hidden(e))
-                {
-                    let entry = entry?;
-                    if entry.file_type().is_file() {
-                        let path = Utf8PathBuf::try_from(entry.into_path())?;
-                        let Some(ext) = path.extension() else {
-                            continue;
-                        };
-
-                        if ext == "html" || ext == "txt" || ext == "subject" {
-                            let relative = path.strip_prefix(&root)?;
-                            debug!(%relative, "Registering template");
-                            let template = std::fs::read_to_string(&path)?;
-                            env.add_template_owned(relative.as_str().to_owned(), template)?;
-                            loaded.insert(relative.as_str().to_owned());
-                        }
+                add_templates_from_dir(&mut env, &mut loaded, &root)?;
+
+                // The templates of the theme replace the built-in ones
+                if let Some(theme_path) = theme_path {
+                    let theme_templates = theme_path.join("templates");
+                    if theme_templates.is_dir() {
+                        let root = theme_templates.canonicalize_utf8()?;
+                        info!(%root, "Loading theme templates from filesystem");
+                        add_templates_from_dir(&mut env, &mut loaded, &root)?;
                     }
                 }
 
@@ -259,21 +337,119 @@ impl Templates {
         err,
     )]
     pub async fn reload(&self) -> Result<(), TemplateLoadingError> {
+        let branding = SiteBranding::clone(&self.branding.load());
+        let features = **self.features.load();
+        self.reload_with(branding, features).await
+    }
+
+    /// Reload the templates on disk, replacing the site branding and features
+    /// exposed to them
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the templates could not be reloaded from disk.
+    pub async fn reload_with(
+        &self,
+        branding: SiteBranding,
+        features: SiteFeatures,
+    ) -> Result<(), TemplateLoadingError> {
+        let prepared = self.prepare_reload(branding, features).await?;
+        self.apply_reload(prepared);
+        Ok(())
+    }
+
+    /// Load the templates on disk with the given site branding and features,
+    /// without replacing the current ones yet
+    ///
+    /// This lets callers check that other parts of a reload succeed before
+    /// applying it with [`Self::apply_reload`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the templates could not be loaded from disk.
+    pub async fn prepare_reload(
+        &self,
+        branding: SiteBranding,
+        features: SiteFeatures,
+    ) -> Result<PreparedTemplates, TemplateLoadingError> {
         let (translator, environment) = Self::load_(
             &self.path,
+            self.theme_path.as_deref(),
             self.url_builder.clone(),
             &self.vite_manifest_path,
             &self.translations_path,
-            self.branding.clone(),
-            self.features,
+            self.translations_overrides_path.as_deref(),
+            &self.translations_fallbacks,
+            branding.clone(),
+            features,
         )
         .await?;
 
-        // Swap them
-        self.environment.store(environment);
-        self.translator.store(translator);
+        Ok(PreparedTemplates {
+            translator,
+            environment,
+            branding,
+            features,
+        })
+    }
 
-        Ok(())
+    /// Replace the current templates with ones loaded by
+    /// [`Self::prepare_reload`]
+    pub fn apply_reload(&self, prepared: PreparedTemplates) {
+        self.environment.store(prepared.environment);
+        self.translator.store(prepared.translator);
+        self.branding.store(Arc::new(prepared.branding));
+        self.features.store(Arc::new(prepared.features));
+    }
+
+    /// Compute a fingerprint of the files the templates are loaded from,
+    /// including the translations, the assets manifest and the theme.
+    ///
+    /// It changes whenever one of those files is added, removed or modified.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files could not be listed.
+    pub async fn fingerprint(&self) -> Result<u64, TemplateLoadingError> {
+        let roots: Vec<Utf8PathBuf> = [
+            Some(self.path.clone()),
+            self.theme_path.clone(),
+            Some(self.translations_path.clone()),
+            self.translations_overrides_path.clone(),
+            Some(self.vite_manifest_path.clone()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        tokio::task::spawn_blocking(move || {
+            let mut hasher = DefaultHasher::new();
+            for root in roots {
+                // A missing file changes the fingerprint, but is not an error
+                let exists = root.exists();
+                exists.hash(&mut hasher);
+                if !exists {
+                    continue;
+                }
+
+                for entry in walkdir::WalkDir::new(root)
+                    .sort_by_file_name()
+                    .into_iter()
+                    .filter_entry(|e| !is_hidden(e))
+                {
+                    let entry = entry?;
+                    if entry.file_type().is_file() {
+                        let metadata = entry.metadata()?;
+                        entry.path().hash(&mut hasher);
+                        metadata.len().hash(&mut hasher);
+                        metadata.modified()?.hash(&mut hasher);
+                    }
+                }
+            }
+
+            Ok::<_, TemplateLoadingError>(hasher.finish())
+        })
+        .await?
     }
 
     /// Get the translator
@@ -367,6 +543,12 @@ register_templates! {
     /// Render the re-authentication form
     pub fn render_reauth(WithLanguage<WithCsrf<WithSession<ReauthContext>>>) { "pages/reauth.html" }
 
+    /// Render the page asking the user to accept the terms of service
+    pub fn render_terms(WithLanguage<WithCsrf<WithSession<TermsContext>>>) { "pages/terms.html" }
+
+    /// Render the page confirming the impersonation of a user
+    pub fn render_impersonate(WithLanguage<WithCsrf<WithSession<ImpersonationContext>>>) { "pages/impersonate.html" }
+
     /// Render the form used by the form_post response mode
     pub fn render_form_post<T: Serialize>(FormPostContext<T>) { "form_post.html" }
 
@@ -382,6 +564,78 @@ register_templates! {
     /// Render the email recovery subject
     pub fn render_email_recovery_subject(WithLanguage<EmailRecoveryContext>) { "emails/recovery.subject" }
 
+    /// Render the CIBA approval email (plain text variant)
+    pub fn render_email_ciba_txt(WithLanguage<EmailCibaContext>) { "emails/ciba.txt" }
+
+    /// Render the CIBA approval email (HTML text variant)
+    pub fn render_email_ciba_html(WithLanguage<EmailCibaContext>) { "emails/ciba.html" }
+
+    /// Render the CIBA approval email subject
+    pub fn render_email_ciba_subject(WithLanguage<EmailCibaContext>) { "emails/ciba.subject" }
+
+    /// Render the refresh token reuse notification email (plain text variant)
+    pub fn render_email_refresh_token_reused_txt(WithLanguage<EmailRefreshTokenReuseContext>) { "emails/refresh_token_reused.txt" }
+
+    /// Render the refresh token reuse notification email (HTML text variant)
+    pub fn render_email_refresh_token_reused_html(WithLanguage<EmailRefreshTokenReuseContext>) { "emails/refresh_token_reused.html" }
+
+    /// Render the refresh token reuse notification email subject
+    pub fn render_email_refresh_token_reused_subject(WithLanguage<EmailRefreshTokenReuseContext>) { "emails/refresh_token_reused.subject" }
+
+    /// Render the honeytoken alert email (plain text variant)
+    pub fn render_email_honeytoken_used_txt(WithLanguage<EmailHoneytokenUsedContext>) { "emails/honeytoken_used.txt" }
+
+    /// Render the honeytoken alert email (HTML text variant)
+    pub fn render_email_honeytoken_used_html(WithLanguage<EmailHoneytokenUsedContext>) { "emails/honeytoken_used.html" }
+
+    /// Render the honeytoken alert email subject
+    pub fn render_email_honeytoken_used_subject(WithLanguage<EmailHoneytokenUsedContext>) { "emails/honeytoken_used.subject" }
+
+    /// Render the suspicious login notification email (plain text variant)
+    pub fn render_email_suspicious_login_txt(WithLanguage<EmailSuspiciousLoginContext>) { "emails/suspicious_login.txt" }
+
+    /// Render the suspicious login notification email (HTML text variant)
+    pub fn render_email_suspicious_login_html(WithLanguage<EmailSuspiciousLoginContext>) { "emails/suspicious_login.html" }
+
+    /// Render the suspicious login notification email subject
+    pub fn render_email_suspicious_login_subject(WithLanguage<EmailSuspiciousLoginContext>) { "emails/suspicious_login.subject" }
+
+    /// Render the new device notification email (plain text variant)
+    pub fn render_email_new_device_txt(WithLanguage<EmailNewDeviceContext>) { "emails/new_device.txt" }
+
+    /// Render the new device notification email (HTML text variant)
+    pub fn render_email_new_device_html(WithLanguage<EmailNewDeviceContext>) { "emails/new_device.html" }
+
+    /// Render the new device notification email subject
+    pub fn render_email_new_device_subject(WithLanguage<EmailNewDeviceContext>) { "emails/new_device.subject" }
+
+    /// Render the expiring credentials warning email (plain text variant)
+    pub fn render_email_credentials_expiring_txt(WithLanguage<EmailCredentialsExpiringContext>) { "emails/credentials_expiring.txt" }
+
+    /// Render the expiring credentials warning email (HTML text variant)
+    pub fn render_email_credentials_expiring_html(WithLanguage<EmailCredentialsExpiringContext>) { "emails/credentials_expiring.html" }
+
+    /// Render the expiring credentials warning email subject
+    pub fn render_email_credentials_expiring_subject(WithLanguage<EmailCredentialsExpiringContext>) { "emails/credentials_expiring.subject" }
+
+    /// Render the activity digest email (plain text variant)
+    pub fn render_email_activity_digest_txt(WithLanguage<EmailActivityDigestContext>) { "emails/activity_digest.txt" }
+
+    /// Render the activity digest email (HTML text variant)
+    pub fn render_email_activity_digest_html(WithLanguage<EmailActivityDigestContext>) { "emails/activity_digest.html" }
+
+    /// Render the activity digest email subject
+    pub fn render_email_activity_digest_subject(WithLanguage<EmailActivityDigestContext>) { "emails/activity_digest.subject" }
+
+    /// Render the inactive account warning email (plain text variant)
+    pub fn render_email_inactive_account_txt(WithLanguage<EmailInactiveAccountContext>) { "emails/inactive_account.txt" }
+
+    /// Render the inactive account warning email (HTML text variant)
+    pub fn render_email_inactive_account_html(WithLanguage<EmailInactiveAccountContext>) { "emails/inactive_account.html" }
+
+    /// Render the inactive account warning email subject
+    pub fn render_email_inactive_account_subject(WithLanguage<EmailInactiveAccountContext>) { "emails/inactive_account.subject" }
+
     /// Render the email verification email (plain text variant)
     pub fn render_email_verification_txt(WithLanguage<EmailVerificationContext>) { "emails/verification.txt" }
 
@@ -405,9 +659,98 @@ register_templates! {
 
     /// Render the device code consent page
     pub fn render_device_consent(WithLanguage<WithCsrf<WithSession<DeviceConsentContext>>>) { "pages/device_consent.html" }
+
+    /// Render the CIBA consent page
+    pub fn render_ciba_consent(WithLanguage<WithCsrf<WithSession<CibaConsentContext>>>) { "pages/ciba_consent.html" }
+}
+
+/// The samples of a template, rendered with the generated sample contexts
+#[derive(Debug, Clone)]
+pub struct RenderedSamples {
+    /// The name of the template
+    pub template: &'static str,
+
+    /// The rendered output for each sample context
+    pub samples: Vec<String>,
 }
 
 impl Templates {
+    /// Render all templates with the generated samples
+    ///
+    /// This is used to preview the templates without going through the actual
+    /// flows.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the templates fails to render
+    pub fn render_samples(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<Vec<RenderedSamples>> {
+        Ok(vec![
+            check::render_not_found(self, now, rng)?,
+            check::render_app(self, now, rng)?,
+            check::render_swagger(self, now, rng)?,
+            check::render_swagger_callback(self, now, rng)?,
+            check::render_login(self, now, rng)?,
+            check::render_register(self, now, rng)?,
+            check::render_consent(self, now, rng)?,
+            check::render_policy_violation(self, now, rng)?,
+            check::render_sso_login(self, now, rng)?,
+            check::render_index(self, now, rng)?,
+            check::render_account_verify_email(self, now, rng)?,
+            check::render_account_add_email(self, now, rng)?,
+            check::render_recovery_start(self, now, rng)?,
+            check::render_recovery_progress(self, now, rng)?,
+            check::render_recovery_finish(self, now, rng)?,
+            check::render_recovery_expired(self, now, rng)?,
+            check::render_recovery_consumed(self, now, rng)?,
+            check::render_recovery_disabled(self, now, rng)?,
+            check::render_reauth(self, now, rng)?,
+            check::render_terms(self, now, rng)?,
+            check::render_impersonate(self, now, rng)?,
+            check::render_form_post::<EmptyContext>(self, now, rng)?,
+            check::render_error(self, now, rng)?,
+            check::render_email_recovery_txt(self, now, rng)?,
+            check::render_email_recovery_html(self, now, rng)?,
+            check::render_email_recovery_subject(self, now, rng)?,
+            check::render_email_ciba_txt(self, now, rng)?,
+            check::render_email_ciba_html(self, now, rng)?,
+            check::render_email_ciba_subject(self, now, rng)?,
+            check::render_email_refresh_token_reused_txt(self, now, rng)?,
+            check::render_email_refresh_token_reused_html(self, now, rng)?,
+            check::render_email_refresh_token_reused_subject(self, now, rng)?,
+            check::render_email_honeytoken_used_txt(self, now, rng)?,
+            check::render_email_honeytoken_used_html(self, now, rng)?,
+            check::render_email_honeytoken_used_subject(self, now, rng)?,
+            check::render_email_suspicious_login_txt(self, now, rng)?,
+            check::render_email_suspicious_login_html(self, now, rng)?,
+            check::render_email_suspicious_login_subject(self, now, rng)?,
+            check::render_email_new_device_txt(self, now, rng)?,
+            check::render_email_new_device_html(self, now, rng)?,
+            check::render_email_new_device_subject(self, now, rng)?,
+            check::render_email_credentials_expiring_txt(self, now, rng)?,
+            check::render_email_credentials_expiring_html(self, now, rng)?,
+            check::render_email_credentials_expiring_subject(self, now, rng)?,
+            check::render_email_activity_digest_txt(self, now, rng)?,
+            check::render_email_activity_digest_html(self, now, rng)?,
+            check::render_email_activity_digest_subject(self, now, rng)?,
+            check::render_email_inactive_account_txt(self, now, rng)?,
+            check::render_email_inactive_account_html(self, now, rng)?,
+            check::render_email_inactive_account_subject(self, now, rng)?,
+            check::render_email_verification_txt(self, now, rng)?,
+            check::render_email_verification_html(self, now, rng)?,
+            check::render_email_verification_subject(self, now, rng)?,
+            check::render_upstream_oauth2_link_mismatch(self, now, rng)?,
+            check::render_upstream_oauth2_suggest_link(self, now, rng)?,
+            check::render_upstream_oauth2_do_register(self, now, rng)?,
+            check::render_device_link(self, now, rng)?,
+            check::render_device_consent(self, now, rng)?,
+            check::render_ciba_consent(self, now, rng)?,
+        ])
+    }
+
     /// Render all templates with the generated samples to check if they render
     /// properly
     ///
@@ -419,33 +762,7 @@ impl Templates {
         now: chrono::DateTime<chrono::Utc>,
         rng: &mut impl Rng,
     ) -> anyhow::Result<()> {
-        check::render_not_found(self, now, rng)?;
-        check::render_app(self, now, rng)?;
-        check::render_swagger(self, now, rng)?;
-        check::render_swagger_callback(self, now, rng)?;
-        check::render_login(self, now, rng)?;
-        check::render_register(self, now, rng)?;
-        check::render_consent(self, now, rng)?;
-        check::render_policy_violation(self, now, rng)?;
-        check::render_sso_login(self, now, rng)?;
-        check::render_index(self, now, rng)?;
-        check::render_account_add_email(self, now, rng)?;
-        check::render_account_verify_email(self, now, rng)?;
-        check::render_recovery_start(self, now, rng)?;
-        check::render_recovery_progress(self, now, rng)?;
-        check::render_recovery_finish(self, now, rng)?;
-        check::render_recovery_expired(self, now, rng)?;
-        check::render_recovery_consumed(self, now, rng)?;
-        check::render_recovery_disabled(self, now, rng)?;
-        check::render_reauth(self, now, rng)?;
-        check::render_form_post::<EmptyContext>(self, now, rng)?;
-        check::render_error(self, now, rng)?;
-        check::render_email_verification_txt(self, now, rng)?;
-        check::render_email_verification_html(self, now, rng)?;
-        check::render_email_verification_subject(self, now, rng)?;
-        check::render_upstream_oauth2_link_mismatch(self, now, rng)?;
-        check::render_upstream_oauth2_suggest_link(self, now, rng)?;
-        check::render_upstream_oauth2_do_register(self, now, rng)?;
+        self.render_samples(now, rng)?;
         Ok(())
     }
 }
@@ -463,11 +780,16 @@ mod tests {
 
         let path = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../templates/");
         let url_builder = UrlBuilder::new("https://example.com/".parse().unwrap(), None, None);
-        let branding = SiteBranding::new("example.com");
+        let branding = SiteBranding::new("example.com")
+            .with_service_name("Example")
+            .with_logo_uri("https://example.com/logo.png")
+            .with_color("bg-action-primary-rest", "#0dbd8b");
         let features = SiteFeatures {
             password_login: true,
             password_registration: true,
             account_recovery: true,
+            session_binding: true,
+            remember_browser: true,
         };
         let vite_manifest_path =
             Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../frontend/dist/manifest.json");
@@ -475,9 +797,12 @@ mod tests {
             Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../translations");
         let templates = Templates::load(
             path,
+            None,
             url_builder,
             vite_manifest_path,
             translations_path,
+            None,
+            BTreeMap::new(),
             branding,
             features,
         )
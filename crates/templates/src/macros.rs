@@ -78,18 +78,20 @@ macro_rules! register_templates {
                 pub fn $name
                     $(< $( $lt $( : $clt $(+ $dlt )* + TemplateContext )? ),+ >)?
                     (templates: &Templates, now: chrono::DateTime<chrono::Utc>, rng: &mut impl rand::Rng)
-                -> anyhow::Result<()> {
+                -> anyhow::Result<RenderedSamples> {
                     let samples: Vec< $param > = TemplateContext::sample(now, rng);
 
                     let name = $template;
+                    let mut rendered = Vec::with_capacity(samples.len());
                     for sample in samples {
                         let context = serde_json::to_value(&sample)?;
                         ::tracing::info!(name, %context, "Rendering template");
-                        templates. $name (&sample)
+                        let output = templates. $name (&sample)
                             .with_context(|| format!("Failed to render template {:?} with context {}", name, context))?;
+                        rendered.push(output);
                     }
 
-                    Ok(())
+                    Ok(RenderedSamples { template: name, samples: rendered })
                 }
             )*
         }
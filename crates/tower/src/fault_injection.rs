@@ -0,0 +1,181 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{sync::Arc, time::Duration};
+
+use opentelemetry::{metrics::Counter, KeyValue};
+use rand::Rng;
+
+/// The error returned when a fault was injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedFault {
+    target: &'static str,
+}
+
+impl InjectedFault {
+    /// The kind of call this fault was injected in.
+    #[must_use]
+    pub fn target(&self) -> &'static str {
+        self.target
+    }
+}
+
+impl std::fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fault injected in {} call", self.target)
+    }
+}
+
+impl std::error::Error for InjectedFault {}
+
+#[derive(Debug)]
+struct Inner {
+    target: &'static str,
+    latency_rate: f64,
+    max_latency: Duration,
+    error_rate: f64,
+    counter: Counter<u64>,
+}
+
+/// Randomly adds latency to and fails outbound calls, to validate monitoring
+/// and retry logic in staging deployments.
+///
+/// The default [`FaultInjector`] is disabled and never injects anything.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector {
+    inner: Option<Arc<Inner>>,
+}
+
+impl FaultInjector {
+    /// Create a [`FaultInjector`] which never injects anything.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Create a new [`FaultInjector`].
+    ///
+    /// # Parameters
+    ///
+    /// * `target`: The kind of call faults are injected in, used in logs and
+    ///   metrics
+    /// * `latency_rate`: The probability of adding latency to a call
+    /// * `max_latency`: The maximum latency to add to a call
+    /// * `error_rate`: The probability of making a call fail
+    #[must_use]
+    pub fn new(
+        target: &'static str,
+        latency_rate: f64,
+        max_latency: Duration,
+        error_rate: f64,
+    ) -> Self {
+        if latency_rate <= 0.0 && error_rate <= 0.0 {
+            return Self::disabled();
+        }
+
+        let counter = crate::meter()
+            .u64_counter("mas.chaos.injected_faults")
+            .with_unit("{fault}")
+            .with_description("The number of faults injected in outbound calls")
+            .init();
+
+        Self {
+            inner: Some(Arc::new(Inner {
+                target,
+                latency_rate: latency_rate.clamp(0.0, 1.0),
+                max_latency,
+                error_rate: error_rate.clamp(0.0, 1.0),
+                counter,
+            })),
+        }
+    }
+
+    /// Whether this [`FaultInjector`] may inject faults.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Maybe inject a fault before a call.
+    ///
+    /// This may sleep for a random duration, and may return an error which
+    /// the caller should treat as if the call failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a failure was injected.
+    pub async fn inject(&self) -> Result<(), InjectedFault> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+
+        // Fault injection is not security sensitive, and the RNG must not be held
+        // across the await point below
+        let (latency, fail) = {
+            #[allow(clippy::disallowed_methods)]
+            let mut rng = rand::thread_rng();
+            let latency = (rng.gen_bool(inner.latency_rate) && !inner.max_latency.is_zero())
+                .then(|| rng.gen_range(Duration::ZERO..=inner.max_latency));
+            let fail = rng.gen_bool(inner.error_rate);
+            (latency, fail)
+        };
+
+        if let Some(latency) = latency {
+            tracing::warn!(
+                target: "mas_tower::fault_injection",
+                chaos.target = inner.target,
+                ?latency,
+                "Injecting latency"
+            );
+            inner.counter.add(
+                1,
+                &[
+                    KeyValue::new("target", inner.target),
+                    KeyValue::new("kind", "latency"),
+                ],
+            );
+            tokio::time::sleep(latency).await;
+        }
+
+        if fail {
+            tracing::warn!(
+                target: "mas_tower::fault_injection",
+                chaos.target = inner.target,
+                "Injecting error"
+            );
+            inner.counter.add(
+                1,
+                &[
+                    KeyValue::new("target", inner.target),
+                    KeyValue::new("kind", "error"),
+                ],
+            );
+            return Err(InjectedFault {
+                target: inner.target,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fault_injector() {
+        assert!(FaultInjector::disabled().inject().await.is_ok());
+
+        let injector = FaultInjector::new("test", 0.0, Duration::ZERO, 0.0);
+        assert!(!injector.is_enabled());
+        assert!(injector.inject().await.is_ok());
+
+        let injector = FaultInjector::new("test", 0.0, Duration::ZERO, 1.0);
+        assert!(injector.is_enabled());
+        let error = injector.inject().await.unwrap_err();
+        assert_eq!(error.target(), "test");
+    }
+}
@@ -6,12 +6,16 @@
 
 #![allow(clippy::module_name_repetitions)]
 
+mod fault_injection;
 mod metrics;
+mod request_id;
 mod trace_context;
 mod tracing;
 mod utils;
 
-pub use self::{metrics::*, trace_context::*, tracing::*, utils::*};
+pub use self::{
+    fault_injection::*, metrics::*, request_id::*, trace_context::*, tracing::*, utils::*,
+};
 
 fn meter() -> opentelemetry::metrics::Meter {
     opentelemetry::global::meter_with_version(
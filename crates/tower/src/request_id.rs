@@ -0,0 +1,236 @@
+// Copyright 2024 New Vector Ltd.
+//
+// SPDX-License-Identifier: AGPL-3.0-only
+// Please see LICENSE in the repository root for full details.
+
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+    task::{ready, Poll},
+    time::SystemTime,
+};
+
+use http::{header::HeaderName, HeaderValue, Request, Response};
+use pin_project_lite::pin_project;
+use tokio::task::futures::TaskLocalFuture;
+use tower::{Layer, Service};
+use ulid::Ulid;
+
+/// The header used to carry the request ID, both on inbound and outbound
+/// requests.
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// The maximum length of a request ID we accept from an inbound request.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// An identifier used to correlate everything that happens because of a
+/// single inbound request: spawned jobs, calls to the homeserver, outgoing
+/// emails and the error pages shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(Arc<str>);
+
+/// The error returned when parsing an invalid request ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidRequestId;
+
+impl std::fmt::Display for InvalidRequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid request ID")
+    }
+}
+
+impl std::error::Error for InvalidRequestId {}
+
+impl RequestId {
+    /// Generate a new random request ID.
+    #[must_use]
+    pub fn generate() -> Self {
+        // Request IDs are not security sensitive, so it is fine to source them
+        // from the thread-local RNG and the system clock
+        #[allow(clippy::disallowed_methods)]
+        let mut rng = rand::thread_rng();
+        let ulid = Ulid::from_datetime_with_source(SystemTime::now(), &mut rng);
+        Self(ulid.to_string().into())
+    }
+
+    /// Get the request ID of the request currently being processed, if any.
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    /// Run the given future with this request ID set as the current one.
+    pub fn scope<F: Future>(self, future: F) -> TaskLocalFuture<RequestId, F> {
+        CURRENT_REQUEST_ID.scope(self, future)
+    }
+
+    /// Get the request ID as a string.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the request ID as an HTTP header value.
+    #[must_use]
+    pub fn to_header_value(&self) -> HeaderValue {
+        // The request ID is validated when parsed, so this can't fail
+        HeaderValue::from_str(&self.0).expect("request ID should be a valid header value")
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for RequestId {
+    type Err = InvalidRequestId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Only accept a conservative set of characters, so that the ID can be
+        // safely included in headers, logs and HTML pages
+        let valid = !s.is_empty()
+            && s.len() <= MAX_REQUEST_ID_LENGTH
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'));
+
+        if valid {
+            Ok(Self(s.into()))
+        } else {
+            Err(InvalidRequestId)
+        }
+    }
+}
+
+/// A [`Layer`] which assigns a [`RequestId`] to each inbound request.
+///
+/// If the request already has a valid `X-Request-ID` header, typically set
+/// by a reverse proxy, it is reused. Otherwise a new one is generated. The
+/// request ID is added to the request extensions, set as the current request
+/// ID while the request is being processed, and echoed back in the response
+/// headers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestIdLayer {
+    _private: (),
+}
+
+impl RequestIdLayer {
+    /// Create a new [`RequestIdLayer`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService::new(inner)
+    }
+}
+
+/// A [`Service`] which assigns a [`RequestId`] to each inbound request.
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S> RequestIdService<S> {
+    /// Create a new [`RequestIdService`].
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestIdService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = RequestIdFuture<S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(RequestId::generate);
+
+        let header = request_id.to_header_value();
+        req.headers_mut()
+            .insert(REQUEST_ID_HEADER.clone(), header.clone());
+        req.extensions_mut().insert(request_id.clone());
+
+        RequestIdFuture {
+            inner: request_id.scope(self.inner.call(req)),
+            header,
+        }
+    }
+}
+
+pin_project! {
+    /// The future returned by [`RequestIdService`].
+    pub struct RequestIdFuture<F: Future> {
+        #[pin]
+        inner: TaskLocalFuture<RequestId, F>,
+        header: HeaderValue,
+    }
+}
+
+impl<F, B, E> Future for RequestIdFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = ready!(this.inner.poll(cx))?;
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER.clone(), this.header.clone());
+        Poll::Ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_id() {
+        assert!("01J9Z6K3V2W1QF0XKQ4Y5T6R7S".parse::<RequestId>().is_ok());
+        assert!("abc-123_def.456:789".parse::<RequestId>().is_ok());
+        assert!("".parse::<RequestId>().is_err());
+        assert!("with space".parse::<RequestId>().is_err());
+        assert!("<script>".parse::<RequestId>().is_err());
+        assert!("a".repeat(129).parse::<RequestId>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_current_request_id() {
+        assert_eq!(RequestId::current(), None);
+
+        let id = RequestId::generate();
+        let current = id.clone().scope(async { RequestId::current() }).await;
+        assert_eq!(current, Some(id));
+
+        assert_eq!(RequestId::current(), None);
+    }
+}
@@ -11,6 +11,8 @@ use tower::{Layer, Service};
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+use crate::{RequestId, REQUEST_ID_HEADER};
+
 /// A trait to get an [`Injector`] from a request.
 trait AsInjector {
     type Injector<'a>: Injector
@@ -32,6 +34,9 @@ impl<B> AsInjector for Request<B> {
 }
 
 /// A [`Layer`] that adds a trace context to the request.
+///
+/// If a [`RequestId`] is set for the current task, it is also propagated
+/// through the `X-Request-ID` header.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TraceContextLayer {
     _private: (),
@@ -94,6 +99,10 @@ where
             opentelemetry::global::get_text_map_propagator(|propagator| {
                 propagator.inject_context(&context, &mut injector);
             });
+
+            if let Some(request_id) = RequestId::current() {
+                injector.set(REQUEST_ID_HEADER.as_str(), request_id.to_string());
+            }
         }
 
         self.inner.call(req)
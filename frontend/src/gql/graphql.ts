@@ -129,6 +129,11 @@ export type AppSessionConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -140,6 +145,81 @@ export type AppSessionEdge = {
   node: AppSession;
 };
 
+/** A security-relevant event recorded in the audit log. */
+export type AuditEvent = {
+  __typename?: 'AuditEvent';
+  /** The user who performed the action, if any. */
+  actor?: Maybe<User>;
+  /** When the event was recorded. */
+  createdAt: Scalars['DateTime']['output'];
+  /** Additional details about the event, as a JSON-encoded object. */
+  details: Scalars['String']['output'];
+  /** The hash of this event, which includes the previous hash. */
+  hash: Scalars['String']['output'];
+  /** ID of the object. */
+  id: Scalars['ID']['output'];
+  /** The IP address of the requester, if known. */
+  ipAddress?: Maybe<Scalars['String']['output']>;
+  /** The kind of event. */
+  kind: AuditEventKind;
+  /** The hash of the previous event in the chain, if any. */
+  previousHash?: Maybe<Scalars['String']['output']>;
+  /** The user the action was performed on, if any. */
+  user?: Maybe<User>;
+  /** The user agent of the requester, if known. */
+  userAgent?: Maybe<Scalars['String']['output']>;
+};
+
+export type AuditEventConnection = {
+  __typename?: 'AuditEventConnection';
+  /** A list of edges. */
+  edges: Array<AuditEventEdge>;
+  /** A list of nodes. */
+  nodes: Array<AuditEvent>;
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+  /** Identifies the total count of items in the connection. */
+  totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
+};
+
+/** An edge in a connection. */
+export type AuditEventEdge = {
+  __typename?: 'AuditEventEdge';
+  /** A cursor for use in pagination */
+  cursor: Scalars['String']['output'];
+  /** The item at the end of the edge */
+  node: AuditEvent;
+};
+
+/** The kind of an audit event. */
+export enum AuditEventKind {
+  /** An administrator performed an action on a user. */
+  AdminAction = 'ADMIN_ACTION',
+  /** A user authorized a client to access their account. */
+  ClientAuthorized = 'CLIENT_AUTHORIZED',
+  /** An emergency access link was issued for an administrator. */
+  EmergencyAccessIssued = 'EMERGENCY_ACCESS_ISSUED',
+  /** A honeytoken was used. */
+  HoneytokenUsed = 'HONEYTOKEN_USED',
+  /** A user successfully logged in. */
+  Login = 'LOGIN',
+  /** A user failed to log in. */
+  LoginFailed = 'LOGIN_FAILED',
+  /** The password of a user was changed. */
+  PasswordChanged = 'PASSWORD_CHANGED',
+  /** A refresh token was used more than once, and its session was ended. */
+  RefreshTokenReused = 'REFRESH_TOKEN_REUSED',
+  /** A session was ended. */
+  SessionEnded = 'SESSION_ENDED',
+  /** A login looked suspicious. */
+  SuspiciousLogin = 'SUSPICIOUS_LOGIN'
+}
+
 /**
  * An authentication records when a user enter their credential in a browser
  * session.
@@ -201,6 +281,11 @@ export type BrowserSessionConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -268,6 +353,11 @@ export type CompatSessionConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -320,6 +410,11 @@ export type CompatSsoLoginConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -331,6 +426,17 @@ export type CompatSsoLoginEdge = {
   node: CompatSsoLogin;
 };
 
+/** How to count the total number of items in a connection */
+export enum CountMode {
+  /**
+   * Only count the items exactly if there are few of them, else estimate
+   * their number.
+   */
+  Estimated = 'ESTIMATED',
+  /** Count the items exactly. */
+  Exact = 'EXACT'
+}
+
 /** The input of the `createOauth2Session` mutation. */
 export type CreateOAuth2SessionInput = {
   /** Whether the session should issue a never-expiring access token */
@@ -710,6 +816,11 @@ export type Oauth2SessionConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -737,6 +848,12 @@ export type PageInfo = {
 /** The query root of the GraphQL interface. */
 export type Query = {
   __typename?: 'Query';
+  /**
+   * Get a list of audit events.
+   *
+   * This is only available to administrators.
+   */
+  auditEvents: AuditEventConnection;
   /** Fetch a browser session by its ID. */
   browserSession?: Maybe<BrowserSession>;
   /** Fetch a compatible session by its ID. */
@@ -786,6 +903,21 @@ export type Query = {
 };
 
 
+/** The query root of the GraphQL interface. */
+export type QueryAuditEventsArgs = {
+  actor?: InputMaybe<Scalars['ID']['input']>;
+  after?: InputMaybe<Scalars['String']['input']>;
+  before?: InputMaybe<Scalars['String']['input']>;
+  countMode?: InputMaybe<CountMode>;
+  first?: InputMaybe<Scalars['Int']['input']>;
+  kind?: InputMaybe<AuditEventKind>;
+  last?: InputMaybe<Scalars['Int']['input']>;
+  since?: InputMaybe<Scalars['DateTime']['input']>;
+  until?: InputMaybe<Scalars['DateTime']['input']>;
+  user?: InputMaybe<Scalars['ID']['input']>;
+};
+
+
 /** The query root of the GraphQL interface. */
 export type QueryBrowserSessionArgs = {
   id: Scalars['ID']['input'];
@@ -852,6 +984,7 @@ export type QueryUserArgs = {
 
 /** The query root of the GraphQL interface. */
 export type QueryUserByUsernameArgs = {
+  homeserver?: InputMaybe<Scalars['String']['input']>;
   username: Scalars['String']['input'];
 };
 
@@ -867,6 +1000,7 @@ export type QueryUsersArgs = {
   after?: InputMaybe<Scalars['String']['input']>;
   before?: InputMaybe<Scalars['String']['input']>;
   canRequestAdmin?: InputMaybe<Scalars['Boolean']['input']>;
+  countMode?: InputMaybe<CountMode>;
   first?: InputMaybe<Scalars['Int']['input']>;
   last?: InputMaybe<Scalars['Int']['input']>;
   state?: InputMaybe<UserState>;
@@ -1154,6 +1288,11 @@ export type UpstreamOAuth2LinkConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -1187,6 +1326,11 @@ export type UpstreamOAuth2ProviderConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -1295,6 +1439,7 @@ export type UserOauth2SessionsArgs = {
   after?: InputMaybe<Scalars['String']['input']>;
   before?: InputMaybe<Scalars['String']['input']>;
   client?: InputMaybe<Scalars['ID']['input']>;
+  countMode?: InputMaybe<CountMode>;
   first?: InputMaybe<Scalars['Int']['input']>;
   last?: InputMaybe<Scalars['Int']['input']>;
   lastActive?: InputMaybe<DateFilter>;
@@ -1339,6 +1484,11 @@ export type UserConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
@@ -1376,6 +1526,11 @@ export type UserEmailConnection = {
   pageInfo: PageInfo;
   /** Identifies the total count of items in the connection. */
   totalCount: Scalars['Int']['output'];
+  /**
+   * Whether the total count is an estimation. This only happens when the
+   * connection was requested with the `ESTIMATED` count mode.
+   */
+  totalCountEstimated: Scalars['Boolean']['output'];
 };
 
 /** An edge in a connection. */
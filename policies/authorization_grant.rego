@@ -27,6 +27,15 @@ interactive_grant_type("authorization_code") = true
 
 interactive_grant_type("urn:ietf:params:oauth:grant-type:device_code") = true
 
+interactive_grant_type("urn:openid:params:grant-type:ciba") = true
+
+# Grant types which result in a session for a user
+user_grant_type(grant_type) {
+	interactive_grant_type(grant_type)
+}
+
+user_grant_type("urn:ietf:params:oauth:grant-type:jwt-bearer") = true
+
 # Special case to make empty scope work
 allowed_scope("") = true
 
@@ -59,15 +68,57 @@ allowed_scope("urn:mas:admin") {
 	input.client.id == client
 }
 
+# Placing users under legal hold is restricted to the users and clients
+# explicitly listed for it
+allowed_scope("urn:mas:admin:legal-hold") {
+	interactive_grant_type(input.grant_type)
+	some admin_user in data.legal_hold_admin_users
+	input.user.username == admin_user
+}
+
+allowed_scope("urn:mas:admin:legal-hold") {
+	input.grant_type == "client_credentials"
+	some client in data.legal_hold_admin_clients
+	input.client.id == client
+}
+
+# Deployment-specific scopes, from the `scopes.custom` configuration, can be
+# requested by the clients listed for them, or by any client if none are listed
+allowed_scope(scope) {
+	some custom_scope in data.custom_scopes
+	scope == custom_scope.scope
+	count(custom_scope.clients) == 0
+}
+
+allowed_scope(scope) {
+	some custom_scope in data.custom_scopes
+	scope == custom_scope.scope
+	some client in custom_scope.clients
+	input.client.id == client
+}
+
 allowed_scope(scope) {
 	# Grant access to the C-S API only if there is a user
-	interactive_grant_type(input.grant_type)
+	user_grant_type(input.grant_type)
 	regex.match("^urn:matrix:org.matrix.msc2967.client:device:[A-Za-z0-9._~!$&'()*+,;=:@/-]{10,}$", scope)
 }
 
 allowed_scope("urn:matrix:org.matrix.msc2967.client:api:*") {
 	# Grant access to the C-S API only if there is a user
-	interactive_grant_type(input.grant_type)
+	user_grant_type(input.grant_type)
+}
+
+# Assertions for the JWT bearer grant are only accepted from the issuers listed
+# in the policy data
+allowed_assertion_issuer(issuer) {
+	some allowed_issuer in data.jwt_bearer_issuers
+	issuer == allowed_issuer
+}
+
+violation[{"msg": msg}] {
+	input.grant_type == "urn:ietf:params:oauth:grant-type:jwt-bearer"
+	not allowed_assertion_issuer(input.assertion_issuer)
+	msg := sprintf("assertion issuer '%s' not allowed", [input.assertion_issuer])
 }
 
 violation[{"msg": msg}] {
@@ -80,3 +131,12 @@ violation[{"msg": "only one device scope is allowed at a time"}] {
 	scope_list := split(input.scope, " ")
 	count({key | scope_list[key]; startswith(scope_list[key], "urn:matrix:org.matrix.msc2967.client:device:")}) > 1
 }
+
+# Users can only authorize a limited number of clients at the same time, if
+# the limit is set in the policy data
+violation[{"msg": msg}] {
+	input.grant_type == "authorization_code"
+	limit := data.authorization_grant.max_authorized_clients
+	input.authorized_clients >= limit
+	msg := sprintf("too many authorized clients (limit is %d)", [limit])
+}
@@ -43,6 +43,11 @@ test_matrix_scopes {
 		with input.grant_type as "urn:ietf:params:oauth:grant-type:device_code"
 		with input.scope as "urn:matrix:org.matrix.msc2967.client:api:*"
 
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "urn:openid:params:grant-type:ciba"
+		with input.scope as "urn:matrix:org.matrix.msc2967.client:api:*"
+
 	not allow with input.user as user
 		with input.client as client
 		with input.grant_type as "client_credentials"
@@ -155,3 +160,124 @@ test_mas_scopes {
 		with input.grant_type as "authorization_code"
 		with input.scope as "urn:mas:admin"
 }
+
+test_legal_hold_scope {
+	allow with input.user as user
+		with input.client as client
+		with data.admin_users as ["john"]
+		with data.legal_hold_admin_users as ["john"]
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:admin urn:mas:admin:legal-hold"
+
+	# Being an admin is not enough
+	not allow with input.user as user
+		with input.client as client
+		with data.admin_users as ["john"]
+		with data.legal_hold_admin_users as []
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:admin urn:mas:admin:legal-hold"
+
+	allow with input.client as {"id": "client"}
+		with data.admin_clients as ["client"]
+		with data.legal_hold_admin_clients as ["client"]
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin urn:mas:admin:legal-hold"
+
+	not allow with input.client as {"id": "client"}
+		with data.admin_clients as ["client"]
+		with data.legal_hold_admin_clients as []
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin urn:mas:admin:legal-hold"
+}
+
+test_jwt_bearer_grant {
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "urn:ietf:params:oauth:grant-type:jwt-bearer"
+		with input.assertion_issuer as "https://hr.example.com"
+		with input.scope as "urn:matrix:org.matrix.msc2967.client:api:* urn:matrix:org.matrix.msc2967.client:device:AAbbCCdd01"
+		with data.jwt_bearer_issuers as ["https://hr.example.com"]
+
+	# The issuer must be allowed
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "urn:ietf:params:oauth:grant-type:jwt-bearer"
+		with input.assertion_issuer as "https://evil.example.com"
+		with input.scope as "urn:matrix:org.matrix.msc2967.client:api:*"
+		with data.jwt_bearer_issuers as ["https://hr.example.com"]
+
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "urn:ietf:params:oauth:grant-type:jwt-bearer"
+		with input.assertion_issuer as "https://hr.example.com"
+		with input.scope as "urn:matrix:org.matrix.msc2967.client:api:*"
+
+	# Admin scopes can't be requested with an assertion
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "urn:ietf:params:oauth:grant-type:jwt-bearer"
+		with input.assertion_issuer as "https://hr.example.com"
+		with input.scope as "urn:mas:admin"
+		with data.jwt_bearer_issuers as ["https://hr.example.com"]
+		with data.admin_users as ["john"]
+}
+
+test_max_authorized_clients {
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+		with input.authorized_clients as 10
+
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+		with input.authorized_clients as 1
+		with data.authorization_grant.max_authorized_clients as 2
+
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+		with input.authorized_clients as 2
+		with data.authorization_grant.max_authorized_clients as 2
+
+	# The limit doesn't apply to the other grants
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "urn:ietf:params:oauth:grant-type:device_code"
+		with input.scope as "openid"
+		with data.authorization_grant.max_authorized_clients as 0
+}
+
+test_custom_scopes {
+	# Custom scopes not listed for any client can be requested by any client
+	allow with input.client as {"id": "client"}
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:ourcorp:calendar"
+		with data.custom_scopes as [{"scope": "urn:ourcorp:calendar", "clients": []}]
+
+	allow with input.client as {"id": "bridge"}
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:ourcorp:bridge:*"
+		with data.custom_scopes as [{"scope": "urn:ourcorp:bridge:*", "clients": ["bridge"]}]
+
+	allow with input.user as user
+		with input.client as {"id": "bridge"}
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid urn:ourcorp:bridge:*"
+		with data.custom_scopes as [{"scope": "urn:ourcorp:bridge:*", "clients": ["bridge"]}]
+
+	# Other clients can't request them
+	not allow with input.client as {"id": "client"}
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:ourcorp:bridge:*"
+		with data.custom_scopes as [{"scope": "urn:ourcorp:bridge:*", "clients": ["bridge"]}]
+
+	# Scopes which aren't configured are still rejected
+	not allow with input.client as {"id": "bridge"}
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:ourcorp:bridge:admin"
+		with data.custom_scopes as [{"scope": "urn:ourcorp:bridge:*", "clients": ["bridge"]}]
+}
@@ -125,6 +125,16 @@ violation[{"msg": "client_credentials grant_type requires some form of client au
 	is_public_client
 }
 
+violation[{"msg": "jwt-bearer grant_type requires some form of client authentication"}] {
+	uses_grant_type("urn:ietf:params:oauth:grant-type:jwt-bearer")
+	is_public_client
+}
+
+violation[{"msg": "ciba grant_type requires some form of client authentication"}] {
+	uses_grant_type("urn:openid:params:grant-type:ciba")
+	is_public_client
+}
+
 violation[{"msg": "missing redirect_uris"}] {
 	requires_redirect_uris
 	not input.client_metadata.redirect_uris
@@ -203,6 +213,20 @@ valid_native_redirector(x) {
 	reverse_dns_match(client_uri.host, url.scheme)
 }
 
+# Custom schemes can also be allowed explicitly by the deployment
+valid_native_redirector(x) {
+	url := parse_uri(x)
+
+	# They should have no host/port
+	url.authority == ""
+	some scheme in data.redirect_uris.native_custom_schemes
+	url.scheme == scheme
+}
+
+is_native_client {
+	input.client_metadata.application_type == "native"
+}
+
 valid_redirect_uri(uri) {
 	input.client_metadata.application_type == "native"
 	valid_native_redirector(uri)
@@ -213,6 +237,16 @@ valid_redirect_uri(uri) {
 	host_matches_client_uri(uri)
 }
 
+# Web clients can use plain HTTP redirect URIs if the deployment allows it
+valid_redirect_uri(uri) {
+	not is_native_client
+	data.redirect_uris.web_require_https == false
+	url := parse_uri(uri)
+	url.scheme == "http"
+	not is_localhost(url.host)
+	host_matches_client_uri(uri)
+}
+
 violation[{"msg": "invalid redirect_uri", "redirect_uri": redirect_uri}] {
 	some redirect_uri in input.client_metadata.redirect_uris
 	not valid_redirect_uri(redirect_uri)
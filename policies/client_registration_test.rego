@@ -324,6 +324,81 @@ test_native_redirect_uri {
 	}
 }
 
+test_native_custom_schemes {
+	# Custom schemes can be allowed by the deployment
+	allow with input.client_metadata as {
+		"application_type": "native",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["myapp:/callback"],
+	}
+		with data.redirect_uris.native_custom_schemes as ["myapp"]
+
+	not allow with input.client_metadata as {
+		"application_type": "native",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["otherapp:/callback"],
+	}
+		with data.redirect_uris.native_custom_schemes as ["myapp"]
+
+	# They should have no host
+	not allow with input.client_metadata as {
+		"application_type": "native",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["myapp://example.com/callback"],
+	}
+		with data.redirect_uris.native_custom_schemes as ["myapp"]
+
+	# Only for native clients
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["myapp:/callback"],
+	}
+		with data.redirect_uris.native_custom_schemes as ["myapp"]
+}
+
+test_web_http_redirect_uri {
+	# Web clients can use plain HTTP if the deployment allows it
+	allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["http://app.example.com/callback"],
+	}
+		with data.redirect_uris.web_require_https as false
+
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["http://app.example.com/callback"],
+	}
+		with data.redirect_uris.web_require_https as true
+
+	# It should still be on the client_uri host
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["http://bad.com/callback"],
+	}
+		with data.redirect_uris.web_require_https as false
+
+	# But not on localhost
+	not allow with input.client_metadata as {
+		"application_type": "web",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["http://127.0.0.1:1234/callback"],
+	}
+		with data.redirect_uris.web_require_https as false
+		with data.client_registration.allow_host_mismatch as true
+
+	# This doesn't apply to native clients
+	not allow with input.client_metadata as {
+		"application_type": "native",
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["http://app.example.com/callback"],
+	}
+		with data.redirect_uris.web_require_https as false
+}
+
 test_reverse_dns_match {
 	client_uri := parse_uri("https://element.io/")
 	redirect_uri := parse_uri("io.element.app:/callback")
@@ -351,6 +426,22 @@ test_client_credentials_grant {
 	}
 }
 
+test_ciba_grant {
+	# Allowed for confidential clients
+	allow with input.client_metadata as {
+		"grant_types": ["urn:openid:params:grant-type:ciba"],
+		"token_endpoint_auth_method": "client_secret_basic",
+		"client_uri": "https://example.com/",
+	}
+
+	# Disallowed for public clients
+	not allow with input.client_metadata as {
+		"grant_types": ["urn:openid:params:grant-type:ciba"],
+		"token_endpoint_auth_method": "none",
+		"client_uri": "https://example.com/",
+	}
+}
+
 test_is_subdomain {
 	is_subdomain("example.com", "example.com")
 	is_subdomain("example.com", "app.example.com")
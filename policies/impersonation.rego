@@ -0,0 +1,34 @@
+# METADATA
+# schemas:
+#   - input: schema["impersonation_input"]
+package impersonation
+
+import future.keywords.in
+
+default allow := false
+
+allow {
+	count(violation) == 0
+}
+
+# Users can impersonate others if either:
+# 1. They are in the admin_users list
+is_admin(user) {
+	some admin_user in data.admin_users
+	user.username == admin_user
+}
+
+# 2. They have the can_request_admin flag set to true
+is_admin(user) {
+	user.can_request_admin
+}
+
+violation[{"msg": "only administrators can impersonate users"}] {
+	not is_admin(input.context.actor)
+}
+
+# Administrators can't impersonate each other, so that impersonation can't be
+# used to act with someone else's privileges
+violation[{"msg": "administrators can't be impersonated"}] {
+	is_admin(input.user)
+}
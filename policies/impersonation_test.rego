@@ -0,0 +1,36 @@
+package impersonation
+
+admin := {"username": "admin", "can_request_admin": true}
+
+alice := {"username": "alice", "can_request_admin": false}
+
+bob := {"username": "bob", "can_request_admin": false}
+
+test_admin_can_impersonate {
+	allow with input.user as alice
+		with input.context.actor as admin
+}
+
+test_admin_from_config_can_impersonate {
+	allow with input.user as alice
+		with input.context.actor as bob
+		with data.admin_users as ["bob"]
+}
+
+test_user_cant_impersonate {
+	not allow with input.user as alice
+		with input.context.actor as bob
+}
+
+test_no_actor {
+	not allow with input.user as alice
+}
+
+test_cant_impersonate_admin {
+	not allow with input.user as admin
+		with input.context.actor as {"username": "other", "can_request_admin": true}
+
+	not allow with input.user as bob
+		with input.context.actor as admin
+		with data.admin_users as ["bob"]
+}
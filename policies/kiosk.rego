@@ -0,0 +1,38 @@
+# METADATA
+# schemas:
+#   - input: schema["kiosk_input"]
+package kiosk
+
+import future.keywords.in
+
+default allow := false
+
+allow {
+	count(violation) == 0
+}
+
+# Only the clients listed in the kiosk_clients data can register kiosk devices
+# and log users in on them
+is_kiosk_client(client) {
+	some kiosk_client in data.kiosk_clients
+	client.id == kiosk_client
+}
+
+violation[{"msg": "client is not allowed to act as a kiosk"}] {
+	not is_kiosk_client(input.context.client)
+}
+
+# Administrators can't log in on shared devices, as their session could be
+# picked up by the next person using the device
+is_admin(user) {
+	some admin_user in data.admin_users
+	user.username == admin_user
+}
+
+is_admin(user) {
+	user.can_request_admin
+}
+
+violation[{"msg": "administrators can't log in on a kiosk"}] {
+	is_admin(input.user)
+}
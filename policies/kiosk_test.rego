@@ -0,0 +1,46 @@
+package kiosk
+
+kiosk_client := {"id": "01H8PKNWKKRPCBW4YGH1RWV279"}
+
+other_client := {"id": "01H8PKNWKKRPCBW4YGH1RWV280"}
+
+alice := {"username": "alice", "can_request_admin": false}
+
+admin := {"username": "admin", "can_request_admin": true}
+
+test_kiosk_client_can_register {
+	allow with input.device_id as "KIOSK"
+		with input.context.client as kiosk_client
+		with data.kiosk_clients as [kiosk_client.id]
+}
+
+test_other_client_cant_register {
+	not allow with input.device_id as "KIOSK"
+		with input.context.client as other_client
+		with data.kiosk_clients as [kiosk_client.id]
+}
+
+test_no_kiosk_clients {
+	not allow with input.device_id as "KIOSK"
+		with input.context.client as kiosk_client
+}
+
+test_user_can_log_in {
+	allow with input.device_id as "KIOSK"
+		with input.user as alice
+		with input.context.client as kiosk_client
+		with data.kiosk_clients as [kiosk_client.id]
+}
+
+test_admin_cant_log_in {
+	not allow with input.device_id as "KIOSK"
+		with input.user as admin
+		with input.context.client as kiosk_client
+		with data.kiosk_clients as [kiosk_client.id]
+
+	not allow with input.device_id as "KIOSK"
+		with input.user as alice
+		with input.context.client as kiosk_client
+		with data.kiosk_clients as [kiosk_client.id]
+		with data.admin_users as ["alice"]
+}
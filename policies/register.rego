@@ -30,7 +30,15 @@ violation[{"msg": "unspecified registration method"}] {
 }
 
 violation[{"msg": "unknown registration method"}] {
-	not input.registration_method in ["password", "upstream-oauth2"]
+	not input.registration_method in ["password", "upstream-oauth2", "guest"]
+}
+
+# Guest accounts can be disabled by the policy, even if the guest registration
+# is enabled in the configuration
+violation[{"msg": "guest registration is disabled"}] {
+	input.registration_method == "guest"
+
+	data.registration.guests == false
 }
 
 # Check that we supplied an email for password registration
@@ -50,3 +50,12 @@ test_long_username {
 test_invalid_username {
 	not allow with input as {"username": "hello world", "registration_method": "upstream-oauth2"}
 }
+
+test_guest {
+	allow with input as {"username": "guest-0123456789", "registration_method": "guest"}
+}
+
+test_guest_disabled {
+	not allow with input as {"username": "guest-0123456789", "registration_method": "guest"}
+		with data.registration.guests as false
+}